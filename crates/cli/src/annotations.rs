@@ -0,0 +1,371 @@
+//! `markon annotations` — read the annotation database directly, without a
+//! running server. SQLite's WAL mode lets this run beside a live daemon.
+
+use dialoguer::Confirm;
+use markon_core::annotations::{self, AnnotationFilter, ExportFormat, ImportEntry};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+pub struct ExportOptions<'a> {
+    /// File or directory to export; `None` exports everything.
+    pub path: Option<&'a str>,
+    pub format: &'a str,
+    /// Only annotations in this review category.
+    pub category: Option<&'a str>,
+    /// Only annotations carrying this tag.
+    pub tag: Option<&'a str>,
+    /// Only annotations by this author name.
+    pub author: Option<&'a str>,
+    pub output: Option<&'a str>,
+    pub db_path: Option<String>,
+}
+
+/// Which stored rows an export covers.
+pub(crate) enum Scope {
+    All,
+    Dir(PathBuf),
+    File(PathBuf),
+}
+
+impl Scope {
+    /// The scope of a file or directory argument; `None` covers everything.
+    pub(crate) fn of(path: Option<&str>) -> Result<Self, String> {
+        let Some(path) = path else {
+            return Ok(Self::All);
+        };
+        let target = dunce::canonicalize(path).map_err(|_| format!("Path '{path}' not found."))?;
+        Ok(if target.is_dir() {
+            Self::Dir(target)
+        } else {
+            Self::File(target)
+        })
+    }
+
+    /// The file or directory stored paths must fall under.
+    pub(crate) fn root(&self) -> Option<&Path> {
+        match self {
+            Self::All => None,
+            Self::Dir(path) | Self::File(path) => Some(path),
+        }
+    }
+
+    /// How a stored path is shown, or `None` when it is out of scope. Paths
+    /// under a directory are relative to it; a file shows its name; `All`
+    /// keeps paths absolute.
+    pub(crate) fn display(&self, path: &Path) -> Option<String> {
+        let shown = match self {
+            Self::All => path.to_path_buf(),
+            Self::Dir(dir) => path.strip_prefix(dir).ok()?.to_path_buf(),
+            Self::File(file) if path == file => PathBuf::from(path.file_name()?),
+            Self::File(_) => return None,
+        };
+        Some(shown.to_string_lossy().replace('\\', "/"))
+    }
+}
+
+pub fn export(options: ExportOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let format: ExportFormat = options.format.parse()?;
+    let filter = AnnotationFilter {
+        tag: options.tag.map(str::to_string),
+        author: options.author.map(str::to_string),
+        resolved: None,
+        category: options.category.map(str::to_string),
+    }
+    .normalized()?;
+    let scope = Scope::of(options.path)?;
+    let db_path =
+        annotations::database_path(options.db_path).ok_or("Cannot find home directory")?;
+    let conn = annotations::open_read_only(&db_path)?;
+    let mut exported = annotations::load(&conn, |path| scope.display(path))?;
+    exported.retain(|annotation| filter.matches(&annotation.annotation));
+
+    let body = annotations::render(&exported, format);
+    match options.output {
+        Some(output) => {
+            std::fs::write(output, body)?;
+            eprintln!("Exported {} annotation(s) to {output}", exported.len());
+        }
+        None => print!("{body}"),
+    }
+    Ok(())
+}
+
+pub struct ImportOptions<'a> {
+    /// JSON or Web Annotation file written by `markon annotations export`
+    /// or another annotation tool.
+    pub file: &'a str,
+    /// Root the annotated files lived under when exported, and where they
+    /// live now.
+    pub remap: Option<(&'a str, &'a str)>,
+    pub db_path: Option<String>,
+}
+
+pub fn import(options: ImportOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let json = std::fs::read_to_string(options.file)
+        .map_err(|e| format!("Failed to read {}: {e}", options.file))?;
+    let entries = annotations::parse_dump(&json)?;
+    let remap = options
+        .remap
+        .map(|(from, to)| {
+            let to = dunce::canonicalize(to).map_err(|_| format!("Path '{to}' not found."))?;
+            Ok::<_, String>((PathBuf::from(from), to))
+        })
+        .transpose()?;
+
+    let db_path =
+        annotations::database_path(options.db_path).ok_or("Cannot find home directory")?;
+    let mut conn = annotations::open(&db_path)?;
+    let report = annotations::import(&mut conn, &entries, |entry| {
+        resolve(
+            entry,
+            remap
+                .as_ref()
+                .map(|(from, to)| (from.as_path(), to.as_path())),
+        )
+    })?;
+
+    println!("Imported {} annotation(s).", report.imported);
+    if report.missing_files > 0 {
+        println!(
+            "Skipped {} whose file does not exist here; use --from/--to if the files moved.",
+            report.missing_files
+        );
+    }
+    if report.conflicts > 0 {
+        println!(
+            "Skipped {} whose id already belongs to another file.",
+            report.conflicts
+        );
+    }
+    if report.invalid > 0 {
+        println!("Skipped {} malformed entries.", report.invalid);
+    }
+    Ok(())
+}
+
+/// Print the deleted annotations of `file` still held for undo.
+pub fn history(file: &str, db_path: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let target = canonical_or_deleted(file).ok_or_else(|| format!("Path '{file}' not found."))?;
+    let db_path = annotations::database_path(db_path).ok_or("Cannot find home directory")?;
+    let conn = annotations::open(&db_path)?;
+    let entries = annotations::history(&conn, &target.to_string_lossy())?;
+    if entries.is_empty() {
+        println!("No deleted annotations for {file}.");
+        return Ok(());
+    }
+    for entry in &entries {
+        println!(
+            "{}  {:<6}  {}  {}",
+            entry.deleted_at,
+            entry.action,
+            entry.id,
+            excerpt(&entry.annotation)
+        );
+    }
+    Ok(())
+}
+
+/// The canonical path of `file`, which may since have been deleted (its
+/// history outlives it, e.g. after `prune --archive`) as long as its
+/// directory remains.
+pub(crate) fn canonical_or_deleted(file: &str) -> Option<PathBuf> {
+    if let Ok(path) = dunce::canonicalize(file) {
+        return Some(path);
+    }
+    let path = Path::new(file);
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    Some(dunce::canonicalize(dir).ok()?.join(path.file_name()?))
+}
+
+/// The quoted text of an annotation, shortened to one line.
+fn excerpt(annotation: &serde_json::Value) -> String {
+    const MAX_CHARS: usize = 60;
+    let text = annotation
+        .get("text")
+        .and_then(serde_json::Value::as_str)
+        .unwrap_or("")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if text.chars().count() > MAX_CHARS {
+        let short: String = text.chars().take(MAX_CHARS - 1).collect();
+        format!("{short}…")
+    } else {
+        text
+    }
+}
+
+pub struct PruneOptions<'a> {
+    /// Directory whose deleted files to look for.
+    pub dir: &'a str,
+    /// Move annotations to history instead of deleting them.
+    pub archive: bool,
+    pub dry_run: bool,
+    /// Skip the confirmation prompt.
+    pub yes: bool,
+    pub db_path: Option<String>,
+}
+
+pub fn prune(options: PruneOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = dunce::canonicalize(options.dir)
+        .ok()
+        .filter(|dir| dir.is_dir())
+        .ok_or_else(|| format!("Directory '{}' not found.", options.dir))?;
+    let db_path =
+        annotations::database_path(options.db_path).ok_or("Cannot find home directory")?;
+    let conn = if options.dry_run {
+        annotations::open_read_only(&db_path)?
+    } else {
+        annotations::open(&db_path)?
+    };
+    let missing = annotations::missing_files(&conn, &dir)?;
+    if missing.is_empty() {
+        println!("No stored data for deleted files under {}.", dir.display());
+        return Ok(());
+    }
+    for file in &missing {
+        let shown = Path::new(&file.file_path)
+            .strip_prefix(&dir)
+            .unwrap_or(Path::new(&file.file_path));
+        let viewed = if file.viewed { ", viewed state" } else { "" };
+        println!(
+            "{}  {} annotation(s){viewed}",
+            shown.display(),
+            file.annotations
+        );
+    }
+    let total: usize = missing.iter().map(|file| file.annotations).sum();
+    println!("{} deleted file(s), {total} annotation(s).", missing.len());
+    if options.dry_run {
+        return Ok(());
+    }
+
+    let confirmed = if options.yes {
+        true
+    } else if std::io::stdin().is_terminal() {
+        let prompt = if options.archive {
+            "Move these annotations to history and drop their viewed state?"
+        } else {
+            "Permanently delete these annotations and their viewed state?"
+        };
+        Confirm::new()
+            .with_prompt(prompt)
+            .default(false)
+            .interact()?
+    } else {
+        return Err("prune requires confirmation; rerun with --yes".into());
+    };
+    if !confirmed {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let pruned = annotations::prune_missing_files(&conn, &missing, options.archive)?;
+    let removed: usize = pruned.iter().map(|file| file.annotations).sum();
+    if options.archive {
+        println!(
+            "Archived {removed} annotation(s) from {} file(s); `markon annotations history` lists them.",
+            pruned.len()
+        );
+    } else {
+        println!(
+            "Deleted {removed} annotation(s) from {} file(s).",
+            pruned.len()
+        );
+    }
+    Ok(())
+}
+
+pub struct MigrateOptions<'a> {
+    /// Project root whose annotations move into its own database.
+    pub dir: &'a str,
+    /// Database to copy into; the project database when `None`.
+    pub target: Option<&'a str>,
+    /// Configured global database path.
+    pub source: Option<String>,
+}
+
+pub fn migrate(options: MigrateOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = dunce::canonicalize(options.dir)
+        .ok()
+        .filter(|dir| dir.is_dir())
+        .ok_or_else(|| format!("Directory '{}' not found.", options.dir))?;
+    let source_path =
+        annotations::database_path(options.source).ok_or("Cannot find home directory")?;
+    let target_path = options
+        .target
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dir.join(annotations::PROJECT_DATABASE));
+    if target_path.is_file()
+        && dunce::canonicalize(&target_path).ok() == dunce::canonicalize(&source_path).ok()
+    {
+        return Err("Source and target are the same database.".into());
+    }
+    let source = annotations::open_read_only(&source_path)?;
+    let mut target = annotations::open(&target_path)?;
+    let copied = annotations::copy_under(&source, &mut target, &dir)?;
+    println!(
+        "Copied {copied} annotation(s) under {} into {}.",
+        dir.display(),
+        target_path.display()
+    );
+    if options.target.is_none() {
+        println!("Markon uses this database when serving that directory from now on.");
+    }
+    Ok(())
+}
+
+/// The canonical path an entry belongs to here, after remapping its root.
+fn resolve(entry: &ImportEntry, remap: Option<(&Path, &Path)>) -> Option<PathBuf> {
+    let path = Path::new(&entry.file_path);
+    let path = match remap {
+        Some((from, to)) => annotations::remap_root(path, from, to),
+        None => path.to_path_buf(),
+    };
+    dunce::canonicalize(path).ok().filter(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scope_filters_and_shortens_paths() {
+        let file = Path::new("/ws/docs/a.md");
+        let dir = Scope::Dir(PathBuf::from("/ws"));
+        assert_eq!(dir.display(file).as_deref(), Some("docs/a.md"));
+        assert_eq!(dir.display(Path::new("/other/a.md")), None);
+
+        let single = Scope::File(file.to_path_buf());
+        assert_eq!(single.display(file).as_deref(), Some("a.md"));
+        assert_eq!(single.display(Path::new("/ws/docs/b.md")), None);
+
+        assert_eq!(Scope::All.display(file).as_deref(), Some("/ws/docs/a.md"));
+    }
+
+    #[test]
+    fn deleted_files_resolve_beside_their_directory() {
+        let dir = std::env::temp_dir();
+        let name = format!("markon-gone-{}.md", std::process::id());
+        assert_eq!(
+            canonical_or_deleted(&dir.join(&name).to_string_lossy()),
+            Some(dunce::canonicalize(&dir).unwrap().join(&name))
+        );
+        assert_eq!(
+            canonical_or_deleted(&dir.join("markon-missing-dir").join(&name).to_string_lossy()),
+            None
+        );
+    }
+
+    #[test]
+    fn excerpt_is_one_short_line() {
+        let annotation = serde_json::json!({ "text": "Retry\n  loop" });
+        assert_eq!(excerpt(&annotation), "Retry loop");
+        let long = serde_json::json!({ "text": "x".repeat(80) });
+        assert_eq!(excerpt(&long).chars().count(), 60);
+        assert_eq!(excerpt(&serde_json::json!({})), "");
+    }
+}
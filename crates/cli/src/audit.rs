@@ -0,0 +1,99 @@
+//! `markon audit` — list the log a server started with `--audit` keeps of
+//! annotation changes and served files, without a running server.
+
+use crate::annotations::Scope;
+use markon_core::annotations;
+use markon_core::audit::{self, Action, AuditEntry, AuditQuery};
+use std::path::Path;
+
+pub struct Options<'a> {
+    /// File or directory whose entries to list; `None` lists everything.
+    pub path: Option<&'a str>,
+    pub action: Option<Action>,
+    pub limit: usize,
+    /// `text` or `json`.
+    pub format: &'a str,
+    pub db_path: Option<String>,
+}
+
+pub fn run(options: Options<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = Scope::of(options.path)?;
+    let db_path =
+        annotations::database_path(options.db_path).ok_or("Cannot find home directory")?;
+    let conn = annotations::open_read_only(&db_path)?;
+    let entries = audit::list(
+        &conn,
+        &AuditQuery {
+            scope: scope.root(),
+            action: options.action,
+            limit: Some(options.limit),
+        },
+    )?;
+    if options.format == "json" {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        println!("No audit entries. Start the server with --audit to record them.");
+        return Ok(());
+    }
+    // Oldest first, like a log file.
+    for entry in entries.iter().rev() {
+        let path = scope
+            .display(Path::new(&entry.file_path))
+            .unwrap_or_else(|| entry.file_path.clone());
+        println!("{}", line(entry, &path));
+    }
+    Ok(())
+}
+
+/// `2024-05-01T09:30:00Z  delete   Ana (collaborator, 192.168.1.20)  a.md  anno-1`
+fn line(entry: &AuditEntry, path: &str) -> String {
+    let actor = entry.actor.as_deref().unwrap_or("-");
+    let context: Vec<&str> = [entry.role.as_deref(), entry.ip.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect();
+    let who = if context.is_empty() {
+        actor.to_string()
+    } else {
+        format!("{actor} ({})", context.join(", "))
+    };
+    let mut line = format!("{}  {:<7}  {who}  {path}", entry.at, entry.action);
+    if let Some(id) = &entry.annotation_id {
+        line.push_str("  ");
+        line.push_str(id);
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_name_who_did_what_where() {
+        let mut entry = AuditEntry {
+            id: 1,
+            at: "2024-05-01T09:30:00Z".into(),
+            action: "delete".into(),
+            file_path: "/ws/a.md".into(),
+            annotation_id: Some("anno-1".into()),
+            actor: Some("Ana".into()),
+            role: Some("collaborator".into()),
+            ip: Some("192.168.1.20".into()),
+        };
+        assert_eq!(
+            line(&entry, "a.md"),
+            "2024-05-01T09:30:00Z  delete   Ana (collaborator, 192.168.1.20)  a.md  anno-1"
+        );
+        entry.action = "serve".into();
+        entry.annotation_id = None;
+        entry.actor = None;
+        entry.role = None;
+        assert_eq!(
+            line(&entry, "a.md"),
+            "2024-05-01T09:30:00Z  serve    - (192.168.1.20)  a.md"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! `markon --clipboard` — render the text on the system clipboard.
+//!
+//! Like standard input, the text is buffered into a file,
+//! `~/.markon/clipboard/clipboard.md`, and opened as a single-file workspace.
+//! The command then stays in the terminal: pressing Enter reads the
+//! clipboard again and rewrites the file, and the open page follows. The
+//! clipboard is read with the platform's own tool (`pbpaste`, PowerShell's
+//! `Get-Clipboard`, or `wl-paste` / `xclip` / `xsel`).
+
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread::JoinHandle;
+
+/// File name the clipboard text is rendered under.
+pub const DOCUMENT_NAME: &str = "clipboard.md";
+
+/// Directory holding the clipboard text.
+pub fn document_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".markon").join("clipboard"))
+}
+
+/// Commands that print the clipboard, tried in order.
+#[cfg(target_os = "macos")]
+const READERS: &[(&str, &[&str])] = &[("pbpaste", &[])];
+#[cfg(windows)]
+const READERS: &[(&str, &[&str])] = &[(
+    "powershell",
+    &[
+        "-NoProfile",
+        "-NonInteractive",
+        "-Command",
+        "Get-Clipboard -Raw",
+    ],
+)];
+#[cfg(not(any(target_os = "macos", windows)))]
+const READERS: &[(&str, &[&str])] = &[
+    ("wl-paste", &["--no-newline"]),
+    ("xclip", &["-selection", "clipboard", "-out"]),
+    ("xsel", &["--clipboard", "--output"]),
+];
+
+/// The text on the clipboard.
+pub fn read() -> io::Result<String> {
+    read_with(READERS)
+}
+
+fn read_with(readers: &[(&str, &[&str])]) -> io::Result<String> {
+    let mut failures = Vec::new();
+    for (program, args) in readers {
+        match Command::new(program)
+            .args(*args)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+        {
+            Ok(output) if output.status.success() => {
+                return Ok(String::from_utf8_lossy(&output.stdout).into_owned());
+            }
+            Ok(output) => failures.push(format!("{program} exited with {}", output.status)),
+            Err(e) => failures.push(format!("{program}: {e}")),
+        }
+    }
+    Err(io::Error::other(format!(
+        "no clipboard reader worked ({})",
+        failures.join("; ")
+    )))
+}
+
+/// Read the clipboard into the document in `dir`, returning its length.
+pub fn store(dir: &Path) -> io::Result<usize> {
+    write(dir, &read()?)
+}
+
+fn write(dir: &Path, text: &str) -> io::Result<usize> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(DOCUMENT_NAME), text)?;
+    Ok(text.len())
+}
+
+/// Re-read the clipboard into the document in `dir` each time a line
+/// (Enter) arrives on `input`, on a background thread that ends with the
+/// input.
+pub fn follow(dir: PathBuf, input: impl BufRead + Send + 'static) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        for line in input.lines() {
+            if line.is_err() {
+                break;
+            }
+            match store(&dir) {
+                Ok(len) => println!("Re-read the clipboard ({len} bytes)."),
+                Err(e) => eprintln!("Error: cannot read the clipboard: {e}"),
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clipboard_text_becomes_the_document() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(write(dir.path(), "# Draft\n").unwrap(), 8);
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(DOCUMENT_NAME)).unwrap(),
+            "# Draft\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_first_working_reader_is_used() {
+        let text = read_with(&[
+            ("markon-no-such-clipboard-tool", &[]),
+            ("false", &[]),
+            ("printf", &["# From %s", "clipboard"]),
+        ])
+        .unwrap();
+        assert_eq!(text, "# From clipboard");
+
+        let err = read_with(&[("false", &[])]).unwrap_err();
+        assert!(err.to_string().contains("false exited"), "{err}");
+    }
+}
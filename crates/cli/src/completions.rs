@@ -0,0 +1,334 @@
+//! `markon completions <shell>` — tab-completion scripts for bash, zsh and
+//! fish.
+//!
+//! The scripts are generated from the clap command tree, so every flag and
+//! subcommand added to the CLI completes without touching this file. Each
+//! script walks the words typed so far to find the subcommand being
+//! completed, then offers its flags, its subcommands, the possible values of
+//! the flag before the cursor, or file names.
+
+use clap::{Arg, ArgAction, Command, ValueEnum};
+use std::fmt::Write;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+/// One command of the tree, named by the words leading to it.
+struct Node<'a> {
+    /// `markon`, `markon admin`, `markon admin open`, …
+    words: Vec<&'a str>,
+    command: &'a Command,
+}
+
+fn nodes(command: &Command) -> Vec<Node<'_>> {
+    let mut out = Vec::new();
+    collect(command, vec![command.get_name()], &mut out);
+    out
+}
+
+fn collect<'a>(command: &'a Command, words: Vec<&'a str>, out: &mut Vec<Node<'a>>) {
+    out.push(Node {
+        words: words.clone(),
+        command,
+    });
+    for sub in visible_subcommands(command) {
+        let mut sub_words = words.clone();
+        sub_words.push(sub.get_name());
+        collect(sub, sub_words, out);
+    }
+}
+
+fn visible_subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+}
+
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+}
+
+/// `--long` and `-s` spellings of an option, visible aliases included.
+fn spellings(arg: &Arg) -> Vec<String> {
+    let mut out: Vec<String> = arg
+        .get_long_and_visible_aliases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|long| format!("--{long}"))
+        .collect();
+    out.extend(
+        arg.get_short_and_visible_aliases()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|short| format!("-{short}")),
+    );
+    out
+}
+
+fn takes_value(arg: &Arg) -> bool {
+    matches!(arg.get_action(), ArgAction::Set | ArgAction::Append)
+}
+
+fn possible_values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .into_iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+/// First line of an argument's or command's help, for one-line descriptions.
+fn summary(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|help| help.to_string())
+        .and_then(|help| help.lines().next().map(str::to_string))
+        .unwrap_or_default()
+}
+
+pub fn generate(command: &mut Command, shell: Shell) -> String {
+    command.build();
+    match shell {
+        Shell::Bash => bash(command),
+        Shell::Zsh => zsh(command),
+        Shell::Fish => fish(command),
+    }
+}
+
+fn bash(command: &Command) -> String {
+    let name = command.get_name();
+    let nodes = nodes(command);
+    let mut out = String::new();
+    writeln!(out, "# bash completion for {name}").unwrap();
+    writeln!(out, "_{name}() {{").unwrap();
+    writeln!(out, "    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\"").unwrap();
+    writeln!(out, "    local prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"").unwrap();
+    writeln!(out, "    local cmd=\"{name}\" i").unwrap();
+    writeln!(out, "    for ((i = 1; i < COMP_CWORD; i++)); do").unwrap();
+    writeln!(out, "        case \"${{cmd}}__${{COMP_WORDS[i]}}\" in").unwrap();
+    for node in nodes.iter().skip(1) {
+        let parent = node.words[..node.words.len() - 1].join("__");
+        let word = node.words.last().unwrap();
+        let aliases: Vec<String> = std::iter::once(word.to_string())
+            .chain(node.command.get_visible_aliases().map(str::to_string))
+            .map(|alias| format!("{parent}__{alias}"))
+            .collect();
+        writeln!(
+            out,
+            "            {}) cmd=\"{}\" ;;",
+            aliases.join("|"),
+            node.words.join("__")
+        )
+        .unwrap();
+    }
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done").unwrap();
+    writeln!(out, "    local opts=\"\"").unwrap();
+    writeln!(out, "    case \"$cmd\" in").unwrap();
+    for node in &nodes {
+        writeln!(out, "        {})", node.words.join("__")).unwrap();
+        writeln!(out, "            case \"$prev\" in").unwrap();
+        for arg in options(node.command).filter(|arg| takes_value(arg)) {
+            let values = possible_values(arg);
+            let reply = if values.is_empty() {
+                "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+            } else {
+                format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    values.join(" ")
+                )
+            };
+            writeln!(
+                out,
+                "                {}) {reply}; return ;;",
+                spellings(arg).join("|")
+            )
+            .unwrap();
+        }
+        writeln!(out, "            esac").unwrap();
+        let words: Vec<String> = options(node.command)
+            .flat_map(spellings)
+            .chain(visible_subcommands(node.command).map(|sub| sub.get_name().to_string()))
+            .collect();
+        writeln!(out, "            opts=\"{}\" ;;", words.join(" ")).unwrap();
+    }
+    writeln!(out, "    esac").unwrap();
+    writeln!(out, "    COMPREPLY=($(compgen -W \"$opts\" -- \"$cur\"))").unwrap();
+    writeln!(out, "}}").unwrap();
+    // `-o default` falls back to file names when no word matches.
+    writeln!(out, "complete -F _{name} -o default {name}").unwrap();
+    out
+}
+
+/// Quote `text` for a single-quoted zsh/fish word.
+fn single_quoted(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Escape the `:` and `\` that `_describe` treats as separators.
+fn describe_entry(word: &str, help: &str) -> String {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace(':', "\\:");
+    single_quoted(&format!("{}:{}", escape(word), escape(help)))
+}
+
+fn zsh(command: &Command) -> String {
+    let name = command.get_name();
+    let nodes = nodes(command);
+    let mut out = String::new();
+    writeln!(out, "#compdef {name}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "_{name}() {{").unwrap();
+    writeln!(out, "    local cmd={name} i").unwrap();
+    writeln!(out, "    local -a described").unwrap();
+    writeln!(out, "    for ((i = 2; i < CURRENT; i++)); do").unwrap();
+    writeln!(out, "        case \"${{cmd}}__${{words[i]}}\" in").unwrap();
+    for node in nodes.iter().skip(1) {
+        let parent = node.words[..node.words.len() - 1].join("__");
+        let aliases: Vec<String> = std::iter::once(*node.words.last().unwrap())
+            .chain(node.command.get_visible_aliases())
+            .map(|alias| format!("{parent}__{alias}"))
+            .collect();
+        writeln!(
+            out,
+            "            {}) cmd={} ;;",
+            aliases.join("|"),
+            node.words.join("__")
+        )
+        .unwrap();
+    }
+    writeln!(out, "        esac").unwrap();
+    writeln!(out, "    done").unwrap();
+    writeln!(out, "    local prev=\"${{words[CURRENT-1]}}\"").unwrap();
+    writeln!(out, "    case \"$cmd\" in").unwrap();
+    for node in &nodes {
+        writeln!(out, "        {})", node.words.join("__")).unwrap();
+        writeln!(out, "            case \"$prev\" in").unwrap();
+        for arg in options(node.command).filter(|arg| takes_value(arg)) {
+            let values = possible_values(arg);
+            let reply = if values.is_empty() {
+                "_files".to_string()
+            } else {
+                format!("compadd -- {}", values.join(" "))
+            };
+            writeln!(
+                out,
+                "                {}) {reply}; return ;;",
+                spellings(arg).join("|")
+            )
+            .unwrap();
+        }
+        writeln!(out, "            esac").unwrap();
+        writeln!(out, "            if [[ \"$PREFIX\" == -* ]]; then").unwrap();
+        let flags: Vec<String> = options(node.command)
+            .flat_map(|arg| {
+                let help = summary(arg.get_help());
+                spellings(arg)
+                    .into_iter()
+                    .map(move |spelling| describe_entry(&spelling, &help))
+            })
+            .collect();
+        writeln!(out, "                described=({})", flags.join(" ")).unwrap();
+        writeln!(out, "                _describe -t options option described").unwrap();
+        writeln!(out, "            else").unwrap();
+        let subs: Vec<String> = visible_subcommands(node.command)
+            .map(|sub| describe_entry(sub.get_name(), &summary(sub.get_about())))
+            .collect();
+        writeln!(out, "                described=({})", subs.join(" ")).unwrap();
+        writeln!(
+            out,
+            "                _describe -t commands command described; _files"
+        )
+        .unwrap();
+        writeln!(out, "            fi ;;").unwrap();
+    }
+    writeln!(out, "    esac").unwrap();
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "compdef _{name} {name}").unwrap();
+    out
+}
+
+fn fish(command: &Command) -> String {
+    let name = command.get_name();
+    let mut out = String::new();
+    writeln!(out, "# fish completion for {name}").unwrap();
+    for node in nodes(command) {
+        // The subcommand path must be typed and none of its children yet.
+        let mut conditions: Vec<String> = node.words[1..]
+            .iter()
+            .map(|word| format!("__fish_seen_subcommand_from {word}"))
+            .collect();
+        let children: Vec<&str> = visible_subcommands(node.command)
+            .flat_map(|sub| std::iter::once(sub.get_name()).chain(sub.get_visible_aliases()))
+            .collect();
+        if !children.is_empty() {
+            conditions.push(format!(
+                "not __fish_seen_subcommand_from {}",
+                children.join(" ")
+            ));
+        }
+        let condition = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!(" -n {}", single_quoted(&conditions.join("; and ")))
+        };
+        for sub in visible_subcommands(node.command) {
+            writeln!(
+                out,
+                "complete -c {name}{condition} -f -a {} -d {}",
+                sub.get_name(),
+                single_quoted(&summary(sub.get_about()))
+            )
+            .unwrap();
+        }
+        for arg in options(node.command) {
+            let mut line = format!("complete -c {name}{condition}");
+            for long in arg.get_long_and_visible_aliases().unwrap_or_default() {
+                write!(line, " -l {long}").unwrap();
+            }
+            for short in arg.get_short_and_visible_aliases().unwrap_or_default() {
+                write!(line, " -s {short}").unwrap();
+            }
+            if takes_value(arg) {
+                line.push_str(" -r");
+                let values = possible_values(arg);
+                if !values.is_empty() {
+                    write!(line, " -f -a {}", single_quoted(&values.join(" "))).unwrap();
+                }
+            }
+            write!(line, " -d {}", single_quoted(&summary(arg.get_help()))).unwrap();
+            writeln!(out, "{line}").unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn scripts_cover_nested_subcommands_flags_and_values() {
+        let bash = generate(&mut crate::Cli::command(), Shell::Bash);
+        assert!(bash.contains("markon__annotations__export"));
+        assert!(bash.contains("markon__shutdown|markon__stop) cmd=\"markon__shutdown\""));
+        assert!(
+            bash.contains("--theme) COMPREPLY=($(compgen -W \"light dark sepia high-contrast\"")
+        );
+        assert!(bash.ends_with("complete -F _markon -o default markon\n"));
+
+        let zsh = generate(&mut crate::Cli::command(), Shell::Zsh);
+        assert!(zsh.starts_with("#compdef markon"));
+        assert!(zsh.contains("'--custom-css:"));
+
+        let fish = generate(&mut crate::Cli::command(), Shell::Fish);
+        assert!(fish
+            .contains("complete -c markon -n '__fish_seen_subcommand_from render' -l standalone"));
+        assert!(fish.contains(r"-a render -d 'Print a Markdown file'\''s HTML"));
+    }
+}
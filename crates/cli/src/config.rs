@@ -0,0 +1,429 @@
+//! `markon.toml` — defaults for the command-line flags, so they don't have to
+//! be retyped on every start.
+//!
+//! Two files are read and layered, the later one winning key by key: the
+//! user's `~/.config/markon/config.toml` (under `$XDG_CONFIG_HOME` when set),
+//! then `markon.toml` in the working directory. Flags given on the command
+//! line override both. Relative paths in a file are taken from its directory.
+//!
+//! A `markon.toml` that came with a cloned repository is not trusted to widen
+//! what the server exposes. It may not set `host` or `allow_remote`, may only
+//! switch `[features]` off, may not replace the user's `allow_paths`, and its
+//! `custom_css`, `theme` and `db` paths must stay inside its directory;
+//! anything else is dropped with a warning and has to come from the user's
+//! file or the command line. Its `deny_paths` add to the user's.
+//!
+//! ```toml
+//! port = 8080
+//! host = "0.0.0.0"
+//! theme = "sepia"            # or a custom theme: "themes/paper.css"
+//! search_exclude = ["node_modules", "vendor/**"]
+//! search_max_file_size = 2       # MiB
+//! allow_remote = ["raw.githubusercontent.com"]
+//! link_schemes = ["jira=https://jira.example.com/browse/"]
+//! deny_paths = ["private/**", "*.draft.md"]
+//! custom_css = ["markon.css"]
+//! db = ".markon/annotations.sqlite"
+//!
+//! [features]
+//! edit = true
+//! chat = false
+//! ```
+
+use markon_core::workspace::WorkspaceFlags;
+use serde::Deserialize;
+use std::path::{Component, Path, PathBuf};
+
+/// Name of the per-project file, looked up in the working directory.
+pub const PROJECT_FILE: &str = "markon.toml";
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    pub port: Option<u16>,
+    pub host: Option<String>,
+    /// A built-in theme name or a custom theme file, as `--theme` takes;
+    /// overrides the saved theme.
+    pub theme: Option<String>,
+    /// Like `--search-exclude`; a non-empty list replaces the one below it.
+    #[serde(default)]
+    pub search_exclude: Vec<String>,
+    /// Like `--search-writer-memory`, in MiB.
+    pub search_writer_memory: Option<usize>,
+    /// Like `--search-max-file-size`, in MiB.
+    pub search_max_file_size: Option<u64>,
+    /// Like `--search-max-index-size`, in MiB.
+    pub search_max_index_size: Option<u64>,
+    /// Like `--allow-remote`; a non-empty list replaces the one below it.
+    #[serde(default)]
+    pub allow_remote: Vec<String>,
+    /// Like `--link-scheme`; a non-empty list replaces the one below it.
+    #[serde(default)]
+    pub link_schemes: Vec<String>,
+    /// Like `--allow-path`; a non-empty list replaces the one below it.
+    #[serde(default)]
+    pub allow_paths: Vec<String>,
+    /// Like `--deny-path`; added to the one below it, so a project file can
+    /// only hide more.
+    #[serde(default)]
+    pub deny_paths: Vec<String>,
+    /// Feature switches for the workspace being opened.
+    #[serde(default)]
+    pub features: FeatureConfig,
+    /// Stylesheet files, linked into every page like `--custom-css` and
+    /// ahead of the ones given there; a non-empty list replaces the one
+    /// below it.
+    #[serde(default)]
+    pub custom_css: Vec<PathBuf>,
+    /// Like `--db`.
+    pub db: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeatureConfig {
+    pub search: Option<bool>,
+    pub viewed: Option<bool>,
+    pub edit: Option<bool>,
+    pub live: Option<bool>,
+    pub chat: Option<bool>,
+    pub shared: Option<bool>,
+}
+
+/// Whether `path` stays inside `dir` (canonical) with symlinks in its
+/// existing part resolved. The part that doesn't exist yet, like a database
+/// about to be created, may not step out with `..`.
+fn is_within(dir: &Path, path: &Path) -> bool {
+    path.ancestors()
+        .find_map(|ancestor| {
+            let real = dunce::canonicalize(ancestor).ok()?;
+            Some((real, path.strip_prefix(ancestor).ok()?))
+        })
+        .is_some_and(|(real, rest)| {
+            real.starts_with(dir)
+                && rest
+                    .components()
+                    .all(|part| matches!(part, Component::Normal(_)))
+        })
+}
+
+/// Where the user-wide file lives, if a home directory is known.
+pub fn global_config_path() -> Option<PathBuf> {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))
+        .map(|dir| dir.join("markon").join("config.toml"))
+}
+
+impl FileConfig {
+    /// The user-wide file layered under `cwd`'s `markon.toml`. Missing files
+    /// are skipped; unreadable or malformed ones are an error.
+    pub fn load(cwd: &Path) -> Result<Self, String> {
+        let global = match global_config_path() {
+            Some(path) => Self::read(&path)?,
+            None => None,
+        };
+        let path = cwd.join(PROJECT_FILE);
+        let mut project = Self::read(&path)?.unwrap_or_default();
+        let dir = dunce::canonicalize(cwd).unwrap_or_else(|_| cwd.to_path_buf());
+        let mut ignored = project.confine_to_project(&dir);
+        // An allow list of its own would expose what the user's leaves out.
+        if global
+            .as_ref()
+            .is_some_and(|global| !global.allow_paths.is_empty())
+            && !std::mem::take(&mut project.allow_paths).is_empty()
+        {
+            ignored.push("allow_paths");
+        }
+        if !ignored.is_empty() {
+            eprintln!(
+                "Warning: ignoring {} in {}: a project file may not open the server \
+                 to the network, turn features on, widen the user config's \
+                 allow_paths, or point outside its directory. Set it in the user \
+                 config or on the command line.",
+                ignored.join(", "),
+                path.display()
+            );
+        }
+        Ok(global.unwrap_or_default().merge(project))
+    }
+
+    /// Clear what a project file in `dir` (canonical) may not set, returning
+    /// the names of the keys it touched.
+    fn confine_to_project(&mut self, dir: &Path) -> Vec<&'static str> {
+        let mut ignored = Vec::new();
+        if self.host.take().is_some() {
+            ignored.push("host");
+        }
+        if !std::mem::take(&mut self.allow_remote).is_empty() {
+            ignored.push("allow_remote");
+        }
+        let features = &mut self.features;
+        for (name, switch) in [
+            ("features.search", &mut features.search),
+            ("features.viewed", &mut features.viewed),
+            ("features.edit", &mut features.edit),
+            ("features.live", &mut features.live),
+            ("features.chat", &mut features.chat),
+            ("features.shared", &mut features.shared),
+        ] {
+            if *switch == Some(true) {
+                *switch = None;
+                ignored.push(name);
+            }
+        }
+        let css = self.custom_css.len();
+        self.custom_css.retain(|file| is_within(dir, file));
+        if self.custom_css.len() != css {
+            ignored.push("custom_css");
+        }
+        if self
+            .theme
+            .take_if(|theme| {
+                !markon_core::theme::is_built_in(theme) && !is_within(dir, Path::new(theme))
+            })
+            .is_some()
+        {
+            ignored.push("theme");
+        }
+        if self.db.take_if(|db| !is_within(dir, db)).is_some() {
+            ignored.push("db");
+        }
+        ignored
+    }
+
+    /// Parse one file, `None` when it doesn't exist.
+    pub fn read(path: &Path) -> Result<Option<Self>, String> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Cannot read {}: {e}", path.display())),
+        };
+        let mut config: Self =
+            toml::from_str(&text).map_err(|e| format!("Invalid {}: {e}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        if let Some(theme) = &mut config.theme {
+            if !markon_core::theme::is_built_in(theme) {
+                let file = Path::new(theme.as_str());
+                if !matches!(
+                    file.extension().and_then(|ext| ext.to_str()),
+                    Some("css" | "json")
+                ) {
+                    return Err(format!(
+                        "Invalid {}: theme must be {}, or a .css or .json file",
+                        path.display(),
+                        markon_core::theme::BUILT_IN.join(", ")
+                    ));
+                }
+                if file.is_relative() {
+                    *theme = dir.join(file).to_string_lossy().into_owned();
+                }
+            }
+        }
+        for file in config.custom_css.iter_mut().chain(&mut config.db) {
+            if file.is_relative() {
+                *file = dir.join(&*file);
+            }
+        }
+        Ok(Some(config))
+    }
+
+    /// `self` with every key `over` sets replaced by its value.
+    pub fn merge(self, over: Self) -> Self {
+        let features = FeatureConfig {
+            search: over.features.search.or(self.features.search),
+            viewed: over.features.viewed.or(self.features.viewed),
+            edit: over.features.edit.or(self.features.edit),
+            live: over.features.live.or(self.features.live),
+            chat: over.features.chat.or(self.features.chat),
+            shared: over.features.shared.or(self.features.shared),
+        };
+        Self {
+            port: over.port.or(self.port),
+            host: over.host.or(self.host),
+            theme: over.theme.or(self.theme),
+            search_exclude: if over.search_exclude.is_empty() {
+                self.search_exclude
+            } else {
+                over.search_exclude
+            },
+            search_writer_memory: over.search_writer_memory.or(self.search_writer_memory),
+            search_max_file_size: over.search_max_file_size.or(self.search_max_file_size),
+            search_max_index_size: over.search_max_index_size.or(self.search_max_index_size),
+            allow_remote: if over.allow_remote.is_empty() {
+                self.allow_remote
+            } else {
+                over.allow_remote
+            },
+            link_schemes: if over.link_schemes.is_empty() {
+                self.link_schemes
+            } else {
+                over.link_schemes
+            },
+            allow_paths: if over.allow_paths.is_empty() {
+                self.allow_paths
+            } else {
+                over.allow_paths
+            },
+            deny_paths: self.deny_paths.into_iter().chain(over.deny_paths).collect(),
+            features,
+            custom_css: if over.custom_css.is_empty() {
+                self.custom_css
+            } else {
+                over.custom_css
+            },
+            db: over.db.or(self.db),
+        }
+    }
+}
+
+impl FeatureConfig {
+    /// `flags` with the switches set here applied.
+    pub fn apply(&self, flags: WorkspaceFlags) -> WorkspaceFlags {
+        WorkspaceFlags {
+            enable_search: self.search.unwrap_or(flags.enable_search),
+            enable_viewed: self.viewed.unwrap_or(flags.enable_viewed),
+            enable_edit: self.edit.unwrap_or(flags.enable_edit),
+            enable_live: self.live.unwrap_or(flags.enable_live),
+            enable_chat: self.chat.unwrap_or(flags.enable_chat),
+            shared_annotation: self.shared.unwrap_or(flags.shared_annotation),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn project_file_overrides_global_keys_and_resolves_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let global = dir.path().join("config.toml");
+        std::fs::write(
+            &global,
+            "port = 7000\ntheme = \"dark\"\nsearch_exclude = [\"vendor\"]\n\
+             deny_paths = [\"private/**\"]\n[features]\nedit = true\nchat = true\n",
+        )
+        .unwrap();
+        let project = dir.path().join("proj").join(PROJECT_FILE);
+        std::fs::create_dir_all(project.parent().unwrap()).unwrap();
+        std::fs::write(
+            &project,
+            "port = 8080\ndb = \"notes.sqlite\"\ncustom_css = [\"site.css\", \"css/print.css\"]\n\
+             deny_paths = [\"*.draft.md\"]\n[features]\nchat = false\n",
+        )
+        .unwrap();
+
+        let merged = FileConfig::read(&global)
+            .unwrap()
+            .unwrap()
+            .merge(FileConfig::read(&project).unwrap().unwrap());
+        assert_eq!(merged.port, Some(8080));
+        assert_eq!(merged.theme.as_deref(), Some("dark"));
+        assert_eq!(merged.search_exclude, ["vendor"]);
+        assert_eq!(merged.deny_paths, ["private/**", "*.draft.md"]);
+        assert_eq!(merged.db, Some(dir.path().join("proj/notes.sqlite")));
+        assert_eq!(
+            merged.custom_css,
+            [
+                dir.path().join("proj/site.css"),
+                dir.path().join("proj/css/print.css")
+            ]
+        );
+        assert_eq!(merged.features.edit, Some(true));
+        assert_eq!(merged.features.chat, Some(false));
+
+        let flags = merged.features.apply(WorkspaceFlags {
+            enable_search: true,
+            enable_viewed: false,
+            enable_edit: false,
+            enable_live: false,
+            enable_chat: true,
+            shared_annotation: false,
+        });
+        assert!(flags.enable_search && flags.enable_edit);
+        assert!(!flags.enable_viewed && !flags.enable_chat);
+    }
+
+    #[test]
+    fn project_files_cannot_widen_network_exposure() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dunce::canonicalize(dir.path()).unwrap();
+        let proj = root.join("proj");
+        std::fs::create_dir_all(proj.join("css")).unwrap();
+        std::fs::write(proj.join("css/site.css"), "").unwrap();
+        std::fs::write(root.join("secret.css"), "").unwrap();
+        let path = proj.join(PROJECT_FILE);
+        std::fs::write(
+            &path,
+            "port = 8080\nhost = \"0.0.0.0\"\nallow_remote = [\"example.com\"]\n\
+             custom_css = [\"css/site.css\", \"../secret.css\", \"/etc/hosts\"]\n\
+             theme = \"../secret.css\"\ndb = \"../elsewhere.sqlite\"\n\
+             [features]\nedit = true\nshared = true\nchat = false\n",
+        )
+        .unwrap();
+        let mut project = FileConfig::read(&path).unwrap().unwrap();
+        assert_eq!(
+            project.confine_to_project(&proj),
+            [
+                "host",
+                "allow_remote",
+                "features.edit",
+                "features.shared",
+                "custom_css",
+                "theme",
+                "db"
+            ]
+        );
+        assert_eq!(project.host, None);
+        assert!(project.allow_remote.is_empty());
+        assert_eq!(project.custom_css, [proj.join("css/site.css")]);
+        assert_eq!(project.theme, None);
+        assert_eq!(project.db, None);
+        assert_eq!(project.features.edit, None);
+        assert_eq!(project.features.shared, None);
+        assert_eq!(project.features.chat, Some(false));
+        assert_eq!(project.port, Some(8080));
+        assert!(project.confine_to_project(&proj).is_empty());
+
+        // Paths inside the project stay, including a database not yet created.
+        std::fs::write(
+            &path,
+            "theme = \"css/site.css\"\ndb = \".markon/notes.sqlite\"\n",
+        )
+        .unwrap();
+        let mut project = FileConfig::read(&path).unwrap().unwrap();
+        assert!(project.confine_to_project(&proj).is_empty());
+        assert_eq!(project.db, Some(proj.join(".markon/notes.sqlite")));
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&root, proj.join("up")).unwrap();
+            std::fs::write(&path, "custom_css = [\"up/secret.css\"]\n").unwrap();
+            let mut project = FileConfig::read(&path).unwrap().unwrap();
+            assert_eq!(project.confine_to_project(&proj), ["custom_css"]);
+        }
+    }
+
+    #[test]
+    fn missing_files_are_skipped_and_bad_ones_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(FileConfig::read(&dir.path().join("none.toml")), Ok(None));
+
+        let path = dir.path().join(PROJECT_FILE);
+        std::fs::write(&path, "prot = 1\n").unwrap();
+        assert!(FileConfig::read(&path).unwrap_err().contains("prot"));
+        std::fs::write(&path, "theme = \"ocean\"\n").unwrap();
+        assert!(FileConfig::read(&path).is_err());
+        std::fs::write(&path, "theme = \"sepia\"\n").unwrap();
+        let config = FileConfig::read(&path).unwrap().unwrap();
+        assert_eq!(config.theme.as_deref(), Some("sepia"));
+        std::fs::write(&path, "theme = \"themes/paper.css\"\n").unwrap();
+        let config = FileConfig::read(&path).unwrap().unwrap();
+        assert_eq!(
+            config.theme.map(PathBuf::from),
+            Some(dir.path().join("themes/paper.css"))
+        );
+    }
+}
@@ -1,9 +1,9 @@
-use clap::Parser;
+use clap::{CommandFactory, Parser};
 use dialoguer::{Confirm, Select};
 use markon_core::control::RunningServer;
 use markon_core::daemon::{DaemonConfig, DaemonWorkspace};
 use markon_core::net::{available_bind_hosts, BindHostKind};
-use markon_core::server::{self, ServerConfig, WorkspaceInit};
+use markon_core::server::{self, ServerBuilder, WorkspaceInit};
 use markon_core::settings::AppSettings;
 use markon_core::workspace::{
     expand_and_canonicalize, hash_access_code, ServerLock, WorkspaceFlags, WorkspaceRegistry,
@@ -12,8 +12,19 @@ use std::io::IsTerminal;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+mod annotations;
+mod audit;
+mod clipboard;
+mod completions;
+mod config;
 mod feedback;
+mod manpage;
+mod remote;
+mod render;
+mod search;
+mod stdin;
 mod tui;
+mod viewed;
 
 fn get_available_hosts() -> Vec<(String, String)> {
     available_bind_hosts()
@@ -59,12 +70,28 @@ struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
 
-    /// The markdown file or directory to open.
-    file: Option<String>,
+    /// The markdown file or directory to open. Several files or glob patterns
+    /// (e.g. `docs/*.md spec.md`) open just those files; `-` reads standard
+    /// input; an `https://` URL fetches a remote document.
+    files: Vec<String>,
+
+    /// Render markdown read from standard input (same as passing `-`).
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    stdin: bool,
+
+    /// Like `--stdin`, but keep reading: the page follows the input as it
+    /// arrives until the pipe is closed.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    watch_stdin: bool,
+
+    /// Render the text on the system clipboard. The command stays in the
+    /// terminal: press Enter to read the clipboard again and refresh the page.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    clipboard: bool,
 
     /// Port for the server (default: 6419).
-    #[arg(short, long, default_value_t = 6419)]
-    port: u16,
+    #[arg(short, long)]
+    port: Option<u16>,
 
     /// Host address to bind (interactive if flag given without value).
     #[arg(long, value_name = "IP", action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "select")]
@@ -74,14 +101,78 @@ struct Cli {
     #[arg(long, alias = "qr", value_name = "URL_PREFIX", action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "missing")]
     entry: Option<String>,
 
+    /// Also write the QR code to an image file (PNG, or SVG for a `.svg`
+    /// name), for a slide or a handout. Points at the `--entry` URL when
+    /// given, else the LAN address.
+    #[arg(long = "qr-out", value_name = "FILE")]
+    qr_out: Option<PathBuf>,
+
+    /// Require a random token, generated at start, on every request. The
+    /// printed URL, the QR code and the opened browser carry it; anyone else
+    /// on the network gets 401.
+    #[arg(long = "share-token", action = clap::ArgAction::SetTrue)]
+    share_token: bool,
+
     /// Additional exact Host/origin accepted by the server (repeatable).
     #[arg(long = "trusted-host", value_name = "HOST_OR_ORIGIN", action = clap::ArgAction::Append)]
     trusted_hosts: Vec<String>,
 
+    /// Host the `/_/remote?url=` page may fetch Markdown from (repeatable;
+    /// `*.example.com` or `*` for any). Off unless given.
+    #[arg(long = "allow-remote", value_name = "HOST", action = clap::ArgAction::Append)]
+    allow_remote: Vec<String>,
+
+    /// Send links written `NAME:rest` to `URL` followed by `rest`
+    /// (repeatable), e.g. `jira=https://jira.example.com/browse/`.
+    #[arg(long = "link-scheme", value_name = "NAME=URL", action = clap::ArgAction::Append)]
+    link_schemes: Vec<String>,
+
+    /// Load each .wasm file in DIR as a sandboxed render plugin that can
+    /// draw custom fenced blocks or expand macros. Needs a build with the
+    /// `wasm-plugins` feature.
+    #[arg(long, value_name = "DIR")]
+    plugins: Option<String>,
+
+    /// POST to this URL when a watched document is created, modified or
+    /// deleted (repeatable). Slack webhooks and ntfy topics are recognized;
+    /// prefix `slack:` or `ntfy:` for other hosts. Others get JSON.
+    #[arg(long = "notify-url", value_name = "URL", action = clap::ArgAction::Append)]
+    notify_urls: Vec<String>,
+
+    /// Only notify changes to documents matching this glob (repeatable,
+    /// e.g. `docs/**`).
+    #[arg(long = "notify-path", value_name = "GLOB", action = clap::ArgAction::Append)]
+    notify_paths: Vec<String>,
+
+    /// Only notify this kind of change: created, modified or deleted
+    /// (repeatable).
+    #[arg(long = "notify-event", value_name = "EVENT", action = clap::ArgAction::Append)]
+    notify_events: Vec<String>,
+
+    /// Render OpenDocument text, RTF and MediaWiki files by converting them
+    /// with an installed `pandoc`.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pandoc: bool,
+
+    /// Keep each version of the Markdown files in the server database, with
+    /// a Version history page per document to view and compare them.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    snapshots: bool,
+
+    /// Record who creates, edits, resolves or deletes annotations, and which
+    /// files are served, in an append-only log; list it with `markon audit`.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    audit: bool,
+
     /// Automatically open browser (best-effort). Default is true if a path is provided.
     #[arg(short = 'b', long, value_name = "BASE_URL", action = clap::ArgAction::Set, num_args = 0..=1, default_missing_value = "local")]
     open_browser: Option<String>,
 
+    /// Open the browser at a heading of the opened document, given by its
+    /// text or `#anchor`. `-b '#anchor'` does the same.
+    #[arg(long, value_name = "HEADING")]
+    open_to: Option<String>,
+
     /// Salt for workspace ID generation.
     #[arg(long)]
     salt: Option<String>,
@@ -94,6 +185,155 @@ struct Cli {
     /// collapsed bodies and mark them with a placeholder.
     #[arg(long, action = clap::ArgAction::SetTrue)]
     print_collapsed_content: bool,
+
+    /// Override the Content-Security-Policy header. Use "off" to omit it.
+    /// Default: a policy compatible with the embedded assets and mermaid.
+    #[arg(long, value_name = "POLICY")]
+    csp: Option<String>,
+
+    /// Who may frame pages: deny, sameorigin (default), or off. Sets
+    /// X-Frame-Options and the built-in CSP's frame-ancestors.
+    #[arg(long, value_name = "MODE")]
+    frame_options: Option<String>,
+
+    /// Re-render documents on every request instead of reusing cached renders
+    /// of unchanged files.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    no_cache: bool,
+
+    /// Leave matching paths out of search, on top of .gitignore/.ignore
+    /// (repeatable). A bare name like "node_modules" matches at any depth.
+    #[arg(long = "search-exclude", value_name = "GLOB", global = true)]
+    search_exclude: Vec<String>,
+
+    /// Search tokenizer: jieba (default, Chinese), en (English stemming), or
+    /// ngram (dictionary-free, for Japanese/Korean).
+    #[arg(long = "search-lang", value_name = "LANG", global = true)]
+    search_lang: Option<String>,
+
+    /// Largest number of results one search request may ask for (default: 100).
+    #[arg(long = "search-max-limit", value_name = "N")]
+    search_max_limit: Option<usize>,
+
+    /// Memory the search indexer buffers before writing a segment, in MiB
+    /// (default: 50, minimum: 15).
+    #[arg(long = "search-writer-memory", value_name = "MIB", global = true)]
+    search_writer_memory: Option<usize>,
+
+    /// Index at most this many MiB of each Markdown file; the rest of a
+    /// larger file is not searchable (default: 10).
+    #[arg(long = "search-max-file-size", value_name = "MIB", global = true)]
+    search_max_file_size: Option<u64>,
+
+    /// Stop indexing further files once the index holds this many MiB of
+    /// Markdown (default: unlimited).
+    #[arg(long = "search-max-index-size", value_name = "MIB", global = true)]
+    search_max_index_size: Option<u64>,
+
+    /// Require a display name for shared annotations and viewed-state
+    /// changes; browsers ask for one on first visit.
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    require_name: bool,
+
+    /// Only browsers holding this secret (opened with `?annotation_token=`)
+    /// may change shared annotations; others see them read-only.
+    #[arg(long, value_name = "SECRET")]
+    annotation_token: Option<String>,
+
+    /// File rendered below a directory listing when present (repeatable,
+    /// first match wins). Default: README.md, then index.md.
+    #[arg(long = "readme", value_name = "NAME")]
+    readme_names: Vec<String>,
+
+    /// mdBook-style `SUMMARY.md`, relative to the workspace root, that adds
+    /// a chapter sidebar with previous/next links (repeatable, first match
+    /// wins). Default: SUMMARY.md, then src/SUMMARY.md.
+    #[arg(long = "nav-file", value_name = "PATH")]
+    nav_files: Vec<String>,
+
+    /// File extension rendered as markdown (repeatable, e.g. `txt`); replaces
+    /// the default set: md, markdown, mdown, mkd, mkdn.
+    #[arg(long = "markdown-ext", value_name = "EXT")]
+    markdown_extensions: Vec<String>,
+
+    /// List and search dotfiles and dot-directories such as `.github/`
+    /// (`.git` stays hidden).
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    show_hidden: bool,
+
+    /// Follow symlinks that resolve inside the workspace (the default).
+    #[arg(long, action = clap::ArgAction::SetTrue, overrides_with = "no_follow_symlinks")]
+    follow_symlinks: bool,
+
+    /// Refuse every path through a symlink, and leave links out of listings,
+    /// search and live reload.
+    #[arg(long, action = clap::ArgAction::SetTrue, overrides_with = "follow_symlinks")]
+    no_follow_symlinks: bool,
+
+    /// Only serve, list and search paths matching this glob, relative to
+    /// the workspace (repeatable, e.g. `docs/**`). Folders leading to them
+    /// stay browsable.
+    #[arg(long = "allow-path", value_name = "GLOB", action = clap::ArgAction::Append)]
+    allow_paths: Vec<String>,
+
+    /// Never serve, list or search paths matching this glob (repeatable,
+    /// e.g. `private/**`; a glob without `/` matches at any depth).
+    #[arg(long = "deny-path", value_name = "GLOB", action = clap::ArgAction::Append)]
+    deny_paths: Vec<String>,
+
+    /// Entries per page of a directory listing (default: 500).
+    #[arg(long = "listing-page-size", value_name = "N")]
+    listing_page_size: Option<usize>,
+
+    /// Split rendered documents larger than this many KiB into pages at
+    /// their top-level headings. The table of contents still lists the
+    /// whole document and anchors lead to the page that holds them.
+    #[arg(long, value_name = "KIB")]
+    paginate: Option<usize>,
+
+    /// Page theme: auto, light, dark, sepia, high-contrast, or a custom theme
+    /// given as a .css file of token overrides or a .json manifest.
+    #[arg(long, value_name = "NAME_OR_FILE")]
+    theme: Option<String>,
+
+    /// Stylesheet linked into every page after the built-in styles, for fonts,
+    /// widths or colors (repeatable): a file path or an http(s) URL.
+    #[arg(long = "custom-css", value_name = "FILE_OR_URL")]
+    custom_css: Vec<String>,
+
+    /// Script file every page loads after the built-in scripts, e.g. for
+    /// extra keyboard shortcuts (repeatable).
+    #[arg(long = "custom-js", value_name = "FILE")]
+    custom_js: Vec<String>,
+
+    /// Log more: `-v` adds debug messages, `-vv` traces everything.
+    #[arg(short, long, action = clap::ArgAction::Count, global = true, conflicts_with = "quiet")]
+    verbose: u8,
+
+    /// Log only warnings and errors.
+    #[arg(short, long, global = true)]
+    quiet: bool,
+
+    /// Write the log to this file instead of the terminal; a server started
+    /// in the background logs there too.
+    #[arg(long, value_name = "PATH", global = true)]
+    log_file: Option<PathBuf>,
+
+    /// Annotation database to use. Default: the served root's
+    /// `.markon/annotations.sqlite` when it exists, else the configured or
+    /// global `~/.markon/annotation.sqlite`.
+    #[arg(long, value_name = "PATH", global = true)]
+    db: Option<String>,
+
+    /// Keep annotations and viewed state in a shared Postgres database
+    /// (`postgres://user@host/db`) so several servers see the same notes.
+    /// TLS follows the URL's `sslmode`: `prefer` by default, which falls back
+    /// to an unencrypted connection when the server has no TLS; add
+    /// `?sslmode=require` to insist on it. Certificates must chain to a
+    /// system-trusted root.
+    /// Needs a build with the `postgres` feature.
+    #[arg(long, value_name = "URL")]
+    db_url: Option<String>,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -131,7 +371,75 @@ enum Commands {
         yes: bool,
     },
     /// Shutdown the background Markon server.
+    #[command(visible_alias = "stop")]
     Shutdown,
+    /// Show whether the background server is running, and its PID, address,
+    /// uptime and workspace count.
+    Status,
+    /// Print the endpoints and token an editor plugin uses to preview the
+    /// workspace holding PATH, as JSON; registers its directory if needed.
+    Editor {
+        /// File or directory the editor works in. Default: current directory.
+        path: Option<String>,
+    },
+    /// Search a directory's Markdown from the terminal; no server needed.
+    Search {
+        /// Search query, e.g. "broadcast channel".
+        query: String,
+        /// Directory (or single file) to search. Default: current directory.
+        path: Option<String>,
+        /// Maximum number of results to print.
+        #[arg(long, short = 'n', default_value_t = 20)]
+        limit: usize,
+    },
+    /// Print a Markdown file's HTML to standard output; no server needed.
+    Render {
+        /// Markdown file, or `-` to read standard input.
+        file: String,
+        /// Print a complete HTML page with the stylesheets inlined.
+        #[arg(long, conflicts_with = "no_template")]
+        standalone: bool,
+        /// Print only the rendered HTML, without the `<article>` wrapper.
+        #[arg(long)]
+        no_template: bool,
+        /// Color scheme of a `--standalone` page.
+        #[arg(long, value_parser = ["light", "dark", "sepia", "high-contrast"], default_value = "light")]
+        theme: String,
+    },
+    /// Open two Markdown files side by side, rendered, with the blocks that
+    /// differ highlighted.
+    Diff {
+        /// The file shown on the left, e.g. the published version.
+        left: String,
+        /// The file shown on the right, e.g. the draft.
+        right: String,
+    },
+    /// Work with stored annotations directly; no server needed.
+    Annotations {
+        #[command(subcommand)]
+        command: AnnotationCommands,
+    },
+    /// Inspect or clear stored viewed-section checkmarks; no server needed.
+    Viewed {
+        #[command(subcommand)]
+        command: ViewedCommands,
+    },
+    /// List who changed annotations and which files were served, as logged
+    /// by a server started with `--audit`, oldest first; no server needed.
+    Audit {
+        /// File, or directory whose files' entries to list. Default: all.
+        path: Option<String>,
+        /// Only this action: create, edit, resolve, reopen, delete, clear,
+        /// restore, import, prune, or serve.
+        #[arg(long, short = 'a')]
+        action: Option<markon_core::audit::Action>,
+        /// Show the latest N entries.
+        #[arg(long, short = 'n', default_value_t = 100)]
+        limit: usize,
+        /// Output format.
+        #[arg(long, short = 'f', value_parser = ["text", "json"], default_value = "text")]
+        format: String,
+    },
     /// File a bug report on GitHub (requires `gh`, authenticated).
     Bug {
         /// Issue title. If omitted, you'll be prompted.
@@ -159,6 +467,14 @@ enum Commands {
         #[arg(long, short = 'b')]
         body: Option<String>,
     },
+    /// Print a tab-completion script, e.g.
+    /// `markon completions bash > ~/.local/share/bash-completion/completions/markon`.
+    Completions {
+        #[arg(value_enum)]
+        shell: completions::Shell,
+    },
+    /// Print the man page, e.g. `markon man > ~/.local/share/man/man1/markon.1`.
+    Man,
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -169,6 +485,102 @@ enum AdminCommands {
     Code,
 }
 
+#[derive(clap::Subcommand, Debug)]
+enum AnnotationCommands {
+    /// Export annotations with their quoted text, note, author, and
+    /// timestamps, e.g. `markon annotations export docs/ --format markdown`.
+    Export {
+        /// Markdown file, or directory whose files' annotations to export.
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        path: Option<String>,
+        /// Export every annotation in the database.
+        #[arg(long)]
+        all: bool,
+        /// Output format: json (default), csv, markdown, or w3c (Web
+        /// Annotation JSON-LD, as used by Hypothesis).
+        #[arg(long, short = 'f', default_value = "json")]
+        format: String,
+        /// Only annotations in this category: question, suggestion, or must-fix.
+        #[arg(long, short = 'c')]
+        category: Option<String>,
+        /// Only annotations carrying this tag.
+        #[arg(long, short = 't')]
+        tag: Option<String>,
+        /// Only annotations by this author name.
+        #[arg(long)]
+        author: Option<String>,
+        /// Write to this file instead of standard output.
+        #[arg(long, short = 'o', value_name = "FILE")]
+        output: Option<String>,
+    },
+    /// Import a JSON or W3C Web Annotation export into the database, e.g.
+    /// after moving machines or from another annotation tool.
+    Import {
+        /// JSON or JSON-LD file written by `markon annotations export` or
+        /// another Web Annotation tool.
+        file: String,
+        /// Directory the annotated files lived under when exported.
+        #[arg(long, value_name = "DIR", requires = "to")]
+        from: Option<String>,
+        /// Directory they live under now.
+        #[arg(long, value_name = "DIR", requires = "from")]
+        to: Option<String>,
+    },
+    /// List annotations deleted from a file that can still be restored,
+    /// newest first.
+    History {
+        /// Markdown file whose deleted annotations to list.
+        file: String,
+    },
+    /// Find annotations and viewed state of files deleted from a directory,
+    /// and remove them.
+    Prune {
+        /// Directory whose deleted files to look for. Default: current directory.
+        dir: Option<String>,
+        /// Keep pruned annotations restorable with `history` for 30 days
+        /// instead of deleting them outright.
+        #[arg(long)]
+        archive: bool,
+        /// Only report what would be removed.
+        #[arg(long, conflicts_with = "yes")]
+        dry_run: bool,
+        /// Skip the confirmation prompt.
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Copy a project's annotations from the global database into its own
+    /// `.markon/annotations.sqlite`, which is used from then on.
+    Migrate {
+        /// Project root directory. Default: current directory.
+        dir: Option<String>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ViewedCommands {
+    /// Clear the viewed checkmarks of a file, of every file under a
+    /// directory, or of everything, e.g. `markon viewed reset docs/`.
+    Reset {
+        /// Markdown file or directory whose checkmarks to clear.
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        path: Option<String>,
+        /// Clear every file's checkmarks.
+        #[arg(long)]
+        all: bool,
+        /// Skip the confirmation prompt.
+        #[arg(long, short = 'y')]
+        yes: bool,
+    },
+    /// Print the stored viewed state as JSON.
+    Export {
+        /// Markdown file or directory to export. Default: everything.
+        path: Option<String>,
+        /// Write to this file instead of standard output.
+        #[arg(long, short = 'o', value_name = "FILE")]
+        output: Option<String>,
+    },
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
 enum WorkspaceListFormat {
     Cards,
@@ -236,6 +648,10 @@ impl CliColors {
         self.paint(text, "2")
     }
 
+    fn highlight(&self, text: &str) -> String {
+        self.paint(text, "1;33")
+    }
+
     fn local_url(&self, text: &str) -> String {
         self.paint(text, "36")
     }
@@ -394,6 +810,63 @@ fn pad_right(text: &str, width: usize) -> String {
     format!("{text}{}", " ".repeat(pad))
 }
 
+/// Port used when neither `-p` nor `markon.toml` sets one.
+const DEFAULT_PORT: u16 = 6419;
+
+/// Fill the flags left off the command line from `markon.toml`.
+fn apply_file_config(cli: &mut Cli, file: &config::FileConfig) {
+    cli.port = cli.port.or(file.port);
+    cli.host = cli.host.take().or_else(|| file.host.clone());
+    if cli.search_exclude.is_empty() {
+        cli.search_exclude = file.search_exclude.clone();
+    }
+    cli.search_writer_memory = cli.search_writer_memory.or(file.search_writer_memory);
+    cli.search_max_file_size = cli.search_max_file_size.or(file.search_max_file_size);
+    cli.search_max_index_size = cli.search_max_index_size.or(file.search_max_index_size);
+    if cli.allow_remote.is_empty() {
+        cli.allow_remote = file.allow_remote.clone();
+    }
+    if cli.link_schemes.is_empty() {
+        cli.link_schemes = file.link_schemes.clone();
+    }
+    if cli.allow_paths.is_empty() {
+        cli.allow_paths = file.allow_paths.clone();
+    }
+    if cli.deny_paths.is_empty() {
+        cli.deny_paths = file.deny_paths.clone();
+    }
+    // Config stylesheets come first so the command line's win the cascade.
+    cli.custom_css.splice(
+        0..0,
+        file.custom_css
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned()),
+    );
+    if cli.db.is_none() {
+        cli.db = file
+            .db
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned());
+    }
+}
+
+/// Annotation database for work rooted at `root`: `--db`, then the root's
+/// project database, then the configured path. `None` leaves the choice to
+/// [`markon_core::annotations::database_path`] (env var or global default).
+fn annotation_db_path(
+    cli_db: Option<&str>,
+    root: &Path,
+    configured: Option<String>,
+) -> Option<String> {
+    cli_db
+        .map(str::to_string)
+        .or_else(|| {
+            markon_core::annotations::project_database(root)
+                .map(|path| path.to_string_lossy().into_owned())
+        })
+        .or(configured)
+}
+
 /// Resolve the bind host used for printed / opened URLs without prompting.
 /// Precedence: explicit `--host` (ignoring the interactive `select` sentinel)
 /// > global config `settings.host` (when non-empty) > loopback.
@@ -642,6 +1115,38 @@ async fn detach_workspace(
     Ok(())
 }
 
+/// `markon editor`: find the workspace serving `path` — the innermost
+/// directory workspace containing it — or register the directory, then print
+/// its editor-plugin session.
+async fn editor_session(
+    server: &RunningServer,
+    path: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = dunce::canonicalize(path.unwrap_or("."))
+        .map_err(|e| format!("cannot resolve {}: {e}", path.unwrap_or(".")))?;
+    let workspaces = server.list_workspaces().await?;
+    let serving = workspaces
+        .iter()
+        .filter(|ws| !ws.ephemeral && path.starts_with(&ws.path))
+        .max_by_key(|ws| ws.path.len());
+    let id = match serving {
+        Some(ws) => ws.id.clone(),
+        None => {
+            let dir = if path.is_dir() {
+                path.as_path()
+            } else {
+                path.parent().unwrap_or(&path)
+            };
+            server
+                .add_workspace(&dir.to_string_lossy(), WorkspaceFlags::default(), "")
+                .await?
+        }
+    };
+    let session = server.editor_session(&id).await?;
+    println!("{}", serde_json::to_string_pretty(&session)?);
+    Ok(())
+}
+
 /// Toggle one feature flag on a workspace, resolved by ID or `markon ls` index.
 /// Fetches the current flags, flips the requested one, and PUTs the full set
 /// back (the mgmt endpoint replaces flags wholesale).
@@ -689,6 +1194,50 @@ async fn set_workspace_feature(
     Ok(())
 }
 
+async fn server_status(
+    lock: &ServerLock,
+    server: &RunningServer,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let workspaces = server.list_workspaces().await?;
+    let colors = CliColors::detect();
+    println!("{}", colors.title("Markon server is running."));
+    if lock.pid != 0 {
+        println!("  PID:        {}", lock.pid);
+    }
+    println!(
+        "  Address:    {}",
+        server::local_browser_base_url(&lock.host, lock.port)
+    );
+    if !lock.service_version.is_empty() {
+        println!("  Version:    {}", lock.service_version);
+    }
+    if lock.started_at != 0 {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        println!(
+            "  Uptime:     {}",
+            format_uptime(now.saturating_sub(lock.started_at))
+        );
+    }
+    println!("  Workspaces: {}", workspaces.len());
+    Ok(())
+}
+
+/// `secs` as its two largest units, e.g. `3h 12m`.
+fn format_uptime(secs: u64) -> String {
+    let (days, hours, minutes) = (secs / 86_400, secs / 3600 % 24, secs / 60 % 60);
+    if days > 0 {
+        format!("{days}d {hours}h")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {}s", secs % 60)
+    } else {
+        format!("{secs}s")
+    }
+}
+
 async fn shutdown_server(server: &RunningServer) -> Result<(), Box<dyn std::error::Error>> {
     server.shutdown().await?;
 
@@ -787,6 +1336,11 @@ async fn admin_browser_command(
 /// both render identically.
 struct ForwardPlan<'a> {
     ws_root: &'a Path,
+    /// The file under `ws_root` a single-file workspace exposes (standard
+    /// input's buffer).
+    single_file: Option<&'a str>,
+    /// The curated files, relative to `ws_root`, when several were given.
+    files: &'a [String],
     flags: WorkspaceFlags,
     initial_path: Option<&'a str>,
     /// `Some(hash)` only when the CLI passed `--collaborator-access-code`; the
@@ -796,6 +1350,12 @@ struct ForwardPlan<'a> {
     advertised_host: &'a str,
     entry: Option<&'a str>,
     open_browser_target: Option<&'a str>,
+    /// Heading anchor the browser lands on.
+    open_anchor: Option<&'a str>,
+    /// `--qr-out` image file.
+    qr_out: Option<&'a Path>,
+    /// The two files `markon diff` opens side by side.
+    compare: Option<(&'a str, &'a str)>,
 }
 
 /// Register (or refresh) the workspace on the running `server` over the control
@@ -808,14 +1368,28 @@ async fn forward_to_running_server(
     lock_port: u16,
     plan: &ForwardPlan<'_>,
 ) {
-    match server
-        .add_or_update_workspace(
-            &plan.ws_root.to_string_lossy(),
-            plan.flags,
-            plan.collaborator_hash_if_set,
-        )
-        .await
-    {
+    let added = if plan.files.is_empty() {
+        server
+            .add_or_update_workspace_scoped(
+                &plan.ws_root.to_string_lossy(),
+                plan.flags,
+                plan.single_file,
+                plan.collaborator_hash_if_set,
+                None,
+            )
+            .await
+    } else {
+        server
+            .add_or_update_file_set(
+                &plan.ws_root.to_string_lossy(),
+                plan.files,
+                plan.flags,
+                plan.collaborator_hash_if_set,
+                None,
+            )
+            .await
+    };
+    match added {
         Ok(workspace_id) => {
             // Prefer the running daemon's actual bind host (recorded in the
             // lock); fall back to our own resolved host for locks written before
@@ -836,8 +1410,23 @@ async fn forward_to_running_server(
                 plan.entry,
             );
             print_workspace_access_summary(&summary);
+            if let Some(path) = plan.qr_out {
+                let share_url = summary.qr_url.as_ref().unwrap_or(&summary.featured_url);
+                match server::write_qr_image(share_url, path) {
+                    Ok(()) => println!("QR code for {share_url} written to {}", path.display()),
+                    Err(e) => eprintln!("Failed to write QR code to {}: {e}", path.display()),
+                }
+            }
             if let Some(base_option) = plan.open_browser_target {
-                let redirect = server::workspace_url_path(&workspace_id, plan.initial_path);
+                let mut redirect = match plan.compare {
+                    Some((left, right)) => {
+                        server::workspace_compare_url(&workspace_id, left, right)
+                    }
+                    None => server::workspace_url_path(&workspace_id, plan.initial_path),
+                };
+                if let Some(anchor) = plan.open_anchor {
+                    redirect = format!("{redirect}#{anchor}");
+                }
                 // The daemon mints the one-time bootstrap URL (nonce + its own
                 // bind-aware local base) over the control socket. An explicit
                 // trusted reverse-proxy origin remains an intentional override.
@@ -868,36 +1457,253 @@ fn workspace_init_to_daemon(w: &WorkspaceInit) -> DaemonWorkspace {
         flags: w.flags,
         initial_path: w.initial_path.clone(),
         single_file: w.single_file.clone(),
+        files: w.files.clone(),
         collaborator_access_code_hash: w.collaborator_access_code_hash.clone(),
         alias: w.alias.clone(),
     }
 }
 
-fn init_tracing() {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
-        )
+/// Keep the process alive while `--watch-stdin` is still copying input into
+/// the document the server renders, or `--clipboard` is waiting for Enter
+/// to re-read the clipboard into it.
+fn wait_for_input(reader: Option<(std::thread::JoinHandle<()>, &str)>) {
+    if let Some((reader, hint)) = reader {
+        println!("{hint}");
+        let _ = reader.join();
+    }
+}
+
+/// Split an `--open-browser` value into the base URL and the section it
+/// names: `-b '#usage'` opens the local server at `#usage`.
+fn split_open_browser_target(value: &str) -> (&str, Option<&str>) {
+    if value.starts_with('#') {
+        ("local", Some(value))
+    } else {
+        (value, None)
+    }
+}
+
+/// The anchor `heading` names in `document`. An `#anchor` that matches no
+/// heading is used as written (the document may still be streaming in); an
+/// unknown heading opens the top of the page.
+fn resolve_open_anchor(document: Option<&Path>, heading: &str) -> Option<String> {
+    let markdown = document
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .unwrap_or_default();
+    if let Some(anchor) = server::heading_anchor(&markdown, heading) {
+        return Some(anchor);
+    }
+    match heading.trim().strip_prefix('#').map(str::trim) {
+        Some(anchor) if !anchor.is_empty() => Some(anchor.to_string()),
+        _ => {
+            eprintln!("Note: no heading '{heading}' in the opened document; opening at the top.");
+            None
+        }
+    }
+}
+
+/// A `--theme` value as the page's built-in theme plus, for a custom theme,
+/// the stylesheet layered over it.
+fn resolve_theme(value: &str) -> Result<(String, Option<PathBuf>), String> {
+    if markon_core::theme::is_built_in(value) {
+        return Ok((value.to_string(), None));
+    }
+    let theme = markon_core::theme::CustomTheme::load(Path::new(value))?;
+    Ok((theme.base, Some(theme.stylesheet)))
+}
+
+/// `--custom-css` / `--custom-js` entries as the server takes them: URLs (if
+/// `allow_urls`) as given, files as absolute paths, since the background
+/// server runs from another directory.
+fn resolve_custom_assets(entries: &[String], allow_urls: bool) -> Result<Vec<String>, String> {
+    entries
+        .iter()
+        .map(|entry| {
+            if allow_urls && (entry.starts_with("http://") || entry.starts_with("https://")) {
+                return Ok(entry.clone());
+            }
+            match dunce::canonicalize(entry) {
+                Ok(path) if path.is_file() => Ok(path.to_string_lossy().into_owned()),
+                _ => Err(format!("File '{entry}' not found.")),
+            }
+        })
+        .collect()
+}
+
+/// Whether `arg` is a glob pattern rather than a path: it has wildcard
+/// characters and names nothing on disk as written.
+fn is_glob_pattern(arg: &str) -> bool {
+    arg.contains(['*', '?', '[']) && !Path::new(arg).exists()
+}
+
+/// Expand the positional arguments of `markon a.md docs/*.md` into the
+/// workspace root (the deepest directory holding every file) and the files
+/// relative to it, in argument order. Patterns expand alphabetically; a file
+/// named twice is kept at its first position.
+fn resolve_file_set(args: &[String]) -> Result<(PathBuf, Vec<String>), String> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for arg in args {
+        let matches: Vec<PathBuf> = if is_glob_pattern(arg) {
+            let found: Vec<PathBuf> = glob::glob(arg)
+                .map_err(|e| format!("Invalid pattern '{arg}': {e}"))?
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .collect();
+            if found.is_empty() {
+                return Err(format!("No files match '{arg}'."));
+            }
+            found
+        } else {
+            let path = PathBuf::from(arg);
+            if path.is_dir() {
+                return Err(format!(
+                    "'{arg}' is a directory; a directory can only be opened on its own."
+                ));
+            }
+            vec![path]
+        };
+        for path in matches {
+            let canonical = dunce::canonicalize(&path)
+                .map_err(|_| format!("Path '{}' not found.", path.display()))?;
+            if !paths.contains(&canonical) {
+                paths.push(canonical);
+            }
+        }
+    }
+    let Some(first) = paths.first() else {
+        return Err("No files given.".to_string());
+    };
+    let mut root = first.parent().unwrap_or(first).to_path_buf();
+    for path in &paths {
+        while !path.starts_with(&root) {
+            match root.parent() {
+                Some(parent) => root = parent.to_path_buf(),
+                None => break,
+            }
+        }
+    }
+    let files = paths
+        .iter()
+        .map(|path| {
+            path.strip_prefix(&root)
+                .unwrap_or(path)
+                .to_string_lossy()
+                .replace('\\', "/")
+        })
+        .collect();
+    Ok((root, files))
+}
+
+/// `tracing` filter directives for `-v`/`-vv`/`-q`; `None` leaves the level
+/// to `RUST_LOG`.
+fn log_filter(verbose: u8, quiet: bool) -> Option<&'static str> {
+    match (quiet, verbose) {
+        (true, _) => Some("warn"),
+        (false, 0) => None,
+        (false, 1) => Some("info,markon=debug,markon_core=debug,markond=debug"),
+        (false, _) => Some("debug,markon=trace,markon_core=trace,markond=trace"),
+    }
+}
+
+fn init_tracing(filter: Option<&str>, log_file: Option<&Path>) -> Result<(), String> {
+    let filter = match filter {
+        Some(directives) => tracing_subscriber::EnvFilter::new(directives),
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
         .with_target(false)
-        .compact()
-        .init();
+        .compact();
+    match log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("Cannot open log file {}: {e}", path.display()))?;
+            subscriber
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .init();
+        }
+        None => subscriber.init(),
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() {
-    init_tracing();
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+    // Completions and the man page describe the CLI itself; nothing else
+    // (config files, logging, the banner) is involved.
+    match &cli.command {
+        Some(Commands::Completions { shell }) => {
+            print!("{}", completions::generate(&mut Cli::command(), *shell));
+            return;
+        }
+        Some(Commands::Man) => {
+            print!("{}", manpage::generate(&mut Cli::command()));
+            return;
+        }
+        _ => {}
+    }
+    // The background server may run elsewhere; hand it an absolute path.
+    if let Some(path) = cli.log_file.take() {
+        cli.log_file = Some(std::path::absolute(&path).unwrap_or(path));
+    }
+    let log_filter = log_filter(cli.verbose, cli.quiet);
+    if let Err(e) = init_tracing(log_filter, cli.log_file.as_deref()) {
+        eprintln!("Error: {e}");
+        std::process::exit(1);
+    }
+    let file_config = match std::env::current_dir()
+        .map_err(|e| format!("Cannot determine working directory: {e}"))
+        .and_then(|cwd| config::FileConfig::load(&cwd))
+    {
+        Ok(file_config) => file_config,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    apply_file_config(&mut cli, &file_config);
+    let port = cli.port.unwrap_or(DEFAULT_PORT);
     let cli_entry = cli.entry.clone();
     // Suppress the version banner when we're about to enter the full-screen
     // browser: it would flash on the primary screen just before EnterAlternateScreen
     // and remain as the only on-screen residue after LeaveAlternateScreen on quit.
     let launching_tui =
         matches!(&cli.command, Some(Commands::Ls { format: None })) && tui_enabled();
-    if !launching_tui {
+    // Exports and renders may be written to stdout, where the banner would
+    // corrupt them.
+    let exporting = matches!(
+        &cli.command,
+        Some(
+            Commands::Annotations {
+                command: AnnotationCommands::Export { .. }
+            } | Commands::Viewed {
+                command: ViewedCommands::Export { .. }
+            } | Commands::Render { .. }
+        )
+    ) || matches!(&cli.command, Some(Commands::Audit { format, .. }) if format == "json");
+    if !launching_tui && !exporting {
         println!("Markon v{}", env!("CARGO_PKG_VERSION"));
     }
 
+    // `markon diff` opens its two files as a file set, on the page comparing
+    // them.
+    let diff_files = match cli.command.take() {
+        Some(Commands::Diff { left, right }) => {
+            cli.files = vec![left, right];
+            true
+        }
+        command => {
+            cli.command = command;
+            false
+        }
+    };
+
     // Handle subcommands.
     if let Some(cmd) = cli.command {
         // Feedback commands run without a server.
@@ -915,20 +1721,171 @@ async fn main() {
             return;
         }
 
+        // Render reads the file and prints; nothing else is involved.
+        if let Commands::Render {
+            file,
+            standalone,
+            no_template,
+            theme,
+        } = &cmd
+        {
+            let options = render::RenderOptions {
+                path: file,
+                standalone: *standalone,
+                no_template: *no_template,
+                theme,
+            };
+            if let Err(e) = render::run(options) {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // Search builds its own index from disk.
+        if let Commands::Search { query, path, limit } = &cmd {
+            let options = search::SearchOptions {
+                query,
+                path: path.as_deref(),
+                limit: *limit,
+                exclude: &cli.search_exclude,
+                lang: cli.search_lang.as_deref(),
+                writer_memory: cli.search_writer_memory,
+                max_file_size: cli.search_max_file_size,
+                max_index_size: cli.search_max_index_size,
+            };
+            if let Err(e) = search::run(options, CliColors::detect()) {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // Annotation commands read the database file directly.
+        if let Commands::Annotations { command } = &cmd {
+            let db_path = || {
+                let cwd = std::env::current_dir().unwrap_or_default();
+                annotation_db_path(cli.db.as_deref(), &cwd, AppSettings::load().db_path)
+            };
+            let result = match command {
+                AnnotationCommands::Export {
+                    path,
+                    all: _,
+                    format,
+                    category,
+                    tag,
+                    author,
+                    output,
+                } => annotations::export(annotations::ExportOptions {
+                    path: path.as_deref(),
+                    format,
+                    category: category.as_deref(),
+                    tag: tag.as_deref(),
+                    author: author.as_deref(),
+                    output: output.as_deref(),
+                    db_path: db_path(),
+                }),
+                AnnotationCommands::Import { file, from, to } => {
+                    annotations::import(annotations::ImportOptions {
+                        file,
+                        remap: from.as_deref().zip(to.as_deref()),
+                        db_path: db_path(),
+                    })
+                }
+                AnnotationCommands::History { file } => annotations::history(file, db_path()),
+                AnnotationCommands::Prune {
+                    dir,
+                    archive,
+                    dry_run,
+                    yes,
+                } => {
+                    let dir = dir.as_deref().unwrap_or(".");
+                    annotations::prune(annotations::PruneOptions {
+                        dir,
+                        archive: *archive,
+                        dry_run: *dry_run,
+                        yes: *yes,
+                        db_path: annotation_db_path(
+                            cli.db.as_deref(),
+                            Path::new(dir),
+                            AppSettings::load().db_path,
+                        ),
+                    })
+                }
+                AnnotationCommands::Migrate { dir } => {
+                    annotations::migrate(annotations::MigrateOptions {
+                        dir: dir.as_deref().unwrap_or("."),
+                        target: cli.db.as_deref(),
+                        source: AppSettings::load().db_path,
+                    })
+                }
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // Viewed commands share the annotation database.
+        if let Commands::Viewed { command } = &cmd {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let db_path = annotation_db_path(cli.db.as_deref(), &cwd, AppSettings::load().db_path);
+            let result = match command {
+                ViewedCommands::Reset { path, all: _, yes } => {
+                    viewed::reset(viewed::ResetOptions {
+                        path: path.as_deref(),
+                        yes: *yes,
+                        db_path,
+                    })
+                }
+                ViewedCommands::Export { path, output } => viewed::export(viewed::ExportOptions {
+                    path: path.as_deref(),
+                    output: output.as_deref(),
+                    db_path,
+                }),
+            };
+            if let Err(e) = result {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+
+        // The audit log lives in the annotation database too.
+        if let Commands::Audit {
+            path,
+            action,
+            limit,
+            format,
+        } = &cmd
+        {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            let db_path = annotation_db_path(cli.db.as_deref(), &cwd, AppSettings::load().db_path);
+            if let Err(e) = audit::run(audit::Options {
+                path: path.as_deref(),
+                action: *action,
+                limit: *limit,
+                format,
+                db_path,
+            }) {
+                eprintln!("Error: {e}");
+                std::process::exit(1);
+            }
+            return;
+        }
+
         // Workspace-management commands talk to the running server over its
         // privileged control socket (recorded in the lock).
-        let lock = ServerLock::read();
-        let (lock_host, lock_advertised, server) = match lock {
-            Some(ref l) if l.is_alive() => (
-                l.host.clone(),
-                l.advertised_host.clone(),
-                RunningServer::from_lock(l),
-            ),
-            _ => {
+        let Some(lock) = ServerLock::read().filter(ServerLock::is_alive) else {
+            if matches!(cmd, Commands::Status) {
+                println!("Markon server is not running.");
+            } else {
                 eprintln!("Error: No running Markon server found.");
-                std::process::exit(1);
             }
+            std::process::exit(1);
         };
+        let server = RunningServer::from_lock(&lock);
         let res = match cmd {
             Commands::Admin { command } => admin_browser_command(&server, command).await,
             Commands::Ls { format } => {
@@ -936,13 +1893,14 @@ async fn main() {
                 // host both come from the lock (what the *owning* daemon actually
                 // serves under), falling back to the shared global config only for
                 // a pre-field lock that didn't record its advertised host.
-                let advertised_host = lock_advertised
+                let advertised_host = lock
+                    .advertised_host
                     .clone()
                     .unwrap_or_else(|| AppSettings::load().advertised_host);
-                let bind_host = if lock_host.trim().is_empty() {
+                let bind_host = if lock.host.trim().is_empty() {
                     "127.0.0.1".to_string()
                 } else {
-                    lock_host.clone()
+                    lock.host.clone()
                 };
                 match format {
                     // Explicit --format cards|table: static render, byte-for-byte
@@ -1011,7 +1969,19 @@ async fn main() {
             } => set_workspace_feature(&server, &target, &feature, &value).await,
             Commands::Cleanup { yes } => cleanup_data(&server, yes).await,
             Commands::Shutdown => shutdown_server(&server).await,
-            Commands::Bug { .. } | Commands::Idea { .. } | Commands::Ask { .. } => {
+            Commands::Editor { path } => editor_session(&server, path.as_deref()).await,
+            Commands::Status => server_status(&lock, &server).await,
+            Commands::Bug { .. }
+            | Commands::Idea { .. }
+            | Commands::Ask { .. }
+            | Commands::Search { .. }
+            | Commands::Render { .. }
+            | Commands::Diff { .. }
+            | Commands::Annotations { .. }
+            | Commands::Viewed { .. }
+            | Commands::Audit { .. }
+            | Commands::Completions { .. }
+            | Commands::Man => {
                 unreachable!("handled above")
             }
         };
@@ -1027,7 +1997,94 @@ async fn main() {
         return;
     }
 
-    let (ws_root, initial_path) = if let Some(ref file_str) = cli.file {
+    let read_stdin = cli.stdin || cli.watch_stdin || cli.files.iter().any(|arg| arg == "-");
+    let mut files = Vec::new();
+    let mut compare = None;
+    let mut single_file = None;
+    let mut input_reader = None;
+    let (ws_root, initial_path) = if cli.clipboard {
+        if read_stdin || !cli.files.is_empty() {
+            eprintln!("Error: --clipboard cannot be combined with paths or standard input.");
+            std::process::exit(1);
+        }
+        let Some(dir) = clipboard::document_dir() else {
+            eprintln!("Error: no home directory to buffer the clipboard in.");
+            std::process::exit(1);
+        };
+        if let Err(e) = clipboard::store(&dir) {
+            eprintln!("Error: cannot read the clipboard: {e}");
+            std::process::exit(1);
+        }
+        if std::io::stdin().is_terminal() {
+            let reader = clipboard::follow(dir.clone(), std::io::BufReader::new(std::io::stdin()));
+            input_reader = Some((
+                reader,
+                "Press Enter to re-read the clipboard; Ctrl-D to stop.",
+            ));
+        }
+        single_file = Some(clipboard::DOCUMENT_NAME.to_string());
+        (dir, None)
+    } else if read_stdin {
+        if cli.files.iter().any(|arg| arg != "-") {
+            eprintln!("Error: standard input cannot be combined with other paths.");
+            std::process::exit(1);
+        }
+        let Some(dir) = stdin::document_dir() else {
+            eprintln!("Error: no home directory to buffer standard input in.");
+            std::process::exit(1);
+        };
+        let buffered = if cli.watch_stdin {
+            stdin::stream(&dir, std::io::stdin()).map(|reader| {
+                input_reader = Some((
+                    reader,
+                    "Following standard input; close it (Ctrl-D) to stop.",
+                ))
+            })
+        } else {
+            stdin::buffer(&dir, std::io::stdin().lock())
+        };
+        if let Err(e) = buffered {
+            eprintln!("Error: cannot buffer standard input: {e}");
+            std::process::exit(1);
+        }
+        single_file = Some(stdin::DOCUMENT_NAME.to_string());
+        (dir, None)
+    } else if let Some(url) = cli
+        .files
+        .first()
+        .filter(|arg| markon_core::remote_markdown::is_remote_url(arg))
+    {
+        if cli.files.len() > 1 {
+            eprintln!("Error: a URL cannot be combined with other paths.");
+            std::process::exit(1);
+        }
+        match remote::fetch(url).await {
+            Ok((dir, name)) => {
+                single_file = Some(name);
+                (dir, None)
+            }
+            Err(e) => {
+                eprintln!("Error: cannot fetch {url}: {e}");
+                std::process::exit(1);
+            }
+        }
+    } else if cli.files.len() > 1 || cli.files.iter().any(|arg| is_glob_pattern(arg)) {
+        match resolve_file_set(&cli.files) {
+            Ok((root, set)) => {
+                if diff_files {
+                    // The same file named twice is listed once.
+                    let right = set.last().cloned().unwrap_or_default();
+                    compare = Some((set[0].clone(), right));
+                }
+                files = set;
+                (root, None)
+            }
+            Err(e) => {
+                eprintln!("Error: {e}");
+                return;
+            }
+        }
+    } else if let Some(file_str) = cli.files.first() {
         let path = Path::new(file_str);
         let canonical = match dunce::canonicalize(path) {
             Ok(p) => p,
@@ -1057,24 +2114,29 @@ async fn main() {
     // (CLI / GUI) opens it. The port-derived fallback only kicks in when no
     // settings file exists yet.
     let mut settings = AppSettings::load();
-    let saved_workspace = settings
-        .workspaces
-        .iter()
-        .find(|w| w.single_file.is_none() && workspace_path_matches(&w.path, &ws_root));
-    let flags = saved_workspace
-        .map(|w| w.flags)
-        .unwrap_or_else(|| default_workspace_flags(&settings));
+    let saved_workspace = settings.workspaces.iter().find(|w| {
+        w.single_file == single_file
+            && w.files == files
+            && workspace_path_matches(&w.path, &ws_root)
+    });
+    let flags = file_config.features.apply(
+        saved_workspace
+            .map(|w| w.flags)
+            .unwrap_or_else(|| default_workspace_flags(&settings)),
+    );
     let ws_init = WorkspaceInit {
         path: ws_root.clone(),
         flags,
         initial_path: initial_path.clone(),
-        single_file: None,
+        single_file: single_file.clone(),
+        files: files.clone(),
         collaborator_access_code_hash: String::new(),
         alias: saved_workspace.map(|w| w.alias.clone()).unwrap_or_default(),
+        compare: compare.clone(),
     };
     let effective_salt = cli.salt.clone().unwrap_or_else(|| {
         if settings.salt.is_empty() {
-            format!("markon:{}", cli.port)
+            format!("markon:{port}")
         } else {
             settings.salt.clone()
         }
@@ -1096,15 +2158,46 @@ async fn main() {
         collaborator_access_code_hash: workspace_collaborator_access_code_hash.clone(),
         ..ws_init
     };
-    let open_browser_target = cli.open_browser.clone().or_else(|| {
-        if cli.file.is_some() {
+    let (open_browser_base, open_fragment) = match cli.open_browser.as_deref() {
+        Some(value) => {
+            let (base, fragment) = split_open_browser_target(value);
+            (Some(base.to_string()), fragment)
+        }
+        None => (None, None),
+    };
+    let open_heading = cli.open_to.as_deref().or(open_fragment);
+    let open_browser_target = open_browser_base.or_else(|| {
+        if !cli.files.is_empty() || read_stdin || cli.clipboard || open_heading.is_some() {
             Some("local".to_string())
         } else {
             None
         }
     });
+    let open_anchor = open_heading.and_then(|heading| {
+        let document = initial_path.as_deref().or(single_file.as_deref());
+        resolve_open_anchor(document.map(|file| ws_root.join(file)).as_deref(), heading)
+    });
+
+    let (user_css, user_js) = match resolve_custom_assets(&cli.custom_css, true)
+        .and_then(|css| Ok((css, resolve_custom_assets(&cli.custom_js, false)?)))
+    {
+        Ok(assets) => assets,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
+    // Absolute, since the background server runs from another directory.
+    let plugins_dir = match cli.plugins.as_deref().map(dunce::canonicalize).transpose() {
+        Ok(dir) => dir,
+        Err(e) => {
+            eprintln!("Error: Cannot open --plugins directory: {e}");
+            std::process::exit(1);
+        }
+    };
 
     let advertised_host = settings.advertised_host.clone();
+    let allow_remote = cli.allow_remote.clone();
     let mut trusted_hosts = settings.trusted_hosts.clone();
     trusted_hosts.extend(cli.trusted_hosts.iter().cloned());
     trusted_hosts.sort();
@@ -1116,6 +2209,32 @@ async fn main() {
 
     if let Some(lock) = ServerLock::read() {
         if lock.is_alive() {
+            // One server holds one database for all its workspaces.
+            if let Some(db) = annotation_db_path(cli.db.as_deref(), &ws_root, None) {
+                eprintln!(
+                    "Note: the running server keeps the annotation database it started with; \
+                     run `markon shutdown` first to use {db}."
+                );
+            }
+            if !user_css.is_empty() || !user_js.is_empty() || cli.theme.is_some() {
+                eprintln!(
+                    "Note: the running server keeps the theme, stylesheets and scripts it \
+                     started with; run `markon shutdown` first to use --theme, --custom-css \
+                     or --custom-js."
+                );
+            }
+            if plugins_dir.is_some() {
+                eprintln!(
+                    "Note: the running server keeps the plugins it started with; \
+                     run `markon shutdown` first to use --plugins."
+                );
+            }
+            if log_filter.is_some() || cli.log_file.is_some() {
+                eprintln!(
+                    "Note: the running server keeps its log settings; \
+                     run `markon shutdown` first to change them."
+                );
+            }
             let server = RunningServer::from_lock(&lock);
             // The daemon we're attaching to may have been started (by a prior CLI
             // or the GUI) with a different `--entry`, so the featured/QR host it
@@ -1132,6 +2251,8 @@ async fn main() {
                 lock.port,
                 &ForwardPlan {
                     ws_root: &ws_root,
+                    single_file: single_file.as_deref(),
+                    files: &files,
                     flags,
                     initial_path: initial_path.as_deref(),
                     collaborator_hash_if_set: cli
@@ -1142,9 +2263,13 @@ async fn main() {
                     advertised_host: &effective_advertised,
                     entry: cli.entry.as_deref(),
                     open_browser_target: open_browser_target.as_deref(),
+                    open_anchor: open_anchor.as_deref(),
+                    qr_out: cli.qr_out.as_deref(),
+                    compare: compare.as_ref().map(|(l, r)| (l.as_str(), r.as_str())),
                 },
             )
             .await;
+            wait_for_input(input_reader);
             return;
         }
     }
@@ -1175,25 +2300,29 @@ async fn main() {
         .iter()
         .filter(|w| !w.path.is_empty())
         .map(|w| {
-            let explicit = w.single_file.is_none() && workspace_path_matches(&w.path, &ws_root);
+            let explicit = w.single_file == single_file
+                && w.files == files
+                && workspace_path_matches(&w.path, &ws_root);
             if explicit {
                 loaded_explicit_workspace = true;
             }
             WorkspaceInit {
                 path: PathBuf::from(&w.path),
-                flags: w.flags,
+                flags: if explicit { flags } else { w.flags },
                 initial_path: if explicit {
                     initial_path.clone()
                 } else {
                     w.single_file.clone()
                 },
                 single_file: w.single_file.clone(),
+                files: w.files.clone(),
                 collaborator_access_code_hash: if explicit {
                     workspace_collaborator_access_code_hash.clone()
                 } else {
                     w.collaborator_access_code_hash.clone()
                 },
                 alias: w.alias.clone(),
+                compare: if explicit { compare.clone() } else { None },
             }
         })
         .collect();
@@ -1201,10 +2330,21 @@ async fn main() {
     let language = settings.effective_web_language();
     let shortcuts_json = settings.render_shortcuts_json();
     let styles_css = settings.render_styles_css();
-    let theme = settings.theme.clone();
+    let (theme, theme_css) = match resolve_theme(
+        cli.theme
+            .as_deref()
+            .or(file_config.theme.as_deref())
+            .unwrap_or(&settings.theme),
+    ) {
+        Ok(theme) => theme,
+        Err(e) => {
+            eprintln!("Error: {e}");
+            std::process::exit(1);
+        }
+    };
     let default_chat_mode = settings.default_chat_mode.clone();
     let collaborator_access_code_hash = settings.collaborator_access_code_hash.clone();
-    let db_path = settings.db_path.clone();
+    let db_path = annotation_db_path(cli.db.as_deref(), &ws_root, settings.db_path.clone());
     // CLI flag forces inclusion; otherwise inherit the persisted preference so
     // GUI-set values still apply when launching from the command line.
     let print_collapsed_content = cli.print_collapsed_content || settings.print_collapsed_content;
@@ -1222,13 +2362,15 @@ async fn main() {
             host: configured_host.clone(),
             advertised_host: advertised_host.clone(),
             trusted_hosts: trusted_hosts.clone(),
-            port: cli.port,
+            port,
             theme: theme.clone(),
+            theme_css: theme_css.clone(),
             qr: cli.entry.clone(),
             // The daemon never opens the browser itself — the CLI does, over the
             // control socket, after forwarding the workspace.
             open_browser: None,
             db_path: db_path.clone(),
+            db_url: cli.db_url.clone(),
             salt: Some(effective_salt.clone()),
             workspaces: restored_workspaces
                 .iter()
@@ -1237,9 +2379,43 @@ async fn main() {
             language: language.clone(),
             shortcuts_json: shortcuts_json.clone(),
             styles_css: styles_css.clone(),
+            user_css: user_css.clone(),
+            user_js: user_js.clone(),
             default_chat_mode: default_chat_mode.clone(),
             collaborator_access_code_hash: collaborator_access_code_hash.clone(),
             print_collapsed_content,
+            content_security_policy: cli.csp.clone(),
+            frame_options: cli.frame_options.clone(),
+            no_cache: cli.no_cache,
+            search_exclude: cli.search_exclude.clone(),
+            search_lang: cli.search_lang.clone(),
+            search_max_limit: cli.search_max_limit,
+            search_writer_memory: cli.search_writer_memory,
+            search_max_file_size: cli.search_max_file_size,
+            search_max_index_size: cli.search_max_index_size,
+            require_name: cli.require_name,
+            annotation_token: cli.annotation_token.clone(),
+            readme_names: cli.readme_names.clone(),
+            nav_files: cli.nav_files.clone(),
+            markdown_extensions: cli.markdown_extensions.clone(),
+            show_hidden: cli.show_hidden,
+            no_follow_symlinks: cli.no_follow_symlinks && !cli.follow_symlinks,
+            allow_paths: cli.allow_paths.clone(),
+            deny_paths: cli.deny_paths.clone(),
+            listing_page_size: cli.listing_page_size,
+            paginate_kib: cli.paginate,
+            allow_remote: allow_remote.clone(),
+            notify_urls: cli.notify_urls.clone(),
+            notify_paths: cli.notify_paths.clone(),
+            notify_events: cli.notify_events.clone(),
+            pandoc: cli.pandoc,
+            snapshots: cli.snapshots,
+            audit: cli.audit,
+            link_schemes: cli.link_schemes.clone(),
+            plugins_dir: plugins_dir.clone(),
+            share_token: cli.share_token,
+            log_filter: log_filter.map(str::to_string),
+            log_file: cli.log_file.clone(),
         };
 
         println!("Starting Markon server in background...");
@@ -1256,6 +2432,8 @@ async fn main() {
                     server.port(),
                     &ForwardPlan {
                         ws_root: &ws_root,
+                        single_file: single_file.as_deref(),
+                        files: &files,
                         flags,
                         initial_path: initial_path.as_deref(),
                         collaborator_hash_if_set: cli
@@ -1266,9 +2444,13 @@ async fn main() {
                         advertised_host: &advertised_host,
                         entry: cli.entry.as_deref(),
                         open_browser_target: open_browser_target.as_deref(),
+                        open_anchor: open_anchor.as_deref(),
+                        qr_out: cli.qr_out.as_deref(),
+                        compare: compare.as_ref().map(|(l, r)| (l.as_str(), r.as_str())),
                     },
                 )
                 .await;
+                wait_for_input(input_reader);
                 return;
             }
             // Readiness timeout is a hard error (the daemon spawned but never came
@@ -1300,42 +2482,74 @@ async fn main() {
     let registry = Arc::new(WorkspaceRegistry::new(effective_salt.clone()));
     registry.set_persist_hook(AppSettings::persist_hook(settings.clone()));
 
-    if let Err(e) = server::start(ServerConfig {
-        // `--host select` prompts interactively; otherwise reuse the resolved
-        // host (--host > global config settings.host > loopback).
-        host: match &cli.host {
-            Some(h) if h == "select" => match select_host() {
-                Ok(h) => h,
-                Err(e) => {
-                    eprintln!("Failed to select host: {e}");
-                    return;
-                }
-            },
-            _ => configured_host.clone(),
+    let mut config = ServerBuilder::new().build();
+    // `--host select` prompts interactively; otherwise reuse the resolved
+    // host (--host > global config settings.host > loopback).
+    config.host = match &cli.host {
+        Some(h) if h == "select" => match select_host() {
+            Ok(h) => h,
+            Err(e) => {
+                eprintln!("Failed to select host: {e}");
+                return;
+            }
         },
-        advertised_host,
-        trusted_hosts,
-        port: cli.port,
-        theme,
-        qr: cli.entry,
-        open_browser: open_browser_target,
-        shared_annotation: initial_workspaces.iter().any(|w| w.flags.shared_annotation),
-        db_path,
-        salt: Some(effective_salt),
-        initial_workspaces,
-        bound_listener: None,
-        registry: Some(registry),
-        management_token: None,
-        admin_bootstraps: None,
-        language,
-        shortcuts_json,
-        styles_css,
-        default_chat_mode,
-        collaborator_access_code_hash,
-        print_collapsed_content,
-    })
-    .await
-    {
+        _ => configured_host.clone(),
+    };
+    config.advertised_host = advertised_host;
+    config.trusted_hosts = trusted_hosts;
+    config.port = port;
+    config.theme = theme;
+    config.theme_css = theme_css;
+    config.qr = cli.entry;
+    config.qr_out = cli.qr_out;
+    config.open_browser = open_browser_target;
+    config.open_anchor = open_anchor;
+    config.shared_annotation = initial_workspaces.iter().any(|w| w.flags.shared_annotation);
+    config.db_path = db_path;
+    config.db_url = cli.db_url;
+    config.salt = Some(effective_salt);
+    config.initial_workspaces = initial_workspaces;
+    config.registry = Some(registry);
+    config.language = language;
+    config.shortcuts_json = shortcuts_json;
+    config.styles_css = styles_css;
+    config.user_css = user_css;
+    config.user_js = user_js;
+    config.default_chat_mode = default_chat_mode;
+    config.collaborator_access_code_hash = collaborator_access_code_hash;
+    config.print_collapsed_content = print_collapsed_content;
+    config.content_security_policy = cli.csp;
+    config.frame_options = cli.frame_options;
+    config.no_cache = cli.no_cache;
+    config.search_exclude = cli.search_exclude;
+    config.search_lang = cli.search_lang;
+    config.search_max_limit = cli.search_max_limit;
+    config.search_writer_memory = cli.search_writer_memory;
+    config.search_max_file_size = cli.search_max_file_size;
+    config.search_max_index_size = cli.search_max_index_size;
+    config.require_name = cli.require_name;
+    config.annotation_token = cli.annotation_token;
+    config.readme_names = cli.readme_names;
+    config.nav_files = cli.nav_files;
+    config.markdown_extensions = cli.markdown_extensions;
+    config.show_hidden = cli.show_hidden;
+    config.no_follow_symlinks = cli.no_follow_symlinks && !cli.follow_symlinks;
+    config.allow_paths = cli.allow_paths;
+    config.deny_paths = cli.deny_paths;
+    config.listing_page_size = cli.listing_page_size;
+    config.paginate_kib = cli.paginate;
+    config.allow_remote = allow_remote;
+    config.notify_urls = cli.notify_urls;
+    config.notify_paths = cli.notify_paths;
+    config.notify_events = cli.notify_events;
+    config.pandoc = cli.pandoc;
+    config.snapshots = cli.snapshots;
+    config.audit = cli.audit;
+    config.link_schemes = cli.link_schemes;
+    config.plugins_dir = plugins_dir;
+    config.share_token = cli.share_token;
+
+    if let Err(e) = server::start(config).await {
         eprintln!("Error: {e}");
         std::process::exit(1);
     }
@@ -1363,6 +2577,95 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn editor_subcommand_defaults_to_the_current_directory() {
+        let bare = Cli::try_parse_from(["markon", "editor"]).unwrap();
+        assert!(matches!(
+            bare.command,
+            Some(Commands::Editor { path: None })
+        ));
+        let file = Cli::try_parse_from(["markon", "editor", "docs/a.md"]).unwrap();
+        assert!(matches!(
+            file.command,
+            Some(Commands::Editor { path: Some(ref p) }) if p == "docs/a.md"
+        ));
+    }
+
+    #[test]
+    fn diff_subcommand_takes_exactly_two_files() {
+        let cli = Cli::try_parse_from(["markon", "diff", "published.md", "draft.md"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Commands::Diff { ref left, ref right })
+                if left == "published.md" && right == "draft.md"
+        ));
+        assert!(Cli::try_parse_from(["markon", "diff", "draft.md"]).is_err());
+    }
+
+    #[test]
+    fn annotations_export_requires_a_path_or_all() {
+        let all =
+            Cli::try_parse_from(["markon", "annotations", "export", "--all", "-f", "csv"]).unwrap();
+        assert!(matches!(
+            all.command,
+            Some(Commands::Annotations {
+                command: AnnotationCommands::Export { path: None, all: true, ref format, .. }
+            }) if format == "csv"
+        ));
+        assert!(Cli::try_parse_from(["markon", "annotations", "export"]).is_err());
+        assert!(Cli::try_parse_from([
+            "markon",
+            "annotations",
+            "import",
+            "dump.json",
+            "--from",
+            "/old"
+        ])
+        .is_err());
+        assert!(Cli::try_parse_from(["markon", "annotations", "export", "a.md", "--all"]).is_err());
+    }
+
+    #[test]
+    fn viewed_reset_requires_a_path_or_all() {
+        let reset = Cli::try_parse_from(["markon", "viewed", "reset", "docs/", "-y"]).unwrap();
+        assert!(matches!(
+            reset.command,
+            Some(Commands::Viewed {
+                command: ViewedCommands::Reset { path: Some(ref path), all: false, yes: true }
+            }) if path == "docs/"
+        ));
+        assert!(Cli::try_parse_from(["markon", "viewed", "reset"]).is_err());
+        assert!(Cli::try_parse_from(["markon", "viewed", "reset", "a.md", "--all"]).is_err());
+        assert!(matches!(
+            Cli::try_parse_from(["markon", "viewed", "export"])
+                .unwrap()
+                .command,
+            Some(Commands::Viewed {
+                command: ViewedCommands::Export {
+                    path: None,
+                    output: None
+                }
+            })
+        ));
+    }
+
+    #[test]
+    fn audit_filters_by_action_and_rejects_unknown_ones() {
+        let audit =
+            Cli::try_parse_from(["markon", "audit", "docs/", "--action", "delete", "-n", "5"])
+                .unwrap();
+        assert!(matches!(
+            audit.command,
+            Some(Commands::Audit {
+                path: Some(ref path),
+                action: Some(markon_core::audit::Action::Delete),
+                limit: 5,
+                ref format,
+            }) if path == "docs/" && format == "text"
+        ));
+        assert!(Cli::try_parse_from(["markon", "audit", "--action", "view"]).is_err());
+    }
+
     #[test]
     fn workspace_summary_lists_local_and_public_urls() {
         let flags = WorkspaceFlags {
@@ -1444,6 +2747,116 @@ mod tests {
         );
     }
 
+    #[test]
+    fn file_set_arguments_expand_in_order_under_a_common_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dunce::canonicalize(dir.path()).unwrap();
+        std::fs::create_dir(root.join("docs")).unwrap();
+        for file in ["spec.md", "docs/b.md", "docs/a.md", "docs/notes.txt"] {
+            std::fs::write(root.join(file), "x").unwrap();
+        }
+        let arg = |rel: &str| root.join(rel).to_string_lossy().into_owned();
+
+        let (set_root, files) =
+            resolve_file_set(&[arg("spec.md"), arg("docs/*.md"), arg("spec.md")]).unwrap();
+        assert_eq!(set_root, root);
+        assert_eq!(files, ["spec.md", "docs/a.md", "docs/b.md"]);
+
+        let (set_root, files) = resolve_file_set(&[arg("docs/b.md"), arg("docs/a.md")]).unwrap();
+        assert_eq!(set_root, root.join("docs"));
+        assert_eq!(files, ["b.md", "a.md"]);
+
+        assert!(resolve_file_set(&[arg("docs/*.rst")]).is_err());
+        assert!(resolve_file_set(&[arg("docs"), arg("spec.md")]).is_err());
+        assert!(resolve_file_set(&[arg("missing.md"), arg("spec.md")]).is_err());
+        let cli = Cli::try_parse_from(["markon", "a.md", "b.md"]).unwrap();
+        assert_eq!(cli.files, ["a.md", "b.md"]);
+    }
+
+    #[test]
+    fn uptime_shows_its_two_largest_units() {
+        assert_eq!(format_uptime(42), "42s");
+        assert_eq!(format_uptime(5 * 60 + 3), "5m 3s");
+        assert_eq!(format_uptime(3 * 3600 + 12 * 60 + 9), "3h 12m");
+        assert_eq!(format_uptime(2 * 86_400 + 5 * 3600), "2d 5h");
+        let cli = Cli::try_parse_from(["markon", "stop"]).unwrap();
+        assert!(matches!(cli.command, Some(Commands::Shutdown)));
+    }
+
+    #[test]
+    fn verbosity_flags_pick_the_log_filter() {
+        let cli = Cli::try_parse_from(["markon", "-vv", "docs"]).unwrap();
+        assert_eq!(cli.verbose, 2);
+        assert!(log_filter(cli.verbose, cli.quiet)
+            .unwrap()
+            .contains("markon_core=trace"));
+        let cli = Cli::try_parse_from(["markon", "list", "-q", "--log-file", "m.log"]).unwrap();
+        assert_eq!(log_filter(cli.verbose, cli.quiet), Some("warn"));
+        assert_eq!(cli.log_file, Some(PathBuf::from("m.log")));
+        assert_eq!(log_filter(0, false), None);
+        assert!(Cli::try_parse_from(["markon", "-v", "-q"]).is_err());
+    }
+
+    #[test]
+    fn open_to_resolves_a_heading_of_the_opened_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let doc = dir.path().join("guide.md");
+        std::fs::write(&doc, "# Guide\n\n## Getting Started\n").unwrap();
+
+        assert_eq!(
+            resolve_open_anchor(Some(&doc), "Getting Started").as_deref(),
+            Some("getting-started")
+        );
+        // An anchor the document doesn't have (yet) is kept as written.
+        assert_eq!(
+            resolve_open_anchor(Some(&doc), "#later").as_deref(),
+            Some("later")
+        );
+        assert_eq!(resolve_open_anchor(Some(&doc), "Missing"), None);
+
+        let cli = Cli::try_parse_from(["markon", "guide.md", "-b", "#usage"]).unwrap();
+        assert_eq!(
+            split_open_browser_target(cli.open_browser.as_deref().unwrap()),
+            ("local", Some("#usage"))
+        );
+        assert_eq!(
+            split_open_browser_target("https://md.example.com"),
+            ("https://md.example.com", None)
+        );
+        let cli = Cli::try_parse_from(["markon", "guide.md", "--open-to", "Usage"]).unwrap();
+        assert_eq!(cli.open_to.as_deref(), Some("Usage"));
+    }
+
+    #[test]
+    fn command_line_flags_override_the_config_file() {
+        let file = config::FileConfig {
+            port: Some(7000),
+            host: Some("0.0.0.0".into()),
+            search_exclude: vec!["vendor".into()],
+            custom_css: vec![PathBuf::from("/cfg/site.css")],
+            db: Some(PathBuf::from("/cfg/notes.sqlite")),
+            ..Default::default()
+        };
+        let mut cli = Cli::try_parse_from(["markon", "-p", "8080"]).unwrap();
+        apply_file_config(&mut cli, &file);
+        assert_eq!(cli.port, Some(8080));
+        assert_eq!(cli.host.as_deref(), Some("0.0.0.0"));
+        assert_eq!(cli.search_exclude, ["vendor"]);
+        assert_eq!(cli.db.as_deref(), Some("/cfg/notes.sqlite"));
+
+        let mut cli =
+            Cli::try_parse_from(["markon", "--search-exclude", "dist", "--db", "/x.sqlite"])
+                .unwrap();
+        apply_file_config(&mut cli, &file);
+        assert_eq!(cli.port, Some(7000));
+        assert_eq!(cli.search_exclude, ["dist"]);
+        assert_eq!(cli.db.as_deref(), Some("/x.sqlite"));
+
+        let mut cli = Cli::try_parse_from(["markon", "--custom-css", "extra.css"]).unwrap();
+        apply_file_config(&mut cli, &file);
+        assert_eq!(cli.custom_css, ["/cfg/site.css", "extra.css"]);
+    }
+
     #[test]
     fn configured_bind_host_precedence() {
         // Explicit --host wins over everything.
@@ -1469,6 +2882,30 @@ mod tests {
         assert_eq!(configured_bind_host(Some("select"), ""), "127.0.0.1");
     }
 
+    #[test]
+    fn annotation_db_path_prefers_flag_then_project_then_config() {
+        let root = std::env::temp_dir().join(format!("markon-db-test-{}", std::process::id()));
+        let configured = || Some("/cfg/annotation.sqlite".to_string());
+        assert_eq!(
+            annotation_db_path(Some("/x.sqlite"), &root, configured()).as_deref(),
+            Some("/x.sqlite")
+        );
+        assert_eq!(annotation_db_path(None, &root, configured()), configured());
+
+        let project = root.join(markon_core::annotations::PROJECT_DATABASE);
+        std::fs::create_dir_all(project.parent().unwrap()).unwrap();
+        std::fs::write(&project, "").unwrap();
+        assert_eq!(
+            annotation_db_path(None, &root, configured()),
+            Some(project.to_string_lossy().into_owned())
+        );
+        assert_eq!(
+            annotation_db_path(Some("/x.sqlite"), &root, None).as_deref(),
+            Some("/x.sqlite")
+        );
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
     #[test]
     fn explicit_trusted_origin_can_redeem_the_same_admin_bootstrap() {
         assert_eq!(
@@ -0,0 +1,172 @@
+//! `markon man` — a roff man page for `markon(1)`, generated from the clap
+//! command tree like the completion scripts.
+//!
+//! ```sh
+//! markon man > ~/.local/share/man/man1/markon.1
+//! ```
+
+use clap::{Arg, ArgAction, Command};
+use std::fmt::Write;
+
+/// Escape `text` for roff text lines: backslashes and hyphens, and a
+/// leading `.` or `'` that would otherwise read as a request.
+fn roff(text: &str) -> String {
+    text.lines()
+        .map(|line| {
+            let escaped = line.replace('\\', "\\e").replace('-', "\\-");
+            if escaped.starts_with(['.', '\'']) {
+                format!("\\&{escaped}")
+            } else {
+                escaped
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn help_text(help: Option<&clap::builder::StyledStr>) -> String {
+    help.map(|help| help.to_string()).unwrap_or_default()
+}
+
+/// `\fB--port\fR, \fB-p\fR \fI<PORT>\fR` for an option's `.TP` tag.
+fn option_tag(arg: &Arg) -> String {
+    let mut names: Vec<String> = arg
+        .get_short_and_visible_aliases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|short| format!("\\fB\\-{short}\\fR"))
+        .collect();
+    names.extend(
+        arg.get_long_and_visible_aliases()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|long| format!("\\fB\\-\\-{}\\fR", roff(long))),
+    );
+    let mut tag = names.join(", ");
+    if matches!(arg.get_action(), ArgAction::Set | ArgAction::Append) {
+        let value = arg
+            .get_value_names()
+            .and_then(|names| names.first())
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| arg.get_id().as_str().to_uppercase());
+        write!(tag, " \\fI<{}>\\fR", roff(&value)).unwrap();
+    }
+    tag
+}
+
+fn write_options(out: &mut String, command: &Command) {
+    for arg in command.get_arguments().filter(|arg| !arg.is_hide_set()) {
+        writeln!(out, ".TP").unwrap();
+        if arg.is_positional() {
+            writeln!(
+                out,
+                "\\fI<{}>\\fR",
+                roff(&arg.get_id().as_str().to_uppercase())
+            )
+            .unwrap();
+        } else {
+            writeln!(out, "{}", option_tag(arg)).unwrap();
+        }
+        let help = help_text(arg.get_long_help().or(arg.get_help()));
+        writeln!(out, "{}", roff(&help)).unwrap();
+        let values: Vec<String> = arg
+            .get_possible_values()
+            .into_iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_string())
+            .collect();
+        if !values.is_empty() {
+            writeln!(out, ".br").unwrap();
+            writeln!(out, "Values: {}", roff(&values.join(", "))).unwrap();
+        }
+    }
+}
+
+fn write_subcommands(out: &mut String, command: &Command, prefix: &str) {
+    for sub in command
+        .get_subcommands()
+        .filter(|sub| !sub.is_hide_set() && sub.get_name() != "help")
+    {
+        let path = format!("{prefix} {}", sub.get_name());
+        writeln!(out, ".SS \"{}\"", roff(&path)).unwrap();
+        let about = help_text(sub.get_long_about().or(sub.get_about()));
+        if !about.is_empty() {
+            writeln!(out, "{}", roff(&about)).unwrap();
+        }
+        let aliases: Vec<&str> = sub.get_visible_aliases().collect();
+        if !aliases.is_empty() {
+            writeln!(out, ".br").unwrap();
+            writeln!(out, "Alias: {}", roff(&aliases.join(", "))).unwrap();
+        }
+        // Global flags are listed once, under OPTIONS.
+        let own = sub.clone().mut_args(|arg| {
+            if arg.is_global_set() {
+                arg.hide(true)
+            } else {
+                arg
+            }
+        });
+        write_options(out, &own);
+        write_subcommands(out, sub, &path);
+    }
+}
+
+pub fn generate(command: &mut Command) -> String {
+    command.build();
+    let name = command.get_name().to_string();
+    let version = command.get_version().unwrap_or_default().to_string();
+    let mut out = String::new();
+    writeln!(
+        out,
+        ".TH {} 1 \"\" \"{} {}\"",
+        name.to_uppercase(),
+        roff(&name),
+        roff(&version)
+    )
+    .unwrap();
+    // The about line may already read "markon - …".
+    let about = help_text(command.get_about());
+    let about = about.strip_prefix(&format!("{name} - ")).unwrap_or(&about);
+    writeln!(out, ".SH NAME").unwrap();
+    writeln!(out, "{} \\- {}", roff(&name), roff(about)).unwrap();
+    writeln!(out, ".SH SYNOPSIS").unwrap();
+    writeln!(
+        out,
+        "\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fIFILES\\fR]...",
+        roff(&name)
+    )
+    .unwrap();
+    writeln!(out, ".br").unwrap();
+    writeln!(
+        out,
+        "\\fB{}\\fR \\fICOMMAND\\fR [\\fIOPTIONS\\fR]",
+        roff(&name)
+    )
+    .unwrap();
+    writeln!(out, ".SH OPTIONS").unwrap();
+    write_options(&mut out, command);
+    writeln!(out, ".SH COMMANDS").unwrap();
+    write_subcommands(&mut out, command, &name);
+    writeln!(out, ".SH VERSION").unwrap();
+    writeln!(out, "v{}", roff(&version)).unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn page_lists_options_and_nested_commands() {
+        let page = generate(&mut crate::Cli::command());
+        assert!(page.starts_with(".TH MARKON 1 "));
+        assert!(page.contains(".SH NAME\nmarkon \\- Mark it on.\n"));
+        assert!(page.contains("\\fB\\-p\\fR, \\fB\\-\\-port\\fR \\fI<PORT>\\fR"));
+        assert!(page.contains(".SS \"markon annotations export\""));
+        assert!(page.contains("Alias: stop"));
+        assert!(page.contains("Values: light, dark, sepia, high\\-contrast"));
+        // Global flags appear once, under OPTIONS.
+        assert_eq!(page.matches("\\fB\\-\\-log\\-file\\fR").count(), 1);
+    }
+}
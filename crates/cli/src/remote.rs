@@ -0,0 +1,56 @@
+//! `markon https://…/README.md` — render a Markdown document fetched over
+//! HTTP(S).
+//!
+//! Like standard input, the document is buffered into a file and opened as a
+//! single-file workspace: `~/.markon/remote/<host>/<name>`. The URL given on
+//! the command line is its own permission, so this works without
+//! `--allow-remote`, which only governs the `/_/remote?url=` page. Relative
+//! images are rewritten to absolute URLs before the file is written, so they
+//! still load from beside the original.
+
+use markon_core::remote_markdown::{parse_url, RemoteAllowlist, RemoteDocument, RemoteMarkdown};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory a document fetched from `host` is buffered in.
+pub fn document_dir(host: &str) -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".markon").join("remote").join(host))
+}
+
+/// Fetch the document at `url` and buffer it; returns the directory and
+/// file name it was stored under.
+pub async fn fetch(url: &str) -> Result<(PathBuf, String), String> {
+    let url = parse_url(url).map_err(|e| e.to_string())?;
+    let host = url.host_str().unwrap_or_default().to_string();
+    let remote = RemoteMarkdown::new(RemoteAllowlist::new(std::slice::from_ref(&host)));
+    let document = remote.fetch(&url).await.map_err(|e| e.to_string())?;
+    let dir = document_dir(&host).ok_or("no home directory to buffer the document in")?;
+    let name = store(&dir, &document).map_err(|e| format!("cannot buffer the document: {e}"))?;
+    Ok((dir, name))
+}
+
+fn store(dir: &Path, document: &RemoteDocument) -> io::Result<String> {
+    let name = document.file_name();
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(&name), &document.markdown)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetched_documents_are_stored_under_their_file_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let document = RemoteDocument {
+            url: parse_url("https://example.com/repo/main/README.md").unwrap(),
+            markdown: "# Remote\n".to_string(),
+        };
+        assert_eq!(store(dir.path(), &document).unwrap(), "README.md");
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("README.md")).unwrap(),
+            "# Remote\n"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! `markon render` — print a document's HTML to standard output without
+//! starting the server, for piping into other tools and CI checks.
+//!
+//! By default the rendered body comes wrapped in the `<article
+//! class="markdown-body">` the pages use; `--no-template` prints it bare and
+//! `--standalone` prints a complete page with the stylesheets inlined.
+
+use std::io::{Read, Write};
+
+pub struct RenderOptions<'a> {
+    /// Markdown file, or `-` for standard input.
+    pub path: &'a str,
+    pub standalone: bool,
+    pub no_template: bool,
+    /// `light` or `dark`, for `--standalone`.
+    pub theme: &'a str,
+}
+
+pub fn run(options: RenderOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let (markdown, name) = if options.path == "-" {
+        let mut markdown = String::new();
+        std::io::stdin().read_to_string(&mut markdown)?;
+        (markdown, crate::stdin::DOCUMENT_NAME.to_string())
+    } else {
+        let path = std::path::Path::new(options.path);
+        let markdown = std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {e}", path.display()))?;
+        let name = path.file_name().map_or_else(
+            || options.path.to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        (markdown, name)
+    };
+    let html = render(&markdown, &name, &options)?;
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(html.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn render(markdown: &str, name: &str, options: &RenderOptions<'_>) -> Result<String, String> {
+    if options.standalone {
+        return markon_core::render::render_standalone(markdown, options.theme, name);
+    }
+    let fragment = markon_core::render::render_fragment(markdown);
+    Ok(if options.no_template {
+        fragment
+    } else {
+        format!("<article class=\"markdown-body\">\n{fragment}\n</article>\n")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_is_wrapped_bare_or_a_whole_page() {
+        let options = |standalone, no_template| RenderOptions {
+            path: "notes.md",
+            standalone,
+            no_template,
+            theme: "light",
+        };
+        let markdown = "Hello *there*\n";
+
+        let wrapped = render(markdown, "notes.md", &options(false, false)).unwrap();
+        assert!(wrapped.starts_with("<article class=\"markdown-body\">"));
+        assert!(wrapped.contains("<em>there</em>"));
+
+        let bare = render(markdown, "notes.md", &options(false, true)).unwrap();
+        assert!(!bare.contains("<article"));
+        assert!(bare.contains("<em>there</em>"));
+
+        let page = render(markdown, "notes.md", &options(true, false)).unwrap();
+        assert!(page.contains("<title>notes.md</title>"));
+        assert!(page.contains(bare.trim()));
+    }
+}
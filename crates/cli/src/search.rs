@@ -0,0 +1,194 @@
+//! `markon search` — query a directory's Markdown from the terminal without
+//! starting the HTTP server.
+//!
+//! Builds the same Tantivy index the server uses (ignore rules, exclude list,
+//! analyzer, field boosts), prints ranked hits as `path:line` with a colored
+//! snippet, and exits. The index is ephemeral, exactly like the server's, so
+//! every run re-indexes the directory.
+
+use crate::CliColors;
+use markon_core::search::{
+    SearchConfig, SearchExclude, SearchIndex, SearchLanguage, SearchLimits, SearchResult,
+};
+use std::path::Path;
+
+pub struct SearchOptions<'a> {
+    pub query: &'a str,
+    pub path: Option<&'a str>,
+    pub limit: usize,
+    pub exclude: &'a [String],
+    pub lang: Option<&'a str>,
+    /// `--search-writer-memory`, `--search-max-file-size` and
+    /// `--search-max-index-size`, in MiB.
+    pub writer_memory: Option<usize>,
+    pub max_file_size: Option<u64>,
+    pub max_index_size: Option<u64>,
+}
+
+pub fn run(
+    options: SearchOptions<'_>,
+    colors: CliColors,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let target = options.path.unwrap_or(".");
+    let target = dunce::canonicalize(target).map_err(|_| format!("Path '{target}' not found."))?;
+
+    let (root, index) = if target.is_dir() {
+        let config = SearchConfig {
+            exclude: SearchExclude::new(options.exclude)?,
+            language: options
+                .lang
+                .map(str::parse::<SearchLanguage>)
+                .transpose()?
+                .unwrap_or_default(),
+            limits: SearchLimits::from_mib(
+                options.writer_memory,
+                options.max_file_size,
+                options.max_index_size,
+            )?,
+        };
+        let index = SearchIndex::with_config(&target, config)?;
+        (target, index)
+    } else {
+        let parent = target
+            .parent()
+            .ok_or("file has no parent directory")?
+            .to_path_buf();
+        let file_name = target
+            .file_name()
+            .and_then(|name| name.to_str())
+            .ok_or("file name is not valid UTF-8")?;
+        let index = SearchIndex::new_single_file(&parent, file_name)?;
+        (parent, index)
+    };
+
+    let page = index.search_page(options.query, 0, options.limit)?;
+    if page.results.is_empty() {
+        println!("No results for \"{}\".", options.query);
+        return Ok(());
+    }
+    for result in &page.results {
+        print_result(&root, result, colors);
+    }
+    println!("{} of {} result(s)", page.results.len(), page.total);
+    Ok(())
+}
+
+fn print_result(root: &Path, result: &SearchResult, colors: CliColors) {
+    let content = std::fs::read_to_string(root.join(&result.file_path)).unwrap_or_default();
+    let location = match hit_line(&content, result) {
+        Some(line) => format!("{}:{line}", result.file_path),
+        None => result.file_path.clone(),
+    };
+    let title = match &result.heading {
+        Some(heading) => format!("{} › {heading}", result.title),
+        None => result.title.clone(),
+    };
+    println!("{}  {}", colors.path(&location), colors.title(&title));
+    let snippet = render_snippet(&result.snippet, colors);
+    if !snippet.trim().is_empty() {
+        println!("    {}", snippet.trim());
+    }
+    println!();
+}
+
+/// 1-based line of the hit: the first line at or after the hit's heading that
+/// contains the snippet's first highlighted term. Falls back to the heading
+/// line (or the first line) when the snippet highlights nothing.
+fn hit_line(content: &str, result: &SearchResult) -> Option<usize> {
+    let lines: Vec<&str> = content.lines().collect();
+    if lines.is_empty() {
+        return None;
+    }
+    let start = result
+        .heading
+        .as_deref()
+        .and_then(|heading| {
+            lines
+                .iter()
+                .position(|line| line.trim_start().starts_with('#') && line.contains(heading))
+        })
+        .unwrap_or(0);
+    let found = first_highlight(&result.snippet).and_then(|term| {
+        let term = term.to_lowercase();
+        lines[start..]
+            .iter()
+            .position(|line| line.to_lowercase().contains(&term))
+    });
+    Some(start + found.unwrap_or(0) + 1)
+}
+
+fn first_highlight(snippet: &str) -> Option<String> {
+    let open = snippet.find("<b>")? + "<b>".len();
+    let len = snippet[open..].find("</b>")?;
+    Some(unescape_html(&snippet[open..open + len]))
+}
+
+/// Turn Tantivy's snippet HTML (escaped text with `<b>` around matched terms)
+/// into one line of terminal text with the matches highlighted.
+fn render_snippet(snippet: &str, colors: CliColors) -> String {
+    let flat = snippet.replace(['\n', '\r'], " ");
+    let mut out = String::new();
+    let mut rest = flat.as_str();
+    while let Some(open) = rest.find("<b>") {
+        out.push_str(&unescape_html(&rest[..open]));
+        let after = &rest[open + "<b>".len()..];
+        let close = after.find("</b>").unwrap_or(after.len());
+        out.push_str(&colors.highlight(&unescape_html(&after[..close])));
+        rest = after.get(close + "</b>".len()..).unwrap_or("");
+    }
+    out.push_str(&unescape_html(rest));
+    out
+}
+
+fn unescape_html(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(heading: Option<&str>, snippet: &str) -> SearchResult {
+        SearchResult {
+            file_path: "guide.md".into(),
+            file_name: "guide".into(),
+            title: "Guide".into(),
+            heading: heading.map(str::to_string),
+            anchor: None,
+            snippet: snippet.into(),
+            score: 1.0,
+            lines: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn hit_line_starts_at_the_section_heading() {
+        let content = "# Guide\nbroadcast intro\n\n# Channels\nA Broadcast channel";
+        assert_eq!(
+            hit_line(content, &result(None, "<b>broadcast</b> intro")),
+            Some(2)
+        );
+        assert_eq!(
+            hit_line(
+                content,
+                &result(Some("Channels"), "A <b>Broadcast</b> channel")
+            ),
+            Some(5)
+        );
+        assert_eq!(hit_line(content, &result(Some("Channels"), "")), Some(4));
+        assert_eq!(hit_line("", &result(None, "")), None);
+    }
+
+    #[test]
+    fn render_snippet_unescapes_and_flattens() {
+        assert_eq!(
+            render_snippet("a &lt;<b>tag</b>&gt;\nnext &amp; more", CliColors::plain()),
+            "a <tag> next & more"
+        );
+    }
+}
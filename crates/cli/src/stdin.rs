@@ -0,0 +1,79 @@
+//! `cat notes.md | markon -` — render markdown piped from standard input.
+//!
+//! The input is buffered into `~/.markon/stdin/stdin.md` and opened as a
+//! single-file workspace, so it renders, reloads and searches exactly like a
+//! file opened with Open-With. Every run writes the same file, which keeps the
+//! workspace URL stable: piping new output refreshes a tab that is already
+//! open. With `--watch-stdin` the file grows as input arrives and the page
+//! follows along until the writer closes the pipe.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
+
+/// File name the buffered input is rendered under.
+pub const DOCUMENT_NAME: &str = "stdin.md";
+
+/// Directory holding the buffered input.
+pub fn document_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".markon").join("stdin"))
+}
+
+/// Read `input` to the end and store it as the document in `dir`.
+pub fn buffer(dir: &Path, mut input: impl Read) -> io::Result<()> {
+    let mut content = Vec::new();
+    input.read_to_end(&mut content)?;
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(dir.join(DOCUMENT_NAME), content)
+}
+
+/// Start the document in `dir` empty and append `input` to it as it arrives,
+/// on a background thread that ends when the input does.
+pub fn stream(dir: &Path, input: impl Read + Send + 'static) -> io::Result<JoinHandle<()>> {
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(DOCUMENT_NAME);
+    File::create(&path)?;
+    Ok(std::thread::spawn(move || {
+        if let Err(e) = append_all(&path, input) {
+            tracing::warn!("stopped reading standard input: {e}");
+        }
+    }))
+}
+
+fn append_all(path: &Path, mut input: impl Read) -> io::Result<()> {
+    let mut file = OpenOptions::new().append(true).open(path)?;
+    let mut chunk = [0u8; 8192];
+    loop {
+        let read = match input.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        // Each chunk lands on disk right away so the file watcher sees it.
+        file.write_all(&chunk[..read])?;
+        file.flush()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffered_and_streamed_input_become_the_document() {
+        let dir = tempfile::tempdir().unwrap();
+        let document = dir.path().join(DOCUMENT_NAME);
+
+        buffer(dir.path(), "# Piped\n".as_bytes()).unwrap();
+        assert_eq!(std::fs::read_to_string(&document).unwrap(), "# Piped\n");
+
+        let input = io::Cursor::new(b"# Live\n\nmore\n".to_vec());
+        stream(dir.path(), input).unwrap().join().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&document).unwrap(),
+            "# Live\n\nmore\n"
+        );
+    }
+}
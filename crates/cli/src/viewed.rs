@@ -0,0 +1,132 @@
+//! `markon viewed` — inspect and clear the viewed-section checkmarks stored
+//! in the database, without a running server.
+
+use crate::annotations::{canonical_or_deleted, Scope};
+use dialoguer::Confirm;
+use markon_core::annotations;
+use std::io::IsTerminal;
+use std::path::Path;
+
+pub struct ResetOptions<'a> {
+    /// File or directory to reset; `None` resets everything.
+    pub path: Option<&'a str>,
+    /// Skip the confirmation prompt.
+    pub yes: bool,
+    pub db_path: Option<String>,
+}
+
+pub fn reset(options: ResetOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    // A deleted file's checkmarks can still be cleared by name.
+    let target = options
+        .path
+        .map(|path| canonical_or_deleted(path).ok_or_else(|| format!("Path '{path}' not found.")))
+        .transpose()?;
+    let db_path =
+        annotations::database_path(options.db_path).ok_or("Cannot find home directory")?;
+    let conn = annotations::open(&db_path)?;
+    let stored = annotations::viewed_states(&conn, target.as_deref())?;
+    if stored.is_empty() {
+        println!("No viewed state stored.");
+        return Ok(());
+    }
+    for record in &stored {
+        println!(
+            "{}  {} viewed",
+            record.file_path,
+            viewed_count(&record.state)
+        );
+    }
+
+    let confirmed = if options.yes {
+        true
+    } else if std::io::stdin().is_terminal() {
+        Confirm::new()
+            .with_prompt(format!(
+                "Clear the viewed checkmarks of {} file(s)?",
+                stored.len()
+            ))
+            .default(false)
+            .interact()?
+    } else {
+        return Err("reset requires confirmation; rerun with --yes".into());
+    };
+    if !confirmed {
+        println!("Cancelled.");
+        return Ok(());
+    }
+
+    let reset = annotations::reset_viewed(&conn, target.as_deref())?;
+    println!(
+        "Cleared viewed state of {} file(s). Pages already open keep their checkmarks until reloaded.",
+        reset.len()
+    );
+    Ok(())
+}
+
+pub struct ExportOptions<'a> {
+    /// File or directory to export; `None` exports everything.
+    pub path: Option<&'a str>,
+    pub output: Option<&'a str>,
+    pub db_path: Option<String>,
+}
+
+pub fn export(options: ExportOptions<'_>) -> Result<(), Box<dyn std::error::Error>> {
+    let scope = Scope::of(options.path)?;
+    let db_path =
+        annotations::database_path(options.db_path).ok_or("Cannot find home directory")?;
+    let conn = annotations::open_read_only(&db_path)?;
+    let stored = annotations::viewed_states(&conn, scope.root())?;
+    // `path` is shown relative to an exported directory; `viewed` lists the
+    // checked heading ids and `state` keeps the stored map verbatim.
+    let files: Vec<serde_json::Value> = stored
+        .iter()
+        .filter_map(|record| {
+            Some(serde_json::json!({
+                "path": scope.display(Path::new(&record.file_path))?,
+                "file_path": record.file_path,
+                "viewed": viewed_ids(&record.state),
+                "state": record.state,
+            }))
+        })
+        .collect();
+    let count = files.len();
+    let export = serde_json::json!({ "version": 1, "files": files });
+    let body = serde_json::to_string_pretty(&export)? + "\n";
+    match options.output {
+        Some(output) => {
+            std::fs::write(output, body)?;
+            eprintln!("Exported viewed state of {count} file(s) to {output}");
+        }
+        None => print!("{body}"),
+    }
+    Ok(())
+}
+
+/// Heading ids marked viewed in a stored state.
+fn viewed_ids(state: &serde_json::Value) -> Vec<&str> {
+    state
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(_, viewed)| viewed.as_bool() == Some(true))
+        .map(|(id, _)| id.as_str())
+        .collect()
+}
+
+fn viewed_count(state: &serde_json::Value) -> usize {
+    viewed_ids(state).len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_sections_marked_true_count_as_viewed() {
+        let state = serde_json::json!({ "intro": true, "usage": false, "faq": true });
+        let mut ids = viewed_ids(&state);
+        ids.sort_unstable();
+        assert_eq!(ids, ["faq", "intro"]);
+        assert_eq!(viewed_count(&serde_json::Value::Null), 0);
+    }
+}
@@ -0,0 +1,56 @@
+/**
+ * Side-by-side compare page (`/_/{id}/compare`, `markon diff`): layout i18n
+ * and stepping between the rows whose blocks differ.
+ *
+ * Mirrors the wiring pattern of {@link ./page-shortcuts}: construct a manager,
+ * register the page's real shortcuts (Help, Theme, Escape and the `j` / `k`
+ * change navigation), route `keydown` through it, and expose it as
+ * `window.shortcutsManager`.
+ */
+
+import { KeyboardShortcutsManager } from './managers/keyboard-shortcuts';
+import { CONFIG } from './core/config';
+
+const t: (key: string) => string = (window.__MARKON_I18N__?.t) || ((k: string) => k);
+
+/** Dismiss the shortcuts help overlay if it's open. Returns whether it was. */
+const closeHelpPanel = (): boolean => {
+    const panel = document.querySelector<HTMLElement>('.shortcuts-help-panel');
+    if (!panel) return false;
+    panel.classList.remove('visible');
+    setTimeout(() => panel.remove(), CONFIG.ANIMATION.PANEL_TRANSITION);
+    return true;
+};
+
+const init = (): void => {
+    document.querySelectorAll<HTMLElement>('[data-i18n]').forEach((el) => {
+        el.textContent = t(el.getAttribute('data-i18n') || '');
+    });
+
+    const changes = [...document.querySelectorAll<HTMLElement>('.compare-row:not(.is-equal)')];
+    let current = -1;
+    const step = (delta: number): void => {
+        if (changes.length === 0) return;
+        changes[current]?.classList.remove('is-focused');
+        current = Math.max(0, Math.min(changes.length - 1, current + delta));
+        changes[current].classList.add('is-focused');
+        changes[current].scrollIntoView({ block: 'start', behavior: 'smooth' });
+    };
+
+    const km = new KeyboardShortcutsManager();
+    km.register('HELP', () => km.showHelp());
+    km.register('THEME_PANEL', () => window.MarkonTheme?.togglePanel());
+    km.register('ESCAPE', () => closeHelpPanel());
+    km.register('COMPARE_NEXT_CHANGE', () => step(1));
+    km.register('COMPARE_PREV_CHANGE', () => step(-1));
+
+    document.addEventListener('keydown', (e) => km.handle(e));
+
+    window.shortcutsManager = km;
+};
+
+if (document.readyState === 'loading') {
+    document.addEventListener('DOMContentLoaded', init, { once: true });
+} else {
+    init();
+}
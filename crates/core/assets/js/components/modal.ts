@@ -287,18 +287,28 @@ export abstract class BaseModal {
  * Options for {@link NoteInputModal}.
  */
 export interface NoteInputModalOptions extends BaseModalOptions {
-    onSave?: (value: string) => void;
+    onSave?: (value: string, attachments: string[]) => void;
     onCancel?: () => void;
     initialValue?: string;
+    /** Image attachment ids already on the note being edited. */
+    initialAttachments?: string[];
+    /** Upload a pasted image, resolving to its attachment id. Pasting images
+     *  is only offered when this is set. */
+    uploadAttachment?: (image: Blob) => Promise<string>;
+    /** URL an attachment id is served from, for the thumbnails. */
+    attachmentUrl?: (id: string) => string;
 }
 
 /**
  * Note input modal.
  */
 export class NoteInputModal extends BaseModal {
-    #onSave: (value: string) => void;
+    #onSave: (value: string, attachments: string[]) => void;
     #onCancel: () => void;
     #initialValue: string;
+    #initialAttachments: string[];
+    #uploadAttachment: ((image: Blob) => Promise<string>) | null;
+    #attachmentUrl: (id: string) => string;
     #resizeObserver: ResizeObserver | null = null;
     #sizeTrackingReady = false;
 
@@ -314,6 +324,9 @@ export class NoteInputModal extends BaseModal {
         this.#onSave = options.onSave ?? (() => {});
         this.#onCancel = options.onCancel ?? (() => {});
         this.#initialValue = options.initialValue ?? '';
+        this.#initialAttachments = options.initialAttachments ?? [];
+        this.#uploadAttachment = options.uploadAttachment ?? null;
+        this.#attachmentUrl = options.attachmentUrl ?? ((id) => id);
     }
 
     override show(anchorElement: HTMLElement | null = null): void {
@@ -343,6 +356,7 @@ export class NoteInputModal extends BaseModal {
             <span class="note-input-drag-region note-input-drag-left" aria-hidden="true"></span>
             <div class="note-input-field">
                 <textarea class="note-textarea" placeholder="${_t('web.modal.note.placeholder')}"></textarea>
+                <div class="note-input-attachments" hidden></div>
                 <div class="note-input-actions">
                     <button class="note-cancel" type="button" title="${_t('web.modal.cancel')}" aria-label="${_t('web.modal.cancel')}">
                         <svg viewBox="0 0 24 24" aria-hidden="true" focusable="false">
@@ -389,11 +403,58 @@ export class NoteInputModal extends BaseModal {
             this.close();
         });
 
+        // Pasted images upload right away; the note keeps their ids.
+        const attachments = [...this.#initialAttachments];
+        const strip = modal.querySelector('.note-input-attachments') as HTMLElement;
+        const renderAttachments = (): void => {
+            strip.replaceChildren(...attachments.map((id) => {
+                const item = document.createElement('span');
+                item.className = 'note-input-attachment';
+                const image = document.createElement('img');
+                image.src = this.#attachmentUrl(id);
+                image.alt = _t('web.modal.note.attachment');
+                const remove = document.createElement('button');
+                remove.type = 'button';
+                remove.className = 'note-input-attachment-remove';
+                remove.title = _t('web.modal.note.attachment.remove');
+                remove.setAttribute('aria-label', remove.title);
+                remove.textContent = '×';
+                remove.addEventListener('click', () => {
+                    attachments.splice(attachments.indexOf(id), 1);
+                    renderAttachments();
+                });
+                item.append(image, remove);
+                return item;
+            }));
+            strip.hidden = attachments.length === 0;
+        };
+        renderAttachments();
+
+        const upload = this.#uploadAttachment;
+        if (upload) {
+            textarea.addEventListener('paste', (e: ClipboardEvent) => {
+                const images = Array.from(e.clipboardData?.files ?? [])
+                    .filter((file) => file.type.startsWith('image/'));
+                if (images.length === 0) return;
+                e.preventDefault();
+                for (const image of images) {
+                    upload(image)
+                        .then((id) => {
+                            if (!attachments.includes(id)) attachments.push(id);
+                            renderAttachments();
+                        })
+                        .catch((error: unknown) => {
+                            window.alert(error instanceof Error ? error.message : String(error));
+                        });
+                }
+            });
+        }
+
         // Save button.
         const save = (): void => {
             const value = textarea.value.trim();
             if (value) {
-                this.#onSave(value);
+                this.#onSave(value, attachments);
                 this.close();
             }
         };
@@ -8,13 +8,15 @@ const files = [
     { path: 'Cargo.toml', name: 'Cargo.toml', url: '/ws/Cargo.toml', is_markdown: false },
 ];
 
-const contentResults = [
-    {
-        title: 'Guide',
-        file_path: 'docs/Guide.md',
-        snippet: 'A <b>guide</b> to the workspace',
-    },
-];
+let contentResults: Record<string, unknown>[] = [];
+let annotationResults: Record<string, unknown>[] = [];
+const guideResult = {
+    title: 'Guide',
+    file_path: 'docs/Guide.md',
+    snippet: 'A <b>guide</b> to the workspace',
+    heading: null,
+    anchor: null,
+};
 
 async function flush(): Promise<void> {
     await Promise.resolve();
@@ -27,11 +29,19 @@ describe('WorkspaceSpotlight', () => {
     beforeEach(() => {
         document.body.innerHTML = '';
         window.history.replaceState(null, '', '/ws/docs/Guide.md');
+        contentResults = [guideResult];
+        annotationResults = [];
         vi.stubGlobal('fetch', vi.fn((url: string) => {
             if (url.startsWith('/_/ws/search')) {
                 return Promise.resolve({
                     ok: true,
-                    json: () => Promise.resolve(contentResults),
+                    json: () => Promise.resolve({
+                        total: contentResults.length,
+                        offset: 0,
+                        limit: 30,
+                        results: contentResults,
+                        annotations: annotationResults,
+                    }),
                 });
             }
             return Promise.resolve({
@@ -86,7 +96,7 @@ describe('WorkspaceSpotlight', () => {
         await new Promise((resolve) => setTimeout(resolve, 150));
         await flush();
 
-        expect(fetch).toHaveBeenCalledWith('/_/ws/search?q=guide', { credentials: 'same-origin' });
+        expect(fetch).toHaveBeenCalledWith('/_/ws/search?q=guide&limit=30', { credentials: 'same-origin' });
         const text = document.body.textContent || '';
         expect(text).toContain('web.wsnav.files');
         expect(text).toContain('web.wsnav.contents');
@@ -94,6 +104,55 @@ describe('WorkspaceSpotlight', () => {
         expect(document.querySelector('.workspace-spotlight-result--content')?.innerHTML).toContain('<b>guide</b>');
     });
 
+    it('deep-links content matches inside a heading section', async () => {
+        contentResults = [{
+            ...guideResult,
+            title: 'Advanced Tutorial',
+            heading: 'Step 3',
+            anchor: 'step-3',
+            snippet: 'Tune the <b>guide</b> rail',
+        }];
+        const nav = new WorkspaceSpotlight({ workspaceId: 'ws', enableContentSearch: true });
+        nav.open();
+        await flush();
+
+        const input = document.querySelector<HTMLInputElement>('.workspace-spotlight-input')!;
+        input.value = 'guide';
+        input.dispatchEvent(new Event('input', { bubbles: true }));
+        await new Promise((resolve) => setTimeout(resolve, 150));
+        await flush();
+
+        const link = document.querySelector<HTMLAnchorElement>('.workspace-spotlight-result--content')!;
+        expect(link.querySelector('.workspace-spotlight-result-title')?.textContent).toBe('Advanced Tutorial › Step 3');
+        expect(link.getAttribute('href')).toMatch(/\?highlight=guide#step-3$/);
+    });
+
+    it('lists matching annotations and links to the annotated passage', async () => {
+        contentResults = [];
+        annotationResults = [{
+            file_path: 'docs/Guide.md',
+            annotation_id: 'anno-1',
+            text: 'retry loop',
+            note: 'guide timeouts',
+            snippet: '<b>guide</b> timeouts',
+        }];
+        const nav = new WorkspaceSpotlight({ workspaceId: 'ws', enableContentSearch: true });
+        nav.open();
+        await flush();
+
+        const input = document.querySelector<HTMLInputElement>('.workspace-spotlight-input')!;
+        input.value = 'guide';
+        input.dispatchEvent(new Event('input', { bubbles: true }));
+        await new Promise((resolve) => setTimeout(resolve, 150));
+        await flush();
+
+        expect(document.body.textContent).toContain('web.wsnav.annotations');
+        const link = document.querySelector<HTMLAnchorElement>('.workspace-spotlight-result--annotation')!;
+        expect(link.dataset.annotationId).toBe('anno-1');
+        expect(link.getAttribute('href')).toMatch(/\?highlight=retry%20loop$/);
+        expect(link.innerHTML).toContain('<b>guide</b> timeouts');
+    });
+
     it('closes from Escape inside the navigator input', async () => {
         const nav = new WorkspaceSpotlight({ workspaceId: 'ws' });
         nav.open();
@@ -9,7 +9,7 @@
  */
 
 import { CONFIG, i18n } from '../core/config';
-import { workspaceFilesDataUrl, workspaceFileUrl, workspaceSearchUrl } from '../core/routes';
+import { basePath, workspaceFilesDataUrl, workspaceFileUrl, workspaceSearchUrl } from '../core/routes';
 import { makeModalDraggable, MODAL_FRAME_DRAG_REGION_SELECTOR, renderModalFrameDragRegions } from './modal';
 
 interface WorkspaceFileEntry {
@@ -29,6 +29,16 @@ interface SearchResultPayload {
     title: string;
     file_path: string;
     snippet: string;
+    /** Set when the hit is inside a heading section below the title. */
+    heading: string | null;
+    anchor: string | null;
+}
+
+interface AnnotationHitPayload {
+    file_path: string;
+    annotation_id: string;
+    text: string;
+    snippet: string;
 }
 
 const MAX_RESULTS = 80;
@@ -38,7 +48,10 @@ const CONTENT_QUERY_MIN_LENGTH = 2;
 const CONTENT_SEARCH_DEBOUNCE_MS = 120;
 
 function currentRoutePath(workspaceId: string): string {
-    const raw = window.location.pathname.replace(/^\/+/, '');
+    const base = basePath();
+    const pathname = window.location.pathname;
+    const raw = (base && pathname.startsWith(`${base}/`) ? pathname.slice(base.length) : pathname)
+        .replace(/^\/+/, '');
     const directPrefix = `${workspaceId}/`;
     const underscoredPrefix = `_/${workspaceId}/`;
     const route = raw.startsWith(directPrefix)
@@ -80,6 +93,7 @@ export class WorkspaceSpotlight {
     #files: WorkspaceFileEntry[] | null = null;
     #filesPromise: Promise<WorkspaceFileEntry[]> | null = null;
     #contentResults: SearchResultPayload[] = [];
+    #annotationResults: AnnotationHitPayload[] = [];
     #contentStatus: 'idle' | 'loading' | 'error' = 'idle';
     #contentQuery = '';
     #contentTimer: number | null = null;
@@ -117,6 +131,7 @@ export class WorkspaceSpotlight {
         this.#ensureDraggable();
         this.#input.value = '';
         this.#contentResults = [];
+        this.#annotationResults = [];
         this.#contentStatus = 'idle';
         this.#contentQuery = '';
         this.#render();
@@ -309,6 +324,7 @@ export class WorkspaceSpotlight {
         }
         if (query.length < CONTENT_QUERY_MIN_LENGTH) {
             this.#contentResults = [];
+            this.#annotationResults = [];
             this.#contentStatus = 'idle';
             this.#contentQuery = '';
             this.#render();
@@ -316,6 +332,7 @@ export class WorkspaceSpotlight {
         }
         if (query !== this.#contentQuery) {
             this.#contentResults = [];
+            this.#annotationResults = [];
         }
         this.#contentStatus = 'loading';
         this.#contentQuery = query;
@@ -329,25 +346,29 @@ export class WorkspaceSpotlight {
     async #loadContentResults(query: string): Promise<void> {
         const seq = ++this.#contentSeq;
         try {
-            const response = await fetch(workspaceSearchUrl(this.#workspaceId, query), { credentials: 'same-origin' });
+            const response = await fetch(workspaceSearchUrl(this.#workspaceId, query, MAX_CONTENT_RESULTS), { credentials: 'same-origin' });
             if (!response.ok) throw new Error(response.statusText);
             const raw: unknown = await response.json();
             if (seq !== this.#contentSeq || query !== this.#contentQuery) return;
             this.#contentResults = this.#coerceContentResults(raw);
+            this.#annotationResults = this.#coerceAnnotationResults(raw);
             this.#contentStatus = 'idle';
             this.#render();
         } catch {
             if (seq !== this.#contentSeq) return;
             this.#contentResults = [];
+            this.#annotationResults = [];
             this.#contentStatus = 'error';
             this.#render();
         }
     }
 
     #coerceContentResults(raw: unknown): SearchResultPayload[] {
-        if (!Array.isArray(raw)) return [];
+        // The endpoint returns one page: `{ total, offset, limit, results }`.
+        const hits = raw && typeof raw === 'object' ? (raw as Record<string, unknown>)['results'] : null;
+        if (!Array.isArray(hits)) return [];
         const out: SearchResultPayload[] = [];
-        for (const item of raw) {
+        for (const item of hits) {
             if (
                 item &&
                 typeof item === 'object' &&
@@ -359,16 +380,43 @@ export class WorkspaceSpotlight {
                 const title = obj['title'];
                 const filePath = obj['file_path'];
                 const snippet = obj['snippet'];
+                const heading = obj['heading'];
+                const anchor = obj['anchor'];
                 out.push({
                     title: typeof title === 'string' ? title : '',
                     file_path: typeof filePath === 'string' ? filePath : '',
                     snippet: typeof snippet === 'string' ? snippet : '',
+                    heading: typeof heading === 'string' && heading ? heading : null,
+                    anchor: typeof anchor === 'string' && anchor ? anchor : null,
                 });
             }
         }
         return out;
     }
 
+    #coerceAnnotationResults(raw: unknown): AnnotationHitPayload[] {
+        // Only present when the reader may see shared annotations.
+        const hits = raw && typeof raw === 'object' ? (raw as Record<string, unknown>)['annotations'] : null;
+        if (!Array.isArray(hits)) return [];
+        const out: AnnotationHitPayload[] = [];
+        for (const item of hits) {
+            if (!item || typeof item !== 'object') continue;
+            const obj = item as Record<string, unknown>;
+            const filePath = obj['file_path'];
+            const id = obj['annotation_id'];
+            if (typeof filePath !== 'string' || typeof id !== 'string') continue;
+            const text = obj['text'];
+            const snippet = obj['snippet'];
+            out.push({
+                file_path: filePath,
+                annotation_id: id,
+                text: typeof text === 'string' ? text : '',
+                snippet: typeof snippet === 'string' ? snippet : '',
+            });
+        }
+        return out;
+    }
+
     #render(): void {
         if (!this.#results || !this.#empty) return;
         this.#results.innerHTML = '';
@@ -385,7 +433,8 @@ export class WorkspaceSpotlight {
         const contentList = query.length >= CONTENT_QUERY_MIN_LENGTH
             ? this.#contentResults.slice(0, MAX_CONTENT_RESULTS)
             : [];
-        const hasMatches = fileList.length > 0 || contentList.length > 0;
+        const annotationList = query.length >= CONTENT_QUERY_MIN_LENGTH ? this.#annotationResults : [];
+        const hasMatches = fileList.length > 0 || contentList.length > 0 || annotationList.length > 0;
 
         this.#empty.style.display = hasMatches ? 'none' : '';
         if (!hasMatches) {
@@ -428,13 +477,16 @@ export class WorkspaceSpotlight {
             const li = document.createElement('li');
             const link = document.createElement('a');
             link.className = 'workspace-spotlight-result workspace-spotlight-result--content';
-            link.href = `${workspaceFileUrl(this.#workspaceId, result.file_path)}?highlight=${encodeURIComponent(this.#contentQuery)}`;
+            const hash = result.anchor ? `#${encodeURIComponent(result.anchor)}` : '';
+            link.href = `${workspaceFileUrl(this.#workspaceId, result.file_path)}?highlight=${encodeURIComponent(this.#contentQuery)}${hash}`;
             link.setAttribute('role', 'option');
+            const title = result.title || result.file_path;
+            const label = result.heading ? `${title} › ${result.heading}` : title;
             // Tantivy snippet.to_html() escapes source text and wraps hits in <b>.
             link.innerHTML = `
                 <span class="workspace-spotlight-file-icon" aria-hidden="true"></span>
                 <span class="workspace-spotlight-result-main">
-                    <span class="workspace-spotlight-result-title">${escapeHtml(result.title || result.file_path)}</span>
+                    <span class="workspace-spotlight-result-title">${escapeHtml(label)}</span>
                     <span class="workspace-spotlight-result-path">${highlightPath(result.file_path, query)}</span>
                     <span class="workspace-spotlight-result-snippet">${result.snippet}</span>
                 </span>
@@ -443,6 +495,31 @@ export class WorkspaceSpotlight {
             li.appendChild(link);
             fragment.appendChild(li);
         }
+        if (annotationList.length > 0) {
+            fragment.appendChild(this.#sectionLabel(i18n.t('web.wsnav.annotations')));
+        }
+        for (const hit of annotationList) {
+            const li = document.createElement('li');
+            const link = document.createElement('a');
+            link.className = 'workspace-spotlight-result workspace-spotlight-result--annotation';
+            // Highlight the annotated passage so the page scrolls to it.
+            const target = hit.text.split('\n')[0] || this.#contentQuery;
+            link.href = `${workspaceFileUrl(this.#workspaceId, hit.file_path)}?highlight=${encodeURIComponent(target)}`;
+            link.dataset.annotationId = hit.annotation_id;
+            link.setAttribute('role', 'option');
+            // The server escapes the note and wraps query terms in <b>.
+            link.innerHTML = `
+                <span class="workspace-spotlight-file-icon" aria-hidden="true"></span>
+                <span class="workspace-spotlight-result-main">
+                    <span class="workspace-spotlight-result-title">${escapeHtml(hit.text)}</span>
+                    <span class="workspace-spotlight-result-path">${highlightPath(hit.file_path, query)}</span>
+                    <span class="workspace-spotlight-result-snippet">${hit.snippet}</span>
+                </span>
+                <span class="workspace-spotlight-result-badge">${escapeHtml(i18n.t('web.wsnav.annotation'))}</span>
+            `;
+            li.appendChild(link);
+            fragment.appendChild(li);
+        }
         if (this.#contentStatus === 'loading') {
             fragment.appendChild(this.#statusRow(i18n.t('web.wsnav.searching')));
         } else if (this.#contentStatus === 'error') {
@@ -0,0 +1,29 @@
+import { describe, it, expect, beforeEach } from 'vitest';
+import { AnnotationToken, ANNOTATION_TOKEN_HEADER } from './annotation-token';
+
+describe('AnnotationToken', () => {
+    beforeEach(() => {
+        sessionStorage.clear();
+        history.replaceState(null, '', '/ws/doc.md');
+    });
+
+    it('moves the query parameter into session storage', () => {
+        history.replaceState(null, '', '/ws/doc.md?annotation_token=s3cret&x=1#intro');
+        AnnotationToken.capture();
+        expect(AnnotationToken.get()).toBe('s3cret');
+        expect(window.location.search).toBe('?x=1');
+        expect(window.location.hash).toBe('#intro');
+        expect(AnnotationToken.headers()).toEqual({ [ANNOTATION_TOKEN_HEADER]: 's3cret' });
+    });
+
+    it('leaves storage alone without the parameter and clears on an empty one', () => {
+        AnnotationToken.capture();
+        expect(AnnotationToken.get()).toBeNull();
+        expect(AnnotationToken.headers()).toEqual({});
+
+        sessionStorage.setItem('markon-annotation-token', 'old');
+        history.replaceState(null, '', '/ws/doc.md?annotation_token=');
+        AnnotationToken.capture();
+        expect(AnnotationToken.get()).toBeNull();
+    });
+});
@@ -0,0 +1,39 @@
+/**
+ * Write secret for shared annotations when the server runs with
+ * `--annotation-token`. The presenter opens the page with
+ * `?annotation_token=<secret>`; the value moves into sessionStorage and out of
+ * the address bar (so it is not shared along with the URL) and rides on every
+ * document-state write as `X-Markon-Annotation-Token`.
+ */
+import { CONFIG } from './config';
+
+const KEY = CONFIG.STORAGE_KEYS.ANNOTATION_TOKEN;
+const PARAM = 'annotation_token';
+
+export const ANNOTATION_TOKEN_HEADER = 'X-Markon-Annotation-Token';
+
+export const AnnotationToken = {
+    /** Move a `?annotation_token=` query parameter into session storage. */
+    capture(): void {
+        const url = new URL(window.location.href);
+        const token = url.searchParams.get(PARAM);
+        if (token === null) return;
+        if (token) {
+            sessionStorage.setItem(KEY, token);
+        } else {
+            sessionStorage.removeItem(KEY);
+        }
+        url.searchParams.delete(PARAM);
+        history.replaceState(history.state, '', url.toString());
+    },
+
+    get(): string | null {
+        return sessionStorage.getItem(KEY);
+    },
+
+    /** Headers to add to a write request; empty without a token. */
+    headers(): Record<string, string> {
+        const token = this.get();
+        return token ? { [ANNOTATION_TOKEN_HEADER]: token } : {};
+    },
+};
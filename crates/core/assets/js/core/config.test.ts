@@ -26,6 +26,8 @@ describe('CONFIG.SHORTCUTS', () => {
         expect(CONFIG.SHORTCUTS.DIFF_TOGGLE_VIEW).toMatchObject({ key: 'm', ctrl: false, shift: false });
         expect(CONFIG.SHORTCUTS.DIFF_NEXT_FILE).toMatchObject({ key: 'n', ctrl: false, shift: false });
         expect(CONFIG.SHORTCUTS.DIFF_PREV_FILE).toMatchObject({ key: 'p', ctrl: false, shift: false });
+        expect(CONFIG.SHORTCUTS.COMPARE_NEXT_CHANGE).toMatchObject({ key: 'j', ctrl: false, shift: false });
+        expect(CONFIG.SHORTCUTS.COMPARE_PREV_CHANGE).toMatchObject({ key: 'k', ctrl: false, shift: false });
         expect(CONFIG.SHORTCUTS.VISUAL_ZOOM_TOOL).toMatchObject({ key: 'z', ctrl: false, shift: false });
         expect(CONFIG.SHORTCUTS.VISUAL_ZOOM_TOOL_OUT).toMatchObject({ key: 'z', ctrl: false, shift: true });
         expect(CONFIG.SHORTCUTS.VISUAL_ZOOM_FIT_CMD).toMatchObject({ key: '0', ctrl: true, shift: false });
@@ -70,6 +72,8 @@ describe('CONFIG.SHORTCUTS', () => {
     it('matches the documented snapshot of names', () => {
         expect(Object.keys(CONFIG.SHORTCUTS).sort()).toMatchInlineSnapshot(`
           [
+            "COMPARE_NEXT_CHANGE",
+            "COMPARE_PREV_CHANGE",
             "DIFF_NEXT_FILE",
             "DIFF_PREV_FILE",
             "DIFF_TOGGLE_VIEW",
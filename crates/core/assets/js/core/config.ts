@@ -133,6 +133,10 @@ export const CONFIG = {
         DIFF_TOGGLE_VIEW: { key: 'm', ctrl: false, shift: false, desc: _t('web.kbd.diff.view'), cat: 'diff' },
         DIFF_NEXT_FILE:   { key: 'n', ctrl: false, shift: false, desc: _t('web.kbd.diff.nextfile'), cat: 'diff' },
         DIFF_PREV_FILE:   { key: 'p', ctrl: false, shift: false, desc: _t('web.kbd.diff.prevfile'), cat: 'diff' },
+        // Side-by-side compare page. Shares j / k with heading navigation,
+        // which that page doesn't have.
+        COMPARE_NEXT_CHANGE: { key: 'j', ctrl: false, shift: false, desc: _t('web.kbd.diff.nextfile'), cat: 'diff', feature: 'NEXT_HEADING' },
+        COMPARE_PREV_CHANGE: { key: 'k', ctrl: false, shift: false, desc: _t('web.kbd.diff.prevfile'), cat: 'diff', feature: 'PREV_HEADING' },
 
         // Visual fullscreen viewer. Registered only while the viewer is open.
         VISUAL_ZOOM_IN: { key: '+', ctrl: false, shift: false, desc: _t('web.kbd.visual.zoom_in'), cat: 'visual', feature: 'visual_zoom_in' },
@@ -152,6 +156,7 @@ export const CONFIG = {
         LIVE_POS: 'markon-live-pos',
         LIVE_COLOR: 'markon-user-color',
         IDENTITY_NAME: 'markon-user-name',
+        ANNOTATION_TOKEN: 'markon-annotation-token',
         LIVE_MODE: 'markon-live-mode',
         CLIENT_ID: 'markon-client-id',
         CHAT_POS: 'markon-chat-pos',
@@ -186,6 +191,8 @@ export const CONFIG = {
         WORKSPACE_ID: 'workspace-id',
         CAN_MANAGE: 'can-manage',
         SHARED_ANNOTATION: 'shared-annotation',
+        REQUIRE_NAME: 'require-name',
+        ANNOTATION_TOKEN_REQUIRED: 'annotation-token-required',
         ENABLE_SEARCH: 'enable-search',
         ENABLE_VIEWED: 'enable-viewed',
         ENABLE_EDIT: 'enable-edit',
@@ -1,4 +1,4 @@
-import { describe, it, expect, beforeEach } from 'vitest';
+import { describe, it, expect, beforeEach, vi } from 'vitest';
 import { Identity } from './identity';
 import { CONFIG } from './config';
 
@@ -25,6 +25,16 @@ describe('Identity', () => {
         expect(Identity.name()).toBe('');
     });
 
+    it('ensureName() prompts only while no nickname is set', () => {
+        const prompt = vi.spyOn(window, 'prompt').mockReturnValue('  ana ');
+        Identity.ensureName('Who are you?');
+        expect(prompt).toHaveBeenCalledWith('Who are you?');
+        expect(Identity.name()).toBe('ana');
+        Identity.ensureName('Who are you?');
+        expect(prompt).toHaveBeenCalledTimes(1);
+        prompt.mockRestore();
+    });
+
     it('author() carries colour, and name only when set', () => {
         Identity.setColor('#abcdef');
         expect(Identity.author()).toEqual({ color: '#abcdef' });
@@ -55,6 +55,13 @@ export const Identity = {
         }
     },
 
+    /** Ask for a nickname when the server runs with `--require-name` and
+     *  none is set yet. Cancelling leaves it unset; writes are then refused. */
+    ensureName(message: string): void {
+        if (this.name()) return;
+        this.setName(window.prompt(message) ?? '');
+    },
+
     /** Author stamp for a newly created annotation. Name omitted when unset. */
     author(): Author {
         const author: Author = { color: this.color() };
@@ -1,5 +1,6 @@
-import { describe, expect, it } from 'vitest';
+import { afterEach, describe, expect, it } from 'vitest';
 import {
+    basePath,
     encodePathSegments,
     workspaceChatUrl,
     workspaceChatApiUrl,
@@ -10,6 +11,7 @@ import {
     workspaceRootUrl,
     workspaceSearchUrl,
     workspaceWebSocketUrl,
+    withBase,
 } from './routes';
 
 describe('route helpers', () => {
@@ -31,9 +33,26 @@ describe('route helpers', () => {
 
     it('builds the canonical workspace search URL', () => {
         expect(workspaceSearchUrl('abcd1234', 'hello world')).toBe('/_/abcd1234/search?q=hello%20world');
+        expect(workspaceSearchUrl('abcd1234', 'a&b', 30)).toBe('/_/abcd1234/search?q=a%26b&limit=30');
     });
 
     it('encodes segments without encoding path separators', () => {
         expect(encodePathSegments('a b/c+d/e#f')).toBe('a%20b/c%2Bd/e%23f');
     });
 });
+
+describe('base path', () => {
+    afterEach(() => {
+        document.head.innerHTML = '';
+    });
+
+    it('prefixes every route with the announced base path', () => {
+        expect(basePath()).toBe('');
+        document.head.innerHTML = '<meta name="markon-base-path" content="/docs">';
+        expect(basePath()).toBe('/docs');
+        expect(withBase('/_/admin')).toBe('/docs/_/admin');
+        expect(workspaceFileUrl('abcd1234', 'a.md')).toBe('/docs/abcd1234/a.md');
+        expect(workspaceInternalUrl('abcd1234', 'search')).toBe('/docs/_/abcd1234/search');
+        expect(workspaceChatApiUrl('abcd1234')).toBe('/docs/api/chat/abcd1234');
+    });
+});
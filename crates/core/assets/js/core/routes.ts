@@ -6,8 +6,22 @@
  *   - /{workspace_id}/...       user document/file space
  *   - /_/{workspace_id}/...     workspace tools, data, and chrome pages
  *   - /api/...                  programmatic APIs
+ *
+ * An app mounted under a base path (`router` with `base_path`) announces it
+ * in `<meta name="markon-base-path">`; every builder here puts it in front.
  */
 
+/** The path the app is mounted under (`/docs`), or `''` at the root. */
+export function basePath(): string {
+    const meta = document.querySelector<HTMLMetaElement>('meta[name="markon-base-path"]');
+    return (meta?.content ?? '').replace(/\/+$/, '');
+}
+
+/** `path`, a root-relative URL into the app, under the base path. */
+export function withBase(path: string): string {
+    return `${basePath()}${path}`;
+}
+
 /** Percent-encode path segments while preserving `/` separators. */
 export function encodePathSegments(path: string): string {
     return path
@@ -27,7 +41,7 @@ function cleanToolPath(path: string): string {
 
 export function workspaceRootUrl(workspaceId: string): string {
     const ws = cleanWorkspaceId(workspaceId);
-    return ws ? `/${ws}/` : '/';
+    return withBase(ws ? `/${ws}/` : '/');
 }
 
 export function workspaceFileUrl(workspaceId: string, path = ''): string {
@@ -39,11 +53,12 @@ export function workspaceFileUrl(workspaceId: string, path = ''): string {
 export function workspaceInternalUrl(workspaceId: string, path: string): string {
     const ws = cleanWorkspaceId(workspaceId);
     const rel = cleanToolPath(path);
-    return ws ? `/_/${ws}/${rel}` : `/_/${rel}`;
+    return withBase(ws ? `/_/${ws}/${rel}` : `/_/${rel}`);
 }
 
-export function workspaceSearchUrl(workspaceId: string, query: string): string {
-    return `${workspaceInternalUrl(workspaceId, 'search')}?q=${encodeURIComponent(query)}`;
+export function workspaceSearchUrl(workspaceId: string, query: string, limit?: number): string {
+    const base = `${workspaceInternalUrl(workspaceId, 'search')}?q=${encodeURIComponent(query)}`;
+    return limit === undefined ? base : `${base}&limit=${limit}`;
 }
 
 export function workspaceFilesDataUrl(workspaceId: string): string {
@@ -54,6 +69,18 @@ export function workspaceDocumentStateUrl(workspaceId: string): string {
     return workspaceInternalUrl(workspaceId, 'data/document-state');
 }
 
+export function workspaceViewedStateUrl(workspaceId: string): string {
+    return workspaceInternalUrl(workspaceId, 'data/viewed-state');
+}
+
+export function workspaceAnnotationAttachmentsUrl(workspaceId: string): string {
+    return workspaceInternalUrl(workspaceId, 'annotations/attachments');
+}
+
+export function workspaceAnnotationAttachmentUrl(workspaceId: string, attachmentId: string): string {
+    return `${workspaceAnnotationAttachmentsUrl(workspaceId)}/${encodeURIComponent(attachmentId)}`;
+}
+
 export function workspaceFileDeleteUrl(workspaceId: string): string {
     return workspaceInternalUrl(workspaceId, 'files/delete');
 }
@@ -69,6 +96,6 @@ export function workspaceWebSocketUrl(workspaceId: string): string {
 export function workspaceChatApiUrl(workspaceId: string, path = ''): string {
     const ws = cleanWorkspaceId(workspaceId);
     const rel = cleanToolPath(path);
-    if (!ws) return rel ? `/api/chat/${rel}` : '/api/chat';
-    return rel ? `/api/chat/${ws}/${rel}` : `/api/chat/${ws}`;
+    if (!ws) return withBase(rel ? `/api/chat/${rel}` : '/api/chat');
+    return withBase(rel ? `/api/chat/${ws}/${rel}` : `/api/chat/${ws}`);
 }
@@ -17,11 +17,11 @@
  * and wrapping.
  */
 
-import { CONFIG } from './core/config';
+import { CONFIG, i18n } from './core/config';
 import { copyText, flashBeside, flashCopied } from './core/clipboard';
 import { Meta } from './services/dom';
 import { workspaceFileNoteLink } from './services/note-link';
-import { StorageManager } from './managers/storage-manager';
+import { StaleAnnotationError, StorageManager } from './managers/storage-manager';
 import { AnnotationManager, type Annotation } from './managers/annotation-manager';
 import { EditorManager } from './managers/editor-manager';
 import { NoteManager } from './managers/note-manager';
@@ -282,6 +282,14 @@ class DiffAnnotationCoordinator {
                     }
                     window.getSelection()?.removeAllRanges();
                 })().catch((error: unknown) => {
+                    if (error instanceof StaleAnnotationError) {
+                        ctx.annotationManager.removeFromDOM(error.current.id);
+                        void ctx.annotationManager.add(error.current, true);
+                        ctx.annotationManager.applyToDOM([error.current]);
+                        ctx.noteManager.render();
+                        window.alert(i18n.t('web.collab.stale_annotation'));
+                        return;
+                    }
                     window.alert(error instanceof Error ? error.message : String(error));
                 });
             },
@@ -2,8 +2,8 @@
  * Directory / workspace landing page controls.
  *
  * i18n label application, the Markdown/all file filter, workspace feature
- * toggles, copy-to-clipboard buttons, the workspace dropdown, branch checkout,
- * and the workspace modals for adding files/folders.
+ * toggles, copy-to-clipboard buttons, recent/pinned file pins, the workspace
+ * dropdown, branch checkout, and the workspace modals for adding files/folders.
  *
  * Built as a CLASSIC (IIFE) bundle and loaded as a non-module `<script>` at the
  * same spot in `directory.html` where this used to live inline — it runs during
@@ -15,11 +15,22 @@ import {
     requireActiveAdminSession,
     showAdminActionError,
 } from './core/admin-actions';
+import { flashBeside } from './core/clipboard';
 
 type I18nFn = (key: string) => string;
 
 const t: I18nFn = (window.__MARKON_I18N__?.t) || ((k: string) => k);
 
+/** Reading progress of a file: sections marked viewed out of all sections. */
+interface SectionProgress {
+    viewed: number;
+    total: number;
+    percent: number;
+}
+function progressTitle(viewed: number, total: number): string {
+    return `${t('web.ws.progress')} ${viewed}/${total}`;
+}
+
 // ── Static i18n labels ──────────────────────────────────────────────────────
 const heading = document.getElementById('dir-heading');
 // Only translate when an i18n key is present; a server-rendered alias has no
@@ -44,6 +55,12 @@ document.querySelectorAll<HTMLElement>('[data-i18n-placeholder]').forEach((el) =
 document.querySelectorAll<HTMLElement>('[data-i18n-aria]').forEach((el) => {
     el.setAttribute('aria-label', t(el.getAttribute('data-i18n-aria') || ''));
 });
+document.querySelectorAll<HTMLElement>('.workspace-entry-progress').forEach((badge) => {
+    badge.title = progressTitle(
+        Number(badge.dataset['progressViewed']),
+        Number(badge.dataset['progressTotal']),
+    );
+});
 document.querySelectorAll<HTMLElement>('[data-workspace-spotlight-trigger]').forEach((trigger) => {
     trigger.setAttribute('aria-label', t('web.wsnav.open'));
     trigger.setAttribute('title', t('web.wsnav.open'));
@@ -202,6 +219,32 @@ document.querySelectorAll<HTMLElement>('[data-set-alias]').forEach((button) => {
     });
 });
 
+// ── Recent and pinned files ─────────────────────────────────────────────────
+document.querySelectorAll<HTMLButtonElement>('[data-pin-path]').forEach((button) => {
+    button.addEventListener('click', () => {
+        const url = button.closest('[data-pins-url]')?.getAttribute('data-pins-url');
+        if (!url || button.disabled) return;
+        button.disabled = true;
+        fetch(url, {
+            method: 'POST',
+            credentials: 'same-origin',
+            headers: { 'Content-Type': 'application/json' },
+            body: JSON.stringify({
+                path: button.getAttribute('data-pin-path') || '',
+                pinned: button.getAttribute('data-pinned') !== 'true',
+            }),
+        })
+            .then((resp) => {
+                if (!resp.ok) throw new Error(resp.statusText);
+                window.location.reload();
+            })
+            .catch(() => {
+                button.disabled = false;
+                flashBeside(button, t('web.ws.quick.pin_failed'));
+            });
+    });
+});
+
 // ── Workspace dropdown ──────────────────────────────────────────────────────
 document.querySelectorAll<HTMLElement>('[data-workspace-dropdown] > button').forEach((button) => {
     button.addEventListener('click', (event) => {
@@ -333,21 +376,43 @@ interface DirEntry {
     is_dir: boolean;
     is_markdown: boolean;
     is_hidden: boolean;
+    is_ignored: boolean;
     show_in_markdown: boolean;
     link: string;
     rel_git_path: string;
     last_commit_subject: string | null;
     last_commit_time: string | null;
+    last_commit_author: string | null;
+    /** Bytes and Unix seconds; regular files only. */
+    size?: number;
+    mtime?: number;
+    /** Frontmatter title or first heading of a markdown file. */
+    title?: string;
+    /** Present for markdown files someone has started reading. */
+    progress?: SectionProgress;
+    /** Nested listing, only when fetched with `depth` > 1 (see expandAll). */
+    children?: DirEntry[];
 }
 const dirList = document.querySelector<HTMLElement>('.workspace-repo-file-list[data-dir-data-url]');
 const dirDataUrl = (dirList && dirList.getAttribute('data-dir-data-url')) || '';
+// Nested listings follow the page's `?sort=&order=&show_ignored=` so expanded
+// folders read in the same order, and with the same entries, as the top level.
+const dirSortParams = (() => {
+    const page = new URLSearchParams(location.search);
+    let extra = '';
+    for (const key of ['sort', 'order', 'show_ignored']) {
+        const value = page.get(key);
+        if (value) extra += '&' + key + '=' + encodeURIComponent(value);
+    }
+    return extra;
+})();
 // Per-directory fetch cache (keyed by rel path) so re-expanding never refetches.
 const dirCache = new Map<string, Promise<DirEntry[]>>();
 function fetchDir(dirPath: string): Promise<DirEntry[]> {
     const cached = dirCache.get(dirPath);
     if (cached) return cached;
     if (!dirDataUrl) return Promise.resolve([]);
-    const url = dirDataUrl + '?path=' + encodeURIComponent(dirPath);
+    const url = dirDataUrl + '?path=' + encodeURIComponent(dirPath) + dirSortParams;
     const req = fetch(url, { credentials: 'same-origin' })
         .then((resp) => { if (!resp.ok) throw new Error(resp.statusText); return resp.json() as Promise<DirEntry[]>; })
         .then((entries) => entries || [])
@@ -355,6 +420,37 @@ function fetchDir(dirPath: string): Promise<DirEntry[]> {
     dirCache.set(dirPath, req);
     return req;
 }
+function makeProgressBadge(progress: SectionProgress): HTMLElement {
+    const badge = document.createElement('span');
+    badge.className = 'workspace-entry-progress';
+    badge.classList.toggle('is-complete', progress.percent === 100);
+    badge.style.setProperty('--progress', `${progress.percent}%`);
+    badge.title = progressTitle(progress.viewed, progress.total);
+    badge.textContent = `${progress.percent}%`;
+    return badge;
+}
+// Same shape as the server's `filesizeformat` (1024-based, up to 2 decimals).
+function formatSize(bytes: number): string {
+    const units = ['B', 'kB', 'MB', 'GB', 'TB'];
+    let value = bytes;
+    let unit = 0;
+    while (value >= 1024 && unit < units.length - 1) {
+        value /= 1024;
+        unit += 1;
+    }
+    return `${unit ? String(Number(value.toFixed(2))) : String(value)} ${units[unit]}`;
+}
+function makeEntryMeta(size: number, mtime?: number): HTMLElement {
+    const meta = document.createElement('span');
+    meta.className = 'workspace-entry-meta';
+    meta.textContent = formatSize(size);
+    if (mtime != null) {
+        const iso = new Date(mtime * 1000).toISOString();
+        meta.textContent += ' · ' + iso.slice(0, 10);
+        meta.title = iso.slice(0, 16).replace('T', ' ');
+    }
+    return meta;
+}
 function makeIcon(kind: 'folder' | 'file'): HTMLElement {
     const icon = document.createElement('span');
     icon.className = 'dir-icon dir-icon-' + kind;
@@ -381,6 +477,7 @@ function renderTree(ul: HTMLElement, entries: DirEntry[], depth: number): void {
         li.className = 'workspace-tree-item';
         li.setAttribute('data-entry-path', entry.rel_git_path);
         li.setAttribute('data-filter-visible-markdown', entry.show_in_markdown ? 'true' : 'false');
+        li.setAttribute('data-entry-ignored', entry.is_ignored ? 'true' : 'false');
         const row = document.createElement('div');
         row.className = 'workspace-tree-row';
         const name = document.createElement('div');
@@ -399,11 +496,22 @@ function renderTree(ul: HTMLElement, entries: DirEntry[], depth: number): void {
             name.appendChild(makeIcon('folder'));
             name.appendChild(label);
         } else {
+            // A document title reads better than a date-stamped file name;
+            // the name stays beside it as secondary text.
             const a = document.createElement('a');
             a.href = entry.link;
-            a.textContent = entry.name;
+            a.textContent = entry.title || entry.name;
+            if (entry.title) a.title = entry.title;
             name.appendChild(makeIcon('file'));
             name.appendChild(a);
+            if (entry.title) {
+                const fileName = document.createElement('span');
+                fileName.className = 'workspace-entry-filename';
+                fileName.textContent = entry.name;
+                name.appendChild(fileName);
+            }
+            if (entry.progress) name.appendChild(makeProgressBadge(entry.progress));
+            if (entry.size != null) name.appendChild(makeEntryMeta(entry.size, entry.mtime));
         }
         const commit = document.createElement('div');
         commit.className = 'workspace-entry-commit';
@@ -414,6 +522,7 @@ function renderTree(ul: HTMLElement, entries: DirEntry[], depth: number): void {
         const time = document.createElement('div');
         time.className = 'workspace-entry-time';
         if (entry.last_commit_time) time.textContent = entry.last_commit_time;
+        if (entry.last_commit_author) time.title = entry.last_commit_author;
         row.appendChild(name);
         row.appendChild(commit);
         row.appendChild(time);
@@ -628,6 +737,58 @@ function restoreOpenFromHash(): void {
     };
     void expandTopDir(topBtn).then((ul) => descend(ul, 1));
 }
+// ── Expand / collapse every folder ──────────────────────────────────────────
+// "Expand all" fetches the whole tree under the current listing in one request
+// (`depth`, capped server-side) and seeds `dirCache` from the nested `children`,
+// so the walk below never refetches per folder. Folders the server left
+// unloaded (hidden, or past its entry budget) stay collapsed until clicked.
+const EXPAND_ALL_DEPTH = 8;
+function seedDirCache(entries: DirEntry[]): void {
+    for (const entry of entries) {
+        if (!entry.is_dir || !entry.children) continue;
+        dirCache.set(entry.rel_git_path, Promise.resolve(entry.children));
+        seedDirCache(entry.children);
+    }
+}
+function expandLoaded(ul: HTMLElement | null): Promise<void> {
+    if (!ul) return Promise.resolve();
+    const dirs = Array.from(ul.querySelectorAll<HTMLElement>(':scope > li[data-tree-dir]'))
+        .filter((li) => dirCache.has(li.getAttribute('data-tree-path') || ''));
+    return Promise.all(dirs.map((li) => expandTreeDir(li).then(expandLoaded))).then(() => undefined);
+}
+function expandAll(): void {
+    const buttons = Array.from(document.querySelectorAll<HTMLElement>('[data-dir-toggle]'));
+    const first = buttons[0]?.getAttribute('data-dir-path');
+    if (!dirDataUrl || first == null) return;
+    const base = first.includes('/') ? first.slice(0, first.lastIndexOf('/')) : '';
+    const url = dirDataUrl + '?path=' + encodeURIComponent(base) + '&depth=' + EXPAND_ALL_DEPTH + dirSortParams;
+    fetch(url, { credentials: 'same-origin' })
+        .then((resp) => { if (!resp.ok) throw new Error(resp.statusText); return resp.json() as Promise<DirEntry[]>; })
+        .then((entries) => {
+            seedDirCache(entries || []);
+            const loaded = buttons.filter((b) => dirCache.has(b.getAttribute('data-dir-path') || ''));
+            return Promise.all(loaded.map((b) => expandTopDir(b).then(expandLoaded)));
+        })
+        .catch(() => undefined);
+}
+function collapseAll(): void {
+    document.querySelectorAll<HTMLElement>('[data-dir-toggle][aria-expanded="true"]').forEach((button) => {
+        toggleTopDir(button);
+    });
+    document.querySelectorAll<HTMLElement>('.workspace-tree-row[aria-expanded="true"]').forEach((row) => {
+        row.setAttribute('aria-expanded', 'false');
+        row.parentElement?.querySelector(':scope > ul.workspace-tree')?.classList.add('is-collapsed');
+    });
+    clearWorkspaceTarget();
+    writeHashPath('');
+}
+document.querySelectorAll<HTMLElement>('[data-tree-expand-all]').forEach((button) => {
+    button.addEventListener('click', expandAll);
+});
+document.querySelectorAll<HTMLElement>('[data-tree-collapse-all]').forEach((button) => {
+    button.addEventListener('click', collapseAll);
+});
+
 if (document.readyState === 'loading') {
     document.addEventListener('DOMContentLoaded', restoreOpenFromHash, { once: true });
 } else {
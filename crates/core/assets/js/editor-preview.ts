@@ -0,0 +1,68 @@
+/**
+ * Editor-plugin preview page (`/api/editor/{id}/preview`): follows the
+ * editor events stream named in `<meta name="editor-events-url">`, showing
+ * each `render` and scrolling along with the editor's cursor.
+ *
+ * Classic (IIFE) bundle, loaded as a non-module `<script>` after i18n-boot
+ * and math-render.
+ */
+
+interface EditorRenderEvent {
+    buffer: string;
+    html: string;
+}
+
+interface EditorScrollEvent {
+    buffer: string;
+    heading?: string | null;
+    fraction: number;
+}
+
+interface EditorCloseEvent {
+    buffer: string;
+}
+
+const t: (key: string) => string = (window.__MARKON_I18N__?.t) || ((k: string) => k);
+
+const article = document.getElementById('editor-preview');
+const eventsUrl = document.querySelector<HTMLMetaElement>('meta[name="editor-events-url"]')?.content;
+
+if (article && eventsUrl) {
+    let shown: string | null = null;
+
+    const status = (key: string): void => {
+        const p = document.createElement('p');
+        p.className = 'editor-preview-status';
+        p.textContent = t(key);
+        article.replaceChildren(p);
+    };
+    status('web.editor.waiting');
+
+    const events = new EventSource(eventsUrl);
+    events.addEventListener('render', (e) => {
+        const data = JSON.parse((e as MessageEvent<string>).data) as EditorRenderEvent;
+        shown = data.buffer;
+        article.innerHTML = data.html;
+        window.markonRenderMath?.(article);
+    });
+    events.addEventListener('scroll', (e) => {
+        const data = JSON.parse((e as MessageEvent<string>).data) as EditorScrollEvent;
+        if (data.buffer !== shown) return;
+        const heading = data.heading ? document.getElementById(data.heading) : null;
+        if (heading) {
+            heading.scrollIntoView({ block: 'start' });
+        } else {
+            const range = document.documentElement.scrollHeight - window.innerHeight;
+            window.scrollTo(0, Math.max(0, range) * data.fraction);
+        }
+    });
+    events.addEventListener('close', (e) => {
+        const data = JSON.parse((e as MessageEvent<string>).data) as EditorCloseEvent;
+        if (data.buffer === shown) {
+            shown = null;
+            status('web.editor.closed');
+        }
+    });
+}
+
+export {};
@@ -5,6 +5,10 @@
  *     `window.__markonTocSetSelected` for `main.ts` (j/k navigation) and the
  *     in-content click sync.
  *  2. Static i18n labels for the layout chrome (TOC title, footer, etc.).
+ *  3. Paginated documents (`--paginate`): an `#anchor` that lives on another
+ *     page is followed to that page.
+ *  4. API reference pages: an `#anchor` inside a collapsed operation or schema
+ *     opens it.
  *
  * Built as a CLASSIC (IIFE) bundle and loaded as a non-module `<script>` at the
  * same spot in `layout.html` where these lived inline — so it runs during parse
@@ -260,7 +264,20 @@ function initLayoutI18n(): void {
         'clear-annot-text': 'web.annot.clear',
         'feedback-link-text': 'web.footer.feedback',
         'kbd-link-text': 'web.kbd.link',
+        'present-link-text': 'web.present.link',
+        'print-view-link-text': 'web.print.link',
+        'snapshots-link-text': 'web.snapshots.title',
         'workspace-spotlight-trigger-text': 'web.wsnav.trigger',
+        'document-pager-prev': 'web.doc.page.prev',
+        'document-pager-next': 'web.doc.page.next',
+        'document-commit-label': 'web.doc.last_commit',
+        'document-history-link': 'web.doc.history',
+        'document-converted-label': 'web.doc.converted',
+        'document-archive-label': 'web.doc.archive',
+        'document-original-link': 'web.doc.original',
+        'book-nav-title': 'web.book.contents',
+        'book-pager-prev': 'web.book.prev',
+        'book-pager-next': 'web.book.next',
     };
     for (const [id, key] of Object.entries(labelMap)) {
         const el = document.getElementById(id);
@@ -270,9 +287,55 @@ function initLayoutI18n(): void {
         trigger.setAttribute('aria-label', t('web.wsnav.open'));
         trigger.setAttribute('title', t('web.wsnav.open'));
     });
+    document.getElementById('document-pager')?.setAttribute('aria-label', t('web.doc.page.nav'));
+    document.getElementById('book-pager')?.setAttribute('aria-label', t('web.book.nav'));
+    // Section labels generated into an API reference page carry their key.
+    document.querySelectorAll<HTMLElement>('.openapi-reference [data-i18n], .openapi-source-link[data-i18n]').forEach((el) => {
+        el.textContent = t(el.dataset.i18n || '');
+    });
+}
+
+// ── 3. Anchors on other pages ───────────────────────────────────────────────
+function initDocumentPageAnchors(): void {
+    const data = document.getElementById('document-page-anchors');
+    if (!data) return;
+    let pageOf: Record<string, number>;
+    try {
+        pageOf = JSON.parse(data.textContent || '{}') as Record<string, number>;
+    } catch {
+        return;
+    }
+    const follow = (): void => {
+        let id = location.hash.slice(1);
+        try { id = decodeURIComponent(id); } catch { /* ignore */ }
+        if (!id || document.getElementById(id)) return;
+        const page = pageOf[id];
+        if (page) location.replace(`?page=${page}${location.hash}`);
+    };
+    follow();
+    window.addEventListener('hashchange', follow);
+}
+
+// ── 4. Collapsed API reference sections ─────────────────────────────────────
+function initApiReferenceAnchors(): void {
+    if (!document.querySelector('.openapi-reference')) return;
+    const open = (): void => {
+        let id = location.hash.slice(1);
+        try { id = decodeURIComponent(id); } catch { /* ignore */ }
+        const target = id ? document.getElementById(id) : null;
+        const details = target?.closest('details');
+        if (target && details && !details.open) {
+            details.open = true;
+            target.scrollIntoView();
+        }
+    };
+    open();
+    window.addEventListener('hashchange', open);
 }
 
 initTocTracking();
 initLayoutI18n();
+initDocumentPageAnchors();
+initApiReferenceAnchors();
 
 export {};
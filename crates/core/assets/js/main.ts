@@ -9,10 +9,15 @@
 import { CONFIG, i18n } from './core/config';
 import { Logger } from './core/utils';
 import { copyText, flashBeside, flashCopied } from './core/clipboard';
+import { AnnotationToken } from './core/annotation-token';
+import { withBase, workspaceAnnotationAttachmentUrl } from './core/routes';
+import { Identity } from './core/identity';
 import { Meta } from './services/dom';
 import { Position } from './services/position';
 import { Text } from './services/text';
-import { StorageManager } from './managers/storage-manager';
+import { TextAnchoring } from './services/text-anchor';
+import { StaleAnnotationError, StorageManager } from './managers/storage-manager';
+import { ClientViewedStore } from './managers/client-viewed-store';
 import { WebSocketManager } from './managers/websocket-manager';
 import { AnnotationManager, type Annotation } from './managers/annotation-manager';
 import { NoteManager } from './managers/note-manager';
@@ -28,10 +33,15 @@ import { ChatManager } from './managers/chat-manager';
 import { VisualZoomManager } from './managers/visual-zoom-manager';
 import { TOCNavigator } from './navigators/toc-navigator';
 import { AnnotationNavigator } from './navigators/annotation-navigator';
-import { ModalManager, showConfirmDialog } from './components/modal';
+import { ModalManager, showConfirmDialog, type NoteInputModalOptions } from './components/modal';
 import { FloatingLayer } from './components/floating-layer';
 import { mergeAnnotationSnapshots } from './services/annotation-sync';
-import { currentPageNoteLink, noteLinkIdFromHash } from './services/note-link';
+import {
+    annotationLinkIdFromHash,
+    currentPageNoteLink,
+    noteLinkIdFromHash,
+    readAnnotationIndex,
+} from './services/note-link';
 
 const INTERACTIVE_MARKDOWN_BODY_SELECTOR = '[data-markon-interactive-body]';
 
@@ -86,6 +96,9 @@ export class MarkonApp {
     #filePath: string;
     #isSharedMode: boolean;
     #canUseDocumentState: boolean;
+    /** Shared session under `--annotation-token` without the secret: the
+     *  page shows annotations but offers no way to make them. */
+    #annotationReadOnly: boolean;
     #enableSearch: boolean;
     #enableEdit: boolean;
     #enableLive: boolean;
@@ -105,6 +118,11 @@ export class MarkonApp {
         this.#isSharedMode = config.isSharedMode || false;
         this.#canUseDocumentState =
             Meta.flag(CONFIG.META_TAGS.CAN_MANAGE) || this.#isSharedMode;
+        AnnotationToken.capture();
+        this.#annotationReadOnly =
+            Meta.flag(CONFIG.META_TAGS.ANNOTATION_TOKEN_REQUIRED) &&
+            !Meta.flag(CONFIG.META_TAGS.CAN_MANAGE) &&
+            !AnnotationToken.get();
         this.#enableSearch = config.enableSearch || false;
         this.#enableEdit = config.enableEdit || false;
         this.#enableLive = config.enableLive || false;
@@ -142,7 +160,11 @@ export class MarkonApp {
             return;
         }
 
-        // 1. Initialize storage
+        // 1. Initialize storage. Under `--require-name` the server refuses
+        // unattributed writes, so settle on a nickname first.
+        if (this.#isSharedMode && Meta.flag(CONFIG.META_TAGS.REQUIRE_NAME)) {
+            Identity.ensureName(i18n.t('web.collab.name_required'));
+        }
         await this.#initStorage();
 
         // 2. Initialize managers
@@ -254,11 +276,21 @@ export class MarkonApp {
             this.#setupFileChangedHandler();
         }
 
-        if (!this.#markdownBody || !this.#canUseDocumentState) {
+        if (!this.#markdownBody) {
             return;
         }
 
         const workspaceId = Meta.get(CONFIG.META_TAGS.WORKSPACE_ID) ?? '';
+        if (!this.#canUseDocumentState) {
+            // Without shared annotations a collaborator's checkmarks are
+            // private to this browser.
+            if (workspaceId && window.viewedManager?.enableViewed) {
+                await window.viewedManager
+                    .attachStorage(new ClientViewedStore(this.#filePath, workspaceId), null)
+                    .catch((error: unknown) => Logger.warn('MarkonApp', 'Private viewed state unavailable:', error));
+            }
+            return;
+        }
         if (!workspaceId) {
             Logger.warn('MarkonApp', 'Document state unavailable without workspace-id');
             return;
@@ -377,14 +409,17 @@ export class MarkonApp {
 
     /** Setup event listeners. @private */
     #setupEventListeners(): void {
-        // Selection events
-        document.addEventListener('mouseup', (e) => {
-            this.#popoverManager?.handleSelection(e);
-        });
+        // Selection events (none for read-only guests: the server would
+        // refuse whatever the toolbar creates)
+        if (!this.#annotationReadOnly) {
+            document.addEventListener('mouseup', (e) => {
+                this.#popoverManager?.handleSelection(e);
+            });
 
-        document.addEventListener('touchend', (e) => {
-            this.#popoverManager?.handleSelection(e);
-        });
+            document.addEventListener('touchend', (e) => {
+                this.#popoverManager?.handleSelection(e);
+            });
+        }
 
         // Click on highlighted element
         document.addEventListener('click', (e) => {
@@ -655,6 +690,16 @@ export class MarkonApp {
         window.getSelection()?.removeAllRanges();
     }
 
+    /** Show a newer revision of an annotation in place of this tab's copy. */
+    #replaceAnnotation(annotation: Annotation): void {
+        if (!this.#annotationManager || !this.#noteManager) return;
+        this.#annotationManager.removeFromDOM(annotation.id);
+        void this.#annotationManager.add(annotation, true);
+        this.#annotationManager.applyToDOM([annotation]);
+        this.#noteManager.render();
+        this.#dispatchNotesCountChanged();
+    }
+
     /**
      * Add an annotation and apply it to the DOM. Optionally records the
      * addition on the undo stack and/or re-renders the margin notes.
@@ -739,7 +784,9 @@ export class MarkonApp {
         ModalManager.showNoteInput({
             anchorElement,
             initialValue: annotation ? annotation.note ?? '' : '',
-            onSave: (noteText: string) => {
+            initialAttachments: annotation?.attachments ?? [],
+            ...(this.#storage ? this.#attachmentOptions(this.#storage) : {}),
+            onSave: (noteText: string, attachments: string[]) => {
                 void (async () => {
                     const annotationManager = this.#annotationManager;
                     const noteManager = this.#noteManager;
@@ -748,7 +795,19 @@ export class MarkonApp {
 
                     if (noteText) {
                         if (annotation) {
-                            await annotationManager.add({ ...annotation, note: noteText });
+                            // The server re-renders `noteHtml` for the new text.
+                            const { noteHtml: _stale, attachments: _previous, ...edited } = annotation;
+                            try {
+                                await annotationManager.add({
+                                    ...edited,
+                                    note: noteText,
+                                    ...(attachments.length > 0 ? { attachments } : {}),
+                                });
+                            } catch (error) {
+                                if (!(error instanceof StaleAnnotationError)) throw error;
+                                this.#replaceAnnotation(error.current);
+                                window.alert(i18n.t('web.collab.stale_annotation'));
+                            }
                         } else {
                             const newAnnotation = annotationManager.createAnnotation(
                                 selection,
@@ -756,6 +815,7 @@ export class MarkonApp {
                                 CONFIG.HTML_TAGS.HIGHLIGHT as Annotation['tagName'],
                                 noteText,
                             );
+                            if (attachments.length > 0) newAnnotation.attachments = attachments;
                             await this.#applyAdd(newAnnotation, { pushUndo: true });
                         }
 
@@ -778,6 +838,15 @@ export class MarkonApp {
         });
     }
 
+    /** Note-input options for pasting images into a note. @private */
+    #attachmentOptions(storage: StorageManager): Pick<NoteInputModalOptions, 'uploadAttachment' | 'attachmentUrl'> {
+        const workspaceId = Meta.get(CONFIG.META_TAGS.WORKSPACE_ID) ?? '';
+        return {
+            uploadAttachment: (image) => storage.uploadAttachment(image),
+            attachmentUrl: (id) => workspaceAnnotationAttachmentUrl(workspaceId, id),
+        };
+    }
+
     /** Setup WebSocket message handlers. @private */
     #setupWebSocketHandlers(): void {
         const ws = this.#wsManager;
@@ -830,10 +899,20 @@ export class MarkonApp {
                 Logger.warn('WebSocket', `Dropped new_annotation with invalid id: ${String(incoming?.id)}`);
                 return;
             }
-            // Belt-and-braces: a stray dupe (legacy peer, replay) still gets filtered.
+            if (!TextAnchoring.isAnchor(incoming.anchor)) {
+                Logger.warn('WebSocket', `Dropped new_annotation with a malformed anchor: ${incoming.id}`);
+                return;
+            }
+            // An edit made elsewhere arrives as a newer revision of an
+            // annotation this tab already shows. Anything else is a stray dupe
+            // (legacy peer, replay) and is filtered.
             const existingAnnotation = annotationManager.getById(incoming.id);
             if (existingAnnotation) {
-                Logger.log('WebSocket', `Annotation ${incoming.id} already exists locally, skipping`);
+                if ((incoming.revision ?? 0) > (existingAnnotation.revision ?? 0)) {
+                    this.#replaceAnnotation(incoming);
+                } else {
+                    Logger.log('WebSocket', `Annotation ${incoming.id} already exists locally, skipping`);
+                }
                 return;
             }
 
@@ -1512,9 +1591,22 @@ export class MarkonApp {
     }
 
     #setupNoteLinkHandling(): void {
+        const index = readAnnotationIndex();
         const openFromHash = (): void => {
-            const annotationId = noteLinkIdFromHash(window.location.hash);
-            if (annotationId) this.#focusNoteLink(annotationId);
+            const noteId = noteLinkIdFromHash(window.location.hash);
+            if (noteId) {
+                this.#focusNoteLink(noteId);
+                return;
+            }
+            const annotationId = annotationLinkIdFromHash(window.location.hash);
+            if (!annotationId) return;
+            // The server's index says up front whether the annotation exists,
+            // so a stale link fails at once instead of after the retry window.
+            if (index && !index.some(entry => entry.id === annotationId)) {
+                window.alert(i18n.t('web.annotation.linkmissing'));
+                return;
+            }
+            this.#focusAnnotationLink(annotationId);
         };
         openFromHash();
         window.addEventListener('hashchange', openFromHash);
@@ -1560,6 +1652,37 @@ export class MarkonApp {
         window.alert(i18n.t('web.note.linkmissing'));
     }
 
+    /** Scroll to and flash any annotation's highlight; noted annotations
+     *  also open their note like a note link. */
+    #focusAnnotationLink(annotationId: string, attempts = 50): void {
+        const annotation = this.#annotationManager?.getById(annotationId);
+        if (annotation?.note?.trim()) {
+            this.#focusNoteLink(annotationId, attempts);
+            return;
+        }
+        const highlightElements = Array.from(
+            this.#markdownBody?.querySelectorAll<HTMLElement>(
+                `[data-annotation-id="${annotationId}"]`,
+            ) ?? [],
+        );
+        const first = highlightElements[0];
+        if (annotation && first) {
+            first.scrollIntoView({ behavior: 'smooth', block: 'center' });
+            highlightElements.forEach(el => el.classList.add('annotation-link-target'));
+            window.setTimeout(() => {
+                highlightElements.forEach(el => el.classList.remove('annotation-link-target'));
+            }, 1800);
+            return;
+        }
+
+        if (attempts > 0) {
+            window.setTimeout(() => this.#focusAnnotationLink(annotationId, attempts - 1), 100);
+            return;
+        }
+
+        window.alert(i18n.t('web.annotation.linkmissing'));
+    }
+
     #noteLinkAnchorElement(elements: HTMLElement[], annotationId: string): HTMLElement | null {
         return elements.find((element) => {
             let parent = element.parentElement;
@@ -1787,7 +1910,7 @@ document.addEventListener('DOMContentLoaded', () => {
         let stopped = false;
         const connectConfigWs = (): void => {
             if (stopped) return;
-            const sock = new WebSocket(`${proto}//${location.host}/_/ws/${wsId}`);
+            const sock = new WebSocket(`${proto}//${location.host}${withBase(`/_/ws/${wsId}`)}`);
             sock.onopen = () => {
                 attempt = 0;
             };
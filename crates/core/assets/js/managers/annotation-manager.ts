@@ -88,12 +88,22 @@ export interface Annotation {
     text: string;
     /** Optional attached note body. `null` when absent. */
     note: string | null;
+    /** Server-rendered, sanitized HTML of `note`. Absent until the server has
+     *  stored the current note; never set by the client. */
+    noteHtml?: string;
+    /** Ids of images attached to the note, served from the workspace's
+     *  annotation attachment route. */
+    attachments?: string[];
     /** Creation timestamp (ms since epoch). */
     createdAt: number;
     /** Author stamp (colour + optional nickname), snapshotted at creation.
      *  Absent on annotations created before authorship existed → render
      *  anonymously (neutral). */
     author?: Author;
+    /** Server-assigned edit revision, bumped on every save. Saves send back
+     *  the revision they were edited from; an older one is rejected. Absent
+     *  until first saved. */
+    revision?: number;
 }
 
 /** Storage strategy contract used by AnnotationManager.
@@ -250,7 +260,7 @@ export class AnnotationManager {
                     // feature injects into headings.
                     const clone = prev.cloneNode(true) as HTMLElement;
                     clone.querySelectorAll(
-                        '.viewed-checkbox-label, .viewed-toolbar, .section-actions, .section-action, .section-action-separator',
+                        '.viewed-checkbox-label, .viewed-changed-badge, .viewed-toolbar, .section-actions, .section-action, .section-action-separator',
                     ).forEach((el) => el.remove());
                     return {
                         level: parseInt(prev.tagName.substring(1), 10),
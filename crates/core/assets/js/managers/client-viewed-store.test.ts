@@ -0,0 +1,55 @@
+import { afterEach, describe, expect, it, vi } from 'vitest';
+import { ClientViewedStore } from './client-viewed-store.js';
+
+function jsonResponse(body: unknown) {
+    return {
+        ok: true,
+        status: 200,
+        text: async (): Promise<string> => '',
+        json: async (): Promise<unknown> => body,
+    };
+}
+
+describe('ClientViewedStore private viewed state', () => {
+    afterEach(() => {
+        vi.unstubAllGlobals();
+        vi.restoreAllMocks();
+    });
+
+    it('loads once and saves after the load issued the client cookie', async () => {
+        const fetchMock = vi.fn(async (_url: string, init?: RequestInit) =>
+            init?.method === 'POST'
+                ? { ok: true, status: 204, text: async (): Promise<string> => '' }
+                : jsonResponse({ viewed_state: { intro: false }, viewed_changed: ['intro'] }),
+        );
+        vi.stubGlobal('fetch', fetchMock);
+        const store = new ClientViewedStore('/docs/foo.md', 'abc123');
+
+        expect(await store.loadViewedState()).toEqual({ intro: false });
+        expect(await store.loadViewedChanged()).toEqual(['intro']);
+        await store.saveViewedState({ intro: true });
+
+        expect(fetchMock).toHaveBeenCalledTimes(2);
+        expect(String(fetchMock.mock.calls[0]?.[0])).toContain(
+            '/_/abc123/data/viewed-state?path=%2Fdocs%2Ffoo.md',
+        );
+        const [url, init] = fetchMock.mock.calls[1] as unknown as [string, RequestInit];
+        expect(url).toBe('/_/abc123/data/viewed-state');
+        expect(JSON.parse(init.body as string)).toEqual({
+            path: '/docs/foo.md',
+            state: { intro: true },
+        });
+        expect(await store.loadViewedChanged()).toEqual([]);
+    });
+
+    it('rejects a failed save', async () => {
+        vi.stubGlobal('fetch', vi.fn(async (_url: string, init?: RequestInit) =>
+            init?.method === 'POST'
+                ? { ok: false, status: 403, text: async (): Promise<string> => 'forbidden' }
+                : jsonResponse({ viewed_state: {} }),
+        ));
+        const store = new ClientViewedStore('foo.md', 'abc123');
+
+        await expect(store.saveViewedState({ intro: true })).rejects.toThrow('viewed state save failed (403)');
+    });
+});
@@ -0,0 +1,77 @@
+/**
+ * ClientViewedStore - private viewed state for collaborators.
+ *
+ * Without shared annotations a collaborator has no document state, so their
+ * checkmarks are kept per browser instead: the server keys them by an
+ * HttpOnly client cookie it issues on the first load. They survive browser
+ * restarts and are never broadcast to other viewers.
+ */
+
+import { workspaceViewedStateUrl } from '../core/routes';
+
+type ViewedSnapshot = {
+    viewed_state: Record<string, boolean>;
+    viewed_changed?: string[];
+};
+
+export class ClientViewedStore {
+    #workspaceId: string;
+    #filePath: string;
+    #snapshot: Promise<ViewedSnapshot> | null = null;
+    #viewedChanged: string[] = [];
+    #writeQueue: Promise<void> = Promise.resolve();
+
+    constructor(filePath: string, workspaceId: string) {
+        this.#workspaceId = workspaceId;
+        this.#filePath = filePath;
+    }
+
+    async #loadSnapshot(): Promise<ViewedSnapshot> {
+        this.#snapshot ??= (async () => {
+            const query = new URLSearchParams({ path: this.#filePath });
+            const response = await fetch(
+                `${workspaceViewedStateUrl(this.#workspaceId)}?${query.toString()}`,
+                { credentials: 'same-origin', cache: 'no-store' },
+            );
+            if (!response.ok) {
+                throw new Error(`viewed state load failed (${response.status}): ${await response.text()}`);
+            }
+            const snapshot = await response.json() as ViewedSnapshot;
+            this.#viewedChanged = [...(snapshot.viewed_changed ?? [])];
+            return snapshot;
+        })();
+        return this.#snapshot;
+    }
+
+    async loadViewedState(): Promise<Record<string, boolean>> {
+        const snapshot = await this.#loadSnapshot();
+        return { ...(snapshot.viewed_state ?? {}) };
+    }
+
+    async loadViewedChanged(): Promise<string[]> {
+        await this.#loadSnapshot();
+        return [...this.#viewedChanged];
+    }
+
+    async saveViewedState(viewedState: Record<string, boolean>): Promise<void> {
+        // The load issues the client cookie a save needs.
+        await this.#loadSnapshot();
+        const state = { ...viewedState };
+        const run = async (): Promise<void> => {
+            const response = await fetch(workspaceViewedStateUrl(this.#workspaceId), {
+                method: 'POST',
+                credentials: 'same-origin',
+                headers: { 'Content-Type': 'application/json' },
+                body: JSON.stringify({ path: this.#filePath, state }),
+            });
+            if (!response.ok) {
+                throw new Error(`viewed state save failed (${response.status}): ${await response.text()}`);
+            }
+        };
+        // Serialize writes so a slow earlier save cannot overwrite a newer one.
+        const pending = this.#writeQueue.then(run, run);
+        this.#writeQueue = pending.then(() => {}, () => {});
+        await pending;
+        this.#viewedChanged = this.#viewedChanged.filter((id) => !state[id]);
+    }
+}
@@ -5,7 +5,12 @@ import {
     type CollaborationApp,
     type LiveAction,
 } from './collaboration-manager';
-import type { WebSocketManager, WsHandler, WsInbound } from './websocket-manager';
+import type {
+    SelectionRange,
+    WebSocketManager,
+    WsHandler,
+    WsInbound,
+} from './websocket-manager';
 import { CONFIG } from '../core/config';
 
 /**
@@ -15,28 +20,27 @@ import { CONFIG } from '../core/config';
  */
 function makeFakeWs() {
     const sent: unknown[] = [];
-    const liveHandlers: WsHandler<'live_action'>[] = [];
+    const handlers = new Map<string, ((msg: WsInbound) => void)[]>();
     const fake = {
         isConnected: () => true,
         on: vi.fn(<T extends WsInbound['type']>(type: T, handler: WsHandler<T>): void => {
-            if (type === 'live_action') {
-                liveHandlers.push(handler as unknown as WsHandler<'live_action'>);
-            }
+            handlers.set(type, [...(handlers.get(type) ?? []), handler as (msg: WsInbound) => void]);
         }),
         send: vi.fn(async (msg: unknown) => {
             sent.push(msg);
         }),
     };
     /**
-     * Test helper: inject a `live_action` frame as if it had arrived from
-     * the server, exercising the handler that init() registered.
+     * Test helpers: inject a frame as if it had arrived from the server,
+     * exercising the handlers that init() registered.
      */
+    const dispatch = (msg: WsInbound): void => {
+        for (const h of handlers.get(msg.type) ?? []) h(msg);
+    };
     const dispatchLiveAction = (data: LiveAction): void => {
-        for (const h of liveHandlers) {
-            h({ type: 'live_action', data: data as unknown as { action: string; [k: string]: unknown } });
-        }
+        dispatch({ type: 'live_action', data: data as unknown as { action: string; [k: string]: unknown } });
     };
-    return { ws: fake as unknown as WebSocketManager, sent, dispatchLiveAction };
+    return { ws: fake as unknown as WebSocketManager, sent, dispatch, dispatchLiveAction };
 }
 
 /**
@@ -308,4 +312,90 @@ describe('CollaborationManager', () => {
         expect(focused).toBeTruthy();
         expect(focused?.id).toBe('intro');
     });
+
+    it('presence frames drive the viewer badge and list', () => {
+        const { ws, dispatch } = makeFakeWs();
+        const mgr = new CollaborationManager({ enableLive: true, ws });
+        mgr.init();
+        trackLayers(mgr);
+        const badge = document.querySelector<HTMLElement>('.presence-count');
+        const list = document.querySelector<HTMLElement>('.presence-list');
+
+        dispatch({ type: 'presence', client_id: 'me', viewers: [{ client_id: 'me' }] });
+        expect(badge?.hidden).toBe(true);
+        expect(list?.hidden).toBe(true);
+
+        // Our own join echo is ignored; someone else's adds a dot.
+        dispatch({ type: 'presence_join', viewer: { client_id: 'me' }, viewers: 1 });
+        dispatch({
+            type: 'presence_join',
+            viewer: { client_id: 'other', identity: { color: '#0EA5E9', name: 'Ada' } },
+            viewers: 2,
+        });
+        expect(badge?.hidden).toBe(false);
+        expect(badge?.textContent).toBe('2');
+        const dots = list?.querySelectorAll<HTMLElement>('.presence-viewer') ?? [];
+        expect(dots).toHaveLength(2);
+        expect(itemAt(dots, 0).classList.contains('self')).toBe(true);
+        expect(itemAt(dots, 1).title).toBe('Ada');
+
+        dispatch({ type: 'presence_leave', client_id: 'other', viewers: 1 });
+        expect(badge?.hidden).toBe(true);
+        expect(mgr.viewers).toEqual([{ client_id: 'me' }]);
+    });
+
+    it('shares our selection and draws other viewers\' selections', () => {
+        const article = setupArticle();
+        const { ws, sent, dispatch } = makeFakeWs();
+        localStorage.setItem('markon-live-mode', 'follow');
+        const mgr = new CollaborationManager({ enableLive: true, ws });
+        mgr.init();
+        trackLayers(mgr);
+        dispatch({ type: 'presence', client_id: 'me', viewers: [{ client_id: 'me' }] });
+        // jsdom has no layout; give ranges one box to draw.
+        vi.spyOn(Range.prototype, 'getClientRects').mockReturnValue(
+            [new DOMRect(10, 20, 30, 16)] as unknown as DOMRectList,
+        );
+
+        const text = article.querySelector('p')?.firstChild;
+        if (!text) throw new Error('missing paragraph text');
+        const range = document.createRange();
+        range.setStart(text, 0);
+        range.setEnd(text, 5);
+        window.getSelection()?.removeAllRanges();
+        window.getSelection()?.addRange(range);
+        mgr._shareSelection(article);
+        mgr._shareSelection(article);
+        const shared = sent.filter((m) => (m as { type: string }).type === 'selection');
+        expect(shared).toEqual([{
+            type: 'selection',
+            range: {
+                startPath: '//article[1]/DIV[1]/P[1]',
+                startOffset: 0,
+                endPath: '//article[1]/DIV[1]/P[1]',
+                endOffset: 5,
+            },
+        }]);
+
+        const remote = itemAt(shared, 0) as { range: SelectionRange };
+        dispatch({ type: 'selection', client_id: 'me', range: remote.range });
+        expect(document.querySelector('.markon-remote-selection')).toBeNull();
+        dispatch({
+            type: 'selection',
+            client_id: 'other',
+            identity: { color: '#0EA5E9', name: 'Ada' },
+            range: remote.range,
+        });
+        const overlay = document.querySelector<HTMLElement>(
+            '.markon-remote-selection[data-client-id="other"]',
+        );
+        expect(overlay?.style.getPropertyValue('--viewer-color')).toBe('#0EA5E9');
+        expect(overlay?.querySelectorAll('.markon-remote-selection-rect')).toHaveLength(1);
+
+        dispatch({ type: 'selection', client_id: 'other', range: null });
+        expect(document.querySelector('.markon-remote-selection')).toBeNull();
+        dispatch({ type: 'selection', client_id: 'other', range: remote.range });
+        dispatch({ type: 'presence_leave', client_id: 'other', viewers: 1 });
+        expect(document.querySelector('.markon-remote-selection')).toBeNull();
+    });
 });
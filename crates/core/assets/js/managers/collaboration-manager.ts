@@ -14,13 +14,14 @@
  */
 
 import { CONFIG, i18n } from '../core/config';
-import { Identity } from '../core/identity';
+import { Identity, type Author } from '../core/identity';
 import { Ids, Logger } from '../core/utils';
+import { withBase } from '../core/routes';
 import { Meta } from '../services/dom';
 import { Position } from '../services/position';
 import { XPath } from '../services/xpath';
 import { FloatingLayer } from '../components/floating-layer';
-import type { WebSocketManager } from './websocket-manager';
+import type { PresenceViewer, SelectionRange, WebSocketManager } from './websocket-manager';
 
 const _t = (key: string, ...args: unknown[]): string => i18n.t(key, ...args);
 
@@ -44,6 +45,12 @@ const ICON_IDENTITY = `
     <path d="M5.5 19a6.6 6.6 0 0 1 13 0"/>
 </svg>`;
 
+const ICON_VIEWERS = `
+<svg viewBox="0 0 24 24" width="15" height="15" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round" aria-hidden="true">
+    <path d="M2 12s3.6-6.5 10-6.5S22 12 22 12s-3.6 6.5-10 6.5S2 12 2 12z"/>
+    <circle cx="12" cy="12" r="2.8"/>
+</svg>`;
+
 const ICON_LIVE = `
 <svg viewBox="0 0 24 24" width="15" height="15" fill="none" stroke="currentColor" stroke-width="2" stroke-linecap="round" stroke-linejoin="round" aria-hidden="true">
     <circle cx="12" cy="12" r="1.7" fill="currentColor" stroke="none"/>
@@ -149,6 +156,12 @@ export class CollaborationManager {
     mode: LiveModeValue;
     activeLeader: LiveAction | null;
     leaderTimer: ReturnType<typeof setTimeout> | null;
+    /** Everyone viewing this document, ourselves included, in join order. */
+    viewers: PresenceViewer[] = [];
+    /** Our server-assigned presence id (distinct from the Live clientId). */
+    presenceId: string | null = null;
+    /** What other viewers currently have selected, by presence id. */
+    remoteSelections = new Map<string, { range: SelectionRange; identity: Author | undefined }>();
 
     container: HTMLElement | null;
     sphere: HTMLElement | null;
@@ -160,6 +173,7 @@ export class CollaborationManager {
     _applyingRemote?: boolean;
     _lastFocusXPath?: string;
     _lastSelectionKey?: string | null;
+    _lastSharedSelectionKey?: string | null;
     _lastViewportKey?: string | null;
     // Retained so a future destroy() / reinit cycle can detach without
     // leaking accumulated observers on every call to _observeFocusedSection.
@@ -212,6 +226,35 @@ export class CollaborationManager {
             // inside `handleLiveAction`.
             this.handleLiveAction(msg.data as unknown as LiveAction);
         });
+        this.app.ws.on('presence', (msg) => {
+            this.presenceId = msg.client_id;
+            this.viewers = msg.viewers;
+            this._renderPresence();
+        });
+        this.app.ws.on('presence_join', (msg) => {
+            const id = msg.viewer.client_id;
+            if (id === this.presenceId || this.viewers.some((v) => v.client_id === id)) return;
+            this.viewers = [...this.viewers, msg.viewer];
+            this._renderPresence();
+        });
+        this.app.ws.on('presence_leave', (msg) => {
+            this.viewers = this.viewers.filter((v) => v.client_id !== msg.client_id);
+            this._renderPresence();
+            this.remoteSelections.delete(msg.client_id);
+            this._renderRemoteSelection(msg.client_id);
+        });
+        this.app.ws.on('selection', (msg) => {
+            if (msg.client_id === this.presenceId) return;
+            if (msg.range && this.liveEnabled && this.mode !== LiveMode.OFF) {
+                this.remoteSelections.set(msg.client_id, {
+                    range: msg.range,
+                    identity: msg.identity,
+                });
+            } else {
+                this.remoteSelections.delete(msg.client_id);
+            }
+            this._renderRemoteSelection(msg.client_id);
+        });
 
         if (this.isBroadcasting) {
             this._broadcastNavigation();
@@ -245,6 +288,7 @@ export class CollaborationManager {
         if (ACTIVE_MODES.includes(mode)) this._lastActiveMode = mode;
         this.mode = mode;
         localStorage.setItem(CONFIG.STORAGE_KEYS.LIVE_MODE, mode);
+        if (mode === LiveMode.OFF) this._clearRemoteSelections();
         this._updateUIState();
         if (mode === LiveMode.BROADCAST) {
             this._broadcastNavigation();
@@ -324,8 +368,8 @@ export class CollaborationManager {
         }
         if (url.origin !== window.location.origin) return null;
         if (!this.workspaceId) return url;
-        const wsRoot = `/${this.workspaceId}`;
-        const internalRoot = `/_/${this.workspaceId}`;
+        const wsRoot = withBase(`/${this.workspaceId}`);
+        const internalRoot = withBase(`/_/${this.workspaceId}`);
         const path = url.pathname;
         if (
             path !== wsRoot &&
@@ -437,27 +481,35 @@ export class CollaborationManager {
         }
     }
 
-    _applySelection(data: LiveSelectionAction): void {
+    /** Rebuild a DOM range from its Live XPath/offset form; `null` when the
+     *  paths no longer resolve in this page. */
+    _resolveRange(data: Partial<SelectionRange>): Range | null {
         if (
             data.startPath === undefined ||
             data.endPath === undefined ||
             data.startOffset === undefined ||
             data.endOffset === undefined
-        ) return;
+        ) return null;
 
         const startEl = XPath.resolve(data.startPath);
         const endEl = XPath.resolve(data.endPath);
-        if (!startEl || !endEl) return;
+        if (!startEl || !endEl) return null;
         const start = XPath.findNode(startEl, data.startOffset);
         const end = XPath.findNode(endEl, data.endOffset);
-        if (!start.node || !end.node) return;
+        if (!start.node || !end.node) return null;
         const range = document.createRange();
         try {
             range.setStart(start.node, start.offset);
             range.setEnd(end.node, end.offset);
         } catch {
-            return;
+            return null;
         }
+        return range;
+    }
+
+    _applySelection(data: LiveSelectionAction): void {
+        const range = this._resolveRange(data);
+        if (!range) return;
         this._applyingRemote = true;
         try {
             // Rendering = native browser selection. No custom color, no
@@ -551,7 +603,14 @@ export class CollaborationManager {
         let timer: ReturnType<typeof setTimeout> | undefined;
         document.addEventListener('selectionchange', () => {
             if (timer) clearTimeout(timer);
-            timer = setTimeout(() => this._broadcastSelection(article), 120);
+            timer = setTimeout(() => {
+                this._broadcastSelection(article);
+                this._shareSelection(article);
+            }, 120);
+        });
+        // Remote selections are drawn in page coordinates; reflow moves them.
+        window.addEventListener('resize', () => {
+            for (const id of this.remoteSelections.keys()) this._renderRemoteSelection(id);
         });
     }
 
@@ -586,19 +645,13 @@ export class CollaborationManager {
         });
     }
 
-    _broadcastSelection(article: Element): void {
-        if (this._applyingRemote) return;
-        if (!this.isBroadcasting || !this.app.ws) return;
+    /** The local selection in Live form: `null` when nothing is selected,
+     *  `undefined` when the selection lies outside the article. */
+    _currentSelectionRange(article: Element): SelectionRange | null | undefined {
         const sel = window.getSelection();
-        if (!sel || sel.rangeCount === 0 || sel.isCollapsed) {
-            if (this._lastSelectionKey) {
-                this._lastSelectionKey = null;
-                this.broadcastAction('selection', { cleared: true });
-            }
-            return;
-        }
+        if (!sel || sel.rangeCount === 0 || sel.isCollapsed) return null;
         const range = sel.getRangeAt(0);
-        if (!article.contains(range.commonAncestorContainer)) return;
+        if (!article.contains(range.commonAncestorContainer)) return undefined;
         const startParent: Node | null =
             range.startContainer.nodeType === 3
                 ? (range.startContainer.parentElement)
@@ -607,13 +660,27 @@ export class CollaborationManager {
             range.endContainer.nodeType === 3
                 ? (range.endContainer.parentElement)
                 : range.endContainer;
-        if (!startParent || !endParent) return;
-        const payload = {
+        if (!startParent || !endParent) return undefined;
+        return {
             startPath: XPath.create(startParent),
             startOffset: XPath.getAbsoluteOffset(range.startContainer, range.startOffset),
             endPath: XPath.create(endParent),
             endOffset: XPath.getAbsoluteOffset(range.endContainer, range.endOffset),
         };
+    }
+
+    _broadcastSelection(article: Element): void {
+        if (this._applyingRemote) return;
+        if (!this.isBroadcasting || !this.app.ws) return;
+        const payload = this._currentSelectionRange(article);
+        if (payload === undefined) return;
+        if (payload === null) {
+            if (this._lastSelectionKey) {
+                this._lastSelectionKey = null;
+                this.broadcastAction('selection', { cleared: true });
+            }
+            return;
+        }
         const key =
             `${payload.startPath}|${payload.startOffset}|${payload.endPath}|${payload.endOffset}`;
         if (key === this._lastSelectionKey) return;
@@ -621,6 +688,57 @@ export class CollaborationManager {
         this.broadcastAction('selection', payload);
     }
 
+    /** Show everyone in the document what we have selected, in any active
+     *  mode. Unlike Broadcast this never moves anyone's page or selection;
+     *  peers just see a tinted overlay in our colour. */
+    _shareSelection(article: Element): void {
+        if (this._applyingRemote) return;
+        if (!this.liveEnabled || this.mode === LiveMode.OFF || !this.app.ws) return;
+        const range = this._currentSelectionRange(article);
+        if (range === undefined) return;
+        const key = range
+            ? `${range.startPath}|${range.startOffset}|${range.endPath}|${range.endOffset}`
+            : null;
+        if (key === (this._lastSharedSelectionKey ?? null)) return;
+        this._lastSharedSelectionKey = key;
+        void this.app.ws.send({ type: 'selection', range });
+    }
+
+    /** Redraw one viewer's selection overlay (or remove it when they have
+     *  none). Overlays sit above the text and ignore the pointer. */
+    _renderRemoteSelection(clientId: string): void {
+        document
+            .querySelectorAll(`.markon-remote-selection[data-client-id="${CSS.escape(clientId)}"]`)
+            .forEach((el) => el.remove());
+        const selection = this.remoteSelections.get(clientId);
+        if (!selection) return;
+        const range = this._resolveRange(selection.range);
+        if (!range) return;
+        const overlay = document.createElement('div');
+        overlay.className = 'markon-remote-selection';
+        overlay.dataset['clientId'] = clientId;
+        if (selection.identity?.color) {
+            overlay.style.setProperty('--viewer-color', selection.identity.color);
+        }
+        for (const rect of Array.from(range.getClientRects())) {
+            if (rect.width === 0 || rect.height === 0) continue;
+            const box = document.createElement('div');
+            box.className = 'markon-remote-selection-rect';
+            box.style.left = `${rect.left + window.scrollX}px`;
+            box.style.top = `${rect.top + window.scrollY}px`;
+            box.style.width = `${rect.width}px`;
+            box.style.height = `${rect.height}px`;
+            overlay.appendChild(box);
+        }
+        document.body.appendChild(overlay);
+    }
+
+    _clearRemoteSelections(): void {
+        const ids = [...this.remoteSelections.keys()];
+        this.remoteSelections.clear();
+        ids.forEach((id) => this._renderRemoteSelection(id));
+    }
+
     _createUI(): void {
         const modeLabel = (m: LiveModeValue): string =>
             ({
@@ -663,6 +781,7 @@ export class CollaborationManager {
                         <path d="M7 7 L17 17 M17 7 L7 17"/>
                     </svg>
                     <div class="leader-info"></div>
+                    <span class="presence-count" title="${_t('web.collab.viewing')}" hidden></span>
                 </div>
                 <div class="markon-live-body">
                     <div class="panel-header">${_t('web.collab.title')}</div>
@@ -670,7 +789,9 @@ export class CollaborationManager {
                     <div class="panel-row-color">
                         <div class="color-picker">${colorDots}</div>
                         <input type="text" class="identity-name" maxlength="24" placeholder="${_t('web.collab.nickname')}">
-                    </div>${liveSection}
+                    </div>
+                    <div class="panel-section-label presence-label" role="img" aria-label="${_t('web.collab.viewing')}" title="${_t('web.collab.viewing')}" hidden>${ICON_VIEWERS}</div>
+                    <div class="presence-list" hidden></div>${liveSection}
                 </div>
             </div>
         `;
@@ -799,6 +920,35 @@ export class CollaborationManager {
         }
     }
 
+    /** Badge on the sphere with the viewer count, plus one dot per viewer in
+     *  the panel. Both stay hidden while we are the only one here. */
+    _renderPresence(): void {
+        if (!this.container) return;
+        const others = this.viewers.length > 1;
+        const badge = this.container.querySelector<HTMLElement>('.presence-count');
+        if (badge) {
+            badge.textContent = String(this.viewers.length);
+            badge.hidden = !others;
+        }
+        const label = this.container.querySelector<HTMLElement>('.presence-label');
+        if (label) label.hidden = !others;
+        const list = this.container.querySelector<HTMLElement>('.presence-list');
+        if (!list) return;
+        list.hidden = !others;
+        list.replaceChildren(
+            ...this.viewers.map((viewer) => {
+                const dot = document.createElement('span');
+                dot.className = 'presence-viewer';
+                dot.classList.toggle('self', viewer.client_id === this.presenceId);
+                if (viewer.identity?.color) {
+                    dot.style.setProperty('--viewer-color', viewer.identity.color);
+                }
+                dot.title = viewer.identity?.name || _t('web.author.anon');
+                return dot;
+            }),
+        );
+    }
+
     _getOrCreateClientId(): string {
         let id = sessionStorage.getItem(CONFIG.STORAGE_KEYS.CLIENT_ID);
         if (!id) {
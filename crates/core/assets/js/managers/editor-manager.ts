@@ -12,6 +12,7 @@ import { Meta } from '../services/dom';
 import { Text } from '../services/text';
 import { downloadTextFile, toMarkdownFilename } from '../core/download';
 import { copyText, flashText } from '../core/clipboard';
+import { withBase } from '../core/routes';
 
 const _t = (key: string, ...args: unknown[]): string => i18n.t(key, ...args);
 
@@ -371,7 +372,7 @@ export class EditorManager {
                 file_path: this.#filePath,
                 content,
             };
-            const response = await fetch('/api/save', {
+            const response = await fetch(withBase('/api/save'), {
                 method: 'POST',
                 headers: {
                     'Content-Type': 'application/json',
@@ -857,7 +858,7 @@ export class EditorManager {
             content,
         };
         try {
-            const response = await fetch('/api/preview', {
+            const response = await fetch(withBase('/api/preview'), {
                 method: 'POST',
                 headers: {
                     'Content-Type': 'application/json',
@@ -905,9 +906,9 @@ export class EditorManager {
         if (window.markonRenderMath) return Promise.resolve();
         if (this.#mathRendererPromise) return this.#mathRendererPromise;
 
-        this.#ensureStylesheet('/_/js/katex/katex.min.css');
-        this.#mathRendererPromise = this.#loadScript('/_/js/katex/katex.min.js')
-            .then(() => this.#loadScript('/_/js/math-render.js'))
+        this.#ensureStylesheet(withBase('/_/js/katex/katex.min.css'));
+        this.#mathRendererPromise = this.#loadScript(withBase('/_/js/katex/katex.min.js'))
+            .then(() => this.#loadScript(withBase('/_/js/math-render.js')))
             .catch((err: unknown) => {
                 this.#mathRendererPromise = null;
                 Logger.warn('EditorManager', 'Math renderer failed to load:', err);
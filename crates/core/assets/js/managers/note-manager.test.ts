@@ -185,6 +185,43 @@ describe('NoteManager', () => {
         expect(card.querySelector('a[href^="javascript:"]')).toBeNull();
     });
 
+    it('shows the server-rendered noteHtml when present', () => {
+        const root = setupBody(`<p><span class="has-note" data-annotation-id="x">y</span></p>`);
+        const annos = [
+            makeAnno({
+                id: 'x',
+                note: 'use `retry()`',
+                noteHtml: '<p>use <code>retry()</code></p>\n',
+            }),
+        ];
+        const mgr = new NoteManager(fakeAnnotationManager(annos), root);
+        mgr.render();
+
+        const card = document.querySelector('.note-card-margin .note-content')!;
+        expect(card.classList.contains('note-markdown')).toBe(true);
+        expect(card.querySelector('code')?.textContent).toBe('retry()');
+        expect(mgr.getNoteCardsData()[0]?.noteHtml).toContain('<code>');
+    });
+
+    it('shows image attachments as thumbnails served by the workspace', () => {
+        const meta = document.createElement('meta');
+        meta.name = 'workspace-id';
+        meta.content = 'ws1';
+        document.head.appendChild(meta);
+        const root = setupBody(`<p><span class="has-note" data-annotation-id="x">y</span></p>`);
+        const id = `${'a'.repeat(64)}.png`;
+        const mgr = new NoteManager(
+            fakeAnnotationManager([makeAnno({ id: 'x', note: 'see screenshot', attachments: [id] })]),
+            root,
+        );
+        mgr.render();
+
+        const image = document.querySelector<HTMLImageElement>('.note-card-margin .note-attachments img');
+        expect(image?.getAttribute('src')).toBe(`/_/ws1/annotations/attachments/${id}`);
+        expect(mgr.getNoteCardsData()[0]?.attachments).toEqual([id]);
+        meta.remove();
+    });
+
     it('clear() removes all note cards and resets state', () => {
         const root = setupBody(`<p><span class="has-note" data-annotation-id="a">x</span></p>`);
         const mgr = new NoteManager(
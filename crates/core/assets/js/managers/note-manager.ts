@@ -5,6 +5,8 @@
 
 import { CONFIG, i18n } from '../core/config';
 import { PlatformUtils, Logger } from '../core/utils';
+import { workspaceAnnotationAttachmentUrl } from '../core/routes';
+import { Meta } from '../services/dom';
 import { LayoutEngine } from '../services/layout';
 import { Text } from '../services/text';
 import type { AnnotationManager, Annotation } from './annotation-manager';
@@ -73,6 +75,27 @@ function renderNoteContent(note: string): string {
     return parts.join('');
 }
 
+/** The `.note-content` block: the server's rendered markdown when present,
+ *  else the plain note with its URLs linked. */
+function noteContentHtml(note: string, noteHtml: string | undefined): string {
+    return noteHtml !== undefined
+        ? `<div class="note-content note-markdown">${noteHtml}</div>`
+        : `<div class="note-content">${renderNoteContent(note)}</div>`;
+}
+
+/** Thumbnails of the note's image attachments, each linking to the full
+ *  image. Empty when there are none. */
+function noteAttachmentsHtml(attachments: string[] | undefined): string {
+    if (!attachments?.length) return '';
+    const workspaceId = Meta.get(CONFIG.META_TAGS.WORKSPACE_ID) ?? '';
+    const alt = Text.escape(_t('web.modal.note.attachment'));
+    const images = attachments.map((id) => {
+        const url = Text.escape(workspaceAnnotationAttachmentUrl(workspaceId, id));
+        return `<a href="${url}" target="_blank" rel="noopener noreferrer"><img src="${url}" alt="${alt}" loading="lazy"></a>`;
+    });
+    return `<div class="note-attachments">${images.join('')}</div>`;
+}
+
 /**
  * Internal in-memory record kept per rendered note card. Exposed via
  * `getNoteCardsData()` (currently only consumed by unit tests).
@@ -86,6 +109,10 @@ export interface NoteCard {
     highlightElement: Element;
     /** Mirrored from the annotation's `note` field (always non-empty here). */
     note: string;
+    /** Mirrored from the annotation's server-rendered `noteHtml`, if any. */
+    noteHtml?: string | undefined;
+    /** Mirrored from the annotation's image `attachments`, if any. */
+    attachments?: string[] | undefined;
 }
 
 /** NoteManager construction options. */
@@ -169,6 +196,8 @@ export class NoteManager {
                 highlightId: anno.id,
                 highlightElement,
                 note: anno.note,
+                noteHtml: anno.noteHtml,
+                attachments: anno.attachments,
             });
         });
 
@@ -329,7 +358,8 @@ export class NoteManager {
 
         noteCard.innerHTML = `
             ${this.#noteActionsHtml(annotation.id)}
-            <div class="note-content">${renderNoteContent(annotation.note ?? '')}</div>
+            ${noteContentHtml(annotation.note ?? '', annotation.noteHtml)}
+            ${noteAttachmentsHtml(annotation.attachments)}
             ${this.#noteAuthorLine(annotation)}
         `;
 
@@ -606,7 +636,8 @@ export class NoteManager {
         popup.dataset['annotationId'] = annotationId;
         popup.innerHTML = `
             ${this.#noteActionsHtml(annotationId)}
-            <div class="note-content">${renderNoteContent(noteData.note)}</div>
+            ${noteContentHtml(noteData.note, noteData.noteHtml)}
+            ${noteAttachmentsHtml(noteData.attachments)}
         `;
 
         // Position the popup below the highlight.
@@ -1,5 +1,5 @@
 import { afterEach, beforeEach, describe, expect, it, vi } from 'vitest';
-import { StorageManager } from './storage-manager.js';
+import { StaleAnnotationError, StorageManager } from './storage-manager.js';
 import type { Annotation } from './annotation-manager.js';
 import type { WebSocketManager } from './websocket-manager.js';
 
@@ -111,6 +111,37 @@ describe('StorageManager SQLite-only document state', () => {
         expect(localStorage.length).toBe(0);
     });
 
+    it('continues from the stored revision and rejects stale saves with the current copy', async () => {
+        const current = makeAnno({ id: 'x1', note: 'theirs', revision: 3 });
+        const responses = [
+            {
+                ok: true,
+                status: 200,
+                text: async (): Promise<string> => '',
+                json: async (): Promise<Annotation> => makeAnno({ id: 'x1', note: 'mine', revision: 2 }),
+            },
+            {
+                ok: false,
+                status: 409,
+                text: async (): Promise<string> => '',
+                json: async () => ({ success: false, annotation: current }),
+            },
+        ];
+        vi.stubGlobal('fetch', vi.fn(async (_url: string, init?: RequestInit) =>
+            init?.method === 'POST' ? responses.shift() : snapshotResponse(),
+        ));
+        const manager = new StorageManager('foo.md', false, null, 'abc123');
+        const mine = makeAnno({ id: 'x1', note: 'mine', revision: 1 });
+
+        await manager.saveAnnotation(mine);
+        expect(mine.revision).toBe(2);
+
+        const stale = manager.saveAnnotation({ ...mine, note: 'again' });
+        await expect(stale).rejects.toBeInstanceOf(StaleAnnotationError);
+        await expect(stale).rejects.toMatchObject({ current });
+        expect(await manager.loadAnnotations()).toEqual([current]);
+    });
+
     it('round-trips version 2 fragment anchors without changing the persisted schema', async () => {
         const annotation = makeAnno({
             id: 'cross',
@@ -195,12 +226,14 @@ describe('StorageManager SQLite-only document state', () => {
         await manager.clearAnnotations();
         expect(await manager.loadAnnotations()).toEqual([]);
 
-        const actions = fetchMock.mock.calls
+        const commands = fetchMock.mock.calls
             .filter(([, init]) => init?.method === 'POST')
             .map(([, init]) =>
-                (JSON.parse(init?.body as string) as { action: string }).action,
+                JSON.parse(init?.body as string) as { action: string; by?: { color?: string } },
             );
-        expect(actions).toEqual(['delete_annotation', 'clear_annotations']);
+        expect(commands.map((command) => command.action)).toEqual(['delete_annotation', 'clear_annotations']);
+        // The deleter's identity goes along for the server's audit log.
+        expect(commands.every((command) => typeof command.by?.color === 'string')).toBe(true);
         expect(localStorage.length).toBe(0);
     });
 
@@ -6,7 +6,9 @@
  * browser never mirrors, migrates, queues, or falls back to localStorage.
  */
 
-import { workspaceDocumentStateUrl } from '../core/routes';
+import { AnnotationToken } from '../core/annotation-token';
+import { Identity } from '../core/identity';
+import { workspaceAnnotationAttachmentsUrl, workspaceDocumentStateUrl } from '../core/routes';
 import { Logger } from '../core/utils';
 import type { Annotation } from './annotation-manager';
 import { makeOpId, type WebSocketManager } from './websocket-manager';
@@ -14,8 +16,22 @@ import { makeOpId, type WebSocketManager } from './websocket-manager';
 type DocumentSnapshot = {
     annotations: Annotation[];
     viewed_state: Record<string, boolean>;
+    /** Sections the server unchecked because they changed after being viewed. */
+    viewed_changed?: string[];
 };
 
+/** A save rejected because someone else saved the annotation after this
+ *  copy was loaded. Carries the stored version. */
+export class StaleAnnotationError extends Error {
+    readonly current: Annotation;
+
+    constructor(current: Annotation) {
+        super(`annotation ${current.id} was changed by someone else`);
+        this.name = 'StaleAnnotationError';
+        this.current = current;
+    }
+}
+
 export class StorageManager {
     #workspaceId: string;
     #filePath: string;
@@ -24,6 +40,7 @@ export class StorageManager {
     #snapshot: Promise<DocumentSnapshot> | null = null;
     #annotations: Annotation[] = [];
     #viewedState: Record<string, boolean> = {};
+    #viewedChanged: string[] = [];
     #writeQueue: Promise<void> = Promise.resolve();
 
     constructor(
@@ -55,15 +72,17 @@ export class StorageManager {
             const snapshot = await response.json() as DocumentSnapshot;
             this.#annotations = [...(snapshot.annotations ?? [])];
             this.#viewedState = { ...(snapshot.viewed_state ?? {}) };
+            this.#viewedChanged = [...(snapshot.viewed_changed ?? [])];
             return {
                 annotations: [...this.#annotations],
                 viewed_state: { ...this.#viewedState },
+                viewed_changed: [...this.#viewedChanged],
             };
         })();
         return this.#snapshot;
     }
 
-    async #post(command: Record<string, unknown>): Promise<string | null> {
+    async #post(command: Record<string, unknown>): Promise<{ opId: string | null; body: unknown }> {
         let opId: string | null = null;
         if (this.#isSharedMode && this.#wsManager?.isConnected()) {
             opId = makeOpId();
@@ -74,21 +93,26 @@ export class StorageManager {
         // Independent fetches are not ordered. Serialize all mutations for one
         // document so a slow earlier viewed-state write cannot overwrite the
         // user's newest state.
-        const run = async (): Promise<void> => {
+        const run = async (): Promise<unknown> => {
             const response = await fetch(workspaceDocumentStateUrl(this.#workspaceId), {
                 method: 'POST',
                 credentials: 'same-origin',
-                headers: { 'Content-Type': 'application/json' },
+                headers: { 'Content-Type': 'application/json', ...AnnotationToken.headers() },
                 body: JSON.stringify(command),
             });
+            if (response.status === 409) {
+                const { annotation } = await response.json() as { annotation: Annotation };
+                throw new StaleAnnotationError(annotation);
+            }
             if (!response.ok) {
                 throw new Error(`document state save failed (${response.status}): ${await response.text()}`);
             }
+            return response.status === 204 ? null : response.json();
         };
         const pending = this.#writeQueue.then(run, run);
-        this.#writeQueue = pending.catch(() => {});
-        await pending;
-        return opId;
+        this.#writeQueue = pending.then(() => {}, () => {});
+        const body = await pending;
+        return { opId, body };
     }
 
     async loadAnnotations(): Promise<Annotation[]> {
@@ -96,24 +120,39 @@ export class StorageManager {
         return [...this.#annotations];
     }
 
+    /** Save `annotation` and stamp the revision the server stored it under.
+     *  Rejects with {@link StaleAnnotationError} when the copy is outdated. */
     async saveAnnotation(annotation: Annotation): Promise<string | null> {
         await this.#loadSnapshot();
-        const opId = await this.#post({
-            action: 'save_annotation',
-            path: this.#filePath,
-            annotation,
-        });
+        let saved: { opId: string | null; body: unknown };
+        try {
+            saved = await this.#post({
+                action: 'save_annotation',
+                path: this.#filePath,
+                annotation,
+                by: Identity.author(),
+            });
+        } catch (error) {
+            if (error instanceof StaleAnnotationError) {
+                this.#annotations = this.#annotations.filter(item => item.id !== annotation.id);
+                this.#annotations.push(error.current);
+            }
+            throw error;
+        }
+        const stored = saved.body as Annotation | null;
+        if (stored?.revision !== undefined) annotation.revision = stored.revision;
         this.#annotations = this.#annotations.filter(item => item.id !== annotation.id);
         this.#annotations.push(annotation);
-        return opId;
+        return saved.opId;
     }
 
     async deleteAnnotation(annotationId: string): Promise<string | null> {
         await this.#loadSnapshot();
-        const opId = await this.#post({
+        const { opId } = await this.#post({
             action: 'delete_annotation',
             path: this.#filePath,
             id: annotationId,
+            by: Identity.author(),
         });
         this.#annotations = this.#annotations.filter(item => item.id !== annotationId);
         return opId;
@@ -121,26 +160,50 @@ export class StorageManager {
 
     async clearAnnotations(): Promise<string | null> {
         await this.#loadSnapshot();
-        const opId = await this.#post({
+        const { opId } = await this.#post({
             action: 'clear_annotations',
             path: this.#filePath,
+            by: Identity.author(),
         });
         this.#annotations = [];
         return opId;
     }
 
+    /** Upload an image for an annotation's `attachments`; resolves to its id. */
+    async uploadAttachment(image: Blob): Promise<string> {
+        const response = await fetch(workspaceAnnotationAttachmentsUrl(this.#workspaceId), {
+            method: 'POST',
+            credentials: 'same-origin',
+            headers: { 'Content-Type': image.type || 'application/octet-stream', ...AnnotationToken.headers() },
+            body: image,
+        });
+        if (!response.ok) {
+            throw new Error(`attachment upload failed (${response.status}): ${await response.text()}`);
+        }
+        const { id } = await response.json() as { id: string };
+        return id;
+    }
+
     async loadViewedState(): Promise<Record<string, boolean>> {
         await this.#loadSnapshot();
         return { ...this.#viewedState };
     }
 
+    /** Heading ids flagged as changed since viewed; checking one clears it. */
+    async loadViewedChanged(): Promise<string[]> {
+        await this.#loadSnapshot();
+        return [...this.#viewedChanged];
+    }
+
     async saveViewedState(viewedState: Record<string, boolean>): Promise<void> {
         await this.#post({
             action: 'save_viewed_state',
             path: this.#filePath,
             state: viewedState,
+            by: Identity.author(),
         });
         this.#viewedState = { ...viewedState };
+        this.#viewedChanged = this.#viewedChanged.filter((id) => !viewedState[id]);
     }
 
     async clearViewedState(): Promise<void> {
@@ -148,6 +211,7 @@ export class StorageManager {
             action: 'save_viewed_state',
             path: this.#filePath,
             state: {},
+            by: Identity.author(),
         });
         this.#viewedState = {};
     }
@@ -76,6 +76,7 @@ describe('WebSocketManager', () => {
     });
 
     afterEach(() => {
+        localStorage.clear();
         vi.unstubAllGlobals();
         vi.useRealTimers();
         logSpy.mockRestore();
@@ -84,6 +85,8 @@ describe('WebSocketManager', () => {
     });
 
     it('connect() resolves on onopen and transitions to CONNECTED', async () => {
+        localStorage.setItem('markon-user-color', '#0EA5E9');
+        localStorage.setItem('markon-user-name', 'Ada');
         const m = documentManager('/workspace/docs/intro.md');
         const states: string[] = [];
         m.onStateChange((next) => states.push(next));
@@ -101,6 +104,7 @@ describe('WebSocketManager', () => {
         expect(JSON.parse(itemAt(ws.sent, 0))).toEqual({
             type: 'hello',
             target: { kind: 'document', path: '/workspace/docs/intro.md' },
+            identity: { color: '#0EA5E9', name: 'Ada' },
         });
     });
 
@@ -148,6 +152,25 @@ describe('WebSocketManager', () => {
         expect(newAnno).not.toHaveBeenCalled();
     });
 
+    it('reports error replies for frames the server rejected', async () => {
+        const m = documentManager();
+        const handler = vi.fn();
+        m.on('error', handler);
+
+        await m.connect();
+        itemAt(MockWS.instances, 0).dispatchMessage({
+            type: 'error',
+            reason: 'invalid_live_action',
+            message: 'live_action data must be an object with a string `action`',
+        });
+
+        expect(handler).toHaveBeenCalledTimes(1);
+        expect(errorSpy).toHaveBeenCalledWith(
+            '[WebSocket]',
+            expect.stringContaining('invalid_live_action'),
+        );
+    });
+
     it('off() unregisters a handler', async () => {
         const m = documentManager();
         const handler = vi.fn();
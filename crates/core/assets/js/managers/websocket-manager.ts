@@ -5,6 +5,7 @@
  */
 
 import { CONFIG } from '../core/config';
+import { Identity, type Author } from '../core/identity';
 import { workspaceWebSocketUrl } from '../core/routes';
 import { Logger } from '../core/utils';
 
@@ -35,16 +36,47 @@ export type WsInbound =
     | { type: 'new_annotation'; annotation: unknown; op_id?: string | null }
     | { type: 'delete_annotation'; id: string; op_id?: string | null }
     | { type: 'clear_annotations'; op_id?: string | null }
-    | { type: 'viewed_state'; state: Record<string, boolean>; op_id?: string | null }
+    | { type: 'viewed_state'; state: Record<string, boolean>; by?: Author; changed?: string[]; op_id?: string | null }
     | { type: 'live_action'; data: { action: string; [k: string]: unknown } }
-    | { type: 'file_changed'; workspace_id: string; path: string };
+    | { type: 'file_changed'; workspace_id: string; path: string }
+    /** Our own presence id plus everyone viewing the document, sent on join. */
+    | { type: 'presence'; client_id: string; viewers: PresenceViewer[] }
+    | { type: 'presence_join'; viewer: PresenceViewer; viewers: number }
+    | { type: 'presence_leave'; client_id: string; viewers: number }
+    /** Another viewer's current selection; `range: null` means cleared. */
+    | {
+          type: 'selection';
+          client_id: string;
+          identity?: Author;
+          range: SelectionRange | null;
+      }
+    /** Reply to a frame of ours the server rejected (nothing was relayed). */
+    | { type: 'error'; reason: string; message: string };
 
 /**
  * WebSocket input is intentionally Live-only. Annotation/viewed mutations use
  * the document-state HTTP endpoint and are broadcast back as `WsInbound`.
+ * Selections are ephemeral; the server throttles and relays them, never
+ * stores them.
  */
 export type WsOutbound =
-    | { type: 'live_action'; data: { action: string; [k: string]: unknown } };
+    | { type: 'live_action'; data: { action: string; [k: string]: unknown } }
+    | { type: 'selection'; range: SelectionRange | null };
+
+/** A text range addressed the way Live addresses it: article XPaths plus
+ *  text offsets within those elements. */
+export interface SelectionRange {
+    startPath: string;
+    startOffset: number;
+    endPath: string;
+    endOffset: number;
+}
+
+/** One browser connected to the same document. */
+export interface PresenceViewer {
+    client_id: string;
+    identity?: Author;
+}
 
 export type WsTarget =
     | { kind: 'document'; path: string }
@@ -137,8 +169,12 @@ export class WebSocketManager {
                     // Declare the authorized document/surface channel before
                     // any collaboration message. The server rejects malformed,
                     // out-of-workspace, and feature-incompatible targets.
+                    // Document hellos carry our identity for presence.
                     if (this.#ws) {
-                        this.#ws.send(JSON.stringify({ type: 'hello', target: this.#target }));
+                        const hello = this.#target.kind === 'document'
+                            ? { type: 'hello', target: this.#target, identity: Identity.author() }
+                            : { type: 'hello', target: this.#target };
+                        this.#ws.send(JSON.stringify(hello));
                         Logger.log('WebSocket', `Sent ${this.#target.kind} hello`);
                     }
 
@@ -300,6 +336,9 @@ export class WebSocketManager {
             const raw = JSON.parse(event.data as string) as unknown;
             if (!raw || typeof raw !== 'object' || !('type' in raw)) return;
             const message = raw as WsInbound;
+            if (message.type === 'error') {
+                Logger.error('WebSocket', `Server rejected a message (${message.reason}): ${message.message}`);
+            }
             const handlers = this.#messageHandlers.get(message.type);
             if (!handlers || handlers.length === 0) {
                 // No handler yet — buffer (bounded) so it can be replayed once
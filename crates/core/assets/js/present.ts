@@ -0,0 +1,127 @@
+/**
+ * Presentation mode (present.html): show one slide at a time and move with
+ * the keyboard, a click or a tap.
+ *
+ * The current slide lives in the URL hash (`#slide-3`), so a reload or a
+ * shared link keeps the place. Windows of the same deck in one browser —
+ * the audience view and the speaker view opened with S — follow each other
+ * over a BroadcastChannel. Classic (IIFE) bundle, loaded after i18n-boot.
+ */
+
+const t: (key: string) => string = (window.__MARKON_I18N__?.t) || ((k: string) => k);
+
+document.querySelectorAll<HTMLElement>('[data-i18n]').forEach((el) => {
+    el.textContent = t(el.getAttribute('data-i18n') || '');
+});
+
+const slides = Array.from(document.querySelectorAll<HTMLElement>('.present-slide'));
+const counter = document.getElementById('present-counter');
+const nextPreview = document.getElementById('present-next');
+const speakerNotes = document.getElementById('present-speaker-notes');
+const speaker = document.body.classList.contains('present-speaker');
+const channel = 'BroadcastChannel' in window
+    ? new BroadcastChannel(`markon-present:${location.pathname}`)
+    : null;
+let current = -1;
+
+function indexFromHash(): number {
+    const match = /^#slide-(\d+)$/.exec(location.hash);
+    return match ? Number(match[1]) - 1 : 0;
+}
+
+/** Copy a slide's content (not its notes) into `target`. */
+function mirror(target: HTMLElement | null, slide: HTMLElement | undefined, notes: boolean): void {
+    if (!target) return;
+    const source = slide?.querySelector(notes ? '.present-notes' : '.present-content');
+    target.replaceChildren(...(source ? Array.from(source.cloneNode(true).childNodes) : []));
+}
+
+function show(index: number, broadcast = true): void {
+    const next = Math.max(0, Math.min(slides.length - 1, index));
+    if (next === current || slides.length === 0) return;
+    current = next;
+    slides.forEach((slide, i) => {
+        slide.hidden = i !== current;
+    });
+    slides[current].parentElement?.scrollTo(0, 0);
+    document.body.classList.toggle('present-first', current === 0);
+    if (counter) counter.textContent = `${current + 1} / ${slides.length}`;
+    mirror(nextPreview, slides[current + 1], false);
+    mirror(speakerNotes, slides[current], true);
+    history.replaceState(null, '', `#slide-${current + 1}`);
+    if (broadcast) channel?.postMessage({ slide: current });
+}
+
+function toggleFullscreen(): void {
+    if (document.fullscreenElement) {
+        void document.exitFullscreen();
+    } else {
+        void document.documentElement.requestFullscreen?.();
+    }
+}
+
+document.addEventListener('keydown', (event) => {
+    if (event.ctrlKey || event.metaKey || event.altKey) return;
+    switch (event.key) {
+        case 'ArrowRight':
+        case 'ArrowDown':
+        case 'PageDown':
+        case ' ':
+        case 'n':
+            show(current + (event.shiftKey && event.key === ' ' ? -1 : 1));
+            break;
+        case 'ArrowLeft':
+        case 'ArrowUp':
+        case 'PageUp':
+        case 'Backspace':
+        case 'p':
+            show(current - 1);
+            break;
+        case 'Home':
+            show(0);
+            break;
+        case 'End':
+            show(slides.length - 1);
+            break;
+        case 'f':
+            toggleFullscreen();
+            break;
+        case 'q':
+            document.body.classList.toggle('present-qr-open');
+            break;
+        case 's':
+            if (!speaker) {
+                window.open(`${location.pathname}?speaker=true#slide-${current + 1}`, 'markon-speaker');
+            }
+            break;
+        case 'Escape':
+            document.body.classList.remove('present-qr-open');
+            return;
+        default:
+            return;
+    }
+    event.preventDefault();
+});
+
+// A click or tap on the right two thirds advances, on the left goes back.
+document.getElementById('present-deck')?.addEventListener('click', (event) => {
+    if ((event.target as Element).closest('a, button, input, summary, details')) return;
+    if (window.getSelection()?.toString()) return;
+    show(event.clientX < window.innerWidth / 3 ? current - 1 : current + 1);
+});
+
+window.addEventListener('hashchange', () => show(indexFromHash()));
+channel?.addEventListener('message', (event: MessageEvent<{ slide?: number }>) => {
+    if (typeof event.data?.slide === 'number') show(event.data.slide, false);
+});
+
+const timer = document.getElementById('present-timer');
+if (timer) {
+    const started = Date.now();
+    window.setInterval(() => {
+        const seconds = Math.floor((Date.now() - started) / 1000);
+        timer.textContent = `${Math.floor(seconds / 60)}:${String(seconds % 60).padStart(2, '0')}`;
+    }, 1000);
+}
+
+show(indexFromHash(), false);
@@ -18,6 +18,7 @@ const ANNOTATION_CHROME_SELECTOR = [
     '.viewed-checkbox',
     '.viewed-checkbox-label',
     '.viewed-text',
+    '.viewed-changed-badge',
     '.viewed-toolbar',
     '.section-toggle-btn',
     '.section-actions',
@@ -1,8 +1,11 @@
 import { describe, expect, it } from 'vitest';
 import {
+    annotationLinkHash,
+    annotationLinkIdFromHash,
     currentPageNoteLink,
     noteLinkHash,
     noteLinkIdFromHash,
+    readAnnotationIndex,
     workspaceFileNoteLink,
 } from './note-link';
 
@@ -31,4 +34,24 @@ describe('note-link', () => {
         const link = workspaceFileNoteLink('9b964b8d', 'docs/a b.md', 'anno-abc');
         expect(link).toBe(`${window.location.origin}/9b964b8d/docs/a%20b.md#note-anno-abc`);
     });
+
+    it('round-trips annotation permalink hashes separately from note hashes', () => {
+        const hash = annotationLinkHash('anno-abc');
+        expect(hash).toBe('annotation-anno-abc');
+        expect(annotationLinkIdFromHash(`#${hash}`)).toBe('anno-abc');
+        expect(annotationLinkIdFromHash('#note-anno-abc')).toBeNull();
+        expect(noteLinkIdFromHash(`#${hash}`)).toBeNull();
+        expect(annotationLinkIdFromHash('#annotation-bad')).toBeNull();
+    });
+
+    it('reads the server-embedded annotation index', () => {
+        expect(readAnnotationIndex()).toBeNull();
+        const script = document.createElement('script');
+        script.type = 'application/json';
+        script.id = 'annotation-index';
+        script.textContent = '[{"id":"anno-a","type":"has-note","position":4},{"id":"anno-b","position":null}]';
+        document.body.appendChild(script);
+        expect(readAnnotationIndex()?.map(entry => entry.id)).toEqual(['anno-a', 'anno-b']);
+        script.remove();
+    });
 });
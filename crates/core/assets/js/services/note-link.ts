@@ -1,6 +1,7 @@
 import { workspaceFileUrl } from '../core/routes';
 
 const NOTE_HASH_PREFIX = 'note-';
+const ANNOTATION_HASH_PREFIX = 'annotation-';
 const VALID_ANNOTATION_ID = /^anno-[A-Za-z0-9-]{1,64}$/;
 
 export function isValidNoteLinkId(id: string): boolean {
@@ -23,6 +24,43 @@ export function noteLinkIdFromHash(hash: string): string | null {
     }
 }
 
+/** `#annotation-<id>` permalink hash for any annotation, noted or not. */
+export function annotationLinkHash(annotationId: string): string {
+    if (!isValidNoteLinkId(annotationId)) return '';
+    return `${ANNOTATION_HASH_PREFIX}${encodeURIComponent(annotationId)}`;
+}
+
+export function annotationLinkIdFromHash(hash: string): string | null {
+    const raw = hash.replace(/^#/, '');
+    if (!raw.startsWith(ANNOTATION_HASH_PREFIX)) return null;
+    try {
+        const id = decodeURIComponent(raw.slice(ANNOTATION_HASH_PREFIX.length));
+        return isValidNoteLinkId(id) ? id : null;
+    } catch {
+        return null;
+    }
+}
+
+/** One entry of the page's server-embedded annotation index. */
+export interface AnnotationIndexEntry {
+    id: string;
+    type?: string;
+    position: number | null;
+}
+
+/** The annotation ids of this document in position order, as embedded by the
+ *  server; `null` when the page carries no index (annotations not visible). */
+export function readAnnotationIndex(doc: Document = document): AnnotationIndexEntry[] | null {
+    const data = doc.getElementById('annotation-index')?.textContent;
+    if (!data) return null;
+    try {
+        const parsed: unknown = JSON.parse(data);
+        return Array.isArray(parsed) ? parsed as AnnotationIndexEntry[] : null;
+    } catch {
+        return null;
+    }
+}
+
 export function currentPageNoteLink(annotationId: string, href = window.location.href): string {
     const hash = noteLinkHash(annotationId);
     if (!hash) return '';
@@ -177,4 +177,20 @@ describe('TextAnchoring', () => {
         expect(back?.startContainer).toBe(root.querySelectorAll('li')[0]?.firstChild);
         expect(back?.endContainer).toBe(root.querySelectorAll('li')[2]?.firstChild);
     });
+
+    it('recognizes legacy and cross-block anchors and rejects malformed ones', () => {
+        const quote = { position: 3, exact: 'first', prefix: '', suffix: '' };
+        expect(TextAnchoring.isAnchor(quote)).toBe(true);
+        expect(TextAnchoring.isAnchor({
+            ...quote,
+            version: 2,
+            fragments: [{ ...quote, blockTag: 'LI' }, { ...quote, position: 9, exact: 'second', blockTag: 'LI' }],
+        })).toBe(true);
+
+        expect(TextAnchoring.isAnchor(null)).toBe(false);
+        expect(TextAnchoring.isAnchor({ ...quote, position: -1 })).toBe(false);
+        expect(TextAnchoring.isAnchor({ ...quote, fragments: [{ ...quote, blockTag: 'P' }] })).toBe(false);
+        expect(TextAnchoring.isAnchor({ ...quote, version: 2, fragments: [] })).toBe(false);
+        expect(TextAnchoring.isAnchor({ ...quote, version: 2, fragments: [quote] })).toBe(false);
+    });
 });
@@ -328,7 +328,32 @@ export interface AnchorOptions {
     blockFor?: BlockResolver;
 }
 
+function isQuote(value: unknown): value is TextQuoteAnchor {
+    if (!value || typeof value !== 'object') return false;
+    const quote = value as Record<string, unknown>;
+    return Number.isInteger(quote['position'])
+        && (quote['position'] as number) >= 0
+        && typeof quote['exact'] === 'string'
+        && typeof quote['prefix'] === 'string'
+        && typeof quote['suffix'] === 'string';
+}
+
 export const TextAnchoring = {
+    /** Whether `value` is a well-formed anchor: a flat quote, plus for version
+     *  2 a non-empty list of block fragments. Guards peer-supplied payloads. */
+    isAnchor(value: unknown): value is TextAnchor {
+        if (!isQuote(value)) return false;
+        const { version, fragments } = value as { version?: unknown; fragments?: unknown };
+        if (version === undefined) return fragments === undefined;
+        if (version !== 2) return false;
+        return fragments === undefined || (
+            Array.isArray(fragments)
+            && fragments.length > 0
+            && fragments.every((fragment: unknown) =>
+                isQuote(fragment) && typeof (fragment as { blockTag?: unknown }).blockTag === 'string')
+        );
+    },
+
     /** Capture a content anchor for `range` within `root`. */
     describe(root: Node, range: Range, reject?: RejectFn): TextAnchor {
         const { text, segments } = collect(root, reject);
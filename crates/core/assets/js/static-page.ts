@@ -0,0 +1,30 @@
+/**
+ * Shared page controller for the small server-rendered pages that don't boot
+ * MarkonApp (archive listings, snapshot history, print view, past
+ * revisions, remote documents): translates every `[data-i18n]` label through
+ * the runtime that i18n-boot injects, formats `time[data-time]`
+ * epoch-millisecond stamps in the reader's locale, and wires the print view's
+ * Print button.
+ *
+ * ESM module, loaded with `<script type="module">` next to page-shortcuts.
+ */
+
+const t: (key: string) => string = (window.__MARKON_I18N__?.t) || ((k: string) => k);
+
+const init = (): void => {
+    document.querySelectorAll<HTMLElement>('[data-i18n]').forEach((el) => {
+        el.textContent = t(el.getAttribute('data-i18n') || '');
+    });
+    document.querySelectorAll<HTMLTimeElement>('time[data-time]').forEach((el) => {
+        const taken = new Date(Number(el.getAttribute('data-time')));
+        el.dateTime = taken.toISOString();
+        el.textContent = taken.toLocaleString();
+    });
+    document.getElementById('print-button')?.addEventListener('click', () => window.print());
+};
+
+if (document.readyState === 'loading') {
+    document.addEventListener('DOMContentLoaded', init, { once: true });
+} else {
+    init();
+}
@@ -55,9 +55,10 @@ function itemAt<T>(items: ArrayLike<T>, index: number): T {
     return item;
 }
 
-function fakeStorage(viewedState: Record<string, boolean> = {}) {
+function fakeStorage(viewedState: Record<string, boolean> = {}, changed: string[] = []) {
     const storage = {
         loadViewedState: vi.fn(async () => ({ ...viewedState })),
+        loadViewedChanged: vi.fn(async () => [...changed]),
         saveViewedState: vi.fn(async () => {}),
     };
     return {
@@ -478,6 +479,28 @@ describe('SectionViewedManager', () => {
         expect(document.getElementById('h2-b')?.classList.contains('section-collapsed')).toBe(false);
     });
 
+    it('flags sections changed since viewed until they are checked again', async () => {
+        seedMeta('file-path', 'docs/x.md');
+        seedMeta('enable-viewed', 'true');
+        buildArticle(['h2-a', 'h2-b']);
+        localStorage.setItem('markon-collapsed-docs/x.md', JSON.stringify({ 'h2-a': true }));
+
+        const mgr = new SectionViewedManager(false, null);
+        const { storage } = fakeStorage({ 'h2-a': false, 'h2-b': true }, ['h2-a']);
+        await mgr.attachStorage(storage, null);
+
+        const badge = () => document.querySelector('#h2-a > .viewed-changed-badge');
+        expect(badge()?.textContent).toBeTruthy();
+        expect(document.querySelector('#h2-b > .viewed-changed-badge')).toBeNull();
+        // The collapse it inherited from being viewed no longer applies.
+        expect(document.getElementById('h2-a')?.classList.contains('section-collapsed')).toBe(false);
+
+        mgr.toggleViewed('h2-a', true);
+        expect(badge()).toBeNull();
+        mgr.toggleViewed('h2-a', false);
+        expect(badge()).toBeNull();
+    });
+
     it('saveState without attached SQLite writes nowhere', () => {
         seedMeta('file-path', 'docs/x.md');
         seedMeta('enable-viewed', 'true');
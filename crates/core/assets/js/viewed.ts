@@ -13,6 +13,7 @@
 
 import type { StorageManager } from './managers/storage-manager';
 import type { WebSocketManager } from './managers/websocket-manager';
+import { withBase } from './core/routes';
 
 const _t = (window.__MARKON_I18N__?.t) || ((k: string): string => k);
 
@@ -23,7 +24,7 @@ const runtimeTheme = (): 'light' | 'dark' => {
     return resolved === 'dark' ? 'dark' : 'light';
 };
 
-const runtimeMarkdownCss = (): string => `/_/css/github-markdown-${runtimeTheme()}.css`;
+const runtimeMarkdownCss = (): string => withBase(`/_/css/github-markdown-${runtimeTheme()}.css`);
 
 /**
  * All section headings (h2-h6) inside the rendered markdown body. The
@@ -50,6 +51,9 @@ const collapsedStorageKey = (filePath: string): string => `markon-collapsed-${fi
 type ViewedState = Record<string, boolean>;
 /** Map of `headingId → collapsed?`, independent from viewed state. */
 type CollapsedState = Record<string, boolean>;
+/** Where viewed state persists: the shared document state, or the private
+ *  per-browser store (`ClientViewedStore`). */
+export type ViewedStore = Pick<StorageManager, 'loadViewedState' | 'loadViewedChanged' | 'saveViewedState'>;
 
 type RevealContentOptions = {
     animate?: boolean;
@@ -87,6 +91,8 @@ export class SectionViewedManager {
     wsManager: OpIdAwareWs | null;
     filePath: string;
     viewedState: ViewedState;
+    /** Sections the server unchecked because they changed after being viewed. */
+    changedSections: Set<string>;
     collapsedState: CollapsedState;
     stateLoaded: boolean;
     enableViewed: boolean;
@@ -96,7 +102,7 @@ export class SectionViewedManager {
     private noteFocusReveal: NoteFocusReveal | null;
     private noteFocusRevealSeq: number;
     private preserveExpansionOnNextSharedState: boolean;
-    private storageManager: StorageManager | null;
+    private storageManager: ViewedStore | null;
 
     /** Latest WS handler — kept so we can detach it before re-attaching. */
     private _wsMessageHandler: ((event: MessageEvent) => void) | null;
@@ -111,6 +117,7 @@ export class SectionViewedManager {
         const filePathMeta = document.querySelector('meta[name="file-path"]');
         this.filePath = filePathMeta ? filePathMeta.getAttribute('content') ?? window.location.pathname : window.location.pathname;
         this.viewedState = {};
+        this.changedSections = new Set();
         this.collapsedState = {};
         this.stateLoaded = false;
         this.allViewedCheckbox = null;
@@ -121,14 +128,16 @@ export class SectionViewedManager {
         this.preserveExpansionOnNextSharedState = false;
         this.storageManager = null;
 
-        // Viewed state is writable only by the administrator, or by a
-        // collaborator when shared annotations are explicitly enabled.
+        // The administrator, and collaborators when shared annotations are
+        // enabled, write the shared document state. Other collaborators keep
+        // private per-browser checkmarks, which need a served workspace.
         const enableViewedMeta = document.querySelector('meta[name="enable-viewed"]');
         const canManage = document.querySelector('meta[name="can-manage"]')?.getAttribute('content') === 'true';
         const sharedAnnotation =
             document.querySelector('meta[name="shared-annotation"]')?.getAttribute('content') === 'true';
+        const workspaceId = document.querySelector('meta[name="workspace-id"]')?.getAttribute('content') ?? '';
         const configured = enableViewedMeta ? enableViewedMeta.getAttribute('content') === 'true' : true;
-        this.enableViewed = configured && (canManage || sharedAnnotation);
+        this.enableViewed = configured && (canManage || sharedAnnotation || workspaceId.trim() !== '');
         this.revealSeq = 0;
 
         if (this.isSharedMode && this.ws) {
@@ -166,7 +175,7 @@ export class SectionViewedManager {
     }
 
     /** Attach the canonical SQLite store after MarkonApp has initialized it. */
-    async attachStorage(storage: StorageManager, wsManager: WebSocketManager | null): Promise<void> {
+    async attachStorage(storage: ViewedStore, wsManager: WebSocketManager | null): Promise<void> {
         await this.ready;
         this.storageManager = storage;
         this.wsManager = wsManager;
@@ -175,6 +184,7 @@ export class SectionViewedManager {
             this.setupWebSocketListeners();
         }
         this.viewedState = await storage.loadViewedState();
+        this.setChangedSections(await storage.loadViewedChanged());
         this.stateLoaded = true;
         this.updateCheckboxes();
         this.applyViewedState();
@@ -191,6 +201,7 @@ export class SectionViewedManager {
                 const data = JSON.parse(event.data as string) as {
                     type?: string;
                     state?: ViewedState;
+                    changed?: string[];
                     op_id?: string | null;
                 };
                 if (data.type !== 'viewed_state') return;
@@ -201,6 +212,7 @@ export class SectionViewedManager {
                 }
 
                 this.viewedState = data.state ?? {};
+                this.setChangedSections(data.changed ?? []);
                 this.stateLoaded = true;
 
                 if (document.querySelector('.viewed-checkbox')) {
@@ -229,6 +241,35 @@ export class SectionViewedManager {
                 checkbox.checked = !!this.viewedState[headingId];
             }
         });
+        this.updateChangedBadges();
+    }
+
+    private setChangedSections(headingIds: string[]): void {
+        this.changedSections = new Set(headingIds);
+        // A changed section needs reading again, so it drops the collapse it
+        // inherited from being viewed.
+        if (headingIds.some((id) => this.hasCollapsedState(id))) {
+            headingIds.forEach((id) => delete this.collapsedState[id]);
+            this.saveCollapsedState();
+        }
+    }
+
+    /** Flag each unchecked section whose content changed since it was viewed. */
+    private updateChangedBadges(): void {
+        document.querySelectorAll<HTMLElement>(SECTION_HEADINGS_SELECTOR).forEach((heading) => {
+            const changed = this.changedSections.has(heading.id) && !this.viewedState[heading.id];
+            const badge = heading.querySelector<HTMLElement>(':scope > .viewed-changed-badge');
+            if (!changed) {
+                badge?.remove();
+                return;
+            }
+            if (badge) return;
+            const created = document.createElement('span');
+            created.className = 'viewed-changed-badge';
+            created.textContent = _t('web.viewed.changed');
+            created.title = _t('web.viewed.changed.tip');
+            heading.insertBefore(created, heading.querySelector(':scope > .section-actions'));
+        });
     }
 
     injectCheckboxes(): void {
@@ -595,7 +636,7 @@ export class SectionViewedManager {
 
         const headingClone = heading.cloneNode(true) as HTMLElement;
         headingClone
-            .querySelectorAll('.section-actions, .viewed-checkbox-label, .section-action-separator, .section-print-btn, .section-expand-toggle')
+            .querySelectorAll('.section-actions, .viewed-checkbox-label, .viewed-changed-badge, .section-action-separator, .section-print-btn, .section-expand-toggle')
             .forEach((el) => el.remove());
         sectionContainer.appendChild(headingClone);
 
@@ -627,7 +668,7 @@ export class SectionViewedManager {
         // eslint-disable-next-line @typescript-eslint/no-deprecated -- Intentional write into a temporary print iframe.
         doc.write(`<!DOCTYPE html><html><head><meta charset="utf-8"><title>Print Section</title>
 <link rel="stylesheet" href="${themeCss}">
-<link rel="stylesheet" href="/_/css/github-print.css">
+<link rel="stylesheet" href="${withBase('/_/css/github-print.css')}">
 <style>
   html, body { margin:0; padding:0; background: transparent !important; }
   /* Tweak page margins slightly to avoid leading blank page */
@@ -793,7 +834,7 @@ export class SectionViewedManager {
         // STEP 2: prepare content (async OK — window is already open).
         const headingClone = heading.cloneNode(true) as HTMLElement;
         headingClone
-            .querySelectorAll('.section-actions, .viewed-checkbox-label, .section-action-separator, .section-print-btn, .section-expand-toggle, .section-action')
+            .querySelectorAll('.section-actions, .viewed-checkbox-label, .viewed-changed-badge, .section-action-separator, .section-print-btn, .section-expand-toggle, .section-action')
             .forEach((el) => el.remove());
 
         const contentClones = content.map((el) => el.cloneNode(true) as HTMLElement);
@@ -859,7 +900,7 @@ export class SectionViewedManager {
     }
 
     async fetchPrintStyles(): Promise<string> {
-        const cssFiles = [runtimeMarkdownCss(), '/_/css/github-print.css'];
+        const cssFiles = [runtimeMarkdownCss(), withBase('/_/css/github-print.css')];
 
         const cssContents = await Promise.all(
             cssFiles.map(async (file) => {
@@ -903,6 +944,9 @@ export class SectionViewedManager {
         this.cancelNoteSourceRevealForHeading(headingId);
         this.viewedState[headingId] = isViewed;
         this.collapsedState[headingId] = isViewed;
+        if (isViewed) {
+            this.changedSections.delete(headingId);
+        }
 
         if (isViewed) {
             this.collapseSection(headingId);
@@ -1147,6 +1191,7 @@ export class SectionViewedManager {
         this.cancelNoteSourceReveal();
         this.sectionHeadingIds().forEach((headingId) => {
             this.viewedState[headingId] = true;
+            this.changedSections.delete(headingId);
             this.collapsedState[headingId] = true;
             this.collapseSection(headingId);
         });
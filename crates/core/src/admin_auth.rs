@@ -220,7 +220,7 @@ pub(crate) fn admin_cookie_valid(secret: &str, cookie_header: Option<&str>, now:
     !session_id.is_empty() && exp.parse::<u64>().is_ok_and(|exp| exp > now)
 }
 
-fn cookie_value<'a>(cookie_header: Option<&'a str>, name: &str) -> Option<&'a str> {
+pub(crate) fn cookie_value<'a>(cookie_header: Option<&'a str>, name: &str) -> Option<&'a str> {
     cookie_header?
         .split(';')
         .filter_map(|part| part.trim().split_once('='))
@@ -0,0 +1,2190 @@
+//! Annotation storage helpers shared by the server and the CLI: export rows
+//! as a JSON dump, CSV, or a Markdown review report, and import a JSON dump
+//! back.
+//!
+//! Rows are keyed by canonical absolute file path (see `ARCHITECTURE.md`);
+//! callers decide which files to include and how each path is displayed or
+//! resolved, so the CLI can work by filesystem path and the server by
+//! workspace route.
+
+use crate::frontmatter::normalize_tag;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Where the annotation database lives: `MARKON_SQLITE_PATH`, then the
+/// configured path, then `~/.markon/annotation.sqlite`.
+pub fn database_path(configured: Option<String>) -> Option<PathBuf> {
+    std::env::var("MARKON_SQLITE_PATH")
+        .ok()
+        .or(configured)
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".markon/annotation.sqlite")))
+}
+
+/// Location of a project's own database, relative to the served root.
+pub const PROJECT_DATABASE: &str = ".markon/annotations.sqlite";
+
+/// `root`'s project database, if one has been created there.
+pub fn project_database(root: &Path) -> Option<PathBuf> {
+    let path = root.join(PROJECT_DATABASE);
+    path.is_file().then_some(path)
+}
+
+/// Copy the annotations of files under `dir` from `source` into `target`,
+/// e.g. from the global database into a project database. Rows already in
+/// `target` are updated; ids `target` holds for other files are skipped.
+/// Returns how many annotations were copied.
+pub fn copy_under(
+    source: &Connection,
+    target: &mut Connection,
+    dir: &Path,
+) -> rusqlite::Result<usize> {
+    let rows = {
+        let mut stmt = source.prepare("SELECT id, file_path, data FROM annotations")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let tx = target.transaction()?;
+    let mut copied = 0;
+    for (id, file_path, data) in rows {
+        if Path::new(&file_path).starts_with(dir)
+            && upsert_annotation_for_file(&tx, &id, &file_path, &data)?
+        {
+            copied += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(copied)
+}
+
+/// Open an existing database read-only, for tools that run beside a server.
+pub fn open_read_only(path: &Path) -> Result<Connection, String> {
+    if !path.is_file() {
+        return Err(format!("No annotation database at {}", path.display()));
+    }
+    Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|e| format!("Failed to open {}: {e}", path.display()))
+}
+
+/// Open (creating if needed) a database for writing from outside the server.
+pub fn open(path: &Path) -> Result<Connection, String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {e}", parent.display()))?;
+    }
+    let conn =
+        Connection::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))?;
+    // A running server may hold the write lock briefly; WAL keeps its readers
+    // unblocked while we write.
+    conn.pragma_update(None, "journal_mode", "WAL")
+        .and_then(|_| conn.pragma_update(None, "busy_timeout", 5000))
+        .and_then(|_| create_table(&conn))
+        .map_err(|e| format!("Failed to prepare {}: {e}", path.display()))?;
+    Ok(conn)
+}
+
+pub(crate) fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS annotations (
+            id TEXT PRIMARY KEY,
+            file_path TEXT NOT NULL,
+            data TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS annotation_history (
+            seq INTEGER PRIMARY KEY AUTOINCREMENT,
+            batch INTEGER NOT NULL,
+            id TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            action TEXT NOT NULL,
+            data TEXT NOT NULL,
+            at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS annotation_history_file
+            ON annotation_history (file_path, batch);",
+    )
+}
+
+pub(crate) fn valid_annotation_id(id: &str) -> bool {
+    id.len() >= 6
+        && id.len() <= 69
+        && id.starts_with("anno-")
+        && id[5..]
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
+}
+
+/// Review categories an annotation may carry in its `category` field.
+pub const CATEGORIES: &[&str] = &["question", "suggestion", "must-fix"];
+
+/// Set `noteHtml` to the rendered `note` (see
+/// [`crate::markdown::render_note_html`]), dropping any client-supplied
+/// value. Annotations without a note carry no `noteHtml`.
+pub(crate) fn render_note(annotation: &mut serde_json::Value) {
+    let Some(object) = annotation.as_object_mut() else {
+        return;
+    };
+    let html = object
+        .get("note")
+        .and_then(serde_json::Value::as_str)
+        .filter(|note| !note.trim().is_empty())
+        .map(crate::markdown::render_note_html);
+    match html {
+        Some(html) => object.insert("noteHtml".into(), html.into()),
+        None => object.remove("noteHtml"),
+    };
+}
+
+/// One annotation in a document's index, embedded in the rendered page so a
+/// `#annotation-<id>` permalink resolves before the annotations load.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct IndexEntry {
+    pub id: String,
+    /// The client's highlight type (`has-note`, `highlight-orange`, …).
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    /// Where the quote starts in the rendered text; `None` without an anchor.
+    pub position: Option<u64>,
+}
+
+/// The annotations on `file_path`, in document order. Annotations without an
+/// anchor sort last.
+pub(crate) fn position_index(
+    conn: &Connection,
+    file_path: &str,
+) -> rusqlite::Result<Vec<IndexEntry>> {
+    let mut stmt = conn.prepare("SELECT id, data FROM annotations WHERE file_path = ?1")?;
+    let rows = stmt.query_map([file_path], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut index = Vec::new();
+    for row in rows {
+        let (id, data) = row?;
+        let annotation: serde_json::Value = serde_json::from_str(&data).unwrap_or_default();
+        let position = annotation
+            .pointer("/anchor/fragments/0/position")
+            .or_else(|| annotation.pointer("/anchor/position"))
+            .and_then(serde_json::Value::as_u64);
+        index.push(IndexEntry {
+            id,
+            kind: annotation
+                .get("type")
+                .and_then(serde_json::Value::as_str)
+                .map(str::to_string),
+            position,
+        });
+    }
+    index.sort_by(|a, b| {
+        (a.position.is_none(), a.position, &a.id).cmp(&(b.position.is_none(), b.position, &b.id))
+    });
+    Ok(index)
+}
+
+/// Check the parts of an annotation payload the server interprets. The rest
+/// of the object stays opaque client data.
+pub(crate) fn validate_annotation(annotation: &serde_json::Value) -> Result<(), &'static str> {
+    let Some(object) = annotation.as_object() else {
+        return Err("Annotation must be a JSON object");
+    };
+    match object.get("category") {
+        None | Some(serde_json::Value::Null) => {}
+        Some(serde_json::Value::String(category)) if CATEGORIES.contains(&category.as_str()) => {}
+        Some(_) => return Err("Annotation category must be question, suggestion, or must-fix"),
+    }
+    match object.get("tags") {
+        None | Some(serde_json::Value::Null) => {}
+        Some(serde_json::Value::Array(tags))
+            if tags.len() <= MAX_TAGS
+                && tags.iter().all(|tag| {
+                    tag.as_str().is_some_and(|tag| {
+                        !normalize_tag(tag).is_empty() && tag.chars().count() <= MAX_TAG_CHARS
+                    })
+                }) => {}
+        Some(_) => return Err("Annotation tags must be a list of at most 32 short strings"),
+    }
+    crate::attachments::validate(object.get("attachments"))?;
+    match object.get("text") {
+        None | Some(serde_json::Value::Null) => {}
+        Some(serde_json::Value::String(text)) if text.chars().count() <= MAX_QUOTE_CHARS => {}
+        Some(_) => return Err("Annotation text must be a string of at most 100000 characters"),
+    }
+    match object.get("anchor") {
+        None | Some(serde_json::Value::Null) => {}
+        Some(anchor) => validate_anchor(anchor)?,
+    }
+    match object.get("revision") {
+        None | Some(serde_json::Value::Null) => {}
+        Some(revision) if revision.as_u64().is_some() => {}
+        Some(_) => return Err("Annotation revision must be a non-negative integer"),
+    }
+    if !matches!(
+        object.get("resolved"),
+        None | Some(serde_json::Value::Null | serde_json::Value::Bool(_))
+    ) {
+        return Err("Annotation resolved flag must be a boolean");
+    }
+    match object.get("resolvedBy") {
+        None | Some(serde_json::Value::Null) => Ok(()),
+        Some(by) => validate_resolver(by),
+    }
+}
+
+/// Longest quote an annotation may carry. Cross-block selections span
+/// several paragraphs or list items, so this is generous.
+const MAX_QUOTE_CHARS: usize = 100_000;
+
+/// Most structural fragments (one per block a selection crosses) an anchor
+/// may carry.
+const MAX_ANCHOR_FRAGMENTS: usize = 1024;
+
+/// Check an anchor captured by `text-anchor.ts`: a flat quote selector, plus
+/// for `version: 2` the ordered per-block `fragments` of a cross-block
+/// selection. Anchors without `version`/`fragments` predate cross-block
+/// support and stay valid.
+fn validate_anchor(anchor: &serde_json::Value) -> Result<(), &'static str> {
+    const INVALID: &str =
+        "Annotation anchor must be a text quote with position, exact, prefix, and suffix";
+    let object = anchor.as_object().ok_or(INVALID)?;
+    if !valid_quote(object) {
+        return Err(INVALID);
+    }
+    let fragments = match (object.get("version"), object.get("fragments")) {
+        (None | Some(serde_json::Value::Null), None | Some(serde_json::Value::Null)) => {
+            return Ok(());
+        }
+        (Some(version), fragments) if version.as_u64() == Some(2) => match fragments {
+            None | Some(serde_json::Value::Null) => return Ok(()),
+            Some(serde_json::Value::Array(fragments)) => fragments,
+            Some(_) => return Err("Annotation anchor fragments must be a list"),
+        },
+        _ => return Err("Annotation anchor fragments need anchor version 2"),
+    };
+    if fragments.is_empty() || fragments.len() > MAX_ANCHOR_FRAGMENTS {
+        return Err("Annotation anchor must have between 1 and 1024 fragments");
+    }
+    let mut previous = 0;
+    let mut quoted = 0;
+    for fragment in fragments {
+        let fragment = fragment
+            .as_object()
+            .filter(|fragment| valid_quote(fragment))
+            .filter(|fragment| {
+                fragment.get("blockTag").is_none_or(|tag| {
+                    tag.as_str().is_some_and(|tag| {
+                        tag.len() <= 32
+                            && tag
+                                .bytes()
+                                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'#'))
+                    })
+                })
+            })
+            .ok_or("Annotation anchor fragments must be text quotes with a block tag")?;
+        // `valid_quote` checked both fields.
+        let position = fragment["position"].as_u64().unwrap_or_default();
+        if position < previous {
+            return Err("Annotation anchor fragments must be in document order");
+        }
+        previous = position;
+        quoted += fragment["exact"]
+            .as_str()
+            .map_or(0, |exact| exact.chars().count());
+        if quoted > MAX_QUOTE_CHARS {
+            return Err("Annotation anchor quotes at most 100000 characters");
+        }
+    }
+    Ok(())
+}
+
+/// `position` is a non-negative offset; `exact`, `prefix`, and `suffix` are
+/// strings, the context ones short.
+fn valid_quote(quote: &serde_json::Map<String, serde_json::Value>) -> bool {
+    let context = |key: &str| {
+        quote.get(key).is_none_or(|value| {
+            value
+                .as_str()
+                .is_some_and(|value| value.chars().count() <= MAX_CONTEXT_CHARS)
+        })
+    };
+    quote.get("position").is_some_and(serde_json::Value::is_u64)
+        && quote
+            .get("exact")
+            .and_then(serde_json::Value::as_str)
+            .is_some_and(|exact| exact.chars().count() <= MAX_QUOTE_CHARS)
+        && context("prefix")
+        && context("suffix")
+}
+
+/// The client captures 32 UTF-16 units of context; leave room for surrogate
+/// pairs and future tuning.
+pub(crate) const MAX_CONTEXT_CHARS: usize = 256;
+
+/// A resolver identity is shaped like `author`: `{ "name"?, "color"? }`.
+pub(crate) fn validate_resolver(by: &serde_json::Value) -> Result<(), &'static str> {
+    if valid_identity(by) {
+        Ok(())
+    } else {
+        Err("Annotation resolver must be an object with a short name and color")
+    }
+}
+
+/// Whether an `author`/`resolvedBy`-style identity carries a non-empty name,
+/// as `--require-name` demands.
+pub(crate) fn has_display_name(identity: Option<&serde_json::Value>) -> bool {
+    identity
+        .and_then(|identity| identity.get("name"))
+        .and_then(serde_json::Value::as_str)
+        .is_some_and(|name| !name.trim().is_empty())
+}
+
+fn valid_identity(identity: &serde_json::Value) -> bool {
+    identity.as_object().is_some_and(|object| {
+        object.iter().all(|(key, value)| {
+            matches!(key.as_str(), "name" | "color")
+                && value
+                    .as_str()
+                    .is_some_and(|value| value.chars().count() <= MAX_IDENTITY_CHARS)
+        })
+    })
+}
+
+const MAX_IDENTITY_CHARS: usize = 64;
+
+pub(crate) const MAX_TAGS: usize = 32;
+pub(crate) const MAX_TAG_CHARS: usize = 64;
+
+/// The annotation's tags, normalized like document tags (trimmed,
+/// lower-cased, no leading `#`).
+pub(crate) fn tags_of(annotation: &serde_json::Value) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in annotation
+        .get("tags")
+        .and_then(serde_json::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(serde_json::Value::as_str)
+    {
+        let tag = normalize_tag(tag);
+        if !tag.is_empty() && !tags.contains(&tag) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// Narrows an annotation list: every set field must match. Deserialized from
+/// list/export query strings and the WebSocket hello's `filter`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationFilter {
+    /// Carries this tag (case-insensitive, `#` optional).
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Created by this author name (case-insensitive).
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Resolved or still open; annotations without the flag are open.
+    #[serde(default)]
+    pub resolved: Option<bool>,
+    /// One of [`CATEGORIES`].
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl AnnotationFilter {
+    /// Canonicalize the fields, dropping empty ones; fails on an unknown
+    /// category.
+    pub fn normalized(self) -> Result<Self, String> {
+        let non_empty = |value: Option<String>| value.filter(|value| !value.trim().is_empty());
+        Ok(Self {
+            tag: non_empty(self.tag)
+                .map(|tag| normalize_tag(&tag))
+                .filter(|tag| !tag.is_empty()),
+            author: non_empty(self.author).map(|author| author.trim().to_lowercase()),
+            resolved: self.resolved,
+            category: non_empty(self.category)
+                .map(|category| parse_category(&category))
+                .transpose()?,
+        })
+    }
+
+    /// Whether `annotation` passes. Expects a [`normalized`](Self::normalized)
+    /// filter.
+    pub fn matches(&self, annotation: &serde_json::Value) -> bool {
+        if let Some(tag) = &self.tag {
+            if !tags_of(annotation).contains(tag) {
+                return false;
+            }
+        }
+        if let Some(author) = &self.author {
+            let name = annotation
+                .pointer("/author/name")
+                .and_then(serde_json::Value::as_str)
+                .map(|name| name.trim().to_lowercase());
+            if name.as_ref() != Some(author) {
+                return false;
+            }
+        }
+        if let Some(resolved) = self.resolved {
+            let is_resolved = annotation
+                .get("resolved")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false);
+            if is_resolved != resolved {
+                return false;
+            }
+        }
+        if let Some(category) = &self.category {
+            if category_of(annotation) != Some(category.as_str()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Parse a `category` filter, rejecting values no annotation can have.
+pub fn parse_category(category: &str) -> Result<String, String> {
+    let category = category.trim().to_ascii_lowercase();
+    if CATEGORIES.contains(&category.as_str()) {
+        Ok(category)
+    } else {
+        Err(format!(
+            "unknown category '{category}' (expected {})",
+            CATEGORIES.join(", ")
+        ))
+    }
+}
+
+/// The annotation's category, if it has a valid one.
+pub(crate) fn category_of(annotation: &serde_json::Value) -> Option<&str> {
+    annotation
+        .get("category")
+        .and_then(serde_json::Value::as_str)
+        .filter(|category| CATEGORIES.contains(category))
+}
+
+/// Insert or update one annotation. Returns `false` (and changes nothing)
+/// when the id already belongs to a different file.
+pub(crate) fn upsert_annotation_for_file(
+    conn: &Connection,
+    id: &str,
+    file_path: &str,
+    data: &str,
+) -> rusqlite::Result<bool> {
+    conn.execute(
+        "INSERT INTO annotations (id, file_path, data)
+         VALUES (?1, ?2, ?3)
+         ON CONFLICT(id) DO UPDATE SET data = excluded.data
+         WHERE annotations.file_path = excluded.file_path",
+        rusqlite::params![id, file_path, data],
+    )
+    .map(|changed| changed > 0)
+}
+
+/// The annotation's edit revision. Annotations saved before revisions
+/// existed count as 0.
+pub(crate) fn revision(annotation: &serde_json::Value) -> u64 {
+    annotation
+        .get("revision")
+        .and_then(serde_json::Value::as_u64)
+        .unwrap_or(0)
+}
+
+fn bump_revision(annotation: &mut serde_json::Value) {
+    let next = revision(annotation) + 1;
+    if let Some(object) = annotation.as_object_mut() {
+        object.insert("revision".into(), next.into());
+    }
+}
+
+/// Outcome of saving a writer's copy of an annotation.
+#[derive(Debug, PartialEq)]
+pub(crate) enum AnnotationSave {
+    /// Stored under its next `revision`.
+    Saved(serde_json::Value),
+    /// Someone saved the annotation after the writer loaded it. Holds the
+    /// stored annotation; nothing was written.
+    Stale(serde_json::Value),
+    /// The id already belongs to a different file.
+    Foreign,
+}
+
+/// Check a write against the stored annotation (`None` when there is none)
+/// and stamp the next revision. Writers send back the `revision` they last
+/// saw, so a copy edited from an older revision is rejected instead of
+/// silently overwriting the newer one.
+pub(crate) fn next_revision(
+    stored: Option<serde_json::Value>,
+    mut annotation: serde_json::Value,
+) -> AnnotationSave {
+    if let Some(stored) = stored {
+        if revision(&stored) != revision(&annotation) {
+            return AnnotationSave::Stale(stored);
+        }
+    }
+    bump_revision(&mut annotation);
+    AnnotationSave::Saved(annotation)
+}
+
+/// Save a writer's copy of annotation `id` on `file_path`; see
+/// [`next_revision`].
+pub(crate) fn save_annotation(
+    conn: &Connection,
+    id: &str,
+    file_path: &str,
+    annotation: serde_json::Value,
+) -> rusqlite::Result<AnnotationSave> {
+    let tx = crate::db::savepoint(conn)?;
+    let stored: Option<(String, String)> = tx
+        .query_row(
+            "SELECT file_path, data FROM annotations WHERE id = ?1",
+            [id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let stored = match stored {
+        Some((owner, _)) if owner != file_path => return Ok(AnnotationSave::Foreign),
+        Some((_, data)) => serde_json::from_str(&data).ok(),
+        None => None,
+    };
+    let outcome = next_revision(stored, annotation);
+    if let AnnotationSave::Saved(annotation) = &outcome {
+        tx.execute(
+            "INSERT OR REPLACE INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+            rusqlite::params![id, file_path, annotation.to_string()],
+        )?;
+        tx.commit()?;
+    }
+    Ok(outcome)
+}
+
+/// Mark annotation `id` on `file_path` resolved (stamping `resolvedBy` and
+/// `resolvedAt` in epoch milliseconds) or open again. Returns the updated
+/// annotation, or `None` when no such row exists. `by` must already pass
+/// [`validate_resolver`].
+pub(crate) fn set_resolved(
+    conn: &Connection,
+    id: &str,
+    file_path: &str,
+    resolved: bool,
+    by: Option<serde_json::Value>,
+) -> rusqlite::Result<Option<serde_json::Value>> {
+    let data: Option<String> = conn
+        .query_row(
+            "SELECT data FROM annotations WHERE id = ?1 AND file_path = ?2",
+            [id, file_path],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let Some(mut annotation) =
+        data.and_then(|data| serde_json::from_str::<serde_json::Value>(&data).ok())
+    else {
+        return Ok(None);
+    };
+    if !apply_resolution(&mut annotation, resolved, by) {
+        return Ok(None);
+    }
+    conn.execute(
+        "UPDATE annotations SET data = ?1 WHERE id = ?2 AND file_path = ?3",
+        rusqlite::params![annotation.to_string(), id, file_path],
+    )?;
+    Ok(Some(annotation))
+}
+
+/// Stamp `resolved` (with `resolvedBy` and `resolvedAt`) on a stored
+/// annotation, or clear them, as a new revision. False when it is not a JSON
+/// object.
+pub(crate) fn apply_resolution(
+    annotation: &mut serde_json::Value,
+    resolved: bool,
+    by: Option<serde_json::Value>,
+) -> bool {
+    bump_revision(annotation);
+    let Some(object) = annotation.as_object_mut() else {
+        return false;
+    };
+    object.insert("resolved".into(), resolved.into());
+    if resolved {
+        object.insert("resolvedAt".into(), now_millis().into());
+        match by {
+            Some(by) => object.insert("resolvedBy".into(), by),
+            None => object.remove("resolvedBy"),
+        };
+    } else {
+        object.remove("resolvedAt");
+        object.remove("resolvedBy");
+    }
+    true
+}
+
+/// How long deleted annotations stay restorable before [`prune_history`]
+/// drops them.
+pub const HISTORY_RETENTION: std::time::Duration =
+    std::time::Duration::from_secs(30 * 24 * 60 * 60);
+
+pub(crate) fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Delete annotation `id` on `file_path`, or every annotation on the file
+/// when `id` is `None`, keeping the removed rows in `annotation_history` as
+/// one undoable batch. Returns how many annotations were removed.
+pub(crate) fn delete_annotations(
+    conn: &Connection,
+    file_path: &str,
+    id: Option<&str>,
+) -> rusqlite::Result<usize> {
+    let tx = crate::db::savepoint(conn)?;
+    let action = if id.is_some() { "delete" } else { "clear" };
+    let removed = archive_rows(&tx, file_path, id, action)?;
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// Move the matching rows of `file_path` into `annotation_history` as one
+/// batch recorded under `action`.
+fn archive_rows(
+    tx: &crate::db::Savepoint<'_>,
+    file_path: &str,
+    id: Option<&str>,
+    action: &str,
+) -> rusqlite::Result<usize> {
+    tx.execute(
+        "INSERT INTO annotation_history (batch, id, file_path, action, data, at)
+         SELECT (SELECT COALESCE(MAX(batch), 0) + 1 FROM annotation_history),
+                id, file_path, ?3, data, ?4
+         FROM annotations WHERE file_path = ?1 AND (?2 IS NULL OR id = ?2)",
+        rusqlite::params![file_path, id, action, now_millis()],
+    )?;
+    tx.execute(
+        "DELETE FROM annotations WHERE file_path = ?1 AND (?2 IS NULL OR id = ?2)",
+        rusqlite::params![file_path, id],
+    )
+}
+
+/// One removed annotation kept for undo.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistoryEntry {
+    pub seq: i64,
+    /// Rows removed by the same delete or clear share a batch.
+    pub batch: i64,
+    pub id: String,
+    /// `delete`, `clear`, or `prune`.
+    pub action: String,
+    /// When it was removed, as `YYYY-MM-DDTHH:MM:SSZ`.
+    pub deleted_at: String,
+    pub annotation: serde_json::Value,
+}
+
+/// Removed annotations of `file_path`, newest first.
+pub fn history(conn: &Connection, file_path: &str) -> rusqlite::Result<Vec<HistoryEntry>> {
+    let mut stmt = conn.prepare(
+        "SELECT seq, batch, id, action,
+                strftime('%Y-%m-%dT%H:%M:%SZ', at / 1000, 'unixepoch'), data
+         FROM annotation_history
+         WHERE file_path = ?1 ORDER BY seq DESC",
+    )?;
+    let rows = stmt.query_map([file_path], |row| {
+        let data: String = row.get(5)?;
+        Ok(HistoryEntry {
+            seq: row.get(0)?,
+            batch: row.get(1)?,
+            id: row.get(2)?,
+            action: row.get(3)?,
+            deleted_at: row.get(4)?,
+            annotation: serde_json::from_str(&data).unwrap_or(serde_json::Value::Null),
+        })
+    })?;
+    rows.collect()
+}
+
+/// Restore the most recent delete or clear on `file_path` and drop it from
+/// history. Annotations whose id was reused since are left alone. Returns the
+/// restored annotations.
+pub(crate) fn undo(conn: &Connection, file_path: &str) -> rusqlite::Result<Vec<serde_json::Value>> {
+    let tx = crate::db::savepoint(conn)?;
+    let batch: Option<i64> = tx.query_row(
+        "SELECT MAX(batch) FROM annotation_history WHERE file_path = ?1",
+        [file_path],
+        |row| row.get(0),
+    )?;
+    let Some(batch) = batch else {
+        return Ok(Vec::new());
+    };
+    let rows = {
+        let mut stmt = tx.prepare(
+            "SELECT id, data FROM annotation_history
+             WHERE file_path = ?1 AND batch = ?2 ORDER BY seq",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![file_path, batch], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let mut restored = Vec::new();
+    for (id, data) in rows {
+        let inserted = tx.execute(
+            "INSERT OR IGNORE INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+            rusqlite::params![id, file_path, data],
+        )?;
+        if inserted > 0 {
+            if let Ok(annotation) = serde_json::from_str(&data) {
+                restored.push(annotation);
+            }
+        }
+    }
+    tx.execute(
+        "DELETE FROM annotation_history WHERE file_path = ?1 AND batch = ?2",
+        rusqlite::params![file_path, batch],
+    )?;
+    tx.commit()?;
+    Ok(restored)
+}
+
+/// Drop history recorded more than `retention` ago. Returns the number of
+/// rows removed.
+pub fn prune_history(conn: &Connection, retention: std::time::Duration) -> rusqlite::Result<usize> {
+    let cutoff = now_millis().saturating_sub(retention.as_millis() as u64);
+    conn.execute("DELETE FROM annotation_history WHERE at < ?1", [cutoff])
+}
+
+/// Stored data of a file that no longer exists on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MissingFile {
+    pub file_path: String,
+    pub annotations: usize,
+    /// Whether viewed state is stored for it.
+    pub viewed: bool,
+}
+
+/// Databases written only by the CLI have no viewed_state table.
+fn has_viewed_state(conn: &Connection) -> rusqlite::Result<bool> {
+    has_table(conn, "viewed_state")
+}
+
+/// Nor, when written before private viewed state, a client_viewed_state one.
+fn has_client_viewed_state(conn: &Connection) -> rusqlite::Result<bool> {
+    has_table(conn, "client_viewed_state")
+}
+
+pub(crate) fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    Ok(conn
+        .query_row(
+            "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+            [name],
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+/// Re-key the annotations, history, viewed state, and recent or pinned
+/// entries of `from` — a file, or every file under a directory — to the same
+/// place under `to`, after a rename. State already stored for a destination file is replaced, as the
+/// moved file replaced it. Returns each moved file path, old and new.
+pub(crate) fn move_file_data(
+    conn: &Connection,
+    from: &Path,
+    to: &Path,
+) -> rusqlite::Result<Vec<(String, String)>> {
+    let viewed = has_viewed_state(conn)?;
+    let client_viewed = has_client_viewed_state(conn)?;
+    let recent = has_table(conn, "file_views")? && has_table(conn, "pinned_files")?;
+    let stored = {
+        let mut sql = String::from("SELECT file_path FROM annotations");
+        if viewed {
+            sql.push_str(" UNION SELECT file_path FROM viewed_state");
+        }
+        if client_viewed {
+            sql.push_str(" UNION SELECT file_path FROM client_viewed_state");
+        }
+        if recent {
+            sql.push_str(
+                " UNION SELECT file_path FROM file_views UNION SELECT file_path FROM pinned_files",
+            );
+        }
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let moves = file_moves(stored, from, to);
+    if moves.is_empty() {
+        return Ok(moves);
+    }
+
+    let tx = crate::db::savepoint(conn)?;
+    for (old, new) in &moves {
+        tx.execute(
+            "UPDATE annotations SET file_path = ?2 WHERE file_path = ?1",
+            [old, new],
+        )?;
+        tx.execute(
+            "UPDATE annotation_history SET file_path = ?2 WHERE file_path = ?1",
+            [old, new],
+        )?;
+        if viewed {
+            tx.execute(
+                "UPDATE OR REPLACE viewed_state SET file_path = ?2 WHERE file_path = ?1",
+                [old, new],
+            )?;
+        }
+        if client_viewed {
+            tx.execute(
+                "UPDATE OR REPLACE client_viewed_state SET file_path = ?2 WHERE file_path = ?1",
+                [old, new],
+            )?;
+        }
+        if recent {
+            for table in ["file_views", "pinned_files"] {
+                tx.execute(
+                    &format!("UPDATE OR REPLACE {table} SET file_path = ?2 WHERE file_path = ?1"),
+                    [old, new],
+                )?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(moves)
+}
+
+/// The new path of each `stored` file path at or under `from` once it moved
+/// to `to`, paired with the old one.
+pub(crate) fn file_moves(
+    stored: impl IntoIterator<Item = String>,
+    from: &Path,
+    to: &Path,
+) -> Vec<(String, String)> {
+    stored
+        .into_iter()
+        .filter_map(|old| {
+            let rel = Path::new(&old).strip_prefix(from).ok()?;
+            let new = if rel.as_os_str().is_empty() {
+                to.to_path_buf()
+            } else {
+                to.join(rel)
+            };
+            Some((old.clone(), new.to_string_lossy().into_owned()))
+        })
+        .collect()
+}
+
+/// Files under `root` with stored annotations or viewed state that no longer
+/// exist, sorted by path. The caller must make sure `root` itself exists —
+/// an unmounted drive would otherwise report every file on it.
+pub fn missing_files(conn: &Connection, root: &Path) -> rusqlite::Result<Vec<MissingFile>> {
+    let annotated = {
+        let mut stmt =
+            conn.prepare("SELECT file_path, COUNT(*) FROM annotations GROUP BY file_path")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, usize>(1)?))
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+    };
+    let mut viewed = Vec::new();
+    let viewed_tables = [
+        ("viewed_state", has_viewed_state(conn)?),
+        ("client_viewed_state", has_client_viewed_state(conn)?),
+    ];
+    for (table, _) in viewed_tables.iter().filter(|(_, exists)| *exists) {
+        let mut stmt = conn.prepare(&format!("SELECT DISTINCT file_path FROM {table}"))?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            viewed.push(row?);
+        }
+    }
+    Ok(missing_among(root, annotated, viewed))
+}
+
+/// [`missing_files`] over what a store holds: annotation counts per file,
+/// and the files with (shared or private) viewed state.
+pub(crate) fn missing_among(
+    root: &Path,
+    annotated: impl IntoIterator<Item = (String, usize)>,
+    viewed: impl IntoIterator<Item = String>,
+) -> Vec<MissingFile> {
+    let mut files = std::collections::BTreeMap::<String, MissingFile>::new();
+    let gone = |path: &str| {
+        let path = Path::new(path);
+        path.starts_with(root) && !path.exists()
+    };
+    for (file_path, annotations) in annotated {
+        if gone(&file_path) {
+            files.insert(
+                file_path.clone(),
+                MissingFile {
+                    file_path,
+                    annotations,
+                    viewed: false,
+                },
+            );
+        }
+    }
+    for file_path in viewed {
+        if gone(&file_path) {
+            files
+                .entry(file_path.clone())
+                .or_insert(MissingFile {
+                    file_path,
+                    annotations: 0,
+                    viewed: false,
+                })
+                .viewed = true;
+        }
+    }
+    files.into_values().collect()
+}
+
+/// Remove the stored data of `files` (from [`missing_files`]), skipping any
+/// that have reappeared since. With `archive`, each file's annotations move
+/// to `annotation_history` as a `prune` batch, restorable with undo until
+/// [`prune_history`] drops it; otherwise they are deleted outright. Viewed
+/// state is always deleted. Returns the files actually pruned.
+pub fn prune_missing_files(
+    conn: &Connection,
+    files: &[MissingFile],
+    archive: bool,
+) -> rusqlite::Result<Vec<MissingFile>> {
+    let client_viewed = has_client_viewed_state(conn)?;
+    let tx = crate::db::savepoint(conn)?;
+    let mut pruned = Vec::new();
+    for file in files {
+        if Path::new(&file.file_path).exists() {
+            continue;
+        }
+        let annotations = if archive {
+            archive_rows(&tx, &file.file_path, None, "prune")?
+        } else {
+            tx.execute(
+                "DELETE FROM annotations WHERE file_path = ?1",
+                [&file.file_path],
+            )?
+        };
+        if file.viewed {
+            tx.execute(
+                "DELETE FROM viewed_state WHERE file_path = ?1",
+                [&file.file_path],
+            )?;
+            if client_viewed {
+                tx.execute(
+                    "DELETE FROM client_viewed_state WHERE file_path = ?1",
+                    [&file.file_path],
+                )?;
+            }
+        }
+        pruned.push(MissingFile {
+            annotations,
+            ..file.clone()
+        });
+    }
+    tx.commit()?;
+    Ok(pruned)
+}
+
+/// One document's stored viewed state.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ViewedRecord {
+    pub file_path: String,
+    /// `{heading id: viewed}` as the page stored it.
+    pub state: serde_json::Value,
+}
+
+/// The viewed state stored for `scope` — a file, or every file under a
+/// directory — or for every file when `None`, sorted by path.
+pub fn viewed_states(
+    conn: &Connection,
+    scope: Option<&Path>,
+) -> rusqlite::Result<Vec<ViewedRecord>> {
+    if !has_viewed_state(conn)? {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare("SELECT file_path, state FROM viewed_state ORDER BY file_path")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut records = Vec::new();
+    for row in rows {
+        let (file_path, state) = row?;
+        if scope.is_some_and(|scope| !Path::new(&file_path).starts_with(scope)) {
+            continue;
+        }
+        records.push(ViewedRecord {
+            file_path,
+            state: serde_json::from_str(&state).unwrap_or(serde_json::Value::Null),
+        });
+    }
+    Ok(records)
+}
+
+/// Clear the viewed state [`viewed_states`] would return for `scope`, along
+/// with each browser's private viewed state there. Returns the paths of the
+/// files reset.
+pub fn reset_viewed(conn: &Connection, scope: Option<&Path>) -> rusqlite::Result<Vec<String>> {
+    let mut files: std::collections::BTreeSet<String> = viewed_states(conn, scope)?
+        .into_iter()
+        .map(|record| record.file_path)
+        .collect();
+    let client_viewed = has_client_viewed_state(conn)?;
+    if client_viewed {
+        let mut stmt = conn.prepare("SELECT DISTINCT file_path FROM client_viewed_state")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            let file_path = row?;
+            if scope.is_none_or(|scope| Path::new(&file_path).starts_with(scope)) {
+                files.insert(file_path);
+            }
+        }
+    }
+    let tx = crate::db::savepoint(conn)?;
+    for file_path in &files {
+        tx.execute("DELETE FROM viewed_state WHERE file_path = ?1", [file_path])?;
+        if client_viewed {
+            tx.execute(
+                "DELETE FROM client_viewed_state WHERE file_path = ?1",
+                [file_path],
+            )?;
+        }
+    }
+    tx.commit()?;
+    Ok(files.into_iter().collect())
+}
+
+/// Open and resolved annotation counts for one file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ResolutionCounts {
+    pub open: u64,
+    pub resolved: u64,
+}
+
+/// Open/resolved counts per file path of `rows`, sorted by path. Files
+/// without annotations are absent.
+pub(crate) fn resolution_counts(rows: &[StoredAnnotation]) -> Vec<(String, ResolutionCounts)> {
+    let mut files = std::collections::BTreeMap::<&str, ResolutionCounts>::new();
+    for row in rows {
+        let counts = files.entry(&row.file_path).or_default();
+        let resolved = row.annotation.get("resolved");
+        if resolved.and_then(serde_json::Value::as_bool) == Some(true)
+            || resolved.and_then(serde_json::Value::as_f64) == Some(1.0)
+        {
+            counts.resolved += 1;
+        } else {
+            counts.open += 1;
+        }
+    }
+    files
+        .into_iter()
+        .map(|(file_path, counts)| (file_path.to_string(), counts))
+        .collect()
+}
+
+/// One annotation row as a store holds it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StoredAnnotation {
+    pub id: String,
+    pub file_path: String,
+    pub annotation: serde_json::Value,
+}
+
+/// Every annotation in the database. Rows whose data isn't JSON are skipped.
+pub(crate) fn stored_annotations(conn: &Connection) -> rusqlite::Result<Vec<StoredAnnotation>> {
+    let mut stmt = conn.prepare("SELECT id, file_path, data FROM annotations")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+    let mut out = Vec::new();
+    for row in rows {
+        let (id, file_path, data) = row?;
+        if let Ok(annotation) = serde_json::from_str(&data) {
+            out.push(StoredAnnotation {
+                id,
+                file_path,
+                annotation,
+            });
+        }
+    }
+    Ok(out)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Json,
+    Csv,
+    Markdown,
+    /// W3C Web Annotation JSON-LD, see [`crate::web_annotation`].
+    WebAnnotation,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "w3c" | "jsonld" | "web-annotation" => Ok(Self::WebAnnotation),
+            other => Err(format!(
+                "unknown export format '{other}' (expected json, csv, markdown, or w3c)"
+            )),
+        }
+    }
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Json => "application/json; charset=utf-8",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::WebAnnotation => {
+                "application/ld+json; profile=\"http://www.w3.org/ns/anno.jsonld\""
+            }
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Csv => "csv",
+            Self::Markdown => "md",
+            Self::WebAnnotation => "jsonld",
+        }
+    }
+}
+
+/// One stored annotation with the fields a review export cares about pulled
+/// out of its JSON payload. `annotation` keeps the payload verbatim so a JSON
+/// export loses nothing.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ExportedAnnotation {
+    /// Canonical absolute path the row is stored under.
+    pub file_path: String,
+    /// Path as shown to the reader (workspace route or the absolute path).
+    pub path: String,
+    pub id: String,
+    /// The quoted passage.
+    pub text: String,
+    pub note: Option<String>,
+    pub author: Option<String>,
+    /// One of [`CATEGORIES`].
+    pub category: Option<String>,
+    pub tags: Vec<String>,
+    /// UTC, `YYYY-MM-DDTHH:MM:SSZ`.
+    pub created_at: Option<String>,
+    pub updated_at: Option<String>,
+    pub annotation: serde_json::Value,
+}
+
+/// Load every annotation whose file `display` maps to a shown path, ordered
+/// by path and then creation time. Files `display` returns `None` for are
+/// skipped.
+pub fn load(
+    conn: &Connection,
+    display: impl Fn(&Path) -> Option<String>,
+) -> rusqlite::Result<Vec<ExportedAnnotation>> {
+    Ok(exported(stored_annotations(conn)?, display))
+}
+
+/// [`load`] over rows already read from a store.
+pub(crate) fn exported(
+    mut rows: Vec<StoredAnnotation>,
+    display: impl Fn(&Path) -> Option<String>,
+) -> Vec<ExportedAnnotation> {
+    let millis = |annotation: &serde_json::Value, key: &str| {
+        annotation.get(key).and_then(serde_json::Value::as_f64)
+    };
+    rows.sort_by(|a, b| {
+        a.file_path
+            .cmp(&b.file_path)
+            .then_with(|| {
+                let (a, b) = (
+                    millis(&a.annotation, "createdAt"),
+                    millis(&b.annotation, "createdAt"),
+                );
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    let timestamp = |annotation: &serde_json::Value, key: &str| {
+        millis(annotation, key).map(|ms| utc_timestamp((ms / 1000.0).floor() as i64))
+    };
+    let mut out = Vec::new();
+    for StoredAnnotation {
+        id,
+        file_path,
+        annotation,
+    } in rows
+    {
+        let Some(path) = display(Path::new(&file_path)) else {
+            continue;
+        };
+        let created_at = timestamp(&annotation, "createdAt");
+        let updated_at = timestamp(&annotation, "updatedAt");
+        let text_field = |key: &str| {
+            annotation
+                .get(key)
+                .and_then(serde_json::Value::as_str)
+                .filter(|value| !value.trim().is_empty())
+                .map(str::to_string)
+        };
+        out.push(ExportedAnnotation {
+            text: text_field("text").unwrap_or_default(),
+            note: text_field("note"),
+            author: annotation
+                .pointer("/author/name")
+                .and_then(serde_json::Value::as_str)
+                .filter(|name| !name.trim().is_empty())
+                .map(str::to_string),
+            category: category_of(&annotation).map(str::to_string),
+            tags: tags_of(&annotation),
+            file_path,
+            path,
+            id,
+            created_at,
+            updated_at,
+            annotation,
+        });
+    }
+    out
+}
+
+/// `secs` since the Unix epoch as `YYYY-MM-DDTHH:MM:SSZ` (Howard Hinnant's
+/// `civil_from_days`).
+pub(crate) fn utc_timestamp(secs: i64) -> String {
+    let (days, time) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = era * 400 + year_of_era + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3_600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    version: u32,
+    annotations: &'a [ExportedAnnotation],
+}
+
+pub fn render(annotations: &[ExportedAnnotation], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Json => {
+            let export = JsonExport {
+                version: 1,
+                annotations,
+            };
+            serde_json::to_string_pretty(&export).unwrap_or_default() + "\n"
+        }
+        ExportFormat::Csv => render_csv(annotations),
+        ExportFormat::Markdown => render_markdown(annotations),
+        ExportFormat::WebAnnotation => crate::web_annotation::render(annotations),
+    }
+}
+
+/// One annotation read back from a JSON export. Only the fields import
+/// needs; the rest of an exported entry is derived from `annotation`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ImportEntry {
+    /// Absolute path on the exporting machine.
+    pub file_path: String,
+    /// Path as shown in the export (workspace route or absolute path).
+    #[serde(default)]
+    pub path: Option<String>,
+    pub id: String,
+    pub annotation: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct JsonImport {
+    version: u32,
+    annotations: Vec<ImportEntry>,
+}
+
+/// Parse a JSON or Web Annotation export produced by [`render`], or Web
+/// Annotations from another tool.
+pub fn parse_dump(json: &str) -> Result<Vec<ImportEntry>, String> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).map_err(|e| format!("not an annotation export: {e}"))?;
+    if crate::web_annotation::is_web_annotation(&value) {
+        return crate::web_annotation::parse(&value);
+    }
+    let dump: JsonImport =
+        serde_json::from_value(value).map_err(|e| format!("not an annotation export: {e}"))?;
+    if dump.version != 1 {
+        return Err(format!("unsupported export version {}", dump.version));
+    }
+    Ok(dump.annotations)
+}
+
+/// `file_path` with its leading `from` replaced by `to`, for a workspace
+/// that lives somewhere else on this machine. Paths outside `from` are
+/// returned unchanged.
+pub fn remap_root(file_path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match file_path.strip_prefix(from) {
+        Ok(rest) => to.join(rest),
+        Err(_) => file_path.to_path_buf(),
+    }
+}
+
+/// Outcome of [`import`], one counter per entry.
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Inserted, or updated in place when re-importing the same annotation.
+    pub imported: usize,
+    /// The file does not exist here (after remapping).
+    pub missing_files: usize,
+    /// The id is already used by an annotation on a different file.
+    pub conflicts: usize,
+    /// Malformed id or payload.
+    pub invalid: usize,
+}
+
+/// Store `entries` in one transaction. `resolve` maps an entry to the
+/// canonical path of the file it belongs to on this machine, or `None` when
+/// that file does not exist.
+pub fn import(
+    conn: &mut Connection,
+    entries: &[ImportEntry],
+    resolve: impl Fn(&ImportEntry) -> Option<PathBuf>,
+) -> rusqlite::Result<ImportReport> {
+    let (rows, mut report) = import_rows(entries, resolve);
+    let tx = conn.savepoint()?;
+    for row in &rows {
+        let stored =
+            upsert_annotation_for_file(&tx, &row.id, &row.file_path, &row.annotation.to_string())?;
+        if stored {
+            report.imported += 1;
+        } else {
+            report.conflicts += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(report)
+}
+
+/// The rows [`import`] would store, with the invalid and missing entries
+/// already counted in the report.
+pub(crate) fn import_rows(
+    entries: &[ImportEntry],
+    resolve: impl Fn(&ImportEntry) -> Option<PathBuf>,
+) -> (Vec<StoredAnnotation>, ImportReport) {
+    let mut rows = Vec::new();
+    let mut report = ImportReport::default();
+    for entry in entries {
+        if validate_annotation(&entry.annotation).is_err() {
+            report.invalid += 1;
+            continue;
+        }
+        let id_matches = entry
+            .annotation
+            .get("id")
+            .and_then(serde_json::Value::as_str)
+            == Some(entry.id.as_str());
+        if !valid_annotation_id(&entry.id) || !id_matches {
+            report.invalid += 1;
+            continue;
+        }
+        let Some(file) = resolve(entry) else {
+            report.missing_files += 1;
+            continue;
+        };
+        let mut annotation = entry.annotation.clone();
+        render_note(&mut annotation);
+        rows.push(StoredAnnotation {
+            id: entry.id.clone(),
+            file_path: file.to_string_lossy().into_owned(),
+            annotation,
+        });
+    }
+    (rows, report)
+}
+
+fn render_csv(annotations: &[ExportedAnnotation]) -> String {
+    let mut out = String::from("path,id,category,tags,text,note,author,created_at,updated_at\r\n");
+    for a in annotations {
+        let tags = a.tags.join(" ");
+        let fields = [
+            a.path.as_str(),
+            a.id.as_str(),
+            a.category.as_deref().unwrap_or(""),
+            tags.as_str(),
+            a.text.as_str(),
+            a.note.as_deref().unwrap_or(""),
+            a.author.as_deref().unwrap_or(""),
+            a.created_at.as_deref().unwrap_or(""),
+            a.updated_at.as_deref().unwrap_or(""),
+        ];
+        out.push_str(&fields.map(csv_field).join(","));
+        out.push_str("\r\n");
+    }
+    out
+}
+
+/// RFC 4180 quoting: wrap in quotes when the field has a delimiter, quote,
+/// or line break, doubling embedded quotes.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn render_markdown(annotations: &[ExportedAnnotation]) -> String {
+    let mut out = String::from("# Annotation report\n");
+    let files = {
+        let mut paths: Vec<&str> = annotations.iter().map(|a| a.path.as_str()).collect();
+        paths.dedup();
+        paths.len()
+    };
+    let _ = writeln!(
+        out,
+        "\n{} annotation(s) across {files} file(s).",
+        annotations.len()
+    );
+    let mut current: Option<&str> = None;
+    for a in annotations {
+        if current != Some(a.path.as_str()) {
+            let _ = writeln!(out, "\n## {}", a.path);
+            current = Some(a.path.as_str());
+        }
+        out.push('\n');
+        for line in a.text.lines() {
+            let _ = writeln!(out, "> {line}");
+        }
+        if let Some(note) = &a.note {
+            let _ = writeln!(out, "\n{}", note.trim());
+        }
+        let tags = (!a.tags.is_empty()).then(|| {
+            a.tags
+                .iter()
+                .map(|tag| format!("#{tag}"))
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        let byline: Vec<&str> = [
+            a.category.as_deref(),
+            tags.as_deref(),
+            a.author.as_deref(),
+            a.created_at.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !byline.is_empty() {
+            let _ = writeln!(out, "\n*{}*", byline.join(" · "));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::params;
+
+    fn store(rows: &[(&str, &str, serde_json::Value)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL);",
+        )
+        .unwrap();
+        for (id, path, data) in rows {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                params![id, path, data.to_string()],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn load_orders_filters_and_extracts_fields() {
+        let conn = store(&[
+            (
+                "anno-late",
+                "/ws/a.md",
+                serde_json::json!({ "id": "anno-late", "text": "later", "note": null, "createdAt": 1_700_000_100_000i64 }),
+            ),
+            (
+                "anno-early",
+                "/ws/a.md",
+                serde_json::json!({
+                    "id": "anno-early",
+                    "text": "Retry loop",
+                    "note": "Add jitter",
+                    "author": { "color": "#f00", "name": "Ana" },
+                    "createdAt": 1_700_000_000_000i64
+                }),
+            ),
+            (
+                "anno-other",
+                "/elsewhere/b.md",
+                serde_json::json!({ "id": "anno-other", "text": "x" }),
+            ),
+        ]);
+        let loaded = load(&conn, |path| {
+            path.strip_prefix("/ws")
+                .ok()
+                .map(|rel| rel.to_string_lossy().into_owned())
+        })
+        .unwrap();
+        let ids: Vec<&str> = loaded.iter().map(|a| a.id.as_str()).collect();
+        assert_eq!(ids, ["anno-early", "anno-late"]);
+        let first = &loaded[0];
+        assert_eq!(first.path, "a.md");
+        assert_eq!(first.file_path, "/ws/a.md");
+        assert_eq!(first.note.as_deref(), Some("Add jitter"));
+        assert_eq!(first.author.as_deref(), Some("Ana"));
+        assert_eq!(first.created_at.as_deref(), Some("2023-11-14T22:13:20Z"));
+        assert_eq!(loaded[1].note, None);
+        assert_eq!(loaded[1].author, None);
+    }
+
+    #[test]
+    fn import_round_trips_an_export_and_remaps_roots() {
+        let source = store(&[(
+            "anno-1",
+            "/old/ws/a.md",
+            serde_json::json!({ "id": "anno-1", "text": "Retry loop", "note": "jitter" }),
+        )]);
+        let dump = render(
+            &load(&source, |path| Some(path.to_string_lossy().into_owned())).unwrap(),
+            ExportFormat::Json,
+        );
+        let mut entries = parse_dump(&dump).unwrap();
+        entries.push(ImportEntry {
+            file_path: "/old/ws/gone.md".into(),
+            path: None,
+            id: "anno-2".into(),
+            annotation: serde_json::json!({ "id": "anno-2" }),
+        });
+        entries.push(ImportEntry {
+            file_path: "/old/ws/a.md".into(),
+            path: None,
+            id: "anno-3".into(),
+            annotation: serde_json::json!({ "id": "anno-mismatch" }),
+        });
+        entries.push(ImportEntry {
+            file_path: "/old/ws/a.md".into(),
+            path: None,
+            id: "anno-taken".into(),
+            annotation: serde_json::json!({ "id": "anno-taken" }),
+        });
+
+        let mut target = store(&[(
+            "anno-taken",
+            "/new/ws/b.md",
+            serde_json::json!({ "id": "anno-taken" }),
+        )]);
+        let resolve = |entry: &ImportEntry| {
+            let path = remap_root(
+                Path::new(&entry.file_path),
+                Path::new("/old/ws"),
+                Path::new("/new/ws"),
+            );
+            (!path.ends_with("gone.md")).then_some(path)
+        };
+        let report = import(&mut target, &entries, resolve).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 1,
+                missing_files: 1,
+                conflicts: 1,
+                invalid: 1,
+            }
+        );
+        let stored: String = target
+            .query_row(
+                "SELECT file_path FROM annotations WHERE id = 'anno-1'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(stored, "/new/ws/a.md");
+
+        // Re-importing the same dump updates in place.
+        assert_eq!(
+            import(&mut target, &entries[..1], resolve)
+                .unwrap()
+                .imported,
+            1
+        );
+        assert!(parse_dump(r#"{"version": 2, "annotations": []}"#).is_err());
+    }
+
+    #[test]
+    fn copies_only_annotations_under_a_directory() {
+        let source = store(&[
+            ("anno-1", "/ws/a.md", serde_json::json!({ "id": "anno-1" })),
+            (
+                "anno-2",
+                "/ws/docs/b.md",
+                serde_json::json!({ "id": "anno-2" }),
+            ),
+            ("anno-3", "/wsx/c.md", serde_json::json!({ "id": "anno-3" })),
+        ]);
+        let mut target = Connection::open_in_memory().unwrap();
+        create_table(&target).unwrap();
+
+        assert_eq!(copy_under(&source, &mut target, Path::new("/ws")), Ok(2));
+        let count: usize = target
+            .query_row("SELECT COUNT(*) FROM annotations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 2);
+        // Copying again updates in place.
+        assert_eq!(copy_under(&source, &mut target, Path::new("/ws")), Ok(2));
+
+        let root = tempfile::tempdir().unwrap();
+        assert_eq!(project_database(root.path()), None);
+        let db = root.path().join(PROJECT_DATABASE);
+        open(&db).unwrap();
+        assert_eq!(project_database(root.path()), Some(db));
+    }
+
+    #[test]
+    fn renders_csv_and_markdown() {
+        let annotation = ExportedAnnotation {
+            file_path: "/ws/a.md".into(),
+            path: "a.md".into(),
+            id: "anno-1".into(),
+            text: "first line\nsecond, \"quoted\"".into(),
+            note: Some("Check this".into()),
+            author: Some("Ana".into()),
+            category: Some("must-fix".into()),
+            tags: vec!["retries".into(), "api".into()],
+            created_at: Some("2023-11-14T22:13:20Z".into()),
+            updated_at: None,
+            annotation: serde_json::json!({ "id": "anno-1" }),
+        };
+        let csv = render(std::slice::from_ref(&annotation), ExportFormat::Csv);
+        assert_eq!(
+            csv,
+            "path,id,category,tags,text,note,author,created_at,updated_at\r\n\
+             a.md,anno-1,must-fix,retries api,\"first line\nsecond, \"\"quoted\"\"\",Check this,Ana,2023-11-14T22:13:20Z,\r\n"
+        );
+
+        let report = render(&[annotation], ExportFormat::Markdown);
+        assert!(
+            report.contains("1 annotation(s) across 1 file(s)."),
+            "{report}"
+        );
+        assert!(
+            report.contains(
+                "## a.md\n\n> first line\n> second, \"quoted\"\n\nCheck this\n\n*must-fix · #retries #api · Ana · 2023-11-14T22:13:20Z*\n"
+            ),
+            "{report}"
+        );
+
+        assert_eq!("md".parse::<ExportFormat>(), Ok(ExportFormat::Markdown));
+        assert_eq!(
+            "W3C".parse::<ExportFormat>(),
+            Ok(ExportFormat::WebAnnotation)
+        );
+        assert!("xml".parse::<ExportFormat>().is_err());
+        assert_eq!(parse_category(" Must-Fix "), Ok("must-fix".to_string()));
+        assert!(parse_category("nit").is_err());
+    }
+
+    #[test]
+    fn validate_annotation_checks_category_and_tags() {
+        let valid = |value: serde_json::Value| validate_annotation(&value).is_ok();
+        assert!(valid(serde_json::json!({ "id": "anno-1" })));
+        assert!(valid(serde_json::json!({ "category": null })));
+        assert!(valid(serde_json::json!({ "category": "question" })));
+        assert!(!valid(serde_json::json!({ "category": "urgent" })));
+        assert!(!valid(serde_json::json!({ "category": 3 })));
+        assert!(!valid(serde_json::json!(["not", "an", "object"])));
+        assert!(valid(serde_json::json!({ "tags": ["api", "#Retries"] })));
+        assert!(!valid(serde_json::json!({ "tags": "api" })));
+        assert!(!valid(serde_json::json!({ "tags": ["  "] })));
+        assert!(!valid(serde_json::json!({ "tags": ["x".repeat(65)] })));
+    }
+
+    #[test]
+    fn save_annotation_rejects_writes_from_stale_revisions() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        let save = |annotation: serde_json::Value| {
+            save_annotation(&conn, "anno-1", "/ws/a.md", annotation).unwrap()
+        };
+
+        let AnnotationSave::Saved(first) = save(serde_json::json!({ "id": "anno-1", "note": "a" }))
+        else {
+            panic!("new annotation rejected");
+        };
+        assert_eq!(revision(&first), 1);
+        let mut mine = first.clone();
+        mine["note"] = "mine".into();
+        let mut theirs = first;
+        theirs["note"] = "theirs".into();
+
+        let AnnotationSave::Saved(saved) = save(mine) else {
+            panic!("current revision rejected");
+        };
+        assert_eq!(revision(&saved), 2);
+        assert_eq!(save(theirs), AnnotationSave::Stale(saved.clone()));
+        assert_eq!(
+            save_annotation(&conn, "anno-1", "/ws/b.md", saved).unwrap(),
+            AnnotationSave::Foreign
+        );
+        assert!(validate_annotation(&serde_json::json!({ "revision": -1 })).is_err());
+    }
+
+    #[test]
+    fn set_resolved_stamps_the_resolver_and_counts_per_file() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        for (id, file) in [
+            ("anno-1", "/ws/a.md"),
+            ("anno-2", "/ws/a.md"),
+            ("anno-3", "/ws/b.md"),
+        ] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                rusqlite::params![id, file, serde_json::json!({ "id": id }).to_string()],
+            )
+            .unwrap();
+        }
+
+        let by = serde_json::json!({ "name": "Ana", "color": "#f00" });
+        let resolved = set_resolved(&conn, "anno-1", "/ws/a.md", true, Some(by.clone()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(resolved["resolved"], true);
+        assert_eq!(resolved["resolvedBy"], by);
+        assert!(resolved["resolvedAt"].as_u64().unwrap() > 0);
+        assert_eq!(revision(&resolved), 1);
+        assert!(validate_annotation(&resolved).is_ok());
+        assert_eq!(
+            set_resolved(&conn, "anno-1", "/ws/b.md", true, None),
+            Ok(None)
+        );
+
+        let counts = |file: &str| {
+            resolution_counts(&stored_annotations(&conn).unwrap())
+                .into_iter()
+                .find(|(path, _)| path == file)
+                .map(|(_, counts)| counts)
+        };
+        assert_eq!(
+            counts("/ws/a.md"),
+            Some(ResolutionCounts {
+                open: 1,
+                resolved: 1
+            })
+        );
+        assert_eq!(
+            counts("/ws/b.md"),
+            Some(ResolutionCounts {
+                open: 1,
+                resolved: 0
+            })
+        );
+
+        let reopened = set_resolved(&conn, "anno-1", "/ws/a.md", false, None)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            reopened,
+            serde_json::json!({ "id": "anno-1", "resolved": false, "revision": 2 })
+        );
+        assert_eq!(
+            counts("/ws/a.md"),
+            Some(ResolutionCounts {
+                open: 2,
+                resolved: 0
+            })
+        );
+
+        assert!(validate_resolver(&serde_json::json!({ "name": 3 })).is_err());
+        assert!(validate_resolver(&serde_json::json!({ "email": "a@b" })).is_err());
+        assert!(validate_annotation(&serde_json::json!({ "resolved": "yes" })).is_err());
+    }
+
+    #[test]
+    fn validate_annotation_accepts_cross_block_anchors() {
+        let quote = |position: u64, exact: &str| {
+            serde_json::json!({
+                "position": position, "exact": exact, "prefix": "", "suffix": "", "blockTag": "li"
+            })
+        };
+        let with_anchor = |anchor: serde_json::Value| {
+            validate_annotation(&serde_json::json!({
+                "id": "anno-1",
+                "text": "first item\nsecond item",
+                "anchor": anchor,
+            }))
+        };
+
+        // Pre-fragment anchors stay valid.
+        assert!(with_anchor(serde_json::json!({
+            "position": 3, "exact": "first item", "prefix": "", "suffix": ""
+        }))
+        .is_ok());
+        assert!(with_anchor(serde_json::json!({
+            "position": 3, "exact": "first itemsecond item", "prefix": "", "suffix": "",
+            "version": 2,
+            "fragments": [quote(3, "first item"), quote(13, "second item")],
+        }))
+        .is_ok());
+
+        assert!(with_anchor(serde_json::json!("first item")).is_err());
+        assert!(with_anchor(serde_json::json!({ "position": -1, "exact": "x" })).is_err());
+        assert!(with_anchor(serde_json::json!({
+            "position": 3, "exact": "x", "fragments": [quote(3, "x")],
+        }))
+        .is_err());
+        assert!(with_anchor(serde_json::json!({
+            "position": 3, "exact": "x", "version": 2, "fragments": [],
+        }))
+        .is_err());
+        assert!(with_anchor(serde_json::json!({
+            "position": 3, "exact": "x", "version": 2,
+            "fragments": [quote(13, "second item"), quote(3, "first item")],
+        }))
+        .is_err());
+        let mut bad_tag = quote(3, "x");
+        bad_tag["blockTag"] = "<script>".into();
+        assert!(with_anchor(serde_json::json!({
+            "position": 3, "exact": "x", "version": 2, "fragments": [bad_tag],
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn position_index_orders_annotations_by_anchor() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        for (id, data) in [
+            (
+                "anno-late",
+                serde_json::json!({ "type": "has-note", "anchor": { "position": 40 } }),
+            ),
+            (
+                "anno-loose",
+                serde_json::json!({ "type": "highlight-orange" }),
+            ),
+            (
+                "anno-early",
+                serde_json::json!({
+                    "anchor": { "position": 9, "version": 2, "fragments": [{ "position": 5 }] }
+                }),
+            ),
+        ] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, '/ws/a.md', ?2)",
+                rusqlite::params![id, data.to_string()],
+            )
+            .unwrap();
+        }
+
+        let index = position_index(&conn, "/ws/a.md").unwrap();
+        let order: Vec<(&str, Option<u64>)> = index
+            .iter()
+            .map(|entry| (entry.id.as_str(), entry.position))
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("anno-early", Some(5)),
+                ("anno-late", Some(40)),
+                ("anno-loose", None)
+            ]
+        );
+        assert_eq!(index[1].kind.as_deref(), Some("has-note"));
+        assert!(position_index(&conn, "/ws/b.md").unwrap().is_empty());
+    }
+
+    #[test]
+    fn deletes_are_kept_in_history_and_undone_batch_by_batch() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        let insert = |id: &str, file: &str| {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                rusqlite::params![id, file, serde_json::json!({ "id": id }).to_string()],
+            )
+            .unwrap();
+        };
+        insert("anno-1", "/ws/a.md");
+        insert("anno-2", "/ws/a.md");
+        insert("anno-3", "/ws/b.md");
+        let ids = |file: &str| {
+            let mut stmt = conn
+                .prepare("SELECT id FROM annotations WHERE file_path = ?1 ORDER BY id")
+                .unwrap();
+            stmt.query_map([file], |row| row.get::<_, String>(0))
+                .unwrap()
+                .collect::<rusqlite::Result<Vec<_>>>()
+                .unwrap()
+        };
+
+        assert_eq!(delete_annotations(&conn, "/ws/a.md", Some("anno-1")), Ok(1));
+        assert_eq!(delete_annotations(&conn, "/ws/a.md", None), Ok(1));
+        assert_eq!(delete_annotations(&conn, "/ws/a.md", Some("anno-3")), Ok(0));
+        assert_eq!(ids("/ws/a.md"), Vec::<String>::new());
+        assert_eq!(ids("/ws/b.md"), ["anno-3"]);
+
+        let log = history(&conn, "/ws/a.md").unwrap();
+        assert_eq!(
+            log.iter()
+                .map(|entry| (entry.id.as_str(), entry.action.as_str()))
+                .collect::<Vec<_>>(),
+            [("anno-2", "clear"), ("anno-1", "delete")]
+        );
+        assert_eq!(log[1].annotation, serde_json::json!({ "id": "anno-1" }));
+        assert!(history(&conn, "/ws/b.md").unwrap().is_empty());
+
+        assert_eq!(
+            undo(&conn, "/ws/a.md").unwrap(),
+            [serde_json::json!({ "id": "anno-2" })]
+        );
+        assert_eq!(ids("/ws/a.md"), ["anno-2"]);
+        // A reused id is not clobbered by the older copy.
+        insert("anno-1", "/ws/b.md");
+        assert!(undo(&conn, "/ws/a.md").unwrap().is_empty());
+        assert_eq!(ids("/ws/a.md"), ["anno-2"]);
+        assert!(history(&conn, "/ws/a.md").unwrap().is_empty());
+        assert!(undo(&conn, "/ws/a.md").unwrap().is_empty());
+
+        delete_annotations(&conn, "/ws/b.md", None).unwrap();
+        assert_eq!(prune_history(&conn, HISTORY_RETENTION), Ok(0));
+        conn.execute("UPDATE annotation_history SET at = 0", [])
+            .unwrap();
+        assert_eq!(prune_history(&conn, HISTORY_RETENTION), Ok(2));
+    }
+
+    #[test]
+    fn move_file_data_follows_file_and_directory_renames() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL);",
+        )
+        .unwrap();
+        crate::recent::create_tables(&conn).unwrap();
+        crate::recent::set_pinned(&conn, "c1", "/ws/docs/b.md", true).unwrap();
+        for (id, path) in [
+            ("anno-1", "/ws/a.md"),
+            ("anno-2", "/ws/docs/b.md"),
+            ("anno-3", "/ws/docs-old/c.md"),
+        ] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, '{}')",
+                params![id, path],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO viewed_state (file_path, state) VALUES ('/ws/a.md', 'old'), ('/ws/z.md', 'replaced')",
+            [],
+        )
+        .unwrap();
+        let file_of = |id: &str| -> String {
+            conn.query_row(
+                "SELECT file_path FROM annotations WHERE id = ?1",
+                [id],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            move_file_data(&conn, Path::new("/ws/a.md"), Path::new("/ws/z.md")).unwrap(),
+            [("/ws/a.md".to_string(), "/ws/z.md".to_string())]
+        );
+        assert_eq!(file_of("anno-1"), "/ws/z.md");
+        let viewed: Vec<(String, String)> = {
+            let mut stmt = conn
+                .prepare("SELECT file_path, state FROM viewed_state")
+                .unwrap();
+            stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        };
+        assert_eq!(viewed, [("/ws/z.md".to_string(), "old".to_string())]);
+
+        // Only whole path components match: `docs` is not a prefix of `docs-old`.
+        move_file_data(&conn, Path::new("/ws/docs"), Path::new("/ws/guide")).unwrap();
+        assert_eq!(file_of("anno-2"), "/ws/guide/b.md");
+        assert_eq!(file_of("anno-3"), "/ws/docs-old/c.md");
+        assert_eq!(
+            crate::recent::pinned_files(&conn, "c1", Path::new("/ws")).unwrap(),
+            ["/ws/guide/b.md"]
+        );
+        assert!(
+            move_file_data(&conn, Path::new("/ws/none.md"), Path::new("/ws/x.md"))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn viewed_state_is_listed_and_reset_by_scope() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        assert!(viewed_states(&conn, None).unwrap().is_empty());
+        conn.execute_batch(
+            r#"CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL);
+               INSERT INTO viewed_state (file_path, state) VALUES
+                   ('/ws/docs/a.md', '{"intro":true}'),
+                   ('/ws/docs-old/b.md', '{}'),
+                   ('/ws/readme.md', 'not json');"#,
+        )
+        .unwrap();
+
+        let all = viewed_states(&conn, None).unwrap();
+        assert_eq!(
+            all.iter().map(|r| r.file_path.as_str()).collect::<Vec<_>>(),
+            ["/ws/docs-old/b.md", "/ws/docs/a.md", "/ws/readme.md"]
+        );
+        assert_eq!(all[1].state, serde_json::json!({ "intro": true }));
+        assert_eq!(all[2].state, serde_json::Value::Null);
+
+        assert_eq!(
+            reset_viewed(&conn, Some(Path::new("/ws/docs"))).unwrap(),
+            ["/ws/docs/a.md"]
+        );
+        assert_eq!(
+            reset_viewed(&conn, Some(Path::new("/ws/readme.md"))).unwrap(),
+            ["/ws/readme.md"]
+        );
+        assert_eq!(reset_viewed(&conn, None).unwrap(), ["/ws/docs-old/b.md"]);
+        assert!(viewed_states(&conn, None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn prune_removes_data_of_deleted_files_under_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        let kept = root.join("kept.md");
+        std::fs::write(&kept, "# kept").unwrap();
+        let (kept, gone, elsewhere) = (
+            kept.to_string_lossy().into_owned(),
+            root.join("gone.md").to_string_lossy().into_owned(),
+            "/elsewhere/gone.md",
+        );
+        let conn = Connection::open_in_memory().unwrap();
+        create_table(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL);",
+        )
+        .unwrap();
+        for (id, path) in [
+            ("anno-1", kept.as_str()),
+            ("anno-2", gone.as_str()),
+            ("anno-3", gone.as_str()),
+            ("anno-4", elsewhere),
+        ] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, '{}')",
+                params![id, path],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT OR IGNORE INTO viewed_state (file_path, state) VALUES (?1, '{}')",
+                [path],
+            )
+            .unwrap();
+        }
+
+        let missing = missing_files(&conn, root).unwrap();
+        assert_eq!(
+            missing,
+            [MissingFile {
+                file_path: gone.clone(),
+                annotations: 2,
+                viewed: true,
+            }]
+        );
+        assert_eq!(prune_missing_files(&conn, &missing, true).unwrap(), missing);
+        assert!(missing_files(&conn, root).unwrap().is_empty());
+        let count = |table: &str| -> usize {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert_eq!((count("annotations"), count("viewed_state")), (2, 2));
+        let log = history(&conn, &gone).unwrap();
+        assert_eq!(log.len(), 2);
+        assert!(log.iter().all(|entry| entry.action == "prune"));
+        assert_eq!(undo(&conn, &gone).unwrap().len(), 2);
+
+        // A file that reappeared since the report is left alone.
+        std::fs::write(root.join("gone.md"), "# back").unwrap();
+        assert!(prune_missing_files(&conn, &missing, false)
+            .unwrap()
+            .is_empty());
+        assert_eq!(count("annotations"), 4);
+    }
+
+    #[test]
+    fn filter_matches_tags_author_resolution_and_category() {
+        let annotation = serde_json::json!({
+            "id": "anno-1",
+            "tags": ["API", "#retries"],
+            "author": { "color": "#f00", "name": "Ana" },
+            "category": "question"
+        });
+        let filter = |json: serde_json::Value| {
+            serde_json::from_value::<AnnotationFilter>(json)
+                .unwrap()
+                .normalized()
+                .unwrap()
+        };
+        assert!(filter(serde_json::json!({})).matches(&annotation));
+        assert!(
+            filter(serde_json::json!({ "tag": "#Retries", "author": " ana " }))
+                .matches(&annotation)
+        );
+        assert!(
+            filter(serde_json::json!({ "resolved": false, "category": "question" }))
+                .matches(&annotation)
+        );
+        assert!(!filter(serde_json::json!({ "tag": "ops" })).matches(&annotation));
+        assert!(!filter(serde_json::json!({ "author": "Bo" })).matches(&annotation));
+        assert!(!filter(serde_json::json!({ "resolved": true })).matches(&annotation));
+        assert!(serde_json::from_value::<AnnotationFilter>(
+            serde_json::json!({ "category": "nit" })
+        )
+        .unwrap()
+        .normalized()
+        .is_err());
+    }
+}
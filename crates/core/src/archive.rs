@@ -0,0 +1,437 @@
+//! Zip and tar archives browsed as read-only directories.
+//!
+//! A route that passes through an archive file (`/{id}/docs.zip/guide/`)
+//! lists the entries under that folder of the archive, and a Markdown entry
+//! renders like a document, without extracting anything to disk. Entry
+//! names are normalized to `/`-separated relative paths; names that are
+//! absolute or climb out with `..` are left out.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Entries listed from one archive at most.
+const MAX_ENTRIES: usize = 20_000;
+
+/// Largest entry read out of an archive.
+pub(crate) const MAX_ENTRY_BYTES: u64 = 16 * 1024 * 1024;
+
+/// Tar listings kept by an [`IndexCache`].
+const INDEXED_ARCHIVES: usize = 16;
+
+/// The archive formats that can be browsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+/// File name suffixes of each kind, matched case-insensitively.
+const SUFFIXES: &[(&str, Kind)] = &[
+    (".zip", Kind::Zip),
+    (".tar", Kind::Tar),
+    (".tar.gz", Kind::TarGz),
+    (".tgz", Kind::TarGz),
+];
+
+/// The archive kind of a file named `name`.
+fn kind_for_name(name: &str) -> Option<Kind> {
+    let name = name.to_ascii_lowercase();
+    SUFFIXES
+        .iter()
+        .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix))
+        .map(|(_, kind)| *kind)
+}
+
+/// The archive kind of `path`, by its file name.
+pub(crate) fn kind_for(path: &Path) -> Option<Kind> {
+    kind_for_name(path.file_name()?.to_str()?)
+}
+
+/// Split a workspace route at the first component named like an archive
+/// and followed by `/`: `docs.zip/guide/a.md` gives `("docs.zip",
+/// "guide/a.md")`, and `docs.zip/` gives `("docs.zip", "")`.
+pub(crate) fn split_route(route: &str) -> Option<(&str, &str)> {
+    let mut start = 0;
+    while let Some(slash) = route[start..].find('/').map(|at| start + at) {
+        if kind_for_name(&route[start..slash]).is_some() {
+            return Some((&route[..slash], &route[slash + 1..]));
+        }
+        start = slash + 1;
+    }
+    None
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum ArchiveError {
+    #[error("could not read the archive: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("could not read the archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+    #[error("the archive holds more than {MAX_ENTRIES} entries")]
+    TooManyEntries,
+    #[error("the entry is larger than {} MiB", MAX_ENTRY_BYTES / 1024 / 1024)]
+    TooLarge,
+    #[error("the entry is encrypted")]
+    Encrypted,
+    #[error("no such entry in the archive")]
+    NotFound,
+}
+
+/// A file or folder inside an archive.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct Entry {
+    /// Normalized path inside the archive, without a trailing `/`.
+    pub(crate) path: String,
+    pub(crate) is_dir: bool,
+    /// Uncompressed size; 0 for folders.
+    pub(crate) size: u64,
+}
+
+impl Entry {
+    /// The last component of the path.
+    pub(crate) fn name(&self) -> &str {
+        self.path.rsplit('/').next().unwrap_or(&self.path)
+    }
+}
+
+/// `name` as a clean relative path, or `None` when it is empty, absolute or
+/// leaves the archive.
+fn normalize(name: &str) -> Option<String> {
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') {
+        return None;
+    }
+    let mut parts = Vec::new();
+    for part in name.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            part => parts.push(part),
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+/// Every entry of the archive at `path`, folders first, then by path. Folders
+/// that are only implied by the paths of their files are included.
+pub(crate) fn entries(path: &Path, kind: Kind) -> Result<Vec<Entry>, ArchiveError> {
+    let mut found = BTreeMap::new();
+    let mut add = |name: &str, is_dir: bool, size: u64| -> Result<(), ArchiveError> {
+        let Some(path) = normalize(name) else {
+            return Ok(());
+        };
+        let mut parent = path.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            found.entry(dir.to_string()).or_insert((true, 0));
+            parent = dir;
+        }
+        found.insert(path, (is_dir, if is_dir { 0 } else { size }));
+        if found.len() > MAX_ENTRIES {
+            return Err(ArchiveError::TooManyEntries);
+        }
+        Ok(())
+    };
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            for index in 0..archive.len() {
+                let file = archive.by_index_raw(index)?;
+                add(file.name(), file.is_dir(), file.size())?;
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            let mut archive = tar::Archive::new(tar_reader(path, kind)?);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                let entry_type = entry.header().entry_type();
+                if !entry_type.is_file() && !entry_type.is_dir() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().into_owned();
+                add(&name, entry_type.is_dir(), entry.size())?;
+            }
+        }
+    }
+    let mut entries: Vec<Entry> = found
+        .into_iter()
+        .map(|(path, (is_dir, size))| Entry { path, is_dir, size })
+        .collect();
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.path.cmp(&b.path)));
+    Ok(entries)
+}
+
+/// Listings of tar archives. A tar has no central directory, so listing one
+/// reads the whole file (and decompresses it when gzipped); a listing is
+/// reused while the archive's modification time is unchanged. Zip listings
+/// come from the central directory and are read each time.
+#[derive(Default)]
+pub(crate) struct IndexCache {
+    indexes: HashMap<PathBuf, (SystemTime, Arc<Vec<Entry>>)>,
+    order: VecDeque<PathBuf>,
+}
+
+impl IndexCache {
+    /// [`entries`] of the archive at `path`, from `cache` when it is current.
+    pub(crate) fn entries(
+        cache: &Mutex<Self>,
+        path: &Path,
+        kind: Kind,
+    ) -> Result<Arc<Vec<Entry>>, ArchiveError> {
+        if kind == Kind::Zip {
+            return entries(path, kind).map(Arc::new);
+        }
+        let modified = std::fs::metadata(path)?.modified()?;
+        if let Some((at, listed)) = cache.lock().unwrap().indexes.get(path) {
+            if *at == modified {
+                return Ok(listed.clone());
+            }
+        }
+        // Listed without the lock, so one large archive doesn't hold up others.
+        let listed = Arc::new(entries(path, kind)?);
+        let mut cache = cache.lock().unwrap();
+        cache
+            .indexes
+            .insert(path.to_path_buf(), (modified, listed.clone()));
+        cache.order.retain(|indexed| indexed != path);
+        cache.order.push_back(path.to_path_buf());
+        while cache.order.len() > INDEXED_ARCHIVES {
+            if let Some(oldest) = cache.order.pop_front() {
+                cache.indexes.remove(&oldest);
+            }
+        }
+        Ok(listed)
+    }
+}
+
+/// The entries directly inside the folder `dir` (`""` for the top level).
+pub(crate) fn children<'a>(entries: &'a [Entry], dir: &'a str) -> impl Iterator<Item = &'a Entry> {
+    entries.iter().filter(move |entry| {
+        let rest = if dir.is_empty() {
+            Some(entry.path.as_str())
+        } else {
+            entry
+                .path
+                .strip_prefix(dir)
+                .and_then(|rest| rest.strip_prefix('/'))
+        };
+        rest.is_some_and(|rest| !rest.contains('/'))
+    })
+}
+
+/// The contents of the file `inner` in the archive at `path`. Other entries
+/// are matched by name only, so an encrypted one elsewhere in the archive
+/// doesn't get in the way.
+pub(crate) fn read(path: &Path, kind: Kind, inner: &str) -> Result<Vec<u8>, ArchiveError> {
+    match kind {
+        Kind::Zip => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(File::open(path)?))?;
+            for index in 0..archive.len() {
+                let file = archive.by_index_raw(index)?;
+                if file.is_dir() || normalize(file.name()).as_deref() != Some(inner) {
+                    continue;
+                }
+                drop(file);
+                return match archive.by_index(index) {
+                    Ok(file) => read_limited(file),
+                    Err(zip::result::ZipError::UnsupportedArchive(
+                        zip::result::ZipError::PASSWORD_REQUIRED,
+                    )) => Err(ArchiveError::Encrypted),
+                    Err(e) => Err(e.into()),
+                };
+            }
+        }
+        Kind::Tar | Kind::TarGz => {
+            let mut archive = tar::Archive::new(tar_reader(path, kind)?);
+            for entry in archive.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file()
+                    && normalize(&entry.path()?.to_string_lossy()).as_deref() == Some(inner)
+                {
+                    return read_limited(entry);
+                }
+            }
+        }
+    }
+    Err(ArchiveError::NotFound)
+}
+
+fn tar_reader(path: &Path, kind: Kind) -> std::io::Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(path)?);
+    Ok(match kind {
+        Kind::TarGz => Box::new(flate2::read::GzDecoder::new(file)),
+        _ => Box::new(file),
+    })
+}
+
+/// Read `reader` to the end, refusing more than [`MAX_ENTRY_BYTES`] — the
+/// size an archive declares for an entry can't be trusted.
+fn read_limited(reader: impl Read) -> Result<Vec<u8>, ArchiveError> {
+    let mut bytes = Vec::new();
+    reader.take(MAX_ENTRY_BYTES + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_ENTRY_BYTES {
+        return Err(ArchiveError::TooLarge);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn zip_file(dir: &Path, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let path = dir.join("bundle.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (name, content) in files {
+            writer
+                .start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn routes_split_at_the_first_archive_component() {
+        assert_eq!(
+            split_route("docs/release.tar.gz/guide/a.md"),
+            Some(("docs/release.tar.gz", "guide/a.md"))
+        );
+        assert_eq!(split_route("Bundle.ZIP/"), Some(("Bundle.ZIP", "")));
+        assert_eq!(split_route("bundle.zip"), None);
+        assert_eq!(split_route(".zip/a.md"), None);
+        assert_eq!(kind_for(Path::new("x.tgz")), Some(Kind::TarGz));
+    }
+
+    #[test]
+    fn zip_entries_list_implied_folders_and_skip_escaping_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = zip_file(
+            dir.path(),
+            &[
+                ("./guide/intro.md", "# Intro"),
+                ("README.md", "# Bundle"),
+                ("../evil.md", "no"),
+            ],
+        );
+        let entries = entries(&path, Kind::Zip).unwrap();
+        let paths: Vec<_> = entries.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["guide", "README.md", "guide/intro.md"]);
+        let top: Vec<_> = children(&entries, "").map(Entry::name).collect();
+        assert_eq!(top, ["guide", "README.md"]);
+        let guide: Vec<_> = children(&entries, "guide").map(Entry::name).collect();
+        assert_eq!(guide, ["intro.md"]);
+
+        assert_eq!(
+            read(&path, Kind::Zip, "guide/intro.md").unwrap(),
+            b"# Intro"
+        );
+        assert!(matches!(
+            read(&path, Kind::Zip, "guide"),
+            Err(ArchiveError::NotFound)
+        ));
+    }
+
+    #[test]
+    fn an_encrypted_zip_entry_fails_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = zip_file(
+            dir.path(),
+            &[("secret.md", "# Secret"), ("README.md", "# Bundle")],
+        );
+        // Mark the first entry encrypted (general purpose flag bit 0) in its
+        // local header and central directory record.
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[6] |= 1;
+        let central = bytes
+            .windows(4)
+            .position(|window| window == b"PK\x01\x02")
+            .unwrap();
+        bytes[central + 8] |= 1;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert_eq!(entries(&path, Kind::Zip).unwrap().len(), 2);
+        assert!(matches!(
+            read(&path, Kind::Zip, "secret.md"),
+            Err(ArchiveError::Encrypted)
+        ));
+        assert_eq!(read(&path, Kind::Zip, "README.md").unwrap(), b"# Bundle");
+    }
+
+    fn tar_gz_file(path: &Path, files: &[(&str, &str)]) {
+        let encoder = flate2::write::GzEncoder::new(
+            File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut builder = tar::Builder::new(encoder);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn tar_listings_are_cached_until_the_archive_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.tgz");
+        tar_gz_file(&path, &[("a.md", "a")]);
+        let cache = Mutex::new(IndexCache::default());
+
+        let first = IndexCache::entries(&cache, &path, Kind::TarGz).unwrap();
+        let again = IndexCache::entries(&cache, &path, Kind::TarGz).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+
+        tar_gz_file(&path, &[("a.md", "a"), ("b.md", "b")]);
+        let later = SystemTime::now() + std::time::Duration::from_secs(5);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        let changed = IndexCache::entries(&cache, &path, Kind::TarGz).unwrap();
+        let paths: Vec<_> = changed.iter().map(|entry| entry.path.as_str()).collect();
+        assert_eq!(paths, ["a.md", "b.md"]);
+    }
+
+    #[test]
+    fn gzipped_tar_entries_are_listed_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("release.tar.gz");
+        let content = b"# Notes\n";
+        tar_gz_file(&path, &[("release/NOTES.md", "# Notes\n")]);
+
+        let entries = entries(&path, Kind::TarGz).unwrap();
+        assert_eq!(
+            entries,
+            [
+                Entry {
+                    path: "release".into(),
+                    is_dir: true,
+                    size: 0
+                },
+                Entry {
+                    path: "release/NOTES.md".into(),
+                    is_dir: false,
+                    size: 8
+                },
+            ]
+        );
+        assert_eq!(
+            read(&path, Kind::TarGz, "release/NOTES.md").unwrap(),
+            content
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use rust_embed::RustEmbed;
+use rust_embed::{EmbeddedFile, RustEmbed};
 
 #[derive(RustEmbed)]
 #[folder = "assets/css/"]
@@ -15,3 +15,113 @@ pub(crate) struct Templates;
 #[derive(RustEmbed)]
 #[folder = "assets/icons/"]
 pub(crate) struct IconAssets;
+
+/// Hex digits of the content hash carried in a fingerprinted asset name.
+const FINGERPRINT_LEN: usize = 10;
+
+/// `Cache-Control` for fingerprinted URLs: a new build changes the name, so
+/// browsers may keep the old one forever.
+pub(crate) const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Leading hex digits of the SHA-256 rust-embed records for `file` (at
+/// compile time in release builds).
+pub(crate) fn fingerprint(file: &EmbeddedFile) -> String {
+    file.metadata.sha256_hash()[..FINGERPRINT_LEN / 2]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// `/_/css/tokens.css` → `/_/css/tokens.<hash>.css` for an embedded
+/// stylesheet or script. Any other URL, or an asset missing from this build,
+/// is returned unchanged and served without long-lived caching.
+pub(crate) fn fingerprinted_url(url: &str) -> String {
+    let file = if let Some(name) = url.strip_prefix("/_/css/") {
+        CssAssets::get(name)
+    } else if let Some(path) = url.strip_prefix("/_/js/") {
+        JsAssets::get(path)
+    } else {
+        None
+    };
+    let Some(file) = file else {
+        return url.to_string();
+    };
+    let name_start = url.rfind('/').map_or(0, |slash| slash + 1);
+    match url[name_start..].rfind('.') {
+        Some(dot) => {
+            let dot = name_start + dot;
+            format!("{}.{}{}", &url[..dot], fingerprint(&file), &url[dot..])
+        }
+        None => format!("{url}.{}", fingerprint(&file)),
+    }
+}
+
+/// Split `name.<hash>.ext` back into `name.ext` and the hash.
+pub(crate) fn strip_fingerprint(path: &str) -> Option<(String, &str)> {
+    let name_start = path.rfind('/').map_or(0, |slash| slash + 1);
+    let name = &path[name_start..];
+    let (stem, extension) = match name.rfind('.') {
+        Some(dot) => (&name[..dot], &name[dot..]),
+        None => (name, ""),
+    };
+    let (base, hash) = stem.rsplit_once('.')?;
+    let is_fingerprint = hash.len() == FINGERPRINT_LEN
+        && hash
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte));
+    if !is_fingerprint || base.is_empty() {
+        return None;
+    }
+    Some((format!("{}{base}{extension}", &path[..name_start]), hash))
+}
+
+/// `{{ '/_/js/main.js' | asset }}`: the fingerprinted URL templates link
+/// stylesheets and scripts through.
+struct AssetFilter;
+
+impl tera::Filter for AssetFilter {
+    fn filter(
+        &self,
+        value: &tera::Value,
+        _: &std::collections::HashMap<String, tera::Value>,
+    ) -> tera::Result<tera::Value> {
+        let url = tera::try_get_value!("asset", "value", String, value);
+        Ok(tera::Value::String(fingerprinted_url(&url)))
+    }
+
+    /// Template literals in, embedded asset names out: nothing to escape.
+    fn is_safe(&self) -> bool {
+        true
+    }
+}
+
+/// Register the template filters for embedded assets.
+pub(crate) fn register_filters(tera: &mut tera::Tera) {
+    tera.register_filter("asset", AssetFilter);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fingerprinted_names_round_trip_to_the_embedded_file() {
+        let url = fingerprinted_url("/_/css/tokens.css");
+        let hash = fingerprint(&CssAssets::get("tokens.css").unwrap());
+        assert_eq!(url, format!("/_/css/tokens.{hash}.css"));
+        assert_eq!(
+            strip_fingerprint(url.strip_prefix("/_/css/").unwrap()),
+            Some(("tokens.css".to_string(), hash.as_str()))
+        );
+        assert_eq!(
+            strip_fingerprint("katex/katex.min.0123456789.css"),
+            Some(("katex/katex.min.css".to_string(), "0123456789"))
+        );
+        assert_eq!(strip_fingerprint("katex/katex.min.css"), None);
+        assert_eq!(
+            fingerprinted_url("/_/css/missing.css"),
+            "/_/css/missing.css"
+        );
+        assert_eq!(fingerprinted_url("/_/favicon.svg"), "/_/favicon.svg");
+    }
+}
@@ -0,0 +1,173 @@
+//! Image attachments on annotations (pasted screenshots).
+//!
+//! Blobs live in an `attachments/` directory beside the annotation database,
+//! named by content hash plus extension — the name is the attachment id an
+//! annotation lists under `attachments`. Identical uploads share one file, and
+//! an id can never name anything outside the directory.
+
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Largest accepted upload.
+pub const MAX_BYTES: usize = 5 * 1024 * 1024;
+
+/// Most attachments one annotation may list.
+pub const MAX_PER_ANNOTATION: usize = 8;
+
+/// Image formats accepted, by extension and content type. The type is sniffed
+/// from the bytes, never taken from the client.
+const FORMATS: &[(&str, &str)] = &[
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+];
+
+/// The attachment directory for the database at `db_path`.
+pub fn directory(db_path: &Path) -> PathBuf {
+    db_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .join("attachments")
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AttachmentError {
+    #[error("Attachment is larger than 5 MiB")]
+    TooLarge,
+    #[error("Attachment must be a PNG, JPEG, GIF, or WebP image")]
+    UnsupportedType,
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("png")
+    } else if bytes.starts_with(b"\xff\xd8\xff") {
+        Some("jpg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("webp")
+    } else {
+        None
+    }
+}
+
+/// Whether `id` has the shape [`store`] produces.
+pub fn valid_id(id: &str) -> bool {
+    id.split_once('.').is_some_and(|(hash, ext)| {
+        hash.len() == 64
+            && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+            && FORMATS.iter().any(|(known, _)| *known == ext)
+    })
+}
+
+/// Content type to serve attachment `id` with.
+pub fn content_type(id: &str) -> Option<&'static str> {
+    let (_, ext) = id.rsplit_once('.')?;
+    FORMATS
+        .iter()
+        .find(|(known, _)| *known == ext)
+        .map(|(_, mime)| *mime)
+}
+
+/// Store an image under `dir` and return its id.
+pub fn store(dir: &Path, bytes: &[u8]) -> Result<String, AttachmentError> {
+    if bytes.len() > MAX_BYTES {
+        return Err(AttachmentError::TooLarge);
+    }
+    let ext = sniff(bytes).ok_or(AttachmentError::UnsupportedType)?;
+    let mut id = String::with_capacity(64 + 1 + ext.len());
+    for byte in Sha256::digest(bytes) {
+        let _ = write!(id, "{byte:02x}");
+    }
+    id.push('.');
+    id.push_str(ext);
+
+    let path = dir.join(&id);
+    if path.is_file() {
+        return Ok(id);
+    }
+    std::fs::create_dir_all(dir)?;
+    // Write aside and rename so a reader never sees a partial image.
+    let tmp = dir.join(format!(".{id}.{}.tmp", std::process::id()));
+    let written = std::fs::File::create(&tmp)
+        .and_then(|mut file| file.write_all(bytes).and_then(|()| file.sync_all()))
+        .and_then(|()| std::fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(id)
+}
+
+/// Read attachment `id` from `dir`; `None` for an unknown or malformed id.
+pub fn load(dir: &Path, id: &str) -> Option<Vec<u8>> {
+    if !valid_id(id) {
+        return None;
+    }
+    std::fs::read(dir.join(id)).ok()
+}
+
+/// Check an annotation's `attachments` field: absent, or a short list of ids.
+pub(crate) fn validate(value: Option<&serde_json::Value>) -> Result<(), &'static str> {
+    match value {
+        None | Some(serde_json::Value::Null) => Ok(()),
+        Some(serde_json::Value::Array(ids))
+            if ids.len() <= MAX_PER_ANNOTATION
+                && ids.iter().all(|id| id.as_str().is_some_and(valid_id)) =>
+        {
+            Ok(())
+        }
+        Some(_) => Err("Annotation attachments must be a list of at most 8 attachment ids"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn stores_images_by_content_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let attachments = dir.path().join("attachments");
+
+        let id = store(&attachments, PNG).unwrap();
+        assert!(valid_id(&id));
+        assert!(id.ends_with(".png"));
+        assert_eq!(content_type(&id), Some("image/png"));
+        assert_eq!(store(&attachments, PNG).unwrap(), id);
+        assert_eq!(load(&attachments, &id).as_deref(), Some(PNG));
+        assert_eq!(std::fs::read_dir(&attachments).unwrap().count(), 1);
+
+        assert!(matches!(
+            store(&attachments, b"<svg onload=alert(1)>"),
+            Err(AttachmentError::UnsupportedType)
+        ));
+        let mut huge = PNG.to_vec();
+        huge.resize(MAX_BYTES + 1, 0);
+        assert!(matches!(
+            store(&attachments, &huge),
+            Err(AttachmentError::TooLarge)
+        ));
+    }
+
+    #[test]
+    fn ids_cannot_escape_the_directory() {
+        assert!(!valid_id("../annotation.sqlite"));
+        assert!(!valid_id(&format!("{}.svg", "a".repeat(64))));
+        assert!(!valid_id(&format!("{}.png", "A".repeat(64))));
+        assert!(load(Path::new("/"), "../etc/passwd").is_none());
+
+        let id = format!("{}.webp", "0".repeat(64));
+        assert!(validate(Some(&serde_json::json!([&id]))).is_ok());
+        assert!(validate(Some(&serde_json::json!(["x.png"]))).is_err());
+        assert!(validate(Some(&serde_json::Value::Array(vec![id.into(); 9]))).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! An append-only log of who changed annotations and which files were
+//! served (`--audit`).
+//!
+//! With the log on, every annotation created, edited, resolved, reopened,
+//! deleted, cleared, restored, imported or pruned through the server, and
+//! every file it serves, adds a row to the server database: when, what,
+//! which file and annotation, the display name the browser sent (names are
+//! self-declared), the access role the request was granted and the peer
+//! address. Triggers refuse to update or delete rows, so the log only
+//! grows. `markon audit` lists it without a running server.
+
+use std::path::Path;
+
+use rusqlite::{params, Connection};
+
+pub(crate) fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            at INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            annotation_id TEXT,
+            actor TEXT,
+            role TEXT,
+            ip TEXT
+        );
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_update BEFORE UPDATE ON audit_log
+        BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+        CREATE TRIGGER IF NOT EXISTS audit_log_no_delete BEFORE DELETE ON audit_log
+        BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+    )
+}
+
+/// What happened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Create,
+    Edit,
+    Resolve,
+    Reopen,
+    Delete,
+    Clear,
+    Restore,
+    Import,
+    Prune,
+    Serve,
+}
+
+impl Action {
+    const ALL: [Action; 10] = [
+        Action::Create,
+        Action::Edit,
+        Action::Resolve,
+        Action::Reopen,
+        Action::Delete,
+        Action::Clear,
+        Action::Restore,
+        Action::Import,
+        Action::Prune,
+        Action::Serve,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Action::Create => "create",
+            Action::Edit => "edit",
+            Action::Resolve => "resolve",
+            Action::Reopen => "reopen",
+            Action::Delete => "delete",
+            Action::Clear => "clear",
+            Action::Restore => "restore",
+            Action::Import => "import",
+            Action::Prune => "prune",
+            Action::Serve => "serve",
+        }
+    }
+}
+
+impl std::str::FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> = Action::ALL.iter().map(|action| action.as_str()).collect();
+                format!("unknown audit action `{s}` (one of {})", names.join(", "))
+            })
+    }
+}
+
+/// One thing a request did, attached by a handler to its response; the
+/// server fills in the role and peer address and writes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Event {
+    pub(crate) action: Action,
+    /// Canonical path, like the annotations' keys.
+    pub(crate) file_path: String,
+    pub(crate) annotation_id: Option<String>,
+    /// Display name from the `{name, color}` identity the request carried.
+    pub(crate) actor: Option<String>,
+}
+
+impl Event {
+    pub(crate) fn new(action: Action, file_path: impl Into<String>) -> Self {
+        Self {
+            action,
+            file_path: file_path.into(),
+            annotation_id: None,
+            actor: None,
+        }
+    }
+
+    pub(crate) fn annotation(mut self, id: impl Into<String>) -> Self {
+        self.annotation_id = Some(id.into());
+        self
+    }
+
+    /// The display name of `identity`, when it has one.
+    pub(crate) fn by(mut self, identity: Option<&serde_json::Value>) -> Self {
+        self.actor = identity
+            .and_then(|identity| identity.get("name"))
+            .and_then(serde_json::Value::as_str)
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string);
+        self
+    }
+}
+
+/// The events of one response, as a response extension.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Trail(pub(crate) Vec<Event>);
+
+/// Append `events`, done by a request granted `role` from `ip`.
+pub(crate) fn record(
+    conn: &Connection,
+    events: &[Event],
+    role: Option<&str>,
+    ip: Option<&str>,
+) -> rusqlite::Result<()> {
+    let at = crate::annotations::now_millis() as i64;
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO audit_log (at, action, file_path, annotation_id, actor, role, ip)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    )?;
+    for event in events {
+        stmt.execute(params![
+            at,
+            event.action.as_str(),
+            event.file_path,
+            event.annotation_id,
+            event.actor,
+            role,
+            ip
+        ])?;
+    }
+    Ok(())
+}
+
+/// One row of the log.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    /// UTC time, `2024-05-01T09:30:00Z`.
+    pub at: String,
+    pub action: String,
+    pub file_path: String,
+    pub annotation_id: Option<String>,
+    pub actor: Option<String>,
+    /// `admin` or `collaborator`.
+    pub role: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// Which rows [`list`] returns.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery<'a> {
+    /// A file, or a directory whose files' rows to list.
+    pub scope: Option<&'a Path>,
+    pub action: Option<Action>,
+    /// At most this many rows; all when `None`.
+    pub limit: Option<usize>,
+}
+
+/// Rows of the log matching `query`, newest first. A database the server
+/// never audited into has none.
+pub fn list(conn: &Connection, query: &AuditQuery<'_>) -> rusqlite::Result<Vec<AuditEntry>> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'audit_log')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(Vec::new());
+    }
+    let mut stmt = conn.prepare(
+        "SELECT id, strftime('%Y-%m-%dT%H:%M:%SZ', at / 1000, 'unixepoch'), action,
+                file_path, annotation_id, actor, role, ip
+         FROM audit_log
+         WHERE ?1 IS NULL OR action = ?1
+         ORDER BY id DESC",
+    )?;
+    let rows = stmt.query_map([query.action.map(Action::as_str)], |row| {
+        Ok(AuditEntry {
+            id: row.get(0)?,
+            at: row.get(1)?,
+            action: row.get(2)?,
+            file_path: row.get(3)?,
+            annotation_id: row.get(4)?,
+            actor: row.get(5)?,
+            role: row.get(6)?,
+            ip: row.get(7)?,
+        })
+    })?;
+    let mut entries = Vec::new();
+    for row in rows {
+        let entry = row?;
+        if query
+            .scope
+            .is_some_and(|scope| !Path::new(&entry.file_path).starts_with(scope))
+        {
+            continue;
+        }
+        entries.push(entry);
+        if query.limit.is_some_and(|limit| entries.len() >= limit) {
+            break;
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_log_only_grows_and_lists_newest_first() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        let alice = serde_json::json!({ "name": " Alice ", "color": "#f00" });
+        record(
+            &conn,
+            &[
+                Event::new(Action::Create, "/ws/docs/a.md")
+                    .annotation("anno-1")
+                    .by(Some(&alice)),
+                Event::new(Action::Serve, "/ws/notes.md"),
+            ],
+            Some("collaborator"),
+            Some("192.168.1.20"),
+        )
+        .unwrap();
+        record(
+            &conn,
+            &[Event::new(Action::Clear, "/ws/docs/a.md")],
+            Some("admin"),
+            None,
+        )
+        .unwrap();
+
+        assert!(conn.execute("DELETE FROM audit_log", []).is_err());
+        assert!(conn
+            .execute("UPDATE audit_log SET actor = 'Mallory'", [])
+            .is_err());
+
+        let all = list(&conn, &AuditQuery::default()).unwrap();
+        let actions: Vec<_> = all.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(actions, ["clear", "serve", "create"]);
+        assert_eq!(all[2].actor.as_deref(), Some("Alice"));
+        assert_eq!(all[2].annotation_id.as_deref(), Some("anno-1"));
+        assert_eq!(all[2].ip.as_deref(), Some("192.168.1.20"));
+        assert_eq!(all[0].role.as_deref(), Some("admin"));
+
+        let docs = list(
+            &conn,
+            &AuditQuery {
+                scope: Some(Path::new("/ws/docs")),
+                action: Some(Action::Create),
+                ..AuditQuery::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(docs.len(), 1);
+        let latest = AuditQuery {
+            limit: Some(1),
+            ..AuditQuery::default()
+        };
+        assert_eq!(list(&conn, &latest).unwrap()[0].action, "clear");
+        assert!("serve".parse::<Action>().is_ok());
+        assert!("view".parse::<Action>().is_err());
+
+        let fresh = Connection::open_in_memory().unwrap();
+        assert!(list(&fresh, &AuditQuery::default()).unwrap().is_empty());
+    }
+}
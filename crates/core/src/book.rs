@@ -0,0 +1,315 @@
+//! mdBook-style `SUMMARY.md` reader for the book navigation sidebar.
+//!
+//! A workspace whose root holds a nav file (`--nav-file`, by default
+//! `SUMMARY.md` or mdBook's `src/SUMMARY.md`) is read as a book: every
+//! document page lists its chapters and links the previous and next one, so
+//! the documents can be read in order. Parsing is line-based and forgiving,
+//! like mdBook's own format:
+//!
+//! ```markdown
+//! # Summary
+//!
+//! [Introduction](intro.md)
+//!
+//! # User Guide
+//!
+//! - [Installation](guide/install.md)
+//!     - [From source](guide/source.md)
+//! - [Draft chapter]()
+//!
+//! ---
+//!
+//! [Contributors](contributors.md)
+//! ```
+//!
+//! Links outside a list are unnumbered prefix/suffix chapters, list items
+//! are numbered chapters nested by indentation, later headings are part
+//! titles and `---` is a separator. Chapter paths are relative to the nav
+//! file and are kept relative to the workspace root.
+
+use std::path::Path;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Book {
+    /// Workspace-relative path of the nav file the book was read from.
+    pub(crate) nav_path: String,
+    pub(crate) items: Vec<BookItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum BookItem {
+    Chapter(Chapter),
+    PartTitle(String),
+    Separator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chapter {
+    pub(crate) title: String,
+    /// Workspace-relative document path; `None` for a draft chapter or a
+    /// link that leaves the workspace.
+    pub(crate) path: Option<String>,
+    /// `0` for prefix/suffix chapters, else the list nesting level from 1.
+    pub(crate) depth: usize,
+    /// Section number of a numbered chapter, e.g. `1.2.`.
+    pub(crate) number: Option<String>,
+}
+
+impl Book {
+    /// Read the first of `nav_files` present under `root`. A workspace
+    /// without one is not a book.
+    pub(crate) fn load(root: &Path, nav_files: &[String]) -> Option<Self> {
+        nav_files.iter().find_map(|name| {
+            let rel = name.trim().trim_start_matches("./").replace('\\', "/");
+            let path = root.join(&rel);
+            let content = std::fs::read_to_string(path).ok()?;
+            Some(Self::parse(&content, &rel))
+        })
+    }
+
+    /// Parse nav file `content` found at workspace-relative `nav_path`.
+    pub(crate) fn parse(content: &str, nav_path: &str) -> Self {
+        let base = match nav_path.rsplit_once('/') {
+            Some((dir, _)) => dir,
+            None => "",
+        };
+        let mut items = Vec::new();
+        let mut indents: Vec<usize> = Vec::new();
+        let mut numbers: Vec<usize> = Vec::new();
+        let mut seen_title = false;
+        let mut in_fence = false;
+        for line in content.lines() {
+            let trimmed = line.trim();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence || trimmed.is_empty() {
+                continue;
+            }
+            if let Some(heading) = heading_text(trimmed) {
+                // The first heading names the summary itself ("# Summary").
+                if seen_title || !items.is_empty() {
+                    items.push(BookItem::PartTitle(heading.to_string()));
+                }
+                seen_title = true;
+                indents.clear();
+                continue;
+            }
+            if is_separator(trimmed) {
+                items.push(BookItem::Separator);
+                indents.clear();
+                continue;
+            }
+            let indent = indent_width(line);
+            let Some(entry) = list_item(trimmed) else {
+                // A link outside a list is a prefix or suffix chapter.
+                if let Some((title, target)) = first_link(trimmed) {
+                    items.push(BookItem::Chapter(Chapter {
+                        title,
+                        path: resolve(base, &target),
+                        depth: 0,
+                        number: None,
+                    }));
+                }
+                indents.clear();
+                continue;
+            };
+            while indents.last().is_some_and(|&last| last > indent) {
+                indents.pop();
+            }
+            if indents.last() != Some(&indent) {
+                indents.push(indent);
+            }
+            let depth = indents.len();
+            numbers.truncate(depth);
+            numbers.resize(depth, 0);
+            numbers[depth - 1] += 1;
+            let (title, path) = match first_link(entry) {
+                Some((title, target)) => (title, resolve(base, &target)),
+                None => (unescape(entry), None),
+            };
+            items.push(BookItem::Chapter(Chapter {
+                title,
+                path,
+                depth,
+                number: Some(numbers.iter().map(|n| format!("{n}.")).collect()),
+            }));
+        }
+        Self {
+            nav_path: nav_path.to_string(),
+            items,
+        }
+    }
+
+    /// Chapters with a document, in reading order.
+    pub(crate) fn chapters(&self) -> impl Iterator<Item = &Chapter> {
+        self.items.iter().filter_map(|item| match item {
+            BookItem::Chapter(chapter) if chapter.path.is_some() => Some(chapter),
+            _ => None,
+        })
+    }
+
+    /// The chapters read before and after the document at `path`, when it is
+    /// part of the book.
+    pub(crate) fn neighbours(&self, path: &str) -> Option<(Option<&Chapter>, Option<&Chapter>)> {
+        let chapters: Vec<&Chapter> = self.chapters().collect();
+        let index = chapters
+            .iter()
+            .position(|chapter| chapter.path.as_deref() == Some(path))?;
+        Some((
+            index.checked_sub(1).map(|prev| chapters[prev]),
+            chapters.get(index + 1).copied(),
+        ))
+    }
+}
+
+fn heading_text(line: &str) -> Option<&str> {
+    let text = line.trim_start_matches('#');
+    (text.len() < line.len() && (text.is_empty() || text.starts_with([' ', '\t'])))
+        .then(|| text.trim().trim_end_matches('#').trim())
+}
+
+fn is_separator(line: &str) -> bool {
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|&mark| compact.chars().all(|c| c == mark))
+}
+
+/// Columns of leading whitespace, a tab counting as four.
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+/// The text after a `-`, `*` or `+` bullet.
+fn list_item(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix(['-', '*', '+'])?;
+    rest.starts_with([' ', '\t']).then(|| rest.trim())
+}
+
+/// Title and target of the first `[title](target)` link in `text`.
+fn first_link(text: &str) -> Option<(String, String)> {
+    let open = text.find('[')?;
+    let mut depth = 0usize;
+    let mut close = None;
+    let mut escaped = false;
+    for (i, c) in text[open..].char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    close = Some(open + i);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let close = close?;
+    let rest = text[close + 1..].strip_prefix('(')?;
+    let end = rest.find(')')?;
+    let target = rest[..end]
+        .split_whitespace()
+        .next()
+        .unwrap_or_default()
+        .trim_matches(|c| c == '<' || c == '>');
+    Some((unescape(&text[open + 1..close]), target.to_string()))
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.extend(chars.next()),
+            _ => out.push(c),
+        }
+    }
+    out.trim().to_string()
+}
+
+/// Workspace-relative path of link `target` written in a nav file under
+/// `base`; `None` for an empty (draft) or external target, or one that
+/// climbs out of the workspace.
+fn resolve(base: &str, target: &str) -> Option<String> {
+    let target = target.split(['#', '?']).next().unwrap_or_default();
+    if target.is_empty() || target.starts_with('/') || target.contains(':') {
+        return None;
+    }
+    let target = urlencoding::decode(target).ok()?;
+    let mut parts: Vec<&str> = base.split('/').filter(|part| !part.is_empty()).collect();
+    for part in target.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop()?;
+            }
+            part => parts.push(part),
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chapter(title: &str, path: Option<&str>, depth: usize, number: Option<&str>) -> BookItem {
+        BookItem::Chapter(Chapter {
+            title: title.to_string(),
+            path: path.map(str::to_string),
+            depth,
+            number: number.map(str::to_string),
+        })
+    }
+
+    #[test]
+    fn summary_parses_prefix_numbered_nested_draft_and_suffix_chapters() {
+        let summary = "# Summary\n\n\
+            [Introduction](./intro.md)\n\n\
+            # User Guide\n\n\
+            - [Installation](guide/install.md)\n\
+            \x20   - [From \\[source\\]](guide/from%20source.md#build)\n\
+            \x20   - [Upgrading](../../outside.md)\n\
+            - [Reading](guide/reading.md)\n\
+            - [Draft]()\n\
+            \n---\n\n\
+            [Contributors](contributors.md)\n";
+        let book = Book::parse(summary, "src/SUMMARY.md");
+        assert_eq!(
+            book.items,
+            vec![
+                chapter("Introduction", Some("src/intro.md"), 0, None),
+                BookItem::PartTitle("User Guide".into()),
+                chapter("Installation", Some("src/guide/install.md"), 1, Some("1.")),
+                chapter(
+                    "From [source]",
+                    Some("src/guide/from source.md"),
+                    2,
+                    Some("1.1.")
+                ),
+                chapter("Upgrading", None, 2, Some("1.2.")),
+                chapter("Reading", Some("src/guide/reading.md"), 1, Some("2.")),
+                chapter("Draft", None, 1, Some("3.")),
+                BookItem::Separator,
+                chapter("Contributors", Some("src/contributors.md"), 0, None),
+            ]
+        );
+
+        let (prev, next) = book.neighbours("src/guide/install.md").unwrap();
+        assert_eq!(prev.unwrap().title, "Introduction");
+        assert_eq!(next.unwrap().title, "From [source]");
+        let (prev, next) = book.neighbours("src/contributors.md").unwrap();
+        assert_eq!(prev.unwrap().title, "Reading");
+        assert!(next.is_none());
+        assert!(book.neighbours("src/unlisted.md").is_none());
+    }
+}
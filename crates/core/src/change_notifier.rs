@@ -0,0 +1,428 @@
+//! Outgoing notifications when watched documents change (`--notify-url`).
+//!
+//! The workspace watchers report each Markdown file they see created,
+//! modified or deleted. Changes that pass the filters (`--notify-path`
+//! globs, `--notify-event` kinds) are collected until [`NOTIFY_DEBOUNCE`] of
+//! quiet, so a burst of saves or a `git pull` becomes one message per
+//! workspace, and then POSTed to every target:
+//!
+//! * Slack incoming webhooks (`https://hooks.slack.com/…`, or any URL with a
+//!   `slack:` prefix) get `{"text": …}`;
+//! * ntfy topics (`https://ntfy.sh/…`, or a `ntfy:` prefix for a
+//!   self-hosted server) get the message as the body with a `Title`;
+//! * anything else gets a JSON document listing the changes.
+//!
+//! Delivery is best effort: a failed POST is logged and not retried.
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Quiet period that ends a batch of changes.
+const NOTIFY_DEBOUNCE: Duration = Duration::from_secs(3);
+
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Changed documents named in a text message before the rest are counted.
+const MAX_LISTED_CHANGES: usize = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+}
+
+impl ChangeKind {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "created" | "create" => Some(Self::Created),
+            "modified" | "modify" => Some(Self::Modified),
+            "deleted" | "delete" | "removed" => Some(Self::Deleted),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Modified => "modified",
+            Self::Deleted => "deleted",
+        }
+    }
+
+    /// What a path amounts to after `self` and then `next` happened to it
+    /// within one batch; `None` when it came and went.
+    fn then(self, next: Self) -> Option<Self> {
+        match (self, next) {
+            (Self::Created, Self::Deleted) => None,
+            (Self::Created, _) => Some(Self::Created),
+            (Self::Deleted, Self::Created) => Some(Self::Modified),
+            (_, next) => Some(next),
+        }
+    }
+}
+
+/// One document a watcher saw change.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DocumentChange {
+    pub(crate) workspace_id: String,
+    /// Alias or directory name the workspace is shown under.
+    pub(crate) workspace: String,
+    /// Workspace-relative path with `/` separators.
+    pub(crate) path: String,
+    pub(crate) kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    Slack(Url),
+    Ntfy(Url),
+    Generic(Url),
+}
+
+impl Target {
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (kind, raw) = match value.split_once(':') {
+            Some((kind @ ("slack" | "ntfy"), rest)) => (Some(kind), rest),
+            _ => (None, value),
+        };
+        let url = Url::parse(raw)
+            .ok()
+            .filter(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+            .ok_or_else(|| format!("invalid --notify-url '{value}': not an http(s) URL"))?;
+        Ok(match kind {
+            Some("slack") => Self::Slack(url),
+            Some(_) => Self::Ntfy(url),
+            None => match url.host_str() {
+                Some("hooks.slack.com") => Self::Slack(url),
+                Some("ntfy.sh") => Self::Ntfy(url),
+                _ => Self::Generic(url),
+            },
+        })
+    }
+}
+
+/// Which changes are worth a notification.
+#[derive(Debug, Clone)]
+struct ChangeFilter {
+    paths: Option<GlobSet>,
+    kinds: Vec<ChangeKind>,
+}
+
+impl ChangeFilter {
+    fn new(paths: &[String], events: &[String]) -> Result<Self, String> {
+        let mut builder = GlobSetBuilder::new();
+        let mut any = false;
+        for raw in paths {
+            let pattern = raw.trim().trim_start_matches('/');
+            if pattern.is_empty() {
+                continue;
+            }
+            // Like `--search-exclude`: a bare name matches at any depth.
+            let anchored = if pattern.contains('/') {
+                pattern.to_string()
+            } else {
+                format!("**/{pattern}")
+            };
+            builder.add(
+                GlobBuilder::new(&anchored)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| format!("invalid --notify-path pattern '{raw}': {e}"))?,
+            );
+            any = true;
+        }
+        let paths = if any {
+            Some(
+                builder
+                    .build()
+                    .map_err(|e| format!("invalid --notify-path patterns: {e}"))?,
+            )
+        } else {
+            None
+        };
+        let kinds = events
+            .iter()
+            .map(|event| {
+                ChangeKind::parse(event).ok_or_else(|| {
+                    format!(
+                        "invalid --notify-event '{event}': expected created, modified or deleted"
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { paths, kinds })
+    }
+
+    fn wants(&self, path: &str, kind: ChangeKind) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&kind))
+            && self.paths.as_ref().is_none_or(|set| set.is_match(path))
+    }
+}
+
+/// Filters document changes and hands them to the delivery task.
+pub(crate) struct ChangeNotifier {
+    filter: ChangeFilter,
+    tx: mpsc::UnboundedSender<DocumentChange>,
+}
+
+impl ChangeNotifier {
+    /// A notifier for the `--notify-*` options, delivering from a task on the
+    /// current runtime; `None` when no `--notify-url` is given.
+    pub(crate) fn start(
+        urls: &[String],
+        paths: &[String],
+        events: &[String],
+    ) -> Result<Option<Arc<Self>>, String> {
+        let targets = urls
+            .iter()
+            .filter(|url| !url.trim().is_empty())
+            .map(|url| Target::parse(url))
+            .collect::<Result<Vec<_>, _>>()?;
+        if targets.is_empty() {
+            return Ok(None);
+        }
+        let filter = ChangeFilter::new(paths, events)?;
+        Ok(Some(Arc::new(Self::spawn(
+            targets,
+            filter,
+            NOTIFY_DEBOUNCE,
+        ))))
+    }
+
+    fn spawn(targets: Vec<Target>, filter: ChangeFilter, debounce: Duration) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let client = reqwest::Client::builder()
+            .timeout(NOTIFY_TIMEOUT)
+            .build()
+            .expect("failed to build the notification HTTP client");
+        tokio::spawn(deliver(rx, client, targets, debounce));
+        Self { filter, tx }
+    }
+
+    /// Queue `change` when it passes the filters. Never blocks, so the
+    /// watcher threads can call it directly.
+    pub(crate) fn queue(&self, change: DocumentChange) {
+        if self.filter.wants(&change.path, change.kind) {
+            let _ = self.tx.send(change);
+        }
+    }
+}
+
+/// The changes of one workspace in a batch, in path order.
+struct WorkspaceBatch {
+    workspace: String,
+    changes: BTreeMap<String, ChangeKind>,
+}
+
+async fn deliver(
+    mut rx: mpsc::UnboundedReceiver<DocumentChange>,
+    client: reqwest::Client,
+    targets: Vec<Target>,
+    debounce: Duration,
+) {
+    while let Some(first) = rx.recv().await {
+        let mut batches: BTreeMap<String, WorkspaceBatch> = BTreeMap::new();
+        let mut next = Some(first);
+        while let Some(change) = next {
+            let batch = batches
+                .entry(change.workspace_id)
+                .or_insert_with(|| WorkspaceBatch {
+                    workspace: change.workspace,
+                    changes: BTreeMap::new(),
+                });
+            match batch.changes.remove(&change.path) {
+                Some(previous) => {
+                    if let Some(kind) = previous.then(change.kind) {
+                        batch.changes.insert(change.path, kind);
+                    }
+                }
+                None => {
+                    batch.changes.insert(change.path, change.kind);
+                }
+            }
+            next = tokio::time::timeout(debounce, rx.recv())
+                .await
+                .ok()
+                .flatten();
+        }
+        for (workspace_id, batch) in batches {
+            if batch.changes.is_empty() {
+                continue;
+            }
+            for target in &targets {
+                let request = build_request(&client, target, &workspace_id, &batch);
+                let result = request.send().await.and_then(|r| r.error_for_status());
+                if let Err(e) = result {
+                    tracing::warn!("document change notification failed: {}", e.without_url());
+                }
+            }
+        }
+    }
+}
+
+fn build_request(
+    client: &reqwest::Client,
+    target: &Target,
+    workspace_id: &str,
+    batch: &WorkspaceBatch,
+) -> reqwest::RequestBuilder {
+    match target {
+        Target::Slack(url) => client.post(url.clone()).json(&serde_json::json!({
+            "text": message_text(batch),
+        })),
+        Target::Ntfy(url) => client
+            .post(url.clone())
+            .header("Title", message_title(batch))
+            .header("Tags", "memo")
+            .body(message_lines(batch)),
+        Target::Generic(url) => client.post(url.clone()).json(&serde_json::json!({
+            "event": "documents_changed",
+            "workspace_id": workspace_id,
+            "workspace": batch.workspace,
+            "changes": batch
+                .changes
+                .iter()
+                .map(|(path, kind)| serde_json::json!({ "path": path, "change": kind }))
+                .collect::<Vec<_>>(),
+        })),
+    }
+}
+
+fn message_title(batch: &WorkspaceBatch) -> String {
+    match batch.changes.len() {
+        1 => format!("1 document changed in {}", batch.workspace),
+        count => format!("{count} documents changed in {}", batch.workspace),
+    }
+}
+
+fn message_lines(batch: &WorkspaceBatch) -> String {
+    let mut lines: Vec<String> = batch
+        .changes
+        .iter()
+        .take(MAX_LISTED_CHANGES)
+        .map(|(path, kind)| format!("{} {path}", kind.as_str()))
+        .collect();
+    if batch.changes.len() > MAX_LISTED_CHANGES {
+        lines.push(format!(
+            "… and {} more",
+            batch.changes.len() - MAX_LISTED_CHANGES
+        ));
+    }
+    lines.join("\n")
+}
+
+fn message_text(batch: &WorkspaceBatch) -> String {
+    format!("{}\n{}", message_title(batch), message_lines(batch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::post, Router};
+
+    #[test]
+    fn targets_and_filters_parse_from_the_options() {
+        assert!(matches!(
+            Target::parse("https://hooks.slack.com/services/T/B/X").unwrap(),
+            Target::Slack(_)
+        ));
+        assert!(matches!(
+            Target::parse("https://ntfy.sh/docs").unwrap(),
+            Target::Ntfy(_)
+        ));
+        assert!(matches!(
+            Target::parse("ntfy:https://push.example.com/docs").unwrap(),
+            Target::Ntfy(_)
+        ));
+        assert!(matches!(
+            Target::parse("http://127.0.0.1:9000/hook").unwrap(),
+            Target::Generic(_)
+        ));
+        assert!(Target::parse("ftp://example.com/hook").is_err());
+
+        let filter = ChangeFilter::new(
+            &["docs/**".into(), "CHANGELOG.md".into()],
+            &["modified".into()],
+        )
+        .unwrap();
+        assert!(filter.wants("docs/guide/intro.md", ChangeKind::Modified));
+        assert!(filter.wants("sub/CHANGELOG.md", ChangeKind::Modified));
+        assert!(!filter.wants("notes.md", ChangeKind::Modified));
+        assert!(!filter.wants("docs/guide/intro.md", ChangeKind::Created));
+        assert!(ChangeFilter::new(&[], &[])
+            .unwrap()
+            .wants("any.md", ChangeKind::Deleted));
+        assert!(ChangeFilter::new(&[], &["renamed".into()]).is_err());
+
+        assert_eq!(
+            ChangeKind::Created.then(ChangeKind::Modified),
+            Some(ChangeKind::Created)
+        );
+        assert_eq!(ChangeKind::Created.then(ChangeKind::Deleted), None);
+        assert_eq!(
+            ChangeKind::Deleted.then(ChangeKind::Created),
+            Some(ChangeKind::Modified)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_burst_of_changes_is_posted_as_one_message_per_workspace() {
+        let (seen_tx, mut seen_rx) = mpsc::unbounded_channel::<serde_json::Value>();
+        let app = Router::new().route(
+            "/hook",
+            post(move |axum::Json(body): axum::Json<serde_json::Value>| {
+                let seen_tx = seen_tx.clone();
+                async move {
+                    let _ = seen_tx.send(body);
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let _ = axum::serve(listener, app).await;
+        });
+
+        let notifier = ChangeNotifier::spawn(
+            vec![Target::parse(&format!("http://{addr}/hook")).unwrap()],
+            ChangeFilter::new(&["*.md".into()], &[]).unwrap(),
+            Duration::from_millis(100),
+        );
+        let change = |path: &str, kind| DocumentChange {
+            workspace_id: "ws1".into(),
+            workspace: "docs".into(),
+            path: path.into(),
+            kind,
+        };
+        notifier.queue(change("guide.md", ChangeKind::Modified));
+        notifier.queue(change("guide.md", ChangeKind::Modified));
+        notifier.queue(change("draft.md", ChangeKind::Created));
+        notifier.queue(change("draft.md", ChangeKind::Deleted));
+        notifier.queue(change("new.md", ChangeKind::Created));
+        notifier.queue(change("image.png", ChangeKind::Created));
+
+        let body = tokio::time::timeout(Duration::from_secs(5), seen_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "event": "documents_changed",
+                "workspace_id": "ws1",
+                "workspace": "docs",
+                "changes": [
+                    { "path": "guide.md", "change": "modified" },
+                    { "path": "new.md", "change": "created" },
+                ],
+            })
+        );
+    }
+}
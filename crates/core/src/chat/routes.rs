@@ -769,7 +769,6 @@ mod tests {
     use crate::workspace::{WorkspaceConfig, WorkspaceFlags, WorkspaceRegistry};
     use axum::body::{to_bytes, Body};
     use axum::http::{Request, StatusCode};
-    use rusqlite::Connection;
     use std::sync::Mutex;
     use tempfile::TempDir;
     use tera::Tera;
@@ -803,14 +802,14 @@ mod tests {
         });
 
         let db_tmp = tempfile::NamedTempFile::new().expect("sqlite tmpfile");
-        let conn = Connection::open(db_tmp.path()).expect("open db");
-        ChatStorage::init(&conn).expect("init schema");
-        let db = Arc::new(Mutex::new(conn));
+        let db = crate::db::Database::open(db_tmp.path()).expect("open db");
         let storage = ChatStorage::new(db.clone());
 
         let state = AppState {
             theme: Arc::new("dark".into()),
+            custom_theme: false,
             tera: Arc::new(Tera::default()),
+            base_path: "".into(),
             db: Some(db),
             workspace_registry: registry,
             management_token: Arc::new("token".into()),
@@ -821,12 +820,35 @@ mod tests {
             i18n_lang: Arc::new("zh".into()),
             shortcuts_json: Arc::new("null".into()),
             styles_css: Arc::new(String::new()),
+            user_stylesheets: Arc::new(Vec::new()),
+            user_scripts: Arc::new(Vec::new()),
             default_chat_mode: Arc::new("in_page".into()),
             collaborator_access_code_hash: Arc::new(String::new()),
             access_secret: Arc::new("test-salt".into()),
             access_attempts: Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
             markdown_diff_cache: Arc::new(Mutex::new(crate::server::MarkdownDiffCache::default())),
+            archive_index: Arc::new(Mutex::new(crate::archive::IndexCache::default())),
+            render_hooks: Arc::new([]),
             print_collapsed_content: false,
+            render_cache: true,
+            search_max_limit: crate::search::MAX_SEARCH_LIMIT,
+            require_name: false,
+            annotation_token: None,
+            readme_names: Arc::new(vec!["README.md".into(), "index.md".into()]),
+            nav_files: Arc::new(vec!["SUMMARY.md".into()]),
+            listing_page_size: crate::server::DEFAULT_LISTING_PAGE_SIZE,
+            paginate_bytes: None,
+            share_base: Arc::default(),
+            remote_markdown: Arc::new(crate::remote_markdown::RemoteMarkdown::new(
+                Default::default(),
+            )),
+            attachments_dir: None,
+            remote_store: None,
+            presence: Arc::default(),
+            editor: Arc::default(),
+            pandoc: None,
+            share_token: None,
+            audit: false,
             #[cfg(debug_assertions)]
             dev_reload_tx: Arc::new(broadcast::channel::<()>(1).0),
         };
@@ -26,16 +26,15 @@
 //! );
 //! ```
 //!
-//! All per-request methods are `async` and offload the synchronous rusqlite
-//! work to `tokio::task::spawn_blocking`, so the tokio worker pool never sits
-//! on `Mutex<Connection>` across `.await` boundaries. Only [`ChatStorage::init`]
-//! stays synchronous: it runs once at boot against an owned `Connection`
-//! before the value is wrapped in `Arc<Mutex<…>>`.
+//! All per-request methods are `async`: reads run on a pooled reader and
+//! writes on the database's writer task (see [`crate::db`]). Only
+//! [`ChatStorage::init`] stays synchronous: it runs once at boot against the
+//! writer connection before the [`Database`] is built around it.
 
 use crate::chat::message::{ContentBlock, Role};
+use crate::db::Database;
 use rusqlite::{params, Connection};
 use serde::{Deserialize, Serialize};
-use std::sync::{Arc, Mutex};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -64,10 +63,9 @@ pub(crate) enum StorageError {
     NotFound,
     #[error("serde error: {0}")]
     Serde(String),
-    /// Blocking worker panicked or was cancelled — treated as a hard failure
-    /// because it means the connection's invariants may no longer hold.
-    #[error("blocking task join error: {0}")]
-    Join(String),
+    /// The database job panicked or its transaction did not commit.
+    #[error("database error: {0}")]
+    Db(#[from] crate::db::DbError),
 }
 
 impl From<rusqlite::Error> for StorageError {
@@ -80,15 +78,10 @@ impl From<serde_json::Error> for StorageError {
         Self::Serde(e.to_string())
     }
 }
-impl From<tokio::task::JoinError> for StorageError {
-    fn from(e: tokio::task::JoinError) -> Self {
-        Self::Join(e.to_string())
-    }
-}
 
 #[derive(Clone)]
 pub(crate) struct ChatStorage {
-    db: Arc<Mutex<Connection>>,
+    db: Database,
 }
 
 fn now_ms() -> i64 {
@@ -118,49 +111,32 @@ fn role_from_str(s: &str) -> Result<Role, StorageError> {
 }
 
 impl ChatStorage {
-    pub(crate) fn new(db: Arc<Mutex<Connection>>) -> Self {
+    pub(crate) fn new(db: Database) -> Self {
         Self { db }
     }
 
-    /// Run `f` on the blocking pool with an exclusive `&Connection`. Keeps
-    /// the lock-and-unlock boilerplate out of every method body and ensures
-    /// the `MutexGuard` never crosses an `.await`.
-    async fn with_conn<F, R>(&self, f: F) -> Result<R, StorageError>
+    /// Run the read-only `f` on a pooled reader.
+    async fn read<F, R>(&self, f: F) -> Result<R, StorageError>
     where
         F: FnOnce(&Connection) -> Result<R, StorageError> + Send + 'static,
         R: Send + 'static,
     {
-        let db = self.db.clone();
-        tokio::task::spawn_blocking(move || {
-            let conn = db
-                .lock()
-                .map_err(|e| StorageError::Sqlite(format!("mutex poisoned: {e}")))?;
-            f(&conn)
-        })
-        .await?
+        self.db.read(f).await?
     }
 
-    /// Like [`with_conn`] but hands `f` an exclusive `&mut Connection`, which
-    /// `rusqlite::Connection::transaction*` requires.
-    async fn with_conn_mut<F, R>(&self, f: F) -> Result<R, StorageError>
+    /// Run `f` on the writer task, resolving once it has committed.
+    async fn write<F, R>(&self, f: F) -> Result<R, StorageError>
     where
-        F: FnOnce(&mut Connection) -> Result<R, StorageError> + Send + 'static,
+        F: FnOnce(&Connection) -> Result<R, StorageError> + Send + 'static,
         R: Send + 'static,
     {
-        let db = self.db.clone();
-        tokio::task::spawn_blocking(move || {
-            let mut conn = db
-                .lock()
-                .map_err(|e| StorageError::Sqlite(format!("mutex poisoned: {e}")))?;
-            f(&mut conn)
-        })
-        .await?
+        self.db.write(|conn| f(conn)).await?
     }
 
     /// Idempotent table creation — invoked once at server startup. Runs
     /// synchronously because boot owns the `Connection` outright; only the
-    /// per-request paths (which share the connection through `Arc<Mutex<…>>`)
-    /// need to hop onto the blocking pool.
+    /// per-request paths (which share it through a [`Database`]) go through
+    /// the reader pool and the writer task.
     pub(crate) fn init(conn: &Connection) -> Result<(), StorageError> {
         // Enable FK enforcement for cascade-delete on chat_messages.
         conn.execute_batch("PRAGMA foreign_keys = ON;")?;
@@ -201,7 +177,7 @@ impl ChatStorage {
     ) -> Result<Thread, StorageError> {
         let workspace_id = workspace_id.to_string();
         let title = title.to_string();
-        self.with_conn(move |conn| {
+        self.write(move |conn| {
             let id = uuid::Uuid::new_v4().to_string();
             let now = now_ms();
             conn.execute(
@@ -222,7 +198,7 @@ impl ChatStorage {
 
     pub(crate) async fn get_thread(&self, thread_id: &str) -> Result<Thread, StorageError> {
         let thread_id = thread_id.to_string();
-        self.with_conn(move |conn| {
+        self.read(move |conn| {
             let mut stmt = conn.prepare(
                 "SELECT id, workspace_id, title, created_at, updated_at
                    FROM chat_threads
@@ -245,10 +221,10 @@ impl ChatStorage {
 
     pub(crate) async fn delete_thread(&self, thread_id: &str) -> Result<(), StorageError> {
         let thread_id = thread_id.to_string();
-        self.with_conn(move |conn| {
-            // Ensure FK cascade is on for this connection — `init` set it, but
-            // `PRAGMA foreign_keys` is per-connection and cheap to re-assert.
-            conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        self.write(move |conn| {
+            // The cascade to chat_messages relies on `init` having enabled
+            // `PRAGMA foreign_keys` on the writer connection; the pragma
+            // cannot change inside the writer's transaction.
             let n = conn.execute("DELETE FROM chat_threads WHERE id = ?1", params![thread_id])?;
             if n == 0 {
                 return Err(StorageError::NotFound);
@@ -263,7 +239,7 @@ impl ChatStorage {
         thread_id: &str,
     ) -> Result<Vec<StoredMessage>, StorageError> {
         let thread_id = thread_id.to_string();
-        self.with_conn(move |conn| {
+        self.read(move |conn| {
             let mut stmt = conn.prepare_cached(
                 "SELECT thread_id, seq, role, content_json, created_at
                    FROM chat_messages
@@ -307,12 +283,13 @@ impl ChatStorage {
         let content_json = serde_json::to_string(content)?;
         let role_s = role_to_str(role);
 
-        self.with_conn_mut(move |conn| {
+        self.write(move |conn| {
             let now = now_ms();
 
-            // IMMEDIATE so SELECT MAX(seq)+INSERT is atomic against concurrent
-            // appends in other transactions on the same DB.
-            let tx = conn.transaction_with_behavior(rusqlite::TransactionBehavior::Immediate)?;
+            // The writer runs one job at a time, so SELECT MAX(seq)+INSERT
+            // cannot interleave with another append; the savepoint keeps the
+            // statements all-or-nothing.
+            let tx = crate::db::savepoint(conn)?;
 
             // Confirm the thread exists — otherwise we'd insert orphan rows
             // that would only be caught at FK check time, and we want a clean
@@ -365,7 +342,7 @@ impl ChatStorage {
         workspace_id: &str,
     ) -> Result<Vec<ThreadSummary>, StorageError> {
         let workspace_id = workspace_id.to_string();
-        self.with_conn(move |conn| {
+        self.read(move |conn| {
             let mut stmt = conn.prepare(
                 "SELECT t.id, t.title, t.created_at, t.updated_at,
                         COALESCE(COUNT(m.seq), 0) AS message_count
@@ -408,14 +385,12 @@ pub struct ThreadSummary {
 mod tests {
     use super::*;
     use crate::chat::message::ContentBlock;
-    use std::sync::{Arc, Mutex};
     use tempfile::NamedTempFile;
 
     fn fresh_storage() -> (ChatStorage, NamedTempFile) {
         let tmp = NamedTempFile::new().expect("tempfile");
-        let conn = Connection::open(tmp.path()).expect("open db");
-        ChatStorage::init(&conn).expect("init");
-        (ChatStorage::new(Arc::new(Mutex::new(conn))), tmp)
+        let db = Database::open(tmp.path()).expect("open db");
+        (ChatStorage::new(db), tmp)
     }
 
     /// The `role` column stores `role_to_str` while the wire format uses
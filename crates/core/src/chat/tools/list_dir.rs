@@ -8,7 +8,7 @@
 //!   - mark dirs with trailing `/`, mark files with size suffix
 //!   - return as a newline-separated list so the model can grep it later.
 
-use super::{default_walker, Tool, ToolContext, ToolError, MAX_TOOL_OUTPUT_BYTES};
+use super::{Tool, ToolContext, ToolError, MAX_TOOL_OUTPUT_BYTES};
 use async_trait::async_trait;
 use serde::Deserialize;
 
@@ -66,10 +66,15 @@ impl Tool for ListDirTool {
             )));
         }
 
-        // Walk only depth==1 children; default_walker rooted at `abs` already
+        // Walk only depth==1 children; a walker rooted at `abs` already
         // applies gitignore semantics (it consults parent .gitignore files via
-        // `parents(true)`).
-        let walker = default_walker(&abs).max_depth(Some(1)).build();
+        // `parents(true)`), and the workspace root bounds followed symlinks.
+        let boundary = ctx.directory_root().unwrap_or(&abs);
+        let walker = ctx
+            .walk_policy()
+            .bounded_walker(&abs, boundary)
+            .max_depth(Some(1))
+            .build();
         let mut dirs: Vec<String> = Vec::new();
         let mut files: Vec<(String, u64)> = Vec::new();
 
@@ -16,7 +16,7 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::mpsc;
 
-pub(crate) use crate::fswalk::{default_walker, path_to_forward_slash};
+pub(crate) use crate::fswalk::path_to_forward_slash;
 
 /// Per-request tool scope. Filesystem access is delegated to `WorkspaceFs` so
 /// tools cannot accidentally turn a serving directory into broader authority.
@@ -78,6 +78,10 @@ impl ToolContext {
         self.workspace_fs.directory_root()
     }
 
+    pub(crate) fn walk_policy(&self) -> &crate::fswalk::WalkPolicy {
+        self.workspace_fs.policy()
+    }
+
     pub(crate) fn route_for_path(&self, path: &Path) -> Option<String> {
         self.workspace_fs.route_for_path(path)
     }
@@ -19,10 +19,10 @@
 pub mod proto;
 pub mod transport;
 
-pub use proto::{ControlRequest, ControlResponse};
+pub use proto::{ControlRequest, ControlResponse, EditorSession};
 pub use transport::{
     bind, dispatch, serve, AdminBootstrapCodeFn, AdminBootstrapFn, ControlContext, ControlServer,
-    ControlSocketName,
+    ControlSocketName, EditorSessionFn,
 };
 
 use crate::data_maintenance::{DataCleanupResult, DataCleanupStats};
@@ -203,6 +203,7 @@ impl RunningServer {
                 flags,
                 collaborator_access_code_hash: collaborator_access_code_hash.to_string(),
                 single_file: None,
+                files: Vec::new(),
                 alias: String::new(),
             })
             .await?
@@ -230,6 +231,7 @@ impl RunningServer {
                 flags,
                 collaborator_access_code_hash: collaborator_access_code_hash.to_string(),
                 single_file: Some(single_file.to_string()),
+                files: Vec::new(),
                 alias: String::new(),
             })
             .await?
@@ -263,6 +265,48 @@ impl RunningServer {
         single_file: Option<&str>,
         collaborator_access_code_hash: Option<&str>,
         alias: Option<&str>,
+    ) -> Result<String, ControlError> {
+        self.add_or_update(
+            path,
+            flags,
+            single_file,
+            &[],
+            collaborator_access_code_hash,
+            alias,
+        )
+        .await
+    }
+
+    /// Register a curated file-set workspace over `files` (relative to `path`,
+    /// listed in this order), or refresh the one already registered for the
+    /// same set. Used by `markon a.md b.md` and GUI replay of such entries.
+    pub async fn add_or_update_file_set(
+        &self,
+        path: &str,
+        files: &[String],
+        flags: WorkspaceFlags,
+        collaborator_access_code_hash: Option<&str>,
+        alias: Option<&str>,
+    ) -> Result<String, ControlError> {
+        self.add_or_update(
+            path,
+            flags,
+            None,
+            files,
+            collaborator_access_code_hash,
+            alias,
+        )
+        .await
+    }
+
+    async fn add_or_update(
+        &self,
+        path: &str,
+        flags: WorkspaceFlags,
+        single_file: Option<&str>,
+        files: &[String],
+        collaborator_access_code_hash: Option<&str>,
+        alias: Option<&str>,
     ) -> Result<String, ControlError> {
         // The server stores canonical roots. Normalize before matching so macOS
         // `/var` -> `/private/var`, symlinks, and `..` cannot make an existing
@@ -270,11 +314,9 @@ impl RunningServer {
         let canonical_path = expand_and_canonicalize(path)
             .map(|value| value.to_string_lossy().into_owned())
             .unwrap_or_else(|_| path.to_string());
-        let existing = self
-            .list_workspaces()
-            .await?
-            .into_iter()
-            .find(|w| w.path == canonical_path && w.single_file.as_deref() == single_file);
+        let existing = self.list_workspaces().await?.into_iter().find(|w| {
+            w.path == canonical_path && w.single_file.as_deref() == single_file && w.files == files
+        });
         if let Some(existing) = existing {
             // Mirror the embedded registry's `add`, which refreshes the flags of
             // an already-registered identity: re-adding the same path applies the
@@ -296,6 +338,7 @@ impl RunningServer {
                     .unwrap_or("")
                     .to_string(),
                 single_file: single_file.map(str::to_string),
+                files: files.to_vec(),
                 alias: alias.unwrap_or("").to_string(),
             })
             .await?
@@ -408,6 +451,17 @@ impl RunningServer {
         }
     }
 
+    /// Editor-plugin connection details for workspace `id`.
+    pub async fn editor_session(&self, id: &str) -> Result<EditorSession, ControlError> {
+        match self
+            .call(ControlRequest::EditorSession { id: id.to_string() })
+            .await?
+        {
+            ControlResponse::EditorSession(session) => Ok(session),
+            _ => Err(ControlError::Unexpected),
+        }
+    }
+
     /// Ask the running server to exit.
     pub async fn shutdown(&self) -> Result<(), ControlError> {
         match self.call(ControlRequest::Shutdown).await? {
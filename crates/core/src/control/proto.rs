@@ -31,6 +31,8 @@ pub enum ControlRequest {
     /// `name`; `None` for an ordinary directory workspace. It mirrors
     /// [`crate::workspace::WorkspaceConfig::single_file`] so a front-end can
     /// register either kind over the socket exactly like the in-process add.
+    /// `files` likewise mirrors [`crate::workspace::WorkspaceConfig::files`]
+    /// for a curated file-set workspace.
     AddWorkspace {
         path: String,
         flags: WorkspaceFlags,
@@ -38,6 +40,8 @@ pub enum ControlRequest {
         #[serde(default)]
         single_file: Option<String>,
         #[serde(default)]
+        files: Vec<String>,
+        #[serde(default)]
         alias: String,
     },
     /// Replace a workspace's feature flags wholesale.
@@ -63,10 +67,28 @@ pub enum ControlRequest {
     /// Mint a one-time administrator pairing code and return the manual-entry
     /// URL. This preserves the non-browser-launching `markon admin code` flow.
     AdminBootstrapCode { redirect: String },
+    /// Connection details an editor plugin uses to drive workspace `id`'s
+    /// preview (see `markon editor`).
+    EditorSession { id: String },
     /// Ask the running server to exit.
     Shutdown,
 }
 
+/// Where and how an editor plugin talks to a workspace: the editor-plugin
+/// endpoints and the capability token they require.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EditorSession {
+    pub workspace_id: String,
+    /// JSON-RPC endpoint (`POST`).
+    pub rpc_url: String,
+    /// Server-Sent Events stream.
+    pub events_url: String,
+    /// Browser page following the events.
+    pub preview_url: String,
+    /// Sent as `X-Markon-Token`, or `?token=` where headers can't be set.
+    pub token: String,
+}
+
 /// The single response to a [`ControlRequest`]. Handlers that don't produce data
 /// answer [`ControlResponse::Ok`]; failures answer [`ControlResponse::Err`] with
 /// a human-readable message that the client maps back to a `ControlError`.
@@ -81,6 +103,8 @@ pub enum ControlResponse {
     /// Manual administrator bootstrap details (answer to
     /// `AdminBootstrapCode`).
     AdminCode { url: String, code: String },
+    /// Answer to `EditorSession`.
+    EditorSession(EditorSession),
     /// Persistent-data cleanup preview.
     DataCleanupStats(DataCleanupStats),
     /// Result of an explicit persistent-data cleanup.
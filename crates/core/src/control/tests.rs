@@ -6,7 +6,6 @@ use super::*;
 use crate::control::transport::{bind, ControlContext};
 use crate::workspace::{WorkspaceFlags, WorkspaceRegistry};
 use std::sync::Arc;
-use std::sync::Mutex;
 use tokio::sync::{mpsc, oneshot};
 
 /// Bind a control server on a unique temp socket, spawn its accept loop, and
@@ -40,6 +39,15 @@ async fn harness() -> Harness {
             "123456".to_string(),
         ))
     });
+    let editor: EditorSessionFn = Arc::new(|id: &str| {
+        Ok(EditorSession {
+            workspace_id: id.to_string(),
+            rpc_url: format!("http://127.0.0.1:7000/api/editor/{id}/rpc"),
+            events_url: format!("http://127.0.0.1:7000/api/editor/{id}/events"),
+            preview_url: format!("http://127.0.0.1:7000/api/editor/{id}/preview"),
+            token: "editor-token".to_string(),
+        })
+    });
     let conn = rusqlite::Connection::open_in_memory().unwrap();
     conn.execute_batch(
         "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL);
@@ -50,10 +58,11 @@ async fn harness() -> Harness {
 
     let ctx = ControlContext {
         registry: registry.clone(),
-        db: Some(Arc::new(Mutex::new(conn))),
+        db: Some(crate::db::Database::from_connection(conn)),
         shutdown: Some(shutdown_tx),
         admin_bootstrap: Some(admin),
         admin_bootstrap_code: Some(admin_code),
+        editor_session: Some(editor),
     };
 
     // Bind synchronously (awaited) so the socket exists before any connect.
@@ -208,6 +217,9 @@ async fn control_round_trips_every_method() {
     let (manual_url, code) = h.client.admin_bootstrap_code("/workspace/").await.unwrap();
     assert_eq!(manual_url, "http://127.0.0.1:7000/_/admin");
     assert_eq!(code, "123456");
+    let session = h.client.editor_session(&id).await.unwrap();
+    assert_eq!(session.workspace_id, id);
+    assert_eq!(session.token, "editor-token");
 
     // Persistent-data maintenance uses the same privileged control channel.
     let stats = h.client.data_cleanup_stats().await.unwrap();
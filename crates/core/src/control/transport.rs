@@ -24,11 +24,9 @@ use interprocess::local_socket::{ListenerOptions, Name};
 use tokio::sync::mpsc;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
-use super::proto::{ControlRequest, ControlResponse};
+use super::proto::{ControlRequest, ControlResponse, EditorSession};
 use crate::data_maintenance::{cleanup_orphaned_data, data_cleanup_stats};
 use crate::workspace::{expand_and_canonicalize, WorkspaceConfig, WorkspaceRegistry};
-use rusqlite::Connection;
-use std::sync::Mutex;
 
 /// Maximum time a single accepted connection may take to deliver its one framed
 /// request. A client that connects and then stalls (or dribbles a partial length
@@ -129,7 +127,7 @@ pub struct ControlContext {
     pub registry: Arc<WorkspaceRegistry>,
     /// The running service's persistent store. Present in production; optional
     /// for registry-only transport tests and minimal embedders.
-    pub db: Option<Arc<Mutex<Connection>>>,
+    pub db: Option<crate::db::Database>,
     /// Signal channel the running server watches to exit (mirrors the HTTP
     /// `/api/shutdown` handler). `None` → `Shutdown` is unsupported.
     pub shutdown: Option<mpsc::Sender<()>>,
@@ -140,6 +138,9 @@ pub struct ControlContext {
     /// Mint a manual-entry admin URL and pairing code. `None` means the older
     /// URL-only bootstrap flow is the only supported mode.
     pub admin_bootstrap_code: Option<AdminBootstrapCodeFn>,
+    /// Issue an editor-plugin session for a workspace id. `None` →
+    /// `EditorSession` is unsupported.
+    pub editor_session: Option<EditorSessionFn>,
 }
 
 /// Given a redirect path, return the full one-time admin bootstrap URL (or an
@@ -149,6 +150,10 @@ pub type AdminBootstrapFn = Arc<dyn Fn(&str) -> Result<String, String> + Send +
 /// Given a redirect path, return `(manual_entry_url, one_time_code)`.
 pub type AdminBootstrapCodeFn = Arc<dyn Fn(&str) -> Result<(String, String), String> + Send + Sync>;
 
+/// Given a workspace id, return its editor-plugin session (or an error
+/// message).
+pub type EditorSessionFn = Arc<dyn Fn(&str) -> Result<EditorSession, String> + Send + Sync>;
+
 impl ControlContext {
     /// A context backed only by a registry — `Shutdown` and `AdminBootstrap`
     /// answer `Err`.
@@ -159,6 +164,7 @@ impl ControlContext {
             shutdown: None,
             admin_bootstrap: None,
             admin_bootstrap_code: None,
+            editor_session: None,
         }
     }
 }
@@ -174,6 +180,7 @@ pub fn dispatch(req: ControlRequest, ctx: &ControlContext) -> ControlResponse {
             flags,
             collaborator_access_code_hash,
             single_file,
+            files,
             alias,
         } => {
             let path = match expand_and_canonicalize(&path) {
@@ -195,12 +202,31 @@ pub fn dispatch(req: ControlRequest, ctx: &ControlContext) -> ControlResponse {
                     );
                 }
             }
-            // Same call the in-process add makes: `single_file` selects a
-            // temporary single-file workspace vs. an ordinary directory one.
+            // Files of a set may sit in subdirectories, but never above the
+            // served root.
+            if files.iter().any(|file| {
+                let file = std::path::Path::new(file);
+                file.as_os_str().is_empty()
+                    || !file
+                        .components()
+                        .all(|c| matches!(c, std::path::Component::Normal(_)))
+            }) {
+                return ControlResponse::Err(
+                    "files must be workspace-relative file paths".to_string(),
+                );
+            }
+            if single_file.is_some() && !files.is_empty() {
+                return ControlResponse::Err(
+                    "single_file and files are mutually exclusive".to_string(),
+                );
+            }
+            // Same call the in-process add makes: `single_file` or `files`
+            // selects a temporary scoped workspace vs. an ordinary directory one.
             let id = ctx.registry.add(WorkspaceConfig {
                 path,
                 flags,
                 single_file,
+                files,
                 collaborator_access_code_hash,
                 alias,
             });
@@ -231,8 +257,7 @@ pub fn dispatch(req: ControlRequest, ctx: &ControlContext) -> ControlResponse {
             let Some(db) = &ctx.db else {
                 return ControlResponse::Err("persistent data store unavailable".to_string());
             };
-            let conn = db.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
-            match data_cleanup_stats(&conn, &ctx.registry) {
+            match db.read_blocking(|conn| data_cleanup_stats(conn, &ctx.registry)) {
                 Ok(stats) => ControlResponse::DataCleanupStats(stats),
                 Err(error) => ControlResponse::Err(error),
             }
@@ -241,8 +266,8 @@ pub fn dispatch(req: ControlRequest, ctx: &ControlContext) -> ControlResponse {
             let Some(db) = &ctx.db else {
                 return ControlResponse::Err("persistent data store unavailable".to_string());
             };
-            let mut conn = db.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
-            match cleanup_orphaned_data(&mut conn, &ctx.registry) {
+            // VACUUM cannot run inside the writer's batch transaction.
+            match db.exclusive_blocking(|conn| cleanup_orphaned_data(conn, &ctx.registry)) {
                 Ok(result) => ControlResponse::DataCleanupResult(result),
                 Err(error) => ControlResponse::Err(error),
             }
@@ -275,6 +300,13 @@ pub fn dispatch(req: ControlRequest, ctx: &ControlContext) -> ControlResponse {
             },
             None => ControlResponse::Err("admin code bootstrap unsupported".to_string()),
         },
+        ControlRequest::EditorSession { id } => match &ctx.editor_session {
+            Some(issue) => match issue(&id) {
+                Ok(session) => ControlResponse::EditorSession(session),
+                Err(e) => ControlResponse::Err(e),
+            },
+            None => ControlResponse::Err("editor sessions unsupported".to_string()),
+        },
         ControlRequest::Shutdown => match &ctx.shutdown {
             Some(tx) => {
                 let _ = tx.try_send(());
@@ -443,6 +475,14 @@ async fn handle_connection(stream: Stream, ctx: &ControlContext) -> io::Result<(
     };
     let frame = frame?;
     let response = match serde_json::from_slice::<ControlRequest>(&frame) {
+        // The data-cleanup requests scan and rewrite the database; keep that
+        // off the async worker so HTTP/WebSocket handlers keep running.
+        Ok(req @ (ControlRequest::DataCleanupStats | ControlRequest::CleanupOrphanedData)) => {
+            let ctx = ctx.clone();
+            tokio::task::spawn_blocking(move || dispatch(req, &ctx))
+                .await
+                .unwrap_or_else(|e| ControlResponse::Err(format!("request failed: {e}")))
+        }
         Ok(req) => dispatch(req, ctx),
         Err(e) => ControlResponse::Err(format!("malformed request: {e}")),
     };
@@ -30,6 +30,8 @@ pub struct DaemonWorkspace {
     #[serde(default)]
     pub single_file: Option<String>,
     #[serde(default)]
+    pub files: Vec<String>,
+    #[serde(default)]
     pub collaborator_access_code_hash: String,
     #[serde(default)]
     pub alias: String,
@@ -42,8 +44,10 @@ impl From<DaemonWorkspace> for WorkspaceInit {
             flags: w.flags,
             initial_path: w.initial_path,
             single_file: w.single_file,
+            files: w.files,
             collaborator_access_code_hash: w.collaborator_access_code_hash,
             alias: w.alias,
+            compare: None,
         }
     }
 }
@@ -65,12 +69,16 @@ pub struct DaemonConfig {
     #[serde(default = "default_theme")]
     pub theme: String,
     #[serde(default)]
+    pub theme_css: Option<PathBuf>,
+    #[serde(default)]
     pub qr: Option<String>,
     #[serde(default)]
     pub open_browser: Option<String>,
     #[serde(default)]
     pub db_path: Option<String>,
     #[serde(default)]
+    pub db_url: Option<String>,
+    #[serde(default)]
     pub salt: Option<String>,
     #[serde(default)]
     pub workspaces: Vec<DaemonWorkspace>,
@@ -81,11 +89,82 @@ pub struct DaemonConfig {
     #[serde(default)]
     pub styles_css: Option<String>,
     #[serde(default)]
+    pub user_css: Vec<String>,
+    #[serde(default)]
+    pub user_js: Vec<String>,
+    #[serde(default)]
     pub default_chat_mode: String,
     #[serde(default)]
     pub collaborator_access_code_hash: String,
     #[serde(default)]
     pub print_collapsed_content: bool,
+    #[serde(default)]
+    pub content_security_policy: Option<String>,
+    #[serde(default)]
+    pub frame_options: Option<String>,
+    #[serde(default)]
+    pub no_cache: bool,
+    #[serde(default)]
+    pub search_exclude: Vec<String>,
+    #[serde(default)]
+    pub search_lang: Option<String>,
+    #[serde(default)]
+    pub search_max_limit: Option<usize>,
+    #[serde(default)]
+    pub require_name: bool,
+    #[serde(default)]
+    pub annotation_token: Option<String>,
+    #[serde(default)]
+    pub readme_names: Vec<String>,
+    #[serde(default)]
+    pub nav_files: Vec<String>,
+    #[serde(default)]
+    pub markdown_extensions: Vec<String>,
+    #[serde(default)]
+    pub show_hidden: bool,
+    #[serde(default)]
+    pub no_follow_symlinks: bool,
+    #[serde(default)]
+    pub allow_paths: Vec<String>,
+    #[serde(default)]
+    pub deny_paths: Vec<String>,
+    #[serde(default)]
+    pub listing_page_size: Option<usize>,
+    #[serde(default)]
+    pub search_writer_memory: Option<usize>,
+    #[serde(default)]
+    pub search_max_file_size: Option<u64>,
+    #[serde(default)]
+    pub search_max_index_size: Option<u64>,
+    #[serde(default)]
+    pub paginate_kib: Option<usize>,
+    #[serde(default)]
+    pub allow_remote: Vec<String>,
+    #[serde(default)]
+    pub notify_urls: Vec<String>,
+    #[serde(default)]
+    pub notify_paths: Vec<String>,
+    #[serde(default)]
+    pub notify_events: Vec<String>,
+    #[serde(default)]
+    pub pandoc: bool,
+    #[serde(default)]
+    pub snapshots: bool,
+    #[serde(default)]
+    pub audit: bool,
+    #[serde(default)]
+    pub link_schemes: Vec<String>,
+    #[serde(default)]
+    pub plugins_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub share_token: bool,
+    /// `tracing` filter directives for the daemon's log (`markon -v`/`-q`).
+    /// `None` falls back to `RUST_LOG`, then `info`.
+    #[serde(default)]
+    pub log_filter: Option<String>,
+    /// Log file replacing `~/.markon/logs/markond.log` (`markon --log-file`).
+    #[serde(default)]
+    pub log_file: Option<PathBuf>,
 }
 
 fn default_theme() -> String {
@@ -114,10 +193,14 @@ impl ServerConfig {
             trusted_hosts: cfg.trusted_hosts,
             port: cfg.port,
             theme: cfg.theme,
+            theme_css: cfg.theme_css,
             qr: cfg.qr,
+            qr_out: None,
             open_browser: cfg.open_browser,
+            open_anchor: None,
             shared_annotation,
             db_path: cfg.db_path,
+            db_url: cfg.db_url,
             salt: cfg.salt,
             initial_workspaces,
             bound_listener: None,
@@ -127,9 +210,42 @@ impl ServerConfig {
             language: cfg.language,
             shortcuts_json: cfg.shortcuts_json,
             styles_css: cfg.styles_css,
+            user_css: cfg.user_css,
+            user_js: cfg.user_js,
             default_chat_mode: cfg.default_chat_mode,
             collaborator_access_code_hash: cfg.collaborator_access_code_hash,
             print_collapsed_content: cfg.print_collapsed_content,
+            content_security_policy: cfg.content_security_policy,
+            frame_options: cfg.frame_options,
+            no_cache: cfg.no_cache,
+            search_exclude: cfg.search_exclude,
+            search_lang: cfg.search_lang,
+            search_max_limit: cfg.search_max_limit,
+            require_name: cfg.require_name,
+            annotation_token: cfg.annotation_token,
+            readme_names: cfg.readme_names,
+            nav_files: cfg.nav_files,
+            markdown_extensions: cfg.markdown_extensions,
+            show_hidden: cfg.show_hidden,
+            no_follow_symlinks: cfg.no_follow_symlinks,
+            allow_paths: cfg.allow_paths,
+            deny_paths: cfg.deny_paths,
+            listing_page_size: cfg.listing_page_size,
+            search_writer_memory: cfg.search_writer_memory,
+            search_max_file_size: cfg.search_max_file_size,
+            search_max_index_size: cfg.search_max_index_size,
+            paginate_kib: cfg.paginate_kib,
+            allow_remote: cfg.allow_remote,
+            notify_urls: cfg.notify_urls,
+            notify_paths: cfg.notify_paths,
+            notify_events: cfg.notify_events,
+            pandoc: cfg.pandoc,
+            snapshots: cfg.snapshots,
+            audit: cfg.audit,
+            link_schemes: cfg.link_schemes,
+            plugins_dir: cfg.plugins_dir,
+            share_token: cfg.share_token,
+            base_path: String::new(),
         }
     }
 }
@@ -325,10 +441,12 @@ mod tests {
             advertised_host: "192.168.1.5".to_string(),
             trusted_hosts: vec!["md.example.com".to_string()],
             port: 6419,
-            theme: "auto".to_string(),
+            theme: "sepia".to_string(),
+            theme_css: Some(PathBuf::from("/srv/paper.css")),
             qr: Some("https://md.example.com".to_string()),
             open_browser: None,
             db_path: Some("/tmp/x.sqlite".to_string()),
+            db_url: Some("postgres://markon@db.internal/markon".to_string()),
             salt: Some("markon:6419".to_string()),
             workspaces: vec![DaemonWorkspace {
                 path: PathBuf::from("/tmp/docs"),
@@ -339,28 +457,71 @@ mod tests {
                 },
                 initial_path: Some("readme.md".to_string()),
                 single_file: None,
+                files: Vec::new(),
                 collaborator_access_code_hash: "deadbeef".to_string(),
                 alias: "docs".to_string(),
             }],
             language: Some("en".to_string()),
             shortcuts_json: None,
             styles_css: None,
+            user_css: vec!["/srv/brand.css".to_string()],
+            user_js: vec!["/srv/keys.js".to_string()],
             default_chat_mode: "in_page".to_string(),
             collaborator_access_code_hash: "cafef00d".to_string(),
             print_collapsed_content: true,
+            content_security_policy: Some("default-src 'self'".to_string()),
+            frame_options: Some("deny".to_string()),
+            no_cache: true,
+            search_exclude: vec!["node_modules".to_string()],
+            search_lang: Some("en".to_string()),
+            search_max_limit: Some(500),
+            require_name: true,
+            annotation_token: Some("present".to_string()),
+            readme_names: vec!["INDEX.md".to_string()],
+            nav_files: vec!["book/SUMMARY.md".to_string()],
+            markdown_extensions: vec!["txt".to_string()],
+            show_hidden: true,
+            no_follow_symlinks: true,
+            allow_paths: vec!["docs/**".to_string()],
+            deny_paths: vec!["docs/private/**".to_string()],
+            listing_page_size: Some(50),
+            search_writer_memory: Some(64),
+            search_max_file_size: Some(2),
+            search_max_index_size: Some(512),
+            paginate_kib: Some(512),
+            allow_remote: vec!["raw.githubusercontent.com".to_string()],
+            notify_urls: vec!["https://ntfy.sh/docs".to_string()],
+            notify_paths: vec!["docs/**".to_string()],
+            notify_events: vec!["deleted".to_string()],
+            pandoc: true,
+            snapshots: true,
+            audit: true,
+            link_schemes: vec!["jira=https://jira.example.com/browse/".to_string()],
+            plugins_dir: Some(PathBuf::from("/srv/plugins")),
+            share_token: true,
+            log_filter: Some("debug".to_string()),
+            log_file: Some(PathBuf::from("/tmp/markond.log")),
         };
 
         let json = serde_json::to_string(&cfg).unwrap();
         let back: DaemonConfig = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.log_filter.as_deref(), Some("debug"));
+        assert_eq!(back.log_file, Some(PathBuf::from("/tmp/markond.log")));
         let server = ServerConfig::from_daemon_config(back);
 
         assert_eq!(server.host, "127.0.0.1");
         assert_eq!(server.port, 6419);
+        assert_eq!(server.theme, "sepia");
+        assert_eq!(server.theme_css, Some(PathBuf::from("/srv/paper.css")));
         assert_eq!(server.advertised_host, "192.168.1.5");
         assert_eq!(server.trusted_hosts, vec!["md.example.com".to_string()]);
         assert_eq!(server.qr.as_deref(), Some("https://md.example.com"));
         assert!(server.open_browser.is_none());
         assert_eq!(server.salt.as_deref(), Some("markon:6419"));
+        assert_eq!(
+            server.db_url.as_deref(),
+            Some("postgres://markon@db.internal/markon")
+        );
         assert!(server.shared_annotation, "derived from workspace flags");
         assert_eq!(server.initial_workspaces.len(), 1);
         let ws = &server.initial_workspaces[0];
@@ -369,6 +530,42 @@ mod tests {
         assert_eq!(ws.alias, "docs");
         assert_eq!(server.collaborator_access_code_hash, "cafef00d");
         assert!(server.print_collapsed_content);
+        assert_eq!(
+            server.content_security_policy.as_deref(),
+            Some("default-src 'self'")
+        );
+        assert_eq!(server.frame_options.as_deref(), Some("deny"));
+        assert!(server.no_cache);
+        assert_eq!(server.search_exclude, vec!["node_modules".to_string()]);
+        assert_eq!(server.search_lang.as_deref(), Some("en"));
+        assert_eq!(server.search_max_limit, Some(500));
+        assert!(server.require_name);
+        assert_eq!(server.annotation_token.as_deref(), Some("present"));
+        assert_eq!(server.readme_names, vec!["INDEX.md".to_string()]);
+        assert_eq!(server.nav_files, vec!["book/SUMMARY.md".to_string()]);
+        assert_eq!(server.markdown_extensions, vec!["txt".to_string()]);
+        assert!(server.show_hidden);
+        assert!(server.no_follow_symlinks);
+        assert_eq!(server.allow_paths, ["docs/**"]);
+        assert_eq!(server.deny_paths, ["docs/private/**"]);
+        assert_eq!(server.listing_page_size, Some(50));
+        assert_eq!(server.search_max_index_size, Some(512));
+        assert_eq!(server.paginate_kib, Some(512));
+        assert_eq!(server.allow_remote, ["raw.githubusercontent.com"]);
+        assert_eq!(server.notify_urls, ["https://ntfy.sh/docs"]);
+        assert_eq!(server.notify_paths, ["docs/**"]);
+        assert_eq!(server.notify_events, ["deleted"]);
+        assert!(server.pandoc);
+        assert!(server.snapshots);
+        assert!(server.audit);
+        assert_eq!(
+            server.link_schemes,
+            ["jira=https://jira.example.com/browse/"]
+        );
+        assert_eq!(server.plugins_dir, Some(PathBuf::from("/srv/plugins")));
+        assert!(server.share_token);
+        assert_eq!(server.user_css, vec!["/srv/brand.css".to_string()]);
+        assert_eq!(server.user_js, vec!["/srv/keys.js".to_string()]);
         // Runtime handles are never reconstructed from the declarative config.
         assert!(server.registry.is_none());
         assert!(server.bound_listener.is_none());
@@ -67,19 +67,24 @@ fn database_bytes(conn: &Connection) -> u64 {
     pages.saturating_mul(page_size)
 }
 
-fn workspace_file(info: &WorkspaceInfo) -> Option<PathBuf> {
+/// The files a scoped (single-file or file-set) workspace exposes; empty for
+/// a directory workspace.
+fn workspace_files(info: &WorkspaceInfo) -> Vec<PathBuf> {
     info.single_file
-        .as_deref()
+        .iter()
+        .chain(&info.files)
         .map(|name| Path::new(&info.path).join(name))
+        .collect()
 }
 
 fn file_is_active(path: &str, workspaces: &[WorkspaceInfo]) -> bool {
     let candidate = Path::new(path);
     workspaces.iter().any(|workspace| {
-        if let Some(single_file) = workspace_file(workspace) {
-            candidate == single_file
-        } else {
+        let files = workspace_files(workspace);
+        if files.is_empty() {
             candidate.starts_with(Path::new(&workspace.path))
+        } else {
+            files.iter().any(|file| candidate == file)
         }
     })
 }
@@ -122,7 +127,16 @@ fn collect(
     stats.orphaned_annotation_files = orphaned_annotation_files.len();
 
     {
-        let mut stmt = conn.prepare("SELECT file_path, length(state) FROM viewed_state")?;
+        // Private per-browser viewed state counts toward the same files.
+        let sql = if crate::annotations::has_table(conn, "client_viewed_state")? {
+            "SELECT file_path, SUM(length(state)) FROM (
+                SELECT file_path, state FROM viewed_state
+                UNION ALL SELECT file_path, state FROM client_viewed_state
+            ) GROUP BY file_path"
+        } else {
+            "SELECT file_path, length(state) FROM viewed_state"
+        };
+        let mut stmt = conn.prepare(sql)?;
         let rows = stmt.query_map([], |row| {
             Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1).unwrap_or(0)))
         })?;
@@ -201,6 +215,8 @@ pub fn cleanup_orphaned_data(
         });
     }
 
+    let client_viewed = crate::annotations::has_table(conn, "client_viewed_state")
+        .map_err(|error| error.to_string())?;
     let tx = conn.transaction().map_err(|error| error.to_string())?;
     for id in &keys.annotation_ids {
         tx.execute("DELETE FROM annotations WHERE id = ?1", params![id])
@@ -212,6 +228,13 @@ pub fn cleanup_orphaned_data(
             params![path],
         )
         .map_err(|error| error.to_string())?;
+        if client_viewed {
+            tx.execute(
+                "DELETE FROM client_viewed_state WHERE file_path = ?1",
+                params![path],
+            )
+            .map_err(|error| error.to_string())?;
+        }
     }
     // Delete messages explicitly instead of relying on a connection-local
     // foreign_keys pragma, then remove their owning threads.
@@ -270,6 +293,7 @@ mod tests {
             path: active.path().to_path_buf(),
             flags: WorkspaceFlags::default(),
             single_file: None,
+            files: Vec::new(),
             collaborator_access_code_hash: String::new(),
             alias: String::new(),
         });
@@ -332,6 +356,7 @@ mod tests {
             path: dir.path().to_path_buf(),
             flags: WorkspaceFlags::default(),
             single_file: Some("kept.md".into()),
+            files: Vec::new(),
             collaborator_access_code_hash: String::new(),
             alias: String::new(),
         });
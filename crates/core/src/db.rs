@@ -0,0 +1,469 @@
+//! The server's SQLite database: opening and migrating it, and running
+//! queries off the async workers.
+//!
+//! A [`Database`] owns one writer connection and a small pool of read-only
+//! ones. Reads run on the blocking pool against whichever reader is free, so
+//! a page listing progress badges never waits behind a save. Every write is
+//! handed to a single background thread that owns the writer: it takes the
+//! writes that queued up while it was busy and commits them together in one
+//! transaction, so a burst of annotation and viewed-state saves from a busy
+//! shared session costs one commit instead of contending for the lock one
+//! by one. A write resolves only once its transaction has committed, so a
+//! read issued after it sees its rows.
+
+use rusqlite::{Connection, OpenFlags};
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard, PoisonError};
+
+/// Read-only connections opened beside the writer.
+const READERS: usize = 4;
+
+/// Most writes committed in one transaction.
+const MAX_BATCH: usize = 64;
+
+/// Open (creating if needed) the server database at `path` and bring its
+/// schema up to date.
+pub(crate) fn open(path: &Path) -> Result<Connection, String> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create database directory: {e}"))?;
+    }
+    let conn = Connection::open(path).map_err(|e| format!("Failed to open database: {e}"))?;
+    // WAL: the single-server invariant is not enforced (the GUI can start a
+    // second server on a different/auto port while a CLI daemon holds the same
+    // db — it never consults the server lock), so two processes can open this
+    // file concurrently. WAL gives multi-reader / single-writer concurrency
+    // across processes instead of the rollback journal's whole-file lock. It is
+    // persisted in the db header (set-once; re-asserting on each open is cheap)
+    // and adds `-wal`/`-shm` sidecar files — a plain-copy backup must include
+    // them or checkpoint first.
+    let journal_mode: String = conn
+        .query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))
+        .unwrap_or_default();
+    if !journal_mode.eq_ignore_ascii_case("wal") {
+        tracing::warn!(
+            got = %journal_mode,
+            "could not enable WAL journal mode; concurrent multi-process access may hit 'database is locked'"
+        );
+    }
+    // Wait up to 5s for a competing writer to release the lock instead of
+    // failing immediately with SQLITE_BUSY — complements WAL under write bursts.
+    conn.pragma_update(None, "busy_timeout", 5000)
+        .map_err(|e| format!("Failed to set busy_timeout: {e}"))?;
+    crate::annotations::create_table(&conn)
+        .map_err(|e| format!("Failed to create annotations table: {e}"))?;
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS viewed_state (
+            file_path TEXT PRIMARY KEY,
+            state TEXT NOT NULL,
+            updated_by TEXT,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            sections TEXT
+        )",
+        [],
+    )
+    .map_err(|e| format!("Failed to create viewed_state table: {e}"))?;
+    // Databases from before viewed-state attribution lack `updated_by`.
+    if conn
+        .prepare("SELECT updated_by FROM viewed_state LIMIT 0")
+        .is_err()
+    {
+        conn.execute("ALTER TABLE viewed_state ADD COLUMN updated_by TEXT", [])
+            .map_err(|e| format!("Failed to migrate viewed_state table: {e}"))?;
+    }
+    // Nor do databases from before per-section content hashes.
+    if conn
+        .prepare("SELECT sections FROM viewed_state LIMIT 0")
+        .is_err()
+    {
+        conn.execute("ALTER TABLE viewed_state ADD COLUMN sections TEXT", [])
+            .map_err(|e| format!("Failed to migrate viewed_state table: {e}"))?;
+    }
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS client_viewed_state (
+            client_id TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            state TEXT NOT NULL,
+            sections TEXT,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (client_id, file_path)
+        )",
+        [],
+    )
+    .map_err(|e| format!("Failed to create client_viewed_state table: {e}"))?;
+    crate::recent::create_tables(&conn)
+        .map_err(|e| format!("Failed to create recent files tables: {e}"))?;
+    crate::snapshots::create_tables(&conn)
+        .map_err(|e| format!("Failed to create snapshot tables: {e}"))?;
+    crate::audit::create_tables(&conn)
+        .map_err(|e| format!("Failed to create audit tables: {e}"))?;
+    crate::chat::storage::ChatStorage::init(&conn)
+        .map_err(|e| format!("Failed to create chat tables: {e}"))?;
+    Ok(conn)
+}
+
+/// Open a read-only connection to the database the writer at `path` created.
+fn open_reader(path: &Path) -> Result<Connection, String> {
+    let conn = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI,
+    )
+    .map_err(|e| format!("Failed to open database reader: {e}"))?;
+    conn.pragma_update(None, "busy_timeout", 5000)
+        .map_err(|e| format!("Failed to set busy_timeout: {e}"))?;
+    Ok(conn)
+}
+
+/// Why a database job produced no result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DbError {
+    /// The job panicked, or the writer stopped before running it.
+    Aborted,
+    /// The transaction the write ran in was rolled back instead of committed.
+    Commit(String),
+}
+
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Aborted => f.write_str("database job aborted"),
+            Self::Commit(e) => write!(f, "database commit failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DbError {}
+
+/// Hands a write's result to its caller once the batch has been committed.
+type Delivery = Box<dyn FnOnce(Result<(), DbError>) + Send>;
+type Job = Box<dyn FnOnce(&mut Connection) -> Delivery + Send>;
+
+/// The server database: a writer task and a pool of readers. Cloning is cheap
+/// and shares both.
+#[derive(Clone)]
+pub struct Database {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    writer: Arc<Mutex<Connection>>,
+    readers: Vec<Arc<Mutex<Connection>>>,
+    next_reader: AtomicUsize,
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Database {
+    /// Open (creating and migrating if needed) the database at `path` with a
+    /// pool of read-only connections beside the writer.
+    pub(crate) fn open(path: &Path) -> Result<Self, String> {
+        let writer = open(path)?;
+        let readers = (0..READERS)
+            .map(|_| open_reader(path).map(|conn| Arc::new(Mutex::new(conn))))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self::spawn(Arc::new(Mutex::new(writer)), readers))
+    }
+
+    /// A database served by `conn` alone, reads included — for an in-memory
+    /// database, which other connections cannot see.
+    #[cfg(test)]
+    pub(crate) fn from_connection(conn: Connection) -> Self {
+        let writer = Arc::new(Mutex::new(conn));
+        Self::spawn(writer.clone(), vec![writer])
+    }
+
+    fn spawn(writer: Arc<Mutex<Connection>>, readers: Vec<Arc<Mutex<Connection>>>) -> Self {
+        let (jobs, queue) = mpsc::channel();
+        let conn = writer.clone();
+        std::thread::Builder::new()
+            .name("markon-db-writer".into())
+            .spawn(move || run_writer(&conn, &queue))
+            .expect("failed to spawn the database writer thread");
+        Self {
+            inner: Arc::new(Inner {
+                writer,
+                readers,
+                next_reader: AtomicUsize::new(0),
+                jobs,
+            }),
+        }
+    }
+
+    /// Run the read-only `f` on a pooled reader, on the blocking pool.
+    pub(crate) async fn read<F, R>(&self, f: F) -> Result<R, DbError>
+    where
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let db = self.clone();
+        tokio::task::spawn_blocking(move || f(&db.reader()))
+            .await
+            .map_err(|_| DbError::Aborted)
+    }
+
+    /// Like [`Self::read`], on the calling thread. For code already off the
+    /// async workers.
+    pub(crate) fn read_blocking<R>(&self, f: impl FnOnce(&Connection) -> R) -> R {
+        f(&self.reader())
+    }
+
+    /// The first idle reader, or the next one in turn when all are busy.
+    fn reader(&self) -> MutexGuard<'_, Connection> {
+        let readers = &self.inner.readers;
+        let start = self.inner.next_reader.fetch_add(1, Ordering::Relaxed);
+        (0..readers.len())
+            .find_map(|i| readers[(start + i) % readers.len()].try_lock().ok())
+            .unwrap_or_else(|| {
+                readers[start % readers.len()]
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+            })
+    }
+
+    /// Queue `f` on the writer and wait for the transaction it joined to
+    /// commit. Run anything that must be all-or-nothing inside a
+    /// [`savepoint`]: a failed statement does not undo the job's earlier ones.
+    pub(crate) async fn write<F, R>(&self, f: F) -> Result<R, DbError>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        self.submit(f, move |result| {
+            let _ = tx.send(result);
+        })?;
+        rx.await.unwrap_or(Err(DbError::Aborted))
+    }
+
+    /// Like [`Self::write`], blocking the calling thread. For the watcher
+    /// threads and other code off the async workers.
+    pub(crate) fn write_blocking<F, R>(&self, f: F) -> Result<R, DbError>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = mpsc::sync_channel(1);
+        self.submit(f, move |result| {
+            let _ = tx.send(result);
+        })?;
+        rx.recv().unwrap_or(Err(DbError::Aborted))
+    }
+
+    fn submit<F, R>(
+        &self,
+        f: F,
+        reply: impl FnOnce(Result<R, DbError>) + Send + 'static,
+    ) -> Result<(), DbError>
+    where
+        F: FnOnce(&mut Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let job: Job = Box::new(move |conn| {
+            let value = f(conn);
+            Box::new(move |committed| reply(committed.map(|()| value)))
+        });
+        self.inner.jobs.send(job).map_err(|_| DbError::Aborted)
+    }
+
+    /// Run `f` on the writer connection outside any transaction, between
+    /// batches — for work such as `VACUUM` that cannot run inside one.
+    pub(crate) fn exclusive_blocking<R>(&self, f: impl FnOnce(&mut Connection) -> R) -> R {
+        let mut conn = self
+            .inner
+            .writer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        f(&mut conn)
+    }
+}
+
+/// The writer thread: commit queued jobs in batches until every
+/// [`Database`] handle is gone.
+fn run_writer(conn: &Mutex<Connection>, queue: &mpsc::Receiver<Job>) {
+    while let Ok(first) = queue.recv() {
+        let mut batch = vec![first];
+        while batch.len() < MAX_BATCH {
+            match queue.try_recv() {
+                Ok(job) => batch.push(job),
+                Err(_) => break,
+            }
+        }
+        let mut conn = conn.lock().unwrap_or_else(PoisonError::into_inner);
+        commit_batch(&mut conn, batch);
+    }
+}
+
+fn begin(conn: &Connection) -> bool {
+    match conn.execute_batch("BEGIN IMMEDIATE") {
+        Ok(()) => true,
+        Err(e) => {
+            // Each job still runs inside its own savepoint, which commits
+            // on its own outside a transaction.
+            tracing::warn!("could not open a write transaction, committing one by one: {e}");
+            false
+        }
+    }
+}
+
+fn commit_batch(conn: &mut Connection, batch: Vec<Job>) {
+    let mut in_transaction = begin(conn);
+    let mut pending: Vec<Delivery> = Vec::with_capacity(batch.len());
+    for job in batch {
+        let _ = conn.execute_batch("SAVEPOINT markon_job");
+        match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(&mut *conn))) {
+            Ok(delivery) => {
+                let _ = conn.execute_batch("RELEASE markon_job");
+                pending.push(delivery);
+            }
+            // The job's reply channel was dropped with it; its caller sees
+            // `Aborted`. Its statements are undone, the batch's others kept.
+            Err(_) => {
+                tracing::error!("database write panicked; rolled back");
+                let _ = conn.execute_batch("ROLLBACK TO markon_job; RELEASE markon_job");
+            }
+        }
+        if in_transaction && conn.is_autocommit() {
+            // Some errors (a full disk, I/O) make SQLite roll back the whole
+            // transaction, taking the batch's earlier writes with it.
+            for delivery in pending.drain(..) {
+                delivery(Err(DbError::Commit("transaction rolled back".into())));
+            }
+            in_transaction = begin(conn);
+        }
+    }
+    let committed = if in_transaction {
+        conn.execute_batch("COMMIT").map_err(|e| {
+            let _ = conn.execute_batch("ROLLBACK");
+            DbError::Commit(e.to_string())
+        })
+    } else {
+        Ok(())
+    };
+    for delivery in pending {
+        delivery(committed.clone());
+    }
+}
+
+/// An all-or-nothing scope on a shared `&Connection`, like
+/// [`Connection::unchecked_transaction`] but nestable: writes run inside the
+/// writer's batch transaction, where a second `BEGIN` fails. Outside a
+/// transaction it opens and commits its own. Dropping it uncommitted rolls
+/// its statements back.
+pub(crate) struct Savepoint<'conn> {
+    conn: &'conn Connection,
+    released: bool,
+}
+
+pub(crate) fn savepoint(conn: &Connection) -> rusqlite::Result<Savepoint<'_>> {
+    conn.execute_batch("SAVEPOINT markon_scope")?;
+    Ok(Savepoint {
+        conn,
+        released: false,
+    })
+}
+
+impl Savepoint<'_> {
+    pub(crate) fn commit(mut self) -> rusqlite::Result<()> {
+        self.released = true;
+        self.conn.execute_batch("RELEASE markon_scope")
+    }
+}
+
+impl Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    fn drop(&mut self) {
+        if !self.released {
+            let _ = self
+                .conn
+                .execute_batch("ROLLBACK TO markon_scope; RELEASE markon_scope");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_enables_wal_and_migrates_viewed_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/annotation.sqlite");
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(
+                "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP);",
+            )
+            .unwrap();
+
+        let conn = open(&path).unwrap();
+        let mode: String = conn
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+        assert!(conn
+            .prepare("SELECT updated_by, sections FROM viewed_state LIMIT 0")
+            .is_ok());
+        assert!(conn
+            .prepare("SELECT seq FROM annotation_history LIMIT 0")
+            .is_ok());
+    }
+
+    #[tokio::test]
+    async fn writes_commit_in_batches_and_reads_see_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open(&dir.path().join("db.sqlite")).unwrap();
+        db.write(|conn| conn.execute_batch("CREATE TABLE notes (id INTEGER)"))
+            .await
+            .unwrap()
+            .unwrap();
+
+        let writes = (0..50).map(|id| {
+            let db = db.clone();
+            async move {
+                db.write(move |conn| conn.execute("INSERT INTO notes VALUES (?1)", [id]))
+                    .await
+            }
+        });
+        for inserted in futures::future::join_all(writes).await {
+            assert_eq!(inserted, Ok(Ok(1)));
+        }
+        let count = |conn: &Connection| -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM notes", [], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(db.read(count).await, Ok(50));
+
+        // Readers are read-only; a panicking write is undone on its own.
+        assert!(db
+            .read(|conn| conn.execute("INSERT INTO notes VALUES (0)", []))
+            .await
+            .unwrap()
+            .is_err());
+        let panicked = db
+            .write(|conn| {
+                conn.execute("INSERT INTO notes VALUES (0)", []).unwrap();
+                panic!("boom")
+            })
+            .await;
+        assert_eq!(panicked, Err::<(), _>(DbError::Aborted));
+        // An uncommitted savepoint rolls back inside the batch transaction.
+        db.write_blocking(|conn| {
+            let scope = savepoint(conn).unwrap();
+            scope.execute("INSERT INTO notes VALUES (0)", []).unwrap();
+        })
+        .unwrap();
+        assert_eq!(db.read_blocking(count), 50);
+        assert_eq!(db.exclusive_blocking(|conn| count(conn)), 50);
+    }
+}
@@ -0,0 +1,472 @@
+//! Editor-plugin protocol: markon as the preview backend of an editor
+//! (Neovim, VS Code, …) rather than only a standalone server.
+//!
+//! A plugin asks `markon editor [PATH]` for the connection details of the
+//! workspace holding its files — the endpoints below and a token scoped to
+//! that workspace — then:
+//!
+//! - `POST /api/editor/{workspace_id}/rpc` — JSON-RPC 2.0 calls: `render` a
+//!   buffer and get its HTML and TOC back, push a buffer with
+//!   `buffer/update`, report the cursor with `buffer/scroll`, and
+//!   `buffer/close` it. A request without an `id` is a notification and is
+//!   answered `204 No Content`.
+//! - `GET /api/editor/{workspace_id}/events` — Server-Sent Events: `render`,
+//!   `scroll` and `close`, optionally for one `?buffer=`. A new subscriber
+//!   first gets the current render of that buffer (or the last updated one).
+//! - `GET /api/editor/{workspace_id}/preview` — a browser page following the
+//!   events, for plugins without a webview of their own.
+//!
+//! The token travels as `X-Markon-Token`, or as `?token=` where a client
+//! can't set headers (`EventSource`, a browser tab). The full method and
+//! event reference lives in `docs/advanced/editor-protocol.md`.
+
+use crate::markdown::TocItem;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+
+/// Bumped when a method or event changes incompatibly.
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+/// Methods answered by the RPC endpoint, as listed by `initialize`.
+pub(crate) const METHODS: [&str; 5] = [
+    "initialize",
+    "render",
+    "buffer/update",
+    "buffer/scroll",
+    "buffer/close",
+];
+
+/// Events a slow subscriber may fall behind by before it skips ahead.
+const EVENT_CAPACITY: usize = 64;
+
+// ── JSON-RPC envelope ────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RpcRequest {
+    /// Absent for a notification, which gets no response.
+    #[serde(default)]
+    pub(crate) id: Option<Value>,
+    pub(crate) method: String,
+    #[serde(default)]
+    pub(crate) params: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct RpcResponse {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<RpcError>,
+}
+
+impl RpcResponse {
+    pub(crate) fn new(id: Value, outcome: Result<Value, RpcError>) -> Self {
+        let (result, error) = match outcome {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Self {
+            jsonrpc: "2.0",
+            id,
+            result,
+            error,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub(crate) struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    pub(crate) fn parse_error() -> Self {
+        Self {
+            code: -32700,
+            message: "Parse error".into(),
+        }
+    }
+
+    pub(crate) fn invalid_request(detail: impl std::fmt::Display) -> Self {
+        Self {
+            code: -32600,
+            message: format!("Invalid request: {detail}"),
+        }
+    }
+
+    pub(crate) fn method_not_found(method: &str) -> Self {
+        Self {
+            code: -32601,
+            message: format!("Method not found: {method}"),
+        }
+    }
+
+    pub(crate) fn invalid_params(detail: impl std::fmt::Display) -> Self {
+        Self {
+            code: -32602,
+            message: format!("Invalid params: {detail}"),
+        }
+    }
+
+    pub(crate) fn internal(detail: impl std::fmt::Display) -> Self {
+        Self {
+            code: -32603,
+            message: format!("Internal error: {detail}"),
+        }
+    }
+}
+
+/// Parse a request body into a call, or the error response to send back.
+pub(crate) fn parse_request(body: &[u8]) -> Result<RpcRequest, RpcResponse> {
+    let value: Value = serde_json::from_slice(body)
+        .map_err(|_| RpcResponse::new(Value::Null, Err(RpcError::parse_error())))?;
+    let id = value.get("id").cloned().unwrap_or(Value::Null);
+    if value.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(RpcResponse::new(
+            id,
+            Err(RpcError::invalid_request("expected \"jsonrpc\": \"2.0\"")),
+        ));
+    }
+    serde_json::from_value(value)
+        .map_err(|e| RpcResponse::new(id, Err(RpcError::invalid_request(e))))
+}
+
+/// Deserialize a method's `params`.
+pub(crate) fn params<T: serde::de::DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(RpcError::invalid_params)
+}
+
+// ── Methods ──────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct RenderParams {
+    pub(crate) content: String,
+    /// Workspace-relative path of the buffer's file, so relative images and
+    /// links resolve beside it.
+    #[serde(default)]
+    pub(crate) path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UpdateParams {
+    /// Client-chosen buffer name, e.g. the editor's buffer number or URI.
+    pub(crate) buffer: String,
+    pub(crate) content: String,
+    #[serde(default)]
+    pub(crate) path: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ScrollParams {
+    pub(crate) buffer: String,
+    /// Zero-based cursor (or top visible) line.
+    pub(crate) line: u32,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CloseParams {
+    pub(crate) buffer: String,
+}
+
+/// A rendered buffer: the answer to `render` and `buffer/update`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct Rendered {
+    pub(crate) html: String,
+    pub(crate) toc: Vec<TocItem>,
+    pub(crate) has_mermaid: bool,
+    pub(crate) has_math: bool,
+}
+
+/// Where a preview should scroll to for a cursor line: the heading the line
+/// sits under, and the line's position through the buffer for one without
+/// headings.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub(crate) struct ScrollTarget {
+    pub(crate) line: u32,
+    pub(crate) heading: Option<String>,
+    pub(crate) fraction: f64,
+}
+
+/// A pushed buffer with what scroll sync needs to map lines to headings.
+#[derive(Debug, Clone)]
+pub(crate) struct Buffer {
+    path: Option<String>,
+    rendered: Rendered,
+    /// Source line (1-based) of each TOC entry's heading.
+    heading_lines: Vec<u32>,
+    line_count: u32,
+}
+
+impl Buffer {
+    pub(crate) fn new(content: &str, path: Option<String>, rendered: Rendered) -> Self {
+        Self {
+            path,
+            rendered,
+            heading_lines: crate::markdown_ast::heading_lines(content),
+            line_count: content.lines().count().try_into().unwrap_or(u32::MAX),
+        }
+    }
+
+    pub(crate) fn rendered(&self) -> &Rendered {
+        &self.rendered
+    }
+
+    fn scroll_target(&self, line: u32) -> ScrollTarget {
+        let under = self
+            .heading_lines
+            .iter()
+            .rposition(|&start| start != 0 && start <= line.saturating_add(1));
+        ScrollTarget {
+            line,
+            heading: under
+                .and_then(|index| self.rendered.toc.get(index))
+                .map(|item| item.id.clone()),
+            fraction: match self.line_count {
+                0 | 1 => 0.0,
+                count => (f64::from(line) / f64::from(count - 1)).min(1.0),
+            },
+        }
+    }
+
+    fn render_event(&self, buffer: &str) -> EditorEvent {
+        EditorEvent::Render {
+            buffer: buffer.to_string(),
+            path: self.path.clone(),
+            rendered: self.rendered.clone(),
+        }
+    }
+}
+
+// ── Events ───────────────────────────────────────────────────────────────────
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum EditorEvent {
+    Render {
+        buffer: String,
+        path: Option<String>,
+        #[serde(flatten)]
+        rendered: Rendered,
+    },
+    Scroll {
+        buffer: String,
+        #[serde(flatten)]
+        target: ScrollTarget,
+    },
+    Close {
+        buffer: String,
+    },
+}
+
+impl EditorEvent {
+    /// The SSE event name.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Self::Render { .. } => "render",
+            Self::Scroll { .. } => "scroll",
+            Self::Close { .. } => "close",
+        }
+    }
+
+    pub(crate) fn buffer(&self) -> &str {
+        match self {
+            Self::Render { buffer, .. } | Self::Scroll { buffer, .. } | Self::Close { buffer } => {
+                buffer
+            }
+        }
+    }
+}
+
+// ── Hub ──────────────────────────────────────────────────────────────────────
+
+/// The pushed buffers of every workspace and the channel their events fan
+/// out on. Buffers live in memory only, until closed.
+#[derive(Default)]
+pub(crate) struct EditorHub {
+    workspaces: Mutex<HashMap<String, WorkspaceBuffers>>,
+}
+
+struct WorkspaceBuffers {
+    tx: broadcast::Sender<EditorEvent>,
+    buffers: HashMap<String, Buffer>,
+    /// The buffer updated or scrolled last.
+    active: Option<String>,
+}
+
+impl Default for WorkspaceBuffers {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_CAPACITY).0,
+            buffers: HashMap::new(),
+            active: None,
+        }
+    }
+}
+
+impl EditorHub {
+    fn with_workspace<T>(
+        &self,
+        workspace_id: &str,
+        f: impl FnOnce(&mut WorkspaceBuffers) -> T,
+    ) -> T {
+        let mut workspaces = self
+            .workspaces
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(workspaces.entry(workspace_id.to_string()).or_default())
+    }
+
+    /// Subscribe to a workspace's events, with the current render of `buffer`
+    /// (or of the active buffer) to show first.
+    pub(crate) fn subscribe(
+        &self,
+        workspace_id: &str,
+        buffer: Option<&str>,
+    ) -> (Option<EditorEvent>, broadcast::Receiver<EditorEvent>) {
+        self.with_workspace(workspace_id, |ws| {
+            let name = buffer.or(ws.active.as_deref());
+            let current = name.and_then(|name| Some(ws.buffers.get(name)?.render_event(name)));
+            (current, ws.tx.subscribe())
+        })
+    }
+
+    /// Store a pushed buffer and announce its render.
+    pub(crate) fn update(&self, workspace_id: &str, name: &str, buffer: Buffer) {
+        self.with_workspace(workspace_id, |ws| {
+            let _ = ws.tx.send(buffer.render_event(name));
+            ws.buffers.insert(name.to_string(), buffer);
+            ws.active = Some(name.to_string());
+        });
+    }
+
+    /// Announce where a buffer's previews should scroll for `line`.
+    pub(crate) fn scroll(
+        &self,
+        workspace_id: &str,
+        name: &str,
+        line: u32,
+    ) -> Result<ScrollTarget, RpcError> {
+        self.with_workspace(workspace_id, |ws| {
+            let buffer = ws
+                .buffers
+                .get(name)
+                .ok_or_else(|| RpcError::invalid_params(format!("unknown buffer {name:?}")))?;
+            let target = buffer.scroll_target(line);
+            let _ = ws.tx.send(EditorEvent::Scroll {
+                buffer: name.to_string(),
+                target: target.clone(),
+            });
+            ws.active = Some(name.to_string());
+            Ok(target)
+        })
+    }
+
+    /// Drop a buffer; `false` when there was none.
+    pub(crate) fn close(&self, workspace_id: &str, name: &str) -> bool {
+        self.with_workspace(workspace_id, |ws| {
+            let closed = ws.buffers.remove(name).is_some();
+            if closed {
+                let _ = ws.tx.send(EditorEvent::Close {
+                    buffer: name.to_string(),
+                });
+                if ws.active.as_deref() == Some(name) {
+                    ws.active = None;
+                }
+            }
+            closed
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(content: &str, ids: &[&str]) -> Buffer {
+        let toc = ids
+            .iter()
+            .map(|id| TocItem {
+                level: 2,
+                id: id.to_string(),
+                text: id.to_string(),
+            })
+            .collect();
+        Buffer::new(
+            content,
+            Some("notes.md".into()),
+            Rendered {
+                html: String::new(),
+                toc,
+                has_mermaid: false,
+                has_math: false,
+            },
+        )
+    }
+
+    #[test]
+    fn requests_are_validated_before_dispatch() {
+        let error = |body: &str| {
+            let response = parse_request(body.as_bytes()).unwrap_err();
+            serde_json::to_value(response).unwrap()
+        };
+        assert_eq!(error("{")["error"]["code"], -32700);
+        assert_eq!(
+            error(r#"{"id":1,"method":"render"}"#)["error"]["code"],
+            -32600
+        );
+        assert_eq!(error(r#"{"jsonrpc":"2.0","id":2}"#)["id"], 2);
+
+        let request =
+            parse_request(br#"{"jsonrpc":"2.0","method":"buffer/close","params":{"buffer":"1"}}"#)
+                .unwrap();
+        assert!(request.id.is_none());
+        assert_eq!(params::<CloseParams>(request.params).unwrap().buffer, "1");
+        assert_eq!(
+            params::<ScrollParams>(serde_json::json!({"buffer": "1"}))
+                .unwrap_err()
+                .code,
+            -32602
+        );
+    }
+
+    #[test]
+    fn scroll_maps_cursor_lines_to_the_enclosing_heading() {
+        let content = "intro\n\n## First\n\ntext\n\n```\n## not a heading\n```\n\n## Second\nend\n";
+        let buffer = buffer(content, &["first", "second"]);
+        assert_eq!(buffer.scroll_target(0).heading, None);
+        assert_eq!(buffer.scroll_target(2).heading.as_deref(), Some("first"));
+        assert_eq!(buffer.scroll_target(7).heading.as_deref(), Some("first"));
+        assert_eq!(buffer.scroll_target(10).heading.as_deref(), Some("second"));
+        assert_eq!(buffer.scroll_target(11).fraction, 1.0);
+    }
+
+    #[tokio::test]
+    async fn hub_replays_the_active_buffer_and_fans_out_events() {
+        let hub = EditorHub::default();
+        hub.update("ws", "a", buffer("# A\n", &["a"]));
+        let (current, mut rx) = hub.subscribe("ws", None);
+        assert_eq!(current.unwrap().buffer(), "a");
+        assert!(hub.subscribe("ws", Some("b")).0.is_none());
+
+        let target = hub.scroll("ws", "a", 0).unwrap();
+        assert_eq!(target.heading.as_deref(), Some("a"));
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.name(), "scroll");
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"type": "scroll", "buffer": "a", "line": 0, "heading": "a", "fraction": 0.0})
+        );
+        assert!(hub.scroll("ws", "missing", 0).is_err());
+
+        assert!(hub.close("ws", "a"));
+        assert_eq!(rx.recv().await.unwrap().name(), "close");
+        assert!(!hub.close("ws", "a"));
+        assert!(hub.subscribe("ws", None).0.is_none());
+    }
+}
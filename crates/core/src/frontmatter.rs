@@ -0,0 +1,151 @@
+//! Minimal YAML frontmatter reader.
+//!
+//! Markon does not interpret frontmatter as a whole; it only pulls out the
+//! few keys other features need. Parsing is line-based and dependency-free:
+//! it understands `key: value`, inline lists (`[a, b]`), and block lists
+//! (`- a`), which covers how tags are written in practice.
+
+/// Keys Markon reads from a document's leading `---` block.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct FrontMatter {
+    /// Normalized tags: trimmed, lower-cased, without a leading `#`,
+    /// de-duplicated in source order.
+    pub(crate) tags: Vec<String>,
+    /// `title:` as written, without surrounding quotes.
+    pub(crate) title: Option<String>,
+}
+
+/// Parse the frontmatter block at the very start of `content`, if any.
+pub(crate) fn parse(content: &str) -> Option<FrontMatter> {
+    let (block, _) = split(content)?;
+    let mut front = FrontMatter::default();
+    let mut lines = block.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        // Nested keys are indented; only top-level ones count.
+        if line.starts_with([' ', '\t']) {
+            continue;
+        }
+        let value = value.trim();
+        if key.trim() == "title" {
+            let title = value
+                .trim_matches(|c| c == '"' || c == '\'')
+                .trim()
+                .to_string();
+            front.title = (!title.is_empty()).then_some(title);
+            continue;
+        }
+        if !matches!(key.trim(), "tags" | "tag") {
+            continue;
+        }
+        let raw: Vec<String> = if value.is_empty() {
+            let mut items = Vec::new();
+            while let Some(item) = lines
+                .peek()
+                .and_then(|next| next.trim_start().strip_prefix('-'))
+            {
+                items.push(item.to_string());
+                lines.next();
+            }
+            items
+        } else {
+            let inline = value
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+                .unwrap_or(value);
+            inline.split(',').map(str::to_string).collect()
+        };
+        for tag in raw {
+            let tag = normalize_tag(&tag);
+            if !tag.is_empty() && !front.tags.contains(&tag) {
+                front.tags.push(tag);
+            }
+        }
+    }
+    Some(front)
+}
+
+/// Canonical form used for indexing and querying tags.
+pub(crate) fn normalize_tag(tag: &str) -> String {
+    tag.trim()
+        .trim_matches(|c| c == '"' || c == '\'')
+        .trim()
+        .trim_start_matches('#')
+        .to_lowercase()
+}
+
+/// `content` without its frontmatter block, or unchanged if it has none.
+pub(crate) fn strip(content: &str) -> &str {
+    split(content).map_or(content, |(_, body)| body)
+}
+
+/// Split into the text between an opening `---` on the first line and the
+/// next line that is exactly `---` (or `...`), and the body after it.
+fn split(content: &str) -> Option<(&str, &str)> {
+    let content = content.strip_prefix('\u{feff}').unwrap_or(content);
+    let rest = content
+        .strip_prefix("---\n")
+        .or_else(|| content.strip_prefix("---\r\n"))?;
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if matches!(line.trim_end(), "---" | "...") {
+            return Some((&rest[..offset], &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_inline_block_and_scalar_tags() {
+        let inline = "---\ntitle: Design\ntags: [Architecture, \"retries\", #ops]\n---\n# Body";
+        assert_eq!(
+            parse(inline).unwrap().tags,
+            ["architecture", "retries", "ops"]
+        );
+
+        let block = "---\ntags:\n  - api\n  - 'API'\n  - design\nauthor: me\n---\n";
+        assert_eq!(parse(block).unwrap().tags, ["api", "design"]);
+
+        let scalar = "---\ntag: notes\n---\n";
+        assert_eq!(parse(scalar).unwrap().tags, ["notes"]);
+    }
+
+    #[test]
+    fn reads_a_quoted_or_bare_title() {
+        assert_eq!(
+            parse("---\ntitle: \"Weekly sync\"\ntags: [notes]\n---\n")
+                .unwrap()
+                .title
+                .as_deref(),
+            Some("Weekly sync")
+        );
+        assert_eq!(
+            parse("---\ntitle: Design\n---\n").unwrap().title.as_deref(),
+            Some("Design")
+        );
+        assert_eq!(parse("---\ntitle:\n---\n").unwrap().title, None);
+        assert_eq!(
+            parse("---\nmeta:\n  title: nested\n---\n").unwrap().title,
+            None
+        );
+    }
+
+    #[test]
+    fn requires_a_closed_block_at_the_start() {
+        assert_eq!(parse("# Title\n---\ntags: [a]\n---\n"), None);
+        assert_eq!(parse("---\ntags: [a]\nno closing fence"), None);
+        assert_eq!(strip("---\ntags: [a]\n---\n# Body\n"), "# Body\n");
+        assert_eq!(strip("# Body\n"), "# Body\n");
+        assert_eq!(
+            parse("---\ntitle: x\n---\n").unwrap().tags,
+            Vec::<String>::new()
+        );
+    }
+}
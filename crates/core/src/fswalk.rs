@@ -1,4 +1,137 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::markdown::MarkdownExtensions;
+use crate::path_rules::PathRules;
+
+fn hidden_name(name: &str, show_hidden: bool) -> bool {
+    name.starts_with('.') && (name == ".git" || !show_hidden)
+}
+
+/// How the files of a workspace are walked and resolved, and which of them
+/// are Markdown. A workspace keeps the policy of the registry it was
+/// registered with, so servers sharing a process can each have their own.
+#[derive(Debug, Clone)]
+pub(crate) struct WalkPolicy {
+    /// Whether dotfiles and dot-directories are listed and indexed
+    /// (`--show-hidden`). `.git` stays hidden either way.
+    pub(crate) show_hidden: bool,
+    /// Whether symlinks inside the workspace are followed
+    /// (`--no-follow-symlinks` turns it off). Followed links must still
+    /// resolve inside the workspace; unfollowed ones are left out of
+    /// listings, walks and watcher events and refused when requested.
+    pub(crate) follow_symlinks: bool,
+    /// The `--allow-path` / `--deny-path` rules, applied to walks here and
+    /// by [`crate::workspace_fs::WorkspaceFs`] to both the requested and the
+    /// resolved path.
+    pub(crate) rules: Arc<PathRules>,
+    pub(crate) markdown: MarkdownExtensions,
+}
+
+impl Default for WalkPolicy {
+    fn default() -> Self {
+        Self {
+            show_hidden: false,
+            follow_symlinks: true,
+            rules: Arc::default(),
+            markdown: MarkdownExtensions::default(),
+        }
+    }
+}
+
+impl WalkPolicy {
+    /// Whether an entry named `name` is hidden from listings and search.
+    pub(crate) fn is_hidden_name(&self, name: &str) -> bool {
+        hidden_name(name, self.show_hidden)
+    }
+
+    /// Whether `path` is a Markdown document by its extension.
+    pub(crate) fn is_markdown(&self, path: &Path) -> bool {
+        self.markdown.matches(path)
+    }
+
+    /// Whether `path` under `boundary` is reachable by the symlink policy:
+    /// none of the components below `boundary` is a link, or links are
+    /// followed and each one resolves inside `boundary`. Components that no
+    /// longer exist pass, so a watcher still hears about removals.
+    pub(crate) fn symlink_allowed(&self, boundary: &Path, path: &Path) -> bool {
+        path_allowed(boundary, path, self.follow_symlinks)
+    }
+
+    /// Ignore-rule walker that respects `.gitignore`, `.ignore`, and
+    /// hidden-file conventions. This is the shared baseline for workspace
+    /// reads that should behave like the chat tools and ripgrep.
+    pub(crate) fn walker(&self, root: &Path) -> ignore::WalkBuilder {
+        self.bounded_walker(root, root)
+    }
+
+    /// [`Self::walker`] for a directory inside the workspace at `boundary`:
+    /// followed symlinks may lead anywhere within `boundary`.
+    pub(crate) fn bounded_walker(&self, root: &Path, boundary: &Path) -> ignore::WalkBuilder {
+        walker(
+            root,
+            boundary,
+            self.show_hidden,
+            self.follow_symlinks,
+            self.rules.clone(),
+        )
+    }
+
+    /// The entry filter [`Self::bounded_walker`] installs. A caller that
+    /// replaces it with its own `filter_entry` must keep applying this one.
+    pub(crate) fn walk_filter(
+        &self,
+        boundary: &Path,
+    ) -> impl Fn(&ignore::DirEntry) -> bool + Send + Sync + 'static {
+        entry_filter(boundary, self.follow_symlinks, self.rules.clone())
+    }
+
+    /// Names of the direct children of `dir` that the walker would visit,
+    /// i.e. those not excluded by ignore rules, hidden-file conventions, the
+    /// symlink policy or the path rules.
+    pub(crate) fn walked_children(
+        &self,
+        dir: &Path,
+        boundary: &Path,
+    ) -> std::collections::HashSet<std::ffi::OsString> {
+        self.bounded_walker(dir, boundary)
+            .max_depth(Some(1))
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.depth() == 1)
+            .map(|entry| entry.file_name().to_os_string())
+            .collect()
+    }
+}
+
+fn path_allowed(boundary: &Path, path: &Path, follow: bool) -> bool {
+    let Ok(rel) = path.strip_prefix(boundary) else {
+        return true;
+    };
+    let canonical_boundary = canonical(boundary);
+    let mut current = boundary.to_path_buf();
+    for component in rel.components() {
+        current.push(component);
+        match std::fs::symlink_metadata(&current) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                if !(follow && link_inside(&current, &canonical_boundary)) {
+                    return false;
+                }
+            }
+            Ok(_) => {}
+            Err(_) => return true,
+        }
+    }
+    true
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    dunce::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn link_inside(link: &Path, canonical_boundary: &Path) -> bool {
+    dunce::canonicalize(link).is_ok_and(|target| target.starts_with(canonical_boundary))
+}
 
 /// Render a path with forward slashes regardless of platform.
 pub(crate) fn path_to_forward_slash(rel: &Path) -> String {
@@ -8,11 +141,169 @@ pub(crate) fn path_to_forward_slash(rel: &Path) -> String {
         .join("/")
 }
 
-/// Default ignore-rule walker that respects `.gitignore`, `.ignore`, and
-/// hidden-file conventions. This is the shared baseline for workspace reads
-/// that should behave like the chat tools and ripgrep.
-pub(crate) fn default_walker(root: &Path) -> ignore::WalkBuilder {
+fn walker(
+    root: &Path,
+    boundary: &Path,
+    show_hidden: bool,
+    follow: bool,
+    rules: Arc<PathRules>,
+) -> ignore::WalkBuilder {
     let mut b = ignore::WalkBuilder::new(root);
-    b.standard_filters(true);
+    b.standard_filters(true).follow_links(follow);
+    if show_hidden {
+        b.hidden(false);
+    }
+    b.filter_entry(entry_filter(boundary, follow, rules));
     b
 }
+
+/// Drops `.git` (which `--show-hidden` would otherwise reveal), the
+/// symlinks the policy refuses and the paths the path rules hide.
+fn entry_filter(
+    boundary: &Path,
+    follow: bool,
+    rules: Arc<PathRules>,
+) -> impl Fn(&ignore::DirEntry) -> bool + Send + Sync + 'static {
+    let boundary = boundary.to_path_buf();
+    let canonical_boundary = canonical(&boundary);
+    move |entry| {
+        if entry.depth() == 0 {
+            return true;
+        }
+        if entry.file_name() == ".git" {
+            return false;
+        }
+        if entry.path_is_symlink() && !(follow && link_inside(entry.path(), &canonical_boundary)) {
+            return false;
+        }
+        rules.is_empty()
+            || entry.path().strip_prefix(&boundary).is_ok_and(|rel| {
+                rules.permits(rel, entry.file_type().is_some_and(|kind| kind.is_dir()))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn show_hidden_walks_dot_directories_but_not_git() {
+        let dir = tempfile::tempdir().unwrap();
+        for rel in [".github/CONTRIBUTING.md", ".git/HEAD.md", "README.md"] {
+            let path = dir.path().join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "# x").unwrap();
+        }
+        let files = |show_hidden| {
+            let mut files: Vec<String> =
+                walker(dir.path(), dir.path(), show_hidden, true, Arc::default())
+                    .build()
+                    .filter_map(Result::ok)
+                    .filter(|entry| entry.path().is_file())
+                    .map(|entry| {
+                        path_to_forward_slash(entry.path().strip_prefix(dir.path()).unwrap())
+                    })
+                    .collect();
+            files.sort();
+            files
+        };
+        assert_eq!(files(false), vec!["README.md"]);
+        assert_eq!(files(true), vec![".github/CONTRIBUTING.md", "README.md"]);
+
+        assert!(hidden_name(".github", false));
+        assert!(!hidden_name(".github", true));
+        assert!(hidden_name(".git", true));
+        assert!(!hidden_name("docs", false));
+    }
+
+    #[test]
+    fn path_rules_prune_walks() {
+        let dir = tempfile::tempdir().unwrap();
+        for rel in [
+            "README.md",
+            "docs/guide.md",
+            "docs/private/plan.md",
+            "src/lib.md",
+        ] {
+            let path = dir.path().join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "# x").unwrap();
+        }
+        let files = |allow: &[&str], deny: &[&str]| {
+            let owned =
+                |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            let rules = Arc::new(PathRules::new(&owned(allow), &owned(deny)).unwrap());
+            let mut files: Vec<String> = walker(dir.path(), dir.path(), false, true, rules)
+                .build()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_file())
+                .map(|entry| path_to_forward_slash(entry.path().strip_prefix(dir.path()).unwrap()))
+                .collect();
+            files.sort();
+            files
+        };
+        assert_eq!(
+            files(&[], &["private"]),
+            vec!["README.md", "docs/guide.md", "src/lib.md"]
+        );
+        assert_eq!(
+            files(&["docs/**"], &["docs/private/**"]),
+            vec!["docs/guide.md"]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_policy_keeps_walks_inside_the_workspace() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("docs")).unwrap();
+        std::fs::write(root.join("docs/guide.md"), "# Guide").unwrap();
+        std::fs::write(outside.path().join("secret.md"), "# Secret").unwrap();
+        symlink(root.join("docs"), root.join("linked-docs")).unwrap();
+        symlink(outside.path(), root.join("escape")).unwrap();
+        symlink(outside.path().join("secret.md"), root.join("secret.md")).unwrap();
+        symlink("docs/../escape/secret.md", root.join("docs-escape.md")).unwrap();
+        symlink("..", root.join("docs/up")).unwrap();
+
+        let files = |follow| {
+            let mut files: Vec<String> = walker(root, root, false, follow, Arc::default())
+                .build()
+                .filter_map(Result::ok)
+                .filter(|entry| entry.path().is_file())
+                .map(|entry| path_to_forward_slash(entry.path().strip_prefix(root).unwrap()))
+                .collect();
+            files.sort();
+            files
+        };
+        assert_eq!(files(true), vec!["docs/guide.md", "linked-docs/guide.md"]);
+        assert_eq!(files(false), vec!["docs/guide.md"]);
+
+        // A subdirectory walk may follow links anywhere in its workspace.
+        let children: Vec<String> = walker(&root.join("docs"), root, false, true, Arc::default())
+            .max_depth(Some(1))
+            .build()
+            .filter_map(Result::ok)
+            .filter(|entry| entry.depth() == 1)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(children.contains(&"up".to_string()), "{children:?}");
+
+        for follow in [true, false] {
+            assert!(path_allowed(root, &root.join("docs/guide.md"), follow));
+            assert!(path_allowed(root, &root.join("docs/removed.md"), follow));
+            assert!(!path_allowed(root, &root.join("escape/secret.md"), follow));
+            assert!(!path_allowed(root, &root.join("docs-escape.md"), follow));
+        }
+        assert!(path_allowed(root, &root.join("linked-docs/guide.md"), true));
+        assert!(!path_allowed(
+            root,
+            &root.join("linked-docs/guide.md"),
+            false
+        ));
+    }
+}
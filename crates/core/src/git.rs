@@ -4,6 +4,7 @@ use std::io::Write;
 use std::path::Path;
 use std::process::{Command, Output, Stdio};
 
+use crate::markdown::MarkdownExtensions;
 use crate::workspace_fs::WorkspaceFs;
 
 #[derive(Debug, Clone, Serialize)]
@@ -62,10 +63,28 @@ pub struct GitCommit {
     pub subject: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct GitPathCommit {
     pub subject: String,
+    /// Relative commit time, e.g. `3 days ago`.
     pub time: String,
+    pub author: String,
+    /// Commit time, strict ISO 8601.
+    pub date: String,
+}
+
+/// `%x1f`-separated fields [`parse_path_commit`] reads.
+const PATH_COMMIT_FORMAT: &str = "%s%x1f%cr%x1f%an%x1f%cI";
+
+fn parse_path_commit(line: &str) -> GitPathCommit {
+    let mut fields = line.splitn(4, '\x1f');
+    let mut next = || fields.next().unwrap_or_default().to_string();
+    GitPathCommit {
+        subject: next(),
+        time: next(),
+        author: next(),
+        date: next(),
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -185,6 +204,9 @@ pub struct HistoryFilter {
     pub author: Option<String>,
     /// `--since=<value>` filter (any git approxidate, e.g. "1 week ago").
     pub since: Option<String>,
+    /// Workspace-relative path to list the commits of instead of the whole
+    /// workspace. Callers pass an already validated path.
+    pub path: Option<String>,
 }
 
 pub fn history(root: &Path, limit: usize) -> Result<Vec<GitCommit>> {
@@ -239,7 +261,13 @@ pub fn history_filtered(
         }
     }
     args.push("--".to_string());
-    args.push(".".to_string());
+    // `./` keeps a name starting with `:` from reading as pathspec magic.
+    args.push(
+        filter
+            .path
+            .as_deref()
+            .map_or_else(|| ".".to_string(), |path| format!("./{path}")),
+    );
 
     let arg_refs: Vec<&str> = args.iter().map(String::as_str).collect();
     let output = run_git(root, &arg_refs)?;
@@ -519,8 +547,7 @@ pub fn last_commit_for_path(root: &Path, rel_path: &str) -> Result<Option<GitPat
         &[
             "log",
             "-1",
-            "--date=relative",
-            "--format=%s%x1f%cr",
+            &format!("--format={PATH_COMMIT_FORMAT}"),
             "--",
             rel_path,
         ],
@@ -538,11 +565,7 @@ pub fn last_commit_for_path(root: &Path, rel_path: &str) -> Result<Option<GitPat
     if line.is_empty() {
         return Ok(None);
     }
-    let (subject, time) = line.split_once('\x1f').unwrap_or((line.as_str(), ""));
-    Ok(Some(GitPathCommit {
-        subject: subject.to_string(),
-        time: time.to_string(),
-    }))
+    Ok(Some(parse_path_commit(&line)))
 }
 
 pub fn last_commits_for_paths(
@@ -561,8 +584,7 @@ pub fn last_commits_for_paths(
 
     let mut args = vec![
         "log".to_string(),
-        "--date=relative".to_string(),
-        "--format=%x1e%s%x1f%cr".to_string(),
+        format!("--format=%x1e{PATH_COMMIT_FORMAT}"),
         "--name-only".to_string(),
         "--".to_string(),
     ];
@@ -588,7 +610,7 @@ pub fn last_commits_for_paths(
         let Some(header) = lines.next() else {
             continue;
         };
-        let (subject, time) = header.split_once('\x1f').unwrap_or((header, ""));
+        let commit = parse_path_commit(header);
         for changed_path in lines.map(str::trim).filter(|line| !line.is_empty()) {
             for rel_path in rel_paths.iter() {
                 if commits.contains_key(*rel_path)
@@ -596,13 +618,7 @@ pub fn last_commits_for_paths(
                 {
                     continue;
                 }
-                commits.insert(
-                    (*rel_path).to_string(),
-                    GitPathCommit {
-                        subject: subject.to_string(),
-                        time: time.to_string(),
-                    },
-                );
+                commits.insert((*rel_path).to_string(), commit.clone());
             }
         }
         if commits.len() >= rel_paths.len() {
@@ -739,6 +755,7 @@ pub(crate) fn markdown_diff_listing(
         .directory_root()
         .ok_or_else(|| GitError::Io("directory workspace required".to_string()))?;
     ensure_repo(root)?;
+    let markdown = &workspace_fs.policy().markdown;
     let base = validate_compare_ref(root, base, false)?;
     let compare = validate_compare_ref(root, compare, true)?;
     let worktree = compare == "worktree";
@@ -803,7 +820,7 @@ pub(crate) fn markdown_diff_listing(
             ],
         ) {
             for rel in list {
-                if is_markdown_git_path(&rel) {
+                if is_markdown_git_path(markdown, &rel) {
                     let additions = workspace_fs
                         .read_content_to_string(&rel)
                         .map(|c| c.lines().count())
@@ -823,7 +840,10 @@ pub(crate) fn markdown_diff_listing(
     }
 
     entries.retain(|e| {
-        is_markdown_git_path(&e.path) || e.old_path.as_deref().is_some_and(is_markdown_git_path)
+        is_markdown_git_path(markdown, &e.path)
+            || e.old_path
+                .as_deref()
+                .is_some_and(|path| is_markdown_git_path(markdown, path))
     });
 
     // Fill per-file diffstat counts (untracked entries already carry a line count).
@@ -1079,12 +1099,17 @@ fn git_stdout_nul(root: &Path, args: &[&str]) -> Option<Vec<String>> {
     )
 }
 
-pub fn diff_has_markdown_changes(root: &Path, base: &str, compare: &str) -> Result<bool> {
+pub fn diff_has_markdown_changes(
+    root: &Path,
+    markdown: &MarkdownExtensions,
+    base: &str,
+    compare: &str,
+) -> Result<bool> {
     ensure_repo(root)?;
     let base = validate_compare_ref(root, base, false)?;
     let compare = validate_compare_ref(root, compare, true)?;
     if compare == "worktree" && base == "HEAD" && !has_head(root) {
-        return Ok(has_untracked_markdown_changes(root));
+        return Ok(has_untracked_markdown_changes(root, markdown));
     }
     let output = if compare == "worktree" {
         run_git(
@@ -1110,12 +1135,12 @@ pub fn diff_has_markdown_changes(root: &Path, base: &str, compare: &str) -> Resu
     }
     if String::from_utf8_lossy(&output.stdout)
         .lines()
-        .any(is_markdown_git_path)
+        .any(|path| is_markdown_git_path(markdown, path))
     {
         return Ok(true);
     }
     if compare == "worktree" {
-        return Ok(has_untracked_markdown_changes(root));
+        return Ok(has_untracked_markdown_changes(root, markdown));
     }
     Ok(false)
 }
@@ -1124,7 +1149,12 @@ pub fn diff_has_markdown_changes(root: &Path, base: &str, compare: &str) -> Resu
 /// For hot loops (e.g. the compare dropdown) where both refs already come from a
 /// trusted enumeration, this is a single `git diff --name-only` instead of ~4
 /// subprocesses. A bad ref just errors, which callers treat as "has changes".
-pub fn diff_has_markdown_changes_unchecked(root: &Path, base: &str, compare: &str) -> Result<bool> {
+pub fn diff_has_markdown_changes_unchecked(
+    root: &Path,
+    markdown: &MarkdownExtensions,
+    base: &str,
+    compare: &str,
+) -> Result<bool> {
     let output = if compare == "worktree" {
         run_git(
             root,
@@ -1149,23 +1179,25 @@ pub fn diff_has_markdown_changes_unchecked(root: &Path, base: &str, compare: &st
     }
     if String::from_utf8_lossy(&output.stdout)
         .lines()
-        .any(is_markdown_git_path)
+        .any(|path| is_markdown_git_path(markdown, path))
     {
         return Ok(true);
     }
     if compare == "worktree" {
-        return Ok(has_untracked_markdown_changes(root));
+        return Ok(has_untracked_markdown_changes(root, markdown));
     }
     Ok(false)
 }
 
-fn has_untracked_markdown_changes(root: &Path) -> bool {
+fn has_untracked_markdown_changes(root: &Path, markdown: &MarkdownExtensions) -> bool {
     let untracked = git_stdout(
         root,
         &["ls-files", "--others", "--exclude-standard", "--", "."],
     )
     .unwrap_or_default();
-    untracked.lines().any(is_markdown_git_path)
+    untracked
+        .lines()
+        .any(|path| is_markdown_git_path(markdown, path))
 }
 
 pub fn commit_diff(root: &Path, rev: &str) -> Result<GitDiff> {
@@ -1237,7 +1269,11 @@ pub struct CommitDiffInfo {
 /// commit — hundreds of git subprocesses for a full page. `--no-walk=unsorted`
 /// lists exactly the given commits (no ancestry walk) with their parents and a
 /// name-status file list, all at once.
-pub fn commit_diff_index(root: &Path, hashes: &[&str]) -> Result<HashMap<String, CommitDiffInfo>> {
+pub fn commit_diff_index(
+    root: &Path,
+    markdown: &MarkdownExtensions,
+    hashes: &[&str],
+) -> Result<HashMap<String, CommitDiffInfo>> {
     ensure_repo(root)?;
     // Only feed trusted full hashes (they come from our own history walk); this
     // also guarantees none can be mistaken for a flag on the command line.
@@ -1298,7 +1334,12 @@ pub fn commit_diff_index(root: &Path, hashes: &[&str]) -> Result<HashMap<String,
             // either side of a rename that ends in `.md` marks the commit.
             if let Some(hash) = current.as_deref() {
                 if let Some(info) = map.get_mut(hash) {
-                    if !info.has_markdown && line.split('\t').skip(1).any(is_markdown_git_path) {
+                    if !info.has_markdown
+                        && line
+                            .split('\t')
+                            .skip(1)
+                            .any(|path| is_markdown_git_path(markdown, path))
+                    {
                         info.has_markdown = true;
                     }
                 }
@@ -1316,6 +1357,53 @@ pub fn parent_commit(root: &Path, rev: &str) -> Result<Option<String>> {
     Ok(git_stdout(root, &["rev-parse", &format!("{rev}^")]).filter(|s| !s.is_empty()))
 }
 
+/// The commit `rev` (a hex hash) names, with the fields [`history`] lists.
+pub fn commit(root: &Path, rev: &str) -> Result<GitCommit> {
+    ensure_repo(root)?;
+    if !valid_hex_rev(rev) {
+        return Err(GitError::InvalidRevision);
+    }
+    let commit = format!("{rev}^{{commit}}");
+    git_stdout(
+        root,
+        &[
+            "log",
+            "-1",
+            "--date=iso-strict",
+            "--format=%H%x1f%h%x1f%an%x1f%ad%x1f%cr%x1f%s",
+            &commit,
+            "--",
+        ],
+    )
+    .as_deref()
+    .and_then(parse_commit_line)
+    .ok_or(GitError::InvalidRevision)
+}
+
+/// Content of `rel_path` (relative to `root`) as committed in `rev`, via
+/// `git show <rev>:./<path>`. `None` when the path doesn't exist in that
+/// commit.
+pub fn file_at_commit(root: &Path, rev: &str, rel_path: &str) -> Result<Option<Vec<u8>>> {
+    ensure_repo(root)?;
+    if !valid_hex_rev(rev) {
+        return Err(GitError::InvalidRevision);
+    }
+    let object = format!("{rev}:./{rel_path}");
+    let output = run_git(root, &["show", &object])?;
+    Ok(output.status.success().then_some(output.stdout))
+}
+
+/// Whether `rel_path` differs in the working tree from `HEAD`. False outside a
+/// repository, before the first commit, or when git fails.
+pub fn path_has_changes(root: &Path, rel_path: &str) -> bool {
+    let pathspec = format!("./{rel_path}");
+    run_git(
+        root,
+        &["diff", "--no-ext-diff", "--quiet", "HEAD", "--", &pathspec],
+    )
+    .is_ok_and(|output| output.status.code() == Some(1))
+}
+
 pub fn commit_workspace(root: &Path, message: &str) -> Result<GitCommitResult> {
     ensure_repo(root)?;
     let subject = message.trim();
@@ -1347,6 +1435,12 @@ fn ensure_repo(root: &Path) -> Result<()> {
     }
 }
 
+/// Full hash of `HEAD`; `None` outside a repository or before its first
+/// commit.
+pub fn head_commit(root: &Path) -> Option<String> {
+    git_stdout(root, &["rev-parse", "--verify", "--quiet", "HEAD"]).filter(|hash| !hash.is_empty())
+}
+
 fn has_head(root: &Path) -> bool {
     git_stdout(root, &["rev-parse", "--verify", "HEAD"]).is_some()
 }
@@ -1360,6 +1454,22 @@ fn validate_compare_ref(root: &Path, value: &str, allow_worktree: bool) -> Resul
     if allow_worktree && value == "worktree" {
         return Ok(value.to_string());
     }
+    // `HEAD~1`, `main^`, `v1.0~2^2`: an accepted ref plus ancestry steps. Ref
+    // names cannot contain `~` or `^`, so the split is unambiguous.
+    if let Some(at) = value.find(['~', '^']) {
+        let (base, steps) = value.split_at(at);
+        if !steps
+            .bytes()
+            .all(|b| matches!(b, b'~' | b'^') || b.is_ascii_digit())
+        {
+            return Err(GitError::InvalidRevision);
+        }
+        validate_compare_ref(root, base, false)?;
+        let commit = format!("{value}^{{commit}}");
+        return git_stdout(root, &["rev-parse", "--verify", "--quiet", &commit])
+            .map(|_| value.to_string())
+            .ok_or(GitError::InvalidRevision);
+    }
     if value == "HEAD" {
         return Ok(value.to_string());
     }
@@ -1417,10 +1527,8 @@ fn git_path_matches_entry(changed_path: &str, entry_path: &str) -> bool {
             .is_some_and(|rest| rest.starts_with('/'))
 }
 
-fn is_markdown_git_path(path: &str) -> bool {
-    Path::new(path.trim())
-        .extension()
-        .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case("md"))
+fn is_markdown_git_path(markdown: &MarkdownExtensions, path: &str) -> bool {
+    markdown.matches(Path::new(path.trim()))
 }
 
 fn parse_commit_line(line: &str) -> Option<GitCommit> {
@@ -1594,6 +1702,23 @@ mod tests {
         assert!(!git_path_matches_entry("README.zh.md", "README.md"));
     }
 
+    #[test]
+    fn parses_path_commit_fields() {
+        let commit = parse_path_commit(
+            "Fix typo\x1f3 days ago\x1fAda Lovelace\x1f2024-05-01T10:00:00+02:00",
+        );
+        assert_eq!(
+            commit,
+            GitPathCommit {
+                subject: "Fix typo".into(),
+                time: "3 days ago".into(),
+                author: "Ada Lovelace".into(),
+                date: "2024-05-01T10:00:00+02:00".into(),
+            }
+        );
+        assert_eq!(parse_path_commit("Only a subject").author, "");
+    }
+
     #[test]
     fn rejects_non_hex_revisions() {
         assert!(valid_hex_rev("abc123"));
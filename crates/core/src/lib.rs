@@ -1,3 +1,23 @@
+//! The library behind the `markon` command, the `markond` service and the
+//! desktop app.
+//!
+//! Other Rust tools can reuse the same rendering, search and server without
+//! running the CLI:
+//!
+//! - [`MarkdownRenderer`] renders GitHub-style Markdown to HTML with a
+//!   [`TocItem`] per heading; [`render`] wraps it in a standalone page.
+//! - [`SearchIndex`] builds the full-text index of a directory.
+//! - [`ServerBuilder`] starts the preview server, or [`router`] returns it
+//!   as an axum `Router` to serve from another application.
+//!
+//! ```
+//! let output = markon_core::MarkdownRenderer::new("light").render_document("# Notes\n");
+//! assert_eq!(output.toc[0].text, "Notes");
+//! ```
+
+pub mod annotations;
+pub mod attachments;
+pub mod audit;
 pub mod chat;
 pub mod control;
 pub mod daemon;
@@ -5,14 +25,45 @@ pub mod data_maintenance;
 pub mod git;
 pub mod i18n;
 pub mod net;
+pub mod remote_markdown;
+pub mod render;
 pub mod search;
 pub mod server;
 pub mod settings;
+pub mod theme;
 pub mod workspace;
 
 pub mod admin_auth;
+pub(crate) mod archive;
 pub(crate) mod assets;
+pub(crate) mod book;
+pub(crate) mod change_notifier;
+pub(crate) mod db;
+pub(crate) mod editor;
+pub(crate) mod frontmatter;
 pub(crate) mod fswalk;
 pub(crate) mod markdown;
 pub(crate) mod markdown_ast;
+pub(crate) mod metadata;
+pub(crate) mod openapi;
+pub(crate) mod pandoc;
+pub(crate) mod path_rules;
+pub(crate) mod presence;
+pub(crate) mod present;
+pub(crate) mod reanchor;
+pub(crate) mod recent;
+pub(crate) mod render_hook;
+pub(crate) mod snapshots;
+pub(crate) mod store;
+pub(crate) mod viewed;
+pub(crate) mod wasm_plugin;
+pub(crate) mod web_annotation;
 pub(crate) mod workspace_fs;
+
+pub use markdown::{
+    MarkdownDiagnostic, MarkdownExtensions, MarkdownRenderOutput, MarkdownRenderer, TocItem,
+};
+pub use render_hook::{register_render_hook, LinkSchemes, RenderHook};
+pub use search::{SearchIndex, SearchResult};
+pub use server::{router, ServerBuilder, ServerConfig};
+pub use workspace::WorkspaceFlags;
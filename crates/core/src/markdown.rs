@@ -74,6 +74,11 @@ lazy_static! {
     static ref CSS_URL_REGEX: Regex = Regex::new(
         r#"url\(\s*['"]?([^'")]+)['"]?\s*\)"#
     ).expect("Failed to compile CSS_URL_REGEX");
+    /// The `<img src="` / `<source src='` prefix (group 1) and URL (group 2)
+    /// of a raw HTML media tag, for rewriting the URL in place.
+    static ref HTML_IMAGE_SRC_ATTR_REGEX: Regex = Regex::new(
+        r#"(?i)(<(?:img|source|video|audio)[^>]*\ssrc\s*=\s*["'])([^"']+)"#
+    ).expect("Failed to compile HTML_IMAGE_SRC_ATTR_REGEX");
     static ref MARKDOWN_IMAGE_REGEX: Regex = Regex::new(
         r#"!\[([^\]\n]*)\]\(([^)\n]+)\)"#
     ).expect("Failed to compile MARKDOWN_IMAGE_REGEX");
@@ -183,21 +188,22 @@ fn sanitize_asset_ref(raw: &str) -> Option<String> {
 
 #[derive(Debug, Clone)]
 pub(crate) struct MarkdownAssetContext {
-    workspace_id: String,
+    /// The workspace root's URL, `/{workspace_id}/` under any base path.
+    workspace_url: String,
     file_path: PathBuf,
     workspace_root: PathBuf,
 }
 
 impl MarkdownAssetContext {
     fn new(
-        workspace_id: impl Into<String>,
+        workspace_url: impl Into<String>,
         file_path: impl Into<PathBuf>,
         workspace_root: impl Into<PathBuf>,
     ) -> Self {
         let file_path = file_path.into();
         let workspace_root = workspace_root.into();
         Self {
-            workspace_id: workspace_id.into(),
+            workspace_url: workspace_url.into(),
             file_path: dunce::canonicalize(&file_path).unwrap_or(file_path),
             workspace_root: dunce::canonicalize(&workspace_root).unwrap_or(workspace_root),
         }
@@ -205,6 +211,30 @@ impl MarkdownAssetContext {
 }
 
 fn normalize_local_image_destinations(markdown: &str) -> Cow<'_, str> {
+    rewrite_image_segments(
+        markdown,
+        &|segment| match normalize_image_destinations_in_segment(segment) {
+            Cow::Borrowed(_) => None,
+            Cow::Owned(normalized) => Some(normalized),
+        },
+    )
+}
+
+/// `markdown` with relative image destinations, `![](img/a.png)` and raw
+/// `<img src="img/a.png">` alike, resolved against `base`: the URL a remote
+/// document was fetched from, so its images load from beside it.
+pub(crate) fn resolve_remote_images<'a>(markdown: &'a str, base: &reqwest::Url) -> Cow<'a, str> {
+    rewrite_image_segments(markdown, &|segment| {
+        resolve_remote_images_in_segment(segment, base)
+    })
+}
+
+/// Apply `rewrite` to every stretch of `markdown` outside fenced, indented and
+/// inline code; it returns `None` for a segment it leaves alone.
+fn rewrite_image_segments<'a>(
+    markdown: &'a str,
+    rewrite: &dyn Fn(&str) -> Option<String>,
+) -> Cow<'a, str> {
     let mut output = String::with_capacity(markdown.len());
     let mut changed = false;
     let mut fence: Option<(char, usize)> = None;
@@ -230,7 +260,7 @@ fn normalize_local_image_destinations(markdown: &str) -> Cow<'_, str> {
             continue;
         }
 
-        match normalize_line_image_destinations(line) {
+        match rewrite_line_image_segments(line, rewrite) {
             Cow::Borrowed(_) => output.push_str(line),
             Cow::Owned(normalized) => {
                 output.push_str(&normalized);
@@ -246,7 +276,10 @@ fn normalize_local_image_destinations(markdown: &str) -> Cow<'_, str> {
     }
 }
 
-fn normalize_line_image_destinations(line: &str) -> Cow<'_, str> {
+fn rewrite_line_image_segments<'a>(
+    line: &'a str,
+    rewrite: &dyn Fn(&str) -> Option<String>,
+) -> Cow<'a, str> {
     let mut output = String::with_capacity(line.len());
     let mut changed = false;
     let mut cursor = 0;
@@ -254,12 +287,12 @@ fn normalize_line_image_destinations(line: &str) -> Cow<'_, str> {
     while cursor < line.len() {
         let Some(tick_rel) = line[cursor..].find('`') else {
             let segment = &line[cursor..];
-            append_normalized_image_segment(segment, &mut output, &mut changed);
+            append_rewritten_segment(segment, rewrite, &mut output, &mut changed);
             break;
         };
         let tick_start = cursor + tick_rel;
         let segment = &line[cursor..tick_start];
-        append_normalized_image_segment(segment, &mut output, &mut changed);
+        append_rewritten_segment(segment, rewrite, &mut output, &mut changed);
 
         let tick_count = count_repeated_char(&line[tick_start..], '`');
         let code_start = tick_start + tick_count;
@@ -281,14 +314,60 @@ fn normalize_line_image_destinations(line: &str) -> Cow<'_, str> {
     }
 }
 
-fn append_normalized_image_segment(segment: &str, output: &mut String, changed: &mut bool) {
-    match normalize_image_destinations_in_segment(segment) {
-        Cow::Borrowed(_) => output.push_str(segment),
-        Cow::Owned(normalized) => {
-            output.push_str(&normalized);
+fn append_rewritten_segment(
+    segment: &str,
+    rewrite: &dyn Fn(&str) -> Option<String>,
+    output: &mut String,
+    changed: &mut bool,
+) {
+    match rewrite(segment) {
+        Some(rewritten) => {
+            output.push_str(&rewritten);
             *changed = true;
         }
+        None => output.push_str(segment),
+    }
+}
+
+fn resolve_remote_images_in_segment(segment: &str, base: &reqwest::Url) -> Option<String> {
+    let markdown = MARKDOWN_IMAGE_REGEX.replace_all(segment, |caps: &regex::Captures| {
+        let full = caps.get(0).map_or("", |m| m.as_str());
+        let alt = caps.get(1).map_or("", |m| m.as_str());
+        let inner = caps.get(2).map_or("", |m| m.as_str());
+        match resolve_remote_image_destination(inner, base) {
+            Some(resolved) => format!("![{alt}]({resolved})"),
+            None => full.to_string(),
+        }
+    });
+    let html = HTML_IMAGE_SRC_ATTR_REGEX.replace_all(&markdown, |caps: &regex::Captures| {
+        let prefix = caps.get(1).map_or("", |m| m.as_str());
+        let src = caps.get(2).map_or("", |m| m.as_str());
+        let resolved = is_local_image_destination(src)
+            .then(|| base.join(src.trim()).ok())
+            .flatten();
+        match resolved {
+            Some(resolved) => format!("{prefix}{resolved}"),
+            None => format!("{prefix}{src}"),
+        }
+    });
+    (html != segment).then(|| html.into_owned())
+}
+
+/// `img/a.png "Title"` → `<https://host/docs/img/a.png> "Title"`.
+fn resolve_remote_image_destination(inner: &str, base: &reqwest::Url) -> Option<String> {
+    let rest = inner.trim();
+    let (destination, title) = match rest.strip_prefix('<') {
+        Some(wrapped) => {
+            let close = wrapped.find('>')?;
+            (&wrapped[..close], &wrapped[close + 1..])
+        }
+        None => rest.split_at(rest.find(char::is_whitespace).unwrap_or(rest.len())),
+    };
+    if !is_local_image_destination(destination) || !is_image_destination_title_tail(title) {
+        return None;
     }
+    let resolved = base.join(destination.trim()).ok()?;
+    Some(format!("<{resolved}>{title}"))
 }
 
 fn normalize_image_destinations_in_segment(segment: &str) -> Cow<'_, str> {
@@ -518,14 +597,14 @@ fn local_asset_route_from_url(raw_url: &str, ctx: &MarkdownAssetContext) -> Opti
 }
 
 fn rewrite_local_asset_url(raw_url: &str, ctx: &MarkdownAssetContext) -> Option<String> {
-    if ctx.workspace_id.is_empty() {
+    if ctx.workspace_url.is_empty() {
         return None;
     }
     let route = local_asset_route_from_url(raw_url, ctx)?;
     let encoded_route = encode_route_path(&route);
     let suffix_start = raw_url.find(['#', '?']).unwrap_or(raw_url.len());
     let suffix = &raw_url[suffix_start..];
-    Some(format!("/{}/{encoded_route}{suffix}", ctx.workspace_id))
+    Some(format!("{}{encoded_route}{suffix}", ctx.workspace_url))
 }
 
 fn path_to_route(path: &Path) -> String {
@@ -543,27 +622,34 @@ fn encode_route_path(path: &str) -> String {
 /// fence-warning banner so the two copies can't drift apart.
 const OCTICON_ALERT_SVG: &str = r#"<svg class="octicon octicon-alert mr-2" viewBox="0 0 16 16" version="1.1" width="16" height="16" aria-hidden="true"><path d="M6.457 1.047c.659-1.234 2.427-1.234 3.086 0l6.082 11.378A1.75 1.75 0 0 1 14.082 15H1.918a1.75 1.75 0 0 1-1.543-2.575Zm1.763.707a.25.25 0 0 0-.44 0L1.698 13.132a.25.25 0 0 0 .22.368h12.164a.25.25 0 0 0 .22-.368Zm.53 3.996v2.5a.75.75 0 0 1-1.5 0v-2.5a.75.75 0 0 1 1.5 0ZM9 11a1 1 0 1 1-2 0 1 1 0 0 1 2 0Z"></path></svg>"#;
 
+/// A heading of a rendered document, in document order.
 #[derive(Debug, Clone, serde::Serialize)]
-pub(crate) struct TocItem {
+pub struct TocItem {
+    /// Heading level, 1 to 6.
     pub level: u8,
+    /// The `id` of the heading element, unique within the document.
     pub id: String,
     pub text: String,
 }
 
+/// A problem found in a document's source, such as an unclosed code fence.
 #[derive(Debug, Clone, serde::Serialize)]
-pub(crate) struct MarkdownDiagnostic {
+pub struct MarkdownDiagnostic {
     pub code: String,
     pub severity: String,
     pub message: String,
     pub line: Option<usize>,
 }
 
+/// A document rendered by [`MarkdownRenderer::render_document`].
 #[derive(Debug, Clone)]
-pub(crate) struct MarkdownRenderOutput {
+pub struct MarkdownRenderOutput {
+    /// The body HTML, what the server puts inside a page's `<article>`.
     pub html: String,
     pub has_mermaid: bool,
     pub has_math: bool,
     pub toc: Vec<TocItem>,
+    /// Local files the document links to, as written.
     pub referenced_assets: std::collections::HashSet<String>,
     pub diagnostics: Vec<MarkdownDiagnostic>,
 }
@@ -734,8 +820,17 @@ pub(crate) fn highlight_source_file(token: &str, code: &str) -> String {
     highlight_code_to_classed_html(syntax, ss, code)
 }
 
-pub(crate) struct MarkdownRenderer {
+/// The GitHub-flavored renderer behind every page the server shows:
+/// alerts, footnotes, task lists, math and diagram fences, syntax
+/// highlighting with `mk-` classes, and heading ids for the TOC.
+pub struct MarkdownRenderer {
     asset_context: Option<MarkdownAssetContext>,
+    /// Note mode (see [`render_note_html`]): raw HTML is escaped, images
+    /// keep only their alt text, and nothing emits ids, heading sections, or
+    /// diagrams that could clash with the host document.
+    restricted: bool,
+    /// See [`crate::render_hook`]; not consulted in note mode.
+    hooks: Vec<std::sync::Arc<dyn crate::render_hook::RenderHook>>,
 }
 
 impl MarkdownRenderer {
@@ -743,26 +838,39 @@ impl MarkdownRenderer {
     /// highlighting: code is emitted as CSS classes (see
     /// `highlight_code_to_classed_html`) and coloured by the `--markon-code-*`
     /// design tokens, which switch with the page's `data-theme`.
-    pub(crate) fn new(_theme: &str) -> Self {
+    pub fn new(_theme: &str) -> Self {
         Self {
             asset_context: None,
+            restricted: false,
+            hooks: crate::render_hook::registered_hooks(),
         }
     }
 
+    /// Run `hook` after the hooks this renderer already has.
+    pub fn with_hook(mut self, hook: std::sync::Arc<dyn crate::render_hook::RenderHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
     pub(crate) fn with_asset_context(
         mut self,
-        workspace_id: impl Into<String>,
+        workspace_url: impl Into<String>,
         file_path: impl Into<PathBuf>,
         workspace_root: impl Into<PathBuf>,
     ) -> Self {
         self.asset_context = Some(MarkdownAssetContext::new(
-            workspace_id,
+            workspace_url,
             file_path,
             workspace_root,
         ));
         self
     }
 
+    /// Render `markdown` to HTML, with its headings and diagnostics.
+    pub fn render_document(&self, markdown: &str) -> MarkdownRenderOutput {
+        MarkdownEngine::render(self, markdown)
+    }
+
     #[cfg(test)]
     pub(crate) fn render(&self, markdown: &str) -> (String, bool, Vec<TocItem>) {
         let output = MarkdownEngine::render(self, markdown);
@@ -776,6 +884,16 @@ impl MarkdownRenderer {
 
 impl MarkdownHtmlRenderer for MarkdownRenderer {
     fn render_html(&self, markdown: &str) -> MarkdownHtmlOutput {
+        let markdown = self
+            .hooks
+            .iter()
+            .fold(Cow::Borrowed(markdown), |markdown, hook| {
+                match hook.pre_parse(&markdown) {
+                    Cow::Borrowed(_) => markdown,
+                    Cow::Owned(rewritten) => Cow::Owned(rewritten),
+                }
+            });
+        let markdown = markdown.as_ref();
         let normalized = normalize_local_image_destinations(markdown);
         let ast = supramark_markdown::parse(normalized.as_ref());
         let mut html_output = String::new();
@@ -797,6 +915,10 @@ impl MarkdownHtmlRenderer for MarkdownRenderer {
         } else {
             format!("{warnings_html}{html_output}")
         };
+        let html_output = self
+            .hooks
+            .iter()
+            .fold(html_output, |html, hook| hook.post_html(html));
 
         MarkdownHtmlOutput {
             html: html_output,
@@ -1084,8 +1206,326 @@ impl MarkdownRenderer {
     }
 }
 
-pub(crate) fn default_markdown_engine(theme: &str) -> MarkdownRenderer {
-    MarkdownRenderer::new(theme)
+/// A top-level heading and the Markdown source up to the next top-level
+/// heading. The leading section has no heading when the document starts with
+/// body text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HeadingSection {
+    pub heading: Option<String>,
+    /// The same id the renderer assigns to the heading, so `#anchor` links
+    /// land on it.
+    pub anchor: Option<String>,
+    pub text: String,
+}
+
+/// Split `markdown` at its top-level headings. Headings nested in containers
+/// (blockquotes, list items) stay inside the enclosing section.
+pub(crate) fn heading_sections(markdown: &str) -> Vec<HeadingSection> {
+    use supramark_markdown::SupramarkNode;
+
+    let ast = supramark_markdown::parse(markdown);
+    let SupramarkNode::Root { children, .. } = &ast else {
+        return Vec::new();
+    };
+
+    let mut ctx = RenderContext::default();
+    let mut sections = Vec::new();
+    let mut current: Option<(String, String, usize)> = None;
+    let mut preamble_end = markdown.len();
+
+    for node in children {
+        let SupramarkNode::Heading {
+            children,
+            position: Some(position),
+            ..
+        } = node
+        else {
+            continue;
+        };
+        let start = position.start.byte_offset;
+        let text = heading_plain_text(children);
+        let id =
+            MarkdownRenderer::next_heading_id(&mut ctx, &MarkdownRenderer::generate_slug(&text));
+        match current.take() {
+            Some((heading, anchor, from)) => sections.push(HeadingSection {
+                heading: Some(heading),
+                anchor: Some(anchor),
+                text: markdown.get(from..start).unwrap_or_default().to_string(),
+            }),
+            None => preamble_end = start,
+        }
+        current = Some((text, id, start));
+    }
+
+    let preamble = markdown.get(..preamble_end).unwrap_or_default();
+    if !preamble.trim().is_empty() {
+        sections.insert(
+            0,
+            HeadingSection {
+                heading: None,
+                anchor: None,
+                text: preamble.to_string(),
+            },
+        );
+    }
+    if let Some((heading, anchor, from)) = current {
+        sections.push(HeadingSection {
+            heading: Some(heading),
+            anchor: Some(anchor),
+            text: markdown.get(from..).unwrap_or_default().to_string(),
+        });
+    }
+    sections
+}
+
+/// A top-level heading and the Markdown source its rendered
+/// `.heading-section` wraps: everything up to the next top-level heading of
+/// the same or a higher level, so a section includes its subsections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct HeadingSpan {
+    pub anchor: String,
+    pub level: u8,
+    pub text: String,
+}
+
+/// Every top-level heading of `markdown` with the source it covers, ids
+/// assigned as in [`heading_sections`].
+pub(crate) fn heading_spans(markdown: &str) -> Vec<HeadingSpan> {
+    use supramark_markdown::SupramarkNode;
+
+    let ast = supramark_markdown::parse(markdown);
+    let SupramarkNode::Root { children, .. } = &ast else {
+        return Vec::new();
+    };
+
+    let mut ctx = RenderContext::default();
+    let mut headings = Vec::new();
+    for node in children {
+        let SupramarkNode::Heading {
+            depth,
+            children,
+            position: Some(position),
+            ..
+        } = node
+        else {
+            continue;
+        };
+        let slug = MarkdownRenderer::generate_slug(&heading_plain_text(children));
+        let id = MarkdownRenderer::next_heading_id(&mut ctx, &slug);
+        headings.push((id, (*depth).clamp(1, 6), position.start.byte_offset));
+    }
+
+    headings
+        .iter()
+        .enumerate()
+        .map(|(index, (anchor, level, start))| {
+            let end = headings[index + 1..]
+                .iter()
+                .find(|(_, next_level, _)| next_level <= level)
+                .map_or(markdown.len(), |(_, _, next_start)| *next_start);
+            HeadingSpan {
+                anchor: anchor.clone(),
+                level: *level,
+                text: markdown.get(*start..end).unwrap_or_default().to_string(),
+            }
+        })
+        .collect()
+}
+
+/// One page of a rendered document split by [`paginate_html`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DocumentPage {
+    pub html: String,
+    /// Text of the heading the page starts at; the first page takes the
+    /// document's first heading.
+    pub title: Option<String>,
+    /// Every `id` on the page, so `#anchor` links elsewhere can find it.
+    pub anchors: Vec<String>,
+}
+
+/// Split rendered `html` into pages of roughly `target_bytes`, breaking only
+/// before headings of the shallowest level that occurs at least twice (a lone
+/// title `#` leaves the `##` chapters to split on). `.heading-section`
+/// wrappers still open at a break are closed at the end of the page and
+/// reopened at the start of the next, so every page is balanced and keeps the
+/// nesting the styles and section tracking expect.
+///
+/// `None` when the document fits in one page, has nothing to split on, or a
+/// heading from `toc` can't be found in `html`.
+pub(crate) fn paginate_html(
+    html: &str,
+    toc: &[TocItem],
+    target_bytes: usize,
+) -> Option<Vec<DocumentPage>> {
+    if html.len() <= target_bytes {
+        return None;
+    }
+    let split_level =
+        (1..=6).find(|level| toc.iter().filter(|item| item.level == *level).count() >= 2)?;
+
+    // Where each split-level heading's wrapper starts, with the levels of the
+    // wrappers enclosing it there.
+    let mut breaks: Vec<(usize, Vec<u8>, &str)> = Vec::new();
+    let mut open: Vec<u8> = Vec::new();
+    let mut cursor = 0;
+    for item in toc {
+        let mut marker = format!(
+            "<div class=\"heading-section\" data-level=\"{0}\"><h{0} id=\"",
+            item.level
+        );
+        html_escape::encode_double_quoted_attribute_to_string(&item.id, &mut marker);
+        marker.push('"');
+        let at = cursor + html[cursor..].find(&marker)?;
+        while open.last().is_some_and(|level| *level >= item.level) {
+            open.pop();
+        }
+        if item.level == split_level {
+            breaks.push((at, open.clone(), item.text.as_str()));
+        }
+        open.push(item.level);
+        cursor = at + marker.len();
+    }
+
+    let reopen = |levels: &[u8]| -> String {
+        levels
+            .iter()
+            .map(|level| format!("<div class=\"heading-section\" data-level=\"{level}\">"))
+            .collect()
+    };
+    let mut pages = Vec::new();
+    let mut start = 0;
+    let mut prefix = String::new();
+    let mut title = toc.first().map(|item| item.text.clone());
+    for (at, enclosing, text) in breaks {
+        if at - start < target_bytes {
+            continue;
+        }
+        let mut page = std::mem::replace(&mut prefix, reopen(&enclosing));
+        page.push_str(&html[start..at]);
+        page.push_str(&"</div>".repeat(enclosing.len()));
+        pages.push(document_page(page, title.replace(text.to_string())));
+        start = at;
+    }
+    if pages.is_empty() {
+        return None;
+    }
+    prefix.push_str(&html[start..]);
+    pages.push(document_page(prefix, title));
+    Some(pages)
+}
+
+fn document_page(html: String, title: Option<String>) -> DocumentPage {
+    let mut anchors = Vec::new();
+    let mut rest = html.as_str();
+    while let Some(at) = rest.find(" id=\"") {
+        rest = &rest[at + 5..];
+        let Some(end) = rest.find('"') else {
+            break;
+        };
+        anchors.push(html_escape::decode_html_entities(&rest[..end]).into_owned());
+        rest = &rest[end..];
+    }
+    DocumentPage {
+        html,
+        title,
+        anchors,
+    }
+}
+
+/// File extensions served as markdown when `--markdown-ext` is not given.
+pub const DEFAULT_MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "mkd", "mkdn"];
+
+/// Lowercase extensions treated as markdown for rendering, listings, the
+/// file watcher and the search index (`--markdown-ext`). Each workspace
+/// keeps the set of the server it was registered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MarkdownExtensions(std::sync::Arc<[String]>);
+
+impl MarkdownExtensions {
+    /// The extensions to match, with or without a leading dot; an empty list
+    /// means [`DEFAULT_MARKDOWN_EXTENSIONS`].
+    pub fn new(extensions: &[String]) -> Self {
+        Self(normalize_markdown_extensions(extensions).into())
+    }
+
+    /// Whether `path` has one of the extensions (case-insensitive).
+    pub fn matches(&self, path: &Path) -> bool {
+        let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+            return false;
+        };
+        self.0.iter().any(|known| known.eq_ignore_ascii_case(ext))
+    }
+}
+
+impl Default for MarkdownExtensions {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+
+/// Lowercase `extensions` with any leading dot removed; empty falls back to
+/// [`DEFAULT_MARKDOWN_EXTENSIONS`].
+fn normalize_markdown_extensions(extensions: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for ext in extensions {
+        let ext = ext.trim().trim_start_matches('.').to_ascii_lowercase();
+        if !ext.is_empty() && !normalized.contains(&ext) {
+            normalized.push(ext);
+        }
+    }
+    if normalized.is_empty() {
+        normalized = DEFAULT_MARKDOWN_EXTENSIONS
+            .iter()
+            .map(|ext| ext.to_string())
+            .collect();
+    }
+    normalized
+}
+
+/// Text of the first heading line (`# Title`): a document title that needs
+/// no parse, for listings and search results.
+pub(crate) fn first_heading_text(content: &str) -> Option<String> {
+    content
+        .lines()
+        .find(|line| line.starts_with('#'))
+        .map(|line| line.trim_start_matches('#').trim().to_string())
+}
+
+/// Title a document presents itself with: its frontmatter `title:`, else its
+/// first heading.
+pub(crate) fn document_title(content: &str) -> Option<String> {
+    crate::frontmatter::parse(content)
+        .and_then(|front| front.title)
+        .or_else(|| first_heading_text(crate::frontmatter::strip(content)))
+        .filter(|title| !title.is_empty())
+}
+
+/// A renderer with `configured` (a server's built-in hooks, see
+/// [`crate::render_hook`]) run before the registered ones.
+pub(crate) fn default_markdown_engine(
+    theme: &str,
+    configured: &[std::sync::Arc<dyn crate::render_hook::RenderHook>],
+) -> MarkdownRenderer {
+    let mut renderer = MarkdownRenderer::new(theme);
+    renderer.hooks.splice(0..0, configured.iter().cloned());
+    renderer
+}
+
+/// Render an annotation note for display beside the document. Notes come
+/// from any collaborator, so this is the restricted pass: inline markdown,
+/// lists, quotes, tables and code blocks render as usual, but raw HTML is
+/// shown as text and images, headings, footnotes and diagrams are reduced
+/// to plain content.
+pub(crate) fn render_note_html(note: &str) -> String {
+    let renderer = MarkdownRenderer {
+        asset_context: None,
+        restricted: true,
+        hooks: Vec::new(),
+    };
+    let mut out = String::new();
+    let mut ctx = RenderContext::default();
+    renderer.render_node(&supramark_markdown::parse(note), &mut out, &mut ctx);
+    out
 }
 
 impl MarkdownRenderer {
@@ -1111,6 +1551,28 @@ impl MarkdownRenderer {
         use supramark_markdown::SupramarkNode;
         match node {
             SupramarkNode::Root { children, .. } => self.render_nodes(children, out, ctx),
+            SupramarkNode::Heading { children, .. } if self.restricted => {
+                out.push_str("<p><strong>");
+                self.render_nodes(children, out, ctx);
+                out.push_str("</strong></p>\n");
+            }
+            SupramarkNode::Image { alt, .. } if self.restricted => {
+                html_escape::encode_text_to_string(alt, out);
+            }
+            SupramarkNode::Raw { value, .. } if self.restricted => {
+                html_escape::encode_text_to_string(value, out);
+            }
+            SupramarkNode::Diagram { code, .. } if self.restricted => {
+                out.push_str("<pre><code>");
+                html_escape::encode_text_to_string(code, out);
+                out.push_str("</code></pre>");
+            }
+            SupramarkNode::FootnoteDefinition { children, .. } if self.restricted => {
+                self.render_nodes(children, out, ctx);
+            }
+            SupramarkNode::FootnoteReference { index, .. } if self.restricted => {
+                out.push_str(&format!("<sup>{index}</sup>"));
+            }
             SupramarkNode::Paragraph { children, .. } => {
                 out.push_str("<p>");
                 self.render_nodes(children, out, ctx);
@@ -1163,6 +1625,12 @@ impl MarkdownRenderer {
                 children,
                 ..
             } => {
+                let hooked_url = if self.restricted {
+                    None
+                } else {
+                    self.hooks.iter().find_map(|hook| hook.link_url(url))
+                };
+                let url = hooked_url.as_ref().unwrap_or(url);
                 // Drop the href for unsafe schemes (javascript:, data:, …) so a
                 // `[text](javascript:…)` link renders as inert text, not a click
                 // that executes script.
@@ -1175,6 +1643,9 @@ impl MarkdownRenderer {
                         html_escape::encode_double_quoted_attribute_to_string(title, out);
                         out.push('"');
                     }
+                    if self.restricted {
+                        out.push_str(" target=\"_blank\" rel=\"noopener noreferrer\"");
+                    }
                     out.push('>');
                 } else {
                     out.push_str("<a>");
@@ -1214,9 +1685,17 @@ impl MarkdownRenderer {
                 out.push_str("</del>");
             }
             SupramarkNode::Code { value, lang, .. } => {
+                if let (false, Some(info)) = (self.restricted, lang.as_deref()) {
+                    if let Some(html) = self.hooks.iter().find_map(|hook| hook.fence(info, value)) {
+                        out.push_str(&html);
+                        return;
+                    }
+                }
                 if let Some(engine) = code_fence_diagram_engine(lang.as_deref()) {
-                    self.render_diagram(engine, value, out);
-                    return;
+                    if !self.restricted {
+                        self.render_diagram(engine, value, out);
+                        return;
+                    }
                 }
 
                 let syntax = resolve_syntax(&SYNTAX_SET, lang.as_deref().unwrap_or(""));
@@ -1482,6 +1961,21 @@ impl MarkdownRenderer {
 
     fn render_diagram(&self, engine: &str, code: &str, out: &mut String) {
         let engine_id = engine.trim().to_ascii_lowercase();
+        let spec = match chart_spec_json(&engine_id, code) {
+            Ok(spec) => spec,
+            Err(message) => {
+                self.render_source_fallback_with_message(
+                    "Diagram render failed",
+                    engine,
+                    Some(engine),
+                    code,
+                    Some(&message),
+                    out,
+                );
+                return;
+            }
+        };
+        let code = spec.as_ref();
         let result = {
             let _guard = DIAGRAM_RENDER_LOCK
                 .lock()
@@ -1564,6 +2058,12 @@ impl MarkdownRenderer {
     }
 
     fn render_text(&self, out: &mut String, text: &str) {
+        if !self.restricted {
+            if let Some(html) = self.hooks.iter().find_map(|hook| hook.text(text)) {
+                out.push_str(&html);
+                return;
+            }
+        }
         let text = self.replace_emoji_shortcodes(text);
         html_escape::encode_text_to_string(&text, out);
     }
@@ -1692,6 +2192,40 @@ fn code_fence_diagram_engine(lang: Option<&str>) -> Option<&'static str> {
     }
 }
 
+/// Engines whose fence holds a JSON spec (Vega, Vega-Lite, ECharts, Chart.js,
+/// Plotly). Their specs may also be written in YAML.
+const JSON_SPEC_ENGINES: &[&str] = &[
+    "vega-lite",
+    "vega",
+    "echarts",
+    "chart",
+    "chartjs",
+    "chart.js",
+    "plotly",
+];
+
+/// The spec of a chart fence as the engine takes it: JSON as written, or a
+/// YAML spec converted to JSON. Other engines' code passes through. A spec
+/// that is neither JSON nor a YAML mapping is reported without reaching the
+/// engine.
+fn chart_spec_json<'a>(engine: &str, code: &'a str) -> Result<Cow<'a, str>, String> {
+    if !JSON_SPEC_ENGINES.contains(&engine) {
+        return Ok(Cow::Borrowed(code));
+    }
+    let trimmed = code.trim_start();
+    // JSON (or broken JSON, which the engine reports best).
+    if trimmed.starts_with(['{', '[']) || serde_json::from_str::<serde_json::Value>(code).is_ok() {
+        return Ok(Cow::Borrowed(code));
+    }
+    match serde_yml::from_str::<serde_yml::Value>(code) {
+        Ok(value @ serde_yml::Value::Mapping(_)) => {
+            Ok(Cow::Owned(crate::openapi::yaml_to_json(value).to_string()))
+        }
+        Ok(_) => Err(format!("{engine}: the spec must be a JSON or YAML object")),
+        Err(e) => Err(format!("{engine}: the spec is neither JSON nor YAML: {e}")),
+    }
+}
+
 fn heading_plain_text(nodes: &[supramark_markdown::SupramarkNode]) -> String {
     let mut out = String::new();
     for node in nodes {
@@ -2247,10 +2781,12 @@ fn supramark_children(
 mod assets_tests {
     use super::MarkdownRenderer;
     use super::{
-        extract_referenced_assets, normalize_local_image_destinations, sanitize_asset_ref,
-        sanitize_raw_html_fragment, url_scheme_is_safe,
+        chart_spec_json, extract_referenced_assets, heading_sections, heading_spans,
+        normalize_local_image_destinations, paginate_html, render_note_html, resolve_remote_images,
+        sanitize_asset_ref, sanitize_raw_html_fragment, url_scheme_is_safe,
     };
     use crate::markdown::MarkdownEngine;
+    use std::borrow::Cow;
 
     fn assert_set(actual: std::collections::HashSet<String>, expected: &[&str]) {
         let want: std::collections::HashSet<String> =
@@ -2454,6 +2990,53 @@ mod assets_tests {
         assert_eq!(sanitize_raw_html_fragment("a < b"), "a &lt; b");
     }
 
+    #[test]
+    fn note_html_renders_markdown_without_raw_html_or_ids() {
+        let html = render_note_html(
+            "# Why\nUse `retry()` per [docs](https://example.com).\n\n- one\n- two\n\n<script>alert(1)</script> ![pixel](https://example.com/p.png)\n\n[bad](javascript:alert(1))",
+        );
+        assert!(html.contains("<p><strong>Why</strong></p>"), "{html}");
+        assert!(html.contains("<code>retry()</code>"), "{html}");
+        assert!(
+            html.contains(r#"<a href="https://example.com" target="_blank" rel="noopener noreferrer">docs</a>"#),
+            "{html}"
+        );
+        assert!(html.contains("<ul>\n<li>"), "{html}");
+        assert!(html.contains("&lt;script&gt;"), "{html}");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("<img"), "{html}");
+        assert!(!html.contains("id=\""), "{html}");
+        assert!(!html.contains("href=\"javascript:"), "{html}");
+
+        // Whatever the spelling, no href may resolve to a script URL.
+        for bad in [
+            "[x](JaVaScRiPt:alert(1))",
+            "[x](jav&#x61;script:alert(1))",
+            "[x](&#106;avascript:alert(1))",
+            "[x]( javascript:alert(1))",
+            "[x](<\tjavascript:alert(1)>)",
+            "<JAVASCRIPT:alert(1)>",
+            "<a href=\" JaVaScRiPt:alert(1)\">x</a>",
+        ] {
+            let html = render_note_html(bad);
+            for tag in html.split("<a ").skip(1) {
+                let tag = tag.split('>').next().unwrap_or_default();
+                let Some((_, href)) = tag.split_once("href=\"") else {
+                    continue;
+                };
+                let href = href.split('"').next().unwrap_or_default();
+                let decoded: String = html_escape::decode_html_entities(href)
+                    .chars()
+                    .filter(|c| !c.is_whitespace() && !c.is_control())
+                    .collect();
+                assert!(
+                    !decoded.to_ascii_lowercase().starts_with("javascript:"),
+                    "{bad} -> {html}"
+                );
+            }
+        }
+    }
+
     #[test]
     fn windows_absolute_image_path_normalizes_markdown_escapes() {
         let normalized = normalize_local_image_destinations(
@@ -2496,6 +3079,28 @@ mod assets_tests {
         assert!(html.contains("pic with space.png"), "html: {html}");
     }
 
+    #[test]
+    fn remote_images_resolve_against_the_document_url_outside_code() {
+        let base = reqwest::Url::parse("https://host/repo/main/docs/guide.md").unwrap();
+        let md = "![logo](img/logo.png \"Logo\") ![up](../banner.svg)\n\
+                  <img width=\"80\" src=\"./shot.png\">\n\
+                  ![badge](https://ci.example.com/badge.svg) ![top](#top)\n\
+                  `![code](img/code.png)`\n\
+                  ```\n![fenced](img/fenced.png)\n```\n";
+        let resolved = resolve_remote_images(md, &base);
+        assert!(resolved.contains(r#"![logo](<https://host/repo/main/docs/img/logo.png> "Logo")"#));
+        assert!(resolved.contains("![up](<https://host/repo/main/banner.svg>)"));
+        assert!(resolved.contains(r#"src="https://host/repo/main/docs/shot.png""#));
+        assert!(resolved.contains("![badge](https://ci.example.com/badge.svg)"));
+        assert!(resolved.contains("![top](#top)"));
+        assert!(resolved.contains("`![code](img/code.png)`"));
+        assert!(resolved.contains("![fenced](img/fenced.png)"));
+        assert!(matches!(
+            resolve_remote_images("no images\n", &base),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+
     #[test]
     fn workspace_absolute_image_path_is_rewritten() {
         let dir = tempfile::tempdir().unwrap();
@@ -2506,7 +3111,7 @@ mod assets_tests {
         let doc = root.join("note.md");
         std::fs::write(&doc, "# note").unwrap();
 
-        let renderer = MarkdownRenderer::new("light").with_asset_context("wsid", &doc, &root);
+        let renderer = MarkdownRenderer::new("light").with_asset_context("/wsid/", &doc, &root);
         let md = format!("![alt](<{}>)", image.to_string_lossy());
         let output = MarkdownEngine::render(&renderer, &md);
 
@@ -2531,7 +3136,7 @@ mod assets_tests {
         let doc = root.join("note.md");
         std::fs::write(&doc, "# note").unwrap();
 
-        let renderer = MarkdownRenderer::new("light").with_asset_context("wsid", &doc, &root);
+        let renderer = MarkdownRenderer::new("light").with_asset_context("/wsid/", &doc, &root);
         let output = MarkdownEngine::render(&renderer, "![alt](/assets/pic.png)");
 
         assert!(
@@ -2553,7 +3158,7 @@ mod assets_tests {
         let doc = root.join("note.md");
         std::fs::write(&doc, "# note").unwrap();
 
-        let renderer = MarkdownRenderer::new("light").with_asset_context("wsid", &doc, &root);
+        let renderer = MarkdownRenderer::new("light").with_asset_context("/wsid/", &doc, &root);
         let md = format!("![alt]({})", outside.path().to_string_lossy());
         let output = MarkdownEngine::render(&renderer, &md);
 
@@ -2920,6 +3525,63 @@ mod assets_tests {
         );
     }
 
+    #[test]
+    fn supramark_renderer_renders_yaml_chart_specs() {
+        let renderer = MarkdownRenderer::new("light");
+        let output = super::MarkdownEngine::render(
+            &renderer,
+            r#"```vega-lite
+data:
+  values:
+    - {area: Rendering, score: 35}
+    - {area: Search, score: 20}
+mark: bar
+encoding:
+  x: {field: area, type: nominal}
+  y: {field: score, type: quantitative}
+```
+
+```chart
+- just a list
+```
+"#,
+        );
+
+        assert!(
+            output
+                .html
+                .contains("class=\"markon-diagram markon-diagram-vega-lite\""),
+            "html: {}",
+            output.html
+        );
+        assert!(output.html.contains("<svg"), "html: {}", output.html);
+        assert!(
+            output
+                .html
+                .contains("chart: the spec must be a JSON or YAML object"),
+            "html: {}",
+            output.html
+        );
+    }
+
+    #[test]
+    fn chart_specs_pass_json_and_other_engines_through() {
+        let json = r#"{"mark": "bar"}"#;
+        assert!(matches!(
+            chart_spec_json("vega-lite", json),
+            Ok(Cow::Borrowed(spec)) if spec == json
+        ));
+        assert!(matches!(
+            chart_spec_json("mermaid", "graph TD\n  a: b"),
+            Ok(Cow::Borrowed(_))
+        ));
+        assert_eq!(
+            chart_spec_json("echarts", "series:\n  - type: pie\n    data: [1, 2]\n").unwrap(),
+            r#"{"series":[{"data":[1,2],"type":"pie"}]}"#
+        );
+        assert!(chart_spec_json("vega", "mark: [unclosed").is_err());
+    }
+
     #[test]
     fn supramark_renderer_renders_vega_lite_diagram() {
         let renderer = MarkdownRenderer::new("light");
@@ -3332,6 +3994,86 @@ mod assets_tests {
         );
     }
 
+    #[test]
+    fn heading_sections_split_source_and_match_renderer_ids() {
+        let md =
+            "Intro text.\n\n# Same\nOne.\n\n## Same\nTwo.\n\n> # Quoted\n\n# Step 3!\nThree.\n";
+        let sections = heading_sections(md);
+        let toc = MarkdownRenderer::new("light").render(md).2;
+
+        assert_eq!(
+            sections
+                .iter()
+                .map(|s| (s.heading.as_deref(), s.anchor.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                (None, None),
+                (Some("Same"), Some("same")),
+                (Some("Same"), Some("same-1")),
+                (Some("Step 3!"), Some("step-3-")),
+            ]
+        );
+        assert_eq!(sections[0].text, "Intro text.\n\n");
+        assert!(sections[2].text.contains("> # Quoted"));
+        assert_eq!(
+            sections.iter().map(|s| s.text.as_str()).collect::<String>(),
+            md
+        );
+        for section in &sections[1..] {
+            assert!(toc
+                .iter()
+                .any(|item| Some(item.id.as_str()) == section.anchor.as_deref()));
+        }
+    }
+
+    #[test]
+    fn heading_spans_cover_subsections_up_to_the_next_peer() {
+        let md = "# Top\n\n## Setup\nA.\n\n### Detail\nB.\n\n## Setup\nC.\n";
+        let spans = heading_spans(md);
+        assert_eq!(
+            spans
+                .iter()
+                .map(|s| (s.anchor.as_str(), s.level))
+                .collect::<Vec<_>>(),
+            vec![("top", 1), ("setup", 2), ("detail", 3), ("setup-1", 2)]
+        );
+        assert_eq!(spans[0].text, md);
+        assert_eq!(spans[1].text, "## Setup\nA.\n\n### Detail\nB.\n\n");
+        assert_eq!(spans[2].text, "### Detail\nB.\n\n");
+        assert_eq!(spans[3].text, "## Setup\nC.\n");
+    }
+
+    #[test]
+    fn paginate_html_breaks_before_chapters_and_rebalances_sections() {
+        let md = "# Spec\nIntro.\n\n## One\nA[^n].\n\n### Deep\nB.\n\n## Two\nC.\n\n## Three\nD.\n\n[^n]: Note.\n";
+        let renderer = MarkdownRenderer::new("light");
+        let output = super::MarkdownEngine::render(&renderer, md);
+        assert!(paginate_html(&output.html, &output.toc, output.html.len()).is_none());
+
+        let pages = paginate_html(&output.html, &output.toc, 1).unwrap();
+        assert_eq!(
+            pages
+                .iter()
+                .map(|page| page.title.as_deref())
+                .collect::<Vec<_>>(),
+            vec![Some("Spec"), Some("One"), Some("Two"), Some("Three")]
+        );
+        for page in &pages {
+            assert_eq!(
+                page.html.matches("<div").count(),
+                page.html.matches("</div>").count(),
+                "unbalanced page: {}",
+                page.html
+            );
+        }
+        // The `#` wrapper is reopened around later chapters.
+        assert!(pages[2]
+            .html
+            .starts_with("<div class=\"heading-section\" data-level=\"1\"><div class=\"heading-section\" data-level=\"2\"><h2 id=\"two\""));
+        assert!(pages[1].anchors.contains(&"deep".to_string()));
+        assert!(pages[3].anchors.iter().any(|id| id.starts_with("fn")));
+    }
+
     #[test]
     fn supramark_renderer_builds_github_alerts_from_ast() {
         let renderer = MarkdownRenderer::new("light");
@@ -3371,7 +4113,7 @@ mod assets_tests {
 
     #[test]
     fn default_engine_is_supramark() {
-        let renderer = super::default_markdown_engine("light");
+        let renderer = super::default_markdown_engine("light", &[]);
         let output = super::MarkdownEngine::render(&renderer, ":::map\ncenter: [0, 0]\n");
 
         assert!(
@@ -3383,4 +4125,44 @@ mod assets_tests {
             output.diagnostics
         );
     }
+
+    #[test]
+    fn document_title_prefers_frontmatter_over_the_first_heading() {
+        assert_eq!(
+            super::document_title("---\ntitle: Standup notes\n---\n# Monday\n").as_deref(),
+            Some("Standup notes")
+        );
+        assert_eq!(
+            super::document_title("---\ntags: [a]\n---\n# Monday\n").as_deref(),
+            Some("Monday")
+        );
+        assert_eq!(super::document_title("no heading\n"), None);
+    }
+
+    #[test]
+    fn markdown_extensions_normalize_and_fall_back_to_defaults() {
+        let set = super::normalize_markdown_extensions(&[
+            ".TXT".into(),
+            " md ".into(),
+            "txt".into(),
+            String::new(),
+        ]);
+        assert_eq!(set, vec!["txt".to_string(), "md".to_string()]);
+        assert_eq!(
+            super::normalize_markdown_extensions(&[]),
+            super::DEFAULT_MARKDOWN_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect::<Vec<_>>()
+        );
+        let defaults = super::MarkdownExtensions::default();
+        for name in ["a.md", "b.Markdown", "c.mdown", "d.MKD"] {
+            assert!(defaults.matches(std::path::Path::new(name)), "{name}");
+        }
+        assert!(!defaults.matches(std::path::Path::new("notes.txt")));
+        assert!(!defaults.matches(std::path::Path::new("README")));
+        let text = super::MarkdownExtensions::new(&["txt".into()]);
+        assert!(text.matches(std::path::Path::new("notes.TXT")));
+        assert!(!text.matches(std::path::Path::new("a.md")));
+    }
 }
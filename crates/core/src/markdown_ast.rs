@@ -64,6 +64,62 @@ where
     summarize_document_impl(markdown, render_block)
 }
 
+/// Source line (1-based) of every heading, in document order — the order the
+/// renderer lists them in the TOC.
+pub(crate) fn heading_lines(markdown: &str) -> Vec<u32> {
+    fn walk(node: &supramark_markdown::SupramarkNode, lines: &mut Vec<u32>) {
+        if let supramark_markdown::SupramarkNode::Heading { position, .. } = node {
+            lines.push(position.as_ref().map_or(0, |p| p.start.line));
+        }
+        for child in children_of(node).unwrap_or_default() {
+            walk(child, lines);
+        }
+    }
+    let mut lines = Vec::new();
+    walk(&supramark_markdown::parse(markdown), &mut lines);
+    lines
+}
+
+/// Top-level blocks a presentation is cut at, by 1-based source line.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct SlideMarks {
+    /// `---` thematic breaks (not setext underlines or fenced code).
+    pub breaks: Vec<u32>,
+    /// Level-2 headings.
+    pub h2: Vec<u32>,
+    /// HTML comment blocks, as inclusive line ranges.
+    pub comments: Vec<(u32, u32)>,
+}
+
+pub(crate) fn slide_marks(markdown: &str) -> SlideMarks {
+    use supramark_markdown::SupramarkNode;
+
+    let mut marks = SlideMarks::default();
+    let ast = supramark_markdown::parse(markdown);
+    for node in children_of(&ast).unwrap_or_default() {
+        match node {
+            SupramarkNode::ThematicBreak {
+                position: Some(position),
+            } => marks.breaks.push(position.start.line),
+            SupramarkNode::Heading {
+                depth: 2,
+                position: Some(position),
+                ..
+            } => marks.h2.push(position.start.line),
+            SupramarkNode::Raw {
+                format,
+                value,
+                block: true,
+                position: Some(position),
+            } if format == "html" && value.trim_start().starts_with("<!--") => marks
+                .comments
+                .push((position.start.line, position.end.line)),
+            _ => {}
+        }
+    }
+    marks
+}
+
 fn engine_info_impl() -> MarkdownAstEngineInfo {
     MarkdownAstEngineInfo {
         name: "supramark-markdown",
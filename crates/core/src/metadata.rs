@@ -0,0 +1,274 @@
+//! Per-file document metadata shared by directory listings, regex search,
+//! reading progress and the page header.
+//!
+//! Titles and frontmatter tags come from the head of a file, so a listing never
+//! reads whole documents. The table of contents needs a render and is only
+//! filled in once something (reading progress) asked for it. An entry counts
+//! while the file's size and modification time are unchanged, and the
+//! workspace watcher drops the paths it sees change. Last commits are kept
+//! apart, keyed by workspace path and valid while `HEAD` stays put.
+
+use crate::git::{self, GitPathCommit};
+use crate::markdown::TocItem;
+use std::{
+    collections::HashMap,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// Bytes read from a markdown file to find its title and tags.
+const HEAD_SCAN_BYTES: u64 = 8 * 1024;
+
+/// Files [`MetadataCache`] remembers before it starts over.
+const METADATA_CACHE_LIMIT: usize = 10_000;
+
+/// What a document says about itself up front.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct DocumentMetadata {
+    /// Frontmatter `title:`, else the first heading.
+    pub title: Option<String>,
+    /// Normalized frontmatter tags.
+    pub tags: Vec<String>,
+}
+
+impl DocumentMetadata {
+    pub(crate) fn parse(content: &str) -> Self {
+        Self {
+            title: crate::markdown::document_title(content),
+            tags: crate::frontmatter::parse(content)
+                .map(|front| front.tags)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Parse the first [`HEAD_SCAN_BYTES`] of `path`; empty if unreadable.
+    fn read_head(path: &Path) -> Self {
+        let mut head = Vec::new();
+        let read =
+            fs::File::open(path).and_then(|file| file.take(HEAD_SCAN_BYTES).read_to_end(&mut head));
+        if read.is_err() {
+            return Self::default();
+        }
+        Self::parse(&String::from_utf8_lossy(&head))
+    }
+}
+
+/// Size and modification time an entry was read at.
+type Stamp = (u64, Option<SystemTime>);
+
+fn stamp(meta: &fs::Metadata) -> Stamp {
+    (meta.len(), meta.modified().ok())
+}
+
+struct Entry {
+    stamp: Stamp,
+    metadata: Arc<DocumentMetadata>,
+    toc: Option<Arc<Vec<TocItem>>>,
+}
+
+/// Last commit of each path asked about, as of one `HEAD`.
+#[derive(Default)]
+struct CommitCache {
+    head: String,
+    /// `None` for a path no commit touches (yet).
+    commits: HashMap<String, Option<GitPathCommit>>,
+}
+
+/// [`DocumentMetadata`] and rendered tables of contents keyed by canonical
+/// path. When full the cache is simply cleared, which costs one re-read per
+/// file asked for again.
+#[derive(Default)]
+pub(crate) struct MetadataCache {
+    entries: HashMap<PathBuf, Entry>,
+    commits: CommitCache,
+}
+
+impl MetadataCache {
+    /// Metadata of the file at `path`, read from its head on a miss. The file
+    /// is read outside the lock.
+    pub(crate) fn metadata(
+        cache: &Mutex<Self>,
+        path: &Path,
+        meta: &fs::Metadata,
+    ) -> Arc<DocumentMetadata> {
+        let stamp = stamp(meta);
+        if let Some(entry) = lock(cache).fresh(path, stamp) {
+            return entry.metadata.clone();
+        }
+        let metadata = Arc::new(DocumentMetadata::read_head(path));
+        lock(cache).insert(
+            path.to_path_buf(),
+            Entry {
+                stamp,
+                metadata: metadata.clone(),
+                toc: None,
+            },
+        );
+        metadata
+    }
+
+    /// The cached table of contents of `path`, if it was rendered unchanged.
+    pub(crate) fn toc(&self, path: &Path, meta: &fs::Metadata) -> Option<Arc<Vec<TocItem>>> {
+        self.fresh(path, stamp(meta))?.toc.clone()
+    }
+
+    /// Remember the table of contents rendered from `markdown`, the content of
+    /// `path` as of `meta`.
+    pub(crate) fn insert_toc(
+        &mut self,
+        path: &Path,
+        meta: &fs::Metadata,
+        markdown: &str,
+        toc: Arc<Vec<TocItem>>,
+    ) {
+        let stamp = stamp(meta);
+        let metadata = match self.fresh(path, stamp) {
+            Some(entry) => entry.metadata.clone(),
+            None => Arc::new(DocumentMetadata::parse(markdown)),
+        };
+        self.insert(
+            path.to_path_buf(),
+            Entry {
+                stamp,
+                metadata,
+                toc: Some(toc),
+            },
+        );
+    }
+
+    /// Last commit touching each of `rel_paths` (relative to `root`), for the
+    /// paths some commit touches. Answers hold until `HEAD` moves, so only a
+    /// commit, checkout or reset costs another `git log`; outside a repository
+    /// this is one `git rev-parse`.
+    pub(crate) fn last_commits(
+        cache: &Mutex<Self>,
+        root: &Path,
+        rel_paths: &[String],
+    ) -> HashMap<String, GitPathCommit> {
+        let Some(head) = git::head_commit(root) else {
+            return HashMap::new();
+        };
+        let mut found = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let mut guard = lock(cache);
+            if guard.commits.head != head {
+                guard.commits = CommitCache {
+                    head: head.clone(),
+                    commits: HashMap::new(),
+                };
+            }
+            for rel_path in rel_paths {
+                match guard.commits.commits.get(rel_path) {
+                    Some(Some(commit)) => {
+                        found.insert(rel_path.clone(), commit.clone());
+                    }
+                    Some(None) => {}
+                    None => missing.push(rel_path.clone()),
+                }
+            }
+        }
+        if missing.is_empty() {
+            return found;
+        }
+        let fetched = match git::last_commits_for_paths(root, &missing) {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                tracing::debug!("last commits for {} paths failed: {e}", missing.len());
+                return found;
+            }
+        };
+        let mut guard = lock(cache);
+        if guard.commits.head == head {
+            if guard.commits.commits.len() + missing.len() > METADATA_CACHE_LIMIT {
+                guard.commits.commits.clear();
+            }
+            for rel_path in missing {
+                let commit = fetched.get(&rel_path).cloned();
+                guard.commits.commits.insert(rel_path, commit);
+            }
+        }
+        drop(guard);
+        found.extend(fetched);
+        found
+    }
+
+    pub(crate) fn invalidate(&mut self, path: &Path) {
+        self.entries.remove(path);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn fresh(&self, path: &Path, stamp: Stamp) -> Option<&Entry> {
+        self.entries.get(path).filter(|entry| entry.stamp == stamp)
+    }
+
+    fn insert(&mut self, path: PathBuf, entry: Entry) {
+        if self.entries.len() >= METADATA_CACHE_LIMIT && !self.entries.contains_key(&path) {
+            self.entries.clear();
+        }
+        self.entries.insert(path, entry);
+    }
+}
+
+fn lock(cache: &Mutex<MetadataCache>) -> std::sync::MutexGuard<'_, MetadataCache> {
+    cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_miss_once_the_file_changes_or_is_invalidated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("standup.md");
+        fs::write(&path, "---\ntitle: Standup\ntags: [Daily]\n---\n## Notes\n").unwrap();
+        let cache = Mutex::new(MetadataCache::default());
+
+        let meta = fs::metadata(&path).unwrap();
+        let metadata = MetadataCache::metadata(&cache, &path, &meta);
+        assert_eq!(metadata.title.as_deref(), Some("Standup"));
+        assert_eq!(metadata.tags, ["daily"]);
+        assert!(cache.lock().unwrap().toc(&path, &meta).is_none());
+
+        let toc = Arc::new(vec![TocItem {
+            level: 2,
+            id: "notes".into(),
+            text: "Notes".into(),
+        }]);
+        cache
+            .lock()
+            .unwrap()
+            .insert_toc(&path, &meta, "", toc.clone());
+        assert_eq!(cache.lock().unwrap().toc(&path, &meta).unwrap().len(), 1);
+        // The head read before the render is kept.
+        assert_eq!(
+            MetadataCache::metadata(&cache, &path, &meta)
+                .title
+                .as_deref(),
+            Some("Standup")
+        );
+
+        fs::write(&path, "# Retro\n").unwrap();
+        let changed = fs::metadata(&path).unwrap();
+        assert!(cache.lock().unwrap().toc(&path, &changed).is_none());
+        assert_eq!(
+            MetadataCache::metadata(&cache, &path, &changed)
+                .title
+                .as_deref(),
+            Some("Retro")
+        );
+
+        cache.lock().unwrap().insert_toc(&path, &changed, "", toc);
+        cache.lock().unwrap().invalidate(&path);
+        assert!(cache.lock().unwrap().toc(&path, &changed).is_none());
+    }
+}
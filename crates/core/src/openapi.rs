@@ -0,0 +1,790 @@
+//! API reference pages for OpenAPI 3 and Swagger 2 specs found in a
+//! workspace (`openapi.yaml`, `swagger.json`, `petstore.openapi.yml`, …).
+//!
+//! The spec is rendered server-side into the document layout: operations
+//! grouped by tag as collapsible blocks with their parameters, request body
+//! and responses, then the named schemas, with `$ref`s linking between them.
+//! Descriptions go through the restricted note renderer, since a spec is
+//! data rather than a trusted page. A file that doesn't parse as a spec falls
+//! back to the plain source view.
+
+use crate::markdown::TocItem;
+use html_escape::{encode_double_quoted_attribute as attr, encode_text as text};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+/// HTTP methods an OpenAPI path item may hold, in display order.
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// How deep object properties are expanded before a schema is summarized
+/// by its type alone.
+const MAX_SCHEMA_DEPTH: usize = 3;
+
+/// A rendered spec.
+pub(crate) struct ApiReference {
+    pub(crate) title: String,
+    pub(crate) html: String,
+    pub(crate) toc: Vec<TocItem>,
+}
+
+/// Whether `path` is named like an API spec: `openapi` or `swagger`, alone
+/// or as the last part of the stem, with a YAML or JSON extension.
+pub(crate) fn is_spec_path(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    let name = name.to_ascii_lowercase();
+    let Some((stem, ext)) = name.rsplit_once('.') else {
+        return false;
+    };
+    matches!(ext, "yaml" | "yml" | "json")
+        && ["openapi", "swagger"].iter().any(|kind| {
+            stem == *kind
+                || stem
+                    .strip_suffix(kind)
+                    .is_some_and(|rest| rest.ends_with(['.', '-', '_']))
+        })
+}
+
+/// Render `content` as an API reference; `None` when it is not an OpenAPI 3
+/// or Swagger 2 document.
+pub(crate) fn render(content: &str) -> Option<ApiReference> {
+    let spec = parse(content)?;
+    let version = spec
+        .get("openapi")
+        .or_else(|| spec.get("swagger"))
+        .and_then(scalar)?;
+    if !(version.starts_with('3') || version.starts_with('2')) {
+        return None;
+    }
+    Some(Renderer::new(&spec).render())
+}
+
+fn parse(content: &str) -> Option<Value> {
+    if let Ok(value @ Value::Object(_)) = serde_json::from_str::<Value>(content) {
+        return Some(value);
+    }
+    let yaml: serde_yml::Value = serde_yml::from_str(content).ok()?;
+    match yaml_to_json(yaml) {
+        value @ Value::Object(_) => Some(value),
+        _ => None,
+    }
+}
+
+/// YAML allows non-string keys (`200:` for a response code); JSON-style
+/// lookups want them as strings. Chart fences written in YAML go through
+/// this too.
+pub(crate) fn yaml_to_json(value: serde_yml::Value) -> Value {
+    match value {
+        serde_yml::Value::Null => Value::Null,
+        serde_yml::Value::Bool(b) => Value::Bool(b),
+        serde_yml::Value::Number(n) => serde_json::from_str(&n.to_string()).unwrap_or(Value::Null),
+        serde_yml::Value::String(s) => Value::String(s),
+        serde_yml::Value::Sequence(items) => {
+            Value::Array(items.into_iter().map(yaml_to_json).collect())
+        }
+        serde_yml::Value::Mapping(mapping) => Value::Object(
+            mapping
+                .into_iter()
+                .filter_map(|(key, value)| Some((yaml_key(key)?, yaml_to_json(value))))
+                .collect(),
+        ),
+        serde_yml::Value::Tagged(tagged) => yaml_to_json(tagged.value),
+    }
+}
+
+fn yaml_key(key: serde_yml::Value) -> Option<String> {
+    match key {
+        serde_yml::Value::String(s) => Some(s),
+        serde_yml::Value::Number(n) => Some(n.to_string()),
+        serde_yml::Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// A string, number or boolean as text.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn str_of<'a>(value: &'a Value, key: &str) -> Option<&'a str> {
+    value.get(key).and_then(Value::as_str)
+}
+
+fn slug(value: &str) -> String {
+    let mut slug = String::new();
+    for c in value.chars() {
+        if c.is_alphanumeric() {
+            slug.extend(c.to_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_matches('-').to_string()
+}
+
+fn description_html(value: &Value) -> String {
+    match str_of(value, "description").or_else(|| str_of(value, "summary")) {
+        Some(description) if !description.trim().is_empty() => format!(
+            r#"<div class="openapi-description">{}</div>"#,
+            crate::markdown::render_note_html(description)
+        ),
+        _ => String::new(),
+    }
+}
+
+fn label(key: &str, fallback: &str) -> String {
+    format!(r#"<span data-i18n="{key}">{fallback}</span>"#)
+}
+
+struct Operation<'a> {
+    method: &'static str,
+    path: &'a str,
+    item: &'a Value,
+    operation: &'a Value,
+}
+
+struct Renderer<'a> {
+    spec: &'a Value,
+    /// Named schemas: `components.schemas` (OpenAPI 3) or `definitions`
+    /// (Swagger 2).
+    schemas: Option<&'a Map<String, Value>>,
+    ids: HashSet<String>,
+    toc: Vec<TocItem>,
+}
+
+impl<'a> Renderer<'a> {
+    fn new(spec: &'a Value) -> Self {
+        let schemas = spec
+            .pointer("/components/schemas")
+            .or_else(|| spec.get("definitions"))
+            .and_then(Value::as_object);
+        Self {
+            spec,
+            schemas,
+            ids: HashSet::new(),
+            toc: Vec::new(),
+        }
+    }
+
+    /// A unique heading id, listed in the TOC at `level`.
+    fn heading(&mut self, level: u8, base: &str, title: &str) -> String {
+        let base = if base.is_empty() { "section" } else { base };
+        let mut id = base.to_string();
+        let mut n = 1;
+        while !self.ids.insert(id.clone()) {
+            n += 1;
+            id = format!("{base}-{n}");
+        }
+        self.toc.push(TocItem {
+            level,
+            id: id.clone(),
+            text: title.to_string(),
+        });
+        id
+    }
+
+    fn render(mut self) -> ApiReference {
+        let info = self.spec.get("info").cloned().unwrap_or(Value::Null);
+        let title = str_of(&info, "title")
+            .unwrap_or("API reference")
+            .to_string();
+        let mut html = String::from(r#"<div class="openapi-reference">"#);
+        let _ = write!(html, "<h1>{}", text(&title));
+        if let Some(version) = info.get("version").and_then(scalar) {
+            let _ = write!(
+                html,
+                r#" <span class="openapi-version">{}</span>"#,
+                text(&version)
+            );
+        }
+        html.push_str("</h1>");
+        html.push_str(&description_html(&info));
+        self.render_servers(&mut html);
+
+        for (tag, operations) in self.operations_by_tag() {
+            let id = self.heading(1, &format!("tag-{}", slug(&tag)), &tag);
+            let _ = write!(html, r#"<h2 id="{}">{}</h2>"#, attr(&id), text(&tag));
+            if let Some(tag_info) = self.tag_info(&tag) {
+                html.push_str(&description_html(tag_info));
+            }
+            for operation in operations {
+                self.render_operation(&mut html, &operation);
+            }
+        }
+
+        if let Some(schemas) = self.schemas.filter(|schemas| !schemas.is_empty()) {
+            let id = self.heading(1, "schemas", "Schemas");
+            let _ = write!(
+                html,
+                r#"<h2 id="{}">{}</h2>"#,
+                attr(&id),
+                label("web.openapi.schemas", "Schemas")
+            );
+            for (name, schema) in schemas {
+                let id = format!("schema-{}", slug(name));
+                self.ids.insert(id.clone());
+                let _ = write!(
+                    html,
+                    r#"<details class="openapi-schema" id="{}"><summary><code>{}</code></summary>{}{}</details>"#,
+                    attr(&id),
+                    text(name),
+                    description_html(schema),
+                    self.schema_body(schema, 0),
+                );
+            }
+        }
+        html.push_str("</div>");
+        ApiReference {
+            title,
+            html,
+            toc: self.toc,
+        }
+    }
+
+    fn render_servers(&self, html: &mut String) {
+        let mut servers: Vec<String> = self
+            .spec
+            .get("servers")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|server| str_of(server, "url").map(str::to_string))
+            .collect();
+        if let Some(host) = str_of(self.spec, "host") {
+            let base = str_of(self.spec, "basePath").unwrap_or("");
+            let schemes: Vec<&str> = self
+                .spec
+                .get("schemes")
+                .and_then(Value::as_array)
+                .map(|schemes| schemes.iter().filter_map(Value::as_str).collect())
+                .unwrap_or_else(|| vec!["https"]);
+            servers.extend(
+                schemes
+                    .iter()
+                    .map(|scheme| format!("{scheme}://{host}{base}")),
+            );
+        }
+        if servers.is_empty() {
+            return;
+        }
+        let _ = write!(
+            html,
+            r#"<p class="openapi-servers">{}: "#,
+            label("web.openapi.servers", "Servers")
+        );
+        let servers: Vec<String> = servers
+            .iter()
+            .map(|server| format!("<code>{}</code>", text(server)))
+            .collect();
+        html.push_str(&servers.join(" "));
+        html.push_str("</p>");
+    }
+
+    fn tag_info(&self, name: &str) -> Option<&'a Value> {
+        self.spec
+            .get("tags")?
+            .as_array()?
+            .iter()
+            .find(|tag| str_of(tag, "name") == Some(name))
+    }
+
+    /// Operations grouped under their first tag: declared tags first, in
+    /// order, then tags as operations introduce them, untagged ones last.
+    fn operations_by_tag(&self) -> Vec<(String, Vec<Operation<'a>>)> {
+        let mut order: Vec<String> = self
+            .spec
+            .get("tags")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|tag| str_of(tag, "name").map(str::to_string))
+            .collect();
+        let mut groups: HashMap<String, Vec<Operation<'a>>> = HashMap::new();
+        let paths = self.spec.get("paths").and_then(Value::as_object);
+        for (path, item) in paths.into_iter().flatten() {
+            for method in METHODS {
+                let Some(operation) = item.get(method).filter(|op| op.is_object()) else {
+                    continue;
+                };
+                let tag = operation
+                    .get("tags")
+                    .and_then(Value::as_array)
+                    .and_then(|tags| tags.first())
+                    .and_then(Value::as_str)
+                    .unwrap_or("default")
+                    .to_string();
+                if !order.contains(&tag) && tag != "default" {
+                    order.push(tag.clone());
+                }
+                groups.entry(tag).or_default().push(Operation {
+                    method,
+                    path,
+                    item,
+                    operation,
+                });
+            }
+        }
+        order.push("default".to_string());
+        order
+            .into_iter()
+            .filter_map(|tag| groups.remove(&tag).map(|operations| (tag, operations)))
+            .collect()
+    }
+
+    fn render_operation(&mut self, html: &mut String, op: &Operation<'a>) {
+        let base = match str_of(op.operation, "operationId") {
+            Some(id) => format!("op-{}", slug(id)),
+            None => format!("op-{}-{}", op.method, slug(op.path)),
+        };
+        let summary = str_of(op.operation, "summary").unwrap_or_default();
+        let toc_text = if summary.is_empty() {
+            format!("{} {}", op.method.to_ascii_uppercase(), op.path)
+        } else {
+            summary.to_string()
+        };
+        let id = self.heading(2, &base, &toc_text);
+        let deprecated = op
+            .operation
+            .get("deprecated")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let _ = write!(
+            html,
+            r#"<details class="openapi-operation openapi-method-{method}{deprecated_class}" id="{id}"><summary><span class="openapi-method">{upper}</span> <code class="openapi-path">{path}</code> <span class="openapi-summary">{summary}</span>{deprecated_badge}</summary>"#,
+            method = op.method,
+            deprecated_class = if deprecated {
+                " openapi-deprecated"
+            } else {
+                ""
+            },
+            id = attr(&id),
+            upper = op.method.to_ascii_uppercase(),
+            path = text(op.path),
+            summary = text(summary),
+            deprecated_badge = if deprecated {
+                format!(
+                    r#" <span class="openapi-badge">{}</span>"#,
+                    label("web.openapi.deprecated", "deprecated")
+                )
+            } else {
+                String::new()
+            },
+        );
+        if let Some(description) = str_of(op.operation, "description") {
+            let _ = write!(
+                html,
+                r#"<div class="openapi-description">{}</div>"#,
+                crate::markdown::render_note_html(description)
+            );
+        }
+        self.render_parameters(html, op);
+        self.render_request_body(html, op);
+        self.render_responses(html, op);
+        html.push_str("</details>");
+    }
+
+    /// Follow a local `$ref` (`#/components/parameters/Id`).
+    fn resolve(&self, value: &'a Value) -> &'a Value {
+        match str_of(value, "$ref").and_then(|r| r.strip_prefix('#')) {
+            Some(pointer) => self.spec.pointer(pointer).unwrap_or(value),
+            None => value,
+        }
+    }
+
+    fn render_parameters(&self, html: &mut String, op: &Operation<'a>) {
+        // Operation parameters override path-level ones with the same name
+        // and location.
+        let mut parameters: Vec<&Value> = Vec::new();
+        for list in [op.item.get("parameters"), op.operation.get("parameters")] {
+            for parameter in list.and_then(Value::as_array).into_iter().flatten() {
+                let parameter = self.resolve(parameter);
+                let key = (str_of(parameter, "name"), str_of(parameter, "in"));
+                parameters.retain(|p| (str_of(p, "name"), str_of(p, "in")) != key);
+                parameters.push(parameter);
+            }
+        }
+        let parameters: Vec<&Value> = parameters
+            .into_iter()
+            .filter(|p| str_of(p, "in") != Some("body"))
+            .collect();
+        if parameters.is_empty() {
+            return;
+        }
+        let _ = write!(
+            html,
+            r#"<h4>{}</h4><table class="openapi-parameters"><tbody>"#,
+            label("web.openapi.parameters", "Parameters")
+        );
+        for parameter in parameters {
+            let required = parameter
+                .get("required")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            // Swagger 2 puts the type on the parameter itself.
+            let schema = parameter.get("schema").unwrap_or(parameter);
+            let _ = write!(
+                html,
+                r#"<tr><td><code>{name}</code>{required}</td><td class="openapi-in">{location}</td><td>{schema}</td><td>{description}</td></tr>"#,
+                name = text(str_of(parameter, "name").unwrap_or_default()),
+                required = if required {
+                    format!(
+                        r#" <span class="openapi-required">{}</span>"#,
+                        label("web.openapi.required", "required")
+                    )
+                } else {
+                    String::new()
+                },
+                location = text(str_of(parameter, "in").unwrap_or_default()),
+                schema = self.type_html(schema),
+                description = str_of(parameter, "description")
+                    .map(crate::markdown::render_note_html)
+                    .unwrap_or_default(),
+            );
+        }
+        html.push_str("</tbody></table>");
+    }
+
+    fn render_request_body(&self, html: &mut String, op: &Operation<'a>) {
+        let body = op
+            .operation
+            .get("requestBody")
+            .map(|body| self.resolve(body));
+        let swagger_body = op
+            .operation
+            .get("parameters")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .map(|parameter| self.resolve(parameter))
+            .find(|parameter| str_of(parameter, "in") == Some("body"));
+        let (holder, content) = match (body, swagger_body) {
+            (Some(body), _) => (body, self.content_html(body)),
+            (None, Some(parameter)) => (
+                parameter,
+                parameter
+                    .get("schema")
+                    .map(|schema| self.schema_body(schema, 0))
+                    .unwrap_or_default(),
+            ),
+            (None, None) => return,
+        };
+        let _ = write!(
+            html,
+            r#"<h4>{}</h4>{}{}"#,
+            label("web.openapi.request_body", "Request body"),
+            description_html(holder),
+            content
+        );
+    }
+
+    fn render_responses(&self, html: &mut String, op: &Operation<'a>) {
+        let Some(responses) = op.operation.get("responses").and_then(Value::as_object) else {
+            return;
+        };
+        let _ = write!(
+            html,
+            r#"<h4>{}</h4><table class="openapi-responses"><tbody>"#,
+            label("web.openapi.responses", "Responses")
+        );
+        for (status, response) in responses {
+            let response = self.resolve(response);
+            let content = match response.get("schema") {
+                Some(schema) => self.schema_body(schema, 0),
+                None => self.content_html(response),
+            };
+            let _ = write!(
+                html,
+                r#"<tr><td class="openapi-status openapi-status-{class}"><code>{status}</code></td><td>{description}{content}</td></tr>"#,
+                class = status.chars().next().unwrap_or('x'),
+                status = text(status),
+                description = str_of(response, "description")
+                    .map(crate::markdown::render_note_html)
+                    .unwrap_or_default(),
+            );
+        }
+        html.push_str("</tbody></table>");
+    }
+
+    /// The schema of each media type in an OpenAPI 3 `content` map.
+    fn content_html(&self, holder: &Value) -> String {
+        let Some(content) = holder.get("content").and_then(Value::as_object) else {
+            return String::new();
+        };
+        let mut html = String::new();
+        for (media_type, media) in content {
+            let _ = write!(
+                html,
+                r#"<div class="openapi-media"><code class="openapi-media-type">{}</code>"#,
+                text(media_type)
+            );
+            if let Some(schema) = media.get("schema") {
+                html.push_str(&self.schema_body(schema, 0));
+            }
+            html.push_str("</div>");
+        }
+        html
+    }
+
+    /// A schema's type, then its properties when it is an object.
+    fn schema_body(&self, schema: &Value, depth: usize) -> String {
+        let mut html = format!(
+            r#"<div class="openapi-type">{}</div>"#,
+            self.type_html(schema)
+        );
+        html.push_str(&self.properties_html(schema, depth));
+        html
+    }
+
+    fn properties_html(&self, schema: &Value, depth: usize) -> String {
+        if depth >= MAX_SCHEMA_DEPTH || schema.get("$ref").is_some() {
+            return String::new();
+        }
+        let schema = match schema.get("items") {
+            Some(items) if str_of(schema, "type") == Some("array") => items,
+            _ => schema,
+        };
+        let Some(properties) = schema.get("properties").and_then(Value::as_object) else {
+            return String::new();
+        };
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .map(|names| names.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut html = String::from(r#"<ul class="openapi-properties">"#);
+        for (name, property) in properties {
+            let _ = write!(
+                html,
+                r#"<li><code>{name}</code> <span class="openapi-type">{kind}</span>{required}{description}{nested}</li>"#,
+                name = text(name),
+                kind = self.type_html(property),
+                required = if required.contains(&name.as_str()) {
+                    format!(
+                        r#" <span class="openapi-required">{}</span>"#,
+                        label("web.openapi.required", "required")
+                    )
+                } else {
+                    String::new()
+                },
+                description = str_of(property, "description")
+                    .map(|d| format!(" — {}", text(d)))
+                    .unwrap_or_default(),
+                nested = self.properties_html(property, depth + 1),
+            );
+        }
+        html.push_str("</ul>");
+        html
+    }
+
+    /// A one-line summary of a schema's type: a link for a named schema,
+    /// `array of …`, a composition, or the type with its format and enum.
+    fn type_html(&self, schema: &Value) -> String {
+        if let Some(reference) = str_of(schema, "$ref") {
+            let name = reference.rsplit('/').next().unwrap_or(reference);
+            let known = reference.starts_with('#')
+                && self
+                    .schemas
+                    .is_some_and(|schemas| schemas.contains_key(name));
+            return if known {
+                format!(
+                    r##"<a href="#schema-{}"><code>{}</code></a>"##,
+                    attr(&slug(name)),
+                    text(name)
+                )
+            } else {
+                format!("<code>{}</code>", text(reference))
+            };
+        }
+        for (key, joiner) in [("oneOf", " | "), ("anyOf", " | "), ("allOf", " & ")] {
+            if let Some(parts) = schema.get(key).and_then(Value::as_array) {
+                let parts: Vec<String> = parts.iter().map(|part| self.type_html(part)).collect();
+                return parts.join(joiner);
+            }
+        }
+        let kind = match schema.get("type") {
+            Some(Value::Array(kinds)) => kinds
+                .iter()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>()
+                .join(" | "),
+            Some(kind) => kind.as_str().unwrap_or_default().to_string(),
+            None if schema.get("properties").is_some() => "object".to_string(),
+            None => String::new(),
+        };
+        if kind == "array" {
+            let items = schema
+                .get("items")
+                .map(|items| self.type_html(items))
+                .unwrap_or_default();
+            return format!("array of {items}").trim_end().to_string();
+        }
+        let mut html = text(&kind).into_owned();
+        if let Some(format) = str_of(schema, "format") {
+            let _ = write!(html, " ({})", text(format));
+        }
+        if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+            let values: Vec<String> = values
+                .iter()
+                .filter_map(scalar)
+                .map(|value| format!("<code>{}</code>", text(&value)))
+                .collect();
+            let _ = write!(html, " {{{}}}", values.join(", "));
+        }
+        html.trim().to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_names_are_recognized_by_stem_and_extension() {
+        assert!(is_spec_path(Path::new("api/openapi.yaml")));
+        assert!(is_spec_path(Path::new("Swagger.JSON")));
+        assert!(is_spec_path(Path::new("petstore.openapi.yml")));
+        assert!(is_spec_path(Path::new("billing-swagger.json")));
+        assert!(!is_spec_path(Path::new("openapi.md")));
+        assert!(!is_spec_path(Path::new("myopenapi.yaml")));
+        assert!(!is_spec_path(Path::new("config.yaml")));
+    }
+
+    #[test]
+    fn openapi_3_yaml_renders_operations_parameters_and_linked_schemas() {
+        let spec = r#"
+openapi: 3.0.3
+info:
+  title: Pet Store
+  version: 1.2.0
+  description: Pets, *lots* of them.
+servers:
+  - url: https://api.example.com/v1
+tags:
+  - name: pets
+    description: Everything about pets
+paths:
+  /pets/{petId}:
+    parameters:
+      - name: petId
+        in: path
+        required: true
+        schema: { type: integer, format: int64 }
+    get:
+      tags: [pets]
+      operationId: getPet
+      summary: Find a pet
+      responses:
+        200:
+          description: The pet
+          content:
+            application/json:
+              schema:
+                $ref: '#/components/schemas/Pet'
+        404:
+          description: No such pet
+  /health:
+    get:
+      summary: Health check
+      deprecated: true
+      responses:
+        "204": { description: Up }
+components:
+  schemas:
+    Pet:
+      type: object
+      required: [name]
+      properties:
+        name: { type: string, description: "Call name <b>" }
+        status: { type: string, enum: [available, sold] }
+"#;
+        let api = render(spec).unwrap();
+        assert_eq!(api.title, "Pet Store");
+        let html = &api.html;
+        assert!(
+            html.contains(r#"<span class="openapi-version">1.2.0</span>"#),
+            "{html}"
+        );
+        assert!(html.contains("<em>lots</em>"), "{html}");
+        assert!(
+            html.contains("<code>https://api.example.com/v1</code>"),
+            "{html}"
+        );
+        assert!(html.contains(r#"id="op-getpet""#), "{html}");
+        assert!(
+            html.contains(r#"<code class="openapi-path">/pets/{petId}</code>"#),
+            "{html}"
+        );
+        assert!(html.contains("<code>petId</code>"), "{html}");
+        assert!(html.contains("integer (int64)"), "{html}");
+        assert!(
+            html.contains(r##"<a href="#schema-pet"><code>Pet</code></a>"##),
+            "{html}"
+        );
+        assert!(html.contains(r#"<code>404</code>"#), "{html}");
+        assert!(html.contains("openapi-deprecated"), "{html}");
+        assert!(html.contains(r#"id="schema-pet""#), "{html}");
+        assert!(
+            html.contains("<code>available</code>, <code>sold</code>"),
+            "{html}"
+        );
+        assert!(html.contains("Call name &lt;b&gt;"), "{html}");
+        // Declared tags come first, untagged operations last.
+        assert!(html.find("tag-pets").unwrap() < html.find("tag-default").unwrap());
+        let toc: Vec<&str> = api.toc.iter().map(|item| item.text.as_str()).collect();
+        assert_eq!(
+            toc,
+            ["pets", "Find a pet", "default", "Health check", "Schemas"]
+        );
+    }
+
+    #[test]
+    fn swagger_2_json_reads_body_parameters_and_definitions() {
+        let spec = r##"{
+            "swagger": "2.0",
+            "info": { "title": "Legacy", "version": "1" },
+            "host": "legacy.example.com",
+            "basePath": "/api",
+            "schemes": ["https"],
+            "paths": {
+                "/users": {
+                    "post": {
+                        "parameters": [
+                            { "name": "body", "in": "body", "schema": { "$ref": "#/definitions/User" } },
+                            { "name": "dry_run", "in": "query", "type": "boolean" }
+                        ],
+                        "responses": {
+                            "201": { "description": "Created", "schema": { "type": "array", "items": { "$ref": "#/definitions/User" } } }
+                        }
+                    }
+                }
+            },
+            "definitions": { "User": { "properties": { "id": { "type": "string" } } } }
+        }"##;
+        let api = render(spec).unwrap();
+        let html = &api.html;
+        assert!(
+            html.contains("<code>https://legacy.example.com/api</code>"),
+            "{html}"
+        );
+        assert!(html.contains("web.openapi.request_body"), "{html}");
+        assert!(html.contains("<code>dry_run</code>"), "{html}");
+        assert!(!html.contains("<code>body</code>"), "{html}");
+        assert!(
+            html.contains(r##"array of <a href="#schema-user">"##),
+            "{html}"
+        );
+
+        assert!(render("name: not a spec\n").is_none());
+        assert!(render("openapi: [").is_none());
+    }
+}
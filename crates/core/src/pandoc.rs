@@ -0,0 +1,260 @@
+//! Optional pandoc fallback (`--pandoc`) for documents markon can't read
+//! itself: OpenDocument text, RTF and MediaWiki markup.
+//!
+//! An installed `pandoc` converts such a file to GitHub-flavored Markdown,
+//! which then goes through the regular renderer, so the page gets markon's
+//! layout, theme, table of contents and annotations. Conversions run in
+//! pandoc's `--sandbox` (the document can't pull in other files) and are
+//! kept until the file changes.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A conversion still running after this long is killed.
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Conversions the cache holds before it starts over.
+const CACHE_LIMIT: usize = 64;
+
+/// An input format delegated to pandoc.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Format {
+    /// pandoc reader (`--from`).
+    pub(crate) reader: &'static str,
+    /// Name shown on the converted page.
+    pub(crate) label: &'static str,
+}
+
+const ODT: Format = Format {
+    reader: "odt",
+    label: "ODT",
+};
+const RTF: Format = Format {
+    reader: "rtf",
+    label: "RTF",
+};
+const MEDIAWIKI: Format = Format {
+    reader: "mediawiki",
+    label: "MediaWiki",
+};
+
+/// File extensions handed to pandoc, matched case-insensitively.
+const FORMATS: &[(&str, &Format)] = &[
+    ("odt", &ODT),
+    ("rtf", &RTF),
+    ("mediawiki", &MEDIAWIKI),
+    ("wiki", &MEDIAWIKI),
+];
+
+/// The pandoc format of `path`, by extension.
+pub(crate) fn format_for(path: &Path) -> Option<&'static Format> {
+    let ext = path.extension()?.to_str()?;
+    FORMATS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(ext))
+        .map(|(_, format)| *format)
+}
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum PandocError {
+    #[error("could not run pandoc: {0}")]
+    Spawn(std::io::Error),
+    #[error("pandoc failed: {0}")]
+    Failed(String),
+    #[error("pandoc took longer than {} seconds", CONVERT_TIMEOUT.as_secs())]
+    TimedOut,
+}
+
+/// A converted document and the file state it was converted from.
+struct Converted {
+    modified: Option<SystemTime>,
+    len: u64,
+    markdown: Arc<String>,
+}
+
+/// The `pandoc` executable and the conversions it has made.
+pub(crate) struct Pandoc {
+    program: PathBuf,
+    cache: Mutex<HashMap<PathBuf, Converted>>,
+}
+
+impl Pandoc {
+    pub(crate) fn new(program: impl Into<PathBuf>) -> Self {
+        Self {
+            program: program.into(),
+            cache: Mutex::default(),
+        }
+    }
+
+    /// `pandoc` from `PATH`, or `None` when it isn't installed.
+    pub(crate) fn locate() -> Option<Self> {
+        let name = if cfg!(windows) {
+            "pandoc.exe"
+        } else {
+            "pandoc"
+        };
+        std::env::split_paths(&std::env::var_os("PATH")?)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+            .map(Self::new)
+    }
+
+    pub(crate) fn program(&self) -> &Path {
+        &self.program
+    }
+
+    /// Convert `path` to Markdown, reusing the last conversion while the
+    /// file's size and modification time are unchanged.
+    pub(crate) fn to_markdown(
+        &self,
+        path: &Path,
+        format: &Format,
+    ) -> Result<Arc<String>, PandocError> {
+        let metadata = std::fs::metadata(path).map_err(PandocError::Spawn)?;
+        let (modified, len) = (metadata.modified().ok(), metadata.len());
+        let mut cache = self.cache.lock().unwrap_or_else(|p| p.into_inner());
+        if let Some(hit) = cache
+            .get(path)
+            .filter(|hit| modified.is_some() && hit.modified == modified && hit.len == len)
+        {
+            return Ok(hit.markdown.clone());
+        }
+        drop(cache);
+
+        let markdown = Arc::new(self.convert(path, format)?);
+        cache = self.cache.lock().unwrap_or_else(|p| p.into_inner());
+        if cache.len() >= CACHE_LIMIT {
+            cache.clear();
+        }
+        cache.insert(
+            path.to_path_buf(),
+            Converted {
+                modified,
+                len,
+                markdown: markdown.clone(),
+            },
+        );
+        Ok(markdown)
+    }
+
+    fn convert(&self, path: &Path, format: &Format) -> Result<String, PandocError> {
+        let mut child = Command::new(&self.program)
+            .args(["--sandbox", "--wrap=none", "--to=gfm"])
+            .arg(format!("--from={}", format.reader))
+            .arg("--")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(PandocError::Spawn)?;
+        // Drain both pipes off this thread so a chatty pandoc can't block on
+        // a full pipe while we wait for it to exit.
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            std::thread::spawn(move || {
+                let mut bytes = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut bytes);
+                }
+                bytes
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+        let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+
+        let deadline = Instant::now() + CONVERT_TIMEOUT;
+        let status = loop {
+            match child.try_wait().map_err(PandocError::Spawn)? {
+                Some(status) => break status,
+                None if Instant::now() >= deadline => {
+                    let _ = child.kill();
+                    let _ = child.wait();
+                    return Err(PandocError::TimedOut);
+                }
+                None => std::thread::sleep(Duration::from_millis(20)),
+            }
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            let message = String::from_utf8_lossy(&stderr).trim().to_string();
+            return Err(PandocError::Failed(if message.is_empty() {
+                status.to_string()
+            } else {
+                message
+            }));
+        }
+        Ok(String::from_utf8_lossy(&stdout).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_chosen_by_extension() {
+        assert_eq!(format_for(Path::new("a/b.odt")), Some(&ODT));
+        assert_eq!(format_for(Path::new("NOTES.RTF")), Some(&RTF));
+        assert_eq!(format_for(Path::new("page.wiki")), Some(&MEDIAWIKI));
+        assert_eq!(format_for(Path::new("page.mediawiki")), Some(&MEDIAWIKI));
+        assert_eq!(format_for(Path::new("README.md")), None);
+        assert_eq!(format_for(Path::new("odt")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn conversions_are_cached_until_the_file_changes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Stands in for pandoc: prints its reader and how often it ran.
+        let program = dir.path().join("pandoc");
+        let runs = dir.path().join("runs");
+        std::fs::write(
+            &program,
+            format!(
+                "#!/bin/sh\necho x >> '{}'\necho \"# $4\"\nwc -l < '{}'\n",
+                runs.display(),
+                runs.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let doc = dir.path().join("page.wiki");
+        std::fs::write(&doc, "== Title ==").unwrap();
+
+        let pandoc = Pandoc::new(&program);
+        let first = pandoc.to_markdown(&doc, &MEDIAWIKI).unwrap();
+        assert_eq!(first.lines().next(), Some("# --from=mediawiki"));
+        assert_eq!(first.lines().nth(1).map(str::trim), Some("1"));
+        assert!(Arc::ptr_eq(
+            &first,
+            &pandoc.to_markdown(&doc, &MEDIAWIKI).unwrap()
+        ));
+
+        std::fs::write(&doc, "== Longer title ==").unwrap();
+        let second = pandoc.to_markdown(&doc, &MEDIAWIKI).unwrap();
+        assert_eq!(second.lines().nth(1).map(str::trim), Some("2"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn failures_carry_pandoc_stderr() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let program = dir.path().join("pandoc");
+        std::fs::write(&program, "#!/bin/sh\necho 'Unknown reader' >&2\nexit 22\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let doc = dir.path().join("a.rtf");
+        std::fs::write(&doc, "{\\rtf1}").unwrap();
+
+        let err = Pandoc::new(&program).to_markdown(&doc, &RTF).unwrap_err();
+        assert_eq!(err.to_string(), "pandoc failed: Unknown reader");
+    }
+}
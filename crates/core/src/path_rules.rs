@@ -0,0 +1,185 @@
+//! Which paths of a workspace are exposed (`--allow-path`, `--deny-path`).
+//!
+//! Patterns are globs relative to the workspace root: `docs/private/**`
+//! covers that folder and everything in it, and a pattern without `/`
+//! (`*.draft.md`, `secrets`) matches at any depth. A path is exposed unless
+//! a deny pattern matches it; when allow patterns are given it must also
+//! match one of them. The folders leading to allowed paths stay browsable,
+//! so `docs/**` still lists the root with only `docs` in it.
+//!
+//! The rules are applied wherever a workspace path is resolved or walked:
+//! serving, listings, search indexing, chat tools and live updates.
+
+use std::path::{Component, Path};
+
+use globset::{GlobBuilder, GlobMatcher, GlobSet, GlobSetBuilder};
+
+#[derive(Debug, Default)]
+pub(crate) struct PathRules {
+    allow: Option<GlobSet>,
+    /// The components of each allow pattern, `None` standing for `**`; a
+    /// folder whose path matches a leading part of one can hold allowed
+    /// paths.
+    allow_prefixes: Vec<Vec<Option<GlobMatcher>>>,
+    deny: Option<GlobSet>,
+}
+
+impl PathRules {
+    pub(crate) fn new(allow: &[String], deny: &[String]) -> Result<Self, String> {
+        let allow_patterns = patterns(allow);
+        let allow_prefixes = allow_patterns
+            .iter()
+            .map(|pattern| {
+                pattern
+                    .split('/')
+                    .map(|part| (part != "**").then(|| component_glob(part)).transpose())
+                    .collect::<Result<_, _>>()
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            allow: glob_set(&allow_patterns, "--allow-path")?,
+            allow_prefixes,
+            deny: glob_set(&patterns(deny), "--deny-path")?,
+        })
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.allow.is_none() && self.deny.is_none()
+    }
+
+    /// Whether the file or folder at `rel`, relative to the workspace root,
+    /// is exposed. The root itself always is.
+    pub(crate) fn permits(&self, rel: &Path, is_dir: bool) -> bool {
+        if rel.as_os_str().is_empty() {
+            return true;
+        }
+        if self.deny.as_ref().is_some_and(|deny| deny.is_match(rel)) {
+            return false;
+        }
+        match &self.allow {
+            None => true,
+            Some(allow) => allow.is_match(rel) || (is_dir && self.leads_to_allowed(rel)),
+        }
+    }
+
+    fn leads_to_allowed(&self, dir: &Path) -> bool {
+        let parts: Vec<_> = dir
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect();
+        self.allow_prefixes.iter().any(|pattern| {
+            for (index, part) in parts.iter().enumerate() {
+                match pattern.get(index) {
+                    // Past the end the folder is inside a match, which the
+                    // glob set already answered.
+                    None => return false,
+                    Some(None) => return true,
+                    Some(Some(glob)) if !glob.is_match(part.as_ref()) => return false,
+                    Some(Some(_)) => {}
+                }
+            }
+            true
+        })
+    }
+}
+
+/// Normalized patterns: no surrounding `/`, no trailing `/**`, prefixed
+/// with `**/` when they were written without a `/`.
+fn patterns(raw: &[String]) -> Vec<String> {
+    raw.iter()
+        .filter_map(|raw| {
+            let trimmed = raw.trim().trim_end_matches('/');
+            let anchored = trimmed.contains('/');
+            let mut pattern = trimmed.trim_start_matches('/');
+            while let Some(folder) = pattern.strip_suffix("/**") {
+                pattern = folder;
+            }
+            if pattern.is_empty() || pattern == "**" {
+                return None;
+            }
+            Some(if anchored {
+                pattern.to_string()
+            } else {
+                format!("**/{pattern}")
+            })
+        })
+        .collect()
+}
+
+fn glob_set(patterns: &[String], flag: &str) -> Result<Option<GlobSet>, String> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        for glob in [pattern.clone(), format!("{pattern}/**")] {
+            builder.add(
+                GlobBuilder::new(&glob)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| format!("invalid {flag} pattern '{pattern}': {e}"))?,
+            );
+        }
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| format!("invalid {flag} patterns: {e}"))
+}
+
+fn component_glob(part: &str) -> Result<GlobMatcher, String> {
+    GlobBuilder::new(part)
+        .literal_separator(true)
+        .build()
+        .map(|glob| glob.compile_matcher())
+        .map_err(|e| format!("invalid --allow-path pattern component '{part}': {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(allow: &[&str], deny: &[&str]) -> PathRules {
+        let owned = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        PathRules::new(&owned(allow), &owned(deny)).unwrap()
+    }
+
+    #[test]
+    fn deny_covers_folders_and_names_at_any_depth() {
+        let rules = rules(&[], &["private/**", "*.secret.md"]);
+        assert!(!rules.permits(Path::new("private"), true));
+        assert!(!rules.permits(Path::new("private/plan.md"), false));
+        assert!(!rules.permits(Path::new("docs/keys.secret.md"), false));
+        assert!(rules.permits(Path::new("docs/private.md"), false));
+        assert!(rules.permits(Path::new("docs/sub/private"), true));
+        assert!(rules.permits(Path::new(""), true));
+        assert!(PathRules::new(&[], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn allow_exposes_matches_and_the_folders_leading_to_them() {
+        let mixed = rules(&["docs/guide/**", "*.md"], &["docs/guide/internal"]);
+        assert!(mixed.permits(Path::new("docs"), true));
+        assert!(mixed.permits(Path::new("docs/guide/setup.png"), false));
+        assert!(mixed.permits(Path::new("src/README.md"), false));
+        assert!(!mixed.permits(Path::new("src/main.rs"), false));
+        assert!(!mixed.permits(Path::new("docs/guide/internal/notes.md"), false));
+        // `*.md` may match in any folder, so every folder stays browsable.
+        assert!(mixed.permits(Path::new("src"), true));
+
+        let docs_only = rules(&["docs/**"], &[]);
+        assert!(docs_only.permits(Path::new("docs/a/b.txt"), false));
+        assert!(!docs_only.permits(Path::new("src"), true));
+        assert!(!docs_only.permits(Path::new("README.md"), false));
+        assert!(!docs_only.permits(Path::new("docsx"), true));
+    }
+
+    #[test]
+    fn malformed_patterns_are_rejected() {
+        let err = PathRules::new(&[], &["docs/[".into()]).unwrap_err();
+        assert!(err.contains("--deny-path"), "{err}");
+    }
+}
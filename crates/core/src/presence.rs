@@ -0,0 +1,167 @@
+//! Who is viewing which document, for "3 people viewing" indicators, and
+//! the rate limit on the selections they share.
+//!
+//! Every document WebSocket session is one viewer under a server-assigned
+//! client id, keyed by workspace and canonical file path. Nothing is
+//! persisted: a restart starts everyone over, and reconnecting browsers join
+//! again.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// One connected viewer of a document.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct Viewer {
+    pub client_id: String,
+    /// The viewer's `{color, name}` identity, shaped like an annotation
+    /// `author`; absent when the browser sent none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity: Option<serde_json::Value>,
+}
+
+#[derive(Default)]
+pub(crate) struct PresenceRegistry {
+    documents: Mutex<HashMap<(String, String), Vec<Viewer>>>,
+}
+
+impl PresenceRegistry {
+    /// Add `viewer` to a document. Returns everyone viewing it now, in the
+    /// order they joined.
+    pub(crate) fn join(&self, workspace_id: &str, file_path: &str, viewer: Viewer) -> Vec<Viewer> {
+        let mut documents = self
+            .documents
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let viewers = documents
+            .entry((workspace_id.to_string(), file_path.to_string()))
+            .or_default();
+        viewers.retain(|known| known.client_id != viewer.client_id);
+        viewers.push(viewer);
+        viewers.clone()
+    }
+
+    /// Remove a viewer. Returns how many are left on the document.
+    pub(crate) fn leave(&self, workspace_id: &str, file_path: &str, client_id: &str) -> usize {
+        let mut documents = self
+            .documents
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let key = (workspace_id.to_string(), file_path.to_string());
+        let Some(viewers) = documents.get_mut(&key) else {
+            return 0;
+        };
+        viewers.retain(|viewer| viewer.client_id != client_id);
+        let left = viewers.len();
+        if left == 0 {
+            documents.remove(&key);
+        }
+        left
+    }
+}
+
+/// Latest-wins rate limit for one session's shared selection. A drag fires
+/// many updates; peers only need the newest one, at most once per `interval`,
+/// and must still see where the drag ended.
+pub(crate) struct Throttle<T> {
+    interval: Duration,
+    last_sent: Option<Instant>,
+    pending: Option<T>,
+}
+
+impl<T> Throttle<T> {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: None,
+            pending: None,
+        }
+    }
+
+    /// Returns `item` if it may go out now; otherwise keeps it, replacing
+    /// any older pending item, until [`Throttle::take_due`].
+    pub(crate) fn offer(&mut self, item: T, now: Instant) -> Option<T> {
+        if self
+            .last_sent
+            .is_some_and(|sent| now.duration_since(sent) < self.interval)
+        {
+            self.pending = Some(item);
+            return None;
+        }
+        self.pending = None;
+        self.last_sent = Some(now);
+        Some(item)
+    }
+
+    /// When the pending item becomes due, if there is one.
+    pub(crate) fn deadline(&self) -> Option<Instant> {
+        self.pending.as_ref()?;
+        Some(self.last_sent? + self.interval)
+    }
+
+    /// The pending item, once its deadline has passed.
+    pub(crate) fn take_due(&mut self, now: Instant) -> Option<T> {
+        if self.deadline().is_some_and(|deadline| deadline <= now) {
+            self.last_sent = Some(now);
+            return self.pending.take();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewer(id: &str) -> Viewer {
+        Viewer {
+            client_id: id.to_string(),
+            identity: None,
+        }
+    }
+
+    #[test]
+    fn tracks_viewers_per_workspace_document() {
+        let presence = PresenceRegistry::default();
+        assert_eq!(presence.join("ws", "/a.md", viewer("c1")).len(), 1);
+        assert_eq!(
+            presence.join("ws", "/a.md", viewer("c2")),
+            [viewer("c1"), viewer("c2")]
+        );
+        assert_eq!(presence.join("ws", "/b.md", viewer("c3")).len(), 1);
+        assert_eq!(presence.join("other", "/a.md", viewer("c4")).len(), 1);
+
+        assert_eq!(presence.leave("ws", "/a.md", "c1"), 1);
+        assert_eq!(presence.leave("ws", "/a.md", "c1"), 1);
+        assert_eq!(presence.leave("ws", "/a.md", "c2"), 0);
+        assert!(!presence
+            .documents
+            .lock()
+            .unwrap()
+            .contains_key(&("ws".to_string(), "/a.md".to_string())));
+        assert_eq!(presence.leave("ws", "/missing.md", "c1"), 0);
+    }
+
+    #[test]
+    fn throttle_keeps_only_the_latest_item_until_due() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut throttle = Throttle::new(Duration::from_millis(100));
+
+        assert_eq!(throttle.offer(1, at(0)), Some(1));
+        assert_eq!(throttle.deadline(), None);
+        assert_eq!(throttle.offer(2, at(30)), None);
+        assert_eq!(throttle.offer(3, at(60)), None);
+        assert_eq!(throttle.deadline(), Some(at(100)));
+        assert_eq!(throttle.take_due(at(99)), None);
+        assert_eq!(throttle.take_due(at(100)), Some(3));
+        assert_eq!(throttle.take_due(at(300)), None);
+
+        // A quiet period lets the next item straight through.
+        assert_eq!(throttle.offer(4, at(250)), Some(4));
+        assert_eq!(throttle.offer(5, at(260)), None);
+        assert_eq!(throttle.offer(6, at(400)), Some(6));
+        assert_eq!(throttle.deadline(), None);
+    }
+}
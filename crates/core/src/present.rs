@@ -0,0 +1,121 @@
+//! Presentation mode (`/_/{workspace_id}/present/{*path}`): a Markdown
+//! document cut into slides.
+//!
+//! Slides are separated by `---` thematic breaks; a document without any is
+//! cut before each `##` heading instead. HTML comment blocks on a slide are
+//! its speaker notes, shown only in the speaker view. The frontmatter is
+//! never part of a slide.
+
+use crate::markdown_ast::{slide_marks, SlideMarks};
+
+/// One slide: the Markdown shown and the speaker notes kept from it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Slide {
+    pub(crate) markdown: String,
+    pub(crate) notes: String,
+}
+
+impl Slide {
+    fn is_empty(&self) -> bool {
+        self.markdown.trim().is_empty() && self.notes.is_empty()
+    }
+}
+
+/// Cut `markdown` into slides; blank ones are dropped.
+pub(crate) fn split(markdown: &str) -> Vec<Slide> {
+    let body = crate::frontmatter::strip(markdown);
+    let SlideMarks {
+        breaks,
+        h2,
+        comments,
+    } = slide_marks(body);
+    let on_breaks = !breaks.is_empty();
+
+    let mut slides = Vec::new();
+    let mut slide = Slide::default();
+    let mut comment = String::new();
+    for (index, line) in body.split_inclusive('\n').enumerate() {
+        let number = index as u32 + 1;
+        let cut = if on_breaks {
+            breaks.contains(&number)
+        } else {
+            h2.contains(&number)
+        };
+        if cut {
+            slides.push(std::mem::take(&mut slide));
+            if on_breaks {
+                continue;
+            }
+        }
+        match comments
+            .iter()
+            .find(|(start, end)| (*start..=*end).contains(&number))
+        {
+            Some(&(_, end)) => {
+                comment.push_str(line);
+                if number == end {
+                    add_note(&mut slide.notes, &std::mem::take(&mut comment));
+                }
+            }
+            None => slide.markdown.push_str(line),
+        }
+    }
+    slides.push(slide);
+    slides.retain(|slide| !slide.is_empty());
+    slides
+}
+
+/// Append the text of an `<!-- … -->` block to `notes`.
+fn add_note(notes: &mut String, comment: &str) {
+    let text = comment.trim();
+    let text = text.strip_prefix("<!--").unwrap_or(text);
+    let text = text.strip_suffix("-->").unwrap_or(text).trim();
+    if text.is_empty() {
+        return;
+    }
+    if !notes.is_empty() {
+        notes.push_str("\n\n");
+    }
+    notes.push_str(text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn markdown_of(slides: &[Slide]) -> Vec<&str> {
+        slides.iter().map(|slide| slide.markdown.trim()).collect()
+    }
+
+    #[test]
+    fn thematic_breaks_cut_slides_outside_code_and_setext_headings() {
+        let slides = split(
+            "---\ntitle: Deck\n---\n# Deck\n\n---\n\nSetext\n---\n\n```\n---\n```\n\n***\n\n## Last\n",
+        );
+        assert_eq!(
+            markdown_of(&slides),
+            ["# Deck", "Setext\n---\n\n```\n---\n```", "## Last"]
+        );
+    }
+
+    #[test]
+    fn documents_without_breaks_are_cut_before_each_h2() {
+        let slides = split("# Talk\n\nIntro\n\n## One\n\n### Detail\n\n## Two\n");
+        assert_eq!(
+            markdown_of(&slides),
+            ["# Talk\n\nIntro", "## One\n\n### Detail", "## Two"]
+        );
+    }
+
+    #[test]
+    fn comment_blocks_become_speaker_notes() {
+        let slides = split(
+            "## One\n\n<!--\nMention the demo.\n-->\n\nText <!-- inline stays -->\n\n<!-- Pause. -->\n\n## Two\n",
+        );
+        assert_eq!(slides.len(), 2);
+        assert_eq!(slides[0].notes, "Mention the demo.\n\nPause.");
+        assert!(!slides[0].markdown.contains("Mention"));
+        assert!(slides[0].markdown.contains("<!-- inline stays -->"));
+        assert_eq!(slides[1].notes, "");
+    }
+}
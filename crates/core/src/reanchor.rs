@@ -0,0 +1,350 @@
+//! Server-side annotation re-anchoring after a Markdown file changes.
+//!
+//! Anchors are captured by `assets/js/services/text-anchor.ts` against the
+//! rendered document's text content: a quote (`exact` + up to 32 chars of
+//! `prefix`/`suffix`) plus a `position` hint, in UTF-16 code units. When the
+//! watcher sees an annotated file change, the quote is re-found in the new
+//! rendering with the same scoring the browser uses, and the stored anchor is
+//! refreshed so the hint and context track the edit. Quotes that no longer
+//! occur are flagged `"orphaned": true` (cleared again if the text returns);
+//! the browser keeps trying to anchor them either way.
+
+use crate::markdown::{default_markdown_engine, MarkdownHtmlRenderer};
+use crate::store::{DocumentStore, StoreError};
+use rusqlite::{params, Connection};
+use serde_json::{Map, Value};
+
+/// Context length on each side of a quote, matching `text-anchor.ts`.
+const CONTEXT: usize = 32;
+
+/// The rendered document's text content in UTF-16 code units, approximating
+/// the browser's concatenated text nodes: markup dropped, entities decoded.
+pub(crate) fn document_text(markdown: &str) -> Vec<u16> {
+    let html = default_markdown_engine("light", &[])
+        .render_html(markdown)
+        .html;
+    html_text(&html).encode_utf16().collect()
+}
+
+fn html_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(open) = rest.find('<') {
+        text.push_str(&html_escape::decode_html_entities(&rest[..open]));
+        rest = match rest[open..].find('>') {
+            Some(close) => &rest[open + close + 1..],
+            None => "",
+        };
+    }
+    text.push_str(&html_escape::decode_html_entities(rest));
+    text
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Relocation {
+    Unchanged,
+    /// The anchor was refreshed, or a previously orphaned one was found again.
+    Moved,
+    /// The quote is gone; the annotation is newly flagged as orphaned.
+    Orphaned,
+}
+
+/// Re-find `annotation`'s anchor in `text` and update it in place.
+pub(crate) fn relocate(text: &[u16], annotation: &mut Value) -> Relocation {
+    let Some(anchor) = annotation.get("anchor").and_then(Value::as_object) else {
+        return Relocation::Unchanged;
+    };
+    let mut updated = anchor.clone();
+    let mut changed = false;
+    let mut found = true;
+    let has_fragments = match updated.get_mut("fragments").and_then(Value::as_array_mut) {
+        Some(fragments) if !fragments.is_empty() => {
+            for fragment in fragments.iter_mut().filter_map(Value::as_object_mut) {
+                match relocate_quote(text, fragment) {
+                    Some(moved) => changed |= moved,
+                    None => found = false,
+                }
+            }
+            true
+        }
+        _ => false,
+    };
+    // Version 2 anchors resolve by fragment; the flat quote is only a
+    // fallback for older clients, so it decides orphaning only without them.
+    match relocate_quote(text, &mut updated) {
+        Some(moved) => changed |= moved,
+        None if !has_fragments => found = false,
+        None => {}
+    }
+
+    let Some(object) = annotation.as_object_mut() else {
+        return Relocation::Unchanged;
+    };
+    let was_orphaned = object.get("orphaned") == Some(&Value::Bool(true));
+    if !found {
+        if was_orphaned {
+            return Relocation::Unchanged;
+        }
+        object.insert("orphaned".into(), Value::Bool(true));
+        return Relocation::Orphaned;
+    }
+    if was_orphaned {
+        object.remove("orphaned");
+        changed = true;
+    }
+    if !changed {
+        return Relocation::Unchanged;
+    }
+    object.insert("anchor".into(), Value::Object(updated));
+    Relocation::Moved
+}
+
+/// Best occurrence of one quote, scored like `quoteCandidates` in
+/// `text-anchor.ts` (context match, then closeness to the old position).
+/// Returns whether the stored selector changed, or `None` if the quote is gone.
+fn relocate_quote(text: &[u16], quote: &mut Map<String, Value>) -> Option<bool> {
+    let field = |key: &str| -> Vec<u16> {
+        quote
+            .get(key)
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .encode_utf16()
+            .collect()
+    };
+    let exact = field("exact");
+    if exact.is_empty() || exact.len() > text.len() {
+        return None;
+    }
+    let prefix = field("prefix");
+    let suffix = field("suffix");
+    let position = quote.get("position").and_then(Value::as_u64).unwrap_or(0) as f64;
+
+    let mut best: Option<(usize, f64)> = None;
+    for start in 0..=text.len() - exact.len() {
+        if text[start..start + exact.len()] != exact[..] {
+            continue;
+        }
+        let end = start + exact.len();
+        let before = &text[start.saturating_sub(prefix.len())..start];
+        let after = &text[end..(end + suffix.len()).min(text.len())];
+        let context = common_suffix_len(before, &prefix) + common_prefix_len(after, &suffix);
+        let score = context as f64 - (start as f64 - position).abs() / 10_000.0;
+        if best.is_none_or(|(_, top)| score > top) {
+            best = Some((start, score));
+        }
+    }
+    let (start, _) = best?;
+    let end = start + exact.len();
+    let refreshed = [
+        ("position", Value::from(start as u64)),
+        (
+            "prefix",
+            Value::from(String::from_utf16_lossy(
+                &text[start.saturating_sub(CONTEXT)..start],
+            )),
+        ),
+        (
+            "suffix",
+            Value::from(String::from_utf16_lossy(
+                &text[end..(end + CONTEXT).min(text.len())],
+            )),
+        ),
+    ];
+    let mut changed = false;
+    for (key, value) in refreshed {
+        if quote.get(key) != Some(&value) {
+            quote.insert(key.into(), value);
+            changed = true;
+        }
+    }
+    Some(changed)
+}
+
+fn common_suffix_len(a: &[u16], b: &[u16]) -> usize {
+    a.iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+fn common_prefix_len(a: &[u16], b: &[u16]) -> usize {
+    a.iter().zip(b).take_while(|(x, y)| x == y).count()
+}
+
+/// Relocate `annotations` against a file's new `markdown`, returning the
+/// ones that changed. The document is only rendered when there are any.
+pub(crate) fn reanchor(annotations: Vec<Value>, markdown: &str) -> Vec<Value> {
+    if annotations.is_empty() {
+        return annotations;
+    }
+    let text = document_text(markdown);
+    annotations
+        .into_iter()
+        .filter_map(|mut annotation| {
+            (relocate(&text, &mut annotation) != Relocation::Unchanged).then_some(annotation)
+        })
+        .collect()
+}
+
+/// Re-anchor every annotation stored for `file_path` against its new
+/// `markdown`, persisting the ones that changed and returning them.
+pub(crate) fn reanchor_file(
+    conn: &Connection,
+    file_path: &str,
+    markdown: &str,
+) -> rusqlite::Result<Vec<Value>> {
+    let annotations: Vec<Value> = {
+        let mut stmt = conn.prepare("SELECT data FROM annotations WHERE file_path = ?1")?;
+        let rows = stmt.query_map([file_path], |row| row.get::<_, String>(0))?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()?
+            .iter()
+            .filter_map(|data| serde_json::from_str(data).ok())
+            .collect()
+    };
+    let changed = reanchor(annotations, markdown);
+    update_anchors(conn, file_path, &changed)?;
+    Ok(changed)
+}
+
+/// [`reanchor_file`] against any document store.
+pub(crate) async fn reanchor_stored(
+    store: &dyn DocumentStore,
+    file_path: &str,
+    markdown: &str,
+) -> Result<Vec<Value>, StoreError> {
+    let changed = reanchor(store.annotations(file_path).await?, markdown);
+    if !changed.is_empty() {
+        store.update_anchors(file_path, &changed).await?;
+    }
+    Ok(changed)
+}
+
+/// Overwrite the stored copies of re-anchored `annotations` on `file_path`
+/// in one transaction. Revisions stay as they are: moving an anchor isn't
+/// an edit a writer could conflict with.
+pub(crate) fn update_anchors(
+    conn: &Connection,
+    file_path: &str,
+    annotations: &[Value],
+) -> rusqlite::Result<()> {
+    let tx = crate::db::savepoint(conn)?;
+    for annotation in annotations {
+        let Some(id) = annotation.get("id").and_then(Value::as_str) else {
+            continue;
+        };
+        tx.execute(
+            "UPDATE annotations SET data = ?1 WHERE id = ?2 AND file_path = ?3",
+            params![annotation.to_string(), id, file_path],
+        )?;
+    }
+    tx.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str) -> Vec<u16> {
+        text.encode_utf16().collect()
+    }
+
+    fn annotation(exact: &str, prefix: &str, suffix: &str, position: usize) -> Value {
+        serde_json::json!({
+            "id": "anno-1",
+            "anchor": { "position": position, "exact": exact, "prefix": prefix, "suffix": suffix }
+        })
+    }
+
+    #[test]
+    fn html_text_drops_markup_and_decodes_entities() {
+        assert_eq!(
+            html_text("<h1 id=\"a\">A &amp; B</h1>\n<p>x &lt; <em>y</em></p>"),
+            "A & B\nx < y"
+        );
+    }
+
+    #[test]
+    fn relocate_follows_an_insertion_and_prefers_matching_context() {
+        let mut anno = annotation("retry", "Always ", " the call", 7);
+        let text = utf16("Intro line. Never retry blindly. Always retry the call.");
+        assert_eq!(relocate(&text, &mut anno), Relocation::Moved);
+        let anchor = &anno["anchor"];
+        assert_eq!(anchor["position"], 40);
+        assert_eq!(anchor["prefix"], "ne. Never retry blindly. Always ");
+        assert_eq!(anchor["suffix"], " the call.");
+
+        // A second pass over the same text changes nothing.
+        assert_eq!(relocate(&text, &mut anno), Relocation::Unchanged);
+    }
+
+    #[test]
+    fn relocate_orphans_missing_quotes_and_recovers_them() {
+        let mut anno = annotation("backoff", "", "", 0);
+        assert_eq!(
+            relocate(&utf16("no such word"), &mut anno),
+            Relocation::Orphaned
+        );
+        assert_eq!(anno["orphaned"], true);
+        assert_eq!(anno["anchor"]["exact"], "backoff");
+        assert_eq!(
+            relocate(&utf16("still nothing"), &mut anno),
+            Relocation::Unchanged
+        );
+
+        assert_eq!(
+            relocate(&utf16("use backoff"), &mut anno),
+            Relocation::Moved
+        );
+        assert!(anno.get("orphaned").is_none());
+        assert_eq!(anno["anchor"]["position"], 4);
+    }
+
+    #[test]
+    fn fragments_decide_orphaning_for_version_2_anchors() {
+        let mut anno = serde_json::json!({
+            "id": "anno-2",
+            "anchor": {
+                "version": 2, "position": 0, "exact": "one\ntwo", "prefix": "", "suffix": "",
+                "fragments": [
+                    { "position": 0, "exact": "one", "prefix": "", "suffix": "", "blockTag": "P" },
+                    { "position": 4, "exact": "two", "prefix": "", "suffix": "", "blockTag": "P" }
+                ]
+            }
+        });
+        // The flat quote spans a block break that is gone; fragments still match.
+        let text = utf16("zero one\n\ntwo");
+        assert_eq!(relocate(&text, &mut anno), Relocation::Moved);
+        assert_eq!(anno["anchor"]["fragments"][1]["position"], 10);
+        assert!(anno.get("orphaned").is_none());
+    }
+
+    #[test]
+    fn reanchor_file_persists_changed_annotations() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let stored = annotation("Retry loop", "", "", 0);
+        conn.execute(
+            "INSERT INTO annotations (id, file_path, data) VALUES ('anno-1', '/ws/a.md', ?1)",
+            [stored.to_string()],
+        )
+        .unwrap();
+
+        let changed = reanchor_file(&conn, "/ws/a.md", "# Notes\n\nFirst a Retry loop.").unwrap();
+        assert_eq!(changed.len(), 1);
+        let data: String = conn
+            .query_row(
+                "SELECT data FROM annotations WHERE id = 'anno-1'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        let data: Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(data["anchor"]["suffix"], changed[0]["anchor"]["suffix"]);
+        assert!(data["anchor"]["position"].as_u64().unwrap() > 0);
+
+        assert!(reanchor_file(&conn, "/ws/other.md", "# Other")
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+//! Recently viewed and pinned documents, per browser.
+//!
+//! Each rendered Markdown page is recorded against the browser's client
+//! cookie (the one private viewed state uses), so a workspace root can offer
+//! the documents a reader keeps coming back to above its file tree. Pins are
+//! the reader's own favorites and stay until unpinned; views age out once a
+//! browser has more than [`VIEW_HISTORY_LIMIT`] of them.
+
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+/// Views kept per browser; older ones are forgotten as new ones arrive.
+const VIEW_HISTORY_LIMIT: usize = 100;
+
+pub(crate) fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS file_views (
+            client_id TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            viewed_at INTEGER NOT NULL,
+            PRIMARY KEY (client_id, file_path)
+        );
+        CREATE TABLE IF NOT EXISTS pinned_files (
+            client_id TEXT NOT NULL,
+            file_path TEXT NOT NULL,
+            pinned_at INTEGER NOT NULL,
+            PRIMARY KEY (client_id, file_path)
+        );",
+    )
+}
+
+/// Record that `client_id` just viewed `file_path`.
+pub(crate) fn record_view(
+    conn: &Connection,
+    client_id: &str,
+    file_path: &str,
+) -> rusqlite::Result<()> {
+    let now = crate::annotations::now_millis() as i64;
+    conn.execute(
+        "INSERT OR REPLACE INTO file_views (client_id, file_path, viewed_at) VALUES (?1, ?2, ?3)",
+        params![client_id, file_path, now],
+    )?;
+    conn.execute(
+        "DELETE FROM file_views WHERE client_id = ?1 AND file_path NOT IN (
+            SELECT file_path FROM file_views WHERE client_id = ?1
+            ORDER BY viewed_at DESC LIMIT ?2
+        )",
+        params![client_id, VIEW_HISTORY_LIMIT as i64],
+    )?;
+    Ok(())
+}
+
+/// Pin `file_path` for `client_id`, or unpin it.
+pub(crate) fn set_pinned(
+    conn: &Connection,
+    client_id: &str,
+    file_path: &str,
+    pinned: bool,
+) -> rusqlite::Result<()> {
+    if pinned {
+        let now = crate::annotations::now_millis() as i64;
+        conn.execute(
+            "INSERT OR IGNORE INTO pinned_files (client_id, file_path, pinned_at) VALUES (?1, ?2, ?3)",
+            params![client_id, file_path, now],
+        )?;
+    } else {
+        conn.execute(
+            "DELETE FROM pinned_files WHERE client_id = ?1 AND file_path = ?2",
+            params![client_id, file_path],
+        )?;
+    }
+    Ok(())
+}
+
+/// The files under `root` that `client_id` viewed, most recent first.
+pub(crate) fn recent_files(
+    conn: &Connection,
+    client_id: &str,
+    root: &Path,
+) -> rusqlite::Result<Vec<String>> {
+    files_under(
+        conn,
+        "SELECT file_path FROM file_views WHERE client_id = ?1 ORDER BY viewed_at DESC",
+        client_id,
+        root,
+    )
+}
+
+/// The files under `root` that `client_id` pinned, in the order pinned.
+pub(crate) fn pinned_files(
+    conn: &Connection,
+    client_id: &str,
+    root: &Path,
+) -> rusqlite::Result<Vec<String>> {
+    files_under(
+        conn,
+        "SELECT file_path FROM pinned_files WHERE client_id = ?1 ORDER BY pinned_at, file_path",
+        client_id,
+        root,
+    )
+}
+
+fn files_under(
+    conn: &Connection,
+    sql: &str,
+    client_id: &str,
+    root: &Path,
+) -> rusqlite::Result<Vec<String>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([client_id], |row| row.get::<_, String>(0))?;
+    let mut files = Vec::new();
+    for row in rows {
+        let file_path = row?;
+        if Path::new(&file_path).starts_with(root) {
+            files.push(file_path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn views_are_per_client_newest_first_and_capped() {
+        let conn = conn();
+        record_view(&conn, "c1", "/ws/a.md").unwrap();
+        record_view(&conn, "c1", "/ws/b.md").unwrap();
+        record_view(&conn, "c2", "/ws/c.md").unwrap();
+        record_view(&conn, "c1", "/other/d.md").unwrap();
+        // Age the earlier views so the revisit sorts first.
+        conn.execute("UPDATE file_views SET viewed_at = 1", [])
+            .unwrap();
+        record_view(&conn, "c1", "/ws/a.md").unwrap();
+
+        assert_eq!(
+            recent_files(&conn, "c1", Path::new("/ws")).unwrap(),
+            ["/ws/a.md", "/ws/b.md"]
+        );
+        assert_eq!(
+            recent_files(&conn, "c2", Path::new("/ws")).unwrap(),
+            ["/ws/c.md"]
+        );
+
+        for i in 0..VIEW_HISTORY_LIMIT + 5 {
+            conn.execute(
+                "INSERT INTO file_views (client_id, file_path, viewed_at) VALUES ('c3', ?1, ?2)",
+                params![format!("/ws/{i}.md"), i as i64],
+            )
+            .unwrap();
+        }
+        record_view(&conn, "c3", "/ws/new.md").unwrap();
+        let recent = recent_files(&conn, "c3", Path::new("/ws")).unwrap();
+        assert_eq!(recent.len(), VIEW_HISTORY_LIMIT);
+        assert_eq!(recent[0], "/ws/new.md");
+        assert!(!recent.contains(&"/ws/0.md".to_string()));
+    }
+
+    #[test]
+    fn pins_toggle_and_keep_their_order() {
+        let conn = conn();
+        set_pinned(&conn, "c1", "/ws/b.md", true).unwrap();
+        conn.execute("UPDATE pinned_files SET pinned_at = 1", [])
+            .unwrap();
+        set_pinned(&conn, "c1", "/ws/a.md", true).unwrap();
+        // Pinning again keeps the original position.
+        set_pinned(&conn, "c1", "/ws/b.md", true).unwrap();
+        assert_eq!(
+            pinned_files(&conn, "c1", Path::new("/ws")).unwrap(),
+            ["/ws/b.md", "/ws/a.md"]
+        );
+        assert!(pinned_files(&conn, "c2", Path::new("/ws"))
+            .unwrap()
+            .is_empty());
+
+        set_pinned(&conn, "c1", "/ws/b.md", false).unwrap();
+        assert_eq!(
+            pinned_files(&conn, "c1", Path::new("/ws")).unwrap(),
+            ["/ws/a.md"]
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Markdown fetched over HTTP(S): `markon https://…/README.md` and the
+//! `/_/remote?url=` route.
+//!
+//! The route is off unless the server was started with `--allow-remote`
+//! hosts, since it makes the server fetch URLs on a visitor's behalf.
+//! Redirects are followed only while they stay on an allowed host. Loopback,
+//! private and link-local addresses (a cloud metadata service at
+//! 169.254.169.254, say) are refused unless the allowlist names that host or
+//! address itself: `*` and `*.example.com` never reach them. Relative
+//! images in a fetched document are resolved against its URL, so they load
+//! from beside the original. Documents are kept for [`CACHE_TTL`].
+
+use reqwest::Url;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Largest document fetched; anything bigger is refused.
+pub const MAX_REMOTE_BYTES: usize = 5 * 1024 * 1024;
+
+/// How long a fetched document is served before it is fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Documents the cache holds before it starts over.
+const CACHE_LIMIT: usize = 64;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(20);
+
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RemoteError {
+    #[error("not an http(s) URL: {0}")]
+    InvalidUrl(String),
+    #[error("remote Markdown is disabled; start markon with --allow-remote <HOST>")]
+    Disabled,
+    #[error("host {0} is not in --allow-remote")]
+    HostNotAllowed(String),
+    #[error(
+        "host {0} is a private or loopback address; name it in --allow-remote to fetch from it"
+    )]
+    PrivateAddress(String),
+    #[error("fetch failed: {0}")]
+    Fetch(String),
+    #[error("remote server answered {0}")]
+    Status(u16),
+    #[error("document is larger than {} MiB", MAX_REMOTE_BYTES / 1024 / 1024)]
+    TooLarge,
+}
+
+/// Whether `value` names a remote document rather than a local path.
+pub fn is_remote_url(value: &str) -> bool {
+    let lower = value.trim_start().to_ascii_lowercase();
+    lower.starts_with("http://") || lower.starts_with("https://")
+}
+
+/// Parse an `http://` or `https://` URL.
+pub fn parse_url(value: &str) -> Result<Url, RemoteError> {
+    let url = Url::parse(value.trim()).map_err(|_| RemoteError::InvalidUrl(value.to_string()))?;
+    if !matches!(url.scheme(), "http" | "https") || url.host_str().is_none() {
+        return Err(RemoteError::InvalidUrl(value.to_string()));
+    }
+    Ok(url)
+}
+
+/// Hosts remote documents may be read from. An entry is a host name, a
+/// `*.example.com` suffix, or `*` for any host.
+#[derive(Debug, Clone, Default)]
+pub struct RemoteAllowlist {
+    hosts: Vec<String>,
+}
+
+impl RemoteAllowlist {
+    pub fn new(hosts: &[String]) -> Self {
+        Self {
+            hosts: hosts
+                .iter()
+                .map(|host| host.trim().trim_end_matches('.').to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    pub fn allows(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.hosts
+            .iter()
+            .any(|allowed| match allowed.strip_prefix("*.") {
+                Some(suffix) => host
+                    .strip_suffix(suffix)
+                    .is_some_and(|rest| rest.ends_with('.')),
+                None => allowed == "*" || *allowed == host,
+            })
+    }
+
+    /// Whether `host`, resolved to `ip`, may be connected to: any public
+    /// address, and others only when the host or address is listed as is.
+    fn reaches(&self, host: &str, ip: IpAddr) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        is_public(ip)
+            || self
+                .hosts
+                .iter()
+                .any(|allowed| *allowed == host || *allowed == ip.to_string())
+    }
+
+    /// [`Self::reaches`] for a URL naming an address instead of a host name,
+    /// which is connected to without resolving it. Host names pass here;
+    /// [`PublicResolver`] checks what they resolve to.
+    fn reaches_literal(&self, url: &Url) -> bool {
+        literal_ip(url).is_none_or(|ip| self.reaches(&ip.to_string(), ip))
+    }
+}
+
+/// The address a URL names in place of a host name.
+fn literal_ip(url: &Url) -> Option<IpAddr> {
+    let host = url.host_str()?;
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+/// Whether `ip` is on the public internet rather than this machine, a
+/// private network or a link.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (64..128).contains(&b)))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(v4) => is_public(v4.into()),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local, fc00::/7, and link-local, fe80::/10.
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Resolves host names for the fetching client, dropping the addresses the
+/// allowlist doesn't reach. Checking at connect time, not only before the
+/// fetch, keeps a name that re-resolves to an internal address (DNS
+/// rebinding) or a redirect to one from getting through.
+struct PublicResolver {
+    allowlist: Arc<RemoteAllowlist>,
+}
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let allowlist = self.allowlist.clone();
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+                .await?
+                .filter(|addr| allowlist.reaches(&host, addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(RemoteError::PrivateAddress(host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// A fetched document, its relative images already resolved.
+#[derive(Debug)]
+pub struct RemoteDocument {
+    /// Where the document was read from, after redirects.
+    pub url: Url,
+    pub markdown: String,
+}
+
+impl RemoteDocument {
+    /// The last path segment, as a Markdown file name: `README.md`, or
+    /// `remote.md` for a URL without one.
+    pub fn file_name(&self) -> String {
+        let name = self
+            .url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .map(|segment| {
+                urlencoding::decode(segment)
+                    .map(|name| name.into_owned())
+                    .unwrap_or_else(|_| segment.to_string())
+            })
+            .filter(|name| !name.is_empty() && !name.contains(['/', '\\']))
+            .unwrap_or_else(|| "remote".to_string());
+        if crate::markdown::MarkdownExtensions::default().matches(std::path::Path::new(&name)) {
+            name
+        } else {
+            format!("{name}.md")
+        }
+    }
+}
+
+/// Fetches remote documents for hosts on one allowlist and caches them.
+pub struct RemoteMarkdown {
+    allowlist: Arc<RemoteAllowlist>,
+    client: reqwest::Client,
+    cache: Mutex<HashMap<Url, (Instant, Arc<RemoteDocument>)>>,
+}
+
+impl RemoteMarkdown {
+    pub fn new(allowlist: RemoteAllowlist) -> Self {
+        let allowlist = Arc::new(allowlist);
+        let redirects = allowlist.clone();
+        let client = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .dns_resolver(Arc::new(PublicResolver {
+                allowlist: allowlist.clone(),
+            }))
+            .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if redirects.allows(attempt.url())
+                    && redirects.reaches_literal(attempt.url())
+                {
+                    attempt.follow()
+                } else {
+                    attempt.stop()
+                }
+            }))
+            .build()
+            .expect("failed to build the remote Markdown HTTP client");
+        Self {
+            allowlist,
+            client,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.allowlist.is_empty()
+    }
+
+    /// The document at `url`, from the cache while it is fresh.
+    pub async fn fetch(&self, url: &Url) -> Result<Arc<RemoteDocument>, RemoteError> {
+        if !self.enabled() {
+            return Err(RemoteError::Disabled);
+        }
+        if !self.allowlist.allows(url) {
+            return Err(RemoteError::HostNotAllowed(
+                url.host_str().unwrap_or_default().to_string(),
+            ));
+        }
+        self.check_addresses(url).await?;
+        if let Some((fetched_at, document)) = self.lock().get(url) {
+            if fetched_at.elapsed() < CACHE_TTL {
+                return Ok(document.clone());
+            }
+        }
+        let document = Arc::new(self.download(url).await?);
+        let mut cache = self.lock();
+        if cache.len() >= CACHE_LIMIT {
+            cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < CACHE_TTL);
+            if cache.len() >= CACHE_LIMIT {
+                cache.clear();
+            }
+        }
+        cache.insert(url.clone(), (Instant::now(), document.clone()));
+        Ok(document)
+    }
+
+    /// Refuse `url` up front when its host is, or resolves only to, an
+    /// address the allowlist doesn't reach. The client's resolver checks
+    /// again when it connects.
+    async fn check_addresses(&self, url: &Url) -> Result<(), RemoteError> {
+        let host = url.host_str().unwrap_or_default();
+        let reachable = if literal_ip(url).is_some() {
+            self.allowlist.reaches_literal(url)
+        } else {
+            let port = url.port_or_known_default().unwrap_or(80);
+            tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| RemoteError::Fetch(e.to_string()))?
+                .any(|addr| self.allowlist.reaches(host, addr.ip()))
+        };
+        if reachable {
+            Ok(())
+        } else {
+            Err(RemoteError::PrivateAddress(host.to_string()))
+        }
+    }
+
+    async fn download(&self, url: &Url) -> Result<RemoteDocument, RemoteError> {
+        let fetch_error = |e: reqwest::Error| RemoteError::Fetch(e.without_url().to_string());
+        let mut response = self
+            .client
+            .get(url.clone())
+            .header(reqwest::header::ACCEPT, "text/markdown, text/plain, */*")
+            .send()
+            .await
+            .map_err(fetch_error)?;
+        if !response.status().is_success() {
+            return Err(RemoteError::Status(response.status().as_u16()));
+        }
+        if response
+            .content_length()
+            .is_some_and(|len| len > MAX_REMOTE_BYTES as u64)
+        {
+            return Err(RemoteError::TooLarge);
+        }
+        let final_url = response.url().clone();
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(fetch_error)? {
+            if body.len() + chunk.len() > MAX_REMOTE_BYTES {
+                return Err(RemoteError::TooLarge);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let markdown = String::from_utf8_lossy(&body);
+        let markdown = crate::markdown::resolve_remote_images(&markdown, &final_url).into_owned();
+        Ok(RemoteDocument {
+            url: final_url,
+            markdown,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<Url, (Instant, Arc<RemoteDocument>)>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_matches_exact_hosts_suffixes_and_wildcard() {
+        let url = |value: &str| parse_url(value).unwrap();
+        let allowlist = RemoteAllowlist::new(&[
+            "Raw.GitHubUserContent.com".to_string(),
+            "*.example.com".to_string(),
+        ]);
+        assert!(allowlist.allows(&url("https://raw.githubusercontent.com/a/b/README.md")));
+        assert!(allowlist.allows(&url("http://docs.example.com/guide.md")));
+        assert!(!allowlist.allows(&url("http://example.com/guide.md")));
+        assert!(!allowlist.allows(&url("http://badexample.com/guide.md")));
+        assert!(!allowlist.allows(&url("http://127.0.0.1/secret.md")));
+        assert!(RemoteAllowlist::new(&["*".to_string()]).allows(&url("http://10.0.0.1/a.md")));
+        assert!(RemoteAllowlist::new(&[]).is_empty());
+
+        assert!(parse_url("file:///etc/passwd").is_err());
+        assert!(parse_url("notes.md").is_err());
+        assert!(is_remote_url("HTTPS://host/a.md"));
+        assert!(!is_remote_url("docs/a.md"));
+    }
+
+    #[test]
+    fn file_name_comes_from_the_last_path_segment() {
+        let document = |value: &str| RemoteDocument {
+            url: parse_url(value).unwrap(),
+            markdown: String::new(),
+        };
+        assert_eq!(
+            document("https://host/repo/main/READ%20ME.md").file_name(),
+            "READ ME.md"
+        );
+        assert_eq!(document("https://host/notes").file_name(), "notes.md");
+        assert_eq!(document("https://host/").file_name(), "remote.md");
+    }
+
+    #[test]
+    fn only_public_addresses_pass_without_naming_them() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.20",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["93.184.216.34", "2606:4700::1111", "::ffff:1.1.1.1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        let any = RemoteAllowlist::new(&["*".to_string()]);
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(!any.reaches("localhost", loopback));
+        let named = RemoteAllowlist::new(&["localhost".to_string(), "10.0.0.5".to_string()]);
+        assert!(named.reaches("localhost", loopback));
+        assert!(named.reaches("10.0.0.5", "10.0.0.5".parse().unwrap()));
+        assert!(!named.reaches("10.0.0.6", "10.0.0.6".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn fetches_refuse_private_addresses_a_wildcard_would_allow() {
+        let url = |value: &str| parse_url(value).unwrap();
+        let any = RemoteMarkdown::new(RemoteAllowlist::new(&["*".to_string()]));
+        for target in [
+            "http://127.0.0.1:9/a.md",
+            "http://10.0.0.1/a.md",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]:9/a.md",
+            "http://localhost:9/a.md",
+        ] {
+            assert!(
+                matches!(
+                    any.fetch(&url(target)).await,
+                    Err(RemoteError::PrivateAddress(_))
+                ),
+                "{target}"
+            );
+        }
+
+        // Naming the host or address lets the fetch go ahead (and fail here,
+        // with nothing listening).
+        for (entry, target) in [
+            ("127.0.0.1", "http://127.0.0.1:9/a.md"),
+            ("localhost", "http://localhost:9/a.md"),
+        ] {
+            let named = RemoteMarkdown::new(RemoteAllowlist::new(&[entry.to_string()]));
+            assert!(
+                matches!(named.fetch(&url(target)).await, Err(RemoteError::Fetch(_))),
+                "{entry}"
+            );
+        }
+
+        // The client's own resolver refuses too, for redirects and names
+        // that resolve differently by the time it connects.
+        let resolver = PublicResolver {
+            allowlist: Arc::new(RemoteAllowlist::new(&["*".to_string()])),
+        };
+        let name = "localhost".parse().unwrap();
+        assert!(reqwest::dns::Resolve::resolve(&resolver, name)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,94 @@
+//! Render a Markdown document to HTML without a server, for `markon render`.
+//!
+//! The HTML is what the server puts inside a page's `<article>`; a
+//! standalone document wraps it with the stylesheets inlined. Local image
+//! paths are left as written, so the output works next to the source file.
+//! Diagrams and math are drawn by scripts in the browser and stay as source.
+
+use crate::assets::{CssAssets, Templates};
+use crate::markdown::{default_markdown_engine, document_title, MarkdownEngine};
+
+/// Stylesheets a standalone document inlines after the GitHub Markdown sheet
+/// for its theme: the design tokens and the rendered-content styles.
+const STANDALONE_STYLES: [&str; 2] = ["tokens.css", "editor.css"];
+
+/// The body HTML for `markdown`.
+pub fn render_fragment(markdown: &str) -> String {
+    MarkdownEngine::render(&default_markdown_engine("light", &[]), markdown).html
+}
+
+/// `markdown` as a complete HTML page with its CSS inlined. `theme` is one
+/// of [`crate::theme::BUILT_IN`] other than `auto`; `fallback_title` names the page when the document has
+/// neither a frontmatter title nor a heading.
+pub fn render_standalone(
+    markdown: &str,
+    theme: &str,
+    fallback_title: &str,
+) -> Result<String, String> {
+    let scheme = if crate::theme::base_scheme(theme) == "dark" {
+        "dark"
+    } else {
+        "light"
+    };
+    let markdown_sheet = if scheme == "dark" {
+        "github-markdown-dark.css"
+    } else {
+        "github-markdown-light.css"
+    };
+    let mut styles = String::new();
+    for name in std::iter::once(markdown_sheet).chain(STANDALONE_STYLES) {
+        let file = CssAssets::get(name).ok_or_else(|| format!("missing stylesheet {name}"))?;
+        styles.push_str(&String::from_utf8_lossy(&file.data));
+        styles.push('\n');
+    }
+    let template = Templates::get("standalone.html").ok_or("missing standalone template")?;
+
+    let mut tera = tera::Tera::default();
+    tera.add_raw_template("standalone.html", &String::from_utf8_lossy(&template.data))
+        .map_err(|e| format!("standalone template: {e}"))?;
+    let mut context = tera::Context::new();
+    context.insert("theme", scheme);
+    context.insert(
+        "variant",
+        match theme {
+            "sepia" | "high-contrast" => theme,
+            _ => "",
+        },
+    );
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert(
+        "title",
+        &document_title(markdown).unwrap_or_else(|| fallback_title.to_string()),
+    );
+    context.insert("styles", &styles);
+    context.insert("content", &render_fragment(markdown));
+    tera.render("standalone.html", &context)
+        .map_err(|e| format!("standalone template: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standalone_document_inlines_styles_around_the_fragment() {
+        let markdown = "# Release <notes>\n\nSome `code`.\n";
+        let fragment = render_fragment(markdown);
+        assert!(fragment.contains("<code>code</code>"));
+
+        let page = render_standalone(markdown, "dark", "notes.md").unwrap();
+        assert!(page.starts_with("<!DOCTYPE html>"));
+        assert!(page.contains("data-theme=\"dark\""));
+        assert!(page.contains("<title>Release &lt;notes&gt;</title>"));
+        assert!(page.contains("--markon-bg-default"));
+        assert!(page.contains(&fragment));
+        assert!(!page.contains("<script"));
+
+        let untitled = render_standalone("plain text\n", "light", "notes.md").unwrap();
+        assert!(untitled.contains("<title>notes.md</title>"));
+        assert!(untitled.contains("data-theme=\"light\">"));
+
+        let sepia = render_standalone(markdown, "sepia", "notes.md").unwrap();
+        assert!(sepia.contains("data-theme=\"light\" data-theme-variant=\"sepia\""));
+    }
+}
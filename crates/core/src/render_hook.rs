@@ -0,0 +1,212 @@
+//! Hooks that extend Markdown rendering without patching the renderer.
+//!
+//! A [`RenderHook`] sees a document at three stages: its source before it
+//! is parsed, each text run, link destination and fenced code block as it
+//! renders, and the finished HTML. Hooks passed to [`register_render_hook`]
+//! apply to every renderer in the process, the server's included; ones
+//! given to [`crate::MarkdownRenderer::with_hook`] only to that renderer.
+//! Annotation notes come from any collaborator and are rendered without
+//! hooks.
+//!
+//! The built-ins, [`LinkSchemes`] (`--link-scheme`) and the WASM plugins in
+//! [`crate::wasm_plugin`] (`--plugins`), apply only to the server they were
+//! given to.
+
+use std::borrow::Cow;
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+/// A custom transformation of rendered Markdown. Every stage defaults to
+/// leaving the document alone; when several hooks handle the same text run
+/// or link, the first one registered wins.
+pub trait RenderHook: Send + Sync {
+    /// Rewrite the source before it is parsed, e.g. to expand macros into
+    /// Markdown.
+    fn pre_parse<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(markdown)
+    }
+
+    /// HTML to emit for a run of plain text instead of the escaped text.
+    /// The HTML is used as is, so escape whatever is kept of `text`.
+    fn text(&self, _text: &str) -> Option<String> {
+        None
+    }
+
+    /// Where a link written with `url` should point instead. Unsafe schemes
+    /// are still dropped afterwards.
+    fn link_url(&self, _url: &str) -> Option<String> {
+        None
+    }
+
+    /// HTML to emit for a fenced code block whose info string is `info`
+    /// instead of the highlighted code, e.g. to draw a custom diagram. The
+    /// HTML is used as is.
+    fn fence(&self, _info: &str, _code: &str) -> Option<String> {
+        None
+    }
+
+    /// Rewrite the document's HTML after rendering.
+    fn post_html(&self, html: String) -> String {
+        html
+    }
+}
+
+lazy_static! {
+    /// Hooks registered by the embedding program.
+    static ref REGISTERED: RwLock<Vec<Arc<dyn RenderHook>>> = RwLock::new(Vec::new());
+}
+
+/// Apply `hook` to every renderer created from now on.
+pub fn register_render_hook(hook: Arc<dyn RenderHook>) {
+    REGISTERED
+        .write()
+        .expect("render hooks lock poisoned")
+        .push(hook);
+}
+
+/// The hooks a new renderer starts with.
+pub(crate) fn registered_hooks() -> Vec<Arc<dyn RenderHook>> {
+    REGISTERED
+        .read()
+        .expect("render hooks lock poisoned")
+        .clone()
+}
+
+/// Links written `NAME:rest` that point at a URL prefix followed by `rest`
+/// (`--link-scheme jira=https://jira.example.com/browse/` turns
+/// `[ABC-1](jira:ABC-1)` into a link to `…/browse/ABC-1`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkSchemes {
+    schemes: Vec<(String, String)>,
+}
+
+impl LinkSchemes {
+    /// Parse `NAME=URL` specs. Names are matched case-insensitively and must
+    /// not shadow a scheme links already use; URLs must be http(s).
+    pub fn parse(specs: &[String]) -> Result<Self, String> {
+        let mut schemes = Vec::new();
+        for spec in specs {
+            let (name, url) = spec
+                .split_once('=')
+                .ok_or_else(|| format!("--link-scheme expects NAME=URL, got `{spec}`"))?;
+            let name = name.trim().to_ascii_lowercase();
+            let url = url.trim();
+            let valid_name = name.starts_with(|c: char| c.is_ascii_alphabetic())
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+            if !valid_name {
+                return Err(format!("--link-scheme: `{name}` is not a scheme name"));
+            }
+            if matches!(name.as_str(), "http" | "https" | "mailto" | "tel" | "ftp") {
+                return Err(format!("--link-scheme: `{name}` is a standard scheme"));
+            }
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(format!(
+                    "--link-scheme: `{url}` is not an http(s) URL prefix"
+                ));
+            }
+            schemes.push((name, url.to_string()));
+        }
+        Ok(Self { schemes })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemes.is_empty()
+    }
+}
+
+impl RenderHook for LinkSchemes {
+    fn link_url(&self, url: &str) -> Option<String> {
+        let (name, rest) = url.split_once(':')?;
+        self.schemes
+            .iter()
+            .find(|(scheme, _)| scheme.eq_ignore_ascii_case(name))
+            .map(|(_, prefix)| format!("{prefix}{rest}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown::render_note_html;
+    use crate::MarkdownRenderer;
+
+    /// Expands `{{version}}`, turns `!badge` into a badge, draws `shout`
+    /// fences and stamps the document.
+    struct Macros;
+
+    impl RenderHook for Macros {
+        fn pre_parse<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+            Cow::Owned(markdown.replace("{{version}}", "1.2.3"))
+        }
+
+        fn text(&self, text: &str) -> Option<String> {
+            let (before, after) = text.split_once("!badge")?;
+            Some(format!(
+                "{}<span class=\"badge\">new</span>{}",
+                html_escape::encode_text(before),
+                html_escape::encode_text(after)
+            ))
+        }
+
+        fn fence(&self, info: &str, code: &str) -> Option<String> {
+            (info == "shout").then(|| {
+                format!(
+                    "<p class=\"shout\">{}</p>",
+                    html_escape::encode_text(&code.trim().to_uppercase())
+                )
+            })
+        }
+
+        fn post_html(&self, html: String) -> String {
+            format!("{html}<!-- stamped -->")
+        }
+    }
+
+    #[test]
+    fn hooks_run_at_each_stage() {
+        let renderer = MarkdownRenderer::new("light")
+            .with_hook(Arc::new(Macros))
+            .with_hook(Arc::new(
+                LinkSchemes::parse(&["Jira=https://jira.example.com/browse/".into()]).unwrap(),
+            ));
+        let html = renderer
+            .render_document(
+                "# Release {{version}}\n\nNow !badge R&D [ABC-1](JIRA:ABC-1) [x](other.md)\n\n\
+                 ```shout\nhey\n```\n\n```text\nquiet\n```\n",
+            )
+            .html;
+        assert!(html.contains("Release 1.2.3</h1>"), "{html}");
+        assert!(
+            html.contains("Now <span class=\"badge\">new</span> R&amp;D"),
+            "{html}"
+        );
+        assert!(
+            html.contains("<a href=\"https://jira.example.com/browse/ABC-1\">ABC-1</a>"),
+            "{html}"
+        );
+        assert!(html.contains("<a href=\"other.md\">x</a>"), "{html}");
+        assert!(html.contains("<p class=\"shout\">HEY</p>"), "{html}");
+        assert!(html.contains("quiet") && !html.contains("QUIET"), "{html}");
+        assert!(html.ends_with("<!-- stamped -->"), "{html}");
+
+        // Notes are rendered without hooks, registered or not.
+        assert!(!render_note_html("!badge").contains("badge\""));
+        assert!(!render_note_html("```shout\nhey\n```").contains("shout\""));
+    }
+
+    #[test]
+    fn link_schemes_reject_malformed_specs() {
+        for spec in [
+            "jira",
+            "1ira=https://x/",
+            "https=https://x/",
+            "jira=javascript:alert(1)",
+        ] {
+            assert!(LinkSchemes::parse(&[spec.to_string()]).is_err(), "{spec}");
+        }
+        assert!(LinkSchemes::parse(&[]).unwrap().is_empty());
+    }
+}
@@ -1,38 +1,565 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
-#[cfg(test)]
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::{
-    collections::BTreeSet,
+    collections::{BTreeSet, HashMap, HashSet},
+    ops::Bound,
     path::{Path, PathBuf},
-    sync::{Arc, Mutex, MutexGuard},
+    sync::{
+        atomic::{AtomicI64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
 };
 use tantivy::{
-    collector::TopDocs,
-    query::QueryParser,
+    collector::{Count, FacetCollector, TopDocs},
+    query::{BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery},
     schema::*,
     snippet::SnippetGenerator,
-    tokenizer::{LowerCaser, TextAnalyzer},
-    Index, IndexReader, IndexWriter, TantivyDocument, TantivyError,
+    tokenizer::{Language, LowerCaser, NgramTokenizer, SimpleTokenizer, Stemmer, TextAnalyzer},
+    DateTime, Index, IndexReader, IndexWriter, TantivyDocument, TantivyError,
 };
 use tantivy_jieba::JiebaTokenizer;
 
+use crate::frontmatter;
+use crate::markdown::{heading_sections, HeadingSection};
+use crate::metadata::MetadataCache;
 use crate::workspace_fs::{WorkspaceFs, WorkspaceRelPath};
 
 const INDEX_DOCUMENT_BATCH_SIZE: usize = 64;
+/// Files per commit during the initial build. Committing in batches bounds
+/// the writer's pending segment and gives large trees visible progress.
+const INDEX_COMMIT_BATCH_SIZE: usize = 2_000;
+
+/// `kind` of the one document per file that carries its title and file name.
+const KIND_FILE: &str = "file";
+/// `kind` of the extra documents indexed for each later top-level heading.
+const KIND_SECTION: &str = "section";
+
+/// Query-time score multipliers. A query naming a document should rank it
+/// above documents that only mention the words: file name first, then title,
+/// then a section heading, then body text (boost 1).
+const FILE_NAME_BOOST: f32 = 5.0;
+const TITLE_BOOST: f32 = 3.0;
+const HEADING_BOOST: f32 = 2.0;
+
+/// Page size used when the client does not pass `limit`.
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+/// Largest page the search endpoint will return in one response.
+pub const MAX_SEARCH_LIMIT: usize = 100;
+/// Deepest `offset` a search may page to. Tantivy reserves room for
+/// `offset + limit` hits up front, so an unbounded offset is an allocation
+/// of the caller's choosing.
+pub const MAX_SEARCH_OFFSET: usize = 10_000;
+
+/// Largest number of matching lines reported per file in regex mode.
+const MAX_REGEX_LINES_PER_FILE: usize = 50;
+/// Compiled-program budget for user-supplied patterns, so a pathological
+/// regex is rejected instead of consuming the server's memory.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// Extra paths kept out of search on top of the workspace's `.gitignore` /
+/// `.ignore` files, e.g. vendored docs in `node_modules` or build output.
+///
+/// Patterns are globs matched against workspace routes. A pattern without a
+/// `/` matches a file or directory name at any depth (`node_modules`,
+/// `*.generated.md`); one with a `/` is anchored at the workspace root
+/// (`docs/archive`). Matching a directory excludes everything below it.
+#[derive(Debug, Clone, Default)]
+pub struct SearchExclude {
+    patterns: Vec<String>,
+    set: GlobSet,
+}
+
+impl SearchExclude {
+    pub fn new(patterns: &[String]) -> Result<Self, String> {
+        let mut builder = GlobSetBuilder::new();
+        let mut kept = Vec::new();
+        for raw in patterns {
+            let pattern = raw.trim().trim_start_matches('/').trim_end_matches('/');
+            if pattern.is_empty() {
+                continue;
+            }
+            let anchored = if pattern.contains('/') {
+                pattern.to_string()
+            } else {
+                format!("**/{pattern}")
+            };
+            for glob in [anchored.clone(), format!("{anchored}/**")] {
+                builder.add(
+                    GlobBuilder::new(&glob)
+                        .literal_separator(true)
+                        .build()
+                        .map_err(|e| format!("invalid search exclude pattern '{raw}': {e}"))?,
+                );
+            }
+            kept.push(pattern.to_string());
+        }
+        let set = builder
+            .build()
+            .map_err(|e| format!("invalid search exclude patterns: {e}"))?;
+        Ok(Self {
+            patterns: kept,
+            set,
+        })
+    }
+
+    pub fn patterns(&self) -> &[String] {
+        &self.patterns
+    }
+
+    pub(crate) fn is_excluded(&self, route: &WorkspaceRelPath) -> bool {
+        !self.patterns.is_empty() && self.set.is_match(route.as_path())
+    }
+}
+
+/// Name the text analyzer is registered under; every tokenized field uses it.
+const TEXT_TOKENIZER: &str = "markon_text";
+
+/// Text analyzer used for tokenized fields (`--search-lang`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SearchLanguage {
+    /// Jieba word segmentation: Chinese plus unsegmented Latin words.
+    #[default]
+    Jieba,
+    /// Whitespace/punctuation tokens with English stemming, so "running"
+    /// matches "run".
+    English,
+    /// Overlapping one- and two-character grams. Needs no dictionary, which
+    /// makes it a reasonable fit for Japanese and Korean text.
+    Ngram,
+}
+
+impl std::str::FromStr for SearchLanguage {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "jieba" | "zh" => Ok(Self::Jieba),
+            "en" | "english" => Ok(Self::English),
+            "ngram" | "cjk" | "ja" | "ko" => Ok(Self::Ngram),
+            other => Err(format!(
+                "unknown search language '{other}' (expected jieba, en, or ngram)"
+            )),
+        }
+    }
+}
+
+impl SearchLanguage {
+    /// Every analyzer lower-cases, so Latin search is case-insensitive in all
+    /// modes. The same analyzer runs at index and query time.
+    fn analyzer(self) -> tantivy::Result<TextAnalyzer> {
+        Ok(match self {
+            Self::Jieba => TextAnalyzer::builder(JiebaTokenizer {})
+                .filter(LowerCaser)
+                .build(),
+            Self::English => TextAnalyzer::builder(SimpleTokenizer::default())
+                .filter(LowerCaser)
+                .filter(Stemmer::new(Language::English))
+                .build(),
+            Self::Ngram => TextAnalyzer::builder(NgramTokenizer::new(1, 2, false)?)
+                .filter(LowerCaser)
+                .build(),
+        })
+    }
+}
+
+/// Default snippet length, matching Tantivy's own default.
+pub const DEFAULT_SNIPPET_CHARS: usize = 150;
+/// Longest snippet a caller may ask for.
+pub const MAX_SNIPPET_CHARS: usize = 1_000;
+
+/// How result snippets are cut and how matched terms are marked up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnippetOptions {
+    /// Longest snippet, in characters.
+    pub max_chars: usize,
+    /// Element wrapped around each matched term, e.g. `b` or `mark`.
+    pub highlight_tag: String,
+    /// Optional `class` attribute on that element.
+    pub highlight_class: Option<String>,
+}
+
+impl Default for SnippetOptions {
+    fn default() -> Self {
+        Self {
+            max_chars: DEFAULT_SNIPPET_CHARS,
+            highlight_tag: "b".to_string(),
+            highlight_class: None,
+        }
+    }
+}
 
-/// Query string for `GET /_/{workspace_id}/search?q=…`.
-#[derive(Deserialize)]
+impl SnippetOptions {
+    /// Validated options: the length is 1..=[`MAX_SNIPPET_CHARS`] and the tag
+    /// is a bare element name, so the markup cannot inject attributes.
+    pub fn new(
+        max_chars: usize,
+        highlight_tag: &str,
+        highlight_class: Option<&str>,
+    ) -> Result<Self, String> {
+        if !(1..=MAX_SNIPPET_CHARS).contains(&max_chars) {
+            return Err(format!(
+                "snippet length must be between 1 and {MAX_SNIPPET_CHARS}"
+            ));
+        }
+        if highlight_tag.is_empty() || !highlight_tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("invalid highlight tag '{highlight_tag}'"));
+        }
+        Ok(Self {
+            max_chars,
+            highlight_tag: highlight_tag.to_ascii_lowercase(),
+            highlight_class: highlight_class
+                .map(str::trim)
+                .filter(|class| !class.is_empty())
+                .map(str::to_string),
+        })
+    }
+
+    fn open_tag(&self) -> String {
+        match &self.highlight_class {
+            Some(class) => format!(
+                "<{} class=\"{}\">",
+                self.highlight_tag,
+                html_escape::encode_double_quoted_attribute(class)
+            ),
+            None => format!("<{}>", self.highlight_tag),
+        }
+    }
+
+    fn close_tag(&self) -> String {
+        format!("</{}>", self.highlight_tag)
+    }
+}
+
+const MIB: u64 = 1024 * 1024;
+/// Writer heap when no `--search-writer-memory` is given.
+pub const DEFAULT_WRITER_MEMORY: usize = 50 * MIB as usize;
+/// Smallest writer heap Tantivy accepts (one indexing thread's 15 MB arena),
+/// rounded up to whole MiB.
+pub const MIN_WRITER_MEMORY: usize = 15 * MIB as usize;
+/// Bytes of one file indexed when no `--search-max-file-size` is given.
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * MIB;
+
+/// Memory and size bounds for a workspace index, so a tree of generated
+/// Markdown can't grow the process without limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SearchLimits {
+    /// Heap the Tantivy writer fills before flushing a segment, shared by
+    /// its indexing threads.
+    pub writer_memory: usize,
+    /// Bytes of a file's Markdown that are indexed. A larger file is indexed
+    /// up to the cut, so its name, title and opening stay searchable.
+    pub max_file_size: u64,
+    /// Markdown bytes the whole index may hold; files that would go past it
+    /// are left out. `None` is unbounded.
+    pub max_index_size: Option<u64>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        Self {
+            writer_memory: DEFAULT_WRITER_MEMORY,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_index_size: None,
+        }
+    }
+}
+
+impl SearchLimits {
+    /// Limits from the `--search-writer-memory`, `--search-max-file-size` and
+    /// `--search-max-index-size` MiB values; unset ones keep their default.
+    pub fn from_mib(
+        writer_memory: Option<usize>,
+        max_file_size: Option<u64>,
+        max_index_size: Option<u64>,
+    ) -> Result<Self, String> {
+        let defaults = Self::default();
+        let writer_memory = match writer_memory {
+            Some(mib) if mib.saturating_mul(MIB as usize) < MIN_WRITER_MEMORY => {
+                return Err(format!(
+                    "--search-writer-memory must be at least {} MiB",
+                    MIN_WRITER_MEMORY as u64 / MIB
+                ))
+            }
+            Some(mib) => mib.saturating_mul(MIB as usize),
+            None => defaults.writer_memory,
+        };
+        let max_file_size = match max_file_size {
+            Some(0) => return Err("--search-max-file-size must be at least 1 MiB".into()),
+            Some(mib) => mib.saturating_mul(MIB),
+            None => defaults.max_file_size,
+        };
+        let max_index_size = match max_index_size {
+            Some(0) => return Err("--search-max-index-size must be at least 1 MiB".into()),
+            size => size.map(|mib| mib.saturating_mul(MIB)),
+        };
+        Ok(Self {
+            writer_memory,
+            max_file_size,
+            max_index_size,
+        })
+    }
+}
+
+/// Per-workspace search settings fixed when the index is built.
+#[derive(Debug, Clone, Default)]
+pub struct SearchConfig {
+    pub exclude: SearchExclude,
+    pub language: SearchLanguage,
+    pub limits: SearchLimits,
+}
+
+/// How the `q` parameter is interpreted.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Tokenized full-text query against the Tantivy index.
+    #[default]
+    Text,
+    /// Regular expression scanned line by line over every Markdown file,
+    /// bypassing the index.
+    Regex,
+}
+
+/// Query string for `GET /_/{workspace_id}/search?q=…&mode=…&offset=…&limit=…`,
+/// optionally scoped with `path=docs/`, `ext=md`, `tag=`, and `modified_after=`.
+/// In text mode, `tag:name` terms inside `q` are filters too.
+#[derive(Deserialize, Default)]
 pub struct SearchQuery {
     pub q: String,
+    #[serde(default)]
+    pub mode: SearchMode,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default)]
+    pub limit: Option<usize>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub ext: Option<String>,
+    /// Snippet length in characters (default 150, at most 1000).
+    #[serde(default)]
+    pub snippet_chars: Option<usize>,
+    /// Element wrapped around matched terms (default `b`).
+    #[serde(default)]
+    pub highlight_tag: Option<String>,
+    /// `class` attribute for the highlight element.
+    #[serde(default)]
+    pub highlight_class: Option<String>,
+    /// Comma-separated frontmatter tags; a file must carry all of them.
+    #[serde(default)]
+    pub tag: Option<String>,
+    /// Unix seconds or a `YYYY-MM-DD` date (UTC midnight).
+    #[serde(default)]
+    pub modified_after: Option<String>,
+}
+
+impl SearchQuery {
+    /// Parse the scoping parameters; empty values are treated as absent.
+    pub fn filters(&self) -> Result<SearchFilters, String> {
+        let non_empty = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+                .map(str::to_string)
+        };
+        let modified_after = match non_empty(&self.modified_after) {
+            Some(raw) => Some(
+                parse_modified_after(&raw)
+                    .ok_or_else(|| format!("invalid modified_after '{raw}'"))?,
+            ),
+            None => None,
+        };
+        Ok(SearchFilters {
+            path_prefix: non_empty(&self.path)
+                .map(|path| path.trim_start_matches('/').to_string())
+                .filter(|path| !path.is_empty()),
+            ext: non_empty(&self.ext).map(|ext| ext.trim_start_matches('.').to_ascii_lowercase()),
+            tags: non_empty(&self.tag)
+                .map(|tags| {
+                    tags.split(',')
+                        .map(frontmatter::normalize_tag)
+                        .filter(|tag| !tag.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            modified_after,
+        })
+    }
+
+    /// Snippet length and highlight markup requested with the query.
+    pub fn snippet_options(&self) -> Result<SnippetOptions, String> {
+        let defaults = SnippetOptions::default();
+        SnippetOptions::new(
+            self.snippet_chars.unwrap_or(defaults.max_chars),
+            self.highlight_tag
+                .as_deref()
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .unwrap_or(&defaults.highlight_tag),
+            self.highlight_class.as_deref(),
+        )
+    }
+}
+
+/// Restricts a search to a subtree, an extension, frontmatter tags, or
+/// recently changed files.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SearchFilters {
+    /// Workspace route prefix, e.g. `docs/`.
+    pub path_prefix: Option<String>,
+    /// Lower-case extension without the dot.
+    pub ext: Option<String>,
+    /// Normalized frontmatter tags, all of which a file must carry.
+    pub tags: Vec<String>,
+    /// Unix seconds; only files modified strictly after this are returned.
+    pub modified_after: Option<i64>,
+}
+
+impl SearchFilters {
+    /// Whether a file with these frontmatter tags passes the tag filter.
+    fn matches_tags(&self, tags: &[String]) -> bool {
+        self.tags.iter().all(|tag| tags.contains(tag))
+    }
+
+    fn matches(&self, route: &str, modified: Option<i64>) -> bool {
+        self.path_prefix
+            .as_deref()
+            .is_none_or(|prefix| route.starts_with(prefix))
+            && self
+                .ext
+                .as_deref()
+                .is_none_or(|ext| route_extension(route).as_deref() == Some(ext))
+            && self
+                .modified_after
+                .is_none_or(|after| modified.is_some_and(|modified| modified > after))
+    }
+}
+
+/// A line matched by a regex-mode search.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SearchLineMatch {
+    /// 1-based line number in the file.
+    pub line: usize,
+    pub text: String,
 }
 
-/// One hit returned by the workspace search endpoint.
+/// One hit returned by the workspace search endpoint. `heading`/`anchor` are
+/// set when the hit is inside a heading section rather than the top of the
+/// file, so the UI can render "Title › Heading" and link to `path#anchor`.
 #[derive(Serialize, Debug)]
 pub struct SearchResult {
     pub file_path: String,
     pub file_name: String,
     pub title: String,
+    pub heading: Option<String>,
+    pub anchor: Option<String>,
     pub snippet: String,
+    pub score: f32,
+    /// Matching lines; only filled in regex mode.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lines: Vec<SearchLineMatch>,
+}
+
+/// One page of hits plus the total match count, so the UI can paginate and
+/// show "134 results" without fetching them all.
+#[derive(Serialize, Debug, Default)]
+pub struct SearchPage {
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub results: Vec<SearchResult>,
+    /// Shared annotations whose note or quoted text matches; only on the
+    /// first page of a text search by a reader allowed to see annotations.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub annotations: Vec<AnnotationHit>,
+}
+
+/// A frontmatter tag and the number of indexed files carrying it.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+/// Health of a workspace index, for diagnosing a watcher that missed changes.
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct SearchStatus {
+    /// False while the initial build is still running.
+    pub ready: bool,
+    /// Indexed Markdown files.
+    pub documents: usize,
+    /// Heading-section documents indexed alongside those files.
+    pub sections: usize,
+    /// Unix seconds of the most recent commit.
+    pub last_commit: Option<i64>,
+    /// Updates waiting on or holding the index writer.
+    pub pending_updates: usize,
+    /// Markdown bytes held in the index.
+    pub indexed_bytes: u64,
+    /// Files indexed only up to [`SearchLimits::max_file_size`].
+    pub truncated_files: usize,
+    /// Files left out because the index reached
+    /// [`SearchLimits::max_index_size`].
+    pub skipped_files: usize,
+}
+
+/// Most annotation hits returned with one search.
+pub const MAX_ANNOTATION_HITS: usize = 20;
+
+/// A shared annotation matching a search query.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AnnotationHit {
+    pub file_path: String,
+    pub annotation_id: String,
+    /// The annotated source text.
+    pub text: String,
+    pub note: Option<String>,
+    /// Note (or quoted text when there is no note) as escaped HTML with each
+    /// query term in `<b>`.
+    pub snippet: String,
+}
+
+/// Match a stored annotation's JSON against `query`. Every whitespace-separated
+/// term must occur, case-insensitively, in the note or the quoted text.
+pub(crate) fn match_annotation(
+    query: &str,
+    file_path: &str,
+    annotation: &serde_json::Value,
+    snippet: &SnippetOptions,
+) -> Option<AnnotationHit> {
+    let terms: Vec<String> = query.split_whitespace().map(str::to_lowercase).collect();
+    if terms.is_empty() {
+        return None;
+    }
+    let annotation_id = annotation["id"].as_str()?.to_string();
+    let text = annotation["text"].as_str().unwrap_or_default().to_string();
+    let note = annotation["note"]
+        .as_str()
+        .filter(|note| !note.trim().is_empty())
+        .map(str::to_string);
+    let haystack = format!("{}\n{}", note.as_deref().unwrap_or_default(), text).to_lowercase();
+    if !terms.iter().all(|term| haystack.contains(term.as_str())) {
+        return None;
+    }
+    let shown = note.as_deref().unwrap_or(&text);
+    let pattern = terms
+        .iter()
+        .map(|term| regex::escape(term))
+        .collect::<Vec<_>>()
+        .join("|");
+    let snippet = regex::RegexBuilder::new(&pattern)
+        .case_insensitive(true)
+        .build()
+        .map(|regex| highlight_matches(&regex, shown, snippet))
+        .unwrap_or_else(|_| html_escape::encode_text(shown).into_owned());
+    Some(AnnotationHit {
+        file_path: file_path.to_string(),
+        annotation_id,
+        text,
+        note,
+        snippet,
+    })
 }
 
 pub struct SearchIndex {
@@ -42,34 +569,128 @@ pub struct SearchIndex {
     field_path: Field,
     field_file_name: Field,
     field_title: Field,
+    field_heading: Field,
+    field_anchor: Field,
+    field_doc_title: Field,
+    field_kind: Field,
+    field_ext: Field,
+    field_modified: Field,
+    field_tags: Field,
     field_content: Field,
     start_dir: PathBuf,
     workspace_fs: Arc<WorkspaceFs>,
+    exclude: SearchExclude,
+    limits: SearchLimits,
+    budget: Mutex<IndexBudget>,
+    /// Unix seconds of the last commit; 0 until the first one.
+    last_commit: AtomicI64,
+    pending_updates: AtomicUsize,
     #[cfg(test)]
     commit_count: AtomicUsize,
 }
 
+/// Counts an index update as pending from before it queues on the writer
+/// until it has committed and reloaded the reader.
+struct PendingUpdate<'a>(&'a AtomicUsize);
+
+impl<'a> PendingUpdate<'a> {
+    fn start(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for PendingUpdate<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What the index holds against [`SearchLimits`], by route.
+#[derive(Default)]
+struct IndexBudget {
+    sizes: HashMap<String, u64>,
+    total: u64,
+    truncated: HashSet<String>,
+    skipped: HashSet<String>,
+}
+
+impl IndexBudget {
+    /// Drop `route` after its documents were deleted.
+    fn forget(&mut self, route: &str) {
+        if let Some(size) = self.sizes.remove(route) {
+            self.total -= size;
+        }
+        self.truncated.remove(route);
+        self.skipped.remove(route);
+    }
+
+    fn is_full(&self, max: Option<u64>) -> bool {
+        max.is_some_and(|max| self.total >= max)
+    }
+
+    fn skip(&mut self, route: &str) {
+        if self.skipped.is_empty() {
+            tracing::warn!(
+                "search index size limit reached; further files are not indexed (first: {route})"
+            );
+        }
+        self.skipped.insert(route.to_string());
+    }
+
+    /// Account for `route` if `size` more bytes fit, else record it skipped.
+    fn admit(&mut self, route: &str, size: u64, truncated: bool, max: Option<u64>) -> bool {
+        if max.is_some_and(|max| self.total + size > max) {
+            self.skip(route);
+            return false;
+        }
+        self.total += size;
+        self.sizes.insert(route.to_string(), size);
+        if truncated {
+            tracing::warn!("indexing only the first {size} bytes of {route}");
+            self.truncated.insert(route.to_string());
+        }
+        true
+    }
+}
+
 impl SearchIndex {
     /// Build an empty index whose schema/tokenizer/reader/writer are wired up
     /// but which holds no documents yet. Every stored path is supplied as a
     /// normalized workspace route, keeping initial and incremental keys
     /// consistent across directory and single-file scopes.
-    fn empty(workspace_fs: Arc<WorkspaceFs>) -> tantivy::Result<Self> {
+    fn empty(workspace_fs: Arc<WorkspaceFs>, config: SearchConfig) -> tantivy::Result<Self> {
         // Build schema
         let mut schema_builder = Schema::builder();
 
         let indexed_text_options = TextOptions::default().set_indexing_options(
             TextFieldIndexing::default()
-                .set_tokenizer("jieba")
+                .set_tokenizer(TEXT_TOKENIZER)
                 .set_index_option(IndexRecordOption::WithFreqsAndPositions),
         );
         let stored_text_options = indexed_text_options.clone().set_stored();
 
         // Use STRING for path field - indexed but not tokenized, so we can delete by exact match
-        let field_path = schema_builder.add_text_field("path", STRING | STORED);
+        let field_path = schema_builder.add_text_field("path", STRING | STORED | FAST);
         let field_file_name =
             schema_builder.add_text_field("file_name", stored_text_options.clone());
-        let field_title = schema_builder.add_text_field("title", stored_text_options);
+        let field_title = schema_builder.add_text_field("title", stored_text_options.clone());
+        // Each later top-level heading is indexed as its own document sharing
+        // the file's `path`, so deleting by path still removes every section.
+        // Only the file document indexes `file_name`/`title`; sections carry
+        // the title as a stored-only `doc_title` so a title match is not
+        // reported once per section.
+        let field_heading = schema_builder.add_text_field("heading", stored_text_options);
+        let field_anchor = schema_builder.add_text_field("anchor", STRING | STORED);
+        let field_doc_title = schema_builder.add_text_field("doc_title", STORED);
+        let field_kind = schema_builder.add_text_field("kind", STRING);
+        // Scoping filters. Every document of a file (sections included)
+        // carries them so a filter never drops only part of a file.
+        let field_ext = schema_builder.add_text_field("ext", STRING | FAST);
+        let field_modified = schema_builder.add_date_field("modified", INDEXED | FAST);
+        // Frontmatter tags as `/tag` facets: filtered on like the fields
+        // above, and counted over file documents for the tag list.
+        let field_tags = schema_builder.add_facet_field("tags", FacetOptions::default());
         // Full Markdown remains indexed for search, but is intentionally not
         // STORED in Tantivy. Search snippets read at most the returned hits
         // through WorkspaceFs, avoiding a second full-text copy in RAM.
@@ -82,17 +703,14 @@ impl SearchIndex {
         // forcing the entire workspace index to remain in process RAM.
         let index = Index::create_from_tempdir(schema)?;
 
-        // Register jieba + a LowerCaser so search is case-insensitive for Latin
-        // text (CJK has no case, so jieba's output is unaffected). The same
-        // analyzer runs at index and query time, so both sides lower-case
-        // consistently — "Hello" matches "hello".
-        let analyzer = TextAnalyzer::builder(JiebaTokenizer {})
-            .filter(LowerCaser)
-            .build();
-        index.tokenizers().register("jieba", analyzer);
+        // The analyzer lower-cases in every language, so "Hello" matches
+        // "hello" on both the index and the query side.
+        index
+            .tokenizers()
+            .register(TEXT_TOKENIZER, config.language.analyzer()?);
 
         // Create writer and reader
-        let writer = index.writer(50_000_000)?;
+        let writer = index.writer(config.limits.writer_memory)?;
         let reader = index.reader()?;
 
         Ok(Self {
@@ -102,20 +720,43 @@ impl SearchIndex {
             field_path,
             field_file_name,
             field_title,
+            field_heading,
+            field_anchor,
+            field_doc_title,
+            field_kind,
+            field_ext,
+            field_modified,
+            field_tags,
             field_content,
             start_dir: workspace_fs.ambient_root().to_path_buf(),
             workspace_fs,
+            exclude: config.exclude,
+            limits: config.limits,
+            budget: Mutex::default(),
+            last_commit: AtomicI64::new(0),
+            pending_updates: AtomicUsize::new(0),
             #[cfg(test)]
             commit_count: AtomicUsize::new(0),
         })
     }
 
     pub fn new(start_dir: &Path) -> tantivy::Result<Self> {
-        Self::for_workspace(Arc::new(WorkspaceFs::new(start_dir.to_path_buf(), None)))
+        Self::with_config(start_dir, SearchConfig::default())
     }
 
-    pub(crate) fn for_workspace(workspace_fs: Arc<WorkspaceFs>) -> tantivy::Result<Self> {
-        let search_index = Self::empty(workspace_fs)?;
+    /// [`Self::new`] with an exclude list and text analyzer.
+    pub fn with_config(start_dir: &Path, config: SearchConfig) -> tantivy::Result<Self> {
+        Self::for_workspace(
+            Arc::new(WorkspaceFs::new(start_dir.to_path_buf(), None)),
+            config,
+        )
+    }
+
+    pub(crate) fn for_workspace(
+        workspace_fs: Arc<WorkspaceFs>,
+        config: SearchConfig,
+    ) -> tantivy::Result<Self> {
+        let search_index = Self::empty(workspace_fs, config)?;
 
         // Index all markdown files
         search_index.index_workspace()?;
@@ -130,10 +771,10 @@ impl SearchIndex {
     /// walks its parent. `start_dir` remains the stored path base so watcher
     /// updates keep the same relative document key.
     pub fn new_single_file(start_dir: &Path, file_name: &str) -> tantivy::Result<Self> {
-        Self::for_workspace(Arc::new(WorkspaceFs::new(
-            start_dir.to_path_buf(),
-            Some(file_name),
-        )))
+        Self::for_workspace(
+            Arc::new(WorkspaceFs::new(start_dir.to_path_buf(), Some(file_name))),
+            SearchConfig::default(),
+        )
     }
 
     /// Acquire the writer lock, mapping poisoning to a tantivy error
@@ -146,25 +787,33 @@ impl SearchIndex {
         })
     }
 
+    fn budget(&self) -> tantivy::Result<MutexGuard<'_, IndexBudget>> {
+        self.budget.lock().map_err(|err| {
+            TantivyError::SystemError(format!("search index budget mutex poisoned: {err}"))
+        })
+    }
+
     fn commit(&self, writer: &mut IndexWriter) -> tantivy::Result<()> {
         writer.commit()?;
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        self.last_commit.store(now, Ordering::Relaxed);
         #[cfg(test)]
         self.commit_count.fetch_add(1, Ordering::Relaxed);
         Ok(())
     }
 
     fn workspace_markdown_files(&self) -> Vec<(WorkspaceRelPath, PathBuf)> {
-        self.workspace_fs
-            .content_files(usize::MAX)
-            .into_iter()
-            .filter(|(rel, _)| rel.as_path().extension().is_some_and(|ext| ext == "md"))
-            .collect()
+        markdown_files(&self.workspace_fs, &self.exclude)
     }
 
     /// Read and tokenize a bounded group of files in parallel, then hand the
     /// documents to the single Tantivy writer. Keeping only one small batch of
     /// Markdown bodies alive avoids a workspace-sized memory spike during
-    /// initial indexing or an ignore-rule rebuild.
+    /// initial indexing or an ignore-rule rebuild. Each file is read only up
+    /// to [`SearchLimits::max_file_size`], and files stop being read once the
+    /// index holds [`SearchLimits::max_index_size`].
     fn add_documents(
         &self,
         writer: &mut IndexWriter,
@@ -172,42 +821,77 @@ impl SearchIndex {
     ) -> tantivy::Result<()> {
         use rayon::prelude::*;
 
+        let max_index_size = self.limits.max_index_size;
         for batch in files.chunks(INDEX_DOCUMENT_BATCH_SIZE) {
-            let docs: Vec<TantivyDocument> = batch
+            {
+                let mut budget = self.budget()?;
+                if budget.is_full(max_index_size) {
+                    for (rel, _) in batch {
+                        budget.skip(&rel.as_route());
+                    }
+                    continue;
+                }
+            }
+            let docs: Vec<(String, u64, bool, Vec<TantivyDocument>)> = batch
                 .par_iter()
                 .filter_map(|(rel, path)| {
                     let relative_path = rel.as_route();
-                    let content = self
+                    let (content, truncated) = self
                         .workspace_fs
-                        .read_content_to_string(&relative_path)
+                        .read_content_prefix(&relative_path, self.limits.max_file_size)
                         .ok()?;
-                    Some(self.build_document(&relative_path, path, &content))
+                    let docs = self.build_documents(&relative_path, path, &content);
+                    Some((relative_path, content.len() as u64, truncated, docs))
                 })
                 .collect();
-            for doc in docs {
-                writer.add_document(doc)?;
+            let mut budget = self.budget()?;
+            for (route, size, truncated, docs) in docs {
+                budget.forget(&route);
+                if !budget.admit(&route, size, truncated, max_index_size) {
+                    continue;
+                }
+                for doc in docs {
+                    writer.add_document(doc)?;
+                }
             }
         }
         Ok(())
     }
 
     fn index_workspace(&self) -> tantivy::Result<()> {
+        self.index_workspace_in_batches(INDEX_COMMIT_BATCH_SIZE)
+    }
+
+    fn index_workspace_in_batches(&self, commit_batch: usize) -> tantivy::Result<()> {
         tracing::info!("indexing markdown files in {:?}", self.start_dir);
 
         // Snapshot only paths up front; Markdown bodies are read later in
         // bounded parallel batches so the entire workspace is never buffered.
         let files = self.workspace_markdown_files();
+        let total = files.len();
 
-        // Acquire the writer once and commit the complete build once. The guard
-        // is dropped before reload(), so searches remain lock-free.
+        // Acquire the writer once and commit every `commit_batch` files. The
+        // index is not published until this returns, so partial commits are
+        // never observed by searches. The guard is dropped before reload(),
+        // so searches remain lock-free.
         {
             let mut writer = self.writer()?;
-            self.add_documents(&mut writer, &files)?;
-            self.commit(&mut writer)?;
+            let mut indexed = 0;
+            for chunk in files.chunks(commit_batch.max(1)) {
+                self.add_documents(&mut writer, chunk)?;
+                self.commit(&mut writer)?;
+                indexed += chunk.len();
+                if indexed < total {
+                    tracing::info!("indexed {indexed}/{total} markdown files");
+                }
+            }
+            if files.is_empty() {
+                self.commit(&mut writer)?;
+            }
         }
 
         self.reader.reload()?;
-        tracing::info!("indexing complete");
+        tracing::info!("indexing complete: {total} markdown files");
 
         Ok(())
     }
@@ -216,6 +900,7 @@ impl SearchIndex {
         {
             let mut writer = self.writer()?;
             writer.delete_all_documents()?;
+            *self.budget()? = IndexBudget::default();
             self.add_documents(&mut writer, files)?;
             self.commit(&mut writer)?;
         }
@@ -223,46 +908,158 @@ impl SearchIndex {
         Ok(())
     }
 
-    /// Build a TantivyDocument for an already-authorized route. Pure CPU work
-    /// — does not touch the writer and is safe to call from rayon workers.
-    fn build_document(&self, relative_path: &str, path: &Path, content: &str) -> TantivyDocument {
+    /// Build the TantivyDocuments for an already-authorized route: one for the
+    /// file itself plus one per later heading section. Pure CPU work — does not
+    /// touch the writer and is safe to call from rayon workers.
+    fn build_documents(
+        &self,
+        relative_path: &str,
+        path: &Path,
+        content: &str,
+    ) -> Vec<TantivyDocument> {
         let file_name = path
             .file_stem()
             .and_then(|s| s.to_str())
             .unwrap_or("")
             .to_string();
 
-        // Extract title from first heading or filename
-        let title = content
-            .lines()
-            .find(|line| line.starts_with('#'))
-            .map(|line| line.trim_start_matches('#').trim().to_string())
-            .unwrap_or_else(|| file_name.clone());
+        let title = document_title(content, &file_name);
+
+        let (head, sections) = document_sections(content);
+        let ext = route_extension(relative_path).unwrap_or_default();
+        let modified = DateTime::from_timestamp_secs(file_modified_secs(path).unwrap_or(0));
+        let tags = frontmatter::parse(content)
+            .map(|front| front.tags)
+            .unwrap_or_default();
+        let filter_fields = |doc: &mut TantivyDocument| {
+            doc.add_text(self.field_path, relative_path);
+            doc.add_text(self.field_ext, &ext);
+            doc.add_date(self.field_modified, modified);
+            for tag in &tags {
+                doc.add_facet(self.field_tags, Facet::from_path([tag]));
+            }
+        };
 
         let mut doc = TantivyDocument::default();
-        doc.add_text(self.field_path, relative_path);
+        filter_fields(&mut doc);
+        doc.add_text(self.field_kind, KIND_FILE);
         doc.add_text(self.field_file_name, &file_name);
         doc.add_text(self.field_title, &title);
-        doc.add_text(self.field_content, content);
-        doc
+        doc.add_text(self.field_doc_title, &title);
+        doc.add_text(self.field_content, &head);
+
+        let mut docs = Vec::with_capacity(sections.len() + 1);
+        docs.push(doc);
+        for section in sections {
+            let mut doc = TantivyDocument::default();
+            filter_fields(&mut doc);
+            doc.add_text(self.field_kind, KIND_SECTION);
+            doc.add_text(self.field_doc_title, &title);
+            if let Some(heading) = &section.heading {
+                doc.add_text(self.field_heading, heading);
+            }
+            if let Some(anchor) = &section.anchor {
+                doc.add_text(self.field_anchor, anchor);
+            }
+            doc.add_text(self.field_content, &section.text);
+            docs.push(doc);
+        }
+        docs
     }
 
     pub fn search(&self, query_str: &str, limit: usize) -> tantivy::Result<Vec<SearchResult>> {
+        Ok(self.search_page(query_str, 0, limit)?.results)
+    }
+
+    /// Run `query_str` and return hits `offset..offset + limit` (by score)
+    /// together with the total number of matching documents.
+    pub fn search_page(
+        &self,
+        query_str: &str,
+        offset: usize,
+        limit: usize,
+    ) -> tantivy::Result<SearchPage> {
+        self.search_page_filtered(query_str, &SearchFilters::default(), offset, limit)
+    }
+
+    /// [`Self::search_page`] restricted by `filters`.
+    pub fn search_page_filtered(
+        &self,
+        query_str: &str,
+        filters: &SearchFilters,
+        offset: usize,
+        limit: usize,
+    ) -> tantivy::Result<SearchPage> {
+        self.search_page_with(
+            query_str,
+            filters,
+            &SnippetOptions::default(),
+            offset,
+            limit,
+        )
+    }
+
+    /// [`Self::search_page_filtered`] with custom snippet length and markup.
+    pub fn search_page_with(
+        &self,
+        query_str: &str,
+        filters: &SearchFilters,
+        snippet: &SnippetOptions,
+        offset: usize,
+        limit: usize,
+    ) -> tantivy::Result<SearchPage> {
+        if offset > MAX_SEARCH_OFFSET {
+            return Err(TantivyError::InvalidArgument(format!(
+                "offset must be at most {MAX_SEARCH_OFFSET}, got {offset}"
+            )));
+        }
         let searcher = self.reader.searcher();
 
-        // Search across file_name, title, and content
-        let query_parser = QueryParser::for_index(
+        // Search across file_name, title, heading, and content
+        let mut query_parser = QueryParser::for_index(
             &self.index,
-            vec![self.field_file_name, self.field_title, self.field_content],
+            vec![
+                self.field_file_name,
+                self.field_title,
+                self.field_heading,
+                self.field_content,
+            ],
         );
-
-        let query = query_parser.parse_query(query_str)?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(limit))?;
+        query_parser.set_field_boost(self.field_file_name, FILE_NAME_BOOST);
+        query_parser.set_field_boost(self.field_title, TITLE_BOOST);
+        query_parser.set_field_boost(self.field_heading, HEADING_BOOST);
+
+        let (text, query_tags) = split_tag_terms(query_str);
+        let mut filters = filters.clone();
+        for tag in query_tags {
+            if !filters.tags.contains(&tag) {
+                filters.tags.push(tag);
+            }
+        }
+        // A query made only of `tag:` terms lists every file with the tags.
+        let base: Box<dyn Query> = if text.trim().is_empty() && !filters.tags.is_empty() {
+            Box::new(TermQuery::new(
+                Term::from_field_text(self.field_kind, KIND_FILE),
+                IndexRecordOption::Basic,
+            ))
+        } else {
+            query_parser.parse_query(&text)?
+        };
+        let query = self.apply_filters(base, &filters);
+        // TopDocs rejects a zero limit; an empty page still reports the total.
+        let (top_docs, total) = searcher.search(
+            &query,
+            &(TopDocs::with_limit(limit.max(1)).and_offset(offset), Count),
+        )?;
+        let top_docs = top_docs.into_iter().take(limit);
 
         let mut results = Vec::new();
-        let snippet_generator = SnippetGenerator::create(&searcher, &query, self.field_content)?;
+        let mut snippet_generator =
+            SnippetGenerator::create(&searcher, &query, self.field_content)?;
+        snippet_generator.set_max_num_chars(snippet.max_chars);
+        let (open_tag, close_tag) = (snippet.open_tag(), snippet.close_tag());
 
-        for (_score, doc_address) in top_docs {
+        for (score, doc_address) in top_docs {
             let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
 
             let file_path = retrieved_doc
@@ -271,33 +1068,125 @@ impl SearchIndex {
                 .unwrap_or("")
                 .to_string();
 
-            let file_name = retrieved_doc
-                .get_first(self.field_file_name)
-                .and_then(|v| v.as_str())
+            let file_name = Path::new(&file_path)
+                .file_stem()
+                .and_then(|s| s.to_str())
                 .unwrap_or("")
                 .to_string();
 
             let title = retrieved_doc
-                .get_first(self.field_title)
+                .get_first(self.field_doc_title)
                 .and_then(|v| v.as_str())
                 .unwrap_or("")
                 .to_string();
 
+            let heading = retrieved_doc
+                .get_first(self.field_heading)
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+
+            let anchor = retrieved_doc
+                .get_first(self.field_anchor)
+                .and_then(|v| v.as_str())
+                .map(str::to_string);
+
+            // Snippets come from the hit's own section of the current file so
+            // they show the text under the linked heading. A hit on the title,
+            // file name, or heading alone has no body fragment; show the
+            // section's opening paragraph instead.
             let snippet_html = self
                 .workspace_fs
                 .read_content_to_string(&file_path)
-                .map(|content| snippet_generator.snippet(&content).to_html())
+                .map(|content| {
+                    let (head, sections) = document_sections(&content);
+                    let text = match &anchor {
+                        Some(anchor) => sections
+                            .into_iter()
+                            .find(|section| section.anchor.as_ref() == Some(anchor))
+                            .map(|section| section.text)
+                            .unwrap_or(content),
+                        None => head,
+                    };
+                    let mut fragment = snippet_generator.snippet(&text);
+                    if fragment.is_empty() {
+                        return opening_paragraph(&text, snippet.max_chars);
+                    }
+                    fragment.set_snippet_prefix_postfix(&open_tag, &close_tag);
+                    fragment.to_html()
+                })
                 .unwrap_or_default();
 
             results.push(SearchResult {
                 file_path,
                 file_name,
                 title,
+                heading,
+                anchor,
                 snippet: snippet_html,
+                score,
+                lines: Vec::new(),
             });
         }
 
-        Ok(results)
+        Ok(SearchPage {
+            total,
+            offset,
+            limit,
+            results,
+            ..SearchPage::default()
+        })
+    }
+
+    fn apply_filters(&self, query: Box<dyn Query>, filters: &SearchFilters) -> Box<dyn Query> {
+        if filters == &SearchFilters::default() {
+            return query;
+        }
+        let mut clauses = vec![(Occur::Must, query)];
+        if let Some(prefix) = &filters.path_prefix {
+            // Every route starting with `prefix` sorts between the prefix
+            // itself and the prefix followed by the largest code point.
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new(
+                    Bound::Included(Term::from_field_text(self.field_path, prefix)),
+                    Bound::Excluded(Term::from_field_text(
+                        self.field_path,
+                        &format!("{prefix}{}", char::MAX),
+                    )),
+                )),
+            ));
+        }
+        if let Some(ext) = &filters.ext {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(self.field_ext, ext),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        for tag in &filters.tags {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_facet(self.field_tags, &Facet::from_path([tag])),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        if let Some(after) = filters.modified_after {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new(
+                    Bound::Excluded(Term::from_field_date(
+                        self.field_modified,
+                        DateTime::from_timestamp_secs(after),
+                    )),
+                    Bound::Unbounded,
+                )),
+            ));
+        }
+        Box::new(BooleanQuery::new(clauses))
     }
 
     /// Reconcile a debounced batch of watcher paths against the filesystem's
@@ -306,19 +1195,26 @@ impl SearchIndex {
     /// both sides of renames converge correctly in one commit + reader reload.
     ///
     /// `content_files_for_routes` applies the same ignore policy as the initial
-    /// workspace walk. An ignored file is therefore deleted if an older build
-    /// or an earlier race ever placed it in the index.
+    /// workspace walk, and the exclude list is applied on top. An ignored file
+    /// is therefore deleted if an older build or an earlier race ever placed
+    /// it in the index.
     pub(crate) fn reconcile_files(&self, paths: &[PathBuf]) -> tantivy::Result<()> {
+        let _pending = PendingUpdate::start(&self.pending_updates);
         let routes: BTreeSet<_> = paths
             .iter()
-            .filter(|path| path.extension().is_some_and(|ext| ext == "md"))
+            .filter(|path| self.workspace_fs.policy().is_markdown(path))
             .filter_map(|path| self.workspace_fs.lexical_route(path))
             .collect();
         if routes.is_empty() {
             return Ok(());
         }
 
-        let files = self.workspace_fs.content_files_for_routes(&routes);
+        let files: Vec<_> = self
+            .workspace_fs
+            .content_files_for_routes(&routes)
+            .into_iter()
+            .filter(|(route, _)| !self.exclude.is_excluded(route))
+            .collect();
         let visible_routes: BTreeSet<_> = files.iter().map(|(route, _)| route.clone()).collect();
         let searcher = self.reader.searcher();
         let mut affected_routes = BTreeSet::new();
@@ -336,9 +1232,12 @@ impl SearchIndex {
 
         {
             let mut writer = self.writer()?;
+            let mut budget = self.budget()?;
             for route in &affected_routes {
                 writer.delete_term(Term::from_field_text(self.field_path, &route.as_route()));
+                budget.forget(&route.as_route());
             }
+            drop(budget);
             self.add_documents(&mut writer, &files)?;
             self.commit(&mut writer)?;
         }
@@ -352,6 +1251,7 @@ impl SearchIndex {
     /// rule or directory topology changes, where per-file reconciliation cannot
     /// determine every route that became visible or hidden.
     pub(crate) fn rebuild(&self) -> tantivy::Result<()> {
+        let _pending = PendingUpdate::start(&self.pending_updates);
         let files = self.workspace_markdown_files();
         self.replace_all(&files)?;
         tracing::debug!("rebuilt search index");
@@ -365,13 +1265,22 @@ impl SearchIndex {
     /// compared with deleting and re-tokenizing the entire Tantivy index, and
     /// lets those otherwise-empty batches remain true no-ops.
     pub(crate) fn rebuild_if_routes_changed(&self) -> tantivy::Result<()> {
+        let _pending = PendingUpdate::start(&self.pending_updates);
         let files = self.workspace_markdown_files();
         let searcher = self.reader.searcher();
-        let mut routes_match = searcher.num_docs() == files.len() as u64;
+        // Sections share their file's path, so compare only file documents.
+        let file_docs = TermQuery::new(
+            Term::from_field_text(self.field_kind, KIND_FILE),
+            IndexRecordOption::Basic,
+        );
+        let mut routes_match = searcher.search(&file_docs, &Count)? == files.len();
         if routes_match {
             for (route, _) in &files {
-                let term = Term::from_field_text(self.field_path, &route.as_route());
-                if searcher.doc_freq(&term)? != 1 {
+                let route_docs = TermQuery::new(
+                    Term::from_field_text(self.field_path, &route.as_route()),
+                    IndexRecordOption::Basic,
+                );
+                if searcher.search(&route_docs, &Count)? == 0 {
                     routes_match = false;
                     break;
                 }
@@ -387,6 +1296,55 @@ impl SearchIndex {
         Ok(())
     }
 
+    /// Every frontmatter tag in the index with the number of files carrying
+    /// it, most used first.
+    pub fn tag_counts(&self) -> tantivy::Result<Vec<TagCount>> {
+        let searcher = self.reader.searcher();
+        let mut collector = FacetCollector::for_field("tags");
+        collector.add_facet(Facet::root());
+        let file_docs = TermQuery::new(
+            Term::from_field_text(self.field_kind, KIND_FILE),
+            IndexRecordOption::Basic,
+        );
+        let counts = searcher.search(&file_docs, &collector)?;
+        let mut tags: Vec<TagCount> = counts
+            .get(Facet::root())
+            .filter_map(|(facet, count)| {
+                Some(TagCount {
+                    tag: facet.to_path().last()?.to_string(),
+                    count: count as usize,
+                })
+            })
+            .collect();
+        tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+        Ok(tags)
+    }
+
+    /// Document counts and writer activity. Counts reflect the last reader
+    /// reload, so they lag a pending update until it commits.
+    pub fn status(&self) -> tantivy::Result<SearchStatus> {
+        let searcher = self.reader.searcher();
+        let count_kind = |kind: &str| {
+            let query = TermQuery::new(
+                Term::from_field_text(self.field_kind, kind),
+                IndexRecordOption::Basic,
+            );
+            searcher.search(&query, &Count)
+        };
+        let last_commit = self.last_commit.load(Ordering::Relaxed);
+        let budget = self.budget()?;
+        Ok(SearchStatus {
+            ready: true,
+            documents: count_kind(KIND_FILE)?,
+            sections: count_kind(KIND_SECTION)?,
+            last_commit: (last_commit > 0).then_some(last_commit),
+            pending_updates: self.pending_updates.load(Ordering::Relaxed),
+            indexed_bytes: budget.total,
+            truncated_files: budget.truncated.len(),
+            skipped_files: budget.skipped.len(),
+        })
+    }
+
     pub fn update_file(&self, path: &Path) -> tantivy::Result<()> {
         self.reconcile_files(&[path.to_path_buf()])
     }
@@ -395,11 +1353,13 @@ impl SearchIndex {
         let Some(route) = self.workspace_fs.lexical_route(path) else {
             return Ok(());
         };
+        let _pending = PendingUpdate::start(&self.pending_updates);
 
         {
             let mut writer = self.writer()?;
             let term = Term::from_field_text(self.field_path, &route.as_route());
             writer.delete_term(term);
+            self.budget()?.forget(&route.as_route());
             self.commit(&mut writer)?;
         }
 
@@ -411,6 +1371,239 @@ impl SearchIndex {
     }
 }
 
+fn markdown_files(
+    workspace_fs: &WorkspaceFs,
+    exclude: &SearchExclude,
+) -> Vec<(WorkspaceRelPath, PathBuf)> {
+    workspace_fs
+        .content_files(usize::MAX)
+        .into_iter()
+        .filter(|(rel, _)| workspace_fs.policy().is_markdown(rel.as_path()))
+        .filter(|(rel, _)| !exclude.is_excluded(rel))
+        .collect()
+}
+
+/// Title from the first heading line, falling back to the file name.
+fn document_title(content: &str, file_name: &str) -> String {
+    crate::markdown::document_title(content).unwrap_or_else(|| file_name.to_string())
+}
+
+/// Grep-style scan of every visible Markdown file for `pattern`, without the
+/// Tantivy index. Files are read in parallel; hits are ordered by path, scored
+/// by their number of matches, and carry up to [`MAX_REGEX_LINES_PER_FILE`]
+/// matching lines. The snippet is the first matching line with each match in
+/// `<b>`, mirroring Tantivy's snippet HTML. Titles and tags come from
+/// `metadata`, so a tag filter skips files without reading them whole.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn regex_search_page(
+    workspace_fs: &WorkspaceFs,
+    exclude: &SearchExclude,
+    metadata: &Mutex<MetadataCache>,
+    pattern: &str,
+    filters: &SearchFilters,
+    snippet: &SnippetOptions,
+    offset: usize,
+    limit: usize,
+) -> Result<SearchPage, regex::Error> {
+    use rayon::prelude::*;
+
+    let regex = regex::RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()?;
+
+    let mut hits: Vec<SearchResult> = markdown_files(workspace_fs, exclude)
+        .par_iter()
+        .filter_map(|(rel, path)| {
+            let file_path = rel.as_route();
+            let modified = filters
+                .modified_after
+                .and_then(|_| file_modified_secs(path));
+            if !filters.matches(&file_path, modified) {
+                return None;
+            }
+            let document = MetadataCache::metadata(metadata, path, &std::fs::metadata(path).ok()?);
+            if !filters.matches_tags(&document.tags) {
+                return None;
+            }
+            let content = workspace_fs.read_content_to_string(&file_path).ok()?;
+            let mut count = 0;
+            let mut lines = Vec::new();
+            for (index, line) in content.lines().enumerate() {
+                let matches = regex.find_iter(line).count();
+                if matches == 0 {
+                    continue;
+                }
+                count += matches;
+                if lines.len() < MAX_REGEX_LINES_PER_FILE {
+                    lines.push(SearchLineMatch {
+                        line: index + 1,
+                        text: line.to_string(),
+                    });
+                }
+            }
+            let first = lines.first()?;
+            let file_name = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("")
+                .to_string();
+            Some(SearchResult {
+                title: document.title.clone().unwrap_or_else(|| file_name.clone()),
+                snippet: highlight_matches(&regex, &first.text, snippet),
+                file_path,
+                file_name,
+                heading: None,
+                anchor: None,
+                score: count as f32,
+                lines,
+            })
+        })
+        .collect();
+    hits.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+    Ok(SearchPage {
+        total: hits.len(),
+        offset,
+        limit,
+        results: hits.into_iter().skip(offset).take(limit).collect(),
+        ..SearchPage::default()
+    })
+}
+
+/// Pull `tag:name` (or `tag:"two words"`) terms out of a text query, returning
+/// the remaining query text and the normalized tags.
+fn split_tag_terms(query: &str) -> (String, Vec<String>) {
+    let mut text = String::new();
+    let mut tags = Vec::new();
+    let mut rest = query;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(value) = rest.strip_prefix("tag:") {
+            let (raw, after) = match value.strip_prefix('"') {
+                Some(quoted) => match quoted.split_once('"') {
+                    Some((raw, after)) => (raw, after),
+                    None => (quoted, ""),
+                },
+                None => value.split_at(value.find(char::is_whitespace).unwrap_or(value.len())),
+            };
+            let tag = frontmatter::normalize_tag(raw);
+            if !tag.is_empty() && !tags.contains(&tag) {
+                tags.push(tag);
+            }
+            rest = after;
+            continue;
+        }
+        // Copy one whitespace-delimited token, keeping quoted phrases whole.
+        let end = if let Some(quoted) = rest.strip_prefix('"') {
+            quoted.find('"').map_or(rest.len(), |close| close + 2)
+        } else {
+            rest.find(char::is_whitespace).unwrap_or(rest.len())
+        };
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(&rest[..end]);
+        rest = &rest[end..];
+    }
+    (text, tags)
+}
+
+fn route_extension(route: &str) -> Option<String> {
+    Path::new(route)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+fn file_modified_secs(path: &Path) -> Option<i64> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    let secs = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    i64::try_from(secs).ok()
+}
+
+/// Accept Unix seconds or a `YYYY-MM-DD` calendar date (UTC midnight).
+fn parse_modified_after(raw: &str) -> Option<i64> {
+    if let Ok(secs) = raw.parse::<i64>() {
+        return Some(secs);
+    }
+    let mut parts = raw.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since 1970-01-01 in the proleptic Gregorian calendar.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    Some((era * 146_097 + doe - 719_468) * 86_400)
+}
+
+fn highlight_matches(regex: &regex::Regex, line: &str, snippet: &SnippetOptions) -> String {
+    let line = truncate_chars(line, snippet.max_chars);
+    let (open_tag, close_tag) = (snippet.open_tag(), snippet.close_tag());
+    let mut out = String::with_capacity(line.len() + 16);
+    let mut last = 0;
+    for m in regex.find_iter(line).filter(|m| !m.is_empty()) {
+        html_escape::encode_text_to_string(&line[last..m.start()], &mut out);
+        out.push_str(&open_tag);
+        html_escape::encode_text_to_string(m.as_str(), &mut out);
+        out.push_str(&close_tag);
+        last = m.end();
+    }
+    html_escape::encode_text_to_string(&line[last..], &mut out);
+    out
+}
+
+/// `text` cut to at most `max_chars` characters, on a char boundary.
+fn truncate_chars(text: &str, max_chars: usize) -> &str {
+    text.char_indices()
+        .nth(max_chars)
+        .map_or(text, |(end, _)| &text[..end])
+}
+
+/// Escaped first prose paragraph of `text` (skipping frontmatter, headings,
+/// and blank lines), cut to `max_chars` with an ellipsis.
+fn opening_paragraph(text: &str, max_chars: usize) -> String {
+    let paragraph = frontmatter::strip(text)
+        .lines()
+        .map(str::trim)
+        .skip_while(|line| line.is_empty() || line.starts_with('#'))
+        .take_while(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let cut = truncate_chars(&paragraph, max_chars);
+    let mut out = html_escape::encode_text(cut).into_owned();
+    if cut.len() < paragraph.len() {
+        out.push('…');
+    }
+    out
+}
+
+/// Split `content` into the file document's text and its section documents.
+/// The first heading is the document title, so it stays with any preamble on
+/// the file document; every later top-level heading becomes a section.
+fn document_sections(content: &str) -> (String, Vec<HeadingSection>) {
+    let mut sections = heading_sections(content);
+    let split = sections
+        .iter()
+        .position(|section| section.heading.is_some())
+        .map_or(sections.len(), |first| first + 1);
+    let rest = sections.split_off(split);
+    let head = sections.into_iter().map(|section| section.text).collect();
+    (head, rest)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -587,6 +1780,57 @@ mod tests {
         assert!(results[0].snippet.contains("<b>") || results[0].snippet.contains("snippet"));
     }
 
+    #[test]
+    fn test_snippet_options_and_title_only_fallback() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_file(
+            dir_path,
+            "body.md",
+            "# Notes\nThe retry budget is shared across every client in the pool.",
+        )
+        .unwrap();
+        create_test_file(
+            dir_path,
+            "kubernetes.md",
+            "# Cluster Guide\n\nDeploy <services> with care.\n",
+        )
+        .unwrap();
+        let index = SearchIndex::new(dir_path).unwrap();
+
+        let markup = SnippetOptions::new(20, "mark", Some("hit \"x\"")).unwrap();
+        let page = index
+            .search_page_with("retry", &SearchFilters::default(), &markup, 0, 10)
+            .unwrap();
+        let snippet = &page.results[0].snippet;
+        assert!(
+            snippet.contains(r#"<mark class="hit &quot;x&quot;">retry</mark>"#),
+            "{snippet}"
+        );
+        assert!(!snippet.contains("pool"), "snippet not cut: {snippet}");
+
+        // The file name matches but the body never mentions the query.
+        let page = index.search_page("kubernetes", 0, 10).unwrap();
+        assert_eq!(
+            page.results[0].snippet,
+            "Deploy &lt;services&gt; with care."
+        );
+
+        let short = SnippetOptions::new(6, "b", None).unwrap();
+        let page = index
+            .search_page_with("kubernetes", &SearchFilters::default(), &short, 0, 10)
+            .unwrap();
+        assert_eq!(page.results[0].snippet, "Deploy…");
+
+        assert!(SnippetOptions::new(0, "b", None).is_err());
+        assert!(SnippetOptions::new(100, "b onclick=x", None).is_err());
+        let query = SearchQuery {
+            snippet_chars: Some(MAX_SNIPPET_CHARS + 1),
+            ..SearchQuery::default()
+        };
+        assert!(query.snippet_options().is_err());
+    }
+
     #[test]
     fn test_content_is_indexed_not_stored_and_snippet_reads_current_file() {
         let temp_dir = TempDir::new().unwrap();
@@ -653,6 +1897,42 @@ mod tests {
         assert!(results.is_err() || results.unwrap().is_empty());
     }
 
+    #[test]
+    fn test_file_name_and_title_matches_rank_above_body_mentions() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_file(dir_path, "misc.md", "# Misc\nWe deploy on fridays").unwrap();
+        create_test_file(dir_path, "guide.md", "# Deploy Handbook\nSteps").unwrap();
+        create_test_file(dir_path, "deploy.md", "Checklist only").unwrap();
+
+        let results = SearchIndex::new(dir_path)
+            .unwrap()
+            .search("deploy", 10)
+            .unwrap();
+        let order: Vec<_> = results.iter().map(|r| r.file_path.as_str()).collect();
+        assert_eq!(order, ["deploy.md", "guide.md", "misc.md"]);
+    }
+
+    #[test]
+    fn test_section_heading_ranks_above_body_mention() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_file(dir_path, "a.md", "# A\nIntro\n# Rollback\nUndo the release").unwrap();
+        create_test_file(
+            dir_path,
+            "b.md",
+            "# B\nIntro\n# Notes\nA rollback happened once",
+        )
+        .unwrap();
+
+        let results = SearchIndex::new(dir_path)
+            .unwrap()
+            .search("rollback", 10)
+            .unwrap();
+        assert_eq!(results[0].file_path, "a.md");
+        assert_eq!(results[0].anchor.as_deref(), Some("rollback"));
+    }
+
     #[test]
     fn test_title_extraction() {
         let temp_dir = TempDir::new().unwrap();
@@ -675,6 +1955,402 @@ mod tests {
         assert_eq!(results[0].title, "no-title");
     }
 
+    #[test]
+    fn test_heading_sections_are_deep_linked() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        create_test_file(
+            dir_path,
+            "tutorial.md",
+            "# Advanced Tutorial\nIntro.\n\n## Step 1\nPrepare.\n\n## Step 3\nTune the flux capacitor.\n",
+        )
+        .unwrap();
+
+        let index = SearchIndex::new(dir_path).unwrap();
+        assert_eq!(index.reader.searcher().num_docs(), 3);
+
+        let results = index.search("capacitor", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "Advanced Tutorial");
+        assert_eq!(results[0].file_name, "tutorial");
+        assert_eq!(results[0].heading.as_deref(), Some("Step 3"));
+        assert_eq!(results[0].anchor.as_deref(), Some("step-3"));
+        assert!(results[0].snippet.contains("<b>capacitor</b>"));
+        assert!(!results[0].snippet.contains("Prepare"));
+
+        // Title matches stay on the file document and link to the top.
+        let results = index.search("Advanced", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].anchor, None);
+
+        // Rewriting the file drops stale sections with the rest of its docs.
+        fs::write(dir_path.join("tutorial.md"), "# Advanced Tutorial\nShort.").unwrap();
+        index.update_file(&dir_path.join("tutorial.md")).unwrap();
+        assert!(index.search("capacitor", 10).unwrap().is_empty());
+        assert_eq!(index.reader.searcher().num_docs(), 1);
+
+        // Section documents do not look like a route-set change.
+        let commits_before = index.commit_count.load(Ordering::Relaxed);
+        index.rebuild_if_routes_changed().unwrap();
+        assert_eq!(index.commit_count.load(Ordering::Relaxed), commits_before);
+    }
+
+    #[test]
+    fn test_regex_search_reports_line_numbers() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        create_test_file(
+            dir_path,
+            "todo.md",
+            "# Todo\nTODO(alice): fix <tags>\nnothing\nTODO(bob) and TODO(carol)\n",
+        )
+        .unwrap();
+        create_test_file(dir_path, "notes.md", "# Notes\nTODO: lowercase owner").unwrap();
+        create_test_file(dir_path, "skip.txt", "TODO(dave)").unwrap();
+
+        let workspace_fs = WorkspaceFs::new(dir_path.to_path_buf(), None);
+        let page = regex_search_page(
+            &workspace_fs,
+            &SearchExclude::default(),
+            &Mutex::default(),
+            r"TODO\(\w+\)",
+            &SearchFilters::default(),
+            &SnippetOptions::default(),
+            0,
+            10,
+        )
+        .unwrap();
+        assert_eq!(page.total, 1);
+        let hit = &page.results[0];
+        assert_eq!(hit.file_path, "todo.md");
+        assert_eq!(hit.title, "Todo");
+        assert_eq!(hit.score, 3.0);
+        assert_eq!(
+            hit.lines
+                .iter()
+                .map(|m| (m.line, m.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (2, "TODO(alice): fix <tags>"),
+                (4, "TODO(bob) and TODO(carol)")
+            ]
+        );
+        assert_eq!(hit.snippet, "<b>TODO(alice)</b>: fix &lt;tags&gt;");
+
+        let page = regex_search_page(
+            &workspace_fs,
+            &SearchExclude::default(),
+            &Mutex::default(),
+            "TODO",
+            &SearchFilters::default(),
+            &SnippetOptions::default(),
+            1,
+            10,
+        )
+        .unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.results.len(), 1);
+        assert_eq!(page.results[0].file_path, "todo.md");
+
+        assert!(regex_search_page(
+            &workspace_fs,
+            &SearchExclude::default(),
+            &Mutex::default(),
+            "(unclosed",
+            &SearchFilters::default(),
+            &SnippetOptions::default(),
+            0,
+            10
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_search_filters_scope_path_ext_and_modified_time() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        fs::create_dir_all(dir_path.join("docs/api")).unwrap();
+        fs::create_dir_all(dir_path.join("docsite")).unwrap();
+
+        create_test_file(dir_path, "docs/api/old.md", "# Old\nwidget reference").unwrap();
+        create_test_file(
+            dir_path,
+            "docs/new.md",
+            "# New\nwidget guide\n## Usage\nwidget",
+        )
+        .unwrap();
+        create_test_file(dir_path, "docsite/site.md", "# Site\nwidget site").unwrap();
+        let old = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(dir_path.join("docs/api/old.md"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let index = SearchIndex::new(dir_path).unwrap();
+        let search = |filters: SearchFilters| {
+            let mut paths: Vec<_> = index
+                .search_page_filtered("widget", &filters, 0, 10)
+                .unwrap()
+                .results
+                .into_iter()
+                .map(|r| r.file_path)
+                .collect();
+            paths.sort();
+            paths.dedup();
+            paths
+        };
+
+        let query = SearchQuery {
+            q: "widget".into(),
+            path: Some("/docs/".into()),
+            ext: Some(".MD".into()),
+            modified_after: Some("2021-01-01".into()),
+            ..SearchQuery::default()
+        };
+        let filters = query.filters().unwrap();
+        assert_eq!(filters.path_prefix.as_deref(), Some("docs/"));
+        assert_eq!(filters.ext.as_deref(), Some("md"));
+        assert_eq!(filters.modified_after, Some(1_609_459_200));
+
+        assert_eq!(search(filters.clone()), vec!["docs/new.md"]);
+        assert_eq!(
+            search(SearchFilters {
+                path_prefix: Some("docs/".into()),
+                ..SearchFilters::default()
+            }),
+            vec!["docs/api/old.md", "docs/new.md"]
+        );
+        assert!(search(SearchFilters {
+            ext: Some("markdown".into()),
+            ..SearchFilters::default()
+        })
+        .is_empty());
+
+        let workspace_fs = WorkspaceFs::new(dir_path.to_path_buf(), None);
+        let page = regex_search_page(
+            &workspace_fs,
+            &SearchExclude::default(),
+            &Mutex::default(),
+            "widget",
+            &filters,
+            &SnippetOptions::default(),
+            0,
+            10,
+        )
+        .unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.results[0].file_path, "docs/new.md");
+
+        let invalid = SearchQuery {
+            modified_after: Some("yesterday".into()),
+            ..SearchQuery::default()
+        };
+        assert!(invalid.filters().is_err());
+    }
+
+    #[test]
+    fn test_frontmatter_tags_filter_queries_and_are_counted() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_file(
+            dir_path,
+            "design.md",
+            "---\ntags: [Architecture, retries]\n---\n# Design\nRetries back off\n# Later\nMore retries",
+        )
+        .unwrap();
+        create_test_file(
+            dir_path,
+            "ops.md",
+            "---\ntags:\n  - ops\n  - retries\n---\n# Ops\nRetries in production",
+        )
+        .unwrap();
+        create_test_file(dir_path, "plain.md", "# Plain\nRetries everywhere").unwrap();
+        let index = SearchIndex::new(dir_path).unwrap();
+
+        let paths = |query: &str| {
+            let mut paths: Vec<_> = index
+                .search(query, 10)
+                .unwrap()
+                .into_iter()
+                .map(|r| r.file_path)
+                .collect();
+            paths.sort();
+            paths.dedup();
+            paths
+        };
+        assert_eq!(paths("tag:architecture retries"), ["design.md"]);
+        assert_eq!(paths("tag:Retries"), ["design.md", "ops.md"]);
+        assert_eq!(paths("tag:retries tag:ops"), ["ops.md"]);
+        assert_eq!(paths("retries").len(), 3);
+
+        let filters = SearchQuery {
+            tag: Some("ops".into()),
+            ..SearchQuery::default()
+        }
+        .filters()
+        .unwrap();
+        let page = index
+            .search_page_filtered("retries", &filters, 0, 10)
+            .unwrap();
+        assert_eq!(page.total, 1);
+
+        let tags = index.tag_counts().unwrap();
+        let tags: Vec<_> = tags.iter().map(|t| (t.tag.as_str(), t.count)).collect();
+        assert_eq!(tags, [("retries", 2), ("architecture", 1), ("ops", 1)]);
+    }
+
+    #[test]
+    fn test_split_tag_terms() {
+        assert_eq!(
+            split_tag_terms(r#"tag:a "exact phrase" tag:"two words" rest"#),
+            (
+                r#""exact phrase" rest"#.to_string(),
+                vec!["a".to_string(), "two words".to_string()]
+            )
+        );
+    }
+
+    #[test]
+    fn test_exclude_list_keeps_paths_out_of_the_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        fs::create_dir_all(dir_path.join("node_modules/pkg")).unwrap();
+        fs::create_dir_all(dir_path.join("docs/archive")).unwrap();
+        fs::create_dir_all(dir_path.join("archive")).unwrap();
+
+        create_test_file(dir_path, "node_modules/pkg/README.md", "# Pkg\nwidget").unwrap();
+        create_test_file(dir_path, "docs/archive/old.md", "# Old\nwidget").unwrap();
+        create_test_file(dir_path, "archive/keep.md", "# Keep\nwidget").unwrap();
+        create_test_file(dir_path, "guide.md", "# Guide\nwidget").unwrap();
+
+        let exclude =
+            SearchExclude::new(&["node_modules".into(), "/docs/archive/".into(), " ".into()])
+                .unwrap();
+        assert_eq!(exclude.patterns(), ["node_modules", "docs/archive"]);
+        let index = SearchIndex::with_config(
+            dir_path,
+            SearchConfig {
+                exclude: exclude.clone(),
+                ..SearchConfig::default()
+            },
+        )
+        .unwrap();
+
+        let mut paths: Vec<_> = index
+            .search("widget", 10)
+            .unwrap()
+            .into_iter()
+            .map(|r| r.file_path)
+            .collect();
+        paths.sort();
+        assert_eq!(paths, vec!["archive/keep.md", "guide.md"]);
+
+        // Watcher updates apply the same list.
+        let vendored = dir_path.join("node_modules/pkg/README.md");
+        fs::write(&vendored, "# Pkg\nwidget gadget").unwrap();
+        index.reconcile_files(&[vendored]).unwrap();
+        assert!(index.search("gadget", 10).unwrap().is_empty());
+
+        let workspace_fs = WorkspaceFs::new(dir_path.to_path_buf(), None);
+        let page = regex_search_page(
+            &workspace_fs,
+            &exclude,
+            &Mutex::default(),
+            "widget",
+            &SearchFilters::default(),
+            &SnippetOptions::default(),
+            0,
+            10,
+        )
+        .unwrap();
+        assert_eq!(page.total, 2);
+
+        assert!(SearchExclude::new(&["docs/[".into()]).is_err());
+    }
+
+    #[test]
+    fn test_search_language_selects_the_analyzer() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        create_test_file(dir_path, "en.md", "# Runner\nShe was running quickly.").unwrap();
+        create_test_file(dir_path, "ja.md", "# 日本語\n東京都に住んでいます。").unwrap();
+
+        let index_for = |lang: &str| {
+            SearchIndex::with_config(
+                dir_path,
+                SearchConfig {
+                    language: lang.parse().unwrap(),
+                    ..SearchConfig::default()
+                },
+            )
+            .unwrap()
+        };
+
+        // Stemming maps "runs" and "running" to the same term.
+        assert!(index_for("jieba").search("runs", 10).unwrap().is_empty());
+        let results = index_for("en").search("runs", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_path, "en.md");
+
+        let results = index_for("ja").search("東京", 10).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].file_path, "ja.md");
+
+        assert_eq!("CJK".parse(), Ok(SearchLanguage::Ngram));
+        assert!("klingon".parse::<SearchLanguage>().is_err());
+    }
+
+    #[test]
+    fn test_annotation_matching_requires_every_term() {
+        let annotation = serde_json::json!({
+            "id": "anno-1",
+            "text": "retry loop",
+            "note": "Timeouts should back off <exponentially>",
+        });
+
+        let hit = match_annotation(
+            "timeouts RETRY",
+            "api.md",
+            &annotation,
+            &SnippetOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(hit.annotation_id, "anno-1");
+        assert_eq!(hit.file_path, "api.md");
+        assert_eq!(hit.text, "retry loop");
+        assert_eq!(
+            hit.snippet,
+            "<b>Timeouts</b> should back off &lt;exponentially&gt;"
+        );
+
+        assert!(match_annotation(
+            "timeouts cache",
+            "api.md",
+            &annotation,
+            &SnippetOptions::default()
+        )
+        .is_none());
+        assert!(
+            match_annotation("  ", "api.md", &annotation, &SnippetOptions::default()).is_none()
+        );
+
+        let highlight_only =
+            serde_json::json!({ "id": "anno-2", "text": "Retry budget", "note": null });
+        let hit = match_annotation(
+            "budget",
+            "api.md",
+            &highlight_only,
+            &SnippetOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(hit.note, None);
+        assert_eq!(hit.snippet, "Retry <b>budget</b>");
+    }
+
     #[test]
     fn test_search_limit() {
         let temp_dir = TempDir::new().unwrap();
@@ -700,6 +2376,61 @@ mod tests {
         assert_eq!(results.len(), 10);
     }
 
+    #[test]
+    fn test_search_page_offset_and_total() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+
+        for i in 0..12 {
+            create_test_file(
+                dir_path,
+                &format!("file{}.md", i),
+                "# Document\nCommon content",
+            )
+            .unwrap();
+        }
+
+        let index = SearchIndex::new(dir_path).unwrap();
+
+        let first = index.search_page("Common", 0, 5).unwrap();
+        assert_eq!(first.total, 12);
+        assert_eq!(first.results.len(), 5);
+        assert!(first.results.iter().all(|hit| hit.score > 0.0));
+
+        let last = index.search_page("Common", 10, 5).unwrap();
+        assert_eq!(last.total, 12);
+        assert_eq!(last.results.len(), 2);
+
+        let mut seen: BTreeSet<String> = BTreeSet::new();
+        for offset in (0..12).step_by(5) {
+            for hit in index.search_page("Common", offset, 5).unwrap().results {
+                assert!(seen.insert(hit.file_path), "pages must not overlap");
+            }
+        }
+        assert_eq!(seen.len(), 12);
+
+        let count_only = index.search_page("Common", 0, 0).unwrap();
+        assert_eq!(count_only.total, 12);
+        assert!(count_only.results.is_empty());
+    }
+
+    #[test]
+    fn test_search_page_rejects_offsets_past_the_cap() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "a.md", "# A\nCommon content").unwrap();
+        let index = SearchIndex::new(temp_dir.path()).unwrap();
+
+        assert!(index
+            .search_page("Common", MAX_SEARCH_OFFSET, 5)
+            .unwrap()
+            .results
+            .is_empty());
+        let err = index
+            .search_page("Common", 1_000_000_000_000, 5)
+            .unwrap_err();
+        assert!(matches!(err, TantivyError::InvalidArgument(_)), "{err}");
+    }
+
     #[test]
     fn test_subdirectory_relative_paths() {
         let temp_dir = TempDir::new().unwrap();
@@ -836,6 +2567,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_status_counts_files_and_sections() {
+        let temp_dir = TempDir::new().unwrap();
+        create_test_file(temp_dir.path(), "a.md", "# A\nintro\n# Later\nmore").unwrap();
+        create_test_file(temp_dir.path(), "b.md", "plain").unwrap();
+        let index = SearchIndex::new(temp_dir.path()).unwrap();
+
+        let status = index.status().unwrap();
+        assert!(status.ready);
+        assert_eq!(status.documents, 2);
+        assert_eq!(status.sections, 1);
+        assert_eq!(status.pending_updates, 0);
+        assert!(status.last_commit.is_some_and(|secs| secs > 0));
+
+        // A forced rebuild picks up files the watcher never reported.
+        create_test_file(temp_dir.path(), "c.md", "# C\nunseen-by-watcher").unwrap();
+        assert!(index.search("unseen-by-watcher", 10).unwrap().is_empty());
+        index.rebuild().unwrap();
+        assert_eq!(index.search("unseen-by-watcher", 10).unwrap().len(), 1);
+        assert_eq!(index.status().unwrap().documents, 3);
+    }
+
+    #[test]
+    fn test_limits_truncate_large_files_and_cap_the_index() {
+        let temp_dir = TempDir::new().unwrap();
+        let big = format!("# Big\nalpha {}\nomega", "filler ".repeat(400));
+        create_test_file(temp_dir.path(), "big.md", &big).unwrap();
+        create_test_file(temp_dir.path(), "small.md", "# Small\nbeta").unwrap();
+        let limits = SearchLimits {
+            max_file_size: 1024,
+            ..SearchLimits::default()
+        };
+        let index = SearchIndex::with_config(
+            temp_dir.path(),
+            SearchConfig {
+                limits,
+                ..SearchConfig::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(index.search("alpha", 10).unwrap().len(), 1);
+        assert!(index.search("omega", 10).unwrap().is_empty());
+        let status = index.status().unwrap();
+        assert_eq!(status.truncated_files, 1);
+        assert_eq!(status.skipped_files, 0);
+
+        // Only one of the two files fits, whichever is indexed first.
+        let index = SearchIndex::with_config(
+            temp_dir.path(),
+            SearchConfig {
+                limits: SearchLimits {
+                    max_index_size: Some(1024),
+                    ..limits
+                },
+                ..SearchConfig::default()
+            },
+        )
+        .unwrap();
+        let status = index.status().unwrap();
+        assert_eq!(status.skipped_files, 1);
+        assert!(status.indexed_bytes <= 1024);
+
+        assert!(SearchLimits::from_mib(Some(8), None, None).is_err());
+        assert!(SearchLimits::from_mib(None, Some(0), None).is_err());
+        assert_eq!(
+            SearchLimits::from_mib(None, Some(2), Some(512))
+                .unwrap()
+                .max_index_size,
+            Some(512 * MIB)
+        );
+    }
+
     #[test]
     fn test_rebuild_if_routes_changed_skips_ignored_directory_churn() {
         let temp_dir = TempDir::new().unwrap();
@@ -1010,6 +2813,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_initial_index_commits_in_batches() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..5 {
+            create_test_file(temp_dir.path(), &format!("doc{i}.md"), "batched-token").unwrap();
+        }
+        let index = SearchIndex::empty(
+            Arc::new(WorkspaceFs::new(temp_dir.path().to_path_buf(), None)),
+            SearchConfig::default(),
+        )
+        .unwrap();
+
+        index.index_workspace_in_batches(2).unwrap();
+        assert_eq!(index.commit_count.load(Ordering::Relaxed), 3);
+        assert_eq!(index.search("batched-token", 10).unwrap().len(), 5);
+    }
+
     /// Search is case-insensitive: the analyzer lower-cases both the indexed
     /// text and the query, so case never affects whether a term matches.
     #[test]
@@ -12,7 +12,7 @@ use futures_util::{stream::StreamExt, SinkExt};
 use qrcode::render::unicode::Dense1x2;
 use qrcode::{EcLevel, QrCode};
 use rayon::prelude::*;
-use rusqlite::{params, Connection};
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use similar::{ChangeTag, TextDiff};
@@ -26,14 +26,24 @@ use tokio::net::TcpListener;
 use tokio::sync::{broadcast, mpsc};
 
 use crate::admin_auth::{self, AdminBootstrapStore};
+use crate::annotations::{
+    valid_annotation_id, validate_annotation, AnnotationFilter, AnnotationSave,
+};
 use crate::assets::{CssAssets, IconAssets, JsAssets, Templates};
 use crate::git;
 use crate::i18n;
+pub use crate::markdown::DEFAULT_MARKDOWN_EXTENSIONS;
 use crate::markdown::{
-    default_markdown_engine, MarkdownEngine, MarkdownHtmlRenderer, MarkdownRenderer,
+    default_markdown_engine, MarkdownEngine, MarkdownExtensions, MarkdownHtmlRenderer,
+    MarkdownRenderOutput, MarkdownRenderer, TocItem,
 };
 use crate::markdown_ast;
-use crate::search::{SearchQuery, SearchResult};
+use crate::metadata::MetadataCache;
+use crate::remote_markdown::{RemoteAllowlist, RemoteError, RemoteMarkdown};
+use crate::search::{
+    SearchIndex, SearchMode, SearchPage, SearchQuery, SearchStatus, TagCount, DEFAULT_SEARCH_LIMIT,
+    MAX_SEARCH_LIMIT, MAX_SEARCH_OFFSET,
+};
 use crate::workspace::{
     ct_eq, expand_and_canonicalize, generate_token, ServerLock, WorkspaceConfig, WorkspaceEntry,
     WorkspaceEvent, WorkspaceFlags, WorkspaceRegistry,
@@ -42,6 +52,23 @@ use crate::workspace_fs::WorkspaceFs;
 
 const WORKSPACE_WS_ROUTE: &str = "/_/{workspace_id}/ws";
 const DOCUMENT_STATE_ROUTE: &str = "/_/{workspace_id}/data/document-state";
+const CLIENT_VIEWED_STATE_ROUTE: &str = "/_/{workspace_id}/data/viewed-state";
+const PINNED_FILES_ROUTE: &str = "/_/{workspace_id}/data/pins";
+const ANNOTATIONS_ROUTE: &str = "/_/{workspace_id}/annotations";
+const ANNOTATIONS_EXPORT_ROUTE: &str = "/_/{workspace_id}/annotations/export";
+const ANNOTATIONS_IMPORT_ROUTE: &str = "/_/{workspace_id}/annotations/import";
+const ANNOTATIONS_SUMMARY_ROUTE: &str = "/_/{workspace_id}/annotations/summary";
+const PROGRESS_ROUTE: &str = "/_/{workspace_id}/progress";
+const ANNOTATIONS_HISTORY_ROUTE: &str = "/_/{workspace_id}/annotations/history";
+const ANNOTATIONS_UNDO_ROUTE: &str = "/_/{workspace_id}/annotations/undo";
+const ANNOTATIONS_PRUNE_ROUTE: &str = "/_/{workspace_id}/annotations/prune";
+const ANNOTATION_ATTACHMENTS_ROUTE: &str = "/_/{workspace_id}/annotations/attachments";
+const ANNOTATION_ATTACHMENT_ROUTE: &str =
+    "/_/{workspace_id}/annotations/attachments/{attachment_id}";
+const ANNOTATION_ROUTE: &str = "/_/{workspace_id}/annotations/{annotation_id}";
+const ANNOTATION_RESOLUTION_ROUTE: &str =
+    "/_/{workspace_id}/annotations/{annotation_id}/resolution";
+const REMOTE_MARKDOWN_ROUTE: &str = "/_/remote";
 
 /// Public wire-format types served by the (non-chat) HTTP surface.
 ///
@@ -64,14 +91,23 @@ pub struct WorkspaceInit {
     /// its parent directory and the registry keeps serving/search scoped to
     /// this file and its explicitly referenced local assets.
     pub single_file: Option<String>,
+    /// Files of a curated file-set workspace, relative to `path`; see
+    /// [`WorkspaceConfig::files`].
+    pub files: Vec<String>,
     /// Per-workspace collaborator access-code hash (empty = inherit the server
     /// collaborator code).
     pub collaborator_access_code_hash: String,
     /// Optional short display name (empty = none).
     pub alias: String,
+    /// Two files of this workspace the browser opens side by side instead of
+    /// `initial_path` (`markon diff`).
+    pub compare: Option<(String, String)>,
 }
 
-/// Server configuration
+/// Server configuration. Start from [`ServerBuilder`] (or
+/// [`ServerBuilder::build`]) rather than a struct literal: fields are added
+/// as the server grows.
+#[non_exhaustive]
 pub struct ServerConfig {
     pub host: String,
     /// Preferred address to feature when bound to a wildcard (0.0.0.0/::):
@@ -84,13 +120,24 @@ pub struct ServerConfig {
     /// be bare hosts or full http(s) origins; no wildcards are accepted.
     pub trusted_hosts: Vec<String>,
     pub port: u16,
+    /// One of [`crate::theme::BUILT_IN`]: the page's default theme.
     pub theme: String,
+    /// Custom theme stylesheet (`--theme <FILE>`), linked after the built-in
+    /// styles and before any `--custom-css`.
+    pub theme_css: Option<PathBuf>,
     pub qr: Option<String>,
+    /// Image file the shareable URL's QR code is written to (`--qr-out`).
+    pub qr_out: Option<PathBuf>,
     pub open_browser: Option<String>,
+    /// Heading anchor the opened browser scrolls to (`--open-to`).
+    pub open_anchor: Option<String>,
     pub shared_annotation: bool,
     /// SQLite path for annotations, viewed state, and chat.
     /// `MARKON_SQLITE_PATH` still takes precedence when present.
     pub db_path: Option<String>,
+    /// Shared database for document state (`postgres://…`); see
+    /// [`crate::store`]. `None` keeps it in the SQLite database.
+    pub db_url: Option<String>,
     /// Random salt for workspace ID generation; None = auto-generate.
     pub salt: Option<String>,
     pub initial_workspaces: Vec<WorkspaceInit>,
@@ -113,6 +160,12 @@ pub struct ServerConfig {
     /// Custom CSS variable overrides pre-rendered by
     /// `AppSettings::render_styles_css`; templates inject it verbatim.
     pub styles_css: Option<String>,
+    /// `--custom-css` stylesheets, file paths or http(s) URLs, linked into
+    /// every page after the built-in styles.
+    pub user_css: Vec<String>,
+    /// `--custom-js` script files loaded by every page after the built-in
+    /// scripts.
+    pub user_js: Vec<String>,
     /// Default chat surface: "in_page" or "popout". Surfaced to the browser
     /// via the `default-chat-mode` meta tag.
     pub default_chat_mode: String,
@@ -123,6 +176,291 @@ pub struct ServerConfig {
     /// content ends up on paper. When false (default) the content stays hidden
     /// and a small placeholder marks the position of the collapsed section.
     pub print_collapsed_content: bool,
+    /// Content-Security-Policy override. `None` keeps the built-in policy
+    /// (compatible with the embedded assets and mermaid); `"off"` omits it.
+    pub content_security_policy: Option<String>,
+    /// Framing policy: `"deny"`, `"sameorigin"` (default) or `"off"`. Sets
+    /// X-Frame-Options and the built-in CSP's `frame-ancestors`.
+    pub frame_options: Option<String>,
+    /// Disable the per-workspace rendered-page cache (`--no-cache`); every
+    /// request re-renders the document.
+    pub no_cache: bool,
+    /// Glob patterns kept out of the search index in addition to ignore
+    /// files (`--search-exclude`); see [`crate::search::SearchExclude`].
+    pub search_exclude: Vec<String>,
+    /// Search text analyzer (`--search-lang`): `jieba` (default), `en`, or
+    /// `ngram`; see [`crate::search::SearchLanguage`].
+    pub search_lang: Option<String>,
+    /// Largest `limit` a search request may ask for (`--search-max-limit`);
+    /// `None` uses [`MAX_SEARCH_LIMIT`].
+    pub search_max_limit: Option<usize>,
+    /// Search index writer heap in MiB (`--search-writer-memory`); `None`
+    /// uses [`crate::search::DEFAULT_WRITER_MEMORY`].
+    pub search_writer_memory: Option<usize>,
+    /// MiB of each file that is indexed (`--search-max-file-size`); `None`
+    /// uses [`crate::search::DEFAULT_MAX_FILE_SIZE`].
+    pub search_max_file_size: Option<u64>,
+    /// MiB of Markdown the index holds before skipping further files
+    /// (`--search-max-index-size`); `None` is unbounded.
+    pub search_max_index_size: Option<u64>,
+    /// Reject shared annotation and viewed-state changes that carry no
+    /// display name (`--require-name`); the page asks for one on first visit.
+    pub require_name: bool,
+    /// Secret a non-admin client must present (`X-Markon-Annotation-Token`)
+    /// to change annotations (`--annotation-token`). Without it, shared
+    /// sessions are read-only for everyone but admins.
+    pub annotation_token: Option<String>,
+    /// File names rendered below a directory listing, first match wins
+    /// (`--readme`). Empty uses [`DEFAULT_README_NAMES`].
+    pub readme_names: Vec<String>,
+    /// Workspace-relative mdBook-style `SUMMARY.md` files that turn a
+    /// workspace into a book with a chapter sidebar, first match wins
+    /// (`--nav-file`). Empty uses [`DEFAULT_NAV_FILES`].
+    pub nav_files: Vec<String>,
+    /// Extensions rendered, listed, watched and indexed as markdown
+    /// (`--markdown-ext`). Empty uses [`DEFAULT_MARKDOWN_EXTENSIONS`].
+    pub markdown_extensions: Vec<String>,
+    /// List and index dotfiles and dot-directories other than `.git`
+    /// (`--show-hidden`).
+    pub show_hidden: bool,
+    /// Refuse every path through a symlink instead of following the ones
+    /// that stay inside the workspace (`--no-follow-symlinks`).
+    pub no_follow_symlinks: bool,
+    /// Workspace-relative globs a path must match to be served, listed or
+    /// indexed (`--allow-path`); see [`crate::path_rules`]. Empty allows
+    /// every path.
+    pub allow_paths: Vec<String>,
+    /// Workspace-relative globs of paths never served, listed or indexed
+    /// (`--deny-path`).
+    pub deny_paths: Vec<String>,
+    /// Entries per page of a directory listing (`--listing-page-size`);
+    /// `None` uses [`DEFAULT_LISTING_PAGE_SIZE`].
+    pub listing_page_size: Option<usize>,
+    /// Documents rendering to more than this many KiB are split into pages
+    /// (`--paginate`); `None` always renders one page.
+    pub paginate_kib: Option<usize>,
+    /// Hosts `/_/remote?url=` may fetch Markdown from (`--allow-remote`); see
+    /// [`crate::remote_markdown::RemoteAllowlist`]. Empty turns the route off.
+    pub allow_remote: Vec<String>,
+    /// URLs POSTed to when watched documents are created, modified or
+    /// deleted (`--notify-url`); see [`crate::change_notifier`]. Empty sends
+    /// nothing.
+    pub notify_urls: Vec<String>,
+    /// Workspace-relative globs a change must match to be notified
+    /// (`--notify-path`). Empty notifies every document.
+    pub notify_paths: Vec<String>,
+    /// Change kinds notified: `created`, `modified`, `deleted`
+    /// (`--notify-event`). Empty notifies all three.
+    pub notify_events: Vec<String>,
+    /// Render the formats in [`crate::pandoc`] by converting them with an
+    /// installed `pandoc` (`--pandoc`).
+    pub pandoc: bool,
+    /// Record each version of the watched documents in the database
+    /// (`--snapshots`); see [`crate::snapshots`].
+    pub snapshots: bool,
+    /// Keep an append-only log of annotation changes and served files
+    /// (`--audit`); see [`crate::audit`].
+    pub audit: bool,
+    /// `NAME=URL` link schemes (`--link-scheme`); see
+    /// [`crate::LinkSchemes`].
+    pub link_schemes: Vec<String>,
+    /// Directory of WASM render plugins (`--plugins`); see
+    /// [`crate::wasm_plugin`]. Needs the `wasm-plugins` feature.
+    pub plugins_dir: Option<PathBuf>,
+    /// Require a random per-start token on every route (`--share-token`).
+    /// The printed URLs, QR codes and opened browser carry it.
+    pub share_token: bool,
+    /// Path the app is mounted under when served with [`router`], e.g.
+    /// `/docs`. Empty mounts it at the root. [`start`] always serves at the
+    /// root and rejects a base path.
+    pub base_path: String,
+}
+
+/// Port the server listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 6419;
+
+/// A [`ServerConfig`] for embedding the server in another program: the
+/// defaults of a plain `markon` run, serving only on localhost, with no
+/// browser opened and no workspace until one is added.
+///
+/// ```no_run
+/// # async fn serve() -> Result<(), String> {
+/// use markon_core::{ServerBuilder, WorkspaceFlags};
+///
+/// let flags = WorkspaceFlags {
+///     enable_search: true,
+///     ..Default::default()
+/// };
+/// ServerBuilder::new()
+///     .port(8080)
+///     .workspace("docs", flags)
+///     .start()
+///     .await
+/// # }
+/// ```
+pub struct ServerBuilder {
+    config: ServerConfig,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: ServerConfig {
+                host: "127.0.0.1".to_string(),
+                advertised_host: String::new(),
+                trusted_hosts: Vec::new(),
+                port: DEFAULT_PORT,
+                theme: "auto".to_string(),
+                theme_css: None,
+                qr: None,
+                qr_out: None,
+                open_browser: None,
+                open_anchor: None,
+                shared_annotation: false,
+                db_path: None,
+                db_url: None,
+                salt: None,
+                initial_workspaces: Vec::new(),
+                bound_listener: None,
+                registry: None,
+                management_token: None,
+                admin_bootstraps: None,
+                language: None,
+                shortcuts_json: None,
+                styles_css: None,
+                user_css: Vec::new(),
+                user_js: Vec::new(),
+                default_chat_mode: "in_page".to_string(),
+                collaborator_access_code_hash: String::new(),
+                print_collapsed_content: false,
+                content_security_policy: None,
+                frame_options: None,
+                no_cache: false,
+                search_exclude: Vec::new(),
+                search_lang: None,
+                search_max_limit: None,
+                search_writer_memory: None,
+                search_max_file_size: None,
+                search_max_index_size: None,
+                require_name: false,
+                annotation_token: None,
+                readme_names: Vec::new(),
+                nav_files: Vec::new(),
+                markdown_extensions: Vec::new(),
+                show_hidden: false,
+                no_follow_symlinks: false,
+                allow_paths: Vec::new(),
+                deny_paths: Vec::new(),
+                listing_page_size: None,
+                paginate_kib: None,
+                allow_remote: Vec::new(),
+                notify_urls: Vec::new(),
+                notify_paths: Vec::new(),
+                notify_events: Vec::new(),
+                pandoc: false,
+                snapshots: false,
+                audit: false,
+                link_schemes: Vec::new(),
+                plugins_dir: None,
+                share_token: false,
+                base_path: String::new(),
+            },
+        }
+    }
+
+    /// Address to bind; `0.0.0.0` shares the server on the network.
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.config.host = host.into();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.config.port = port;
+        self
+    }
+
+    /// Serve on a listener the caller already bound, e.g. to port 0.
+    pub fn listener(mut self, listener: std::net::TcpListener) -> Self {
+        self.config.bound_listener = Some(listener);
+        self
+    }
+
+    /// Another host name pages may be reached at, or its `https://` origin;
+    /// see [`ServerConfig::trusted_hosts`].
+    pub fn trusted_host(mut self, host: impl Into<String>) -> Self {
+        self.config.trusted_hosts.push(host.into());
+        self
+    }
+
+    /// One of [`crate::theme::BUILT_IN`].
+    pub fn theme(mut self, theme: impl Into<String>) -> Self {
+        self.config.theme = theme.into();
+        self
+    }
+
+    /// UI language, e.g. `"en"`; detected from the system when unset.
+    pub fn language(mut self, language: impl Into<String>) -> Self {
+        self.config.language = Some(language.into());
+        self
+    }
+
+    /// SQLite file for annotations, viewed state and chat.
+    pub fn db_path(mut self, path: impl Into<String>) -> Self {
+        self.config.db_path = Some(path.into());
+        self
+    }
+
+    /// Serve the directory at `path` as a workspace.
+    pub fn workspace(mut self, path: impl Into<PathBuf>, flags: WorkspaceFlags) -> Self {
+        self.config.shared_annotation |= flags.shared_annotation;
+        self.config.initial_workspaces.push(WorkspaceInit {
+            path: path.into(),
+            flags,
+            initial_path: None,
+            single_file: None,
+            files: Vec::new(),
+            collaborator_access_code_hash: String::new(),
+            alias: String::new(),
+            compare: None,
+        });
+        self
+    }
+
+    /// Mount the app under `path` (`/docs`) instead of at the root; see
+    /// [`router`].
+    pub fn base_path(mut self, path: impl Into<String>) -> Self {
+        self.config.base_path = path.into();
+        self
+    }
+
+    /// Share a registry with the caller, which can then add and remove
+    /// workspaces while the server runs.
+    pub fn registry(mut self, registry: Arc<WorkspaceRegistry>) -> Self {
+        self.config.registry = Some(registry);
+        self
+    }
+
+    /// The configuration, for settings this builder has no method for.
+    pub fn build(self) -> ServerConfig {
+        self.config
+    }
+
+    /// Run the server until it is shut down; see [`start`].
+    pub async fn start(self) -> Result<(), String> {
+        start(self.config).await
+    }
+
+    /// The web app alone, to serve from another axum application; see
+    /// [`router`].
+    pub async fn router(self) -> Result<Router, String> {
+        router(self.config).await
+    }
 }
 
 /// Per-IP failed-unlock state for the access-code brute-force cooldown.
@@ -260,8 +598,14 @@ impl AllowedHosts {
 #[derive(Clone)]
 pub(crate) struct AppState {
     pub theme: Arc<String>,
+    /// A `--theme` stylesheet is linked: the page's document surfaces follow
+    /// the tokens rather than the GitHub Markdown palette.
+    pub custom_theme: bool,
     pub tera: Arc<Tera>,
-    pub db: Option<Arc<Mutex<Connection>>>,
+    /// The prefix the app is mounted under (see [`router`]), put in front
+    /// of every URL it hands out; empty at the root.
+    pub base_path: Arc<str>,
+    pub db: Option<crate::db::Database>,
     pub workspace_registry: Arc<WorkspaceRegistry>,
     pub management_token: Arc<String>,
     pub admin_bootstraps: Arc<AdminBootstrapStore>,
@@ -279,6 +623,10 @@ pub(crate) struct AppState {
     pub shortcuts_json: Arc<String>,
     /// CSS variable overrides string.
     pub styles_css: Arc<String>,
+    /// `--custom-css` stylesheets, in the order they are linked.
+    pub user_stylesheets: Arc<Vec<UserAsset>>,
+    /// `--custom-js` scripts, in the order they load.
+    pub user_scripts: Arc<Vec<UserAsset>>,
     /// Default chat surface ("in_page" or "popout").
     pub default_chat_mode: Arc<String>,
     /// Access gate: server-level collaborator access-code hash.
@@ -292,9 +640,53 @@ pub(crate) struct AppState {
     /// In-memory rendered Markdown diff cache. Scoped to this server state so
     /// theme/config changes get their own cache lifecycle.
     pub(crate) markdown_diff_cache: Arc<Mutex<MarkdownDiffCache>>,
+    /// Tar archive listings, reused while the archive is unchanged.
+    pub(crate) archive_index: Arc<Mutex<crate::archive::IndexCache>>,
+    /// Built-in render hooks from the config (`--link-scheme`), run before
+    /// the ones registered with [`crate::register_render_hook`].
+    pub(crate) render_hooks: Arc<[Arc<dyn crate::RenderHook>]>,
     /// Whether collapsed sections should be printed (true) or replaced by a
     /// placeholder (false). Mirrored to the browser as a `<html>` data attr.
     pub print_collapsed_content: bool,
+    /// Serve repeat views of unchanged documents from the workspace's
+    /// [`RenderCache`](crate::workspace::RenderCache). Off with `--no-cache`.
+    pub render_cache: bool,
+    /// Upper bound for a search request's `limit`.
+    pub search_max_limit: usize,
+    /// Annotation and viewed-state changes must carry a named identity.
+    pub require_name: bool,
+    /// Write secret for non-admin annotation changes; `None` leaves writes open.
+    pub annotation_token: Option<Arc<String>>,
+    /// README candidates for directory listings, in priority order.
+    pub readme_names: Arc<Vec<String>>,
+    /// Book nav file candidates, in priority order.
+    pub nav_files: Arc<Vec<String>>,
+    /// Resolved `--listing-page-size`.
+    pub listing_page_size: usize,
+    /// `--paginate` in bytes; `None` renders every document as one page.
+    pub paginate_bytes: Option<usize>,
+    /// Base URL a shared QR code points at: the `--entry` prefix when given,
+    /// else the featured address. Set once the listener is bound.
+    pub share_base: Arc<std::sync::OnceLock<String>>,
+    /// Fetches and caches documents for `/_/remote`.
+    pub remote_markdown: Arc<RemoteMarkdown>,
+    /// Where annotation image attachments are stored, beside the database.
+    pub attachments_dir: Option<Arc<PathBuf>>,
+    /// Shared document store from `--db-url`. When set, document state lives
+    /// there and the SQLite-only annotation features are unavailable.
+    pub remote_store: Option<Arc<dyn crate::store::DocumentStore>>,
+    /// Who is connected to each document over WebSocket.
+    pub(crate) presence: Arc<crate::presence::PresenceRegistry>,
+    /// Buffers pushed by editor plugins, see [`crate::editor`].
+    pub(crate) editor: Arc<crate::editor::EditorHub>,
+    /// Converter for the formats in [`crate::pandoc`]; `None` unless
+    /// `--pandoc` was given and pandoc is installed.
+    pub(crate) pandoc: Option<Arc<crate::pandoc::Pandoc>>,
+    /// `--share-token`: the token every request must carry, or have traded
+    /// for the share cookie.
+    pub(crate) share_token: Option<Arc<String>>,
+    /// `--audit`: write the [`crate::audit::Trail`] of each response.
+    pub(crate) audit: bool,
     /// Dev-only: esbuild watcher posts to /_/dev/reload-trigger and the
     /// webview's SSE stream listens on this channel to fire location.reload().
     /// Cheap to keep in release builds (one Arc<broadcast::Sender>); the
@@ -303,6 +695,23 @@ pub(crate) struct AppState {
     pub dev_reload_tx: Arc<broadcast::Sender<()>>,
 }
 
+impl AppState {
+    /// Where document state is read and written.
+    fn document_store(&self) -> Option<Arc<dyn crate::store::DocumentStore>> {
+        self.remote_store.clone().or_else(|| {
+            self.db.clone().map(|db| {
+                Arc::new(crate::store::sqlite::SqliteStore::new(db))
+                    as Arc<dyn crate::store::DocumentStore>
+            })
+        })
+    }
+
+    /// The SQLite database holding annotations, unless a remote store does.
+    fn annotation_db(&self) -> Option<&crate::db::Database> {
+        self.db.as_ref().filter(|_| self.remote_store.is_none())
+    }
+}
+
 fn detect_lang(override_lang: &Option<String>) -> String {
     match override_lang {
         Some(lang) => i18n::resolve_lang(lang).to_string(),
@@ -319,33 +728,132 @@ fn js_json_safe(json: String) -> String {
         .replace('&', "\\u0026")
 }
 
+/// Path of a workspace, or of `initial_path` in it, on a server [`start`]
+/// runs.
 pub fn workspace_url_path(workspace_id: &str, initial_path: Option<&str>) -> String {
     match initial_path {
-        Some(path) => workspace_file_url(workspace_id, path),
-        None => workspace_root_url(workspace_id),
+        Some(path) => workspace_file_url("", workspace_id, path),
+        None => workspace_root_url("", workspace_id),
+    }
+}
+
+/// The id of the section in `markdown` that `heading` names, for landing a
+/// browser on it: the heading's text (ignoring case) or its anchor, with or
+/// without a leading `#`. `None` when the document has no such heading.
+pub fn heading_anchor(markdown: &str, heading: &str) -> Option<String> {
+    let wanted = heading.trim();
+    let wanted = wanted.strip_prefix('#').unwrap_or(wanted).trim();
+    if wanted.is_empty() {
+        return None;
     }
+    let sections = crate::markdown::heading_sections(crate::frontmatter::strip(markdown));
+    let wanted_text = wanted.to_lowercase();
+    sections
+        .iter()
+        .find(|section| {
+            section
+                .heading
+                .as_deref()
+                .is_some_and(|text| text.trim().to_lowercase() == wanted_text)
+        })
+        .or_else(|| {
+            sections
+                .iter()
+                .find(|section| section.anchor.as_deref() == Some(wanted))
+        })
+        .and_then(|section| section.anchor.clone())
 }
 
-fn workspace_root_url(workspace_id: &str) -> String {
-    format!("/{workspace_id}/")
+fn workspace_root_url(base_path: &str, workspace_id: &str) -> String {
+    format!("{base_path}/{workspace_id}/")
 }
 
-fn workspace_file_url(workspace_id: &str, path: &str) -> String {
+fn workspace_file_url(base_path: &str, workspace_id: &str, path: &str) -> String {
     let rel = path.trim_start_matches('/');
     if rel.is_empty() {
-        workspace_root_url(workspace_id)
+        workspace_root_url(base_path, workspace_id)
     } else {
-        format!("/{workspace_id}/{}", encode_route_path(rel))
+        format!("{base_path}/{workspace_id}/{}", encode_route_path(rel))
     }
 }
 
-fn workspace_internal_url(workspace_id: &str, path: &str) -> String {
+fn workspace_internal_url(base_path: &str, workspace_id: &str, path: &str) -> String {
     let rel = path.trim_start_matches('/');
-    format!("/_/{workspace_id}/{rel}")
+    format!("{base_path}/_/{workspace_id}/{rel}")
+}
+
+fn workspace_git_history_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "git/history")
 }
 
-fn workspace_git_history_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "git/history")
+fn document_history_url(base_path: &str, workspace_id: &str, path: &str) -> String {
+    workspace_internal_url(
+        base_path,
+        workspace_id,
+        &format!("history/{}", encode_route_path(path)),
+    )
+}
+
+fn document_present_url(base_path: &str, workspace_id: &str, path: &str) -> String {
+    workspace_internal_url(
+        base_path,
+        workspace_id,
+        &format!("present/{}", encode_route_path(path)),
+    )
+}
+
+fn document_print_url(base_path: &str, workspace_id: &str, path: &str) -> String {
+    workspace_internal_url(
+        base_path,
+        workspace_id,
+        &format!("print/{}", encode_route_path(path)),
+    )
+}
+
+fn document_snapshots_url(base_path: &str, workspace_id: &str, path: &str) -> String {
+    workspace_internal_url(
+        base_path,
+        workspace_id,
+        &format!("snapshots/{}", encode_route_path(path)),
+    )
+}
+
+/// The side-by-side comparison of two documents of a workspace, on a server
+/// [`start`] runs.
+pub fn workspace_compare_url(workspace_id: &str, left: &str, right: &str) -> String {
+    format!(
+        "{}?left={}&right={}",
+        workspace_internal_url("", workspace_id, "compare"),
+        urlencoding::encode(left),
+        urlencoding::encode(right)
+    )
+}
+
+fn document_revision_url(base_path: &str, workspace_id: &str, path: &str, rev: &str) -> String {
+    format!(
+        "{}?rev={}",
+        document_history_url(base_path, workspace_id, path),
+        urlencoding::encode(rev)
+    )
+}
+
+fn document_diff_url(
+    base_path: &str,
+    workspace_id: &str,
+    path: &str,
+    from: &str,
+    to: &str,
+) -> String {
+    format!(
+        "{}?from={}&to={}",
+        workspace_internal_url(
+            base_path,
+            workspace_id,
+            &format!("diff/{}", encode_route_path(path))
+        ),
+        urlencoding::encode(from),
+        urlencoding::encode(to)
+    )
 }
 
 fn normalize_host_name(value: &str) -> Option<String> {
@@ -421,44 +929,48 @@ fn build_allowed_hosts(
     allowed
 }
 
-fn workspace_git_branches_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "git/branches")
+fn workspace_git_branches_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "git/branches")
+}
+
+fn workspace_git_tags_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "git/tags")
 }
 
-fn workspace_git_tags_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "git/tags")
+fn workspace_git_checkout_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "git/checkout")
 }
 
-fn workspace_git_checkout_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "git/checkout")
+fn workspace_files_data_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "files/data")
 }
 
-fn workspace_files_data_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "files/data")
+fn workspace_files_dir_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "files/dir")
 }
 
-fn workspace_files_dir_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "files/dir")
+fn workspace_file_create_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "files/create")
 }
 
-fn workspace_file_create_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "files/create")
+fn workspace_folder_create_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "files/folder")
 }
 
-fn workspace_folder_create_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "files/folder")
+fn workspace_pins_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "data/pins")
 }
 
-fn workspace_settings_features_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "settings/features")
+fn workspace_settings_features_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "settings/features")
 }
 
-fn workspace_compare_base_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "compare")
+fn workspace_compare_base_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "compare")
 }
 
-fn workspace_compare_options_url(workspace_id: &str) -> String {
-    workspace_internal_url(workspace_id, "compare/options")
+fn workspace_compare_options_url(base_path: &str, workspace_id: &str) -> String {
+    workspace_internal_url(base_path, workspace_id, "compare/options")
 }
 
 /// One reachable base URL with a human-facing label (network interface name,
@@ -726,17 +1238,22 @@ fn path_to_hash(path: &FsPath) -> String {
     encode_route_path(&path_to_route(path))
 }
 
-fn workspace_file_back_link(workspace_id: &str, path: &FsPath, root: &FsPath) -> String {
+fn workspace_file_back_link(
+    base_path: &str,
+    workspace_id: &str,
+    path: &FsPath,
+    root: &FsPath,
+) -> String {
     workspace_relative_path(path, root)
         .map(|rel| {
             let hash_path = path_to_hash(&rel);
             if hash_path.is_empty() {
-                workspace_root_url(workspace_id)
+                workspace_root_url(base_path, workspace_id)
             } else {
-                format!("/{workspace_id}/#{hash_path}")
+                format!("{base_path}/{workspace_id}/#{hash_path}")
             }
         })
-        .unwrap_or_else(|| workspace_root_url(workspace_id))
+        .unwrap_or_else(|| workspace_root_url(base_path, workspace_id))
 }
 
 fn sanitize_new_file_path(path: &str) -> Option<PathBuf> {
@@ -761,11 +1278,6 @@ fn sanitize_new_file_path(path: &str) -> Option<PathBuf> {
 
 /// The file-type rule deciding what the server renders as markdown (vs raw-
 /// serves, lists, or allows editing).
-fn is_markdown_path(path: &FsPath) -> bool {
-    path.extension()
-        .is_some_and(|e| e.to_string_lossy().to_lowercase() == "md")
-}
-
 pub fn print_compact_qr(data: &str) -> Result<(), Box<dyn std::error::Error>> {
     // Use low error correction level for smaller QR codes
     let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::L)?;
@@ -789,6 +1301,37 @@ pub fn print_compact_qr(data: &str) -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+/// `data` as a standalone SVG QR code, for `/_/qr.svg` and `--qr-out`.
+pub fn qr_svg(data: &str) -> Result<String, qrcode::types::QrError> {
+    // Medium error correction survives a projector or a phone held at an angle.
+    let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)?;
+    Ok(code
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(256, 256)
+        .build())
+}
+
+/// Write a QR code for `data` to `path` (`--qr-out`): SVG for a `.svg` file,
+/// PNG otherwise.
+pub fn write_qr_image(
+    data: &str,
+    path: &std::path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let is_svg = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        std::fs::write(path, qr_svg(data)?)?;
+    } else {
+        let code = QrCode::with_error_correction_level(data.as_bytes(), EcLevel::M)?;
+        code.render::<image::Luma<u8>>()
+            .min_dimensions(512, 512)
+            .build()
+            .save_with_format(path, image::ImageFormat::Png)?;
+    }
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type")]
 enum WebSocketMessage {
@@ -817,6 +1360,13 @@ enum WebSocketMessage {
     #[serde(rename = "viewed_state")]
     ViewedState {
         state: serde_json::Value,
+        /// Identity (`{name, color}`) of whoever last changed the state.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        by: Option<serde_json::Value>,
+        /// Heading ids unchecked because their section changed after being
+        /// viewed (see [`crate::viewed`]).
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        changed: Vec<String>,
         #[serde(default, skip_serializing_if = "Option::is_none")]
         op_id: Option<String>,
     },
@@ -827,6 +1377,77 @@ enum WebSocketMessage {
     /// what it's currently displaying and reloads if it matches.
     #[serde(rename = "file_changed")]
     FileChanged { workspace_id: String, path: String },
+    /// Sent once to a document session after it joins: its own client id
+    /// and everyone currently viewing the document, itself included.
+    #[serde(rename = "presence")]
+    Presence {
+        client_id: String,
+        viewers: Vec<crate::presence::Viewer>,
+    },
+    /// Someone opened the document; `viewers` is the new total.
+    #[serde(rename = "presence_join")]
+    PresenceJoin {
+        viewer: crate::presence::Viewer,
+        viewers: usize,
+    },
+    /// Someone closed the document; `viewers` is the new total.
+    #[serde(rename = "presence_leave")]
+    PresenceLeave { client_id: String, viewers: usize },
+    /// Ephemeral text selection of one document viewer, never stored. Clients
+    /// send only `range` (`null` when nothing is selected); the server stamps
+    /// the sender's presence id and identity before relaying.
+    #[serde(rename = "selection")]
+    Selection {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        client_id: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        identity: Option<serde_json::Value>,
+        range: Option<SelectionRange>,
+    },
+    /// Reply to a client frame the server rejected; nothing was relayed.
+    #[serde(rename = "error")]
+    Error {
+        reason: WsErrorReason,
+        message: String,
+    },
+}
+
+/// A selection as the Live protocol addresses it: article XPaths plus text
+/// offsets within those elements.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SelectionRange {
+    start_path: String,
+    start_offset: u32,
+    end_path: String,
+    end_offset: u32,
+}
+
+/// Why an inbound WebSocket frame was rejected.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum WsErrorReason {
+    TooLarge,
+    Malformed,
+    Unsupported,
+    InvalidLiveAction,
+    InvalidSelection,
+}
+
+impl WsErrorReason {
+    fn message(self) -> &'static str {
+        match self {
+            Self::TooLarge => "message is too large",
+            Self::Malformed => "message must be a JSON object with a string `type`",
+            Self::Unsupported => {
+                "only live_action and document selection messages are accepted; save document state over HTTP"
+            }
+            Self::InvalidLiveAction => "live_action data must be an object with a string `action`",
+            Self::InvalidSelection => {
+                "selection range must be null or {startPath, startOffset, endPath, endOffset}"
+            }
+        }
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -835,6 +1456,12 @@ struct WsHello {
     #[serde(rename = "type")]
     _kind: WsHelloKind,
     target: WsTarget,
+    /// Narrows the initial `all_annotations` payload of a document session.
+    #[serde(default)]
+    filter: AnnotationFilter,
+    /// `{color, name}` shown to other viewers of a document session.
+    #[serde(default)]
+    identity: Option<serde_json::Value>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -862,17 +1489,80 @@ struct WsSession {
     target: WsSessionTarget,
 }
 
-pub async fn start(config: ServerConfig) -> Result<(), String> {
+/// The web app of a server, built from `config` and ready to serve, with
+/// the settings and handles [`start`] still needs to bind it and run the
+/// control socket.
+struct Prepared {
+    app: Router,
+    base_path: String,
+    host: String,
+    advertised_host: String,
+    port: u16,
+    bound_listener: Option<std::net::TcpListener>,
+    qr: Option<String>,
+    qr_out: Option<PathBuf>,
+    open_browser: Option<String>,
+    open_anchor: Option<String>,
+    first_workspace_url_path: Option<String>,
+    registry: Arc<WorkspaceRegistry>,
+    db: Option<crate::db::Database>,
+    admin_bootstraps: Arc<AdminBootstrapStore>,
+    save_token: Arc<String>,
+    share_token: Option<Arc<String>>,
+    shutdown_tx: mpsc::Sender<()>,
+    shutdown_rx: mpsc::Receiver<()>,
+    share_base: Arc<std::sync::OnceLock<String>>,
+    history_gc_task: Option<tokio::task::JoinHandle<()>>,
+    startup_started: Instant,
+}
+
+/// The markon web app as an [`axum::Router`], for serving it from another
+/// axum application instead of running [`start`]: the host keeps its own
+/// runtime, listener, TLS and middleware. No listener is bound, no browser
+/// is opened, and there is no control socket or lock file, so `markon`
+/// commands can't manage this server; workspaces come from `config` or
+/// from a shared [`ServerConfig::registry`].
+///
+/// The router answers at the root unless `config.base_path` names a prefix
+/// such as `/docs`: its routes then live below it, and the redirects, page
+/// links and JSON URLs it builds carry it. Merge the returned router
+/// into the host app rather than nesting it yourself, and serve it with
+/// `into_make_service_with_connect_info::<SocketAddr>()`. Requests must
+/// still name an allowed host: `config.host`, `config.port` and
+/// `config.trusted_hosts` should match the address the app is reached at,
+/// and `config.qr` may name its public URL, base path included, for share
+/// links and QR codes.
+pub async fn router(config: ServerConfig) -> Result<Router, String> {
+    let prepared = prepare(config).await?;
+    if let Some(base) = prepared.qr.as_deref().filter(|base| *base != "missing") {
+        // Share links are built from the app's own URLs, which already
+        // carry the base path.
+        let base = base.trim_end_matches('/');
+        let origin = base.strip_suffix(&prepared.base_path).unwrap_or(base);
+        let _ = prepared.share_base.set(format!("{origin}/"));
+    }
+    if prepared.base_path.is_empty() {
+        Ok(prepared.app)
+    } else {
+        Ok(Router::new().nest(&prepared.base_path, prepared.app))
+    }
+}
+
+async fn prepare(config: ServerConfig) -> Result<Prepared, String> {
     let ServerConfig {
         host,
         advertised_host,
         trusted_hosts,
         port,
         theme,
+        theme_css,
         qr,
+        qr_out,
         open_browser,
+        open_anchor,
         shared_annotation: _,
         db_path,
+        db_url,
         salt,
         initial_workspaces,
         bound_listener,
@@ -882,11 +1572,70 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
         language,
         shortcuts_json,
         styles_css,
+        user_css,
+        user_js,
         default_chat_mode,
         collaborator_access_code_hash,
         print_collapsed_content,
+        content_security_policy,
+        frame_options,
+        no_cache,
+        search_exclude,
+        search_lang,
+        search_max_limit,
+        search_writer_memory,
+        search_max_file_size,
+        search_max_index_size,
+        require_name,
+        annotation_token,
+        readme_names,
+        nav_files,
+        markdown_extensions,
+        show_hidden,
+        no_follow_symlinks,
+        allow_paths,
+        deny_paths,
+        listing_page_size,
+        paginate_kib,
+        allow_remote,
+        notify_urls,
+        notify_paths,
+        notify_events,
+        pandoc,
+        snapshots,
+        audit,
+        link_schemes,
+        plugins_dir,
+        share_token,
+        base_path,
     } = config;
+    let base_path = normalize_base_path(&base_path)?;
+    let link_schemes = crate::LinkSchemes::parse(&link_schemes)?;
+    let mut render_hooks: Vec<Arc<dyn crate::RenderHook>> = Vec::new();
+    if !link_schemes.is_empty() {
+        render_hooks.push(Arc::new(link_schemes));
+    }
+    if let Some(dir) = &plugins_dir {
+        render_hooks.extend(crate::wasm_plugin::load_plugins(dir)?);
+    }
+    let render_hooks: Arc<[Arc<dyn crate::RenderHook>]> = render_hooks.into();
+    let path_rules = Arc::new(crate::path_rules::PathRules::new(
+        &allow_paths,
+        &deny_paths,
+    )?);
     let startup_started = Instant::now();
+    let stylesheets: Vec<String> = theme_css
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .chain(user_css)
+        .collect();
+    let user_stylesheets = user_assets(&stylesheets, &format!("{base_path}/_/user-css"), true)?;
+    let user_scripts = user_assets(&user_js, &format!("{base_path}/_/user-js"), false)?;
+    let security_policy = Arc::new(SecurityHeaders::from_overrides(
+        content_security_policy.as_deref(),
+        frame_options.as_deref(),
+        &user_asset_origins(&user_stylesheets),
+    )?);
     tracing::info!(
         version = env!("CARGO_PKG_VERSION"),
         bind_host = %host,
@@ -897,6 +1646,7 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
 
     // Initialize Tera template engine from embedded resources.
     let mut tera = Tera::default();
+    crate::assets::register_filters(&mut tera);
     for file_name in Templates::iter() {
         if let Some(file) = Templates::get(&file_name) {
             match std::str::from_utf8(&file.data) {
@@ -916,65 +1666,76 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     // the SQLite-backed stores must exist even when the corresponding features
     // were disabled at process start. Collaboration fan-out lives on each
     // WorkspaceEntry so cross-workspace delivery is impossible by construction.
-    let db_path = std::env::var("MARKON_SQLITE_PATH")
-        .ok()
-        .or(db_path)
-        .unwrap_or_else(|| {
-            let home = dirs::home_dir().expect("Cannot find home directory");
-            home.join(".markon/annotation.sqlite")
-                .to_string_lossy()
-                .to_string()
-        });
-    let parent_dir = std::path::Path::new(&db_path).parent().unwrap();
-    fs::create_dir_all(parent_dir).expect("Failed to create database directory");
-    let conn = Connection::open(&db_path).expect("Failed to open database");
-    // WAL: the single-server invariant is not enforced (the GUI can start a
-    // second server on a different/auto port while a CLI daemon holds the same
-    // db — it never consults the server lock), so two processes can open this
-    // file concurrently. WAL gives multi-reader / single-writer concurrency
-    // across processes instead of the rollback journal's whole-file lock. It is
-    // persisted in the db header (set-once; re-asserting on each open is cheap)
-    // and adds `-wal`/`-shm` sidecar files — a plain-copy backup must include
-    // them or checkpoint first.
-    let journal_mode: String = conn
-        .query_row("PRAGMA journal_mode=WAL", [], |row| row.get(0))
-        .unwrap_or_default();
-    if !journal_mode.eq_ignore_ascii_case("wal") {
-        tracing::warn!(
-            got = %journal_mode,
-            "could not enable WAL journal mode; concurrent multi-process access may hit 'database is locked'"
-        );
-    }
-    // Wait up to 5s for a competing writer to release the lock instead of
-    // failing immediately with SQLITE_BUSY — complements WAL under write bursts.
-    conn.pragma_update(None, "busy_timeout", 5000)
-        .expect("Failed to set busy_timeout");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS annotations (
-            id TEXT PRIMARY KEY,
-            file_path TEXT NOT NULL,
-            data TEXT NOT NULL
-        )",
-        [],
-    )
-    .expect("Failed to create annotations table");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS viewed_state (
-            file_path TEXT PRIMARY KEY,
-            state TEXT NOT NULL,
-            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        )",
-        [],
-    )
-    .expect("Failed to create viewed_state table");
-    crate::chat::storage::ChatStorage::init(&conn).expect("Failed to create chat tables");
-    let db = Some(Arc::new(Mutex::new(conn)));
+    let db_path = crate::annotations::database_path(db_path).expect("Cannot find home directory");
+    let db = crate::db::Database::open(std::path::Path::new(&db_path))?;
+    let remote_store = match db_url {
+        Some(url) => Some(crate::store::connect(&url).await?),
+        None => None,
+    };
 
     // Build workspace registry and register initial workspaces.
     let effective_salt = salt.unwrap_or_else(|| format!("markon:{port}"));
     // Sign access cookies with the persistent salt so they survive restarts.
     let access_cookie_secret = effective_salt.clone();
     let registry = registry.unwrap_or_else(|| Arc::new(WorkspaceRegistry::new(effective_salt)));
+    // Watchers re-anchor and re-key annotations and uncheck viewed sections in
+    // whichever store holds them.
+    match &remote_store {
+        Some(store) => registry.set_remote_store(store.clone()),
+        None => registry.set_annotation_db(db.clone()),
+    }
+    registry.set_walk_policy(crate::fswalk::WalkPolicy {
+        show_hidden,
+        follow_symlinks: !no_follow_symlinks,
+        rules: path_rules,
+        markdown: MarkdownExtensions::new(&markdown_extensions),
+    });
+    registry.set_search_config(crate::search::SearchConfig {
+        exclude: crate::search::SearchExclude::new(&search_exclude)?,
+        language: search_lang
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or_default(),
+        limits: crate::search::SearchLimits::from_mib(
+            search_writer_memory,
+            search_max_file_size,
+            search_max_index_size,
+        )?,
+    });
+    if let Some(notifier) =
+        crate::change_notifier::ChangeNotifier::start(&notify_urls, &notify_paths, &notify_events)?
+    {
+        registry.set_change_notifier(notifier);
+    }
+    if snapshots {
+        registry.enable_snapshots();
+    }
+    let pandoc = match pandoc.then(crate::pandoc::Pandoc::locate) {
+        Some(Some(pandoc)) => {
+            tracing::info!(program = %pandoc.program().display(), "pandoc fallback enabled");
+            Some(Arc::new(pandoc))
+        }
+        Some(None) => {
+            tracing::warn!("--pandoc: pandoc was not found on PATH; fallback disabled");
+            None
+        }
+        None => None,
+    };
+    let search_max_limit = match search_max_limit {
+        Some(0) => return Err("--search-max-limit must be at least 1".into()),
+        Some(max) => max,
+        None => MAX_SEARCH_LIMIT,
+    };
+    let listing_page_size = match listing_page_size {
+        Some(0) => return Err("--listing-page-size must be at least 1".into()),
+        Some(size) => size,
+        None => DEFAULT_LISTING_PAGE_SIZE,
+    };
+    let paginate_bytes = match paginate_kib {
+        Some(0) => return Err("--paginate must be at least 1 KiB".into()),
+        size => size.map(|kib| kib.saturating_mul(1024)),
+    };
 
     // Track first workspace's URL path for browser/QR.
     let mut first_workspace_url_path: Option<String> = None;
@@ -986,11 +1747,15 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             path,
             flags: ws_init.flags,
             single_file: ws_init.single_file,
+            files: ws_init.files,
             collaborator_access_code_hash: ws_init.collaborator_access_code_hash,
             alias: ws_init.alias,
         });
         if first_workspace_url_path.is_none() {
-            let url_path = workspace_url_path(&id, ws_init.initial_path.as_deref());
+            let url_path = match &ws_init.compare {
+                Some((left, right)) => workspace_compare_url(&id, left, right),
+                None => workspace_url_path(&id, ws_init.initial_path.as_deref()),
+            };
             first_workspace_url_path = Some(url_path);
         }
     }
@@ -1022,18 +1787,20 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     // Distinct from the management token: this one is embedded in served edit
     // pages, so it must not unlock the privileged management routes.
     let save_token = Arc::new(generate_token());
+    let share_token = share_token.then(|| Arc::new(generate_token()));
 
-    let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
 
     // The control plane (privileged local socket) drives the SAME registry and
     // shutdown channel the web app uses, so both surfaces observe one state.
     let control_registry = registry.clone();
-    let control_shutdown_tx = shutdown_tx.clone();
 
     let state = AppState {
         theme: Arc::new(theme),
+        custom_theme: theme_css.is_some(),
         tera: Arc::new(tera),
-        db,
+        base_path: base_path.as_str().into(),
+        db: Some(db),
         workspace_registry: registry,
         management_token: token.clone(),
         admin_bootstraps: admin_bootstraps.clone(),
@@ -1051,12 +1818,49 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             shortcuts_json.unwrap_or_else(|| "null".to_string()),
         )),
         styles_css: Arc::new(styles_css.unwrap_or_default()),
+        user_stylesheets: Arc::new(user_stylesheets),
+        user_scripts: Arc::new(user_scripts),
         default_chat_mode: Arc::new(default_chat_mode),
         collaborator_access_code_hash: Arc::new(collaborator_access_code_hash),
         access_secret: Arc::new(access_cookie_secret),
         access_attempts: Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
         markdown_diff_cache: Arc::new(Mutex::new(MarkdownDiffCache::default())),
+        archive_index: Arc::new(Mutex::new(crate::archive::IndexCache::default())),
+        render_hooks,
         print_collapsed_content,
+        render_cache: !no_cache,
+        search_max_limit,
+        require_name,
+        annotation_token: annotation_token
+            .filter(|token| !token.is_empty())
+            .map(Arc::new),
+        readme_names: Arc::new(if readme_names.is_empty() {
+            DEFAULT_README_NAMES
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            readme_names
+        }),
+        nav_files: Arc::new(if nav_files.is_empty() {
+            DEFAULT_NAV_FILES
+                .iter()
+                .map(|name| name.to_string())
+                .collect()
+        } else {
+            nav_files
+        }),
+        listing_page_size,
+        paginate_bytes,
+        share_base: Arc::default(),
+        remote_markdown: Arc::new(RemoteMarkdown::new(RemoteAllowlist::new(&allow_remote))),
+        attachments_dir: Some(Arc::new(crate::attachments::directory(&db_path))),
+        remote_store,
+        presence: Arc::default(),
+        editor: Arc::default(),
+        pandoc,
+        audit,
+        share_token: share_token.clone(),
         #[cfg(debug_assertions)]
         dev_reload_tx: Arc::new(broadcast::channel::<()>(16).0),
     };
@@ -1089,12 +1893,34 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             require_local_save_origin,
         ));
 
+    // Editor-plugin protocol (see `crate::editor`): each handler requires the
+    // workspace-scoped editor capability from `markon editor`. Like the
+    // preview API it only answers same-origin pages and loopback clients;
+    // whole buffers are pushed, so the global 2 MB body limit applies.
+    let editor = Router::new()
+        .route("/api/editor/{workspace_id}/rpc", post(handle_editor_rpc))
+        .route(
+            "/api/editor/{workspace_id}/events",
+            get(handle_editor_events),
+        )
+        .route(
+            "/api/editor/{workspace_id}/preview",
+            get(handle_editor_preview),
+        )
+        .layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            require_local_save_origin,
+        ));
+
     let app = Router::new()
         // Static assets (literal prefix beats /{workspace_id}/ param)
         .route("/favicon.ico", get(serve_favicon))
         .route("/_/favicon.ico", get(serve_favicon))
         .route("/_/favicon.svg", get(serve_favicon_svg))
+        .route("/_/qr.svg", get(serve_qr_svg))
         .route("/_/css/{filename}", get(serve_css))
+        .route("/_/user-css/{filename}", get(serve_user_css))
+        .route("/_/user-js/{filename}", get(serve_user_js))
         .route("/_/js/{*path}", get(serve_js))
         .route("/_/admin", get(admin_bootstrap_page))
         .route("/_/admin/bootstrap", get(admin_bootstrap_page))
@@ -1102,6 +1928,20 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
         .route("/_/ws/{workspace_id}", get(config_ws_handler))
         // Read-only public APIs
         .route("/_/{workspace_id}/search", get(workspace_search_handler))
+        .route(
+            "/_/{workspace_id}/search/tags",
+            get(workspace_search_tags_handler),
+        )
+        .route(
+            "/_/{workspace_id}/search/status",
+            get(workspace_search_status_handler),
+        )
+        .route(
+            "/_/{workspace_id}/search/rebuild",
+            post(workspace_search_rebuild_handler)
+                .route_layer(axum::middleware::from_fn(require_admin_role))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
         // Access-code gate: unlock endpoint (not itself gated).
         .route("/_/unlock", post(unlock_handler))
         // Workspace content routes
@@ -1139,6 +1979,22 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             "/_/{workspace_id}/compare/{*range}",
             get(handle_pretty_compare_diff),
         )
+        .route("/_/{workspace_id}/diff/{*path}", get(handle_document_diff))
+        .route(
+            "/_/{workspace_id}/history/{*path}",
+            get(handle_document_history),
+        )
+        .route(
+            "/_/{workspace_id}/present/{*path}",
+            get(handle_presentation),
+        )
+        .route("/_/{workspace_id}/print/{*path}", get(handle_print_view))
+        .route("/_/{workspace_id}/compare", get(handle_compare_documents))
+        .route(
+            "/_/{workspace_id}/snapshots/{*path}",
+            get(handle_document_snapshots),
+        )
+        .route(REMOTE_MARKDOWN_ROUTE, get(handle_remote_markdown))
         .route(
             "/_/{workspace_id}/git/commit",
             post(handle_git_commit)
@@ -1159,12 +2015,98 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             DOCUMENT_STATE_ROUTE,
             get(handle_document_state)
                 .post(handle_document_state_command)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    require_annotation_token,
+                ))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            CLIENT_VIEWED_STATE_ROUTE,
+            get(handle_client_viewed_state)
+                .post(handle_client_viewed_state_save)
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            PINNED_FILES_ROUTE,
+            post(handle_pin_file).route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            ANNOTATIONS_ROUTE,
+            get(handle_annotations_list)
+                .post(handle_annotation_create)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    require_annotation_token,
+                ))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(ANNOTATIONS_EXPORT_ROUTE, get(handle_annotations_export))
+        .route(ANNOTATIONS_SUMMARY_ROUTE, get(handle_annotations_summary))
+        .route(PROGRESS_ROUTE, get(handle_progress))
+        .route(ANNOTATIONS_HISTORY_ROUTE, get(handle_annotations_history))
+        .route(
+            ANNOTATIONS_UNDO_ROUTE,
+            post(handle_annotations_undo)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    require_annotation_token,
+                ))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            ANNOTATIONS_PRUNE_ROUTE,
+            get(handle_annotations_prune_report)
+                .post(handle_annotations_prune)
+                .route_layer(axum::middleware::from_fn(require_admin_role))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            ANNOTATION_ATTACHMENTS_ROUTE,
+            post(handle_annotation_attachment_upload)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    require_annotation_token,
+                ))
+                .route_layer(axum::middleware::from_fn(require_same_origin))
+                .layer(axum::extract::DefaultBodyLimit::max(
+                    crate::attachments::MAX_BYTES,
+                )),
+        )
+        .route(
+            ANNOTATION_ATTACHMENT_ROUTE,
+            get(handle_annotation_attachment),
+        )
+        .route(
+            ANNOTATIONS_IMPORT_ROUTE,
+            post(handle_annotations_import)
+                .route_layer(axum::middleware::from_fn(require_admin_role))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            ANNOTATION_ROUTE,
+            axum::routing::put(handle_annotation_update)
+                .delete(handle_annotation_delete)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    require_annotation_token,
+                ))
+                .route_layer(axum::middleware::from_fn(require_same_origin)),
+        )
+        .route(
+            ANNOTATION_RESOLUTION_ROUTE,
+            axum::routing::put(handle_annotation_resolution)
+                .route_layer(axum::middleware::from_fn_with_state(
+                    state.clone(),
+                    require_annotation_token,
+                ))
                 .route_layer(axum::middleware::from_fn(require_same_origin)),
         )
         .route(
             "/_/{workspace_id}/files/dir",
             get(handle_workspace_dir_data),
         )
+        .route("/_/{workspace_id}/files/ls", get(handle_workspace_ls))
         .route(
             "/_/{workspace_id}/files/create",
             post(handle_workspace_create_file)
@@ -1202,7 +2144,8 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
         // Everything else → 404
         .fallback(|| async { StatusCode::NOT_FOUND })
         .merge(save)
-        .merge(preview);
+        .merge(preview)
+        .merge(editor);
 
     // Dev-only live-reload: esbuild's watch onEnd hook POSTs the trigger,
     // server fans it out as an SSE event, the webview reloads. cfg gate keeps
@@ -1226,11 +2169,22 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     // Administrator-rendered pages contain privileged controls. Never let a
     // browser reuse them after a daemon restart invalidates the admin cookie.
     let app = app.layer(axum::middleware::from_fn(prevent_admin_response_caching));
+    // Audit log of what collaborators did (no-op without `--audit`). Inside
+    // the access-code gate, so each request's role is known.
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        record_audit,
+    ));
     // Access-code gate over every workspace-scoped route (no-op when unset).
     let app = app.layer(axum::middleware::from_fn_with_state(
         state.clone(),
         require_access_code,
     ));
+    // Share-token gate over every route (no-op without `--share-token`).
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        require_share_token,
+    ));
 
     // Reject unknown Host authorities before any route can read or mutate
     // state. Origin==Host alone is insufficient under DNS rebinding.
@@ -1240,20 +2194,77 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     ));
 
     // Hardening headers (CSP / nosniff / frame options) on every response.
-    let app = app.layer(axum::middleware::from_fn(security_headers));
+    let app = app.layer(axum::middleware::from_fn_with_state(
+        security_policy,
+        security_headers,
+    ));
 
     let control_db = state.db.clone();
+    let share_base = state.share_base.clone();
+    let history_gc_task = state.db.clone().map(spawn_annotation_history_gc);
     let app = app.with_state(state);
+    Ok(Prepared {
+        app,
+        base_path,
+        host,
+        advertised_host,
+        port,
+        bound_listener,
+        qr,
+        qr_out,
+        open_browser,
+        open_anchor,
+        first_workspace_url_path,
+        registry: control_registry,
+        db: control_db,
+        admin_bootstraps,
+        save_token,
+        share_token,
+        shutdown_tx,
+        shutdown_rx,
+        share_base,
+        history_gc_task,
+        startup_started,
+    })
+}
 
-    let listener = if let Some(std_listener) = bound_listener {
-        std_listener
-            .set_nonblocking(true)
-            .map_err(|e| format!("Failed to set non-blocking: {e}"))?;
-        TcpListener::from_std(std_listener)
-            .map_err(|e| format!("Failed to convert listener: {e}"))?
-    } else {
-        let addr = crate::net::bind_socket_addr(&host, port)?;
-        TcpListener::bind(&addr)
+pub async fn start(config: ServerConfig) -> Result<(), String> {
+    if !normalize_base_path(&config.base_path)?.is_empty() {
+        return Err("a base path is only supported when embedding the router".to_string());
+    }
+    let Prepared {
+        app,
+        base_path: _,
+        host,
+        advertised_host,
+        port,
+        bound_listener,
+        qr,
+        qr_out,
+        open_browser,
+        open_anchor,
+        first_workspace_url_path,
+        registry: control_registry,
+        db: control_db,
+        admin_bootstraps,
+        save_token,
+        share_token,
+        shutdown_tx: control_shutdown_tx,
+        mut shutdown_rx,
+        share_base,
+        history_gc_task,
+        startup_started,
+    } = prepare(config).await?;
+
+    let listener = if let Some(std_listener) = bound_listener {
+        std_listener
+            .set_nonblocking(true)
+            .map_err(|e| format!("Failed to set non-blocking: {e}"))?;
+        TcpListener::from_std(std_listener)
+            .map_err(|e| format!("Failed to convert listener: {e}"))?
+    } else {
+        let addr = crate::net::bind_socket_addr(&host, port)?;
+        TcpListener::bind(&addr)
             .await
             .map_err(|e| format!("Failed to bind to {addr}: {e}"))?
     };
@@ -1292,15 +2303,20 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     let admin_bootstraps_for_control = admin_bootstraps.clone();
     let admin_bind_host = host.clone();
     let admin_port = addr.port();
+    let admin_share_token = share_token.clone();
     let admin_bootstrap_fn: crate::control::AdminBootstrapFn = Arc::new(move |redirect: &str| {
         let nonce = admin_bootstraps_for_control.issue_url(redirect);
         let admin_base = local_browser_base_url(&admin_bind_host, admin_port);
-        Ok(build_admin_bootstrap_url(&admin_base, redirect, &nonce))
+        Ok(with_share_token(
+            &build_admin_bootstrap_url(&admin_base, redirect, &nonce),
+            admin_share_token.as_deref().map(String::as_str),
+        ))
     });
     let admin_bootstraps_for_code = admin_bootstraps.clone();
     let admin_code_bind_host = host.clone();
     let admin_code_advertised_host = advertised_host.clone();
     let admin_code_port = addr.port();
+    let admin_code_share_token = share_token.clone();
     let admin_bootstrap_code_fn: crate::control::AdminBootstrapCodeFn =
         Arc::new(move |redirect: &str| {
             let code = admin_bootstraps_for_code.issue_code(redirect);
@@ -1309,9 +2325,30 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
                 &admin_code_advertised_host,
                 admin_code_port,
             );
-            let url = build_workspace_url(&admin_code_base, "/_/admin");
+            let url = with_share_token(
+                &build_workspace_url(&admin_code_base, "/_/admin"),
+                admin_code_share_token.as_deref().map(String::as_str),
+            );
             Ok((url, code))
         });
+    // Editor plugins run on this machine, so their session points at the
+    // local base URL.
+    let editor_registry = control_registry.clone();
+    let editor_secret = save_token.clone();
+    let editor_base = local_browser_base_url(&host, addr.port());
+    let editor_session_fn: crate::control::EditorSessionFn = Arc::new(move |id: &str| {
+        if editor_registry.get(id).is_none() {
+            return Err(format!("no such workspace: {id}"));
+        }
+        let api = format!("{editor_base}{}", editor_api_url("", id));
+        Ok(crate::control::EditorSession {
+            workspace_id: id.to_string(),
+            rpc_url: format!("{api}/rpc"),
+            events_url: format!("{api}/events"),
+            preview_url: format!("{api}/preview"),
+            token: workspace_editor_token(&editor_secret, id),
+        })
+    });
 
     let control_ctx = crate::control::ControlContext {
         registry: control_registry,
@@ -1319,6 +2356,7 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
         shutdown: Some(control_shutdown_tx),
         admin_bootstrap: Some(admin_bootstrap_fn),
         admin_bootstrap_code: Some(admin_bootstrap_code_fn),
+        editor_session: Some(editor_session_fn),
     };
     let (control_stop_tx, control_stop_rx) = tokio::sync::oneshot::channel::<()>();
     let control_task = tokio::spawn(async move {
@@ -1345,6 +2383,8 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             advertised_host: Some(advertised_host.clone()),
             service_version: env!("CARGO_PKG_VERSION").to_string(),
             owner: owner_nonce.clone(),
+            pid: std::process::id(),
+            started_at: access_now_unix(),
         })
         .write()
         {
@@ -1380,6 +2420,13 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
             None => format!("{}/", base.trim_end_matches('/')),
         }
     };
+    // The URLs handed out for opening; `share_base` stays a plain base.
+    let share_url = |base_option: &str, ws_path: &Option<String>| -> String {
+        with_share_token(
+            &make_url(base_option, ws_path),
+            share_token.as_deref().map(String::as_str),
+        )
+    };
 
     let custom_base = qr
         .as_ref()
@@ -1388,22 +2435,32 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     if let Some(base) = custom_base {
         println!(
             "accessible at {}",
-            make_url(base, &first_workspace_url_path)
+            share_url(base, &first_workspace_url_path)
         );
     }
 
     if let Some(ref qr_option) = qr {
         println!();
         let qr_url = if qr_option == "missing" {
-            make_url("local", &first_workspace_url_path)
+            share_url("local", &first_workspace_url_path)
         } else {
-            make_url(qr_option, &first_workspace_url_path)
+            share_url(qr_option, &first_workspace_url_path)
         };
         if let Err(e) = print_compact_qr(&qr_url) {
             eprintln!("Failed to generate QR code: {e}");
         }
     }
 
+    let share_base_url = qr.as_deref().filter(|u| *u != "missing").unwrap_or("local");
+    let _ = share_base.set(make_url(share_base_url, &None));
+    if let Some(path) = &qr_out {
+        let url = share_url(share_base_url, &first_workspace_url_path);
+        match write_qr_image(&url, path) {
+            Ok(()) => println!("QR code for {url} written to {}", path.display()),
+            Err(e) => eprintln!("Failed to write QR code to {}: {e}", path.display()),
+        }
+    }
+
     if let Some(ref base_option) = open_browser {
         // The default follows the actual bind. A caller may still explicitly
         // name a trusted reverse-proxy origin through --open-browser=URL.
@@ -1412,9 +2469,18 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
         } else {
             base_option.to_string()
         };
-        let redirect = first_workspace_url_path.as_deref().unwrap_or("/");
-        let nonce = admin_bootstraps.issue_url(redirect);
-        let url = build_admin_bootstrap_url(&base, redirect, &nonce);
+        let mut redirect = first_workspace_url_path
+            .as_deref()
+            .unwrap_or("/")
+            .to_string();
+        if let Some(anchor) = &open_anchor {
+            redirect = format!("{redirect}#{anchor}");
+        }
+        let nonce = admin_bootstraps.issue_url(&redirect);
+        let url = with_share_token(
+            &build_admin_bootstrap_url(&base, &redirect, &nonce),
+            share_token.as_deref().map(String::as_str),
+        );
         if let Err(e) = open::that(&url) {
             tracing::warn!("best-effort browser open failed: {e}");
         }
@@ -1434,11 +2500,41 @@ pub async fn start(config: ServerConfig) -> Result<(), String> {
     // fresh socket and no orphaned accept loop lingers.
     let _ = control_stop_tx.send(());
     let _ = control_task.await;
+    if let Some(task) = history_gc_task {
+        task.abort();
+    }
 
     serve_result.map_err(|e| format!("Server error: {e}"))?;
     Ok(())
 }
 
+/// How often deleted-annotation history past
+/// [`crate::annotations::HISTORY_RETENTION`] is dropped.
+const ANNOTATION_HISTORY_GC_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(6 * 60 * 60);
+
+/// Prune annotation history at startup and then every
+/// [`ANNOTATION_HISTORY_GC_INTERVAL`].
+fn spawn_annotation_history_gc(db: crate::db::Database) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(ANNOTATION_HISTORY_GC_INTERVAL);
+        loop {
+            tick.tick().await;
+            let pruned = db
+                .write(move |conn| {
+                    crate::annotations::prune_history(conn, crate::annotations::HISTORY_RETENTION)
+                })
+                .await;
+            match pruned {
+                Ok(Ok(0)) => {}
+                Ok(Ok(rows)) => tracing::debug!(rows, "pruned annotation history"),
+                Ok(Err(e)) => tracing::warn!("annotation history cleanup failed: {e}"),
+                Err(e) => tracing::warn!("annotation history cleanup worker failed: {e}"),
+            }
+        }
+    })
+}
+
 /// Lightweight always-on WebSocket per workspace — pushes a "reload" text frame
 /// whenever workspace flags change. Requires same-origin (see
 /// `check_ws_origin`) so a foreign page cannot subscribe to a victim's
@@ -1515,7 +2611,11 @@ fn same_origin_or_loopback_no_origin(
     }
 }
 
-fn authorize_ws_target(entry: &WorkspaceEntry, target: WsTarget) -> Option<WsSession> {
+fn authorize_ws_target(
+    base_path: &str,
+    entry: &WorkspaceEntry,
+    target: WsTarget,
+) -> Option<WsSession> {
     match target {
         WsTarget::Document { path } => {
             let requested = FsPath::new(&path);
@@ -1543,7 +2643,7 @@ fn authorize_ws_target(entry: &WorkspaceEntry, target: WsTarget) -> Option<WsSes
             if !entry.enable_live.load(std::sync::atomic::Ordering::Relaxed) {
                 return None;
             }
-            let key = canonical_ws_surface_key(&entry.id, &key)?;
+            let key = canonical_ws_surface_key(&entry.id, key.strip_prefix(base_path)?)?;
             Some(WsSession {
                 channel: format!("surface:{key}"),
                 target: WsSessionTarget::Surface,
@@ -1861,7 +2961,9 @@ fn render_access_gate(
     let mut ctx = tera::Context::new();
     ctx.insert("workspace_id", ws_id);
     ctx.insert("redirect", &access_safe_redirect(redirect, ws_id));
+    ctx.insert("base_path", state.base_path.as_ref());
     ctx.insert("theme", state.theme.as_str());
+    ctx.insert("custom_theme", &state.custom_theme);
     ctx.insert("i18n_json", state.i18n_json.as_str());
     ctx.insert("i18n_lang", state.i18n_lang.as_str());
     // Always define these so the template's `{% if error == ... %}` is valid
@@ -1889,14 +2991,23 @@ async fn require_access_code(
     next: axum::middleware::Next,
 ) -> Response {
     let path = req.uri().path().to_string();
-    let ws_id = access_gated_workspace(&path);
-    let Some(ws_id) = ws_id else {
-        return next.run(req).await;
-    };
     let cookie = req
         .headers()
         .get(axum::http::header::COOKIE)
         .and_then(|value| value.to_str().ok());
+    if path == REMOTE_MARKDOWN_ROUTE {
+        return match remote_access_role(&state, cookie) {
+            Some(role) => {
+                req.extensions_mut().insert(role);
+                next.run(req).await
+            }
+            None => (StatusCode::UNAUTHORIZED, "Access code required").into_response(),
+        };
+    }
+    let ws_id = access_gated_workspace(&path);
+    let Some(ws_id) = ws_id else {
+        return next.run(req).await;
+    };
     // Administration is an explicit browser capability. Network position is
     // never promoted to identity, so loopback proxies and DNS rebinding cannot
     // inherit management privileges.
@@ -1928,6 +3039,33 @@ async fn require_access_code(
     }
 }
 
+/// `/_/remote` makes the server fetch URLs, so it belongs to no workspace
+/// and is open to no anonymous visitor: it takes an admin session or a
+/// collaborator cookie unlocked with an access code, the server's or a
+/// workspace's.
+fn remote_access_role(state: &AppState, cookie: Option<&str>) -> Option<AccessRole> {
+    if admin_auth::admin_cookie_valid(&state.management_token, cookie, access_now_unix()) {
+        return Some(AccessRole::Admin);
+    }
+    access_cookie_scopes(&state.access_secret, cookie)
+        .iter()
+        .any(|(scope, hash)| {
+            if scope == "s:collaborator" {
+                let server_hash = state.collaborator_access_code_hash.as_str();
+                return !server_hash.is_empty() && ct_eq(hash.as_bytes(), server_hash.as_bytes());
+            }
+            scope
+                .strip_prefix("w:")
+                .and_then(|scope| scope.strip_suffix(":collaborator"))
+                .is_some_and(|ws_id| {
+                    access_requirements_for(state, ws_id)
+                        .iter()
+                        .any(|req| req.scope == *scope && req.hash == *hash)
+                })
+        })
+        .then_some(AccessRole::Collaborator)
+}
+
 /// Structural browser operations require the explicit administrator role
 /// inserted by `require_access_code` after validating the admin session cookie.
 async fn require_admin_role(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
@@ -1938,6 +3076,180 @@ async fn require_admin_role(req: axum::extract::Request, next: axum::middleware:
     }
 }
 
+// ════════════════════════ Share token ════════════════════════
+// With `--share-token`, every route needs a random token generated at start.
+// The URLs the server prints, the QR codes it draws and the browser it opens
+// carry it as `?share_token=`; the first request trades it for a cookie and
+// is redirected to the same address without it. A LAN scanner that never saw
+// a code gets 401 everywhere.
+
+const SHARE_COOKIE: &str = "markon_share";
+const SHARE_TOKEN_PARAM: &str = "share_token";
+
+/// `url` with `token` added to its query, ahead of any fragment.
+fn with_share_token(url: &str, token: Option<&str>) -> String {
+    let Some(token) = token else {
+        return url.to_string();
+    };
+    let (url, fragment) = match url.split_once('#') {
+        Some((url, fragment)) => (url, Some(fragment)),
+        None => (url, None),
+    };
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let mut out = format!("{url}{separator}{SHARE_TOKEN_PARAM}={token}");
+    if let Some(fragment) = fragment {
+        out.push('#');
+        out.push_str(fragment);
+    }
+    out
+}
+
+/// The share cookie's value: a tag of the token, so the cookie jar never
+/// holds the token itself.
+fn share_cookie_value(secret: &str, token: &str) -> String {
+    admin_auth::auth_tag(secret, b"markon-share-cookie\0", token)
+}
+
+/// The `Set-Cookie` value granting the share cookie for `token`.
+fn share_cookie(state: &AppState, headers: &axum::http::HeaderMap, token: &str) -> String {
+    let host = headers
+        .get(axum::http::header::HOST)
+        .and_then(|value| value.to_str().ok());
+    let secure_attr = if state.allowed_hosts.is_secure_header(host) {
+        "; Secure"
+    } else {
+        ""
+    };
+    format!(
+        "{SHARE_COOKIE}={}; Path=/; Max-Age={ACCESS_TTL_SECS}; HttpOnly; SameSite=Lax{secure_attr}",
+        share_cookie_value(&state.access_secret, token)
+    )
+}
+
+/// Middleware: require the share token, from the cookie or the query, on
+/// every route. Browser administrators pass on their own session. The
+/// editor-plugin API is left to its own capability: plugins get their URLs
+/// over the control socket and EventSource clients don't follow the
+/// redirect that trades the token for a cookie.
+async fn require_share_token(
+    State(state): State<AppState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let Some(token) = state.share_token.clone() else {
+        return next.run(req).await;
+    };
+    if req.uri().path().starts_with("/api/editor/") {
+        return next.run(req).await;
+    }
+    let expected = share_cookie_value(&state.access_secret, &token);
+    let cookie = req
+        .headers()
+        .get(axum::http::header::COOKIE)
+        .and_then(|value| value.to_str().ok());
+    if admin_auth::cookie_value(cookie, SHARE_COOKIE)
+        .is_some_and(|value| ct_eq(value.as_bytes(), expected.as_bytes()))
+        || admin_auth::admin_cookie_valid(&state.management_token, cookie, access_now_unix())
+    {
+        return next.run(req).await;
+    }
+
+    let query = req.uri().query().unwrap_or("");
+    let presented = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == SHARE_TOKEN_PARAM)
+        .map(|(_, value)| value);
+    if !presented.is_some_and(|value| ct_eq(value.as_bytes(), token.as_bytes())) {
+        return (StatusCode::UNAUTHORIZED, "Share token required").into_response();
+    }
+    let cookie = share_cookie(&state, req.headers(), &token);
+    if req.method() == axum::http::Method::GET {
+        // Take the token out of the address bar, history and Referer.
+        let rest: Vec<&str> = query
+            .split('&')
+            .filter(|pair| {
+                !pair.is_empty()
+                    && pair.split_once('=').map_or(*pair, |(name, _)| name) != SHARE_TOKEN_PARAM
+            })
+            .collect();
+        let path = format!("{}{}", state.base_path, req.uri().path());
+        let location = if rest.is_empty() {
+            path
+        } else {
+            format!("{path}?{}", rest.join("&"))
+        };
+        return (
+            StatusCode::SEE_OTHER,
+            [
+                (axum::http::header::LOCATION, location),
+                (axum::http::header::SET_COOKIE, cookie),
+            ],
+        )
+            .into_response();
+    }
+    let mut response = next.run(req).await;
+    if let Ok(cookie) = axum::http::HeaderValue::from_str(&cookie) {
+        response
+            .headers_mut()
+            .append(axum::http::header::SET_COOKIE, cookie);
+    }
+    response
+}
+
+/// Header carrying the `--annotation-token` secret.
+const ANNOTATION_TOKEN_HEADER: &str = "X-Markon-Annotation-Token";
+
+/// Under `--annotation-token`, writes to shared document state need the
+/// secret unless the caller is an admin. Reads pass, so guests still receive
+/// every annotation; viewed state is shared data too and is gated alike.
+async fn require_annotation_token(
+    State(state): State<AppState>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    let Some(token) = state.annotation_token.as_deref() else {
+        return next.run(req).await;
+    };
+    let presented = req
+        .headers()
+        .get(ANNOTATION_TOKEN_HEADER)
+        .map(|value| value.as_bytes());
+    if matches!(
+        *req.method(),
+        axum::http::Method::GET | axum::http::Method::HEAD
+    ) || req.extensions().get::<AccessRole>() == Some(&AccessRole::Admin)
+        || presented.is_some_and(|presented| ct_eq(presented, token.as_bytes()))
+    {
+        return next.run(req).await;
+    }
+    AnnotationApiError(
+        StatusCode::FORBIDDEN,
+        "Annotation changes need the write token",
+    )
+    .into_response()
+}
+
+/// `base_path` as `/seg/seg` without a trailing slash, or empty for the
+/// root. Segments are limited to unreserved URL characters, so the path can
+/// be written into pages and headers as is.
+fn normalize_base_path(value: &str) -> Result<String, String> {
+    let trimmed = value.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    let valid = trimmed.split('/').all(|segment| {
+        !matches!(segment, "" | "." | "..")
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+    });
+    if !valid {
+        return Err(format!("base path `{value}` is not a plain URL path"));
+    }
+    Ok(format!("/{trimmed}"))
+}
+
 /// Administrator pages expose controls that stop working as soon as their
 /// short-lived capability expires (or the daemon restarts with a new token).
 /// Prevent browsers from restoring a stale privileged page from cache.
@@ -2067,7 +3379,7 @@ async fn unlock_handler(
     }
     let requirements = access_requirements_for(&state, &form.workspace_id);
     if requirements.is_empty() {
-        return Redirect::to(&redirect).into_response();
+        return Redirect::to(&format!("{}{redirect}", state.base_path)).into_response();
     };
     if let Some(req) = requirements.iter().find(|req| {
         crate::workspace::access_code_matches(&state.access_secret, &form.code, &req.hash)
@@ -2097,7 +3409,7 @@ async fn unlock_handler(
         );
         return (
             [(axum::http::header::SET_COOKIE, cookie)],
-            Redirect::to(&redirect),
+            Redirect::to(&format!("{}{redirect}", state.base_path)),
         )
             .into_response();
     }
@@ -2111,6 +3423,20 @@ async fn unlock_handler(
 /// broadcast amplification from a hostile peer; real annotations are tiny.
 const MAX_WS_MSG_BYTES: usize = 256 * 1024;
 
+/// Max relayed Live frame. Live actions are a few hundred bytes of paths and
+/// offsets; every follower receives a copy, so larger ones are rejected.
+const MAX_LIVE_ACTION_BYTES: usize = 16 * 1024;
+
+/// Longest accepted Live `action` name.
+const MAX_LIVE_ACTION_NAME: usize = 64;
+
+/// Max shared-selection frame: two XPaths and two offsets.
+const MAX_SELECTION_BYTES: usize = 4 * 1024;
+
+/// Shared selections go out at most this often per session; the newest one
+/// in between wins.
+const SELECTION_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
 /// Conservative Content-Security-Policy. Untrusted markdown is sanitised at the
 /// source (raw HTML is scrubbed and link/image schemes are allow-listed in
 /// `markdown.rs`), which removes the injected-inline-handler / `javascript:`
@@ -2127,8 +3453,86 @@ img-src * data: blob:; media-src * data: blob:; font-src 'self' data:; \
 connect-src 'self'; object-src 'none'; base-uri 'self'; form-action 'self'; \
 frame-ancestors 'self'";
 
+/// Resolved hardening headers. A `None` value omits that header entirely (an
+/// explicit `off` override); the defaults are [`SECURITY_CSP`] and
+/// `SAMEORIGIN`, which the embedded assets and mermaid rendering work under.
+#[derive(Clone, Debug)]
+pub(crate) struct SecurityHeaders {
+    csp: Option<axum::http::HeaderValue>,
+    frame_options: Option<axum::http::HeaderValue>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            csp: Some(axum::http::HeaderValue::from_static(SECURITY_CSP)),
+            frame_options: Some(axum::http::HeaderValue::from_static("SAMEORIGIN")),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// Build the policy from the `--csp` / `--frame-options` overrides. An empty
+    /// or absent value keeps the default. The built-in CSP's `frame-ancestors`
+    /// follows the frame mode (`deny` tightens it to `'none'`, `off` drops it),
+    /// since modern browsers consult that directive before X-Frame-Options.
+    pub(crate) fn from_overrides(
+        csp: Option<&str>,
+        frame_options: Option<&str>,
+        style_origins: &[String],
+    ) -> Result<Self, String> {
+        let frame_options = frame_options.map(str::trim).unwrap_or("");
+        let (frame_options, frame_ancestors) = match frame_options.to_ascii_lowercase().as_str() {
+            "" | "sameorigin" => (Some("SAMEORIGIN"), "; frame-ancestors 'self'"),
+            "deny" => (Some("DENY"), "; frame-ancestors 'none'"),
+            "off" => (None, ""),
+            other => {
+                return Err(format!(
+                    "invalid frame options '{other}' (expected deny, sameorigin, or off)"
+                ))
+            }
+        };
+        // `--custom-css` URLs: their origins may serve the stylesheet and the
+        // fonts it references.
+        let extra_origins: String = style_origins
+            .iter()
+            .map(|origin| format!(" {origin}"))
+            .collect();
+        let csp = match csp.map(str::trim).unwrap_or("") {
+            "" => Some(
+                SECURITY_CSP
+                    .replace("; frame-ancestors 'self'", frame_ancestors)
+                    .replace(
+                        "style-src 'self' 'unsafe-inline';",
+                        &format!("style-src 'self' 'unsafe-inline'{extra_origins};"),
+                    )
+                    .replace(
+                        "font-src 'self' data:;",
+                        &format!("font-src 'self' data:{extra_origins};"),
+                    ),
+            ),
+            policy if policy.eq_ignore_ascii_case("off") => None,
+            policy => Some(policy.to_string()),
+        };
+        let csp = csp
+            .map(|policy| {
+                axum::http::HeaderValue::from_str(&policy)
+                    .map_err(|e| format!("invalid Content-Security-Policy: {e}"))
+            })
+            .transpose()?;
+        Ok(Self {
+            csp,
+            frame_options: frame_options.map(axum::http::HeaderValue::from_static),
+        })
+    }
+}
+
 /// Attach hardening headers to every response (CSP + nosniff + frame options).
-async fn security_headers(req: axum::extract::Request, next: axum::middleware::Next) -> Response {
+async fn security_headers(
+    State(policy): State<Arc<SecurityHeaders>>,
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
     let mut resp = next.run(req).await;
 
     // A bare status-only 404 has no Content-Type. Combined with `nosniff`,
@@ -2149,14 +3553,12 @@ async fn security_headers(req: axum::extract::Request, next: axum::middleware::N
         axum::http::header::X_CONTENT_TYPE_OPTIONS,
         axum::http::HeaderValue::from_static("nosniff"),
     );
-    h.insert(
-        axum::http::header::X_FRAME_OPTIONS,
-        axum::http::HeaderValue::from_static("SAMEORIGIN"),
-    );
-    h.insert(
-        axum::http::header::CONTENT_SECURITY_POLICY,
-        axum::http::HeaderValue::from_static(SECURITY_CSP),
-    );
+    if let Some(value) = &policy.frame_options {
+        h.insert(axum::http::header::X_FRAME_OPTIONS, value.clone());
+    }
+    if let Some(value) = &policy.csp {
+        h.insert(axum::http::header::CONTENT_SECURITY_POLICY, value.clone());
+    }
     resp
 }
 
@@ -2231,31 +3633,67 @@ struct DocumentStateQuery {
 struct DocumentStateResponse {
     annotations: Vec<serde_json::Value>,
     viewed_state: serde_json::Value,
+    /// Who last changed `viewed_state`, when known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    viewed_by: Option<serde_json::Value>,
+    /// Sections unchecked because they changed after being viewed.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    viewed_changed: Vec<String>,
 }
 
 #[derive(Deserialize)]
 #[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
 enum DocumentStateCommand {
+    /// `by` is the `{name, color}` identity of whoever saves, which for an
+    /// edit need not be the annotation's author.
     SaveAnnotation {
         path: String,
         annotation: serde_json::Value,
         #[serde(default)]
+        by: Option<serde_json::Value>,
+        #[serde(default)]
         op_id: Option<String>,
     },
     DeleteAnnotation {
         path: String,
         id: String,
         #[serde(default)]
+        by: Option<serde_json::Value>,
+        #[serde(default)]
         op_id: Option<String>,
     },
     ClearAnnotations {
         path: String,
         #[serde(default)]
+        by: Option<serde_json::Value>,
+        #[serde(default)]
+        op_id: Option<String>,
+    },
+    /// Restore the most recent delete or clear on `path`.
+    UndoAnnotations {
+        path: String,
+        #[serde(default)]
+        by: Option<serde_json::Value>,
+        #[serde(default)]
+        op_id: Option<String>,
+    },
+    /// Toggle an annotation's `resolved` flag; `by` is the resolver's
+    /// `{name, color}` identity.
+    ResolveAnnotation {
+        path: String,
+        id: String,
+        resolved: bool,
+        #[serde(default)]
+        by: Option<serde_json::Value>,
+        #[serde(default)]
         op_id: Option<String>,
     },
     SaveViewedState {
         path: String,
         state: serde_json::Value,
+        /// Identity of the reader making the change, shaped like `author`.
+        #[serde(default)]
+        by: Option<serde_json::Value>,
         #[serde(default)]
         op_id: Option<String>,
     },
@@ -2267,9 +3705,39 @@ impl DocumentStateCommand {
             Self::SaveAnnotation { path, .. }
             | Self::DeleteAnnotation { path, .. }
             | Self::ClearAnnotations { path, .. }
+            | Self::UndoAnnotations { path, .. }
+            | Self::ResolveAnnotation { path, .. }
             | Self::SaveViewedState { path, .. } => path,
         }
     }
+
+    /// The `{name, color}` identity the command was sent with.
+    fn by(&self) -> Option<&serde_json::Value> {
+        match self {
+            Self::SaveAnnotation { by, .. }
+            | Self::DeleteAnnotation { by, .. }
+            | Self::ClearAnnotations { by, .. }
+            | Self::UndoAnnotations { by, .. }
+            | Self::ResolveAnnotation { by, .. }
+            | Self::SaveViewedState { by, .. } => by.as_ref(),
+        }
+    }
+
+    /// Whether the command carries a named identity, for `--require-name`.
+    /// Deletions and undo are not gated.
+    fn is_named(&self) -> bool {
+        match self {
+            Self::SaveAnnotation { annotation, .. } => {
+                crate::annotations::has_display_name(annotation.get("author"))
+            }
+            Self::ResolveAnnotation { by, .. } | Self::SaveViewedState { by, .. } => {
+                crate::annotations::has_display_name(by.as_ref())
+            }
+            Self::DeleteAnnotation { .. }
+            | Self::ClearAnnotations { .. }
+            | Self::UndoAnnotations { .. } => true,
+        }
+    }
 }
 
 fn document_state_access_allowed(role: Option<AccessRole>, entry: &WorkspaceEntry) -> bool {
@@ -2306,27 +3774,20 @@ async fn handle_document_state(
     let Some(file_path) = authorize_document_path(&entry, &query.path) else {
         return StatusCode::NOT_FOUND.into_response();
     };
-    let Some(db) = state.db else {
+    let Some(store) = state.document_store() else {
         return StatusCode::SERVICE_UNAVAILABLE.into_response();
     };
-    let annotations = load_annotations(db.clone(), file_path.clone()).await;
-    let viewed_state = load_viewed_state(db, file_path).await;
+    let annotations = load_annotations(store.as_ref(), &file_path).await;
+    let viewed = load_viewed_state(store.as_ref(), &file_path).await;
     Json(DocumentStateResponse {
         annotations,
-        viewed_state,
+        viewed_state: viewed.state,
+        viewed_by: viewed.by,
+        viewed_changed: viewed.sections.changed.into_iter().collect(),
     })
     .into_response()
 }
 
-fn valid_annotation_id(id: &str) -> bool {
-    id.len() >= 6
-        && id.len() <= 69
-        && id.starts_with("anno-")
-        && id[5..]
-            .bytes()
-            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-')
-}
-
 async fn handle_document_state_command(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
@@ -2342,6785 +3803,15526 @@ async fn handle_document_state_command(
     let Some(file_path) = authorize_document_path(&entry, command.path()) else {
         return StatusCode::NOT_FOUND.into_response();
     };
-    let Some(db) = state.db.clone() else {
+    if state.require_name && !command.is_named() {
+        return (StatusCode::BAD_REQUEST, "a display name is required").into_response();
+    }
+    if let Some(by) = command.by() {
+        if let Err(message) = crate::annotations::validate_resolver(by) {
+            return bad_request(message);
+        }
+    }
+    let Some(store) = state.document_store() else {
         return StatusCode::SERVICE_UNAVAILABLE.into_response();
     };
     let shared = entry
         .shared_annotation
         .load(std::sync::atomic::Ordering::Relaxed);
     let channel = format!("document:{file_path}");
-    let events = entry.events_tx.clone();
-
-    let outcome = tokio::task::spawn_blocking(move || -> Result<Vec<WebSocketMessage>, String> {
-        let conn = db
-            .lock()
-            .unwrap_or_else(std::sync::PoisonError::into_inner);
+    let by = command.by().cloned();
+    let event = |action| crate::audit::Event::new(action, &file_path).by(by.as_ref());
+
+    // The stored annotation a save answers with, so the writer continues
+    // from its new revision.
+    let mut saved = None;
+    let mut trail = Vec::new();
+    let outcome: Result<Vec<WebSocketMessage>, Response> = async {
+        let stored = |e: crate::store::StoreError| {
+            tracing::error!(file_path = %file_path, "document-state write failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        };
         let mut broadcasts = Vec::new();
         match command {
             DocumentStateCommand::SaveAnnotation {
-                annotation,
+                mut annotation,
                 op_id,
                 ..
             } => {
+                crate::annotations::render_note(&mut annotation);
                 let Some(id) = annotation["id"].as_str() else {
-                    return Err("annotation id is required".to_string());
+                    return Err(bad_request("annotation id is required"));
                 };
                 if !valid_annotation_id(id) {
-                    return Err("invalid annotation id".to_string());
+                    return Err(bad_request("invalid annotation id"));
                 }
-                let data = serde_json::to_string(&annotation).map_err(|e| e.to_string())?;
-                if !upsert_annotation_for_file(&conn, id, &file_path, &data)
-                    .map_err(|e| e.to_string())?
+                validate_annotation(&annotation).map_err(bad_request)?;
+                let id = id.to_string();
+                let annotation = match store
+                    .save_annotation(&file_path, &id, annotation)
+                    .await
+                    .map_err(stored)?
                 {
-                    return Err("annotation id belongs to another document".to_string());
-                }
+                    AnnotationSave::Saved(annotation) => annotation,
+                    AnnotationSave::Stale(current) => {
+                        return Err((
+                            StatusCode::CONFLICT,
+                            Json(serde_json::json!({
+                                "success": false,
+                                "message": "annotation was changed by someone else",
+                                "annotation": current,
+                            })),
+                        )
+                            .into_response());
+                    }
+                    AnnotationSave::Foreign => {
+                        return Err(bad_request("annotation id belongs to another document"));
+                    }
+                };
+                // Whoever creates an annotation is its author.
+                trail.push(
+                    if crate::annotations::revision(&annotation) == 1 {
+                        crate::audit::Event::new(crate::audit::Action::Create, &file_path)
+                            .by(by.as_ref().or(annotation.get("author")))
+                    } else {
+                        event(crate::audit::Action::Edit)
+                    }
+                    .annotation(&id),
+                );
+                saved = Some(annotation.clone());
                 broadcasts.push(WebSocketMessage::NewAnnotation { annotation, op_id });
             }
             DocumentStateCommand::DeleteAnnotation { id, op_id, .. } => {
                 if !valid_annotation_id(&id) {
-                    return Err("invalid annotation id".to_string());
+                    return Err(bad_request("invalid annotation id"));
                 }
-                conn.execute(
-                    "DELETE FROM annotations WHERE id = ?1 AND file_path = ?2",
-                    params![id, file_path],
-                )
-                .map_err(|e| e.to_string())?;
+                store
+                    .delete_annotations(&file_path, Some(&id))
+                    .await
+                    .map_err(stored)?;
+                trail.push(event(crate::audit::Action::Delete).annotation(&id));
                 broadcasts.push(WebSocketMessage::DeleteAnnotation { id, op_id });
             }
             DocumentStateCommand::ClearAnnotations { op_id, .. } => {
-                conn.execute(
-                    "DELETE FROM annotations WHERE file_path = ?1",
-                    [file_path.as_str()],
-                )
-                .map_err(|e| e.to_string())?;
+                store
+                    .delete_annotations(&file_path, None)
+                    .await
+                    .map_err(stored)?;
+                trail.push(event(crate::audit::Action::Clear));
                 broadcasts.push(WebSocketMessage::ClearAnnotations { op_id });
             }
+            DocumentStateCommand::UndoAnnotations { op_id, .. } => {
+                let restored = store.undo(&file_path).await.map_err(stored)?;
+                trail.extend(restored.iter().filter_map(|annotation| {
+                    let id = annotation["id"].as_str()?;
+                    Some(event(crate::audit::Action::Restore).annotation(id))
+                }));
+                broadcasts.extend(restored.into_iter().map(|annotation| {
+                    WebSocketMessage::NewAnnotation {
+                        annotation,
+                        op_id: op_id.clone(),
+                    }
+                }));
+            }
+            DocumentStateCommand::ResolveAnnotation {
+                id,
+                resolved,
+                by,
+                op_id,
+                ..
+            } => {
+                if !valid_annotation_id(&id) {
+                    return Err(bad_request("invalid annotation id"));
+                }
+                let annotation = store
+                    .set_resolved(&file_path, &id, resolved, by)
+                    .await
+                    .map_err(stored)?
+                    .ok_or_else(|| bad_request("annotation not found"))?;
+                let action = if resolved {
+                    crate::audit::Action::Resolve
+                } else {
+                    crate::audit::Action::Reopen
+                };
+                trail.push(event(action).annotation(&id));
+                broadcasts.push(WebSocketMessage::NewAnnotation { annotation, op_id });
+            }
             DocumentStateCommand::SaveViewedState {
                 state: viewed,
+                by,
                 op_id,
                 ..
             } => {
                 if !viewed.is_object() {
-                    return Err("viewed state must be an object".to_string());
+                    return Err(bad_request("viewed state must be an object"));
                 }
-                let state_json = serde_json::to_string(&viewed).map_err(|e| e.to_string())?;
-                conn.execute(
-                    "INSERT OR REPLACE INTO viewed_state (file_path, state, updated_at) VALUES (?1, ?2, CURRENT_TIMESTAMP)",
-                    params![file_path, state_json],
-                )
-                .map_err(|e| e.to_string())?;
-                broadcasts.push(WebSocketMessage::ViewedState {
+                let previous = store.viewed_state(&file_path).await.map_err(stored)?;
+                let sections = viewed_sections(previous, &viewed, &file_path).await;
+                let viewed = crate::store::ViewedState {
                     state: viewed,
+                    by,
+                    sections,
+                };
+                store
+                    .save_viewed_state(&file_path, &viewed)
+                    .await
+                    .map_err(stored)?;
+                broadcasts.push(WebSocketMessage::ViewedState {
+                    state: viewed.state,
+                    by: viewed.by,
+                    changed: viewed.sections.changed.into_iter().collect(),
                     op_id,
                 });
             }
         }
         Ok(broadcasts)
-    })
+    }
     .await;
 
     match outcome {
-        Ok(Ok(broadcasts)) => {
+        Ok(broadcasts) => {
             if shared {
                 for message in broadcasts {
-                    broadcast_msg(&events, &channel, &message);
+                    broadcast_msg(&entry.events_tx, &channel, &message);
                 }
             }
-            StatusCode::NO_CONTENT.into_response()
-        }
-        Ok(Err(error)) => (StatusCode::BAD_REQUEST, error).into_response(),
-        Err(error) => {
-            tracing::error!("document-state worker failed: {error}");
-            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            let response = match saved {
+                Some(annotation) => Json(annotation).into_response(),
+                None => StatusCode::NO_CONTENT.into_response(),
+            };
+            audited(response, trail)
         }
+        Err(response) => response,
     }
 }
 
-#[cfg(debug_assertions)]
-async fn dev_reload_stream(State(state): State<AppState>) -> impl IntoResponse {
-    use axum::response::sse::{Event, KeepAlive, Sse};
-    use std::convert::Infallible;
-    let rx = state.dev_reload_tx.subscribe();
-    let stream = tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(|item| async move {
-        // Drop lagged frames silently; we only need *some* recent reload.
-        item.ok()
-            .map(|()| Ok::<Event, Infallible>(Event::default().event("reload")))
-    });
-    Sse::new(stream).keep_alive(KeepAlive::default())
+fn bad_request(message: &str) -> Response {
+    (StatusCode::BAD_REQUEST, message.to_string()).into_response()
 }
 
-#[cfg(debug_assertions)]
-async fn dev_reload_trigger(State(state): State<AppState>) -> impl IntoResponse {
-    // send() errors only when there are no subscribers; that's fine — esbuild
-    // can fire before any webview connects, we just no-op.
-    let _ = state.dev_reload_tx.send(());
-    StatusCode::NO_CONTENT
+/// The section hashes to store with a newly saved viewed `state`: the checked
+/// sections as they read now, so a later edit can uncheck them.
+async fn viewed_sections(
+    previous: Option<crate::store::ViewedState>,
+    state: &serde_json::Value,
+    file_path: &str,
+) -> crate::viewed::Sections {
+    let markdown = tokio::fs::read_to_string(file_path)
+        .await
+        .unwrap_or_default();
+    previous
+        .map(|stored| stored.sections)
+        .unwrap_or_default()
+        .record(state, &markdown)
 }
 
-async fn load_annotations(db: Arc<Mutex<Connection>>, file_path: String) -> Vec<serde_json::Value> {
-    tokio::task::spawn_blocking(move || {
-        let db = db.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
-        let mut stmt = match db.prepare("SELECT data FROM annotations WHERE file_path = ?1") {
-            Ok(s) => s,
-            Err(e) => {
-                tracing::error!(file_path = %file_path, "load_annotations: prepare failed: {e}");
-                return Vec::new();
-            }
-        };
-        let rows = match stmt.query_map([file_path.as_str()], |row| row.get::<_, String>(0)) {
-            Ok(r) => r,
-            Err(e) => {
-                tracing::error!(file_path = %file_path, "load_annotations: query_map failed: {e}");
-                return Vec::new();
-            }
-        };
-        rows.filter_map(Result::ok)
-            .filter_map(|s| serde_json::from_str(&s).ok())
-            .collect()
-    })
-    .await
-    .unwrap_or_else(|e| {
-        tracing::error!("load_annotations join error: {e}");
-        Vec::new()
-    })
-}
+// ── Private viewed state ─────────────────────────────────────────────────────
+//
+// Without shared annotations a collaborator has no document state, but their
+// checkmarks still persist: keyed by a long-lived browser cookie, so reading
+// progress survives browser restarts without being visible to anyone else.
 
-async fn load_viewed_state(db: Arc<Mutex<Connection>>, file_path: String) -> serde_json::Value {
-    tokio::task::spawn_blocking(move || {
-        let db = db.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
-        let state_json = db
-            .query_row(
-                "SELECT state FROM viewed_state WHERE file_path = ?1",
-                [file_path.as_str()],
-                |row| row.get::<_, String>(0),
-            )
-            .unwrap_or_else(|_| "{}".to_string());
-        serde_json::from_str(&state_json).unwrap_or_else(|_| serde_json::json!({}))
-    })
-    .await
-    .unwrap_or_else(|e| {
-        tracing::error!("load_viewed_state join error: {e}");
-        serde_json::json!({})
-    })
+const CLIENT_COOKIE: &str = "markon_client";
+const CLIENT_COOKIE_MAX_AGE_SECS: u64 = 365 * 24 * 60 * 60;
+
+fn private_viewed_allowed(role: Option<AccessRole>, entry: &WorkspaceEntry) -> bool {
+    role == Some(AccessRole::Collaborator)
+        && !entry
+            .shared_annotation
+            .load(std::sync::atomic::Ordering::Relaxed)
+        && entry.flags().enable_viewed
 }
 
-async fn send_json(
-    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
-    msg: &WebSocketMessage,
-) -> Result<(), ()> {
-    let Ok(encoded) = serde_json::to_string(msg) else {
-        return Err(());
-    };
-    sender
-        .send(Message::Text(encoded.into()))
-        .await
-        .map_err(|_| ())
+/// The request's client id, if it carries a well-formed one.
+fn client_id(headers: &axum::http::HeaderMap) -> Option<String> {
+    let cookie = headers
+        .get(axum::http::header::COOKIE)
+        .and_then(|value| value.to_str().ok());
+    admin_auth::cookie_value(cookie, CLIENT_COOKIE)
+        .filter(|id| id.len() == 32 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(str::to_ascii_lowercase)
 }
 
-async fn send_initial_document_state(
-    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
-    db: Arc<Mutex<Connection>>,
-    file_path: String,
-) -> Result<(), ()> {
-    let annotations = load_annotations(db.clone(), file_path.clone()).await;
-    tracing::debug!(
-        file_path = %file_path,
-        count = annotations.len(),
-        "sending initial annotations to client",
-    );
-    send_json(sender, &WebSocketMessage::AllAnnotations { annotations }).await?;
-    let viewed = load_viewed_state(db, file_path).await;
-    send_json(
-        sender,
-        &WebSocketMessage::ViewedState {
-            state: viewed,
-            op_id: None,
-        },
+fn make_client_cookie(id: &str, secure: bool) -> String {
+    let secure_attr = if secure { "; Secure" } else { "" };
+    format!(
+        "{CLIENT_COOKIE}={id}; Path=/; Max-Age={CLIENT_COOKIE_MAX_AGE_SECS}; HttpOnly; SameSite=Lax{secure_attr}"
     )
-    .await
 }
 
-fn broadcast_msg(tx: &broadcast::Sender<WorkspaceEvent>, channel: &str, msg: &WebSocketMessage) {
-    if let Ok(encoded) = serde_json::to_string(msg) {
-        let _ = tx.send(WorkspaceEvent::Channel {
-            channel: channel.to_string(),
-            payload: encoded,
-        });
+/// The request's client id, or a fresh one with the cookie that issues it.
+fn client_id_or_issue(
+    state: &AppState,
+    headers: &axum::http::HeaderMap,
+) -> (String, Option<String>) {
+    match client_id(headers) {
+        Some(id) => (id, None),
+        None => {
+            let id = crate::workspace::generate_token();
+            let host = headers
+                .get(axum::http::header::HOST)
+                .and_then(|value| value.to_str().ok());
+            let cookie = make_client_cookie(&id, state.allowed_hosts.is_secure_header(host));
+            (id, Some(cookie))
+        }
     }
 }
 
-fn workspace_event_payload(event: WorkspaceEvent, channel: &str) -> Option<String> {
-    match event {
-        WorkspaceEvent::Workspace { payload } => Some(payload),
-        WorkspaceEvent::Channel {
-            channel: event_channel,
-            payload,
-        } if event_channel == channel => Some(payload),
-        WorkspaceEvent::Channel { .. } => None,
-    }
+#[derive(Serialize)]
+struct ClientViewedStateResponse {
+    viewed_state: serde_json::Value,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    viewed_changed: Vec<String>,
 }
 
-/// Insert or update an annotation only when an existing global id already
-/// belongs to this same document. The persisted schema intentionally keeps its
-/// historical global primary key, so the query itself must prevent a client on
-/// one document from moving/replacing a row owned by another document.
-fn upsert_annotation_for_file(
-    conn: &Connection,
-    id: &str,
-    file_path: &str,
-    data: &str,
-) -> rusqlite::Result<bool> {
-    conn.execute(
-        "INSERT INTO annotations (id, file_path, data)
-         VALUES (?1, ?2, ?3)
-         ON CONFLICT(id) DO UPDATE SET data = excluded.data
-         WHERE annotations.file_path = excluded.file_path",
-        rusqlite::params![id, file_path, data],
-    )
-    .map(|changed| changed > 0)
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientViewedStateSave {
+    path: String,
+    state: serde_json::Value,
 }
 
-fn handle_client_msg(entry: &WorkspaceEntry, session: &WsSession, msg: WebSocketMessage) {
-    // Browser persistence always goes through the document-state HTTP endpoint
-    // before any shared broadcast. WebSocket input is deliberately Live-only;
-    // annotation/viewed variants remain deserializable as outbound protocol
-    // messages but cannot form a second database mutation path.
-    if let WebSocketMessage::LiveAction { data } = msg {
-        if entry.enable_live.load(std::sync::atomic::Ordering::Relaxed) {
-            broadcast_msg(
-                &entry.events_tx,
-                &session.channel,
-                &WebSocketMessage::LiveAction { data },
-            );
+/// `GET /_/{workspace_id}/data/viewed-state?path=…` — this browser's private
+/// viewed state, issuing its client cookie on the first visit.
+async fn handle_client_viewed_state(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    headers: axum::http::HeaderMap,
+    Query(query): Query<DocumentStateQuery>,
+) -> Response {
+    let Some(entry) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !private_viewed_allowed(role.map(|Extension(role)| role), &entry) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(file_path) = authorize_document_path(&entry, &query.path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(store) = state.document_store() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let (id, cookie) = client_id_or_issue(&state, &headers);
+    let viewed = match store.client_viewed_state(&id, &file_path).await {
+        Ok(viewed) => viewed,
+        Err(e) => {
+            tracing::error!(file_path = %file_path, "client viewed state load failed: {e}");
+            None
         }
+    };
+    let body = Json(match viewed {
+        Some(viewed) => ClientViewedStateResponse {
+            viewed_state: viewed.state,
+            viewed_changed: viewed.sections.changed.into_iter().collect(),
+        },
+        None => ClientViewedStateResponse {
+            viewed_state: serde_json::json!({}),
+            viewed_changed: Vec::new(),
+        },
+    });
+    match cookie {
+        Some(cookie) => ([(axum::http::header::SET_COOKIE, cookie)], body).into_response(),
+        None => body.into_response(),
     }
 }
 
-async fn handle_socket(socket: WebSocket, state: AppState, entry: Arc<WorkspaceEntry>) {
-    let (mut sender, mut receiver) = socket.split();
-    let db = state.db.clone();
-    let mut rx = entry.events_tx.subscribe();
-    let mut config_rx = entry.config_tx.subscribe();
-
-    let hello = match tokio::time::timeout(std::time::Duration::from_secs(5), receiver.next()).await
-    {
-        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<WsHello>(&text).ok(),
-        Err(_) => {
-            tracing::warn!(workspace_id = %entry.id, "timed out waiting for websocket hello");
-            return;
-        }
-        _ => {
-            tracing::warn!(workspace_id = %entry.id, "missing or invalid websocket hello");
-            return;
-        }
+/// `POST /_/{workspace_id}/data/viewed-state` — replace this browser's
+/// private viewed state of `path`.
+async fn handle_client_viewed_state_save(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    headers: axum::http::HeaderMap,
+    Json(save): Json<ClientViewedStateSave>,
+) -> Response {
+    let Some(entry) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
     };
-    let Some(session) = hello.and_then(|hello| authorize_ws_target(&entry, hello.target)) else {
-        tracing::warn!(workspace_id = %entry.id, "rejecting unauthorized websocket target");
-        return;
+    if !private_viewed_allowed(role.map(|Extension(role)| role), &entry) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(file_path) = authorize_document_path(&entry, &save.path) else {
+        return StatusCode::NOT_FOUND.into_response();
     };
-    let session = Arc::new(session);
-
-    // A Live-only connection receives no stored annotation/viewed data. Surface
-    // sessions never receive it, even when shared annotations are enabled.
-    if entry
-        .shared_annotation
-        .load(std::sync::atomic::Ordering::Relaxed)
-    {
-        if let WsSessionTarget::Document { file_path } = &session.target {
-            let Some(db) = db.as_ref() else { return };
-            tokio::select! {
-                biased;
-                _ = config_rx.recv() => return,
-                result = send_initial_document_state(&mut sender, db.clone(), file_path.clone()) => {
-                    if result.is_err() {
-                        return;
-                    }
-                }
-            }
+    let Some(id) = client_id(&headers) else {
+        return bad_request("client id cookie is required");
+    };
+    if !save.state.is_object() {
+        return bad_request("viewed state must be an object");
+    }
+    let Some(store) = state.document_store() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    };
+    let result = async {
+        let previous = store.client_viewed_state(&id, &file_path).await?;
+        let sections = viewed_sections(previous, &save.state, &file_path).await;
+        let viewed = crate::store::ViewedState {
+            state: save.state,
+            by: None,
+            sections,
+        };
+        store
+            .save_client_viewed_state(&id, &file_path, &viewed)
+            .await
+    }
+    .await;
+    match result {
+        Ok(()) => StatusCode::NO_CONTENT.into_response(),
+        Err(e) => {
+            tracing::error!(file_path = %file_path, "client viewed state write failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
     }
+}
 
-    let send_channel = session.channel.clone();
-    let mut send_task = tokio::spawn(async move {
-        loop {
-            match rx.recv().await {
-                Ok(event) => {
-                    let Some(payload) = workspace_event_payload(event, &send_channel) else {
-                        continue;
-                    };
-                    if sender.send(Message::Text(payload.into())).await.is_err() {
-                        break;
-                    }
-                }
-                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
-                    tracing::warn!(skipped = n, "ws broadcast lagged; continuing");
-                    continue;
-                }
-                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
-            }
-        }
-    });
+// ── Recent and pinned files ─────────────────────────────────────────────────
+//
+// Markdown page views and pins are kept per browser under the same client
+// cookie as private viewed state (see `crate::recent`), and surface on the
+// workspace root above the file tree.
 
-    let recv_entry = entry.clone();
-    let recv_session = session.clone();
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(Message::Text(text))) = receiver.next().await {
-            if text.len() > MAX_WS_MSG_BYTES {
-                tracing::warn!("dropping oversized ws message ({} bytes)", text.len());
-                continue;
-            }
-            let Ok(msg) = serde_json::from_str::<WebSocketMessage>(&text) else {
-                continue;
-            };
-            handle_client_msg(&recv_entry, &recv_session, msg);
-        }
-    });
+/// Documents listed in the root page's "Recent" strip, pins aside.
+const RECENT_FILES_SHOWN: usize = 8;
 
-    tokio::select! {
-        _ = (&mut send_task) => recv_task.abort(),
-        _ = (&mut recv_task) => send_task.abort(),
-        _ = config_rx.recv() => {
-            send_task.abort();
-            recv_task.abort();
-        }
-    };
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PinFileRequest {
+    /// Workspace-relative path of a Markdown file.
+    path: String,
+    pinned: bool,
 }
 
-// ── Workspace content handlers ────────────────────────────────────────────────
+/// A pinned or recently viewed document on the workspace root.
+#[derive(Serialize)]
+struct QuickAccessEntry {
+    name: String,
+    title: Option<String>,
+    /// Workspace-relative route path, as the pin endpoint takes it.
+    path: String,
+    link: String,
+}
 
-/// Standalone chat-only page. Opened by ChatManager.#openPopout() in its own
-/// browser-level window. Returns the minimal `chat.html` template — no
-/// markdown body, no TOC, no Live, no annotations bundle. The shared
-/// `main.js` bundle still loads, but at boot it sees `<meta name="chat-only">`
-/// and routes to `ChatManager.initPopout()` instead of `MarkonApp`.
-async fn handle_chat_popout(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
+/// Record that this browser is viewing `file_path`, returning the client
+/// cookie to set when the browser had none yet.
+async fn record_file_view(
+    state: &AppState,
+    ws: &WorkspaceEntry,
+    headers: &axum::http::HeaderMap,
+    file_path: &FsPath,
+) -> Option<String> {
+    let db = state.db.clone().filter(|_| !ws.is_ephemeral())?;
+    let (id, cookie) = client_id_or_issue(state, headers);
+    let file_path = file_path.to_string_lossy().into_owned();
+    match db
+        .write(move |conn| crate::recent::record_view(conn, &id, &file_path))
+        .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::warn!("recording a file view failed: {e}"),
+        Err(e) => tracing::error!("file view task join error: {e}"),
+    }
+    cookie
+}
+
+/// Record the version of `file_path` being opened, so a document's history
+/// starts before its first edit; see [`crate::snapshots`].
+async fn record_document_snapshot(ws: &WorkspaceEntry, file_path: &FsPath) {
+    let Some(db) = ws.annotation_db.clone().filter(|_| ws.snapshots) else {
+        return;
     };
-    // Hide the chat page entirely if chat is disabled for this workspace —
-    // mirrors the same gate the in-page chat panel respects via the
-    // `enable-chat` meta flag. Chat is a collaboration ability, so it stays open
-    // to remote collaborators too whenever the flag is on.
-    if !ws.flags().enable_chat {
-        return StatusCode::NOT_FOUND.into_response();
+    let Ok(markdown) = tokio::fs::read_to_string(file_path).await else {
+        return;
+    };
+    let file_path = file_path.to_string_lossy().into_owned();
+    match db
+        .write(move |conn| crate::snapshots::record(conn, &file_path, &markdown))
+        .await
+    {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => tracing::warn!("recording a snapshot failed: {e}"),
+        Err(e) => tracing::error!("snapshot task join error: {e}"),
     }
-    let mut context = base_context(&state);
-    context.insert("workspace_id", &workspace_id);
-    context.insert("title", &"Markon Chat".to_string());
-    render_template(&state, "chat.html", &context)
 }
 
-async fn handle_workspace_root(
+/// `POST /_/{workspace_id}/data/pins` — pin `path` to this browser's quick
+/// access strip on the workspace root, or unpin it.
+async fn handle_pin_file(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
     role: Option<Extension<AccessRole>>,
-) -> impl IntoResponse {
+    headers: axum::http::HeaderMap,
+    Json(request): Json<PinFileRequest>,
+) -> Response {
     let Some(ws) = state.workspace_registry.get(&workspace_id) else {
         return StatusCode::NOT_FOUND.into_response();
     };
-    // Single-file workspace: there's no listing, just the one document.
-    // 302 to the file URL so the user lands directly on the rendered .md.
-    if let Some(only) = &ws.single_file {
-        return Redirect::to(&workspace_file_url(&workspace_id, only)).into_response();
+    if role.is_none() || ws.is_ephemeral() {
+        return StatusCode::FORBIDDEN.into_response();
     }
-    let root = canonical_workspace_root(&ws);
-    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
-    render_directory_listing(&workspace_id, &ws, &root, None, &state, can_manage)
-}
-
-async fn handle_workspace_path(
-    State(state): State<AppState>,
-    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
-    role: Option<Extension<AccessRole>>,
-    headers: axum::http::HeaderMap,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
+    let Some(db) = state.db.clone() else {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
     };
-
-    let decoded = urlencoding::decode(&path).unwrap_or_else(|_| path.clone().into());
-    let rel = decoded.trim_start_matches('/');
-    let canonical = match ws.fs.resolve_served(rel) {
-        Ok(path) => path,
-        Err(
-            crate::workspace_fs::WorkspaceFsError::InvalidPath
-            | crate::workspace_fs::WorkspaceFsError::Denied,
-        ) if !ws.is_ephemeral() => {
-            return (StatusCode::FORBIDDEN, "Access denied").into_response();
-        }
-        Err(_) => {
-            return (StatusCode::NOT_FOUND, format!("Path not found: {decoded}")).into_response();
-        }
+    let rel = request.path.trim_start_matches('/').to_string();
+    let resolve_ws = ws.clone();
+    let file_path = tokio::task::spawn_blocking(move || {
+        resolve_ws
+            .fs
+            .resolve_served(&rel)
+            .ok()
+            .filter(|path| path.is_file() && resolve_ws.fs.policy().is_markdown(path))
+    })
+    .await
+    .ok()
+    .flatten();
+    let Some(file_path) = file_path else {
+        return StatusCode::NOT_FOUND.into_response();
     };
-
-    let root = canonical_workspace_root(&ws);
-    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
-    if !is_inside_workspace(&canonical, &root) {
-        return (StatusCode::FORBIDDEN, "Access denied").into_response();
-    }
-
-    if canonical.is_file() {
-        if is_markdown_path(&canonical) {
-            render_markdown_file_async(
-                canonical.to_string_lossy().into_owned(),
-                workspace_id.clone(),
-                ws.clone(),
-                root.clone(),
-                state.clone(),
-                can_manage,
-            )
-            .await
-        } else {
-            // Small UTF-8 text/code files get an elegant read-only, syntax-
-            // highlighted preview page. Everything else — images, media, PDFs,
-            // binaries, oversized text — is served as raw bytes (the browser
-            // displays what it can inline and downloads the rest); this also
-            // keeps embedded resources like markdown images working verbatim.
-            match render_preview_or_none(
-                canonical.clone(),
-                workspace_id.clone(),
-                ws.clone(),
-                root.clone(),
-                state.clone(),
+    let (id, cookie) = client_id_or_issue(&state, &headers);
+    let file_path = file_path.to_string_lossy().into_owned();
+    let pinned = request.pinned;
+    match db
+        .write(move |conn| crate::recent::set_pinned(conn, &id, &file_path, pinned))
+        .await
+    {
+        Ok(Ok(())) => match cookie {
+            Some(cookie) => (
+                [(axum::http::header::SET_COOKIE, cookie)],
+                StatusCode::NO_CONTENT,
             )
-            .await
-            {
-                Some(resp) => resp,
-                None => serve_file(&canonical, &headers).await,
-            }
-        }
-    } else if canonical.is_dir() {
-        if ws.is_ephemeral() {
-            // Single-file capabilities never authorize directories. Keep this
-            // explicit as defense in depth if serving policy changes later.
-            return (StatusCode::NOT_FOUND, "Path not found").into_response();
+                .into_response(),
+            None => StatusCode::NO_CONTENT.into_response(),
+        },
+        Ok(Err(e)) => {
+            tracing::error!("pinning a file failed: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
-        // Subdirectories are browsed in place on the workspace root via a URL
-        // hash (e.g. "/{id}/#docs/") which the frontend expands as an inline
-        // tree — there is no standalone subdirectory listing page anymore.
-        // Redirect any direct/legacy subdirectory URL to that anchor form.
-        match workspace_relative_path(&canonical, &root).map(|rel| path_to_route(&rel)) {
-            Some(rel_str) if !rel_str.is_empty() => Redirect::to(&format!(
-                "{}#{}/",
-                workspace_root_url(&workspace_id),
-                rel_str
-            ))
-            .into_response(),
-            // The workspace root itself is served by `handle_workspace_root`;
-            // this arm is just a safe fallback.
-            _ => render_directory_listing(&workspace_id, &ws, &root, None, &state, can_manage),
+        Err(e) => {
+            tracing::error!("pin task join error: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
         }
-    } else {
-        (StatusCode::NOT_FOUND, "Path not found").into_response()
     }
 }
 
-#[derive(Deserialize)]
-struct GitHistoryQuery {
-    branch: Option<String>,
-    author: Option<String>,
-    range: Option<String>,
+/// The pinned and recent documents `client_id` has under `root`, dropping
+/// files that are gone and recent ones that are also pinned.
+fn quick_access_entries(
+    base_path: &str,
+    client_id: &str,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    db: &crate::db::Database,
+) -> (Vec<QuickAccessEntry>, Vec<QuickAccessEntry>) {
+    let (pinned, recent) = db.read_blocking(|conn| {
+        let pinned = crate::recent::pinned_files(conn, client_id, root).unwrap_or_else(|e| {
+            tracing::warn!("loading pinned files failed: {e}");
+            Vec::new()
+        });
+        let recent = crate::recent::recent_files(conn, client_id, root).unwrap_or_else(|e| {
+            tracing::warn!("loading recent files failed: {e}");
+            Vec::new()
+        });
+        (pinned, recent)
+    });
+    let entry = |file_path: &String| -> Option<QuickAccessEntry> {
+        let path = FsPath::new(file_path);
+        let meta = fs::metadata(path).ok().filter(fs::Metadata::is_file)?;
+        let rel = path_to_route(&workspace_relative_path(path, root)?);
+        Some(QuickAccessEntry {
+            name: path.file_name()?.to_string_lossy().into_owned(),
+            title: cached_markdown_title(&ws.metadata_cache, path, &meta),
+            link: workspace_file_url(base_path, workspace_id, &rel),
+            path: rel,
+        })
+    };
+    let recent = recent
+        .iter()
+        .filter(|file_path| !pinned.contains(file_path))
+        .filter_map(entry)
+        .take(RECENT_FILES_SHOWN)
+        .collect();
+    (pinned.iter().filter_map(entry).collect(), recent)
 }
 
-/// Map a toolbar range key to a git `--since` approxidate. `""`/`"all"` (and any
-/// unknown key) mean "no lower bound".
-fn git_history_since(range: Option<&str>) -> Option<String> {
-    match range.map(str::trim).unwrap_or("") {
-        "day" => Some("1 day ago".to_string()),
-        "week" => Some("1 week ago".to_string()),
-        "month" => Some("1 month ago".to_string()),
-        "year" => Some("1 year ago".to_string()),
-        _ => None,
-    }
+// ── Audit log ────────────────────────────────────────────────────────────────
+//
+// Handlers attach what a request did to its response as a `crate::audit::Trail`;
+// `record_audit` writes it with the request's role and peer address once the
+// handler succeeded. Without `--audit` trails are dropped unread.
+
+/// `response` carrying `events` for the audit log.
+fn audited(mut response: Response, events: Vec<crate::audit::Event>) -> Response {
+    response
+        .extensions_mut()
+        .insert(crate::audit::Trail(events));
+    response
 }
 
-async fn handle_git_history(
+async fn record_audit(
     State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Query(q): Query<GitHistoryQuery>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
+    req: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> Response {
+    if !state.audit {
+        return next.run(req).await;
+    }
+    let role = req.extensions().get::<AccessRole>().map(|role| match role {
+        AccessRole::Admin => "admin",
+        AccessRole::Collaborator => "collaborator",
+    });
+    let ip = req
+        .extensions()
+        .get::<axum::extract::ConnectInfo<std::net::SocketAddr>>()
+        .map(|axum::extract::ConnectInfo(addr)| addr.ip().to_string());
+    let mut response = next.run(req).await;
+    let status = response.status();
+    let Some(crate::audit::Trail(events)) = response.extensions_mut().remove() else {
+        return response;
     };
-    let branch = q
-        .branch
-        .as_deref()
-        .map(str::trim)
-        .filter(|b| !b.is_empty())
-        .map(str::to_string);
-    let author = q
-        .author
-        .as_deref()
-        .map(str::trim)
-        .filter(|a| !a.is_empty())
-        .map(str::to_string);
-    let range_key = q
-        .range
-        .as_deref()
-        .map(str::trim)
-        .filter(|r| !r.is_empty() && *r != "all")
-        .unwrap_or("")
-        .to_string();
-    let filter = git::HistoryFilter {
-        branch: branch.clone(),
-        author: author.clone(),
-        since: git_history_since(Some(&range_key)),
+    let Some(db) = state.db.clone() else {
+        return response;
     };
-    let root = directory_root_or_not_found!(ws).to_path_buf();
-    let git_root = root.clone();
-    let history =
-        tokio::task::spawn_blocking(move || git::history_filtered(&git_root, 80, &filter))
-            .await
-            .unwrap_or_else(|e| {
-                tracing::error!("git history blocking task join error: {e}");
-                Err(git::GitError::Command("internal task error".into()))
-            });
-    match history {
-        Ok(commits) => render_git_history_page(
-            &state,
-            &workspace_id,
-            &root,
-            &commits,
-            branch.as_deref(),
-            author.as_deref(),
-            &range_key,
-        ),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list git history: {e}"),
-        )
-            .into_response(),
+    if events.is_empty() || status.is_client_error() || status.is_server_error() {
+        return response;
+    }
+    match db
+        .write(move |conn| crate::audit::record(conn, &events, role, ip.as_deref()))
+        .await
+    {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!("audit log write failed: {e}"),
+        Err(e) => tracing::error!("audit log worker failed: {e}"),
     }
+    response
 }
 
-async fn handle_git_history_data(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let root = directory_root_or_not_found!(ws).to_path_buf();
-    let history = tokio::task::spawn_blocking(move || git::history(&root, 80))
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git history blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match history {
-        Ok(commits) => Json(commits).into_response(),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list git history: {e}"),
+// ── Annotation REST API ──────────────────────────────────────────────────────
+//
+// Resource-style access to the same SQLite rows the document-state command
+// endpoint writes, for scripts and editor plugins. Access rules and shared
+// broadcasts match `handle_document_state_command`.
+
+#[derive(Deserialize, Default)]
+struct AnnotationPathQuery {
+    /// Workspace route (`docs/a.md`) or absolute path of the annotated file.
+    path: String,
+    /// List filters; see [`AnnotationFilter`].
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    author: Option<String>,
+    #[serde(default)]
+    resolved: Option<bool>,
+}
+
+/// Failure of an annotation REST call, rendered as a status plus JSON body.
+struct AnnotationApiError(StatusCode, &'static str);
+
+impl IntoResponse for AnnotationApiError {
+    fn into_response(self) -> Response {
+        (
+            self.0,
+            Json(serde_json::json!({ "success": false, "message": self.1 })),
         )
-            .into_response(),
+            .into_response()
     }
 }
 
-async fn handle_git_branches(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let root = directory_root_or_not_found!(ws).to_path_buf();
-    let branches = tokio::task::spawn_blocking(move || git::branches_detailed(&root))
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git branches blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match branches {
-        Ok(branches) => render_git_branches_page(&state, &workspace_id, &branches),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list git branches: {e}"),
-        )
-            .into_response(),
+/// Log a failed store call and report it to the caller as a storage failure.
+fn annotation_storage_failed(operation: &str, e: crate::store::StoreError) -> AnnotationApiError {
+    tracing::error!("{operation} failed: {e}");
+    AnnotationApiError(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Annotation storage failed",
+    )
+}
+
+/// Resolve the workspace and check the caller may read and write its
+/// annotations. Returns the entry with the document store.
+fn annotation_api_access(
+    state: &AppState,
+    workspace_id: &str,
+    role: Option<Extension<AccessRole>>,
+) -> Result<(Arc<WorkspaceEntry>, Arc<dyn crate::store::DocumentStore>), AnnotationApiError> {
+    let entry = state
+        .workspace_registry
+        .get(workspace_id)
+        .ok_or(AnnotationApiError(
+            StatusCode::NOT_FOUND,
+            "Workspace not found",
+        ))?;
+    if !document_state_access_allowed(role.map(|Extension(role)| role), &entry) {
+        return Err(AnnotationApiError(
+            StatusCode::FORBIDDEN,
+            "Annotations are not shared in this workspace",
+        ));
     }
+    let store = state.document_store().ok_or(AnnotationApiError(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Annotation storage is unavailable",
+    ))?;
+    Ok((entry, store))
 }
 
-async fn handle_git_tags(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let root = directory_root_or_not_found!(ws).to_path_buf();
-    let tags = tokio::task::spawn_blocking(move || git::tags(&root, 200))
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git tags blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match tags {
-        Ok(tags) => render_git_tags_page(&state, &workspace_id, &tags),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to list git tags: {e}"),
-        )
-            .into_response(),
+/// Canonical persistence key for a workspace route or absolute path.
+fn annotation_file_key(entry: &WorkspaceEntry, path: &str) -> Result<String, AnnotationApiError> {
+    if path.is_empty() || path.len() > 4096 || path.contains('\0') {
+        return Err(AnnotationApiError(StatusCode::BAD_REQUEST, "Invalid path"));
+    }
+    entry
+        .fs
+        .resolve_content_input(FsPath::new(path))
+        .ok()
+        .filter(|resolved| resolved.is_file())
+        .map(|resolved| resolved.to_string_lossy().into_owned())
+        .ok_or(AnnotationApiError(StatusCode::NOT_FOUND, "File not found"))
+}
+
+/// Build a list filter from query parameters. Query strings can't carry a
+/// flattened struct with typed fields, so the parameters are spelled out.
+fn annotation_list_filter(
+    tag: Option<&str>,
+    author: Option<&str>,
+    resolved: Option<bool>,
+    category: Option<&str>,
+) -> Result<AnnotationFilter, AnnotationApiError> {
+    AnnotationFilter {
+        tag: tag.map(str::to_string),
+        author: author.map(str::to_string),
+        resolved,
+        category: category.map(str::to_string),
+    }
+    .normalized()
+    .map_err(|_| AnnotationApiError(StatusCode::BAD_REQUEST, "Unknown annotation category"))
+}
+
+/// Under `--require-name`, writes must say who made them.
+fn require_display_name(
+    state: &AppState,
+    identity: Option<&serde_json::Value>,
+) -> Result<(), AnnotationApiError> {
+    if state.require_name && !crate::annotations::has_display_name(identity) {
+        return Err(AnnotationApiError(
+            StatusCode::BAD_REQUEST,
+            "A display name is required",
+        ));
     }
+    Ok(())
 }
 
-async fn handle_git_working_diff(
+/// Broadcast to open viewers of `file_path`, as the WebSocket path would.
+fn broadcast_annotation_change(entry: &WorkspaceEntry, file_path: &str, message: WebSocketMessage) {
+    if entry
+        .shared_annotation
+        .load(std::sync::atomic::Ordering::Relaxed)
+    {
+        broadcast_msg(&entry.events_tx, &format!("document:{file_path}"), &message);
+    }
+}
+
+/// The stored file path of annotation `id`, if it belongs to `entry`.
+async fn annotation_owner(
+    store: &dyn crate::store::DocumentStore,
+    entry: &WorkspaceEntry,
+    id: &str,
+) -> Result<String, AnnotationApiError> {
+    let not_found = || AnnotationApiError(StatusCode::NOT_FOUND, "Annotation not found");
+    if !valid_annotation_id(id) {
+        return Err(not_found());
+    }
+    let file_path = store
+        .annotation_file(id)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation lookup", e))?
+        .ok_or_else(not_found)?;
+    entry
+        .fs
+        .route_for_path(FsPath::new(&file_path))
+        .map(|_| file_path)
+        .ok_or_else(not_found)
+}
+
+/// `GET /_/{workspace_id}/annotations?path=…` — every annotation on one file.
+async fn handle_annotations_list(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-    Query(query): Query<GitViewQuery>,
     role: Option<Extension<AccessRole>>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
-    let initial_view = diff_view_from_query(query.view.as_deref());
-    directory_root_or_not_found!(ws);
-    let workspace_fs = ws.fs.clone();
-    let diff = tokio::task::spawn_blocking(move || git::working_diff(&workspace_fs))
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git working diff blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match diff {
-        Ok(diff) => {
-            render_git_diff_page(&state, &workspace_id, &ws, can_manage, &diff, initial_view)
-        }
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to read git diff: {e}"),
-        )
-            .into_response(),
-    }
+    Query(query): Query<AnnotationPathQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let file_path = annotation_file_key(&entry, &query.path)?;
+    let filter = annotation_list_filter(
+        query.tag.as_deref(),
+        query.author.as_deref(),
+        query.resolved,
+        query.category.as_deref(),
+    )?;
+    let mut annotations = load_annotations(store.as_ref(), &file_path).await;
+    annotations.retain(|annotation| filter.matches(annotation));
+    Ok(Json(annotations).into_response())
+}
+
+#[derive(Deserialize, Default)]
+struct AnnotationExportQuery {
+    /// `json` (default), `csv`, or `markdown`.
+    #[serde(default)]
+    format: Option<String>,
+    /// Limit the export to one file; the whole workspace otherwise.
+    #[serde(default)]
+    path: Option<String>,
+    /// List filters, as for `GET /_/{workspace_id}/annotations`.
+    #[serde(default)]
+    category: Option<String>,
+    #[serde(default)]
+    tag: Option<String>,
+    #[serde(default)]
+    author: Option<String>,
+    #[serde(default)]
+    resolved: Option<bool>,
 }
 
-async fn handle_git_working_diff_data(
+/// `GET /_/{workspace_id}/annotations/export` — every annotation in the
+/// workspace (or one file) as a downloadable JSON dump, CSV, or Markdown
+/// report. Paths are shown as workspace routes.
+async fn handle_annotations_export(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-    Query(query): Query<GitViewQuery>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    if diff_view_from_query(query.view.as_deref()) == "rendered" {
-        directory_root_or_not_found!(ws);
-        return match markdown_compare_diff_data_async(
-            &state,
-            &workspace_id,
-            ws.fs.clone(),
-            "HEAD",
-            "worktree",
-            query.f.as_deref(),
-        )
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<AnnotationExportQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let format: crate::annotations::ExportFormat = query
+        .format
+        .as_deref()
+        .unwrap_or("json")
+        .parse()
+        .map_err(|_| AnnotationApiError(StatusCode::BAD_REQUEST, "Unknown export format"))?;
+    let only = query
+        .path
+        .as_deref()
+        .map(|path| annotation_file_key(&entry, path))
+        .transpose()?;
+    let filter = annotation_list_filter(
+        query.tag.as_deref(),
+        query.author.as_deref(),
+        query.resolved,
+        query.category.as_deref(),
+    )?;
+
+    let rows = store
+        .all_annotations()
         .await
+        .map_err(|e| annotation_storage_failed("annotation export", e))?;
+    let mut annotations = crate::annotations::exported(rows, |path| {
+        if only
+            .as_deref()
+            .is_some_and(|only| FsPath::new(only) != path)
         {
-            Ok(data) => Json(data).into_response(),
-            Err(git::GitError::NotRepository) => git_not_repository_response(),
-            Err(git::GitError::InvalidRevision) => {
-                (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
-            }
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to read markdown diff: {e}"),
-            )
-                .into_response(),
-        };
-    }
-    directory_root_or_not_found!(ws);
-    let workspace_fs = ws.fs.clone();
-    let diff = tokio::task::spawn_blocking(move || git::working_diff(&workspace_fs))
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git working diff blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match diff {
-        Ok(diff) => git_diff_json_response(&diff, query.f.as_deref()),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
+            return None;
+        }
+        entry.fs.route_for_path(path)
+    });
+    annotations.retain(|annotation| filter.matches(&annotation.annotation));
+    let body = crate::annotations::render(&annotations, format);
+    let disposition = format!(
+        "attachment; filename=\"annotations.{}\"",
+        format.extension()
+    );
+    Ok((
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+/// `POST /_/{workspace_id}/annotations/import` — store a JSON export in this
+/// workspace. Each entry is placed by its exported `path` first, so a dump
+/// taken where the workspace lived elsewhere lands on the same files here;
+/// the original absolute path is the fallback. Entries for files outside
+/// the workspace are counted as missing.
+async fn handle_annotations_import(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    body: String,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let entries = crate::annotations::parse_dump(&body)
+        .map_err(|_| AnnotationApiError(StatusCode::BAD_REQUEST, "Invalid annotation export"))?;
+    // Logged once, against the workspace rather than each file.
+    let event = crate::audit::Event::new(
+        crate::audit::Action::Import,
+        canonical_workspace_root(&entry).to_string_lossy(),
+    );
+
+    let (rows, mut report) = tokio::task::spawn_blocking(move || {
+        crate::annotations::import_rows(&entries, |item| {
+            [item.path.as_deref(), Some(item.file_path.as_str())]
+                .into_iter()
+                .flatten()
+                .find_map(|path| {
+                    entry
+                        .fs
+                        .resolve_content_input(FsPath::new(path))
+                        .ok()
+                        .filter(|resolved| resolved.is_file())
+                })
+        })
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("annotation import worker failed: {e}");
+        AnnotationApiError(
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to read git diff: {e}"),
+            "Annotation storage failed",
         )
-            .into_response(),
-    }
+    })?;
+    let stored = store
+        .import_annotations(&rows)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation import", e))?;
+    report.imported += stored;
+    report.conflicts += rows.len() - stored;
+    Ok(audited(Json(report).into_response(), vec![event]))
 }
 
-async fn handle_git_commit_diff(
+/// `POST /_/{workspace_id}/annotations?path=…` — create an annotation. A
+/// missing `id` is generated; an id already in use is a conflict.
+async fn handle_annotation_create(
     State(state): State<AppState>,
-    AxumPath((workspace_id, commit)): AxumPath<(String, String)>,
-    Query(query): Query<GitViewQuery>,
+    AxumPath(workspace_id): AxumPath<String>,
     role: Option<Extension<AccessRole>>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
+    Query(query): Query<AnnotationPathQuery>,
+    Json(mut annotation): Json<serde_json::Value>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let file_path = annotation_file_key(&entry, &query.path)?;
+    validate_annotation(&annotation).map_err(|m| AnnotationApiError(StatusCode::BAD_REQUEST, m))?;
+    require_display_name(&state, annotation.get("author"))?;
+    crate::annotations::render_note(&mut annotation);
+    let Some(object) = annotation.as_object_mut() else {
+        return Err(AnnotationApiError(
+            StatusCode::BAD_REQUEST,
+            "Annotation must be a JSON object",
+        ));
     };
-    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
-    let initial_view = diff_view_from_query(query.view.as_deref());
-    let root = directory_root_or_not_found!(ws).to_path_buf();
-    let diff = tokio::task::spawn_blocking(move || git::commit_diff(&root, &commit))
-        .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git commit diff blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match diff {
-        Ok(diff) => {
-            render_git_diff_page(&state, &workspace_id, &ws, can_manage, &diff, initial_view)
+    let id = match object.get("id") {
+        None => {
+            let id = format!("anno-{}", generate_token());
+            object.insert("id".into(), serde_json::Value::String(id.clone()));
+            id
         }
-        Err(git::GitError::InvalidRevision) => {
-            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+        Some(serde_json::Value::String(id)) if valid_annotation_id(id) => id.clone(),
+        Some(_) => {
+            return Err(AnnotationApiError(
+                StatusCode::BAD_REQUEST,
+                "Invalid annotation id",
+            ))
         }
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
+    };
+    object.insert("revision".into(), 1.into());
+
+    let inserted = store
+        .create_annotation(&file_path, &id, &annotation)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation create", e))?;
+    if !inserted {
+        return Err(AnnotationApiError(
+            StatusCode::CONFLICT,
+            "Annotation id already exists",
+        ));
     }
+    broadcast_annotation_change(
+        &entry,
+        &file_path,
+        WebSocketMessage::NewAnnotation {
+            annotation: annotation.clone(),
+            op_id: None,
+        },
+    );
+    let event = crate::audit::Event::new(crate::audit::Action::Create, file_path)
+        .annotation(annotation["id"].as_str().unwrap_or_default())
+        .by(annotation.get("author"));
+    Ok(audited(
+        (StatusCode::CREATED, Json(annotation)).into_response(),
+        vec![event],
+    ))
 }
 
-async fn handle_git_commit_diff_data(
+/// `PUT /_/{workspace_id}/annotations/{annotation_id}` — replace an existing
+/// annotation's data. The annotation stays on its file; the body's `id` is
+/// forced to the one in the URL. The body carries the `revision` it was read
+/// at; a write from an older revision is a conflict.
+async fn handle_annotation_update(
     State(state): State<AppState>,
-    AxumPath((workspace_id, commit)): AxumPath<(String, String)>,
-    Query(query): Query<GitViewQuery>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
+    AxumPath((workspace_id, annotation_id)): AxumPath<(String, String)>,
+    role: Option<Extension<AccessRole>>,
+    Json(mut annotation): Json<serde_json::Value>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    validate_annotation(&annotation).map_err(|m| AnnotationApiError(StatusCode::BAD_REQUEST, m))?;
+    require_display_name(&state, annotation.get("author"))?;
+    crate::annotations::render_note(&mut annotation);
+    let Some(object) = annotation.as_object_mut() else {
+        return Err(AnnotationApiError(
+            StatusCode::BAD_REQUEST,
+            "Annotation must be a JSON object",
+        ));
     };
-    let root = directory_root_or_not_found!(ws).to_path_buf();
-    let diff = tokio::task::spawn_blocking(move || git::commit_diff(&root, &commit))
+    object.insert(
+        "id".into(),
+        serde_json::Value::String(annotation_id.clone()),
+    );
+
+    let file_path = annotation_owner(store.as_ref(), &entry, &annotation_id).await?;
+    let saved = store
+        .save_annotation(&file_path, &annotation_id, annotation)
         .await
-        .unwrap_or_else(|e| {
-            tracing::error!("git commit diff blocking task join error: {e}");
-            Err(git::GitError::Command("internal task error".into()))
-        });
-    match diff {
-        Ok(diff) => git_diff_json_response(&diff, query.f.as_deref()),
-        Err(git::GitError::InvalidRevision) => {
-            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+        .map_err(|e| annotation_storage_failed("annotation update", e))?;
+    let annotation = match saved {
+        AnnotationSave::Saved(annotation) => annotation,
+        AnnotationSave::Stale(_) => {
+            return Err(AnnotationApiError(
+                StatusCode::CONFLICT,
+                "Annotation was changed since it was read",
+            ))
         }
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
-    }
+        // Moved to another file since `annotation_owner` looked it up.
+        AnnotationSave::Foreign => {
+            return Err(AnnotationApiError(
+                StatusCode::NOT_FOUND,
+                "Annotation not found",
+            ))
+        }
+    };
+    broadcast_annotation_change(
+        &entry,
+        &file_path,
+        WebSocketMessage::NewAnnotation {
+            annotation: annotation.clone(),
+            op_id: None,
+        },
+    );
+    let event = crate::audit::Event::new(crate::audit::Action::Edit, file_path)
+        .annotation(annotation["id"].as_str().unwrap_or_default());
+    Ok(audited(Json(annotation).into_response(), vec![event]))
 }
 
 #[derive(Deserialize)]
-struct GitViewQuery {
-    view: Option<String>,
-    f: Option<String>,
+#[serde(deny_unknown_fields)]
+struct AnnotationResolution {
+    resolved: bool,
+    /// Resolver identity, shaped like `author`.
+    #[serde(default)]
+    by: Option<serde_json::Value>,
 }
 
-#[derive(Deserialize)]
-struct PrettyCompareQuery {
-    view: Option<String>,
-    format: Option<String>,
-    f: Option<String>,
+/// `PUT /_/{workspace_id}/annotations/{annotation_id}/resolution` — resolve
+/// or reopen an annotation; returns the updated annotation.
+async fn handle_annotation_resolution(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, annotation_id)): AxumPath<(String, String)>,
+    role: Option<Extension<AccessRole>>,
+    Json(resolution): Json<AnnotationResolution>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    if let Some(by) = &resolution.by {
+        crate::annotations::validate_resolver(by)
+            .map_err(|m| AnnotationApiError(StatusCode::BAD_REQUEST, m))?;
+    }
+    require_display_name(&state, resolution.by.as_ref())?;
+    let action = if resolution.resolved {
+        crate::audit::Action::Resolve
+    } else {
+        crate::audit::Action::Reopen
+    };
+    let by = resolution.by.clone();
+    let file_path = annotation_owner(store.as_ref(), &entry, &annotation_id).await?;
+    let annotation = store
+        .set_resolved(
+            &file_path,
+            &annotation_id,
+            resolution.resolved,
+            resolution.by,
+        )
+        .await
+        .map_err(|e| annotation_storage_failed("annotation resolution", e))?
+        .ok_or(AnnotationApiError(
+            StatusCode::NOT_FOUND,
+            "Annotation not found",
+        ))?;
+    broadcast_annotation_change(
+        &entry,
+        &file_path,
+        WebSocketMessage::NewAnnotation {
+            annotation: annotation.clone(),
+            op_id: None,
+        },
+    );
+    let event = crate::audit::Event::new(action, file_path)
+        .annotation(annotation["id"].as_str().unwrap_or_default())
+        .by(by.as_ref());
+    Ok(audited(Json(annotation).into_response(), vec![event]))
 }
 
 #[derive(Deserialize)]
-struct GitCompareOptionsStatusQuery {
-    base: String,
-    compare: String,
+struct AnnotationSummaryQuery {
+    /// Limit the summary to one file; the whole workspace otherwise.
+    #[serde(default)]
+    path: Option<String>,
 }
 
-fn diff_view_from_query(view: Option<&str>) -> &'static str {
-    // Rendered is the default; only an explicit ?view=raw selects the source view.
-    match view {
-        Some("raw") => "raw",
-        _ => "rendered",
-    }
+#[derive(Serialize)]
+struct AnnotationFileSummary {
+    /// Workspace route of the file.
+    path: String,
+    #[serde(flatten)]
+    counts: crate::annotations::ResolutionCounts,
 }
 
-fn git_not_repository_response() -> Response {
-    (StatusCode::CONFLICT, "Workspace is not a git repository").into_response()
+#[derive(Serialize)]
+struct AnnotationSummary {
+    files: Vec<AnnotationFileSummary>,
+    open: u64,
+    resolved: u64,
 }
 
-async fn handle_pretty_compare_diff(
+/// `GET /_/{workspace_id}/annotations/summary` — open vs resolved annotation
+/// counts per file, with workspace totals.
+async fn handle_annotations_summary(
     State(state): State<AppState>,
-    AxumPath((workspace_id, range)): AxumPath<(String, String)>,
-    Query(query): Query<PrettyCompareQuery>,
+    AxumPath(workspace_id): AxumPath<String>,
     role: Option<Extension<AccessRole>>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let Some((base, compare)) = parse_pretty_compare_range(&range) else {
-        return (StatusCode::BAD_REQUEST, "Invalid compare range").into_response();
-    };
-    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
-    let initial_view = diff_view_from_query(query.view.as_deref());
-    if query.format.as_deref() == Some("data") && initial_view == "rendered" {
-        directory_root_or_not_found!(ws);
-        return match markdown_compare_diff_data_async(
-            &state,
-            &workspace_id,
-            ws.fs.clone(),
-            &base,
-            &compare,
-            query.f.as_deref(),
-        )
+    Query(query): Query<AnnotationSummaryQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let only = query
+        .path
+        .as_deref()
+        .map(|path| annotation_file_key(&entry, path))
+        .transpose()?;
+    let rows = store
+        .all_annotations()
         .await
-        {
-            Ok(data) => Json(data).into_response(),
-            Err(git::GitError::NotRepository) => git_not_repository_response(),
-            Err(git::GitError::InvalidRevision) => {
-                (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
-            }
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Failed to read markdown diff: {e}"),
-            )
-                .into_response(),
-        };
-    }
-    directory_root_or_not_found!(ws);
-    match git::compare_diff(&ws.fs, &base, &compare) {
-        Ok(diff) if query.format.as_deref() == Some("data") => {
-            git_diff_json_response(&diff, query.f.as_deref())
-        }
-        Ok(diff) => {
-            render_git_diff_page(&state, &workspace_id, &ws, can_manage, &diff, initial_view)
-        }
-        Err(git::GitError::InvalidRevision) => {
-            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
-        }
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
-    }
+        .map_err(|e| annotation_storage_failed("annotation summary", e))?;
+    let files: Vec<AnnotationFileSummary> = crate::annotations::resolution_counts(&rows)
+        .into_iter()
+        .filter(|(file_path, _)| only.as_deref().is_none_or(|only| only == file_path))
+        .filter_map(|(file_path, counts)| {
+            let path = entry.fs.route_for_path(FsPath::new(&file_path))?;
+            Some(AnnotationFileSummary { path, counts })
+        })
+        .collect();
+
+    Ok(Json(AnnotationSummary {
+        open: files.iter().map(|file| file.counts.open).sum(),
+        resolved: files.iter().map(|file| file.counts.resolved).sum(),
+        files,
+    })
+    .into_response())
 }
 
-async fn handle_git_compare_options_status(
+#[derive(Serialize)]
+struct FileProgress {
+    /// Workspace route of the file.
+    path: String,
+    #[serde(flatten)]
+    progress: SectionProgress,
+}
+
+#[derive(Serialize)]
+struct ProgressSummary {
+    files: Vec<FileProgress>,
+    /// Sections viewed and in total across `files`.
+    viewed: usize,
+    total: usize,
+}
+
+/// `GET /_/{workspace_id}/progress` — per-file reading progress (sections
+/// marked viewed out of all sections), for the files someone has started
+/// reading. `path` limits it to one file.
+async fn handle_progress(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-    Query(query): Query<GitCompareOptionsStatusQuery>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let root = directory_root_or_not_found!(ws);
-    if !git::status(root).available {
-        return git_not_repository_response();
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<AnnotationSummaryQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    if !entry.flags().enable_viewed {
+        return Err(AnnotationApiError(
+            StatusCode::NOT_FOUND,
+            "Viewed tracking is disabled in this workspace",
+        ));
     }
-    // Each side independently probes ~dozens of candidate refs for markdown
-    // changes (one git diff each), so run the two sides concurrently; each side
-    // also parallelizes its own probes internally.
-    let (base, compare) = rayon::join(
-        || {
-            git_compare_option_statuses(git_compare_options(
-                root,
-                &query.base,
-                false,
-                &query.compare,
-                GitCompareOptionRole::Base,
-                GitCompareOptionStatusMode::Checked,
-            ))
-        },
-        || {
-            git_compare_option_statuses(git_compare_options(
-                root,
-                &query.compare,
-                true,
-                &query.base,
-                GitCompareOptionRole::Compare,
-                GitCompareOptionStatusMode::Checked,
-            ))
+    let only = query
+        .path
+        .as_deref()
+        .map(|path| annotation_file_key(&entry, path))
+        .transpose()?;
+    let storage_failed = |e: &dyn std::fmt::Display| {
+        tracing::error!("reading progress failed: {e}");
+        AnnotationApiError(StatusCode::INTERNAL_SERVER_ERROR, "Viewed storage failed")
+    };
+    let states = store
+        .viewed_states()
+        .await
+        .map_err(|e| storage_failed(&e))?;
+
+    let files = tokio::task::spawn_blocking(move || {
+        let root = canonical_workspace_root(&entry);
+        states
+            .into_iter()
+            .filter(|record| only.as_deref().is_none_or(|only| only == record.file_path))
+            .filter_map(|record| {
+                let file_path = record.file_path;
+                let path = entry.fs.route_for_path(FsPath::new(&file_path))?;
+                let viewed = serde_json::from_value(record.state).ok()?;
+                let progress =
+                    document_progress(&file_path, &viewed, &workspace_id, &entry, &root, &state)?;
+                Some(FileProgress { path, progress })
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| storage_failed(&e))?;
+
+    Ok(Json(ProgressSummary {
+        viewed: files.iter().map(|file| file.progress.viewed).sum(),
+        total: files.iter().map(|file| file.progress.total).sum(),
+        files,
+    })
+    .into_response())
+}
+
+/// `DELETE /_/{workspace_id}/annotations/{annotation_id}`.
+async fn handle_annotation_delete(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, annotation_id)): AxumPath<(String, String)>,
+    role: Option<Extension<AccessRole>>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let file_path = annotation_owner(store.as_ref(), &entry, &annotation_id).await?;
+    store
+        .delete_annotations(&file_path, Some(&annotation_id))
+        .await
+        .map_err(|e| annotation_storage_failed("annotation delete", e))?;
+    let event = crate::audit::Event::new(crate::audit::Action::Delete, &file_path)
+        .annotation(&annotation_id);
+    broadcast_annotation_change(
+        &entry,
+        &file_path,
+        WebSocketMessage::DeleteAnnotation {
+            id: annotation_id,
+            op_id: None,
         },
     );
-    Json(GitCompareOptionsStatus { base, compare }).into_response()
+    Ok(audited(StatusCode::NO_CONTENT.into_response(), vec![event]))
 }
 
-fn parse_pretty_compare_range(range: &str) -> Option<(String, String)> {
-    let (base, compare) = range.split_once("...")?;
-    if base.trim().is_empty() || compare.trim().is_empty() {
-        return None;
-    }
-    Some((base.to_string(), compare.to_string()))
+/// `GET /_/{workspace_id}/annotations/history?path=…` — annotations deleted
+/// from one file that are still restorable, newest first.
+async fn handle_annotations_history(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<DocumentStateQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let file_path = annotation_file_key(&entry, &query.path)?;
+    let history = store
+        .history(&file_path)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation history", e))?;
+    Ok(Json(history).into_response())
 }
 
-#[derive(Deserialize)]
-struct GitCommitRequest {
-    message: String,
+/// `POST /_/{workspace_id}/annotations/undo?path=…` — restore the most
+/// recent delete or clear on one file. Responds with the restored
+/// annotations.
+async fn handle_annotations_undo(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<DocumentStateQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let file_path = annotation_file_key(&entry, &query.path)?;
+    let restored = store
+        .undo(&file_path)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation undo", e))?;
+    let mut trail = Vec::new();
+    for annotation in &restored {
+        broadcast_annotation_change(
+            &entry,
+            &file_path,
+            WebSocketMessage::NewAnnotation {
+                annotation: annotation.clone(),
+                op_id: None,
+            },
+        );
+        trail.push(
+            crate::audit::Event::new(crate::audit::Action::Restore, &file_path)
+                .annotation(annotation["id"].as_str().unwrap_or_default()),
+        );
+    }
+    Ok(audited(Json(restored).into_response(), trail))
 }
 
 #[derive(Deserialize)]
-struct GitCheckoutRequest {
-    branch: String,
+struct AnnotationPruneQuery {
+    /// Keep pruned annotations restorable in history instead of deleting
+    /// them outright.
+    #[serde(default)]
+    archive: bool,
 }
 
 #[derive(Serialize)]
-struct GitCommitResponse {
-    success: bool,
-    message: String,
-    commit: Option<git::GitCommitResult>,
+struct PrunedFile {
+    /// Path of the deleted file, relative to the workspace root.
+    path: String,
+    annotations: usize,
+    viewed: bool,
 }
 
 #[derive(Serialize)]
-struct GitCheckoutResponse {
-    success: bool,
-    message: String,
-    status: Option<git::GitStatus>,
+struct AnnotationPruneReport {
+    files: Vec<PrunedFile>,
+    annotations: usize,
 }
 
-async fn handle_git_commit(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<GitCommitRequest>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    match git::commit_workspace(directory_root_or_not_found!(ws), &payload.message) {
-        Ok(commit) => Json(GitCommitResponse {
-            success: true,
-            message: "Committed workspace changes".to_string(),
-            commit: Some(commit),
-        })
-        .into_response(),
-        Err(git::GitError::NothingToCommit) => Json(GitCommitResponse {
-            success: false,
-            message: "Nothing to commit".to_string(),
-            commit: None,
-        })
-        .into_response(),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to commit workspace changes: {e}"),
-        )
-            .into_response(),
+impl AnnotationPruneReport {
+    fn new(root: &FsPath, files: Vec<crate::annotations::MissingFile>) -> Self {
+        let files: Vec<PrunedFile> = files
+            .into_iter()
+            .map(|file| PrunedFile {
+                path: FsPath::new(&file.file_path)
+                    .strip_prefix(root)
+                    .map(|rel| rel.to_string_lossy().replace('\\', "/"))
+                    .unwrap_or(file.file_path),
+                annotations: file.annotations,
+                viewed: file.viewed,
+            })
+            .collect();
+        Self {
+            annotations: files.iter().map(|file| file.annotations).sum(),
+            files,
+        }
     }
 }
 
-async fn handle_git_checkout(
+/// The directory whose deleted files a prune covers.
+fn prune_root(entry: &WorkspaceEntry) -> Result<PathBuf, AnnotationApiError> {
+    entry
+        .fs
+        .directory_root()
+        .filter(|root| root.is_dir())
+        .map(FsPath::to_path_buf)
+        .ok_or(AnnotationApiError(
+            StatusCode::BAD_REQUEST,
+            "Pruning needs a directory workspace",
+        ))
+}
+
+/// `GET /_/{workspace_id}/annotations/prune` — files under the workspace
+/// that were deleted but still have stored annotations or viewed state.
+async fn handle_annotations_prune_report(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<GitCheckoutRequest>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    match git::checkout_branch(directory_root_or_not_found!(ws), &payload.branch) {
-        Ok(status) => Json(GitCheckoutResponse {
-            success: true,
-            message: "Switched branch".to_string(),
-            status: Some(status),
-        })
-        .into_response(),
-        Err(git::GitError::InvalidRevision) => Json(GitCheckoutResponse {
-            success: false,
-            message: "Invalid branch".to_string(),
-            status: None,
-        })
-        .into_response(),
-        Err(git::GitError::NotRepository) => git_not_repository_response(),
-        Err(e) => Json(GitCheckoutResponse {
-            success: false,
-            message: format!("Failed to switch branch: {e}"),
-            status: None,
-        })
-        .into_response(),
-    }
+    role: Option<Extension<AccessRole>>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let root = prune_root(&entry)?;
+    let missing = store
+        .missing_files(&root)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation prune scan", e))?;
+    Ok(Json(AnnotationPruneReport::new(&root, missing)).into_response())
 }
 
-#[derive(Serialize)]
-struct WorkspaceFileListEntry {
-    path: String,
-    name: String,
-    is_markdown: bool,
-    url: String,
+/// `POST /_/{workspace_id}/annotations/prune?archive=…` — remove the stored
+/// data of deleted files under the workspace. Responds with what was
+/// removed; archived annotations stay in history.
+async fn handle_annotations_prune(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<AnnotationPruneQuery>,
+) -> Result<Response, AnnotationApiError> {
+    let (entry, store) = annotation_api_access(&state, &workspace_id, role)?;
+    let root = prune_root(&entry)?;
+    let missing = store
+        .missing_files(&root)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation prune scan", e))?;
+    let pruned = store
+        .prune_missing_files(missing, query.archive)
+        .await
+        .map_err(|e| annotation_storage_failed("annotation prune", e))?;
+    let trail = pruned
+        .iter()
+        .map(|file| crate::audit::Event::new(crate::audit::Action::Prune, &file.file_path))
+        .collect();
+    Ok(audited(
+        Json(AnnotationPruneReport::new(&root, pruned)).into_response(),
+        trail,
+    ))
 }
 
-async fn handle_workspace_files_data(
+/// `POST /_/{workspace_id}/annotations/attachments` — store an image body
+/// (a pasted screenshot) and return its id for an annotation's
+/// `attachments` list.
+async fn handle_annotation_attachment_upload(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let mut files = Vec::new();
-    for (rel, path) in ws.fs.served_files(2000) {
-        let route = rel.as_route();
-        files.push(WorkspaceFileListEntry {
-            name: path
-                .file_name()
-                .map(|name| name.to_string_lossy().to_string())
-                .unwrap_or_else(|| route.clone()),
-            is_markdown: is_markdown_path(&path),
-            url: workspace_file_url(&workspace_id, &route),
-            path: route,
-        });
+    role: Option<Extension<AccessRole>>,
+    body: axum::body::Bytes,
+) -> Result<Response, AnnotationApiError> {
+    annotation_api_access(&state, &workspace_id, role)?;
+    let dir = state.attachments_dir.clone().ok_or(AnnotationApiError(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Annotation storage is unavailable",
+    ))?;
+    let stored = tokio::task::spawn_blocking(move || crate::attachments::store(&dir, &body))
+        .await
+        .map_err(|e| {
+            tracing::error!("attachment upload worker failed: {e}");
+            AnnotationApiError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Attachment storage failed",
+            )
+        })?;
+    match stored {
+        Ok(id) => Ok((StatusCode::CREATED, Json(serde_json::json!({ "id": id }))).into_response()),
+        Err(crate::attachments::AttachmentError::TooLarge) => Err(AnnotationApiError(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "Attachment is larger than 5 MiB",
+        )),
+        Err(crate::attachments::AttachmentError::UnsupportedType) => Err(AnnotationApiError(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Attachment must be a PNG, JPEG, GIF, or WebP image",
+        )),
+        Err(crate::attachments::AttachmentError::Io(e)) => {
+            tracing::error!("attachment upload failed: {e}");
+            Err(AnnotationApiError(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Attachment storage failed",
+            ))
+        }
     }
-    files.sort_by(|a, b| a.path.cmp(&b.path));
-    Json(files).into_response()
-}
-
-#[derive(Deserialize)]
-struct CreateFileRequest {
-    path: String,
-    content: Option<String>,
 }
 
-#[derive(Serialize)]
-struct CreateFileResponse {
-    success: bool,
-    message: String,
-    url: Option<String>,
+/// `GET /_/{workspace_id}/annotations/attachments/{attachment_id}` — serve a
+/// stored image. Ids are content hashes, so the response never changes.
+async fn handle_annotation_attachment(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, attachment_id)): AxumPath<(String, String)>,
+    role: Option<Extension<AccessRole>>,
+) -> Result<Response, AnnotationApiError> {
+    annotation_api_access(&state, &workspace_id, role)?;
+    let not_found = || AnnotationApiError(StatusCode::NOT_FOUND, "Attachment not found");
+    let dir = state.attachments_dir.clone().ok_or_else(not_found)?;
+    let content_type = crate::attachments::content_type(&attachment_id).ok_or_else(not_found)?;
+    let bytes = tokio::task::spawn_blocking(move || crate::attachments::load(&dir, &attachment_id))
+        .await
+        .ok()
+        .flatten()
+        .ok_or_else(not_found)?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CACHE_CONTROL,
+                "private, max-age=31536000, immutable",
+            ),
+        ],
+        bytes,
+    )
+        .into_response())
 }
 
-async fn handle_workspace_create_file(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<CreateFileRequest>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let Some(root) = ws.fs.directory_root() else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Edit feature is not enabled".to_string(),
-            url: None,
-        })
-        .into_response();
-    }
-    let Some(rel) = sanitize_new_file_path(&payload.path) else {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Invalid file path".to_string(),
-            url: None,
-        })
-        .into_response();
-    };
-    let full_path = root.join(&rel);
-    if fs::symlink_metadata(&full_path).is_ok() {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "File already exists".to_string(),
-            url: None,
-        })
-        .into_response();
-    }
-    // Verify containment BEFORE creating any directory, so a symlinked
-    // intermediate cannot cause `create_dir_all` to materialize dirs outside
-    // the workspace root.
-    if !deepest_existing_ancestor_inside_workspace(&full_path, root) {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Access denied".to_string(),
-            url: None,
-        })
-        .into_response();
-    }
-    if let Some(parent) = full_path.parent() {
-        if let Err(e) = fs::create_dir_all(parent) {
-            return Json(CreateFileResponse {
-                success: false,
-                message: format!("Failed to create directory: {e}"),
-                url: None,
-            })
-            .into_response();
-        }
-    }
-    // Defense in depth: confirm the resolved parent still lands inside the
-    // workspace after creation.
-    if let Some(parent) = full_path.parent() {
-        match canonicalize_route_path(parent) {
-            Ok(parent) if is_inside_workspace(&parent, root) => {}
-            _ => {
-                return Json(CreateFileResponse {
-                    success: false,
-                    message: "Access denied".to_string(),
-                    url: None,
-                })
-                .into_response()
-            }
-        }
+#[cfg(debug_assertions)]
+async fn dev_reload_stream(State(state): State<AppState>) -> impl IntoResponse {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use std::convert::Infallible;
+    let rx = state.dev_reload_tx.subscribe();
+    let stream = tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(|item| async move {
+        // Drop lagged frames silently; we only need *some* recent reload.
+        item.ok()
+            .map(|()| Ok::<Event, Infallible>(Event::default().event("reload")))
+    });
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+#[cfg(debug_assertions)]
+async fn dev_reload_trigger(State(state): State<AppState>) -> impl IntoResponse {
+    // send() errors only when there are no subscribers; that's fine — esbuild
+    // can fire before any webview connects, we just no-op.
+    let _ = state.dev_reload_tx.send(());
+    StatusCode::NO_CONTENT
+}
+
+async fn load_annotations(
+    store: &dyn crate::store::DocumentStore,
+    file_path: &str,
+) -> Vec<serde_json::Value> {
+    store.annotations(file_path).await.unwrap_or_else(|e| {
+        tracing::error!(file_path = %file_path, "load_annotations failed: {e}");
+        Vec::new()
+    })
+}
+
+/// The stored viewed state of `file_path`, empty when there is none.
+async fn load_viewed_state(
+    store: &dyn crate::store::DocumentStore,
+    file_path: &str,
+) -> crate::store::ViewedState {
+    match store.viewed_state(file_path).await {
+        Ok(Some(viewed)) => return viewed,
+        Ok(None) => {}
+        Err(e) => tracing::error!(file_path = %file_path, "load_viewed_state failed: {e}"),
     }
-    let content = payload.content.unwrap_or_default();
-    let write_result = std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&full_path)
-        .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()));
-    if let Err(e) = write_result {
-        return Json(CreateFileResponse {
-            success: false,
-            message: format!("Failed to create file: {e}"),
-            url: None,
-        })
-        .into_response();
+    crate::store::ViewedState {
+        state: serde_json::json!({}),
+        by: None,
+        sections: crate::viewed::Sections::default(),
     }
-    let route = path_to_route(&rel);
-    Json(CreateFileResponse {
-        success: true,
-        message: "File created".to_string(),
-        url: Some(workspace_file_url(&workspace_id, &route)),
-    })
-    .into_response()
 }
 
-/// Create an empty folder inside the workspace. Reuses {@link CreateFileRequest}
-/// (the `content` field is ignored). Same edit gate + traversal-safety as
-/// file creation; `create_dir_all` so intermediate folders are made too.
-async fn handle_workspace_create_folder(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<CreateFileRequest>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let Some(root) = ws.fs.directory_root() else {
-        return StatusCode::NOT_FOUND.into_response();
+async fn send_json(
+    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
+    msg: &WebSocketMessage,
+) -> Result<(), ()> {
+    let Ok(encoded) = serde_json::to_string(msg) else {
+        return Err(());
     };
-    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Edit feature is not enabled".to_string(),
-            url: None,
-        })
-        .into_response();
+    sender
+        .send(Message::Text(encoded.into()))
+        .await
+        .map_err(|_| ())
+}
+
+async fn send_initial_document_state(
+    sender: &mut futures_util::stream::SplitSink<WebSocket, Message>,
+    store: &dyn crate::store::DocumentStore,
+    file_path: String,
+    filter: &AnnotationFilter,
+) -> Result<(), ()> {
+    let mut annotations = load_annotations(store, &file_path).await;
+    annotations.retain(|annotation| filter.matches(annotation));
+    tracing::debug!(
+        file_path = %file_path,
+        count = annotations.len(),
+        "sending initial annotations to client",
+    );
+    send_json(sender, &WebSocketMessage::AllAnnotations { annotations }).await?;
+    let viewed = load_viewed_state(store, &file_path).await;
+    send_json(
+        sender,
+        &WebSocketMessage::ViewedState {
+            state: viewed.state,
+            by: viewed.by,
+            changed: viewed.sections.changed.into_iter().collect(),
+            op_id: None,
+        },
+    )
+    .await
+}
+
+fn broadcast_msg(tx: &broadcast::Sender<WorkspaceEvent>, channel: &str, msg: &WebSocketMessage) {
+    if let Ok(encoded) = serde_json::to_string(msg) {
+        let _ = tx.send(WorkspaceEvent::Channel {
+            channel: channel.to_string(),
+            payload: encoded,
+        });
     }
-    let Some(rel) = sanitize_new_file_path(&payload.path) else {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Invalid folder path".to_string(),
-            url: None,
-        })
-        .into_response();
-    };
-    let full_path = root.join(&rel);
-    if fs::symlink_metadata(&full_path).is_ok() {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Folder already exists".to_string(),
-            url: None,
-        })
-        .into_response();
+}
+
+fn workspace_event_payload(event: WorkspaceEvent, channel: &str) -> Option<String> {
+    match event {
+        WorkspaceEvent::Workspace { payload } => Some(payload),
+        WorkspaceEvent::Channel {
+            channel: event_channel,
+            payload,
+        } if event_channel == channel => Some(payload),
+        WorkspaceEvent::Channel { .. } => None,
     }
-    // Verify containment BEFORE creating any directory, so a symlinked
-    // intermediate cannot cause `create_dir_all` to materialize dirs outside
-    // the workspace root.
-    if !deepest_existing_ancestor_inside_workspace(&full_path, root) {
-        return Json(CreateFileResponse {
-            success: false,
-            message: "Access denied".to_string(),
-            url: None,
-        })
-        .into_response();
+}
+
+/// Check an inbound frame before it is relayed. Clients may only send
+/// `live_action` and `selection`; everything else has an HTTP endpoint.
+fn parse_client_msg(text: &str) -> Result<WebSocketMessage, WsErrorReason> {
+    if text.len() > MAX_WS_MSG_BYTES {
+        return Err(WsErrorReason::TooLarge);
     }
-    if let Err(e) = fs::create_dir_all(&full_path) {
-        return Json(CreateFileResponse {
-            success: false,
-            message: format!("Failed to create folder: {e}"),
-            url: None,
-        })
-        .into_response();
+    let value: serde_json::Value =
+        serde_json::from_str(text).map_err(|_| WsErrorReason::Malformed)?;
+    match value.get("type").and_then(serde_json::Value::as_str) {
+        Some("live_action") => {}
+        Some("selection") => return parse_selection(text, value),
+        Some(_) => return Err(WsErrorReason::Unsupported),
+        None => return Err(WsErrorReason::Malformed),
     }
-    // Defense in depth: confirm the created folder resolved inside the workspace.
-    match canonicalize_route_path(&full_path) {
-        Ok(p) if is_inside_workspace(&p, root) => {}
-        _ => {
-            return Json(CreateFileResponse {
-                success: false,
-                message: "Access denied".to_string(),
-                url: None,
-            })
-            .into_response()
-        }
+    if text.len() > MAX_LIVE_ACTION_BYTES {
+        return Err(WsErrorReason::TooLarge);
     }
-    Json(CreateFileResponse {
-        success: true,
-        message: "Folder created".to_string(),
-        url: None,
-    })
-    .into_response()
+    let valid_action = value
+        .get("data")
+        .and_then(|data| data.get("action"))
+        .and_then(serde_json::Value::as_str)
+        .is_some_and(|action| !action.is_empty() && action.len() <= MAX_LIVE_ACTION_NAME);
+    if !valid_action {
+        return Err(WsErrorReason::InvalidLiveAction);
+    }
+    serde_json::from_value(value).map_err(|_| WsErrorReason::InvalidLiveAction)
 }
 
-#[derive(Deserialize)]
-struct DeleteFileRequest {
-    path: String,
+/// Only `range` is taken from the client; who sent it is filled in later.
+fn parse_selection(
+    text: &str,
+    value: serde_json::Value,
+) -> Result<WebSocketMessage, WsErrorReason> {
+    if text.len() > MAX_SELECTION_BYTES {
+        return Err(WsErrorReason::TooLarge);
+    }
+    let range = match value.get("range") {
+        None | Some(serde_json::Value::Null) => None,
+        Some(range) => {
+            Some(SelectionRange::deserialize(range).map_err(|_| WsErrorReason::InvalidSelection)?)
+        }
+    };
+    Ok(WebSocketMessage::Selection {
+        client_id: String::new(),
+        identity: None,
+        range,
+    })
 }
 
-#[derive(Serialize)]
-struct DeleteFileResponse {
-    success: bool,
-    message: String,
+fn handle_client_msg(entry: &WorkspaceEntry, session: &WsSession, msg: WebSocketMessage) {
+    // Browser persistence always goes through the document-state HTTP endpoint
+    // before any shared broadcast. WebSocket input is deliberately Live-only;
+    // annotation/viewed variants remain deserializable as outbound protocol
+    // messages but cannot form a second database mutation path.
+    if let WebSocketMessage::LiveAction { data } = msg {
+        if entry.enable_live.load(std::sync::atomic::Ordering::Relaxed) {
+            broadcast_msg(
+                &entry.events_tx,
+                &session.channel,
+                &WebSocketMessage::LiveAction { data },
+            );
+        }
+    }
 }
 
-async fn handle_workspace_delete_file(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<DeleteFileRequest>,
-) -> impl IntoResponse {
-    let fail = |message: &str| {
-        Json(DeleteFileResponse {
-            success: false,
-            message: message.to_string(),
-        })
-        .into_response()
-    };
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
+async fn handle_socket(socket: WebSocket, state: AppState, entry: Arc<WorkspaceEntry>) {
+    let (mut sender, mut receiver) = socket.split();
+    let store = state.document_store();
+    let mut rx = entry.events_tx.subscribe();
+    let mut config_rx = entry.config_tx.subscribe();
+
+    let hello = match tokio::time::timeout(std::time::Duration::from_secs(5), receiver.next()).await
+    {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<WsHello>(&text).ok(),
+        Err(_) => {
+            tracing::warn!(workspace_id = %entry.id, "timed out waiting for websocket hello");
+            return;
+        }
+        _ => {
+            tracing::warn!(workspace_id = %entry.id, "missing or invalid websocket hello");
+            return;
+        }
     };
-    let Some(root) = ws.fs.directory_root() else {
-        return StatusCode::NOT_FOUND.into_response();
+    let Some((session, filter, identity)) = hello.and_then(|hello| {
+        let filter = hello.filter.normalized().ok()?;
+        // A malformed identity only costs the viewer their name and colour.
+        let identity = hello
+            .identity
+            .filter(|identity| crate::annotations::validate_resolver(identity).is_ok());
+        Some((
+            authorize_ws_target(&state.base_path, &entry, hello.target)?,
+            filter,
+            identity,
+        ))
+    }) else {
+        tracing::warn!(workspace_id = %entry.id, "rejecting unauthorized websocket target");
+        return;
     };
-    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
-        return fail("Edit feature is not enabled");
-    }
-    let rel = payload.path.trim().trim_start_matches('/');
-    if rel.is_empty() || rel.contains('\0') {
-        return fail("Invalid file path");
+    let session = Arc::new(session);
+
+    // A Live-only connection receives no stored annotation/viewed data. Surface
+    // sessions never receive it, even when shared annotations are enabled.
+    if entry
+        .shared_annotation
+        .load(std::sync::atomic::Ordering::Relaxed)
+    {
+        if let WsSessionTarget::Document { file_path } = &session.target {
+            let Some(store) = store.as_deref() else {
+                return;
+            };
+            tokio::select! {
+                biased;
+                _ = config_rx.recv() => return,
+                result = send_initial_document_state(&mut sender, store, file_path.clone(), &filter) => {
+                    if result.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
     }
-    let canon = match canonicalize_route_path(&root.join(rel)) {
-        Ok(p) if is_inside_workspace(&p, root) => p,
-        _ => return fail("Access denied"),
+
+    // Join presence last, once the session is set up, so a connection that
+    // failed above never shows up as a viewer.
+    let presence = match &session.target {
+        WsSessionTarget::Document { file_path } => {
+            let viewer = crate::presence::Viewer {
+                client_id: generate_token(),
+                identity,
+            };
+            let viewers = state.presence.join(&entry.id, file_path, viewer.clone());
+            let snapshot = WebSocketMessage::Presence {
+                client_id: viewer.client_id.clone(),
+                viewers: viewers.clone(),
+            };
+            broadcast_msg(
+                &entry.events_tx,
+                &session.channel,
+                &WebSocketMessage::PresenceJoin {
+                    viewer: viewer.clone(),
+                    viewers: viewers.len(),
+                },
+            );
+            if send_json(&mut sender, &snapshot).await.is_err() {
+                leave_presence(&state, &entry, &session, file_path, &viewer.client_id);
+                return;
+            }
+            Some((file_path.clone(), viewer))
+        }
+        WsSessionTarget::Surface => None,
     };
-    if !canon.is_file() {
-        return fail("Not a file");
-    }
-    // The workspace file watcher picks up the removal and updates the search
-    // index / notifies viewers, mirroring how create relies on the watcher.
-    match std::fs::remove_file(&canon) {
-        Ok(_) => Json(DeleteFileResponse {
-            success: true,
-            message: "File deleted".to_string(),
-        })
-        .into_response(),
-        Err(e) => fail(&format!("Failed to delete file: {e}")),
-    }
-}
 
-#[derive(Deserialize)]
-struct UpdateWorkspaceFeaturesRequest {
-    #[serde(flatten)]
-    flags: WorkspaceFlags,
-}
+    // Error replies to this client. Bounded, and dropped when full, so a
+    // client flooding bad frames cannot queue unbounded replies.
+    let (reply_tx, mut reply_rx) = mpsc::channel::<WebSocketMessage>(16);
+    let send_channel = session.channel.clone();
+    let mut send_task = tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                event = rx.recv() => match event {
+                    Ok(event) => {
+                        let Some(payload) = workspace_event_payload(event, &send_channel) else {
+                            continue;
+                        };
+                        if sender.send(Message::Text(payload.into())).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        tracing::warn!(skipped = n, "ws broadcast lagged; continuing");
+                        continue;
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+                },
+                Some(reply) = reply_rx.recv() => {
+                    if send_json(&mut sender, &reply).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
 
-#[derive(Serialize)]
-struct UpdateWorkspaceFeaturesResponse {
-    success: bool,
-    message: String,
+    let recv_entry = entry.clone();
+    let recv_session = session.clone();
+    let recv_viewer = presence.as_ref().map(|(_, viewer)| viewer.clone());
+    let mut recv_task = tokio::spawn(async move {
+        let mut selections = crate::presence::Throttle::new(SELECTION_INTERVAL);
+        loop {
+            let frame = match selections.deadline() {
+                Some(deadline) => tokio::select! {
+                    frame = receiver.next() => frame,
+                    _ = tokio::time::sleep_until(deadline.into()) => {
+                        if let Some(msg) = selections.take_due(std::time::Instant::now()) {
+                            relay_selection(&recv_entry, &recv_session, msg);
+                        }
+                        continue;
+                    }
+                },
+                None => receiver.next().await,
+            };
+            let Some(Ok(frame)) = frame else {
+                break;
+            };
+            let parsed = match frame {
+                Message::Text(text) => parse_client_msg(&text),
+                Message::Binary(_) => Err(WsErrorReason::Unsupported),
+                Message::Close(_) => break,
+                Message::Ping(_) | Message::Pong(_) => continue,
+            };
+            let parsed = match (parsed, &recv_viewer) {
+                (Ok(WebSocketMessage::Selection { range, .. }), Some(viewer)) => {
+                    let msg = WebSocketMessage::Selection {
+                        client_id: viewer.client_id.clone(),
+                        identity: viewer.identity.clone(),
+                        range,
+                    };
+                    if let Some(msg) = selections.offer(msg, std::time::Instant::now()) {
+                        relay_selection(&recv_entry, &recv_session, msg);
+                    }
+                    continue;
+                }
+                // Surface sessions have no document to select in.
+                (Ok(WebSocketMessage::Selection { .. }), None) => Err(WsErrorReason::Unsupported),
+                (parsed, _) => parsed,
+            };
+            match parsed {
+                Ok(msg) => handle_client_msg(&recv_entry, &recv_session, msg),
+                Err(reason) => {
+                    tracing::debug!(workspace_id = %recv_entry.id, ?reason, "rejecting ws message");
+                    let _ = reply_tx.try_send(WebSocketMessage::Error {
+                        reason,
+                        message: reason.message().to_string(),
+                    });
+                }
+            }
+        }
+    });
+
+    tokio::select! {
+        _ = (&mut send_task) => recv_task.abort(),
+        _ = (&mut recv_task) => send_task.abort(),
+        _ = config_rx.recv() => {
+            send_task.abort();
+            recv_task.abort();
+        }
+    };
+
+    if let Some((file_path, viewer)) = presence {
+        leave_presence(&state, &entry, &session, &file_path, &viewer.client_id);
+    }
 }
 
-async fn handle_workspace_update_features(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<UpdateWorkspaceFeaturesRequest>,
-) -> Response {
-    if state
-        .workspace_registry
-        .update_flags(&workspace_id, payload.flags)
-    {
-        Json(UpdateWorkspaceFeaturesResponse {
-            success: true,
-            message: "Workspace features updated".to_string(),
-        })
-        .into_response()
-    } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(UpdateWorkspaceFeaturesResponse {
-                success: false,
-                message: "Workspace not found".to_string(),
-            }),
-        )
-            .into_response()
+/// Shared selections are part of Live and follow its workspace toggle.
+fn relay_selection(entry: &WorkspaceEntry, session: &WsSession, msg: WebSocketMessage) {
+    if entry.enable_live.load(std::sync::atomic::Ordering::Relaxed) {
+        broadcast_msg(&entry.events_tx, &session.channel, &msg);
     }
 }
 
-#[derive(Deserialize)]
-struct UpdateWorkspaceAliasRequest {
-    #[serde(default)]
-    alias: String,
+fn leave_presence(
+    state: &AppState,
+    entry: &WorkspaceEntry,
+    session: &WsSession,
+    file_path: &str,
+    client_id: &str,
+) {
+    let viewers = state.presence.leave(&entry.id, file_path, client_id);
+    broadcast_msg(
+        &entry.events_tx,
+        &session.channel,
+        &WebSocketMessage::PresenceLeave {
+            client_id: client_id.to_string(),
+            viewers,
+        },
+    );
 }
 
-/// Set/clear a workspace's alias from the web (directory page). Gated by
-/// `require_admin_role` + `require_same_origin` (NOT the master token), so the
-/// browser can use its narrow, HttpOnly admin session without exposing the
-/// native management bearer.
-async fn handle_workspace_update_alias(
+// ── Workspace content handlers ────────────────────────────────────────────────
+
+/// Standalone chat-only page. Opened by ChatManager.#openPopout() in its own
+/// browser-level window. Returns the minimal `chat.html` template — no
+/// markdown body, no TOC, no Live, no annotations bundle. The shared
+/// `main.js` bundle still loads, but at boot it sees `<meta name="chat-only">`
+/// and routes to `ChatManager.initPopout()` instead of `MarkonApp`.
+async fn handle_chat_popout(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-    Json(payload): Json<UpdateWorkspaceAliasRequest>,
-) -> Response {
-    if state
-        .workspace_registry
-        .set_alias(&workspace_id, payload.alias.trim())
-    {
-        Json(serde_json::json!({ "success": true })).into_response()
-    } else {
-        (
-            StatusCode::NOT_FOUND,
-            Json(serde_json::json!({ "success": false, "message": "Workspace not found" })),
-        )
-            .into_response()
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // Hide the chat page entirely if chat is disabled for this workspace —
+    // mirrors the same gate the in-page chat panel respects via the
+    // `enable-chat` meta flag. Chat is a collaboration ability, so it stays open
+    // to remote collaborators too whenever the flag is on.
+    if !ws.flags().enable_chat {
+        return StatusCode::NOT_FOUND.into_response();
     }
+    let mut context = base_context(&state);
+    context.insert("workspace_id", &workspace_id);
+    context.insert("title", &"Markon Chat".to_string());
+    render_template(&state, "chat.html", &context)
 }
 
-// ── Search handler ────────────────────────────────────────────────────────────
-
-async fn workspace_search_handler(
+async fn handle_workspace_root(
     State(state): State<AppState>,
     AxumPath(workspace_id): AxumPath<String>,
-    axum::extract::Query(query): axum::extract::Query<SearchQuery>,
+    role: Option<Extension<AccessRole>>,
+    Query(view): Query<DirViewQuery>,
+    headers: axum::http::HeaderMap,
 ) -> impl IntoResponse {
-    workspace_search_results(&state, &workspace_id, &query.q).await
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // Single-file workspace: there's no listing, just the one document.
+    // 302 to the file URL so the user lands directly on the rendered .md.
+    if let Some(only) = &ws.single_file {
+        return Redirect::to(&workspace_file_url(&state.base_path, &workspace_id, only))
+            .into_response();
+    }
+    let root = canonical_workspace_root(&ws);
+    let role = role.map(|Extension(role)| role);
+    let can_manage = role == Some(AccessRole::Admin);
+    let show_progress = progress_visible(role, &ws);
+    let view = match view.parse() {
+        Ok(view) => view,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    render_directory_listing_async(
+        workspace_id,
+        ws,
+        root,
+        state,
+        can_manage,
+        show_progress,
+        view,
+        client_id(&headers),
+    )
+    .await
 }
 
-async fn workspace_search_results(
-    state: &AppState,
-    workspace_id: &str,
-    query: &str,
-) -> Json<Vec<SearchResult>> {
-    if query.is_empty() {
-        return Json(Vec::<SearchResult>::new());
-    }
-    let Some(ws) = state.workspace_registry.get(workspace_id) else {
-        return Json(Vec::new());
+async fn handle_workspace_path(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
+    role: Option<Extension<AccessRole>>,
+    Query(view): Query<DirViewQuery>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
     };
-    if !ws.enable_search.load(std::sync::atomic::Ordering::Relaxed) {
-        return Json(Vec::new());
+
+    let decoded = urlencoding::decode(&path).unwrap_or_else(|_| path.clone().into());
+    let rel = decoded.trim_start_matches('/').to_string();
+    // A route through a zip or tar file (`docs.zip/guide/`) browses inside it.
+    if let Some((archive, inner)) = crate::archive::split_route(&rel) {
+        if let Some(response) =
+            handle_archive_route(&state, &workspace_id, &ws, archive, inner).await
+        {
+            return response;
+        }
     }
-    let Some(idx) = ws.search_index.load_full() else {
-        return Json(Vec::new()); // still indexing
-    };
-    // Tantivy search is CPU/IO-bound; run it on the blocking pool so it does not
-    // stall a tokio worker thread.
-    let query_owned = query.to_string();
-    let results = tokio::task::spawn_blocking(move || idx.search(&query_owned, 20))
+    // Resolution canonicalizes (and may stat) the path — keep it off the
+    // async workers like the rest of this handler's filesystem access.
+    let resolve_ws = ws.clone();
+    let resolved = tokio::task::spawn_blocking(move || resolve_ws.fs.resolve_served(&rel))
         .await
         .unwrap_or_else(|e| {
-            tracing::error!("search blocking task join error: {e}");
-            Ok(Vec::new())
-        })
-        .unwrap_or_else(|e| {
-            tracing::warn!("search error: {e}");
-            Vec::new()
+            tracing::error!("resolve_served join error: {e}");
+            Err(crate::workspace_fs::WorkspaceFsError::NotFound)
         });
-    Json(results)
-}
+    let canonical = match resolved {
+        Ok(path) => path,
+        Err(
+            crate::workspace_fs::WorkspaceFsError::InvalidPath
+            | crate::workspace_fs::WorkspaceFsError::Denied,
+        ) if !ws.is_ephemeral() => {
+            return (StatusCode::FORBIDDEN, "Access denied").into_response();
+        }
+        Err(_) => {
+            return (StatusCode::NOT_FOUND, format!("Path not found: {decoded}")).into_response();
+        }
+    };
 
-/// Context pre-seeded with the page-independent keys shared by every template
-/// (extra keys are ignored by templates that don't reference them).
-fn base_context(state: &AppState) -> tera::Context {
-    let mut context = tera::Context::new();
-    context.insert("theme", state.theme.as_str());
-    context.insert("i18n_json", state.i18n_json.as_str());
-    context.insert("i18n_lang", state.i18n_lang.as_str());
-    context.insert("shortcuts_json", state.shortcuts_json.as_str());
-    context.insert("styles_css", state.styles_css.as_str());
-    context.insert("default_chat_mode", state.default_chat_mode.as_str());
-    context.insert("print_collapsed_content", &state.print_collapsed_content);
-    context
-}
+    let root = canonical_workspace_root(&ws);
+    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
+    if !is_inside_workspace(&canonical, &root) {
+        return (StatusCode::FORBIDDEN, "Access denied").into_response();
+    }
 
-/// Render a template, mapping failure to a 500 with the error text.
-fn render_template(state: &AppState, name: &str, context: &tera::Context) -> Response {
-    match state.tera.render(name, context) {
-        Ok(html) => Html(html).into_response(),
-        Err(e) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Template error: {e}"),
-        )
+    let file_type = tokio::fs::metadata(&canonical).await.map(|m| m.file_type());
+    if file_type.as_ref().is_ok_and(|t| t.is_file()) {
+        let served = vec![crate::audit::Event::new(
+            crate::audit::Action::Serve,
+            canonical.to_string_lossy(),
+        )];
+        if ws.fs.policy().is_markdown(&canonical) {
+            let cookie = record_file_view(&state, &ws, &headers, &canonical).await;
+            record_document_snapshot(&ws, &canonical).await;
+            let response = render_markdown_file_async(
+                canonical.to_string_lossy().into_owned(),
+                workspace_id.clone(),
+                ws.clone(),
+                root.clone(),
+                state.clone(),
+                can_manage,
+                view.page.unwrap_or(1),
+            )
+            .await;
+            let response = match cookie {
+                Some(cookie) => {
+                    ([(axum::http::header::SET_COOKIE, cookie)], response).into_response()
+                }
+                None => response,
+            };
+            audited(response, served)
+        } else {
+            // An archive opens as a folder; `?source=true` downloads it.
+            if !view.source && crate::archive::kind_for(&canonical).is_some() {
+                if let Some(route) =
+                    workspace_relative_path(&canonical, &root).map(|rel| path_to_route(&rel))
+                {
+                    return Redirect::to(&workspace_file_url(
+                        &state.base_path,
+                        &workspace_id,
+                        &format!("{route}/"),
+                    ))
+                    .into_response();
+                }
+            }
+            // With `--pandoc`, ODT, RTF and MediaWiki files render like
+            // documents; `?source=true` still gets the file itself.
+            if !view.source {
+                if let Some(resp) =
+                    render_pandoc_or_none(&canonical, &workspace_id, &ws, &root, &state).await
+                {
+                    return audited(resp, served);
+                }
+            }
+            // Small UTF-8 text/code files get an elegant read-only, syntax-
+            // highlighted preview page. Everything else — images, media, PDFs,
+            // binaries, oversized text — is served as raw bytes (the browser
+            // displays what it can inline and downloads the rest); this also
+            // keeps embedded resources like markdown images working verbatim.
+            match render_preview_or_none(
+                canonical.clone(),
+                workspace_id.clone(),
+                ws.clone(),
+                root.clone(),
+                state.clone(),
+                !view.source,
+            )
+            .await
+            {
+                Some(resp) => audited(resp, served),
+                None => audited(serve_file(&canonical, &headers).await, served),
+            }
+        }
+    } else if file_type.is_ok_and(|t| t.is_dir()) {
+        if ws.is_ephemeral() {
+            // Single-file capabilities never authorize directories. Keep this
+            // explicit as defense in depth if serving policy changes later.
+            return (StatusCode::NOT_FOUND, "Path not found").into_response();
+        }
+        // Subdirectories are browsed in place on the workspace root via a URL
+        // hash (e.g. "/{id}/#docs/") which the frontend expands as an inline
+        // tree — there is no standalone subdirectory listing page anymore.
+        // Redirect any direct/legacy subdirectory URL to that anchor form.
+        match workspace_relative_path(&canonical, &root).map(|rel| path_to_route(&rel)) {
+            Some(rel_str) if !rel_str.is_empty() => Redirect::to(&format!(
+                "{}#{}/",
+                workspace_root_url(&state.base_path, &workspace_id),
+                rel_str
+            ))
             .into_response(),
+            // The workspace root itself is served by `handle_workspace_root`;
+            // this arm is just a safe fallback.
+            _ => {
+                let show_progress = progress_visible(role.map(|Extension(role)| role), &ws);
+                let view = match view.parse() {
+                    Ok(view) => view,
+                    Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+                };
+                render_directory_listing_async(
+                    workspace_id,
+                    ws,
+                    root,
+                    state,
+                    can_manage,
+                    show_progress,
+                    view,
+                    client_id(&headers),
+                )
+                .await
+            }
+        }
+    } else {
+        (StatusCode::NOT_FOUND, "Path not found").into_response()
     }
 }
 
-#[derive(Serialize)]
-struct GitDiffTemplate<'a> {
-    range: &'a str,
-    title: &'a str,
-    subtitle: Option<&'a str>,
-    mode_label: String,
-    base_label: String,
-    compare_label: String,
-    base_value: String,
-    compare_value: String,
-    files: Vec<GitDiffFileTemplate<'a>>,
-    nav_entries: Vec<GitDiffNavEntry<'a>>,
-    total_additions: usize,
-    total_deletions: usize,
-}
-
-#[derive(Serialize)]
-struct GitDiffFileTemplate<'a> {
-    path: &'a str,
-    old_path: Option<&'a str>,
-    status: &'a str,
-    additions: usize,
-    deletions: usize,
+#[derive(Deserialize)]
+struct GitHistoryQuery {
+    branch: Option<String>,
+    author: Option<String>,
+    range: Option<String>,
 }
 
-#[derive(Serialize)]
-struct GitDiffNavEntry<'a> {
-    kind: &'static str,
-    name: String,
-    path: String,
-    depth: usize,
-    status: Option<&'a str>,
-    additions: usize,
-    deletions: usize,
+#[derive(Deserialize)]
+struct DocumentRevisionQuery {
+    rev: Option<String>,
 }
 
-#[derive(Serialize)]
-struct GitCompareOption {
-    value: String,
-    label: String,
-    /// Lightweight display alias for special refs/commits, e.g. the newest
-    /// concrete commit that is also reachable as HEAD.
-    alias: String,
-    /// Option family for the rich picker UI: worktree | head | branch | tag | commit.
-    kind: String,
-    /// Commit subject (commits only; "" otherwise).
-    subject: String,
-    /// Secondary detail — short hash for commits/tags, "current" for the current
-    /// branch, "" otherwise.
-    detail: String,
-    /// Relative time (commits/tags; "" otherwise).
-    date: String,
-    selected: bool,
-    disabled: bool,
+/// Map a toolbar range key to a git `--since` approxidate. `""`/`"all"` (and any
+/// unknown key) mean "no lower bound".
+fn git_history_since(range: Option<&str>) -> Option<String> {
+    match range.map(str::trim).unwrap_or("") {
+        "day" => Some("1 day ago".to_string()),
+        "week" => Some("1 week ago".to_string()),
+        "month" => Some("1 month ago".to_string()),
+        "year" => Some("1 year ago".to_string()),
+        _ => None,
+    }
 }
 
-#[derive(Serialize)]
-struct GitCompareOptionStatus {
-    value: String,
-    disabled: bool,
+async fn handle_git_history(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(q): Query<GitHistoryQuery>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    git_history_response(&state, &workspace_id, &ws, root, q, None).await
 }
 
-#[derive(Serialize)]
-struct GitCompareOptionsStatus {
-    base: Vec<GitCompareOptionStatus>,
-    compare: Vec<GitCompareOptionStatus>,
+/// `GET /_/{workspace_id}/history/{path}` — the commits touching one document,
+/// newest first, with the history page's filters. Each commit links to the
+/// document as committed there (`?rev=<hash>`, read with `git show` rather
+/// than from the working tree) and to its diff against the commit before.
+async fn handle_document_history(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
+    Query(q): Query<GitHistoryQuery>,
+    Query(revision): Query<DocumentRevisionQuery>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let Ok(path) = crate::workspace_fs::WorkspaceRelPath::parse(&path) else {
+        return (StatusCode::BAD_REQUEST, "Invalid document path").into_response();
+    };
+    if !ws.fs.policy().is_markdown(path.as_path()) {
+        return (StatusCode::BAD_REQUEST, "Not a Markdown document").into_response();
+    }
+    let path = path.as_route();
+    match revision.rev.as_deref().map(str::trim) {
+        Some(rev) if !rev.is_empty() => {
+            render_document_revision(&state, &workspace_id, &ws, root, path, rev.to_string()).await
+        }
+        _ => git_history_response(&state, &workspace_id, &ws, root, q, Some(path)).await,
+    }
 }
 
-#[derive(Serialize)]
-struct GitDiffData<'a> {
-    range: &'a str,
-    title: &'a str,
-    subtitle: Option<&'a str>,
-    files: Vec<GitDiffDataFile<'a>>,
-    rows: Vec<GitDiffDataRow<'a>>,
-    total_additions: usize,
-    total_deletions: usize,
+/// The history page for the workspace, or only for `document`.
+async fn git_history_response(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: PathBuf,
+    q: GitHistoryQuery,
+    document: Option<String>,
+) -> Response {
+    let branch = q
+        .branch
+        .as_deref()
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .map(str::to_string);
+    let author = q
+        .author
+        .as_deref()
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(str::to_string);
+    let range_key = q
+        .range
+        .as_deref()
+        .map(str::trim)
+        .filter(|r| !r.is_empty() && *r != "all")
+        .unwrap_or("")
+        .to_string();
+    let filter = git::HistoryFilter {
+        branch: branch.clone(),
+        author: author.clone(),
+        since: git_history_since(Some(&range_key)),
+        path: document.clone(),
+    };
+    let git_root = root.clone();
+    let history =
+        tokio::task::spawn_blocking(move || git::history_filtered(&git_root, 80, &filter))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("git history blocking task join error: {e}");
+                Err(git::GitError::Command("internal task error".into()))
+            });
+    match history {
+        Ok(commits) => render_git_history_page(
+            state,
+            workspace_id,
+            &root,
+            &ws.fs.policy().markdown,
+            &commits,
+            branch.as_deref(),
+            author.as_deref(),
+            &range_key,
+            document.as_deref(),
+        ),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list git history: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-#[derive(Serialize)]
-struct GitDiffDataFile<'a> {
-    path: &'a str,
-    old_path: Option<&'a str>,
-    status: &'a str,
-    additions: usize,
-    deletions: usize,
-    start_row: usize,
-    row_count: usize,
-}
-
-#[derive(Serialize)]
-#[serde(tag = "kind")]
-enum GitDiffDataRow<'a> {
-    #[serde(rename = "file")]
-    File {
-        file_index: usize,
-        path: &'a str,
-        old_path: Option<&'a str>,
-        status: &'a str,
-        additions: usize,
-        deletions: usize,
-    },
-    #[serde(rename = "line")]
-    Line {
-        file_index: usize,
-        old_line_no: Option<usize>,
-        new_line_no: Option<usize>,
-        old_class_name: &'static str,
-        new_class_name: &'static str,
-        old_segments: Vec<GitDiffDataSegment>,
-        new_segments: Vec<GitDiffDataSegment>,
-    },
+async fn handle_git_history_data(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let history = tokio::task::spawn_blocking(move || git::history(&root, 80))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git history blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match history {
+        Ok(commits) => Json(commits).into_response(),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list git history: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-#[derive(Serialize)]
-struct GitDiffDataSegment {
-    text: String,
-    class_name: Option<&'static str>,
+async fn handle_git_branches(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let branches = tokio::task::spawn_blocking(move || git::branches_detailed(&root))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git branches blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match branches {
+        Ok(branches) => render_git_branches_page(&state, &workspace_id, &branches),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list git branches: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-struct GitDiffDataSide {
-    line_no: Option<usize>,
-    class_name: &'static str,
-    segments: Vec<GitDiffDataSegment>,
+async fn handle_git_tags(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let tags = tokio::task::spawn_blocking(move || git::tags(&root, 200))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git tags blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match tags {
+        Ok(tags) => render_git_tags_page(&state, &workspace_id, &tags),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to list git tags: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-#[derive(Serialize)]
-struct MarkdownDiffData {
-    title: String,
-    subtitle: Option<String>,
-    engine: markdown_ast::MarkdownAstEngineInfo,
-    files: Vec<MarkdownDiffFile>,
+async fn handle_git_working_diff(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(query): Query<GitViewQuery>,
+    role: Option<Extension<AccessRole>>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
+    let initial_view = diff_view_from_query(query.view.as_deref());
+    directory_root_or_not_found!(ws);
+    let workspace_fs = ws.fs.clone();
+    let diff = tokio::task::spawn_blocking(move || git::working_diff(&workspace_fs))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git working diff blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match diff {
+        Ok(diff) => render_git_diff_page(
+            &state,
+            &workspace_id,
+            &ws,
+            can_manage,
+            &diff,
+            initial_view,
+            None,
+        ),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read git diff: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-#[derive(Clone, Serialize)]
-struct MarkdownDiffFile {
-    path: String,
-    // Canonical absolute path of the new-side file on disk, byte-identical to the
-    // annotation `file_path` key that `render_markdown_file` builds when the same
-    // file is opened normally. The listing uses `git diff --relative`, so
-    // `entry.path` is workspace-relative; joining it onto the workspace root
-    // resolves the same key even when the workspace is a subdirectory of the repo.
-    abs_path: String,
-    old_path: Option<String>,
-    status: String,
-    // Slim per-side outline: the frontend only reads `block_count` and a
-    // per-block {index, kind, label} to compute heading-section indentation.
-    // The heavy rendered HTML lives on `blocks[].old/new` (rendered once), so
-    // shipping full per-side summaries here would triple the HTML payload.
-    old: Option<MarkdownDocOutline>,
-    new: Option<MarkdownDocOutline>,
-    // Full, untruncated source text of each side. The raw (source) diff view
-    // renders exact file lines from these, sliced by each block's line span, so
-    // both views share one block-based segmentation (and stay aligned). Absent
-    // when a side does not exist (added file has no old; deleted has no new).
-    old_source: Option<String>,
-    new_source: Option<String>,
-    additions: usize,
-    deletions: usize,
-    blocks: Vec<MarkdownDiffBlock>,
-    diagnostics: Vec<MarkdownDiffDiagnostic>,
+async fn handle_git_working_diff_data(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(query): Query<GitViewQuery>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if diff_view_from_query(query.view.as_deref()) == "rendered" {
+        directory_root_or_not_found!(ws);
+        return match markdown_compare_diff_data_async(
+            &state,
+            &workspace_id,
+            ws.fs.clone(),
+            "HEAD",
+            "worktree",
+            query.f.as_deref(),
+        )
+        .await
+        {
+            Ok(data) => Json(data).into_response(),
+            Err(git::GitError::NotRepository) => git_not_repository_response(),
+            Err(git::GitError::InvalidRevision) => {
+                (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+            }
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read markdown diff: {e}"),
+            )
+                .into_response(),
+        };
+    }
+    directory_root_or_not_found!(ws);
+    let workspace_fs = ws.fs.clone();
+    let diff = tokio::task::spawn_blocking(move || git::working_diff(&workspace_fs))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git working diff blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match diff {
+        Ok(diff) => git_diff_json_response(&diff, &ws.fs.policy().markdown, query.f.as_deref()),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to read git diff: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-#[derive(Clone, Serialize)]
-struct MarkdownDocOutline {
-    block_count: usize,
-    blocks: Vec<MarkdownBlockOutline>,
+async fn handle_git_commit_diff(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, commit)): AxumPath<(String, String)>,
+    Query(query): Query<GitViewQuery>,
+    role: Option<Extension<AccessRole>>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
+    let initial_view = diff_view_from_query(query.view.as_deref());
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let diff = tokio::task::spawn_blocking(move || git::commit_diff(&root, &commit))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git commit diff blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match diff {
+        Ok(diff) => render_git_diff_page(
+            &state,
+            &workspace_id,
+            &ws,
+            can_manage,
+            &diff,
+            initial_view,
+            None,
+        ),
+        Err(git::GitError::InvalidRevision) => {
+            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+        }
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
+    }
 }
 
-#[derive(Clone, Serialize)]
-struct MarkdownBlockOutline {
-    index: usize,
-    kind: String,
-    label: String,
-}
-
-impl MarkdownDocOutline {
-    fn from_summary(summary: &markdown_ast::MarkdownDocumentSummary) -> Self {
-        MarkdownDocOutline {
-            block_count: summary.block_count,
-            blocks: summary
-                .blocks
-                .iter()
-                .map(|b| MarkdownBlockOutline {
-                    index: b.index,
-                    kind: b.kind.clone(),
-                    label: b.label.clone(),
-                })
-                .collect(),
+async fn handle_git_commit_diff_data(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, commit)): AxumPath<(String, String)>,
+    Query(query): Query<GitViewQuery>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let diff = tokio::task::spawn_blocking(move || git::commit_diff(&root, &commit))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git commit diff blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match diff {
+        Ok(diff) => git_diff_json_response(&diff, &ws.fs.policy().markdown, query.f.as_deref()),
+        Err(git::GitError::InvalidRevision) => {
+            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
         }
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
     }
 }
 
-#[derive(Clone, Serialize)]
-struct MarkdownDiffBlock {
-    kind: &'static str,
-    old: Option<markdown_ast::MarkdownBlockSummary>,
-    new: Option<markdown_ast::MarkdownBlockSummary>,
-}
-
-#[derive(Clone, Serialize)]
-struct MarkdownDiffDiagnostic {
-    side: &'static str,
-    code: String,
-    severity: String,
-    message: String,
-    start_line: Option<u32>,
-    end_line: Option<u32>,
+#[derive(Deserialize)]
+struct GitViewQuery {
+    view: Option<String>,
+    f: Option<String>,
 }
 
-const MARKDOWN_DIFF_CACHE_VERSION: &str = "markdown-diff-cache-v2";
-const MARKDOWN_DIFF_DOCUMENT_CACHE_LIMIT: usize = 256;
-const MARKDOWN_DIFF_FILE_CACHE_LIMIT: usize = 512;
-
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-struct MarkdownDocumentCacheKey {
-    version: &'static str,
-    theme: String,
-    workspace_id: String,
-    file_path: String,
-    content_hash: String,
+#[derive(Deserialize)]
+struct PrettyCompareQuery {
+    view: Option<String>,
+    format: Option<String>,
+    f: Option<String>,
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
-struct MarkdownDiffFileCacheKey {
-    version: &'static str,
-    theme: String,
-    workspace_id: String,
-    path: String,
-    old_path: Option<String>,
-    status: String,
-    // Stable content identity per side: a git blob oid where available (no read
-    // needed — comes straight from `git diff --raw`), or a `h:<sha256>` of the
-    // worktree content. Keying on the blob oid lets a warm reload hit the cache
-    // without reading or re-rendering the old side.
-    old_id: Option<String>,
-    new_id: Option<String>,
+#[derive(Deserialize)]
+struct GitCompareOptionsStatusQuery {
+    base: String,
+    compare: String,
 }
 
-#[derive(Default)]
-pub(crate) struct MarkdownDiffCache {
-    documents: HashMap<MarkdownDocumentCacheKey, Arc<markdown_ast::MarkdownDocumentSummary>>,
-    document_lru: VecDeque<MarkdownDocumentCacheKey>,
-    files: HashMap<MarkdownDiffFileCacheKey, Arc<MarkdownDiffFile>>,
-    file_lru: VecDeque<MarkdownDiffFileCacheKey>,
-    document_hits: u64,
-    document_misses: u64,
-    file_hits: u64,
-    file_misses: u64,
+fn diff_view_from_query(view: Option<&str>) -> &'static str {
+    // Rendered is the default; only an explicit ?view=raw selects the source view.
+    match view {
+        Some("raw") => "raw",
+        _ => "rendered",
+    }
 }
 
-#[cfg(test)]
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
-struct MarkdownDiffCacheStats {
-    document_entries: usize,
-    file_entries: usize,
-    document_hits: u64,
-    document_misses: u64,
-    file_hits: u64,
-    file_misses: u64,
+fn git_not_repository_response() -> Response {
+    (StatusCode::CONFLICT, "Workspace is not a git repository").into_response()
 }
 
-impl MarkdownDiffCache {
-    fn get_document(
-        &mut self,
-        key: &MarkdownDocumentCacheKey,
-    ) -> Option<Arc<markdown_ast::MarkdownDocumentSummary>> {
-        if let Some(summary) = self.documents.get(key).cloned() {
-            self.document_hits += 1;
-            touch_lru_key(&mut self.document_lru, key);
-            Some(summary)
-        } else {
-            self.document_misses += 1;
-            None
-        }
-    }
-
-    fn insert_document(
-        &mut self,
-        key: MarkdownDocumentCacheKey,
-        summary: markdown_ast::MarkdownDocumentSummary,
-    ) -> Arc<markdown_ast::MarkdownDocumentSummary> {
-        let summary = Arc::new(summary);
-        self.documents.insert(key.clone(), summary.clone());
-        touch_lru_key(&mut self.document_lru, &key);
-        trim_lru_cache(
-            &mut self.documents,
-            &mut self.document_lru,
-            MARKDOWN_DIFF_DOCUMENT_CACHE_LIMIT,
-        );
-        summary
+async fn handle_pretty_compare_diff(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, range)): AxumPath<(String, String)>,
+    Query(query): Query<PrettyCompareQuery>,
+    role: Option<Extension<AccessRole>>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some((base, compare)) = parse_pretty_compare_range(&range) else {
+        return (StatusCode::BAD_REQUEST, "Invalid compare range").into_response();
+    };
+    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
+    let initial_view = diff_view_from_query(query.view.as_deref());
+    if query.format.as_deref() == Some("data") && initial_view == "rendered" {
+        directory_root_or_not_found!(ws);
+        return match markdown_compare_diff_data_async(
+            &state,
+            &workspace_id,
+            ws.fs.clone(),
+            &base,
+            &compare,
+            query.f.as_deref(),
+        )
+        .await
+        {
+            Ok(data) => Json(data).into_response(),
+            Err(git::GitError::NotRepository) => git_not_repository_response(),
+            Err(git::GitError::InvalidRevision) => {
+                (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+            }
+            Err(e) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read markdown diff: {e}"),
+            )
+                .into_response(),
+        };
     }
-
-    fn get_file(&mut self, key: &MarkdownDiffFileCacheKey) -> Option<Arc<MarkdownDiffFile>> {
-        if let Some(file) = self.files.get(key).cloned() {
-            self.file_hits += 1;
-            touch_lru_key(&mut self.file_lru, key);
-            Some(file)
-        } else {
-            self.file_misses += 1;
-            None
+    directory_root_or_not_found!(ws);
+    let workspace_fs = ws.fs.clone();
+    let diff =
+        tokio::task::spawn_blocking(move || git::compare_diff(&workspace_fs, &base, &compare))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("git compare diff blocking task join error: {e}");
+                Err(git::GitError::Command("internal task error".into()))
+            });
+    match diff {
+        Ok(diff) if query.format.as_deref() == Some("data") => {
+            git_diff_json_response(&diff, &ws.fs.policy().markdown, query.f.as_deref())
         }
-    }
-
-    fn insert_file(
-        &mut self,
-        key: MarkdownDiffFileCacheKey,
-        file: MarkdownDiffFile,
-    ) -> Arc<MarkdownDiffFile> {
-        let file = Arc::new(file);
-        self.files.insert(key.clone(), file.clone());
-        touch_lru_key(&mut self.file_lru, &key);
-        trim_lru_cache(
-            &mut self.files,
-            &mut self.file_lru,
-            MARKDOWN_DIFF_FILE_CACHE_LIMIT,
-        );
-        file
-    }
-
-    #[cfg(test)]
-    fn stats(&self) -> MarkdownDiffCacheStats {
-        MarkdownDiffCacheStats {
-            document_entries: self.documents.len(),
-            file_entries: self.files.len(),
-            document_hits: self.document_hits,
-            document_misses: self.document_misses,
-            file_hits: self.file_hits,
-            file_misses: self.file_misses,
+        Ok(diff) => render_git_diff_page(
+            &state,
+            &workspace_id,
+            &ws,
+            can_manage,
+            &diff,
+            initial_view,
+            None,
+        ),
+        Err(git::GitError::InvalidRevision) => {
+            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
         }
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
     }
 }
 
-fn touch_lru_key<K>(lru: &mut VecDeque<K>, key: &K)
-where
-    K: Clone + Eq,
-{
-    if let Some(index) = lru.iter().position(|existing| existing == key) {
-        lru.remove(index);
-    }
-    lru.push_back(key.clone());
+#[derive(Deserialize)]
+struct DocumentDiffQuery {
+    from: Option<String>,
+    to: Option<String>,
+    view: Option<String>,
 }
 
-fn trim_lru_cache<K, V>(cache: &mut HashMap<K, V>, lru: &mut VecDeque<K>, limit: usize)
-where
-    K: Clone + Eq + std::hash::Hash,
-{
-    while cache.len() > limit {
-        let Some(key) = lru.pop_front() else {
-            break;
-        };
-        cache.remove(&key);
+/// `GET /_/{workspace_id}/diff/{path}?from=HEAD~1&to=HEAD` — the Markdiff page
+/// for one document between two revisions: both versions rendered, changed
+/// blocks highlighted. `from` defaults to `HEAD` and `to` to the working tree,
+/// i.e. the document's uncommitted changes.
+async fn handle_document_diff(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
+    Query(query): Query<DocumentDiffQuery>,
+    role: Option<Extension<AccessRole>>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    directory_root_or_not_found!(ws);
+    let Ok(path) = crate::workspace_fs::WorkspaceRelPath::parse(&path) else {
+        return (StatusCode::BAD_REQUEST, "Invalid document path").into_response();
+    };
+    if !ws.fs.policy().is_markdown(path.as_path()) {
+        return (StatusCode::BAD_REQUEST, "Not a Markdown document").into_response();
+    }
+    let path = path.as_route();
+    let can_manage = role.is_some_and(|Extension(role)| role == AccessRole::Admin);
+    let initial_view = diff_view_from_query(query.view.as_deref());
+    let base = query.from.unwrap_or_else(|| "HEAD".to_string());
+    let compare = query.to.unwrap_or_else(|| "worktree".to_string());
+    let workspace_fs = ws.fs.clone();
+    let diff =
+        tokio::task::spawn_blocking(move || git::compare_diff(&workspace_fs, &base, &compare))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("git document diff blocking task join error: {e}");
+                Err(git::GitError::Command("internal task error".into()))
+            });
+    match diff {
+        Ok(diff) => render_git_diff_page(
+            &state,
+            &workspace_id,
+            &ws,
+            can_manage,
+            &diff,
+            initial_view,
+            Some(&path),
+        ),
+        Err(git::GitError::InvalidRevision) => {
+            (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+        }
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => (StatusCode::NOT_FOUND, format!("Git diff not found: {e}")).into_response(),
     }
 }
 
-#[derive(Serialize)]
-struct WorkspaceFeatureStatus {
-    key: &'static str,
-    label: &'static str,
-    label_key: &'static str,
-    enabled: bool,
-}
-
-fn git_diff_template<'a>(
-    root: &FsPath,
-    diff: &'a git::GitDiff,
-    base_value: String,
-    compare_value: String,
-) -> GitDiffTemplate<'a> {
-    let files: Vec<GitDiffFileTemplate<'_>> = diff
-        .files
-        .iter()
-        .map(|file| GitDiffFileTemplate {
-            path: &file.path,
-            old_path: file.old_path.as_deref(),
-            status: &file.status,
-            additions: file.additions,
-            deletions: file.deletions,
-        })
-        .collect();
-    let total_additions = diff.files.iter().map(|file| file.additions).sum();
-    let total_deletions = diff.files.iter().map(|file| file.deletions).sum();
-    let (mode_label, base_label, compare_label) =
-        git_diff_range_labels(root, diff, &base_value, &compare_value);
-    GitDiffTemplate {
-        range: &diff.range,
-        title: &diff.title,
-        subtitle: diff.subtitle.as_deref(),
-        mode_label,
-        base_label,
-        compare_label,
-        base_value,
-        compare_value,
-        nav_entries: git_diff_nav_entries(&diff.files),
-        files,
-        total_additions,
-        total_deletions,
+async fn handle_git_compare_options_status(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(query): Query<GitCompareOptionsStatusQuery>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws);
+    if !git::status(root).available {
+        return git_not_repository_response();
     }
+    // Each side independently probes ~dozens of candidate refs for markdown
+    // changes (one git diff each), so run the two sides concurrently; each side
+    // also parallelizes its own probes internally.
+    let markdown = &ws.fs.policy().markdown;
+    let (base, compare) = rayon::join(
+        || {
+            git_compare_option_statuses(git_compare_options(
+                root,
+                markdown,
+                &query.base,
+                false,
+                &query.compare,
+                GitCompareOptionRole::Base,
+                GitCompareOptionStatusMode::Checked,
+            ))
+        },
+        || {
+            git_compare_option_statuses(git_compare_options(
+                root,
+                markdown,
+                &query.compare,
+                true,
+                &query.base,
+                GitCompareOptionRole::Compare,
+                GitCompareOptionStatusMode::Checked,
+            ))
+        },
+    );
+    Json(GitCompareOptionsStatus { base, compare }).into_response()
 }
 
-fn git_diff_range_labels(
-    root: &FsPath,
-    diff: &git::GitDiff,
-    base_value: &str,
-    compare_value: &str,
-) -> (String, String, String) {
-    if diff.range == "HEAD..worktree" {
-        return (
-            "Working tree".to_string(),
-            "HEAD".to_string(),
-            "Worktree".to_string(),
-        );
-    }
-    if let Some((base, compare)) = diff.range.split_once("..") {
-        return (
-            "Git range".to_string(),
-            base.to_string(),
-            compare.to_string(),
-        );
-    }
-    if valid_hex_display_ref(&diff.range) {
-        let base = git::parent_commit(root, &diff.range)
-            .ok()
-            .flatten()
-            .map(|parent| short_git_ref(&parent))
-            .unwrap_or_else(|| "Parent".to_string());
-        return ("Commit".to_string(), base, short_git_ref(compare_value));
+fn parse_pretty_compare_range(range: &str) -> Option<(String, String)> {
+    let (base, compare) = range.split_once("...")?;
+    if base.trim().is_empty() || compare.trim().is_empty() {
+        return None;
     }
-    (
-        "Git range".to_string(),
-        short_git_ref(base_value),
-        short_git_ref(compare_value),
-    )
+    Some((base.to_string(), compare.to_string()))
 }
 
-fn valid_hex_display_ref(value: &str) -> bool {
-    (4..=64).contains(&value.len()) && value.bytes().all(|b| b.is_ascii_hexdigit())
+#[derive(Deserialize)]
+struct GitCommitRequest {
+    message: String,
 }
 
-fn short_git_ref(value: &str) -> String {
-    if value.len() > 12 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
-        value[..12].to_string()
-    } else {
-        value.to_string()
-    }
+#[derive(Deserialize)]
+struct GitCheckoutRequest {
+    branch: String,
 }
 
-fn git_diff_nav_entries(files: &[git::GitDiffFile]) -> Vec<GitDiffNavEntry<'_>> {
-    let mut sorted: Vec<&git::GitDiffFile> = files.iter().collect();
-    sorted.sort_by(|a, b| a.path.cmp(&b.path));
-    let mut emitted_dirs = std::collections::BTreeSet::new();
-    let mut entries = Vec::new();
-
-    for file in sorted {
-        let segments: Vec<&str> = file
-            .path
-            .split('/')
-            .filter(|part| !part.is_empty())
-            .collect();
-        for depth in 0..segments.len().saturating_sub(1) {
-            let path = segments[..=depth].join("/");
-            if emitted_dirs.insert(path.clone()) {
-                entries.push(GitDiffNavEntry {
-                    kind: "dir",
-                    name: segments[depth].to_string(),
-                    path,
-                    depth,
-                    status: None,
-                    additions: 0,
-                    deletions: 0,
-                });
-            }
-        }
-
-        entries.push(GitDiffNavEntry {
-            kind: "file",
-            name: segments
-                .last()
-                .copied()
-                .unwrap_or(file.path.as_str())
-                .to_string(),
-            path: file.path.clone(),
-            depth: segments.len().saturating_sub(1),
-            status: Some(file.status.as_str()),
-            additions: file.additions,
-            deletions: file.deletions,
-        });
-    }
+#[derive(Serialize)]
+struct GitCommitResponse {
+    success: bool,
+    message: String,
+    commit: Option<git::GitCommitResult>,
+}
 
-    entries
+#[derive(Serialize)]
+struct GitCheckoutResponse {
+    success: bool,
+    message: String,
+    status: Option<git::GitStatus>,
 }
 
-fn git_diff_json_response(diff: &git::GitDiff, file_filter: Option<&str>) -> Response {
-    let diff = markdown_only_git_diff(diff, file_filter);
-    match serde_json::to_value(git_diff_data(&diff)) {
-        Ok(value) => Json(value).into_response(),
+async fn handle_git_commit(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<GitCommitRequest>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let result =
+        tokio::task::spawn_blocking(move || git::commit_workspace(&root, &payload.message))
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("git commit blocking task join error: {e}");
+                Err(git::GitError::Command("internal task error".into()))
+            });
+    match result {
+        Ok(commit) => Json(GitCommitResponse {
+            success: true,
+            message: "Committed workspace changes".to_string(),
+            commit: Some(commit),
+        })
+        .into_response(),
+        Err(git::GitError::NothingToCommit) => Json(GitCommitResponse {
+            success: false,
+            message: "Nothing to commit".to_string(),
+            commit: None,
+        })
+        .into_response(),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Failed to serialize git diff: {e}"),
+            format!("Failed to commit workspace changes: {e}"),
         )
             .into_response(),
     }
 }
 
-fn markdown_only_git_diff(diff: &git::GitDiff, file_filter: Option<&str>) -> git::GitDiff {
-    let files: Vec<git::GitDiffFile> = diff
-        .files
-        .iter()
-        .filter(|file| is_markdown_diff_file(file))
-        .filter(|file| diff_file_matches_filter(file, file_filter))
-        .cloned()
-        .collect();
-    let patch = files.iter().map(|file| file.patch.as_str()).collect();
-    git::GitDiff {
-        range: diff.range.clone(),
-        title: diff.title.clone(),
-        subtitle: diff.subtitle.clone(),
-        patch,
-        files,
-    }
+async fn handle_git_checkout(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<GitCheckoutRequest>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = directory_root_or_not_found!(ws).to_path_buf();
+    let result = tokio::task::spawn_blocking(move || git::checkout_branch(&root, &payload.branch))
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("git checkout blocking task join error: {e}");
+            Err(git::GitError::Command("internal task error".into()))
+        });
+    match result {
+        Ok(status) => Json(GitCheckoutResponse {
+            success: true,
+            message: "Switched branch".to_string(),
+            status: Some(status),
+        })
+        .into_response(),
+        Err(git::GitError::InvalidRevision) => Json(GitCheckoutResponse {
+            success: false,
+            message: "Invalid branch".to_string(),
+            status: None,
+        })
+        .into_response(),
+        Err(git::GitError::NotRepository) => git_not_repository_response(),
+        Err(e) => Json(GitCheckoutResponse {
+            success: false,
+            message: format!("Failed to switch branch: {e}"),
+            status: None,
+        })
+        .into_response(),
+    }
 }
 
-fn diff_file_matches_filter(file: &git::GitDiffFile, file_filter: Option<&str>) -> bool {
-    let Some(filter) = file_filter
-        .map(str::trim)
-        .filter(|filter| !filter.is_empty())
-    else {
-        return true;
-    };
-    file.path == filter || file.old_path.as_deref() == Some(filter)
+#[derive(Serialize)]
+struct WorkspaceFileListEntry {
+    path: String,
+    name: String,
+    is_markdown: bool,
+    url: String,
 }
 
-fn git_diff_data(diff: &git::GitDiff) -> GitDiffData<'_> {
-    let mut rows = Vec::new();
+async fn handle_workspace_files_data(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
     let mut files = Vec::new();
-
-    for (file_index, file) in diff.files.iter().enumerate() {
-        let start_row = rows.len();
-        rows.push(GitDiffDataRow::File {
-            file_index,
-            path: &file.path,
-            old_path: file.old_path.as_deref(),
-            status: &file.status,
-            additions: file.additions,
-            deletions: file.deletions,
-        });
-        push_diff_data_rows(file_index, &file.patch, &mut rows);
-        let row_count = rows.len() - start_row;
-        files.push(GitDiffDataFile {
-            path: &file.path,
-            old_path: file.old_path.as_deref(),
-            status: &file.status,
-            additions: file.additions,
-            deletions: file.deletions,
-            start_row,
-            row_count,
+    for (rel, path) in ws.fs.served_files(2000) {
+        let route = rel.as_route();
+        files.push(WorkspaceFileListEntry {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| route.clone()),
+            is_markdown: ws.fs.policy().is_markdown(&path),
+            url: workspace_file_url(&state.base_path, &workspace_id, &route),
+            path: route,
         });
     }
-
-    GitDiffData {
-        range: &diff.range,
-        title: &diff.title,
-        subtitle: diff.subtitle.as_deref(),
-        files,
-        rows,
-        total_additions: diff.files.iter().map(|file| file.additions).sum(),
-        total_deletions: diff.files.iter().map(|file| file.deletions).sum(),
-    }
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    Json(files).into_response()
 }
 
-fn push_diff_data_rows<'a>(
-    file_index: usize,
-    unified_diff: &str,
-    rows: &mut Vec<GitDiffDataRow<'a>>,
-) {
-    let mut old_line_no: Option<usize> = None;
-    let mut new_line_no: Option<usize> = None;
-    let mut pending_deletes: Vec<(usize, &str)> = Vec::new();
-    let mut pending_inserts: Vec<(usize, &str)> = Vec::new();
+#[derive(Deserialize)]
+struct CreateFileRequest {
+    path: String,
+    content: Option<String>,
+}
 
-    for raw_line in unified_diff.split_inclusive('\n') {
-        let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
-        if line.starts_with("@@ ") {
-            flush_diff_data_change_block(
-                rows,
-                file_index,
-                &mut pending_deletes,
-                &mut pending_inserts,
-            );
-            if let Some((old_start, new_start)) = parse_hunk_line_numbers(line) {
-                old_line_no = Some(old_start);
-                new_line_no = Some(new_start);
-            }
-            push_diff_data_meta_line(rows, file_index, line);
-            continue;
-        }
+#[derive(Serialize)]
+struct CreateFileResponse {
+    success: bool,
+    message: String,
+    url: Option<String>,
+}
 
-        if is_diff_delete_line(line) {
-            let current = old_line_no.unwrap_or(0);
-            pending_deletes.push((current, line));
-            if let Some(line_no) = old_line_no.as_mut() {
-                *line_no += 1;
-            }
-            continue;
-        }
-        if is_diff_insert_line(line) {
-            let current = new_line_no.unwrap_or(0);
-            pending_inserts.push((current, line));
-            if let Some(line_no) = new_line_no.as_mut() {
-                *line_no += 1;
-            }
-            continue;
+async fn handle_workspace_create_file(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<CreateFileRequest>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(root) = ws.fs.directory_root() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Edit feature is not enabled".to_string(),
+            url: None,
+        })
+        .into_response();
+    }
+    let Some(rel) = sanitize_new_file_path(&payload.path) else {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Invalid file path".to_string(),
+            url: None,
+        })
+        .into_response();
+    };
+    let full_path = root.join(&rel);
+    if fs::symlink_metadata(&full_path).is_ok() {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "File already exists".to_string(),
+            url: None,
+        })
+        .into_response();
+    }
+    // Verify containment BEFORE creating any directory, so a symlinked
+    // intermediate cannot cause `create_dir_all` to materialize dirs outside
+    // the workspace root.
+    if !deepest_existing_ancestor_inside_workspace(&full_path, root) {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Access denied".to_string(),
+            url: None,
+        })
+        .into_response();
+    }
+    if let Some(parent) = full_path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            return Json(CreateFileResponse {
+                success: false,
+                message: format!("Failed to create directory: {e}"),
+                url: None,
+            })
+            .into_response();
         }
-        flush_diff_data_change_block(rows, file_index, &mut pending_deletes, &mut pending_inserts);
-
-        if let Some(body) = line.strip_prefix(' ') {
-            push_diff_data_split_line(
-                rows,
-                file_index,
-                GitDiffDataSide {
-                    line_no: old_line_no,
-                    class_name: "git-diff-line",
-                    segments: diff_data_plain_segments(body),
-                },
-                GitDiffDataSide {
-                    line_no: new_line_no,
-                    class_name: "git-diff-line",
-                    segments: diff_data_plain_segments(body),
-                },
-            );
-            if let Some(line_no) = old_line_no.as_mut() {
-                *line_no += 1;
-            }
-            if let Some(line_no) = new_line_no.as_mut() {
-                *line_no += 1;
+    }
+    // Defense in depth: confirm the resolved parent still lands inside the
+    // workspace after creation.
+    if let Some(parent) = full_path.parent() {
+        match canonicalize_route_path(parent) {
+            Ok(parent) if is_inside_workspace(&parent, root) => {}
+            _ => {
+                return Json(CreateFileResponse {
+                    success: false,
+                    message: "Access denied".to_string(),
+                    url: None,
+                })
+                .into_response()
             }
-        } else {
-            push_diff_data_meta_line(rows, file_index, line);
         }
     }
-    flush_diff_data_change_block(rows, file_index, &mut pending_deletes, &mut pending_inserts);
-}
-
-fn flush_diff_data_change_block<'a>(
-    rows: &mut Vec<GitDiffDataRow<'a>>,
-    file_index: usize,
-    deletes: &mut Vec<(usize, &str)>,
-    inserts: &mut Vec<(usize, &str)>,
-) {
-    if deletes.is_empty() && inserts.is_empty() {
-        return;
-    }
-    let pairs = deletes.len().max(inserts.len());
-    for index in 0..pairs {
-        let old_line = deletes.get(index).copied();
-        let new_line = inserts.get(index).copied();
-        match (old_line, new_line) {
-            (Some((old_no, old_line)), Some((new_no, new_line))) => {
-                push_diff_data_word_split_line(rows, file_index, old_no, old_line, new_no, new_line)
-            }
-            (Some((old_no, old_line)), None) => push_diff_data_split_line(
-                rows,
-                file_index,
-                GitDiffDataSide {
-                    line_no: Some(old_no),
-                    class_name: "git-diff-line git-diff-del",
-                    segments: diff_data_plain_segments(diff_line_body(old_line)),
-                },
-                GitDiffDataSide {
-                    line_no: None,
-                    class_name: "git-diff-line git-diff-empty-side",
-                    segments: vec![GitDiffDataSegment::blank()],
-                },
-            ),
-            (None, Some((new_no, new_line))) => push_diff_data_split_line(
-                rows,
-                file_index,
-                GitDiffDataSide {
-                    line_no: None,
-                    class_name: "git-diff-line git-diff-empty-side",
-                    segments: vec![GitDiffDataSegment::blank()],
-                },
-                GitDiffDataSide {
-                    line_no: Some(new_no),
-                    class_name: "git-diff-line git-diff-add",
-                    segments: diff_data_plain_segments(diff_line_body(new_line)),
-                },
-            ),
-            (None, None) => {}
-        }
+    let content = payload.content.unwrap_or_default();
+    let write_result = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&full_path)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, content.as_bytes()));
+    if let Err(e) = write_result {
+        return Json(CreateFileResponse {
+            success: false,
+            message: format!("Failed to create file: {e}"),
+            url: None,
+        })
+        .into_response();
     }
-    deletes.clear();
-    inserts.clear();
+    let route = path_to_route(&rel);
+    Json(CreateFileResponse {
+        success: true,
+        message: "File created".to_string(),
+        url: Some(workspace_file_url(&state.base_path, &workspace_id, &route)),
+    })
+    .into_response()
 }
 
-fn push_diff_data_word_split_line<'a>(
-    rows: &mut Vec<GitDiffDataRow<'a>>,
-    file_index: usize,
-    old_line_no: usize,
-    old_line: &str,
-    new_line_no: usize,
-    new_line: &str,
-) {
-    let mut old_segments = Vec::new();
-    let mut new_segments = Vec::new();
-    let word_diff = TextDiff::from_words(diff_line_body(old_line), diff_line_body(new_line));
-    for change in word_diff.iter_all_changes() {
-        match change.tag() {
-            ChangeTag::Delete => {
-                old_segments.push(GitDiffDataSegment {
-                    text: change.value().to_string(),
-                    class_name: Some("git-diff-word-del"),
-                });
-            }
-            ChangeTag::Insert => {
-                new_segments.push(GitDiffDataSegment {
-                    text: change.value().to_string(),
-                    class_name: Some("git-diff-word-add"),
-                });
-            }
-            ChangeTag::Equal => {
-                old_segments.push(GitDiffDataSegment {
-                    text: change.value().to_string(),
-                    class_name: None,
-                });
-                new_segments.push(GitDiffDataSegment {
-                    text: change.value().to_string(),
-                    class_name: None,
-                });
-            }
-        }
+/// Create an empty folder inside the workspace. Reuses {@link CreateFileRequest}
+/// (the `content` field is ignored). Same edit gate + traversal-safety as
+/// file creation; `create_dir_all` so intermediate folders are made too.
+async fn handle_workspace_create_folder(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<CreateFileRequest>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(root) = ws.fs.directory_root() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Edit feature is not enabled".to_string(),
+            url: None,
+        })
+        .into_response();
     }
-    if old_segments.is_empty() {
-        old_segments.push(GitDiffDataSegment::blank());
+    let Some(rel) = sanitize_new_file_path(&payload.path) else {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Invalid folder path".to_string(),
+            url: None,
+        })
+        .into_response();
+    };
+    let full_path = root.join(&rel);
+    if fs::symlink_metadata(&full_path).is_ok() {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Folder already exists".to_string(),
+            url: None,
+        })
+        .into_response();
     }
-    if new_segments.is_empty() {
-        new_segments.push(GitDiffDataSegment::blank());
+    // Verify containment BEFORE creating any directory, so a symlinked
+    // intermediate cannot cause `create_dir_all` to materialize dirs outside
+    // the workspace root.
+    if !deepest_existing_ancestor_inside_workspace(&full_path, root) {
+        return Json(CreateFileResponse {
+            success: false,
+            message: "Access denied".to_string(),
+            url: None,
+        })
+        .into_response();
     }
-    push_diff_data_split_line(
-        rows,
-        file_index,
-        GitDiffDataSide {
-            line_no: Some(old_line_no),
-            class_name: "git-diff-line git-diff-del",
-            segments: old_segments,
-        },
-        GitDiffDataSide {
-            line_no: Some(new_line_no),
-            class_name: "git-diff-line git-diff-add",
-            segments: new_segments,
-        },
-    );
-}
-
-fn push_diff_data_meta_line<'a>(rows: &mut Vec<GitDiffDataRow<'a>>, file_index: usize, line: &str) {
-    push_diff_data_split_line(
-        rows,
-        file_index,
-        GitDiffDataSide {
-            line_no: None,
-            class_name: neutral_diff_line_class(line),
-            segments: diff_data_plain_segments(line),
-        },
-        GitDiffDataSide {
-            line_no: None,
-            class_name: neutral_diff_line_class(line),
-            segments: diff_data_plain_segments(line),
-        },
-    );
-}
-
-fn push_diff_data_split_line<'a>(
-    rows: &mut Vec<GitDiffDataRow<'a>>,
-    file_index: usize,
-    old: GitDiffDataSide,
-    new: GitDiffDataSide,
-) {
-    rows.push(GitDiffDataRow::Line {
-        file_index,
-        old_line_no: old.line_no,
-        new_line_no: new.line_no,
-        old_class_name: old.class_name,
-        new_class_name: new.class_name,
-        old_segments: old.segments,
-        new_segments: new.segments,
-    });
-}
-
-fn diff_line_body(line: &str) -> &str {
-    line.get(1..).unwrap_or("")
-}
-
-fn diff_data_plain_segments(text: &str) -> Vec<GitDiffDataSegment> {
-    vec![GitDiffDataSegment {
-        text: if text.is_empty() {
-            " ".to_string()
-        } else {
-            text.to_string()
-        },
-        class_name: None,
-    }]
-}
-
-impl GitDiffDataSegment {
-    fn blank() -> Self {
-        Self {
-            text: " ".to_string(),
-            class_name: None,
-        }
+    if let Err(e) = fs::create_dir_all(&full_path) {
+        return Json(CreateFileResponse {
+            success: false,
+            message: format!("Failed to create folder: {e}"),
+            url: None,
+        })
+        .into_response();
     }
-}
-
-fn parse_hunk_line_numbers(line: &str) -> Option<(usize, usize)> {
-    let mut parts = line.split_whitespace();
-    if parts.next()? != "@@" {
-        return None;
+    // Defense in depth: confirm the created folder resolved inside the workspace.
+    match canonicalize_route_path(&full_path) {
+        Ok(p) if is_inside_workspace(&p, root) => {}
+        _ => {
+            return Json(CreateFileResponse {
+                success: false,
+                message: "Access denied".to_string(),
+                url: None,
+            })
+            .into_response()
+        }
     }
-    let old = parse_hunk_start(parts.next()?, '-')?;
-    let new = parse_hunk_start(parts.next()?, '+')?;
-    Some((old, new))
+    Json(CreateFileResponse {
+        success: true,
+        message: "Folder created".to_string(),
+        url: None,
+    })
+    .into_response()
 }
 
-fn parse_hunk_start(token: &str, prefix: char) -> Option<usize> {
-    token
-        .strip_prefix(prefix)?
-        .split(',')
-        .next()?
-        .parse::<usize>()
-        .ok()
+#[derive(Deserialize)]
+struct DeleteFileRequest {
+    path: String,
 }
 
-#[cfg(test)]
-fn render_unified_diff_html(unified_diff: &str) -> String {
-    let mut out = String::new();
-    let mut pending_deletes: Vec<&str> = Vec::new();
-    let mut pending_inserts: Vec<&str> = Vec::new();
+#[derive(Serialize)]
+struct DeleteFileResponse {
+    success: bool,
+    message: String,
+}
 
-    for raw_line in unified_diff.split_inclusive('\n') {
-        let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
-        if is_diff_delete_line(line) {
-            pending_deletes.push(line);
-            continue;
-        }
-        if is_diff_insert_line(line) {
-            pending_inserts.push(line);
-            continue;
-        }
-        flush_diff_change_block(&mut out, &mut pending_deletes, &mut pending_inserts);
-        render_diff_line(&mut out, line, neutral_diff_line_class(line));
+async fn handle_workspace_delete_file(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<DeleteFileRequest>,
+) -> impl IntoResponse {
+    let fail = |message: &str| {
+        Json(DeleteFileResponse {
+            success: false,
+            message: message.to_string(),
+        })
+        .into_response()
+    };
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(root) = ws.fs.directory_root() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
+        return fail("Edit feature is not enabled");
+    }
+    let rel = payload.path.trim().trim_start_matches('/');
+    if rel.is_empty() || rel.contains('\0') {
+        return fail("Invalid file path");
+    }
+    let canon = match canonicalize_route_path(&root.join(rel)) {
+        Ok(p) if is_inside_workspace(&p, root) => p,
+        _ => return fail("Access denied"),
+    };
+    if !canon.is_file() {
+        return fail("Not a file");
+    }
+    // The workspace file watcher picks up the removal and updates the search
+    // index / notifies viewers, mirroring how create relies on the watcher.
+    match std::fs::remove_file(&canon) {
+        Ok(_) => Json(DeleteFileResponse {
+            success: true,
+            message: "File deleted".to_string(),
+        })
+        .into_response(),
+        Err(e) => fail(&format!("Failed to delete file: {e}")),
     }
-    flush_diff_change_block(&mut out, &mut pending_deletes, &mut pending_inserts);
-    out
 }
 
-fn is_diff_delete_line(line: &str) -> bool {
-    line.starts_with('-') && !line.starts_with("--- ")
+#[derive(Deserialize)]
+struct UpdateWorkspaceFeaturesRequest {
+    #[serde(flatten)]
+    flags: WorkspaceFlags,
 }
 
-fn is_diff_insert_line(line: &str) -> bool {
-    line.starts_with('+') && !line.starts_with("+++ ")
+#[derive(Serialize)]
+struct UpdateWorkspaceFeaturesResponse {
+    success: bool,
+    message: String,
 }
 
-#[cfg(test)]
-fn flush_diff_change_block<'a>(
-    out: &mut String,
-    deletes: &mut Vec<&'a str>,
-    inserts: &mut Vec<&'a str>,
-) {
-    if deletes.is_empty() && inserts.is_empty() {
-        return;
-    }
-    let pairs = deletes.len().max(inserts.len());
-    for index in 0..pairs {
-        let old_line = deletes.get(index).copied();
-        let new_line = inserts.get(index).copied();
-        match (old_line, new_line) {
-            (Some(old_line), Some(new_line)) => {
-                render_word_diff_line(out, old_line, new_line, ChangeTag::Delete);
-                render_word_diff_line(out, old_line, new_line, ChangeTag::Insert);
-            }
-            (Some(old_line), None) => {
-                render_diff_line(out, old_line, "git-diff-line git-diff-del");
-            }
-            (None, Some(new_line)) => {
-                render_diff_line(out, new_line, "git-diff-line git-diff-add");
-            }
-            (None, None) => {}
-        }
+async fn handle_workspace_update_features(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<UpdateWorkspaceFeaturesRequest>,
+) -> Response {
+    if state
+        .workspace_registry
+        .update_flags(&workspace_id, payload.flags)
+    {
+        Json(UpdateWorkspaceFeaturesResponse {
+            success: true,
+            message: "Workspace features updated".to_string(),
+        })
+        .into_response()
+    } else {
+        (
+            StatusCode::NOT_FOUND,
+            Json(UpdateWorkspaceFeaturesResponse {
+                success: false,
+                message: "Workspace not found".to_string(),
+            }),
+        )
+            .into_response()
     }
-    deletes.clear();
-    inserts.clear();
 }
 
-#[cfg(test)]
-fn render_word_diff_line(out: &mut String, old_line: &str, new_line: &str, side: ChangeTag) {
-    let (line, line_class, word_class) = match side {
-        ChangeTag::Delete => (old_line, "git-diff-line git-diff-del", "git-diff-word-del"),
-        ChangeTag::Insert => (new_line, "git-diff-line git-diff-add", "git-diff-word-add"),
-        ChangeTag::Equal => (old_line, "git-diff-line", ""),
-    };
-    let body = line.get(1..).unwrap_or("");
-    out.push_str("<span class=\"");
-    out.push_str(line_class);
-    out.push_str("\">");
-    push_escaped(out, &line[..line.len().min(1)]);
-
-    let word_diff = TextDiff::from_words(
-        old_line.get(1..).unwrap_or(""),
-        new_line.get(1..).unwrap_or(""),
-    );
-    for change in word_diff.iter_all_changes() {
-        match (side, change.tag()) {
-            (ChangeTag::Delete, ChangeTag::Delete) | (ChangeTag::Insert, ChangeTag::Insert) => {
-                out.push_str("<span class=\"");
-                out.push_str(word_class);
-                out.push_str("\">");
-                push_escaped(out, change.value());
-                out.push_str("</span>");
-            }
-            (ChangeTag::Delete, ChangeTag::Equal) | (ChangeTag::Insert, ChangeTag::Equal) => {
-                push_escaped(out, change.value());
-            }
-            _ => {}
-        }
-    }
-    if body.is_empty() {
-        out.push(' ');
-    }
-    out.push_str("</span>\n");
+#[derive(Deserialize)]
+struct UpdateWorkspaceAliasRequest {
+    #[serde(default)]
+    alias: String,
 }
 
-fn neutral_diff_line_class(line: &str) -> &'static str {
-    if line.starts_with("@@ ")
-        || line.starts_with("--- ")
-        || line.starts_with("+++ ")
-        || line.starts_with("Binary files differ:")
-        || line.starts_with("rename from ")
-        || line.starts_with("rename to ")
-        || line.starts_with("\\ No newline")
+/// Set/clear a workspace's alias from the web (directory page). Gated by
+/// `require_admin_role` + `require_same_origin` (NOT the master token), so the
+/// browser can use its narrow, HttpOnly admin session without exposing the
+/// native management bearer.
+async fn handle_workspace_update_alias(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Json(payload): Json<UpdateWorkspaceAliasRequest>,
+) -> Response {
+    if state
+        .workspace_registry
+        .set_alias(&workspace_id, payload.alias.trim())
     {
-        "git-diff-line git-diff-meta"
+        Json(serde_json::json!({ "success": true })).into_response()
     } else {
-        "git-diff-line"
+        (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "success": false, "message": "Workspace not found" })),
+        )
+            .into_response()
     }
 }
 
-#[cfg(test)]
-fn render_diff_line(out: &mut String, line: &str, class_name: &str) {
-    out.push_str("<span class=\"");
-    out.push_str(class_name);
-    out.push_str("\">");
-    push_escaped(out, line);
-    if line.is_empty() {
-        out.push(' ');
+// ── Search handler ────────────────────────────────────────────────────────────
+
+async fn workspace_search_handler(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    query: Result<Query<SearchQuery>, axum::extract::rejection::QueryRejection>,
+) -> Response {
+    let role = role.map(|Extension(role)| role);
+    // Malformed parameters (e.g. `limit=abc`) get the same JSON error body
+    // as out-of-range values rather than axum's plain-text rejection.
+    let Query(query) = match query {
+        Ok(query) => query,
+        Err(rejection) => return search_bad_request(rejection.body_text()),
+    };
+    match workspace_search_results(&state, &workspace_id, role, &query).await {
+        Ok(page) => page.into_response(),
+        Err(message) => search_bad_request(message),
     }
-    out.push_str("</span>\n");
 }
 
-#[cfg(test)]
-fn push_escaped(out: &mut String, text: &str) {
-    html_escape::encode_text_to_string(text, out);
+fn search_bad_request(message: String) -> Response {
+    (
+        StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({ "success": false, "message": message })),
+    )
+        .into_response()
 }
 
-fn git_diff_ref_values(root: &FsPath, diff: &git::GitDiff) -> (String, String) {
-    if diff.range == "HEAD..worktree" {
-        return ("HEAD".to_string(), "worktree".to_string());
+/// Run a search request. `Err` carries the message for a 400 response when a
+/// parameter is invalid.
+async fn workspace_search_results(
+    state: &AppState,
+    workspace_id: &str,
+    role: Option<AccessRole>,
+    query: &SearchQuery,
+) -> Result<Json<SearchPage>, String> {
+    let offset = query.offset;
+    if offset > MAX_SEARCH_OFFSET {
+        return Err(format!(
+            "offset must be at most {MAX_SEARCH_OFFSET}, got {offset}"
+        ));
     }
-    if let Some((base, compare)) = diff.range.split_once("..") {
-        return (base.to_string(), compare.to_string());
+    let max_limit = state.search_max_limit;
+    let limit = match query.limit {
+        None => DEFAULT_SEARCH_LIMIT.min(max_limit),
+        Some(limit) if (1..=max_limit).contains(&limit) => limit,
+        Some(limit) => {
+            return Err(format!(
+                "limit must be between 1 and {max_limit}, got {limit}"
+            ))
+        }
+    };
+    let empty = || {
+        Json(SearchPage {
+            offset,
+            limit,
+            ..SearchPage::default()
+        })
+    };
+    let filters = query.filters()?;
+    let snippet = query.snippet_options()?;
+    if query.q.is_empty() {
+        return Ok(empty());
     }
-    let base = git::parent_commit(root, &diff.range)
-        .ok()
-        .flatten()
-        .unwrap_or_else(|| "HEAD".to_string());
-    (base, diff.range.clone())
-}
-
-#[derive(Clone, Copy)]
-enum GitCompareOptionRole {
-    Base,
-    Compare,
-}
-
-#[derive(Clone, Copy)]
-enum GitCompareOptionStatusMode {
-    Fast,
-    Checked,
-}
-
-fn git_compare_option_has_markdown_changes(
-    root: &FsPath,
-    value: &str,
-    other_ref: &str,
-    role: GitCompareOptionRole,
-) -> bool {
-    let (base, compare) = match role {
-        GitCompareOptionRole::Base => (value, other_ref),
-        GitCompareOptionRole::Compare => (other_ref, value),
+    let Some(ws) = state.workspace_registry.get(workspace_id) else {
+        return Ok(empty());
     };
-    if base == compare {
-        return false;
-    }
-    // Refs here come from a trusted enumeration (HEAD / history / worktree), so
-    // skip re-validation and run just the single diff probe.
-    git::diff_has_markdown_changes_unchecked(root, base, compare).unwrap_or(true)
-}
-
-fn git_compare_option_statuses(options: Vec<GitCompareOption>) -> Vec<GitCompareOptionStatus> {
-    options
-        .into_iter()
-        .map(|option| GitCompareOptionStatus {
-            value: option.value,
-            disabled: option.disabled,
+    if !ws.enable_search.load(std::sync::atomic::Ordering::Relaxed) {
+        return Ok(empty());
+    }
+    if query.mode == SearchMode::Regex {
+        // The regex scan reads files directly, so it works before the index
+        // has finished building.
+        let ws = ws.clone();
+        let pattern = query.q.clone();
+        let page = tokio::task::spawn_blocking(move || {
+            crate::search::regex_search_page(
+                &ws.fs,
+                &ws.search_config.exclude,
+                &ws.metadata_cache,
+                &pattern,
+                &filters,
+                &snippet,
+                offset,
+                limit,
+            )
         })
-        .collect()
-}
-
-fn git_compare_options(
-    root: &FsPath,
-    selected: &str,
-    include_worktree: bool,
-    other_ref: &str,
-    role: GitCompareOptionRole,
-    status_mode: GitCompareOptionStatusMode,
-) -> Vec<GitCompareOption> {
-    // 1) Gather unique candidates in display order (a handful of cheap git
-    //    calls). `check` marks options whose "disabled" state needs a diff probe.
-    // A candidate carries the rich metadata the picker panel renders (kind /
-    // subject / detail / date), plus `check` = whether its disabled state needs a
-    // markdown-diff probe.
-    struct Cand {
-        value: String,
-        label: String,
-        alias: String,
-        kind: String,
-        subject: String,
-        detail: String,
-        date: String,
-        check: bool,
-    }
-    let mut seen = std::collections::BTreeSet::new();
-    let mut candidates: Vec<Cand> = Vec::new();
-    let mut add = |c: Cand| {
-        if seen.insert(c.value.clone()) {
-            candidates.push(c);
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("regex search blocking task join error: {e}");
+            Ok(SearchPage::default())
+        })
+        .map_err(|e| format!("invalid regex: {e}"))?;
+        return Ok(Json(SearchPage {
+            offset,
+            limit,
+            ..page
+        }));
+    }
+    let page = match ws.search_index.load_full() {
+        // Tantivy search is CPU/IO-bound; run it on the blocking pool so it
+        // does not stall a tokio worker thread.
+        Some(idx) => {
+            let query_owned = query.q.clone();
+            let snippet = snippet.clone();
+            tokio::task::spawn_blocking(move || {
+                idx.search_page_with(&query_owned, &filters, &snippet, offset, limit)
+            })
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!("search blocking task join error: {e}");
+                Ok(SearchPage::default())
+            })
+            .unwrap_or_else(|e| {
+                tracing::warn!("search error: {e}");
+                SearchPage::default()
+            })
+        }
+        None => SearchPage::default(), // still indexing
+    };
+    // Annotations are a second, much smaller source; attach them once, to the
+    // first page, and only where the reader could open them on the document.
+    let annotations = match (state.document_store(), offset) {
+        (Some(store), 0)
+            if ws
+                .shared_annotation
+                .load(std::sync::atomic::Ordering::Relaxed)
+                && document_state_access_allowed(role, &ws) =>
+        {
+            search_annotations(store, ws.clone(), query.q.clone(), snippet).await
         }
+        _ => Vec::new(),
     };
+    Ok(Json(SearchPage {
+        offset,
+        limit,
+        annotations,
+        ..page
+    }))
+}
 
-    add(Cand {
-        value: "HEAD".to_string(),
-        label: "HEAD".to_string(),
-        alias: String::new(),
-        kind: "head".to_string(),
-        subject: "Latest commit".to_string(),
-        detail: String::new(),
-        date: String::new(),
-        check: true,
-    });
-    for branch in git::branches(root).unwrap_or_default() {
-        let label = if branch.current {
-            format!("{} (current)", branch.name)
-        } else {
-            branch.name.clone()
-        };
-        add(Cand {
-            value: branch.name,
-            label,
-            alias: String::new(),
-            kind: "branch".to_string(),
-            subject: String::new(),
-            detail: if branch.current {
-                "current".to_string()
-            } else {
-                String::new()
-            },
-            date: String::new(),
-            check: false,
-        });
-    }
-    for (idx, commit) in git::history(root, 50)
-        .unwrap_or_default()
-        .into_iter()
-        .enumerate()
-    {
-        add(Cand {
-            value: commit.hash,
-            label: format!("{} {}", commit.short_hash, commit.subject),
-            alias: if idx == 0 {
-                "Latest".to_string()
-            } else {
-                String::new()
-            },
-            kind: "commit".to_string(),
-            subject: commit.subject,
-            detail: commit.short_hash,
-            date: commit.relative_time,
-            check: true,
-        });
-    }
-    for tag in git::tags(root, 200).unwrap_or_default() {
-        add(Cand {
-            value: tag.name.clone(),
-            label: format!("{} (tag)", tag.name),
-            alias: String::new(),
-            kind: "tag".to_string(),
-            subject: String::new(),
-            detail: tag.short_hash,
-            date: tag.relative_time,
-            check: false,
-        });
-    }
-    if include_worktree {
-        add(Cand {
-            value: "worktree".to_string(),
-            label: "Worktree".to_string(),
-            alias: String::new(),
-            kind: "worktree".to_string(),
-            subject: "Uncommitted working-tree files".to_string(),
-            detail: String::new(),
-            date: String::new(),
-            check: true,
-        });
-    }
+/// Report how many documents the workspace index holds, when it last
+/// committed, and whether watcher updates are still queued on the writer.
+async fn workspace_search_status_handler(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> Response {
+    let ws = match searchable_workspace(&state, &workspace_id) {
+        Ok(ws) => ws,
+        Err(response) => return *response,
+    };
+    let Some(idx) = ws.search_index.load_full() else {
+        // Still building the initial index.
+        return Json(SearchStatus::default()).into_response();
+    };
+    search_status_response(idx, false).await
+}
 
-    // 2) Resolve each option's `disabled` state in parallel. Every probe spawns
-    //    its own git subprocess(es); the bottleneck is process spawn, so this
-    //    fans the ~dozens of probes across cores instead of running serially.
-    let checked = matches!(status_mode, GitCompareOptionStatusMode::Checked);
-    let mut out: Vec<GitCompareOption> = candidates
-        .par_iter()
-        .map(|c| {
-            let disabled = checked
-                && c.check
-                && c.value != selected
-                && !git_compare_option_has_markdown_changes(root, &c.value, other_ref, role);
-            GitCompareOption {
-                selected: c.value == selected,
-                value: c.value.clone(),
-                label: c.label.clone(),
-                alias: c.alias.clone(),
-                kind: c.kind.clone(),
-                subject: c.subject.clone(),
-                detail: c.detail.clone(),
-                date: c.date.clone(),
-                disabled,
-            }
+/// List frontmatter tags with file counts, for tag browsing and completion.
+async fn workspace_search_tags_handler(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> Response {
+    let ws = match searchable_workspace(&state, &workspace_id) {
+        Ok(ws) => ws,
+        Err(response) => return *response,
+    };
+    let Some(idx) = ws.search_index.load_full() else {
+        return Json(Vec::<TagCount>::new()).into_response();
+    };
+    let tags = tokio::task::spawn_blocking(move || idx.tag_counts())
+        .await
+        .unwrap_or_else(|e| {
+            tracing::error!("search tags blocking task join error: {e}");
+            Ok(Vec::new())
         })
-        .collect();
-
-    if !selected.is_empty() && !seen.contains(selected) {
-        out.insert(
-            0,
-            GitCompareOption {
-                value: selected.to_string(),
-                label: short_git_ref(selected),
-                alias: String::new(),
-                kind: "commit".to_string(),
-                subject: String::new(),
-                detail: short_git_ref(selected),
-                date: String::new(),
-                selected: true,
-                disabled: false,
-            },
-        );
-    }
-
-    out
+        .unwrap_or_else(|e| {
+            tracing::warn!("search tags error: {e}");
+            Vec::new()
+        });
+    Json(tags).into_response()
 }
 
-const GIT_EMPTY_TREE_HASH: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
-
-fn git_commit_compare_base(root: &FsPath, commit: &str) -> String {
-    git::parent_commit(root, commit)
-        .ok()
-        .flatten()
-        .unwrap_or_else(|| GIT_EMPTY_TREE_HASH.to_string())
+/// Re-walk the workspace and replace the whole index, for changes the file
+/// watcher missed (e.g. a large checkout or rebase). Responds once the new
+/// index is committed.
+async fn workspace_search_rebuild_handler(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+) -> Response {
+    let ws = match searchable_workspace(&state, &workspace_id) {
+        Ok(ws) => ws,
+        Err(response) => return *response,
+    };
+    let Some(idx) = ws.search_index.load_full() else {
+        return (
+            StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "success": false,
+                "message": "Search index is still building",
+            })),
+        )
+            .into_response();
+    };
+    search_status_response(idx, true).await
 }
 
-fn git_commit_markdown_diff_url(
-    root: &FsPath,
+fn searchable_workspace(
+    state: &AppState,
     workspace_id: &str,
-    commit: &git::GitCommit,
-    view: &str,
-) -> Option<String> {
-    let diff = git::commit_diff(root, &commit.hash).ok()?;
-    if markdown_only_git_diff(&diff, None).files.is_empty() {
-        return None;
+) -> Result<Arc<WorkspaceEntry>, Box<Response>> {
+    let Some(ws) = state.workspace_registry.get(workspace_id) else {
+        return Err(Box::new(
+            (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "success": false, "message": "Workspace not found" })),
+            )
+                .into_response(),
+        ));
+    };
+    if !ws.enable_search.load(std::sync::atomic::Ordering::Relaxed) {
+        return Err(Box::new(
+            (
+                StatusCode::NOT_FOUND,
+                Json(serde_json::json!({ "success": false, "message": "Search is disabled" })),
+            )
+                .into_response(),
+        ));
     }
-    Some(pretty_compare_page_url(
-        workspace_id,
-        &git_commit_compare_base(root, &commit.hash),
-        &commit.hash,
-        view,
-    ))
+    Ok(ws)
 }
 
-fn git_source_diff_url(
-    workspace_id: &str,
-    root: &FsPath,
-    diff: &git::GitDiff,
-    base: &str,
-    compare: &str,
-) -> String {
-    if diff.range == "HEAD..worktree" {
-        pretty_compare_page_url(workspace_id, "HEAD", "worktree", "raw")
-    } else if diff.range.contains("..") {
-        pretty_compare_page_url(workspace_id, base, compare, "raw")
-    } else {
-        pretty_compare_page_url(
-            workspace_id,
-            &git_commit_compare_base(root, &diff.range),
-            &diff.range,
-            "raw",
-        )
+/// Optionally rebuild `idx`, then report its status. Both walk or count the
+/// whole index, so they run on the blocking pool.
+async fn search_status_response(idx: Arc<SearchIndex>, rebuild: bool) -> Response {
+    let status = tokio::task::spawn_blocking(move || {
+        if rebuild {
+            idx.rebuild()?;
+        }
+        idx.status()
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("search status blocking task join error: {e}");
+        Err(tantivy::TantivyError::SystemError(e.to_string()))
+    });
+    match status {
+        Ok(status) => Json(status).into_response(),
+        Err(e) => {
+            tracing::error!("search status failed: {e}");
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({ "success": false, "message": e.to_string() })),
+            )
+                .into_response()
+        }
     }
 }
 
-fn markdown_work_diff_page_url(workspace_id: &str) -> String {
-    pretty_compare_page_url(workspace_id, "HEAD", "worktree", "rendered")
+/// Shared annotations in `ws` whose note or quoted text matches `query`.
+/// Annotation rows are keyed by absolute path, so each is mapped back to a
+/// workspace route; rows for files that no longer resolve are skipped.
+async fn search_annotations(
+    store: Arc<dyn crate::store::DocumentStore>,
+    ws: Arc<WorkspaceEntry>,
+    query: String,
+    snippet: crate::search::SnippetOptions,
+) -> Vec<crate::search::AnnotationHit> {
+    let rows = match store.all_annotations().await {
+        Ok(rows) => rows,
+        Err(e) => {
+            tracing::error!("search_annotations: query failed: {e}");
+            return Vec::new();
+        }
+    };
+    // Resolving routes touches the filesystem.
+    tokio::task::spawn_blocking(move || {
+        let root = ws.fs.capability_root().to_string_lossy().into_owned();
+        let mut rows: Vec<_> = rows
+            .into_iter()
+            .filter(|row| row.file_path.starts_with(&root))
+            .collect();
+        rows.sort_by(|a, b| (&a.file_path, &a.id).cmp(&(&b.file_path, &b.id)));
+        let mut routes: HashMap<String, Option<String>> = HashMap::new();
+        let mut hits = Vec::new();
+        for crate::annotations::StoredAnnotation {
+            file_path,
+            annotation,
+            ..
+        } in rows
+        {
+            let route = routes
+                .entry(file_path.clone())
+                .or_insert_with(|| ws.fs.route_for_path(FsPath::new(&file_path)));
+            let Some(route) = route else {
+                continue;
+            };
+            if let Some(hit) = crate::search::match_annotation(&query, route, &annotation, &snippet)
+            {
+                hits.push(hit);
+                if hits.len() >= crate::search::MAX_ANNOTATION_HITS {
+                    break;
+                }
+            }
+        }
+        hits
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("search_annotations join error: {e}");
+        Vec::new()
+    })
 }
 
-fn markdown_diff_page_url(
-    workspace_id: &str,
-    root: &FsPath,
-    diff: &git::GitDiff,
-    base: &str,
-    compare: &str,
-) -> String {
-    if diff.range == "HEAD..worktree" {
-        markdown_work_diff_page_url(workspace_id)
-    } else if diff.range.contains("..") {
-        pretty_compare_page_url(workspace_id, base, compare, "rendered")
-    } else {
-        pretty_compare_page_url(
-            workspace_id,
-            &git_commit_compare_base(root, &diff.range),
-            &diff.range,
-            "rendered",
-        )
-    }
+/// Context pre-seeded with the page-independent keys shared by every template
+/// (extra keys are ignored by templates that don't reference them).
+fn base_context(state: &AppState) -> tera::Context {
+    let mut context = tera::Context::new();
+    context.insert("base_path", state.base_path.as_ref());
+    context.insert("theme", state.theme.as_str());
+    context.insert("custom_theme", &state.custom_theme);
+    context.insert("i18n_json", state.i18n_json.as_str());
+    context.insert("i18n_lang", state.i18n_lang.as_str());
+    context.insert("shortcuts_json", state.shortcuts_json.as_str());
+    context.insert("styles_css", state.styles_css.as_str());
+    context.insert("user_stylesheets", state.user_stylesheets.as_slice());
+    context.insert("user_scripts", state.user_scripts.as_slice());
+    context.insert("default_chat_mode", state.default_chat_mode.as_str());
+    context.insert("print_collapsed_content", &state.print_collapsed_content);
+    context.insert("require_name", &state.require_name);
+    context.insert(
+        "annotation_token_required",
+        &state.annotation_token.is_some(),
+    );
+    context
 }
 
-fn pretty_compare_page_url(workspace_id: &str, base: &str, compare: &str, view: &str) -> String {
-    format!(
-        "{}/{}...{}?view={}",
-        workspace_compare_base_url(workspace_id),
-        encode_compare_ref_for_path(base),
-        encode_compare_ref_for_path(compare),
-        view
-    )
+/// Render a template, mapping failure to a 500 with the error text.
+fn render_template(state: &AppState, name: &str, context: &tera::Context) -> Response {
+    match state.tera.render(name, context) {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Template error: {e}"),
+        )
+            .into_response(),
+    }
 }
 
-fn pretty_compare_data_url(workspace_id: &str, base: &str, compare: &str, view: &str) -> String {
-    format!(
-        "{}&format=data",
-        pretty_compare_page_url(workspace_id, base, compare, view)
-    )
+#[derive(Serialize)]
+struct GitDiffTemplate<'a> {
+    range: &'a str,
+    title: &'a str,
+    subtitle: Option<&'a str>,
+    mode_label: String,
+    base_label: String,
+    compare_label: String,
+    base_value: String,
+    compare_value: String,
+    files: Vec<GitDiffFileTemplate<'a>>,
+    nav_entries: Vec<GitDiffNavEntry<'a>>,
+    total_additions: usize,
+    total_deletions: usize,
 }
 
-fn encode_compare_ref_for_path(value: &str) -> String {
-    urlencoding::encode(value).replace("%2F", "/")
+#[derive(Serialize)]
+struct GitDiffFileTemplate<'a> {
+    path: &'a str,
+    old_path: Option<&'a str>,
+    status: &'a str,
+    additions: usize,
+    deletions: usize,
 }
 
-fn markdown_diff_data_url(workspace_id: &str, base: &str, compare: &str) -> String {
-    pretty_compare_data_url(workspace_id, base, compare, "rendered")
+#[derive(Serialize)]
+struct GitDiffNavEntry<'a> {
+    kind: &'static str,
+    name: String,
+    path: String,
+    depth: usize,
+    status: Option<&'a str>,
+    additions: usize,
+    deletions: usize,
 }
 
-fn render_git_diff_page(
-    state: &AppState,
-    workspace_id: &str,
-    ws: &WorkspaceEntry,
-    can_manage: bool,
-    diff: &git::GitDiff,
-    initial_view: &str,
-) -> Response {
-    let flags = ws.flags();
-    let root = directory_root_or_not_found!(ws);
-    let (base_value, compare_value) = git_diff_ref_values(root, diff);
-    let initial_view = if initial_view == "rendered" {
-        "rendered"
-    } else {
-        "raw"
-    };
-    let display_diff = markdown_only_git_diff(diff, None);
-    // Default to the all-files continuous view (no file pre-selected). The left
-    // file list focuses a single file on demand; an empty default means "no `f`"
-    // renders every changed file in one scroll instead of just the first.
-    let default_diff_path = "";
-    let mut context = base_context(state);
-    context.insert("title", &format!("Markdiff · {}", diff.range));
-    context.insert("workspace_id", workspace_id);
-    context.insert(
-        "preview_token",
-        &workspace_preview_token(&state.save_token, workspace_id),
-    );
-    context.insert(
-        "diff",
-        &git_diff_template(
-            root,
-            &display_diff,
-            base_value.clone(),
-            compare_value.clone(),
-        ),
-    );
-    context.insert("can_manage", &can_manage);
-    context.insert("shared_annotation", &flags.shared_annotation);
-    context.insert("enable_live", &flags.enable_live);
-    context.insert("enable_chat", &flags.enable_chat);
-    context.insert("history_url", &workspace_git_history_url(workspace_id));
-    context.insert("files_url", &workspace_root_url(workspace_id));
-    // Home-collapsed workspace path shown beside the title (links to the home).
-    let ws_display_path = workspace_display_path(root);
-    context.insert("workspace_display_path", &ws_display_path);
-    context.insert("workspace_alias", &ws.alias());
-    context.insert("work_diff_url", &markdown_work_diff_page_url(workspace_id));
-    context.insert(
-        "markdown_diff_url",
-        &markdown_diff_page_url(workspace_id, root, diff, &base_value, &compare_value),
-    );
-    context.insert(
-        "source_diff_url",
-        &git_source_diff_url(workspace_id, root, diff, &base_value, &compare_value),
-    );
-    context.insert(
-        "compare_url",
-        &pretty_compare_page_url(workspace_id, &base_value, &compare_value, initial_view),
-    );
-    context.insert(
-        "compare_path_base",
-        &workspace_compare_base_url(workspace_id),
-    );
-    context.insert(
-        "compare_options_status_url",
-        &workspace_compare_options_url(workspace_id),
-    );
-    context.insert("initial_diff_view", initial_view);
-    context.insert("default_diff_path", &default_diff_path);
-    context.insert("is_markdown_diff", &(initial_view == "rendered"));
-    let base_options = git_compare_options(
-        root,
-        &base_value,
-        false,
-        &compare_value,
-        GitCompareOptionRole::Base,
-        GitCompareOptionStatusMode::Fast,
-    );
-    let compare_options = git_compare_options(
-        root,
-        &compare_value,
-        true,
-        &base_value,
-        GitCompareOptionRole::Compare,
-        GitCompareOptionStatusMode::Fast,
-    );
-    // One JSON bundle drives the custom Base↔Compare picker panel (rich rows with
-    // subject/date, quick presets, client-side navigation + status probing).
-    let picker_json = serde_json::json!({
-        "base": base_options,
-        "compare": compare_options,
-        "baseValue": base_value,
-        "compareValue": compare_value,
-        "pathBase": workspace_compare_base_url(workspace_id),
-        "statusUrl": workspace_compare_options_url(workspace_id),
-    })
-    .to_string();
-    context.insert("compare_picker_json", &picker_json);
-    // "Worktree-targeting" = the Compare (new) side is the live worktree, for ANY
-    // base (HEAD…Worktree, a-tag…Worktree, …). That new side is the set of real,
-    // writable files on disk — what annotations bind to and what the sidebar's
-    // create-file/folder writes into. (`worktree` is only ever offered as a
-    // Compare option, never a Base.) Comparing two commits never targets it.
-    let is_worktree_diff = compare_value == "worktree";
-    context.insert("is_worktree_diff", &is_worktree_diff);
-    // The sidebar tree can create files/folders only when the comparison targets
-    // the (writable) worktree AND the workspace permits editing.
-    let diff_editable =
-        is_worktree_diff && ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed);
-    context.insert("diff_editable", &diff_editable);
-    context.insert("create_file_url", &workspace_file_create_url(workspace_id));
-    context.insert(
-        "create_folder_url",
-        &workspace_folder_create_url(workspace_id),
-    );
-    // Both views (rendered + raw source) now consume one unified Markdown block
-    // payload, so a single data URL drives the whole page.
-    let markdown_diff_data_url = markdown_diff_data_url(workspace_id, &base_value, &compare_value);
-    context.insert("markdown_diff_data_url", &markdown_diff_data_url);
-    render_template(state, "git-diff.html", &context)
+#[derive(Serialize)]
+struct GitCompareOption {
+    value: String,
+    label: String,
+    /// Lightweight display alias for special refs/commits, e.g. the newest
+    /// concrete commit that is also reachable as HEAD.
+    alias: String,
+    /// Option family for the rich picker UI: worktree | head | branch | tag | commit.
+    kind: String,
+    /// Commit subject (commits only; "" otherwise).
+    subject: String,
+    /// Secondary detail — short hash for commits/tags, "current" for the current
+    /// branch, "" otherwise.
+    detail: String,
+    /// Relative time (commits/tags; "" otherwise).
+    date: String,
+    selected: bool,
+    disabled: bool,
 }
 
 #[derive(Serialize)]
-struct GitHistoryCommitTemplate<'a> {
-    short_hash: &'a str,
-    author: &'a str,
-    date: &'a str,
-    subject: &'a str,
-    diff_url: Option<String>,
+struct GitCompareOptionStatus {
+    value: String,
+    disabled: bool,
 }
 
-/// One calendar day worth of commits, matching GitHub's date-grouped Commits
-/// page. `commits` keeps the incoming reverse-chronological order.
 #[derive(Serialize)]
-struct GitHistoryDay<'a> {
-    day_label: String,
-    commits: Vec<GitHistoryCommitTemplate<'a>>,
+struct GitCompareOptionsStatus {
+    base: Vec<GitCompareOptionStatus>,
+    compare: Vec<GitCompareOptionStatus>,
 }
 
-/// Turn an ISO date like `2026-03-07T08:30:43+08:00` into GitHub's
-/// `Mar 7, 2026` heading. No new deps — the month is mapped by hand from the
-/// `MM` field. Falls back to the raw string if the date is malformed.
-fn git_history_day_label(date: &str) -> String {
-    if date.len() < 10 {
-        return date.to_string();
-    }
-    let year = &date[0..4];
-    let month = match &date[5..7] {
-        "01" => "Jan",
-        "02" => "Feb",
-        "03" => "Mar",
-        "04" => "Apr",
-        "05" => "May",
-        "06" => "Jun",
-        "07" => "Jul",
-        "08" => "Aug",
-        "09" => "Sep",
-        "10" => "Oct",
-        "11" => "Nov",
-        "12" => "Dec",
-        other => other,
-    };
-    let day = date[8..10].trim_start_matches('0');
-    let day = if day.is_empty() { "0" } else { day };
-    format!("{month} {day}, {year}")
+#[derive(Serialize)]
+struct GitDiffData<'a> {
+    range: &'a str,
+    title: &'a str,
+    subtitle: Option<&'a str>,
+    files: Vec<GitDiffDataFile<'a>>,
+    rows: Vec<GitDiffDataRow<'a>>,
+    total_additions: usize,
+    total_deletions: usize,
 }
 
-/// One selectable branch in the toolbar's branch dropdown; `current` marks the
-/// entry that matches the active `?branch=` (or the real checked-out branch).
 #[derive(Serialize)]
-struct GitBranchOption {
-    name: String,
-    current: bool,
+struct GitDiffDataFile<'a> {
+    path: &'a str,
+    old_path: Option<&'a str>,
+    status: &'a str,
+    additions: usize,
+    deletions: usize,
+    start_row: usize,
+    row_count: usize,
 }
 
-/// A time-range preset for the "All time" dropdown.
 #[derive(Serialize)]
-struct GitRangeOption {
-    key: &'static str,
-    label: &'static str,
-    current: bool,
+#[serde(tag = "kind")]
+enum GitDiffDataRow<'a> {
+    #[serde(rename = "file")]
+    File {
+        file_index: usize,
+        path: &'a str,
+        old_path: Option<&'a str>,
+        status: &'a str,
+        additions: usize,
+        deletions: usize,
+    },
+    #[serde(rename = "line")]
+    Line {
+        file_index: usize,
+        old_line_no: Option<usize>,
+        new_line_no: Option<usize>,
+        old_class_name: &'static str,
+        new_class_name: &'static str,
+        old_segments: Vec<GitDiffDataSegment>,
+        new_segments: Vec<GitDiffDataSegment>,
+    },
 }
 
-/// An author entry for the "All users" dropdown.
 #[derive(Serialize)]
-struct GitAuthorOption {
-    name: String,
-    current: bool,
+struct GitDiffDataSegment {
+    text: String,
+    class_name: Option<&'static str>,
 }
 
-fn render_git_history_page(
-    state: &AppState,
-    workspace_id: &str,
-    root: &FsPath,
-    commits: &[git::GitCommit],
-    selected_branch: Option<&str>,
-    selected_author: Option<&str>,
-    range_key: &str,
-) -> Response {
-    // Group commits by their `YYYY-MM-DD` prefix while preserving the incoming
-    // reverse-chronological order (commits are already sorted newest-first).
-    // Resolve every commit's parent + markdown-changed flag in one git pass, so
-    // the per-commit diff link below is a pure hashmap lookup rather than a
-    // `git show` + `rev-parse` subprocess pair per row.
-    let commit_hashes: Vec<&str> = commits.iter().map(|c| c.hash.as_str()).collect();
-    let diff_index = git::commit_diff_index(root, &commit_hashes).unwrap_or_default();
-    let mut groups: Vec<GitHistoryDay<'_>> = Vec::new();
-    let mut last_key: Option<&str> = None;
-    for commit in commits {
-        let key = commit.date.get(0..10).unwrap_or(commit.date.as_str());
-        let diff_url = diff_index
-            .get(&commit.hash)
-            .filter(|info| info.has_markdown)
-            .map(|info| {
-                pretty_compare_page_url(
-                    workspace_id,
-                    info.parent.as_deref().unwrap_or(GIT_EMPTY_TREE_HASH),
-                    &commit.hash,
-                    "rendered",
-                )
-            });
-        let item = GitHistoryCommitTemplate {
-            short_hash: &commit.short_hash,
-            author: &commit.author,
-            date: &commit.date,
-            subject: &commit.subject,
-            diff_url,
-        };
-        if last_key == Some(key) {
-            groups
-                .last_mut()
-                .expect("last_key set implies a prior group")
-                .commits
-                .push(item);
-        } else {
-            groups.push(GitHistoryDay {
-                day_label: git_history_day_label(&commit.date),
-                commits: vec![item],
-            });
-            last_key = Some(key);
-        }
-    }
-    // Real branch list + the name actually checked out, used both to build the
-    // dropdown and to label it when no explicit `?branch=` is selected.
-    let all_branches = git::branches(root).unwrap_or_default();
-    let checked_out = all_branches
-        .iter()
-        .find(|b| b.current)
-        .map(|b| b.name.clone())
-        .unwrap_or_else(|| "main".to_string());
-    // Only honour a `?branch=` that really exists (mirrors history_filtered's
-    // whitelist, so the label never lies about which rev was walked).
-    let active_branch = selected_branch
-        .filter(|name| all_branches.iter().any(|b| b.name == *name))
-        .map(str::to_string);
-    let current_branch_label = active_branch.clone().unwrap_or_else(|| checked_out.clone());
-    let branch_options: Vec<GitBranchOption> = all_branches
-        .iter()
-        .map(|b| GitBranchOption {
-            name: b.name.clone(),
-            current: b.name == current_branch_label,
-        })
-        .collect();
-
-    let author_list = git::authors(root).unwrap_or_default();
-    let author_options: Vec<GitAuthorOption> = author_list
-        .iter()
-        .map(|name| GitAuthorOption {
-            name: name.clone(),
-            current: selected_author == Some(name.as_str()),
-        })
-        .collect();
-    let current_author_label = selected_author
-        .map(str::to_string)
-        .unwrap_or_else(|| "All users".to_string());
+struct GitDiffDataSide {
+    line_no: Option<usize>,
+    class_name: &'static str,
+    segments: Vec<GitDiffDataSegment>,
+}
 
-    const RANGE_PRESETS: [(&str, &str); 5] = [
-        ("", "All time"),
-        ("day", "Last 24 hours"),
-        ("week", "Last 7 days"),
-        ("month", "Last 30 days"),
-        ("year", "Last 12 months"),
-    ];
-    let range_options: Vec<GitRangeOption> = RANGE_PRESETS
-        .iter()
-        .map(|(key, label)| GitRangeOption {
-            key,
-            label,
-            current: *key == range_key,
-        })
-        .collect();
-    let current_range_label = RANGE_PRESETS
-        .iter()
-        .find(|(key, _)| *key == range_key)
-        .map(|(_, label)| *label)
-        .unwrap_or("All time");
+#[derive(Serialize)]
+struct MarkdownDiffData {
+    title: String,
+    subtitle: Option<String>,
+    engine: markdown_ast::MarkdownAstEngineInfo,
+    files: Vec<MarkdownDiffFile>,
+}
 
-    let work_diff_url = git::diff_has_markdown_changes(root, "HEAD", "worktree")
-        .unwrap_or(false)
-        .then(|| markdown_work_diff_page_url(workspace_id));
-    let mut context = base_context(state);
-    context.insert("title", "markon git history");
-    context.insert("workspace_id", workspace_id);
-    context.insert("groups", &groups);
-    context.insert("commit_count", &commits.len());
-    context.insert("current_branch", &current_branch_label);
-    context.insert("current_branch_label", &current_branch_label);
-    context.insert("branches", &branch_options);
-    context.insert("authors", &author_options);
-    context.insert("current_author", &selected_author);
-    context.insert("current_author_label", &current_author_label);
-    context.insert("ranges", &range_options);
-    context.insert("current_range", &range_key);
-    context.insert("current_range_label", &current_range_label);
-    context.insert("files_url", &workspace_root_url(workspace_id));
-    context.insert("has_commits", &!groups.is_empty());
-    let filters_active = selected_author.is_some() || (!range_key.is_empty() && range_key != "all");
-    context.insert("filters_active", &filters_active);
-    context.insert("work_diff_url", &work_diff_url);
-    render_template(state, "git-history.html", &context)
+#[derive(Clone, Serialize)]
+struct MarkdownDiffFile {
+    path: String,
+    // Canonical absolute path of the new-side file on disk, byte-identical to the
+    // annotation `file_path` key that `render_markdown_file` builds when the same
+    // file is opened normally. The listing uses `git diff --relative`, so
+    // `entry.path` is workspace-relative; joining it onto the workspace root
+    // resolves the same key even when the workspace is a subdirectory of the repo.
+    abs_path: String,
+    old_path: Option<String>,
+    status: String,
+    // Slim per-side outline: the frontend only reads `block_count` and a
+    // per-block {index, kind, label} to compute heading-section indentation.
+    // The heavy rendered HTML lives on `blocks[].old/new` (rendered once), so
+    // shipping full per-side summaries here would triple the HTML payload.
+    old: Option<MarkdownDocOutline>,
+    new: Option<MarkdownDocOutline>,
+    // Full, untruncated source text of each side. The raw (source) diff view
+    // renders exact file lines from these, sliced by each block's line span, so
+    // both views share one block-based segmentation (and stay aligned). Absent
+    // when a side does not exist (added file has no old; deleted has no new).
+    old_source: Option<String>,
+    new_source: Option<String>,
+    additions: usize,
+    deletions: usize,
+    blocks: Vec<MarkdownDiffBlock>,
+    diagnostics: Vec<MarkdownDiffDiagnostic>,
 }
 
-fn render_git_branches_page(
-    state: &AppState,
-    workspace_id: &str,
-    branches: &[git::GitBranchDetail],
-) -> Response {
-    let mut context = base_context(state);
-    context.insert("title", "markon git branches");
-    context.insert("workspace_id", workspace_id);
-    context.insert("files_url", &workspace_root_url(workspace_id));
-    context.insert("history_url", &workspace_git_history_url(workspace_id));
-    context.insert("page_title", "Branches");
-    context.insert("page_title_key", "web.ws.git.branches");
-    context.insert("empty_key", "web.ws.git.no_branches");
-    context.insert("mode", "branches");
+#[derive(Clone, Serialize)]
+struct MarkdownDocOutline {
+    block_count: usize,
+    blocks: Vec<MarkdownBlockOutline>,
+}
 
-    // Row shape the template renders directly: `has_counts` flags whether the
-    // ahead/behind comparison resolved (so `Some(0)` still shows `0｜0` while an
-    // unresolved `None` shows a placeholder), and the counts are flattened to
-    // plain numbers to avoid null/`Option` ambiguity in the template.
-    #[derive(Serialize)]
-    struct BranchRow {
-        name: String,
-        updated: String,
-        is_default: bool,
-        has_counts: bool,
-        behind: usize,
-        ahead: usize,
+#[derive(Clone, Serialize)]
+struct MarkdownBlockOutline {
+    index: usize,
+    kind: String,
+    label: String,
+}
+
+impl MarkdownDocOutline {
+    fn from_summary(summary: &markdown_ast::MarkdownDocumentSummary) -> Self {
+        MarkdownDocOutline {
+            block_count: summary.block_count,
+            blocks: summary
+                .blocks
+                .iter()
+                .map(|b| MarkdownBlockOutline {
+                    index: b.index,
+                    kind: b.kind.clone(),
+                    label: b.label.clone(),
+                })
+                .collect(),
+        }
     }
-    let to_row = |b: &git::GitBranchDetail| BranchRow {
-        name: b.name.clone(),
-        updated: b.updated.clone(),
-        is_default: b.is_default,
-        has_counts: b.behind.is_some() && b.ahead.is_some(),
-        behind: b.behind.unwrap_or(0),
-        ahead: b.ahead.unwrap_or(0),
-    };
+}
 
-    // Group GitHub-style: the default branch on its own, the rest name-sorted.
-    let default_branch = branches.iter().find(|b| b.is_default).map(to_row);
-    let mut other_branches: Vec<BranchRow> = branches
-        .iter()
-        .filter(|b| !b.is_default)
-        .map(to_row)
-        .collect();
-    other_branches.sort_by(|a, b| a.name.cmp(&b.name));
-    let default_branch_name = default_branch
-        .as_ref()
-        .map(|b| b.name.clone())
-        .unwrap_or_default();
+#[derive(Clone, Serialize)]
+struct MarkdownDiffBlock {
+    kind: &'static str,
+    old: Option<markdown_ast::MarkdownBlockSummary>,
+    new: Option<markdown_ast::MarkdownBlockSummary>,
+}
 
-    context.insert("default_branch", &default_branch);
-    context.insert("other_branches", &other_branches);
-    context.insert("default_branch_name", &default_branch_name);
-    context.insert("branch_total", &branches.len());
-    context.insert("has_items", &!branches.is_empty());
-    render_template(state, "git-refs.html", &context)
+#[derive(Clone, Serialize)]
+struct MarkdownDiffDiagnostic {
+    side: &'static str,
+    code: String,
+    severity: String,
+    message: String,
+    start_line: Option<u32>,
+    end_line: Option<u32>,
 }
 
-fn render_git_tags_page(state: &AppState, workspace_id: &str, tags: &[git::GitTag]) -> Response {
-    let mut context = base_context(state);
-    context.insert("title", "markon git tags");
-    context.insert("workspace_id", workspace_id);
-    context.insert("files_url", &workspace_root_url(workspace_id));
-    context.insert("history_url", &workspace_git_history_url(workspace_id));
-    context.insert("page_title", "Tags");
-    context.insert("page_title_key", "web.ws.git.tags");
-    context.insert("empty_key", "web.ws.git.no_tags");
-    context.insert("mode", "tags");
-    context.insert("branches", &Vec::<git::GitBranch>::new());
-    context.insert("tags", tags);
-    context.insert("has_items", &!tags.is_empty());
-    render_template(state, "git-refs.html", &context)
+const MARKDOWN_DIFF_CACHE_VERSION: &str = "markdown-diff-cache-v2";
+const MARKDOWN_DIFF_DOCUMENT_CACHE_LIMIT: usize = 256;
+const MARKDOWN_DIFF_FILE_CACHE_LIMIT: usize = 512;
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct MarkdownDocumentCacheKey {
+    version: &'static str,
+    theme: String,
+    workspace_id: String,
+    file_path: String,
+    content_hash: String,
 }
 
-/// One markdown file whose rendered diff must be (re)built — i.e. it missed the
-/// file cache. Carries the resolved per-side content identity so a batched blob
-/// read + parallel render can finish the job without further git subprocesses.
-struct MarkdownBuildItem<'a> {
-    entry: &'a git::MarkdownDiffEntry,
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct MarkdownDiffFileCacheKey {
+    version: &'static str,
+    theme: String,
+    workspace_id: String,
+    path: String,
+    old_path: Option<String>,
+    status: String,
+    // Stable content identity per side: a git blob oid where available (no read
+    // needed — comes straight from `git diff --raw`), or a `h:<sha256>` of the
+    // worktree content. Keying on the blob oid lets a warm reload hit the cache
+    // without reading or re-rendering the old side.
     old_id: Option<String>,
     new_id: Option<String>,
-    /// Worktree new-side content, already read in pass 1 (worktree has no blob).
-    new_worktree: Option<String>,
-    read_diagnostics: Vec<MarkdownDiffDiagnostic>,
-    file_key: Option<MarkdownDiffFileCacheKey>,
 }
 
-fn markdown_compare_diff_data(
-    state: &AppState,
-    workspace_id: &str,
-    workspace_fs: &WorkspaceFs,
-    base: &str,
-    compare: &str,
-    file_filter: Option<&str>,
-) -> git::Result<MarkdownDiffData> {
-    let root = workspace_fs
-        .directory_root()
-        .ok_or_else(|| git::GitError::Io("directory workspace required".to_string()))?;
-    let engine = markdown_ast::engine_info();
-    // Cheap enumeration: `git diff --raw` (no patch) + untracked md. Gives each
-    // changed file's status and per-side blob oids without reading content.
-    let listing = git::markdown_diff_listing(workspace_fs, base, compare)?;
-    if !engine.enabled {
-        return Ok(MarkdownDiffData {
-            title: "Markdown visual diff".to_string(),
-            subtitle: engine.message.map(str::to_string),
-            engine,
-            files: Vec::new(),
-        });
-    }
+#[derive(Default)]
+pub(crate) struct MarkdownDiffCache {
+    documents: HashMap<MarkdownDocumentCacheKey, Arc<markdown_ast::MarkdownDocumentSummary>>,
+    document_lru: VecDeque<MarkdownDocumentCacheKey>,
+    files: HashMap<MarkdownDiffFileCacheKey, Arc<MarkdownDiffFile>>,
+    file_lru: VecDeque<MarkdownDiffFileCacheKey>,
+    document_hits: u64,
+    document_misses: u64,
+    file_hits: u64,
+    file_misses: u64,
+}
 
-    let filter = file_filter.map(str::trim).filter(|f| !f.is_empty());
-    let entries: Vec<&git::MarkdownDiffEntry> = listing
-        .entries
-        .iter()
-        .filter(|e| filter.is_none_or(|f| e.path == f || e.old_path.as_deref() == Some(f)))
-        .collect();
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct MarkdownDiffCacheStats {
+    document_entries: usize,
+    file_entries: usize,
+    document_hits: u64,
+    document_misses: u64,
+    file_hits: u64,
+    file_misses: u64,
+}
 
-    enum Slot<'a> {
-        Cached(Arc<MarkdownDiffFile>),
-        Build(Box<MarkdownBuildItem<'a>>),
+impl MarkdownDiffCache {
+    fn get_document(
+        &mut self,
+        key: &MarkdownDocumentCacheKey,
+    ) -> Option<Arc<markdown_ast::MarkdownDocumentSummary>> {
+        if let Some(summary) = self.documents.get(key).cloned() {
+            self.document_hits += 1;
+            touch_lru_key(&mut self.document_lru, key);
+            Some(summary)
+        } else {
+            self.document_misses += 1;
+            None
+        }
     }
 
-    // ---- Pass 1: resolve content identity; serve file-cache hits without I/O ----
-    // The old side is identified purely by its blob oid (from `--raw`), so a warm
-    // reload hits the cache without reading or re-rendering it. The worktree new
-    // side has no oid yet, so it is read here (cheap) to derive its identity.
-    let mut slots: Vec<Slot> = Vec::with_capacity(entries.len());
-    let mut needed_blobs: Vec<String> = Vec::new();
+    fn insert_document(
+        &mut self,
+        key: MarkdownDocumentCacheKey,
+        summary: markdown_ast::MarkdownDocumentSummary,
+    ) -> Arc<markdown_ast::MarkdownDocumentSummary> {
+        let summary = Arc::new(summary);
+        self.documents.insert(key.clone(), summary.clone());
+        touch_lru_key(&mut self.document_lru, &key);
+        trim_lru_cache(
+            &mut self.documents,
+            &mut self.document_lru,
+            MARKDOWN_DIFF_DOCUMENT_CACHE_LIMIT,
+        );
+        summary
+    }
 
-    for entry in &entries {
-        let mut read_diagnostics = Vec::new();
-        let old_id = if entry.status == "added" {
-            None
+    fn get_file(&mut self, key: &MarkdownDiffFileCacheKey) -> Option<Arc<MarkdownDiffFile>> {
+        if let Some(file) = self.files.get(key).cloned() {
+            self.file_hits += 1;
+            touch_lru_key(&mut self.file_lru, key);
+            Some(file)
         } else {
-            entry.old_blob.clone()
-        };
-
-        let mut new_worktree = None;
-        let new_id = if entry.status == "deleted" {
+            self.file_misses += 1;
             None
-        } else if let Some(blob) = &entry.new_blob {
-            Some(blob.clone())
-        } else {
-            match workspace_fs.read_content_to_string(&entry.path) {
-                Ok(content) => {
-                    let id = format!("h:{}", markdown_content_hash(&content));
-                    new_worktree = Some(content);
-                    Some(id)
-                }
-                Err(e) => {
-                    read_diagnostics.push(markdown_diff_diagnostic(
-                        "new",
-                        "read_worktree_failed",
-                        "error",
-                        format!("Failed to read {}: {e}", entry.path),
-                    ));
-                    None
-                }
-            }
-        };
-
-        let file_key = read_diagnostics
-            .is_empty()
-            .then(|| MarkdownDiffFileCacheKey {
-                version: MARKDOWN_DIFF_CACHE_VERSION,
-                theme: state.theme.as_str().to_string(),
-                workspace_id: workspace_id.to_string(),
-                path: entry.path.clone(),
-                old_path: entry.old_path.clone(),
-                status: entry.status.clone(),
-                old_id: old_id.clone(),
-                new_id: new_id.clone(),
-            });
+        }
+    }
 
-        if let Some(key) = &file_key {
-            if let Some(cached) = state
-                .markdown_diff_cache
-                .lock()
-                .expect("markdown diff cache poisoned")
-                .get_file(key)
-            {
-                slots.push(Slot::Cached(cached));
-                continue;
-            }
-        }
+    fn insert_file(
+        &mut self,
+        key: MarkdownDiffFileCacheKey,
+        file: MarkdownDiffFile,
+    ) -> Arc<MarkdownDiffFile> {
+        let file = Arc::new(file);
+        self.files.insert(key.clone(), file.clone());
+        touch_lru_key(&mut self.file_lru, &key);
+        trim_lru_cache(
+            &mut self.files,
+            &mut self.file_lru,
+            MARKDOWN_DIFF_FILE_CACHE_LIMIT,
+        );
+        file
+    }
 
-        // Cache miss: schedule the blobs this build will need.
-        if let Some(oid) = &old_id {
-            needed_blobs.push(oid.clone());
-        }
-        if entry.new_blob.is_some() {
-            if let Some(oid) = &new_id {
-                needed_blobs.push(oid.clone());
-            }
+    #[cfg(test)]
+    fn stats(&self) -> MarkdownDiffCacheStats {
+        MarkdownDiffCacheStats {
+            document_entries: self.documents.len(),
+            file_entries: self.files.len(),
+            document_hits: self.document_hits,
+            document_misses: self.document_misses,
+            file_hits: self.file_hits,
+            file_misses: self.file_misses,
         }
-        slots.push(Slot::Build(Box::new(MarkdownBuildItem {
-            entry,
-            old_id,
-            new_id,
-            new_worktree,
-            read_diagnostics,
-            file_key,
-        })));
     }
+}
 
-    // ---- Pass 2: one batched `git cat-file` for every missing blob ----
-    needed_blobs.sort();
-    needed_blobs.dedup();
-    let blobs = git::read_blobs(root, &needed_blobs)?;
+fn touch_lru_key<K>(lru: &mut VecDeque<K>, key: &K)
+where
+    K: Clone + Eq,
+{
+    if let Some(index) = lru.iter().position(|existing| existing == key) {
+        lru.remove(index);
+    }
+    lru.push_back(key.clone());
+}
 
-    // ---- Pass 3: render the misses in parallel (render_html, not full render) ----
-    let builds: Vec<(usize, &MarkdownBuildItem)> = slots
+fn trim_lru_cache<K, V>(cache: &mut HashMap<K, V>, lru: &mut VecDeque<K>, limit: usize)
+where
+    K: Clone + Eq + std::hash::Hash,
+{
+    while cache.len() > limit {
+        let Some(key) = lru.pop_front() else {
+            break;
+        };
+        cache.remove(&key);
+    }
+}
+
+#[derive(Serialize)]
+struct WorkspaceFeatureStatus {
+    key: &'static str,
+    label: &'static str,
+    label_key: &'static str,
+    enabled: bool,
+}
+
+fn git_diff_template<'a>(
+    root: &FsPath,
+    diff: &'a git::GitDiff,
+    base_value: String,
+    compare_value: String,
+) -> GitDiffTemplate<'a> {
+    let files: Vec<GitDiffFileTemplate<'_>> = diff
+        .files
         .iter()
-        .enumerate()
-        .filter_map(|(i, slot)| match slot {
-            Slot::Build(item) => Some((i, item.as_ref())),
-            Slot::Cached(_) => None,
-        })
-        .collect();
-    let mut built: HashMap<usize, MarkdownDiffFile> = builds
-        .into_par_iter()
-        .map(|(i, item)| {
-            (
-                i,
-                build_markdown_diff_file(state, workspace_id, item, &blobs, workspace_fs),
-            )
+        .map(|file| GitDiffFileTemplate {
+            path: &file.path,
+            old_path: file.old_path.as_deref(),
+            status: &file.status,
+            additions: file.additions,
+            deletions: file.deletions,
         })
         .collect();
+    let total_additions = diff.files.iter().map(|file| file.additions).sum();
+    let total_deletions = diff.files.iter().map(|file| file.deletions).sum();
+    let (mode_label, base_label, compare_label) =
+        git_diff_range_labels(root, diff, &base_value, &compare_value);
+    GitDiffTemplate {
+        range: &diff.range,
+        title: &diff.title,
+        subtitle: diff.subtitle.as_deref(),
+        mode_label,
+        base_label,
+        compare_label,
+        base_value,
+        compare_value,
+        nav_entries: git_diff_nav_entries(&diff.files),
+        files,
+        total_additions,
+        total_deletions,
+    }
+}
 
-    // ---- Pass 4: assemble in original order; promote freshly built into cache ----
-    let mut files = Vec::with_capacity(slots.len());
-    for (i, slot) in slots.iter().enumerate() {
-        match slot {
-            Slot::Cached(arc) => files.push((**arc).clone()),
-            Slot::Build(item) => {
-                let file = built.remove(&i).expect("built file present");
-                if let Some(key) = item.file_key.clone() {
-                    let cached = state
-                        .markdown_diff_cache
-                        .lock()
-                        .expect("markdown diff cache poisoned")
-                        .insert_file(key, file);
-                    files.push((*cached).clone());
-                } else {
-                    files.push(file);
-                }
+fn git_diff_range_labels(
+    root: &FsPath,
+    diff: &git::GitDiff,
+    base_value: &str,
+    compare_value: &str,
+) -> (String, String, String) {
+    if diff.range == "HEAD..worktree" {
+        return (
+            "Working tree".to_string(),
+            "HEAD".to_string(),
+            "Worktree".to_string(),
+        );
+    }
+    if let Some((base, compare)) = diff.range.split_once("..") {
+        return (
+            "Git range".to_string(),
+            base.to_string(),
+            compare.to_string(),
+        );
+    }
+    if valid_hex_display_ref(&diff.range) {
+        let base = git::parent_commit(root, &diff.range)
+            .ok()
+            .flatten()
+            .map(|parent| short_git_ref(&parent))
+            .unwrap_or_else(|| "Parent".to_string());
+        return ("Commit".to_string(), base, short_git_ref(compare_value));
+    }
+    (
+        "Git range".to_string(),
+        short_git_ref(base_value),
+        short_git_ref(compare_value),
+    )
+}
+
+fn valid_hex_display_ref(value: &str) -> bool {
+    (4..=64).contains(&value.len()) && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn short_git_ref(value: &str) -> String {
+    if value.len() > 12 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        value[..12].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn git_diff_nav_entries(files: &[git::GitDiffFile]) -> Vec<GitDiffNavEntry<'_>> {
+    let mut sorted: Vec<&git::GitDiffFile> = files.iter().collect();
+    sorted.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut emitted_dirs = std::collections::BTreeSet::new();
+    let mut entries = Vec::new();
+
+    for file in sorted {
+        let segments: Vec<&str> = file
+            .path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect();
+        for depth in 0..segments.len().saturating_sub(1) {
+            let path = segments[..=depth].join("/");
+            if emitted_dirs.insert(path.clone()) {
+                entries.push(GitDiffNavEntry {
+                    kind: "dir",
+                    name: segments[depth].to_string(),
+                    path,
+                    depth,
+                    status: None,
+                    additions: 0,
+                    deletions: 0,
+                });
             }
         }
+
+        entries.push(GitDiffNavEntry {
+            kind: "file",
+            name: segments
+                .last()
+                .copied()
+                .unwrap_or(file.path.as_str())
+                .to_string(),
+            path: file.path.clone(),
+            depth: segments.len().saturating_sub(1),
+            status: Some(file.status.as_str()),
+            additions: file.additions,
+            deletions: file.deletions,
+        });
     }
 
-    Ok(MarkdownDiffData {
-        title: listing.title,
-        subtitle: Some(format!(
-            "{} Markdown files changed · {}",
-            files.len(),
-            listing.range
-        )),
-        engine,
-        files,
-    })
+    entries
 }
 
-/// Async wrapper for [`markdown_compare_diff_data`]. The diff build shells out to
-/// git, reads worktree files, and renders in parallel via rayon — all blocking
-/// work that would otherwise stall a tokio worker. Move it to the blocking pool;
-/// a join failure surfaces as a generic IO error the caller already maps to 500.
-async fn markdown_compare_diff_data_async(
-    state: &AppState,
-    workspace_id: &str,
-    workspace_fs: Arc<WorkspaceFs>,
-    base: &str,
-    compare: &str,
+fn git_diff_json_response(
+    diff: &git::GitDiff,
+    markdown: &MarkdownExtensions,
     file_filter: Option<&str>,
-) -> git::Result<MarkdownDiffData> {
-    let state = state.clone();
-    let workspace_id = workspace_id.to_string();
-    let base = base.to_string();
-    let compare = compare.to_string();
-    let file_filter = file_filter.map(str::to_string);
-    tokio::task::spawn_blocking(move || {
-        markdown_compare_diff_data(
-            &state,
-            &workspace_id,
-            &workspace_fs,
-            &base,
-            &compare,
-            file_filter.as_deref(),
+) -> Response {
+    let diff = markdown_only_git_diff(diff, markdown, file_filter);
+    match serde_json::to_value(git_diff_data(&diff)) {
+        Ok(value) => Json(value).into_response(),
+        Err(e) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("Failed to serialize git diff: {e}"),
         )
-    })
-    .await
-    .unwrap_or_else(|e| {
-        tracing::error!("markdown_compare_diff_data join error: {e}");
-        Err(git::GitError::Io("diff render task failed".to_string()))
-    })
-}
-
-/// Build a single file's rendered diff from already-resolved content sources.
-/// Pure CPU + cache lookups — safe to run in parallel across files.
-fn build_markdown_diff_file(
-    state: &AppState,
-    workspace_id: &str,
-    item: &MarkdownBuildItem,
-    blobs: &HashMap<String, Vec<u8>>,
-    workspace_fs: &WorkspaceFs,
-) -> MarkdownDiffFile {
-    let entry = item.entry;
-    let root = workspace_fs
-        .directory_root()
-        .expect("markdown diff requires a directory workspace");
-    // Canonicalize the new-side path the same way `render_markdown_file` builds
-    // its `file_path` key, so a highlight made in the diff binds to the same
-    // annotation row as one made in the normal file view. `entry.path` is
-    // workspace-relative (the listing uses `git diff --relative`), so joining it
-    // onto the workspace root reaches the exact same on-disk file — and the same
-    // canonical key — as the normal view, including subdirectory-of-repo
-    // workspaces. Deleted files have no on-disk new side, so canonicalize falls
-    // back to the lexical join (such files are not annotatable anyway).
-    let abs_path = {
-        let joined = root.join(&entry.path);
-        workspace_fs
-            .resolve_content(&entry.path)
-            .map(|path| path.to_string_lossy().into_owned())
-            .unwrap_or_else(|_| joined.to_string_lossy().into_owned())
-    };
-    let mut diagnostics = item.read_diagnostics.clone();
-
-    let blob_text =
-        |id: &str, side: &'static str, diags: &mut Vec<MarkdownDiffDiagnostic>| match blobs.get(id)
-        {
-            Some(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
-            None => {
-                diags.push(markdown_diff_diagnostic(
-                    side,
-                    "read_blob_failed",
-                    "error",
-                    format!("Missing blob {id}"),
-                ));
-                None
-            }
-        };
-
-    let old_content = item
-        .old_id
-        .as_deref()
-        .and_then(|id| blob_text(id, "old", &mut diagnostics));
-    let new_content = if entry.status == "deleted" {
-        None
-    } else if let Some(content) = &item.new_worktree {
-        Some(content.clone())
-    } else {
-        item.new_id
-            .as_deref()
-            .and_then(|id| blob_text(id, "new", &mut diagnostics))
-    };
-
-    let old_path = entry.old_path.as_deref().unwrap_or(&entry.path);
-    let old_file_path = root.join(old_path);
-    let new_file_path = root.join(&entry.path);
-    let old_renderer = default_markdown_engine(state.theme.as_str()).with_asset_context(
-        workspace_id,
-        &old_file_path,
-        root,
-    );
-    let new_renderer = default_markdown_engine(state.theme.as_str()).with_asset_context(
-        workspace_id,
-        &new_file_path,
-        root,
-    );
-
-    let old = summarize_side_cached(
-        state,
-        "old",
-        old_content.as_deref(),
-        item.old_id.as_deref(),
-        workspace_id,
-        &old_file_path,
-        &old_renderer,
-    );
-    diagnostics.extend(markdown_side_diagnostics("old", old.as_ref()));
-    let new = summarize_side_cached(
-        state,
-        "new",
-        new_content.as_deref(),
-        item.new_id.as_deref(),
-        workspace_id,
-        &new_file_path,
-        &new_renderer,
-    );
-    diagnostics.extend(markdown_side_diagnostics("new", new.as_ref()));
-
-    let blocks = diff_markdown_blocks(
-        old.as_ref().map(|s| s.blocks.as_slice()),
-        new.as_ref().map(|s| s.blocks.as_slice()),
-    );
-
-    MarkdownDiffFile {
-        path: entry.path.clone(),
-        abs_path,
-        old_path: entry.old_path.clone(),
-        status: entry.status.clone(),
-        old: old.as_ref().map(MarkdownDocOutline::from_summary),
-        new: new.as_ref().map(MarkdownDocOutline::from_summary),
-        old_source: old_content,
-        new_source: new_content,
-        additions: entry.additions,
-        deletions: entry.deletions,
-        blocks,
-        diagnostics,
+            .into_response(),
     }
 }
 
-/// Summarize one side, keyed in the document cache by a stable content id (blob
-/// oid, or `h:<sha256>` for worktree content). The cache includes workspace and
-/// file path because rendered local asset URLs depend on both. Blocks use
-/// `render_html` only; the diff does not need the diagnostic pass of full
-/// `render()`.
-fn summarize_side_cached(
-    state: &AppState,
-    side: &'static str,
-    content: Option<&str>,
-    content_id: Option<&str>,
-    workspace_id: &str,
-    file_path: &FsPath,
-    renderer: &MarkdownRenderer,
-) -> Option<markdown_ast::MarkdownDocumentSummary> {
-    let content = content?;
-    let id_owned;
-    let id = match content_id {
-        Some(id) => id,
-        None => {
-            id_owned = format!("h:{}", markdown_content_hash(content));
-            id_owned.as_str()
-        }
-    };
-    let key = markdown_document_cache_key(state, id, workspace_id, file_path);
-    if let Some(summary) = state
-        .markdown_diff_cache
-        .lock()
-        .expect("markdown diff cache poisoned")
-        .get_document(&key)
-    {
-        return Some((*summary).clone());
+fn markdown_only_git_diff(
+    diff: &git::GitDiff,
+    markdown: &MarkdownExtensions,
+    file_filter: Option<&str>,
+) -> git::GitDiff {
+    let files: Vec<git::GitDiffFile> = diff
+        .files
+        .iter()
+        .filter(|file| is_markdown_diff_file(markdown, file))
+        .filter(|file| diff_file_matches_filter(file, file_filter))
+        .cloned()
+        .collect();
+    let patch = files.iter().map(|file| file.patch.as_str()).collect();
+    git::GitDiff {
+        range: diff.range.clone(),
+        title: diff.title.clone(),
+        subtitle: diff.subtitle.clone(),
+        patch,
+        files,
     }
-
-    let mut render_block = |fragment: &str| renderer.render_html(fragment).html;
-    let summary = match markdown_ast::summarize_document(content, &mut render_block) {
-        Ok(summary) => summary,
-        Err(e) => markdown_summary_error(side, e.message),
-    };
-    let cached = state
-        .markdown_diff_cache
-        .lock()
-        .expect("markdown diff cache poisoned")
-        .insert_document(key, summary);
-    Some((*cached).clone())
 }
 
-fn is_markdown_diff_file(file: &git::GitDiffFile) -> bool {
-    is_markdown_route_path(&file.path)
-        || file.old_path.as_deref().is_some_and(is_markdown_route_path)
+fn diff_file_matches_filter(file: &git::GitDiffFile, file_filter: Option<&str>) -> bool {
+    let Some(filter) = file_filter
+        .map(str::trim)
+        .filter(|filter| !filter.is_empty())
+    else {
+        return true;
+    };
+    file.path == filter || file.old_path.as_deref() == Some(filter)
 }
 
-fn is_markdown_route_path(path: &str) -> bool {
-    FsPath::new(path)
-        .extension()
-        .is_some_and(|e| e.to_string_lossy().eq_ignore_ascii_case("md"))
-}
+fn git_diff_data(diff: &git::GitDiff) -> GitDiffData<'_> {
+    let mut rows = Vec::new();
+    let mut files = Vec::new();
 
-fn markdown_content_hash(content: &str) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(content.as_bytes());
-    format!("{:x}", hasher.finalize())
-}
+    for (file_index, file) in diff.files.iter().enumerate() {
+        let start_row = rows.len();
+        rows.push(GitDiffDataRow::File {
+            file_index,
+            path: &file.path,
+            old_path: file.old_path.as_deref(),
+            status: &file.status,
+            additions: file.additions,
+            deletions: file.deletions,
+        });
+        push_diff_data_rows(file_index, &file.patch, &mut rows);
+        let row_count = rows.len() - start_row;
+        files.push(GitDiffDataFile {
+            path: &file.path,
+            old_path: file.old_path.as_deref(),
+            status: &file.status,
+            additions: file.additions,
+            deletions: file.deletions,
+            start_row,
+            row_count,
+        });
+    }
 
-fn markdown_document_cache_key(
-    state: &AppState,
-    content_hash: &str,
-    workspace_id: &str,
-    file_path: &FsPath,
-) -> MarkdownDocumentCacheKey {
-    MarkdownDocumentCacheKey {
-        version: MARKDOWN_DIFF_CACHE_VERSION,
-        theme: state.theme.as_str().to_string(),
-        workspace_id: workspace_id.to_string(),
-        file_path: file_path.to_string_lossy().into_owned(),
-        content_hash: content_hash.to_string(),
+    GitDiffData {
+        range: &diff.range,
+        title: &diff.title,
+        subtitle: diff.subtitle.as_deref(),
+        files,
+        rows,
+        total_additions: diff.files.iter().map(|file| file.additions).sum(),
+        total_deletions: diff.files.iter().map(|file| file.deletions).sum(),
     }
 }
 
-fn markdown_summary_error(
-    side: &'static str,
-    message: String,
-) -> markdown_ast::MarkdownDocumentSummary {
-    markdown_ast::MarkdownDocumentSummary {
-        block_count: 0,
-        diagnostics: vec![markdown_ast::MarkdownAstDiagnostic {
-            code: format!("{side}_parse_failed"),
-            severity: "error".to_string(),
-            message,
-            start_line: None,
-            end_line: None,
-        }],
-        blocks: Vec::new(),
-    }
-}
-
-fn markdown_side_diagnostics(
-    side: &'static str,
-    summary: Option<&markdown_ast::MarkdownDocumentSummary>,
-) -> Vec<MarkdownDiffDiagnostic> {
-    summary
-        .into_iter()
-        .flat_map(|summary| {
-            summary
-                .diagnostics
-                .iter()
-                .map(move |diagnostic| MarkdownDiffDiagnostic {
-                    side,
-                    code: diagnostic.code.clone(),
-                    severity: diagnostic.severity.clone(),
-                    message: diagnostic.message.clone(),
-                    start_line: diagnostic.start_line,
-                    end_line: diagnostic.end_line,
-                })
-        })
-        .collect()
-}
-
-fn markdown_diff_diagnostic(
-    side: &'static str,
-    code: &'static str,
-    severity: &'static str,
-    message: String,
-) -> MarkdownDiffDiagnostic {
-    MarkdownDiffDiagnostic {
-        side,
-        code: code.to_string(),
-        severity: severity.to_string(),
-        message,
-        start_line: None,
-        end_line: None,
-    }
-}
-
-#[derive(Debug, Clone)]
-enum MarkdownBlockOp {
-    Equal(
-        markdown_ast::MarkdownBlockSummary,
-        markdown_ast::MarkdownBlockSummary,
-    ),
-    Delete(markdown_ast::MarkdownBlockSummary),
-    Add(markdown_ast::MarkdownBlockSummary),
-}
+fn push_diff_data_rows<'a>(
+    file_index: usize,
+    unified_diff: &str,
+    rows: &mut Vec<GitDiffDataRow<'a>>,
+) {
+    let mut old_line_no: Option<usize> = None;
+    let mut new_line_no: Option<usize> = None;
+    let mut pending_deletes: Vec<(usize, &str)> = Vec::new();
+    let mut pending_inserts: Vec<(usize, &str)> = Vec::new();
 
-fn diff_markdown_blocks(
-    old: Option<&[markdown_ast::MarkdownBlockSummary]>,
-    new: Option<&[markdown_ast::MarkdownBlockSummary]>,
-) -> Vec<MarkdownDiffBlock> {
-    let old = old.unwrap_or(&[]);
-    let new = new.unwrap_or(&[]);
-    let ops = if old.len().saturating_mul(new.len()) <= 40_000 {
-        diff_markdown_blocks_lcs(old, new)
-    } else {
-        diff_markdown_blocks_by_index(old, new)
-    };
-    coalesce_markdown_block_ops(ops)
-}
+    for raw_line in unified_diff.split_inclusive('\n') {
+        let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
+        if line.starts_with("@@ ") {
+            flush_diff_data_change_block(
+                rows,
+                file_index,
+                &mut pending_deletes,
+                &mut pending_inserts,
+            );
+            if let Some((old_start, new_start)) = parse_hunk_line_numbers(line) {
+                old_line_no = Some(old_start);
+                new_line_no = Some(new_start);
+            }
+            push_diff_data_meta_line(rows, file_index, line);
+            continue;
+        }
 
-fn diff_markdown_blocks_lcs(
-    old: &[markdown_ast::MarkdownBlockSummary],
-    new: &[markdown_ast::MarkdownBlockSummary],
-) -> Vec<MarkdownBlockOp> {
-    let n = old.len();
-    let m = new.len();
-    let mut dp = vec![vec![0usize; m + 1]; n + 1];
-    for i in (0..n).rev() {
-        for j in (0..m).rev() {
-            dp[i][j] = if old[i].digest == new[j].digest {
-                dp[i + 1][j + 1] + 1
-            } else {
-                dp[i + 1][j].max(dp[i][j + 1])
-            };
+        if is_diff_delete_line(line) {
+            let current = old_line_no.unwrap_or(0);
+            pending_deletes.push((current, line));
+            if let Some(line_no) = old_line_no.as_mut() {
+                *line_no += 1;
+            }
+            continue;
         }
-    }
+        if is_diff_insert_line(line) {
+            let current = new_line_no.unwrap_or(0);
+            pending_inserts.push((current, line));
+            if let Some(line_no) = new_line_no.as_mut() {
+                *line_no += 1;
+            }
+            continue;
+        }
+        flush_diff_data_change_block(rows, file_index, &mut pending_deletes, &mut pending_inserts);
 
-    let mut ops = Vec::new();
-    let (mut i, mut j) = (0, 0);
-    while i < n && j < m {
-        if old[i].digest == new[j].digest {
-            ops.push(MarkdownBlockOp::Equal(old[i].clone(), new[j].clone()));
-            i += 1;
-            j += 1;
-        } else if dp[i + 1][j] >= dp[i][j + 1] {
-            ops.push(MarkdownBlockOp::Delete(old[i].clone()));
-            i += 1;
+        if let Some(body) = line.strip_prefix(' ') {
+            push_diff_data_split_line(
+                rows,
+                file_index,
+                GitDiffDataSide {
+                    line_no: old_line_no,
+                    class_name: "git-diff-line",
+                    segments: diff_data_plain_segments(body),
+                },
+                GitDiffDataSide {
+                    line_no: new_line_no,
+                    class_name: "git-diff-line",
+                    segments: diff_data_plain_segments(body),
+                },
+            );
+            if let Some(line_no) = old_line_no.as_mut() {
+                *line_no += 1;
+            }
+            if let Some(line_no) = new_line_no.as_mut() {
+                *line_no += 1;
+            }
         } else {
-            ops.push(MarkdownBlockOp::Add(new[j].clone()));
-            j += 1;
+            push_diff_data_meta_line(rows, file_index, line);
         }
     }
-    while i < n {
-        ops.push(MarkdownBlockOp::Delete(old[i].clone()));
-        i += 1;
-    }
-    while j < m {
-        ops.push(MarkdownBlockOp::Add(new[j].clone()));
-        j += 1;
-    }
-    ops
+    flush_diff_data_change_block(rows, file_index, &mut pending_deletes, &mut pending_inserts);
 }
 
-fn diff_markdown_blocks_by_index(
-    old: &[markdown_ast::MarkdownBlockSummary],
-    new: &[markdown_ast::MarkdownBlockSummary],
-) -> Vec<MarkdownBlockOp> {
-    let mut ops = Vec::new();
-    let max_len = old.len().max(new.len());
-    for index in 0..max_len {
-        match (old.get(index), new.get(index)) {
-            (Some(old), Some(new)) if old.digest == new.digest => {
-                ops.push(MarkdownBlockOp::Equal(old.clone(), new.clone()));
-            }
-            (Some(old), Some(new)) => {
-                ops.push(MarkdownBlockOp::Delete(old.clone()));
-                ops.push(MarkdownBlockOp::Add(new.clone()));
+fn flush_diff_data_change_block<'a>(
+    rows: &mut Vec<GitDiffDataRow<'a>>,
+    file_index: usize,
+    deletes: &mut Vec<(usize, &str)>,
+    inserts: &mut Vec<(usize, &str)>,
+) {
+    if deletes.is_empty() && inserts.is_empty() {
+        return;
+    }
+    let pairs = deletes.len().max(inserts.len());
+    for index in 0..pairs {
+        let old_line = deletes.get(index).copied();
+        let new_line = inserts.get(index).copied();
+        match (old_line, new_line) {
+            (Some((old_no, old_line)), Some((new_no, new_line))) => {
+                push_diff_data_word_split_line(rows, file_index, old_no, old_line, new_no, new_line)
             }
-            (Some(old), None) => ops.push(MarkdownBlockOp::Delete(old.clone())),
-            (None, Some(new)) => ops.push(MarkdownBlockOp::Add(new.clone())),
+            (Some((old_no, old_line)), None) => push_diff_data_split_line(
+                rows,
+                file_index,
+                GitDiffDataSide {
+                    line_no: Some(old_no),
+                    class_name: "git-diff-line git-diff-del",
+                    segments: diff_data_plain_segments(diff_line_body(old_line)),
+                },
+                GitDiffDataSide {
+                    line_no: None,
+                    class_name: "git-diff-line git-diff-empty-side",
+                    segments: vec![GitDiffDataSegment::blank()],
+                },
+            ),
+            (None, Some((new_no, new_line))) => push_diff_data_split_line(
+                rows,
+                file_index,
+                GitDiffDataSide {
+                    line_no: None,
+                    class_name: "git-diff-line git-diff-empty-side",
+                    segments: vec![GitDiffDataSegment::blank()],
+                },
+                GitDiffDataSide {
+                    line_no: Some(new_no),
+                    class_name: "git-diff-line git-diff-add",
+                    segments: diff_data_plain_segments(diff_line_body(new_line)),
+                },
+            ),
             (None, None) => {}
         }
     }
-    ops
+    deletes.clear();
+    inserts.clear();
 }
 
-fn coalesce_markdown_block_ops(ops: Vec<MarkdownBlockOp>) -> Vec<MarkdownDiffBlock> {
-    let mut out = Vec::new();
-    let mut index = 0;
-    while index < ops.len() {
-        match &ops[index] {
-            MarkdownBlockOp::Equal(old, new) => {
-                out.push(MarkdownDiffBlock {
-                    kind: "equal",
-                    old: Some(old.clone()),
-                    new: Some(new.clone()),
-                });
-                index += 1;
-            }
-            MarkdownBlockOp::Delete(_) => {
-                let start = index;
-                while index < ops.len() && matches!(ops[index], MarkdownBlockOp::Delete(_)) {
-                    index += 1;
-                }
-                let delete_end = index;
-                while index < ops.len() && matches!(ops[index], MarkdownBlockOp::Add(_)) {
-                    index += 1;
-                }
-                let adds = ops[delete_end..index]
-                    .iter()
-                    .filter_map(|op| match op {
-                        MarkdownBlockOp::Add(block) => Some(block.clone()),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>();
-                let deletes = ops[start..delete_end]
-                    .iter()
-                    .filter_map(|op| match op {
-                        MarkdownBlockOp::Delete(block) => Some(block.clone()),
-                        _ => None,
-                    })
-                    .collect::<Vec<_>>();
-                let pair_count = deletes.len().min(adds.len());
-                for pair_index in 0..pair_count {
-                    out.push(MarkdownDiffBlock {
-                        kind: "modified",
-                        old: Some(deletes[pair_index].clone()),
-                        new: Some(adds[pair_index].clone()),
-                    });
-                }
-                for block in deletes.into_iter().skip(pair_count) {
-                    out.push(MarkdownDiffBlock {
-                        kind: "deleted",
-                        old: Some(block),
-                        new: None,
-                    });
-                }
-                for block in adds.into_iter().skip(pair_count) {
-                    out.push(MarkdownDiffBlock {
-                        kind: "added",
-                        old: None,
-                        new: Some(block),
-                    });
-                }
+fn push_diff_data_word_split_line<'a>(
+    rows: &mut Vec<GitDiffDataRow<'a>>,
+    file_index: usize,
+    old_line_no: usize,
+    old_line: &str,
+    new_line_no: usize,
+    new_line: &str,
+) {
+    let mut old_segments = Vec::new();
+    let mut new_segments = Vec::new();
+    let word_diff = TextDiff::from_words(diff_line_body(old_line), diff_line_body(new_line));
+    for change in word_diff.iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Delete => {
+                old_segments.push(GitDiffDataSegment {
+                    text: change.value().to_string(),
+                    class_name: Some("git-diff-word-del"),
+                });
             }
-            MarkdownBlockOp::Add(new) => {
-                out.push(MarkdownDiffBlock {
-                    kind: "added",
-                    old: None,
-                    new: Some(new.clone()),
+            ChangeTag::Insert => {
+                new_segments.push(GitDiffDataSegment {
+                    text: change.value().to_string(),
+                    class_name: Some("git-diff-word-add"),
+                });
+            }
+            ChangeTag::Equal => {
+                old_segments.push(GitDiffDataSegment {
+                    text: change.value().to_string(),
+                    class_name: None,
+                });
+                new_segments.push(GitDiffDataSegment {
+                    text: change.value().to_string(),
+                    class_name: None,
                 });
-                index += 1;
             }
         }
     }
-    out
-}
-
-/// Text/code files above this size skip the (relatively expensive) syntax-
-/// highlight preview and fall back to raw bytes (the browser shows plain text).
-const MAX_TEXT_PREVIEW_BYTES: u64 = 2 * 1024 * 1024;
-
-/// Request-body cap for `POST /api/preview`. The live editor preview never
-/// needs the global 2 MB default; a smaller ceiling bounds the per-request
-/// render cost (defense in depth behind the same-origin guard).
-const PREVIEW_BODY_LIMIT: usize = 512 * 1024;
-
-/// If `path` is a small UTF-8 text/code file, return its contents plus a
-/// language hint (extension, or file name for extension-less files) for the
-/// highlighted preview. Returns `None` for images, media, binaries and
-/// oversized files — the caller serves those as raw bytes.
-fn read_text_for_preview(path: &FsPath) -> Option<(String, String)> {
-    let mime = mime_guess::from_path(path).first_or_octet_stream();
-    if matches!(mime.type_().as_str(), "image" | "video" | "audio")
-        || mime.essence_str() == "application/pdf"
-    {
-        return None;
-    }
-    if fs::metadata(path).map(|m| m.len()).unwrap_or(0) > MAX_TEXT_PREVIEW_BYTES {
-        return None;
+    if old_segments.is_empty() {
+        old_segments.push(GitDiffDataSegment::blank());
     }
-    let bytes = fs::read(path).ok()?;
-    // A NUL byte or invalid UTF-8 means it isn't text we can render.
-    if bytes.contains(&0) {
-        return None;
+    if new_segments.is_empty() {
+        new_segments.push(GitDiffDataSegment::blank());
     }
-    let content = String::from_utf8(bytes).ok()?;
-    let token = path
-        .extension()
-        .and_then(|e| e.to_str())
-        .filter(|s| !s.is_empty())
-        .or_else(|| path.file_name().and_then(|n| n.to_str()))
-        .unwrap_or_default()
-        .to_string();
-    Some((content, token))
+    push_diff_data_split_line(
+        rows,
+        file_index,
+        GitDiffDataSide {
+            line_no: Some(old_line_no),
+            class_name: "git-diff-line git-diff-del",
+            segments: old_segments,
+        },
+        GitDiffDataSide {
+            line_no: Some(new_line_no),
+            class_name: "git-diff-line git-diff-add",
+            segments: new_segments,
+        },
+    );
 }
 
-/// Read-only, syntax-highlighted preview page for a non-markdown text/code file.
-/// No collaboration chrome — just the file contents and line numbers.
-fn render_file_view(
-    path: &FsPath,
-    content: String,
-    token: String,
-    workspace_id: &str,
-    ws: &WorkspaceEntry,
-    root: &FsPath,
-    state: &AppState,
-) -> Response {
-    let file_name = path
-        .file_name()
-        .map(|n| n.to_string_lossy().to_string())
-        .unwrap_or_default();
-    // Back link: workspace root with this exact file highlighted; the directory
-    // tree expands the parent folders from the hash path.
-    let back_link = workspace_file_back_link(workspace_id, path, root);
-    let rel_display = workspace_relative_path(path, root)
-        .map(|rel| rel.to_string_lossy().replace('\\', "/"))
-        .unwrap_or_else(|| file_name.clone());
+fn push_diff_data_meta_line<'a>(rows: &mut Vec<GitDiffDataRow<'a>>, file_index: usize, line: &str) {
+    push_diff_data_split_line(
+        rows,
+        file_index,
+        GitDiffDataSide {
+            line_no: None,
+            class_name: neutral_diff_line_class(line),
+            segments: diff_data_plain_segments(line),
+        },
+        GitDiffDataSide {
+            line_no: None,
+            class_name: neutral_diff_line_class(line),
+            segments: diff_data_plain_segments(line),
+        },
+    );
+}
 
-    // Strip one trailing newline so the highlighted <pre> and the line-number
-    // gutter agree on the visual line count.
-    let normalized = content.strip_suffix('\n').unwrap_or(content.as_str());
-    let code_html = crate::markdown::highlight_source_file(&token, normalized);
-    let line_count = normalized.split('\n').count().max(1);
-    let gutter = (1..=line_count)
-        .map(|n| n.to_string())
-        .collect::<Vec<_>>()
-        .join("\n");
+fn push_diff_data_split_line<'a>(
+    rows: &mut Vec<GitDiffDataRow<'a>>,
+    file_index: usize,
+    old: GitDiffDataSide,
+    new: GitDiffDataSide,
+) {
+    rows.push(GitDiffDataRow::Line {
+        file_index,
+        old_line_no: old.line_no,
+        new_line_no: new.line_no,
+        old_class_name: old.class_name,
+        new_class_name: new.class_name,
+        old_segments: old.segments,
+        new_segments: new.segments,
+    });
+}
 
-    let mut context = base_context(state);
-    context.insert("title", &format!("markon - {file_name}"));
-    context.insert("workspace_id", workspace_id);
-    insert_workspace_header_context(&mut context, ws, root);
-    context.insert("version", env!("CARGO_PKG_VERSION"));
-    context.insert("file_name", &file_name);
-    context.insert("rel_display", &rel_display);
-    context.insert("back_link", &back_link);
-    context.insert("show_back_link", &!ws.is_ephemeral());
-    context.insert("code_html", &code_html);
-    context.insert("gutter", &gutter);
-    context.insert("line_count", &line_count);
+fn diff_line_body(line: &str) -> &str {
+    line.get(1..).unwrap_or("")
+}
 
-    render_template(state, "file-view.html", &context)
+fn diff_data_plain_segments(text: &str) -> Vec<GitDiffDataSegment> {
+    vec![GitDiffDataSegment {
+        text: if text.is_empty() {
+            " ".to_string()
+        } else {
+            text.to_string()
+        },
+        class_name: None,
+    }]
 }
 
-/// Async wrapper for [`render_markdown_file`]: the file read plus the markdown
-/// render (syntect highlighting + server-side diagrams) run on the blocking pool
-/// so a large document can't stall a tokio worker.
-async fn render_markdown_file_async(
-    file_path: String,
-    workspace_id: String,
-    ws: Arc<WorkspaceEntry>,
-    root: PathBuf,
-    state: AppState,
-    is_local: bool,
-) -> Response {
-    tokio::task::spawn_blocking(move || {
-        render_markdown_file(&file_path, &workspace_id, &ws, &root, &state, is_local)
-    })
-    .await
-    .unwrap_or_else(|e| {
-        tracing::error!("render_markdown_file join error: {e}");
-        (StatusCode::INTERNAL_SERVER_ERROR, "render task failed").into_response()
-    })
+impl GitDiffDataSegment {
+    fn blank() -> Self {
+        Self {
+            text: " ".to_string(),
+            class_name: None,
+        }
+    }
 }
 
-/// Async wrapper for the non-markdown preview path: the text sniff/read
-/// ([`read_text_for_preview`]) and, when the file is text, the syntect-
-/// highlighted [`render_file_view`] both run on the blocking pool. Returns
-/// `None` when the file is not previewable text, so the caller falls back to
-/// `serve_file`.
-async fn render_preview_or_none(
-    canonical: PathBuf,
-    workspace_id: String,
-    ws: Arc<WorkspaceEntry>,
-    root: PathBuf,
-    state: AppState,
-) -> Option<Response> {
-    tokio::task::spawn_blocking(move || {
-        let (content, token) = read_text_for_preview(&canonical)?;
-        Some(render_file_view(
-            &canonical,
-            content,
-            token,
-            &workspace_id,
-            &ws,
-            &root,
-            &state,
-        ))
-    })
-    .await
-    .unwrap_or_else(|e| {
-        tracing::error!("render_file_view join error: {e}");
-        Some((StatusCode::INTERNAL_SERVER_ERROR, "preview task failed").into_response())
-    })
+fn parse_hunk_line_numbers(line: &str) -> Option<(usize, usize)> {
+    let mut parts = line.split_whitespace();
+    if parts.next()? != "@@" {
+        return None;
+    }
+    let old = parse_hunk_start(parts.next()?, '-')?;
+    let new = parse_hunk_start(parts.next()?, '+')?;
+    Some((old, new))
 }
 
-fn render_markdown_file(
-    file_path: &str,
-    workspace_id: &str,
-    ws: &WorkspaceEntry,
-    root: &FsPath,
-    state: &AppState,
-    can_manage: bool,
-) -> Response {
-    match fs::read_to_string(file_path) {
-        Ok(markdown_input) => {
-            let renderer = default_markdown_engine(&state.theme).with_asset_context(
-                workspace_id,
-                file_path,
-                root,
-            );
-            let rendered = MarkdownEngine::render(&renderer, &markdown_input);
-
-            let title = std::path::Path::new(file_path)
-                .file_name()
-                .map(|n| n.to_string_lossy().to_string())
-                .unwrap_or_else(|| file_path.to_string());
-
-            let mut context = base_context(state);
-            context.insert("title", &title);
-            context.insert("file_path", file_path);
-            context.insert("workspace_id", workspace_id);
-            context.insert(
-                "preview_token",
-                &workspace_preview_token(&state.save_token, workspace_id),
-            );
-            insert_workspace_header_context(&mut context, ws, root);
-            context.insert("version", env!("CARGO_PKG_VERSION"));
-            context.insert("content", &rendered.html);
-            context.insert("history_url", &workspace_git_history_url(workspace_id));
-            // Back link: the workspace root with this exact file highlighted;
-            // the directory tree expands the parent folders from the hash path.
-            // Suppressed for single-file workspaces — `/{id}/` 303-redirects
-            // back to this same file (see `handle_workspace_root`), so a
-            // "Back" link would be a no-op trap.
-            let back_link =
-                workspace_file_back_link(workspace_id, std::path::Path::new(file_path), root);
-            context.insert("back_link", &back_link);
-            context.insert("show_back_link", &!ws.is_ephemeral());
-            context.insert("has_mermaid", &rendered.has_mermaid);
-            context.insert("has_math", &rendered.has_math);
-            context.insert("toc", &rendered.toc);
-            context.insert("markdown_diagnostics", &rendered.diagnostics);
-            context.insert("referenced_assets", &rendered.referenced_assets);
-            let flags = ws.flags();
-            context.insert("shared_annotation", &flags.shared_annotation);
-            context.insert("enable_viewed", &flags.enable_viewed);
-            context.insert("enable_search", &flags.enable_search);
-            context.insert("can_manage", &can_manage);
-            // Edit/chat are collaboration abilities gated by their flags.
-            // Structural writes require an explicit administrator session.
-            context.insert("enable_edit", &flags.enable_edit);
-            context.insert("enable_live", &flags.enable_live);
-            context.insert("enable_chat", &flags.enable_chat);
+fn parse_hunk_start(token: &str, prefix: char) -> Option<usize> {
+    token
+        .strip_prefix(prefix)?
+        .split(',')
+        .next()?
+        .parse::<usize>()
+        .ok()
+}
 
-            if flags.enable_edit {
-                // JSON-encode and HTML-escape so </script> in content can't break the page.
-                let json = js_json_safe(serde_json::to_string(&markdown_input).unwrap_or_default());
-                context.insert("markdown_content_json", &json);
-                // Embed a token derived for this workspace, NOT the process
-                // secret or master management token. A collaborator cannot
-                // replay it against a differently gated workspace.
-                context.insert(
-                    "save_token",
-                    &workspace_save_token(&state.save_token, workspace_id),
-                );
-            }
+#[cfg(test)]
+fn render_unified_diff_html(unified_diff: &str) -> String {
+    let mut out = String::new();
+    let mut pending_deletes: Vec<&str> = Vec::new();
+    let mut pending_inserts: Vec<&str> = Vec::new();
 
-            render_template(state, "layout.html", &context)
+    for raw_line in unified_diff.split_inclusive('\n') {
+        let line = raw_line.trim_end_matches('\n').trim_end_matches('\r');
+        if is_diff_delete_line(line) {
+            pending_deletes.push(line);
+            continue;
         }
-        Err(e) => {
-            let mut context = base_context(state);
-            context.insert("title", "Error");
-            context.insert("version", env!("CARGO_PKG_VERSION"));
-            context.insert(
-                "content",
-                &format!(
-                    r#"<p style="color: red;">Error reading file '{file_path}': {e}</p>
-                       <a href="/">← Back to file list</a>"#
-                ),
-            );
-            context.insert("show_back_link", &false);
-            context.insert("has_mermaid", &false);
-            context.insert("has_math", &false);
-
-            render_template(state, "layout.html", &context)
+        if is_diff_insert_line(line) {
+            pending_inserts.push(line);
+            continue;
         }
+        flush_diff_change_block(&mut out, &mut pending_deletes, &mut pending_inserts);
+        render_diff_line(&mut out, line, neutral_diff_line_class(line));
     }
+    flush_diff_change_block(&mut out, &mut pending_deletes, &mut pending_inserts);
+    out
 }
 
-/// One row of a directory listing. Shared between the server-rendered file table
-/// (`render_directory_listing`) and the JSON endpoint that feeds the inline tree
-/// (`handle_workspace_dir_data`), so both stay byte-for-byte consistent in what
-/// they list, how they sort, and the commit metadata they attach.
-#[derive(serde::Serialize)]
-struct DirListingEntry {
-    name: String,
-    is_dir: bool,
-    is_markdown: bool,
-    is_hidden: bool,
-    show_in_markdown: bool,
-    link: String,
-    rel_git_path: String,
-    last_commit_subject: Option<String>,
-    last_commit_time: Option<String>,
+fn is_diff_delete_line(line: &str) -> bool {
+    line.starts_with('-') && !line.starts_with("--- ")
 }
 
-/// List the direct children of `current_dir` (already canonicalized and verified
-/// inside `root`), sorted directories-first then by name, with the last-commit
-/// subject/time attached per entry when the workspace is a git repo. Only this
-/// one directory level is walked and only these paths are queried for commits —
-/// cheap enough to serve on demand as a folder is expanded.
-fn collect_directory_entries(
-    workspace_id: &str,
-    root: &FsPath,
-    current_dir: &FsPath,
-) -> std::io::Result<Vec<DirListingEntry>> {
-    let mut entries: Vec<DirListingEntry> = fs::read_dir(current_dir)?
-        .filter_map(|e| e.ok())
-        .filter_map(|entry| {
-            let path = entry.path();
-            let name = entry.file_name().to_string_lossy().to_string();
-            let is_hidden = name.starts_with('.');
-            // Use file_type() — avoids stat() syscall that can block on AutoFS mount points.
-            let file_type = entry.file_type().ok()?;
-            let is_dir = file_type.is_dir();
-            let is_markdown = !is_dir && is_markdown_path(&path);
-            let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
-            let rel_git_path = rel.to_string_lossy().replace('\\', "/");
-            let rel_url = path_to_route(&rel);
-            let link = if is_dir {
-                workspace_file_url(workspace_id, &format!("{rel_url}/"))
-            } else {
-                workspace_file_url(workspace_id, &rel_url)
-            };
-            Some(DirListingEntry {
-                name,
-                is_dir,
-                is_markdown,
-                is_hidden,
-                show_in_markdown: !is_hidden && is_markdown,
-                link,
-                rel_git_path,
-                last_commit_subject: None,
-                last_commit_time: None,
-            })
-        })
-        .collect();
+fn is_diff_insert_line(line: &str) -> bool {
+    line.starts_with('+') && !line.starts_with("+++ ")
+}
 
-    if entries.iter().any(|entry| entry.is_dir && !entry.is_hidden) {
-        let dirs_with_markdown = direct_child_dirs_with_markdown_descendants(root, current_dir);
-        for entry in entries.iter_mut().filter(|entry| entry.is_dir) {
-            entry.show_in_markdown =
-                !entry.is_hidden && dirs_with_markdown.contains(&entry.rel_git_path);
+#[cfg(test)]
+fn flush_diff_change_block<'a>(
+    out: &mut String,
+    deletes: &mut Vec<&'a str>,
+    inserts: &mut Vec<&'a str>,
+) {
+    if deletes.is_empty() && inserts.is_empty() {
+        return;
+    }
+    let pairs = deletes.len().max(inserts.len());
+    for index in 0..pairs {
+        let old_line = deletes.get(index).copied();
+        let new_line = inserts.get(index).copied();
+        match (old_line, new_line) {
+            (Some(old_line), Some(new_line)) => {
+                render_word_diff_line(out, old_line, new_line, ChangeTag::Delete);
+                render_word_diff_line(out, old_line, new_line, ChangeTag::Insert);
+            }
+            (Some(old_line), None) => {
+                render_diff_line(out, old_line, "git-diff-line git-diff-del");
+            }
+            (None, Some(new_line)) => {
+                render_diff_line(out, new_line, "git-diff-line git-diff-add");
+            }
+            (None, None) => {}
         }
     }
+    deletes.clear();
+    inserts.clear();
+}
 
-    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name.cmp(&b.name),
-    });
+#[cfg(test)]
+fn render_word_diff_line(out: &mut String, old_line: &str, new_line: &str, side: ChangeTag) {
+    let (line, line_class, word_class) = match side {
+        ChangeTag::Delete => (old_line, "git-diff-line git-diff-del", "git-diff-word-del"),
+        ChangeTag::Insert => (new_line, "git-diff-line git-diff-add", "git-diff-word-add"),
+        ChangeTag::Equal => (old_line, "git-diff-line", ""),
+    };
+    let body = line.get(1..).unwrap_or("");
+    out.push_str("<span class=\"");
+    out.push_str(line_class);
+    out.push_str("\">");
+    push_escaped(out, &line[..line.len().min(1)]);
 
-    let git_status = git::status(root);
-    if git_status.available {
-        let rel_paths: Vec<String> = entries
-            .iter()
-            .map(|entry| entry.rel_git_path.clone())
-            .collect();
-        if let Ok(path_commits) = git::last_commits_for_paths(root, &rel_paths) {
-            for entry in entries.iter_mut() {
-                let Some(commit) = path_commits.get(&entry.rel_git_path) else {
-                    continue;
-                };
-                entry.last_commit_subject = Some(commit.subject.clone());
-                entry.last_commit_time = Some(commit.time.clone());
+    let word_diff = TextDiff::from_words(
+        old_line.get(1..).unwrap_or(""),
+        new_line.get(1..).unwrap_or(""),
+    );
+    for change in word_diff.iter_all_changes() {
+        match (side, change.tag()) {
+            (ChangeTag::Delete, ChangeTag::Delete) | (ChangeTag::Insert, ChangeTag::Insert) => {
+                out.push_str("<span class=\"");
+                out.push_str(word_class);
+                out.push_str("\">");
+                push_escaped(out, change.value());
+                out.push_str("</span>");
             }
+            (ChangeTag::Delete, ChangeTag::Equal) | (ChangeTag::Insert, ChangeTag::Equal) => {
+                push_escaped(out, change.value());
+            }
+            _ => {}
         }
     }
-
-    Ok(entries)
-}
-
-fn direct_child_dirs_with_markdown_descendants(
-    root: &FsPath,
-    current_dir: &FsPath,
-) -> HashSet<String> {
-    let mut dirs = HashSet::new();
-    let walker = crate::fswalk::default_walker(current_dir).build();
-    for entry in walker.filter_map(|entry| entry.ok()) {
-        let path = entry.path();
-        if path == current_dir || !path.is_file() || !is_markdown_path(path) {
-            continue;
-        }
-        let Ok(rel_to_current) = path.strip_prefix(current_dir) else {
-            continue;
-        };
-        let Some(std::path::Component::Normal(first_component)) =
-            rel_to_current.components().next()
-        else {
-            continue;
-        };
-        let direct_child = current_dir.join(first_component);
-        if direct_child == path {
-            continue;
-        }
-        let rel_to_root = direct_child.strip_prefix(root).unwrap_or(&direct_child);
-        dirs.insert(path_to_route(rel_to_root));
+    if body.is_empty() {
+        out.push(' ');
     }
-    dirs
+    out.push_str("</span>\n");
 }
 
-/// JSON: the direct children of a directory (relative to the workspace root),
-/// used by the inline directory tree on the workspace landing page. Mirrors the
-/// auth/boundary handling of `handle_workspace_files_data`: canonicalize the
-/// requested path and reject anything that escapes the workspace root.
-async fn handle_workspace_dir_data(
-    State(state): State<AppState>,
-    AxumPath(workspace_id): AxumPath<String>,
-    Query(query): Query<DirListingQuery>,
-) -> impl IntoResponse {
-    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    if ws.is_ephemeral() {
-        let rel = query.path.as_deref().unwrap_or("").trim().trim_matches('/');
-        if rel.split('/').any(|part| part == ".." || part == ".") {
-            return StatusCode::NOT_FOUND.into_response();
-        }
-        return Json(scoped_directory_entries(&workspace_id, &ws, rel)).into_response();
-    }
-    let root = canonical_workspace_root(&ws);
-    let rel = query.path.as_deref().unwrap_or("").trim().trim_matches('/');
-    let target = if rel.is_empty() {
-        root.clone()
+fn neutral_diff_line_class(line: &str) -> &'static str {
+    if line.starts_with("@@ ")
+        || line.starts_with("--- ")
+        || line.starts_with("+++ ")
+        || line.starts_with("Binary files differ:")
+        || line.starts_with("rename from ")
+        || line.starts_with("rename to ")
+        || line.starts_with("\\ No newline")
+    {
+        "git-diff-line git-diff-meta"
     } else {
-        root.join(rel)
-    };
-    let current_dir = match canonicalize_route_path(&target) {
-        Ok(p) => p,
-        Err(_) => return StatusCode::NOT_FOUND.into_response(),
-    };
-    if !current_dir.starts_with(&root) {
-        return StatusCode::NOT_FOUND.into_response();
+        "git-diff-line"
     }
-    match collect_directory_entries(&workspace_id, &root, &current_dir) {
-        Ok(entries) => Json(entries).into_response(),
-        Err(_) => Json(Vec::<DirListingEntry>::new()).into_response(),
+}
+
+#[cfg(test)]
+fn render_diff_line(out: &mut String, line: &str, class_name: &str) {
+    out.push_str("<span class=\"");
+    out.push_str(class_name);
+    out.push_str("\">");
+    push_escaped(out, line);
+    if line.is_empty() {
+        out.push(' ');
     }
+    out.push_str("</span>\n");
 }
 
-/// Build a virtual directory view from the single-file capability set without
-/// touching or enumerating sibling filesystem entries.
-fn scoped_directory_entries(
-    workspace_id: &str,
-    ws: &WorkspaceEntry,
-    directory: &str,
-) -> Vec<DirListingEntry> {
-    let prefix = directory.trim_matches('/');
-    let mut entries: HashMap<String, DirListingEntry> = HashMap::new();
-    for (rel, path) in ws.fs.served_files(2000) {
-        let route = rel.as_route();
-        let rest = if prefix.is_empty() {
-            route.as_str()
-        } else if let Some(rest) = route.strip_prefix(prefix).and_then(|r| r.strip_prefix('/')) {
-            rest
-        } else {
-            continue;
-        };
-        let (name, is_dir) = match rest.split_once('/') {
-            Some((name, _)) => (name, true),
-            None => (rest, false),
-        };
-        if name.is_empty() {
-            continue;
-        }
-        let child_route = if prefix.is_empty() {
-            name.to_string()
-        } else {
-            format!("{prefix}/{name}")
-        };
-        let link_route = if is_dir {
-            format!("{child_route}/")
-        } else {
-            child_route.clone()
-        };
-        let markdown_descendant = is_markdown_path(&path);
-        let entry = entries
-            .entry(name.to_string())
-            .or_insert_with(|| DirListingEntry {
-                name: name.to_string(),
-                is_dir,
-                is_markdown: !is_dir && markdown_descendant,
-                is_hidden: name.starts_with('.'),
-                show_in_markdown: !name.starts_with('.') && markdown_descendant,
-                link: workspace_file_url(workspace_id, &link_route),
-                rel_git_path: child_route,
-                last_commit_subject: None,
-                last_commit_time: None,
-            });
-        entry.show_in_markdown |= !entry.is_hidden && markdown_descendant;
+#[cfg(test)]
+fn push_escaped(out: &mut String, text: &str) {
+    html_escape::encode_text_to_string(text, out);
+}
+
+fn git_diff_ref_values(root: &FsPath, diff: &git::GitDiff) -> (String, String) {
+    if diff.range == "HEAD..worktree" {
+        return ("HEAD".to_string(), "worktree".to_string());
     }
-    let mut entries: Vec<_> = entries.into_values().collect();
-    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
-        (true, false) => std::cmp::Ordering::Less,
-        (false, true) => std::cmp::Ordering::Greater,
-        _ => a.name.cmp(&b.name),
-    });
-    entries
+    if let Some((base, compare)) = diff.range.split_once("..") {
+        return (base.to_string(), compare.to_string());
+    }
+    let base = git::parent_commit(root, &diff.range)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| "HEAD".to_string());
+    (base, diff.range.clone())
 }
 
-#[derive(Deserialize)]
-struct DirListingQuery {
-    path: Option<String>,
+#[derive(Clone, Copy)]
+enum GitCompareOptionRole {
+    Base,
+    Compare,
 }
 
-fn render_directory_listing(
-    workspace_id: &str,
-    ws: &WorkspaceEntry,
-    root: &FsPath,
-    dir_param: Option<&str>,
-    state: &AppState,
-    can_manage: bool,
-) -> Response {
-    let Some(workspace_root) = ws.fs.directory_root() else {
-        return StatusCode::NOT_FOUND.into_response();
-    };
-    let current_dir = if let Some(dir_str) = dir_param {
-        let p = PathBuf::from(dir_str);
-        if p.is_absolute() {
-            p
-        } else {
-            workspace_root.join(&p)
-        }
-    } else {
-        workspace_root.to_path_buf()
-    };
+#[derive(Clone, Copy)]
+enum GitCompareOptionStatusMode {
+    Fast,
+    Checked,
+}
 
-    let current_dir = match canonicalize_route_path(&current_dir) {
-        Ok(p) => p,
-        Err(e) => {
-            return (StatusCode::BAD_REQUEST, format!("Invalid directory: {e}")).into_response()
-        }
+fn git_compare_option_has_markdown_changes(
+    root: &FsPath,
+    markdown: &MarkdownExtensions,
+    value: &str,
+    other_ref: &str,
+    role: GitCompareOptionRole,
+) -> bool {
+    let (base, compare) = match role {
+        GitCompareOptionRole::Base => (value, other_ref),
+        GitCompareOptionRole::Compare => (other_ref, value),
     };
-    // Defense in depth: the caller's gate trims the leading slash before its
-    // boundary check, but this function re-derives `current_dir` from the raw
-    // (possibly absolute) `dir_param`. Re-verify the canonical dir is inside the
-    // workspace so an absolute path like `/etc` can't list outside the root.
-    if !current_dir.starts_with(root) {
-        return StatusCode::NOT_FOUND.into_response();
+    if base == compare {
+        return false;
     }
+    // Refs here come from a trusted enumeration (HEAD / history / worktree), so
+    // skip re-validation and run just the single diff probe.
+    git::diff_has_markdown_changes_unchecked(root, markdown, base, compare).unwrap_or(true)
+}
 
-    let entries = match collect_directory_entries(workspace_id, root, &current_dir) {
-        Ok(entries) => entries,
-        Err(e) => {
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                format!("Error reading directory: {e}"),
-            )
-                .into_response()
+fn git_compare_option_statuses(options: Vec<GitCompareOption>) -> Vec<GitCompareOptionStatus> {
+    options
+        .into_iter()
+        .map(|option| GitCompareOptionStatus {
+            value: option.value,
+            disabled: option.disabled,
+        })
+        .collect()
+}
+
+fn git_compare_options(
+    root: &FsPath,
+    markdown: &MarkdownExtensions,
+    selected: &str,
+    include_worktree: bool,
+    other_ref: &str,
+    role: GitCompareOptionRole,
+    status_mode: GitCompareOptionStatusMode,
+) -> Vec<GitCompareOption> {
+    // 1) Gather unique candidates in display order (a handful of cheap git
+    //    calls). `check` marks options whose "disabled" state needs a diff probe.
+    // A candidate carries the rich metadata the picker panel renders (kind /
+    // subject / detail / date), plus `check` = whether its disabled state needs a
+    // markdown-diff probe.
+    struct Cand {
+        value: String,
+        label: String,
+        alias: String,
+        kind: String,
+        subject: String,
+        detail: String,
+        date: String,
+        check: bool,
+    }
+    let mut seen = std::collections::BTreeSet::new();
+    let mut candidates: Vec<Cand> = Vec::new();
+    let mut add = |c: Cand| {
+        if seen.insert(c.value.clone()) {
+            candidates.push(c);
         }
     };
-    let git_status = git::status(root);
 
-    let show_parent = current_dir != root;
-    let parent_link: Option<String> = if show_parent {
-        current_dir.parent().map(|parent| {
-            let rel = parent
-                .strip_prefix(root)
-                .map(path_to_route)
-                .unwrap_or_default();
-            if rel.is_empty() {
-                workspace_root_url(workspace_id)
-            } else {
-                workspace_file_url(workspace_id, &format!("{rel}/"))
-            }
-        })
-    } else {
-        None
-    };
-
-    // Breadcrumb from workspace root down to `current_dir`. The first segment is
-    // the workspace itself (alias, falling back to the root dir name) linking to
-    // the workspace root; each deeper segment links to its own subdirectory. The
-    // final segment is the current directory and carries no link. At the root the
-    // breadcrumb is a single (current) segment. Path components are joined with
-    // `/` so Windows separators normalise like `path_to_route`.
-    #[derive(serde::Serialize)]
-    struct BreadcrumbSegment {
-        name: String,
-        link: String,
-        is_current: bool,
-    }
-    let workspace_display_name = workspace_display_name(ws, root);
-    let rel_components: Vec<String> = current_dir
-        .strip_prefix(root)
-        .ok()
-        .map(|rel| {
-            rel.components()
-                .filter_map(|c| match c {
-                    std::path::Component::Normal(part) => Some(part.to_string_lossy().to_string()),
-                    _ => None,
-                })
-                .collect()
-        })
-        .unwrap_or_default();
-    let mut breadcrumb: Vec<BreadcrumbSegment> = Vec::new();
-    let depth = rel_components.len();
-    breadcrumb.push(BreadcrumbSegment {
-        name: workspace_display_name,
-        link: workspace_root_url(workspace_id),
-        is_current: depth == 0,
+    add(Cand {
+        value: "HEAD".to_string(),
+        label: "HEAD".to_string(),
+        alias: String::new(),
+        kind: "head".to_string(),
+        subject: "Latest commit".to_string(),
+        detail: String::new(),
+        date: String::new(),
+        check: true,
     });
-    let mut acc = String::new();
-    for (i, comp) in rel_components.iter().enumerate() {
-        if acc.is_empty() {
-            acc = comp.clone();
+    for branch in git::branches(root).unwrap_or_default() {
+        let label = if branch.current {
+            format!("{} (current)", branch.name)
         } else {
-            acc = format!("{acc}/{comp}");
-        }
-        breadcrumb.push(BreadcrumbSegment {
-            name: comp.clone(),
-            link: workspace_file_url(workspace_id, &format!("{acc}/")),
-            is_current: i + 1 == depth,
+            branch.name.clone()
+        };
+        add(Cand {
+            value: branch.name,
+            label,
+            alias: String::new(),
+            kind: "branch".to_string(),
+            subject: String::new(),
+            detail: if branch.current {
+                "current".to_string()
+            } else {
+                String::new()
+            },
+            date: String::new(),
+            check: false,
+        });
+    }
+    for (idx, commit) in git::history(root, 50)
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+    {
+        add(Cand {
+            value: commit.hash,
+            label: format!("{} {}", commit.short_hash, commit.subject),
+            alias: if idx == 0 {
+                "Latest".to_string()
+            } else {
+                String::new()
+            },
+            kind: "commit".to_string(),
+            subject: commit.subject,
+            detail: commit.short_hash,
+            date: commit.relative_time,
+            check: true,
+        });
+    }
+    for tag in git::tags(root, 200).unwrap_or_default() {
+        add(Cand {
+            value: tag.name.clone(),
+            label: format!("{} (tag)", tag.name),
+            alias: String::new(),
+            kind: "tag".to_string(),
+            subject: String::new(),
+            detail: tag.short_hash,
+            date: tag.relative_time,
+            check: false,
+        });
+    }
+    if include_worktree {
+        add(Cand {
+            value: "worktree".to_string(),
+            label: "Worktree".to_string(),
+            alias: String::new(),
+            kind: "worktree".to_string(),
+            subject: "Uncommitted working-tree files".to_string(),
+            detail: String::new(),
+            date: String::new(),
+            check: true,
         });
     }
 
-    let flags = ws.flags();
-    let feature_statuses = vec![
-        WorkspaceFeatureStatus {
-            key: "enable_search",
-            label: "Search",
-            label_key: "web.ws.feature.search",
-            enabled: flags.enable_search,
-        },
-        WorkspaceFeatureStatus {
-            key: "enable_viewed",
-            label: "Viewed tracking",
-            label_key: "web.ws.feature.viewed",
-            enabled: flags.enable_viewed,
-        },
-        WorkspaceFeatureStatus {
-            key: "enable_edit",
-            label: "Edit",
-            label_key: "web.ws.feature.edit",
-            enabled: flags.enable_edit,
-        },
-        WorkspaceFeatureStatus {
-            key: "enable_live",
-            label: "Live",
-            label_key: "web.ws.feature.live",
-            enabled: flags.enable_live,
-        },
-        WorkspaceFeatureStatus {
-            key: "enable_chat",
-            label: "AI Chat",
-            label_key: "web.ws.feature.chat",
-            enabled: flags.enable_chat,
-        },
-        WorkspaceFeatureStatus {
-            key: "shared_annotation",
-            label: "Shared notes",
-            label_key: "web.ws.feature.shared",
-            enabled: flags.shared_annotation,
-        },
-    ];
-    let git_commits = if git_status.available {
-        git::history(root, 6).unwrap_or_default()
-    } else {
-        Vec::new()
-    };
-    let git_commit_count = if git_status.available {
-        git::commit_count(root).unwrap_or(0)
-    } else {
-        0
-    };
-    // Detailed branches (adds `is_default`) so the switch-branch panel can flag
-    // the default branch; still carries `name`/`current` for checkout.
-    let git_branches = if git_status.available {
-        git::branches_detailed(root).unwrap_or_default()
-    } else {
-        Vec::new()
-    };
-    let git_branch_count = if git_status.available {
-        git_branches.len()
-    } else {
-        0
-    };
-    let git_tag_count = if git_status.available {
-        git::tag_count(root).unwrap_or(0)
+    // 2) Resolve each option's `disabled` state in parallel. Every probe spawns
+    //    its own git subprocess(es); the bottleneck is process spawn, so this
+    //    fans the ~dozens of probes across cores instead of running serially.
+    let checked = matches!(status_mode, GitCompareOptionStatusMode::Checked);
+    let mut out: Vec<GitCompareOption> = candidates
+        .par_iter()
+        .map(|c| {
+            let disabled = checked
+                && c.check
+                && c.value != selected
+                && !git_compare_option_has_markdown_changes(
+                    root, markdown, &c.value, other_ref, role,
+                );
+            GitCompareOption {
+                selected: c.value == selected,
+                value: c.value.clone(),
+                label: c.label.clone(),
+                alias: c.alias.clone(),
+                kind: c.kind.clone(),
+                subject: c.subject.clone(),
+                detail: c.detail.clone(),
+                date: c.date.clone(),
+                disabled,
+            }
+        })
+        .collect();
+
+    if !selected.is_empty() && !seen.contains(selected) {
+        out.insert(
+            0,
+            GitCompareOption {
+                value: selected.to_string(),
+                label: short_git_ref(selected),
+                alias: String::new(),
+                kind: "commit".to_string(),
+                subject: String::new(),
+                detail: short_git_ref(selected),
+                date: String::new(),
+                selected: true,
+                disabled: false,
+            },
+        );
+    }
+
+    out
+}
+
+const GIT_EMPTY_TREE_HASH: &str = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+
+fn git_commit_compare_base(root: &FsPath, commit: &str) -> String {
+    git::parent_commit(root, commit)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| GIT_EMPTY_TREE_HASH.to_string())
+}
+
+fn git_commit_markdown_diff_url(
+    base_path: &str,
+    root: &FsPath,
+    markdown: &MarkdownExtensions,
+    workspace_id: &str,
+    commit: &git::GitCommit,
+    view: &str,
+) -> Option<String> {
+    let diff = git::commit_diff(root, &commit.hash).ok()?;
+    if markdown_only_git_diff(&diff, markdown, None)
+        .files
+        .is_empty()
+    {
+        return None;
+    }
+    Some(pretty_compare_page_url(
+        base_path,
+        workspace_id,
+        &git_commit_compare_base(root, &commit.hash),
+        &commit.hash,
+        view,
+    ))
+}
+
+fn git_source_diff_url(
+    base_path: &str,
+    workspace_id: &str,
+    root: &FsPath,
+    diff: &git::GitDiff,
+    base: &str,
+    compare: &str,
+) -> String {
+    if diff.range == "HEAD..worktree" {
+        pretty_compare_page_url(base_path, workspace_id, "HEAD", "worktree", "raw")
+    } else if diff.range.contains("..") {
+        pretty_compare_page_url(base_path, workspace_id, base, compare, "raw")
     } else {
-        0
+        pretty_compare_page_url(
+            base_path,
+            workspace_id,
+            &git_commit_compare_base(root, &diff.range),
+            &diff.range,
+            "raw",
+        )
+    }
+}
+
+fn markdown_work_diff_page_url(base_path: &str, workspace_id: &str) -> String {
+    pretty_compare_page_url(base_path, workspace_id, "HEAD", "worktree", "rendered")
+}
+
+fn markdown_diff_page_url(
+    base_path: &str,
+    workspace_id: &str,
+    root: &FsPath,
+    diff: &git::GitDiff,
+    base: &str,
+    compare: &str,
+) -> String {
+    if diff.range == "HEAD..worktree" {
+        markdown_work_diff_page_url(base_path, workspace_id)
+    } else if diff.range.contains("..") {
+        pretty_compare_page_url(base_path, workspace_id, base, compare, "rendered")
+    } else {
+        pretty_compare_page_url(
+            base_path,
+            workspace_id,
+            &git_commit_compare_base(root, &diff.range),
+            &diff.range,
+            "rendered",
+        )
+    }
+}
+
+fn pretty_compare_page_url(
+    base_path: &str,
+    workspace_id: &str,
+    base: &str,
+    compare: &str,
+    view: &str,
+) -> String {
+    format!(
+        "{}/{}...{}?view={}",
+        workspace_compare_base_url(base_path, workspace_id),
+        encode_compare_ref_for_path(base),
+        encode_compare_ref_for_path(compare),
+        view
+    )
+}
+
+fn pretty_compare_data_url(
+    base_path: &str,
+    workspace_id: &str,
+    base: &str,
+    compare: &str,
+    view: &str,
+) -> String {
+    format!(
+        "{}&format=data",
+        pretty_compare_page_url(base_path, workspace_id, base, compare, view)
+    )
+}
+
+fn encode_compare_ref_for_path(value: &str) -> String {
+    urlencoding::encode(value).replace("%2F", "/")
+}
+
+fn markdown_diff_data_url(
+    base_path: &str,
+    workspace_id: &str,
+    base: &str,
+    compare: &str,
+) -> String {
+    pretty_compare_data_url(base_path, workspace_id, base, compare, "rendered")
+}
+
+/// The Markdiff page for `diff`. `document` narrows the file list to one
+/// document and opens the rendered view on it.
+fn render_git_diff_page(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    can_manage: bool,
+    diff: &git::GitDiff,
+    initial_view: &str,
+    document: Option<&str>,
+) -> Response {
+    let flags = ws.flags();
+    let root = directory_root_or_not_found!(ws);
+    let (base_value, compare_value) = git_diff_ref_values(root, diff);
+    let initial_view = if initial_view == "rendered" {
+        "rendered"
+    } else {
+        "raw"
     };
-    let git_changed_count = git_status.added
-        + git_status.modified
-        + git_status.deleted
-        + git_status.renamed
-        + git_status.untracked;
-    let work_diff_has_markdown_changes = git_status.available
-        && git::diff_has_markdown_changes(root, "HEAD", "worktree").unwrap_or(false);
-    let work_diff_url =
-        work_diff_has_markdown_changes.then(|| markdown_work_diff_page_url(workspace_id));
-    let latest_commit = git_commits.first().cloned();
-    let latest_commit_diff_url = latest_commit
-        .as_ref()
-        .and_then(|commit| git_commit_markdown_diff_url(root, workspace_id, commit, "rendered"));
-    let is_workspace_root = current_dir == root;
-    let can_add_file = can_manage && flags.enable_edit;
+    let display_diff = markdown_only_git_diff(diff, &ws.fs.policy().markdown, document);
+    // Default to the all-files continuous view (no file pre-selected). The left
+    // file list focuses a single file on demand; an empty default means "no `f`"
+    // renders every changed file in one scroll instead of just the first.
+    let default_diff_path = document.unwrap_or_default();
+    let mut context = base_context(state);
+    context.insert("title", &format!("Markdiff · {}", diff.range));
+    context.insert("workspace_id", workspace_id);
+    context.insert(
+        "preview_token",
+        &workspace_preview_token(&state.save_token, workspace_id),
+    );
+    context.insert(
+        "diff",
+        &git_diff_template(
+            root,
+            &display_diff,
+            base_value.clone(),
+            compare_value.clone(),
+        ),
+    );
+    context.insert("can_manage", &can_manage);
+    context.insert("shared_annotation", &flags.shared_annotation);
+    context.insert("enable_live", &flags.enable_live);
+    context.insert("enable_chat", &flags.enable_chat);
+    context.insert(
+        "history_url",
+        &workspace_git_history_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "files_url",
+        &workspace_root_url(&state.base_path, workspace_id),
+    );
+    // Home-collapsed workspace path shown beside the title (links to the home).
+    let ws_display_path = workspace_display_path(root);
+    context.insert("workspace_display_path", &ws_display_path);
+    context.insert("workspace_alias", &ws.alias());
+    context.insert(
+        "work_diff_url",
+        &markdown_work_diff_page_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "markdown_diff_url",
+        &markdown_diff_page_url(
+            &state.base_path,
+            workspace_id,
+            root,
+            diff,
+            &base_value,
+            &compare_value,
+        ),
+    );
+    context.insert(
+        "source_diff_url",
+        &git_source_diff_url(
+            &state.base_path,
+            workspace_id,
+            root,
+            diff,
+            &base_value,
+            &compare_value,
+        ),
+    );
+    context.insert(
+        "compare_url",
+        &pretty_compare_page_url(
+            &state.base_path,
+            workspace_id,
+            &base_value,
+            &compare_value,
+            initial_view,
+        ),
+    );
+    context.insert(
+        "compare_path_base",
+        &workspace_compare_base_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "compare_options_status_url",
+        &workspace_compare_options_url(&state.base_path, workspace_id),
+    );
+    context.insert("initial_diff_view", initial_view);
+    context.insert("default_diff_path", &default_diff_path);
+    context.insert("is_markdown_diff", &(initial_view == "rendered"));
+    let base_options = git_compare_options(
+        root,
+        &ws.fs.policy().markdown,
+        &base_value,
+        false,
+        &compare_value,
+        GitCompareOptionRole::Base,
+        GitCompareOptionStatusMode::Fast,
+    );
+    let compare_options = git_compare_options(
+        root,
+        &ws.fs.policy().markdown,
+        &compare_value,
+        true,
+        &base_value,
+        GitCompareOptionRole::Compare,
+        GitCompareOptionStatusMode::Fast,
+    );
+    // One JSON bundle drives the custom Base↔Compare picker panel (rich rows with
+    // subject/date, quick presets, client-side navigation + status probing).
+    let picker_json = serde_json::json!({
+        "base": base_options,
+        "compare": compare_options,
+        "baseValue": base_value,
+        "compareValue": compare_value,
+        "pathBase": workspace_compare_base_url(&state.base_path, workspace_id),
+        "statusUrl": workspace_compare_options_url(&state.base_path, workspace_id),
+    })
+    .to_string();
+    context.insert("compare_picker_json", &picker_json);
+    // "Worktree-targeting" = the Compare (new) side is the live worktree, for ANY
+    // base (HEAD…Worktree, a-tag…Worktree, …). That new side is the set of real,
+    // writable files on disk — what annotations bind to and what the sidebar's
+    // create-file/folder writes into. (`worktree` is only ever offered as a
+    // Compare option, never a Base.) Comparing two commits never targets it.
+    let is_worktree_diff = compare_value == "worktree";
+    context.insert("is_worktree_diff", &is_worktree_diff);
+    // The sidebar tree can create files/folders only when the comparison targets
+    // the (writable) worktree AND the workspace permits editing.
+    let diff_editable =
+        is_worktree_diff && ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed);
+    context.insert("diff_editable", &diff_editable);
+    context.insert(
+        "create_file_url",
+        &workspace_file_create_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "create_folder_url",
+        &workspace_folder_create_url(&state.base_path, workspace_id),
+    );
+    // Both views (rendered + raw source) now consume one unified Markdown block
+    // payload, so a single data URL drives the whole page.
+    let markdown_diff_data_url =
+        markdown_diff_data_url(&state.base_path, workspace_id, &base_value, &compare_value);
+    context.insert("markdown_diff_data_url", &markdown_diff_data_url);
+    render_template(state, "git-diff.html", &context)
+}
+
+#[derive(Serialize)]
+struct GitHistoryCommitTemplate<'a> {
+    short_hash: &'a str,
+    author: &'a str,
+    date: &'a str,
+    subject: &'a str,
+    diff_url: Option<String>,
+    /// The document as of this commit, on a document's history page.
+    view_url: Option<String>,
+}
+
+/// One calendar day worth of commits, matching GitHub's date-grouped Commits
+/// page. `commits` keeps the incoming reverse-chronological order.
+#[derive(Serialize)]
+struct GitHistoryDay<'a> {
+    day_label: String,
+    commits: Vec<GitHistoryCommitTemplate<'a>>,
+}
+
+/// Turn an ISO date like `2026-03-07T08:30:43+08:00` into GitHub's
+/// `Mar 7, 2026` heading. No new deps — the month is mapped by hand from the
+/// `MM` field. Falls back to the raw string if the date is malformed.
+fn git_history_day_label(date: &str) -> String {
+    if date.len() < 10 {
+        return date.to_string();
+    }
+    let year = &date[0..4];
+    let month = match &date[5..7] {
+        "01" => "Jan",
+        "02" => "Feb",
+        "03" => "Mar",
+        "04" => "Apr",
+        "05" => "May",
+        "06" => "Jun",
+        "07" => "Jul",
+        "08" => "Aug",
+        "09" => "Sep",
+        "10" => "Oct",
+        "11" => "Nov",
+        "12" => "Dec",
+        other => other,
+    };
+    let day = date[8..10].trim_start_matches('0');
+    let day = if day.is_empty() { "0" } else { day };
+    format!("{month} {day}, {year}")
+}
+
+/// One selectable branch in the toolbar's branch dropdown; `current` marks the
+/// entry that matches the active `?branch=` (or the real checked-out branch).
+#[derive(Serialize)]
+struct GitBranchOption {
+    name: String,
+    current: bool,
+}
+
+/// A time-range preset for the "All time" dropdown.
+#[derive(Serialize)]
+struct GitRangeOption {
+    key: &'static str,
+    label: &'static str,
+    current: bool,
+}
+
+/// An author entry for the "All users" dropdown.
+#[derive(Serialize)]
+struct GitAuthorOption {
+    name: String,
+    current: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_git_history_page(
+    state: &AppState,
+    workspace_id: &str,
+    root: &FsPath,
+    markdown: &MarkdownExtensions,
+    commits: &[git::GitCommit],
+    selected_branch: Option<&str>,
+    selected_author: Option<&str>,
+    range_key: &str,
+    document: Option<&str>,
+) -> Response {
+    // Group commits by their `YYYY-MM-DD` prefix while preserving the incoming
+    // reverse-chronological order (commits are already sorted newest-first).
+    // Resolve every commit's parent + markdown-changed flag in one git pass, so
+    // the per-commit diff link below is a pure hashmap lookup rather than a
+    // `git show` + `rev-parse` subprocess pair per row.
+    let commit_hashes: Vec<&str> = commits.iter().map(|c| c.hash.as_str()).collect();
+    let diff_index = git::commit_diff_index(root, markdown, &commit_hashes).unwrap_or_default();
+    let mut groups: Vec<GitHistoryDay<'_>> = Vec::new();
+    let mut last_key: Option<&str> = None;
+    for commit in commits {
+        let key = commit.date.get(0..10).unwrap_or(commit.date.as_str());
+        let info = diff_index.get(&commit.hash);
+        let parent = info
+            .and_then(|info| info.parent.as_deref())
+            .unwrap_or(GIT_EMPTY_TREE_HASH);
+        // A document's own history only lists commits that touched it.
+        let diff_url = match document {
+            Some(path) => Some(document_diff_url(
+                &state.base_path,
+                workspace_id,
+                path,
+                parent,
+                &commit.hash,
+            )),
+            None => info.filter(|info| info.has_markdown).map(|_| {
+                pretty_compare_page_url(
+                    &state.base_path,
+                    workspace_id,
+                    parent,
+                    &commit.hash,
+                    "rendered",
+                )
+            }),
+        };
+        let item = GitHistoryCommitTemplate {
+            short_hash: &commit.short_hash,
+            author: &commit.author,
+            date: &commit.date,
+            subject: &commit.subject,
+            diff_url,
+            view_url: document.map(|path| {
+                document_revision_url(&state.base_path, workspace_id, path, &commit.hash)
+            }),
+        };
+        if last_key == Some(key) {
+            groups
+                .last_mut()
+                .expect("last_key set implies a prior group")
+                .commits
+                .push(item);
+        } else {
+            groups.push(GitHistoryDay {
+                day_label: git_history_day_label(&commit.date),
+                commits: vec![item],
+            });
+            last_key = Some(key);
+        }
+    }
+    // Real branch list + the name actually checked out, used both to build the
+    // dropdown and to label it when no explicit `?branch=` is selected.
+    let all_branches = git::branches(root).unwrap_or_default();
+    let checked_out = all_branches
+        .iter()
+        .find(|b| b.current)
+        .map(|b| b.name.clone())
+        .unwrap_or_else(|| "main".to_string());
+    // Only honour a `?branch=` that really exists (mirrors history_filtered's
+    // whitelist, so the label never lies about which rev was walked).
+    let active_branch = selected_branch
+        .filter(|name| all_branches.iter().any(|b| b.name == *name))
+        .map(str::to_string);
+    let current_branch_label = active_branch.clone().unwrap_or_else(|| checked_out.clone());
+    let branch_options: Vec<GitBranchOption> = all_branches
+        .iter()
+        .map(|b| GitBranchOption {
+            name: b.name.clone(),
+            current: b.name == current_branch_label,
+        })
+        .collect();
+
+    let author_list = git::authors(root).unwrap_or_default();
+    let author_options: Vec<GitAuthorOption> = author_list
+        .iter()
+        .map(|name| GitAuthorOption {
+            name: name.clone(),
+            current: selected_author == Some(name.as_str()),
+        })
+        .collect();
+    let current_author_label = selected_author
+        .map(str::to_string)
+        .unwrap_or_else(|| "All users".to_string());
+
+    const RANGE_PRESETS: [(&str, &str); 5] = [
+        ("", "All time"),
+        ("day", "Last 24 hours"),
+        ("week", "Last 7 days"),
+        ("month", "Last 30 days"),
+        ("year", "Last 12 months"),
+    ];
+    let range_options: Vec<GitRangeOption> = RANGE_PRESETS
+        .iter()
+        .map(|(key, label)| GitRangeOption {
+            key,
+            label,
+            current: *key == range_key,
+        })
+        .collect();
+    let current_range_label = RANGE_PRESETS
+        .iter()
+        .find(|(key, _)| *key == range_key)
+        .map(|(_, label)| *label)
+        .unwrap_or("All time");
+
+    let work_diff_url = match document {
+        Some(path) => git::path_has_changes(root, path)
+            .then(|| document_diff_url(&state.base_path, workspace_id, path, "HEAD", "worktree")),
+        None => git::diff_has_markdown_changes(root, markdown, "HEAD", "worktree")
+            .unwrap_or(false)
+            .then(|| markdown_work_diff_page_url(&state.base_path, workspace_id)),
+    };
+    let mut context = base_context(state);
+    match document {
+        Some(path) => {
+            context.insert("title", &format!("markon history - {path}"));
+            context.insert("document_path", path);
+            context.insert(
+                "document_url",
+                &workspace_file_url(&state.base_path, workspace_id, path),
+            );
+        }
+        None => context.insert("title", "markon git history"),
+    }
+    context.insert("workspace_id", workspace_id);
+    context.insert("groups", &groups);
+    context.insert("commit_count", &commits.len());
+    context.insert("current_branch", &current_branch_label);
+    context.insert("current_branch_label", &current_branch_label);
+    context.insert("branches", &branch_options);
+    context.insert("authors", &author_options);
+    context.insert("current_author", &selected_author);
+    context.insert("current_author_label", &current_author_label);
+    context.insert("ranges", &range_options);
+    context.insert("current_range", &range_key);
+    context.insert("current_range_label", &current_range_label);
+    context.insert(
+        "files_url",
+        &workspace_root_url(&state.base_path, workspace_id),
+    );
+    context.insert("has_commits", &!groups.is_empty());
+    let filters_active = selected_author.is_some() || (!range_key.is_empty() && range_key != "all");
+    context.insert("filters_active", &filters_active);
+    context.insert("work_diff_url", &work_diff_url);
+    render_template(state, "git-history.html", &context)
+}
+
+/// The document at `path` as committed in `rev`, rendered read-only with links
+/// to its history, its current version and a diff against that. Rendered
+/// outside the render cache, which holds the working copy.
+async fn render_document_revision(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: PathBuf,
+    path: String,
+    rev: String,
+) -> Response {
+    let (git_root, git_path) = (root.clone(), path.clone());
+    let renderer = default_markdown_engine(&state.theme, &state.render_hooks).with_asset_context(
+        workspace_root_url(&state.base_path, workspace_id),
+        root.join(&path),
+        root.clone(),
+    );
+    let revision = tokio::task::spawn_blocking(move || {
+        let commit = git::commit(&git_root, &rev)?;
+        let rendered = git::file_at_commit(&git_root, &commit.hash, &git_path)?.map(|bytes| {
+            let markdown = String::from_utf8_lossy(&bytes).into_owned();
+            let title = crate::markdown::document_title(&markdown);
+            (title, MarkdownEngine::render(&renderer, &markdown))
+        });
+        Ok((commit, rendered))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("git document revision blocking task join error: {e}");
+        Err(git::GitError::Command("internal task error".into()))
+    });
+    let (commit, title, rendered) = match revision {
+        Ok((commit, Some((title, rendered)))) => (commit, title, rendered),
+        Ok((_, None)) => {
+            return (StatusCode::NOT_FOUND, "Document not found in this commit").into_response()
+        }
+        Err(git::GitError::InvalidRevision) => {
+            return (StatusCode::BAD_REQUEST, "Invalid git revision").into_response()
+        }
+        Err(git::GitError::NotRepository) => return git_not_repository_response(),
+        Err(e) => {
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read git revision: {e}"),
+            )
+                .into_response()
+        }
+    };
+
+    let mut context = base_context(state);
+    context.insert(
+        "title",
+        &format!(
+            "{} @ {}",
+            title.as_deref().unwrap_or(&path),
+            commit.short_hash
+        ),
+    );
+    context.insert("workspace_id", workspace_id);
+    insert_workspace_header_context(&mut context, ws, &root);
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("document_path", &path);
+    context.insert("commit", &commit);
+    context.insert("content", &rendered.html);
+    context.insert("has_math", &rendered.has_math);
+    context.insert(
+        "history_url",
+        &document_history_url(&state.base_path, workspace_id, &path),
+    );
+    context.insert(
+        "document_url",
+        &workspace_file_url(&state.base_path, workspace_id, &path),
+    );
+    context.insert(
+        "compare_url",
+        &document_diff_url(
+            &state.base_path,
+            workspace_id,
+            &path,
+            &commit.hash,
+            "worktree",
+        ),
+    );
+    render_template(state, "document-revision.html", &context)
+}
+
+#[derive(Deserialize)]
+struct RemoteMarkdownQuery {
+    url: Option<String>,
+}
+
+/// `GET /_/remote?url=https://…/README.md` — a remote Markdown document,
+/// rendered read-only. Only hosts given with `--allow-remote` are fetched.
+async fn handle_remote_markdown(
+    State(state): State<AppState>,
+    Query(query): Query<RemoteMarkdownQuery>,
+) -> Response {
+    let Some(url) = query.url.as_deref().filter(|url| !url.trim().is_empty()) else {
+        return (StatusCode::BAD_REQUEST, "Missing url").into_response();
+    };
+    let document = match crate::remote_markdown::parse_url(url) {
+        Ok(url) => state.remote_markdown.fetch(&url).await,
+        Err(e) => Err(e),
+    };
+    let document = match document {
+        Ok(document) => document,
+        Err(e @ RemoteError::InvalidUrl(_)) => {
+            return (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+        }
+        Err(
+            e @ (RemoteError::Disabled
+            | RemoteError::HostNotAllowed(_)
+            | RemoteError::PrivateAddress(_)),
+        ) => return (StatusCode::FORBIDDEN, e.to_string()).into_response(),
+        Err(e) => return (StatusCode::BAD_GATEWAY, e.to_string()).into_response(),
+    };
+    let renderer = default_markdown_engine(&state.theme, &state.render_hooks);
+    let markdown = document.clone();
+    let rendered =
+        tokio::task::spawn_blocking(move || MarkdownEngine::render(&renderer, &markdown.markdown))
+            .await;
+    let rendered = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!("remote markdown render task join error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut context = base_context(&state);
+    context.insert(
+        "title",
+        &crate::markdown::document_title(&document.markdown)
+            .unwrap_or_else(|| document.file_name()),
+    );
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("source_url", document.url.as_str());
+    context.insert("content", &rendered.html);
+    context.insert("has_math", &rendered.has_math);
+    render_template(&state, "remote.html", &context)
+}
+
+/// Resolve `path` like a document view does and read it, off the async
+/// workers. Returns the canonical path, its workspace route and the
+/// Markdown; `None` for anything that isn't a Markdown file in `ws`.
+async fn read_workspace_document(
+    ws: &Arc<WorkspaceEntry>,
+    root: &FsPath,
+    path: &str,
+) -> Option<(PathBuf, String, String)> {
+    let (ws, root, rel) = (
+        ws.clone(),
+        root.to_path_buf(),
+        path.trim_start_matches('/').to_string(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let canonical = ws.fs.resolve_served(&rel).ok()?;
+        if !ws.fs.policy().is_markdown(&canonical) || !is_inside_workspace(&canonical, &root) {
+            return None;
+        }
+        let markdown = fs::read_to_string(&canonical).ok()?;
+        let route = workspace_relative_path(&canonical, &root)
+            .map(|rel| path_to_route(&rel))
+            .unwrap_or(rel);
+        Some((canonical, route, markdown))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("document read join error: {e}");
+        None
+    })
+}
+
+/// `GET /_/{workspace_id}/print/{*path}` — the document alone on a light
+/// page with print-view.css: `<details>` opened, page-break hints before
+/// top-level sections and link targets spelled out, so the browser's Print
+/// or Save as PDF gives a clean copy.
+async fn handle_print_view(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
+) -> Response {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = canonical_workspace_root(&ws);
+    let Some((canonical, route, markdown)) = read_workspace_document(&ws, &root, &path).await
+    else {
+        return (StatusCode::NOT_FOUND, format!("Document not found: {path}")).into_response();
+    };
+    // Printed in light colors whatever the reader's theme.
+    let renderer = default_markdown_engine("light", &state.render_hooks).with_asset_context(
+        workspace_root_url(&state.base_path, &workspace_id),
+        &canonical,
+        root,
+    );
+    let rendered = tokio::task::spawn_blocking(move || {
+        let rendered = MarkdownEngine::render(&renderer, &markdown);
+        (crate::markdown::document_title(&markdown), rendered)
+    })
+    .await;
+    let (title, rendered) = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!("print view render join error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let mut context = base_context(&state);
+    context.insert(
+        "title",
+        &title.unwrap_or_else(|| {
+            canonical
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        }),
+    );
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("content", &expand_details(&rendered.html));
+    context.insert("has_math", &rendered.has_math);
+    context.insert(
+        "document_url",
+        &workspace_file_url(&state.base_path, &workspace_id, &route),
+    );
+    render_template(&state, "print.html", &context)
+}
+
+#[derive(Deserialize)]
+struct CompareQuery {
+    left: String,
+    right: String,
+}
+
+/// One row of the comparison: a block of each document, or of only one
+/// where the other has nothing matching it.
+#[derive(Serialize)]
+struct CompareRow {
+    kind: &'static str,
+    left: Option<String>,
+    right: Option<String>,
+}
+
+/// `GET /_/{workspace_id}/compare?left=a.md&right=b.md` — two documents
+/// rendered side by side, block against block, with the blocks that differ
+/// highlighted (`markon diff a.md b.md`). The blocks are matched like the
+/// Markdiff view matches two revisions, and each row holds both sides, so
+/// the columns scroll together.
+async fn handle_compare_documents(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(query): Query<CompareQuery>,
+) -> Response {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = canonical_workspace_root(&ws);
+    let mut sides = Vec::with_capacity(2);
+    for path in [&query.left, &query.right] {
+        match read_workspace_document(&ws, &root, path).await {
+            Some(side) => sides.push(side),
+            None => {
+                return (StatusCode::NOT_FOUND, format!("Document not found: {path}"))
+                    .into_response()
+            }
+        }
+    }
+    let [left, right] =
+        [&sides[0], &sides[1]].map(|(canonical, route, markdown)| CompareDocument {
+            name: route.clone(),
+            url: workspace_file_url(&state.base_path, &workspace_id, route),
+            canonical: canonical.clone(),
+            markdown: markdown.clone(),
+        });
+    let swap_url = workspace_compare_url(&workspace_id, &sides[1].1, &sides[0].1);
+    render_compare_page(&state, &workspace_id, &root, [left, right], &swap_url).await
+}
+
+/// One side of a comparison page.
+#[derive(Serialize)]
+struct CompareDocument {
+    name: String,
+    /// Where the side's name links to.
+    url: String,
+    /// The file the side's relative links and images resolve against.
+    #[serde(skip)]
+    canonical: PathBuf,
+    #[serde(skip)]
+    markdown: String,
+}
+
+/// Render two documents side by side, block against block, the blocks that
+/// differ highlighted; see [`handle_compare_documents`].
+async fn render_compare_page(
+    state: &AppState,
+    workspace_id: &str,
+    root: &FsPath,
+    sides: [CompareDocument; 2],
+    swap_url: &str,
+) -> Response {
+    let theme = state.theme.clone();
+    let render_hooks = state.render_hooks.clone();
+    let (render_url, render_root) = (
+        workspace_root_url(&state.base_path, workspace_id),
+        root.to_path_buf(),
+    );
+    let documents: Vec<(PathBuf, String)> = sides
+        .iter()
+        .map(|side| (side.canonical.clone(), side.markdown.clone()))
+        .collect();
+    let rendered = tokio::task::spawn_blocking(move || {
+        let mut has_math = false;
+        let mut summaries = Vec::with_capacity(2);
+        for (canonical, markdown) in &documents {
+            let renderer = default_markdown_engine(&theme, &render_hooks).with_asset_context(
+                &render_url,
+                canonical,
+                render_root.clone(),
+            );
+            let render_block = |fragment: &str| {
+                let output = renderer.render_html(fragment);
+                has_math |= output.has_math;
+                output.html
+            };
+            summaries.push(
+                markdown_ast::summarize_document(markdown, render_block)
+                    .unwrap_or_else(|e| markdown_summary_error("compare", e.message)),
+            );
+        }
+        let blocks = diff_markdown_blocks(
+            Some(summaries[0].blocks.as_slice()),
+            Some(summaries[1].blocks.as_slice()),
+        );
+        (blocks, has_math)
+    })
+    .await;
+    let (blocks, has_math) = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!("compare render join error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let rows: Vec<CompareRow> = blocks
+        .into_iter()
+        .map(|block| CompareRow {
+            kind: block.kind,
+            left: block.old.map(|side| side.html),
+            right: block.new.map(|side| side.html),
+        })
+        .collect();
+    let changes = rows.iter().filter(|row| row.kind != "equal").count();
+    let [left, right] = sides;
+    let mut context = base_context(state);
+    context.insert("title", &format!("{} ↔ {}", left.name, right.name));
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("left", &left);
+    context.insert("right", &right);
+    context.insert("rows", &rows);
+    context.insert("changes", &changes);
+    context.insert("has_math", &has_math);
+    context.insert("swap_url", swap_url);
+    render_template(state, "compare.html", &context)
+}
+
+#[derive(Deserialize)]
+struct SnapshotsQuery {
+    format: Option<String>,
+    /// A snapshot whose Markdown to return.
+    id: Option<i64>,
+    /// Versions to compare: snapshot ids, or `current` for the file as it
+    /// is now.
+    from: Option<String>,
+    to: Option<String>,
+}
+
+/// A version listed on a document's snapshot page.
+#[derive(Serialize)]
+struct SnapshotListEntry {
+    #[serde(flatten)]
+    snapshot: crate::snapshots::Snapshot,
+    source_url: String,
+    /// Compares it with the snapshot before it; `None` for the oldest.
+    previous_url: Option<String>,
+    /// Compares it with the current file; `None` once the file is gone.
+    current_url: Option<String>,
+}
+
+/// `GET /_/{workspace_id}/snapshots/{path}` — the versions of a document
+/// recorded with `--snapshots`, newest first, as a page or, with
+/// `?format=json`, as JSON. `?id=N` returns a snapshot's Markdown and
+/// `?from=N&to=M` compares two versions side by side like
+/// [`handle_compare_documents`]; either may be `current`, which `to`
+/// defaults to. A deleted document's snapshots stay reachable.
+async fn handle_document_snapshots(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
+    Query(query): Query<SnapshotsQuery>,
+) -> Response {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let Some(db) = ws.annotation_db.clone().filter(|_| ws.snapshots) else {
+        return (StatusCode::NOT_FOUND, "Snapshots are not enabled").into_response();
+    };
+    let root = canonical_workspace_root(&ws);
+    let (canonical, route, current) = match read_workspace_document(&ws, &root, &path).await {
+        Some((canonical, route, markdown)) => (canonical, route, Some(markdown)),
+        None => match crate::workspace_fs::WorkspaceRelPath::parse(&path) {
+            Ok(rel)
+                if ws.fs.policy().is_markdown(rel.as_path())
+                    && !root.join(rel.as_path()).exists() =>
+            {
+                (root.join(rel.as_path()), rel.as_route(), None)
+            }
+            _ => {
+                return (StatusCode::NOT_FOUND, format!("Document not found: {path}"))
+                    .into_response()
+            }
+        },
+    };
+    let file_path = canonical.to_string_lossy().into_owned();
+    let url = document_snapshots_url(&state.base_path, &workspace_id, &route);
+    let source_url = |id: i64| format!("{url}?id={id}");
+    let compare_url = |from: &str, to: &str| format!("{url}?from={from}&to={to}");
+
+    if let Some(id) = query.id {
+        let found = db
+            .read(move |conn| crate::snapshots::content(conn, &file_path, id))
+            .await;
+        return match found {
+            Ok(Ok(Some(markdown))) => (
+                [(header::CONTENT_TYPE, "text/markdown; charset=utf-8")],
+                markdown,
+            )
+                .into_response(),
+            Ok(Ok(None)) => (StatusCode::NOT_FOUND, "Snapshot not found").into_response(),
+            Ok(Err(e)) => {
+                tracing::warn!("reading a snapshot failed: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+            Err(e) => {
+                tracing::error!("snapshot task join error: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        };
+    }
+
+    if let Some(from) = query.from {
+        let to = query.to.unwrap_or_else(|| "current".to_string());
+        let mut sides = Vec::with_capacity(2);
+        for version in [&from, &to] {
+            let markdown = if version == "current" {
+                current.clone()
+            } else if let Ok(id) = version.parse::<i64>() {
+                let file_path = file_path.clone();
+                db.read(move |conn| crate::snapshots::content(conn, &file_path, id))
+                    .await
+                    .ok()
+                    .and_then(Result::ok)
+                    .flatten()
+            } else {
+                None
+            };
+            let Some(markdown) = markdown else {
+                return (StatusCode::NOT_FOUND, format!("No such version: {version}"))
+                    .into_response();
+            };
+            sides.push(CompareDocument {
+                name: if version == "current" {
+                    route.clone()
+                } else {
+                    format!("{route} #{version}")
+                },
+                url: match version.parse::<i64>() {
+                    Ok(id) => source_url(id),
+                    Err(_) => workspace_file_url(&state.base_path, &workspace_id, &route),
+                },
+                canonical: canonical.clone(),
+                markdown,
+            });
+        }
+        let (Some(right), Some(left)) = (sides.pop(), sides.pop()) else {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        };
+        let swap_url = compare_url(&to, &from);
+        return render_compare_page(&state, &workspace_id, &root, [left, right], &swap_url).await;
+    }
+
+    let listed = db
+        .read(move |conn| crate::snapshots::list(conn, &file_path))
+        .await;
+    let snapshots = match listed {
+        Ok(Ok(snapshots)) => snapshots,
+        Ok(Err(e)) => {
+            tracing::warn!("listing snapshots failed: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        Err(e) => {
+            tracing::error!("snapshot task join error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    if current.is_none() && snapshots.is_empty() {
+        return (StatusCode::NOT_FOUND, format!("Document not found: {path}")).into_response();
+    }
+    let previous_ids: Vec<Option<i64>> = snapshots
+        .iter()
+        .skip(1)
+        .map(|snapshot| Some(snapshot.id))
+        .chain([None])
+        .collect();
+    let entries: Vec<SnapshotListEntry> = snapshots
+        .into_iter()
+        .zip(previous_ids)
+        .map(|(snapshot, previous)| SnapshotListEntry {
+            source_url: source_url(snapshot.id),
+            previous_url: previous
+                .map(|previous| compare_url(&previous.to_string(), &snapshot.id.to_string())),
+            current_url: current
+                .is_some()
+                .then(|| compare_url(&snapshot.id.to_string(), "current")),
+            snapshot,
+        })
+        .collect();
+    if query.format.as_deref() == Some("json") {
+        return Json(serde_json::json!({
+            "path": route,
+            "exists": current.is_some(),
+            "snapshots": entries,
+        }))
+        .into_response();
+    }
+    let mut context = base_context(&state);
+    context.insert("title", &route);
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("path", &route);
+    context.insert(
+        "document_url",
+        &current
+            .is_some()
+            .then(|| workspace_file_url(&state.base_path, &workspace_id, &route)),
+    );
+    context.insert("workspace_url", &workspace_url_path(&workspace_id, None));
+    context.insert("snapshots", &entries);
+    render_template(&state, "snapshots.html", &context)
+}
+
+/// Open every `<details>` in `html`, so a printout shows what they hold.
+fn expand_details(html: &str) -> String {
+    const TAG: &str = "<details";
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(at) = rest.find(TAG) {
+        let after = &rest[at + TAG.len()..];
+        let attributes = &after[..after.find('>').unwrap_or(after.len())];
+        out.push_str(&rest[..at + TAG.len()]);
+        let is_tag = after.starts_with(|c: char| c == '>' || c.is_ascii_whitespace());
+        let is_open = attributes
+            .split_ascii_whitespace()
+            .any(|attribute| attribute == "open" || attribute.starts_with("open="));
+        if is_tag && !is_open {
+            out.push_str(" open");
+        }
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PresentQuery {
+    speaker: bool,
+}
+
+/// One rendered slide of [`handle_presentation`].
+#[derive(Serialize)]
+struct SlideView {
+    html: String,
+    notes: String,
+}
+
+/// `GET /_/{workspace_id}/present/{*path}` — a Markdown document as slides
+/// (see [`crate::present`]), with a QR code of this URL so the audience can
+/// follow along. `?speaker=true` is the presenter's view: notes, the next
+/// slide and a timer, kept on the same slide as the audience view.
+async fn handle_presentation(
+    State(state): State<AppState>,
+    AxumPath((workspace_id, path)): AxumPath<(String, String)>,
+    Query(query): Query<PresentQuery>,
+) -> Response {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let root = canonical_workspace_root(&ws);
+    let Some((canonical, route, markdown)) = read_workspace_document(&ws, &root, &path).await
+    else {
+        return (StatusCode::NOT_FOUND, format!("Document not found: {path}")).into_response();
+    };
+
+    let renderer = default_markdown_engine(&state.theme, &state.render_hooks).with_asset_context(
+        workspace_root_url(&state.base_path, &workspace_id),
+        &canonical,
+        root.clone(),
+    );
+    let rendered = tokio::task::spawn_blocking(move || {
+        let slides = crate::present::split(&markdown);
+        let mut has_math = false;
+        let slides = slides
+            .iter()
+            .map(|slide| {
+                let body = MarkdownEngine::render(&renderer, &slide.markdown);
+                has_math |= body.has_math;
+                let notes = if slide.notes.is_empty() {
+                    String::new()
+                } else {
+                    MarkdownEngine::render(&renderer, &slide.notes).html
+                };
+                SlideView {
+                    html: body.html,
+                    notes,
+                }
+            })
+            .collect::<Vec<_>>();
+        (crate::markdown::document_title(&markdown), slides, has_math)
+    })
+    .await;
+    let (title, slides, has_math) = match rendered {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!("presentation render join error: {e}");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let present_url = document_present_url(&state.base_path, &workspace_id, &route);
+    let mut context = base_context(&state);
+    context.insert(
+        "title",
+        &title.unwrap_or_else(|| {
+            canonical
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        }),
+    );
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("slides", &slides);
+    context.insert("has_math", &has_math);
+    context.insert("speaker", &query.speaker);
+    context.insert(
+        "document_url",
+        &workspace_file_url(&state.base_path, &workspace_id, &route),
+    );
+    context.insert("present_url", &present_url);
+    if let Some(base) = state.share_base.get() {
+        context.insert(
+            "share_url",
+            &with_share_token(
+                &build_workspace_url(base, &present_url),
+                state.share_token.as_deref().map(String::as_str),
+            ),
+        );
+        context.insert(
+            "qr_url",
+            &format!(
+                "{}/_/qr.svg?path={}",
+                state.base_path,
+                urlencoding::encode(&present_url)
+            ),
+        );
+    }
+    render_template(&state, "present.html", &context)
+}
+
+fn render_git_branches_page(
+    state: &AppState,
+    workspace_id: &str,
+    branches: &[git::GitBranchDetail],
+) -> Response {
+    let mut context = base_context(state);
+    context.insert("title", "markon git branches");
+    context.insert("workspace_id", workspace_id);
+    context.insert(
+        "files_url",
+        &workspace_root_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "history_url",
+        &workspace_git_history_url(&state.base_path, workspace_id),
+    );
+    context.insert("page_title", "Branches");
+    context.insert("page_title_key", "web.ws.git.branches");
+    context.insert("empty_key", "web.ws.git.no_branches");
+    context.insert("mode", "branches");
+
+    // Row shape the template renders directly: `has_counts` flags whether the
+    // ahead/behind comparison resolved (so `Some(0)` still shows `0｜0` while an
+    // unresolved `None` shows a placeholder), and the counts are flattened to
+    // plain numbers to avoid null/`Option` ambiguity in the template.
+    #[derive(Serialize)]
+    struct BranchRow {
+        name: String,
+        updated: String,
+        is_default: bool,
+        has_counts: bool,
+        behind: usize,
+        ahead: usize,
+    }
+    let to_row = |b: &git::GitBranchDetail| BranchRow {
+        name: b.name.clone(),
+        updated: b.updated.clone(),
+        is_default: b.is_default,
+        has_counts: b.behind.is_some() && b.ahead.is_some(),
+        behind: b.behind.unwrap_or(0),
+        ahead: b.ahead.unwrap_or(0),
+    };
+
+    // Group GitHub-style: the default branch on its own, the rest name-sorted.
+    let default_branch = branches.iter().find(|b| b.is_default).map(to_row);
+    let mut other_branches: Vec<BranchRow> = branches
+        .iter()
+        .filter(|b| !b.is_default)
+        .map(to_row)
+        .collect();
+    other_branches.sort_by(|a, b| a.name.cmp(&b.name));
+    let default_branch_name = default_branch
+        .as_ref()
+        .map(|b| b.name.clone())
+        .unwrap_or_default();
+
+    context.insert("default_branch", &default_branch);
+    context.insert("other_branches", &other_branches);
+    context.insert("default_branch_name", &default_branch_name);
+    context.insert("branch_total", &branches.len());
+    context.insert("has_items", &!branches.is_empty());
+    render_template(state, "git-refs.html", &context)
+}
+
+fn render_git_tags_page(state: &AppState, workspace_id: &str, tags: &[git::GitTag]) -> Response {
+    let mut context = base_context(state);
+    context.insert("title", "markon git tags");
+    context.insert("workspace_id", workspace_id);
+    context.insert(
+        "files_url",
+        &workspace_root_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "history_url",
+        &workspace_git_history_url(&state.base_path, workspace_id),
+    );
+    context.insert("page_title", "Tags");
+    context.insert("page_title_key", "web.ws.git.tags");
+    context.insert("empty_key", "web.ws.git.no_tags");
+    context.insert("mode", "tags");
+    context.insert("branches", &Vec::<git::GitBranch>::new());
+    context.insert("tags", tags);
+    context.insert("has_items", &!tags.is_empty());
+    render_template(state, "git-refs.html", &context)
+}
+
+/// One markdown file whose rendered diff must be (re)built — i.e. it missed the
+/// file cache. Carries the resolved per-side content identity so a batched blob
+/// read + parallel render can finish the job without further git subprocesses.
+struct MarkdownBuildItem<'a> {
+    entry: &'a git::MarkdownDiffEntry,
+    old_id: Option<String>,
+    new_id: Option<String>,
+    /// Worktree new-side content, already read in pass 1 (worktree has no blob).
+    new_worktree: Option<String>,
+    read_diagnostics: Vec<MarkdownDiffDiagnostic>,
+    file_key: Option<MarkdownDiffFileCacheKey>,
+}
+
+fn markdown_compare_diff_data(
+    state: &AppState,
+    workspace_id: &str,
+    workspace_fs: &WorkspaceFs,
+    base: &str,
+    compare: &str,
+    file_filter: Option<&str>,
+) -> git::Result<MarkdownDiffData> {
+    let root = workspace_fs
+        .directory_root()
+        .ok_or_else(|| git::GitError::Io("directory workspace required".to_string()))?;
+    let engine = markdown_ast::engine_info();
+    // Cheap enumeration: `git diff --raw` (no patch) + untracked md. Gives each
+    // changed file's status and per-side blob oids without reading content.
+    let listing = git::markdown_diff_listing(workspace_fs, base, compare)?;
+    if !engine.enabled {
+        return Ok(MarkdownDiffData {
+            title: "Markdown visual diff".to_string(),
+            subtitle: engine.message.map(str::to_string),
+            engine,
+            files: Vec::new(),
+        });
+    }
+
+    let filter = file_filter.map(str::trim).filter(|f| !f.is_empty());
+    let entries: Vec<&git::MarkdownDiffEntry> = listing
+        .entries
+        .iter()
+        .filter(|e| filter.is_none_or(|f| e.path == f || e.old_path.as_deref() == Some(f)))
+        .collect();
+
+    enum Slot<'a> {
+        Cached(Arc<MarkdownDiffFile>),
+        Build(Box<MarkdownBuildItem<'a>>),
+    }
+
+    // ---- Pass 1: resolve content identity; serve file-cache hits without I/O ----
+    // The old side is identified purely by its blob oid (from `--raw`), so a warm
+    // reload hits the cache without reading or re-rendering it. The worktree new
+    // side has no oid yet, so it is read here (cheap) to derive its identity.
+    let mut slots: Vec<Slot> = Vec::with_capacity(entries.len());
+    let mut needed_blobs: Vec<String> = Vec::new();
+
+    for entry in &entries {
+        let mut read_diagnostics = Vec::new();
+        let old_id = if entry.status == "added" {
+            None
+        } else {
+            entry.old_blob.clone()
+        };
+
+        let mut new_worktree = None;
+        let new_id = if entry.status == "deleted" {
+            None
+        } else if let Some(blob) = &entry.new_blob {
+            Some(blob.clone())
+        } else {
+            match workspace_fs.read_content_to_string(&entry.path) {
+                Ok(content) => {
+                    let id = format!("h:{}", markdown_content_hash(&content));
+                    new_worktree = Some(content);
+                    Some(id)
+                }
+                Err(e) => {
+                    read_diagnostics.push(markdown_diff_diagnostic(
+                        "new",
+                        "read_worktree_failed",
+                        "error",
+                        format!("Failed to read {}: {e}", entry.path),
+                    ));
+                    None
+                }
+            }
+        };
+
+        let file_key = read_diagnostics
+            .is_empty()
+            .then(|| MarkdownDiffFileCacheKey {
+                version: MARKDOWN_DIFF_CACHE_VERSION,
+                theme: state.theme.as_str().to_string(),
+                workspace_id: workspace_id.to_string(),
+                path: entry.path.clone(),
+                old_path: entry.old_path.clone(),
+                status: entry.status.clone(),
+                old_id: old_id.clone(),
+                new_id: new_id.clone(),
+            });
+
+        if let Some(key) = &file_key {
+            if let Some(cached) = state
+                .markdown_diff_cache
+                .lock()
+                .expect("markdown diff cache poisoned")
+                .get_file(key)
+            {
+                slots.push(Slot::Cached(cached));
+                continue;
+            }
+        }
+
+        // Cache miss: schedule the blobs this build will need.
+        if let Some(oid) = &old_id {
+            needed_blobs.push(oid.clone());
+        }
+        if entry.new_blob.is_some() {
+            if let Some(oid) = &new_id {
+                needed_blobs.push(oid.clone());
+            }
+        }
+        slots.push(Slot::Build(Box::new(MarkdownBuildItem {
+            entry,
+            old_id,
+            new_id,
+            new_worktree,
+            read_diagnostics,
+            file_key,
+        })));
+    }
+
+    // ---- Pass 2: one batched `git cat-file` for every missing blob ----
+    needed_blobs.sort();
+    needed_blobs.dedup();
+    let blobs = git::read_blobs(root, &needed_blobs)?;
+
+    // ---- Pass 3: render the misses in parallel (render_html, not full render) ----
+    let builds: Vec<(usize, &MarkdownBuildItem)> = slots
+        .iter()
+        .enumerate()
+        .filter_map(|(i, slot)| match slot {
+            Slot::Build(item) => Some((i, item.as_ref())),
+            Slot::Cached(_) => None,
+        })
+        .collect();
+    let mut built: HashMap<usize, MarkdownDiffFile> = builds
+        .into_par_iter()
+        .map(|(i, item)| {
+            (
+                i,
+                build_markdown_diff_file(state, workspace_id, item, &blobs, workspace_fs),
+            )
+        })
+        .collect();
+
+    // ---- Pass 4: assemble in original order; promote freshly built into cache ----
+    let mut files = Vec::with_capacity(slots.len());
+    for (i, slot) in slots.iter().enumerate() {
+        match slot {
+            Slot::Cached(arc) => files.push((**arc).clone()),
+            Slot::Build(item) => {
+                let file = built.remove(&i).expect("built file present");
+                if let Some(key) = item.file_key.clone() {
+                    let cached = state
+                        .markdown_diff_cache
+                        .lock()
+                        .expect("markdown diff cache poisoned")
+                        .insert_file(key, file);
+                    files.push((*cached).clone());
+                } else {
+                    files.push(file);
+                }
+            }
+        }
+    }
+
+    Ok(MarkdownDiffData {
+        title: listing.title,
+        subtitle: Some(format!(
+            "{} Markdown files changed · {}",
+            files.len(),
+            listing.range
+        )),
+        engine,
+        files,
+    })
+}
+
+/// Async wrapper for [`markdown_compare_diff_data`]. The diff build shells out to
+/// git, reads worktree files, and renders in parallel via rayon — all blocking
+/// work that would otherwise stall a tokio worker. Move it to the blocking pool;
+/// a join failure surfaces as a generic IO error the caller already maps to 500.
+async fn markdown_compare_diff_data_async(
+    state: &AppState,
+    workspace_id: &str,
+    workspace_fs: Arc<WorkspaceFs>,
+    base: &str,
+    compare: &str,
+    file_filter: Option<&str>,
+) -> git::Result<MarkdownDiffData> {
+    let state = state.clone();
+    let workspace_id = workspace_id.to_string();
+    let base = base.to_string();
+    let compare = compare.to_string();
+    let file_filter = file_filter.map(str::to_string);
+    tokio::task::spawn_blocking(move || {
+        markdown_compare_diff_data(
+            &state,
+            &workspace_id,
+            &workspace_fs,
+            &base,
+            &compare,
+            file_filter.as_deref(),
+        )
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("markdown_compare_diff_data join error: {e}");
+        Err(git::GitError::Io("diff render task failed".to_string()))
+    })
+}
+
+/// Build a single file's rendered diff from already-resolved content sources.
+/// Pure CPU + cache lookups — safe to run in parallel across files.
+fn build_markdown_diff_file(
+    state: &AppState,
+    workspace_id: &str,
+    item: &MarkdownBuildItem,
+    blobs: &HashMap<String, Vec<u8>>,
+    workspace_fs: &WorkspaceFs,
+) -> MarkdownDiffFile {
+    let entry = item.entry;
+    let root = workspace_fs
+        .directory_root()
+        .expect("markdown diff requires a directory workspace");
+    // Canonicalize the new-side path the same way `render_markdown_file` builds
+    // its `file_path` key, so a highlight made in the diff binds to the same
+    // annotation row as one made in the normal file view. `entry.path` is
+    // workspace-relative (the listing uses `git diff --relative`), so joining it
+    // onto the workspace root reaches the exact same on-disk file — and the same
+    // canonical key — as the normal view, including subdirectory-of-repo
+    // workspaces. Deleted files have no on-disk new side, so canonicalize falls
+    // back to the lexical join (such files are not annotatable anyway).
+    let abs_path = {
+        let joined = root.join(&entry.path);
+        workspace_fs
+            .resolve_content(&entry.path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or_else(|_| joined.to_string_lossy().into_owned())
+    };
+    let mut diagnostics = item.read_diagnostics.clone();
+
+    let blob_text =
+        |id: &str, side: &'static str, diags: &mut Vec<MarkdownDiffDiagnostic>| match blobs.get(id)
+        {
+            Some(bytes) => Some(String::from_utf8_lossy(bytes).into_owned()),
+            None => {
+                diags.push(markdown_diff_diagnostic(
+                    side,
+                    "read_blob_failed",
+                    "error",
+                    format!("Missing blob {id}"),
+                ));
+                None
+            }
+        };
+
+    let old_content = item
+        .old_id
+        .as_deref()
+        .and_then(|id| blob_text(id, "old", &mut diagnostics));
+    let new_content = if entry.status == "deleted" {
+        None
+    } else if let Some(content) = &item.new_worktree {
+        Some(content.clone())
+    } else {
+        item.new_id
+            .as_deref()
+            .and_then(|id| blob_text(id, "new", &mut diagnostics))
+    };
+
+    let old_path = entry.old_path.as_deref().unwrap_or(&entry.path);
+    let old_file_path = root.join(old_path);
+    let new_file_path = root.join(&entry.path);
+    let old_renderer = default_markdown_engine(&state.theme, &state.render_hooks)
+        .with_asset_context(
+            workspace_root_url(&state.base_path, workspace_id),
+            &old_file_path,
+            root,
+        );
+    let new_renderer = default_markdown_engine(&state.theme, &state.render_hooks)
+        .with_asset_context(
+            workspace_root_url(&state.base_path, workspace_id),
+            &new_file_path,
+            root,
+        );
+
+    let old = summarize_side_cached(
+        state,
+        "old",
+        old_content.as_deref(),
+        item.old_id.as_deref(),
+        workspace_id,
+        &old_file_path,
+        &old_renderer,
+    );
+    diagnostics.extend(markdown_side_diagnostics("old", old.as_ref()));
+    let new = summarize_side_cached(
+        state,
+        "new",
+        new_content.as_deref(),
+        item.new_id.as_deref(),
+        workspace_id,
+        &new_file_path,
+        &new_renderer,
+    );
+    diagnostics.extend(markdown_side_diagnostics("new", new.as_ref()));
+
+    let blocks = diff_markdown_blocks(
+        old.as_ref().map(|s| s.blocks.as_slice()),
+        new.as_ref().map(|s| s.blocks.as_slice()),
+    );
+
+    MarkdownDiffFile {
+        path: entry.path.clone(),
+        abs_path,
+        old_path: entry.old_path.clone(),
+        status: entry.status.clone(),
+        old: old.as_ref().map(MarkdownDocOutline::from_summary),
+        new: new.as_ref().map(MarkdownDocOutline::from_summary),
+        old_source: old_content,
+        new_source: new_content,
+        additions: entry.additions,
+        deletions: entry.deletions,
+        blocks,
+        diagnostics,
+    }
+}
+
+/// Summarize one side, keyed in the document cache by a stable content id (blob
+/// oid, or `h:<sha256>` for worktree content). The cache includes workspace and
+/// file path because rendered local asset URLs depend on both. Blocks use
+/// `render_html` only; the diff does not need the diagnostic pass of full
+/// `render()`.
+fn summarize_side_cached(
+    state: &AppState,
+    side: &'static str,
+    content: Option<&str>,
+    content_id: Option<&str>,
+    workspace_id: &str,
+    file_path: &FsPath,
+    renderer: &MarkdownRenderer,
+) -> Option<markdown_ast::MarkdownDocumentSummary> {
+    let content = content?;
+    let id_owned;
+    let id = match content_id {
+        Some(id) => id,
+        None => {
+            id_owned = format!("h:{}", markdown_content_hash(content));
+            id_owned.as_str()
+        }
+    };
+    let key = markdown_document_cache_key(state, id, workspace_id, file_path);
+    if let Some(summary) = state
+        .markdown_diff_cache
+        .lock()
+        .expect("markdown diff cache poisoned")
+        .get_document(&key)
+    {
+        return Some((*summary).clone());
+    }
+
+    let mut render_block = |fragment: &str| renderer.render_html(fragment).html;
+    let summary = match markdown_ast::summarize_document(content, &mut render_block) {
+        Ok(summary) => summary,
+        Err(e) => markdown_summary_error(side, e.message),
+    };
+    let cached = state
+        .markdown_diff_cache
+        .lock()
+        .expect("markdown diff cache poisoned")
+        .insert_document(key, summary);
+    Some((*cached).clone())
+}
+
+fn is_markdown_diff_file(markdown: &MarkdownExtensions, file: &git::GitDiffFile) -> bool {
+    is_markdown_route_path(markdown, &file.path)
+        || file
+            .old_path
+            .as_deref()
+            .is_some_and(|path| is_markdown_route_path(markdown, path))
+}
+
+fn is_markdown_route_path(markdown: &MarkdownExtensions, path: &str) -> bool {
+    markdown.matches(FsPath::new(path))
+}
+
+fn markdown_content_hash(content: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(content.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn markdown_document_cache_key(
+    state: &AppState,
+    content_hash: &str,
+    workspace_id: &str,
+    file_path: &FsPath,
+) -> MarkdownDocumentCacheKey {
+    MarkdownDocumentCacheKey {
+        version: MARKDOWN_DIFF_CACHE_VERSION,
+        theme: state.theme.as_str().to_string(),
+        workspace_id: workspace_id.to_string(),
+        file_path: file_path.to_string_lossy().into_owned(),
+        content_hash: content_hash.to_string(),
+    }
+}
+
+fn markdown_summary_error(
+    side: &'static str,
+    message: String,
+) -> markdown_ast::MarkdownDocumentSummary {
+    markdown_ast::MarkdownDocumentSummary {
+        block_count: 0,
+        diagnostics: vec![markdown_ast::MarkdownAstDiagnostic {
+            code: format!("{side}_parse_failed"),
+            severity: "error".to_string(),
+            message,
+            start_line: None,
+            end_line: None,
+        }],
+        blocks: Vec::new(),
+    }
+}
+
+fn markdown_side_diagnostics(
+    side: &'static str,
+    summary: Option<&markdown_ast::MarkdownDocumentSummary>,
+) -> Vec<MarkdownDiffDiagnostic> {
+    summary
+        .into_iter()
+        .flat_map(|summary| {
+            summary
+                .diagnostics
+                .iter()
+                .map(move |diagnostic| MarkdownDiffDiagnostic {
+                    side,
+                    code: diagnostic.code.clone(),
+                    severity: diagnostic.severity.clone(),
+                    message: diagnostic.message.clone(),
+                    start_line: diagnostic.start_line,
+                    end_line: diagnostic.end_line,
+                })
+        })
+        .collect()
+}
+
+fn markdown_diff_diagnostic(
+    side: &'static str,
+    code: &'static str,
+    severity: &'static str,
+    message: String,
+) -> MarkdownDiffDiagnostic {
+    MarkdownDiffDiagnostic {
+        side,
+        code: code.to_string(),
+        severity: severity.to_string(),
+        message,
+        start_line: None,
+        end_line: None,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MarkdownBlockOp {
+    Equal(
+        markdown_ast::MarkdownBlockSummary,
+        markdown_ast::MarkdownBlockSummary,
+    ),
+    Delete(markdown_ast::MarkdownBlockSummary),
+    Add(markdown_ast::MarkdownBlockSummary),
+}
+
+fn diff_markdown_blocks(
+    old: Option<&[markdown_ast::MarkdownBlockSummary]>,
+    new: Option<&[markdown_ast::MarkdownBlockSummary]>,
+) -> Vec<MarkdownDiffBlock> {
+    let old = old.unwrap_or(&[]);
+    let new = new.unwrap_or(&[]);
+    let ops = if old.len().saturating_mul(new.len()) <= 40_000 {
+        diff_markdown_blocks_lcs(old, new)
+    } else {
+        diff_markdown_blocks_by_index(old, new)
+    };
+    coalesce_markdown_block_ops(ops)
+}
+
+fn diff_markdown_blocks_lcs(
+    old: &[markdown_ast::MarkdownBlockSummary],
+    new: &[markdown_ast::MarkdownBlockSummary],
+) -> Vec<MarkdownBlockOp> {
+    let n = old.len();
+    let m = new.len();
+    let mut dp = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            dp[i][j] = if old[i].digest == new[j].digest {
+                dp[i + 1][j + 1] + 1
+            } else {
+                dp[i + 1][j].max(dp[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i].digest == new[j].digest {
+            ops.push(MarkdownBlockOp::Equal(old[i].clone(), new[j].clone()));
+            i += 1;
+            j += 1;
+        } else if dp[i + 1][j] >= dp[i][j + 1] {
+            ops.push(MarkdownBlockOp::Delete(old[i].clone()));
+            i += 1;
+        } else {
+            ops.push(MarkdownBlockOp::Add(new[j].clone()));
+            j += 1;
+        }
+    }
+    while i < n {
+        ops.push(MarkdownBlockOp::Delete(old[i].clone()));
+        i += 1;
+    }
+    while j < m {
+        ops.push(MarkdownBlockOp::Add(new[j].clone()));
+        j += 1;
+    }
+    ops
+}
+
+fn diff_markdown_blocks_by_index(
+    old: &[markdown_ast::MarkdownBlockSummary],
+    new: &[markdown_ast::MarkdownBlockSummary],
+) -> Vec<MarkdownBlockOp> {
+    let mut ops = Vec::new();
+    let max_len = old.len().max(new.len());
+    for index in 0..max_len {
+        match (old.get(index), new.get(index)) {
+            (Some(old), Some(new)) if old.digest == new.digest => {
+                ops.push(MarkdownBlockOp::Equal(old.clone(), new.clone()));
+            }
+            (Some(old), Some(new)) => {
+                ops.push(MarkdownBlockOp::Delete(old.clone()));
+                ops.push(MarkdownBlockOp::Add(new.clone()));
+            }
+            (Some(old), None) => ops.push(MarkdownBlockOp::Delete(old.clone())),
+            (None, Some(new)) => ops.push(MarkdownBlockOp::Add(new.clone())),
+            (None, None) => {}
+        }
+    }
+    ops
+}
+
+fn coalesce_markdown_block_ops(ops: Vec<MarkdownBlockOp>) -> Vec<MarkdownDiffBlock> {
+    let mut out = Vec::new();
+    let mut index = 0;
+    while index < ops.len() {
+        match &ops[index] {
+            MarkdownBlockOp::Equal(old, new) => {
+                out.push(MarkdownDiffBlock {
+                    kind: "equal",
+                    old: Some(old.clone()),
+                    new: Some(new.clone()),
+                });
+                index += 1;
+            }
+            MarkdownBlockOp::Delete(_) => {
+                let start = index;
+                while index < ops.len() && matches!(ops[index], MarkdownBlockOp::Delete(_)) {
+                    index += 1;
+                }
+                let delete_end = index;
+                while index < ops.len() && matches!(ops[index], MarkdownBlockOp::Add(_)) {
+                    index += 1;
+                }
+                let adds = ops[delete_end..index]
+                    .iter()
+                    .filter_map(|op| match op {
+                        MarkdownBlockOp::Add(block) => Some(block.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let deletes = ops[start..delete_end]
+                    .iter()
+                    .filter_map(|op| match op {
+                        MarkdownBlockOp::Delete(block) => Some(block.clone()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                let pair_count = deletes.len().min(adds.len());
+                for pair_index in 0..pair_count {
+                    out.push(MarkdownDiffBlock {
+                        kind: "modified",
+                        old: Some(deletes[pair_index].clone()),
+                        new: Some(adds[pair_index].clone()),
+                    });
+                }
+                for block in deletes.into_iter().skip(pair_count) {
+                    out.push(MarkdownDiffBlock {
+                        kind: "deleted",
+                        old: Some(block),
+                        new: None,
+                    });
+                }
+                for block in adds.into_iter().skip(pair_count) {
+                    out.push(MarkdownDiffBlock {
+                        kind: "added",
+                        old: None,
+                        new: Some(block),
+                    });
+                }
+            }
+            MarkdownBlockOp::Add(new) => {
+                out.push(MarkdownDiffBlock {
+                    kind: "added",
+                    old: None,
+                    new: Some(new.clone()),
+                });
+                index += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Text/code files above this size skip the (relatively expensive) syntax-
+/// highlight preview and fall back to raw bytes (the browser shows plain text).
+const MAX_TEXT_PREVIEW_BYTES: u64 = 2 * 1024 * 1024;
+
+/// Request-body cap for `POST /api/preview`. The live editor preview never
+/// needs the global 2 MB default; a smaller ceiling bounds the per-request
+/// render cost (defense in depth behind the same-origin guard).
+const PREVIEW_BODY_LIMIT: usize = 512 * 1024;
+
+/// If `path` is a small UTF-8 text/code file, return its contents plus a
+/// language hint (extension, or file name for extension-less files) for the
+/// highlighted preview. Returns `None` for images, media, binaries and
+/// oversized files — the caller serves those as raw bytes.
+fn read_text_for_preview(path: &FsPath) -> Option<(String, String)> {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    if matches!(mime.type_().as_str(), "image" | "video" | "audio")
+        || mime.essence_str() == "application/pdf"
+    {
+        return None;
+    }
+    if fs::metadata(path).map(|m| m.len()).unwrap_or(0) > MAX_TEXT_PREVIEW_BYTES {
+        return None;
+    }
+    let bytes = fs::read(path).ok()?;
+    // A NUL byte or invalid UTF-8 means it isn't text we can render.
+    if bytes.contains(&0) {
+        return None;
+    }
+    let content = String::from_utf8(bytes).ok()?;
+    let token = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .filter(|s| !s.is_empty())
+        .or_else(|| path.file_name().and_then(|n| n.to_str()))
+        .unwrap_or_default()
+        .to_string();
+    Some((content, token))
+}
+
+/// Read-only, syntax-highlighted preview page for a non-markdown text/code file.
+/// No collaboration chrome — just the file contents and line numbers.
+fn render_file_view(
+    path: &FsPath,
+    content: String,
+    token: String,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+) -> Response {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Back link: workspace root with this exact file highlighted; the directory
+    // tree expands the parent folders from the hash path.
+    let back_link = workspace_file_back_link(&state.base_path, workspace_id, path, root);
+    let rel_display = workspace_relative_path(path, root)
+        .map(|rel| rel.to_string_lossy().replace('\\', "/"))
+        .unwrap_or_else(|| file_name.clone());
+
+    // Strip one trailing newline so the highlighted <pre> and the line-number
+    // gutter agree on the visual line count.
+    let normalized = content.strip_suffix('\n').unwrap_or(content.as_str());
+    let code_html = crate::markdown::highlight_source_file(&token, normalized);
+    let line_count = normalized.split('\n').count().max(1);
+    // Each gutter number is a `#L<n>` anchor so a line can be linked directly,
+    // the way repo browsers do.
+    let gutter = (1..=line_count)
+        .map(|n| format!("<a id=\"L{n}\" href=\"#L{n}\">{n}</a>"))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let mut context = base_context(state);
+    context.insert("title", &format!("markon - {file_name}"));
+    context.insert("workspace_id", workspace_id);
+    insert_workspace_header_context(&mut context, ws, root);
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("file_name", &file_name);
+    context.insert("rel_display", &rel_display);
+    context.insert("back_link", &back_link);
+    context.insert("show_back_link", &ws.single_file.is_none());
+    context.insert("code_html", &code_html);
+    context.insert("gutter", &gutter);
+    context.insert("line_count", &line_count);
+
+    render_template(state, "file-view.html", &context)
+}
+
+/// Async wrapper for [`render_markdown_file`]: the file read plus the markdown
+/// render (syntect highlighting + server-side diagrams) run on the blocking pool
+/// so a large document can't stall a tokio worker.
+async fn render_markdown_file_async(
+    file_path: String,
+    workspace_id: String,
+    ws: Arc<WorkspaceEntry>,
+    root: PathBuf,
+    state: AppState,
+    is_local: bool,
+    page: usize,
+) -> Response {
+    tokio::task::spawn_blocking(move || {
+        render_markdown_file(
+            &file_path,
+            &workspace_id,
+            &ws,
+            &root,
+            &state,
+            is_local,
+            page,
+        )
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("render_markdown_file join error: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "render task failed").into_response()
+    })
+}
+
+/// API reference page for an OpenAPI / Swagger spec, in the document layout.
+/// The spec is shown read-only: no editor, live or chat chrome.
+fn render_openapi_page(
+    path: &FsPath,
+    api: crate::openapi::ApiReference,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+) -> Response {
+    let file_path = path.to_string_lossy();
+    let mut context = base_context(state);
+    context.insert("title", &api.title);
+    context.insert("file_path", &file_path);
+    context.insert("workspace_id", workspace_id);
+    context.insert(
+        "preview_token",
+        &workspace_preview_token(&state.save_token, workspace_id),
+    );
+    insert_workspace_header_context(&mut context, ws, root);
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("content", &api.html);
+    context.insert("toc", &api.toc);
+    context.insert("api_reference", &true);
+    context.insert(
+        "back_link",
+        &workspace_file_back_link(&state.base_path, workspace_id, path, root),
+    );
+    context.insert("show_back_link", &ws.single_file.is_none());
+    context.insert("has_mermaid", &false);
+    context.insert("has_math", &false);
+    let flags = ws.flags();
+    context.insert("shared_annotation", &flags.shared_annotation);
+    context.insert("enable_viewed", &flags.enable_viewed);
+    context.insert("enable_search", &flags.enable_search);
+    context.insert("can_manage", &false);
+    context.insert("enable_edit", &false);
+    context.insert("enable_live", &false);
+    context.insert("enable_chat", &false);
+
+    render_template(state, "layout.html", &context)
+}
+
+/// A document in one of the [`crate::pandoc`] formats, converted by pandoc
+/// and rendered in the document layout. It is read-only, since an edit
+/// couldn't be written back in the original format. `None` when `--pandoc`
+/// is off, the file isn't such a format, or the conversion failed (logged),
+/// so the caller falls back to the plain preview or download.
+async fn render_pandoc_or_none(
+    canonical: &FsPath,
+    workspace_id: &str,
+    ws: &Arc<WorkspaceEntry>,
+    root: &FsPath,
+    state: &AppState,
+) -> Option<Response> {
+    let pandoc = state.pandoc.clone()?;
+    let format = crate::pandoc::format_for(canonical)?;
+    let (canonical, workspace_id, ws, root, state) = (
+        canonical.to_path_buf(),
+        workspace_id.to_string(),
+        ws.clone(),
+        root.to_path_buf(),
+        state.clone(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let markdown = pandoc
+            .to_markdown(&canonical, format)
+            .map_err(|e| tracing::warn!(path = %canonical.display(), "{e}"))
+            .ok()?;
+        let file_path = canonical.to_string_lossy();
+        let rendered =
+            render_markdown_cached(&markdown, &file_path, &workspace_id, &ws, &root, &state);
+
+        let mut context = base_context(&state);
+        context.insert(
+            "title",
+            &canonical
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_else(|| file_path.clone()),
+        );
+        context.insert("file_path", &file_path);
+        context.insert("workspace_id", &workspace_id);
+        context.insert(
+            "preview_token",
+            &workspace_preview_token(&state.save_token, &workspace_id),
+        );
+        insert_workspace_header_context(&mut context, &ws, &root);
+        context.insert("version", env!("CARGO_PKG_VERSION"));
+        context.insert("content", &rendered.html);
+        context.insert("toc", &rendered.toc);
+        context.insert("converted_from", format.label);
+        context.insert(
+            "back_link",
+            &workspace_file_back_link(&state.base_path, &workspace_id, &canonical, &root),
+        );
+        context.insert("show_back_link", &ws.single_file.is_none());
+        context.insert("has_mermaid", &rendered.has_mermaid);
+        context.insert("has_math", &rendered.has_math);
+        let flags = ws.flags();
+        context.insert("shared_annotation", &flags.shared_annotation);
+        context.insert("enable_viewed", &flags.enable_viewed);
+        context.insert("enable_search", &flags.enable_search);
+        context.insert("can_manage", &false);
+        context.insert("enable_edit", &false);
+        context.insert("enable_live", &false);
+        context.insert("enable_chat", &false);
+
+        Some(render_template(&state, "layout.html", &context))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("render_pandoc_or_none join error: {e}");
+        Some((StatusCode::INTERNAL_SERVER_ERROR, "render task failed").into_response())
+    })
+}
+
+/// `/{id}/<archive>/<inner>`: a folder of a zip or tar file in the
+/// workspace, or one of its entries (see [`crate::archive`]). `None` when
+/// `archive` isn't an archive file the workspace serves, so the route is
+/// handled as an ordinary path.
+async fn handle_archive_route(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &Arc<WorkspaceEntry>,
+    archive: &str,
+    inner: &str,
+) -> Option<Response> {
+    let (state, workspace_id, ws, archive, inner) = (
+        state.clone(),
+        workspace_id.to_string(),
+        ws.clone(),
+        archive.to_string(),
+        inner.to_string(),
+    );
+    tokio::task::spawn_blocking(move || {
+        let canonical = ws.fs.resolve_served(&archive).ok()?;
+        let kind = crate::archive::kind_for(&canonical)?;
+        let root = canonical_workspace_root(&ws);
+        if !canonical.is_file() || !is_inside_workspace(&canonical, &root) {
+            return None;
+        }
+        let route = workspace_relative_path(&canonical, &root)
+            .map(|rel| path_to_route(&rel))
+            .unwrap_or(archive);
+        let archive = ArchiveRoute {
+            canonical: &canonical,
+            kind,
+            url: workspace_file_url(&state.base_path, &workspace_id, &route),
+        };
+        Some(render_archive_route(
+            &state,
+            &workspace_id,
+            &ws,
+            &root,
+            &archive,
+            &inner,
+        ))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("archive route join error: {e}");
+        Some((StatusCode::INTERNAL_SERVER_ERROR, "archive task failed").into_response())
+    })
+}
+
+/// An archive file being browsed, and the URL it is browsed under.
+struct ArchiveRoute<'a> {
+    canonical: &'a FsPath,
+    kind: crate::archive::Kind,
+    url: String,
+}
+
+impl ArchiveRoute<'_> {
+    /// URL of the folder or file `path` inside the archive.
+    fn entry_url(&self, path: &str) -> String {
+        format!("{}/{}", self.url, encode_route_path(path))
+    }
+}
+
+/// One row of the archive folder listing.
+#[derive(serde::Serialize)]
+struct ArchiveListingEntry<'a> {
+    name: &'a str,
+    url: String,
+    is_dir: bool,
+    is_markdown: bool,
+    size: u64,
+}
+
+fn render_archive_route(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    archive: &ArchiveRoute<'_>,
+    inner: &str,
+) -> Response {
+    let entries = match crate::archive::IndexCache::entries(
+        &state.archive_index,
+        archive.canonical,
+        archive.kind,
+    ) {
+        Ok(entries) => entries,
+        Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+    };
+    let dir = inner.strip_suffix('/').unwrap_or(inner);
+    if dir.is_empty()
+        || entries
+            .iter()
+            .any(|entry| entry.is_dir && entry.path == dir)
+    {
+        // Folders end in `/`, so relative links resolve inside them.
+        if !dir.is_empty() && !inner.ends_with('/') {
+            return Redirect::to(&archive.entry_url(&format!("{dir}/"))).into_response();
+        }
+        return render_archive_listing(state, workspace_id, ws, root, archive, &entries, dir);
+    }
+    if !entries
+        .iter()
+        .any(|entry| !entry.is_dir && entry.path == inner)
+    {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Not in the archive: {inner}"),
+        )
+            .into_response();
+    }
+    let bytes = match crate::archive::read(archive.canonical, archive.kind, inner) {
+        Ok(bytes) => bytes,
+        Err(crate::archive::ArchiveError::TooLarge) => {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "Archive entry too large").into_response()
+        }
+        Err(e) => return (StatusCode::UNPROCESSABLE_ENTITY, e.to_string()).into_response(),
+    };
+    if ws.fs.policy().is_markdown(FsPath::new(inner)) {
+        let markdown = String::from_utf8_lossy(&bytes);
+        return render_archive_document(state, workspace_id, ws, root, archive, inner, &markdown);
+    }
+    let mime = mime_guess::from_path(inner).first_or_octet_stream();
+    (
+        [(header::CONTENT_TYPE, mime.essence_str().to_string())],
+        bytes,
+    )
+        .into_response()
+}
+
+/// The folder `dir` of an archive (`""` for its top level).
+fn render_archive_listing(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    archive: &ArchiveRoute<'_>,
+    entries: &[crate::archive::Entry],
+    dir: &str,
+) -> Response {
+    #[derive(serde::Serialize)]
+    struct Crumb {
+        name: String,
+        url: String,
+    }
+    let archive_name = archive
+        .canonical
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut breadcrumb = vec![Crumb {
+        name: archive_name.clone(),
+        url: format!("{}/", archive.url),
+    }];
+    let mut acc = String::new();
+    for part in dir.split('/').filter(|part| !part.is_empty()) {
+        acc = if acc.is_empty() {
+            part.to_string()
+        } else {
+            format!("{acc}/{part}")
+        };
+        breadcrumb.push(Crumb {
+            name: part.to_string(),
+            url: archive.entry_url(&format!("{acc}/")),
+        });
+    }
+    let listing: Vec<ArchiveListingEntry> = crate::archive::children(entries, dir)
+        .map(|entry| ArchiveListingEntry {
+            name: entry.name(),
+            url: if entry.is_dir {
+                archive.entry_url(&format!("{}/", entry.path))
+            } else {
+                archive.entry_url(&entry.path)
+            },
+            is_dir: entry.is_dir,
+            is_markdown: !entry.is_dir && ws.fs.policy().is_markdown(FsPath::new(&entry.path)),
+            size: entry.size,
+        })
+        .collect();
+
+    let mut context = base_context(state);
+    context.insert(
+        "title",
+        &if dir.is_empty() {
+            archive_name
+        } else {
+            format!("{archive_name}/{dir}")
+        },
+    );
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("breadcrumb", &breadcrumb);
+    context.insert("entries", &listing);
+    context.insert(
+        "workspace_url",
+        &workspace_file_back_link(&state.base_path, workspace_id, archive.canonical, root),
+    );
+    context.insert("download_url", &format!("{}?source=true", archive.url));
+    render_template(state, "archive.html", &context)
+}
+
+/// A Markdown entry of an archive in the document layout. Like a pandoc
+/// conversion it is read-only, and as it has no file of its own, viewed
+/// tracking and shared notes are off.
+fn render_archive_document(
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    archive: &ArchiveRoute<'_>,
+    inner: &str,
+    markdown: &str,
+) -> Response {
+    #[derive(serde::Serialize)]
+    struct ArchiveSource {
+        name: String,
+        url: String,
+    }
+    let file_path = archive.canonical.join(inner).to_string_lossy().into_owned();
+    let rendered = render_markdown_cached(markdown, &file_path, workspace_id, ws, root, state);
+    let folder = inner.rsplit_once('/').map_or("", |(dir, _)| dir);
+    let folder_url = if folder.is_empty() {
+        format!("{}/", archive.url)
+    } else {
+        archive.entry_url(&format!("{folder}/"))
+    };
+
+    let mut context = base_context(state);
+    context.insert(
+        "title",
+        &crate::markdown::document_title(markdown)
+            .unwrap_or_else(|| inner.rsplit('/').next().unwrap_or(inner).to_string()),
+    );
+    context.insert("file_path", &file_path);
+    context.insert("workspace_id", workspace_id);
+    context.insert(
+        "preview_token",
+        &workspace_preview_token(&state.save_token, workspace_id),
+    );
+    insert_workspace_header_context(&mut context, ws, root);
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("content", &rendered.html);
+    context.insert("toc", &rendered.toc);
+    context.insert(
+        "archive_source",
+        &ArchiveSource {
+            name: format!(
+                "{}/{inner}",
+                archive
+                    .canonical
+                    .file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_default()
+            ),
+            url: folder_url.clone(),
+        },
+    );
+    context.insert("back_link", &folder_url);
+    context.insert("show_back_link", &true);
+    context.insert("has_mermaid", &rendered.has_mermaid);
+    context.insert("has_math", &rendered.has_math);
+    let flags = ws.flags();
+    context.insert("shared_annotation", &false);
+    context.insert("enable_viewed", &false);
+    context.insert("enable_search", &flags.enable_search);
+    context.insert("can_manage", &false);
+    context.insert("enable_edit", &false);
+    context.insert("enable_live", &false);
+    context.insert("enable_chat", &false);
+
+    render_template(state, "layout.html", &context)
+}
+
+/// Async wrapper for the non-markdown preview path: the text sniff/read
+/// ([`read_text_for_preview`]) and, when the file is text, the syntect-
+/// highlighted [`render_file_view`] both run on the blocking pool. An API
+/// spec gets its [`render_openapi_page`] instead, unless `api_reference` is
+/// off (`?source=true`). Returns `None` when the file is not previewable
+/// text, so the caller falls back to `serve_file`.
+async fn render_preview_or_none(
+    canonical: PathBuf,
+    workspace_id: String,
+    ws: Arc<WorkspaceEntry>,
+    root: PathBuf,
+    state: AppState,
+    api_reference: bool,
+) -> Option<Response> {
+    tokio::task::spawn_blocking(move || {
+        let (content, token) = read_text_for_preview(&canonical)?;
+        if api_reference && crate::openapi::is_spec_path(&canonical) {
+            if let Some(api) = crate::openapi::render(&content) {
+                return Some(render_openapi_page(
+                    &canonical,
+                    api,
+                    &workspace_id,
+                    &ws,
+                    &root,
+                    &state,
+                ));
+            }
+        }
+        Some(render_file_view(
+            &canonical,
+            content,
+            token,
+            &workspace_id,
+            &ws,
+            &root,
+            &state,
+        ))
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("render_file_view join error: {e}");
+        Some((StatusCode::INTERNAL_SERVER_ERROR, "preview task failed").into_response())
+    })
+}
+
+/// Render `markdown_input` through the workspace's render cache (unless
+/// `--no-cache`). The cache validates the content hash, so an edit is always
+/// re-rendered even before the watcher evicts the old entry.
+fn render_markdown_cached(
+    markdown_input: &str,
+    file_path: &str,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+) -> Arc<MarkdownRenderOutput> {
+    let render = || {
+        let renderer = default_markdown_engine(&state.theme, &state.render_hooks)
+            .with_asset_context(
+                workspace_root_url(&state.base_path, workspace_id),
+                file_path,
+                root,
+            );
+        MarkdownEngine::render(&renderer, markdown_input)
+    };
+    if !state.render_cache {
+        return Arc::new(render());
+    }
+    let path = FsPath::new(file_path);
+    let content_hash = markdown_content_hash(markdown_input);
+    if let Some(hit) = ws
+        .render_cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .get(path, &content_hash)
+    {
+        return hit;
+    }
+    // Render outside the lock so concurrent views of other documents in the
+    // workspace aren't serialized behind this one.
+    let output = render();
+    ws.render_cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(path.to_path_buf(), content_hash, output)
+}
+
+/// A table-of-contents entry as layout.html links it. `page_url` is set when
+/// the heading sits on another page of a paginated document.
+#[derive(serde::Serialize)]
+struct TocLink<'a> {
+    #[serde(flatten)]
+    item: &'a TocItem,
+    page_url: Option<String>,
+}
+
+/// One entry of a paginated document's page navigation.
+#[derive(serde::Serialize)]
+struct DocumentPageLink<'a> {
+    url: String,
+    title: Option<&'a str>,
+}
+
+/// One row of the book sidebar: a chapter, a part title or a separator.
+#[derive(serde::Serialize)]
+struct BookNavRow<'a> {
+    kind: &'static str,
+    title: &'a str,
+    number: Option<&'a str>,
+    url: Option<String>,
+    /// Nesting below the top-level chapters, for indentation.
+    indent: usize,
+    current: bool,
+}
+
+/// Fill the layout context with the book navigation of a directory
+/// workspace that has a nav file (`--nav-file`): its chapters for the
+/// sidebar, and the chapters before and after `rel` when `rel` is one.
+fn insert_book_context(
+    context: &mut tera::Context,
+    state: &AppState,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    rel: &str,
+) {
+    if ws.single_file.is_some() || !ws.files.is_empty() {
+        return;
+    }
+    let Some(book) = crate::book::Book::load(root, &state.nav_files) else {
+        return;
+    };
+    fn chapter_link<'a>(
+        base_path: &str,
+        workspace_id: &str,
+        chapter: &'a crate::book::Chapter,
+    ) -> DocumentPageLink<'a> {
+        DocumentPageLink {
+            url: workspace_file_url(
+                base_path,
+                workspace_id,
+                chapter.path.as_deref().unwrap_or_default(),
+            ),
+            title: Some(&chapter.title),
+        }
+    }
+    let rows: Vec<BookNavRow> = book
+        .items
+        .iter()
+        .map(|item| match item {
+            crate::book::BookItem::Chapter(chapter) => BookNavRow {
+                kind: "chapter",
+                title: &chapter.title,
+                number: chapter.number.as_deref(),
+                url: chapter
+                    .path
+                    .as_deref()
+                    .map(|path| workspace_file_url(&state.base_path, workspace_id, path)),
+                indent: chapter.depth.saturating_sub(1),
+                current: chapter.path.as_deref() == Some(rel),
+            },
+            crate::book::BookItem::PartTitle(title) => BookNavRow {
+                kind: "part",
+                title,
+                number: None,
+                url: None,
+                indent: 0,
+                current: false,
+            },
+            crate::book::BookItem::Separator => BookNavRow {
+                kind: "separator",
+                title: "",
+                number: None,
+                url: None,
+                indent: 0,
+                current: false,
+            },
+        })
+        .collect();
+    if rows.is_empty() {
+        return;
+    }
+    context.insert("book_nav", &rows);
+    context.insert(
+        "book_summary_url",
+        &workspace_file_url(&state.base_path, workspace_id, &book.nav_path),
+    );
+    if let Some((prev, next)) = book.neighbours(rel) {
+        let link = |chapter| chapter_link(&state.base_path, workspace_id, chapter);
+        context.insert("prev_chapter", &prev.map(link));
+        context.insert("next_chapter", &next.map(link));
+    }
+}
+
+/// Fill the layout context for page `page` (1-based, clamped) of a document
+/// split by `--paginate`: that page's content, a TOC linking headings on
+/// other pages through `?page=`, previous/next links, and the anchor → page
+/// map the page script uses to follow `#anchor` links to their page.
+fn insert_document_page_context(
+    context: &mut tera::Context,
+    toc: &[TocItem],
+    pages: &[crate::markdown::DocumentPage],
+    page: usize,
+) {
+    let page = page.clamp(1, pages.len());
+    let page_of: HashMap<&str, usize> = pages
+        .iter()
+        .enumerate()
+        .rev()
+        .flat_map(|(index, doc_page)| {
+            doc_page
+                .anchors
+                .iter()
+                .map(move |anchor| (anchor.as_str(), index + 1))
+        })
+        .collect();
+    let toc: Vec<TocLink> = toc
+        .iter()
+        .map(|item| TocLink {
+            item,
+            page_url: page_of
+                .get(item.id.as_str())
+                .filter(|on| **on != page)
+                .map(|on| format!("?page={on}")),
+        })
+        .collect();
+    let link = |number: usize| DocumentPageLink {
+        url: format!("?page={number}"),
+        title: pages[number - 1].title.as_deref(),
+    };
+    context.insert("content", &pages[page - 1].html);
+    context.insert("toc", &toc);
+    context.insert("doc_page", &page);
+    context.insert("doc_page_count", &pages.len());
+    context.insert("prev_doc_page", &(page > 1).then(|| link(page - 1)));
+    context.insert(
+        "next_doc_page",
+        &(page < pages.len()).then(|| link(page + 1)),
+    );
+    context.insert(
+        "doc_page_anchors_json",
+        &js_json_safe(serde_json::to_string(&page_of).unwrap_or_default()),
+    );
+}
+
+fn render_markdown_file(
+    file_path: &str,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+    can_manage: bool,
+    page: usize,
+) -> Response {
+    match fs::read_to_string(file_path) {
+        Ok(markdown_input) => {
+            let rendered =
+                render_markdown_cached(&markdown_input, file_path, workspace_id, ws, root, state);
+
+            let title = std::path::Path::new(file_path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| file_path.to_string());
+
+            let mut context = base_context(state);
+            context.insert("title", &title);
+            context.insert("file_path", file_path);
+            context.insert("workspace_id", workspace_id);
+            context.insert(
+                "preview_token",
+                &workspace_preview_token(&state.save_token, workspace_id),
+            );
+            insert_workspace_header_context(&mut context, ws, root);
+            context.insert("version", env!("CARGO_PKG_VERSION"));
+            context.insert("content", &rendered.html);
+            context.insert(
+                "history_url",
+                &workspace_git_history_url(&state.base_path, workspace_id),
+            );
+            if let Ok(rel) = FsPath::new(file_path).strip_prefix(root) {
+                let rel = rel.to_string_lossy().replace('\\', "/");
+                let commits = MetadataCache::last_commits(
+                    &ws.metadata_cache,
+                    root,
+                    std::slice::from_ref(&rel),
+                );
+                context.insert("last_commit", &commits.get(&rel));
+                context.insert(
+                    "document_history_url",
+                    &document_history_url(&state.base_path, workspace_id, &rel),
+                );
+                context.insert(
+                    "present_url",
+                    &document_present_url(&state.base_path, workspace_id, &rel),
+                );
+                context.insert(
+                    "print_url",
+                    &document_print_url(&state.base_path, workspace_id, &rel),
+                );
+                if ws.snapshots {
+                    context.insert(
+                        "snapshots_url",
+                        &document_snapshots_url(&state.base_path, workspace_id, &rel),
+                    );
+                }
+                insert_book_context(&mut context, state, workspace_id, ws, root, &rel);
+            }
+            // Back link: the workspace root with this exact file highlighted;
+            // the directory tree expands the parent folders from the hash path.
+            // Suppressed for single-file workspaces — `/{id}/` 303-redirects
+            // back to this same file (see `handle_workspace_root`), so a
+            // "Back" link would be a no-op trap.
+            let back_link = workspace_file_back_link(
+                &state.base_path,
+                workspace_id,
+                std::path::Path::new(file_path),
+                root,
+            );
+            context.insert("back_link", &back_link);
+            context.insert("show_back_link", &ws.single_file.is_none());
+            context.insert("has_mermaid", &rendered.has_mermaid);
+            context.insert("has_math", &rendered.has_math);
+            context.insert("toc", &rendered.toc);
+            if let Some(pages) = state.paginate_bytes.and_then(|bytes| {
+                crate::markdown::paginate_html(&rendered.html, &rendered.toc, bytes)
+            }) {
+                insert_document_page_context(&mut context, &rendered.toc, &pages, page);
+            }
+            context.insert("markdown_diagnostics", &rendered.diagnostics);
+            context.insert("referenced_assets", &rendered.referenced_assets);
+            let flags = ws.flags();
+            context.insert("shared_annotation", &flags.shared_annotation);
+            context.insert("enable_viewed", &flags.enable_viewed);
+            context.insert("enable_search", &flags.enable_search);
+            context.insert("can_manage", &can_manage);
+            // Edit/chat are collaboration abilities gated by their flags.
+            // Structural writes require an explicit administrator session.
+            context.insert("enable_edit", &flags.enable_edit);
+            context.insert("enable_live", &flags.enable_live);
+            context.insert("enable_chat", &flags.enable_chat);
+            // Lets `#annotation-<id>` permalinks resolve (or fail fast) before
+            // the annotations themselves load. Same visibility as document
+            // state: administrators, or everyone once annotations are shared.
+            if can_manage || flags.shared_annotation {
+                if let Some(db) = state.annotation_db() {
+                    let index = db
+                        .read_blocking(|conn| crate::annotations::position_index(conn, file_path));
+                    match index {
+                        Ok(index) => context.insert(
+                            "annotation_index_json",
+                            &js_json_safe(serde_json::to_string(&index).unwrap_or_default()),
+                        ),
+                        Err(e) => tracing::warn!("annotation index failed: {e}"),
+                    }
+                }
+            }
+
+            if flags.enable_edit {
+                // JSON-encode and HTML-escape so </script> in content can't break the page.
+                let json = js_json_safe(serde_json::to_string(&markdown_input).unwrap_or_default());
+                context.insert("markdown_content_json", &json);
+                // Embed a token derived for this workspace, NOT the process
+                // secret or master management token. A collaborator cannot
+                // replay it against a differently gated workspace.
+                context.insert(
+                    "save_token",
+                    &workspace_save_token(&state.save_token, workspace_id),
+                );
+            }
+
+            render_template(state, "layout.html", &context)
+        }
+        Err(e) => {
+            let mut context = base_context(state);
+            context.insert("title", "Error");
+            context.insert("version", env!("CARGO_PKG_VERSION"));
+            context.insert(
+                "content",
+                &format!(
+                    r#"<p style="color: red;">Error reading file '{file_path}': {e}</p>
+                       <a href="/">← Back to file list</a>"#
+                ),
+            );
+            context.insert("show_back_link", &false);
+            context.insert("has_mermaid", &false);
+            context.insert("has_math", &false);
+
+            render_template(state, "layout.html", &context)
+        }
+    }
+}
+
+/// One row of a directory listing. Shared between the server-rendered file table
+/// (`render_directory_listing`) and the JSON endpoint that feeds the inline tree
+/// (`handle_workspace_dir_data`), so both stay byte-for-byte consistent in what
+/// they list, how they sort, and the commit metadata they attach.
+#[derive(serde::Serialize)]
+struct DirListingEntry {
+    name: String,
+    is_dir: bool,
+    is_markdown: bool,
+    is_hidden: bool,
+    /// Excluded by the workspace's ignore rules (`.gitignore`, `.ignore`), as
+    /// the search indexer and file watcher see it. Such entries are only
+    /// listed on request (see [`DirView::show_ignored`]).
+    is_ignored: bool,
+    show_in_markdown: bool,
+    link: String,
+    rel_git_path: String,
+    last_commit_subject: Option<String>,
+    last_commit_time: Option<String>,
+    last_commit_author: Option<String>,
+    /// File size in bytes; `None` for directories and symlinks, which are
+    /// never stat'ed (see [`collect_directory_entries`]).
+    size: Option<u64>,
+    /// Last modification as Unix seconds, under the same rule as `size`.
+    mtime: Option<u64>,
+    /// Frontmatter title or first heading of a markdown file, shown in place
+    /// of the file name.
+    title: Option<String>,
+    /// Reading progress of a markdown file, for readers of the document state
+    /// in workspaces that track viewed sections.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<SectionProgress>,
+    /// Nested listing of a directory, filled only when the tree was requested
+    /// more than one level deep and the entry budget allowed it. Absent means
+    /// "not loaded yet", not "empty".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<DirListingEntry>>,
+}
+
+/// Entries per directory listing page when no `--listing-page-size` is given.
+pub const DEFAULT_LISTING_PAGE_SIZE: usize = 500;
+
+/// Files rendered below a directory listing when no `--readme` is given, in
+/// priority order.
+pub const DEFAULT_README_NAMES: &[&str] = &["README.md", "index.md"];
+
+/// Book nav files looked up at a workspace root when no `--nav-file` is
+/// given, in priority order: a plain `SUMMARY.md`, then mdBook's layout.
+pub const DEFAULT_NAV_FILES: &[&str] = &["SUMMARY.md", "src/SUMMARY.md"];
+
+/// Deepest tree `/files/dir?depth=` will return in one response.
+const MAX_DIR_TREE_DEPTH: usize = 8;
+/// Entries a single nested listing may carry before deeper folders are left
+/// for the client to load lazily.
+const MAX_DIR_TREE_ENTRIES: usize = 2000;
+
+/// Fill `children` for the non-hidden directories in `entries`, descending up
+/// to `depth` more levels, breadth-first so a tight `budget` still expands the
+/// shallow folders. Folders past the budget keep `children: None`.
+#[allow(clippy::too_many_arguments)]
+fn expand_directory_tree(
+    base_path: &str,
+    workspace_id: &str,
+    metadata: &Mutex<MetadataCache>,
+    policy: &crate::fswalk::WalkPolicy,
+    root: &FsPath,
+    entries: &mut [DirListingEntry],
+    depth: usize,
+    show_ignored: bool,
+    budget: &mut usize,
+) {
+    if depth == 0 {
+        return;
+    }
+    for entry in entries
+        .iter_mut()
+        .filter(|entry| entry.is_dir && !entry.is_hidden)
+    {
+        if *budget == 0 {
+            return;
+        }
+        let Ok(children) = collect_directory_entries(
+            base_path,
+            workspace_id,
+            metadata,
+            policy,
+            root,
+            &root.join(&entry.rel_git_path),
+            show_ignored,
+        ) else {
+            continue;
+        };
+        *budget = budget.saturating_sub(children.len());
+        entry.children = Some(children);
+    }
+    for entry in entries.iter_mut() {
+        if let Some(children) = entry.children.as_mut() {
+            expand_directory_tree(
+                base_path,
+                workspace_id,
+                metadata,
+                policy,
+                root,
+                children,
+                depth - 1,
+                show_ignored,
+                budget,
+            );
+        }
+    }
+}
+
+/// List the direct children of `current_dir` (already canonicalized and verified
+/// inside `root`), sorted directories-first then by name, with the last-commit
+/// subject/time attached per entry when the workspace is a git repo. Only this
+/// one directory level is walked and only these paths are queried for commits —
+/// cheap enough to serve on demand as a folder is expanded. Entries the ignore
+/// rules exclude are dropped unless `show_ignored`. Markdown titles come from
+/// `metadata` when the file is unchanged.
+fn collect_directory_entries(
+    base_path: &str,
+    workspace_id: &str,
+    metadata: &Mutex<MetadataCache>,
+    policy: &crate::fswalk::WalkPolicy,
+    root: &FsPath,
+    current_dir: &FsPath,
+    show_ignored: bool,
+) -> std::io::Result<Vec<DirListingEntry>> {
+    let walked = policy.walked_children(current_dir, root);
+    let mut entries: Vec<DirListingEntry> = fs::read_dir(current_dir)?
+        .filter_map(|e| e.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            let is_hidden = policy.is_hidden_name(&name);
+            let is_ignored = !is_hidden && !walked.contains(&entry.file_name());
+            if is_ignored && !show_ignored {
+                return None;
+            }
+            // Use file_type() — avoids stat() syscall that can block on AutoFS mount points.
+            // Only regular files are stat'ed for size / mtime below.
+            let mut file_type = entry.file_type().ok()?;
+            let is_symlink = file_type.is_symlink();
+            if is_symlink {
+                // A link the symlink policy refuses is left out; a followed
+                // one lists as what it points at.
+                if !policy.symlink_allowed(root, &path) {
+                    return None;
+                }
+                file_type = fs::metadata(&path).ok()?.file_type();
+            }
+            let is_dir = file_type.is_dir();
+            let is_markdown = !is_dir && policy.is_markdown(&path);
+            let meta = if is_symlink {
+                file_type
+                    .is_file()
+                    .then(|| fs::metadata(&path).ok())
+                    .flatten()
+            } else {
+                file_type.is_file().then(|| entry.metadata().ok()).flatten()
+            };
+            let title = match &meta {
+                Some(meta) if is_markdown => cached_markdown_title(metadata, &path, meta),
+                _ => None,
+            };
+            let rel = path.strip_prefix(root).unwrap_or(&path).to_path_buf();
+            let rel_git_path = rel.to_string_lossy().replace('\\', "/");
+            let rel_url = path_to_route(&rel);
+            let link = if is_dir {
+                workspace_file_url(base_path, workspace_id, &format!("{rel_url}/"))
+            } else {
+                workspace_file_url(base_path, workspace_id, &rel_url)
+            };
+            Some(DirListingEntry {
+                name,
+                is_dir,
+                is_markdown,
+                is_hidden,
+                is_ignored,
+                show_in_markdown: !is_hidden && !is_ignored && is_markdown,
+                link,
+                rel_git_path,
+                last_commit_subject: None,
+                last_commit_time: None,
+                last_commit_author: None,
+                size: meta.as_ref().map(|meta| meta.len()),
+                mtime: meta.as_ref().and_then(unix_mtime),
+                title,
+                progress: None,
+                children: None,
+            })
+        })
+        .collect();
+
+    if entries.iter().any(|entry| entry.is_dir && !entry.is_hidden) {
+        let dirs_with_markdown =
+            direct_child_dirs_with_markdown_descendants(policy, root, current_dir);
+        for entry in entries.iter_mut().filter(|entry| entry.is_dir) {
+            entry.show_in_markdown =
+                !entry.is_hidden && dirs_with_markdown.contains(&entry.rel_git_path);
+        }
+    }
+
+    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        _ => a.name.cmp(&b.name),
+    });
+
+    attach_last_commits(&mut entries, metadata, root);
+    Ok(entries)
+}
+
+/// Fill in the last commit of each entry when the workspace is in a git
+/// repository.
+fn attach_last_commits(
+    entries: &mut [DirListingEntry],
+    metadata: &Mutex<MetadataCache>,
+    root: &FsPath,
+) {
+    let rel_paths: Vec<String> = entries
+        .iter()
+        .map(|entry| entry.rel_git_path.clone())
+        .collect();
+    let commits = MetadataCache::last_commits(metadata, root, &rel_paths);
+    for entry in entries.iter_mut() {
+        let Some(commit) = commits.get(&entry.rel_git_path) else {
+            continue;
+        };
+        entry.last_commit_subject = Some(commit.subject.clone());
+        entry.last_commit_time = Some(commit.time.clone());
+        entry.last_commit_author = Some(commit.author.clone());
+    }
+}
+
+/// The files of a file-set workspace as listing rows, in the order they were
+/// given. Each row is named by its path so files from different directories
+/// stay apart; files that have gone missing are left out.
+fn file_set_entries(
+    base_path: &str,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+) -> Vec<DirListingEntry> {
+    let mut entries: Vec<DirListingEntry> = ws
+        .files
+        .iter()
+        .filter_map(|route| {
+            let path = ws.fs.resolve_served(route).ok()?;
+            let meta = fs::metadata(&path).ok().filter(fs::Metadata::is_file)?;
+            let is_markdown = ws.fs.policy().is_markdown(&path);
+            let title = if is_markdown {
+                cached_markdown_title(&ws.metadata_cache, &path, &meta)
+            } else {
+                None
+            };
+            Some(DirListingEntry {
+                name: route.clone(),
+                is_dir: false,
+                is_markdown,
+                is_hidden: false,
+                is_ignored: false,
+                show_in_markdown: is_markdown,
+                link: workspace_file_url(base_path, workspace_id, route),
+                rel_git_path: route.clone(),
+                last_commit_subject: None,
+                last_commit_time: None,
+                last_commit_author: None,
+                size: Some(meta.len()),
+                mtime: unix_mtime(&meta),
+                title,
+                progress: None,
+                children: None,
+            })
+        })
+        .collect();
+
+    attach_last_commits(&mut entries, &ws.metadata_cache, root);
+    entries
+}
+
+/// Title of a listed markdown file, from the workspace's metadata cache.
+fn cached_markdown_title(
+    metadata: &Mutex<MetadataCache>,
+    path: &FsPath,
+    meta: &fs::Metadata,
+) -> Option<String> {
+    MetadataCache::metadata(metadata, path, meta).title.clone()
+}
+
+fn unix_mtime(meta: &fs::Metadata) -> Option<u64> {
+    meta.modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+/// Listing order from `?sort=name|mtime|size&order=asc|desc`. Directories
+/// always come first; `order` defaults to ascending for names and descending
+/// (newest / largest first) for the other keys.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct DirSort {
+    key: DirSortKey,
+    descending: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum DirSortKey {
+    #[default]
+    Name,
+    Mtime,
+    Size,
+}
+
+impl DirSortKey {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Name => "name",
+            Self::Mtime => "mtime",
+            Self::Size => "size",
+        }
+    }
+}
+
+impl DirSort {
+    fn parse(sort: Option<&str>, order: Option<&str>) -> Result<Self, String> {
+        let key = match sort.map(str::trim).unwrap_or("") {
+            "" | "name" => DirSortKey::Name,
+            "mtime" => DirSortKey::Mtime,
+            "size" => DirSortKey::Size,
+            other => return Err(format!("Invalid sort '{other}': use name, mtime, or size")),
+        };
+        let descending = match order.map(str::trim).unwrap_or("") {
+            "" => key != DirSortKey::Name,
+            "asc" => false,
+            "desc" => true,
+            other => return Err(format!("Invalid order '{other}': use asc or desc")),
+        };
+        Ok(Self { key, descending })
+    }
+
+    /// Reorder `entries`, which arrive sorted directories-first then by name.
+    /// Ties, and entries without the key (directories for size / mtime), keep
+    /// name order.
+    fn apply(self, entries: &mut [DirListingEntry]) {
+        entries.sort_by(|a, b| {
+            let by_key = match self.key {
+                DirSortKey::Name => a.name.cmp(&b.name),
+                DirSortKey::Mtime => a.mtime.cmp(&b.mtime),
+                DirSortKey::Size => a.size.cmp(&b.size),
+            };
+            let by_key = if self.descending {
+                by_key.reverse()
+            } else {
+                by_key
+            };
+            b.is_dir
+                .cmp(&a.is_dir)
+                .then(by_key)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+    }
+}
+
+/// [`DirSort::apply`] at every level of an expanded tree.
+fn sort_directory_tree(sort: DirSort, entries: &mut [DirListingEntry]) {
+    sort.apply(entries);
+    for children in entries.iter_mut().filter_map(|e| e.children.as_mut()) {
+        sort_directory_tree(sort, children);
+    }
+}
+
+fn direct_child_dirs_with_markdown_descendants(
+    policy: &crate::fswalk::WalkPolicy,
+    root: &FsPath,
+    current_dir: &FsPath,
+) -> HashSet<String> {
+    let mut dirs = HashSet::new();
+    let walker = policy.bounded_walker(current_dir, root).build();
+    for entry in walker.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        if path == current_dir || !path.is_file() || !policy.is_markdown(path) {
+            continue;
+        }
+        let Ok(rel_to_current) = path.strip_prefix(current_dir) else {
+            continue;
+        };
+        let Some(std::path::Component::Normal(first_component)) =
+            rel_to_current.components().next()
+        else {
+            continue;
+        };
+        let direct_child = current_dir.join(first_component);
+        if direct_child == path {
+            continue;
+        }
+        let rel_to_root = direct_child.strip_prefix(root).unwrap_or(&direct_child);
+        dirs.insert(path_to_route(rel_to_root));
+    }
+    dirs
+}
+
+/// JSON: the direct children of a directory (relative to the workspace root),
+/// used by the inline directory tree on the workspace landing page. Mirrors the
+/// auth/boundary handling of `handle_workspace_files_data`: canonicalize the
+/// requested path and reject anything that escapes the workspace root.
+/// `depth` > 1 nests sub-directory listings under `children` (capped by
+/// [`MAX_DIR_TREE_DEPTH`] / [`MAX_DIR_TREE_ENTRIES`]) so "expand all" costs
+/// one round trip.
+async fn handle_workspace_dir_data(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<DirListingQuery>,
+) -> impl IntoResponse {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let rel = query
+        .path
+        .as_deref()
+        .unwrap_or("")
+        .trim()
+        .trim_matches('/')
+        .to_string();
+    let sort = match DirSort::parse(query.sort.as_deref(), query.order.as_deref()) {
+        Ok(sort) => sort,
+        Err(message) => return (StatusCode::BAD_REQUEST, message).into_response(),
+    };
+    if ws.is_ephemeral() {
+        if rel.split('/').any(|part| part == ".." || part == ".") {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        return Json(scoped_directory_entries(
+            &state.base_path,
+            &workspace_id,
+            &ws,
+            &rel,
+        ))
+        .into_response();
+    }
+    let show_progress = progress_visible(role.map(|Extension(role)| role), &ws);
+    tokio::task::spawn_blocking(move || {
+        let root = canonical_workspace_root(&ws);
+        let target = if rel.is_empty() {
+            root.clone()
+        } else {
+            root.join(&rel)
+        };
+        let current_dir = match canonicalize_route_path(&target) {
+            Ok(p) => p,
+            Err(_) => return StatusCode::NOT_FOUND.into_response(),
+        };
+        if !current_dir.starts_with(&root) {
+            return StatusCode::NOT_FOUND.into_response();
+        }
+        match collect_directory_entries(
+            &state.base_path,
+            &workspace_id,
+            &ws.metadata_cache,
+            ws.fs.policy(),
+            &root,
+            &current_dir,
+            query.show_ignored,
+        ) {
+            Ok(mut entries) => {
+                let depth = query.depth.unwrap_or(1).clamp(1, MAX_DIR_TREE_DEPTH);
+                let mut budget = MAX_DIR_TREE_ENTRIES.saturating_sub(entries.len());
+                expand_directory_tree(
+                    &state.base_path,
+                    &workspace_id,
+                    &ws.metadata_cache,
+                    ws.fs.policy(),
+                    &root,
+                    &mut entries,
+                    depth - 1,
+                    query.show_ignored,
+                    &mut budget,
+                );
+                sort_directory_tree(sort, &mut entries);
+                if show_progress {
+                    attach_progress(&mut entries, &workspace_id, &ws, &root, &state);
+                }
+                Json(entries).into_response()
+            }
+            Err(_) => Json(Vec::<DirListingEntry>::new()).into_response(),
+        }
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("directory data join error: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+/// Build a virtual directory view from the single-file capability set without
+/// touching or enumerating sibling filesystem entries.
+fn scoped_directory_entries(
+    base_path: &str,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    directory: &str,
+) -> Vec<DirListingEntry> {
+    let prefix = directory.trim_matches('/');
+    let mut entries: HashMap<String, DirListingEntry> = HashMap::new();
+    for (rel, path) in ws.fs.served_files(2000) {
+        let route = rel.as_route();
+        let rest = if prefix.is_empty() {
+            route.as_str()
+        } else if let Some(rest) = route.strip_prefix(prefix).and_then(|r| r.strip_prefix('/')) {
+            rest
+        } else {
+            continue;
+        };
+        let (name, is_dir) = match rest.split_once('/') {
+            Some((name, _)) => (name, true),
+            None => (rest, false),
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let child_route = if prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{prefix}/{name}")
+        };
+        let link_route = if is_dir {
+            format!("{child_route}/")
+        } else {
+            child_route.clone()
+        };
+        let markdown_descendant = ws.fs.policy().is_markdown(&path);
+        let entry = entries
+            .entry(name.to_string())
+            .or_insert_with(|| DirListingEntry {
+                name: name.to_string(),
+                is_dir,
+                is_markdown: !is_dir && markdown_descendant,
+                is_hidden: ws.fs.policy().is_hidden_name(name),
+                is_ignored: false,
+                show_in_markdown: !ws.fs.policy().is_hidden_name(name) && markdown_descendant,
+                link: workspace_file_url(base_path, workspace_id, &link_route),
+                rel_git_path: child_route,
+                last_commit_subject: None,
+                last_commit_time: None,
+                last_commit_author: None,
+                size: None,
+                mtime: None,
+                title: None,
+                progress: None,
+                children: None,
+            });
+        entry.show_in_markdown |= !entry.is_hidden && markdown_descendant;
+    }
+    let mut entries: Vec<_> = entries.into_values().collect();
+    entries.sort_by(|a, b| match (a.is_dir, b.is_dir) {
+        (true, false) => std::cmp::Ordering::Less,
+        (false, true) => std::cmp::Ordering::Greater,
+        _ => a.name.cmp(&b.name),
+    });
+    entries
+}
+
+#[derive(Deserialize, Default)]
+struct DirListingQuery {
+    path: Option<String>,
+    /// Levels to list, 1 = direct children only. Only `/files/dir` honours it.
+    depth: Option<usize>,
+    /// See [`DirView`].
+    sort: Option<String>,
+    order: Option<String>,
+    #[serde(default)]
+    show_ignored: bool,
+}
+
+/// Query of the server-rendered listing, see [`DirView`].
+#[derive(Deserialize, Default)]
+struct DirViewQuery {
+    sort: Option<String>,
+    order: Option<String>,
+    #[serde(default)]
+    show_ignored: bool,
+    page: Option<usize>,
+    /// Show an API spec as highlighted source instead of its reference page.
+    #[serde(default)]
+    source: bool,
+}
+
+impl DirViewQuery {
+    fn parse(&self) -> Result<DirView, String> {
+        if self.page == Some(0) {
+            return Err("Invalid page '0': pages start at 1".into());
+        }
+        Ok(DirView {
+            sort: DirSort::parse(self.sort.as_deref(), self.order.as_deref())?,
+            show_ignored: self.show_ignored,
+            page: self.page.unwrap_or(1),
+        })
+    }
+}
+
+/// How a listing is presented.
+#[derive(Clone, Copy, Debug, Default)]
+struct DirView {
+    sort: DirSort,
+    /// `?show_ignored=true` also lists entries excluded by ignore rules.
+    show_ignored: bool,
+    /// 1-based page of `AppState::listing_page_size` entries, taken after
+    /// sorting so pages never overlap. Past the end clamps to the last page.
+    page: usize,
+}
+
+impl DirView {
+    /// Query string for `page` of this same view.
+    fn page_query(self, page: usize) -> String {
+        let mut query = format!("?sort={}", self.sort.key.as_str());
+        if self.sort.descending != (self.sort.key != DirSortKey::Name) {
+            query.push_str(if self.sort.descending {
+                "&order=desc"
+            } else {
+                "&order=asc"
+            });
+        }
+        if self.show_ignored {
+            query.push_str("&show_ignored=true");
+        }
+        if page > 1 {
+            query.push_str(&format!("&page={page}"));
+        }
+        query
+    }
+}
+
+/// One child of a directory in the `/_/{workspace_id}/files/ls` JSON listing.
+/// Unlike [`DirListingEntry`] (shaped for the HTML tree), this stats each entry
+/// so alternate frontends get size / mtime without parsing HTML. The document-
+/// state counts are only filled for markdown files, and only when the caller
+/// may read annotations for this workspace.
+#[derive(Serialize, Debug)]
+struct LsEntry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    path: String,
+    link: String,
+    is_markdown: bool,
+    is_hidden: bool,
+    size: Option<u64>,
+    mtime: Option<u64>,
+    annotation_count: Option<usize>,
+    viewed_count: Option<usize>,
+}
+
+/// Annotation count and number of sections marked viewed for one document.
+fn document_state_counts(conn: &Connection, file_path: &str) -> (usize, usize) {
+    let annotations = conn
+        .query_row(
+            "SELECT COUNT(*) FROM annotations WHERE file_path = ?1",
+            [file_path],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|count| count.max(0) as usize)
+        .unwrap_or(0);
+    let viewed = stored_viewed_state(conn, file_path)
+        .and_then(|state| {
+            state.as_object().map(|sections| {
+                sections
+                    .values()
+                    .filter(|value| value.as_bool() == Some(true))
+                    .count()
+            })
+        })
+        .unwrap_or(0);
+    (annotations, viewed)
+}
+
+/// The stored `{heading id: viewed}` map of one document, if any.
+fn stored_viewed_state(conn: &Connection, file_path: &str) -> Option<serde_json::Value> {
+    conn.query_row(
+        "SELECT state FROM viewed_state WHERE file_path = ?1",
+        [file_path],
+        |row| row.get::<_, String>(0),
+    )
+    .ok()
+    .and_then(|json| serde_json::from_str(&json).ok())
+}
+
+/// Reading progress of one document: how many of its sections (the h2–h6
+/// headings that carry a Viewed checkbox) are marked viewed.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct SectionProgress {
+    viewed: usize,
+    total: usize,
+    /// `viewed / total` as a percentage, rounded down so 100 means done.
+    percent: u8,
+}
+
+impl SectionProgress {
+    /// Progress against the sections in `toc`; `None` without any. Viewed
+    /// entries for headings that no longer exist are ignored.
+    fn of(toc: &[TocItem], viewed: &serde_json::Value) -> Option<Self> {
+        let sections: Vec<&TocItem> = toc.iter().filter(|item| item.level >= 2).collect();
+        let total = sections.len();
+        if total == 0 {
+            return None;
+        }
+        let viewed = sections
+            .iter()
+            .filter(|item| {
+                viewed
+                    .get(&item.id)
+                    .and_then(serde_json::Value::as_bool)
+                    .unwrap_or(false)
+            })
+            .count();
+        Some(Self {
+            viewed,
+            total,
+            percent: (viewed * 100 / total) as u8,
+        })
+    }
+}
+
+/// Whether listings and the progress API show reading progress to `role`.
+fn progress_visible(role: Option<AccessRole>, ws: &WorkspaceEntry) -> bool {
+    ws.flags().enable_viewed && document_state_access_allowed(role, ws)
+}
+
+/// [`SectionProgress`] of `file_path` against its stored `viewed` state. The
+/// section list comes from the metadata cache; a miss renders through the
+/// render cache, so a listing warms the pages it links to.
+fn document_progress(
+    file_path: &str,
+    viewed: &serde_json::Value,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+) -> Option<SectionProgress> {
+    let path = FsPath::new(file_path);
+    let meta = fs::metadata(path).ok()?;
+    let cached = ws
+        .metadata_cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .toc(path, &meta);
+    if let Some(toc) = cached {
+        return SectionProgress::of(&toc, viewed);
+    }
+    let markdown = fs::read_to_string(path).ok()?;
+    let rendered = render_markdown_cached(&markdown, file_path, workspace_id, ws, root, state);
+    let toc = Arc::new(rendered.toc.clone());
+    ws.metadata_cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert_toc(path, &meta, &markdown, toc.clone());
+    SectionProgress::of(&toc, viewed)
+}
+
+/// Fill in [`DirListingEntry::progress`] for the markdown files that have
+/// stored viewed state. Files nobody has started reading stay without a badge,
+/// and are never read or rendered.
+fn attach_progress(
+    entries: &mut [DirListingEntry],
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+) {
+    let Some(db) = state.annotation_db() else {
+        return;
+    };
+    for entry in entries
+        .iter_mut()
+        .filter(|entry| entry.is_markdown && !entry.is_dir)
+    {
+        let file_path = root
+            .join(&entry.rel_git_path)
+            .to_string_lossy()
+            .into_owned();
+        let viewed = db.read_blocking(|conn| stored_viewed_state(conn, &file_path));
+        if let Some(viewed) = viewed {
+            entry.progress = document_progress(&file_path, &viewed, workspace_id, ws, root, state);
+        }
+    }
+    for entry in entries.iter_mut() {
+        if let Some(children) = entry.children.as_mut() {
+            attach_progress(children, workspace_id, ws, root, state);
+        }
+    }
+}
+
+fn ls_entries(
+    root: &FsPath,
+    entries: Vec<DirListingEntry>,
+    db: Option<&crate::db::Database>,
+) -> Vec<LsEntry> {
+    let counts = |path: &FsPath| {
+        db.map(|db| db.read_blocking(|conn| document_state_counts(conn, &path.to_string_lossy())))
+    };
+    entries
+        .into_iter()
+        .map(|entry| {
+            let abs = root.join(&entry.rel_git_path);
+            let meta = fs::metadata(&abs).ok();
+            let mtime = meta
+                .as_ref()
+                .and_then(|m| m.modified().ok())
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let (annotation_count, viewed_count) = match counts(&abs) {
+                Some((annotations, viewed)) if entry.is_markdown => {
+                    (Some(annotations), Some(viewed))
+                }
+                _ => (None, None),
+            };
+            LsEntry {
+                kind: if entry.is_dir { "dir" } else { "file" },
+                size: meta.as_ref().filter(|m| m.is_file()).map(|m| m.len()),
+                mtime,
+                annotation_count,
+                viewed_count,
+                name: entry.name,
+                path: entry.rel_git_path,
+                link: entry.link,
+                is_markdown: entry.is_markdown,
+                is_hidden: entry.is_hidden,
+            }
+        })
+        .collect()
+}
+
+/// JSON directory listing with per-entry type, size, mtime and (for readers of
+/// the document state) annotation / viewed counts. `path` is workspace-relative;
+/// omitted or empty lists the root. Paths outside the workspace 404.
+async fn handle_workspace_ls(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    role: Option<Extension<AccessRole>>,
+    Query(query): Query<DirListingQuery>,
+) -> Response {
+    let Some(ws) = state.workspace_registry.get(&workspace_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let rel = query
+        .path
+        .as_deref()
+        .unwrap_or("")
+        .trim()
+        .trim_matches('/')
+        .to_string();
+    if rel.split('/').any(|part| part == ".." || part == ".") {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    let db = state
+        .annotation_db()
+        .cloned()
+        .filter(|_| document_state_access_allowed(role.map(|Extension(role)| role), &ws));
+    tokio::task::spawn_blocking(move || {
+        let root = canonical_workspace_root(&ws);
+        let entries = if ws.is_ephemeral() {
+            scoped_directory_entries(&state.base_path, &workspace_id, &ws, &rel)
+        } else {
+            let target = if rel.is_empty() {
+                root.clone()
+            } else {
+                root.join(&rel)
+            };
+            let Ok(current_dir) = canonicalize_route_path(&target) else {
+                return StatusCode::NOT_FOUND.into_response();
+            };
+            if !current_dir.starts_with(&root) || !current_dir.is_dir() {
+                return StatusCode::NOT_FOUND.into_response();
+            }
+            match collect_directory_entries(
+                &state.base_path,
+                &workspace_id,
+                &ws.metadata_cache,
+                ws.fs.policy(),
+                &root,
+                &current_dir,
+                true,
+            ) {
+                Ok(entries) => entries,
+                Err(_) => return StatusCode::NOT_FOUND.into_response(),
+            }
+        };
+        Json(ls_entries(&root, entries, db.as_ref())).into_response()
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("workspace ls join error: {e}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
+/// Async wrapper for [`render_directory_listing`]: the directory walk and the
+/// per-entry git lookups run on the blocking pool.
+#[allow(clippy::too_many_arguments)]
+async fn render_directory_listing_async(
+    workspace_id: String,
+    ws: Arc<WorkspaceEntry>,
+    root: PathBuf,
+    state: AppState,
+    can_manage: bool,
+    show_progress: bool,
+    view: DirView,
+    client_id: Option<String>,
+) -> Response {
+    tokio::task::spawn_blocking(move || {
+        render_directory_listing(
+            &workspace_id,
+            &ws,
+            &root,
+            None,
+            &state,
+            can_manage,
+            show_progress,
+            view,
+            client_id.as_deref(),
+        )
+    })
+    .await
+    .unwrap_or_else(|e| {
+        tracing::error!("render_directory_listing join error: {e}");
+        (StatusCode::INTERNAL_SERVER_ERROR, "listing task failed").into_response()
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_directory_listing(
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    dir_param: Option<&str>,
+    state: &AppState,
+    can_manage: bool,
+    show_progress: bool,
+    view: DirView,
+    client_id: Option<&str>,
+) -> Response {
+    // A file-set workspace lists its files, in the order given, in place of
+    // the root directory's entries.
+    let file_set = !ws.files.is_empty();
+    let workspace_root = match ws.fs.directory_root() {
+        Some(workspace_root) => workspace_root,
+        None if file_set && dir_param.is_none() => root,
+        None => return StatusCode::NOT_FOUND.into_response(),
+    };
+    let current_dir = if let Some(dir_str) = dir_param {
+        let p = PathBuf::from(dir_str);
+        if p.is_absolute() {
+            p
+        } else {
+            workspace_root.join(&p)
+        }
+    } else {
+        workspace_root.to_path_buf()
+    };
+
+    let current_dir = match canonicalize_route_path(&current_dir) {
+        Ok(p) => p,
+        Err(e) => {
+            return (StatusCode::BAD_REQUEST, format!("Invalid directory: {e}")).into_response()
+        }
+    };
+    // Defense in depth: the caller's gate trims the leading slash before its
+    // boundary check, but this function re-derives `current_dir` from the raw
+    // (possibly absolute) `dir_param`. Re-verify the canonical dir is inside the
+    // workspace so an absolute path like `/etc` can't list outside the root.
+    if !current_dir.starts_with(root) {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+
+    let (entries, readme) = if file_set {
+        (
+            file_set_entries(&state.base_path, workspace_id, ws, root),
+            None,
+        )
+    } else {
+        let mut entries = match collect_directory_entries(
+            &state.base_path,
+            workspace_id,
+            &ws.metadata_cache,
+            ws.fs.policy(),
+            root,
+            &current_dir,
+            view.show_ignored,
+        ) {
+            Ok(entries) => entries,
+            Err(e) => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    format!("Error reading directory: {e}"),
+                )
+                    .into_response()
+            }
+        };
+        view.sort.apply(&mut entries);
+        // The README is looked up among all entries, not just the current page.
+        let readme = directory_readme(&entries, &current_dir, workspace_id, ws, root, state);
+        (entries, readme)
+    };
+    let page_size = state.listing_page_size.max(1);
+    let total_entries = entries.len();
+    let page_count = total_entries.div_ceil(page_size).max(1);
+    let page = view.page.clamp(1, page_count);
+    let mut entries: Vec<DirListingEntry> = entries
+        .into_iter()
+        .skip((page - 1) * page_size)
+        .take(page_size)
+        .collect();
+    if show_progress {
+        attach_progress(&mut entries, workspace_id, ws, root, state);
+    }
+    let git_status = git::status(root);
+
+    let show_parent = current_dir != root;
+    let parent_link: Option<String> = if show_parent {
+        current_dir.parent().map(|parent| {
+            let rel = parent
+                .strip_prefix(root)
+                .map(path_to_route)
+                .unwrap_or_default();
+            if rel.is_empty() {
+                workspace_root_url(&state.base_path, workspace_id)
+            } else {
+                workspace_file_url(&state.base_path, workspace_id, &format!("{rel}/"))
+            }
+        })
+    } else {
+        None
+    };
+
+    // Breadcrumb from workspace root down to `current_dir`. The first segment is
+    // the workspace itself (alias, falling back to the root dir name) linking to
+    // the workspace root; each deeper segment links to its own subdirectory. The
+    // final segment is the current directory and carries no link. At the root the
+    // breadcrumb is a single (current) segment. Path components are joined with
+    // `/` so Windows separators normalise like `path_to_route`.
+    #[derive(serde::Serialize)]
+    struct BreadcrumbSegment {
+        name: String,
+        link: String,
+        is_current: bool,
+    }
+    let workspace_display_name = workspace_display_name(ws, root);
+    let rel_components: Vec<String> = current_dir
+        .strip_prefix(root)
+        .ok()
+        .map(|rel| {
+            rel.components()
+                .filter_map(|c| match c {
+                    std::path::Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let mut breadcrumb: Vec<BreadcrumbSegment> = Vec::new();
+    let depth = rel_components.len();
+    breadcrumb.push(BreadcrumbSegment {
+        name: workspace_display_name,
+        link: workspace_root_url(&state.base_path, workspace_id),
+        is_current: depth == 0,
+    });
+    let mut acc = String::new();
+    for (i, comp) in rel_components.iter().enumerate() {
+        if acc.is_empty() {
+            acc = comp.clone();
+        } else {
+            acc = format!("{acc}/{comp}");
+        }
+        breadcrumb.push(BreadcrumbSegment {
+            name: comp.clone(),
+            link: workspace_file_url(&state.base_path, workspace_id, &format!("{acc}/")),
+            is_current: i + 1 == depth,
+        });
+    }
+
+    let flags = ws.flags();
+    let feature_statuses = vec![
+        WorkspaceFeatureStatus {
+            key: "enable_search",
+            label: "Search",
+            label_key: "web.ws.feature.search",
+            enabled: flags.enable_search,
+        },
+        WorkspaceFeatureStatus {
+            key: "enable_viewed",
+            label: "Viewed tracking",
+            label_key: "web.ws.feature.viewed",
+            enabled: flags.enable_viewed,
+        },
+        WorkspaceFeatureStatus {
+            key: "enable_edit",
+            label: "Edit",
+            label_key: "web.ws.feature.edit",
+            enabled: flags.enable_edit,
+        },
+        WorkspaceFeatureStatus {
+            key: "enable_live",
+            label: "Live",
+            label_key: "web.ws.feature.live",
+            enabled: flags.enable_live,
+        },
+        WorkspaceFeatureStatus {
+            key: "enable_chat",
+            label: "AI Chat",
+            label_key: "web.ws.feature.chat",
+            enabled: flags.enable_chat,
+        },
+        WorkspaceFeatureStatus {
+            key: "shared_annotation",
+            label: "Shared notes",
+            label_key: "web.ws.feature.shared",
+            enabled: flags.shared_annotation,
+        },
+    ];
+    let git_commits = if git_status.available {
+        git::history(root, 6).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let git_commit_count = if git_status.available {
+        git::commit_count(root).unwrap_or(0)
+    } else {
+        0
+    };
+    // Detailed branches (adds `is_default`) so the switch-branch panel can flag
+    // the default branch; still carries `name`/`current` for checkout.
+    let git_branches = if git_status.available {
+        git::branches_detailed(root).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    let git_branch_count = if git_status.available {
+        git_branches.len()
+    } else {
+        0
+    };
+    let git_tag_count = if git_status.available {
+        git::tag_count(root).unwrap_or(0)
+    } else {
+        0
+    };
+    let git_changed_count = git_status.added
+        + git_status.modified
+        + git_status.deleted
+        + git_status.renamed
+        + git_status.untracked;
+    let work_diff_has_markdown_changes = git_status.available
+        && git::diff_has_markdown_changes(root, &ws.fs.policy().markdown, "HEAD", "worktree")
+            .unwrap_or(false);
+    let work_diff_url = work_diff_has_markdown_changes
+        .then(|| markdown_work_diff_page_url(&state.base_path, workspace_id));
+    let latest_commit = git_commits.first().cloned();
+    let latest_commit_diff_url = latest_commit.as_ref().and_then(|commit| {
+        git_commit_markdown_diff_url(
+            &state.base_path,
+            root,
+            &ws.fs.policy().markdown,
+            workspace_id,
+            commit,
+            "rendered",
+        )
+    });
+    let is_workspace_root = current_dir == root;
+    let can_add_file = can_manage && flags.enable_edit && !file_set;
+    // Quick access is per browser, so it needs the client cookie; a browser
+    // without one has viewed nothing yet.
+    let (pinned_files, recent_files) = match (client_id, state.db.as_ref()) {
+        (Some(client_id), Some(db)) if is_workspace_root && !file_set => {
+            quick_access_entries(&state.base_path, client_id, workspace_id, ws, root, db)
+        }
+        _ => (Vec::new(), Vec::new()),
+    };
+
+    let mut context = base_context(state);
+    context.insert("workspace_id", workspace_id);
+    context.insert("workspace_alias", &ws.alias());
+    context.insert(
+        "workspace_name",
+        &root
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    );
+    context.insert("can_manage", &can_manage);
+    context.insert("shared_annotation", &flags.shared_annotation);
+    context.insert("current_dir", &current_dir.display().to_string());
+    context.insert(
+        "history_url",
+        &workspace_git_history_url(&state.base_path, workspace_id),
+    );
+    context.insert("work_diff_url", &work_diff_url);
+    context.insert("latest_commit", &latest_commit);
+    context.insert("latest_commit_diff_url", &latest_commit_diff_url);
+    context.insert("git_changed_count", &git_changed_count);
+    context.insert("git_commit_count", &git_commit_count);
+    context.insert("git_branch_count", &git_branch_count);
+    context.insert("git_tag_count", &git_tag_count);
+    context.insert("git_branches", &git_branches);
+    context.insert("git_commits", &git_commits);
+    context.insert("feature_statuses", &feature_statuses);
+    context.insert("git", &git_status);
+    context.insert("is_workspace_root", &is_workspace_root);
+    context.insert("can_add_file", &can_add_file);
+    context.insert("pinned_files", &pinned_files);
+    context.insert("recent_files", &recent_files);
+    context.insert(
+        "pins_url",
+        &state
+            .db
+            .as_ref()
+            .map(|_| workspace_pins_url(&state.base_path, workspace_id)),
+    );
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert(
+        "branches_url",
+        &workspace_git_branches_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "tags_url",
+        &workspace_git_tags_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "checkout_url",
+        &workspace_git_checkout_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "files_data_url",
+        &workspace_files_data_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "files_dir_url",
+        &workspace_files_dir_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "settings_features_url",
+        &workspace_settings_features_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "create_file_url",
+        &workspace_file_create_url(&state.base_path, workspace_id),
+    );
+    context.insert(
+        "create_folder_url",
+        &workspace_folder_create_url(&state.base_path, workspace_id),
+    );
+    context.insert("entries", &entries);
+    context.insert("show_parent", &show_parent);
+    context.insert("parent_link", &parent_link);
+    context.insert("breadcrumb", &breadcrumb);
+    context.insert("readme", &readme);
+    context.insert("sort_key", &view.sort.key);
+    context.insert("sort_descending", &view.sort.descending);
+    context.insert("show_ignored", &view.show_ignored);
+    context.insert("page", &page);
+    context.insert("page_count", &page_count);
+    context.insert("total_entries", &total_entries);
+    context.insert(
+        "prev_page_url",
+        &(page > 1).then(|| view.page_query(page - 1)),
+    );
+    context.insert(
+        "next_page_url",
+        &(page < page_count).then(|| view.page_query(page + 1)),
+    );
+    context.insert("enable_search", &flags.enable_search);
+    context.insert("enable_live", &flags.enable_live);
+    context.insert("enable_chat", &flags.enable_chat);
+
+    render_template(state, "directory.html", &context)
+}
+
+/// A directory's README rendered for display under its file table.
+#[derive(serde::Serialize)]
+struct DirectoryReadme {
+    name: String,
+    link: String,
+    html: String,
+}
+
+/// Render the first of `state.readme_names` present among `entries` (file
+/// names compare case-insensitively, like GitHub's `readme.md`). Goes through
+/// the render cache, so opening the README afterwards is a cache hit.
+fn directory_readme(
+    entries: &[DirListingEntry],
+    current_dir: &FsPath,
+    workspace_id: &str,
+    ws: &WorkspaceEntry,
+    root: &FsPath,
+    state: &AppState,
+) -> Option<DirectoryReadme> {
+    let entry = state.readme_names.iter().find_map(|name| {
+        entries
+            .iter()
+            .find(|entry| !entry.is_dir && entry.name.eq_ignore_ascii_case(name))
+    })?;
+    let file_path = current_dir.join(&entry.name);
+    let markdown = fs::read_to_string(&file_path).ok()?;
+    let file_path = file_path.to_string_lossy();
+    let rendered = render_markdown_cached(&markdown, &file_path, workspace_id, ws, root, state);
+    Some(DirectoryReadme {
+        name: entry.name.clone(),
+        link: entry.link.clone(),
+        html: rendered.html.clone(),
+    })
+}
+
+async fn serve_favicon(State(state): State<AppState>) -> impl IntoResponse {
+    // Redirect /_/favicon.ico to /_/favicon.svg
+    (
+        StatusCode::MOVED_PERMANENTLY,
+        [(
+            header::LOCATION,
+            format!("{}/_/favicon.svg", state.base_path),
+        )],
+    )
+        .into_response()
+}
+
+async fn serve_favicon_svg() -> impl IntoResponse {
+    serve_static_file("favicon.svg", IconAssets::get, "image/svg+xml")
+}
+
+#[derive(Deserialize)]
+struct QrQuery {
+    path: Option<String>,
+}
+
+/// `/_/qr.svg[?path=/…]`: a QR code for the server's shareable URL, or for a
+/// path under it, to put on a slide or in a page.
+async fn serve_qr_svg(State(state): State<AppState>, Query(query): Query<QrQuery>) -> Response {
+    let Some(base) = state.share_base.get() else {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server address not known yet",
+        )
+            .into_response();
+    };
+    let root = format!("{}/", state.base_path);
+    let path = query.path.as_deref().unwrap_or(&root);
+    if !path.starts_with('/') || path.starts_with("//") {
+        return (StatusCode::BAD_REQUEST, "path must start with a single /").into_response();
+    }
+    let url = with_share_token(
+        &build_workspace_url(base, path),
+        state.share_token.as_deref().map(String::as_str),
+    );
+    match qr_svg(&url) {
+        Ok(svg) => (
+            [
+                (header::CONTENT_TYPE, "image/svg+xml"),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            svg,
+        )
+            .into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            format!("Cannot encode QR code: {e}"),
+        )
+            .into_response(),
+    }
+}
+
+async fn serve_css(AxumPath(filename): AxumPath<String>) -> impl IntoResponse {
+    serve_static_file(&filename, CssAssets::get, "text/css")
+}
+
+async fn serve_js(AxumPath(path): AxumPath<String>) -> impl IntoResponse {
+    let content_type = mime_guess::from_path(&path)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string();
+    serve_static_file(&path, JsAssets::get, &content_type)
+}
+
+/// Serve an embedded asset by its plain name, or by the fingerprinted name
+/// templates link (see [`crate::assets::fingerprinted_url`]). Only a name
+/// whose hash matches the embedded content is cached as immutable; a stale
+/// hash still gets the current file, revalidated on every load.
+fn serve_static_file<F>(filename: &str, getter: F, content_type: &str) -> Response
+where
+    F: Fn(&str) -> Option<rust_embed::EmbeddedFile>,
+{
+    // `file.data` is Cow::Borrowed in release builds; serving the Cow
+    // directly avoids copying the embedded asset on every request.
+    if let Some(file) = getter(filename) {
+        return (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, content_type)],
+            file.data,
+        )
+            .into_response();
+    }
+    let Some((plain, hash)) = crate::assets::strip_fingerprint(filename) else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+    match getter(&plain) {
+        Some(file) => {
+            let cache_control = if crate::assets::fingerprint(&file) == hash {
+                crate::assets::IMMUTABLE_CACHE_CONTROL
+            } else {
+                "no-cache"
+            };
+            (
+                StatusCode::OK,
+                [
+                    (header::CONTENT_TYPE, content_type),
+                    (header::CACHE_CONTROL, cache_control),
+                ],
+                file.data,
+            )
+                .into_response()
+        }
+        None => (StatusCode::NOT_FOUND, "File not found").into_response(),
+    }
+}
+
+/// A `--custom-css` stylesheet or `--custom-js` script. Files are read on
+/// every request, so edits show up on the next page load.
+#[derive(Clone, Debug, Serialize)]
+pub struct UserAsset {
+    href: String,
+    #[serde(skip)]
+    file: Option<(String, PathBuf)>,
+}
+
+/// Resolve `--custom-css` / `--custom-js` entries: files are served under
+/// `route`; `http(s)://` URLs are linked as given where `allow_urls`.
+fn user_assets(
+    entries: &[String],
+    route: &str,
+    allow_urls: bool,
+) -> Result<Vec<UserAsset>, String> {
+    entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            if allow_urls && asset_url_origin(entry).is_some() {
+                return Ok(UserAsset {
+                    href: entry.clone(),
+                    file: None,
+                });
+            }
+            if entry.contains("://") {
+                return Err(if allow_urls {
+                    format!("custom asset URL '{entry}' must be http(s)")
+                } else {
+                    format!("custom asset '{entry}' must be a local file")
+                });
+            }
+            let path = PathBuf::from(entry);
+            let file_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| format!("custom asset '{entry}' is not a file"))?;
+            // The index keeps two files with the same name apart.
+            let name = format!("{index}-{file_name}");
+            Ok(UserAsset {
+                href: format!("{route}/{}", urlencoding::encode(&name)),
+                file: Some((name, path)),
+            })
+        })
+        .collect()
+}
+
+/// `scheme://host[:port]` of an http(s) asset URL, for the CSP.
+fn asset_url_origin(url: &str) -> Option<String> {
+    let (scheme, rest) = url
+        .strip_prefix("https://")
+        .map(|rest| ("https", rest))
+        .or_else(|| url.strip_prefix("http://").map(|rest| ("http", rest)))?;
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let valid = !authority.is_empty()
+        && authority
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':' | '[' | ']'));
+    valid.then(|| format!("{scheme}://{authority}"))
+}
+
+fn user_asset_origins(assets: &[UserAsset]) -> Vec<String> {
+    let mut origins: Vec<String> = assets
+        .iter()
+        .filter_map(|asset| asset_url_origin(&asset.href))
+        .collect();
+    origins.sort();
+    origins.dedup();
+    origins
+}
+
+async fn serve_user_css(
+    State(state): State<AppState>,
+    AxumPath(filename): AxumPath<String>,
+) -> Response {
+    serve_user_asset(&state.user_stylesheets, &filename, "text/css").await
+}
+
+async fn serve_user_js(
+    State(state): State<AppState>,
+    AxumPath(filename): AxumPath<String>,
+) -> Response {
+    serve_user_asset(&state.user_scripts, &filename, "text/javascript").await
+}
+
+async fn serve_user_asset(assets: &[UserAsset], filename: &str, content_type: &str) -> Response {
+    let path = assets
+        .iter()
+        .filter_map(|asset| asset.file.as_ref())
+        .find(|(name, _)| name == filename)
+        .map(|(_, path)| path.clone());
+    let Some(path) = path else {
+        return (StatusCode::NOT_FOUND, "File not found").into_response();
+    };
+    match tokio::fs::read(&path).await {
+        Ok(body) => (
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, content_type),
+                (header::CACHE_CONTROL, "no-cache"),
+            ],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::warn!("cannot read custom asset {}: {e}", path.display());
+            (StatusCode::NOT_FOUND, "File not found").into_response()
+        }
+    }
+}
+
+/// Serve a raw (non-markdown) workspace file. Delegates to `tower_http`'s
+/// `ServeFile`, which streams the body from async I/O instead of reading the
+/// whole file into memory, and honors `Range` (206) / conditional requests. The
+/// caller's relevant request headers are forwarded so those features work;
+/// `ServeFile` serves the fixed `path` regardless of the request URI. `path`
+/// is already canonicalized and confinement-checked by the caller.
+async fn serve_file(path: &std::path::Path, req_headers: &axum::http::HeaderMap) -> Response {
+    use tower::ServiceExt;
+    let mut req = axum::http::Request::new(axum::body::Body::empty());
+    for name in [
+        header::RANGE,
+        header::IF_RANGE,
+        header::IF_MODIFIED_SINCE,
+        header::IF_NONE_MATCH,
+        header::ACCEPT_ENCODING,
+    ] {
+        if let Some(value) = req_headers.get(&name) {
+            req.headers_mut().insert(name, value.clone());
+        }
+    }
+    match tower_http::services::ServeFile::new(path)
+        .oneshot(req)
+        .await
+    {
+        Ok(resp) => resp.map(axum::body::Body::new).into_response(),
+        // ServeFile's error type is `Infallible`; it reports IO problems as an
+        // error status in the response body, so this arm is effectively dead.
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Error reading file").into_response(),
+    }
+}
+
+// ── File editing API ──────────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct SaveFileRequest {
+    workspace_id: String,
+    file_path: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct SaveFileResponse {
+    success: bool,
+    message: String,
+}
+
+/// Write `content` to `target` atomically: create a uniquely-named temp file in
+/// the SAME directory, write + flush it, then `rename` it over the target. A
+/// crash mid-write can therefore never leave a truncated document — either the
+/// old file or the fully-written new file is visible. The temp file is removed
+/// on any error. The unique name is derived from the process id plus a static
+/// counter to avoid collisions between concurrent saves.
+fn atomic_write(target: &FsPath, content: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let dir = target.parent().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "target has no parent directory",
+        )
+    })?;
+    let base = target
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let tmp_path = dir.join(format!(".{base}.{}.{n}.tmp", std::process::id()));
+
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    // The temp file now exists and is exclusively ours, so any later failure is
+    // safe to clean up.
+    if let Err(e) = file.write_all(content).and_then(|()| file.sync_all()) {
+        drop(file);
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    drop(file);
+    // Preserve the destination's existing permission bits: `rename` swaps in the
+    // fresh temp inode, which would otherwise reset an already-existing file's
+    // mode to the umask default. Best-effort and Unix-only; the crash-safety of
+    // the write does not depend on it succeeding.
+    #[cfg(unix)]
+    if let Ok(meta) = std::fs::metadata(target) {
+        let _ = std::fs::set_permissions(&tmp_path, meta.permissions());
+    }
+    match std::fs::rename(&tmp_path, target) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+async fn save_file_handler(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(payload): Json<SaveFileRequest>,
+) -> impl IntoResponse {
+    let scoped_token = workspace_save_token(&state.save_token, &payload.workspace_id);
+    if !request_token_matches(&headers, &scoped_token, &state.management_token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let ws = match state.workspace_registry.get(&payload.workspace_id) {
+        Some(w) => w,
+        None => {
+            return Json(SaveFileResponse {
+                success: false,
+                message: "Workspace not found".into(),
+            })
+            .into_response()
+        }
+    };
+
+    // Authorization is enforced by the origin middleware, the workspace-bound
+    // token above, and the per-workspace edit flag below.
+    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
+        return Json(SaveFileResponse {
+            success: false,
+            message: "Edit feature is not enabled".into(),
+        })
+        .into_response();
+    }
+
+    let decoded = match urlencoding::decode(&payload.file_path) {
+        Ok(p) => p,
+        Err(_) => {
+            return Json(SaveFileResponse {
+                success: false,
+                message: "Invalid file path encoding".into(),
+            })
+            .into_response()
+        }
+    };
+
+    let decoded_path = std::path::Path::new(decoded.as_ref());
+    let canonical = match ws.fs.resolve_editable_input(decoded_path) {
+        Ok(path) => path,
+        Err(
+            crate::workspace_fs::WorkspaceFsError::InvalidPath
+            | crate::workspace_fs::WorkspaceFsError::Denied,
+        ) => {
+            return Json(SaveFileResponse {
+                success: false,
+                message: "Access denied".into(),
+            })
+            .into_response()
+        }
+        Err(
+            crate::workspace_fs::WorkspaceFsError::NotFound
+            | crate::workspace_fs::WorkspaceFsError::Io(_),
+        ) => {
+            return Json(SaveFileResponse {
+                success: false,
+                message: format!("File not found: {decoded}"),
+            })
+            .into_response()
+        }
+    };
+
+    if !canonical.is_file() {
+        return Json(SaveFileResponse {
+            success: false,
+            message: "Path is not a file".into(),
+        })
+        .into_response();
+    }
+    if !ws.fs.policy().is_markdown(&canonical) {
+        return Json(SaveFileResponse {
+            success: false,
+            message: "Only .md files can be edited".into(),
+        })
+        .into_response();
+    }
+    // Perform the atomic write on the blocking pool so file I/O (open, write,
+    // fsync, rename) does not stall a tokio worker thread.
+    let content = payload.content;
+    let write_result =
+        tokio::task::spawn_blocking(move || atomic_write(&canonical, content.as_bytes())).await;
+    match write_result {
+        Ok(Ok(())) => Json(SaveFileResponse {
+            success: true,
+            message: "File saved successfully".into(),
+        })
+        .into_response(),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::PermissionDenied => Json(SaveFileResponse {
+            success: false,
+            message: "File is read-only".into(),
+        })
+        .into_response(),
+        Ok(Err(e)) => Json(SaveFileResponse {
+            success: false,
+            message: format!("Failed to save: {e}"),
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!("save_file_handler blocking task join error: {e}");
+            Json(SaveFileResponse {
+                success: false,
+                message: "Failed to save: internal error".into(),
+            })
+            .into_response()
+        }
+    }
+}
+
+// ── Markdown preview API ──────────────────────────────────────────────────────
+
+#[derive(Deserialize)]
+struct PreviewRequest {
+    workspace_id: String,
+    content: String,
+}
+
+#[derive(Serialize)]
+struct PreviewResponse {
+    html: String,
+    has_mermaid: bool,
+    has_math: bool,
+}
+
+async fn preview_handler(
+    State(state): State<AppState>,
+    headers: axum::http::HeaderMap,
+    Json(payload): Json<PreviewRequest>,
+) -> impl IntoResponse {
+    let scoped_token = workspace_preview_token(&state.save_token, &payload.workspace_id);
+    if !request_token_matches(&headers, &scoped_token, &state.management_token) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    // Markdown rendering (syntect highlight + AST walk) is CPU-bound; run it on
+    // the blocking pool so a large document can't stall a runtime worker.
+    let theme = state.theme.clone();
+    let render_hooks = state.render_hooks.clone();
+    let content = payload.content;
+    let rendered = match tokio::task::spawn_blocking(move || {
+        let renderer = default_markdown_engine(&theme, &render_hooks);
+        MarkdownEngine::render(&renderer, &content)
+    })
+    .await
+    {
+        Ok(rendered) => rendered,
+        Err(e) => {
+            tracing::error!(error = %e, "preview render task failed");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    Json(PreviewResponse {
+        html: rendered.html,
+        has_mermaid: rendered.has_mermaid,
+        has_math: rendered.has_math,
+    })
+    .into_response()
+}
+
+// ── Editor-plugin protocol ────────────────────────────────────────────────────
+
+/// Derive the editor-plugin capability for exactly one workspace, handed out
+/// over the control socket by `markon editor`. Domain-separated from the
+/// browser capabilities so a page's embedded token can't drive a plugin
+/// session, nor the other way round.
+fn workspace_editor_token(secret: &str, workspace_id: &str) -> String {
+    admin_auth::auth_tag(secret, b"markon-editor-workspace\0", workspace_id)
+}
+
+/// Base path of a workspace's editor-plugin endpoints.
+fn editor_api_url(base_path: &str, workspace_id: &str) -> String {
+    format!(
+        "{base_path}/api/editor/{}",
+        urlencoding::encode(workspace_id)
+    )
+}
+
+#[derive(Deserialize, Default)]
+struct EditorQuery {
+    buffer: Option<String>,
+    /// For clients that can't set `X-Markon-Token` (EventSource, a tab).
+    token: Option<String>,
+}
+
+/// The workspace an editor request targets, once its token checks out.
+fn editor_workspace(
+    state: &AppState,
+    workspace_id: &str,
+    headers: &axum::http::HeaderMap,
+    query_token: Option<&str>,
+) -> Result<Arc<WorkspaceEntry>, StatusCode> {
+    let scoped_token = workspace_editor_token(&state.save_token, workspace_id);
+    let query_matches = query_token.is_some_and(|token| {
+        ct_eq(token.as_bytes(), scoped_token.as_bytes())
+            || ct_eq(token.as_bytes(), state.management_token.as_bytes())
+    });
+    if !query_matches && !request_token_matches(headers, &scoped_token, &state.management_token) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    state
+        .workspace_registry
+        .get(workspace_id)
+        .ok_or(StatusCode::NOT_FOUND)
+}
+
+/// `POST /api/editor/{workspace_id}/rpc` — one JSON-RPC 2.0 call.
+async fn handle_editor_rpc(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    headers: axum::http::HeaderMap,
+    body: axum::body::Bytes,
+) -> Response {
+    let ws = match editor_workspace(&state, &workspace_id, &headers, None) {
+        Ok(ws) => ws,
+        Err(status) => return status.into_response(),
+    };
+    let request = match crate::editor::parse_request(&body) {
+        Ok(request) => request,
+        Err(response) => return Json(response).into_response(),
+    };
+    let id = request.id.clone();
+    let outcome = dispatch_editor_rpc(&state, &workspace_id, ws, request).await;
+    match id {
+        Some(id) => Json(crate::editor::RpcResponse::new(id, outcome)).into_response(),
+        None => StatusCode::NO_CONTENT.into_response(),
+    }
+}
+
+async fn dispatch_editor_rpc(
+    state: &AppState,
+    workspace_id: &str,
+    ws: Arc<WorkspaceEntry>,
+    request: crate::editor::RpcRequest,
+) -> Result<serde_json::Value, crate::editor::RpcError> {
+    use crate::editor::{self, RpcError};
+    fn to_value<T: Serialize>(value: &T) -> Result<serde_json::Value, RpcError> {
+        serde_json::to_value(value).map_err(RpcError::internal)
+    }
+    match request.method.as_str() {
+        "initialize" => {
+            let base = editor_api_url(&state.base_path, workspace_id);
+            Ok(serde_json::json!({
+                "name": "markon",
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocol": editor::PROTOCOL_VERSION,
+                "methods": editor::METHODS,
+                "events_url": format!("{base}/events"),
+                "preview_url": format!("{base}/preview"),
+            }))
+        }
+        "render" => {
+            let params: editor::RenderParams = editor::params(request.params)?;
+            let buffer =
+                render_editor_buffer(state, workspace_id, ws, params.content, params.path).await?;
+            to_value(buffer.rendered())
+        }
+        "buffer/update" => {
+            let params: editor::UpdateParams = editor::params(request.params)?;
+            let buffer =
+                render_editor_buffer(state, workspace_id, ws, params.content, params.path).await?;
+            let rendered = to_value(buffer.rendered());
+            state.editor.update(workspace_id, &params.buffer, buffer);
+            rendered
+        }
+        "buffer/scroll" => {
+            let params: editor::ScrollParams = editor::params(request.params)?;
+            let target = state
+                .editor
+                .scroll(workspace_id, &params.buffer, params.line)?;
+            to_value(&target)
+        }
+        "buffer/close" => {
+            let params: editor::CloseParams = editor::params(request.params)?;
+            Ok(serde_json::Value::Bool(
+                state.editor.close(workspace_id, &params.buffer),
+            ))
+        }
+        method => Err(RpcError::method_not_found(method)),
+    }
+}
+
+/// Render a pushed buffer on the blocking pool. A `path` resolves relative
+/// images and links as if the buffer were saved there; it must stay inside
+/// the workspace, but needn't exist yet.
+async fn render_editor_buffer(
+    state: &AppState,
+    workspace_id: &str,
+    ws: Arc<WorkspaceEntry>,
+    content: String,
+    path: Option<String>,
+) -> Result<crate::editor::Buffer, crate::editor::RpcError> {
+    use crate::editor::{Buffer, Rendered, RpcError};
+    let path = path.map(|path| path.replace('\\', "/"));
+    if let Some(path) = &path {
+        let inside = FsPath::new(path)
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if path.is_empty() || !inside {
+            return Err(RpcError::invalid_params(
+                "path must be a workspace-relative file path",
+            ));
+        }
+    }
+    let theme = state.theme.clone();
+    let render_hooks = state.render_hooks.clone();
+    let workspace_url = workspace_root_url(&state.base_path, workspace_id);
+    tokio::task::spawn_blocking(move || {
+        let mut renderer = default_markdown_engine(&theme, &render_hooks);
+        if let Some(path) = &path {
+            let root = canonical_workspace_root(&ws);
+            let file_path = root.join(path).to_string_lossy().into_owned();
+            renderer = renderer.with_asset_context(&workspace_url, &file_path, &root);
+        }
+        let rendered = MarkdownEngine::render(&renderer, &content);
+        Buffer::new(
+            &content,
+            path,
+            Rendered {
+                html: rendered.html,
+                toc: rendered.toc,
+                has_mermaid: rendered.has_mermaid,
+                has_math: rendered.has_math,
+            },
+        )
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!(error = %e, "editor render task failed");
+        RpcError::internal("render task failed")
+    })
+}
+
+/// `GET /api/editor/{workspace_id}/events` — the workspace's editor events as
+/// Server-Sent Events, starting with the current render.
+async fn handle_editor_events(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(query): Query<EditorQuery>,
+    headers: axum::http::HeaderMap,
+) -> Response {
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use std::convert::Infallible;
+    if let Err(status) = editor_workspace(&state, &workspace_id, &headers, query.token.as_deref()) {
+        return status.into_response();
+    }
+    let buffer = query.buffer.filter(|buffer| !buffer.is_empty());
+    let (current, rx) = state.editor.subscribe(&workspace_id, buffer.as_deref());
+    let to_sse = |event: crate::editor::EditorEvent| {
+        Event::default().event(event.name()).json_data(&event).ok()
+    };
+    let current = futures::stream::iter(current.and_then(to_sse));
+    // A lagging subscriber skips the events it missed; the next render
+    // carries the whole document anyway.
+    let updates = tokio_stream::wrappers::BroadcastStream::new(rx).filter_map(move |item| {
+        let event = item
+            .ok()
+            .filter(|event| buffer.as_deref().is_none_or(|name| event.buffer() == name));
+        async move { event.and_then(to_sse) }
+    });
+    Sse::new(current.chain(updates).map(Ok::<Event, Infallible>))
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// `GET /api/editor/{workspace_id}/preview` — a page following the editor
+/// events, for plugins without a webview: it re-renders on every `render`
+/// and scrolls along with the cursor.
+async fn handle_editor_preview(
+    State(state): State<AppState>,
+    AxumPath(workspace_id): AxumPath<String>,
+    Query(query): Query<EditorQuery>,
+    headers: axum::http::HeaderMap,
+) -> Response {
+    let token = query.token.as_deref();
+    if let Err(status) = editor_workspace(&state, &workspace_id, &headers, token) {
+        return status.into_response();
+    }
+    let mut events_url = format!("{}/events", editor_api_url(&state.base_path, &workspace_id));
+    let mut params = Vec::new();
+    if let Some(buffer) = query.buffer.as_deref().filter(|buffer| !buffer.is_empty()) {
+        params.push(format!("buffer={}", urlencoding::encode(buffer)));
+    }
+    if let Some(token) = token {
+        params.push(format!("token={}", urlencoding::encode(token)));
+    }
+    if !params.is_empty() {
+        events_url = format!("{events_url}?{}", params.join("&"));
+    }
+    let mut context = base_context(&state);
+    context.insert("title", "markon - editor preview");
+    context.insert("version", env!("CARGO_PKG_VERSION"));
+    context.insert("events_url", &events_url);
+    let mut response = render_template(&state, "editor-preview.html", &context);
+    // The editor capability vouches for this tab: let it load the page's
+    // scripts and styles past `--share-token`.
+    if let Some(share_token) = state.share_token.as_deref() {
+        if let Ok(cookie) =
+            axum::http::HeaderValue::from_str(&share_cookie(&state, &headers, share_token))
+        {
+            response
+                .headers_mut()
+                .append(axum::http::header::SET_COOKIE, cookie);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotations::upsert_annotation_for_file;
+    use crate::assets::fingerprinted_url;
+    use axum::body::to_bytes;
+    use rusqlite::params;
+    use serde_json::json;
+
+    use axum::http::HeaderMap;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use tower::ServiceExt;
+
+    fn test_tera() -> Tera {
+        let mut tera = Tera::default();
+        crate::assets::register_filters(&mut tera);
+        for file_name in Templates::iter() {
+            let file = Templates::get(&file_name).expect("embedded template");
+            let content = std::str::from_utf8(&file.data).expect("utf-8 template");
+            tera.add_raw_template(&file_name, content)
+                .expect("template registration");
+        }
+        tera
+    }
+
+    fn test_state(registry: Arc<WorkspaceRegistry>) -> AppState {
+        AppState {
+            theme: Arc::new("light".into()),
+            custom_theme: false,
+            tera: Arc::new(test_tera()),
+            base_path: "".into(),
+            db: None,
+            workspace_registry: registry,
+            management_token: Arc::new("test-token".into()),
+            admin_bootstraps: Arc::new(AdminBootstrapStore::new()),
+            allowed_hosts: Arc::new(build_allowed_hosts("127.0.0.1", "", 6419, &[], &[])),
+            save_token: Arc::new("save-token".into()),
+            i18n_json: Arc::new(i18n::load_i18n()),
+            i18n_lang: Arc::new("en".into()),
+            shortcuts_json: Arc::new("null".into()),
+            styles_css: Arc::new("".into()),
+            user_stylesheets: Arc::new(Vec::new()),
+            user_scripts: Arc::new(Vec::new()),
+            default_chat_mode: Arc::new("in_page".into()),
+            collaborator_access_code_hash: Arc::new(String::new()),
+            access_secret: Arc::new("test-salt".into()),
+            access_attempts: Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
+            markdown_diff_cache: Arc::new(Mutex::new(MarkdownDiffCache::default())),
+            archive_index: Arc::new(Mutex::new(crate::archive::IndexCache::default())),
+            render_hooks: Arc::new([]),
+            print_collapsed_content: false,
+            render_cache: true,
+            search_max_limit: MAX_SEARCH_LIMIT,
+            require_name: false,
+            annotation_token: None,
+            readme_names: Arc::new(vec!["README.md".into(), "index.md".into()]),
+            nav_files: Arc::new(vec!["SUMMARY.md".into()]),
+            listing_page_size: DEFAULT_LISTING_PAGE_SIZE,
+            paginate_bytes: None,
+            share_base: Arc::default(),
+            remote_markdown: Arc::new(RemoteMarkdown::new(RemoteAllowlist::default())),
+            attachments_dir: None,
+            remote_store: None,
+            presence: Arc::default(),
+            editor: Arc::default(),
+            pandoc: None,
+            share_token: None,
+            audit: false,
+            #[cfg(debug_assertions)]
+            dev_reload_tx: Arc::new(broadcast::channel::<()>(1).0),
+        }
+    }
+
+    fn add_test_workspace(
+        registry: &WorkspaceRegistry,
+        root: PathBuf,
+        flags: WorkspaceFlags,
+    ) -> String {
+        registry.add(WorkspaceConfig {
+            path: dunce::canonicalize(root).expect("canonical workspace root"),
+            flags,
+            single_file: None,
+            collaborator_access_code_hash: String::new(),
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn management_add_preserves_single_file_capability_and_alias() {
+        // Management moved off the TCP surface onto the control socket, so this
+        // exercises the socket dispatch. The single-file capability confinement
+        // (expose the one file, hide siblings) and the reject-multi-component
+        // guard are the same guarantees the old HTTP handler enforced.
+        use crate::control::proto::{ControlRequest, ControlResponse};
+        use crate::control::transport::{dispatch, ControlContext};
+
+        let root = tempfile::tempdir().unwrap();
+        std::fs::write(root.path().join("note.md"), "# note").unwrap();
+        std::fs::write(root.path().join("secret.md"), "secret").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("single-file-api".into()));
+        let ctx = ControlContext {
+            registry: registry.clone(),
+            db: None,
+            shutdown: None,
+            admin_bootstrap: None,
+            admin_bootstrap_code: None,
+            editor_session: None,
+        };
+        let add = |single_file: Option<&str>| {
+            dispatch(
+                ControlRequest::AddWorkspace {
+                    path: root.path().to_string_lossy().into_owned(),
+                    flags: WorkspaceFlags::default(),
+                    collaborator_access_code_hash: String::new(),
+                    single_file: single_file.map(str::to_string),
+                    files: Vec::new(),
+                    alias: String::new(),
+                },
+                &ctx,
+            )
+        };
+
+        let id = match add(Some("note.md")) {
+            ControlResponse::WorkspaceId(id) => id,
+            other => panic!("expected WorkspaceId, got {other:?}"),
+        };
+        assert!(matches!(
+            dispatch(
+                ControlRequest::SetAlias {
+                    id: id.clone(),
+                    alias: "Pinned note".into(),
+                },
+                &ctx,
+            ),
+            ControlResponse::Ok
+        ));
+
+        let info = registry
+            .info_list()
+            .into_iter()
+            .find(|info| info.id == id)
+            .unwrap();
+        assert!(info.ephemeral);
+        assert_eq!(info.single_file.as_deref(), Some("note.md"));
+        assert_eq!(info.alias, "Pinned note");
+        let entry = registry.get(&id).unwrap();
+        assert!(entry.fs.resolve_served("note.md").is_ok());
+        assert!(entry.fs.resolve_served("secret.md").is_err());
+
+        // A multi-component single_file is rejected and leaves the registry as-is.
+        assert!(matches!(add(Some("../secret.md")), ControlResponse::Err(_)));
+        assert_eq!(registry.info_list().len(), 1);
+    }
+
+    async fn spawn_collaboration_test_server(
+        state: AppState,
+    ) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let app = Router::new()
+            .route(WORKSPACE_WS_ROUTE, get(ws_handler))
+            .fallback(|| async { StatusCode::NOT_FOUND })
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_access_code,
+            ))
+            .with_state(state);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let _ = axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .await;
+        });
+        (addr, task)
+    }
+
+    fn collaborator_access_scope_for(state: &AppState, id: &str) -> Option<(String, String)> {
+        access_requirements_for(state, id)
+            .into_iter()
+            .find(|req| req.role == AccessRole::Collaborator)
+            .map(|req| (req.hash, req.scope))
+    }
+
+    /// Repro for the reported "only the global code lets me into a workspace
+    /// that has its own code": the workspace collaborator code must override the
+    /// global one both live AND after a restart reseed (same id, code preserved).
+    #[test]
+    fn workspace_code_overrides_global_and_survives_reseed() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = dunce::canonicalize(tmp.path()).unwrap();
+        let salt = "test-salt";
+        let ws_hash = crate::workspace::hash_access_code(salt, "wsCode");
+        let global_hash = crate::workspace::hash_access_code(salt, "global");
+
+        let reg = Arc::new(WorkspaceRegistry::new(salt.into()));
+        let id = reg.add(WorkspaceConfig {
+            path: root.clone(),
+            flags: WorkspaceFlags::default(),
+            single_file: None,
+            collaborator_access_code_hash: String::new(),
+            ..Default::default()
+        });
+        assert!(reg.set_collaborator_access_code(&id, &ws_hash));
+
+        let mut state = test_state(reg.clone());
+        state.collaborator_access_code_hash = Arc::new(global_hash.clone());
+        let (h, scope) = collaborator_access_scope_for(&state, &id).expect("workspace is gated");
+        assert_eq!(
+            scope,
+            format!("w:{id}:collaborator"),
+            "must use the workspace scope"
+        );
+        assert_eq!(h, ws_hash, "live: workspace code must win over global");
+
+        // Simulate a server restart: fresh registry reseeded from the persisted
+        // (path, collaborator_access_code_hash) must yield the SAME id and keep
+        // the code.
+        let reg2 = Arc::new(WorkspaceRegistry::new(salt.into()));
+        let id2 = reg2.add(WorkspaceConfig {
+            path: root,
+            flags: WorkspaceFlags::default(),
+            single_file: None,
+            collaborator_access_code_hash: ws_hash.clone(),
+            ..Default::default()
+        });
+        assert_eq!(id, id2, "workspace id must be stable across reseed");
+        assert_eq!(
+            reg2.get(&id2).unwrap().collaborator_access_code_hash(),
+            ws_hash,
+            "code must survive the reseed"
+        );
+        let mut state2 = test_state(reg2);
+        state2.collaborator_access_code_hash = Arc::new(global_hash);
+        let (h2, scope2) =
+            collaborator_access_scope_for(&state2, &id2).expect("gated after reseed");
+        assert_eq!(scope2, format!("w:{id2}:collaborator"));
+        assert_eq!(h2, ws_hash, "after restart: workspace code must STILL win");
+    }
+
+    /// Repro for "the correct workspace code shows no content": the unlock
+    /// cookie must round-trip a `w:{id}` scope. The scope itself contains a
+    /// colon, so a pair encodes as `w:{id}:{hash}`; decoding must split on the
+    /// LAST colon. With the old `split_once`, a workspace cookie decoded to
+    /// scope "w" and never matched its `w:{id}` gate — only the colon-free `s`
+    /// (global) scope worked, so entering the right workspace code looped back
+    /// to the gate while the global code got in.
+    #[test]
+    fn access_cookie_round_trips_workspace_scope() {
+        let secret = "test-salt";
+        let scopes = vec![
+            ("w:1a2b3c4d".to_string(), "4f965".to_string()),
+            ("s".to_string(), "abcde".to_string()),
+        ];
+        let cookie = make_access_cookie(secret, &scopes, access_now_unix() + 1000, false);
+        let back = access_cookie_scopes(secret, Some(&cookie));
+        assert!(
+            back.contains(&("w:1a2b3c4d".to_string(), "4f965".to_string())),
+            "workspace scope must survive the cookie round-trip: {back:?}"
+        );
+        assert!(back.contains(&("s".to_string(), "abcde".to_string())));
+    }
+
+    #[test]
+    fn access_cookie_resolves_collaborator_role() {
+        let tmp = tempfile::tempdir().unwrap();
+        let salt = "test-salt";
+        let collaborator_hash = crate::workspace::hash_access_code(salt, "guest-code");
+        let reg = Arc::new(WorkspaceRegistry::new(salt.into()));
+        let id = reg.add(WorkspaceConfig {
+            path: dunce::canonicalize(tmp.path()).unwrap(),
+            flags: WorkspaceFlags::default(),
+            single_file: None,
+            collaborator_access_code_hash: collaborator_hash.clone(),
+            ..Default::default()
+        });
+        let state = test_state(reg);
+
+        let collaborator_cookie = make_access_cookie(
+            salt,
+            &[(format!("w:{id}:collaborator"), collaborator_hash)],
+            access_now_unix() + 100,
+            false,
+        );
+        assert_eq!(
+            access_role_from_cookie(&state, &id, Some(&collaborator_cookie)),
+            Some(AccessRole::Collaborator)
+        );
+    }
+
+    async fn response_text(response: Response) -> String {
+        let bytes = response_bytes(response).await;
+        String::from_utf8(bytes.to_vec()).expect("utf-8 response")
+    }
+
+    async fn response_bytes(response: Response) -> axum::body::Bytes {
+        to_bytes(response.into_body(), usize::MAX)
+            .await
+            .expect("response body")
+    }
+
+    fn all_flags() -> WorkspaceFlags {
+        WorkspaceFlags {
+            enable_search: true,
+            enable_viewed: true,
+            enable_edit: true,
+            enable_live: true,
+            enable_chat: true,
+            shared_annotation: true,
+        }
+    }
+
+    fn headers_with(origin: Option<&str>, host: Option<&str>) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        if let Some(o) = origin {
+            h.insert("origin", o.parse().unwrap());
+        }
+        if let Some(host) = host {
+            h.insert("host", host.parse().unwrap());
+        }
+        h
+    }
+
+    fn save_headers(state: &AppState, workspace_id: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Markon-Token",
+            workspace_save_token(&state.save_token, workspace_id)
+                .parse()
+                .unwrap(),
+        );
+        headers
+    }
+
+    fn loopback() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1618)
+    }
+
+    #[tokio::test]
+    async fn serve_file_streams_with_length_and_honors_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clip.bin");
+        let payload: Vec<u8> = (0..=255u8).cycle().take(64 * 1024).collect();
+        fs::write(&path, &payload).unwrap();
+
+        let full = serve_file(&path, &HeaderMap::new()).await;
+        assert_eq!(full.status(), StatusCode::OK);
+        assert_eq!(
+            full.headers().get(header::CONTENT_LENGTH).unwrap(),
+            &payload.len().to_string()
+        );
+        assert_eq!(full.headers().get(header::ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(response_bytes(full).await.as_ref(), payload.as_slice());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::RANGE, "bytes=100-199".parse().unwrap());
+        let partial = serve_file(&path, &headers).await;
+        assert_eq!(partial.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            partial.headers().get(header::CONTENT_LENGTH).unwrap(),
+            "100"
+        );
+        assert_eq!(response_bytes(partial).await.as_ref(), &payload[100..200]);
+    }
+
+    #[tokio::test]
+    async fn qr_endpoint_and_image_file_encode_the_share_url() {
+        let state = test_state(Arc::new(WorkspaceRegistry::new("qr".into())));
+        let query = |path: Option<&str>| {
+            Query(QrQuery {
+                path: path.map(str::to_string),
+            })
+        };
+        let early = serve_qr_svg(State(state.clone()), query(None)).await;
+        assert_eq!(early.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        state
+            .share_base
+            .set("http://192.168.1.20:6419".to_string())
+            .unwrap();
+        let svg = serve_qr_svg(State(state.clone()), query(Some("/abc/notes.md"))).await;
+        assert_eq!(svg.status(), StatusCode::OK);
+        assert_eq!(
+            svg.headers().get(header::CONTENT_TYPE).unwrap(),
+            "image/svg+xml"
+        );
+        let body = response_bytes(svg).await;
+        assert!(std::str::from_utf8(&body).unwrap().contains("<svg"));
+        let offsite = serve_qr_svg(State(state), query(Some("//evil.example"))).await;
+        assert_eq!(offsite.status(), StatusCode::BAD_REQUEST);
+
+        let dir = tempfile::tempdir().unwrap();
+        let png = dir.path().join("qr.png");
+        write_qr_image("http://192.168.1.20:6419/", &png).unwrap();
+        assert!(fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+        let svg_file = dir.path().join("qr.svg");
+        write_qr_image("http://192.168.1.20:6419/", &svg_file).unwrap();
+        assert!(fs::read_to_string(&svg_file).unwrap().contains("<svg"));
+    }
+
+    #[tokio::test]
+    async fn headerless_not_found_is_browser_safe_and_bodyless() {
+        let app = Router::new()
+            .fallback(|| async { StatusCode::NOT_FOUND })
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(SecurityHeaders::default()),
+                security_headers,
+            ));
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/plain; charset=utf-8"
+        );
+        assert_eq!(
+            response
+                .headers()
+                .get(header::X_CONTENT_TYPE_OPTIONS)
+                .unwrap(),
+            "nosniff"
+        );
+        assert!(response
+            .headers()
+            .get(header::CONTENT_DISPOSITION)
+            .is_none());
+        assert!(response_bytes(response).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn not_found_preserves_an_explicit_content_type() {
+        let app = Router::new()
+            .fallback(|| async {
+                (
+                    StatusCode::NOT_FOUND,
+                    [(header::CONTENT_TYPE, "application/problem+json")],
+                    "{}",
+                )
+            })
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(SecurityHeaders::default()),
+                security_headers,
+            ));
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/missing")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/problem+json"
+        );
+        assert_eq!(response_text(response).await, "{}");
+    }
+
+    #[tokio::test]
+    async fn security_header_overrides_are_applied() {
+        let policy =
+            SecurityHeaders::from_overrides(Some("default-src 'none'"), Some("off"), &[]).unwrap();
+        let app =
+            Router::new()
+                .fallback(|| async { "ok" })
+                .layer(axum::middleware::from_fn_with_state(
+                    Arc::new(policy),
+                    security_headers,
+                ));
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(
+            response
+                .headers()
+                .get(header::CONTENT_SECURITY_POLICY)
+                .unwrap(),
+            "default-src 'none'"
+        );
+        assert!(response.headers().get(header::X_FRAME_OPTIONS).is_none());
+    }
+
+    #[tokio::test]
+    async fn frame_options_off_lets_the_default_csp_allow_framing() {
+        let policy = SecurityHeaders::from_overrides(None, Some("off"), &[]).unwrap();
+        let app =
+            Router::new()
+                .fallback(|| async { "ok" })
+                .layer(axum::middleware::from_fn_with_state(
+                    Arc::new(policy),
+                    security_headers,
+                ));
+
+        let response = app
+            .oneshot(
+                axum::http::Request::builder()
+                    .uri("/")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        let csp = response
+            .headers()
+            .get(header::CONTENT_SECURITY_POLICY)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(csp.starts_with("default-src 'self';"), "{csp}");
+        assert!(!csp.contains("frame-ancestors"), "{csp}");
+        assert!(response.headers().get(header::X_FRAME_OPTIONS).is_none());
+    }
+
+    #[test]
+    fn security_header_overrides_validate_input() {
+        let deny = SecurityHeaders::from_overrides(None, Some("DENY"), &[]).unwrap();
+        assert_eq!(deny.frame_options.as_ref().unwrap(), "DENY");
+        assert!(deny
+            .csp
+            .as_ref()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("frame-ancestors 'none'"));
+
+        let off = SecurityHeaders::from_overrides(Some("off"), None, &[]).unwrap();
+        assert!(off.csp.is_none());
+        assert_eq!(off.frame_options.as_ref().unwrap(), "SAMEORIGIN");
+
+        let framable = SecurityHeaders::from_overrides(None, Some("off"), &[]).unwrap();
+        assert!(framable.frame_options.is_none());
+        assert!(!framable
+            .csp
+            .as_ref()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("frame-ancestors"));
+
+        assert!(SecurityHeaders::from_overrides(None, Some("allow-all"), &[]).is_err());
+        assert!(SecurityHeaders::from_overrides(Some("default-src\n'self'"), None, &[]).is_err());
+    }
+
+    fn lan_peer() -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 50)), 51234)
+    }
+
+    #[test]
+    fn ws_origin_accepts_matching_authority() {
+        let h = headers_with(Some("http://192.168.1.10:1618"), Some("192.168.1.10:1618"));
+        assert!(check_ws_origin(&h, &lan_peer()));
+    }
+
+    #[test]
+    fn ws_origin_rejects_cross_origin() {
+        let h = headers_with(Some("http://evil.example.com"), Some("192.168.1.10:1618"));
+        assert!(!check_ws_origin(&h, &lan_peer()));
+    }
+
+    #[test]
+    fn ws_origin_rejects_port_mismatch() {
+        let h = headers_with(Some("http://127.0.0.1:9000"), Some("127.0.0.1:1618"));
+        assert!(!check_ws_origin(&h, &loopback()));
+    }
+
+    #[test]
+    fn ws_origin_rejects_null_origin() {
+        let h = headers_with(Some("null"), Some("127.0.0.1:1618"));
+        assert!(!check_ws_origin(&h, &loopback()));
+    }
+
+    #[test]
+    fn ws_missing_origin_allowed_only_from_loopback() {
+        let h = headers_with(None, Some("127.0.0.1:1618"));
+        assert!(check_ws_origin(&h, &loopback()));
+        assert!(!check_ws_origin(&h, &lan_peer()));
+    }
+
+    #[test]
+    fn save_origin_allows_lan_same_origin_but_not_missing_or_cross_origin() {
+        let same_origin = headers_with(
+            Some("http://192.168.1.13:59285"),
+            Some("192.168.1.13:59285"),
+        );
+        assert!(same_origin_or_loopback_no_origin(&same_origin, &lan_peer()));
+
+        let cross_origin =
+            headers_with(Some("http://evil.example.com"), Some("192.168.1.13:59285"));
+        assert!(!same_origin_or_loopback_no_origin(
+            &cross_origin,
+            &lan_peer()
+        ));
+
+        let missing_origin = headers_with(None, Some("192.168.1.13:59285"));
+        assert!(!same_origin_or_loopback_no_origin(
+            &missing_origin,
+            &lan_peer()
+        ));
+        assert!(same_origin_or_loopback_no_origin(
+            &missing_origin,
+            &loopback()
+        ));
+    }
+
+    #[test]
+    fn ws_origin_case_insensitive_host_match() {
+        let h = headers_with(
+            Some("http://Example.Local:1618"),
+            Some("example.local:1618"),
+        );
+        assert!(check_ws_origin(&h, &loopback()));
+    }
+
+    #[tokio::test]
+    async fn preview_route_requires_origin_and_scoped_capability() {
+        use axum::body::Body;
+        use axum::http::Request;
+
+        let registry = Arc::new(WorkspaceRegistry::new("preview-guard-test".into()));
+        let state = test_state(registry);
+        let workspace_id = "deadbeef";
+        let preview_token = workspace_preview_token(&state.save_token, workspace_id);
+        let save_token = workspace_save_token(&state.save_token, workspace_id);
+        assert_ne!(preview_token, save_token);
+        let app = Router::new()
+            .route("/api/preview", post(preview_handler))
+            .layer(axum::extract::DefaultBodyLimit::max(PREVIEW_BODY_LIMIT))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_local_save_origin,
+            ))
+            .with_state(state);
+
+        let build = |origin: Option<&str>,
+                     host: &str,
+                     peer: SocketAddr,
+                     token: Option<&str>,
+                     content: String| {
+            let mut b = Request::builder()
+                .method("POST")
+                .uri("/api/preview")
+                .header("host", host)
+                .header("content-type", "application/json");
+            if let Some(o) = origin {
+                b = b.header("origin", o);
+            }
+            if let Some(token) = token {
+                b = b.header("X-Markon-Token", token);
+            }
+            let mut req = b
+                .body(Body::from(
+                    json!({ "workspace_id": workspace_id, "content": content }).to_string(),
+                ))
+                .unwrap();
+            req.extensions_mut()
+                .insert(axum::extract::ConnectInfo(peer));
+            req
+        };
+
+        // Cross-site page from the LAN → rejected.
+        let resp = app
+            .clone()
+            .oneshot(build(
+                Some("http://evil.example.com"),
+                "192.168.1.13:6419",
+                lan_peer(),
+                Some(&preview_token),
+                "# hi".into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // Anonymous LAN device (no Origin, not loopback) → rejected even with a
+        // valid token, because browser capabilities do not replace the origin
+        // boundary.
+        let resp = app
+            .clone()
+            .oneshot(build(
+                None,
+                "192.168.1.13:6419",
+                lan_peer(),
+                Some(&preview_token),
+                "# hi".into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        // A LAN client can spoof a matching Origin, so origin alone is not an
+        // authentication boundary.
+        let resp = app
+            .clone()
+            .oneshot(build(
+                Some("http://192.168.1.13:6419"),
+                "192.168.1.13:6419",
+                lan_peer(),
+                None,
+                "# hi".into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Save and preview capabilities are deliberately not interchangeable.
+        let resp = app
+            .clone()
+            .oneshot(build(
+                Some("http://127.0.0.1:6419"),
+                "127.0.0.1:6419",
+                loopback(),
+                Some(&save_token),
+                "# hi".into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Same-origin editor page with its workspace-scoped preview capability
+        // is allowed.
+        let resp = app
+            .clone()
+            .oneshot(build(
+                Some("http://127.0.0.1:6419"),
+                "127.0.0.1:6419",
+                loopback(),
+                Some(&preview_token),
+                "# hi".into(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // Capability possession does not bypass the route-specific body cap.
+        let resp = app
+            .oneshot(build(
+                Some("http://127.0.0.1:6419"),
+                "127.0.0.1:6419",
+                loopback(),
+                Some(&preview_token),
+                "x".repeat(PREVIEW_BODY_LIMIT),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn ws_origin_with_trailing_path_still_matches_authority() {
+        // Defensive: spec says Origin has no path, but some clients append one.
+        let h = headers_with(Some("http://127.0.0.1:1618/"), Some("127.0.0.1:1618"));
+        assert!(check_ws_origin(&h, &loopback()));
+    }
+
+    #[test]
+    fn ws_hello_requires_structured_non_legacy_protocol() {
+        let hello: WsHello = serde_json::from_str(
+            r#"{"type":"hello","target":{"kind":"surface","key":"/abcd1234/"}}"#,
+        )
+        .unwrap();
+        assert!(matches!(hello.target, WsTarget::Surface { .. }));
+        assert_eq!(hello.filter, AnnotationFilter::default());
+        let hello: WsHello = serde_json::from_str(
+            r#"{"type":"hello","target":{"kind":"document","path":"a.md"},"filter":{"tag":"api","resolved":false}}"#,
+        )
+        .unwrap();
+        assert_eq!(hello.filter.tag.as_deref(), Some("api"));
+        assert_eq!(hello.identity, None);
+        let hello: WsHello = serde_json::from_str(
+            r##"{"type":"hello","target":{"kind":"document","path":"a.md"},"identity":{"color":"#ff0000","name":"Ada"}}"##,
+        )
+        .unwrap();
+        assert_eq!(hello.identity.unwrap()["name"], "Ada");
+        assert!(serde_json::from_str::<WsHello>(
+            r#"{"type":"hello","target":{"kind":"surface","key":"/abcd1234/"},"filter":{"owner":"x"}}"#
+        )
+        .is_err());
+        assert!(serde_json::from_str::<WsHello>(r#""/tmp/workspace/doc.md""#).is_err());
+        assert!(serde_json::from_str::<WsHello>(
+            r#"{"type":"legacy","target":{"kind":"surface","key":"/abcd1234/"}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn ws_document_target_is_canonical_and_workspace_scoped() {
+        let root = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let document = root.path().join("note.md");
+        let outside_file = outside.path().join("secret.md");
+        fs::write(&document, "# note").unwrap();
+        fs::write(&outside_file, "secret").unwrap();
+
+        let registry = Arc::new(WorkspaceRegistry::new("ws-document-scope".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let entry = registry.get(&id).unwrap();
+        let session = authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Document {
+                path: document.to_string_lossy().into_owned(),
+            },
+        )
+        .expect("workspace document should be authorized");
+        let canonical = dunce::canonicalize(&document)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        assert_eq!(
+            session.target,
+            WsSessionTarget::Document {
+                file_path: canonical.clone()
+            }
+        );
+        assert_eq!(session.channel, format!("document:{canonical}"));
+
+        assert!(authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Document {
+                path: outside_file.to_string_lossy().into_owned(),
+            }
+        )
+        .is_none());
+        assert!(authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Document {
+                path: "note.md".into(),
+            }
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn ws_document_target_obeys_single_file_capability() {
+        let root = tempfile::tempdir().unwrap();
+        let pinned = root.path().join("pinned.md");
+        let sibling = root.path().join("sibling.md");
+        fs::write(&pinned, "# pinned").unwrap();
+        fs::write(&sibling, "# sibling").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-single-file".into()));
+        let id = registry.add(WorkspaceConfig {
+            path: dunce::canonicalize(root.path()).unwrap(),
+            flags: all_flags(),
+            single_file: Some("pinned.md".into()),
+            files: Vec::new(),
+            collaborator_access_code_hash: String::new(),
+            alias: String::new(),
+        });
+        let entry = registry.get(&id).unwrap();
+
+        assert!(authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Document {
+                path: pinned.to_string_lossy().into_owned(),
+            }
+        )
+        .is_some());
+        assert!(authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Document {
+                path: sibling.to_string_lossy().into_owned(),
+            }
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn ws_surface_target_is_live_only_and_bound_to_workspace_url() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-surface".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let entry = registry.get(&id).unwrap();
+        let surface = authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Surface {
+                key: format!("/_/{id}/compare?base=main#change"),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            surface.channel,
+            format!("surface:/_/{id}/compare?base=main")
+        );
+        assert!(authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Surface {
+                key: "/_/deadbeef/compare".into(),
+            }
+        )
+        .is_none());
+
+        registry.update_flags(
+            &id,
+            WorkspaceFlags {
+                shared_annotation: true,
+                enable_live: false,
+                ..Default::default()
+            },
+        );
+        assert!(authorize_ws_target(
+            "",
+            &entry,
+            WsTarget::Surface {
+                key: format!("/{id}/"),
+            }
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn workspace_event_filter_prevents_cross_channel_delivery() {
+        let event = WorkspaceEvent::Channel {
+            channel: "document:/workspace/a.md".into(),
+            payload: "a".into(),
+        };
+        assert_eq!(
+            workspace_event_payload(event.clone(), "document:/workspace/a.md").as_deref(),
+            Some("a")
+        );
+        assert!(workspace_event_payload(event, "document:/workspace/b.md").is_none());
+        assert_eq!(
+            workspace_event_payload(
+                WorkspaceEvent::Workspace {
+                    payload: "reload".into()
+                },
+                "surface:/abcd1234/"
+            )
+            .as_deref(),
+            Some("reload")
+        );
+    }
+
+    #[test]
+    fn ws_input_is_live_only_and_never_mutates_annotations() {
+        let root = tempfile::tempdir().unwrap();
+        let document = root.path().join("note.md");
+        fs::write(&document, "# note").unwrap();
+
+        let registry = Arc::new(WorkspaceRegistry::new("ws-feature-gates".into()));
+        let id = add_test_workspace(
+            &registry,
+            root.path().to_path_buf(),
+            WorkspaceFlags {
+                shared_annotation: true,
+                enable_live: false,
+                ..Default::default()
+            },
+        );
+        let entry = registry.get(&id).unwrap();
+        let session = Arc::new(
+            authorize_ws_target(
+                "",
+                &entry,
+                WsTarget::Document {
+                    path: document.to_string_lossy().into_owned(),
+                },
+            )
+            .unwrap(),
+        );
+        let mut rx = entry.events_tx.subscribe();
+        handle_client_msg(
+            &entry,
+            &session,
+            WebSocketMessage::NewAnnotation {
+                annotation: json!({ "id": "anno-ignored" }),
+                op_id: None,
+            },
+        );
+        handle_client_msg(
+            &entry,
+            &session,
+            WebSocketMessage::LiveAction { data: json!({}) },
+        );
+        assert!(matches!(
+            rx.try_recv(),
+            Err(tokio::sync::broadcast::error::TryRecvError::Empty)
+        ));
+
+        registry.update_flags(
+            &id,
+            WorkspaceFlags {
+                shared_annotation: false,
+                enable_live: true,
+                ..Default::default()
+            },
+        );
+        let surface = Arc::new(
+            authorize_ws_target(
+                "",
+                &entry,
+                WsTarget::Surface {
+                    key: format!("/{id}/"),
+                },
+            )
+            .unwrap(),
+        );
+        handle_client_msg(
+            &entry,
+            &surface,
+            WebSocketMessage::LiveAction {
+                data: json!({ "marker": "forwarded" }),
+            },
+        );
+        let WorkspaceEvent::Channel { channel, payload } = rx.try_recv().unwrap() else {
+            panic!("expected channel event");
+        };
+        assert_eq!(channel, surface.channel);
+        assert!(payload.contains("forwarded"), "{payload}");
+    }
+
+    #[test]
+    fn test_websocket_message_serialization() {
+        let msg = WebSocketMessage::LiveAction {
+            data: json!({
+                "clientId": "test-id",
+                "action": "scroll_to",
+                "xpath": "/p[1]",
+                "offset": 0.5
+            }),
+        };
+        let serialized = serde_json::to_string(&msg).unwrap();
+        assert!(serialized.contains("\"type\":\"live_action\""));
+        assert!(serialized.contains("\"clientId\":\"test-id\""));
+
+        let file = WebSocketMessage::FileChanged {
+            workspace_id: "ws1".into(),
+            path: "docs/a.md".into(),
+        };
+        let serialized = serde_json::to_string(&file).unwrap();
+        assert!(serialized.contains("\"type\":\"file_changed\""));
+        assert!(serialized.contains("\"workspace_id\":\"ws1\""));
+    }
+
+    /// `NewAnnotation` round-trips `op_id` verbatim in both directions and
+    /// the field is omitted from the wire when `None` — keeping the protocol
+    /// backward-compatible with clients that don't know about it yet.
+    #[test]
+    fn test_new_annotation_op_id_round_trip() {
+        // Some(op_id): present on the wire, parsed back identically.
+        let with = WebSocketMessage::NewAnnotation {
+            annotation: json!({ "id": "anno-1", "text": "hi" }),
+            op_id: Some("op-abc".into()),
+        };
+        let json_with = serde_json::to_string(&with).unwrap();
+        assert!(
+            json_with.contains("\"op_id\":\"op-abc\""),
+            "wire form should include op_id: {json_with}"
+        );
+        let parsed: WebSocketMessage = serde_json::from_str(&json_with).unwrap();
+        match parsed {
+            WebSocketMessage::NewAnnotation { op_id, .. } => {
+                assert_eq!(op_id.as_deref(), Some("op-abc"));
+            }
+            _ => panic!("expected NewAnnotation"),
+        }
+
+        // None: omitted from the wire (back-compat with old clients).
+        let without = WebSocketMessage::NewAnnotation {
+            annotation: json!({ "id": "anno-2" }),
+            op_id: None,
+        };
+        let json_without = serde_json::to_string(&without).unwrap();
+        assert!(
+            !json_without.contains("op_id"),
+            "wire form should omit op_id when None: {json_without}"
+        );
+
+        // An old-client payload with no op_id field deserialises to None.
+        let legacy = r#"{"type":"new_annotation","annotation":{"id":"x"}}"#;
+        let parsed_legacy: WebSocketMessage = serde_json::from_str(legacy).unwrap();
+        match parsed_legacy {
+            WebSocketMessage::NewAnnotation { op_id, .. } => assert!(op_id.is_none()),
+            _ => panic!("expected NewAnnotation"),
+        }
+    }
+
+    #[test]
+    fn client_frames_are_validated_before_relay() {
+        let reject = |text: &str| parse_client_msg(text).unwrap_err();
+
+        assert!(matches!(
+            parse_client_msg(r#"{"type":"live_action","data":{"action":"viewport","top":3}}"#),
+            Ok(WebSocketMessage::LiveAction { .. })
+        ));
+        assert_eq!(reject("not json"), WsErrorReason::Malformed);
+        assert_eq!(reject("[1, 2]"), WsErrorReason::Malformed);
+        assert_eq!(reject(r#"{"type": 7}"#), WsErrorReason::Malformed);
+        assert_eq!(
+            reject(r#"{"type":"new_annotation","annotation":{"id":1}}"#),
+            WsErrorReason::Unsupported
+        );
+        assert_eq!(reject(r#"{"type":"mystery"}"#), WsErrorReason::Unsupported);
+        for data in [
+            "null",
+            "\"focus\"",
+            "{}",
+            r#"{"action": 3}"#,
+            r#"{"action": ""}"#,
+        ] {
+            assert_eq!(
+                reject(&format!(r#"{{"type":"live_action","data":{data}}}"#)),
+                WsErrorReason::InvalidLiveAction,
+                "{data}"
+            );
+        }
+        assert_eq!(
+            reject(r#"{"type":"live_action"}"#),
+            WsErrorReason::InvalidLiveAction
+        );
+        let padded = format!(
+            r#"{{"type":"live_action","data":{{"action":"selection","pad":"{}"}}}}"#,
+            "x".repeat(MAX_LIVE_ACTION_BYTES)
+        );
+        assert_eq!(reject(&padded), WsErrorReason::TooLarge);
+        assert_eq!(
+            reject(&" ".repeat(MAX_WS_MSG_BYTES + 1)),
+            WsErrorReason::TooLarge
+        );
+
+        let reply = serde_json::to_value(WebSocketMessage::Error {
+            reason: WsErrorReason::InvalidLiveAction,
+            message: WsErrorReason::InvalidLiveAction.message().to_string(),
+        })
+        .unwrap();
+        assert_eq!(reply["type"], "error");
+        assert_eq!(reply["reason"], "invalid_live_action");
+    }
+
+    #[test]
+    fn client_selections_carry_only_a_validated_range() {
+        let reject = |text: &str| parse_client_msg(text).unwrap_err();
+        let range = SelectionRange {
+            start_path: "//article[1]/P[1]".into(),
+            start_offset: 2,
+            end_path: "//article[1]/P[2]".into(),
+            end_offset: 5,
+        };
+
+        // A client cannot pick the id or identity it is relayed under.
+        assert_eq!(
+            serde_json::to_value(
+                parse_client_msg(
+                    r#"{"type":"selection","client_id":"spoof","identity":{"name":"x"},
+                        "range":{"startPath":"//article[1]/P[1]","startOffset":2,
+                                 "endPath":"//article[1]/P[2]","endOffset":5}}"#
+                )
+                .unwrap()
+            )
+            .unwrap(),
+            serde_json::json!({ "type": "selection", "range": range })
+        );
+        for cleared in [
+            r#"{"type":"selection","range":null}"#,
+            r#"{"type":"selection"}"#,
+        ] {
+            assert!(matches!(
+                parse_client_msg(cleared),
+                Ok(WebSocketMessage::Selection { range: None, .. })
+            ));
+        }
+        for bad in [
+            r#""p[1]""#,
+            r#"{"startPath":"a","startOffset":-1,"endPath":"b","endOffset":0}"#,
+            r#"{"startPath":"a","startOffset":0,"endPath":"b"}"#,
+            r#"{"startPath":"a","startOffset":0,"endPath":"b","endOffset":0,"x":1}"#,
+        ] {
+            assert_eq!(
+                reject(&format!(r#"{{"type":"selection","range":{bad}}}"#)),
+                WsErrorReason::InvalidSelection,
+                "{bad}"
+            );
+        }
+        let padded = format!(
+            r#"{{"type":"selection","range":{{"startPath":"{}","startOffset":0,"endPath":"b","endOffset":0}}}}"#,
+            "x".repeat(MAX_SELECTION_BYTES)
+        );
+        assert_eq!(reject(&padded), WsErrorReason::TooLarge);
+    }
+
+    #[test]
+    fn test_app_state_identity() {
+        let registry = Arc::new(crate::workspace::WorkspaceRegistry::new("salt".into()));
+        let state = AppState {
+            theme: Arc::new("dark".into()),
+            custom_theme: false,
+            tera: Arc::new(Tera::default()),
+            base_path: "".into(),
+            db: None,
+            workspace_registry: registry,
+            management_token: Arc::new("token".into()),
+            admin_bootstraps: Arc::new(AdminBootstrapStore::new()),
+            allowed_hosts: Arc::new(build_allowed_hosts("127.0.0.1", "", 6419, &[], &[])),
+            save_token: Arc::new("save-token".into()),
+            i18n_json: Arc::new("{}".into()),
+            i18n_lang: Arc::new("zh".into()),
+            shortcuts_json: Arc::new("{}".into()),
+            styles_css: Arc::new("".into()),
+            user_stylesheets: Arc::new(Vec::new()),
+            user_scripts: Arc::new(Vec::new()),
+            default_chat_mode: Arc::new("in_page".into()),
+            collaborator_access_code_hash: Arc::new(String::new()),
+            access_secret: Arc::new("test-salt".into()),
+            access_attempts: Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
+            markdown_diff_cache: Arc::new(Mutex::new(MarkdownDiffCache::default())),
+            archive_index: Arc::new(Mutex::new(crate::archive::IndexCache::default())),
+            render_hooks: Arc::new([]),
+            print_collapsed_content: false,
+            render_cache: true,
+            search_max_limit: MAX_SEARCH_LIMIT,
+            require_name: false,
+            annotation_token: None,
+            readme_names: Arc::new(vec!["README.md".into(), "index.md".into()]),
+            nav_files: Arc::new(vec!["SUMMARY.md".into()]),
+            listing_page_size: DEFAULT_LISTING_PAGE_SIZE,
+            paginate_bytes: None,
+            share_base: Arc::default(),
+            remote_markdown: Arc::new(RemoteMarkdown::new(RemoteAllowlist::default())),
+            attachments_dir: None,
+            remote_store: None,
+            presence: Arc::default(),
+            editor: Arc::default(),
+            pandoc: None,
+            share_token: None,
+            audit: false,
+            #[cfg(debug_assertions)]
+            dev_reload_tx: Arc::new(broadcast::channel::<()>(1).0),
+        };
+        assert_eq!(state.management_token.as_str(), "token");
+    }
+
+    fn sample_hosts() -> Vec<crate::net::BindHostOption> {
+        use crate::net::{BindHostKind, BindHostOption};
+        vec![
+            BindHostOption {
+                address: "127.0.0.1".into(),
+                kind: BindHostKind::Localhost,
+                interface: None,
+            },
+            BindHostOption {
+                address: "::1".into(),
+                kind: BindHostKind::Localhost,
+                interface: None,
+            },
+            BindHostOption {
+                address: "0.0.0.0".into(),
+                kind: BindHostKind::AllInterfaces,
+                interface: None,
+            },
+            BindHostOption {
+                address: "::".into(),
+                kind: BindHostKind::AllInterfaces,
+                interface: None,
+            },
+            BindHostOption {
+                address: "192.168.1.20".into(),
+                kind: BindHostKind::Interface,
+                interface: Some("en0".into()),
+            },
+            BindHostOption {
+                address: "10.0.0.5".into(),
+                kind: BindHostKind::Interface,
+                interface: Some("eth1".into()),
+            },
+            BindHostOption {
+                address: "fd00::20".into(),
+                kind: BindHostKind::Interface,
+                interface: Some("en0".into()),
+            },
+            BindHostOption {
+                address: "2001:db8::5".into(),
+                kind: BindHostKind::Interface,
+                interface: Some("utun0".into()),
+            },
+        ]
+    }
+
+    #[test]
+    fn reachable_ipv4_wildcard_lists_ipv4_localhost_then_interfaces() {
+        let r = assemble_reachable_urls("0.0.0.0", "", 6419, &sample_hosts());
+        assert_eq!(r.all.len(), 3);
+        assert_eq!(r.all[0].label, "localhost");
+        assert_eq!(r.all[0].url, "http://127.0.0.1:6419");
+        assert_eq!(r.all[1].url, "http://192.168.1.20:6419");
+        assert_eq!(r.all[2].url, "http://10.0.0.5:6419");
+        // No advertised preference → first interface is featured (not localhost).
+        assert_eq!(r.featured, "http://192.168.1.20:6419");
+    }
+
+    #[test]
+    fn admin_bootstrap_url_starts_at_final_route_with_fragment_nonce() {
+        assert_eq!(
+            build_admin_bootstrap_url(
+                "http://192.168.1.20:6419/",
+                "/workspace/file.md?mode=preview",
+                "abc123"
+            ),
+            "http://192.168.1.20:6419/workspace/file.md?mode=preview#bootstrap_nonce=abc123"
+        );
+        // The original heading remains in the server-side redirect stored with
+        // the nonce; it must not displace the bootstrap fragment in the first URL.
+        assert_eq!(
+            build_admin_bootstrap_url(
+                "http://127.0.0.1:6419",
+                "/workspace/file.md#heading",
+                "abc123"
+            ),
+            "http://127.0.0.1:6419/workspace/file.md#bootstrap_nonce=abc123"
+        );
+    }
+
+    #[test]
+    fn heading_anchor_matches_heading_text_or_anchor() {
+        let doc = "---\ntitle: Guide\n---\n# Guide\n\n## Getting Started\n\n## Usage\n\n## Usage\n";
+        assert_eq!(
+            heading_anchor(doc, "getting started").as_deref(),
+            Some("getting-started")
+        );
+        assert_eq!(heading_anchor(doc, "#usage-1").as_deref(), Some("usage-1"));
+        assert_eq!(heading_anchor(doc, "Usage").as_deref(), Some("usage"));
+        assert_eq!(heading_anchor(doc, "Missing"), None);
+        assert_eq!(heading_anchor(doc, "#"), None);
+    }
+
+    #[test]
+    fn reachable_ipv6_wildcard_lists_ipv6_localhost_then_interfaces() {
+        let r = assemble_reachable_urls("::", "", 6419, &sample_hosts());
+        assert_eq!(r.all.len(), 3);
+        assert_eq!(r.all[0].label, "localhost");
+        assert_eq!(r.all[0].url, "http://[::1]:6419");
+        assert_eq!(r.all[1].url, "http://[fd00::20]:6419");
+        assert_eq!(r.all[2].url, "http://[2001:db8::5]:6419");
+        assert_eq!(r.featured, "http://[fd00::20]:6419");
+    }
+
+    #[test]
+    fn reachable_wildcard_honours_advertised_host_and_falls_back() {
+        let hosts = sample_hosts();
+        // Advertised host is a live interface → used verbatim.
+        assert_eq!(
+            assemble_reachable_urls("0.0.0.0", "10.0.0.5", 6419, &hosts).featured,
+            "http://10.0.0.5:6419"
+        );
+        // Stale advertised host (not currently bound) → first interface.
+        assert_eq!(
+            assemble_reachable_urls("0.0.0.0", "172.16.9.9", 6419, &hosts).featured,
+            "http://192.168.1.20:6419"
+        );
+        assert_eq!(
+            assemble_reachable_urls("::", "2001:db8::5", 6419, &hosts).featured,
+            "http://[2001:db8::5]:6419"
+        );
+        assert_eq!(
+            assemble_reachable_urls("::", "[fd00::99]", 6419, &hosts).featured,
+            "http://[fd00::20]:6419"
+        );
+    }
+
+    #[test]
+    fn reachable_wildcard_without_interfaces_falls_back_to_localhost() {
+        use crate::net::{BindHostKind, BindHostOption};
+        let hosts = vec![
+            BindHostOption {
+                address: "127.0.0.1".into(),
+                kind: BindHostKind::Localhost,
+                interface: None,
+            },
+            BindHostOption {
+                address: "::1".into(),
+                kind: BindHostKind::Localhost,
+                interface: None,
+            },
+            BindHostOption {
+                address: "0.0.0.0".into(),
+                kind: BindHostKind::AllInterfaces,
+                interface: None,
+            },
+            BindHostOption {
+                address: "::".into(),
+                kind: BindHostKind::AllInterfaces,
+                interface: None,
+            },
+        ];
+        let r = assemble_reachable_urls("0.0.0.0", "", 6419, &hosts);
+        assert_eq!(r.all.len(), 1);
+        assert_eq!(r.featured, "http://127.0.0.1:6419");
+        let r = assemble_reachable_urls("::", "", 6419, &hosts);
+        assert_eq!(r.all.len(), 1);
+        assert_eq!(r.featured, "http://[::1]:6419");
+    }
+
+    #[test]
+    fn reachable_specific_bind_lists_only_that_address() {
+        let r = assemble_reachable_urls("192.168.1.20", "", 6419, &sample_hosts());
+        // A specific bind does NOT serve loopback, so localhost is absent.
+        assert_eq!(r.all.len(), 1);
+        assert_eq!(r.all[0].label, "en0");
+        assert_eq!(r.featured, "http://192.168.1.20:6419");
+    }
+
+    #[test]
+    fn reachable_specific_ipv6_bind_lists_bracketed_address() {
+        let r = assemble_reachable_urls("fd00::20", "", 6419, &sample_hosts());
+        assert_eq!(r.all.len(), 1);
+        assert_eq!(r.all[0].label, "en0");
+        assert_eq!(r.all[0].url, "http://[fd00::20]:6419");
+        assert_eq!(r.featured, "http://[fd00::20]:6419");
+    }
+
+    #[test]
+    fn reachable_loopback_binds() {
+        let hosts = sample_hosts();
+        let v4 = assemble_reachable_urls("127.0.0.1", "", 6419, &hosts);
+        assert_eq!(v4.all.len(), 1);
+        assert_eq!(v4.featured, "http://127.0.0.1:6419");
+        // IPv6 loopback is preserved (bracketed), not collapsed to 127.0.0.1.
+        let v6 = assemble_reachable_urls("::1", "", 6419, &hosts);
+        assert_eq!(v6.featured, "http://[::1]:6419");
+    }
+
+    #[test]
+    fn access_cookie_round_trips_and_rejects_tamper() {
+        let secret = "test-secret";
+        let scopes = vec![("s".to_string(), "h1".to_string())];
+        let raw = make_access_cookie(secret, &scopes, access_now_unix() + 100, false);
+        let kv = raw.split(';').next().unwrap(); // markon_access=PAYLOAD.SIG
+        assert_eq!(access_cookie_scopes(secret, Some(kv)), scopes);
+        // Wrong secret, tampered value, and an expired cookie are all rejected.
+        assert!(access_cookie_scopes("other-secret", Some(kv)).is_empty());
+        assert!(access_cookie_scopes(secret, Some(&format!("{kv}00"))).is_empty());
+        let expired = make_access_cookie(secret, &scopes, 1, false);
+        assert!(access_cookie_scopes(secret, Some(expired.split(';').next().unwrap())).is_empty());
+        let secure = make_access_cookie(secret, &scopes, access_now_unix() + 100, true);
+        assert!(secure.contains("; Secure"));
+    }
+
+    #[test]
+    fn allowed_hosts_are_exact_and_track_explicit_https_origins() {
+        let allowed = build_allowed_hosts(
+            "127.0.0.1",
+            "",
+            6419,
+            &["https://md.example.com".into(), "notes.local".into()],
+            &[],
+        );
+        assert!(allowed.allows_header(Some("127.0.0.1:6419")));
+        assert!(allowed.allows_header(Some("[::1]:6419")));
+        assert!(allowed.allows_header(Some("LOCALHOST:9999")));
+        assert!(allowed.allows_header(Some("md.example.com")));
+        assert!(allowed.allows_header(Some("notes.local:443")));
+        assert!(!allowed.allows_header(Some("evil.example")));
+        assert!(!allowed.allows_header(Some("md.example.com.evil")));
+        assert!(allowed.is_secure_header(Some("md.example.com")));
+        assert!(!allowed.is_secure_header(Some("notes.local")));
+    }
+
+    #[test]
+    fn wildcard_allowed_hosts_follow_interface_address_changes() {
+        use crate::net::{BindHostKind, BindHostOption};
+
+        let allowed = build_allowed_hosts("0.0.0.0", "", 6419, &[], &[]);
+        allowed.replace_dynamic_interfaces(&sample_hosts());
+        assert!(allowed.allows_header(Some("192.168.1.20:6419")));
+
+        let changed = vec![BindHostOption {
+            address: "192.168.50.150".into(),
+            kind: BindHostKind::Interface,
+            interface: Some("en0".into()),
+        }];
+        allowed.replace_dynamic_interfaces(&changed);
+        assert!(!allowed.allows_header(Some("192.168.1.20:6419")));
+        assert!(allowed.allows_header(Some("192.168.50.150:6419")));
+        assert!(!allowed.allows_header(Some("attacker.example:6419")));
+    }
+
+    #[tokio::test]
+    async fn unknown_host_is_rejected_before_route_execution() {
+        let state = test_state(Arc::new(WorkspaceRegistry::new("host-gate".into())));
+        let app = Router::new()
+            .route("/state-change", post(|| async { StatusCode::NO_CONTENT }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_allowed_host,
+            ))
+            .with_state(state);
+
+        let evil = axum::http::Request::builder()
+            .method("POST")
+            .uri("/state-change")
+            .header(header::HOST, "evil.example:6419")
+            .header(header::ORIGIN, "http://evil.example:6419")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(evil).await.unwrap().status(),
+            StatusCode::MISDIRECTED_REQUEST
+        );
+
+        let local = axum::http::Request::builder()
+            .method("POST")
+            .uri("/state-change")
+            .header(header::HOST, "127.0.0.1:6419")
+            .body(axum::body::Body::empty())
+            .unwrap();
+        assert_eq!(
+            app.oneshot(local).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+    }
+
+    #[tokio::test]
+    async fn loopback_is_not_an_admin_identity() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("admin-role".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let required_hash = crate::workspace::hash_access_code("test-salt", "guest");
+        assert!(registry.set_collaborator_access_code(&id, &required_hash));
+        let state = test_state(registry);
+        let route = format!("/_/{id}/danger");
+        let app = Router::new()
+            .route(
+                "/_/{workspace_id}/danger",
+                post(|| async { StatusCode::NO_CONTENT })
+                    .route_layer(axum::middleware::from_fn(require_admin_role)),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_access_code,
+            ));
+
+        let request = |cookie: Option<String>| {
+            let mut builder = axum::http::Request::builder()
+                .method("POST")
+                .uri(&route)
+                .extension(axum::extract::ConnectInfo(loopback()));
+            if let Some(cookie) = cookie {
+                builder = builder.header(header::COOKIE, cookie);
+            }
+            builder.body(axum::body::Body::empty()).unwrap()
+        };
+
+        // A loopback TCP peer without a capability still hits the collaborator
+        // gate; network topology grants no role.
+        assert_eq!(
+            app.clone().oneshot(request(None)).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let collaborator = make_access_cookie(
+            &state.access_secret,
+            &[(format!("w:{id}:collaborator"), required_hash)],
+            access_now_unix() + 60,
+            false,
+        );
+        assert_eq!(
+            app.clone()
+                .oneshot(request(Some(collaborator)))
+                .await
+                .unwrap()
+                .status(),
+            StatusCode::FORBIDDEN
+        );
 
-    let mut context = base_context(state);
-    context.insert("workspace_id", workspace_id);
-    context.insert("workspace_alias", &ws.alias());
-    context.insert(
-        "workspace_name",
-        &root
-            .file_name()
-            .map(|n| n.to_string_lossy().to_string())
-            .unwrap_or_default(),
-    );
-    context.insert("can_manage", &can_manage);
-    context.insert("shared_annotation", &flags.shared_annotation);
-    context.insert("current_dir", &current_dir.display().to_string());
-    context.insert("history_url", &workspace_git_history_url(workspace_id));
-    context.insert("work_diff_url", &work_diff_url);
-    context.insert("latest_commit", &latest_commit);
-    context.insert("latest_commit_diff_url", &latest_commit_diff_url);
-    context.insert("git_changed_count", &git_changed_count);
-    context.insert("git_commit_count", &git_commit_count);
-    context.insert("git_branch_count", &git_branch_count);
-    context.insert("git_tag_count", &git_tag_count);
-    context.insert("git_branches", &git_branches);
-    context.insert("git_commits", &git_commits);
-    context.insert("feature_statuses", &feature_statuses);
-    context.insert("git", &git_status);
-    context.insert("is_workspace_root", &is_workspace_root);
-    context.insert("can_add_file", &can_add_file);
-    context.insert("version", env!("CARGO_PKG_VERSION"));
-    context.insert("branches_url", &workspace_git_branches_url(workspace_id));
-    context.insert("tags_url", &workspace_git_tags_url(workspace_id));
-    context.insert("checkout_url", &workspace_git_checkout_url(workspace_id));
-    context.insert("files_data_url", &workspace_files_data_url(workspace_id));
-    context.insert("files_dir_url", &workspace_files_dir_url(workspace_id));
-    context.insert(
-        "settings_features_url",
-        &workspace_settings_features_url(workspace_id),
-    );
-    context.insert("create_file_url", &workspace_file_create_url(workspace_id));
-    context.insert(
-        "create_folder_url",
-        &workspace_folder_create_url(workspace_id),
-    );
-    context.insert("entries", &entries);
-    context.insert("show_parent", &show_parent);
-    context.insert("parent_link", &parent_link);
-    context.insert("breadcrumb", &breadcrumb);
-    context.insert("enable_search", &flags.enable_search);
-    context.insert("enable_live", &flags.enable_live);
-    context.insert("enable_chat", &flags.enable_chat);
+        let admin =
+            admin_auth::make_admin_cookie(&state.management_token, access_now_unix(), false);
+        assert_eq!(
+            app.oneshot(request(Some(admin))).await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+    }
 
-    render_template(state, "directory.html", &context)
-}
+    #[tokio::test]
+    async fn administrator_workspace_responses_are_not_cached() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("admin-cache".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let route = format!("/{id}/page");
+        let app = Router::new()
+            .route(
+                "/{workspace_id}/page",
+                get(|| async { Html("<p>workspace</p>") }),
+            )
+            .layer(axum::middleware::from_fn(prevent_admin_response_caching))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_access_code,
+            ));
 
-async fn serve_favicon() -> impl IntoResponse {
-    // Redirect /_/favicon.ico to /_/favicon.svg
-    (
-        StatusCode::MOVED_PERMANENTLY,
-        [(header::LOCATION, "/_/favicon.svg")],
-    )
-        .into_response()
-}
+        let request = |cookie: Option<String>| {
+            let mut builder = axum::http::Request::builder().uri(&route);
+            if let Some(cookie) = cookie {
+                builder = builder.header(header::COOKIE, cookie);
+            }
+            builder.body(axum::body::Body::empty()).unwrap()
+        };
 
-async fn serve_favicon_svg() -> impl IntoResponse {
-    serve_static_file("favicon.svg", IconAssets::get, "image/svg+xml")
-}
+        let collaborator_response = app.clone().oneshot(request(None)).await.unwrap();
+        assert!(collaborator_response
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .is_none());
 
-async fn serve_css(AxumPath(filename): AxumPath<String>) -> impl IntoResponse {
-    serve_static_file(&filename, CssAssets::get, "text/css")
-}
+        let admin =
+            admin_auth::make_admin_cookie(&state.management_token, access_now_unix(), false);
+        let admin_response = app.oneshot(request(Some(admin))).await.unwrap();
+        assert_eq!(
+            admin_response
+                .headers()
+                .get(header::CACHE_CONTROL)
+                .and_then(|value| value.to_str().ok()),
+            Some("private, no-store")
+        );
+    }
 
-async fn serve_js(AxumPath(path): AxumPath<String>) -> impl IntoResponse {
-    let content_type = mime_guess::from_path(&path)
-        .first_or_octet_stream()
-        .essence_str()
-        .to_string();
-    serve_static_file(&path, JsAssets::get, &content_type)
-}
+    #[tokio::test]
+    async fn admin_nonce_exchange_sets_single_use_http_only_session() {
+        let state = test_state(Arc::new(WorkspaceRegistry::new("admin-exchange".into())));
+        let nonce = state.admin_bootstraps.issue_url("/abcd1234/");
+        let headers = headers_with(Some("http://127.0.0.1:6419"), Some("127.0.0.1:6419"));
+        let response = admin_session_handler(
+            State(state.clone()),
+            axum::extract::ConnectInfo(loopback()),
+            headers.clone(),
+            Json(AdminSessionRequest {
+                nonce: Some(nonce.clone()),
+                code: None,
+            }),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response
+            .headers()
+            .get(header::SET_COOKIE)
+            .and_then(|value| value.to_str().ok())
+            .expect("admin session cookie");
+        assert!(cookie.contains("HttpOnly; SameSite=Strict"));
+        assert!(admin_auth::admin_cookie_valid(
+            &state.management_token,
+            Some(cookie),
+            access_now_unix(),
+        ));
 
-fn serve_static_file<F>(filename: &str, getter: F, content_type: &str) -> Response
-where
-    F: FnOnce(&str) -> Option<rust_embed::EmbeddedFile>,
-{
-    match getter(filename) {
-        // `file.data` is Cow::Borrowed in release builds; serving the Cow
-        // directly avoids copying the embedded asset on every request.
-        Some(file) => (
-            StatusCode::OK,
-            [(header::CONTENT_TYPE, content_type)],
-            file.data,
+        let replay = admin_session_handler(
+            State(state),
+            axum::extract::ConnectInfo(loopback()),
+            headers,
+            Json(AdminSessionRequest {
+                nonce: Some(nonce),
+                code: None,
+            }),
         )
-            .into_response(),
-        None => (StatusCode::NOT_FOUND, "File not found").into_response(),
+        .await;
+        assert_eq!(replay.status(), StatusCode::UNAUTHORIZED);
     }
-}
 
-/// Serve a raw (non-markdown) workspace file. Delegates to `tower_http`'s
-/// `ServeFile`, which streams the body from async I/O instead of reading the
-/// whole file into memory, and honors `Range` (206) / conditional requests. The
-/// caller's relevant request headers are forwarded so those features work;
-/// `ServeFile` serves the fixed `path` regardless of the request URI. `path`
-/// is already canonicalized and confinement-checked by the caller.
-async fn serve_file(path: &std::path::Path, req_headers: &axum::http::HeaderMap) -> Response {
-    use tower::ServiceExt;
-    let mut req = axum::http::Request::new(axum::body::Body::empty());
-    for name in [
-        header::RANGE,
-        header::IF_RANGE,
-        header::IF_MODIFIED_SINCE,
-        header::IF_NONE_MATCH,
-        header::ACCEPT_ENCODING,
-    ] {
-        if let Some(value) = req_headers.get(&name) {
-            req.headers_mut().insert(name, value.clone());
-        }
-    }
-    match tower_http::services::ServeFile::new(path)
-        .oneshot(req)
-        .await
-    {
-        Ok(resp) => resp.map(axum::body::Body::new).into_response(),
-        // ServeFile's error type is `Infallible`; it reports IO problems as an
-        // error status in the response body, so this arm is effectively dead.
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Error reading file").into_response(),
+    #[tokio::test]
+    async fn save_capability_cannot_cross_workspace_boundary() {
+        let root_a = tempfile::tempdir().unwrap();
+        let root_b = tempfile::tempdir().unwrap();
+        std::fs::write(root_a.path().join("a.md"), "workspace a").unwrap();
+        std::fs::write(root_b.path().join("b.md"), "workspace b").unwrap();
+
+        let registry = Arc::new(WorkspaceRegistry::new("save-scope".into()));
+        let id_a = add_test_workspace(&registry, root_a.path().to_path_buf(), all_flags());
+        let id_b = add_test_workspace(&registry, root_b.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let token_a = workspace_save_token(&state.save_token, &id_a);
+        let token_b = workspace_save_token(&state.save_token, &id_b);
+        let preview_token_b = workspace_preview_token(&state.save_token, &id_b);
+        assert_ne!(token_a, token_b);
+        assert_ne!(token_b, preview_token_b);
+
+        let app = Router::new()
+            .route("/api/save", post(save_file_handler))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_local_save_origin,
+            ))
+            .with_state(state);
+        let request = |token: &str, content: &str| {
+            axum::http::Request::builder()
+                .method("POST")
+                .uri("/api/save")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::HOST, "127.0.0.1:6419")
+                .header(header::ORIGIN, "http://127.0.0.1:6419")
+                .header("X-Markon-Token", token)
+                .extension(axum::extract::ConnectInfo(loopback()))
+                .body(axum::body::Body::from(
+                    json!({
+                        "workspace_id": id_b,
+                        "file_path": "b.md",
+                        "content": content,
+                    })
+                    .to_string(),
+                ))
+                .unwrap()
+        };
+
+        let denied = app
+            .clone()
+            .oneshot(request(&token_a, "stolen"))
+            .await
+            .unwrap();
+        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            std::fs::read_to_string(root_b.path().join("b.md")).unwrap(),
+            "workspace b"
+        );
+
+        let denied = app
+            .clone()
+            .oneshot(request(&preview_token_b, "preview escalation"))
+            .await
+            .unwrap();
+        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            std::fs::read_to_string(root_b.path().join("b.md")).unwrap(),
+            "workspace b"
+        );
+
+        let allowed = app.oneshot(request(&token_b, "updated b")).await.unwrap();
+        assert_eq!(allowed.status(), StatusCode::OK);
+        assert_eq!(
+            std::fs::read_to_string(root_b.path().join("b.md")).unwrap(),
+            "updated b"
+        );
     }
-}
 
-// ── File editing API ──────────────────────────────────────────────────────────
+    #[test]
+    fn annotation_id_cannot_replace_another_documents_row() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE annotations (
+                id TEXT PRIMARY KEY,
+                file_path TEXT NOT NULL,
+                data TEXT NOT NULL
+            )",
+            [],
+        )
+        .unwrap();
 
-#[derive(Deserialize)]
-struct SaveFileRequest {
-    workspace_id: String,
-    file_path: String,
-    content: String,
-}
+        assert!(upsert_annotation_for_file(
+            &conn,
+            "shared-id",
+            "/workspace/a.md",
+            r#"{"id":"shared-id","text":"a"}"#,
+        )
+        .unwrap());
+        assert!(!upsert_annotation_for_file(
+            &conn,
+            "shared-id",
+            "/workspace/b.md",
+            r#"{"id":"shared-id","text":"b"}"#,
+        )
+        .unwrap());
 
-#[derive(Serialize)]
-struct SaveFileResponse {
-    success: bool,
-    message: String,
-}
+        let (file_path, data): (String, String) = conn
+            .query_row(
+                "SELECT file_path, data FROM annotations WHERE id = ?1",
+                ["shared-id"],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(file_path, "/workspace/a.md");
+        assert!(data.contains(r#""text":"a""#));
 
-/// Write `content` to `target` atomically: create a uniquely-named temp file in
-/// the SAME directory, write + flush it, then `rename` it over the target. A
-/// crash mid-write can therefore never leave a truncated document — either the
-/// old file or the fully-written new file is visible. The temp file is removed
-/// on any error. The unique name is derived from the process id plus a static
-/// counter to avoid collisions between concurrent saves.
-fn atomic_write(target: &FsPath, content: &[u8]) -> std::io::Result<()> {
-    use std::io::Write;
-    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
-    let dir = target.parent().ok_or_else(|| {
-        std::io::Error::new(
-            std::io::ErrorKind::InvalidInput,
-            "target has no parent directory",
+        assert!(upsert_annotation_for_file(
+            &conn,
+            "shared-id",
+            "/workspace/a.md",
+            r#"{"id":"shared-id","text":"a2"}"#,
         )
-    })?;
-    let base = target
-        .file_name()
-        .map(|s| s.to_string_lossy().to_string())
-        .unwrap_or_default();
-    let n = COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    let tmp_path = dir.join(format!(".{base}.{}.{n}.tmp", std::process::id()));
-
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .create_new(true)
-        .open(&tmp_path)?;
-    // The temp file now exists and is exclusively ours, so any later failure is
-    // safe to clean up.
-    if let Err(e) = file.write_all(content).and_then(|()| file.sync_all()) {
-        drop(file);
-        let _ = std::fs::remove_file(&tmp_path);
-        return Err(e);
-    }
-    drop(file);
-    // Preserve the destination's existing permission bits: `rename` swaps in the
-    // fresh temp inode, which would otherwise reset an already-existing file's
-    // mode to the umask default. Best-effort and Unix-only; the crash-safety of
-    // the write does not depend on it succeeding.
-    #[cfg(unix)]
-    if let Ok(meta) = std::fs::metadata(target) {
-        let _ = std::fs::set_permissions(&tmp_path, meta.permissions());
-    }
-    match std::fs::rename(&tmp_path, target) {
-        Ok(()) => Ok(()),
-        Err(e) => {
-            let _ = std::fs::remove_file(&tmp_path);
-            Err(e)
-        }
+        .unwrap());
     }
-}
 
-async fn save_file_handler(
-    State(state): State<AppState>,
-    headers: axum::http::HeaderMap,
-    Json(payload): Json<SaveFileRequest>,
-) -> impl IntoResponse {
-    let scoped_token = workspace_save_token(&state.save_token, &payload.workspace_id);
-    if !request_token_matches(&headers, &scoped_token, &state.management_token) {
-        return StatusCode::UNAUTHORIZED.into_response();
-    }
+    #[tokio::test]
+    async fn document_state_is_always_sqlite_for_admin_and_shared_only_for_collaborators() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("note.md");
+        fs::write(&file, "# note").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("document-state".into()));
+        let id = add_test_workspace(
+            &registry,
+            root.path().to_path_buf(),
+            WorkspaceFlags::default(),
+        );
+        let mut events = registry.get(&id).unwrap().events_tx.subscribe();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_by TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, sections TEXT);",
+        )
+        .unwrap();
+        let mut state = test_state(registry.clone());
+        state.db = Some(crate::db::Database::from_connection(conn));
+        let path = file.to_string_lossy().into_owned();
+        let annotation = serde_json::json!({
+            "id": "anno-admin",
+            "text": "note",
+            "anchor": { "position": 0, "exact": "note", "prefix": "", "suffix": "" },
+            "type": "highlight-yellow",
+            "tagName": "span",
+            "createdAt": 1
+        });
 
-    let ws = match state.workspace_registry.get(&payload.workspace_id) {
-        Some(w) => w,
-        None => {
-            return Json(SaveFileResponse {
-                success: false,
-                message: "Workspace not found".into(),
-            })
-            .into_response()
-        }
-    };
+        let denied = handle_document_state_command(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Collaborator)),
+            Json(DocumentStateCommand::SaveAnnotation {
+                path: path.clone(),
+                annotation: annotation.clone(),
+                by: None,
+                op_id: None,
+            }),
+        )
+        .await;
+        assert_eq!(denied.status(), StatusCode::FORBIDDEN);
 
-    // Authorization is enforced by the origin middleware, the workspace-bound
-    // token above, and the per-workspace edit flag below.
-    if !ws.enable_edit.load(std::sync::atomic::Ordering::Relaxed) {
-        return Json(SaveFileResponse {
-            success: false,
-            message: "Edit feature is not enabled".into(),
-        })
-        .into_response();
+        let saved = handle_document_state_command(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            Json(DocumentStateCommand::SaveAnnotation {
+                path: path.clone(),
+                annotation: annotation.clone(),
+                by: None,
+                op_id: None,
+            }),
+        )
+        .await;
+        assert_eq!(saved.status(), StatusCode::OK);
+        let saved: serde_json::Value = serde_json::from_str(&response_text(saved).await).unwrap();
+        assert_eq!(saved["revision"], 1);
+        // A second copy edited from before that save is stale.
+        let stale = handle_document_state_command(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            Json(DocumentStateCommand::SaveAnnotation {
+                path: path.clone(),
+                annotation,
+                by: None,
+                op_id: None,
+            }),
+        )
+        .await;
+        assert_eq!(stale.status(), StatusCode::CONFLICT);
+        let stale: serde_json::Value = serde_json::from_str(&response_text(stale).await).unwrap();
+        assert_eq!(stale["annotation"], saved);
+        assert!(matches!(
+            events.try_recv(),
+            Err(tokio::sync::broadcast::error::TryRecvError::Empty)
+        ));
+        let loaded = handle_document_state(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            Query(DocumentStateQuery { path: path.clone() }),
+        )
+        .await;
+        assert_eq!(loaded.status(), StatusCode::OK);
+        let body = response_text(loaded).await;
+        assert!(body.contains("anno-admin"), "{body}");
+
+        let flags = WorkspaceFlags {
+            shared_annotation: true,
+            ..Default::default()
+        };
+        assert!(registry.update_flags(&id, flags));
+        let anonymous = handle_document_state(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            None,
+            Query(DocumentStateQuery { path: path.clone() }),
+        )
+        .await;
+        assert_eq!(anonymous.status(), StatusCode::FORBIDDEN);
+        let shared_annotation = serde_json::json!({
+            "id": "anno-shared",
+            "text": "shared note",
+            "anchor": { "position": 0, "exact": "note", "prefix": "", "suffix": "" },
+            "type": "highlight-yellow",
+            "tagName": "span",
+            "createdAt": 2
+        });
+        let shared_save = handle_document_state_command(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Collaborator)),
+            Json(DocumentStateCommand::SaveAnnotation {
+                path: path.clone(),
+                annotation: shared_annotation,
+                by: None,
+                op_id: Some("shared-op".to_string()),
+            }),
+        )
+        .await;
+        assert_eq!(shared_save.status(), StatusCode::OK);
+        match events.try_recv().unwrap() {
+            WorkspaceEvent::Channel { channel, payload } => {
+                let canonical = dunce::canonicalize(&file).unwrap();
+                assert_eq!(channel, format!("document:{}", canonical.to_string_lossy()));
+                assert!(payload.contains("anno-shared"), "{payload}");
+                assert!(payload.contains("shared-op"), "{payload}");
+            }
+            other => panic!("unexpected workspace event: {other:?}"),
+        }
+        let shared = handle_document_state(
+            State(state),
+            AxumPath(id),
+            Some(Extension(AccessRole::Collaborator)),
+            Query(DocumentStateQuery { path }),
+        )
+        .await;
+        assert_eq!(shared.status(), StatusCode::OK);
     }
 
-    let decoded = match urlencoding::decode(&payload.file_path) {
-        Ok(p) => p,
-        Err(_) => {
-            return Json(SaveFileResponse {
-                success: false,
-                message: "Invalid file path encoding".into(),
-            })
-            .into_response()
+    #[tokio::test]
+    async fn cleared_annotations_are_listed_in_history_and_undone() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("note.md");
+        fs::write(&file, "# note").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("document-undo".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let mut events = registry.get(&id).unwrap().events_tx.subscribe();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let key = dunce::canonicalize(&file)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        for anno in ["anno-a", "anno-b"] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                params![anno, key, serde_json::json!({ "id": anno }).to_string()],
+            )
+            .unwrap();
         }
-    };
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+        let path = file.to_string_lossy().into_owned();
+        let command = |command: DocumentStateCommand| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                handle_document_state_command(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Collaborator)),
+                    Json(command),
+                )
+                .await
+            }
+        };
+        let history = || {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                let response = handle_annotations_history(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Collaborator)),
+                    Query(DocumentStateQuery {
+                        path: "note.md".into(),
+                    }),
+                )
+                .await
+                .into_response();
+                serde_json::from_str::<serde_json::Value>(&response_text(response).await).unwrap()
+            }
+        };
 
-    let decoded_path = std::path::Path::new(decoded.as_ref());
-    let canonical = match ws.fs.resolve_editable_input(decoded_path) {
-        Ok(path) => path,
-        Err(
-            crate::workspace_fs::WorkspaceFsError::InvalidPath
-            | crate::workspace_fs::WorkspaceFsError::Denied,
-        ) => {
-            return Json(SaveFileResponse {
-                success: false,
-                message: "Access denied".into(),
-            })
-            .into_response()
-        }
-        Err(
-            crate::workspace_fs::WorkspaceFsError::NotFound
-            | crate::workspace_fs::WorkspaceFsError::Io(_),
-        ) => {
-            return Json(SaveFileResponse {
-                success: false,
-                message: format!("File not found: {decoded}"),
-            })
-            .into_response()
+        let cleared = command(DocumentStateCommand::ClearAnnotations {
+            path: path.clone(),
+            by: None,
+            op_id: None,
+        })
+        .await;
+        assert_eq!(cleared.status(), StatusCode::NO_CONTENT);
+        let _ = events.try_recv().unwrap();
+        let log = history().await;
+        assert_eq!(log.as_array().unwrap().len(), 2);
+        assert_eq!(log[0]["action"], "clear");
+
+        let undone = command(DocumentStateCommand::UndoAnnotations {
+            path: path.clone(),
+            by: None,
+            op_id: Some("undo-op".into()),
+        })
+        .await;
+        assert_eq!(undone.status(), StatusCode::NO_CONTENT);
+        for anno in ["anno-a", "anno-b"] {
+            assert!(matches!(
+                events.try_recv().unwrap(),
+                WorkspaceEvent::Channel { payload, .. }
+                    if payload.contains(anno) && payload.contains("undo-op")
+            ));
         }
-    };
+        assert_eq!(history().await, serde_json::json!([]));
 
-    if !canonical.is_file() {
-        return Json(SaveFileResponse {
-            success: false,
-            message: "Path is not a file".into(),
-        })
-        .into_response();
-    }
-    if !is_markdown_path(&canonical) {
-        return Json(SaveFileResponse {
-            success: false,
-            message: "Only .md files can be edited".into(),
+        let deleted = command(DocumentStateCommand::DeleteAnnotation {
+            path,
+            id: "anno-b".into(),
+            by: None,
+            op_id: None,
         })
+        .await;
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        let restored = handle_annotations_undo(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Collaborator)),
+            Query(DocumentStateQuery {
+                path: "note.md".into(),
+            }),
+        )
+        .await
         .into_response();
+        assert_eq!(restored.status(), StatusCode::OK);
+        assert_eq!(response_text(restored).await, r#"[{"id":"anno-b"}]"#);
     }
-    // Perform the atomic write on the blocking pool so file I/O (open, write,
-    // fsync, rename) does not stall a tokio worker thread.
-    let content = payload.content;
-    let write_result =
-        tokio::task::spawn_blocking(move || atomic_write(&canonical, content.as_bytes())).await;
-    match write_result {
-        Ok(Ok(())) => Json(SaveFileResponse {
-            success: true,
-            message: "File saved successfully".into(),
-        })
-        .into_response(),
-        Ok(Err(e)) if e.kind() == std::io::ErrorKind::PermissionDenied => Json(SaveFileResponse {
-            success: false,
-            message: "File is read-only".into(),
-        })
-        .into_response(),
-        Ok(Err(e)) => Json(SaveFileResponse {
-            success: false,
-            message: format!("Failed to save: {e}"),
-        })
-        .into_response(),
-        Err(e) => {
-            tracing::error!("save_file_handler blocking task join error: {e}");
-            Json(SaveFileResponse {
-                success: false,
-                message: "Failed to save: internal error".into(),
-            })
-            .into_response()
-        }
-    }
-}
-
-// ── Markdown preview API ──────────────────────────────────────────────────────
 
-#[derive(Deserialize)]
-struct PreviewRequest {
-    workspace_id: String,
-    content: String,
-}
-
-#[derive(Serialize)]
-struct PreviewResponse {
-    html: String,
-    has_mermaid: bool,
-    has_math: bool,
-}
+    #[test]
+    fn document_pages_embed_the_annotation_index_for_permalinks() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("note.md");
+        fs::write(&file, "# note\n\nsecond paragraph").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("permalinks".into()));
+        let flags = WorkspaceFlags {
+            shared_annotation: false,
+            ..all_flags()
+        };
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), flags);
+        let entry = registry.get(&id).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let key = dunce::canonicalize(&file)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        for (anno, position) in [("anno-second", 6), ("anno-first", 0)] {
+            let data = serde_json::json!({
+                "id": anno,
+                "type": "highlight-orange",
+                "anchor": { "position": position, "exact": "x", "prefix": "", "suffix": "" },
+            });
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                params![anno, key, data.to_string()],
+            )
+            .unwrap();
+        }
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+        let workspace_root = canonical_workspace_root(&entry);
+        let page = |can_manage: bool| {
+            let response =
+                render_markdown_file(&key, &id, &entry, &workspace_root, &state, can_manage, 1);
+            tokio::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap()
+                .block_on(response_text(response))
+        };
 
-async fn preview_handler(
-    State(state): State<AppState>,
-    headers: axum::http::HeaderMap,
-    Json(payload): Json<PreviewRequest>,
-) -> impl IntoResponse {
-    let scoped_token = workspace_preview_token(&state.save_token, &payload.workspace_id);
-    if !request_token_matches(&headers, &scoped_token, &state.management_token) {
-        return StatusCode::UNAUTHORIZED.into_response();
+        let admin = page(true);
+        let index_start = admin.find(r#"id="annotation-index">"#).unwrap();
+        let first = admin[index_start..].find("anno-first").unwrap();
+        let second = admin[index_start..].find("anno-second").unwrap();
+        assert!(first < second);
+        // Unshared annotations stay private to administrators.
+        assert!(!page(false).contains("annotation-index"));
     }
 
-    // Markdown rendering (syntect highlight + AST walk) is CPU-bound; run it on
-    // the blocking pool so a large document can't stall a runtime worker.
-    let theme = state.theme.clone();
-    let content = payload.content;
-    let rendered = match tokio::task::spawn_blocking(move || {
-        let renderer = default_markdown_engine(&theme);
-        MarkdownEngine::render(&renderer, &content)
-    })
-    .await
-    {
-        Ok(rendered) => rendered,
-        Err(e) => {
-            tracing::error!(error = %e, "preview render task failed");
-            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
-        }
-    };
-    Json(PreviewResponse {
-        html: rendered.html,
-        has_mermaid: rendered.has_mermaid,
-        has_math: rendered.has_math,
-    })
-    .into_response()
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::body::to_bytes;
-    use serde_json::json;
+    #[tokio::test]
+    async fn annotation_attachments_upload_and_serve_images_only() {
+        let root = tempfile::tempdir().unwrap();
+        let store = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("attachments".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(
+            Connection::open_in_memory().unwrap(),
+        ));
+        state.attachments_dir = Some(Arc::new(store.path().join("attachments")));
+        let upload = |body: &'static [u8]| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                handle_annotation_attachment_upload(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Collaborator)),
+                    axum::body::Bytes::from_static(body),
+                )
+                .await
+                .into_response()
+            }
+        };
+        let png: &'static [u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+        let created = upload(png).await;
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let created: serde_json::Value =
+            serde_json::from_str(&response_text(created).await).unwrap();
+        let attachment = created["id"].as_str().unwrap().to_string();
+        assert!(attachment.ends_with(".png"));
+        assert_eq!(
+            upload(b"<svg/onload=alert(1)>").await.status(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
 
-    use axum::http::HeaderMap;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-    use tower::ServiceExt;
+        let served = handle_annotation_attachment(
+            State(state.clone()),
+            AxumPath((id.clone(), attachment)),
+            Some(Extension(AccessRole::Collaborator)),
+        )
+        .await
+        .into_response();
+        assert_eq!(served.status(), StatusCode::OK);
+        assert_eq!(served.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(&response_bytes(served).await[..], png);
 
-    fn test_tera() -> Tera {
-        let mut tera = Tera::default();
-        for file_name in Templates::iter() {
-            let file = Templates::get(&file_name).expect("embedded template");
-            let content = std::str::from_utf8(&file.data).expect("utf-8 template");
-            tera.add_raw_template(&file_name, content)
-                .expect("template registration");
-        }
-        tera
+        let missing = handle_annotation_attachment(
+            State(state),
+            AxumPath((id, "../annotation.sqlite".into())),
+            Some(Extension(AccessRole::Collaborator)),
+        )
+        .await
+        .into_response();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
     }
 
-    fn test_state(registry: Arc<WorkspaceRegistry>) -> AppState {
-        AppState {
-            theme: Arc::new("light".into()),
-            tera: Arc::new(test_tera()),
-            db: None,
-            workspace_registry: registry,
-            management_token: Arc::new("test-token".into()),
-            admin_bootstraps: Arc::new(AdminBootstrapStore::new()),
-            allowed_hosts: Arc::new(build_allowed_hosts("127.0.0.1", "", 6419, &[], &[])),
-            save_token: Arc::new("save-token".into()),
-            i18n_json: Arc::new(i18n::load_i18n()),
-            i18n_lang: Arc::new("en".into()),
-            shortcuts_json: Arc::new("null".into()),
-            styles_css: Arc::new("".into()),
-            default_chat_mode: Arc::new("in_page".into()),
-            collaborator_access_code_hash: Arc::new(String::new()),
-            access_secret: Arc::new("test-salt".into()),
-            access_attempts: Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
-            markdown_diff_cache: Arc::new(Mutex::new(MarkdownDiffCache::default())),
-            print_collapsed_content: false,
-            #[cfg(debug_assertions)]
-            dev_reload_tx: Arc::new(broadcast::channel::<()>(1).0),
+    #[tokio::test]
+    async fn annotations_prune_reports_then_removes_deleted_files() {
+        let root = tempfile::tempdir().unwrap();
+        let canonical = dunce::canonicalize(root.path()).unwrap();
+        fs::write(canonical.join("kept.md"), "# kept").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("annotations-prune".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        for (annotation, file) in [("anno-1", "kept.md"), ("anno-2", "gone.md")] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, '{}')",
+                params![annotation, canonical.join(file).to_string_lossy()],
+            )
+            .unwrap();
         }
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+
+        let report = handle_annotations_prune_report(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+        )
+        .await
+        .into_response();
+        assert_eq!(report.status(), StatusCode::OK);
+        let report: serde_json::Value = serde_json::from_str(&response_text(report).await).unwrap();
+        assert_eq!(
+            report,
+            serde_json::json!({
+                "files": [{ "path": "gone.md", "annotations": 1, "viewed": false }],
+                "annotations": 1,
+            })
+        );
+
+        let pruned = handle_annotations_prune(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            Query(AnnotationPruneQuery { archive: false }),
+        )
+        .await
+        .into_response();
+        let pruned: serde_json::Value = serde_json::from_str(&response_text(pruned).await).unwrap();
+        assert_eq!(pruned, report);
+        let remaining: Vec<String> = state.db.as_ref().unwrap().read_blocking(|conn| {
+            let mut stmt = conn.prepare("SELECT id FROM annotations").unwrap();
+            stmt.query_map([], |row| row.get(0))
+                .unwrap()
+                .collect::<rusqlite::Result<_>>()
+                .unwrap()
+        });
+        assert_eq!(remaining, ["anno-1"]);
     }
 
-    fn add_test_workspace(
-        registry: &WorkspaceRegistry,
-        root: PathBuf,
-        flags: WorkspaceFlags,
-    ) -> String {
-        registry.add(WorkspaceConfig {
-            path: dunce::canonicalize(root).expect("canonical workspace root"),
-            flags,
-            single_file: None,
-            collaborator_access_code_hash: String::new(),
-            ..Default::default()
+    #[tokio::test]
+    async fn require_name_gates_writes_and_viewed_state_records_its_author() {
+        let root = tempfile::tempdir().unwrap();
+        let file = root.path().join("note.md");
+        fs::write(&file, "# note").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("document-identity".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let mut events = registry.get(&id).unwrap().events_tx.subscribe();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_by TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, sections TEXT);",
+        )
+        .unwrap();
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+        state.require_name = true;
+        let path = file.to_string_lossy().into_owned();
+        let send = |command: DocumentStateCommand| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                handle_document_state_command(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Collaborator)),
+                    Json(command),
+                )
+                .await
+            }
+        };
+        let annotation = |author: serde_json::Value| serde_json::json!({ "id": "anno-named", "text": "note", "author": author });
+
+        let anonymous = send(DocumentStateCommand::SaveAnnotation {
+            path: path.clone(),
+            annotation: annotation(serde_json::json!({ "color": "#f00" })),
+            by: None,
+            op_id: None,
+        })
+        .await;
+        assert_eq!(anonymous.status(), StatusCode::BAD_REQUEST);
+        let named = send(DocumentStateCommand::SaveAnnotation {
+            path: path.clone(),
+            annotation: annotation(serde_json::json!({ "color": "#f00", "name": "Ana" })),
+            by: None,
+            op_id: None,
+        })
+        .await;
+        assert_eq!(named.status(), StatusCode::OK);
+        let _ = events.try_recv();
+
+        let unnamed_view = send(DocumentStateCommand::SaveViewedState {
+            path: path.clone(),
+            state: serde_json::json!({ "intro": true }),
+            by: None,
+            op_id: None,
+        })
+        .await;
+        assert_eq!(unnamed_view.status(), StatusCode::BAD_REQUEST);
+        let by = serde_json::json!({ "color": "#0a0", "name": "Bo" });
+        let viewed = send(DocumentStateCommand::SaveViewedState {
+            path: path.clone(),
+            state: serde_json::json!({ "intro": true }),
+            by: Some(by.clone()),
+            op_id: None,
         })
+        .await;
+        assert_eq!(viewed.status(), StatusCode::NO_CONTENT);
+        match events.try_recv().unwrap() {
+            WorkspaceEvent::Channel { payload, .. } => {
+                let message: serde_json::Value = serde_json::from_str(&payload).unwrap();
+                assert_eq!(message["type"], "viewed_state");
+                assert_eq!(message["by"], by);
+            }
+            other => panic!("unexpected workspace event: {other:?}"),
+        }
+
+        let loaded = handle_document_state(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Collaborator)),
+            Query(DocumentStateQuery { path }),
+        )
+        .await;
+        let body: serde_json::Value = serde_json::from_str(&response_text(loaded).await).unwrap();
+        assert_eq!(body["viewed_state"], serde_json::json!({ "intro": true }));
+        assert_eq!(body["viewed_by"], by);
     }
 
     #[tokio::test]
-    async fn management_add_preserves_single_file_capability_and_alias() {
-        // Management moved off the TCP surface onto the control socket, so this
-        // exercises the socket dispatch. The single-file capability confinement
-        // (expose the one file, hide siblings) and the reject-multi-component
-        // guard are the same guarantees the old HTTP handler enforced.
-        use crate::control::proto::{ControlRequest, ControlResponse};
-        use crate::control::transport::{dispatch, ControlContext};
-
+    async fn private_viewed_state_is_keyed_by_the_client_cookie() {
         let root = tempfile::tempdir().unwrap();
-        std::fs::write(root.path().join("note.md"), "# note").unwrap();
-        std::fs::write(root.path().join("secret.md"), "secret").unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("single-file-api".into()));
-        let ctx = ControlContext {
-            registry: registry.clone(),
-            db: None,
-            shutdown: None,
-            admin_bootstrap: None,
-            admin_bootstrap_code: None,
+        let file = root.path().join("note.md");
+        fs::write(&file, "## Intro\nHello.\n").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("client-viewed".into()));
+        let flags = WorkspaceFlags {
+            shared_annotation: false,
+            ..all_flags()
         };
-        let add = |single_file: Option<&str>| {
-            dispatch(
-                ControlRequest::AddWorkspace {
-                    path: root.path().to_string_lossy().into_owned(),
-                    flags: WorkspaceFlags::default(),
-                    collaborator_access_code_hash: String::new(),
-                    single_file: single_file.map(str::to_string),
-                    alias: String::new(),
-                },
-                &ctx,
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), flags);
+        let mut state = test_state(registry.clone());
+        state.db = Some(crate::db::Database::open(&root.path().join("db.sqlite")).unwrap());
+        let path = file.to_string_lossy().into_owned();
+        let load = |headers: HeaderMap| {
+            handle_client_viewed_state(
+                State(state.clone()),
+                AxumPath(id.clone()),
+                Some(Extension(AccessRole::Collaborator)),
+                headers,
+                Query(DocumentStateQuery { path: path.clone() }),
             )
         };
-
-        let id = match add(Some("note.md")) {
-            ControlResponse::WorkspaceId(id) => id,
-            other => panic!("expected WorkspaceId, got {other:?}"),
+        let save = |headers: HeaderMap, viewed: serde_json::Value| {
+            handle_client_viewed_state_save(
+                State(state.clone()),
+                AxumPath(id.clone()),
+                Some(Extension(AccessRole::Collaborator)),
+                headers,
+                Json(ClientViewedStateSave {
+                    path: path.clone(),
+                    state: viewed,
+                }),
+            )
         };
-        assert!(matches!(
-            dispatch(
-                ControlRequest::SetAlias {
-                    id: id.clone(),
-                    alias: "Pinned note".into(),
-                },
-                &ctx,
-            ),
-            ControlResponse::Ok
-        ));
 
-        let info = registry
-            .info_list()
-            .into_iter()
-            .find(|info| info.id == id)
-            .unwrap();
-        assert!(info.ephemeral);
-        assert_eq!(info.single_file.as_deref(), Some("note.md"));
-        assert_eq!(info.alias, "Pinned note");
-        let entry = registry.get(&id).unwrap();
-        assert!(entry.fs.resolve_served("note.md").is_ok());
-        assert!(entry.fs.resolve_served("secret.md").is_err());
+        let first = load(HeaderMap::new()).await;
+        assert_eq!(first.status(), StatusCode::OK);
+        let set_cookie = first.headers()[axum::http::header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        assert!(set_cookie.contains("HttpOnly"), "{set_cookie}");
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        let mut headers = HeaderMap::new();
+        headers.insert(axum::http::header::COOKIE, cookie.parse().unwrap());
 
-        // A multi-component single_file is rejected and leaves the registry as-is.
-        assert!(matches!(add(Some("../secret.md")), ControlResponse::Err(_)));
-        assert_eq!(registry.info_list().len(), 1);
+        let intro = serde_json::json!({ "intro": true });
+        assert_eq!(
+            save(HeaderMap::new(), intro.clone()).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            save(headers.clone(), intro.clone()).await.status(),
+            StatusCode::NO_CONTENT
+        );
+        let loaded = load(headers).await;
+        assert!(!loaded
+            .headers()
+            .contains_key(axum::http::header::SET_COOKIE));
+        let body: serde_json::Value = serde_json::from_str(&response_text(loaded).await).unwrap();
+        assert_eq!(body["viewed_state"], intro);
+        let other: serde_json::Value =
+            serde_json::from_str(&response_text(load(HeaderMap::new()).await).await).unwrap();
+        assert_eq!(other["viewed_state"], serde_json::json!({}));
+
+        registry.update_flags(&id, all_flags());
+        assert_eq!(
+            load(HeaderMap::new()).await.status(),
+            StatusCode::FORBIDDEN,
+            "shared workspaces keep viewed state in the document state"
+        );
     }
 
-    async fn spawn_collaboration_test_server(
-        state: AppState,
-    ) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+    #[tokio::test]
+    async fn annotation_token_makes_shared_state_read_only_without_the_secret() {
+        let mut state = test_state(Arc::new(WorkspaceRegistry::new("annotation-token".into())));
+        state.annotation_token = Some(Arc::new("present".into()));
         let app = Router::new()
-            .route(WORKSPACE_WS_ROUTE, get(ws_handler))
-            .fallback(|| async { StatusCode::NOT_FOUND })
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                require_access_code,
-            ))
-            .with_state(state);
-        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
-        let addr = listener.local_addr().unwrap();
-        let task = tokio::spawn(async move {
-            let _ = axum::serve(
-                listener,
-                app.into_make_service_with_connect_info::<SocketAddr>(),
+            .route(
+                "/state",
+                get(|| async { StatusCode::OK }).post(|| async { StatusCode::NO_CONTENT }),
             )
-            .await;
-        });
-        (addr, task)
-    }
-
-    fn collaborator_access_scope_for(state: &AppState, id: &str) -> Option<(String, String)> {
-        access_requirements_for(state, id)
-            .into_iter()
-            .find(|req| req.role == AccessRole::Collaborator)
-            .map(|req| (req.hash, req.scope))
-    }
-
-    /// Repro for the reported "only the global code lets me into a workspace
-    /// that has its own code": the workspace collaborator code must override the
-    /// global one both live AND after a restart reseed (same id, code preserved).
-    #[test]
-    fn workspace_code_overrides_global_and_survives_reseed() {
-        let tmp = tempfile::tempdir().unwrap();
-        let root = dunce::canonicalize(tmp.path()).unwrap();
-        let salt = "test-salt";
-        let ws_hash = crate::workspace::hash_access_code(salt, "wsCode");
-        let global_hash = crate::workspace::hash_access_code(salt, "global");
-
-        let reg = Arc::new(WorkspaceRegistry::new(salt.into()));
-        let id = reg.add(WorkspaceConfig {
-            path: root.clone(),
-            flags: WorkspaceFlags::default(),
-            single_file: None,
-            collaborator_access_code_hash: String::new(),
-            ..Default::default()
-        });
-        assert!(reg.set_collaborator_access_code(&id, &ws_hash));
+            .route_layer(axum::middleware::from_fn_with_state(
+                state,
+                require_annotation_token,
+            ));
+        let call = |method: &str, token: Option<&str>, role: Option<AccessRole>| {
+            let mut request = axum::http::Request::builder().method(method).uri("/state");
+            if let Some(token) = token {
+                request = request.header(ANNOTATION_TOKEN_HEADER, token);
+            }
+            let mut request = request.body(axum::body::Body::empty()).unwrap();
+            if let Some(role) = role {
+                request.extensions_mut().insert(role);
+            }
+            let app = app.clone();
+            async move { app.oneshot(request).await.unwrap().status() }
+        };
 
-        let mut state = test_state(reg.clone());
-        state.collaborator_access_code_hash = Arc::new(global_hash.clone());
-        let (h, scope) = collaborator_access_scope_for(&state, &id).expect("workspace is gated");
+        let guest = Some(AccessRole::Collaborator);
+        assert_eq!(call("GET", None, guest).await, StatusCode::OK);
+        assert_eq!(call("POST", None, guest).await, StatusCode::FORBIDDEN);
         assert_eq!(
-            scope,
-            format!("w:{id}:collaborator"),
-            "must use the workspace scope"
+            call("POST", Some("wrong"), guest).await,
+            StatusCode::FORBIDDEN
         );
-        assert_eq!(h, ws_hash, "live: workspace code must win over global");
-
-        // Simulate a server restart: fresh registry reseeded from the persisted
-        // (path, collaborator_access_code_hash) must yield the SAME id and keep
-        // the code.
-        let reg2 = Arc::new(WorkspaceRegistry::new(salt.into()));
-        let id2 = reg2.add(WorkspaceConfig {
-            path: root,
-            flags: WorkspaceFlags::default(),
-            single_file: None,
-            collaborator_access_code_hash: ws_hash.clone(),
-            ..Default::default()
-        });
-        assert_eq!(id, id2, "workspace id must be stable across reseed");
         assert_eq!(
-            reg2.get(&id2).unwrap().collaborator_access_code_hash(),
-            ws_hash,
-            "code must survive the reseed"
+            call("POST", Some("present"), guest).await,
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(
+            call("POST", None, Some(AccessRole::Admin)).await,
+            StatusCode::NO_CONTENT
         );
-        let mut state2 = test_state(reg2);
-        state2.collaborator_access_code_hash = Arc::new(global_hash);
-        let (h2, scope2) =
-            collaborator_access_scope_for(&state2, &id2).expect("gated after reseed");
-        assert_eq!(scope2, format!("w:{id2}:collaborator"));
-        assert_eq!(h2, ws_hash, "after restart: workspace code must STILL win");
     }
 
-    /// Repro for "the correct workspace code shows no content": the unlock
-    /// cookie must round-trip a `w:{id}` scope. The scope itself contains a
-    /// colon, so a pair encodes as `w:{id}:{hash}`; decoding must split on the
-    /// LAST colon. With the old `split_once`, a workspace cookie decoded to
-    /// scope "w" and never matched its `w:{id}` gate — only the colon-free `s`
-    /// (global) scope worked, so entering the right workspace code looped back
-    /// to the gate while the global code got in.
     #[test]
-    fn access_cookie_round_trips_workspace_scope() {
-        let secret = "test-salt";
-        let scopes = vec![
-            ("w:1a2b3c4d".to_string(), "4f965".to_string()),
-            ("s".to_string(), "abcde".to_string()),
-        ];
-        let cookie = make_access_cookie(secret, &scopes, access_now_unix() + 1000, false);
-        let back = access_cookie_scopes(secret, Some(&cookie));
-        assert!(
-            back.contains(&("w:1a2b3c4d".to_string(), "4f965".to_string())),
-            "workspace scope must survive the cookie round-trip: {back:?}"
-        );
-        assert!(back.contains(&("s".to_string(), "abcde".to_string())));
+    fn access_cooldown_locks_after_threshold() {
+        let state = test_state(Arc::new(WorkspaceRegistry::new("s".into())));
+        let ip: std::net::IpAddr = "1.2.3.4".parse().unwrap();
+        for _ in 0..ACCESS_MAX_FAILS - 1 {
+            assert!(access_record_failure(&state, ip).is_none());
+        }
+        assert!(access_cooldown_remaining(&state, ip).is_none());
+        assert!(access_record_failure(&state, ip).is_some()); // crosses threshold → locks
+        assert!(access_cooldown_remaining(&state, ip).is_some());
+        access_record_success(&state, ip);
+        assert!(access_cooldown_remaining(&state, ip).is_none());
     }
 
     #[test]
-    fn access_cookie_resolves_collaborator_role() {
-        let tmp = tempfile::tempdir().unwrap();
-        let salt = "test-salt";
-        let collaborator_hash = crate::workspace::hash_access_code(salt, "guest-code");
-        let reg = Arc::new(WorkspaceRegistry::new(salt.into()));
-        let id = reg.add(WorkspaceConfig {
-            path: dunce::canonicalize(tmp.path()).unwrap(),
-            flags: WorkspaceFlags::default(),
-            single_file: None,
-            collaborator_access_code_hash: collaborator_hash.clone(),
-            ..Default::default()
-        });
-        let state = test_state(reg);
-
-        let collaborator_cookie = make_access_cookie(
-            salt,
-            &[(format!("w:{id}:collaborator"), collaborator_hash)],
-            access_now_unix() + 100,
-            false,
+    fn access_gated_workspace_recognizes_routes() {
+        assert_eq!(
+            access_gated_workspace("/abcd1234/doc.md").as_deref(),
+            Some("abcd1234")
         );
         assert_eq!(
-            access_role_from_cookie(&state, &id, Some(&collaborator_cookie)),
-            Some(AccessRole::Collaborator)
+            access_gated_workspace("/api/chat/abcd1234/threads").as_deref(),
+            Some("abcd1234")
+        );
+        assert_eq!(
+            access_gated_workspace("/_/ws/abcd1234").as_deref(),
+            Some("abcd1234")
+        );
+        assert_eq!(
+            access_gated_workspace("/_/abcd1234/ws").as_deref(),
+            Some("abcd1234")
+        );
+        assert!(access_gated_workspace("/_/ws").is_none());
+        assert_eq!(
+            access_gated_workspace("/_/abcd1234/git/diff/work").as_deref(),
+            Some("abcd1234")
+        );
+        assert_eq!(
+            access_gated_workspace("/_/abcd1234/search").as_deref(),
+            Some("abcd1234")
         );
+        for encoded in [
+            "/%61bcd1234/doc.md",
+            "/api/chat/%61bcd1234/threads",
+            "/_/ws/%61bcd1234",
+            "/_/%61bcd1234/ws",
+        ] {
+            assert_eq!(
+                access_gated_workspace(encoded).as_deref(),
+                Some("abcd1234"),
+                "encoded workspace id escaped the gate: {encoded}"
+            );
+        }
+        assert!(access_gated_workspace("/_/%2Fbad123/ws").is_none());
+        assert!(access_gated_workspace("/_/css/tokens.css").is_none());
+        assert!(access_gated_workspace("/_/unlock").is_none());
+        assert!(access_gated_workspace("/api/preview").is_none());
+        assert!(access_gated_workspace("/favicon.ico").is_none());
     }
 
-    async fn response_text(response: Response) -> String {
-        let bytes = response_bytes(response).await;
-        String::from_utf8(bytes.to_vec()).expect("utf-8 response")
-    }
+    #[tokio::test]
+    async fn workspace_ws_upgrade_requires_remote_access_cookie() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-access-gate".into()));
+        let id = add_test_workspace(
+            &registry,
+            root.path().to_path_buf(),
+            WorkspaceFlags {
+                enable_live: true,
+                ..Default::default()
+            },
+        );
+        let required_hash = "required-hash".to_string();
+        assert!(registry.set_collaborator_access_code(&id, &required_hash));
+        let state = test_state(registry);
+        let app = Router::new()
+            .route(WORKSPACE_WS_ROUTE, get(|| async { StatusCode::NO_CONTENT }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_access_code,
+            ));
 
-    async fn response_bytes(response: Response) -> axum::body::Bytes {
-        to_bytes(response.into_body(), usize::MAX)
-            .await
-            .expect("response body")
+        let request = axum::http::Request::builder()
+            .uri(format!("/_/{id}/ws"))
+            .header(header::UPGRADE, "websocket")
+            .header(header::ORIGIN, "http://markon.local")
+            .header(header::HOST, "markon.local")
+            .extension(axum::extract::ConnectInfo(lan_peer()))
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let encoded_id = format!("%{:02X}{}", id.as_bytes()[0], &id[1..]);
+        let encoded_request = axum::http::Request::builder()
+            .uri(format!("/_/{encoded_id}/ws"))
+            .header(header::UPGRADE, "websocket")
+            .header(header::ORIGIN, "http://markon.local")
+            .header(header::HOST, "markon.local")
+            .extension(axum::extract::ConnectInfo(lan_peer()))
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let encoded_response = app.clone().oneshot(encoded_request).await.unwrap();
+        assert_eq!(encoded_response.status(), StatusCode::UNAUTHORIZED);
+
+        let cookie = make_access_cookie(
+            &state.access_secret,
+            &[(format!("w:{id}:collaborator"), required_hash)],
+            access_now_unix() + 60,
+            false,
+        );
+        let request = axum::http::Request::builder()
+            .uri(format!("/_/{id}/ws"))
+            .header(header::UPGRADE, "websocket")
+            .header(header::COOKIE, cookie)
+            .extension(axum::extract::ConnectInfo(lan_peer()))
+            .body(axum::body::Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
     }
 
-    fn all_flags() -> WorkspaceFlags {
-        WorkspaceFlags {
-            enable_search: true,
-            enable_viewed: true,
-            enable_edit: true,
+    #[tokio::test]
+    async fn workspace_ws_route_and_broadcast_are_actually_isolated() {
+        use tokio_tungstenite::tungstenite::{Error as WsError, Message as ClientMessage};
+
+        let root_a = tempfile::tempdir().unwrap();
+        let root_b = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-integration".into()));
+        let live_flags = WorkspaceFlags {
             enable_live: true,
-            enable_chat: true,
-            shared_annotation: true,
-        }
-    }
+            ..Default::default()
+        };
+        let id_a = add_test_workspace(&registry, root_a.path().to_path_buf(), live_flags);
+        let id_b = add_test_workspace(&registry, root_b.path().to_path_buf(), live_flags);
+        let (addr, server) = spawn_collaboration_test_server(test_state(registry.clone())).await;
 
-    fn headers_with(origin: Option<&str>, host: Option<&str>) -> HeaderMap {
-        let mut h = HeaderMap::new();
-        if let Some(o) = origin {
-            h.insert("origin", o.parse().unwrap());
-        }
-        if let Some(host) = host {
-            h.insert("host", host.parse().unwrap());
-        }
-        h
-    }
+        let (mut socket_a, _) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
+                .await
+                .unwrap();
+        let (mut socket_a_other_channel, _) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
+                .await
+                .unwrap();
+        let (mut socket_b, _) =
+            tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_b}/ws"))
+                .await
+                .unwrap();
 
-    fn save_headers(state: &AppState, workspace_id: &str) -> HeaderMap {
-        let mut headers = HeaderMap::new();
-        headers.insert(
-            "X-Markon-Token",
-            workspace_save_token(&state.save_token, workspace_id)
-                .parse()
-                .unwrap(),
+        socket_a
+            .send(ClientMessage::Text(
+                serde_json::json!({
+                    "type": "hello",
+                    "target": { "kind": "surface", "key": format!("/{id_a}/") }
+                })
+                .to_string()
+                .into(),
+            ))
+            .await
+            .unwrap();
+        socket_a_other_channel
+            .send(ClientMessage::Text(
+                serde_json::json!({
+                    "type": "hello",
+                    "target": {
+                        "kind": "surface",
+                        "key": format!("/_/{id_a}/compare")
+                    }
+                })
+                .to_string()
+                .into(),
+            ))
+            .await
+            .unwrap();
+        socket_b
+            .send(ClientMessage::Text(
+                serde_json::json!({
+                    "type": "hello",
+                    "target": { "kind": "surface", "key": format!("/{id_b}/") }
+                })
+                .to_string()
+                .into(),
+            ))
+            .await
+            .unwrap();
+
+        socket_a
+            .send(ClientMessage::Text(
+                r#"{"type":"live_action","data":{"action":"focus"}}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let echoed = tokio::time::timeout(std::time::Duration::from_secs(2), socket_a.next())
+            .await
+            .expect("same channel should receive live event")
+            .unwrap()
+            .unwrap();
+        assert!(echoed.to_text().unwrap().contains("live_action"));
+        assert!(tokio::time::timeout(
+            std::time::Duration::from_millis(150),
+            socket_a_other_channel.next()
+        )
+        .await
+        .is_err());
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(150), socket_b.next())
+                .await
+                .is_err()
         );
-        headers
-    }
 
-    fn loopback() -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1618)
+        let legacy = tokio_tungstenite::connect_async(format!("ws://{addr}/_/ws"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(legacy, WsError::Http(response) if response.status() == StatusCode::NOT_FOUND)
+        );
+
+        assert!(registry.remove(&id_a));
+        let detached = tokio::time::timeout(std::time::Duration::from_secs(2), socket_a.next())
+            .await
+            .expect("detaching a workspace must close existing sockets");
+        assert!(matches!(
+            detached,
+            None | Some(Ok(ClientMessage::Close(_))) | Some(Err(_))
+        ));
+        server.abort();
     }
 
     #[tokio::test]
-    async fn headerless_not_found_is_browser_safe_and_bodyless() {
-        let app = Router::new()
-            .fallback(|| async { StatusCode::NOT_FOUND })
-            .layer(axum::middleware::from_fn(security_headers));
-
-        let response = app
-            .oneshot(
-                axum::http::Request::builder()
-                    .uri("/")
-                    .body(axum::body::Body::empty())
-                    .unwrap(),
-            )
-            .await
-            .unwrap();
+    async fn workspace_ws_handshake_rejects_workspace_without_features() {
+        use tokio_tungstenite::tungstenite::Error as WsError;
 
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
-        assert_eq!(
-            response.headers().get(header::CONTENT_TYPE).unwrap(),
-            "text/plain; charset=utf-8"
+        let root = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-disabled".into()));
+        let id = add_test_workspace(
+            &registry,
+            root.path().to_path_buf(),
+            WorkspaceFlags::default(),
         );
-        assert_eq!(
-            response
-                .headers()
-                .get(header::X_CONTENT_TYPE_OPTIONS)
-                .unwrap(),
-            "nosniff"
+        let (addr, server) = spawn_collaboration_test_server(test_state(registry)).await;
+        let error = tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id}/ws"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, WsError::Http(response) if response.status() == StatusCode::FORBIDDEN)
         );
-        assert!(response
-            .headers()
-            .get(header::CONTENT_DISPOSITION)
-            .is_none());
-        assert!(response_bytes(response).await.is_empty());
+        server.abort();
     }
 
     #[tokio::test]
-    async fn not_found_preserves_an_explicit_content_type() {
-        let app = Router::new()
-            .fallback(|| async {
-                (
-                    StatusCode::NOT_FOUND,
-                    [(header::CONTENT_TYPE, "application/problem+json")],
-                    "{}",
+    async fn live_only_document_receives_no_stored_data_and_rejects_foreign_path() {
+        use tokio_tungstenite::tungstenite::Message as ClientMessage;
+
+        let root_a = tempfile::tempdir().unwrap();
+        let root_b = tempfile::tempdir().unwrap();
+        let document_a = root_a.path().join("a.md");
+        let document_b = root_b.path().join("b.md");
+        fs::write(&document_a, "# a").unwrap();
+        fs::write(&document_b, "# b").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-live-only".into()));
+        let id_a = add_test_workspace(
+            &registry,
+            root_a.path().to_path_buf(),
+            WorkspaceFlags {
+                enable_live: true,
+                shared_annotation: false,
+                ..Default::default()
+            },
+        );
+        let _id_b = add_test_workspace(&registry, root_b.path().to_path_buf(), all_flags());
+
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_by TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, sections TEXT)",
+            [],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO annotations (id, file_path, data) VALUES ('secret', ?1, '{\"id\":\"secret\"}')",
+            [dunce::canonicalize(&document_a)
+                .unwrap()
+                .to_string_lossy()
+                .as_ref()],
+        )
+        .unwrap();
+        let db = crate::db::Database::from_connection(conn);
+        let mut state = test_state(registry);
+        state.db = Some(db.clone());
+        let (addr, server) = spawn_collaboration_test_server(state).await;
+
+        let (mut valid, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
+            .await
+            .unwrap();
+        valid
+            .send(ClientMessage::Text(
+                serde_json::json!({
+                    "type": "hello",
+                    "target": {
+                        "kind": "document",
+                        "path": document_a.to_string_lossy()
+                    }
+                })
+                .to_string()
+                .into(),
+            ))
+            .await
+            .unwrap();
+        // Only presence arrives: our own snapshot, then our join broadcast.
+        for expected in ["presence", "presence_join"] {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(2), valid.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            let frame: serde_json::Value = serde_json::from_str(frame.to_text().unwrap()).unwrap();
+            assert_eq!(frame["type"], expected);
+        }
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(150), valid.next())
+                .await
+                .is_err()
+        );
+        valid
+            .send(ClientMessage::Text(
+                r#"{"type":"live_action","data":{"action":"focus"}}"#.into(),
+            ))
+            .await
+            .unwrap();
+        let live = tokio::time::timeout(std::time::Duration::from_secs(2), valid.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(live.to_text().unwrap().contains("live_action"));
+        valid
+            .send(ClientMessage::Text(
+                r#"{"type":"new_annotation","annotation":{"id":"forbidden"}}"#.into(),
+            ))
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let forbidden_count: i64 = db
+            .read_blocking(|conn| {
+                conn.query_row(
+                    "SELECT COUNT(*) FROM annotations WHERE id = 'forbidden'",
+                    [],
+                    |row| row.get(0),
                 )
             })
-            .layer(axum::middleware::from_fn(security_headers));
+            .unwrap();
+        assert_eq!(forbidden_count, 0);
 
-        let response = app
-            .oneshot(
-                axum::http::Request::builder()
-                    .uri("/missing")
-                    .body(axum::body::Body::empty())
-                    .unwrap(),
-            )
+        let (mut foreign, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
             .await
             .unwrap();
-
-        assert_eq!(response.status(), StatusCode::NOT_FOUND);
-        assert_eq!(
-            response.headers().get(header::CONTENT_TYPE).unwrap(),
-            "application/problem+json"
-        );
-        assert_eq!(response_text(response).await, "{}");
+        foreign
+            .send(ClientMessage::Text(
+                serde_json::json!({
+                    "type": "hello",
+                    "target": {
+                        "kind": "document",
+                        "path": document_b.to_string_lossy()
+                    }
+                })
+                .to_string()
+                .into(),
+            ))
+            .await
+            .unwrap();
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(2), foreign.next())
+            .await
+            .expect("foreign path must be rejected before any data is sent");
+        assert!(matches!(
+            closed,
+            None | Some(Ok(ClientMessage::Close(_))) | Some(Err(_))
+        ));
+        server.abort();
     }
 
-    fn lan_peer() -> SocketAddr {
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 50)), 51234)
-    }
+    #[tokio::test]
+    async fn document_selections_are_stamped_and_throttled() {
+        use tokio_tungstenite::tungstenite::Message as ClientMessage;
+        type Socket = tokio_tungstenite::WebSocketStream<
+            tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+        >;
 
-    #[test]
-    fn ws_origin_accepts_matching_authority() {
-        let h = headers_with(Some("http://192.168.1.10:1618"), Some("192.168.1.10:1618"));
-        assert!(check_ws_origin(&h, &lan_peer()));
-    }
+        async fn next_json(socket: &mut Socket) -> serde_json::Value {
+            let frame = tokio::time::timeout(std::time::Duration::from_secs(2), socket.next())
+                .await
+                .unwrap()
+                .unwrap()
+                .unwrap();
+            serde_json::from_str(frame.to_text().unwrap()).unwrap()
+        }
 
-    #[test]
-    fn ws_origin_rejects_cross_origin() {
-        let h = headers_with(Some("http://evil.example.com"), Some("192.168.1.10:1618"));
-        assert!(!check_ws_origin(&h, &lan_peer()));
-    }
+        let root = tempfile::tempdir().unwrap();
+        let document = root.path().join("a.md");
+        fs::write(&document, "# a").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("ws-selection".into()));
+        let id = add_test_workspace(
+            &registry,
+            root.path().to_path_buf(),
+            WorkspaceFlags {
+                enable_live: true,
+                ..Default::default()
+            },
+        );
+        let (addr, server) = spawn_collaboration_test_server(test_state(registry)).await;
 
-    #[test]
-    fn ws_origin_rejects_port_mismatch() {
-        let h = headers_with(Some("http://127.0.0.1:9000"), Some("127.0.0.1:1618"));
-        assert!(!check_ws_origin(&h, &loopback()));
-    }
+        // Join one at a time so the presence frames arrive in a known order.
+        let mut sockets = Vec::new();
+        let mut client_ids = Vec::new();
+        for name in ["Ada", "Bo"] {
+            let (mut socket, _) =
+                tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id}/ws"))
+                    .await
+                    .unwrap();
+            socket
+                .send(ClientMessage::Text(
+                    serde_json::json!({
+                        "type": "hello",
+                        "target": { "kind": "document", "path": document.to_string_lossy() },
+                        "identity": { "color": "#0EA5E9", "name": name }
+                    })
+                    .to_string()
+                    .into(),
+                ))
+                .await
+                .unwrap();
+            let snapshot = next_json(&mut socket).await;
+            assert_eq!(snapshot["type"], "presence");
+            assert_eq!(
+                snapshot["viewers"].as_array().unwrap().len(),
+                sockets.len() + 1
+            );
+            client_ids.push(snapshot["client_id"].clone());
+            assert_eq!(next_json(&mut socket).await["type"], "presence_join");
+            sockets.push(socket);
+        }
+        let joined = next_json(&mut sockets[0]).await;
+        assert_eq!(joined["viewer"]["client_id"], client_ids[1]);
+        assert_eq!(joined["viewer"]["identity"]["name"], "Bo");
+        assert_eq!(joined["viewers"], 2);
+
+        // A burst collapses to its first and last selection.
+        for offset in 0..5 {
+            sockets[0]
+                .send(ClientMessage::Text(
+                    serde_json::json!({
+                        "type": "selection",
+                        "range": {
+                            "startPath": "//article[1]/H1[1]",
+                            "startOffset": 0,
+                            "endPath": "//article[1]/H1[1]",
+                            "endOffset": offset
+                        }
+                    })
+                    .to_string()
+                    .into(),
+                ))
+                .await
+                .unwrap();
+        }
+        let first = next_json(&mut sockets[1]).await;
+        assert_eq!(first["type"], "selection");
+        assert_eq!(first["client_id"], client_ids[0]);
+        assert_eq!(first["identity"]["name"], "Ada");
+        assert_eq!(first["range"]["endOffset"], 0);
+        assert_eq!(next_json(&mut sockets[1]).await["range"]["endOffset"], 4);
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(250), sockets[1].next())
+                .await
+                .is_err()
+        );
 
-    #[test]
-    fn ws_origin_rejects_null_origin() {
-        let h = headers_with(Some("null"), Some("127.0.0.1:1618"));
-        assert!(!check_ws_origin(&h, &loopback()));
+        // Leaving tells the others whose selection to drop.
+        let ada = sockets.remove(0);
+        drop(ada);
+        let left = next_json(&mut sockets[0]).await;
+        assert_eq!(left["type"], "presence_leave");
+        assert_eq!(left["client_id"], client_ids[0]);
+        assert_eq!(left["viewers"], 1);
+        server.abort();
     }
 
     #[test]
-    fn ws_missing_origin_allowed_only_from_loopback() {
-        let h = headers_with(None, Some("127.0.0.1:1618"));
-        assert!(check_ws_origin(&h, &loopback()));
-        assert!(!check_ws_origin(&h, &lan_peer()));
+    fn canonical_route_helpers_keep_file_and_tool_spaces_separate() {
+        assert_eq!(workspace_root_url("", "abcd1234"), "/abcd1234/");
+        assert_eq!(
+            workspace_file_url("", "abcd1234", "docs/readme.md"),
+            "/abcd1234/docs/readme.md"
+        );
+        assert_eq!(
+            workspace_file_url("", "abcd1234", "docs/a b#c?.md"),
+            "/abcd1234/docs/a%20b%23c%3F.md"
+        );
+        assert_eq!(
+            workspace_internal_url("", "abcd1234", "git/history"),
+            "/_/abcd1234/git/history"
+        );
+        assert_eq!(
+            workspace_internal_url("", "abcd1234", "ws"),
+            "/_/abcd1234/ws"
+        );
+        assert_eq!(
+            workspace_compare_base_url("", "abcd1234"),
+            "/_/abcd1234/compare"
+        );
     }
 
     #[test]
-    fn save_origin_allows_lan_same_origin_but_not_missing_or_cross_origin() {
-        let same_origin = headers_with(
-            Some("http://192.168.1.13:59285"),
-            Some("192.168.1.13:59285"),
+    fn featured_base_url_loopback_and_specific_are_network_independent() {
+        // These paths don't enumerate interfaces, so the public wrappers are
+        // deterministic regardless of the machine running the test.
+        assert_eq!(
+            featured_base_url("127.0.0.1", "", 6419),
+            "http://127.0.0.1:6419"
+        );
+        assert_eq!(
+            featured_base_url("198.51.100.7", "", 6419),
+            "http://198.51.100.7:6419"
+        );
+        let r = reachable_urls("127.0.0.1", "", 6419);
+        assert_eq!(r.all.len(), 1);
+        assert_eq!(r.featured, "http://127.0.0.1:6419");
+        assert_eq!(
+            local_browser_base_url("0.0.0.0", 6419),
+            "http://127.0.0.1:6419"
+        );
+        assert_eq!(local_browser_base_url("::", 6419), "http://[::1]:6419");
+        assert_eq!(
+            local_browser_base_url("198.51.100.7", 6419),
+            "http://198.51.100.7:6419"
         );
-        assert!(same_origin_or_loopback_no_origin(&same_origin, &lan_peer()));
-
-        let cross_origin =
-            headers_with(Some("http://evil.example.com"), Some("192.168.1.13:59285"));
-        assert!(!same_origin_or_loopback_no_origin(
-            &cross_origin,
-            &lan_peer()
-        ));
-
-        let missing_origin = headers_with(None, Some("192.168.1.13:59285"));
-        assert!(!same_origin_or_loopback_no_origin(
-            &missing_origin,
-            &lan_peer()
-        ));
-        assert!(same_origin_or_loopback_no_origin(
-            &missing_origin,
-            &loopback()
-        ));
     }
 
+    #[cfg(target_os = "windows")]
     #[test]
-    fn ws_origin_case_insensitive_host_match() {
-        let h = headers_with(
-            Some("http://Example.Local:1618"),
-            Some("example.local:1618"),
+    fn canonicalize_route_path_strips_windows_verbatim_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let std_path = std::fs::canonicalize(dir.path()).unwrap();
+        let route_path = canonicalize_route_path(dir.path()).unwrap();
+
+        assert!(
+            std_path.to_string_lossy().starts_with(r"\\?\"),
+            "test expects Windows std::fs::canonicalize to return verbatim paths, got {std_path:?}"
+        );
+        assert!(
+            !route_path.to_string_lossy().starts_with(r"\\?\"),
+            "route canonicalization must match workspace roots stored through dunce: {route_path:?}"
         );
-        assert!(check_ws_origin(&h, &loopback()));
     }
 
     #[tokio::test]
-    async fn preview_route_requires_origin_and_scoped_capability() {
-        use axum::body::Body;
-        use axum::http::Request;
+    async fn workspace_path_handler_renders_markdown_inside_workspace() {
+        let dir = tempfile::tempdir().unwrap();
+        let docs = dir.path().join("docs");
+        fs::create_dir(&docs).unwrap();
+        let file = docs.join("EVDI_IMPLEMENTATION_PLAN.md");
+        fs::write(&file, "# Windows route check\n\nalpha beta gamma").unwrap();
 
-        let registry = Arc::new(WorkspaceRegistry::new("preview-guard-test".into()));
+        let registry = Arc::new(WorkspaceRegistry::new("route-test".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
         let state = test_state(registry);
-        let workspace_id = "deadbeef";
-        let preview_token = workspace_preview_token(&state.save_token, workspace_id);
-        let save_token = workspace_save_token(&state.save_token, workspace_id);
-        assert_ne!(preview_token, save_token);
-        let app = Router::new()
-            .route("/api/preview", post(preview_handler))
-            .layer(axum::extract::DefaultBodyLimit::max(PREVIEW_BODY_LIMIT))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                require_local_save_origin,
-            ))
-            .with_state(state);
-
-        let build = |origin: Option<&str>,
-                     host: &str,
-                     peer: SocketAddr,
-                     token: Option<&str>,
-                     content: String| {
-            let mut b = Request::builder()
-                .method("POST")
-                .uri("/api/preview")
-                .header("host", host)
-                .header("content-type", "application/json");
-            if let Some(o) = origin {
-                b = b.header("origin", o);
-            }
-            if let Some(token) = token {
-                b = b.header("X-Markon-Token", token);
-            }
-            let mut req = b
-                .body(Body::from(
-                    json!({ "workspace_id": workspace_id, "content": content }).to_string(),
-                ))
-                .unwrap();
-            req.extensions_mut()
-                .insert(axum::extract::ConnectInfo(peer));
-            req
-        };
-
-        // Cross-site page from the LAN → rejected.
-        let resp = app
-            .clone()
-            .oneshot(build(
-                Some("http://evil.example.com"),
-                "192.168.1.13:6419",
-                lan_peer(),
-                Some(&preview_token),
-                "# hi".into(),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
-
-        // Anonymous LAN device (no Origin, not loopback) → rejected even with a
-        // valid token, because browser capabilities do not replace the origin
-        // boundary.
-        let resp = app
-            .clone()
-            .oneshot(build(
-                None,
-                "192.168.1.13:6419",
-                lan_peer(),
-                Some(&preview_token),
-                "# hi".into(),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
 
-        // A LAN client can spoof a matching Origin, so origin alone is not an
-        // authentication boundary.
-        let resp = app
-            .clone()
-            .oneshot(build(
-                Some("http://192.168.1.13:6419"),
-                "192.168.1.13:6419",
-                lan_peer(),
-                None,
-                "# hi".into(),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let response = handle_workspace_path(
+            State(state),
+            AxumPath((id.clone(), "docs/EVDI_IMPLEMENTATION_PLAN.md".to_string())),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = html_escape::decode_html_entities(&response_text(response).await).to_string();
+        assert!(body.contains("Windows route check"));
+        assert!(body.contains("alpha beta gamma"));
+        assert!(body.contains("enable-edit"));
+        assert!(body.contains("enable-search"));
+        assert!(body.contains("window.MarkonTheme"));
+        assert!(body.contains("<title>EVDI_IMPLEMENTATION_PLAN.md</title>"));
+        assert!(!body.contains("<title>markon - EVDI_IMPLEMENTATION_PLAN.md</title>"));
+        assert!(body.contains(&format!("href=\"/{id}/#docs/EVDI_IMPLEMENTATION_PLAN.md\"")));
+        let root = canonicalize_route_path(dir.path()).unwrap();
+        let workspace_name = root
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let workspace_path = workspace_display_path(&root);
+        assert!(body.contains(&format!(
+            "class=\"workspace-back-name\">{workspace_name}</span>"
+        )));
+        assert!(body.contains(&format!(
+            "class=\"workspace-back-path\">{workspace_path}</span>"
+        )));
+        assert!(!body.contains("id=\"back-link-text\""));
+        assert!(!body.contains(&format!("/_/{id}/git/history")));
+    }
 
-        // Save and preview capabilities are deliberately not interchangeable.
-        let resp = app
-            .clone()
-            .oneshot(build(
-                Some("http://127.0.0.1:6419"),
-                "127.0.0.1:6419",
-                loopback(),
-                Some(&save_token),
-                "# hi".into(),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    #[tokio::test]
+    async fn workspace_path_handler_renders_text_file_as_content_only_view() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.txt"), "alpha\nbeta").unwrap();
 
-        // Same-origin editor page with its workspace-scoped preview capability
-        // is allowed.
-        let resp = app
-            .clone()
-            .oneshot(build(
-                Some("http://127.0.0.1:6419"),
-                "127.0.0.1:6419",
-                loopback(),
-                Some(&preview_token),
-                "# hi".into(),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(resp.status(), StatusCode::OK);
+        let registry = Arc::new(WorkspaceRegistry::new("text-preview-test".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
 
-        // Capability possession does not bypass the route-specific body cap.
-        let resp = app
-            .oneshot(build(
-                Some("http://127.0.0.1:6419"),
-                "127.0.0.1:6419",
-                loopback(),
-                Some(&preview_token),
-                "x".repeat(PREVIEW_BODY_LIMIT),
-            ))
-            .await
-            .unwrap();
-        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let response = handle_workspace_path(
+            State(state),
+            AxumPath((id, "notes.txt".to_string())),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await;
+        assert!(body.contains("class=\"code-view\""), "{body}");
+        assert!(body.contains("alpha"), "{body}");
+        assert!(body.contains("beta"), "{body}");
+        assert!(body.contains("<a id=\"L2\" href=\"#L2\">2</a>"), "{body}");
+        assert!(!body.contains("id=\"L3\""), "{body}");
+        assert!(!body.contains("class=\"fv-back\""), "{body}");
+        assert!(!body.contains("class=\"fv-head\""), "{body}");
+        assert!(!body.contains("Back to workspace"), "{body}");
+        assert!(!body.contains("notes.txt</span>"), "{body}");
     }
 
-    #[test]
-    fn ws_origin_with_trailing_path_still_matches_authority() {
-        // Defensive: spec says Origin has no path, but some clients append one.
-        let h = headers_with(Some("http://127.0.0.1:1618/"), Some("127.0.0.1:1618"));
-        assert!(check_ws_origin(&h, &loopback()));
-    }
+    #[tokio::test]
+    async fn workspace_ls_reports_metadata_and_document_state_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("README.md"), "# Readme").unwrap();
+        fs::write(dir.path().join("data.txt"), "12345").unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
 
-    #[test]
-    fn ws_hello_requires_structured_non_legacy_protocol() {
-        let hello: WsHello = serde_json::from_str(
-            r#"{"type":"hello","target":{"kind":"surface","key":"/abcd1234/"}}"#,
+        let registry = Arc::new(WorkspaceRegistry::new("ls-test".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let readme = dunce::canonicalize(dir.path().join("README.md")).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_by TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, sections TEXT);",
         )
         .unwrap();
-        assert!(matches!(hello.target, WsTarget::Surface { .. }));
-        assert!(serde_json::from_str::<WsHello>(r#""/tmp/workspace/doc.md""#).is_err());
-        assert!(serde_json::from_str::<WsHello>(
-            r#"{"type":"legacy","target":{"kind":"surface","key":"/abcd1234/"}}"#
+        let readme_path = readme.to_string_lossy().into_owned();
+        for anno in ["anno-a", "anno-b"] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, '{}')",
+                params![anno, readme_path],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO viewed_state (file_path, state) VALUES (?1, ?2)",
+            params![readme_path, r#"{"intro":true,"usage":false}"#],
         )
-        .is_err());
-    }
-
-    #[test]
-    fn ws_document_target_is_canonical_and_workspace_scoped() {
-        let root = tempfile::tempdir().unwrap();
-        let outside = tempfile::tempdir().unwrap();
-        let document = root.path().join("note.md");
-        let outside_file = outside.path().join("secret.md");
-        fs::write(&document, "# note").unwrap();
-        fs::write(&outside_file, "secret").unwrap();
+        .unwrap();
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+
+        let list = |role: Option<AccessRole>, path: Option<&str>| {
+            handle_workspace_ls(
+                State(state.clone()),
+                AxumPath(id.clone()),
+                role.map(Extension),
+                Query(DirListingQuery {
+                    path: path.map(str::to_string),
+                    depth: None,
+                    ..Default::default()
+                }),
+            )
+        };
 
-        let registry = Arc::new(WorkspaceRegistry::new("ws-document-scope".into()));
-        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
-        let entry = registry.get(&id).unwrap();
-        let session = authorize_ws_target(
-            &entry,
-            WsTarget::Document {
-                path: document.to_string_lossy().into_owned(),
-            },
-        )
-        .expect("workspace document should be authorized");
-        let canonical = dunce::canonicalize(&document)
+        let response = list(Some(AccessRole::Admin), None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let entries: serde_json::Value =
+            serde_json::from_str(&response_text(response).await).unwrap();
+        let entries = entries.as_array().unwrap();
+        assert_eq!(entries[0]["name"], "docs");
+        assert_eq!(entries[0]["type"], "dir");
+        assert!(entries[0]["size"].is_null());
+        let data = entries.iter().find(|e| e["name"] == "data.txt").unwrap();
+        assert_eq!(data["type"], "file");
+        assert_eq!(data["size"], 5);
+        assert!(data["mtime"].as_u64().unwrap() > 0);
+        assert!(data["annotation_count"].is_null());
+        let readme = entries.iter().find(|e| e["name"] == "README.md").unwrap();
+        assert_eq!(readme["annotation_count"], 2);
+        assert_eq!(readme["viewed_count"], 1);
+
+        let response = list(None, None).await;
+        let entries: serde_json::Value =
+            serde_json::from_str(&response_text(response).await).unwrap();
+        let readme = entries
+            .as_array()
             .unwrap()
-            .to_string_lossy()
-            .into_owned();
+            .iter()
+            .find(|e| e["name"] == "README.md")
+            .unwrap();
+        assert!(readme["annotation_count"].is_null());
+
         assert_eq!(
-            session.target,
-            WsSessionTarget::Document {
-                file_path: canonical.clone()
-            }
+            list(Some(AccessRole::Admin), Some("../")).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            list(Some(AccessRole::Admin), Some("data.txt"))
+                .await
+                .status(),
+            StatusCode::NOT_FOUND
         );
-        assert_eq!(session.channel, format!("document:{canonical}"));
-
-        assert!(authorize_ws_target(
-            &entry,
-            WsTarget::Document {
-                path: outside_file.to_string_lossy().into_owned(),
-            }
-        )
-        .is_none());
-        assert!(authorize_ws_target(
-            &entry,
-            WsTarget::Document {
-                path: "note.md".into(),
-            }
-        )
-        .is_none());
     }
 
-    #[test]
-    fn ws_document_target_obeys_single_file_capability() {
-        let root = tempfile::tempdir().unwrap();
-        let pinned = root.path().join("pinned.md");
-        let sibling = root.path().join("sibling.md");
-        fs::write(&pinned, "# pinned").unwrap();
-        fs::write(&sibling, "# sibling").unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("ws-single-file".into()));
-        let id = registry.add(WorkspaceConfig {
-            path: dunce::canonicalize(root.path()).unwrap(),
-            flags: all_flags(),
-            single_file: Some("pinned.md".into()),
-            collaborator_access_code_hash: String::new(),
-            alias: String::new(),
-        });
-        let entry = registry.get(&id).unwrap();
+    #[tokio::test]
+    async fn reading_progress_counts_viewed_sections() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("README.md"),
+            "# Readme\n\n## Intro\n\n## Usage\n\n### Flags\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("untouched.md"), "# Untouched\n\n## Intro\n").unwrap();
+        fs::create_dir(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs/guide.md"), "## Setup\n\n## Use\n").unwrap();
 
-        assert!(authorize_ws_target(
-            &entry,
-            WsTarget::Document {
-                path: pinned.to_string_lossy().into_owned(),
-            }
+        let registry = Arc::new(WorkspaceRegistry::new("progress-test".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let key = |rel: &str| {
+            dunce::canonicalize(dir.path().join(rel))
+                .unwrap()
+                .to_string_lossy()
+                .into_owned()
+        };
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_by TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, sections TEXT);",
         )
-        .is_some());
-        assert!(authorize_ws_target(
-            &entry,
-            WsTarget::Document {
-                path: sibling.to_string_lossy().into_owned(),
+        .unwrap();
+        // `gone` is a heading that no longer exists; the h1 is not a section.
+        for (rel, viewed) in [
+            (
+                "README.md",
+                r#"{"readme":true,"intro":true,"usage":false,"gone":true}"#,
+            ),
+            ("docs/guide.md", r#"{"setup":true,"use":true}"#),
+        ] {
+            conn.execute(
+                "INSERT INTO viewed_state (file_path, state) VALUES (?1, ?2)",
+                params![key(rel), viewed],
+            )
+            .unwrap();
+        }
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+
+        let progress = |role: Option<AccessRole>, path: Option<String>| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                handle_progress(
+                    State(state),
+                    AxumPath(id),
+                    role.map(Extension),
+                    Query(AnnotationSummaryQuery { path }),
+                )
+                .await
+                .into_response()
             }
-        )
-        .is_none());
-    }
+        };
+        let json = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_str::<serde_json::Value>(&response_text(response).await).unwrap()
+        };
+        let body = json(progress(Some(AccessRole::Admin), None).await).await;
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "files": [
+                    { "path": "README.md", "viewed": 1, "total": 3, "percent": 33 },
+                    { "path": "docs/guide.md", "viewed": 2, "total": 2, "percent": 100 },
+                ],
+                "viewed": 3,
+                "total": 5,
+            })
+        );
+        let body = json(progress(Some(AccessRole::Admin), Some(key("docs/guide.md"))).await).await;
+        assert_eq!(body["files"].as_array().unwrap().len(), 1);
+        assert_eq!(progress(None, None).await.status(), StatusCode::FORBIDDEN);
 
-    #[test]
-    fn ws_surface_target_is_live_only_and_bound_to_workspace_url() {
-        let root = tempfile::tempdir().unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("ws-surface".into()));
-        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
-        let entry = registry.get(&id).unwrap();
-        let surface = authorize_ws_target(
-            &entry,
-            WsTarget::Surface {
-                key: format!("/_/{id}/compare?base=main#change"),
-            },
+        // Listing rows carry the same numbers; untouched files have no badge.
+        let response = handle_workspace_root(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
-        .unwrap();
+        .await
+        .into_response();
+        let body = response_text(response).await;
+        assert!(
+            body.contains(r#"data-progress-viewed="1" data-progress-total="3""#),
+            "{body}"
+        );
+        assert_eq!(body.matches("class=\"workspace-entry-progress").count(), 1);
+
+        let dir_data = |role: Option<AccessRole>| {
+            handle_workspace_dir_data(
+                State(state.clone()),
+                AxumPath(id.clone()),
+                role.map(Extension),
+                Query(DirListingQuery {
+                    path: Some("docs".into()),
+                    depth: None,
+                    ..Default::default()
+                }),
+            )
+        };
+        let entries = json(dir_data(Some(AccessRole::Admin)).await.into_response()).await;
         assert_eq!(
-            surface.channel,
-            format!("surface:/_/{id}/compare?base=main")
+            entries[0]["progress"],
+            serde_json::json!({ "viewed": 2, "total": 2, "percent": 100 })
         );
-        assert!(authorize_ws_target(
-            &entry,
-            WsTarget::Surface {
-                key: "/_/deadbeef/compare".into(),
-            }
+        let entries = json(dir_data(None).await.into_response()).await;
+        assert!(entries[0].get("progress").is_none());
+    }
+
+    #[tokio::test]
+    async fn directory_listing_renders_the_first_configured_readme() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs")).unwrap();
+        fs::write(
+            dir.path().join("readme.md"),
+            "# Project\n\nWelcome **here**.",
         )
-        .is_none());
+        .unwrap();
+        fs::write(dir.path().join("index.md"), "# Index\n\nIndex page").unwrap();
+        fs::write(dir.path().join("docs/index.md"), "# Docs index").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("dir-readme".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
 
-        registry.update_flags(
-            &id,
-            WorkspaceFlags {
-                shared_annotation: true,
-                enable_live: false,
-                ..Default::default()
-            },
-        );
-        assert!(authorize_ws_target(
-            &entry,
-            WsTarget::Surface {
-                key: format!("/{id}/"),
+        let root_page = |state: AppState| {
+            let id = id.clone();
+            async move {
+                let response = handle_workspace_root(
+                    State(state),
+                    AxumPath(id),
+                    None,
+                    Query(DirViewQuery::default()),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                response_text(response).await
             }
-        )
-        .is_none());
+        };
+
+        let body = root_page(state.clone()).await;
+        assert!(body.contains(r#"class="workspace-readme""#), "{body}");
+        assert!(body.contains("<strong>here</strong>"), "{body}");
+        assert!(!body.contains("Index page"), "{body}");
+
+        state.readme_names = Arc::new(vec!["index.md".into(), "README.md".into()]);
+        let body = root_page(state.clone()).await;
+        assert!(body.contains("Index page"), "{body}");
+        assert!(!body.contains("<strong>here</strong>"), "{body}");
+
+        state.readme_names = Arc::new(vec!["CONTRIBUTING.md".into()]);
+        let body = root_page(state).await;
+        assert!(!body.contains(r#"class="workspace-readme""#), "{body}");
     }
 
-    #[test]
-    fn workspace_event_filter_prevents_cross_channel_delivery() {
-        let event = WorkspaceEvent::Channel {
-            channel: "document:/workspace/a.md".into(),
-            payload: "a".into(),
+    #[tokio::test]
+    async fn directory_listing_shows_file_metadata_and_honours_sort() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("zdocs")).unwrap();
+        fs::write(dir.path().join("alpha.md"), "# Alpha guide\n\nshort").unwrap();
+        fs::write(dir.path().join("beta.md"), "no heading\n".repeat(200)).unwrap();
+        let old = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        fs::File::options()
+            .write(true)
+            .open(dir.path().join("beta.md"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("dir-sort".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+
+        let root_page = |sort: Option<&str>, order: Option<&str>| {
+            let (state, id) = (state.clone(), id.clone());
+            let query = DirViewQuery {
+                sort: sort.map(str::to_string),
+                order: order.map(str::to_string),
+                ..Default::default()
+            };
+            async move {
+                handle_workspace_root(
+                    State(state),
+                    AxumPath(id),
+                    None,
+                    Query(query),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response()
+            }
         };
-        assert_eq!(
-            workspace_event_payload(event.clone(), "document:/workspace/a.md").as_deref(),
-            Some("a")
-        );
-        assert!(workspace_event_payload(event, "document:/workspace/b.md").is_none());
-        assert_eq!(
-            workspace_event_payload(
-                WorkspaceEvent::Workspace {
-                    payload: "reload".into()
-                },
-                "surface:/abcd1234/"
-            )
-            .as_deref(),
-            Some("reload")
+        let position =
+            |body: &str, name: &str| body.find(&format!(r#"data-entry-path="{name}""#)).unwrap();
+
+        let body = response_text(root_page(None, None).await).await;
+        assert!(
+            body.contains(
+                r#">Alpha guide</a><span class="workspace-entry-filename">alpha.md</span>"#
+            ),
+            "{body}"
         );
+        assert!(body.contains(">beta.md</a>"), "{body}");
+        assert!(body.contains("2.15 kB · 2020-09-13"), "{body}");
+        assert!(position(&body, "alpha.md") < position(&body, "beta.md"));
+
+        let body = response_text(root_page(Some("size"), None).await).await;
+        assert!(position(&body, "beta.md") < position(&body, "alpha.md"));
+        assert!(body.find("zdocs/").unwrap() < position(&body, "beta.md"));
+
+        let body = response_text(root_page(Some("mtime"), None).await).await;
+        assert!(position(&body, "alpha.md") < position(&body, "beta.md"));
+        let body = response_text(root_page(Some("mtime"), Some("asc")).await).await;
+        assert!(position(&body, "beta.md") < position(&body, "alpha.md"));
+
+        let response = root_page(Some("owner"), None).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let response = root_page(Some("name"), Some("sideways")).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
-    #[test]
-    fn ws_input_is_live_only_and_never_mutates_annotations() {
-        let root = tempfile::tempdir().unwrap();
-        let document = root.path().join("note.md");
-        fs::write(&document, "# note").unwrap();
+    #[tokio::test]
+    async fn root_page_lists_recent_and_pinned_files() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "# Alpha\n").unwrap();
+        fs::write(dir.path().join("b.md"), "plain").unwrap();
+        fs::write(dir.path().join("notes.txt"), "text").unwrap();
+        let db_dir = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("quick-access".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::open(&db_dir.path().join("db.sqlite")).unwrap());
+        let mut headers = axum::http::HeaderMap::new();
+        headers.insert(
+            axum::http::header::COOKIE,
+            format!("{CLIENT_COOKIE}=0123456789abcdef0123456789abcdef")
+                .parse()
+                .unwrap(),
+        );
+
+        let view = |path: &str, headers: axum::http::HeaderMap| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            async move {
+                handle_workspace_path(
+                    State(state),
+                    AxumPath((id, path)),
+                    Some(Extension(AccessRole::Collaborator)),
+                    Query(DirViewQuery::default()),
+                    headers,
+                )
+                .await
+                .into_response()
+            }
+        };
+        let root_page = |headers: axum::http::HeaderMap| {
+            let (state, id) = (state.clone(), id.clone());
+            async move {
+                let response = handle_workspace_root(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Collaborator)),
+                    Query(DirViewQuery::default()),
+                    headers,
+                )
+                .await
+                .into_response();
+                response_text(response).await
+            }
+        };
+        let pin = |path: &str, pinned: bool| {
+            let (state, id, headers) = (state.clone(), id.clone(), headers.clone());
+            let request = PinFileRequest {
+                path: path.to_string(),
+                pinned,
+            };
+            async move {
+                handle_pin_file(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Collaborator)),
+                    headers,
+                    Json(request),
+                )
+                .await
+                .status()
+            }
+        };
 
-        let registry = Arc::new(WorkspaceRegistry::new("ws-feature-gates".into()));
-        let id = add_test_workspace(
-            &registry,
-            root.path().to_path_buf(),
-            WorkspaceFlags {
-                shared_annotation: true,
-                enable_live: false,
-                ..Default::default()
-            },
+        assert!(!root_page(headers.clone())
+            .await
+            .contains("workspace-quick-access\""));
+        // A browser's first view issues the client cookie it is tracked by.
+        let first = view("b.md", axum::http::HeaderMap::new()).await;
+        assert!(first
+            .headers()
+            .get(axum::http::header::SET_COOKIE)
+            .is_some_and(|cookie| cookie.to_str().unwrap().starts_with(CLIENT_COOKIE)));
+        assert!(view("b.md", headers.clone())
+            .await
+            .headers()
+            .get(axum::http::header::SET_COOKIE)
+            .is_none());
+        view("notes.txt", headers.clone()).await;
+        view("a.md", headers.clone()).await;
+
+        let body = root_page(headers.clone()).await;
+        let recent = body.find(r#"data-i18n="web.ws.quick.recent""#).unwrap();
+        let alpha = body.find(r#">Alpha</a>"#).unwrap();
+        let b = body
+            .find(r#"data-pin-path="b.md" data-pinned="false""#)
+            .unwrap();
+        assert!(recent < alpha && alpha < b, "{body}");
+        assert!(!body.contains(r#"data-pin-path="notes.txt""#), "{body}");
+        assert!(
+            !body.contains(r#"data-i18n="web.ws.quick.pinned""#),
+            "{body}"
         );
-        let entry = registry.get(&id).unwrap();
-        let session = Arc::new(
-            authorize_ws_target(
-                &entry,
-                WsTarget::Document {
-                    path: document.to_string_lossy().into_owned(),
-                },
-            )
-            .unwrap(),
+        // Another browser has its own history.
+        assert!(!root_page(axum::http::HeaderMap::new())
+            .await
+            .contains("workspace-quick-access\""));
+
+        assert_eq!(pin("b.md", true).await, StatusCode::NO_CONTENT);
+        assert_eq!(pin("notes.txt", true).await, StatusCode::NOT_FOUND);
+        assert_eq!(pin("missing.md", true).await, StatusCode::NOT_FOUND);
+        let body = root_page(headers.clone()).await;
+        assert!(
+            body.contains(r#"data-pin-path="b.md" data-pinned="true""#),
+            "{body}"
         );
-        let mut rx = entry.events_tx.subscribe();
-        handle_client_msg(
-            &entry,
-            &session,
-            WebSocketMessage::NewAnnotation {
-                annotation: json!({ "id": "anno-ignored" }),
-                op_id: None,
-            },
+        assert!(
+            !body.contains(r#"data-pin-path="b.md" data-pinned="false""#),
+            "{body}"
         );
-        handle_client_msg(
-            &entry,
-            &session,
-            WebSocketMessage::LiveAction { data: json!({}) },
+
+        assert_eq!(pin("b.md", false).await, StatusCode::NO_CONTENT);
+        fs::remove_file(dir.path().join("a.md")).unwrap();
+        let body = root_page(headers).await;
+        assert!(!body.contains(r#"data-pinned="true""#), "{body}");
+        assert!(!body.contains(">Alpha</a>"), "{body}");
+        assert!(
+            body.contains(r#"data-pin-path="b.md" data-pinned="false""#),
+            "{body}"
         );
-        assert!(matches!(
-            rx.try_recv(),
-            Err(tokio::sync::broadcast::error::TryRecvError::Empty)
-        ));
+    }
 
-        registry.update_flags(
-            &id,
-            WorkspaceFlags {
-                shared_annotation: false,
-                enable_live: true,
+    #[tokio::test]
+    async fn directory_listing_paginates_in_sort_order() {
+        let dir = tempfile::tempdir().unwrap();
+        for (i, name) in ["a.md", "b.md", "c.md", "d.md", "e.md"].iter().enumerate() {
+            fs::write(dir.path().join(name), "x".repeat(i + 1)).unwrap();
+        }
+        let registry = Arc::new(WorkspaceRegistry::new("dir-pages".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.listing_page_size = 2;
+
+        let root_page = |sort: Option<&str>, page: Option<usize>| {
+            let (state, id) = (state.clone(), id.clone());
+            let query = DirViewQuery {
+                sort: sort.map(str::to_string),
+                page,
                 ..Default::default()
-            },
-        );
-        let surface = Arc::new(
-            authorize_ws_target(
-                &entry,
-                WsTarget::Surface {
-                    key: format!("/{id}/"),
-                },
-            )
-            .unwrap(),
+            };
+            async move {
+                handle_workspace_root(
+                    State(state),
+                    AxumPath(id),
+                    None,
+                    Query(query),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response()
+            }
+        };
+        let names = |body: &str| -> Vec<String> {
+            ["a.md", "b.md", "c.md", "d.md", "e.md"]
+                .into_iter()
+                .filter(|name| body.contains(&format!(">{name}</a>")))
+                .map(str::to_string)
+                .collect()
+        };
+
+        let body = response_text(root_page(None, None).await).await;
+        assert_eq!(names(&body), vec!["a.md", "b.md"]);
+        assert!(body.contains("1 / 3 · 5"), "{body}");
+        assert!(
+            body.contains(r#"href="?sort=name&amp;page=2" rel="next""#),
+            "{body}"
         );
-        handle_client_msg(
-            &entry,
-            &surface,
-            WebSocketMessage::LiveAction {
-                data: json!({ "marker": "forwarded" }),
-            },
+        assert!(!body.contains(r#"rel="prev""#), "{body}");
+
+        let body = response_text(root_page(Some("size"), Some(2)).await).await;
+        assert_eq!(names(&body), vec!["b.md", "c.md"]);
+        assert!(body.contains(r#"href="?sort=size" rel="prev""#), "{body}");
+        assert!(
+            body.contains(r#"href="?sort=size&amp;page=3" rel="next""#),
+            "{body}"
         );
-        let WorkspaceEvent::Channel { channel, payload } = rx.try_recv().unwrap() else {
-            panic!("expected channel event");
-        };
-        assert_eq!(channel, surface.channel);
-        assert!(payload.contains("forwarded"), "{payload}");
-    }
 
-    #[test]
-    fn test_websocket_message_serialization() {
-        let msg = WebSocketMessage::LiveAction {
-            data: json!({
-                "clientId": "test-id",
-                "action": "scroll_to",
-                "xpath": "/p[1]",
-                "offset": 0.5
-            }),
-        };
-        let serialized = serde_json::to_string(&msg).unwrap();
-        assert!(serialized.contains("\"type\":\"live_action\""));
-        assert!(serialized.contains("\"clientId\":\"test-id\""));
+        let body = response_text(root_page(None, Some(99)).await).await;
+        assert_eq!(names(&body), vec!["e.md"]);
+        assert!(!body.contains(r#"rel="next""#), "{body}");
 
-        let file = WebSocketMessage::FileChanged {
-            workspace_id: "ws1".into(),
-            path: "docs/a.md".into(),
-        };
-        let serialized = serde_json::to_string(&file).unwrap();
-        assert!(serialized.contains("\"type\":\"file_changed\""));
-        assert!(serialized.contains("\"workspace_id\":\"ws1\""));
+        let response = root_page(None, Some(0)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
 
-    /// `NewAnnotation` round-trips `op_id` verbatim in both directions and
-    /// the field is omitted from the wire when `None` — keeping the protocol
-    /// backward-compatible with clients that don't know about it yet.
-    #[test]
-    fn test_new_annotation_op_id_round_trip() {
-        // Some(op_id): present on the wire, parsed back identically.
-        let with = WebSocketMessage::NewAnnotation {
-            annotation: json!({ "id": "anno-1", "text": "hi" }),
-            op_id: Some("op-abc".into()),
+    #[tokio::test]
+    async fn paginated_document_serves_one_chapter_per_page_with_the_full_toc() {
+        let dir = tempfile::tempdir().unwrap();
+        let filler = "lorem ipsum ".repeat(100);
+        fs::write(
+            dir.path().join("spec.md"),
+            format!("# Spec\n\n## Alpha\n{filler}\n\n## Beta\n{filler}\n\n## Gamma\n{filler}\n"),
+        )
+        .unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("doc-pages".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+
+        let page = |paginate_bytes: Option<usize>, page: Option<usize>| {
+            let (mut state, id) = (state.clone(), id.clone());
+            state.paginate_bytes = paginate_bytes;
+            async move {
+                let response = handle_workspace_path(
+                    State(state),
+                    AxumPath((id, "spec.md".to_string())),
+                    None,
+                    Query(DirViewQuery {
+                        page,
+                        ..Default::default()
+                    }),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                response_text(response).await
+            }
         };
-        let json_with = serde_json::to_string(&with).unwrap();
+
+        let body = page(Some(1024), None).await;
+        assert!(body.contains(r#"<h2 id="alpha">"#), "{body}");
+        assert!(!body.contains(r#"<h2 id="beta">"#), "{body}");
+        assert!(body.contains(r##"<a href="#alpha">Alpha</a>"##), "{body}");
         assert!(
-            json_with.contains("\"op_id\":\"op-abc\""),
-            "wire form should include op_id: {json_with}"
+            body.contains(r##"<a href="?page=3#gamma">Gamma</a>"##),
+            "{body}"
         );
-        let parsed: WebSocketMessage = serde_json::from_str(&json_with).unwrap();
-        match parsed {
-            WebSocketMessage::NewAnnotation { op_id, .. } => {
-                assert_eq!(op_id.as_deref(), Some("op-abc"));
-            }
-            _ => panic!("expected NewAnnotation"),
-        }
+        assert!(body.contains("1 / 3"), "{body}");
+        assert!(body.contains(r#"id="document-page-anchors""#), "{body}");
 
-        // None: omitted from the wire (back-compat with old clients).
-        let without = WebSocketMessage::NewAnnotation {
-            annotation: json!({ "id": "anno-2" }),
-            op_id: None,
-        };
-        let json_without = serde_json::to_string(&without).unwrap();
+        let body = page(Some(1024), Some(9)).await;
+        assert!(body.contains(r#"<h2 id="gamma">"#), "{body}");
         assert!(
-            !json_without.contains("op_id"),
-            "wire form should omit op_id when None: {json_without}"
+            body.contains(r##"<a href="?page=1#alpha">Alpha</a>"##),
+            "{body}"
         );
+        assert!(body.contains(r#"<a href="?page=2" rel="prev">"#), "{body}");
+        assert!(!body.contains(r#"rel="next""#), "{body}");
 
-        // An old-client payload with no op_id field deserialises to None.
-        let legacy = r#"{"type":"new_annotation","annotation":{"id":"x"}}"#;
-        let parsed_legacy: WebSocketMessage = serde_json::from_str(legacy).unwrap();
-        match parsed_legacy {
-            WebSocketMessage::NewAnnotation { op_id, .. } => assert!(op_id.is_none()),
-            _ => panic!("expected NewAnnotation"),
-        }
+        let body = page(None, None).await;
+        assert!(body.contains(r#"<h2 id="gamma">"#), "{body}");
+        assert!(!body.contains(r#"id="document-pager""#), "{body}");
     }
 
-    #[test]
-    fn test_app_state_identity() {
-        let registry = Arc::new(crate::workspace::WorkspaceRegistry::new("salt".into()));
-        let state = AppState {
-            theme: Arc::new("dark".into()),
-            tera: Arc::new(Tera::default()),
-            db: None,
-            workspace_registry: registry,
-            management_token: Arc::new("token".into()),
-            admin_bootstraps: Arc::new(AdminBootstrapStore::new()),
-            allowed_hosts: Arc::new(build_allowed_hosts("127.0.0.1", "", 6419, &[], &[])),
-            save_token: Arc::new("save-token".into()),
-            i18n_json: Arc::new("{}".into()),
-            i18n_lang: Arc::new("zh".into()),
-            shortcuts_json: Arc::new("{}".into()),
-            styles_css: Arc::new("".into()),
-            default_chat_mode: Arc::new("in_page".into()),
-            collaborator_access_code_hash: Arc::new(String::new()),
-            access_secret: Arc::new("test-salt".into()),
-            access_attempts: Arc::new(std::sync::Mutex::new(std::collections::HashMap::new())),
-            markdown_diff_cache: Arc::new(Mutex::new(MarkdownDiffCache::default())),
-            print_collapsed_content: false,
-            #[cfg(debug_assertions)]
-            dev_reload_tx: Arc::new(broadcast::channel::<()>(1).0),
+    #[tokio::test]
+    async fn document_header_shows_the_last_commit_until_head_moves() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+        };
+        git(&["init"]);
+        git(&["config", "user.email", "ada@example.com"]);
+        git(&["config", "user.name", "Ada Lovelace"]);
+        fs::write(dir.path().join("notes.md"), "# Notes\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-m", "Draft the notes"]);
+        let registry = Arc::new(WorkspaceRegistry::new("last-commit".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let page = || {
+            let (state, id) = (state.clone(), id.clone());
+            async move {
+                let response = handle_workspace_path(
+                    State(state),
+                    AxumPath((id, "notes.md".to_string())),
+                    None,
+                    Query(DirViewQuery::default()),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                response_text(response).await
+            }
         };
-        assert_eq!(state.management_token.as_str(), "token");
-    }
-
-    fn sample_hosts() -> Vec<crate::net::BindHostOption> {
-        use crate::net::{BindHostKind, BindHostOption};
-        vec![
-            BindHostOption {
-                address: "127.0.0.1".into(),
-                kind: BindHostKind::Localhost,
-                interface: None,
-            },
-            BindHostOption {
-                address: "::1".into(),
-                kind: BindHostKind::Localhost,
-                interface: None,
-            },
-            BindHostOption {
-                address: "0.0.0.0".into(),
-                kind: BindHostKind::AllInterfaces,
-                interface: None,
-            },
-            BindHostOption {
-                address: "::".into(),
-                kind: BindHostKind::AllInterfaces,
-                interface: None,
-            },
-            BindHostOption {
-                address: "192.168.1.20".into(),
-                kind: BindHostKind::Interface,
-                interface: Some("en0".into()),
-            },
-            BindHostOption {
-                address: "10.0.0.5".into(),
-                kind: BindHostKind::Interface,
-                interface: Some("eth1".into()),
-            },
-            BindHostOption {
-                address: "fd00::20".into(),
-                kind: BindHostKind::Interface,
-                interface: Some("en0".into()),
-            },
-            BindHostOption {
-                address: "2001:db8::5".into(),
-                kind: BindHostKind::Interface,
-                interface: Some("utun0".into()),
-            },
-        ]
-    }
 
-    #[test]
-    fn reachable_ipv4_wildcard_lists_ipv4_localhost_then_interfaces() {
-        let r = assemble_reachable_urls("0.0.0.0", "", 6419, &sample_hosts());
-        assert_eq!(r.all.len(), 3);
-        assert_eq!(r.all[0].label, "localhost");
-        assert_eq!(r.all[0].url, "http://127.0.0.1:6419");
-        assert_eq!(r.all[1].url, "http://192.168.1.20:6419");
-        assert_eq!(r.all[2].url, "http://10.0.0.5:6419");
-        // No advertised preference → first interface is featured (not localhost).
-        assert_eq!(r.featured, "http://192.168.1.20:6419");
+        let body = page().await;
+        assert!(body.contains(r#"class="document-commit""#), "{body}");
+        assert!(body.contains("Draft the notes"), "{body}");
+        assert!(body.contains("Ada Lovelace · <time datetime="), "{body}");
+
+        fs::write(dir.path().join("notes.md"), "# Notes\n\nMore.\n").unwrap();
+        git(&["commit", "-am", "Expand the notes"]);
+        let body = page().await;
+        assert!(body.contains("Expand the notes"), "{body}");
     }
 
-    #[test]
-    fn admin_bootstrap_url_starts_at_final_route_with_fragment_nonce() {
-        assert_eq!(
-            build_admin_bootstrap_url(
-                "http://192.168.1.20:6419/",
-                "/workspace/file.md?mode=preview",
-                "abc123"
-            ),
-            "http://192.168.1.20:6419/workspace/file.md?mode=preview#bootstrap_nonce=abc123"
+    #[tokio::test]
+    async fn summary_file_adds_book_navigation_to_every_page() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("guide")).unwrap();
+        fs::write(
+            dir.path().join("SUMMARY.md"),
+            "# Summary\n\n[Intro](intro.md)\n\n- [Install](guide/install.md)\n- [Usage](guide/usage.md)\n",
+        )
+        .unwrap();
+        for (path, title) in [
+            ("intro.md", "Intro"),
+            ("guide/install.md", "Install"),
+            ("guide/usage.md", "Usage"),
+            ("notes.md", "Notes"),
+        ] {
+            fs::write(dir.path().join(path), format!("# {title}\n")).unwrap();
+        }
+        let registry = Arc::new(WorkspaceRegistry::new("book-nav".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let page = |path: &str| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            async move {
+                let response = handle_workspace_path(
+                    State(state),
+                    AxumPath((id, path)),
+                    None,
+                    Query(DirViewQuery::default()),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                response_text(response).await.replace("&#x2F;", "/")
+            }
+        };
+
+        let body = page("guide/install.md").await;
+        assert!(body.contains(r#"class="book-nav-list""#), "{body}");
+        assert!(
+            body.contains(&format!(
+                r#"<a href="/{id}/guide/install.md" aria-current="page"><span class="book-nav-number">1.</span> Install</a>"#
+            )),
+            "{body}"
         );
-        // The original heading remains in the server-side redirect stored with
-        // the nonce; it must not displace the bootstrap fragment in the first URL.
-        assert_eq!(
-            build_admin_bootstrap_url(
-                "http://127.0.0.1:6419",
-                "/workspace/file.md#heading",
-                "abc123"
-            ),
-            "http://127.0.0.1:6419/workspace/file.md#bootstrap_nonce=abc123"
+        assert!(
+            body.contains(&format!(r#"class="book-pager-prev" href="/{id}/intro.md""#)),
+            "{body}"
+        );
+        assert!(
+            body.contains(&format!(
+                r#"class="book-pager-next" href="/{id}/guide/usage.md""#
+            )),
+            "{body}"
         );
-    }
 
-    #[test]
-    fn reachable_ipv6_wildcard_lists_ipv6_localhost_then_interfaces() {
-        let r = assemble_reachable_urls("::", "", 6419, &sample_hosts());
-        assert_eq!(r.all.len(), 3);
-        assert_eq!(r.all[0].label, "localhost");
-        assert_eq!(r.all[0].url, "http://[::1]:6419");
-        assert_eq!(r.all[1].url, "http://[fd00::20]:6419");
-        assert_eq!(r.all[2].url, "http://[2001:db8::5]:6419");
-        assert_eq!(r.featured, "http://[fd00::20]:6419");
+        // A page outside the book still gets the sidebar, but no pager.
+        let body = page("notes.md").await;
+        assert!(body.contains(r#"class="book-nav-list""#), "{body}");
+        assert!(!body.contains(r#"id="book-pager""#), "{body}");
+
+        fs::remove_file(dir.path().join("SUMMARY.md")).unwrap();
+        let body = page("intro.md").await;
+        assert!(!body.contains(r#"class="book-nav""#), "{body}");
     }
 
-    #[test]
-    fn reachable_wildcard_honours_advertised_host_and_falls_back() {
-        let hosts = sample_hosts();
-        // Advertised host is a live interface → used verbatim.
+    #[tokio::test]
+    async fn editor_protocol_renders_buffers_and_streams_their_events() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("docs/img.png"), b"png").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("editor".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let token = workspace_editor_token(&state.save_token, &id);
+        let rpc = |body: serde_json::Value, token: &str| {
+            let (state, id) = (state.clone(), id.clone());
+            let mut headers = HeaderMap::new();
+            headers.insert("X-Markon-Token", token.parse().unwrap());
+            async move {
+                let response =
+                    handle_editor_rpc(State(state), AxumPath(id), headers, body.to_string().into())
+                        .await;
+                let status = response.status();
+                let text = response_text(response).await;
+                (
+                    status,
+                    serde_json::from_str(&text).unwrap_or(serde_json::Value::Null),
+                )
+            }
+        };
+
+        let call = json!({"jsonrpc": "2.0", "id": 1, "method": "initialize"});
+        let (status, _) = rpc(call.clone(), "wrong").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        // The browser preview capability doesn't open an editor session.
+        let preview_token = workspace_preview_token(&state.save_token, &id);
         assert_eq!(
-            assemble_reachable_urls("0.0.0.0", "10.0.0.5", 6419, &hosts).featured,
-            "http://10.0.0.5:6419"
+            rpc(call.clone(), &preview_token).await.0,
+            StatusCode::UNAUTHORIZED
         );
-        // Stale advertised host (not currently bound) → first interface.
+        let (_, body) = rpc(call, &token).await;
+        assert_eq!(body["result"]["protocol"], 1);
         assert_eq!(
-            assemble_reachable_urls("0.0.0.0", "172.16.9.9", 6419, &hosts).featured,
-            "http://192.168.1.20:6419"
+            body["result"]["events_url"],
+            format!("/api/editor/{id}/events")
         );
-        assert_eq!(
-            assemble_reachable_urls("::", "2001:db8::5", 6419, &hosts).featured,
-            "http://[2001:db8::5]:6419"
+
+        let (_, body) = rpc(
+            json!({"jsonrpc": "2.0", "id": "r", "method": "render",
+                   "params": {"content": "# Title\n\n![](img.png)\n", "path": "docs/draft.md"}}),
+            &token,
+        )
+        .await;
+        assert_eq!(body["id"], "r");
+        assert_eq!(body["result"]["toc"][0]["id"], "title");
+        assert!(
+            body["result"]["html"]
+                .as_str()
+                .unwrap()
+                .contains(&format!("/{id}/docs/img.png")),
+            "{body}"
         );
-        assert_eq!(
-            assemble_reachable_urls("::", "[fd00::99]", 6419, &hosts).featured,
-            "http://[fd00::20]:6419"
+        let (_, body) = rpc(
+            json!({"jsonrpc": "2.0", "id": 2, "method": "render",
+                   "params": {"content": "x", "path": "../outside.md"}}),
+            &token,
+        )
+        .await;
+        assert_eq!(body["error"]["code"], -32602);
+        let (_, body) = rpc(json!({"jsonrpc": "2.0", "id": 3, "method": "nope"}), &token).await;
+        assert_eq!(body["error"]["code"], -32601);
+
+        let (status, _) = rpc(
+            json!({"jsonrpc": "2.0", "method": "buffer/update",
+                   "params": {"buffer": "7", "content": "# One\n\ntext\n\n## Two\n"}}),
+            &token,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = rpc(
+            json!({"jsonrpc": "2.0", "id": 4, "method": "buffer/scroll",
+                   "params": {"buffer": "7", "line": 4}}),
+            &token,
+        )
+        .await;
+        assert_eq!(body["result"]["heading"], "two");
+
+        // A subscriber starts from the current render of the buffer.
+        let response = handle_editor_events(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Query(EditorQuery {
+                buffer: Some("7".into()),
+                token: Some(token.clone()),
+            }),
+            HeaderMap::new(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+        let first = body.next().await.unwrap().unwrap();
+        let first = String::from_utf8_lossy(&first);
+        assert!(first.starts_with("event: render\n"), "{first}");
+        assert!(first.contains(r#""buffer":"7""#), "{first}");
+        rpc(
+            json!({"jsonrpc": "2.0", "method": "buffer/close", "params": {"buffer": "7"}}),
+            &token,
+        )
+        .await;
+        let next = body.next().await.unwrap().unwrap();
+        assert!(String::from_utf8_lossy(&next).starts_with("event: close\n"));
+
+        let response = handle_editor_preview(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Query(EditorQuery {
+                buffer: None,
+                token: Some(token.clone()),
+            }),
+            HeaderMap::new(),
+        )
+        .await;
+        let page = response_text(response).await.replace("&#x2F;", "/");
+        assert!(
+            page.contains(&format!("/api/editor/{id}/events?token={token}")),
+            "{page}"
         );
     }
 
-    #[test]
-    fn reachable_wildcard_without_interfaces_falls_back_to_localhost() {
-        use crate::net::{BindHostKind, BindHostOption};
-        let hosts = vec![
-            BindHostOption {
-                address: "127.0.0.1".into(),
-                kind: BindHostKind::Localhost,
-                interface: None,
-            },
-            BindHostOption {
-                address: "::1".into(),
-                kind: BindHostKind::Localhost,
-                interface: None,
-            },
-            BindHostOption {
-                address: "0.0.0.0".into(),
-                kind: BindHostKind::AllInterfaces,
-                interface: None,
-            },
-            BindHostOption {
-                address: "::".into(),
-                kind: BindHostKind::AllInterfaces,
-                interface: None,
-            },
-        ];
-        let r = assemble_reachable_urls("0.0.0.0", "", 6419, &hosts);
-        assert_eq!(r.all.len(), 1);
-        assert_eq!(r.featured, "http://127.0.0.1:6419");
-        let r = assemble_reachable_urls("::", "", 6419, &hosts);
-        assert_eq!(r.all.len(), 1);
-        assert_eq!(r.featured, "http://[::1]:6419");
-    }
+    #[tokio::test]
+    async fn openapi_spec_renders_as_api_reference_with_source_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("openapi.yaml"),
+            "openapi: 3.1.0\ninfo:\n  title: Orders API\n  version: '2'\npaths:\n  /orders:\n    get:\n      operationId: listOrders\n      summary: List orders\n      responses:\n        200:\n          description: OK\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("swagger.json"), "{\"not\": \"a spec\"}\n").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("openapi".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let page = |path: &str, source: bool| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            async move {
+                let response = handle_workspace_path(
+                    State(state),
+                    AxumPath((id, path)),
+                    None,
+                    Query(DirViewQuery {
+                        source,
+                        ..DirViewQuery::default()
+                    }),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                response_text(response).await.replace("&#x2F;", "/")
+            }
+        };
 
-    #[test]
-    fn reachable_specific_bind_lists_only_that_address() {
-        let r = assemble_reachable_urls("192.168.1.20", "", 6419, &sample_hosts());
-        // A specific bind does NOT serve loopback, so localhost is absent.
-        assert_eq!(r.all.len(), 1);
-        assert_eq!(r.all[0].label, "en0");
-        assert_eq!(r.featured, "http://192.168.1.20:6419");
-    }
+        let body = page("openapi.yaml", false).await;
+        assert!(body.contains("<title>Orders API</title>"), "{body}");
+        assert!(body.contains("/_/css/openapi"), "{body}");
+        assert!(
+            body.contains(r#"class="openapi-operation openapi-method-get" id="op-listorders""#),
+            "{body}"
+        );
+        assert!(body.contains("<code>200</code>"), "{body}");
+        assert!(body.contains(r##"href="#op-listorders""##), "{body}");
+        assert!(
+            body.contains(r#"<meta name="enable-edit" content="false">"#),
+            "{body}"
+        );
 
-    #[test]
-    fn reachable_specific_ipv6_bind_lists_bracketed_address() {
-        let r = assemble_reachable_urls("fd00::20", "", 6419, &sample_hosts());
-        assert_eq!(r.all.len(), 1);
-        assert_eq!(r.all[0].label, "en0");
-        assert_eq!(r.all[0].url, "http://[fd00::20]:6419");
-        assert_eq!(r.featured, "http://[fd00::20]:6419");
-    }
+        let body = page("openapi.yaml", true).await;
+        assert!(!body.contains("openapi-reference"), "{body}");
+        assert!(body.contains("listOrders"), "{body}");
 
-    #[test]
-    fn reachable_loopback_binds() {
-        let hosts = sample_hosts();
-        let v4 = assemble_reachable_urls("127.0.0.1", "", 6419, &hosts);
-        assert_eq!(v4.all.len(), 1);
-        assert_eq!(v4.featured, "http://127.0.0.1:6419");
-        // IPv6 loopback is preserved (bracketed), not collapsed to 127.0.0.1.
-        let v6 = assemble_reachable_urls("::1", "", 6419, &hosts);
-        assert_eq!(v6.featured, "http://[::1]:6419");
+        // A spec-named file that isn't a spec keeps the source view.
+        let body = page("swagger.json", false).await;
+        assert!(!body.contains("openapi-reference"), "{body}");
     }
 
-    #[test]
-    fn access_cookie_round_trips_and_rejects_tamper() {
-        let secret = "test-secret";
-        let scopes = vec![("s".to_string(), "h1".to_string())];
-        let raw = make_access_cookie(secret, &scopes, access_now_unix() + 100, false);
-        let kv = raw.split(';').next().unwrap(); // markon_access=PAYLOAD.SIG
-        assert_eq!(access_cookie_scopes(secret, Some(kv)), scopes);
-        // Wrong secret, tampered value, and an expired cookie are all rejected.
-        assert!(access_cookie_scopes("other-secret", Some(kv)).is_empty());
-        assert!(access_cookie_scopes(secret, Some(&format!("{kv}00"))).is_empty());
-        let expired = make_access_cookie(secret, &scopes, 1, false);
-        assert!(access_cookie_scopes(secret, Some(expired.split(';').next().unwrap())).is_empty());
-        let secure = make_access_cookie(secret, &scopes, access_now_unix() + 100, true);
-        assert!(secure.contains("; Secure"));
+    #[tokio::test]
+    async fn presentation_renders_slides_with_notes_and_a_follow_along_qr() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("talks")).unwrap();
+        fs::write(
+            dir.path().join("talks/deck.md"),
+            "# Launch\n\n<!-- Welcome everyone. -->\n\n---\n\n## Numbers\n\n![](chart.png)\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("talks/chart.png"), b"png").unwrap();
+        fs::write(dir.path().join("talks/notes.txt"), "plain").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("present".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        state
+            .share_base
+            .set("http://192.168.1.5:6419".to_string())
+            .unwrap();
+        let present = |path: &str, speaker: bool| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            async move {
+                let response = handle_presentation(
+                    State(state),
+                    AxumPath((id, path)),
+                    Query(PresentQuery { speaker }),
+                )
+                .await;
+                let status = response.status();
+                (status, response_text(response).await.replace("&#x2F;", "/"))
+            }
+        };
+
+        let (status, body) = present("talks/deck.md", false).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body.matches(r#"class="present-slide""#).count(),
+            2,
+            "{body}"
+        );
+        assert!(body.contains(r#"id="slide-2" hidden"#), "{body}");
+        assert!(body.contains("<title>Launch</title>"), "{body}");
+        assert!(body.contains(&format!("/{id}/talks/chart.png")), "{body}");
+        assert!(
+            body.contains(r#"<aside class="present-notes markdown-body"><p>Welcome everyone.</p>"#),
+            "{body}"
+        );
+        assert!(!body.contains("present-speaker-panel"), "{body}");
+        let present_url = format!("/_/{id}/present/talks/deck.md");
+        assert!(
+            body.contains(&format!(
+                "/_/qr.svg?path={}",
+                urlencoding::encode(&present_url)
+            )),
+            "{body}"
+        );
+        assert!(body.contains(&format!("http://192.168.1.5:6419{present_url}")));
+        assert!(body.contains("/_/js/present"), "{body}");
+
+        let (_, body) = present("talks/deck.md", true).await;
+        assert!(body.contains("present-speaker-panel"), "{body}");
+        assert!(body.contains(r#"id="present-timer""#), "{body}");
+
+        assert_eq!(
+            present("talks/notes.txt", false).await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(present("../deck.md", false).await.0, StatusCode::NOT_FOUND);
+
+        // The document page links to its presentation.
+        let response = handle_workspace_path(
+            State(state.clone()),
+            AxumPath((id.clone(), "talks/deck.md".to_string())),
+            None,
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        let page = response_text(response).await.replace("&#x2F;", "/");
+        assert!(
+            page.contains(&format!(r#"href="{present_url}" id="present-link-text""#)),
+            "{page}"
+        );
     }
 
-    #[test]
-    fn allowed_hosts_are_exact_and_track_explicit_https_origins() {
-        let allowed = build_allowed_hosts(
-            "127.0.0.1",
-            "",
-            6419,
-            &["https://md.example.com".into(), "notes.local".into()],
-            &[],
+    #[tokio::test]
+    async fn archives_are_browsed_as_read_only_folders() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut zip = zip::ZipWriter::new(fs::File::create(dir.path().join("bundle.zip")).unwrap());
+        for (name, content) in [
+            ("README.md", "# Bundle\n".as_bytes()),
+            ("docs/guide.md", b"# Guide\n\n![chart](chart.png)\n"),
+            ("docs/chart.png", b"\x89PNG"),
+        ] {
+            zip.start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(content).unwrap();
+        }
+        zip.finish().unwrap();
+        // A real folder named like an archive is still a folder.
+        fs::create_dir_all(dir.path().join("notes.zip")).unwrap();
+        fs::write(dir.path().join("notes.zip/a.md"), "# Plain\n").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("archives".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let get = |path: &str, source: bool| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            async move {
+                handle_workspace_path(
+                    State(state),
+                    AxumPath((id, path)),
+                    None,
+                    Query(DirViewQuery {
+                        source,
+                        ..DirViewQuery::default()
+                    }),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response()
+            }
+        };
+        let location = |response: &Response| {
+            response.headers()[header::LOCATION]
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let response = get("bundle.zip", false).await;
+        assert_eq!(location(&response), format!("/{id}/bundle.zip/"));
+        let response = get("bundle.zip", true).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::LOCATION).is_none());
+
+        let response = get("bundle.zip/", false).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await.replace("&#x2F;", "/");
+        assert!(
+            body.contains(&format!(r#"href="/{id}/bundle.zip/docs/""#)),
+            "{body}"
+        );
+        assert!(
+            body.contains(&format!(r#"href="/{id}/bundle.zip/README.md""#)),
+            "{body}"
+        );
+        assert!(
+            body.contains(&format!("/{id}/bundle.zip?source=true")),
+            "{body}"
         );
-        assert!(allowed.allows_header(Some("127.0.0.1:6419")));
-        assert!(allowed.allows_header(Some("[::1]:6419")));
-        assert!(allowed.allows_header(Some("LOCALHOST:9999")));
-        assert!(allowed.allows_header(Some("md.example.com")));
-        assert!(allowed.allows_header(Some("notes.local:443")));
-        assert!(!allowed.allows_header(Some("evil.example")));
-        assert!(!allowed.allows_header(Some("md.example.com.evil")));
-        assert!(allowed.is_secure_header(Some("md.example.com")));
-        assert!(!allowed.is_secure_header(Some("notes.local")));
-    }
 
-    #[test]
-    fn wildcard_allowed_hosts_follow_interface_address_changes() {
-        use crate::net::{BindHostKind, BindHostOption};
+        let response = get("bundle.zip/docs", false).await;
+        assert_eq!(location(&response), format!("/{id}/bundle.zip/docs/"));
 
-        let allowed = build_allowed_hosts("0.0.0.0", "", 6419, &[], &[]);
-        allowed.replace_dynamic_interfaces(&sample_hosts());
-        assert!(allowed.allows_header(Some("192.168.1.20:6419")));
+        let response = get("bundle.zip/docs/guide.md", false).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await.replace("&#x2F;", "/");
+        assert!(body.contains("<title>Guide</title>"), "{body}");
+        assert!(body.contains(r#"src="chart.png""#), "{body}");
+        assert!(body.contains("bundle.zip/docs/guide.md</a>"), "{body}");
+        assert!(
+            body.contains(r#"name="enable-edit" content="false""#),
+            "{body}"
+        );
 
-        let changed = vec![BindHostOption {
-            address: "192.168.50.150".into(),
-            kind: BindHostKind::Interface,
-            interface: Some("en0".into()),
-        }];
-        allowed.replace_dynamic_interfaces(&changed);
-        assert!(!allowed.allows_header(Some("192.168.1.20:6419")));
-        assert!(allowed.allows_header(Some("192.168.50.150:6419")));
-        assert!(!allowed.allows_header(Some("attacker.example:6419")));
+        let response = get("bundle.zip/docs/chart.png", false).await;
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+        assert_eq!(&response_bytes(response).await[..], b"\x89PNG");
+
+        assert_eq!(
+            get("bundle.zip/missing.md", false).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        let response = get("notes.zip/a.md", false).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response_text(response).await.contains("Plain"));
     }
 
     #[tokio::test]
-    async fn unknown_host_is_rejected_before_route_execution() {
-        let state = test_state(Arc::new(WorkspaceRegistry::new("host-gate".into())));
-        let app = Router::new()
-            .route("/state-change", post(|| async { StatusCode::NO_CONTENT }))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                require_allowed_host,
-            ))
-            .with_state(state);
+    async fn print_view_renders_the_bare_document_with_details_open() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("guide.md"),
+            "# Guide\n\n<details>\n<summary>More</summary>\n\nHidden text\n\n</details>\n\n## Links\n\nSee [docs](https://example.com/docs).\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("notes.txt"), "plain").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("print".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let print = |path: &str| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            async move {
+                let response = handle_print_view(State(state), AxumPath((id, path))).await;
+                let status = response.status();
+                (status, response_text(response).await.replace("&#x2F;", "/"))
+            }
+        };
 
-        let evil = axum::http::Request::builder()
-            .method("POST")
-            .uri("/state-change")
-            .header(header::HOST, "evil.example:6419")
-            .header(header::ORIGIN, "http://evil.example:6419")
-            .body(axum::body::Body::empty())
-            .unwrap();
-        assert_eq!(
-            app.clone().oneshot(evil).await.unwrap().status(),
-            StatusCode::MISDIRECTED_REQUEST
+        let (status, body) = print("guide.md").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("<title>Guide</title>"), "{body}");
+        assert!(body.contains("<details open>"), "{body}");
+        assert!(body.contains(r#"data-level="2""#), "{body}");
+        assert!(body.contains("/_/css/print-view"), "{body}");
+        assert!(
+            body.contains(&format!(r#"href="/{id}/guide.md""#)),
+            "{body}"
         );
+        assert!(!body.contains("/_/js/main"), "{body}");
+        assert!(!body.contains("toc-container"), "{body}");
 
-        let local = axum::http::Request::builder()
-            .method("POST")
-            .uri("/state-change")
-            .header(header::HOST, "127.0.0.1:6419")
-            .body(axum::body::Body::empty())
-            .unwrap();
+        assert_eq!(print("notes.txt").await.0, StatusCode::NOT_FOUND);
+        assert_eq!(print("../guide.md").await.0, StatusCode::NOT_FOUND);
+
+        let response = handle_workspace_path(
+            State(state.clone()),
+            AxumPath((id.clone(), "guide.md".to_string())),
+            None,
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        let page = response_text(response).await.replace("&#x2F;", "/");
+        assert!(
+            page.contains(&format!(
+                r#"href="/_/{id}/print/guide.md" id="print-view-link-text""#
+            )),
+            "{page}"
+        );
+    }
+
+    #[test]
+    fn expand_details_opens_only_closed_details_tags() {
         assert_eq!(
-            app.oneshot(local).await.unwrap().status(),
-            StatusCode::NO_CONTENT
+            expand_details(
+                "<details><summary>a</summary></details><details open>\n<details class=\"x\"><detailsx>"
+            ),
+            "<details open><summary>a</summary></details><details open>\n<details open class=\"x\"><detailsx>"
         );
     }
 
     #[tokio::test]
-    async fn loopback_is_not_an_admin_identity() {
-        let root = tempfile::tempdir().unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("admin-role".into()));
-        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
-        let required_hash = crate::workspace::hash_access_code("test-salt", "guest");
-        assert!(registry.set_collaborator_access_code(&id, &required_hash));
-        let state = test_state(registry);
-        let route = format!("/_/{id}/danger");
-        let app = Router::new()
-            .route(
-                "/_/{workspace_id}/danger",
-                post(|| async { StatusCode::NO_CONTENT })
-                    .route_layer(axum::middleware::from_fn(require_admin_role)),
-            )
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                require_access_code,
-            ));
+    async fn snapshots_list_show_and_compare_past_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = tempfile::tempdir().unwrap();
+        let document = dir.path().join("notes.md");
+        fs::write(&document, "# Notes\n\nFirst draft.\n").unwrap();
+        let db = crate::db::Database::open(&data.path().join("db.sqlite")).unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("snapshots".into()));
+        registry.set_annotation_db(db.clone());
+        registry.enable_snapshots();
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let ws = registry.get(&id).unwrap();
+        let canonical = dunce::canonicalize(&document).unwrap();
+        record_document_snapshot(&ws, &canonical).await;
+        record_document_snapshot(&ws, &canonical).await;
+        fs::write(&document, "# Notes\n\nSecond draft.\n").unwrap();
+        record_document_snapshot(&ws, &canonical).await;
+        fs::write(&document, "# Notes\n\nFinal text.\n").unwrap();
 
-        let request = |cookie: Option<String>| {
-            let mut builder = axum::http::Request::builder()
-                .method("POST")
-                .uri(&route)
-                .extension(axum::extract::ConnectInfo(loopback()));
-            if let Some(cookie) = cookie {
-                builder = builder.header(header::COOKIE, cookie);
+        let mut state = test_state(registry.clone());
+        state.db = Some(db);
+        let snapshots = |query: &str| {
+            let (state, id) = (state.clone(), id.clone());
+            let query =
+                Query::<SnapshotsQuery>::try_from_uri(&format!("/?{query}").parse().unwrap())
+                    .unwrap();
+            async move {
+                let response = handle_document_snapshots(
+                    State(state),
+                    AxumPath((id, "notes.md".to_string())),
+                    query,
+                )
+                .await;
+                let status = response.status();
+                (status, response_text(response).await.replace("&#x2F;", "/"))
             }
-            builder.body(axum::body::Body::empty()).unwrap()
         };
 
-        // A loopback TCP peer without a capability still hits the collaborator
-        // gate; network topology grants no role.
+        let (status, body) = snapshots("format=json").await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(listed["exists"], true);
+        let listed = listed["snapshots"].as_array().unwrap();
+        // Two recorded above; the watcher may add the final text as a third.
+        assert!((2..=3).contains(&listed.len()), "{body}");
+        let newest = listed[0]["id"].as_i64().unwrap();
+        let [.., second, first] = listed.as_slice() else {
+            unreachable!()
+        };
+        let oldest = first["id"].as_i64().unwrap();
+        assert_eq!(first["previous_url"], serde_json::Value::Null);
         assert_eq!(
-            app.clone().oneshot(request(None)).await.unwrap().status(),
-            StatusCode::UNAUTHORIZED
+            second["previous_url"],
+            format!(
+                "/_/{id}/snapshots/notes.md?from={oldest}&to={}",
+                second["id"]
+            )
         );
 
-        let collaborator = make_access_cookie(
-            &state.access_secret,
-            &[(format!("w:{id}:collaborator"), required_hash)],
-            access_now_unix() + 60,
-            false,
+        let (status, body) = snapshots(&format!("id={oldest}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "# Notes\n\nFirst draft.\n");
+        assert_eq!(snapshots("id=999").await.0, StatusCode::NOT_FOUND);
+
+        let (status, body) = snapshots(&format!("from={oldest}")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains("First draft."), "{body}");
+        assert!(body.contains("Final text."), "{body}");
+        assert!(body.contains(&format!("notes.md #{oldest}")), "{body}");
+        assert_eq!(snapshots("from=latest").await.0, StatusCode::NOT_FOUND);
+
+        let (status, body) = snapshots("").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            body.contains(&format!("?from={newest}&amp;to=current")),
+            "{body}"
         );
-        assert_eq!(
-            app.clone()
-                .oneshot(request(Some(collaborator)))
-                .await
-                .unwrap()
-                .status(),
-            StatusCode::FORBIDDEN
+
+        let page = response_text(
+            render_markdown_file_async(
+                canonical.to_string_lossy().into_owned(),
+                id.clone(),
+                ws.clone(),
+                canonical_workspace_root(&ws),
+                state.clone(),
+                false,
+                1,
+            )
+            .await,
+        )
+        .await
+        .replace("&#x2F;", "/");
+        assert!(
+            page.contains(&format!(
+                r#"href="/_/{id}/snapshots/notes.md" id="snapshots-link-text""#
+            )),
+            "{page}"
         );
 
-        let admin =
-            admin_auth::make_admin_cookie(&state.management_token, access_now_unix(), false);
+        // A deleted document keeps its history, without the current side.
+        fs::remove_file(&document).unwrap();
+        let (status, body) = snapshots("format=json").await;
+        assert_eq!(status, StatusCode::OK);
+        let listed: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(listed["exists"], false);
         assert_eq!(
-            app.oneshot(request(Some(admin))).await.unwrap().status(),
-            StatusCode::NO_CONTENT
+            listed["snapshots"][0]["current_url"],
+            serde_json::Value::Null
+        );
+        assert_eq!(
+            snapshots(&format!("from={oldest}")).await.0,
+            StatusCode::NOT_FOUND
         );
+
+        let plain = tempfile::tempdir().unwrap();
+        fs::write(plain.path().join("a.md"), "# A").unwrap();
+        let plain_registry = Arc::new(WorkspaceRegistry::new("plain".into()));
+        let plain_id = add_test_workspace(&plain_registry, plain.path().to_path_buf(), all_flags());
+        let response = handle_document_snapshots(
+            State(test_state(plain_registry)),
+            AxumPath((plain_id, "a.md".to_string())),
+            Query::try_from_uri(&"/".parse().unwrap()).unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn server_builder_starts_from_local_defaults() {
+        let config = ServerBuilder::new()
+            .port(0)
+            .theme("sepia")
+            .workspace("/srv/docs", all_flags())
+            .build();
+        assert_eq!(config.host, "127.0.0.1");
+        assert_eq!(config.port, 0);
+        assert_eq!(config.theme, "sepia");
+        assert_eq!(config.default_chat_mode, "in_page");
+        assert!(config.open_browser.is_none());
+        assert!(config.shared_annotation);
+        let [workspace] = config.initial_workspaces.as_slice() else {
+            panic!("one workspace expected");
+        };
+        assert_eq!(workspace.path, PathBuf::from("/srv/docs"));
+        assert_eq!(workspace.flags, all_flags());
+        assert_eq!(ServerBuilder::default().build().port, DEFAULT_PORT);
     }
 
     #[tokio::test]
-    async fn administrator_workspace_responses_are_not_cached() {
-        let root = tempfile::tempdir().unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("admin-cache".into()));
-        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
-        let state = test_state(registry);
-        let route = format!("/{id}/page");
-        let app = Router::new()
-            .route(
-                "/{workspace_id}/page",
-                get(|| async { Html("<p>workspace</p>") }),
-            )
-            .layer(axum::middleware::from_fn(prevent_admin_response_caching))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                require_access_code,
-            ));
+    async fn router_serves_workspaces_without_binding_a_listener() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("README.md"), "# Embedded docs\n").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("embedded".into()));
+        let app = ServerBuilder::new()
+            .db_path(dir.path().join("markon.sqlite").to_string_lossy())
+            .trusted_host("docs.example.com")
+            .workspace(dir.path(), WorkspaceFlags::default())
+            .registry(registry.clone())
+            .router()
+            .await
+            .unwrap();
+        let id = registry.list()[0].id.clone();
 
-        let request = |cookie: Option<String>| {
-            let mut builder = axum::http::Request::builder().uri(&route);
-            if let Some(cookie) = cookie {
-                builder = builder.header(header::COOKIE, cookie);
-            }
-            builder.body(axum::body::Body::empty()).unwrap()
+        let request = |host: &str| {
+            let mut request = axum::http::Request::builder()
+                .uri(format!("/{id}/README.md"))
+                .header(header::HOST, host)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(axum::extract::ConnectInfo(
+                std::net::SocketAddr::from(([127, 0, 0, 1], 50000)),
+            ));
+            request
         };
+        let response = app
+            .clone()
+            .oneshot(request("docs.example.com"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response_text(response).await.contains("Embedded docs"));
 
-        let collaborator_response = app.clone().oneshot(request(None)).await.unwrap();
-        assert!(collaborator_response
-            .headers()
-            .get(header::CACHE_CONTROL)
-            .is_none());
-
-        let admin =
-            admin_auth::make_admin_cookie(&state.management_token, access_now_unix(), false);
-        let admin_response = app.oneshot(request(Some(admin))).await.unwrap();
-        assert_eq!(
-            admin_response
-                .headers()
-                .get(header::CACHE_CONTROL)
-                .and_then(|value| value.to_str().ok()),
-            Some("private, no-store")
-        );
+        let response = app.oneshot(request("elsewhere.example")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MISDIRECTED_REQUEST);
     }
 
     #[tokio::test]
-    async fn admin_nonce_exchange_sets_single_use_http_only_session() {
-        let state = test_state(Arc::new(WorkspaceRegistry::new("admin-exchange".into())));
-        let nonce = state.admin_bootstraps.issue_url("/abcd1234/");
-        let headers = headers_with(Some("http://127.0.0.1:6419"), Some("127.0.0.1:6419"));
-        let response = admin_session_handler(
-            State(state.clone()),
-            axum::extract::ConnectInfo(loopback()),
-            headers.clone(),
-            Json(AdminSessionRequest {
-                nonce: Some(nonce.clone()),
-                code: None,
-            }),
+    async fn router_under_a_base_path_links_into_it() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join("guide")).unwrap();
+        fs::write(
+            dir.path().join("README.md"),
+            "# Embedded docs\n\nLinks look like `'/_/css/x.css'`.\n",
         )
-        .await;
+        .unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("mounted".into()));
+        let app = ServerBuilder::new()
+            .db_path(dir.path().join("markon.sqlite").to_string_lossy())
+            .base_path("/docs/")
+            .workspace(dir.path(), WorkspaceFlags::default())
+            .registry(registry.clone())
+            .router()
+            .await
+            .unwrap();
+        let id = registry.list()[0].id.clone();
+        let get = |uri: String| {
+            let mut request = axum::http::Request::builder()
+                .uri(uri)
+                .header(header::HOST, "127.0.0.1")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(axum::extract::ConnectInfo(
+                std::net::SocketAddr::from(([127, 0, 0, 1], 50000)),
+            ));
+            app.clone().oneshot(request)
+        };
+
+        let response = get(format!("/docs/{id}/README.md")).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
-        let cookie = response
-            .headers()
-            .get(header::SET_COOKIE)
-            .and_then(|value| value.to_str().ok())
-            .expect("admin session cookie");
-        assert!(cookie.contains("HttpOnly; SameSite=Strict"));
-        assert!(admin_auth::admin_cookie_valid(
-            &state.management_token,
-            Some(cookie),
-            access_now_unix(),
-        ));
+        let html = response_text(response).await.replace("&#x2F;", "/");
+        assert!(html.contains("Embedded docs"), "{html}");
+        assert!(
+            html.contains(r#"<meta name="markon-base-path" content="/docs">"#),
+            "{html}"
+        );
+        assert!(html.contains(r#"href="/docs/_/favicon.svg""#), "{html}");
+        assert!(!html.contains(r#"href="/_/favicon.svg""#), "{html}");
+        // The document's own text is not rewritten.
+        assert!(html.contains("'/_/css/x.css'"), "{html}");
+
+        let response = get(format!("/docs/_/{id}/files/dir")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let json = response_text(response).await;
+        assert!(json.contains(&format!(r#""/docs/{id}/guide"#)), "{json}");
+
+        let response = get(format!("/docs/{id}/guide")).await.unwrap();
+        assert!(response.status().is_redirection());
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        assert!(location.starts_with(&format!("/docs/{id}/")), "{location}");
+
+        let response = get(format!("/{id}/README.md")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 
-        let replay = admin_session_handler(
-            State(state),
-            axum::extract::ConnectInfo(loopback()),
-            headers,
-            Json(AdminSessionRequest {
-                nonce: Some(nonce),
-                code: None,
-            }),
-        )
-        .await;
-        assert_eq!(replay.status(), StatusCode::UNAUTHORIZED);
+    #[test]
+    fn base_paths_are_normalized() {
+        assert_eq!(normalize_base_path("").unwrap(), "");
+        assert_eq!(normalize_base_path("/").unwrap(), "");
+        assert_eq!(normalize_base_path(" docs/v1/ ").unwrap(), "/docs/v1");
+        for bad in ["/a b", "/docs/../x", "/a\"b", "/docs?x"] {
+            assert!(normalize_base_path(bad).is_err(), "{bad}");
+        }
     }
 
     #[tokio::test]
-    async fn save_capability_cannot_cross_workspace_boundary() {
-        let root_a = tempfile::tempdir().unwrap();
-        let root_b = tempfile::tempdir().unwrap();
-        std::fs::write(root_a.path().join("a.md"), "workspace a").unwrap();
-        std::fs::write(root_b.path().join("b.md"), "workspace b").unwrap();
+    async fn servers_in_one_process_keep_their_own_rendering_settings() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "[ticket](jira:ABC-1)\n").unwrap();
+        fs::write(dir.path().join("notes.txt"), "# Plain notes\n").unwrap();
+        let serve = |name: &str, customize: fn(&mut ServerConfig)| {
+            let registry = Arc::new(WorkspaceRegistry::new(name.into()));
+            let mut config = ServerBuilder::new()
+                .db_path(dir.path().join(format!("{name}.sqlite")).to_string_lossy())
+                .workspace(dir.path(), WorkspaceFlags::default())
+                .registry(registry.clone())
+                .build();
+            customize(&mut config);
+            async move {
+                let app = router(config).await.unwrap();
+                (app, registry.list()[0].id.clone())
+            }
+        };
+        let (custom, custom_id) = serve("custom", |config| {
+            config.link_schemes = vec!["jira=https://jira.example.com/browse/".into()];
+            config.markdown_extensions = vec!["md".into(), "txt".into()];
+        })
+        .await;
+        let (plain, plain_id) = serve("plain", |_| {}).await;
+        let fetch = |app: Router, uri: String| async move {
+            let mut request = axum::http::Request::builder()
+                .uri(uri)
+                .header(header::HOST, "127.0.0.1")
+                .body(axum::body::Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(axum::extract::ConnectInfo(
+                std::net::SocketAddr::from(([127, 0, 0, 1], 50000)),
+            ));
+            response_text(app.oneshot(request).await.unwrap()).await
+        };
 
-        let registry = Arc::new(WorkspaceRegistry::new("save-scope".into()));
-        let id_a = add_test_workspace(&registry, root_a.path().to_path_buf(), all_flags());
-        let id_b = add_test_workspace(&registry, root_b.path().to_path_buf(), all_flags());
-        let state = test_state(registry);
-        let token_a = workspace_save_token(&state.save_token, &id_a);
-        let token_b = workspace_save_token(&state.save_token, &id_b);
-        let preview_token_b = workspace_preview_token(&state.save_token, &id_b);
-        assert_ne!(token_a, token_b);
-        assert_ne!(token_b, preview_token_b);
+        let jira = "https://jira.example.com/browse/ABC-1";
+        let html = fetch(custom.clone(), format!("/{custom_id}/a.md")).await;
+        assert!(html.contains(jira), "{html}");
+        let html = fetch(plain.clone(), format!("/{plain_id}/a.md")).await;
+        assert!(!html.contains(jira), "{html}");
+
+        let html = fetch(custom, format!("/{custom_id}/notes.txt")).await;
+        assert!(html.contains("Plain notes</h1>"), "{html}");
+        let html = fetch(plain, format!("/{plain_id}/notes.txt")).await;
+        assert!(!html.contains("Plain notes</h1>"), "{html}");
+    }
 
+    #[tokio::test]
+    async fn share_token_is_traded_for_a_cookie_and_required_everywhere() {
+        let mut state = test_state(Arc::new(WorkspaceRegistry::new("share".into())));
+        state.share_token = Some(Arc::new("t0k3n".into()));
         let app = Router::new()
-            .route("/api/save", post(save_file_handler))
+            .route("/{workspace_id}/{*path}", get(|| async { "doc" }))
             .layer(axum::middleware::from_fn_with_state(
                 state.clone(),
-                require_local_save_origin,
+                require_share_token,
             ))
-            .with_state(state);
-        let request = |token: &str, content: &str| {
-            axum::http::Request::builder()
-                .method("POST")
-                .uri("/api/save")
-                .header(header::CONTENT_TYPE, "application/json")
-                .header(header::HOST, "127.0.0.1:6419")
-                .header(header::ORIGIN, "http://127.0.0.1:6419")
-                .header("X-Markon-Token", token)
-                .extension(axum::extract::ConnectInfo(loopback()))
-                .body(axum::body::Body::from(
-                    json!({
-                        "workspace_id": id_b,
-                        "file_path": "b.md",
-                        "content": content,
-                    })
-                    .to_string(),
-                ))
-                .unwrap()
+            .with_state(state.clone());
+        let get = |uri: &str, cookie: Option<&str>| {
+            let mut request = axum::http::Request::builder().uri(uri);
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            app.clone()
+                .oneshot(request.body(axum::body::Body::empty()).unwrap())
         };
 
-        let denied = app
-            .clone()
-            .oneshot(request(&token_a, "stolen"))
-            .await
-            .unwrap();
-        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
-        assert_eq!(
-            std::fs::read_to_string(root_b.path().join("b.md")).unwrap(),
-            "workspace b"
-        );
+        for uri in ["/ws/a.md", "/ws/a.md?share_token=wrong", "/_/qr.svg"] {
+            let response = get(uri, None).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{uri}");
+        }
 
-        let denied = app
-            .clone()
-            .oneshot(request(&preview_token_b, "preview escalation"))
+        let response = get("/ws/a.md?mode=print&share_token=t0k3n", None)
             .await
             .unwrap();
-        assert_eq!(denied.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/ws/a.md?mode=print");
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap();
+        assert!(!cookie.contains("t0k3n"), "{cookie}");
+
+        let response = get("/ws/a.md", Some(cookie)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = get("/ws/a.md", Some("markon_share=forged")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
         assert_eq!(
-            std::fs::read_to_string(root_b.path().join("b.md")).unwrap(),
-            "workspace b"
+            with_share_token("http://10.0.0.5:6419/ws/#bootstrap_nonce=n", Some("t")),
+            "http://10.0.0.5:6419/ws/?share_token=t#bootstrap_nonce=n"
         );
-
-        let allowed = app.oneshot(request(&token_b, "updated b")).await.unwrap();
-        assert_eq!(allowed.status(), StatusCode::OK);
         assert_eq!(
-            std::fs::read_to_string(root_b.path().join("b.md")).unwrap(),
-            "updated b"
+            with_share_token("http://10.0.0.5:6419/ws/a.md?mode=preview", Some("t")),
+            "http://10.0.0.5:6419/ws/a.md?mode=preview&share_token=t"
         );
+        assert_eq!(with_share_token("http://h/", None), "http://h/");
     }
 
-    #[test]
-    fn annotation_id_cannot_replace_another_documents_row() {
-        let conn = Connection::open_in_memory().unwrap();
-        conn.execute(
-            "CREATE TABLE annotations (
-                id TEXT PRIMARY KEY,
-                file_path TEXT NOT NULL,
-                data TEXT NOT NULL
-            )",
-            [],
+    #[tokio::test]
+    async fn editor_sessions_work_under_a_share_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("editor-share".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.share_token = Some(Arc::new("t0k3n".into()));
+        let token = workspace_editor_token(&state.save_token, &id);
+        let app = Router::new()
+            .route("/api/editor/{workspace_id}/rpc", post(handle_editor_rpc))
+            .route(
+                "/api/editor/{workspace_id}/events",
+                get(handle_editor_events),
+            )
+            .route(
+                "/api/editor/{workspace_id}/preview",
+                get(handle_editor_preview),
+            )
+            .route("/_/css/{filename}", get(|| async { "css" }))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_share_token,
+            ))
+            .with_state(state.clone());
+        let send = |request: axum::http::Request<axum::body::Body>| app.clone().oneshot(request);
+
+        let response = send(
+            axum::http::Request::post(format!("/api/editor/{id}/rpc"))
+                .header("X-Markon-Token", &token)
+                .body(
+                    json!({"jsonrpc": "2.0", "id": 1, "method": "initialize"})
+                        .to_string()
+                        .into(),
+                )
+                .unwrap(),
         )
+        .await
         .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_str(&response_text(response).await).unwrap();
+        assert_eq!(body["result"]["protocol"], 1);
 
-        assert!(upsert_annotation_for_file(
-            &conn,
-            "shared-id",
-            "/workspace/a.md",
-            r#"{"id":"shared-id","text":"a"}"#,
+        // EventSource clients don't follow redirects: the stream must open
+        // straight away.
+        let response = send(
+            axum::http::Request::get(format!("/api/editor/{id}/events?token={token}"))
+                .body(axum::body::Body::empty())
+                .unwrap(),
         )
-        .unwrap());
-        assert!(!upsert_annotation_for_file(
-            &conn,
-            "shared-id",
-            "/workspace/b.md",
-            r#"{"id":"shared-id","text":"b"}"#,
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(
+            axum::http::Request::get(format!("/api/editor/{id}/events?token=wrong"))
+                .body(axum::body::Body::empty())
+                .unwrap(),
         )
-        .unwrap());
-
-        let (file_path, data): (String, String) = conn
-            .query_row(
-                "SELECT file_path, data FROM annotations WHERE id = ?1",
-                ["shared-id"],
-                |row| Ok((row.get(0)?, row.get(1)?)),
-            )
-            .unwrap();
-        assert_eq!(file_path, "/workspace/a.md");
-        assert!(data.contains(r#""text":"a""#));
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
 
-        assert!(upsert_annotation_for_file(
-            &conn,
-            "shared-id",
-            "/workspace/a.md",
-            r#"{"id":"shared-id","text":"a2"}"#,
+        // The preview tab gets the share cookie its assets need.
+        let response = send(
+            axum::http::Request::get(format!("/api/editor/{id}/preview?token={token}"))
+                .body(axum::body::Body::empty())
+                .unwrap(),
         )
-        .unwrap());
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let set_cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+        let cookie = set_cookie.split(';').next().unwrap().to_string();
+        let response = send(
+            axum::http::Request::get("/_/css/main.css")
+                .header(header::COOKIE, cookie)
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(
+            axum::http::Request::get("/_/css/main.css")
+                .body(axum::body::Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
     }
 
     #[tokio::test]
-    async fn document_state_is_always_sqlite_for_admin_and_shared_only_for_collaborators() {
+    async fn audit_log_records_annotation_changes_and_served_files() {
         let root = tempfile::tempdir().unwrap();
-        let file = root.path().join("note.md");
-        fs::write(&file, "# note").unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("document-state".into()));
-        let id = add_test_workspace(
-            &registry,
-            root.path().to_path_buf(),
-            WorkspaceFlags::default(),
-        );
-        let mut events = registry.get(&id).unwrap().events_tx.subscribe();
-        let conn = Connection::open_in_memory().unwrap();
-        conn.execute_batch(
-            "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL);
-             CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP);",
-        )
-        .unwrap();
-        let mut state = test_state(registry.clone());
-        state.db = Some(Arc::new(Mutex::new(conn)));
+        fs::write(root.path().join("note.md"), "# note").unwrap();
+        let file = dunce::canonicalize(root.path().join("note.md")).unwrap();
         let path = file.to_string_lossy().into_owned();
-        let annotation = serde_json::json!({
-            "id": "anno-admin",
+        let registry = Arc::new(WorkspaceRegistry::new("audit".into()));
+        let id = add_test_workspace(&registry, root.path().to_path_buf(), all_flags());
+        let data = tempfile::tempdir().unwrap();
+        let db = crate::db::Database::open(&data.path().join("db.sqlite")).unwrap();
+        let mut state = test_state(registry);
+        state.db = Some(db.clone());
+        state.audit = true;
+        let app = Router::new()
+            .route(DOCUMENT_STATE_ROUTE, post(handle_document_state_command))
+            .route("/{workspace_id}/{*path}", get(handle_workspace_path))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                record_audit,
+            ))
+            .layer(axum::middleware::from_fn(
+                |mut req: axum::extract::Request, next: axum::middleware::Next| async move {
+                    req.extensions_mut().insert(AccessRole::Collaborator);
+                    req.extensions_mut()
+                        .insert(axum::extract::ConnectInfo(lan_peer()));
+                    next.run(req).await
+                },
+            ))
+            .with_state(state);
+        let command = |body: serde_json::Value| {
+            app.clone().oneshot(
+                axum::http::Request::post(format!("/_/{id}/data/document-state"))
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(axum::body::Body::from(body.to_string()))
+                    .unwrap(),
+            )
+        };
+        let ana = serde_json::json!({ "name": "Ana", "color": "#f00" });
+        let bo = serde_json::json!({ "name": "Bo", "color": "#00f" });
+        let mut annotation = serde_json::json!({
+            "id": "anno-1",
             "text": "note",
             "anchor": { "position": 0, "exact": "note", "prefix": "", "suffix": "" },
             "type": "highlight-yellow",
             "tagName": "span",
-            "createdAt": 1
+            "createdAt": 1,
+            "author": ana,
         });
-
-        let denied = handle_document_state_command(
-            State(state.clone()),
-            AxumPath(id.clone()),
-            Some(Extension(AccessRole::Collaborator)),
-            Json(DocumentStateCommand::SaveAnnotation {
-                path: path.clone(),
-                annotation: annotation.clone(),
-                op_id: None,
+        let response = command(serde_json::json!({
+            "action": "save_annotation", "path": path, "annotation": annotation,
+        }))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        annotation["revision"] = 1.into();
+        annotation["note"] = "edited".into();
+        for body in [
+            serde_json::json!({
+                "action": "save_annotation", "path": path, "annotation": annotation, "by": bo,
             }),
-        )
-        .await;
-        assert_eq!(denied.status(), StatusCode::FORBIDDEN);
+            serde_json::json!({ "action": "delete_annotation", "path": path, "id": "anno-1", "by": bo }),
+            // A failed command leaves no trace.
+            serde_json::json!({ "action": "delete_annotation", "path": path, "id": "bad id" }),
+        ] {
+            command(body).await.unwrap();
+        }
+        let response = app
+            .clone()
+            .oneshot(
+                axum::http::Request::get(format!("/{id}/note.md"))
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
 
-        let saved = handle_document_state_command(
-            State(state.clone()),
-            AxumPath(id.clone()),
-            Some(Extension(AccessRole::Admin)),
-            Json(DocumentStateCommand::SaveAnnotation {
-                path: path.clone(),
-                annotation,
-                op_id: None,
-            }),
-        )
-        .await;
-        assert_eq!(saved.status(), StatusCode::NO_CONTENT);
-        assert!(matches!(
-            events.try_recv(),
-            Err(tokio::sync::broadcast::error::TryRecvError::Empty)
-        ));
-        let loaded = handle_document_state(
-            State(state.clone()),
-            AxumPath(id.clone()),
-            Some(Extension(AccessRole::Admin)),
-            Query(DocumentStateQuery { path: path.clone() }),
-        )
-        .await;
-        assert_eq!(loaded.status(), StatusCode::OK);
-        let body = response_text(loaded).await;
-        assert!(body.contains("anno-admin"), "{body}");
+        let entries = db
+            .read(|conn| crate::audit::list(conn, &crate::audit::AuditQuery::default()))
+            .await
+            .unwrap()
+            .unwrap();
+        let logged: Vec<_> = entries
+            .iter()
+            .rev()
+            .map(|entry| (entry.action.as_str(), entry.actor.as_deref()))
+            .collect();
+        assert_eq!(
+            logged,
+            [
+                ("create", Some("Ana")),
+                ("edit", Some("Bo")),
+                ("delete", Some("Bo")),
+                ("serve", None),
+            ]
+        );
+        assert!(entries.iter().all(|entry| entry.file_path == path
+            && entry.role.as_deref() == Some("collaborator")
+            && entry.ip.as_deref() == Some("192.168.1.50")));
+        assert_eq!(entries[1].annotation_id.as_deref(), Some("anno-1"));
+    }
 
-        let flags = WorkspaceFlags {
-            shared_annotation: true,
-            ..Default::default()
-        };
-        assert!(registry.update_flags(&id, flags));
-        let anonymous = handle_document_state(
-            State(state.clone()),
-            AxumPath(id.clone()),
-            None,
-            Query(DocumentStateQuery { path: path.clone() }),
+    #[tokio::test]
+    async fn compare_renders_two_documents_side_by_side() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("draft.md"),
+            "# Plan\n\nShip on Friday.\n\nNew paragraph.\n",
         )
-        .await;
-        assert_eq!(anonymous.status(), StatusCode::FORBIDDEN);
-        let shared_annotation = serde_json::json!({
-            "id": "anno-shared",
-            "text": "shared note",
-            "anchor": { "position": 0, "exact": "note", "prefix": "", "suffix": "" },
-            "type": "highlight-yellow",
-            "tagName": "span",
-            "createdAt": 2
-        });
-        let shared_save = handle_document_state_command(
-            State(state.clone()),
-            AxumPath(id.clone()),
-            Some(Extension(AccessRole::Collaborator)),
-            Json(DocumentStateCommand::SaveAnnotation {
-                path: path.clone(),
-                annotation: shared_annotation,
-                op_id: Some("shared-op".to_string()),
-            }),
+        .unwrap();
+        fs::write(
+            dir.path().join("published.md"),
+            "# Plan\n\nShip on Monday.\n\nOld paragraph.\n",
         )
-        .await;
-        assert_eq!(shared_save.status(), StatusCode::NO_CONTENT);
-        match events.try_recv().unwrap() {
-            WorkspaceEvent::Channel { channel, payload } => {
-                let canonical = dunce::canonicalize(&file).unwrap();
-                assert_eq!(channel, format!("document:{}", canonical.to_string_lossy()));
-                assert!(payload.contains("anno-shared"), "{payload}");
-                assert!(payload.contains("shared-op"), "{payload}");
+        .unwrap();
+        fs::write(dir.path().join("notes.txt"), "plain").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("compare".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let compare = |left: &str, right: &str| {
+            let (state, id) = (state.clone(), id.clone());
+            let query = CompareQuery {
+                left: left.to_string(),
+                right: right.to_string(),
+            };
+            async move {
+                let response =
+                    handle_compare_documents(State(state), AxumPath(id), Query(query)).await;
+                let status = response.status();
+                (status, response_text(response).await.replace("&#x2F;", "/"))
             }
-            other => panic!("unexpected workspace event: {other:?}"),
-        }
-        let shared = handle_document_state(
-            State(state),
-            AxumPath(id),
-            Some(Extension(AccessRole::Collaborator)),
-            Query(DocumentStateQuery { path }),
+        };
+
+        let (status, body) = compare("published.md", "draft.md").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            body.contains("<title>published.md ↔ draft.md</title>"),
+            "{body}"
+        );
+        assert!(body.contains(r#"class="compare-row is-equal""#), "{body}");
+        assert!(!body.contains(r#"class="compare-identical""#), "{body}");
+        let changed = body.matches(r#"class="compare-row is-"#).count()
+            - body.matches(r#"class="compare-row is-equal""#).count();
+        assert!(changed >= 1, "{body}");
+        assert!(body.contains("Ship on Monday."), "{body}");
+        assert!(body.contains("Ship on Friday."), "{body}");
+        assert!(
+            body.contains(&format!(
+                r#"href="/_/{id}/compare?left=draft.md&amp;right=published.md""#
+            )),
+            "{body}"
+        );
+
+        let (status, body) = compare("draft.md", "draft.md").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.contains(r#"class="compare-identical""#), "{body}");
+
+        assert_eq!(
+            compare("draft.md", "notes.txt").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            compare("../draft.md", "draft.md").await.0,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            workspace_compare_url("ab12", "docs/a b.md", "b.md"),
+            "/_/ab12/compare?left=docs%2Fa%20b.md&right=b.md"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn pandoc_formats_render_as_read_only_documents() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let tools = tempfile::tempdir().unwrap();
+        // Stands in for pandoc: a Markdown document naming its reader.
+        let program = tools.path().join("pandoc");
+        fs::write(
+            &program,
+            "#!/bin/sh\nprintf '# Minutes\\n\\nRead as %s\\n' \"$4\"\n",
         )
-        .await;
-        assert_eq!(shared.status(), StatusCode::OK);
+        .unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::write(dir.path().join("minutes.odt"), b"PK\x03\x04\0\0binary").unwrap();
+        fs::write(dir.path().join("notes.rtf"), "{\\rtf1 notes}").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("pandoc".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        let page = |state: AppState, path: &str, source: bool| {
+            let (id, path) = (id.clone(), path.to_string());
+            async move {
+                let response = handle_workspace_path(
+                    State(state),
+                    AxumPath((id, path)),
+                    None,
+                    Query(DirViewQuery {
+                        source,
+                        ..DirViewQuery::default()
+                    }),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                response_text(response).await.replace("&#x2F;", "/")
+            }
+        };
+
+        // Without --pandoc the files are served as they are.
+        let body = page(state.clone(), "notes.rtf", false).await;
+        assert!(!body.contains("document-converted"), "{body}");
+
+        state.pandoc = Some(Arc::new(crate::pandoc::Pandoc::new(&program)));
+        let body = page(state.clone(), "minutes.odt", false).await;
+        assert!(body.contains(r#"<h1 id="minutes">Minutes</h1>"#), "{body}");
+        assert!(body.contains("Read as --from=odt"), "{body}");
+        assert!(body.contains("<span>ODT</span>"), "{body}");
+        assert!(body.contains(r#"href="?source=true""#), "{body}");
+        assert!(
+            body.contains(r#"<meta name="enable-edit" content="false">"#),
+            "{body}"
+        );
+        let body = page(state.clone(), "notes.rtf", false).await;
+        assert!(body.contains("Read as --from=rtf"), "{body}");
+
+        // The original stays one click away.
+        let body = page(state.clone(), "minutes.odt", true).await;
+        assert!(body.starts_with("PK"), "{body}");
+        let body = page(state, "notes.rtf", true).await;
+        assert!(!body.contains("document-converted"), "{body}");
     }
 
-    #[test]
-    fn access_cooldown_locks_after_threshold() {
-        let state = test_state(Arc::new(WorkspaceRegistry::new("s".into())));
-        let ip: std::net::IpAddr = "1.2.3.4".parse().unwrap();
-        for _ in 0..ACCESS_MAX_FAILS - 1 {
-            assert!(access_record_failure(&state, ip).is_none());
-        }
-        assert!(access_cooldown_remaining(&state, ip).is_none());
-        assert!(access_record_failure(&state, ip).is_some()); // crosses threshold → locks
-        assert!(access_cooldown_remaining(&state, ip).is_some());
-        access_record_success(&state, ip);
-        assert!(access_cooldown_remaining(&state, ip).is_none());
+    #[tokio::test]
+    async fn document_diff_page_focuses_one_file_between_revisions() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+        };
+        git(&["init"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test User"]);
+        fs::write(dir.path().join("guide.md"), "# Guide\n\nOld step.\n").unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-m", "initial"]);
+        fs::write(dir.path().join("guide.md"), "# Guide\n\nNew step.\n").unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\n\nMore.\n").unwrap();
+        git(&["commit", "-am", "revise"]);
+
+        let registry = Arc::new(WorkspaceRegistry::new("document-diff".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let diff = |path: &str, from: &str| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            let from = from.to_string();
+            async move {
+                handle_document_diff(
+                    State(state),
+                    AxumPath((id, path)),
+                    Query(DocumentDiffQuery {
+                        from: Some(from),
+                        to: Some("HEAD".into()),
+                        view: None,
+                    }),
+                    Some(Extension(AccessRole::Admin)),
+                )
+                .await
+                .into_response()
+            }
+        };
+
+        let response = diff("guide.md", "HEAD~1").await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await;
+        assert!(
+            body.contains(r#"data-default-diff-path="guide.md""#),
+            "{body}"
+        );
+        assert!(body.contains(r#"data-diff-path="guide.md""#), "{body}");
+        assert!(!body.contains(r#"data-diff-path="notes.md""#), "{body}");
+
+        assert_eq!(
+            diff("guide.md", "HEAD~5").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            diff("guide.md", "HEAD~1;rm").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            diff("../guide.md", "HEAD~1").await.status(),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            diff("notes.txt", "HEAD~1").await.status(),
+            StatusCode::BAD_REQUEST
+        );
     }
 
-    #[test]
-    fn access_gated_workspace_recognizes_routes() {
+    #[tokio::test]
+    async fn document_history_lists_its_commits_and_renders_old_versions() {
+        let dir = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(dir.path())
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {:?} failed", args);
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        };
+        git(&["init"]);
+        git(&["config", "user.email", "test@example.com"]);
+        git(&["config", "user.name", "Test User"]);
+        fs::write(dir.path().join("guide.md"), "# Guide\n\nOld step.\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-m", "write the guide"]);
+        let first = git(&["rev-parse", "HEAD"]);
+        fs::write(dir.path().join("notes.md"), "# Notes\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-m", "start notes"]);
+        fs::write(dir.path().join("guide.md"), "# Guide\n\nNew step.\n").unwrap();
+        git(&["commit", "-am", "revise the guide"]);
+
+        let registry = Arc::new(WorkspaceRegistry::new("document-history".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+        let history = |path: &str, rev: Option<&str>| {
+            let (state, id, path) = (state.clone(), id.clone(), path.to_string());
+            let rev = rev.map(str::to_string);
+            async move {
+                handle_document_history(
+                    State(state),
+                    AxumPath((id, path)),
+                    Query(GitHistoryQuery {
+                        branch: None,
+                        author: None,
+                        range: None,
+                    }),
+                    Query(DocumentRevisionQuery { rev }),
+                )
+                .await
+                .into_response()
+            }
+        };
+
+        let response = history("guide.md", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await.replace("&#x2F;", "/");
+        assert!(body.contains("revise the guide"), "{body}");
+        assert!(body.contains("write the guide"), "{body}");
+        assert!(!body.contains("start notes"), "{body}");
+        assert!(
+            body.contains(&format!("/_/{id}/history/guide.md?rev={first}")),
+            "{body}"
+        );
+        assert!(body.contains(&format!("/_/{id}/diff/guide.md?from=")));
+
+        let response = history("guide.md", Some(&first)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await;
+        assert!(body.contains("Old step."), "{body}");
+        assert!(!body.contains("New step."), "{body}");
+        assert!(body.contains("write the guide"), "{body}");
+        // Reading an old version leaves the working copy alone.
         assert_eq!(
-            access_gated_workspace("/abcd1234/doc.md").as_deref(),
-            Some("abcd1234")
+            fs::read_to_string(dir.path().join("guide.md")).unwrap(),
+            "# Guide\n\nNew step.\n"
         );
+
         assert_eq!(
-            access_gated_workspace("/api/chat/abcd1234/threads").as_deref(),
-            Some("abcd1234")
+            history("notes.md", Some(&first)).await.status(),
+            StatusCode::NOT_FOUND
         );
         assert_eq!(
-            access_gated_workspace("/_/ws/abcd1234").as_deref(),
-            Some("abcd1234")
+            history("guide.md", Some("HEAD;rm")).await.status(),
+            StatusCode::BAD_REQUEST
         );
         assert_eq!(
-            access_gated_workspace("/_/abcd1234/ws").as_deref(),
-            Some("abcd1234")
+            history("../guide.md", None).await.status(),
+            StatusCode::BAD_REQUEST
         );
-        assert!(access_gated_workspace("/_/ws").is_none());
+    }
+
+    #[tokio::test]
+    async fn remote_markdown_is_fetched_only_from_allowed_hosts() {
+        let upstream = Router::new().route(
+            "/repo/main/README.md",
+            get(|| async { "# Remote Guide\n\n![logo](img/logo.png)\n" }),
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let _ = axum::serve(listener, upstream).await;
+        });
+        let document_url = format!("http://{addr}/repo/main/README.md");
+
+        let registry = Arc::new(WorkspaceRegistry::new("remote".into()));
+        let fetch = |state: AppState, url: &str| {
+            let url = Some(url.to_string());
+            async move {
+                handle_remote_markdown(State(state), Query(RemoteMarkdownQuery { url }))
+                    .await
+                    .into_response()
+            }
+        };
+
+        let state = test_state(registry.clone());
         assert_eq!(
-            access_gated_workspace("/_/abcd1234/git/diff/work").as_deref(),
-            Some("abcd1234")
+            fetch(state.clone(), &document_url).await.status(),
+            StatusCode::FORBIDDEN
         );
+
+        let mut state = test_state(registry);
+        state.remote_markdown = Arc::new(RemoteMarkdown::new(RemoteAllowlist::new(&[
+            "127.0.0.1".to_string()
+        ])));
         assert_eq!(
-            access_gated_workspace("/_/abcd1234/search").as_deref(),
-            Some("abcd1234")
+            fetch(state.clone(), "file:///etc/passwd").await.status(),
+            StatusCode::BAD_REQUEST
         );
-        for encoded in [
-            "/%61bcd1234/doc.md",
-            "/api/chat/%61bcd1234/threads",
-            "/_/ws/%61bcd1234",
-            "/_/%61bcd1234/ws",
-        ] {
-            assert_eq!(
-                access_gated_workspace(encoded).as_deref(),
-                Some("abcd1234"),
-                "encoded workspace id escaped the gate: {encoded}"
+        assert_eq!(
+            fetch(state.clone(), "http://example.com/README.md")
+                .await
+                .status(),
+            StatusCode::FORBIDDEN
+        );
+        let response = fetch(state.clone(), &document_url).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response_text(response).await.replace("&#x2F;", "/");
+        assert!(body.contains("<title>Remote Guide</title>"), "{body}");
+        assert!(
+            body.contains(&format!("http://{addr}/repo/main/img/logo.png")),
+            "{body}"
+        );
+
+        // A fresh copy is served from the cache while the origin is down.
+        server.abort();
+        let _ = server.await;
+        assert_eq!(fetch(state, &document_url).await.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn remote_markdown_needs_an_admin_or_collaborator() {
+        let salt = "test-salt";
+        let dir = tempfile::tempdir().unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new(salt.into()));
+        let workspace_hash = crate::workspace::hash_access_code(salt, "ws-code");
+        let id = registry.add(WorkspaceConfig {
+            path: dunce::canonicalize(dir.path()).unwrap(),
+            flags: WorkspaceFlags::default(),
+            collaborator_access_code_hash: workspace_hash.clone(),
+            ..Default::default()
+        });
+        let server_hash = crate::workspace::hash_access_code(salt, "server-code");
+        let mut state = test_state(registry);
+        state.collaborator_access_code_hash = Arc::new(server_hash.clone());
+        state.remote_markdown =
+            Arc::new(RemoteMarkdown::new(RemoteAllowlist::new(
+                &["*".to_string()],
+            )));
+        let app = Router::new()
+            .route(REMOTE_MARKDOWN_ROUTE, get(handle_remote_markdown))
+            .layer(axum::middleware::from_fn_with_state(
+                state.clone(),
+                require_access_code,
+            ))
+            .with_state(state.clone());
+        // An unparseable URL is answered 400 by the handler, past the gate.
+        let get = |cookie: Option<String>| {
+            let mut request = axum::http::Request::get("/_/remote?url=nope");
+            if let Some(cookie) = cookie {
+                request = request.header(header::COOKIE, cookie);
+            }
+            app.clone()
+                .oneshot(request.body(axum::body::Body::empty()).unwrap())
+        };
+        let access_cookie = |scope: String, hash: &str| {
+            let cookie = make_access_cookie(
+                salt,
+                &[(scope, hash.to_string())],
+                access_now_unix() + 100,
+                false,
             );
-        }
-        assert!(access_gated_workspace("/_/%2Fbad123/ws").is_none());
-        assert!(access_gated_workspace("/_/css/tokens.css").is_none());
-        assert!(access_gated_workspace("/_/unlock").is_none());
-        assert!(access_gated_workspace("/api/preview").is_none());
-        assert!(access_gated_workspace("/favicon.ico").is_none());
+            cookie.split(';').next().unwrap().to_string()
+        };
+
+        assert_eq!(get(None).await.unwrap().status(), StatusCode::UNAUTHORIZED);
+        let forged = access_cookie("s:collaborator".into(), "not-the-hash");
+        assert_eq!(
+            get(Some(forged)).await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let admin =
+            admin_auth::make_admin_cookie(&state.management_token, access_now_unix(), false);
+        let admin = admin.split(';').next().unwrap().to_string();
+        for cookie in [
+            admin,
+            access_cookie("s:collaborator".into(), &server_hash),
+            access_cookie(format!("w:{id}:collaborator"), &workspace_hash),
+        ] {
+            assert_eq!(
+                get(Some(cookie.clone())).await.unwrap().status(),
+                StatusCode::BAD_REQUEST,
+                "{cookie}"
+            );
+        }
     }
 
     #[tokio::test]
-    async fn workspace_ws_upgrade_requires_remote_access_cookie() {
-        let root = tempfile::tempdir().unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("ws-access-gate".into()));
-        let id = add_test_workspace(
-            &registry,
-            root.path().to_path_buf(),
-            WorkspaceFlags {
-                enable_live: true,
+    async fn directory_listing_hides_ignored_entries_until_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("target/doc")).unwrap();
+        fs::create_dir_all(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join(".ignore"), "target/\n*.log\n").unwrap();
+        fs::write(dir.path().join("target/doc/api.md"), "# API").unwrap();
+        fs::write(dir.path().join("docs/guide.md"), "# Guide").unwrap();
+        fs::write(dir.path().join("build.log"), "noise").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("dir-ignored".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let state = test_state(registry);
+
+        let root_page = |show_ignored: bool| {
+            let (state, id) = (state.clone(), id.clone());
+            let query = DirViewQuery {
+                show_ignored,
                 ..Default::default()
-            },
+            };
+            async move {
+                let response = handle_workspace_root(
+                    State(state),
+                    AxumPath(id),
+                    None,
+                    Query(query),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                response_text(response).await
+            }
+        };
+        let body = root_page(false).await;
+        assert!(body.contains(r#"data-entry-path="docs""#), "{body}");
+        assert!(!body.contains(r#"data-entry-path="target""#), "{body}");
+        assert!(!body.contains(r#"data-entry-path="build.log""#), "{body}");
+
+        let body = root_page(true).await;
+        assert!(body.contains(r#"data-entry-path="target""#), "{body}");
+        assert!(body.contains(r#"data-entry-path="build.log""#), "{body}");
+        assert!(body.contains(r#"data-entry-ignored="true""#), "{body}");
+
+        let tree = |show_ignored: bool| {
+            let (state, id) = (state.clone(), id.clone());
+            async move {
+                let response = handle_workspace_dir_data(
+                    State(state),
+                    AxumPath(id),
+                    None,
+                    Query(DirListingQuery {
+                        show_ignored,
+                        ..Default::default()
+                    }),
+                )
+                .await
+                .into_response();
+                let entries: Vec<serde_json::Value> =
+                    serde_json::from_str(&response_text(response).await).unwrap();
+                entries
+                    .into_iter()
+                    .map(|entry| entry["name"].as_str().unwrap().to_string())
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(tree(false).await, vec!["docs", ".ignore"]);
+        assert_eq!(
+            tree(true).await,
+            vec!["docs", "target", ".ignore", "build.log"]
         );
-        let required_hash = "required-hash".to_string();
-        assert!(registry.set_collaborator_access_code(&id, &required_hash));
+    }
+
+    #[tokio::test]
+    async fn workspace_dir_data_nests_children_up_to_the_requested_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs/guide/deep")).unwrap();
+        fs::create_dir_all(dir.path().join(".hidden")).unwrap();
+        fs::write(dir.path().join("docs/guide/deep/page.md"), "# Page").unwrap();
+        fs::write(dir.path().join(".hidden/secret.md"), "# Secret").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("dir-tree".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
         let state = test_state(registry);
-        let app = Router::new()
-            .route(WORKSPACE_WS_ROUTE, get(|| async { StatusCode::NO_CONTENT }))
-            .layer(axum::middleware::from_fn_with_state(
-                state.clone(),
-                require_access_code,
-            ));
 
-        let request = axum::http::Request::builder()
-            .uri(format!("/_/{id}/ws"))
-            .header(header::UPGRADE, "websocket")
-            .header(header::ORIGIN, "http://markon.local")
-            .header(header::HOST, "markon.local")
-            .extension(axum::extract::ConnectInfo(lan_peer()))
-            .body(axum::body::Body::empty())
-            .unwrap();
-        let response = app.clone().oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let dir_data = |depth: Option<usize>| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                let response = handle_workspace_dir_data(
+                    State(state),
+                    AxumPath(id),
+                    None,
+                    Query(DirListingQuery {
+                        path: None,
+                        depth,
+                        ..Default::default()
+                    }),
+                )
+                .await
+                .into_response();
+                assert_eq!(response.status(), StatusCode::OK);
+                serde_json::from_str::<serde_json::Value>(&response_text(response).await).unwrap()
+            }
+        };
 
-        let encoded_id = format!("%{:02X}{}", id.as_bytes()[0], &id[1..]);
-        let encoded_request = axum::http::Request::builder()
-            .uri(format!("/_/{encoded_id}/ws"))
-            .header(header::UPGRADE, "websocket")
-            .header(header::ORIGIN, "http://markon.local")
-            .header(header::HOST, "markon.local")
-            .extension(axum::extract::ConnectInfo(lan_peer()))
-            .body(axum::body::Body::empty())
+        let flat = dir_data(None).await;
+        assert!(flat
+            .as_array()
+            .unwrap()
+            .iter()
+            .all(|entry| entry.get("children").is_none()));
+
+        let tree = dir_data(Some(3)).await;
+        let entries = tree.as_array().unwrap();
+        let hidden = entries.iter().find(|e| e["name"] == ".hidden").unwrap();
+        assert!(hidden.get("children").is_none());
+        let docs = entries.iter().find(|e| e["name"] == "docs").unwrap();
+        let guide = &docs["children"][0];
+        assert_eq!(guide["rel_git_path"], "docs/guide");
+        let deep = &guide["children"][0];
+        assert_eq!(deep["rel_git_path"], "docs/guide/deep");
+        // Depth 3 lists three levels; the fourth is left for a lazy fetch.
+        assert!(deep.get("children").is_none());
+
+        let capped = dir_data(Some(usize::MAX)).await;
+        let docs = capped
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|e| e["name"] == "docs")
             .unwrap();
-        let encoded_response = app.clone().oneshot(encoded_request).await.unwrap();
-        assert_eq!(encoded_response.status(), StatusCode::UNAUTHORIZED);
-
-        let cookie = make_access_cookie(
-            &state.access_secret,
-            &[(format!("w:{id}:collaborator"), required_hash)],
-            access_now_unix() + 60,
-            false,
+        assert_eq!(
+            docs["children"][0]["children"][0]["children"][0]["name"],
+            "page.md"
         );
-        let request = axum::http::Request::builder()
-            .uri(format!("/_/{id}/ws"))
-            .header(header::UPGRADE, "websocket")
-            .header(header::COOKIE, cookie)
-            .extension(axum::extract::ConnectInfo(lan_peer()))
-            .body(axum::body::Body::empty())
-            .unwrap();
-        let response = app.oneshot(request).await.unwrap();
-        assert_eq!(response.status(), StatusCode::NO_CONTENT);
     }
 
     #[tokio::test]
-    async fn workspace_ws_route_and_broadcast_are_actually_isolated() {
-        use tokio_tungstenite::tungstenite::{Error as WsError, Message as ClientMessage};
-
-        let root_a = tempfile::tempdir().unwrap();
-        let root_b = tempfile::tempdir().unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("ws-integration".into()));
-        let live_flags = WorkspaceFlags {
-            enable_live: true,
-            ..Default::default()
+    async fn workspace_search_rejects_invalid_parameters_with_json_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.md"), "# A\nalpha").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("search-limit".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.search_max_limit = 5;
+
+        let search = |uri: &str| {
+            let state = state.clone();
+            let id = id.clone();
+            let query = Query::<SearchQuery>::try_from_uri(&uri.parse().unwrap());
+            async move {
+                let response =
+                    workspace_search_handler(State(state), AxumPath(id), None, query).await;
+                let status = response.status();
+                let body: serde_json::Value =
+                    serde_json::from_slice(&response_bytes(response).await).unwrap();
+                (status, body)
+            }
         };
-        let id_a = add_test_workspace(&registry, root_a.path().to_path_buf(), live_flags);
-        let id_b = add_test_workspace(&registry, root_b.path().to_path_buf(), live_flags);
-        let (addr, server) = spawn_collaboration_test_server(test_state(registry.clone())).await;
 
-        let (mut socket_a, _) =
-            tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
-                .await
-                .unwrap();
-        let (mut socket_a_other_channel, _) =
-            tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
-                .await
-                .unwrap();
-        let (mut socket_b, _) =
-            tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_b}/ws"))
-                .await
-                .unwrap();
+        for uri in [
+            "/?q=alpha&limit=abc",
+            "/?q=alpha&limit=0",
+            "/?q=alpha&limit=6",
+            "/?q=alpha&offset=1000000000000",
+            "/?q=alpha&offset=1000000000000&mode=regex",
+            "/?q=alpha&modified_after=yesterday",
+            "/?q=alpha&snippet_chars=0",
+            "/?q=%28&mode=regex",
+        ] {
+            let (status, body) = search(uri).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+            assert_eq!(body["success"], false, "{uri}");
+            assert!(!body["message"].as_str().unwrap().is_empty(), "{uri}");
+        }
 
-        socket_a
-            .send(ClientMessage::Text(
-                serde_json::json!({
-                    "type": "hello",
-                    "target": { "kind": "surface", "key": format!("/{id_a}/") }
-                })
-                .to_string()
-                .into(),
-            ))
-            .await
-            .unwrap();
-        socket_a_other_channel
-            .send(ClientMessage::Text(
-                serde_json::json!({
-                    "type": "hello",
-                    "target": {
-                        "kind": "surface",
-                        "key": format!("/_/{id_a}/compare")
-                    }
-                })
-                .to_string()
-                .into(),
-            ))
-            .await
-            .unwrap();
-        socket_b
-            .send(ClientMessage::Text(
-                serde_json::json!({
-                    "type": "hello",
-                    "target": { "kind": "surface", "key": format!("/{id_b}/") }
-                })
-                .to_string()
-                .into(),
-            ))
-            .await
+        let (status, body) = search("/?q=alpha&limit=5").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["limit"], 5);
+        // The default page size is capped by a smaller configured maximum.
+        let (_, body) = search("/?q=alpha").await;
+        assert_eq!(body["limit"], 5);
+    }
+
+    #[tokio::test]
+    async fn workspace_search_includes_matching_shared_annotations() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("api.md"), "# API\nRetry loop").unwrap();
+        fs::write(outside.path().join("other.md"), "# Other").unwrap();
+
+        let registry = Arc::new(WorkspaceRegistry::new("search-annotations".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL);",
+        )
+        .unwrap();
+        let rows = [
+            (
+                "anno-hit",
+                dir.path().join("api.md"),
+                "Timeouts need a backoff",
+            ),
+            ("anno-miss", dir.path().join("api.md"), "Unrelated"),
+            (
+                "anno-out",
+                outside.path().join("other.md"),
+                "Timeouts elsewhere",
+            ),
+        ];
+        for (anno, path, note) in rows {
+            let path = dunce::canonicalize(path).unwrap();
+            let data = serde_json::json!({ "id": anno, "text": "Retry loop", "note": note });
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                params![anno, path.to_string_lossy(), data.to_string()],
+            )
             .unwrap();
+        }
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+
+        let search = |role: Option<AccessRole>, offset: usize| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                let query = SearchQuery {
+                    q: "timeouts".into(),
+                    offset,
+                    ..SearchQuery::default()
+                };
+                workspace_search_results(&state, &id, role, &query)
+                    .await
+                    .unwrap()
+                    .0
+            }
+        };
 
-        socket_a
-            .send(ClientMessage::Text(
-                r#"{"type":"live_action","data":{"action":"focus"}}"#.into(),
-            ))
+        let page = search(Some(AccessRole::Admin), 0).await;
+        assert_eq!(page.annotations.len(), 1);
+        let hit = &page.annotations[0];
+        assert_eq!(hit.annotation_id, "anno-hit");
+        assert_eq!(hit.file_path, "api.md");
+        assert_eq!(hit.snippet, "<b>Timeouts</b> need a backoff");
+
+        // Later pages and readers without annotation access get none.
+        assert!(search(Some(AccessRole::Admin), 20)
             .await
-            .unwrap();
-        let echoed = tokio::time::timeout(std::time::Duration::from_secs(2), socket_a.next())
+            .annotations
+            .is_empty());
+        assert!(search(None, 0).await.annotations.is_empty());
+
+        // A shared store (`--db-url`) is searched in place of SQLite.
+        let db = state.db.take().unwrap();
+        state.remote_store = Some(Arc::new(crate::store::sqlite::SqliteStore::new(db)));
+        let query = SearchQuery {
+            q: "timeouts".into(),
+            ..SearchQuery::default()
+        };
+        let page = workspace_search_results(&state, &id, Some(AccessRole::Admin), &query)
             .await
-            .expect("same channel should receive live event")
             .unwrap()
-            .unwrap();
-        assert!(echoed.to_text().unwrap().contains("live_action"));
-        assert!(tokio::time::timeout(
-            std::time::Duration::from_millis(150),
-            socket_a_other_channel.next()
-        )
-        .await
-        .is_err());
-        assert!(
-            tokio::time::timeout(std::time::Duration::from_millis(150), socket_b.next())
-                .await
-                .is_err()
-        );
+            .0;
+        assert_eq!(page.annotations.len(), 1);
+        assert_eq!(page.annotations[0].annotation_id, "anno-hit");
+    }
 
-        let legacy = tokio_tungstenite::connect_async(format!("ws://{addr}/_/ws"))
-            .await
-            .unwrap_err();
-        assert!(
-            matches!(legacy, WsError::Http(response) if response.status() == StatusCode::NOT_FOUND)
-        );
+    #[tokio::test]
+    async fn annotation_rest_api_crud_round_trips_and_broadcasts() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\nRetry loop").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("annotation-api".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut events = registry.get(&id).unwrap().events_tx.subscribe();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let mut state = test_state(registry.clone());
+        state.db = Some(crate::db::Database::from_connection(conn));
+        let admin = || Some(Extension(AccessRole::Admin));
+        let at = |path: &str| {
+            Query(AnnotationPathQuery {
+                path: path.to_string(),
+                ..Default::default()
+            })
+        };
 
-        assert!(registry.remove(&id_a));
-        let detached = tokio::time::timeout(std::time::Duration::from_secs(2), socket_a.next())
-            .await
-            .expect("detaching a workspace must close existing sockets");
+        let created = handle_annotation_create(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            at("notes.md"),
+            Json(serde_json::json!({
+                "text": "Retry loop",
+                "note": "backoff?",
+                "category": "question",
+                "tags": ["API"],
+                "author": { "color": "#f00", "name": "Ana" }
+            })),
+        )
+        .await
+        .into_response();
+        assert_eq!(created.status(), StatusCode::CREATED);
+        let created: serde_json::Value =
+            serde_json::from_str(&response_text(created).await).unwrap();
+        let anno_id = created["id"].as_str().unwrap().to_string();
+        assert!(valid_annotation_id(&anno_id), "{anno_id}");
+        assert_eq!(created["noteHtml"], "<p>backoff?</p>\n");
         assert!(matches!(
-            detached,
-            None | Some(Ok(ClientMessage::Close(_))) | Some(Err(_))
+            events.try_recv().unwrap(),
+            WorkspaceEvent::Channel { channel, .. } if channel.ends_with("notes.md")
         ));
-        server.abort();
-    }
 
-    #[tokio::test]
-    async fn workspace_ws_handshake_rejects_workspace_without_features() {
-        use tokio_tungstenite::tungstenite::Error as WsError;
+        let duplicate = handle_annotation_create(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            at("notes.md"),
+            Json(created.clone()),
+        )
+        .await
+        .into_response();
+        assert_eq!(duplicate.status(), StatusCode::CONFLICT);
+
+        let uncategorized = handle_annotation_create(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            at("notes.md"),
+            Json(serde_json::json!({ "text": "Retry loop", "category": "urgent" })),
+        )
+        .await
+        .into_response();
+        assert_eq!(uncategorized.status(), StatusCode::BAD_REQUEST);
+        let listed = |query: AnnotationPathQuery| {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                let response = handle_annotations_list(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Admin)),
+                    Query(AnnotationPathQuery {
+                        path: "notes.md".into(),
+                        ..query
+                    }),
+                )
+                .await
+                .into_response();
+                let body: serde_json::Value =
+                    serde_json::from_str(&response_text(response).await).unwrap();
+                body.as_array().map_or(0, Vec::len)
+            }
+        };
+        let filter = |category: Option<&str>, tag: Option<&str>, author: Option<&str>, resolved| {
+            AnnotationPathQuery {
+                category: category.map(str::to_string),
+                tag: tag.map(str::to_string),
+                author: author.map(str::to_string),
+                resolved,
+                ..Default::default()
+            }
+        };
+        assert_eq!(listed(filter(Some("question"), None, None, None)).await, 1);
+        assert_eq!(listed(filter(Some("must-fix"), None, None, None)).await, 0);
+        assert_eq!(
+            listed(filter(None, Some("#api"), Some("ana"), Some(false))).await,
+            1
+        );
+        assert_eq!(listed(filter(None, Some("ops"), None, None)).await, 0);
+        assert_eq!(listed(filter(None, None, None, Some(true))).await, 0);
+
+        let update = |revision: u64| {
+            handle_annotation_update(
+                State(state.clone()),
+                AxumPath((id.clone(), anno_id.clone())),
+                admin(),
+                Json(serde_json::json!({
+                    "id": "anno-other",
+                    "note": "use `jitter`",
+                    "noteHtml": "<script>alert(1)</script>",
+                    "revision": revision
+                })),
+            )
+        };
+        assert_eq!(created["revision"], 1);
+        let updated = update(1).await.into_response();
+        assert_eq!(updated.status(), StatusCode::OK);
+        let _ = events.try_recv().unwrap();
+        let stale = update(1).await.into_response();
+        assert_eq!(stale.status(), StatusCode::CONFLICT);
+
+        let absolute = dunce::canonicalize(dir.path().join("notes.md")).unwrap();
+        let listed = handle_annotations_list(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            at(&absolute.to_string_lossy()),
+        )
+        .await
+        .into_response();
+        let listed: serde_json::Value = serde_json::from_str(&response_text(listed).await).unwrap();
+        assert_eq!(
+            listed,
+            serde_json::json!([{
+                "id": anno_id,
+                "note": "use `jitter`",
+                "noteHtml": "<p>use <code>jitter</code></p>\n",
+                "revision": 2
+            }])
+        );
+
+        let deleted = handle_annotation_delete(
+            State(state.clone()),
+            AxumPath((id.clone(), anno_id.clone())),
+            admin(),
+        )
+        .await
+        .into_response();
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        let _ = events.try_recv().unwrap();
+        let missing = handle_annotation_delete(
+            State(state.clone()),
+            AxumPath((id.clone(), anno_id)),
+            admin(),
+        )
+        .await
+        .into_response();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
 
-        let root = tempfile::tempdir().unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("ws-disabled".into()));
-        let id = add_test_workspace(
-            &registry,
-            root.path().to_path_buf(),
-            WorkspaceFlags::default(),
-        );
-        let (addr, server) = spawn_collaboration_test_server(test_state(registry)).await;
-        let error = tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id}/ws"))
+        let outside = handle_annotations_list(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            at("../escape.md"),
+        )
+        .await
+        .into_response();
+        assert_eq!(outside.status(), StatusCode::NOT_FOUND);
+        let anonymous = handle_annotations_list(State(state), AxumPath(id), None, at("notes.md"))
             .await
-            .unwrap_err();
-        assert!(
-            matches!(error, WsError::Http(response) if response.status() == StatusCode::FORBIDDEN)
-        );
-        server.abort();
+            .into_response();
+        assert_eq!(anonymous.status(), StatusCode::FORBIDDEN);
     }
 
     #[tokio::test]
-    async fn live_only_document_receives_no_stored_data_and_rejects_foreign_path() {
-        use tokio_tungstenite::tungstenite::Message as ClientMessage;
+    async fn annotation_rest_api_is_served_from_a_remote_store() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\nRetry loop").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("annotation-remote".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.remote_store = Some(Arc::new(crate::store::memory::MemoryStore::default()));
+        let admin = || Some(Extension(AccessRole::Admin));
+        let body = |response: Response| async move {
+            serde_json::from_str::<serde_json::Value>(&response_text(response).await).unwrap()
+        };
 
-        let root_a = tempfile::tempdir().unwrap();
-        let root_b = tempfile::tempdir().unwrap();
-        let document_a = root_a.path().join("a.md");
-        let document_b = root_b.path().join("b.md");
-        fs::write(&document_a, "# a").unwrap();
-        fs::write(&document_b, "# b").unwrap();
-        let registry = Arc::new(WorkspaceRegistry::new("ws-live-only".into()));
-        let id_a = add_test_workspace(
-            &registry,
-            root_a.path().to_path_buf(),
-            WorkspaceFlags {
-                enable_live: true,
-                shared_annotation: false,
+        let created = handle_annotation_create(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            Query(AnnotationPathQuery {
+                path: "notes.md".into(),
                 ..Default::default()
-            },
-        );
-        let _id_b = add_test_workspace(&registry, root_b.path().to_path_buf(), all_flags());
-
-        let conn = Connection::open_in_memory().unwrap();
-        conn.execute(
-            "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL)",
-            [],
+            }),
+            Json(serde_json::json!({ "id": "anno-1", "text": "Retry loop" })),
         )
-        .unwrap();
-        conn.execute(
-            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP)",
-            [],
+        .await
+        .into_response();
+        assert_eq!(created.status(), StatusCode::CREATED);
+
+        let updated = handle_annotation_update(
+            State(state.clone()),
+            AxumPath((id.clone(), "anno-1".into())),
+            admin(),
+            Json(serde_json::json!({ "text": "Retry loop", "note": "jitter", "revision": 1 })),
         )
-        .unwrap();
-        conn.execute(
-            "INSERT INTO annotations (id, file_path, data) VALUES ('secret', ?1, '{\"id\":\"secret\"}')",
-            [dunce::canonicalize(&document_a)
-                .unwrap()
-                .to_string_lossy()
-                .as_ref()],
+        .await
+        .into_response();
+        assert_eq!(body(updated).await["revision"], 2);
+
+        let resolved = handle_annotation_resolution(
+            State(state.clone()),
+            AxumPath((id.clone(), "anno-1".into())),
+            admin(),
+            Json(AnnotationResolution {
+                resolved: true,
+                by: None,
+            }),
         )
-        .unwrap();
-        let db = Arc::new(Mutex::new(conn));
-        let mut state = test_state(registry);
-        state.db = Some(db.clone());
-        let (addr, server) = spawn_collaboration_test_server(state).await;
+        .await
+        .into_response();
+        assert_eq!(body(resolved).await["resolved"], true);
 
-        let (mut valid, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
-            .await
-            .unwrap();
-        valid
-            .send(ClientMessage::Text(
-                serde_json::json!({
-                    "type": "hello",
-                    "target": {
-                        "kind": "document",
-                        "path": document_a.to_string_lossy()
-                    }
-                })
-                .to_string()
-                .into(),
-            ))
-            .await
-            .unwrap();
-        assert!(
-            tokio::time::timeout(std::time::Duration::from_millis(150), valid.next())
-                .await
-                .is_err()
+        let summary = handle_annotations_summary(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            admin(),
+            Query(AnnotationSummaryQuery { path: None }),
+        )
+        .await
+        .into_response();
+        assert_eq!(
+            body(summary).await["files"],
+            serde_json::json!([{ "path": "notes.md", "open": 0, "resolved": 1 }])
         );
-        valid
-            .send(ClientMessage::Text(
-                r#"{"type":"live_action","data":{"action":"focus"}}"#.into(),
-            ))
-            .await
-            .unwrap();
-        let live = tokio::time::timeout(std::time::Duration::from_secs(2), valid.next())
-            .await
-            .unwrap()
-            .unwrap()
-            .unwrap();
-        assert!(live.to_text().unwrap().contains("live_action"));
-        valid
-            .send(ClientMessage::Text(
-                r#"{"type":"new_annotation","annotation":{"id":"forbidden"}}"#.into(),
-            ))
-            .await
-            .unwrap();
-        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-        let forbidden_count: i64 = db
-            .lock()
-            .unwrap()
-            .query_row(
-                "SELECT COUNT(*) FROM annotations WHERE id = 'forbidden'",
-                [],
-                |row| row.get(0),
+
+        let export = || {
+            handle_annotations_export(
+                State(state.clone()),
+                AxumPath(id.clone()),
+                admin(),
+                Query(AnnotationExportQuery::default()),
             )
-            .unwrap();
-        assert_eq!(forbidden_count, 0);
+        };
+        let dump = response_text(export().await.into_response()).await;
+        let exported: serde_json::Value = serde_json::from_str(&dump).unwrap();
+        assert_eq!(exported["annotations"][0]["path"], "notes.md");
+        assert_eq!(exported["annotations"][0]["note"], "jitter");
 
-        let (mut foreign, _) = tokio_tungstenite::connect_async(format!("ws://{addr}/_/{id_a}/ws"))
-            .await
-            .unwrap();
-        foreign
-            .send(ClientMessage::Text(
-                serde_json::json!({
-                    "type": "hello",
-                    "target": {
-                        "kind": "document",
-                        "path": document_b.to_string_lossy()
-                    }
-                })
-                .to_string()
-                .into(),
-            ))
-            .await
-            .unwrap();
-        let closed = tokio::time::timeout(std::time::Duration::from_secs(2), foreign.next())
-            .await
-            .expect("foreign path must be rejected before any data is sent");
-        assert!(matches!(
-            closed,
-            None | Some(Ok(ClientMessage::Close(_))) | Some(Err(_))
-        ));
-        server.abort();
-    }
+        let deleted = handle_annotation_delete(
+            State(state.clone()),
+            AxumPath((id.clone(), "anno-1".into())),
+            admin(),
+        )
+        .await
+        .into_response();
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        let at = || {
+            Query(DocumentStateQuery {
+                path: "notes.md".into(),
+            })
+        };
+        let history =
+            handle_annotations_history(State(state.clone()), AxumPath(id.clone()), admin(), at())
+                .await
+                .into_response();
+        let history = body(history).await;
+        assert_eq!(history[0]["id"], "anno-1");
+        assert_eq!(history[0]["action"], "delete");
 
-    #[test]
-    fn canonical_route_helpers_keep_file_and_tool_spaces_separate() {
-        assert_eq!(workspace_root_url("abcd1234"), "/abcd1234/");
-        assert_eq!(
-            workspace_file_url("abcd1234", "docs/readme.md"),
-            "/abcd1234/docs/readme.md"
-        );
-        assert_eq!(
-            workspace_file_url("abcd1234", "docs/a b#c?.md"),
-            "/abcd1234/docs/a%20b%23c%3F.md"
-        );
+        let restored =
+            handle_annotations_undo(State(state.clone()), AxumPath(id.clone()), admin(), at())
+                .await
+                .into_response();
+        assert_eq!(body(restored).await[0]["id"], "anno-1");
+
+        // Re-importing the export updates the restored annotation in place.
+        let imported =
+            handle_annotations_import(State(state.clone()), AxumPath(id.clone()), admin(), dump)
+                .await
+                .into_response();
         assert_eq!(
-            workspace_internal_url("abcd1234", "git/history"),
-            "/_/abcd1234/git/history"
+            body(imported).await,
+            serde_json::json!({ "imported": 1, "missing_files": 0, "conflicts": 0, "invalid": 0 })
         );
-        assert_eq!(workspace_internal_url("abcd1234", "ws"), "/_/abcd1234/ws");
+        let dump = response_text(export().await.into_response()).await;
         assert_eq!(
-            workspace_compare_base_url("abcd1234"),
-            "/_/abcd1234/compare"
+            serde_json::from_str::<serde_json::Value>(&dump).unwrap()["annotations"]
+                .as_array()
+                .map(Vec::len),
+            Some(1)
         );
     }
 
-    #[test]
-    fn featured_base_url_loopback_and_specific_are_network_independent() {
-        // These paths don't enumerate interfaces, so the public wrappers are
-        // deterministic regardless of the machine running the test.
-        assert_eq!(
-            featured_base_url("127.0.0.1", "", 6419),
-            "http://127.0.0.1:6419"
-        );
+    #[tokio::test]
+    async fn annotation_resolution_toggles_and_feeds_the_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\nRetry loop").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("annotation-resolve".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut events = registry.get(&id).unwrap().events_tx.subscribe();
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let file = dunce::canonicalize(dir.path().join("notes.md")).unwrap();
+        for anno in ["anno-a", "anno-b"] {
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                params![
+                    anno,
+                    file.to_string_lossy(),
+                    serde_json::json!({ "id": anno }).to_string()
+                ],
+            )
+            .unwrap();
+        }
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+        let resolve = |anno: &str, body: serde_json::Value| {
+            let state = state.clone();
+            let path = (id.clone(), anno.to_string());
+            async move {
+                handle_annotation_resolution(
+                    State(state),
+                    AxumPath(path),
+                    Some(Extension(AccessRole::Admin)),
+                    Json(serde_json::from_value(body).unwrap()),
+                )
+                .await
+                .into_response()
+            }
+        };
+        let summary = || {
+            let state = state.clone();
+            let id = id.clone();
+            async move {
+                let response = handle_annotations_summary(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Admin)),
+                    Query(AnnotationSummaryQuery { path: None }),
+                )
+                .await
+                .into_response();
+                serde_json::from_str::<serde_json::Value>(&response_text(response).await).unwrap()
+            }
+        };
+
+        let resolved = resolve(
+            "anno-a",
+            serde_json::json!({ "resolved": true, "by": { "name": "Ana" } }),
+        )
+        .await;
+        assert_eq!(resolved.status(), StatusCode::OK);
+        let resolved: serde_json::Value =
+            serde_json::from_str(&response_text(resolved).await).unwrap();
+        assert_eq!(resolved["resolvedBy"]["name"], "Ana");
+        assert!(matches!(
+            events.try_recv().unwrap(),
+            WorkspaceEvent::Channel { payload, .. } if payload.contains("\"resolved\":true")
+        ));
         assert_eq!(
-            featured_base_url("198.51.100.7", "", 6419),
-            "http://198.51.100.7:6419"
+            summary().await,
+            serde_json::json!({
+                "files": [{ "path": "notes.md", "open": 1, "resolved": 1 }],
+                "open": 1,
+                "resolved": 1
+            })
         );
-        let r = reachable_urls("127.0.0.1", "", 6419);
-        assert_eq!(r.all.len(), 1);
-        assert_eq!(r.featured, "http://127.0.0.1:6419");
+
+        let reopened = resolve("anno-a", serde_json::json!({ "resolved": false })).await;
+        assert_eq!(reopened.status(), StatusCode::OK);
+        assert_eq!(summary().await["open"], 2);
         assert_eq!(
-            local_browser_base_url("0.0.0.0", 6419),
-            "http://127.0.0.1:6419"
+            resolve("anno-zz", serde_json::json!({ "resolved": true }))
+                .await
+                .status(),
+            StatusCode::NOT_FOUND
         );
-        assert_eq!(local_browser_base_url("::", 6419), "http://[::1]:6419");
         assert_eq!(
-            local_browser_base_url("198.51.100.7", 6419),
-            "http://198.51.100.7:6419"
+            resolve(
+                "anno-b",
+                serde_json::json!({ "resolved": true, "by": "Ana" })
+            )
+            .await
+            .status(),
+            StatusCode::BAD_REQUEST
         );
     }
 
-    #[cfg(target_os = "windows")]
-    #[test]
-    fn canonicalize_route_path_strips_windows_verbatim_prefix() {
+    #[tokio::test]
+    async fn annotation_export_renders_workspace_annotations_as_attachments() {
         let dir = tempfile::tempdir().unwrap();
-        let std_path = std::fs::canonicalize(dir.path()).unwrap();
-        let route_path = canonicalize_route_path(dir.path()).unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("notes.md"), "# Notes\nRetry loop").unwrap();
+        fs::write(outside.path().join("other.md"), "# Other").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("annotation-export".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE annotations (id TEXT PRIMARY KEY, file_path TEXT NOT NULL, data TEXT NOT NULL);",
+        )
+        .unwrap();
+        for (anno, path) in [
+            ("anno-in", dir.path().join("notes.md")),
+            ("anno-out", outside.path().join("other.md")),
+        ] {
+            let path = dunce::canonicalize(path).unwrap();
+            let data = serde_json::json!({ "id": anno, "text": "Retry loop", "note": "jitter" });
+            conn.execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                params![anno, path.to_string_lossy(), data.to_string()],
+            )
+            .unwrap();
+        }
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
+        let export = |format: &str| {
+            let state = state.clone();
+            let id = id.clone();
+            let format = format.to_string();
+            async move {
+                handle_annotations_export(
+                    State(state),
+                    AxumPath(id),
+                    Some(Extension(AccessRole::Admin)),
+                    Query(AnnotationExportQuery {
+                        format: Some(format),
+                        ..Default::default()
+                    }),
+                )
+                .await
+                .into_response()
+            }
+        };
 
-        assert!(
-            std_path.to_string_lossy().starts_with(r"\\?\"),
-            "test expects Windows std::fs::canonicalize to return verbatim paths, got {std_path:?}"
+        let csv = export("csv").await;
+        assert_eq!(csv.status(), StatusCode::OK);
+        assert_eq!(
+            csv.headers().get(header::CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"annotations.csv\""
         );
-        assert!(
-            !route_path.to_string_lossy().starts_with(r"\\?\"),
-            "route canonicalization must match workspace roots stored through dunce: {route_path:?}"
+        assert_eq!(
+            response_text(csv).await,
+            "path,id,category,tags,text,note,author,created_at,updated_at\r\nnotes.md,anno-in,,,Retry loop,jitter,,,\r\n"
         );
+        assert_eq!(export("xml").await.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn workspace_path_handler_renders_markdown_inside_workspace() {
-        let dir = tempfile::tempdir().unwrap();
-        let docs = dir.path().join("docs");
-        fs::create_dir(&docs).unwrap();
-        let file = docs.join("EVDI_IMPLEMENTATION_PLAN.md");
-        fs::write(&file, "# Windows route check\n\nalpha beta gamma").unwrap();
+    async fn annotation_import_places_entries_by_workspace_route_after_a_move() {
+        let old_root = tempfile::tempdir().unwrap();
+        let new_root = tempfile::tempdir().unwrap();
+        for root in [&old_root, &new_root] {
+            fs::write(root.path().join("notes.md"), "# Notes\nRetry loop").unwrap();
+        }
+        let registry = Arc::new(WorkspaceRegistry::new("annotation-import".into()));
+        let old_id = add_test_workspace(&registry, old_root.path().to_path_buf(), all_flags());
+        let new_id = add_test_workspace(&registry, new_root.path().to_path_buf(), all_flags());
+        let conn = Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let old_file = dunce::canonicalize(old_root.path().join("notes.md")).unwrap();
+        conn.execute(
+            "INSERT INTO annotations (id, file_path, data) VALUES ('anno-moved', ?1, ?2)",
+            params![
+                old_file.to_string_lossy(),
+                serde_json::json!({ "id": "anno-moved", "text": "Retry loop" }).to_string()
+            ],
+        )
+        .unwrap();
+        let mut state = test_state(registry);
+        state.db = Some(crate::db::Database::from_connection(conn));
 
-        let registry = Arc::new(WorkspaceRegistry::new("route-test".into()));
-        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
-        let state = test_state(registry);
+        let exported = handle_annotations_export(
+            State(state.clone()),
+            AxumPath(old_id),
+            Some(Extension(AccessRole::Admin)),
+            Query(AnnotationExportQuery::default()),
+        )
+        .await
+        .into_response();
+        let dump = response_text(exported).await;
 
-        let response = handle_workspace_path(
+        let imported = handle_annotations_import(
+            State(state.clone()),
+            AxumPath(new_id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            dump,
+        )
+        .await
+        .into_response();
+        assert_eq!(imported.status(), StatusCode::OK);
+        let report: serde_json::Value =
+            serde_json::from_str(&response_text(imported).await).unwrap();
+        assert_eq!(report["imported"], 0, "{report}");
+        assert_eq!(report["conflicts"], 1, "{report}");
+
+        // The same dump under fresh ids lands on the moved file.
+        let new_file = dunce::canonicalize(new_root.path().join("notes.md")).unwrap();
+        let dump = serde_json::json!({
+            "version": 1,
+            "annotations": [{
+                "file_path": old_file,
+                "path": "notes.md",
+                "id": "anno-copy",
+                "annotation": { "id": "anno-copy", "text": "Retry loop" }
+            }]
+        });
+        let imported = handle_annotations_import(
+            State(state.clone()),
+            AxumPath(new_id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            dump.to_string(),
+        )
+        .await
+        .into_response();
+        let report: serde_json::Value =
+            serde_json::from_str(&response_text(imported).await).unwrap();
+        assert_eq!(report["imported"], 1, "{report}");
+        let stored: String = state
+            .db
+            .as_ref()
+            .unwrap()
+            .read_blocking(|conn| {
+                conn.query_row(
+                    "SELECT file_path FROM annotations WHERE id = 'anno-copy'",
+                    [],
+                    |row| row.get(0),
+                )
+            })
+            .unwrap();
+        assert_eq!(stored, new_file.to_string_lossy());
+
+        let invalid = handle_annotations_import(
             State(state),
-            AxumPath((id.clone(), "docs/EVDI_IMPLEMENTATION_PLAN.md".to_string())),
+            AxumPath(new_id),
             Some(Extension(AccessRole::Admin)),
-            axum::http::HeaderMap::new(),
+            "not json".into(),
         )
         .await
         .into_response();
-        assert_eq!(response.status(), StatusCode::OK);
-        let body = html_escape::decode_html_entities(&response_text(response).await).to_string();
-        assert!(body.contains("Windows route check"));
-        assert!(body.contains("alpha beta gamma"));
-        assert!(body.contains("enable-edit"));
-        assert!(body.contains("enable-search"));
-        assert!(body.contains("window.MarkonTheme"));
-        assert!(body.contains("<title>EVDI_IMPLEMENTATION_PLAN.md</title>"));
-        assert!(!body.contains("<title>markon - EVDI_IMPLEMENTATION_PLAN.md</title>"));
-        assert!(body.contains(&format!("href=\"/{id}/#docs/EVDI_IMPLEMENTATION_PLAN.md\"")));
-        let root = canonicalize_route_path(dir.path()).unwrap();
-        let workspace_name = root
-            .file_name()
-            .map(|name| name.to_string_lossy().to_string())
-            .unwrap_or_default();
-        let workspace_path = workspace_display_path(&root);
-        assert!(body.contains(&format!(
-            "class=\"workspace-back-name\">{workspace_name}</span>"
-        )));
-        assert!(body.contains(&format!(
-            "class=\"workspace-back-path\">{workspace_path}</span>"
-        )));
-        assert!(!body.contains("id=\"back-link-text\""));
-        assert!(!body.contains(&format!("/_/{id}/git/history")));
+        assert_eq!(invalid.status(), StatusCode::BAD_REQUEST);
     }
 
     #[tokio::test]
-    async fn workspace_path_handler_renders_text_file_as_content_only_view() {
+    async fn search_status_reports_counts_and_rebuild_picks_up_missed_files() {
         let dir = tempfile::tempdir().unwrap();
-        fs::write(dir.path().join("notes.txt"), "alpha\nbeta").unwrap();
+        fs::write(dir.path().join("a.md"), "# A\nfirst").unwrap();
 
-        let registry = Arc::new(WorkspaceRegistry::new("text-preview-test".into()));
+        let registry = Arc::new(WorkspaceRegistry::new("search-status".into()));
         let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let ws = registry.get(&id).unwrap();
+        for _ in 0..200 {
+            if ws.search_index.load().is_some() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
         let state = test_state(registry);
+        let status = |response: Response| async move {
+            assert_eq!(response.status(), StatusCode::OK);
+            serde_json::from_slice::<serde_json::Value>(&response_bytes(response).await).unwrap()
+        };
 
-        let response = handle_workspace_path(
-            State(state),
-            AxumPath((id, "notes.txt".to_string())),
-            Some(Extension(AccessRole::Admin)),
-            axum::http::HeaderMap::new(),
+        let before = status(
+            workspace_search_status_handler(State(state.clone()), AxumPath(id.clone())).await,
         )
-        .await
-        .into_response();
-        assert_eq!(response.status(), StatusCode::OK);
-        let body = response_text(response).await;
-        assert!(body.contains("class=\"code-view\""), "{body}");
-        assert!(body.contains("alpha"), "{body}");
-        assert!(body.contains("beta"), "{body}");
-        assert!(!body.contains("class=\"fv-back\""), "{body}");
-        assert!(!body.contains("class=\"fv-head\""), "{body}");
-        assert!(!body.contains("Back to workspace"), "{body}");
-        assert!(!body.contains("notes.txt</span>"), "{body}");
+        .await;
+        assert_eq!(before["ready"], true);
+        assert_eq!(before["documents"], 1);
+        assert_eq!(before["pending_updates"], 0);
+        assert!(before["last_commit"].as_i64().is_some());
+
+        // A forced rebuild re-walks the workspace instead of trusting the watcher.
+        fs::write(dir.path().join("b.md"), "# B\nsecond").unwrap();
+        let rebuilt = status(
+            workspace_search_rebuild_handler(State(state.clone()), AxumPath(id.clone())).await,
+        )
+        .await;
+        assert_eq!(rebuilt["documents"], 2);
+
+        let missing =
+            workspace_search_status_handler(State(state), AxumPath("missing".into())).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
+    /// Parks the render of any document holding its marker until released.
+    struct HoldRender {
+        entered: std::sync::Mutex<std::sync::mpsc::Sender<()>>,
+        release: std::sync::Mutex<std::sync::mpsc::Receiver<()>>,
+    }
+
+    impl crate::render_hook::RenderHook for HoldRender {
+        fn pre_parse<'a>(&self, markdown: &'a str) -> std::borrow::Cow<'a, str> {
+            if markdown.contains("<!-- hold-render -->") {
+                let _ = self.entered.lock().unwrap().send(());
+                // Only reached on failure: don't hang the suite forever.
+                let _ = self
+                    .release
+                    .lock()
+                    .unwrap()
+                    .recv_timeout(Duration::from_secs(30));
+            }
+            std::borrow::Cow::Borrowed(markdown)
+        }
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn markdown_renders_run_off_the_runtime_thread() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(
+            dir.path().join("held.md"),
+            "# Held\n\n<!-- hold-render -->\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("small.md"), "# Small\n").unwrap();
+        let (entered_tx, entered_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel();
+        let hold: Arc<dyn crate::RenderHook> = Arc::new(HoldRender {
+            entered: std::sync::Mutex::new(entered_tx),
+            release: std::sync::Mutex::new(release_rx),
+        });
+
+        let registry = Arc::new(WorkspaceRegistry::new("concurrent-render-test".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let mut state = test_state(registry);
+        state.render_hooks = Arc::new([hold]);
+        let render = |path: &str| {
+            let state = state.clone();
+            let id = id.clone();
+            let path = path.to_string();
+            async move {
+                handle_workspace_path(
+                    State(state),
+                    AxumPath((id, path)),
+                    Some(Extension(AccessRole::Admin)),
+                    Query(DirViewQuery::default()),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response()
+                .status()
+            }
+        };
+
+        // The runtime has a single thread. A render holding it would keep
+        // every other request waiting until the held render gave up.
+        let held = tokio::spawn(render("held.md"));
+        tokio::task::spawn_blocking(move || entered_rx.recv().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(render("small.md").await, StatusCode::OK);
+        assert!(!held.is_finished(), "the held render blocked the runtime");
+
+        release_tx.send(()).unwrap();
+        assert_eq!(held.await.unwrap(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn markdown_renders_are_cached_until_content_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("README.md");
+        fs::write(&file, "# First").unwrap();
+
+        let registry = Arc::new(WorkspaceRegistry::new("render-cache-test".into()));
+        let id = add_test_workspace(&registry, dir.path().to_path_buf(), all_flags());
+        let entry = registry.get(&id).unwrap();
+        let state = test_state(registry);
+        let view = |state: AppState| {
+            handle_workspace_path(
+                State(state),
+                AxumPath((id.clone(), "README.md".to_string())),
+                Some(Extension(AccessRole::Admin)),
+                Query(DirViewQuery::default()),
+                axum::http::HeaderMap::new(),
+            )
+        };
+
+        let first = response_text(view(state.clone()).await.into_response()).await;
+        assert!(first.contains("First"), "{first}");
+        let cached = entry.render_cache.lock().unwrap().len();
+        assert_eq!(cached, 1);
+
+        // A changed file misses on its content hash even without a watcher event.
+        fs::write(&file, "# Second").unwrap();
+        let second = response_text(view(state.clone()).await.into_response()).await;
+        assert!(second.contains("Second"), "{second}");
+        assert!(!second.contains("First"), "{second}");
+
+        entry.render_cache.lock().unwrap().clear();
+        let mut uncached = state.clone();
+        uncached.render_cache = false;
+        let third = response_text(view(uncached).await.into_response()).await;
+        assert!(third.contains("Second"), "{third}");
+        assert_eq!(entry.render_cache.lock().unwrap().len(), 0);
     }
 
     #[tokio::test]
@@ -9137,15 +19339,25 @@ mod tests {
             State(state),
             AxumPath((id.clone(), "README.md".to_string())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
         assert_eq!(response.status(), StatusCode::OK);
         let body = html_escape::decode_html_entities(&response_text(response).await).to_string();
-        assert!(body.contains("/_/js/katex/katex.min.css"), "{body}");
-        assert!(body.contains("/_/js/katex/katex.min.js"), "{body}");
-        assert!(body.contains("/_/js/math-render.js"), "{body}");
+        assert!(
+            body.contains(&fingerprinted_url("/_/js/katex/katex.min.css")),
+            "{body}"
+        );
+        assert!(
+            body.contains(&fingerprinted_url("/_/js/katex/katex.min.js")),
+            "{body}"
+        );
+        assert!(
+            body.contains(&fingerprinted_url("/_/js/math-render.js")),
+            "{body}"
+        );
         assert!(body.contains("data-math-display=\"true\""), "{body}");
         assert!(!body.contains("data-share-controls"), "{body}");
     }
@@ -9174,6 +19386,8 @@ mod tests {
             State(state.clone()),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -9208,6 +19422,8 @@ mod tests {
             State(state.clone()),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -9219,6 +19435,8 @@ mod tests {
             State(state.clone()),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Collaborator)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -9261,6 +19479,7 @@ mod tests {
                 State(test_state(reg_on)),
                 AxumPath((id_on, "README.md".to_string())),
                 Some(Extension(AccessRole::Collaborator)),
+                Query(DirViewQuery::default()),
                 axum::http::HeaderMap::new(),
             )
             .await
@@ -9291,6 +19510,7 @@ mod tests {
                 State(test_state(reg_off)),
                 AxumPath((id_off, "README.md".to_string())),
                 Some(Extension(AccessRole::Collaborator)),
+                Query(DirViewQuery::default()),
                 axum::http::HeaderMap::new(),
             )
             .await
@@ -9321,6 +19541,36 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn fingerprinted_css_is_cached_as_immutable_only_while_current() {
+        let cache_control = |name: String| async move {
+            let response = serve_css(AxumPath(name)).await.into_response();
+            assert_eq!(response.status(), StatusCode::OK);
+            response
+                .headers()
+                .get(header::CACHE_CONTROL)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+        let current = fingerprinted_url("/_/css/tokens.css");
+        let current = current.strip_prefix("/_/css/").unwrap().to_string();
+        assert_ne!(current, "tokens.css");
+        assert_eq!(
+            cache_control(current).await.as_deref(),
+            Some(crate::assets::IMMUTABLE_CACHE_CONTROL)
+        );
+        assert_eq!(
+            cache_control("tokens.0000000000.css".into())
+                .await
+                .as_deref(),
+            Some("no-cache")
+        );
+        assert_eq!(cache_control("tokens.css".into()).await, None);
+        let missing = serve_css(AxumPath("nope.0000000000.css".into()))
+            .await
+            .into_response();
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+    }
+
     #[test]
     fn unified_diff_html_renders_word_highlights_and_escapes_text() {
         let html = render_unified_diff_html(
@@ -9398,6 +19648,8 @@ mod tests {
             State(state),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -9425,6 +19677,8 @@ mod tests {
             State(state.clone()),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -9541,6 +19795,8 @@ mod tests {
             State(state.clone()),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -9610,9 +19866,9 @@ mod tests {
         assert!(!body.contains("data-md-old-content"));
         assert!(!body.contains("data-md-new-content"));
         assert!(!body.contains("data-markon-interactive-body"));
-        assert!(!body.contains("/_/js/main.js"));
-        assert!(body.contains("/_/js/workspace-diff.js"));
-        assert!(body.contains("/_/js/markdown-diff.js"));
+        assert!(!body.contains(&fingerprinted_url("/_/js/main.js")));
+        assert!(body.contains(&fingerprinted_url("/_/js/workspace-diff.js")));
+        assert!(body.contains(&fingerprinted_url("/_/js/markdown-diff.js")));
         // Both views consume one unified Markdown block payload (view=rendered).
         assert!(!body.contains(&format!(
             "/_/{id}/compare/HEAD...worktree?view=raw&format=data"
@@ -9742,10 +19998,10 @@ mod tests {
         assert!(!body.contains("data-md-old-content"));
         assert!(!body.contains("data-md-new-content"));
         assert!(!body.contains("data-markon-interactive-body"));
-        assert!(body.contains("/_/js/diff-ref-picker.js"));
-        assert!(body.contains("/_/css/editor.css"));
-        assert!(!body.contains("/_/js/main.js"));
-        assert!(body.contains("/_/js/markdown-diff.js"));
+        assert!(body.contains(&fingerprinted_url("/_/js/diff-ref-picker.js")));
+        assert!(body.contains(&fingerprinted_url("/_/css/editor.css")));
+        assert!(!body.contains(&fingerprinted_url("/_/js/main.js")));
+        assert!(body.contains(&fingerprinted_url("/_/js/markdown-diff.js")));
         assert!(body.contains(&format!(
             "/_/{id}/compare/HEAD...worktree?view=rendered&format=data"
         )));
@@ -10192,6 +20448,7 @@ mod tests {
             State(state),
             AxumPath((id, route)),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10220,6 +20477,7 @@ mod tests {
             State(state.clone()),
             AxumPath((id.clone(), "sub/".into())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10237,6 +20495,8 @@ mod tests {
             State(state),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -10267,7 +20527,16 @@ mod tests {
         fs::write(dir.path().join("Cargo.toml"), "[package]\n").unwrap();
 
         let root = dunce::canonicalize(dir.path()).unwrap();
-        let entries = collect_directory_entries("ws", &root, &root).unwrap();
+        let entries = collect_directory_entries(
+            "",
+            "ws",
+            &Mutex::default(),
+            &Default::default(),
+            &root,
+            &root,
+            false,
+        )
+        .unwrap();
         let shown = |name: &str| -> bool {
             entries
                 .iter()
@@ -10475,6 +20744,180 @@ mod tests {
         assert!(!dir.path().join("../escape").exists());
     }
 
+    #[tokio::test]
+    async fn custom_css_and_js_are_served_linked_and_allowed_by_the_csp() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("doc.md"), "# Doc").unwrap();
+        let css = dir.path().join("brand.css");
+        fs::write(&css, "body { max-width: 60em }").unwrap();
+        let js = dir.path().join("keys.js");
+        fs::write(&js, "console.log('keys')").unwrap();
+        let sheets = user_assets(
+            &[
+                css.to_string_lossy().into_owned(),
+                "https://fonts.example.com/css?family=Inter".into(),
+            ],
+            "/_/user-css",
+            true,
+        )
+        .unwrap();
+        let scripts =
+            user_assets(&[js.to_string_lossy().into_owned()], "/_/user-js", false).unwrap();
+        assert!(user_assets(&["ftp://example.com/a.css".into()], "/_/user-css", true).is_err());
+        assert!(user_assets(
+            &["https://cdn.example.com/a.js".into()],
+            "/_/user-js",
+            false
+        )
+        .is_err());
+
+        let policy =
+            SecurityHeaders::from_overrides(None, None, &user_asset_origins(&sheets)).unwrap();
+        let csp = policy.csp.unwrap();
+        let csp = csp.to_str().unwrap();
+        assert!(csp.contains("style-src 'self' 'unsafe-inline' https://fonts.example.com;"));
+        assert!(csp.contains("font-src 'self' data: https://fonts.example.com;"));
+        assert!(csp.contains("script-src 'self' 'unsafe-inline';"));
+
+        let registry = Arc::new(WorkspaceRegistry::new("custom-assets-test".into()));
+        let id = registry.add(WorkspaceConfig {
+            path: dunce::canonicalize(dir.path()).unwrap(),
+            ..Default::default()
+        });
+        let mut state = test_state(registry);
+        state.user_stylesheets = Arc::new(sheets);
+        state.user_scripts = Arc::new(scripts);
+
+        let served = serve_user_css(State(state.clone()), AxumPath("0-brand.css".into())).await;
+        assert_eq!(served.status(), StatusCode::OK);
+        assert_eq!(response_text(served).await, "body { max-width: 60em }");
+        let missing = serve_user_css(State(state.clone()), AxumPath("1-brand.css".into())).await;
+        assert_eq!(missing.status(), StatusCode::NOT_FOUND);
+        let script = serve_user_js(State(state.clone()), AxumPath("0-keys.js".into())).await;
+        assert_eq!(
+            script.headers().get(header::CONTENT_TYPE).unwrap(),
+            "text/javascript"
+        );
+        assert_eq!(response_text(script).await, "console.log('keys')");
+
+        let page = handle_workspace_path(
+            State(state),
+            AxumPath((id, "doc.md".into())),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        let body = response_text(page).await;
+        let built_in = body.find(&fingerprinted_url("/_/css/chat.css")).unwrap();
+        let custom = body
+            .find(r#"href="&#x2F;_&#x2F;user-css&#x2F;0-brand.css""#)
+            .unwrap();
+        assert!(built_in < custom, "body: {body}");
+        assert!(body.contains("https:&#x2F;&#x2F;fonts.example.com&#x2F;css?family=Inter"));
+        let main_js = body.find(&fingerprinted_url("/_/js/main.js")).unwrap();
+        let user_js = body
+            .find(r#"src="&#x2F;_&#x2F;user-js&#x2F;0-keys.js""#)
+            .unwrap();
+        assert!(main_js < user_js, "body: {body}");
+    }
+
+    #[tokio::test]
+    async fn theme_variants_and_custom_themes_reach_the_page() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("doc.md"), "# Doc").unwrap();
+        let registry = Arc::new(WorkspaceRegistry::new("theme-test".into()));
+        let id = registry.add(WorkspaceConfig {
+            path: dunce::canonicalize(dir.path()).unwrap(),
+            ..Default::default()
+        });
+        let render = |state: AppState| {
+            let id = id.clone();
+            async move {
+                let page = handle_workspace_path(
+                    State(state),
+                    AxumPath((id, "doc.md".into())),
+                    Some(Extension(AccessRole::Admin)),
+                    Query(DirViewQuery::default()),
+                    axum::http::HeaderMap::new(),
+                )
+                .await
+                .into_response();
+                response_text(page).await
+            }
+        };
+
+        let mut state = test_state(registry);
+        state.theme = Arc::new("sepia".into());
+        let body = render(state.clone()).await;
+        assert!(body.contains(r#"data-theme-default="sepia""#));
+        assert!(!body.contains("root.setAttribute('data-theme-custom'"));
+
+        state.custom_theme = true;
+        let body = render(state).await;
+        assert!(body.contains("root.setAttribute('data-theme-custom', '');"));
+    }
+
+    #[tokio::test]
+    async fn file_set_workspace_lists_its_files_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("docs")).unwrap();
+        fs::write(dir.path().join("spec.md"), "# Spec").unwrap();
+        fs::write(dir.path().join("docs/a.md"), "# Alpha").unwrap();
+        fs::write(dir.path().join("docs/b.md"), "# Beta").unwrap();
+        fs::write(dir.path().join("other.md"), "# Other").unwrap();
+
+        let registry = Arc::new(WorkspaceRegistry::new("file-set-test".into()));
+        let id = registry.add(WorkspaceConfig {
+            path: dunce::canonicalize(dir.path()).unwrap(),
+            files: vec!["spec.md".into(), "docs/b.md".into(), "docs/a.md".into()],
+            ..Default::default()
+        });
+        assert!(registry.get(&id).unwrap().is_ephemeral());
+        let state = test_state(registry);
+
+        let root = handle_workspace_root(
+            State(state.clone()),
+            AxumPath(id.clone()),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(root.status(), StatusCode::OK);
+        let body = response_text(root).await;
+        let spec = body.find(r#"data-entry-path="spec.md""#).unwrap();
+        let beta = body.find(r#"data-entry-path="docs&#x2F;b.md""#).unwrap();
+        let alpha = body.find(r#"data-entry-path="docs&#x2F;a.md""#).unwrap();
+        assert!(spec < beta && beta < alpha, "body: {body}");
+        assert!(!body.contains(r#"data-entry-path="other.md""#));
+        assert!(!body.contains(r#"data-entry-path="docs""#));
+
+        let listed = handle_workspace_path(
+            State(state.clone()),
+            AxumPath((id.clone(), "docs/a.md".into())),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(listed.status(), StatusCode::OK);
+
+        let other = handle_workspace_path(
+            State(state.clone()),
+            AxumPath((id.clone(), "other.md".into())),
+            Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
+        )
+        .await
+        .into_response();
+        assert_eq!(other.status(), StatusCode::NOT_FOUND);
+    }
+
     #[tokio::test]
     async fn single_file_workspace_redirects_and_hides_siblings() {
         let dir = tempfile::tempdir().unwrap();
@@ -10510,6 +20953,8 @@ mod tests {
             State(state.clone()),
             AxumPath(id.clone()),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
+            axum::http::HeaderMap::new(),
         )
         .await
         .into_response();
@@ -10523,6 +20968,7 @@ mod tests {
             State(state.clone()),
             AxumPath((id.clone(), "opened.md".into())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10543,6 +20989,7 @@ mod tests {
             State(state.clone()),
             AxumPath((id.clone(), "pic.png".into())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10553,6 +21000,7 @@ mod tests {
             State(state.clone()),
             AxumPath((id.clone(), "pic%20with%20space.png".into())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10563,6 +21011,7 @@ mod tests {
             State(state.clone()),
             AxumPath((id.clone(), "nested/root.png".into())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10573,6 +21022,7 @@ mod tests {
             State(state.clone()),
             AxumPath((id.clone(), "sibling.md".into())),
             Some(Extension(AccessRole::Admin)),
+            Query(DirViewQuery::default()),
             axum::http::HeaderMap::new(),
         )
         .await
@@ -10591,7 +21041,12 @@ mod tests {
         let directory = handle_workspace_dir_data(
             State(state.clone()),
             AxumPath(id.clone()),
-            Query(DirListingQuery { path: None }),
+            None,
+            Query(DirListingQuery {
+                path: None,
+                depth: None,
+                ..Default::default()
+            }),
         )
         .await
         .into_response();
@@ -163,6 +163,9 @@ pub struct WorkspaceSettings {
         skip_serializing_if = "Option::is_none"
     )]
     pub single_file: Option<String>,
+    /// Files of a temporary file-set workspace, relative to `path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
     #[serde(flatten, default)]
     pub flags: WorkspaceFlags,
     /// Per-workspace collaborator access code. Empty = inherit the server-level
@@ -547,6 +550,7 @@ impl AppSettings {
             return None;
         }
         recover_field(object, "single_file", &mut workspace.single_file);
+        recover_field(object, "files", &mut workspace.files);
         recover_field(
             object,
             "collaborator_access_code_hash",
@@ -572,9 +576,9 @@ impl AppSettings {
         use std::collections::HashSet;
         let mut seen = HashSet::new();
         self.workspaces
-            .retain(|w| seen.insert((w.path.clone(), w.single_file.clone())));
+            .retain(|w| seen.insert((w.path.clone(), w.single_file.clone(), w.files.clone())));
 
-        if !matches!(self.theme.as_str(), "auto" | "light" | "dark") {
+        if !crate::theme::is_built_in(&self.theme) {
             self.theme = "auto".to_string();
         }
         if self.language.is_empty() {
@@ -688,8 +692,10 @@ impl AppSettings {
                 flags: w.flags,
                 initial_path: w.single_file.clone(),
                 single_file: w.single_file.clone(),
+                files: w.files.clone(),
                 collaborator_access_code_hash: w.collaborator_access_code_hash.clone(),
                 alias: w.alias.clone(),
+                compare: None,
             })
             .collect();
         ServerConfig {
@@ -698,10 +704,14 @@ impl AppSettings {
             trusted_hosts: self.trusted_hosts.clone(),
             port,
             theme: self.theme.clone(),
+            theme_css: None,
             qr: None,
+            qr_out: None,
             open_browser: None,
+            open_anchor: None,
             shared_annotation: initial_workspaces.iter().any(|w| w.flags.shared_annotation),
             db_path: self.db_path.clone(),
+            db_url: None,
             salt: Some(self.salt.clone()),
             initial_workspaces,
             bound_listener: None,
@@ -710,10 +720,43 @@ impl AppSettings {
             admin_bootstraps: None,
             language: Some(self.language.clone()),
             styles_css: self.render_styles_css(),
+            user_css: Vec::new(),
+            user_js: Vec::new(),
             shortcuts_json: self.render_shortcuts_json(),
             default_chat_mode: self.default_chat_mode.clone(),
             collaborator_access_code_hash: self.collaborator_access_code_hash.clone(),
             print_collapsed_content: self.print_collapsed_content,
+            content_security_policy: None,
+            frame_options: None,
+            no_cache: false,
+            search_exclude: Vec::new(),
+            search_lang: None,
+            search_max_limit: None,
+            require_name: false,
+            annotation_token: None,
+            readme_names: Vec::new(),
+            nav_files: Vec::new(),
+            markdown_extensions: Vec::new(),
+            show_hidden: false,
+            no_follow_symlinks: false,
+            allow_paths: Vec::new(),
+            deny_paths: Vec::new(),
+            listing_page_size: None,
+            search_writer_memory: None,
+            search_max_file_size: None,
+            search_max_index_size: None,
+            paginate_kib: None,
+            allow_remote: Vec::new(),
+            notify_urls: Vec::new(),
+            notify_paths: Vec::new(),
+            notify_events: Vec::new(),
+            pandoc: false,
+            snapshots: false,
+            audit: false,
+            link_schemes: Vec::new(),
+            plugins_dir: None,
+            share_token: false,
+            base_path: String::new(),
         }
     }
     pub fn effective_web_language(&self) -> Option<String> {
@@ -765,7 +808,8 @@ impl AppSettings {
             serde_json::to_string(&self.shortcuts).ok()
         }
     }
-    /// Remove temporary single-file entries at process startup when configured.
+    /// Remove temporary single-file (and file-set) entries at process startup
+    /// when configured.
     /// Persistence and cleanup are intentionally separate: persisting the entry
     /// lets a user disable the option during the current session and keep it on
     /// the next launch, while the default preserves the historical cleanup.
@@ -775,7 +819,7 @@ impl AppSettings {
         }
         let before = self.workspaces.len();
         self.workspaces
-            .retain(|workspace| workspace.single_file.is_none());
+            .retain(|workspace| workspace.single_file.is_none() && workspace.files.is_empty());
         before - self.workspaces.len()
     }
 
@@ -788,6 +832,7 @@ impl AppSettings {
             .map(|info| WorkspaceSettings {
                 path: info.path,
                 single_file: info.single_file,
+                files: info.files,
                 flags: info.flags,
                 collaborator_access_code_hash: info.collaborator_access_code_hash,
                 alias: info.alias,
@@ -1528,6 +1573,7 @@ mod tests {
             path: live_root.clone(),
             flags: WorkspaceFlags::default(),
             single_file: None,
+            files: Vec::new(),
             collaborator_access_code_hash: String::new(),
             alias: String::new(),
         });
@@ -0,0 +1,180 @@
+//! Past versions of documents, kept by the server (`--snapshots`).
+//!
+//! With snapshots on, every Markdown file a workspace watcher sees change —
+//! and every document opened, so the version before the first edit is kept
+//! too — is recorded in the server database. Contents are stored once per
+//! SHA-256 digest, so a file saved back to an earlier state, or the same
+//! text in two files, costs one copy; a snapshot row is only added when the
+//! content differs from the file's latest one. Rows stay keyed by canonical
+//! path like annotations, and each file keeps its newest
+//! [`SNAPSHOT_LIMIT`]. A safety net for documents that aren't under git.
+
+use rusqlite::{params, Connection, OptionalExtension};
+use sha2::{Digest, Sha256};
+
+/// Snapshots kept per file; older ones are dropped as new ones arrive.
+pub(crate) const SNAPSHOT_LIMIT: usize = 200;
+
+pub(crate) fn create_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS snapshot_contents (
+            digest TEXT PRIMARY KEY,
+            content TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS snapshots (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_path TEXT NOT NULL,
+            digest TEXT NOT NULL,
+            taken_at INTEGER NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS snapshots_file_path ON snapshots (file_path, id);",
+    )
+}
+
+/// One recorded version of a file.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct Snapshot {
+    pub(crate) id: i64,
+    /// SHA-256 of the content, in hex.
+    pub(crate) digest: String,
+    /// Milliseconds since the Unix epoch.
+    pub(crate) taken_at: i64,
+    /// Content length in bytes.
+    pub(crate) size: i64,
+}
+
+fn digest(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Record `content` as the current version of `file_path`, unless it is
+/// already the latest snapshot. Returns whether a snapshot was added.
+pub(crate) fn record(conn: &Connection, file_path: &str, content: &str) -> rusqlite::Result<bool> {
+    let digest = digest(content);
+    let latest: Option<String> = conn
+        .query_row(
+            "SELECT digest FROM snapshots WHERE file_path = ?1 ORDER BY id DESC LIMIT 1",
+            [file_path],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if latest.as_deref() == Some(digest.as_str()) {
+        return Ok(false);
+    }
+    conn.execute(
+        "INSERT OR IGNORE INTO snapshot_contents (digest, content) VALUES (?1, ?2)",
+        params![digest, content],
+    )?;
+    conn.execute(
+        "INSERT INTO snapshots (file_path, digest, taken_at) VALUES (?1, ?2, ?3)",
+        params![file_path, digest, crate::annotations::now_millis() as i64],
+    )?;
+    conn.execute(
+        "DELETE FROM snapshots WHERE file_path = ?1 AND id NOT IN (
+            SELECT id FROM snapshots WHERE file_path = ?1 ORDER BY id DESC LIMIT ?2
+        )",
+        params![file_path, SNAPSHOT_LIMIT as i64],
+    )?;
+    conn.execute(
+        "DELETE FROM snapshot_contents
+         WHERE digest NOT IN (SELECT DISTINCT digest FROM snapshots)",
+        [],
+    )?;
+    Ok(true)
+}
+
+/// The snapshots of `file_path`, newest first.
+pub(crate) fn list(conn: &Connection, file_path: &str) -> rusqlite::Result<Vec<Snapshot>> {
+    let mut stmt = conn.prepare(
+        "SELECT s.id, s.digest, s.taken_at, length(CAST(c.content AS BLOB))
+         FROM snapshots s JOIN snapshot_contents c ON c.digest = s.digest
+         WHERE s.file_path = ?1 ORDER BY s.id DESC",
+    )?;
+    let rows = stmt.query_map([file_path], |row| {
+        Ok(Snapshot {
+            id: row.get(0)?,
+            digest: row.get(1)?,
+            taken_at: row.get(2)?,
+            size: row.get(3)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// The content of snapshot `id` of `file_path`.
+pub(crate) fn content(
+    conn: &Connection,
+    file_path: &str,
+    id: i64,
+) -> rusqlite::Result<Option<String>> {
+    conn.query_row(
+        "SELECT c.content FROM snapshots s JOIN snapshot_contents c ON c.digest = s.digest
+         WHERE s.file_path = ?1 AND s.id = ?2",
+        params![file_path, id],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conn() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn
+    }
+
+    fn stored_contents(conn: &Connection) -> i64 {
+        conn.query_row("SELECT count(*) FROM snapshot_contents", [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn unchanged_content_is_not_recorded_twice_and_contents_are_shared() {
+        let conn = conn();
+        assert!(record(&conn, "/ws/a.md", "# One\n").unwrap());
+        assert!(!record(&conn, "/ws/a.md", "# One\n").unwrap());
+        assert!(record(&conn, "/ws/a.md", "# Two\n").unwrap());
+        // Back to the first version: a new snapshot, the same stored content.
+        assert!(record(&conn, "/ws/a.md", "# One\n").unwrap());
+        assert!(record(&conn, "/ws/b.md", "# Two\n").unwrap());
+
+        let snapshots = list(&conn, "/ws/a.md").unwrap();
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots[0].digest, snapshots[2].digest);
+        assert_eq!(snapshots[0].size, 6);
+        assert!(snapshots[0].id > snapshots[1].id);
+        assert_eq!(stored_contents(&conn), 2);
+        assert_eq!(
+            content(&conn, "/ws/a.md", snapshots[1].id)
+                .unwrap()
+                .as_deref(),
+            Some("# Two\n")
+        );
+        assert_eq!(content(&conn, "/ws/b.md", snapshots[1].id).unwrap(), None);
+    }
+
+    #[test]
+    fn old_snapshots_and_their_contents_are_dropped_past_the_limit() {
+        let conn = conn();
+        for version in 0..=SNAPSHOT_LIMIT {
+            record(&conn, "/ws/a.md", &format!("version {version}")).unwrap();
+        }
+        let snapshots = list(&conn, "/ws/a.md").unwrap();
+        assert_eq!(snapshots.len(), SNAPSHOT_LIMIT);
+        assert_eq!(
+            content(&conn, "/ws/a.md", snapshots[SNAPSHOT_LIMIT - 1].id)
+                .unwrap()
+                .as_deref(),
+            Some("version 1")
+        );
+        assert_eq!(stored_contents(&conn), SNAPSHOT_LIMIT as i64);
+    }
+}
@@ -0,0 +1,461 @@
+//! [`DocumentStore`] held in memory, for tests that need a store other than
+//! the server's SQLite database.
+
+use super::{DocumentStore, StoreError, ViewedState};
+use crate::annotations::{
+    AnnotationSave, HistoryEntry, MissingFile, StoredAnnotation, ViewedRecord,
+};
+use crate::viewed::Invalidated;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::Mutex;
+
+#[derive(Default)]
+struct Tables {
+    /// Keyed by annotation id.
+    annotations: BTreeMap<String, StoredAnnotation>,
+    history: Vec<HistoryEntry>,
+    /// The file each `history` row was removed from, in the same order.
+    history_files: Vec<String>,
+    /// The last history `seq` handed out.
+    seq: i64,
+    viewed: BTreeMap<String, ViewedState>,
+    /// Keyed by `(client_id, file_path)`.
+    client_viewed: BTreeMap<(String, String), ViewedState>,
+}
+
+impl Tables {
+    /// Move the matching annotations on `file_path` to history as one batch.
+    fn archive(&mut self, file_path: &str, id: Option<&str>, action: &str) -> usize {
+        let ids: Vec<String> = self
+            .annotations
+            .values()
+            .filter(|row| row.file_path == file_path && id.is_none_or(|id| row.id == id))
+            .map(|row| row.id.clone())
+            .collect();
+        if ids.is_empty() {
+            return 0;
+        }
+        let batch = self
+            .history
+            .iter()
+            .map(|entry| entry.batch)
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let deleted_at =
+            crate::annotations::utc_timestamp((crate::annotations::now_millis() / 1000) as i64);
+        for id in &ids {
+            let row = self.annotations.remove(id).expect("listed above");
+            self.seq += 1;
+            self.history.push(HistoryEntry {
+                seq: self.seq,
+                batch,
+                id: row.id,
+                action: action.to_string(),
+                deleted_at: deleted_at.clone(),
+                annotation: row.annotation,
+            });
+            self.history_files.push(row.file_path);
+        }
+        ids.len()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    tables: Mutex<Tables>,
+}
+
+impl MemoryStore {
+    fn tables(&self) -> std::sync::MutexGuard<'_, Tables> {
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait]
+impl DocumentStore for MemoryStore {
+    async fn annotations(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError> {
+        Ok(self
+            .tables()
+            .annotations
+            .values()
+            .filter(|row| row.file_path == file_path)
+            .map(|row| row.annotation.clone())
+            .collect())
+    }
+
+    async fn all_annotations(&self) -> Result<Vec<StoredAnnotation>, StoreError> {
+        Ok(self.tables().annotations.values().cloned().collect())
+    }
+
+    async fn annotation_file(&self, id: &str) -> Result<Option<String>, StoreError> {
+        Ok(self
+            .tables()
+            .annotations
+            .get(id)
+            .map(|row| row.file_path.clone()))
+    }
+
+    async fn create_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        let mut tables = self.tables();
+        if tables.annotations.contains_key(id) {
+            return Ok(false);
+        }
+        tables.annotations.insert(
+            id.to_string(),
+            StoredAnnotation {
+                id: id.to_string(),
+                file_path: file_path.to_string(),
+                annotation: annotation.clone(),
+            },
+        );
+        Ok(true)
+    }
+
+    async fn import_annotations(&self, rows: &[StoredAnnotation]) -> Result<usize, StoreError> {
+        let mut tables = self.tables();
+        let mut stored = 0;
+        for row in rows {
+            if tables
+                .annotations
+                .get(&row.id)
+                .is_some_and(|existing| existing.file_path != row.file_path)
+            {
+                continue;
+            }
+            tables.annotations.insert(row.id.clone(), row.clone());
+            stored += 1;
+        }
+        Ok(stored)
+    }
+
+    async fn save_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: serde_json::Value,
+    ) -> Result<AnnotationSave, StoreError> {
+        let mut tables = self.tables();
+        let stored = match tables.annotations.get(id) {
+            Some(row) if row.file_path != file_path => return Ok(AnnotationSave::Foreign),
+            Some(row) => Some(row.annotation.clone()),
+            None => None,
+        };
+        let outcome = crate::annotations::next_revision(stored, annotation);
+        if let AnnotationSave::Saved(annotation) = &outcome {
+            tables.annotations.insert(
+                id.to_string(),
+                StoredAnnotation {
+                    id: id.to_string(),
+                    file_path: file_path.to_string(),
+                    annotation: annotation.clone(),
+                },
+            );
+        }
+        Ok(outcome)
+    }
+
+    async fn delete_annotations(
+        &self,
+        file_path: &str,
+        id: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let action = if id.is_some() { "delete" } else { "clear" };
+        Ok(self.tables().archive(file_path, id, action))
+    }
+
+    async fn undo(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError> {
+        let mut tables = self.tables();
+        let Some(batch) = tables
+            .history
+            .iter()
+            .zip(&tables.history_files)
+            .filter(|(_, file)| *file == file_path)
+            .map(|(entry, _)| entry.batch)
+            .max()
+        else {
+            return Ok(Vec::new());
+        };
+        let Tables {
+            annotations,
+            history,
+            history_files,
+            ..
+        } = &mut *tables;
+        let mut restored = Vec::new();
+        let mut kept = (Vec::new(), Vec::new());
+        for (entry, file) in history.drain(..).zip(history_files.drain(..)) {
+            if entry.batch != batch || file != file_path {
+                kept.0.push(entry);
+                kept.1.push(file);
+                continue;
+            }
+            // Annotations whose id was reused since are left alone.
+            if !annotations.contains_key(&entry.id) {
+                restored.push(entry.annotation.clone());
+                annotations.insert(
+                    entry.id.clone(),
+                    StoredAnnotation {
+                        id: entry.id,
+                        file_path: file,
+                        annotation: entry.annotation,
+                    },
+                );
+            }
+        }
+        (*history, *history_files) = kept;
+        Ok(restored)
+    }
+
+    async fn history(&self, file_path: &str) -> Result<Vec<HistoryEntry>, StoreError> {
+        let tables = self.tables();
+        Ok(tables
+            .history
+            .iter()
+            .zip(&tables.history_files)
+            .rev()
+            .filter(|(_, file)| *file == file_path)
+            .map(|(entry, _)| entry.clone())
+            .collect())
+    }
+
+    async fn set_resolved(
+        &self,
+        file_path: &str,
+        id: &str,
+        resolved: bool,
+        by: Option<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, StoreError> {
+        let mut tables = self.tables();
+        let Some(row) = tables
+            .annotations
+            .get_mut(id)
+            .filter(|row| row.file_path == file_path)
+        else {
+            return Ok(None);
+        };
+        let mut annotation = row.annotation.clone();
+        if !crate::annotations::apply_resolution(&mut annotation, resolved, by) {
+            return Ok(None);
+        }
+        row.annotation = annotation.clone();
+        Ok(Some(annotation))
+    }
+
+    async fn update_anchors(
+        &self,
+        file_path: &str,
+        annotations: &[serde_json::Value],
+    ) -> Result<(), StoreError> {
+        let mut tables = self.tables();
+        for annotation in annotations {
+            let Some(id) = annotation.get("id").and_then(serde_json::Value::as_str) else {
+                continue;
+            };
+            if let Some(row) = tables
+                .annotations
+                .get_mut(id)
+                .filter(|row| row.file_path == file_path)
+            {
+                row.annotation = annotation.clone();
+            }
+        }
+        Ok(())
+    }
+
+    async fn rename_file(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let stored: BTreeSet<String> = tables
+            .annotations
+            .values()
+            .map(|row| row.file_path.clone())
+            .chain(tables.viewed.keys().cloned())
+            .chain(
+                tables
+                    .client_viewed
+                    .keys()
+                    .map(|(_, file_path)| file_path.clone()),
+            )
+            .collect();
+        let moves = crate::annotations::file_moves(stored, from, to);
+        for (old, new) in &moves {
+            for row in tables.annotations.values_mut() {
+                if row.file_path == *old {
+                    row.file_path = new.clone();
+                }
+            }
+            for file_path in &mut tables.history_files {
+                if file_path == old {
+                    *file_path = new.clone();
+                }
+            }
+            if let Some(viewed) = tables.viewed.remove(old) {
+                tables.viewed.insert(new.clone(), viewed);
+            }
+            let clients: Vec<String> = tables
+                .client_viewed
+                .keys()
+                .filter(|(_, file_path)| file_path == old)
+                .map(|(client_id, _)| client_id.clone())
+                .collect();
+            for client_id in clients {
+                let viewed = tables
+                    .client_viewed
+                    .remove(&(client_id.clone(), old.clone()))
+                    .expect("listed above");
+                tables
+                    .client_viewed
+                    .insert((client_id, new.clone()), viewed);
+            }
+        }
+        Ok(moves)
+    }
+
+    async fn viewed_state(&self, file_path: &str) -> Result<Option<ViewedState>, StoreError> {
+        Ok(self.tables().viewed.get(file_path).cloned())
+    }
+
+    async fn viewed_states(&self) -> Result<Vec<ViewedRecord>, StoreError> {
+        Ok(self
+            .tables()
+            .viewed
+            .iter()
+            .map(|(file_path, viewed)| ViewedRecord {
+                file_path: file_path.clone(),
+                state: viewed.state.clone(),
+            })
+            .collect())
+    }
+
+    async fn save_viewed_state(
+        &self,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError> {
+        self.tables()
+            .viewed
+            .insert(file_path.to_string(), state.clone());
+        Ok(())
+    }
+
+    async fn client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+    ) -> Result<Option<ViewedState>, StoreError> {
+        Ok(self
+            .tables()
+            .client_viewed
+            .get(&(client_id.to_string(), file_path.to_string()))
+            .cloned())
+    }
+
+    async fn save_client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError> {
+        let state = ViewedState {
+            by: None,
+            ..state.clone()
+        };
+        self.tables()
+            .client_viewed
+            .insert((client_id.to_string(), file_path.to_string()), state);
+        Ok(())
+    }
+
+    async fn invalidate_viewed_sections(
+        &self,
+        file_path: &str,
+        markdown: &str,
+    ) -> Result<Option<Invalidated>, StoreError> {
+        let current = crate::viewed::section_hashes(markdown);
+        let invalidate = |viewed: &mut ViewedState| {
+            let mut updated = viewed.clone();
+            let unchecked = updated.sections.invalidate(&mut updated.state, &current);
+            if unchecked {
+                *viewed = updated;
+            }
+            unchecked
+        };
+        let mut tables = self.tables();
+        for ((_, path), viewed) in tables.client_viewed.iter_mut() {
+            if path == file_path {
+                invalidate(viewed);
+            }
+        }
+        let Some(viewed) = tables.viewed.get_mut(file_path) else {
+            return Ok(None);
+        };
+        if !invalidate(viewed) {
+            return Ok(None);
+        }
+        viewed.by = None;
+        Ok(Some(Invalidated {
+            state: viewed.state.clone(),
+            changed: viewed.sections.changed.iter().cloned().collect(),
+        }))
+    }
+
+    async fn missing_files(&self, root: &Path) -> Result<Vec<MissingFile>, StoreError> {
+        let tables = self.tables();
+        let mut annotated = BTreeMap::<String, usize>::new();
+        for row in tables.annotations.values() {
+            *annotated.entry(row.file_path.clone()).or_default() += 1;
+        }
+        let viewed = tables
+            .viewed
+            .keys()
+            .chain(tables.client_viewed.keys().map(|(_, file_path)| file_path))
+            .cloned();
+        Ok(crate::annotations::missing_among(root, annotated, viewed))
+    }
+
+    async fn prune_missing_files(
+        &self,
+        files: Vec<MissingFile>,
+        archive: bool,
+    ) -> Result<Vec<MissingFile>, StoreError> {
+        let mut tables = self.tables();
+        let mut pruned = Vec::new();
+        for file in files {
+            if Path::new(&file.file_path).exists() {
+                continue;
+            }
+            let annotations = if archive {
+                tables.archive(&file.file_path, None, "prune")
+            } else {
+                let before = tables.annotations.len();
+                tables
+                    .annotations
+                    .retain(|_, row| row.file_path != file.file_path);
+                before - tables.annotations.len()
+            };
+            if file.viewed {
+                tables.viewed.remove(&file.file_path);
+                tables
+                    .client_viewed
+                    .retain(|(_, file_path), _| *file_path != file.file_path);
+            }
+            pruned.push(MissingFile {
+                annotations,
+                ..file
+            });
+        }
+        Ok(pruned)
+    }
+}
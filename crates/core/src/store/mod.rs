@@ -0,0 +1,215 @@
+//! Where document state lives: annotations, their undo history, and viewed
+//! state, as read and written by the document-state endpoints every browser
+//! uses.
+//!
+//! The server's SQLite database is the default and backs every annotation
+//! feature. `--db-url postgres://…` (built with the `postgres` feature) moves
+//! document state into a shared Postgres database instead, so several markon
+//! servers serving the same checkout see one set of annotations. Rows stay
+//! keyed by canonical absolute path (see `ARCHITECTURE.md`), so every server
+//! must see the files at the same path. The annotation REST API, annotation
+//! search, and the watchers' re-anchoring, rename following and unchecking
+//! of edited viewed sections all go through the same store, so they work on
+//! either backend.
+
+#[cfg(test)]
+pub(crate) mod memory;
+#[cfg(feature = "postgres")]
+pub(crate) mod postgres;
+pub(crate) mod sqlite;
+
+use crate::annotations::{
+    AnnotationSave, HistoryEntry, MissingFile, StoredAnnotation, ViewedRecord,
+};
+use crate::viewed::Invalidated;
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug, thiserror::Error)]
+pub(crate) enum StoreError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(feature = "postgres")]
+    #[error(transparent)]
+    Postgres(#[from] tokio_postgres::Error),
+    #[error("storage worker failed: {0}")]
+    Worker(#[from] crate::db::DbError),
+}
+
+/// A file's viewed state and the identity that last changed it.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ViewedState {
+    pub state: serde_json::Value,
+    pub by: Option<serde_json::Value>,
+    /// Content hashes of the checked sections; see [`crate::viewed`].
+    pub sections: crate::viewed::Sections,
+}
+
+/// Storage for the document state of files, keyed by canonical path.
+/// Callers validate annotations and identities before writing.
+#[async_trait]
+pub(crate) trait DocumentStore: Send + Sync {
+    /// Every stored annotation on `file_path`.
+    async fn annotations(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError>;
+
+    /// Every stored annotation, on any file.
+    async fn all_annotations(&self) -> Result<Vec<StoredAnnotation>, StoreError>;
+
+    /// The file annotation `id` is stored on, if it exists.
+    async fn annotation_file(&self, id: &str) -> Result<Option<String>, StoreError>;
+
+    /// Store a new annotation `id` on `file_path`. Returns `false`, changing
+    /// nothing, when the id is already taken.
+    async fn create_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: &serde_json::Value,
+    ) -> Result<bool, StoreError>;
+
+    /// Store imported rows in one transaction: new ids are inserted, ids
+    /// already on the same file updated in place, and ids on another file
+    /// skipped. Returns how many were stored.
+    async fn import_annotations(&self, rows: &[StoredAnnotation]) -> Result<usize, StoreError>;
+
+    /// Save a writer's copy of annotation `id` on `file_path` under its next
+    /// revision, unless the stored one moved on since (see
+    /// [`crate::annotations::next_revision`]).
+    async fn save_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: serde_json::Value,
+    ) -> Result<AnnotationSave, StoreError>;
+
+    /// Delete annotation `id`, or every annotation on the file when `None`,
+    /// as one undoable batch. Returns how many were removed.
+    async fn delete_annotations(
+        &self,
+        file_path: &str,
+        id: Option<&str>,
+    ) -> Result<usize, StoreError>;
+
+    /// Restore the most recent delete or clear on `file_path`. Returns the
+    /// restored annotations.
+    async fn undo(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError>;
+
+    /// Removed annotations of `file_path` that are still restorable, newest
+    /// first.
+    async fn history(&self, file_path: &str) -> Result<Vec<HistoryEntry>, StoreError>;
+
+    /// Mark annotation `id` resolved or open again (see
+    /// [`crate::annotations::set_resolved`]). `None` when no such row exists.
+    async fn set_resolved(
+        &self,
+        file_path: &str,
+        id: &str,
+        resolved: bool,
+        by: Option<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, StoreError>;
+
+    /// Overwrite re-anchored `annotations` on `file_path` as they are (see
+    /// [`crate::reanchor::update_anchors`]).
+    async fn update_anchors(
+        &self,
+        file_path: &str,
+        annotations: &[serde_json::Value],
+    ) -> Result<(), StoreError>;
+
+    /// Re-key the document state of `from`, a file or every file under a
+    /// directory, to the same place under `to` after a rename (see
+    /// [`crate::annotations::move_file_data`]). Returns each moved file path,
+    /// old and new.
+    async fn rename_file(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Vec<(String, String)>, StoreError>;
+
+    /// The stored viewed state of `file_path`, if any.
+    async fn viewed_state(&self, file_path: &str) -> Result<Option<ViewedState>, StoreError>;
+
+    /// Every file's shared viewed state, sorted by path.
+    async fn viewed_states(&self) -> Result<Vec<ViewedRecord>, StoreError>;
+
+    async fn save_viewed_state(
+        &self,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError>;
+
+    /// The private viewed state one browser (`client_id`) keeps for
+    /// `file_path` when annotations aren't shared. `by` is always `None`.
+    async fn client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+    ) -> Result<Option<ViewedState>, StoreError>;
+
+    async fn save_client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError>;
+
+    /// Uncheck the viewed sections of `file_path` whose content changed in
+    /// its new `markdown`, shared and per-browser alike (see
+    /// [`crate::viewed::invalidate_file`]). Returns the shared state when any
+    /// of its sections were unchecked.
+    async fn invalidate_viewed_sections(
+        &self,
+        file_path: &str,
+        markdown: &str,
+    ) -> Result<Option<Invalidated>, StoreError>;
+
+    /// Files under `root` whose annotations or viewed state outlived them
+    /// (see [`crate::annotations::missing_files`]).
+    async fn missing_files(&self, root: &Path) -> Result<Vec<MissingFile>, StoreError>;
+
+    /// Remove the stored data of `files`, archiving their annotations to
+    /// history when `archive` is set (see
+    /// [`crate::annotations::prune_missing_files`]). Returns the files pruned.
+    async fn prune_missing_files(
+        &self,
+        files: Vec<MissingFile>,
+        archive: bool,
+    ) -> Result<Vec<MissingFile>, StoreError>;
+}
+
+/// A shared store with the runtime it was connected on, so the workspace
+/// watch threads, which run outside that runtime, can still reach it.
+#[derive(Clone)]
+pub(crate) struct RemoteStore {
+    pub store: Arc<dyn DocumentStore>,
+    pub runtime: tokio::runtime::Handle,
+}
+
+/// Connect the shared store `--db-url` names.
+pub(crate) async fn connect(url: &str) -> Result<Arc<dyn DocumentStore>, String> {
+    match url.split_once("://").map(|(scheme, _)| scheme) {
+        #[cfg(feature = "postgres")]
+        Some("postgres" | "postgresql") => postgres::PostgresStore::connect(url)
+            .await
+            .map(|store| Arc::new(store) as Arc<dyn DocumentStore>)
+            .map_err(|e| format!("Failed to connect to the --db-url database: {e}")),
+        #[cfg(not(feature = "postgres"))]
+        Some("postgres" | "postgresql") => {
+            Err("This markon was built without Postgres support (the `postgres` feature)".into())
+        }
+        _ => Err("--db-url must be a postgres:// URL; choose an SQLite database with --db".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn connect_rejects_other_schemes() {
+        for url in ["sqlite:///tmp/a.sqlite", "libsql://db.example", "not a url"] {
+            assert!(connect(url).await.is_err(), "{url}");
+        }
+    }
+}
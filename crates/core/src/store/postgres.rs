@@ -0,0 +1,787 @@
+//! [`DocumentStore`] over a shared Postgres database (`--db-url`).
+//!
+//! The tables mirror the SQLite ones. The URL's `sslmode` decides whether
+//! the connection uses TLS: `prefer` (the default) falls back to plain TCP
+//! when the server offers no TLS, `require` refuses to, `disable` never
+//! tries. Server certificates are checked against the system's trusted roots;
+//! one that fails the check fails the connection rather than falling back.
+
+use super::{DocumentStore, StoreError, ViewedState};
+use crate::annotations::{
+    AnnotationSave, HistoryEntry, MissingFile, StoredAnnotation, ViewedRecord,
+};
+use crate::viewed::Invalidated;
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio_postgres::{Client, Transaction};
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS annotations (
+        id TEXT PRIMARY KEY,
+        file_path TEXT NOT NULL,
+        data TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS annotations_file ON annotations (file_path);
+    CREATE TABLE IF NOT EXISTS annotation_history (
+        seq BIGSERIAL PRIMARY KEY,
+        batch BIGINT NOT NULL,
+        id TEXT NOT NULL,
+        file_path TEXT NOT NULL,
+        action TEXT NOT NULL,
+        data TEXT NOT NULL,
+        at BIGINT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS annotation_history_file
+        ON annotation_history (file_path, batch);
+    CREATE TABLE IF NOT EXISTS viewed_state (
+        file_path TEXT PRIMARY KEY,
+        state TEXT NOT NULL,
+        updated_by TEXT,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    );
+    ALTER TABLE viewed_state ADD COLUMN IF NOT EXISTS sections TEXT;
+    CREATE TABLE IF NOT EXISTS client_viewed_state (
+        client_id TEXT NOT NULL,
+        file_path TEXT NOT NULL,
+        state TEXT NOT NULL,
+        sections TEXT,
+        updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+        PRIMARY KEY (client_id, file_path)
+    );";
+
+pub(crate) struct PostgresStore {
+    // Transactions need `&mut Client`; document-state writes are small, so
+    // one serialized connection is enough.
+    client: Mutex<Client>,
+}
+
+impl PostgresStore {
+    pub(crate) async fn connect(url: &str) -> Result<Self, tokio_postgres::Error> {
+        let (client, connection) = tokio_postgres::connect(url, tls()).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::error!("annotation database connection closed: {e}");
+            }
+        });
+        client.batch_execute(SCHEMA).await?;
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+}
+
+/// Client TLS trusting the system's root certificates (`SSL_CERT_FILE` /
+/// `SSL_CERT_DIR` when set).
+fn tls() -> MakeRustlsConnect {
+    let mut roots = rustls::RootCertStore::empty();
+    let native = rustls_native_certs::load_native_certs();
+    for e in &native.errors {
+        tracing::warn!("skipping system root certificates: {e}");
+    }
+    roots.add_parsable_certificates(native.certs);
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .expect("ring supports the default TLS versions")
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    MakeRustlsConnect::new(config)
+}
+
+fn parse_all(rows: &[tokio_postgres::Row]) -> Vec<serde_json::Value> {
+    rows.iter()
+        .filter_map(|row| serde_json::from_str(row.get::<_, &str>(0)).ok())
+        .collect()
+}
+
+/// Move annotation `id` on `file_path`, or all of the file's annotations
+/// when `None`, to history as one batch. Returns how many were moved.
+async fn archive(
+    tx: &Transaction<'_>,
+    file_path: &str,
+    id: Option<&str>,
+    action: &str,
+) -> Result<u64, tokio_postgres::Error> {
+    let at = crate::annotations::now_millis() as i64;
+    // Serialize batch numbering across servers sharing the database.
+    tx.batch_execute("LOCK TABLE annotation_history IN EXCLUSIVE MODE")
+        .await?;
+    tx.execute(
+        "INSERT INTO annotation_history (batch, id, file_path, action, data, at)
+         SELECT (SELECT COALESCE(MAX(batch), 0) + 1 FROM annotation_history),
+                id, file_path, $3, data, $4
+         FROM annotations WHERE file_path = $1 AND ($2::TEXT IS NULL OR id = $2)",
+        &[&file_path, &id, &action, &at],
+    )
+    .await?;
+    tx.execute(
+        "DELETE FROM annotations WHERE file_path = $1 AND ($2::TEXT IS NULL OR id = $2)",
+        &[&file_path, &id],
+    )
+    .await
+}
+
+#[async_trait]
+impl DocumentStore for PostgresStore {
+    async fn annotations(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError> {
+        let client = self.client.lock().await;
+        let rows = client
+            .query(
+                "SELECT data FROM annotations WHERE file_path = $1",
+                &[&file_path],
+            )
+            .await?;
+        Ok(parse_all(&rows))
+    }
+
+    async fn all_annotations(&self) -> Result<Vec<StoredAnnotation>, StoreError> {
+        let client = self.client.lock().await;
+        let rows = client
+            .query("SELECT id, file_path, data FROM annotations", &[])
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                Some(StoredAnnotation {
+                    id: row.get(0),
+                    file_path: row.get(1),
+                    annotation: serde_json::from_str(row.get(2)).ok()?,
+                })
+            })
+            .collect())
+    }
+
+    async fn annotation_file(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let client = self.client.lock().await;
+        let row = client
+            .query_opt("SELECT file_path FROM annotations WHERE id = $1", &[&id])
+            .await?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    async fn create_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        let client = self.client.lock().await;
+        let inserted = client
+            .execute(
+                "INSERT INTO annotations (id, file_path, data) VALUES ($1, $2, $3)
+                 ON CONFLICT (id) DO NOTHING",
+                &[&id, &file_path, &annotation.to_string()],
+            )
+            .await?;
+        Ok(inserted > 0)
+    }
+
+    async fn import_annotations(&self, rows: &[StoredAnnotation]) -> Result<usize, StoreError> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let mut stored = 0;
+        for row in rows {
+            stored += tx
+                .execute(
+                    "INSERT INTO annotations (id, file_path, data) VALUES ($1, $2, $3)
+                     ON CONFLICT (id) DO UPDATE SET data = excluded.data
+                     WHERE annotations.file_path = excluded.file_path",
+                    &[&row.id, &row.file_path, &row.annotation.to_string()],
+                )
+                .await? as usize;
+        }
+        tx.commit().await?;
+        Ok(stored)
+    }
+
+    async fn save_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: serde_json::Value,
+    ) -> Result<AnnotationSave, StoreError> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let row = tx
+            .query_opt(
+                "SELECT file_path, data FROM annotations WHERE id = $1 FOR UPDATE",
+                &[&id],
+            )
+            .await?;
+        let stored = match row {
+            Some(row) if row.get::<_, &str>(0) != file_path => return Ok(AnnotationSave::Foreign),
+            Some(row) => serde_json::from_str(row.get(1)).ok(),
+            None => None,
+        };
+        let outcome = crate::annotations::next_revision(stored, annotation);
+        if let AnnotationSave::Saved(annotation) = &outcome {
+            let changed = tx
+                .execute(
+                    "INSERT INTO annotations (id, file_path, data) VALUES ($1, $2, $3)
+                     ON CONFLICT (id) DO UPDATE SET data = excluded.data
+                     WHERE annotations.file_path = excluded.file_path",
+                    &[&id, &file_path, &annotation.to_string()],
+                )
+                .await?;
+            // Another server created the id for a different file meanwhile.
+            if changed == 0 {
+                return Ok(AnnotationSave::Foreign);
+            }
+            tx.commit().await?;
+        }
+        Ok(outcome)
+    }
+
+    async fn delete_annotations(
+        &self,
+        file_path: &str,
+        id: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let action = if id.is_some() { "delete" } else { "clear" };
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let removed = archive(&tx, file_path, id, action).await?;
+        tx.commit().await?;
+        Ok(removed as usize)
+    }
+
+    async fn undo(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let batch: Option<i64> = tx
+            .query_one(
+                "SELECT MAX(batch) FROM annotation_history WHERE file_path = $1",
+                &[&file_path],
+            )
+            .await?
+            .get(0);
+        let Some(batch) = batch else {
+            return Ok(Vec::new());
+        };
+        // Annotations whose id was reused since are left alone.
+        let rows = tx
+            .query(
+                "INSERT INTO annotations (id, file_path, data)
+                 SELECT id, file_path, data FROM annotation_history
+                 WHERE file_path = $1 AND batch = $2 ORDER BY seq
+                 ON CONFLICT (id) DO NOTHING
+                 RETURNING data",
+                &[&file_path, &batch],
+            )
+            .await?;
+        tx.execute(
+            "DELETE FROM annotation_history WHERE file_path = $1 AND batch = $2",
+            &[&file_path, &batch],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(parse_all(&rows))
+    }
+
+    async fn history(&self, file_path: &str) -> Result<Vec<HistoryEntry>, StoreError> {
+        let client = self.client.lock().await;
+        let rows = client
+            .query(
+                "SELECT seq, batch, id, action, at, data FROM annotation_history
+                 WHERE file_path = $1 ORDER BY seq DESC",
+                &[&file_path],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| HistoryEntry {
+                seq: row.get(0),
+                batch: row.get(1),
+                id: row.get(2),
+                action: row.get(3),
+                deleted_at: crate::annotations::utc_timestamp(row.get::<_, i64>(4) / 1000),
+                annotation: serde_json::from_str(row.get(5)).unwrap_or(serde_json::Value::Null),
+            })
+            .collect())
+    }
+
+    async fn set_resolved(
+        &self,
+        file_path: &str,
+        id: &str,
+        resolved: bool,
+        by: Option<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, StoreError> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let row = tx
+            .query_opt(
+                "SELECT data FROM annotations WHERE id = $1 AND file_path = $2 FOR UPDATE",
+                &[&id, &file_path],
+            )
+            .await?;
+        let Some(mut annotation) =
+            row.and_then(|row| serde_json::from_str::<serde_json::Value>(row.get(0)).ok())
+        else {
+            return Ok(None);
+        };
+        if !crate::annotations::apply_resolution(&mut annotation, resolved, by) {
+            return Ok(None);
+        }
+        tx.execute(
+            "UPDATE annotations SET data = $1 WHERE id = $2 AND file_path = $3",
+            &[&annotation.to_string(), &id, &file_path],
+        )
+        .await?;
+        tx.commit().await?;
+        Ok(Some(annotation))
+    }
+
+    async fn update_anchors(
+        &self,
+        file_path: &str,
+        annotations: &[serde_json::Value],
+    ) -> Result<(), StoreError> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        for annotation in annotations {
+            let Some(id) = annotation.get("id").and_then(serde_json::Value::as_str) else {
+                continue;
+            };
+            tx.execute(
+                "UPDATE annotations SET data = $1 WHERE id = $2 AND file_path = $3",
+                &[&annotation.to_string(), &id, &file_path],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn rename_file(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        let file = from.to_string_lossy().into_owned();
+        let prefix = from.join("").to_string_lossy().into_owned();
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let stored = tx
+            .query(
+                "SELECT file_path FROM annotations
+                 UNION SELECT file_path FROM viewed_state
+                 UNION SELECT file_path FROM client_viewed_state",
+                &[],
+            )
+            .await?;
+        let moves = crate::annotations::file_moves(
+            stored
+                .iter()
+                .map(|row| row.get::<_, String>(0))
+                .filter(|path| *path == file || path.starts_with(&prefix)),
+            from,
+            to,
+        );
+        for (old, new) in &moves {
+            for table in ["annotations", "annotation_history"] {
+                tx.execute(
+                    &format!("UPDATE {table} SET file_path = $2 WHERE file_path = $1"),
+                    &[old, new],
+                )
+                .await?;
+            }
+            // State already stored for the destination is replaced, as the
+            // moved file replaced it.
+            tx.execute(
+                "DELETE FROM viewed_state WHERE file_path = $2
+                 AND EXISTS (SELECT 1 FROM viewed_state WHERE file_path = $1)",
+                &[old, new],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE viewed_state SET file_path = $2 WHERE file_path = $1",
+                &[old, new],
+            )
+            .await?;
+            tx.execute(
+                "DELETE FROM client_viewed_state AS dest WHERE dest.file_path = $2
+                 AND EXISTS (SELECT 1 FROM client_viewed_state AS src
+                             WHERE src.file_path = $1 AND src.client_id = dest.client_id)",
+                &[old, new],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE client_viewed_state SET file_path = $2 WHERE file_path = $1",
+                &[old, new],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(moves)
+    }
+
+    async fn viewed_state(&self, file_path: &str) -> Result<Option<ViewedState>, StoreError> {
+        let client = self.client.lock().await;
+        let row = client
+            .query_opt(
+                "SELECT state, updated_by, sections FROM viewed_state WHERE file_path = $1",
+                &[&file_path],
+            )
+            .await?;
+        Ok(row.map(|row| ViewedState {
+            state: serde_json::from_str(row.get(0)).unwrap_or_else(|_| serde_json::json!({})),
+            by: row
+                .get::<_, Option<&str>>(1)
+                .and_then(|by| serde_json::from_str(by).ok()),
+            sections: crate::viewed::Sections::parse(row.get(2)),
+        }))
+    }
+
+    async fn viewed_states(&self) -> Result<Vec<ViewedRecord>, StoreError> {
+        let client = self.client.lock().await;
+        let rows = client
+            .query(
+                "SELECT file_path, state FROM viewed_state ORDER BY file_path COLLATE \"C\"",
+                &[],
+            )
+            .await?;
+        Ok(rows
+            .iter()
+            .map(|row| ViewedRecord {
+                file_path: row.get(0),
+                state: serde_json::from_str(row.get(1)).unwrap_or(serde_json::Value::Null),
+            })
+            .collect())
+    }
+
+    async fn save_viewed_state(
+        &self,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError> {
+        let by = state.by.as_ref().map(ToString::to_string);
+        let sections = state.sections.to_column();
+        let client = self.client.lock().await;
+        client
+            .execute(
+                "INSERT INTO viewed_state (file_path, state, updated_by, updated_at, sections)
+                 VALUES ($1, $2, $3, now(), $4)
+                 ON CONFLICT (file_path) DO UPDATE SET
+                     state = excluded.state,
+                     updated_by = excluded.updated_by,
+                     updated_at = excluded.updated_at,
+                     sections = excluded.sections",
+                &[&file_path, &state.state.to_string(), &by, &sections],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+    ) -> Result<Option<ViewedState>, StoreError> {
+        let client = self.client.lock().await;
+        let row = client
+            .query_opt(
+                "SELECT state, sections FROM client_viewed_state
+                 WHERE client_id = $1 AND file_path = $2",
+                &[&client_id, &file_path],
+            )
+            .await?;
+        Ok(row.map(|row| ViewedState {
+            state: serde_json::from_str(row.get(0)).unwrap_or_else(|_| serde_json::json!({})),
+            by: None,
+            sections: crate::viewed::Sections::parse(row.get(1)),
+        }))
+    }
+
+    async fn save_client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError> {
+        let sections = state.sections.to_column();
+        let client = self.client.lock().await;
+        client
+            .execute(
+                "INSERT INTO client_viewed_state (client_id, file_path, state, sections, updated_at)
+                 VALUES ($1, $2, $3, $4, now())
+                 ON CONFLICT (client_id, file_path) DO UPDATE SET
+                     state = excluded.state,
+                     sections = excluded.sections,
+                     updated_at = excluded.updated_at",
+                &[&client_id, &file_path, &state.state.to_string(), &sections],
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn invalidate_viewed_sections(
+        &self,
+        file_path: &str,
+        markdown: &str,
+    ) -> Result<Option<Invalidated>, StoreError> {
+        let current = crate::viewed::section_hashes(markdown);
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let private = tx
+            .query(
+                "SELECT client_id, state, sections FROM client_viewed_state
+                 WHERE file_path = $1 AND sections IS NOT NULL FOR UPDATE",
+                &[&file_path],
+            )
+            .await?;
+        for row in &private {
+            let Some((state, sections)) =
+                crate::viewed::invalidate_row(row.get(1), row.get(2), &current)
+            else {
+                continue;
+            };
+            tx.execute(
+                "UPDATE client_viewed_state SET state = $1, sections = $2, updated_at = now()
+                 WHERE client_id = $3 AND file_path = $4",
+                &[
+                    &state.to_string(),
+                    &sections.to_column(),
+                    &row.get::<_, &str>(0),
+                    &file_path,
+                ],
+            )
+            .await?;
+        }
+        let shared = tx
+            .query_opt(
+                "SELECT state, sections FROM viewed_state WHERE file_path = $1 FOR UPDATE",
+                &[&file_path],
+            )
+            .await?;
+        let invalidated =
+            shared.and_then(|row| crate::viewed::invalidate_row(row.get(0), row.get(1), &current));
+        if let Some((state, sections)) = &invalidated {
+            // The edit, not a reader, changed the state, so the attribution goes.
+            tx.execute(
+                "UPDATE viewed_state SET state = $1, sections = $2, updated_by = NULL,
+                     updated_at = now()
+                 WHERE file_path = $3",
+                &[&state.to_string(), &sections.to_column(), &file_path],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(invalidated.map(|(state, sections)| Invalidated {
+            state,
+            changed: sections.changed.into_iter().collect(),
+        }))
+    }
+
+    async fn missing_files(&self, root: &Path) -> Result<Vec<MissingFile>, StoreError> {
+        let client = self.client.lock().await;
+        let annotated = client
+            .query(
+                "SELECT file_path, COUNT(*) FROM annotations GROUP BY file_path",
+                &[],
+            )
+            .await?;
+        let viewed = client
+            .query(
+                "SELECT file_path FROM viewed_state
+                 UNION SELECT file_path FROM client_viewed_state",
+                &[],
+            )
+            .await?;
+        Ok(crate::annotations::missing_among(
+            root,
+            annotated
+                .iter()
+                .map(|row| (row.get(0), row.get::<_, i64>(1) as usize)),
+            viewed.iter().map(|row| row.get(0)),
+        ))
+    }
+
+    async fn prune_missing_files(
+        &self,
+        files: Vec<MissingFile>,
+        archive_rows: bool,
+    ) -> Result<Vec<MissingFile>, StoreError> {
+        let mut client = self.client.lock().await;
+        let tx = client.transaction().await?;
+        let mut pruned = Vec::new();
+        for file in files {
+            if std::path::Path::new(&file.file_path).exists() {
+                continue;
+            }
+            let annotations = if archive_rows {
+                archive(&tx, &file.file_path, None, "prune").await?
+            } else {
+                tx.execute(
+                    "DELETE FROM annotations WHERE file_path = $1",
+                    &[&file.file_path],
+                )
+                .await?
+            };
+            if file.viewed {
+                for table in ["viewed_state", "client_viewed_state"] {
+                    tx.execute(
+                        &format!("DELETE FROM {table} WHERE file_path = $1"),
+                        &[&file.file_path],
+                    )
+                    .await?;
+                }
+            }
+            pruned.push(MissingFile {
+                annotations: annotations as usize,
+                ..file
+            });
+        }
+        tx.commit().await?;
+        Ok(pruned)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs against the database in `MARKON_TEST_POSTGRES_URL` (CI starts
+    /// one); skipped when it is unset.
+    #[tokio::test]
+    async fn postgres_store_round_trips_document_state() {
+        let Ok(url) = std::env::var("MARKON_TEST_POSTGRES_URL") else {
+            return;
+        };
+        let store = PostgresStore::connect(&url).await.unwrap();
+        let run = crate::annotations::now_millis();
+        let file = format!("/markon-test/{run}/a.md");
+        let id = format!("anno-{run}");
+        let annotation = serde_json::json!({ "id": id, "text": "x", "revision": 1 });
+
+        assert!(store
+            .create_annotation(&file, &id, &annotation)
+            .await
+            .unwrap());
+        assert!(!store
+            .create_annotation(&file, &id, &annotation)
+            .await
+            .unwrap());
+        assert_eq!(
+            store.annotation_file(&id).await.unwrap(),
+            Some(file.clone())
+        );
+        let AnnotationSave::Saved(saved) = store
+            .save_annotation(&file, &id, annotation.clone())
+            .await
+            .unwrap()
+        else {
+            panic!("annotation not saved");
+        };
+        assert_eq!(saved["revision"], 2);
+        let resolved = store.set_resolved(&file, &id, true, None).await.unwrap();
+        assert_eq!(resolved.unwrap()["resolved"], true);
+        assert!(store
+            .all_annotations()
+            .await
+            .unwrap()
+            .iter()
+            .any(|row| row.id == id && row.file_path == file));
+
+        let moved = serde_json::json!({ "id": id, "text": "x", "anchor": { "position": 3 } });
+        store
+            .update_anchors(&file, std::slice::from_ref(&moved))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.annotations(&file).await.unwrap(),
+            std::slice::from_ref(&moved)
+        );
+
+        assert_eq!(store.delete_annotations(&file, Some(&id)).await.unwrap(), 1);
+        let history = store.history(&file).await.unwrap();
+        assert_eq!(history[0].id, id);
+        assert_eq!(history[0].action, "delete");
+        assert_eq!(store.undo(&file).await.unwrap(), [moved]);
+
+        let imported = StoredAnnotation {
+            id: id.clone(),
+            file_path: format!("/markon-test/{run}/b.md"),
+            annotation: annotation.clone(),
+        };
+        assert_eq!(store.import_annotations(&[imported]).await.unwrap(), 0);
+
+        let viewed = ViewedState {
+            state: serde_json::json!({ "intro": true }),
+            by: None,
+            sections: crate::viewed::Sections::default(),
+        };
+        store.save_viewed_state(&file, &viewed).await.unwrap();
+        assert_eq!(store.viewed_state(&file).await.unwrap(), Some(viewed));
+        assert!(store
+            .viewed_states()
+            .await
+            .unwrap()
+            .iter()
+            .any(|record| record.file_path == file));
+
+        let markdown = "## Intro\nHi.\n";
+        let checked = serde_json::json!({ "intro": true });
+        let viewed = ViewedState {
+            sections: crate::viewed::Sections::default().record(&checked, markdown),
+            state: checked,
+            by: None,
+        };
+        store.save_viewed_state(&file, &viewed).await.unwrap();
+        store
+            .save_client_viewed_state("client-1", &file, &viewed)
+            .await
+            .unwrap();
+        let edited = "## Intro\nHello.\n";
+        let invalidated = store
+            .invalidate_viewed_sections(&file, edited)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(invalidated.state, serde_json::json!({ "intro": false }));
+        assert_eq!(invalidated.changed, ["intro"]);
+        let private = store.client_viewed_state("client-1", &file).await.unwrap();
+        assert_eq!(private.unwrap().state, invalidated.state);
+        assert!(store
+            .invalidate_viewed_sections(&file, edited)
+            .await
+            .unwrap()
+            .is_none());
+
+        let root = std::path::PathBuf::from(format!("/markon-test/{run}"));
+        let renamed = root.join("renamed").join("a.md");
+        let moves = store
+            .rename_file(&root.join("a.md"), &renamed)
+            .await
+            .unwrap();
+        let renamed = renamed.to_string_lossy().into_owned();
+        assert_eq!(moves, [(file.clone(), renamed.clone())]);
+        assert_eq!(
+            store.annotation_file(&id).await.unwrap(),
+            Some(renamed.clone())
+        );
+        assert!(store.viewed_state(&file).await.unwrap().is_none());
+        assert!(store
+            .client_viewed_state("client-1", &renamed)
+            .await
+            .unwrap()
+            .is_some());
+        let moves = store
+            .rename_file(&root.join("renamed"), &root)
+            .await
+            .unwrap();
+        assert_eq!(moves, [(renamed, file.clone())]);
+
+        let missing = store.missing_files(&root).await.unwrap();
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].viewed);
+        let pruned = store.prune_missing_files(missing, false).await.unwrap();
+        assert_eq!(pruned[0].annotations, 1);
+        assert!(store.annotations(&file).await.unwrap().is_empty());
+        assert!(store.missing_files(&root).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+//! [`DocumentStore`] over the server's SQLite database.
+
+use super::{DocumentStore, StoreError, ViewedState};
+use crate::annotations::{
+    AnnotationSave, HistoryEntry, MissingFile, StoredAnnotation, ViewedRecord,
+};
+use crate::db::Database;
+use crate::viewed::Invalidated;
+use async_trait::async_trait;
+use rusqlite::OptionalExtension;
+use std::path::Path;
+
+pub(crate) struct SqliteStore {
+    db: Database,
+}
+
+impl SqliteStore {
+    pub(crate) fn new(db: Database) -> Self {
+        Self { db }
+    }
+}
+
+#[async_trait]
+impl DocumentStore for SqliteStore {
+    async fn annotations(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError> {
+        let file_path = file_path.to_string();
+        self.db
+            .read(move |conn| -> Result<_, StoreError> {
+                let mut stmt = conn.prepare("SELECT data FROM annotations WHERE file_path = ?1")?;
+                let rows = stmt.query_map([file_path.as_str()], |row| row.get::<_, String>(0))?;
+                Ok(rows
+                    .filter_map(Result::ok)
+                    .filter_map(|data| serde_json::from_str(&data).ok())
+                    .collect())
+            })
+            .await?
+    }
+
+    async fn all_annotations(&self) -> Result<Vec<StoredAnnotation>, StoreError> {
+        self.db
+            .read(|conn| crate::annotations::stored_annotations(conn).map_err(StoreError::from))
+            .await?
+    }
+
+    async fn annotation_file(&self, id: &str) -> Result<Option<String>, StoreError> {
+        let id = id.to_string();
+        self.db
+            .read(move |conn| -> Result<_, StoreError> {
+                Ok(conn
+                    .query_row(
+                        "SELECT file_path FROM annotations WHERE id = ?1",
+                        [id.as_str()],
+                        |row| row.get(0),
+                    )
+                    .optional()?)
+            })
+            .await?
+    }
+
+    async fn create_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: &serde_json::Value,
+    ) -> Result<bool, StoreError> {
+        let (file_path, id, data) = (
+            file_path.to_string(),
+            id.to_string(),
+            annotation.to_string(),
+        );
+        self.db
+            .write(move |conn| -> Result<_, StoreError> {
+                let inserted = conn.execute(
+                    "INSERT OR IGNORE INTO annotations (id, file_path, data) VALUES (?1, ?2, ?3)",
+                    rusqlite::params![id, file_path, data],
+                )?;
+                Ok(inserted > 0)
+            })
+            .await?
+    }
+
+    async fn import_annotations(&self, rows: &[StoredAnnotation]) -> Result<usize, StoreError> {
+        let rows = rows.to_vec();
+        self.db
+            .write(move |conn| -> Result<_, StoreError> {
+                let tx = crate::db::savepoint(conn)?;
+                let mut stored = 0;
+                for row in &rows {
+                    if crate::annotations::upsert_annotation_for_file(
+                        &tx,
+                        &row.id,
+                        &row.file_path,
+                        &row.annotation.to_string(),
+                    )? {
+                        stored += 1;
+                    }
+                }
+                tx.commit()?;
+                Ok(stored)
+            })
+            .await?
+    }
+
+    async fn save_annotation(
+        &self,
+        file_path: &str,
+        id: &str,
+        annotation: serde_json::Value,
+    ) -> Result<AnnotationSave, StoreError> {
+        let (file_path, id) = (file_path.to_string(), id.to_string());
+        self.db
+            .write(move |conn| {
+                crate::annotations::save_annotation(conn, &id, &file_path, annotation)
+                    .map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn delete_annotations(
+        &self,
+        file_path: &str,
+        id: Option<&str>,
+    ) -> Result<usize, StoreError> {
+        let (file_path, id) = (file_path.to_string(), id.map(str::to_string));
+        self.db
+            .write(move |conn| {
+                crate::annotations::delete_annotations(conn, &file_path, id.as_deref())
+                    .map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn undo(&self, file_path: &str) -> Result<Vec<serde_json::Value>, StoreError> {
+        let file_path = file_path.to_string();
+        self.db
+            .write(move |conn| crate::annotations::undo(conn, &file_path).map_err(StoreError::from))
+            .await?
+    }
+
+    async fn history(&self, file_path: &str) -> Result<Vec<HistoryEntry>, StoreError> {
+        let file_path = file_path.to_string();
+        self.db
+            .read(move |conn| {
+                crate::annotations::history(conn, &file_path).map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn set_resolved(
+        &self,
+        file_path: &str,
+        id: &str,
+        resolved: bool,
+        by: Option<serde_json::Value>,
+    ) -> Result<Option<serde_json::Value>, StoreError> {
+        let (file_path, id) = (file_path.to_string(), id.to_string());
+        self.db
+            .write(move |conn| {
+                crate::annotations::set_resolved(conn, &id, &file_path, resolved, by)
+                    .map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn update_anchors(
+        &self,
+        file_path: &str,
+        annotations: &[serde_json::Value],
+    ) -> Result<(), StoreError> {
+        let (file_path, annotations) = (file_path.to_string(), annotations.to_vec());
+        self.db
+            .write(move |conn| {
+                crate::reanchor::update_anchors(conn, &file_path, &annotations)
+                    .map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn rename_file(
+        &self,
+        from: &Path,
+        to: &Path,
+    ) -> Result<Vec<(String, String)>, StoreError> {
+        let (from, to) = (from.to_path_buf(), to.to_path_buf());
+        self.db
+            .write(move |conn| {
+                crate::annotations::move_file_data(conn, &from, &to).map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn viewed_state(&self, file_path: &str) -> Result<Option<ViewedState>, StoreError> {
+        let file_path = file_path.to_string();
+        self.db
+            .read(move |conn| -> Result<_, StoreError> {
+                let row = conn
+                    .query_row(
+                        "SELECT state, updated_by, sections FROM viewed_state WHERE file_path = ?1",
+                        [file_path.as_str()],
+                        |row| {
+                            Ok((
+                                row.get::<_, String>(0)?,
+                                row.get::<_, Option<String>>(1)?,
+                                row.get::<_, Option<String>>(2)?,
+                            ))
+                        },
+                    )
+                    .optional()?;
+                Ok(row.map(|(state, by, sections)| ViewedState {
+                    state: serde_json::from_str(&state).unwrap_or_else(|_| serde_json::json!({})),
+                    by: by.and_then(|by| serde_json::from_str(&by).ok()),
+                    sections: crate::viewed::Sections::parse(sections.as_deref()),
+                }))
+            })
+            .await?
+    }
+
+    async fn viewed_states(&self) -> Result<Vec<ViewedRecord>, StoreError> {
+        self.db
+            .read(|conn| crate::annotations::viewed_states(conn, None).map_err(StoreError::from))
+            .await?
+    }
+
+    async fn save_viewed_state(
+        &self,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError> {
+        let file_path = file_path.to_string();
+        let (state, by, sections) = (
+            state.state.to_string(),
+            state.by.as_ref().map(ToString::to_string),
+            state.sections.to_column(),
+        );
+        self.db.write(move |conn| -> Result<_, StoreError> {
+            conn.execute(
+                "INSERT OR REPLACE INTO viewed_state (file_path, state, updated_by, updated_at, sections) VALUES (?1, ?2, ?3, CURRENT_TIMESTAMP, ?4)",
+                rusqlite::params![file_path, state, by, sections],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+    ) -> Result<Option<ViewedState>, StoreError> {
+        let (client_id, file_path) = (client_id.to_string(), file_path.to_string());
+        self.db.read(move |conn| -> Result<_, StoreError> {
+            let row = conn
+                .query_row(
+                    "SELECT state, sections FROM client_viewed_state WHERE client_id = ?1 AND file_path = ?2",
+                    [client_id.as_str(), file_path.as_str()],
+                    |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)),
+                )
+                .optional()?;
+            Ok(row.map(|(state, sections)| ViewedState {
+                state: serde_json::from_str(&state).unwrap_or_else(|_| serde_json::json!({})),
+                by: None,
+                sections: crate::viewed::Sections::parse(sections.as_deref()),
+            }))
+        })
+        .await?
+    }
+
+    async fn save_client_viewed_state(
+        &self,
+        client_id: &str,
+        file_path: &str,
+        state: &ViewedState,
+    ) -> Result<(), StoreError> {
+        let (client_id, file_path) = (client_id.to_string(), file_path.to_string());
+        let (state, sections) = (state.state.to_string(), state.sections.to_column());
+        self.db.write(move |conn| -> Result<_, StoreError> {
+            conn.execute(
+                "INSERT OR REPLACE INTO client_viewed_state (client_id, file_path, state, sections, updated_at) VALUES (?1, ?2, ?3, ?4, CURRENT_TIMESTAMP)",
+                rusqlite::params![client_id, file_path, state, sections],
+            )?;
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn invalidate_viewed_sections(
+        &self,
+        file_path: &str,
+        markdown: &str,
+    ) -> Result<Option<Invalidated>, StoreError> {
+        let (file_path, markdown) = (file_path.to_string(), markdown.to_string());
+        self.db
+            .write(move |conn| {
+                crate::viewed::invalidate_file(conn, &file_path, &markdown)
+                    .map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn missing_files(&self, root: &Path) -> Result<Vec<MissingFile>, StoreError> {
+        let root = root.to_path_buf();
+        self.db
+            .read(move |conn| {
+                crate::annotations::missing_files(conn, &root).map_err(StoreError::from)
+            })
+            .await?
+    }
+
+    async fn prune_missing_files(
+        &self,
+        files: Vec<MissingFile>,
+        archive: bool,
+    ) -> Result<Vec<MissingFile>, StoreError> {
+        self.db
+            .write(move |conn| {
+                crate::annotations::prune_missing_files(conn, &files, archive)
+                    .map_err(StoreError::from)
+            })
+            .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn sqlite_store_round_trips_document_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SqliteStore::new(Database::open(&dir.path().join("a.sqlite")).unwrap());
+        let annotation = serde_json::json!({ "id": "anno-1", "text": "x" });
+
+        let AnnotationSave::Saved(annotation) = store
+            .save_annotation("/ws/a.md", "anno-1", annotation)
+            .await
+            .unwrap()
+        else {
+            panic!("annotation not saved");
+        };
+        assert_eq!(
+            store
+                .save_annotation("/ws/b.md", "anno-1", serde_json::json!({}))
+                .await
+                .unwrap(),
+            AnnotationSave::Foreign
+        );
+        assert_eq!(store.annotations("/ws/a.md").await.unwrap(), [annotation]);
+        let resolved = store
+            .set_resolved("/ws/a.md", "anno-1", true, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(resolved["resolved"], true);
+        assert_eq!(store.delete_annotations("/ws/a.md", None).await.unwrap(), 1);
+        assert_eq!(store.undo("/ws/a.md").await.unwrap(), [resolved]);
+
+        assert_eq!(store.viewed_state("/ws/a.md").await.unwrap(), None);
+        let viewed = ViewedState {
+            state: serde_json::json!({ "intro": true }),
+            by: Some(serde_json::json!({ "name": "Ana" })),
+            sections: crate::viewed::Sections::default()
+                .record(&serde_json::json!({ "intro": true }), "## Intro\nHi.\n"),
+        };
+        store.save_viewed_state("/ws/a.md", &viewed).await.unwrap();
+        assert_eq!(store.viewed_state("/ws/a.md").await.unwrap(), Some(viewed));
+
+        let private = ViewedState {
+            state: serde_json::json!({ "usage": true }),
+            by: None,
+            sections: crate::viewed::Sections::default(),
+        };
+        store
+            .save_client_viewed_state("client-1", "/ws/a.md", &private)
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .client_viewed_state("client-1", "/ws/a.md")
+                .await
+                .unwrap(),
+            Some(private)
+        );
+        assert_eq!(
+            store
+                .client_viewed_state("client-2", "/ws/a.md")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
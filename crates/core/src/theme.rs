@@ -0,0 +1,144 @@
+//! Page themes: the built-in color schemes and `--theme` files.
+//!
+//! `light` and `dark` are the two schemes every stylesheet knows. `sepia` and
+//! `high-contrast` are variants drawn on one of them: the page resolves to
+//! that scheme (`data-theme`) and a `data-theme-variant` block in tokens.css
+//! swaps the `--markon-*` colors, code palette included. A custom theme is a
+//! stylesheet of token overrides linked after the built-in styles, either
+//! given directly or named by a JSON manifest that also picks the scheme it
+//! starts from:
+//!
+//! ```json
+//! { "name": "Solarized", "base": "light", "stylesheet": "solarized.css" }
+//! ```
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Theme names the server and the page switcher accept.
+pub const BUILT_IN: [&str; 5] = ["auto", "light", "dark", "sepia", "high-contrast"];
+
+/// Whether `name` is one of [`BUILT_IN`].
+pub fn is_built_in(name: &str) -> bool {
+    BUILT_IN.contains(&name)
+}
+
+/// The scheme a built-in theme is drawn on; `auto` stays `auto`.
+pub fn base_scheme(name: &str) -> &str {
+    match name {
+        "sepia" => "light",
+        "high-contrast" => "dark",
+        other => other,
+    }
+}
+
+/// A `--theme` file, resolved.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CustomTheme {
+    /// Built-in theme the stylesheet is layered over.
+    pub base: String,
+    /// Absolute path of the stylesheet.
+    pub stylesheet: PathBuf,
+}
+
+/// A `.json` theme manifest. Other keys, such as a display `name`, are
+/// ignored.
+#[derive(Deserialize)]
+struct Manifest {
+    base: Option<String>,
+    stylesheet: PathBuf,
+}
+
+impl CustomTheme {
+    /// Load a `.css` stylesheet, layered over `light`, or a `.json` manifest.
+    /// A manifest's stylesheet path is taken from the manifest's directory.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let path = std::path::absolute(path)
+            .map_err(|e| format!("Cannot resolve theme {}: {e}", path.display()))?;
+        let extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        let theme = match extension.as_deref() {
+            Some("css") => Self {
+                base: "light".to_string(),
+                stylesheet: path.clone(),
+            },
+            Some("json") => {
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| format!("Cannot read theme {}: {e}", path.display()))?;
+                let manifest: Manifest = serde_json::from_str(&text)
+                    .map_err(|e| format!("Invalid theme {}: {e}", path.display()))?;
+                let base = manifest.base.unwrap_or_else(|| "light".to_string());
+                if !is_built_in(&base) {
+                    return Err(format!(
+                        "Invalid theme {}: base must be one of {}",
+                        path.display(),
+                        BUILT_IN.join(", ")
+                    ));
+                }
+                let dir = path.parent().unwrap_or(Path::new(""));
+                Self {
+                    base,
+                    stylesheet: dir.join(manifest.stylesheet),
+                }
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown theme '{}': use one of {}, a .css file, or a .json manifest",
+                    path.display(),
+                    BUILT_IN.join(", ")
+                ))
+            }
+        };
+        if !theme.stylesheet.is_file() {
+            return Err(format!(
+                "Theme stylesheet {} not found",
+                theme.stylesheet.display()
+            ));
+        }
+        Ok(theme)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stylesheets_and_manifests_resolve_to_a_base_and_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let css = dir.path().join("paper.css");
+        std::fs::write(&css, ":root { --markon-bg-default: #fdf6e3; }").unwrap();
+        assert_eq!(
+            CustomTheme::load(&css).unwrap(),
+            CustomTheme {
+                base: "light".to_string(),
+                stylesheet: css.clone(),
+            }
+        );
+
+        let manifest = dir.path().join("night.json");
+        std::fs::write(
+            &manifest,
+            r#"{ "name": "Night", "base": "dark", "stylesheet": "paper.css" }"#,
+        )
+        .unwrap();
+        let theme = CustomTheme::load(&manifest).unwrap();
+        assert_eq!(theme.base, "dark");
+        assert_eq!(theme.stylesheet, css);
+
+        std::fs::write(
+            &manifest,
+            r#"{ "base": "neon", "stylesheet": "paper.css" }"#,
+        )
+        .unwrap();
+        assert!(CustomTheme::load(&manifest).unwrap_err().contains("base"));
+        std::fs::write(&manifest, r#"{ "stylesheet": "missing.css" }"#).unwrap();
+        assert!(CustomTheme::load(&manifest)
+            .unwrap_err()
+            .contains("not found"));
+        assert!(CustomTheme::load(&dir.path().join("ocean")).is_err());
+        assert_eq!(base_scheme("sepia"), "light");
+        assert_eq!(base_scheme("high-contrast"), "dark");
+    }
+}
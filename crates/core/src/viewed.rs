@@ -0,0 +1,296 @@
+//! Content hashes behind viewed checkmarks.
+//!
+//! When a reader checks a section, the server records a hash of the section's
+//! Markdown source (the heading through its subsections, see
+//! [`crate::markdown::heading_spans`]). When the watcher sees the file change,
+//! sections whose hash no longer matches are unchecked and flagged as changed
+//! since viewed, so a checkmark never vouches for text nobody has read. The
+//! flag clears once the section is checked again.
+
+use crate::markdown::heading_spans;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Hex digits kept from each section's SHA-256; enough to tell edits apart.
+const HASH_CHARS: usize = 16;
+
+/// The `sections` column of a `viewed_state` row.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Sections {
+    /// Content hash of each checked section, taken when it was checked.
+    #[serde(default)]
+    pub hashes: BTreeMap<String, String>,
+    /// Sections unchecked because their content changed after being viewed.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub changed: BTreeSet<String>,
+}
+
+impl Sections {
+    /// Parse a stored column; rows from before section hashes have none.
+    pub(crate) fn parse(raw: Option<&str>) -> Self {
+        raw.and_then(|raw| serde_json::from_str(raw).ok())
+            .unwrap_or_default()
+    }
+
+    /// The column value, `None` when there is nothing to keep.
+    pub(crate) fn to_column(&self) -> Option<String> {
+        if self.hashes.is_empty() && self.changed.is_empty() {
+            None
+        } else {
+            serde_json::to_string(self).ok()
+        }
+    }
+
+    /// The sections after a reader saved `state` against `markdown`: every
+    /// checked section is hashed afresh, and a changed flag survives until
+    /// its section is checked again.
+    pub(crate) fn record(&self, state: &Value, markdown: &str) -> Self {
+        let current = section_hashes(markdown);
+        let hashes = current
+            .into_iter()
+            .filter(|(id, _)| is_checked(state, id))
+            .collect();
+        let changed = self
+            .changed
+            .iter()
+            .filter(|id| !is_checked(state, id))
+            .cloned()
+            .collect();
+        Sections { hashes, changed }
+    }
+
+    /// Uncheck in `state` every section whose content differs from when it
+    /// was checked, given the document's `current` [`section_hashes`].
+    /// Returns whether anything was unchecked.
+    pub(crate) fn invalidate(
+        &mut self,
+        state: &mut Value,
+        current: &BTreeMap<String, String>,
+    ) -> bool {
+        let mut unchecked = false;
+        self.hashes.retain(|id, hash| {
+            if !is_checked(state, id) {
+                return false;
+            }
+            match current.get(id) {
+                Some(now) if now == hash => true,
+                Some(_) => {
+                    state[id.as_str()] = Value::Bool(false);
+                    self.changed.insert(id.clone());
+                    unchecked = true;
+                    false
+                }
+                // The heading itself is gone; its id now matches nothing.
+                None => false,
+            }
+        });
+        unchecked
+    }
+}
+
+fn is_checked(state: &Value, id: &str) -> bool {
+    state.get(id).and_then(Value::as_bool) == Some(true)
+}
+
+/// Hash of every top-level section of `markdown`, by heading id. Trailing
+/// whitespace is ignored so blank lines between sections don't count as edits.
+pub(crate) fn section_hashes(markdown: &str) -> BTreeMap<String, String> {
+    heading_spans(markdown)
+        .into_iter()
+        .map(|span| {
+            let digest = Sha256::digest(span.text.trim_end().as_bytes());
+            let mut hash: String = digest.iter().map(|byte| format!("{byte:02x}")).collect();
+            hash.truncate(HASH_CHARS);
+            (span.anchor, hash)
+        })
+        .collect()
+}
+
+/// A file's viewed state after the watcher unchecked changed sections.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Invalidated {
+    pub state: Value,
+    /// Every section currently flagged as changed since viewed.
+    pub changed: Vec<String>,
+}
+
+/// Compare the viewed sections stored for `file_path` against its new
+/// `markdown`, persisting and returning the shared state when any were
+/// unchecked. Each browser's private viewed state is checked the same way;
+/// those pages pick the result up when they reload.
+pub(crate) fn invalidate_file(
+    conn: &Connection,
+    file_path: &str,
+    markdown: &str,
+) -> rusqlite::Result<Option<Invalidated>> {
+    let current = section_hashes(markdown);
+    invalidate_client_rows(conn, file_path, &current)?;
+    let row: Option<(String, Option<String>)> = conn
+        .query_row(
+            "SELECT state, sections FROM viewed_state WHERE file_path = ?1",
+            [file_path],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+    let Some((state, sections)) =
+        row.and_then(|(state, sections)| invalidate_row(&state, sections.as_deref(), &current))
+    else {
+        return Ok(None);
+    };
+    // The edit, not a reader, changed the state, so the attribution goes.
+    conn.execute(
+        "UPDATE viewed_state SET state = ?1, sections = ?2, updated_by = NULL, updated_at = CURRENT_TIMESTAMP WHERE file_path = ?3",
+        params![state.to_string(), sections.to_column(), file_path],
+    )?;
+    Ok(Some(Invalidated {
+        state,
+        changed: sections.changed.into_iter().collect(),
+    }))
+}
+
+/// A stored viewed `state` with the sections that changed against `current`
+/// unchecked, and the `sections` to store with it; `None` when nothing was
+/// unchecked.
+pub(crate) fn invalidate_row(
+    state: &str,
+    sections: Option<&str>,
+    current: &BTreeMap<String, String>,
+) -> Option<(Value, Sections)> {
+    let mut sections = Sections::parse(sections);
+    if sections.hashes.is_empty() {
+        return None;
+    }
+    let mut state = serde_json::from_str::<Value>(state).ok()?;
+    (state.is_object() && sections.invalidate(&mut state, current)).then_some((state, sections))
+}
+
+fn invalidate_client_rows(
+    conn: &Connection,
+    file_path: &str,
+    current: &BTreeMap<String, String>,
+) -> rusqlite::Result<()> {
+    if !crate::annotations::has_table(conn, "client_viewed_state")? {
+        return Ok(());
+    }
+    let rows: Vec<(String, String, String)> = {
+        let mut stmt = conn.prepare(
+            "SELECT client_id, state, sections FROM client_viewed_state WHERE file_path = ?1 AND sections IS NOT NULL",
+        )?;
+        let rows = stmt.query_map([file_path], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+        rows.collect::<rusqlite::Result<_>>()?
+    };
+    for (client_id, state, sections) in rows {
+        let Some((state, sections)) = invalidate_row(&state, Some(&sections), current) else {
+            continue;
+        };
+        conn.execute(
+            "UPDATE client_viewed_state SET state = ?1, sections = ?2, updated_at = CURRENT_TIMESTAMP WHERE client_id = ?3 AND file_path = ?4",
+            params![state.to_string(), sections.to_column(), client_id, file_path],
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DOC: &str = "# Guide\n\n## Install\nRun it.\n\n## Usage\nCall it.\n";
+
+    #[test]
+    fn record_hashes_checked_sections_and_keeps_unresolved_flags() {
+        let previous = Sections {
+            hashes: BTreeMap::new(),
+            changed: ["install".to_string(), "usage".to_string()].into(),
+        };
+        let sections = previous.record(&json!({ "install": true, "usage": false }), DOC);
+        assert_eq!(
+            sections.hashes.keys().collect::<Vec<_>>(),
+            ["install"],
+            "only checked sections are hashed"
+        );
+        assert_eq!(
+            sections.changed.iter().collect::<Vec<_>>(),
+            ["usage"],
+            "checking a section again clears its flag"
+        );
+        assert_eq!(Sections::default().to_column(), None);
+        assert_eq!(Sections::parse(sections.to_column().as_deref()), sections);
+    }
+
+    #[test]
+    fn invalidate_unchecks_only_edited_sections() {
+        let mut state = json!({ "install": true, "usage": true });
+        let mut sections = Sections::default().record(&state, DOC);
+
+        let spaced = DOC.replace("Call it.\n", "Call it.\n\n\n");
+        assert!(!sections.invalidate(&mut state, &section_hashes(&spaced)));
+
+        let edited = DOC.replace("Call it.", "Call it twice.");
+        assert!(sections.invalidate(&mut state, &section_hashes(&edited)));
+        assert_eq!(state, json!({ "install": true, "usage": false }));
+        assert_eq!(sections.changed.iter().collect::<Vec<_>>(), ["usage"]);
+        assert_eq!(sections.hashes.keys().collect::<Vec<_>>(), ["install"]);
+    }
+
+    #[test]
+    fn invalidate_file_persists_the_unchecked_state() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE viewed_state (file_path TEXT PRIMARY KEY, state TEXT NOT NULL, updated_by TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, sections TEXT);
+             CREATE TABLE client_viewed_state (client_id TEXT NOT NULL, file_path TEXT NOT NULL, state TEXT NOT NULL, sections TEXT, updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP, PRIMARY KEY (client_id, file_path));",
+        )
+        .unwrap();
+        let state = json!({ "install": true });
+        let sections = Sections::default().record(&state, DOC);
+        conn.execute(
+            "INSERT INTO viewed_state (file_path, state, updated_by, sections) VALUES ('/ws/a.md', ?1, '{\"name\":\"Ana\"}', ?2)",
+            params![state.to_string(), sections.to_column()],
+        )
+        .unwrap();
+        conn.execute(
+            "INSERT INTO client_viewed_state (client_id, file_path, state, sections) VALUES ('c1', '/ws/a.md', ?1, ?2)",
+            params![state.to_string(), sections.to_column()],
+        )
+        .unwrap();
+
+        assert_eq!(invalidate_file(&conn, "/ws/a.md", DOC).unwrap(), None);
+        assert_eq!(invalidate_file(&conn, "/ws/b.md", DOC).unwrap(), None);
+
+        let edited = DOC.replace("Run it.", "Run it with care.");
+        let invalidated = invalidate_file(&conn, "/ws/a.md", &edited)
+            .unwrap()
+            .unwrap();
+        assert_eq!(invalidated.state, json!({ "install": false }));
+        assert_eq!(invalidated.changed, ["install"]);
+
+        let (stored, by, column): (String, Option<String>, Option<String>) = conn
+            .query_row(
+                "SELECT state, updated_by, sections FROM viewed_state WHERE file_path = '/ws/a.md'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(stored, r#"{"install":false}"#);
+        assert_eq!(by, None);
+        let private: String = conn
+            .query_row("SELECT state FROM client_viewed_state", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(private, r#"{"install":false}"#);
+        assert_eq!(
+            Sections::parse(column.as_deref())
+                .changed
+                .into_iter()
+                .collect::<Vec<_>>(),
+            ["install"]
+        );
+    }
+}
@@ -0,0 +1,297 @@
+//! Render hooks loaded from sandboxed WebAssembly modules (`--plugins DIR`,
+//! built with the `wasm-plugins` feature).
+//!
+//! Every `*.wasm` file in the directory becomes one [`RenderHook`], applied
+//! in file-name order after `--link-scheme`. A module may not import
+//! anything, so it has no access to the host: it only sees the strings it
+//! is handed and returns new ones. Each call runs in a fresh instance with
+//! bounded memory and fuel, and a call that traps, runs out or returns
+//! garbage leaves the document as it was.
+//!
+//! Strings cross the boundary as UTF-8 in the module's memory. The host
+//! passes each one as a `(ptr, len)` pair of `i32`s, copied into a buffer
+//! from `markon_alloc`; the module answers with an `i64` packing
+//! `ptr << 32 | len`, or `0` to decline. A module exports:
+//!
+//! - `memory` and `markon_alloc(len: i32) -> i32`, always;
+//! - `markon_pre_parse(src) -> i64`, to rewrite the Markdown source (macros);
+//! - `markon_fence_languages() -> i64`, the whitespace-separated fence info
+//!   strings it draws, with `markon_fence(info, code) -> i64` returning the
+//!   HTML for such a block. The HTML is used as is.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::RenderHook;
+
+/// Plugins in `dir` as render hooks, in file-name order.
+#[cfg(feature = "wasm-plugins")]
+pub(crate) fn load_plugins(dir: &Path) -> Result<Vec<Arc<dyn RenderHook>>, String> {
+    let mut files: Vec<_> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read --plugins {}: {e}", dir.display()))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "wasm") && path.is_file())
+        .collect();
+    files.sort();
+    let engine = sandbox::engine().map_err(|e| format!("Cannot start the WASM runtime: {e}"))?;
+    files
+        .iter()
+        .map(|path| {
+            sandbox::WasmPlugin::load(&engine, path)
+                .map(|plugin| {
+                    tracing::info!("loaded render plugin {}", path.display());
+                    Arc::new(plugin) as Arc<dyn RenderHook>
+                })
+                .map_err(|e| format!("Invalid plugin {}: {e}", path.display()))
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "wasm-plugins"))]
+pub(crate) fn load_plugins(_dir: &Path) -> Result<Vec<Arc<dyn RenderHook>>, String> {
+    Err("This markon was built without WASM plugin support (the `wasm-plugins` feature)".into())
+}
+
+#[cfg(feature = "wasm-plugins")]
+mod sandbox {
+    use std::borrow::Cow;
+    use std::path::Path;
+
+    use wasmtime::{Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder, Val};
+
+    /// Instructions (roughly) one call may run before it is stopped.
+    const FUEL: u64 = 500_000_000;
+    /// Linear memory one instance may grow to.
+    const MEMORY_LIMIT: usize = 64 << 20;
+
+    pub(super) fn engine() -> wasmtime::Result<Engine> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        Engine::new(&config)
+    }
+
+    pub(super) struct WasmPlugin {
+        name: String,
+        module: Module,
+        pre_parse: bool,
+        fence_languages: Vec<String>,
+    }
+
+    impl WasmPlugin {
+        pub(super) fn load(engine: &Engine, path: &Path) -> wasmtime::Result<Self> {
+            let module = Module::from_file(engine, path)?;
+            if let Some(import) = module.imports().next() {
+                return Err(wasmtime::Error::msg(format!(
+                    "imports `{}::{}`; plugins run without host access",
+                    import.module(),
+                    import.name()
+                )));
+            }
+            let exports = |name: &str| module.exports().any(|export| export.name() == name);
+            for required in ["memory", "markon_alloc"] {
+                if !exports(required) {
+                    return Err(wasmtime::Error::msg(format!(
+                        "does not export `{required}`"
+                    )));
+                }
+            }
+            let pre_parse = exports("markon_pre_parse");
+            let fences = exports("markon_fence") && exports("markon_fence_languages");
+            let mut plugin = Self {
+                name: path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                module,
+                pre_parse,
+                fence_languages: Vec::new(),
+            };
+            if fences {
+                if let Some(languages) = plugin.call("markon_fence_languages", &[])? {
+                    plugin.fence_languages = languages
+                        .split_whitespace()
+                        .map(str::to_ascii_lowercase)
+                        .collect();
+                }
+            }
+            Ok(plugin)
+        }
+
+        /// Call `export` with `inputs` in a fresh instance; `None` when it
+        /// declines.
+        fn call(&self, export: &str, inputs: &[&str]) -> wasmtime::Result<Option<String>> {
+            let limits = StoreLimitsBuilder::new()
+                .memory_size(MEMORY_LIMIT)
+                .instances(1)
+                .build();
+            let mut store: Store<StoreLimits> = Store::new(self.module.engine(), limits);
+            store.limiter(|limits| limits);
+            store.set_fuel(FUEL)?;
+            let instance = Instance::new(&mut store, &self.module, &[])?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| wasmtime::Error::msg("`memory` is not a memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "markon_alloc")?;
+            let mut params = Vec::with_capacity(inputs.len() * 2);
+            for input in inputs {
+                let len = i32::try_from(input.len())?;
+                let ptr = alloc.call(&mut store, len)?;
+                memory.write(&mut store, ptr as u32 as usize, input.as_bytes())?;
+                params.extend([Val::I32(ptr), Val::I32(len)]);
+            }
+            let func = instance
+                .get_func(&mut store, export)
+                .ok_or_else(|| wasmtime::Error::msg(format!("`{export}` is not a function")))?;
+            let mut result = [Val::I64(0)];
+            func.call(&mut store, &params, &mut result)?;
+            let packed = result[0]
+                .i64()
+                .ok_or_else(|| wasmtime::Error::msg(format!("`{export}` must return an i64")))?
+                as u64;
+            if packed == 0 {
+                return Ok(None);
+            }
+            let (ptr, len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+            let bytes = memory.data(&store).get(ptr..ptr + len).ok_or_else(|| {
+                wasmtime::Error::msg(format!("`{export}` returned out of bounds"))
+            })?;
+            Ok(Some(String::from_utf8(bytes.to_vec())?))
+        }
+
+        /// [`Self::call`], logging failures as declines.
+        fn call_or_decline(&self, export: &str, inputs: &[&str]) -> Option<String> {
+            self.call(export, inputs).unwrap_or_else(|e| {
+                tracing::warn!("render plugin {} failed in {export}: {e}", self.name);
+                None
+            })
+        }
+    }
+
+    impl crate::RenderHook for WasmPlugin {
+        fn pre_parse<'a>(&self, markdown: &'a str) -> Cow<'a, str> {
+            if !self.pre_parse {
+                return Cow::Borrowed(markdown);
+            }
+            match self.call_or_decline("markon_pre_parse", &[markdown]) {
+                Some(rewritten) => Cow::Owned(rewritten),
+                None => Cow::Borrowed(markdown),
+            }
+        }
+
+        fn fence(&self, info: &str, code: &str) -> Option<String> {
+            let language = info.split_whitespace().next()?.to_ascii_lowercase();
+            if !self.fence_languages.contains(&language) {
+                return None;
+            }
+            self.call_or_decline("markon_fence", &[info, code])
+        }
+    }
+}
+
+#[cfg(all(test, feature = "wasm-plugins"))]
+mod tests {
+    use super::*;
+    use crate::MarkdownRenderer;
+
+    /// Upper-cases `shout` fences and rewrites `:wave:` to `hello`. Strings
+    /// live at a bump pointer from 1024; answers are written after them.
+    const SHOUT: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (data (i32.const 16) "shout")
+          (func $alloc (export "markon_alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func $pack (param $ptr i32) (param $len i32) (result i64)
+            (i64.or
+              (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+              (i64.extend_i32_u (local.get $len))))
+          (func (export "markon_fence_languages") (result i64)
+            (call $pack (i32.const 16) (i32.const 5)))
+          (func (export "markon_fence")
+            (param $info i32) (param $info_len i32) (param $code i32) (param $len i32)
+            (result i64)
+            (local $out i32) (local $i i32) (local $c i32)
+            ;; "<b>" + upper-cased code + "</b>"
+            (local.set $out (call $alloc (i32.add (local.get $len) (i32.const 7))))
+            (i32.store8 (local.get $out) (i32.const 60))
+            (i32.store8 (i32.add (local.get $out) (i32.const 1)) (i32.const 98))
+            (i32.store8 (i32.add (local.get $out) (i32.const 2)) (i32.const 62))
+            (block $done
+              (loop $each
+                (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                (local.set $c (i32.load8_u (i32.add (local.get $code) (local.get $i))))
+                (if (i32.and (i32.ge_u (local.get $c) (i32.const 97))
+                             (i32.le_u (local.get $c) (i32.const 122)))
+                  (then (local.set $c (i32.sub (local.get $c) (i32.const 32)))))
+                (i32.store8 (i32.add (i32.add (local.get $out) (i32.const 3)) (local.get $i))
+                            (local.get $c))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $each)))
+            (local.set $c (i32.add (i32.add (local.get $out) (i32.const 3)) (local.get $len)))
+            (i32.store8 (local.get $c) (i32.const 60))
+            (i32.store8 (i32.add (local.get $c) (i32.const 1)) (i32.const 47))
+            (i32.store8 (i32.add (local.get $c) (i32.const 2)) (i32.const 98))
+            (i32.store8 (i32.add (local.get $c) (i32.const 3)) (i32.const 62))
+            (call $pack (local.get $out) (i32.add (local.get $len) (i32.const 7)))))
+    "#;
+
+    /// Spins forever on every source it is handed.
+    const SPIN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "markon_alloc") (param i32) (result i32) (i32.const 1024))
+          (func (export "markon_pre_parse") (param i32 i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+    "#;
+
+    fn write_plugin(dir: &Path, name: &str, wat: &str) {
+        std::fs::write(dir.join(name), wat::parse_str(wat).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn plugins_draw_fences_and_stay_in_their_sandbox() {
+        let dir = tempfile::tempdir().unwrap();
+        write_plugin(dir.path(), "a-shout.wasm", SHOUT);
+        write_plugin(dir.path(), "b-spin.wasm", SPIN);
+        std::fs::write(dir.path().join("notes.txt"), "not a plugin").unwrap();
+
+        let plugins = load_plugins(dir.path()).unwrap();
+        assert_eq!(plugins.len(), 2);
+        let renderer = plugins
+            .into_iter()
+            .fold(MarkdownRenderer::new("light"), |renderer, plugin| {
+                renderer.with_hook(plugin)
+            });
+        let html = renderer
+            .render_document("# Title\n\n```shout\nhey there\n```\n\n```rust\nlet x = 1;\n```\n")
+            .html;
+        assert!(html.contains("<b>HEY THERE"), "{html}");
+        assert!(html.contains("Title</h1>"), "{html}");
+        assert!(html.contains("mk-code"), "{html}");
+    }
+
+    #[test]
+    fn modules_importing_host_functions_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        write_plugin(
+            dir.path(),
+            "host.wasm",
+            r#"(module
+                 (import "env" "read_file" (func (param i32)))
+                 (memory (export "memory") 1)
+                 (func (export "markon_alloc") (param i32) (result i32) (i32.const 0)))"#,
+        );
+        let err = load_plugins(dir.path()).err().unwrap();
+        assert!(err.contains("env::read_file"), "{err}");
+
+        write_plugin(dir.path(), "host.wasm", "(module)");
+        let err = load_plugins(dir.path()).err().unwrap();
+        assert!(err.contains("memory"), "{err}");
+    }
+}
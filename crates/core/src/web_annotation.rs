@@ -0,0 +1,581 @@
+//! W3C Web Annotation (JSON-LD) export and import, for moving annotations
+//! between markon and tools like Hypothesis.
+//!
+//! Our quote anchor maps onto a `TextQuoteSelector` plus a
+//! `TextPositionSelector`, the note onto a commenting `TextualBody`, tags
+//! onto tagging bodies, and the review category onto a classifying body.
+//! Exports also carry a `markon` extension with the stored payload so a
+//! markon-to-markon round trip keeps what the standard has no room for
+//! (highlight colour, attachments, resolution); other tools ignore it.
+
+use crate::annotations::{
+    ExportedAnnotation, ImportEntry, CATEGORIES, MAX_CONTEXT_CHARS, MAX_TAGS, MAX_TAG_CHARS,
+};
+use crate::frontmatter::normalize_tag;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+const CONTEXT: &str = "http://www.w3.org/ns/anno.jsonld";
+
+/// Prefix of the annotation IRIs we mint from our own ids.
+const ID_PREFIX: &str = "urn:markon:";
+
+/// Render `annotations` as one inline `AnnotationCollection`.
+pub(crate) fn render(annotations: &[ExportedAnnotation]) -> String {
+    let items: Vec<Value> = annotations.iter().map(to_web_annotation).collect();
+    let collection = json!({
+        "@context": CONTEXT,
+        "type": "AnnotationCollection",
+        "label": "Markon annotations",
+        "total": items.len(),
+        "first": {
+            "type": "AnnotationPage",
+            "startIndex": 0,
+            "items": items,
+        },
+    });
+    serde_json::to_string_pretty(&collection).unwrap_or_default() + "\n"
+}
+
+fn to_web_annotation(a: &ExportedAnnotation) -> Value {
+    let mut body = Vec::new();
+    if let Some(note) = &a.note {
+        body.push(json!({
+            "type": "TextualBody",
+            "purpose": "commenting",
+            "format": "text/markdown",
+            "value": note,
+        }));
+    }
+    for tag in &a.tags {
+        body.push(json!({ "type": "TextualBody", "purpose": "tagging", "value": tag }));
+    }
+    if let Some(category) = &a.category {
+        body.push(json!({ "type": "TextualBody", "purpose": "classifying", "value": category }));
+    }
+
+    let mut selector = Vec::new();
+    if let Some(anchor) = a.annotation.get("anchor").and_then(Value::as_object) {
+        let exact = anchor
+            .get("exact")
+            .and_then(Value::as_str)
+            .unwrap_or(&a.text);
+        let mut quote = json!({ "type": "TextQuoteSelector", "exact": exact });
+        for key in ["prefix", "suffix"] {
+            if let Some(context) = anchor.get(key).and_then(Value::as_str) {
+                quote[key] = context.into();
+            }
+        }
+        selector.push(quote);
+        if let Some(start) = anchor.get("position").and_then(Value::as_u64) {
+            // Positions count UTF-16 units, as the browser does.
+            let end = start + exact.encode_utf16().count() as u64;
+            selector.push(json!({ "type": "TextPositionSelector", "start": start, "end": end }));
+        }
+    } else if !a.text.is_empty() {
+        selector.push(json!({ "type": "TextQuoteSelector", "exact": a.text }));
+    }
+
+    let mut out = json!({
+        "id": format!("{ID_PREFIX}{}", a.id),
+        "type": "Annotation",
+        "motivation": if a.note.is_some() { "commenting" } else { "highlighting" },
+        "target": { "source": file_url(&a.file_path), "selector": selector },
+        "markon": { "path": a.path, "annotation": a.annotation },
+    });
+    if !body.is_empty() {
+        out["body"] = body.into();
+    }
+    if let Some(created) = &a.created_at {
+        out["created"] = created.as_str().into();
+    }
+    if let Some(modified) = &a.updated_at {
+        out["modified"] = modified.as_str().into();
+    }
+    if let Some(author) = &a.author {
+        out["creator"] = json!({ "type": "Person", "name": author });
+    }
+    out
+}
+
+/// `file://` URL of an absolute path, each segment percent-encoded.
+fn file_url(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let encoded: Vec<String> = path
+        .trim_start_matches('/')
+        .split('/')
+        .map(|segment| {
+            // Keep a Windows drive (`C:`) readable.
+            if segment.len() == 2 && segment.ends_with(':') {
+                segment.to_string()
+            } else {
+                urlencoding::encode(segment).into_owned()
+            }
+        })
+        .collect();
+    format!("file:///{}", encoded.join("/"))
+}
+
+/// The path a `file://` URL names; other IRIs come back unchanged so an
+/// import can still remap their prefix onto local files.
+fn source_path(source: &str) -> String {
+    let Some(rest) = source.strip_prefix("file://") else {
+        return source.to_string();
+    };
+    // `file:///C:/x` names a Windows path, `file:///x` a Unix one.
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+    let decoded = urlencoding::decode(rest)
+        .map(|path| path.into_owned())
+        .unwrap_or_else(|_| rest.to_string());
+    let bytes = decoded.as_bytes();
+    if bytes.len() > 3 && bytes[0] == b'/' && bytes[2] == b':' && bytes[1].is_ascii_alphabetic() {
+        decoded[1..].to_string()
+    } else {
+        decoded
+    }
+}
+
+/// Whether `json` looks like Web Annotation JSON-LD rather than our own dump.
+pub(crate) fn is_web_annotation(value: &Value) -> bool {
+    value.is_array() || value.get("@context").is_some()
+}
+
+/// Read annotations from a collection, a page, a list, or a single
+/// annotation. Items we cannot map still produce an entry, with a null
+/// payload, so the import report counts them as invalid.
+pub(crate) fn parse(value: &Value) -> Result<Vec<ImportEntry>, String> {
+    let items = if let Some(items) = value.as_array() {
+        items.as_slice()
+    } else if has_type(value, "AnnotationCollection") {
+        match value.get("first") {
+            Some(page) if page.is_object() => page_items(page)?,
+            Some(_) => return Err("paged annotation collections must be inlined".into()),
+            None => return Ok(Vec::new()),
+        }
+    } else if has_type(value, "AnnotationPage") {
+        page_items(value)?
+    } else if has_type(value, "Annotation") {
+        std::slice::from_ref(value)
+    } else {
+        return Err("not a Web Annotation, page, or collection".into());
+    };
+    Ok(items
+        .iter()
+        .map(|item| {
+            to_entry(item).unwrap_or_else(|| ImportEntry {
+                file_path: String::new(),
+                path: None,
+                id: String::new(),
+                annotation: Value::Null,
+            })
+        })
+        .collect())
+}
+
+fn page_items(page: &Value) -> Result<&[Value], String> {
+    page.get("items")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .ok_or_else(|| "annotation page has no items".to_string())
+}
+
+fn has_type(value: &Value, kind: &str) -> bool {
+    match value.get("type") {
+        Some(Value::String(t)) => t == kind,
+        Some(Value::Array(types)) => types.iter().any(|t| t.as_str() == Some(kind)),
+        _ => false,
+    }
+}
+
+/// A JSON-LD value that may be one item or a list of them.
+fn one_or_many(value: Option<&Value>) -> &[Value] {
+    match value {
+        Some(Value::Array(items)) => items,
+        Some(value) => std::slice::from_ref(value),
+        None => &[],
+    }
+}
+
+fn to_entry(item: &Value) -> Option<ImportEntry> {
+    if !has_type(item, "Annotation") {
+        return None;
+    }
+    let target = one_or_many(item.get("target")).first()?;
+    let source = match target {
+        Value::String(source) => source.as_str(),
+        target => target.get("source")?.as_str()?,
+    };
+    let file_path = source_path(source);
+    let extension = item.get("markon");
+    let path = extension
+        .and_then(|markon| markon.get("path"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    // Our own export: the stored payload travels verbatim.
+    if let Some(annotation) = extension
+        .and_then(|markon| markon.get("annotation"))
+        .filter(|annotation| annotation.is_object())
+    {
+        let id = annotation.get("id")?.as_str()?.to_string();
+        return Some(ImportEntry {
+            file_path,
+            path,
+            id,
+            annotation: annotation.clone(),
+        });
+    }
+
+    let selectors = match target {
+        Value::Object(target) => one_or_many(target.get("selector")),
+        _ => &[],
+    };
+    let quote = selectors
+        .iter()
+        .find(|selector| has_type(selector, "TextQuoteSelector"))?;
+    let exact = quote.get("exact")?.as_str()?;
+    let position = selectors
+        .iter()
+        .find(|selector| has_type(selector, "TextPositionSelector"))
+        .and_then(|selector| selector.get("start"))
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+    let mut anchor = json!({ "position": position, "exact": exact });
+    // Keep the context nearest the quote when another tool captured more
+    // than we store.
+    if let Some(prefix) = quote.get("prefix").and_then(Value::as_str) {
+        let skip = prefix.chars().count().saturating_sub(MAX_CONTEXT_CHARS);
+        anchor["prefix"] = prefix.chars().skip(skip).collect::<String>().into();
+    }
+    if let Some(suffix) = quote.get("suffix").and_then(Value::as_str) {
+        anchor["suffix"] = suffix
+            .chars()
+            .take(MAX_CONTEXT_CHARS)
+            .collect::<String>()
+            .into();
+    }
+
+    let mut notes = Vec::new();
+    let mut tags = Vec::new();
+    let mut category = None;
+    if let Some(value) = item.get("bodyValue").and_then(Value::as_str) {
+        notes.push(value.to_string());
+    }
+    for body in one_or_many(item.get("body")) {
+        let Some(value) = body.get("value").and_then(Value::as_str) else {
+            continue;
+        };
+        match body.get("purpose").and_then(Value::as_str) {
+            Some("tagging") => {
+                let tag = normalize_tag(value);
+                if !tag.is_empty()
+                    && tag.chars().count() <= MAX_TAG_CHARS
+                    && tags.len() < MAX_TAGS
+                    && !tags.contains(&tag)
+                {
+                    tags.push(tag);
+                }
+            }
+            Some("classifying") if CATEGORIES.contains(&value) => {
+                category = Some(value.to_string());
+            }
+            None | Some("commenting" | "describing" | "replying") => {
+                notes.push(value.to_string());
+            }
+            Some(_) => {}
+        }
+    }
+    let note = notes
+        .into_iter()
+        .filter(|note| !note.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let id = item
+        .get("id")
+        .and_then(Value::as_str)
+        .and_then(|id| id.strip_prefix(ID_PREFIX))
+        .filter(|id| crate::annotations::valid_annotation_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(|| foreign_id(item));
+    let created_at = item
+        .get("created")
+        .and_then(Value::as_str)
+        .and_then(parse_timestamp_ms)
+        .unwrap_or_else(now_ms);
+
+    let mut annotation = Map::new();
+    annotation.insert("id".into(), id.clone().into());
+    annotation.insert(
+        "type".into(),
+        if note.is_empty() {
+            "highlight-yellow"
+        } else {
+            "has-note"
+        }
+        .into(),
+    );
+    annotation.insert("tagName".into(), "span".into());
+    annotation.insert("anchor".into(), anchor);
+    annotation.insert("text".into(), exact.into());
+    annotation.insert(
+        "note".into(),
+        if note.is_empty() {
+            Value::Null
+        } else {
+            note.into()
+        },
+    );
+    annotation.insert("createdAt".into(), created_at.into());
+    if let Some(updated_at) = item
+        .get("modified")
+        .and_then(Value::as_str)
+        .and_then(parse_timestamp_ms)
+    {
+        annotation.insert("updatedAt".into(), updated_at.into());
+    }
+    if let Some(name) = creator_name(item.get("creator")) {
+        annotation.insert("author".into(), json!({ "name": name }));
+    }
+    if !tags.is_empty() {
+        annotation.insert("tags".into(), tags.into());
+    }
+    if let Some(category) = category {
+        annotation.insert("category".into(), category.into());
+    }
+    Some(ImportEntry {
+        file_path,
+        path,
+        id,
+        annotation: Value::Object(annotation),
+    })
+}
+
+/// A stable id for a foreign annotation, so importing the same file twice
+/// updates rather than duplicates.
+fn foreign_id(item: &Value) -> String {
+    let key = match item.get("id").and_then(Value::as_str) {
+        Some(id) => id.to_string(),
+        None => item.to_string(),
+    };
+    let digest = Sha256::digest(key.as_bytes());
+    let hex: String = digest[..12].iter().map(|b| format!("{b:02x}")).collect();
+    format!("anno-w3c-{hex}")
+}
+
+/// `creator` as a short display name: a `name`/`nickname`, or the account
+/// part of an IRI such as Hypothesis's `acct:user@hypothes.is`.
+fn creator_name(creator: Option<&Value>) -> Option<String> {
+    let creator = one_or_many(creator).first()?;
+    let name = match creator {
+        Value::String(iri) => iri.strip_prefix("acct:").unwrap_or(iri),
+        creator => creator
+            .get("name")
+            .or_else(|| creator.get("nickname"))
+            .and_then(Value::as_str)?,
+    };
+    let name: String = name.trim().chars().take(64).collect();
+    (!name.is_empty()).then_some(name)
+}
+
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Milliseconds since the epoch of an RFC 3339 timestamp such as
+/// `2024-05-01T09:30:00Z` or `2019-03-20T10:25:41.287934+00:00`.
+fn parse_timestamp_ms(text: &str) -> Option<u64> {
+    let (date, time) = text.split_once(['T', 't', ' '])?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let (clock, offset_minutes) = if let Some(clock) = time.strip_suffix(['Z', 'z']) {
+        (clock, 0)
+    } else {
+        let split = time.rfind(['+', '-'])?;
+        let (clock, offset) = time.split_at(split);
+        let sign = if offset.starts_with('-') { -1 } else { 1 };
+        let (hours, minutes) = offset[1..].split_once(':')?;
+        (
+            clock,
+            sign * (hours.parse::<i64>().ok()? * 60 + minutes.parse::<i64>().ok()?),
+        )
+    };
+    let (clock, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+    let mut clock = clock.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+        clock.next()?.ok()?,
+    );
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let millis = fraction
+        .chars()
+        .chain(std::iter::repeat('0'))
+        .take(3)
+        .collect::<String>()
+        .parse::<i64>()
+        .ok()?;
+    let seconds = days_from_civil(year, month, day) * 86_400 + hour * 3_600 + minute * 60 + second
+        - offset_minutes * 60;
+    u64::try_from(seconds * 1000 + millis).ok()
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's
+/// `days_from_civil`).
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_from_march = (month + 9) % 12;
+    let day_of_year = (153 * month_from_march + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exported() -> ExportedAnnotation {
+        let annotation = json!({
+            "id": "anno-1",
+            "type": "has-note",
+            "text": "retry loop",
+            "note": "Why **three**?",
+            "anchor": { "position": 12, "exact": "retry loop", "prefix": "the ", "suffix": " backs" },
+            "tags": ["api"],
+            "category": "question",
+            "author": { "name": "Ana", "color": "#3451B2" },
+            "createdAt": 1_714_555_800_000u64,
+        });
+        ExportedAnnotation {
+            file_path: "/ws/docs/a b.md".into(),
+            path: "/abcd1234/docs/a%20b.md".into(),
+            id: "anno-1".into(),
+            text: "retry loop".into(),
+            note: Some("Why **three**?".into()),
+            author: Some("Ana".into()),
+            category: Some("question".into()),
+            tags: vec!["api".into()],
+            created_at: Some("2024-05-01T09:30:00Z".into()),
+            updated_at: None,
+            annotation,
+        }
+    }
+
+    #[test]
+    fn export_maps_anchor_note_and_tags_to_standard_fields() {
+        let collection: Value = serde_json::from_str(&render(&[exported()])).unwrap();
+        assert_eq!(collection["@context"], CONTEXT);
+        assert_eq!(collection["total"], 1);
+        let item = &collection["first"]["items"][0];
+        assert_eq!(item["id"], "urn:markon:anno-1");
+        assert_eq!(item["motivation"], "commenting");
+        assert_eq!(item["creator"]["name"], "Ana");
+        assert_eq!(item["created"], "2024-05-01T09:30:00Z");
+        assert_eq!(item["target"]["source"], "file:///ws/docs/a%20b.md");
+        assert_eq!(
+            item["target"]["selector"],
+            json!([
+                { "type": "TextQuoteSelector", "exact": "retry loop", "prefix": "the ", "suffix": " backs" },
+                { "type": "TextPositionSelector", "start": 12, "end": 22 },
+            ])
+        );
+        assert_eq!(
+            item["body"],
+            json!([
+                { "type": "TextualBody", "purpose": "commenting", "format": "text/markdown", "value": "Why **three**?" },
+                { "type": "TextualBody", "purpose": "tagging", "value": "api" },
+                { "type": "TextualBody", "purpose": "classifying", "value": "question" },
+            ])
+        );
+    }
+
+    #[test]
+    fn our_export_round_trips_its_payload() {
+        let collection: Value = serde_json::from_str(&render(&[exported()])).unwrap();
+        assert!(is_web_annotation(&collection));
+        let entries = parse(&collection).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].id, "anno-1");
+        assert_eq!(entries[0].file_path, "/ws/docs/a b.md");
+        assert_eq!(entries[0].path.as_deref(), Some("/abcd1234/docs/a%20b.md"));
+        assert_eq!(entries[0].annotation, exported().annotation);
+    }
+
+    #[test]
+    fn foreign_annotations_map_selectors_and_bodies() {
+        let hypothesis = json!([{
+            "@context": CONTEXT,
+            "id": "https://hypothes.is/a/abc123",
+            "type": "Annotation",
+            "created": "2019-03-20T10:25:41.287934+00:00",
+            "creator": "acct:ana@hypothes.is",
+            "body": [
+                { "type": "TextualBody", "value": "Needs a source" },
+                { "type": "TextualBody", "purpose": "tagging", "value": "#Citation" },
+            ],
+            "target": {
+                "source": "https://example.com/docs/a.md",
+                "selector": [
+                    { "type": "TextPositionSelector", "start": 40, "end": 45 },
+                    { "type": "TextQuoteSelector", "exact": "claim", "prefix": "the " },
+                ]
+            }
+        }, {
+            "type": "Annotation",
+            "target": "https://example.com/docs/a.md"
+        }]);
+        let entries = parse(&hypothesis).unwrap();
+        assert_eq!(entries.len(), 2);
+        let entry = &entries[0];
+        assert_eq!(entry.file_path, "https://example.com/docs/a.md");
+        assert!(entry.id.starts_with("anno-w3c-"));
+        assert!(crate::annotations::valid_annotation_id(&entry.id));
+        assert_eq!(entries[0].id, parse(&hypothesis).unwrap()[0].id);
+        assert_eq!(
+            entry.annotation["anchor"],
+            json!({ "position": 40, "exact": "claim", "prefix": "the " })
+        );
+        assert_eq!(entry.annotation["type"], "has-note");
+        assert_eq!(entry.annotation["note"], "Needs a source");
+        assert_eq!(entry.annotation["tags"], json!(["citation"]));
+        assert_eq!(
+            entry.annotation["author"],
+            json!({ "name": "ana@hypothes.is" })
+        );
+        assert_eq!(entry.annotation["createdAt"], 1_553_077_541_287u64);
+        assert!(crate::annotations::validate_annotation(&entry.annotation).is_ok());
+        // Nothing to anchor: counted as invalid on import.
+        assert_eq!(entries[1].annotation, Value::Null);
+    }
+
+    #[test]
+    fn file_urls_round_trip() {
+        for path in ["/ws/docs/a b#1.md", "C:/Users/ana/notes.md"] {
+            assert_eq!(source_path(&file_url(path)), path);
+        }
+        assert_eq!(source_path("file:///tmp/x.md"), "/tmp/x.md");
+        assert_eq!(source_path("file://localhost/tmp/x.md"), "/tmp/x.md");
+    }
+
+    #[test]
+    fn timestamps_parse_with_offsets_and_fractions() {
+        assert_eq!(parse_timestamp_ms("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(
+            parse_timestamp_ms("2024-05-01T09:30:00Z"),
+            Some(1_714_555_800_000)
+        );
+        assert_eq!(
+            parse_timestamp_ms("2024-05-01T11:30:00.5+02:00"),
+            Some(1_714_555_800_500)
+        );
+        assert_eq!(parse_timestamp_ms("2024-13-01T00:00:00Z"), None);
+        assert_eq!(parse_timestamp_ms("yesterday"), None);
+    }
+}
@@ -1,26 +1,29 @@
+use crate::change_notifier::{ChangeKind, ChangeNotifier, DocumentChange};
 use crate::chat::edits::PendingEditStore;
 use crate::fswalk::path_to_forward_slash;
-use crate::markdown::extract_referenced_assets_for_file;
-use crate::search::SearchIndex;
+use crate::markdown::{extract_referenced_assets_for_file, MarkdownRenderOutput};
+use crate::metadata::MetadataCache;
+use crate::search::{SearchConfig, SearchIndex};
 use crate::workspace_fs::WorkspaceFs;
 use arc_swap::ArcSwapOption;
 use notify::{
-    event::{CreateKind, ModifyKind, RemoveKind},
+    event::{CreateKind, ModifyKind, RemoveKind, RenameMode},
     EventKind, RecursiveMode, Watcher,
 };
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc, RwLock,
+        Arc, Mutex, RwLock,
     },
 };
 use tokio::sync::broadcast;
 
+/// Asset extensions that trigger a live reload, on top of the markdown ones.
 const LIVE_RELOAD_EXTENSIONS: &[&str] = &[
-    "md", "markdown", "png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "css", "js",
+    "png", "jpg", "jpeg", "gif", "webp", "avif", "svg", "css", "js",
 ];
 const LIVE_RELOAD_IGNORED_DIRS: &[&str] = &[".git", "node_modules", "target"];
 const WATCH_STOP_POLL: std::time::Duration = std::time::Duration::from_millis(500);
@@ -55,6 +58,11 @@ pub struct WorkspaceConfig {
     /// Treated as temporary; settings may persist it so startup policy can
     /// either restore or automatically remove it.
     pub single_file: Option<String>,
+    /// Non-empty → a curated workspace over several files (workspace-relative,
+    /// e.g. `markon docs/*.md spec.md`). Like `single_file` it exposes only
+    /// these files and the local assets they reference; its root page lists
+    /// them in this order. Temporary in the same way as `single_file`.
+    pub files: Vec<String>,
     /// Per-workspace collaborator access-code hash (empty = inherit the
     /// server-level collaborator code).
     pub collaborator_access_code_hash: String,
@@ -79,9 +87,29 @@ pub(crate) struct WorkspaceEntry {
     /// attached to this entry.
     pub events_tx: broadcast::Sender<WorkspaceEvent>,
     pub search_index: ArcSwapOption<SearchIndex>,
+    /// Exclude list and analyzer for this workspace's search, fixed at
+    /// registration from [`WorkspaceRegistry::set_search_config`].
+    pub search_config: SearchConfig,
+    /// Annotation store, fixed at registration from
+    /// [`WorkspaceRegistry::set_annotation_db`]. The watch thread uses it to
+    /// re-anchor annotations on edited files.
+    pub annotation_db: Option<crate::db::Database>,
+    /// Shared store (`--db-url`), fixed at registration from
+    /// [`WorkspaceRegistry::set_remote_store`]. When set, the watch thread
+    /// re-anchors annotations there instead of in `annotation_db`.
+    pub(crate) remote_store: Option<crate::store::RemoteStore>,
+    /// Outgoing document change notifications (`--notify-url`), fixed at
+    /// registration from [`WorkspaceRegistry::set_change_notifier`].
+    pub(crate) change_notifier: Option<Arc<ChangeNotifier>>,
+    /// Whether the watch thread records changed documents in
+    /// [`crate::snapshots`] (`--snapshots`), fixed at registration from
+    /// [`WorkspaceRegistry::enable_snapshots`].
+    pub(crate) snapshots: bool,
     /// Set for temporary single-file workspaces. Holds the file name (relative
     /// to the filesystem capability root). Serving policy lives in `fs`.
     pub single_file: Option<String>,
+    /// Set for curated file-set workspaces; see [`WorkspaceConfig::files`].
+    pub files: Vec<String>,
     /// In-flight `edit_file` proposals from the chat tool, awaiting the
     /// user's accept/reject. Lives on the workspace so HTTP handlers and
     /// the agent loop can share the same store.
@@ -92,6 +120,12 @@ pub(crate) struct WorkspaceEntry {
     /// Optional short display name (empty = none). RwLock so the GUI/web can
     /// rename a workspace live without re-registering it.
     pub alias: RwLock<String>,
+    /// Rendered markdown for recently viewed documents. The watch thread evicts
+    /// paths as they change; see [`RenderCache`].
+    pub render_cache: Mutex<RenderCache>,
+    /// Titles, tags and tables of contents of the workspace's documents; see
+    /// [`MetadataCache`]. The watch thread evicts paths as they change.
+    pub(crate) metadata_cache: Mutex<MetadataCache>,
     /// Shutdown flag for the background watch thread. `remove()` sets it before
     /// dropping the map entry; the watch loop observes it and exits, dropping
     /// its own `Arc<WorkspaceEntry>` so the OS thread and the in-RAM search
@@ -99,6 +133,76 @@ pub(crate) struct WorkspaceEntry {
     stopped: Arc<AtomicBool>,
 }
 
+/// Documents kept per workspace in [`RenderCache`].
+const RENDER_CACHE_LIMIT: usize = 64;
+
+/// Last render of each recently viewed document, keyed by canonical path. A hit
+/// also requires the same content hash, so a missed watcher event can never
+/// serve a stale page; watcher eviction just keeps edited files from pinning
+/// renders nobody will ask for again.
+#[derive(Default)]
+pub(crate) struct RenderCache {
+    entries: HashMap<PathBuf, (String, Arc<MarkdownRenderOutput>)>,
+    lru: VecDeque<PathBuf>,
+}
+
+impl RenderCache {
+    pub(crate) fn get(
+        &mut self,
+        path: &Path,
+        content_hash: &str,
+    ) -> Option<Arc<MarkdownRenderOutput>> {
+        let (hash, output) = self.entries.get(path)?;
+        if hash != content_hash {
+            return None;
+        }
+        let output = output.clone();
+        self.touch(path);
+        Some(output)
+    }
+
+    pub(crate) fn insert(
+        &mut self,
+        path: PathBuf,
+        content_hash: String,
+        output: MarkdownRenderOutput,
+    ) -> Arc<MarkdownRenderOutput> {
+        let output = Arc::new(output);
+        self.touch(&path);
+        self.entries.insert(path, (content_hash, output.clone()));
+        while self.entries.len() > RENDER_CACHE_LIMIT {
+            let Some(oldest) = self.lru.pop_front() else {
+                break;
+            };
+            self.entries.remove(&oldest);
+        }
+        output
+    }
+
+    pub(crate) fn invalidate(&mut self, path: &Path) {
+        if self.entries.remove(path).is_some() {
+            self.lru.retain(|existing| existing != path);
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.lru.clear();
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn touch(&mut self, path: &Path) {
+        if let Some(index) = self.lru.iter().position(|existing| existing == path) {
+            self.lru.remove(index);
+        }
+        self.lru.push_back(path.to_path_buf());
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) enum WorkspaceEvent {
     Channel { channel: String, payload: String },
@@ -122,7 +226,7 @@ impl WorkspaceEntry {
     }
 
     pub(crate) fn is_ephemeral(&self) -> bool {
-        self.fs.is_single_file()
+        self.fs.is_file_scoped()
     }
 
     pub(crate) fn collaborator_access_code_hash(&self) -> String {
@@ -156,6 +260,9 @@ pub struct WorkspaceInfo {
     /// or re-derive the URL. Omitted from the wire format when None.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub single_file: Option<String>,
+    /// The files of an ephemeral file-set workspace, relative to `path`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
     /// Per-workspace collaborator access-code hash (empty = inherit the server code).
     #[serde(default, skip_serializing_if = "String::is_empty")]
     pub collaborator_access_code_hash: String,
@@ -174,6 +281,12 @@ pub struct WorkspaceRegistry {
     inner: RwLock<HashMap<String, Arc<WorkspaceEntry>>>,
     pub(crate) salt: String,
     persist: RwLock<Option<PersistHook>>,
+    search_config: RwLock<SearchConfig>,
+    walk_policy: RwLock<crate::fswalk::WalkPolicy>,
+    annotation_db: RwLock<Option<crate::db::Database>>,
+    remote_store: RwLock<Option<crate::store::RemoteStore>>,
+    change_notifier: RwLock<Option<Arc<ChangeNotifier>>>,
+    snapshots: AtomicBool,
 }
 
 /// Stable workspace id: truncated SHA-256 of salt + path.
@@ -367,8 +480,49 @@ impl WorkspaceRegistry {
             inner: RwLock::new(HashMap::new()),
             salt,
             persist: RwLock::new(None),
+            search_config: RwLock::new(SearchConfig::default()),
+            walk_policy: RwLock::new(crate::fswalk::WalkPolicy::default()),
+            annotation_db: RwLock::new(None),
+            remote_store: RwLock::new(None),
+            change_notifier: RwLock::new(None),
+            snapshots: AtomicBool::new(false),
         }
     }
+    /// Search settings for workspaces registered after this call
+    /// (`--search-exclude`, `--search-lang`).
+    pub fn set_search_config(&self, config: SearchConfig) {
+        *self.search_config.write().unwrap() = config;
+    }
+    /// How workspaces registered after this call are walked and resolved
+    /// (`--show-hidden`, `--markdown-ext`, `--no-follow-symlinks`,
+    /// `--allow-path`, `--deny-path`).
+    pub(crate) fn set_walk_policy(&self, policy: crate::fswalk::WalkPolicy) {
+        *self.walk_policy.write().unwrap() = policy;
+    }
+    /// Annotation store the watchers of workspaces registered after this call
+    /// re-anchor against when annotated files change.
+    pub(crate) fn set_annotation_db(&self, db: crate::db::Database) {
+        *self.annotation_db.write().unwrap() = Some(db);
+    }
+    /// Shared store the watchers of workspaces registered after this call
+    /// re-anchor annotations in. Call from within the runtime that
+    /// connected it.
+    pub(crate) fn set_remote_store(&self, store: Arc<dyn crate::store::DocumentStore>) {
+        *self.remote_store.write().unwrap() = Some(crate::store::RemoteStore {
+            store,
+            runtime: tokio::runtime::Handle::current(),
+        });
+    }
+    /// Notifier the watchers of workspaces registered after this call report
+    /// created, modified and deleted documents to.
+    pub(crate) fn set_change_notifier(&self, notifier: Arc<ChangeNotifier>) {
+        *self.change_notifier.write().unwrap() = Some(notifier);
+    }
+    /// Keep snapshots of the documents of workspaces registered after this
+    /// call; see [`crate::snapshots`].
+    pub(crate) fn enable_snapshots(&self) {
+        self.snapshots.store(true, Ordering::Relaxed);
+    }
     pub fn set_persist_hook(&self, hook: PersistHook) {
         *self.persist.write().unwrap() = Some(hook);
     }
@@ -385,6 +539,16 @@ impl WorkspaceRegistry {
         // re-opened → same id → idempotent reuse.
         let identity = match &config.single_file {
             Some(name) => config.path.join(name),
+            None if !config.files.is_empty() => {
+                // A set is its own identity: the same files in another order
+                // list differently, so they make a different workspace.
+                let mut identity = config.path.clone().into_os_string();
+                for file in &config.files {
+                    identity.push("\0");
+                    identity.push(file);
+                }
+                PathBuf::from(identity)
+            }
             None => config.path.clone(),
         };
         let id = hash_id(&identity, &self.salt);
@@ -396,12 +560,17 @@ impl WorkspaceRegistry {
             // source application finished materializing the file. Re-opening
             // the same identity must refresh the file-derived capability state
             // instead of preserving the empty asset allowlist forever.
-            if let Some(name) = config.single_file.as_deref() {
-                refresh_allowed_assets(&entry, name);
+            if entry.is_ephemeral() {
+                refresh_allowed_assets(&entry);
                 if let Some(index) = entry.search_index.load_full() {
-                    let file = config.path.join(name);
-                    if let Err(error) = index.reconcile_files(std::slice::from_ref(&file)) {
-                        tracing::warn!(%error, "failed to refresh reopened single-file workspace");
+                    let files: Vec<PathBuf> = entry
+                        .fs
+                        .document_routes()
+                        .iter()
+                        .map(|route| config.path.join(route))
+                        .collect();
+                    if let Err(error) = index.reconcile_files(&files) {
+                        tracing::warn!(%error, "failed to refresh reopened file-scoped workspace");
                     }
                 }
             }
@@ -411,10 +580,14 @@ impl WorkspaceRegistry {
         let (config_tx, _) = broadcast::channel(4);
         let (events_tx, _) = broadcast::channel(100);
         let single_file = config.single_file.clone();
-        let workspace_fs = Arc::new(WorkspaceFs::new(
-            config.path.clone(),
-            single_file.as_deref(),
-        ));
+        let scoped_files: Vec<&str> = match single_file.as_deref() {
+            Some(name) => vec![name],
+            None => config.files.iter().map(String::as_str).collect(),
+        };
+        let workspace_fs = Arc::new(
+            WorkspaceFs::with_files(config.path.clone(), &scoped_files)
+                .with_policy(self.walk_policy.read().unwrap().clone()),
+        );
         let entry = Arc::new(WorkspaceEntry {
             id: id.clone(),
             fs: workspace_fs,
@@ -427,34 +600,39 @@ impl WorkspaceRegistry {
             config_tx,
             events_tx,
             search_index: ArcSwapOption::empty(),
+            search_config: self.search_config.read().unwrap().clone(),
+            annotation_db: self.annotation_db.read().unwrap().clone(),
+            remote_store: self.remote_store.read().unwrap().clone(),
+            change_notifier: self.change_notifier.read().unwrap().clone(),
+            snapshots: self.snapshots.load(Ordering::Relaxed),
             single_file: single_file.clone(),
+            files: config.files.clone(),
             pending_edits: Arc::new(PendingEditStore::new()),
             collaborator_access_code_hash: RwLock::new(config.collaborator_access_code_hash),
             alias: RwLock::new(config.alias),
+            render_cache: Mutex::new(RenderCache::default()),
+            metadata_cache: Mutex::new(MetadataCache::default()),
             stopped: Arc::new(AtomicBool::new(false)),
         });
         self.inner
             .write()
             .unwrap()
             .insert(id.clone(), entry.clone());
-        match single_file {
-            Some(name) => {
-                // Seed scoped assets from the file's current content, then watch
-                // for external edits to keep it fresh. When search is enabled,
-                // build an index scoped to ONLY this file (no parent WalkDir, no
-                // sibling leakage); the single-file watcher refreshes it on edit.
-                refresh_allowed_assets(&entry, &name);
-                if config.flags.enable_search {
-                    spawn_search_indexer(entry.clone());
-                }
-                spawn_single_file_watcher(config.path, entry.clone(), name);
+        if entry.is_ephemeral() {
+            // Seed scoped assets from the files' current content, then watch
+            // for external edits to keep them fresh. When search is enabled,
+            // build an index scoped to ONLY these files (no parent WalkDir, no
+            // sibling leakage); the file watcher refreshes it on edit.
+            refresh_allowed_assets(&entry);
+            if config.flags.enable_search {
+                spawn_search_indexer(entry.clone());
             }
-            None => {
-                if config.flags.enable_search {
-                    spawn_search_indexer(entry.clone());
-                }
-                spawn_directory_watcher(config.path, entry.clone());
+            spawn_scoped_files_watcher(config.path, entry.clone());
+        } else {
+            if config.flags.enable_search {
+                spawn_search_indexer(entry.clone());
             }
+            spawn_directory_watcher(config.path, entry.clone());
         }
         self.notify_persist();
         id
@@ -542,12 +720,13 @@ impl WorkspaceRegistry {
         // workspace list (GUI + `GET /api/workspaces`) and `settings.json`
         // (re-written in a different order each save). Sort by serving root,
         // then pinned file name, so the order is stable and path-alphabetical —
-        // single-file entries group under their parent dir. (root, single_file)
-        // is the workspace identity, so this key is unique and total.
+        // single-file entries group under their parent dir. (root, single_file,
+        // files) is the workspace identity, so this key is unique and total.
         v.sort_by(|a, b| {
             a.fs.ambient_root()
                 .cmp(b.fs.ambient_root())
                 .then_with(|| a.single_file.cmp(&b.single_file))
+                .then_with(|| a.files.cmp(&b.files))
         });
         v
     }
@@ -561,6 +740,7 @@ impl WorkspaceRegistry {
                 search_ready: e.search_ready(),
                 ephemeral: e.is_ephemeral(),
                 single_file: e.single_file.clone(),
+                files: e.files.clone(),
                 collaborator_access_code_hash: e.collaborator_access_code_hash(),
                 alias: e.alias(),
             })
@@ -568,17 +748,19 @@ impl WorkspaceRegistry {
     }
 }
 
-/// Read the single-file's current content and replace its scoped asset map
-/// with the local asset paths it explicitly references. Errors (file gone,
-/// unreadable) clear the set — a missing source can't legitimately bless any
-/// sibling.
-fn refresh_allowed_assets(entry: &WorkspaceEntry, file_name: &str) {
+/// Read the scoped files' current content and replace the asset map with the
+/// local asset paths they explicitly reference. A file that is gone or
+/// unreadable contributes nothing — a missing source can't legitimately bless
+/// any sibling.
+fn refresh_allowed_assets(entry: &WorkspaceEntry) {
     let root = entry.fs.ambient_root();
-    let abs = root.join(file_name);
-    let new_set = match std::fs::read_to_string(&abs) {
-        Ok(content) => extract_referenced_assets_for_file(&content, &abs, root),
-        Err(_) => HashSet::new(),
-    };
+    let mut new_set = HashSet::new();
+    for route in entry.fs.document_routes() {
+        let abs = root.join(&route);
+        if let Ok(content) = std::fs::read_to_string(&abs) {
+            new_set.extend(extract_referenced_assets_for_file(&content, &abs, root));
+        }
+    }
     entry.fs.replace_assets(new_set);
 }
 
@@ -662,25 +844,34 @@ fn spawn_watch_thread(
     });
 }
 
-/// Watch the parent directory of a single-file workspace and:
-///   * filter events down to `{file_name} ∪ scoped assets`
-///   * on changes to `file_name`, re-derive the asset allowlist so that newly
-///     referenced local assets become accessible (and removed ones stop being)
+/// Watch the directory of a file-scoped workspace and:
+///   * filter events down to `{scoped files} ∪ scoped assets`
+///   * on changes to a scoped file, re-derive the asset allowlist so that
+///     newly referenced local assets become accessible (and removed ones stop
+///     being)
 ///   * push a `file_changed` WS message so the open browser tab reloads.
 ///
 /// `notify` cannot reliably watch a single file across platforms, so the
-/// minimum viable scope is the parent directory — non-recursive.
-fn spawn_single_file_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>, file_name: String) {
-    let target = root.join(&file_name);
+/// minimum viable scope is the root directory — non-recursive unless a file
+/// of the set lives in a subdirectory.
+fn spawn_scoped_files_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>) {
+    let routes = entry.fs.document_routes();
+    let targets: Vec<PathBuf> = routes.iter().map(|route| root.join(route)).collect();
+    let mode = if routes.iter().any(|route| route.contains('/')) {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
     let expected_root = entry.fs.capability_root().to_path_buf();
     let stopped = entry.stopped.clone();
     spawn_watch_thread(
         root.clone(),
         expected_root,
-        RecursiveMode::NonRecursive,
+        mode,
         stopped,
         move |events: Vec<notify::Event>| {
-            let mut pinned_changed = false;
+            queue_document_changes(&entry, &root, &events, |path| targets.contains(path));
+            let mut changed = BTreeSet::new();
             let mut broadcast_paths = BTreeSet::new();
 
             for event in events {
@@ -694,32 +885,52 @@ fn spawn_single_file_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>, file_nam
                     let Ok(rel) = path.strip_prefix(&root) else {
                         continue;
                     };
-                    let touched_pinned = path == target;
+                    let touched_document = targets.contains(&path);
                     let touched_asset = entry.fs.is_asset(rel);
-                    if !(touched_pinned || touched_asset) {
+                    if !(touched_document || touched_asset) {
                         continue;
                     }
-                    pinned_changed |= touched_pinned;
+                    if touched_document {
+                        changed.insert(path.clone());
+                    }
                     if is_create_or_modify {
-                        broadcast_paths.insert(rel.to_string_lossy().to_string());
+                        broadcast_paths.insert(path_to_forward_slash(rel));
                     }
                 }
             }
 
-            if pinned_changed {
-                if target.is_file() {
-                    refresh_allowed_assets(&entry, &file_name);
-                } else {
+            if !changed.is_empty() {
+                let changed: Vec<PathBuf> = changed.into_iter().collect();
+                {
+                    let mut cache = entry
+                        .render_cache
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                    let mut metadata = entry
+                        .metadata_cache
+                        .lock()
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
+                    for path in &changed {
+                        cache.invalidate(path);
+                        metadata.invalidate(path);
+                    }
+                }
+                refresh_allowed_assets(&entry);
+                for path in changed.iter().filter(|path| !path.is_file()) {
                     // Don't broadcast for a final removal: the file just went
                     // away and reloading would 404 the tab.
-                    entry.fs.clear_assets();
-                    broadcast_paths.remove(&file_name);
+                    if let Ok(rel) = path.strip_prefix(&root) {
+                        broadcast_paths.remove(&path_to_forward_slash(rel));
+                    }
                 }
                 if let Some(idx) = entry.search_index.load_full() {
-                    if let Err(error) = idx.reconcile_files(std::slice::from_ref(&target)) {
-                        tracing::warn!("single-file search index update failed: {error}");
+                    if let Err(error) = idx.reconcile_files(&changed) {
+                        tracing::warn!("scoped search index update failed: {error}");
                     }
                 }
+                reanchor_annotations(&entry, &changed);
+                uncheck_changed_sections(&entry, &changed);
+                snapshot_documents(&entry, &changed);
             }
 
             for rel_str in broadcast_paths {
@@ -739,7 +950,7 @@ fn spawn_single_file_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>, file_nam
 
 fn spawn_search_indexer(entry: Arc<WorkspaceEntry>) {
     std::thread::spawn(move || {
-        if let Ok(idx) = SearchIndex::for_workspace(entry.fs.clone()) {
+        if let Ok(idx) = SearchIndex::for_workspace(entry.fs.clone(), entry.search_config.clone()) {
             entry.search_index.store(Some(Arc::new(idx)));
         }
     });
@@ -748,13 +959,22 @@ fn spawn_search_indexer(entry: Arc<WorkspaceEntry>) {
 fn spawn_directory_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>) {
     let expected_root = entry.fs.capability_root().to_path_buf();
     let stopped = entry.stopped.clone();
+    let policy = entry.fs.policy().clone();
     spawn_watch_thread(
         root.clone(),
         expected_root,
         RecursiveMode::Recursive,
         stopped,
         move |events: Vec<notify::Event>| {
-            let search_changes = coalesce_search_changes(&root, &events);
+            let events = exposed_events(&root, &policy, events);
+            if events.is_empty() {
+                return;
+            }
+            evict_changed_renders(&entry, &events);
+            queue_document_changes(&entry, &root, &events, |path| {
+                directory_live_reload_path(&root, &policy, path).is_some()
+            });
+            let search_changes = coalesce_search_changes(&root, &policy, &events);
             if let Some(idx) = entry.search_index.load_full() {
                 let result = if search_changes.rebuild {
                     if search_changes.paths.is_empty() {
@@ -770,6 +990,19 @@ fn spawn_directory_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>) {
                 }
             }
 
+            move_renamed_annotations(&entry, &root, &renamed_paths(&events));
+
+            let edited: BTreeSet<&Path> = events
+                .iter()
+                .filter(|event| matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)))
+                .flat_map(|event| event.paths.iter().map(PathBuf::as_path))
+                .filter(|path| policy.is_markdown(path))
+                .collect();
+            let edited: Vec<&Path> = edited.into_iter().collect();
+            reanchor_annotations(&entry, &edited);
+            uncheck_changed_sections(&entry, &edited);
+            snapshot_documents(&entry, &edited);
+
             let mut broadcast_paths = BTreeSet::new();
             for event in events {
                 if !matches!(
@@ -779,7 +1012,7 @@ fn spawn_directory_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>) {
                     continue;
                 }
                 for path in event.paths {
-                    if let Some(rel_str) = directory_live_reload_path(&root, &path) {
+                    if let Some(rel_str) = directory_live_reload_path(&root, &policy, &path) {
                         broadcast_paths.insert(rel_str);
                     }
                 }
@@ -797,6 +1030,354 @@ fn spawn_directory_watcher(root: PathBuf, entry: Arc<WorkspaceEntry>) {
     );
 }
 
+/// notify follows every symlinked directory below a recursive watch, even
+/// ones leading out of the workspace, and reports paths the path rules hide.
+/// Keep only the paths the symlink policy lets the workspace reach and the
+/// rules expose.
+fn exposed_events(
+    root: &Path,
+    policy: &crate::fswalk::WalkPolicy,
+    events: Vec<notify::Event>,
+) -> Vec<notify::Event> {
+    events
+        .into_iter()
+        .filter_map(|mut event| {
+            event.paths.retain(|path| {
+                policy.symlink_allowed(root, path)
+                    && path
+                        .strip_prefix(root)
+                        .map_or(true, |rel| policy.rules.permits(rel, path.is_dir()))
+            });
+            (!event.paths.is_empty() || event.need_rescan()).then_some(event)
+        })
+        .collect()
+}
+
+/// Report the documents a watcher batch created, modified or deleted to the
+/// workspace's change notifier (`--notify-url`), if it has one. `watched`
+/// keeps the paths the workspace serves.
+fn queue_document_changes(
+    entry: &WorkspaceEntry,
+    root: &Path,
+    events: &[notify::Event],
+    watched: impl Fn(&PathBuf) -> bool,
+) {
+    let Some(notifier) = entry.change_notifier.as_ref() else {
+        return;
+    };
+    let mut touched = BTreeMap::new();
+    for event in events {
+        let created = match event.kind {
+            EventKind::Create(_) => true,
+            EventKind::Modify(ModifyKind::Metadata(_)) => continue,
+            EventKind::Modify(_) | EventKind::Remove(_) => false,
+            _ => continue,
+        };
+        for path in &event.paths {
+            if !entry.fs.policy().is_markdown(path) || !watched(path) {
+                continue;
+            }
+            let Ok(rel) = path.strip_prefix(root) else {
+                continue;
+            };
+            let seen = touched
+                .entry(path_to_forward_slash(rel))
+                .or_insert((path, false));
+            seen.1 |= created;
+        }
+    }
+    if touched.is_empty() {
+        return;
+    }
+    let alias = entry.alias();
+    let workspace = if alias.is_empty() {
+        root.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    } else {
+        alias
+    };
+    for (rel, (path, created)) in touched {
+        let kind = if !path.is_file() {
+            ChangeKind::Deleted
+        } else if created {
+            ChangeKind::Created
+        } else {
+            ChangeKind::Modified
+        };
+        notifier.queue(DocumentChange {
+            workspace_id: entry.id.clone(),
+            workspace: workspace.clone(),
+            path: rel,
+            kind,
+        });
+    }
+}
+
+/// Re-anchor stored annotations on edited Markdown files (see
+/// [`crate::reanchor`]) before the reload broadcast, so reloading pages load
+/// the corrected anchors. Shared sessions also receive each corrected
+/// annotation directly.
+fn reanchor_annotations(entry: &WorkspaceEntry, paths: &[impl AsRef<Path>]) {
+    if entry.annotation_db.is_none() && entry.remote_store.is_none() {
+        return;
+    }
+    for path in paths {
+        let Ok(canonical) = dunce::canonicalize(path.as_ref()) else {
+            continue;
+        };
+        if entry.fs.route_for_path(&canonical).is_none() {
+            continue;
+        }
+        let Ok(markdown) = std::fs::read_to_string(&canonical) else {
+            continue;
+        };
+        let file_path = canonical.to_string_lossy().into_owned();
+        let changed = match (&entry.remote_store, &entry.annotation_db) {
+            (Some(remote), _) => remote
+                .runtime
+                .block_on(crate::reanchor::reanchor_stored(
+                    remote.store.as_ref(),
+                    &file_path,
+                    &markdown,
+                ))
+                .map_err(|error| error.to_string()),
+            (None, Some(db)) => {
+                let key = file_path.clone();
+                db.write_blocking(move |conn| {
+                    crate::reanchor::reanchor_file(conn, &key, &markdown)
+                        .map_err(|error| error.to_string())
+                })
+                .unwrap_or_else(|error| Err(error.to_string()))
+            }
+            (None, None) => return,
+        };
+        let changed = match changed {
+            Ok(changed) => changed,
+            Err(error) => {
+                tracing::warn!("annotation re-anchoring failed for {file_path}: {error}");
+                continue;
+            }
+        };
+        if changed.is_empty() || !entry.shared_annotation.load(Ordering::Relaxed) {
+            continue;
+        }
+        for annotation in changed {
+            let payload = serde_json::json!({
+                "type": "new_annotation",
+                "annotation": annotation,
+            })
+            .to_string();
+            let _ = entry.events_tx.send(WorkspaceEvent::Channel {
+                channel: format!("document:{file_path}"),
+                payload,
+            });
+        }
+    }
+}
+
+/// Record the new content of edited Markdown files as snapshots (see
+/// [`crate::snapshots`]) when the workspace keeps them.
+fn snapshot_documents(entry: &WorkspaceEntry, paths: &[impl AsRef<Path>]) {
+    let Some(db) = entry.annotation_db.as_ref().filter(|_| entry.snapshots) else {
+        return;
+    };
+    for path in paths {
+        let Ok(canonical) = dunce::canonicalize(path.as_ref()) else {
+            continue;
+        };
+        if !entry.fs.policy().is_markdown(&canonical)
+            || entry.fs.route_for_path(&canonical).is_none()
+        {
+            continue;
+        }
+        let Ok(markdown) = std::fs::read_to_string(&canonical) else {
+            continue;
+        };
+        let file_path = canonical.to_string_lossy().into_owned();
+        let key = file_path.clone();
+        let recorded = db.write_blocking(move |conn| {
+            crate::snapshots::record(conn, &key, &markdown).map_err(|error| error.to_string())
+        });
+        if let Err(error) = recorded.unwrap_or_else(|error| Err(error.to_string())) {
+            tracing::warn!("snapshot of {file_path} failed: {error}");
+        }
+    }
+}
+
+/// Uncheck viewed sections of edited Markdown files whose content changed
+/// since they were checked (see [`crate::viewed`]). Like re-anchoring, this
+/// runs before the reload broadcast; shared sessions also receive the new
+/// state directly.
+fn uncheck_changed_sections(entry: &WorkspaceEntry, paths: &[impl AsRef<Path>]) {
+    if entry.annotation_db.is_none() && entry.remote_store.is_none() {
+        return;
+    }
+    for path in paths {
+        let Ok(canonical) = dunce::canonicalize(path.as_ref()) else {
+            continue;
+        };
+        if entry.fs.route_for_path(&canonical).is_none() {
+            continue;
+        }
+        let Ok(markdown) = std::fs::read_to_string(&canonical) else {
+            continue;
+        };
+        let file_path = canonical.to_string_lossy().into_owned();
+        let invalidated = match (&entry.remote_store, &entry.annotation_db) {
+            (Some(remote), _) => remote
+                .runtime
+                .block_on(
+                    remote
+                        .store
+                        .invalidate_viewed_sections(&file_path, &markdown),
+                )
+                .map_err(|error| error.to_string()),
+            (None, Some(db)) => {
+                let key = file_path.clone();
+                db.write_blocking(move |conn| {
+                    crate::viewed::invalidate_file(conn, &key, &markdown)
+                        .map_err(|error| error.to_string())
+                })
+                .unwrap_or_else(|error| Err(error.to_string()))
+            }
+            (None, None) => return,
+        };
+        let invalidated = match invalidated {
+            Ok(Some(invalidated)) => invalidated,
+            Ok(None) => continue,
+            Err(error) => {
+                tracing::warn!("viewed section check failed for {file_path}: {error}");
+                continue;
+            }
+        };
+        if !entry.shared_annotation.load(Ordering::Relaxed) {
+            continue;
+        }
+        let payload = serde_json::json!({
+            "type": "viewed_state",
+            "state": invalidated.state,
+            "changed": invalidated.changed,
+        })
+        .to_string();
+        let _ = entry.events_tx.send(WorkspaceEvent::Channel {
+            channel: format!("document:{file_path}"),
+            payload,
+        });
+    }
+}
+
+/// Source and destination of each rename in a watcher batch. Backends report
+/// a rename as one event carrying both paths, as a `From`/`To` pair sharing a
+/// tracker cookie, or both. A half without its partner moved into or out of
+/// the tree and is not a rename.
+fn renamed_paths(events: &[notify::Event]) -> Vec<(PathBuf, PathBuf)> {
+    let mut renames = Vec::new();
+    let mut sources = HashMap::new();
+    for event in events {
+        let EventKind::Modify(ModifyKind::Name(mode)) = event.kind else {
+            continue;
+        };
+        let rename = match (mode, event.paths.as_slice()) {
+            (RenameMode::Both, [from, to]) => Some((from.clone(), to.clone())),
+            (RenameMode::From, [from]) => {
+                if let Some(tracker) = event.tracker() {
+                    sources.insert(tracker, from.clone());
+                }
+                None
+            }
+            (RenameMode::To, [to]) => event
+                .tracker()
+                .and_then(|tracker| sources.remove(&tracker))
+                .map(|from| (from, to.clone())),
+            _ => None,
+        };
+        if let Some(rename) = rename {
+            if !renames.contains(&rename) {
+                renames.push(rename);
+            }
+        }
+    }
+    renames
+}
+
+/// Follow renamed files and directories with their annotations and viewed
+/// state, which are keyed by canonical path and would otherwise be stranded
+/// under the old one.
+fn move_renamed_annotations(entry: &WorkspaceEntry, root: &Path, renames: &[(PathBuf, PathBuf)]) {
+    if entry.annotation_db.is_none() && entry.remote_store.is_none() {
+        return;
+    }
+    // Rows are keyed under the canonical root; the source no longer exists
+    // to canonicalize, so both sides are mapped lexically.
+    let canonical_root = entry.fs.capability_root();
+    let canonical = |path: &Path| Some(canonical_root.join(path.strip_prefix(root).ok()?));
+    for (from, to) in renames {
+        if from.exists() || !to.exists() {
+            continue;
+        }
+        let (Some(from), Some(to)) = (canonical(from), canonical(to)) else {
+            continue;
+        };
+        let moved = match (&entry.remote_store, &entry.annotation_db) {
+            (Some(remote), _) => remote
+                .runtime
+                .block_on(remote.store.rename_file(&from, &to))
+                .map_err(|error| error.to_string()),
+            (None, Some(db)) => {
+                let (old_path, new_path) = (from.clone(), to.clone());
+                db.write_blocking(move |conn| {
+                    crate::annotations::move_file_data(conn, &old_path, &new_path)
+                        .map_err(|error| error.to_string())
+                })
+                .unwrap_or_else(|error| Err(error.to_string()))
+            }
+            (None, None) => return,
+        };
+        match moved {
+            Ok(moved) => {
+                for (old, new) in moved {
+                    tracing::info!("moved annotations and viewed state of {old} to {new}");
+                }
+            }
+            Err(error) => tracing::warn!(
+                "moving annotations from {} to {} failed: {error}",
+                from.display(),
+                to.display()
+            ),
+        }
+    }
+}
+
+/// Drop cached renders and metadata for every path a watcher batch touched. A
+/// rescan hint means events were lost, so both caches go.
+fn evict_changed_renders(entry: &WorkspaceEntry, events: &[notify::Event]) {
+    let mut cache = entry
+        .render_cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let mut metadata = entry
+        .metadata_cache
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    for event in events {
+        if event.need_rescan() {
+            cache.clear();
+            metadata.clear();
+            return;
+        }
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            for path in &event.paths {
+                cache.invalidate(path);
+                metadata.invalidate(path);
+            }
+        }
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq)]
 struct SearchChangeBatch {
     paths: Vec<PathBuf>,
@@ -807,7 +1388,11 @@ struct SearchChangeBatch {
 /// Ordinary Markdown paths are reconciled once. Ignore-rule or directory
 /// topology changes require a full rebuild because they may affect paths that
 /// did not themselves emit an event.
-fn coalesce_search_changes(root: &Path, events: &[notify::Event]) -> SearchChangeBatch {
+fn coalesce_search_changes(
+    root: &Path,
+    policy: &crate::fswalk::WalkPolicy,
+    events: &[notify::Event],
+) -> SearchChangeBatch {
     let mut paths = BTreeSet::new();
     let mut rebuild = false;
 
@@ -824,7 +1409,7 @@ fn coalesce_search_changes(root: &Path, events: &[notify::Event]) -> SearchChang
         let relevant_paths: Vec<_> = event
             .paths
             .iter()
-            .filter(|path| !is_search_event_path_ignored(root, path))
+            .filter(|path| !is_search_event_path_ignored(root, policy, path))
             .collect();
         if relevant_paths.is_empty() {
             continue;
@@ -861,7 +1446,7 @@ fn coalesce_search_changes(root: &Path, events: &[notify::Event]) -> SearchChang
             if is_search_ignore_file(rel) {
                 rebuild = true;
             }
-            if path.extension().is_some_and(|ext| ext == "md") {
+            if policy.is_markdown(path) {
                 paths.insert(path.clone());
             }
         }
@@ -877,7 +1462,11 @@ fn coalesce_search_changes(root: &Path, events: &[notify::Event]) -> SearchChang
 /// Drop paths that the shared walker excludes intrinsically before they can
 /// trigger an empty commit or a full rebuild. Explicit ignore-rule files are
 /// retained because changing them can alter visibility elsewhere.
-fn is_search_event_path_ignored(root: &Path, path: &Path) -> bool {
+fn is_search_event_path_ignored(
+    root: &Path,
+    policy: &crate::fswalk::WalkPolicy,
+    path: &Path,
+) -> bool {
     let rel = path.strip_prefix(root).unwrap_or(path);
     let components: Vec<_> = rel.components().map(|part| part.as_os_str()).collect();
     let retained_rule_suffix = if components.last().is_some_and(|name| {
@@ -897,7 +1486,7 @@ fn is_search_event_path_ignored(root: &Path, path: &Path) -> bool {
         .iter()
         .any(|component| {
             let name = component.to_string_lossy();
-            name.starts_with('.')
+            policy.is_hidden_name(&name)
                 || LIVE_RELOAD_IGNORED_DIRS
                     .iter()
                     .any(|ignored| name.eq_ignore_ascii_case(ignored))
@@ -919,7 +1508,11 @@ fn is_search_ignore_file(rel: &Path) -> bool {
     ])
 }
 
-fn directory_live_reload_path(root: &Path, path: &Path) -> Option<String> {
+fn directory_live_reload_path(
+    root: &Path,
+    policy: &crate::fswalk::WalkPolicy,
+    path: &Path,
+) -> Option<String> {
     let rel = path.strip_prefix(root).ok()?;
     if rel.as_os_str().is_empty()
         || rel.components().any(|component| {
@@ -932,7 +1525,7 @@ fn directory_live_reload_path(root: &Path, path: &Path) -> Option<String> {
         return None;
     }
     let ext = rel.extension()?.to_string_lossy().to_ascii_lowercase();
-    if !LIVE_RELOAD_EXTENSIONS.contains(&ext.as_str()) {
+    if !LIVE_RELOAD_EXTENSIONS.contains(&ext.as_str()) && !policy.is_markdown(rel) {
         return None;
     }
     Some(path_to_forward_slash(rel))
@@ -973,6 +1566,13 @@ pub struct ServerLock {
     /// `#[serde(default)]` keeps pre-nonce lock files readable (empty nonce).
     #[serde(default)]
     pub owner: String,
+    /// Process id of the server, for `markon status`. Zero in locks written
+    /// before the field existed.
+    #[serde(default)]
+    pub pid: u32,
+    /// Unix time (seconds) the server started listening; zero when unknown.
+    #[serde(default)]
+    pub started_at: u64,
 }
 impl ServerLock {
     pub(crate) fn path() -> PathBuf {
@@ -1175,32 +1775,168 @@ mod tests {
     #[test]
     fn directory_live_reload_filter_tracks_docs_and_assets_only() {
         let root = Path::new("/repo");
+        let policy = crate::fswalk::WalkPolicy::default();
 
         assert_eq!(
-            directory_live_reload_path(root, &root.join("docs").join("a.md")).as_deref(),
+            directory_live_reload_path(root, &policy, &root.join("docs").join("a.md")).as_deref(),
             Some("docs/a.md")
         );
         assert_eq!(
-            directory_live_reload_path(root, &root.join("assets").join("app.js")).as_deref(),
+            directory_live_reload_path(root, &policy, &root.join("assets").join("app.js"))
+                .as_deref(),
             Some("assets/app.js")
         );
         assert_eq!(
-            directory_live_reload_path(root, &root.join("img").join("hero.PNG")).as_deref(),
+            directory_live_reload_path(root, &policy, &root.join("img").join("hero.PNG"))
+                .as_deref(),
             Some("img/hero.PNG")
         );
 
-        assert!(directory_live_reload_path(root, &root.join(".git").join("HEAD")).is_none());
         assert!(
-            directory_live_reload_path(root, &root.join("node_modules").join("x.md")).is_none()
+            directory_live_reload_path(root, &policy, &root.join(".git").join("HEAD")).is_none()
         );
-        assert!(directory_live_reload_path(root, &root.join("target").join("x.css")).is_none());
-        assert!(directory_live_reload_path(root, &root.join("README")).is_none());
-        assert!(directory_live_reload_path(root, &root.join("notes.txt")).is_none());
+        assert!(
+            directory_live_reload_path(root, &policy, &root.join("node_modules").join("x.md"))
+                .is_none()
+        );
+        assert!(
+            directory_live_reload_path(root, &policy, &root.join("target").join("x.css")).is_none()
+        );
+        assert!(directory_live_reload_path(root, &policy, &root.join("README")).is_none());
+        assert!(directory_live_reload_path(root, &policy, &root.join("notes.txt")).is_none());
+    }
+
+    #[test]
+    fn renamed_paths_pairs_both_and_tracked_halves() {
+        let (a, b, c, d) = (
+            PathBuf::from("/ws/a.md"),
+            PathBuf::from("/ws/b.md"),
+            PathBuf::from("/ws/c.md"),
+            PathBuf::from("/ws/d.md"),
+        );
+        let name = |mode| EventKind::Modify(ModifyKind::Name(mode));
+        let events = [
+            notify::Event::new(name(RenameMode::From))
+                .add_path(a.clone())
+                .set_tracker(1),
+            notify::Event::new(name(RenameMode::To))
+                .add_path(b.clone())
+                .set_tracker(1),
+            notify::Event::new(name(RenameMode::Both))
+                .add_path(a.clone())
+                .add_path(b.clone()),
+            // Moved out of the tree: no partner.
+            notify::Event::new(name(RenameMode::From))
+                .add_path(c.clone())
+                .set_tracker(2),
+            notify::Event::new(name(RenameMode::Both))
+                .add_path(c.clone())
+                .add_path(d.clone()),
+        ];
+        assert_eq!(renamed_paths(&events), [(a, b), (c, d)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn watcher_events_through_an_escaping_symlink_are_dropped() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        let root = temp.path();
+        std::fs::write(root.join("a.md"), "# A").unwrap();
+        std::fs::write(outside.path().join("secret.md"), "# Secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("escape")).unwrap();
+        let modify = EventKind::Modify(ModifyKind::Data(notify::event::DataChange::Content));
+        let events = vec![
+            notify::Event::new(modify)
+                .add_path(root.join("a.md"))
+                .add_path(root.join("escape/secret.md")),
+            notify::Event::new(modify).add_path(root.join("escape/secret.md")),
+            notify::Event::new(EventKind::Remove(RemoveKind::File)).add_path(root.join("gone.md")),
+        ];
+
+        let kept = exposed_events(root, &Default::default(), events);
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].paths, [root.join("a.md")]);
+        assert_eq!(kept[1].paths, [root.join("gone.md")]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn each_registry_applies_its_own_walk_policy() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let root = dunce::canonicalize(temp.path()).unwrap();
+        std::fs::write(root.join("guide.md"), "# Guide").unwrap();
+        std::os::unix::fs::symlink("guide.md", root.join("linked.md")).unwrap();
+        let workspace = |registry: &WorkspaceRegistry| {
+            let id = registry.add(WorkspaceConfig {
+                path: root.clone(),
+                ..Default::default()
+            });
+            registry.get(&id).unwrap()
+        };
+
+        let following = WorkspaceRegistry::new("salt".into());
+        let strict = WorkspaceRegistry::new("salt".into());
+        strict.set_walk_policy(crate::fswalk::WalkPolicy {
+            follow_symlinks: false,
+            ..Default::default()
+        });
+        assert!(workspace(&following)
+            .fs
+            .resolve_content("linked.md")
+            .is_ok());
+        assert!(workspace(&strict).fs.resolve_content("linked.md").is_err());
+
+        let denying = WorkspaceRegistry::new("salt".into());
+        denying.set_walk_policy(crate::fswalk::WalkPolicy {
+            rules: Arc::new(
+                crate::path_rules::PathRules::new(&[], &["guide.md".to_string()]).unwrap(),
+            ),
+            ..Default::default()
+        });
+        assert!(workspace(&denying).fs.resolve_content("guide.md").is_err());
+        assert!(workspace(&following).fs.resolve_content("guide.md").is_ok());
+
+        let revealing = WorkspaceRegistry::new("salt".into());
+        revealing.set_walk_policy(crate::fswalk::WalkPolicy {
+            show_hidden: true,
+            markdown: crate::markdown::MarkdownExtensions::new(&["txt".to_string()]),
+            ..Default::default()
+        });
+        let policy = workspace(&revealing).fs.policy().clone();
+        assert!(!policy.is_hidden_name(".drafts"));
+        assert!(policy.is_markdown(Path::new("notes.txt")));
+        let policy = workspace(&following).fs.policy().clone();
+        assert!(policy.is_hidden_name(".drafts"));
+        assert!(!policy.is_markdown(Path::new("notes.txt")));
+    }
+
+    #[test]
+    fn watcher_events_for_denied_paths_are_dropped() {
+        let root = Path::new("/repo");
+        let policy = crate::fswalk::WalkPolicy {
+            rules: Arc::new(
+                crate::path_rules::PathRules::new(&[], &["private/**".to_string()]).unwrap(),
+            ),
+            ..Default::default()
+        };
+        let modify = EventKind::Modify(ModifyKind::Data(notify::event::DataChange::Content));
+        let events = vec![
+            notify::Event::new(modify)
+                .add_path(root.join("a.md"))
+                .add_path(root.join("private/plan.md")),
+            notify::Event::new(modify).add_path(root.join("private/notes.md")),
+        ];
+
+        let kept = exposed_events(root, &policy, events);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].paths, [root.join("a.md")]);
     }
 
     #[test]
     fn search_change_batch_deduplicates_markdown_paths() {
         let root = Path::new("/repo");
+        let policy = crate::fswalk::WalkPolicy::default();
         let first = root.join("docs").join("a.md");
         let second = root.join("docs").join("b.md");
         let modify_kind = EventKind::Modify(ModifyKind::Data(notify::event::DataChange::Content));
@@ -1210,7 +1946,7 @@ mod tests {
             notify::Event::new(EventKind::Create(CreateKind::File)).add_path(second.clone()),
         ];
 
-        let batch = coalesce_search_changes(root, &events);
+        let batch = coalesce_search_changes(root, &policy, &events);
         assert!(!batch.rebuild);
         assert_eq!(batch.paths, vec![first, second]);
     }
@@ -1218,6 +1954,7 @@ mod tests {
     #[test]
     fn search_change_batch_rebuilds_for_ignore_and_directory_changes() {
         let root = Path::new("/repo");
+        let policy = crate::fswalk::WalkPolicy::default();
         let ignore_event = notify::Event::new(EventKind::Modify(ModifyKind::Data(
             notify::event::DataChange::Content,
         )))
@@ -1225,8 +1962,8 @@ mod tests {
         let directory_event = notify::Event::new(EventKind::Remove(RemoveKind::Folder))
             .add_path(root.join("old-docs"));
 
-        assert!(coalesce_search_changes(root, &[ignore_event]).rebuild);
-        assert!(coalesce_search_changes(root, &[directory_event]).rebuild);
+        assert!(coalesce_search_changes(root, &policy, &[ignore_event]).rebuild);
+        assert!(coalesce_search_changes(root, &policy, &[directory_event]).rebuild);
         assert!(is_search_ignore_file(Path::new(".git/info/exclude")));
         assert!(!is_search_ignore_file(Path::new(".git/index")));
     }
@@ -1234,6 +1971,7 @@ mod tests {
     #[test]
     fn search_change_batch_drops_intrinsically_ignored_paths() {
         let root = Path::new("/repo");
+        let policy = crate::fswalk::WalkPolicy::default();
         let ignored_file = root.join("node_modules").join("pkg").join("README.md");
         let hidden_file = root.join(".cache").join("generated.md");
         let ignored_directory = root.join("target").join("generated-docs");
@@ -1247,23 +1985,27 @@ mod tests {
         ];
 
         assert_eq!(
-            coalesce_search_changes(root, &events),
+            coalesce_search_changes(root, &policy, &events),
             SearchChangeBatch::default()
         );
         assert!(is_search_event_path_ignored(
             root,
+            &policy,
             &root.join(".hidden").join("note.md")
         ));
         assert!(!is_search_event_path_ignored(
             root,
+            &policy,
             &root.join("docs").join(".gitignore")
         ));
         assert!(!is_search_event_path_ignored(
             root,
+            &policy,
             &root.join(".git").join("info").join("exclude")
         ));
         assert!(is_search_event_path_ignored(
             root,
+            &policy,
             &root.join("node_modules").join("pkg").join(".gitignore")
         ));
     }
@@ -1339,6 +2081,8 @@ mod tests {
             advertised_host: Some("192.168.1.20".into()),
             service_version: "1.2.3".into(),
             owner: "owner-nonce".into(),
+            pid: 4242,
+            started_at: 1_700_000_000,
         };
         let json = serde_json::to_string(&lock).unwrap();
         let back: ServerLock = serde_json::from_str(&json).unwrap();
@@ -1348,6 +2092,8 @@ mod tests {
         assert_eq!(back.advertised_host.as_deref(), Some("192.168.1.20"));
         assert_eq!(back.service_version, "1.2.3");
         assert_eq!(back.owner, "owner-nonce");
+        assert_eq!(back.pid, 4242);
+        assert_eq!(back.started_at, 1_700_000_000);
     }
 
     /// Block until the entry's search index is populated (it's built on a
@@ -1409,6 +2155,230 @@ mod tests {
         );
     }
 
+    #[test]
+    fn renamed_files_keep_their_annotations() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = dunce::canonicalize(temp_dir.path()).unwrap();
+        let (from, to) = (root.join("draft.md"), root.join("final.md"));
+        std::fs::write(&to, "# Final").unwrap();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO annotations (id, file_path, data) VALUES ('anno-1', ?1, '{}')",
+            [from.to_string_lossy()],
+        )
+        .unwrap();
+        let db = crate::db::Database::from_connection(conn);
+        let registry = WorkspaceRegistry::new("test-salt".into());
+        registry.set_annotation_db(db.clone());
+        let id = registry.add(WorkspaceConfig {
+            path: root.clone(),
+            ..Default::default()
+        });
+        let entry = registry.get(&id).unwrap();
+
+        move_renamed_annotations(&entry, &root, &[(from, to.clone())]);
+        let file_path: String = db
+            .read_blocking(|conn| {
+                conn.query_row("SELECT file_path FROM annotations", [], |row| row.get(0))
+            })
+            .unwrap();
+        assert_eq!(file_path, to.to_string_lossy());
+    }
+
+    #[test]
+    fn edited_files_reanchor_annotations_and_notify_shared_viewers() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        std::fs::write(&file, "# Notes\n\nInserted first. Retry loop.").unwrap();
+        let canonical = dunce::canonicalize(&file).unwrap();
+        let file_path = canonical.to_string_lossy().into_owned();
+
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        crate::annotations::create_table(&conn).unwrap();
+        let stored = serde_json::json!({
+            "id": "anno-1",
+            "anchor": { "position": 6, "exact": "Retry loop", "prefix": "Notes\n", "suffix": "" }
+        });
+        conn.execute(
+            "INSERT INTO annotations (id, file_path, data) VALUES ('anno-1', ?1, ?2)",
+            [file_path.as_str(), &stored.to_string()],
+        )
+        .unwrap();
+
+        let registry = WorkspaceRegistry::new("test-salt".into());
+        registry.set_annotation_db(crate::db::Database::from_connection(conn));
+        let id = registry.add(WorkspaceConfig {
+            path: temp_dir.path().to_path_buf(),
+            flags: WorkspaceFlags {
+                shared_annotation: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let entry = registry.get(&id).unwrap();
+        let mut events = entry.events_tx.subscribe();
+
+        reanchor_annotations(&entry, &[file]);
+        match events.try_recv().unwrap() {
+            WorkspaceEvent::Channel { channel, payload } => {
+                assert_eq!(channel, format!("document:{file_path}"));
+                let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+                assert_eq!(payload["type"], "new_annotation");
+                let suffix = payload["annotation"]["anchor"]["suffix"].as_str().unwrap();
+                assert!(suffix.starts_with('.'), "{suffix:?}");
+            }
+            WorkspaceEvent::Workspace { .. } => panic!("expected a document event"),
+        }
+    }
+
+    #[test]
+    fn edited_files_reanchor_annotations_in_a_remote_store() {
+        use crate::store::DocumentStore;
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        std::fs::write(&file, "# Notes\n\nInserted first. Retry loop.").unwrap();
+        let file_path = dunce::canonicalize(&file)
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let store = Arc::new(crate::store::memory::MemoryStore::default());
+        let stored = serde_json::json!({
+            "id": "anno-1",
+            "anchor": { "position": 6, "exact": "Retry loop", "prefix": "Notes\n", "suffix": "" }
+        });
+        runtime
+            .block_on(store.create_annotation(&file_path, "anno-1", &stored))
+            .unwrap();
+
+        let registry = WorkspaceRegistry::new("test-salt".into());
+        runtime.block_on(async { registry.set_remote_store(store.clone()) });
+        let id = registry.add(WorkspaceConfig {
+            path: temp_dir.path().to_path_buf(),
+            ..Default::default()
+        });
+
+        reanchor_annotations(&registry.get(&id).unwrap(), &[file]);
+        let annotations = runtime.block_on(store.annotations(&file_path)).unwrap();
+        let suffix = annotations[0]["anchor"]["suffix"].as_str().unwrap();
+        assert!(suffix.starts_with('.'), "{suffix:?}");
+    }
+
+    #[test]
+    fn renames_and_edited_sections_reach_a_remote_store() {
+        use crate::store::{DocumentStore, ViewedState};
+
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let root = dunce::canonicalize(temp_dir.path()).unwrap();
+        let (from, to) = (root.join("draft.md"), root.join("final.md"));
+        let before = "## Setup\nOne.\n\n## Usage\nTwo.\n";
+        std::fs::write(&to, before).unwrap();
+        let (old_path, new_path) = (
+            from.to_string_lossy().into_owned(),
+            to.to_string_lossy().into_owned(),
+        );
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let store = Arc::new(crate::store::memory::MemoryStore::default());
+        let state = serde_json::json!({ "setup": true, "usage": true });
+        let viewed = ViewedState {
+            sections: crate::viewed::Sections::default().record(&state, before),
+            state,
+            by: None,
+        };
+        runtime.block_on(async {
+            let note = serde_json::json!({ "id": "anno-1" });
+            store
+                .create_annotation(&old_path, "anno-1", &note)
+                .await
+                .unwrap();
+            store.save_viewed_state(&old_path, &viewed).await.unwrap();
+            store
+                .save_client_viewed_state("client-1", &old_path, &viewed)
+                .await
+                .unwrap();
+        });
+
+        let registry = WorkspaceRegistry::new("test-salt".into());
+        runtime.block_on(async { registry.set_remote_store(store.clone()) });
+        let id = registry.add(WorkspaceConfig {
+            path: root.clone(),
+            ..Default::default()
+        });
+        let entry = registry.get(&id).unwrap();
+
+        move_renamed_annotations(&entry, &root, &[(from, to.clone())]);
+        assert_eq!(
+            runtime.block_on(store.annotation_file("anno-1")).unwrap(),
+            Some(new_path.clone())
+        );
+
+        std::fs::write(&to, before.replace("Two.", "Two, revised.")).unwrap();
+        uncheck_changed_sections(&entry, &[to]);
+        let unchecked = serde_json::json!({ "setup": true, "usage": false });
+        let shared = runtime.block_on(store.viewed_state(&new_path)).unwrap();
+        assert_eq!(shared.unwrap().state, unchecked);
+        let private = runtime
+            .block_on(store.client_viewed_state("client-1", &new_path))
+            .unwrap();
+        assert_eq!(private.unwrap().state, unchecked);
+        assert_eq!(
+            runtime.block_on(store.viewed_state(&old_path)).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn edited_sections_are_unchecked_for_shared_viewers() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file = temp_dir.path().join("notes.md");
+        let before = "## Setup\nOne.\n\n## Usage\nTwo.\n";
+        std::fs::write(&file, before).unwrap();
+        let canonical = dunce::canonicalize(&file).unwrap();
+        let file_path = canonical.to_string_lossy().into_owned();
+
+        let db = temp_dir.path().join("annotation.sqlite");
+        let conn = crate::db::open(&db).unwrap();
+        let state = serde_json::json!({ "setup": true, "usage": true });
+        let sections = crate::viewed::Sections::default().record(&state, before);
+        conn.execute(
+            "INSERT INTO viewed_state (file_path, state, sections) VALUES (?1, ?2, ?3)",
+            rusqlite::params![file_path, state.to_string(), sections.to_column()],
+        )
+        .unwrap();
+
+        let registry = WorkspaceRegistry::new("test-salt".into());
+        registry.set_annotation_db(crate::db::Database::from_connection(conn));
+        let id = registry.add(WorkspaceConfig {
+            path: temp_dir.path().to_path_buf(),
+            flags: WorkspaceFlags {
+                shared_annotation: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let entry = registry.get(&id).unwrap();
+        let mut events = entry.events_tx.subscribe();
+
+        std::fs::write(&file, before.replace("Two.", "Two, revised.")).unwrap();
+        uncheck_changed_sections(&entry, &[file]);
+        match events.try_recv().unwrap() {
+            WorkspaceEvent::Channel { channel, payload } => {
+                assert_eq!(channel, format!("document:{file_path}"));
+                let payload: serde_json::Value = serde_json::from_str(&payload).unwrap();
+                assert_eq!(payload["type"], "viewed_state");
+                assert_eq!(
+                    payload["state"],
+                    serde_json::json!({ "setup": true, "usage": false })
+                );
+                assert_eq!(payload["changed"], serde_json::json!(["usage"]));
+            }
+            WorkspaceEvent::Workspace { .. } => panic!("expected a document event"),
+        }
+    }
+
     #[test]
     fn reopening_single_file_refreshes_late_materialized_assets() {
         let temp_dir = tempfile::TempDir::new().unwrap();
@@ -1482,4 +2452,47 @@ mod tests {
             "disabling search must clear the single-file index"
         );
     }
+
+    fn render_output(html: &str) -> MarkdownRenderOutput {
+        MarkdownRenderOutput {
+            html: html.to_string(),
+            has_mermaid: false,
+            has_math: false,
+            toc: Vec::new(),
+            referenced_assets: HashSet::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn render_cache_requires_matching_content_hash() {
+        let mut cache = RenderCache::default();
+        let path = PathBuf::from("/docs/a.md");
+        cache.insert(path.clone(), "h1".into(), render_output("<p>a</p>"));
+        assert_eq!(cache.get(&path, "h1").unwrap().html, "<p>a</p>");
+        assert!(cache.get(&path, "h2").is_none());
+
+        cache.invalidate(&path);
+        assert!(cache.get(&path, "h1").is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn render_cache_evicts_least_recently_used() {
+        let mut cache = RenderCache::default();
+        for i in 0..RENDER_CACHE_LIMIT {
+            cache.insert(
+                PathBuf::from(format!("/docs/{i}.md")),
+                "h".into(),
+                render_output(""),
+            );
+        }
+        // Touch the oldest entry so the next insert evicts `1.md` instead.
+        assert!(cache.get(Path::new("/docs/0.md"), "h").is_some());
+        cache.insert(PathBuf::from("/docs/new.md"), "h".into(), render_output(""));
+
+        assert_eq!(cache.len(), RENDER_CACHE_LIMIT);
+        assert!(cache.get(Path::new("/docs/0.md"), "h").is_some());
+        assert!(cache.get(Path::new("/docs/1.md"), "h").is_none());
+    }
 }
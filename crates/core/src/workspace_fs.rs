@@ -11,7 +11,7 @@ use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::{Component, Path, PathBuf};
 use std::sync::{Arc, RwLock};
 
-use crate::fswalk::{default_walker, path_to_forward_slash};
+use crate::fswalk::{path_to_forward_slash, WalkPolicy};
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub(crate) struct WorkspaceRelPath(PathBuf);
@@ -47,8 +47,11 @@ impl WorkspaceRelPath {
 #[derive(Debug)]
 enum WorkspaceScope {
     Directory,
-    SingleFile {
-        document: ScopedPath,
+    /// A single Open-With file or a curated set named on the command line:
+    /// only `documents` (in the order given) and the local assets they
+    /// reference are reachable.
+    Files {
+        documents: Vec<ScopedPath>,
         assets: RwLock<HashMap<WorkspaceRelPath, WorkspaceRelPath>>,
     },
 }
@@ -73,6 +76,10 @@ pub(crate) struct WorkspaceFs {
     /// permanent 404 for the lifetime of the daemon.
     root: RwLock<Option<Arc<Dir>>>,
     scope: WorkspaceScope,
+    /// How the workspace is walked and resolved. Without following
+    /// symlinks, a path through any link is denied — except the documents a
+    /// file-scoped workspace was opened with, which were named explicitly.
+    policy: WalkPolicy,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -89,40 +96,75 @@ pub(crate) enum WorkspaceFsError {
 
 impl WorkspaceFs {
     pub(crate) fn new(root: PathBuf, single_file: Option<&str>) -> Self {
+        Self::with_files(root, single_file.as_slice())
+    }
+
+    /// A workspace limited to `files` (workspace-relative), or the whole
+    /// directory when `files` is empty.
+    pub(crate) fn with_files(root: PathBuf, files: &[&str]) -> Self {
         let ambient_root = root;
         let canonical_root = canonicalize_root_allow_missing(&ambient_root);
         let dir = Dir::open_ambient_dir(&canonical_root, ambient_authority())
             .ok()
             .map(Arc::new);
-        let scope = match single_file {
-            Some(file) => {
-                let parsed = WorkspaceRelPath::parse(file)
-                    .expect("single-file workspace name must be relative");
-                let target = dir
-                    .as_deref()
-                    .and_then(|dir| dir.canonicalize(parsed.as_path()).ok())
-                    .and_then(|path| WorkspaceRelPath::parse(path).ok())
-                    .unwrap_or_else(|| parsed.clone());
-                WorkspaceScope::SingleFile {
-                    document: ScopedPath {
+        let scope = if files.is_empty() {
+            WorkspaceScope::Directory
+        } else {
+            let documents = files
+                .iter()
+                .map(|file| {
+                    let parsed = WorkspaceRelPath::parse(file)
+                        .expect("scoped workspace file must be relative");
+                    let target = dir
+                        .as_deref()
+                        .and_then(|dir| dir.canonicalize(parsed.as_path()).ok())
+                        .and_then(|path| WorkspaceRelPath::parse(path).ok())
+                        .unwrap_or_else(|| parsed.clone());
+                    ScopedPath {
                         route: parsed,
                         target,
-                    },
-                    assets: RwLock::new(HashMap::new()),
-                }
+                    }
+                })
+                .collect();
+            WorkspaceScope::Files {
+                documents,
+                assets: RwLock::new(HashMap::new()),
             }
-            None => WorkspaceScope::Directory,
         };
         Self {
             ambient_root,
             canonical_root,
             root: RwLock::new(dir),
             scope,
+            policy: WalkPolicy::default(),
         }
     }
 
-    pub(crate) fn is_single_file(&self) -> bool {
-        matches!(self.scope, WorkspaceScope::SingleFile { .. })
+    /// This workspace walked and resolved under `policy` instead of the
+    /// default one.
+    pub(crate) fn with_policy(mut self, policy: WalkPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub(crate) fn policy(&self) -> &WalkPolicy {
+        &self.policy
+    }
+
+    pub(crate) fn is_file_scoped(&self) -> bool {
+        matches!(self.scope, WorkspaceScope::Files { .. })
+    }
+
+    /// Routes of the documents a file-scoped workspace exposes, in the order
+    /// they were given; empty for a directory workspace.
+    pub(crate) fn document_routes(&self) -> Vec<String> {
+        match &self.scope {
+            WorkspaceScope::Directory => Vec::new(),
+            WorkspaceScope::Files { documents, .. } => documents
+                .iter()
+                .map(|document| document.route.as_route())
+                .collect(),
+        }
     }
 
     /// Ambient path for identity, display, persistence, watchers, and trusted
@@ -139,7 +181,7 @@ impl WorkspaceFs {
     }
 
     pub(crate) fn replace_assets(&self, assets: HashSet<String>) {
-        let WorkspaceScope::SingleFile {
+        let WorkspaceScope::Files {
             assets: current, ..
         } = &self.scope
         else {
@@ -161,7 +203,7 @@ impl WorkspaceFs {
             return false;
         };
         match &self.scope {
-            WorkspaceScope::SingleFile { assets, .. } => assets
+            WorkspaceScope::Files { assets, .. } => assets
                 .read()
                 .expect("workspace asset lock poisoned")
                 .contains_key(&lexical),
@@ -169,15 +211,6 @@ impl WorkspaceFs {
         }
     }
 
-    pub(crate) fn clear_assets(&self) {
-        if let WorkspaceScope::SingleFile { assets, .. } = &self.scope {
-            assets
-                .write()
-                .expect("workspace asset lock poisoned")
-                .clear();
-        }
-    }
-
     pub(crate) fn resolve_served(
         &self,
         rel: impl AsRef<Path>,
@@ -188,8 +221,8 @@ impl WorkspaceFs {
                 let target = self.canonicalize_rel(&route)?;
                 Ok(self.absolute(&target))
             }
-            WorkspaceScope::SingleFile { document, assets } => {
-                if route == document.route {
+            WorkspaceScope::Files { documents, assets } => {
+                if let Some(document) = documents.iter().find(|d| d.route == route) {
                     return self.resolve_scoped(&route, &document.target);
                 }
                 let assets = assets.read().expect("workspace asset lock poisoned");
@@ -234,6 +267,41 @@ impl WorkspaceFs {
             .map_err(map_io_error)
     }
 
+    /// [`Self::read_content_to_string`] reading at most `max_bytes`. A longer
+    /// file is cut at the last character boundary before the cap; the flag
+    /// reports the cut.
+    pub(crate) fn read_content_prefix(
+        &self,
+        rel: impl AsRef<Path>,
+        max_bytes: u64,
+    ) -> Result<(String, bool), WorkspaceFsError> {
+        use std::io::Read;
+
+        let route = WorkspaceRelPath::parse(rel)?;
+        let target = self.content_target(&route)?;
+        let file = self
+            .root_dir()?
+            .open(target.as_path())
+            .map_err(map_io_error)?;
+        let mut bytes = Vec::new();
+        file.take(max_bytes.saturating_add(1))
+            .read_to_end(&mut bytes)
+            .map_err(map_io_error)?;
+        let truncated = bytes.len() as u64 > max_bytes;
+        if truncated {
+            bytes.truncate(max_bytes as usize);
+            if let Err(error) = std::str::from_utf8(&bytes) {
+                if error.error_len().is_none() {
+                    bytes.truncate(error.valid_up_to());
+                }
+            }
+        }
+        let content = String::from_utf8(bytes).map_err(|error| {
+            map_io_error(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+        })?;
+        Ok((content, truncated))
+    }
+
     /// Resolve either a workspace-relative route or an absolute path supplied
     /// by a higher-level protocol, then enforce this workspace's content
     /// capability. The returned path is canonical and safe to use as a stable
@@ -273,10 +341,10 @@ impl WorkspaceFs {
         let rel = canonical.strip_prefix(&self.canonical_root).ok()?;
         let target = WorkspaceRelPath::parse(rel).ok()?;
         match &self.scope {
-            WorkspaceScope::SingleFile { document, .. } if target == document.target => {
-                Some(document.route.as_route())
-            }
-            WorkspaceScope::SingleFile { .. } => None,
+            WorkspaceScope::Files { documents, .. } => documents
+                .iter()
+                .find(|document| document.target == target)
+                .map(|document| document.route.as_route()),
             WorkspaceScope::Directory => Some(target.as_route()),
         }
     }
@@ -287,13 +355,7 @@ impl WorkspaceFs {
 
     pub(crate) fn content_files(&self, limit: usize) -> Vec<(WorkspaceRelPath, PathBuf)> {
         match &self.scope {
-            WorkspaceScope::SingleFile { document, .. } => self
-                .resolve_scoped(&document.route, &document.target)
-                .ok()
-                .filter(|abs| abs.is_file())
-                .map(|abs| (document.route.clone(), abs))
-                .into_iter()
-                .collect(),
+            WorkspaceScope::Files { documents, .. } => self.existing_documents(documents, |_| true),
             WorkspaceScope::Directory => self.walk_authorized(limit, |_| true),
         }
     }
@@ -325,16 +387,8 @@ impl WorkspaceFs {
         routes: &BTreeSet<WorkspaceRelPath>,
     ) -> Vec<(WorkspaceRelPath, PathBuf)> {
         match &self.scope {
-            WorkspaceScope::SingleFile { document, .. } => {
-                if !routes.contains(&document.route) {
-                    return Vec::new();
-                }
-                self.resolve_scoped(&document.route, &document.target)
-                    .ok()
-                    .filter(|abs| abs.is_file())
-                    .map(|abs| (document.route.clone(), abs))
-                    .into_iter()
-                    .collect()
+            WorkspaceScope::Files { documents, .. } => {
+                self.existing_documents(documents, |route| routes.contains(route))
             }
             WorkspaceScope::Directory => {
                 let candidates: BTreeSet<PathBuf> = routes
@@ -359,8 +413,12 @@ impl WorkspaceFs {
                         }
                     }
                 }
-                let mut walker = default_walker(&root);
-                walker.filter_entry(move |entry| relevant_paths.contains(entry.path()));
+                let mut walker = self.policy.walker(&root);
+                // Replaces the walker's own filter, so it is applied here too.
+                let allowed = self.policy.walk_filter(&root);
+                walker.filter_entry(move |entry| {
+                    allowed(entry) && relevant_paths.contains(entry.path())
+                });
                 walker
                     .build()
                     .filter_map(Result::ok)
@@ -381,9 +439,11 @@ impl WorkspaceFs {
     pub(crate) fn served_files(&self, limit: usize) -> Vec<(WorkspaceRelPath, PathBuf)> {
         match &self.scope {
             WorkspaceScope::Directory => self.walk_authorized(limit, |_| true),
-            WorkspaceScope::SingleFile { document, assets } => {
-                let mut paths = BTreeMap::new();
-                paths.insert(document.route.clone(), document.target.clone());
+            WorkspaceScope::Files { documents, assets } => {
+                let mut paths: BTreeMap<_, _> = documents
+                    .iter()
+                    .map(|document| (document.route.clone(), document.target.clone()))
+                    .collect();
                 paths.extend(
                     assets
                         .read()
@@ -402,12 +462,30 @@ impl WorkspaceFs {
         }
     }
 
+    fn existing_documents(
+        &self,
+        documents: &[ScopedPath],
+        allow: impl Fn(&WorkspaceRelPath) -> bool,
+    ) -> Vec<(WorkspaceRelPath, PathBuf)> {
+        documents
+            .iter()
+            .filter(|document| allow(&document.route))
+            .filter_map(|document| {
+                let abs = self
+                    .resolve_scoped(&document.route, &document.target)
+                    .ok()?;
+                abs.is_file().then(|| (document.route.clone(), abs))
+            })
+            .collect()
+    }
+
     fn walk_authorized(
         &self,
         limit: usize,
         allow: impl Fn(&WorkspaceRelPath) -> bool,
     ) -> Vec<(WorkspaceRelPath, PathBuf)> {
-        default_walker(&self.canonical_root)
+        self.policy
+            .walker(&self.canonical_root)
             .build()
             .filter_map(Result::ok)
             .filter(|entry| entry.path().is_file())
@@ -433,12 +511,49 @@ impl WorkspaceFs {
     fn canonicalize_rel(
         &self,
         rel: &WorkspaceRelPath,
+    ) -> Result<WorkspaceRelPath, WorkspaceFsError> {
+        if !self.policy.follow_symlinks {
+            self.reject_symlinks(rel)?;
+        }
+        let canonical = self.canonicalize_following(rel)?;
+        let rules = &self.policy.rules;
+        if !rules.is_empty() {
+            let is_dir = self
+                .root_dir()?
+                .metadata(canonical.as_path())
+                .map_err(map_io_error)?
+                .is_dir();
+            if !(rules.permits(rel.as_path(), is_dir) && rules.permits(canonical.as_path(), is_dir))
+            {
+                return Err(WorkspaceFsError::Denied);
+            }
+        }
+        Ok(canonical)
+    }
+
+    fn canonicalize_following(
+        &self,
+        rel: &WorkspaceRelPath,
     ) -> Result<WorkspaceRelPath, WorkspaceFsError> {
         let root = self.root_dir()?;
         let canonical = root.canonicalize(rel.as_path()).map_err(map_io_error)?;
         WorkspaceRelPath::parse(canonical)
     }
 
+    /// Deny `rel` when any of its components is a symlink.
+    fn reject_symlinks(&self, rel: &WorkspaceRelPath) -> Result<(), WorkspaceFsError> {
+        let root = self.root_dir()?;
+        let mut prefix = PathBuf::new();
+        for component in rel.as_path().components() {
+            prefix.push(component);
+            let meta = root.symlink_metadata(&prefix).map_err(map_io_error)?;
+            if meta.file_type().is_symlink() {
+                return Err(WorkspaceFsError::Denied);
+            }
+        }
+        Ok(())
+    }
+
     /// Return the capability root, opening it lazily if it was unavailable
     /// during registration. The double check avoids replacing a handle another
     /// request installed while this request was opening the same directory.
@@ -472,10 +587,13 @@ impl WorkspaceFs {
     ) -> Result<WorkspaceRelPath, WorkspaceFsError> {
         match &self.scope {
             WorkspaceScope::Directory => self.canonicalize_rel(route),
-            WorkspaceScope::SingleFile { document, .. } if route == &document.route => {
+            WorkspaceScope::Files { documents, .. } => {
+                let document = documents
+                    .iter()
+                    .find(|document| &document.route == route)
+                    .ok_or(WorkspaceFsError::Denied)?;
                 self.scoped_target(route, &document.target)
             }
-            WorkspaceScope::SingleFile { .. } => Err(WorkspaceFsError::Denied),
         }
     }
 
@@ -493,7 +611,17 @@ impl WorkspaceFs {
         route: &WorkspaceRelPath,
         expected_target: &WorkspaceRelPath,
     ) -> Result<WorkspaceRelPath, WorkspaceFsError> {
-        let current_target = self.canonicalize_rel(route)?;
+        let named = match &self.scope {
+            WorkspaceScope::Files { documents, .. } => {
+                documents.iter().any(|document| &document.route == route)
+            }
+            WorkspaceScope::Directory => false,
+        };
+        let current_target = if named {
+            self.canonicalize_following(route)?
+        } else {
+            self.canonicalize_rel(route)?
+        };
         if &current_target != expected_target {
             return Err(WorkspaceFsError::Denied);
         }
@@ -567,6 +695,33 @@ mod tests {
         assert!(fs.directory_root().is_none());
     }
 
+    #[test]
+    fn file_set_exposes_only_its_documents_in_order() {
+        let temp = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp.path().join("docs")).unwrap();
+        std::fs::write(temp.path().join("spec.md"), "spec").unwrap();
+        std::fs::write(temp.path().join("docs/guide.md"), "guide").unwrap();
+        std::fs::write(temp.path().join("docs/other.md"), "other").unwrap();
+        let fs = WorkspaceFs::with_files(temp.path().to_path_buf(), &["spec.md", "docs/guide.md"]);
+
+        assert!(fs.is_file_scoped());
+        assert_eq!(fs.document_routes(), ["spec.md", "docs/guide.md"]);
+        let routes: Vec<_> = fs
+            .content_files(10)
+            .into_iter()
+            .map(|(rel, _)| rel.as_route())
+            .collect();
+        assert_eq!(routes, ["spec.md", "docs/guide.md"]);
+        assert!(fs.resolve_content("docs/guide.md").is_ok());
+        assert!(matches!(
+            fs.resolve_served("docs/other.md"),
+            Err(WorkspaceFsError::Denied)
+        ));
+        let guide = fs.resolve_served("docs/guide.md").unwrap();
+        assert_eq!(fs.route_for_path(&guide).as_deref(), Some("docs/guide.md"));
+        assert!(fs.directory_root().is_none());
+    }
+
     #[test]
     fn workspace_registered_before_root_exists_recovers_lazily() {
         let temp = tempfile::TempDir::new().unwrap();
@@ -661,6 +816,81 @@ mod tests {
         assert!(fs.route_for_path(&link).is_none());
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn no_follow_policy_denies_every_path_through_a_symlink() {
+        use std::os::unix::fs::symlink;
+
+        let temp = tempfile::TempDir::new().unwrap();
+        let outside = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(temp.path().join("docs")).unwrap();
+        std::fs::write(temp.path().join("docs/guide.md"), "guide").unwrap();
+        std::fs::write(outside.path().join("secret.md"), "secret").unwrap();
+        symlink("docs", temp.path().join("linked-docs")).unwrap();
+        symlink("docs/guide.md", temp.path().join("linked.md")).unwrap();
+        symlink(outside.path(), temp.path().join("escape")).unwrap();
+
+        let mut fs = WorkspaceFs::new(temp.path().to_path_buf(), None);
+        fs.policy.follow_symlinks = true;
+        assert!(fs.resolve_served("linked-docs/guide.md").is_ok());
+        assert!(fs.resolve_content("linked.md").is_ok());
+        assert!(fs.resolve_content("escape/secret.md").is_err());
+
+        fs.policy.follow_symlinks = false;
+        for route in ["linked-docs/guide.md", "linked.md", "escape/secret.md"] {
+            assert!(
+                matches!(fs.resolve_served(route), Err(WorkspaceFsError::Denied)),
+                "{route}"
+            );
+        }
+        assert!(fs.read_content("linked.md").is_err());
+        assert_eq!(fs.read_content_to_string("docs/guide.md").unwrap(), "guide");
+        assert!(matches!(
+            fs.resolve_served("docs/missing.md"),
+            Err(WorkspaceFsError::NotFound)
+        ));
+        let routes: Vec<_> = fs
+            .served_files(10)
+            .into_iter()
+            .map(|(rel, _)| rel.as_route())
+            .collect();
+        assert_eq!(routes, ["docs/guide.md"]);
+
+        // A document the workspace was opened with is followed regardless.
+        let mut scoped = WorkspaceFs::new(temp.path().to_path_buf(), Some("linked.md"));
+        scoped.policy.follow_symlinks = false;
+        assert!(scoped.resolve_content("linked.md").is_ok());
+    }
+
+    #[test]
+    fn path_rules_deny_resolving_hidden_paths() {
+        let temp = tempfile::TempDir::new().unwrap();
+        for rel in ["README.md", "docs/guide.md", "docs/private/plan.md"] {
+            let path = temp.path().join(rel);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "# x").unwrap();
+        }
+        let mut fs = WorkspaceFs::new(temp.path().to_path_buf(), None);
+        fs.policy.rules = Arc::new(
+            crate::path_rules::PathRules::new(&["docs/**".into()], &["private".into()]).unwrap(),
+        );
+
+        assert!(fs.resolve_served("docs/guide.md").is_ok());
+        assert!(fs.resolve_served("docs").is_ok());
+        for route in ["README.md", "docs/private/plan.md", "docs/private"] {
+            assert!(
+                matches!(fs.resolve_served(route), Err(WorkspaceFsError::Denied)),
+                "{route}"
+            );
+        }
+        let routes: Vec<_> = fs
+            .served_files(10)
+            .into_iter()
+            .map(|(rel, _)| rel.as_route())
+            .collect();
+        assert_eq!(routes, ["docs/guide.md"]);
+    }
+
     #[cfg(unix)]
     #[test]
     fn scoped_symlink_keeps_public_route_and_rejects_target_swap() {
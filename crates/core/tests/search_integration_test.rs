@@ -285,8 +285,10 @@ fn test_large_file_indexing() {
     let results = index.search("unique500", 20).unwrap();
     assert_eq!(results.len(), 1);
 
+    // Every section heading contains "Section", so the phrase matches many
+    // sections; the exact one must rank first.
     let results = index.search("Section 999", 20).unwrap();
-    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].heading.as_deref(), Some("Section 999"));
 }
 
 #[test]
@@ -353,9 +355,12 @@ fn test_search_query_deserialization() {
     // Test that SearchQuery can be properly deserialized from query strings
     let query = SearchQuery {
         q: "test query".to_string(),
+        ..SearchQuery::default()
     };
+    assert_eq!(query.offset, 0);
+    assert_eq!(query.limit, None);
     assert_eq!(query.q, "test query");
 
-    let empty_query = SearchQuery { q: String::new() };
+    let empty_query = SearchQuery::default();
     assert!(empty_query.q.is_empty());
 }
@@ -78,6 +78,7 @@ fn workspace_panel_json(info: WorkspaceInfo, browser_base: &str) -> serde_json::
         // Lets the Settings UI filter out Open-With single-file workspaces.
         "ephemeral": info.ephemeral,
         "single_file": info.single_file,
+        "files": info.files,
         // Optional short display name (empty = none).
         "alias": info.alias,
     })
@@ -186,6 +186,7 @@ fn ensure_example_workspace(app: &tauri::App, settings: &mut AppSettings) {
     settings.workspaces.push(WorkspaceSettings {
         path: canonical.to_string_lossy().to_string(),
         single_file: None,
+        files: Vec::new(),
         flags: WorkspaceFlags {
             enable_search: true,
             enable_viewed: true,
@@ -287,12 +288,13 @@ fn is_markdown_file(path: &Path) -> bool {
     if !path.is_file() {
         return false;
     }
-    matches!(
-        path.extension()
-            .and_then(|ext| ext.to_str())
-            .map(|ext| ext.to_ascii_lowercase()),
-        Some(ext) if ext == "md" || ext == "markdown"
-    )
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            markon_core::server::DEFAULT_MARKDOWN_EXTENSIONS
+                .iter()
+                .any(|known| ext.eq_ignore_ascii_case(known))
+        })
 }
 
 #[cfg(any(target_os = "macos", test))]
@@ -16,7 +16,6 @@ use crate::commands::effective_port;
 use markon_core::control::RunningServer;
 use markon_core::daemon::{DaemonConfig, DaemonWorkspace};
 use markon_core::settings::AppSettings;
-use markon_core::workspace::WorkspaceFlags;
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
@@ -90,6 +89,7 @@ fn workspaces_for_daemon(settings: &AppSettings) -> Vec<DaemonWorkspace> {
             flags: w.flags,
             initial_path: w.single_file.clone(),
             single_file: w.single_file.clone(),
+            files: w.files.clone(),
             collaborator_access_code_hash: w.collaborator_access_code_hash.clone(),
             alias: w.alias.clone(),
         })
@@ -106,26 +106,62 @@ pub fn daemon_config_from_settings(settings: &AppSettings, port: u16) -> DaemonC
         trusted_hosts: settings.trusted_hosts.clone(),
         port,
         theme: settings.theme.clone(),
+        theme_css: None,
         qr: None,
         // The daemon never opens a browser itself; the GUI opens URLs over the
         // control socket (admin bootstrap) when the user asks.
         open_browser: None,
         db_path: settings.db_path.clone(),
+        db_url: None,
         salt: Some(settings.salt.clone()),
         workspaces: workspaces_for_daemon(settings),
         language: Some(settings.language.clone()),
         shortcuts_json: settings.render_shortcuts_json(),
         styles_css: settings.render_styles_css(),
+        user_css: Vec::new(),
+        user_js: Vec::new(),
         default_chat_mode: settings.default_chat_mode.clone(),
         collaborator_access_code_hash: settings.collaborator_access_code_hash.clone(),
         print_collapsed_content: settings.print_collapsed_content,
+        content_security_policy: None,
+        frame_options: None,
+        no_cache: false,
+        search_exclude: Vec::new(),
+        search_lang: None,
+        search_max_limit: None,
+        require_name: false,
+        annotation_token: None,
+        readme_names: Vec::new(),
+        nav_files: Vec::new(),
+        markdown_extensions: Vec::new(),
+        show_hidden: false,
+        no_follow_symlinks: false,
+        allow_paths: Vec::new(),
+        deny_paths: Vec::new(),
+        listing_page_size: None,
+        search_writer_memory: None,
+        search_max_file_size: None,
+        search_max_index_size: None,
+        paginate_kib: None,
+        allow_remote: Vec::new(),
+        notify_urls: Vec::new(),
+        notify_paths: Vec::new(),
+        notify_events: Vec::new(),
+        pandoc: false,
+        snapshots: false,
+        audit: false,
+        link_schemes: Vec::new(),
+        plugins_dir: None,
+        share_token: false,
+        log_filter: None,
+        log_file: None,
     }
 }
 
 /// Forward every workspace that remains persisted after startup pruning to an
 /// already-running service. When automatic single-file cleanup is disabled,
 /// those entries are durable by contract and must be replayed with their exact
-/// `(path, single_file)` identity, alias, flags, and access-code hash.
+/// `(path, single_file, files)` identity, alias, flags, and access-code hash.
 async fn forward_persisted(
     remote: &RunningServer,
     settings: &Arc<Mutex<AppSettings>>,
@@ -138,30 +174,30 @@ async fn forward_persisted(
     // Read the cross-process source of truth immediately before replaying it;
     // the GUI's boot snapshot may lag mutations a daemon just persisted.
     let persisted = AppSettings::load();
-    let to_forward: Vec<(String, WorkspaceFlags, Option<String>, String, String)> = persisted
-        .workspaces
-        .into_iter()
-        .map(|w| {
-            (
-                w.path,
-                w.flags,
-                w.single_file,
-                w.collaborator_access_code_hash,
-                w.alias,
-            )
-        })
-        .collect();
-    for (path, flags, single_file, hash, alias) in to_forward {
-        if let Err(e) = remote
-            .add_or_update_workspace_scoped(
-                &path,
-                flags,
-                single_file.as_deref(),
-                Some(&hash),
-                Some(&alias),
-            )
-            .await
-        {
+    for w in persisted.workspaces {
+        let path = w.path;
+        let forwarded = if w.files.is_empty() {
+            remote
+                .add_or_update_workspace_scoped(
+                    &path,
+                    w.flags,
+                    w.single_file.as_deref(),
+                    Some(&w.collaborator_access_code_hash),
+                    Some(&w.alias),
+                )
+                .await
+        } else {
+            remote
+                .add_or_update_file_set(
+                    &path,
+                    &w.files,
+                    w.flags,
+                    Some(&w.collaborator_access_code_hash),
+                    Some(&w.alias),
+                )
+                .await
+        };
+        if let Err(e) = forwarded {
             tracing::warn!(%path, "failed to forward workspace to the markon service: {e}");
         }
     }
@@ -293,6 +329,8 @@ mod tests {
             advertised_host: Some(String::new()),
             service_version: version.into(),
             owner: "test-owner".into(),
+            pid: 0,
+            started_at: 0,
         })
     }
 
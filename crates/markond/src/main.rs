@@ -127,21 +127,31 @@ impl Write for RollingLogWriter {
     }
 }
 
-fn open_log_writer() -> std::io::Result<(PathBuf, RollingLogWriter)> {
-    let log_dir = dirs::home_dir()
-        .ok_or_else(|| std::io::Error::other("HOME directory required"))?
-        .join(".markon")
-        .join("logs");
-    std::fs::create_dir_all(&log_dir)?;
-    let path = log_dir.join("markond.log");
+fn open_log_writer(path: Option<&std::path::Path>) -> std::io::Result<(PathBuf, RollingLogWriter)> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => dirs::home_dir()
+            .ok_or_else(|| std::io::Error::other("HOME directory required"))?
+            .join(".markon")
+            .join("logs")
+            .join("markond.log"),
+    };
+    if let Some(log_dir) = path.parent() {
+        std::fs::create_dir_all(log_dir)?;
+    }
     let writer = RollingLogWriter::open(path.clone(), LOG_MAX_BYTES, LOG_BACKUPS)?;
     Ok((path, writer))
 }
 
-fn init_tracing() -> Option<PathBuf> {
-    let filter = tracing_subscriber::EnvFilter::try_from_default_env()
-        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
-    match open_log_writer() {
+/// Log at the level and to the file the config asks for. `config` is `None`
+/// when it could not be loaded; the failure is then logged with the defaults.
+fn init_tracing(config: Option<&DaemonConfig>) -> Option<PathBuf> {
+    let filter = match config.and_then(|cfg| cfg.log_filter.as_deref()) {
+        Some(directives) => tracing_subscriber::EnvFilter::new(directives),
+        None => tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+    };
+    match open_log_writer(config.and_then(|cfg| cfg.log_file.as_deref())) {
         Ok((path, writer)) => {
             tracing_subscriber::fmt()
                 .with_env_filter(filter)
@@ -183,9 +193,30 @@ fn parse_config_path() -> Result<PathBuf, String> {
     Err(format!("unexpected argument: {arg}"))
 }
 
+/// Read the config handed off at `path`, then delete it. Runs before logging
+/// is set up, since the config says where to log, so problems are returned:
+/// a fatal error, or a warning that the secret-bearing file stayed behind.
+fn load_daemon_config(path: &std::path::Path) -> Result<(DaemonConfig, Option<String>), String> {
+    let raw = std::fs::read(path).map_err(|e| format!("failed to read daemon config: {e}"))?;
+    // The handoff file holds the collaborator access-code hash; remove it as
+    // soon as it is read so the secret does not linger on disk.
+    let cleanup = std::fs::remove_file(path).err().map(|e| {
+        format!(
+            "failed to remove daemon config file {}: {e}",
+            path.display()
+        )
+    });
+    let config =
+        serde_json::from_slice(&raw).map_err(|e| format!("failed to parse daemon config: {e}"))?;
+    Ok((config, cleanup))
+}
+
 #[tokio::main]
 async fn main() -> ExitCode {
-    let log_path = init_tracing();
+    let loaded = parse_config_path()
+        .map_err(|e| format!("invalid markond invocation: {e}"))
+        .and_then(|path| load_daemon_config(&path));
+    let log_path = init_tracing(loaded.as_ref().ok().map(|(cfg, _)| cfg));
     tracing::info!(
         version = env!("CARGO_PKG_VERSION"),
         pid = std::process::id(),
@@ -193,34 +224,15 @@ async fn main() -> ExitCode {
         "markond starting"
     );
 
-    let config_path = match parse_config_path() {
-        Ok(p) => p,
-        Err(e) => {
-            tracing::error!(error = %e, "invalid markond invocation");
-            return ExitCode::FAILURE;
-        }
-    };
-
-    let raw = match std::fs::read(&config_path) {
-        Ok(bytes) => bytes,
-        Err(e) => {
-            tracing::error!(error = %e, "failed to read daemon config");
-            return ExitCode::FAILURE;
+    let daemon_config = match loaded {
+        Ok((cfg, cleanup)) => {
+            if let Some(warning) = cleanup {
+                tracing::warn!("{warning}");
+            }
+            cfg
         }
-    };
-    // The handoff file holds the collaborator access-code hash; remove it as
-    // soon as it is read so the secret does not linger on disk.
-    if let Err(e) = std::fs::remove_file(&config_path) {
-        tracing::warn!(
-            "failed to remove daemon config file {}: {e}",
-            config_path.display()
-        );
-    }
-
-    let daemon_config: DaemonConfig = match serde_json::from_slice(&raw) {
-        Ok(cfg) => cfg,
         Err(e) => {
-            tracing::error!(error = %e, "failed to parse daemon config");
+            tracing::error!(error = %e, "cannot start markond");
             return ExitCode::FAILURE;
         }
     };
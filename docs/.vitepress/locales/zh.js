@@ -37,6 +37,7 @@ export const zh = {
             { text: '导出便条', link: '/features/export' },
             { text: '实时协作 (Live)', link: '/features/live' },
             { text: '章节打印', link: '/features/print' },
+            { text: '演示模式', link: '/features/present' },
             { text: '访问码', link: '/features/access' },
           ],
         },
@@ -49,6 +50,7 @@ export const zh = {
             { text: '反向代理', link: '/advanced/reverse-proxy' },
             { text: '自定义样式', link: '/advanced/custom-styles' },
             { text: '键盘快捷键', link: '/advanced/shortcuts' },
+            { text: '编辑器插件协议', link: '/advanced/editor-protocol' },
           ],
         },
       ],
@@ -183,6 +183,39 @@ async function build() {
     target: ['es2022'],
     // main.ts owns the dev reload EventSource.
   };
+  // Side-by-side compare page: i18n + change navigation. ESM module so it can
+  // pull in KeyboardShortcutsManager, like page-shortcuts.
+  const compareOpts = {
+    ...shared,
+    entryPoints: [resolve(srcDir, 'compare.ts')],
+    outfile: resolve(outDir, 'compare.js'),
+    format: 'esm',
+    target: ['es2022'],
+  };
+  // Shared i18n glue for the small server-rendered pages (archive listings,
+  // snapshot history, print view, past revisions, remote documents).
+  const staticPageOpts = {
+    ...shared,
+    entryPoints: [resolve(srcDir, 'static-page.ts')],
+    outfile: resolve(outDir, 'static-page.js'),
+    format: 'esm',
+    target: ['es2022'],
+  };
+  // Editor-plugin preview page: follows the session's SSE events stream.
+  const editorPreviewOpts = {
+    ...shared,
+    entryPoints: [resolve(srcDir, 'editor-preview.ts')],
+    outfile: resolve(outDir, 'editor-preview.js'),
+    format: 'iife',
+    target: ['es2022'],
+  };
+  const presentOpts = {
+    ...shared,
+    entryPoints: [resolve(srcDir, 'present.ts')],
+    outfile: resolve(outDir, 'present.js'),
+    format: 'iife',
+    target: ['es2022'],
+  };
   const mathRenderOpts = {
     ...shared,
     entryPoints: [resolve(srcDir, 'math-render.ts')],
@@ -224,6 +257,10 @@ async function build() {
     const ctxAdminSessionBoot = await esbuild.context(adminSessionBootOpts);
     const ctxGitRefs = await esbuild.context(gitRefsOpts);
     const ctxPageShortcuts = await esbuild.context(pageShortcutsOpts);
+    const ctxCompare = await esbuild.context(compareOpts);
+    const ctxStaticPage = await esbuild.context(staticPageOpts);
+    const ctxEditorPreview = await esbuild.context(editorPreviewOpts);
+    const ctxPresent = await esbuild.context(presentOpts);
     const ctxMathRender = await esbuild.context(mathRenderOpts);
     await ctxAppEsm.watch();
     await ctxViewed.watch();
@@ -240,6 +277,10 @@ async function build() {
     await ctxAdminSessionBoot.watch();
     await ctxGitRefs.watch();
     await ctxPageShortcuts.watch();
+    await ctxCompare.watch();
+    await ctxStaticPage.watch();
+    await ctxEditorPreview.watch();
+    await ctxPresent.watch();
     await ctxMathRender.watch();
     console.log('[build] watching…');
   } else {
@@ -259,6 +300,10 @@ async function build() {
       esbuild.build(adminSessionBootOpts),
       esbuild.build(gitRefsOpts),
       esbuild.build(pageShortcutsOpts),
+      esbuild.build(compareOpts),
+      esbuild.build(staticPageOpts),
+      esbuild.build(editorPreviewOpts),
+      esbuild.build(presentOpts),
       esbuild.build(mathRenderOpts),
     ]);
     console.log('[build] done');